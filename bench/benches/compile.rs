@@ -0,0 +1,141 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Compiler benchmarks: parsing, projection and `choreography!` expansion
+//
+// `expand` times what a DSL `choreography!` macro does in process: parsing,
+// validation, projection of every role and code generation. `expand_build`
+// times a real build of a crate invoking the macro, trybuild style. Each of
+// its samples is a cargo invocation, so it only runs with
+// `RUMPSTEAK_BENCH_BUILD=1`. `expand_jobs` times the build script path with
+// roles projected and generated on several threads.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rumpsteak_aura_bench::{fixtures, ScratchCrate};
+use rumpsteak_aura_choreography::compile_choreography_with_extensions;
+use rumpsteak_aura_choreography::compiler::{
+    parser::{parse_choreography_str, parse_choreography_str_with_extensions},
+    projection::project,
+};
+use rumpsteak_aura_choreography::{parse_and_generate_with_jobs, ExtensionRegistry};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for fixture in fixtures() {
+        group.throughput(Throughput::Bytes(fixture.source.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(fixture.name),
+            &fixture.source,
+            |b, source| b.iter(|| parse_choreography_str(black_box(source)).unwrap()),
+        );
+    }
+    group.finish();
+
+    // Builtin grammar extensions reuse the cached composed grammar
+    let registry = ExtensionRegistry::with_builtin_extensions();
+    let mut group = c.benchmark_group("parse_with_extensions");
+    for fixture in fixtures() {
+        group.throughput(Throughput::Bytes(fixture.source.len() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(fixture.name),
+            &fixture.source,
+            |b, source| {
+                b.iter(|| {
+                    parse_choreography_str_with_extensions(black_box(source), &registry).unwrap()
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_project(c: &mut Criterion) {
+    let mut group = c.benchmark_group("project");
+    for fixture in fixtures() {
+        let choreography = parse_choreography_str(&fixture.source).unwrap();
+        group.bench_with_input(
+            BenchmarkId::from_parameter(fixture.name),
+            &choreography,
+            |b, choreography| {
+                b.iter(|| {
+                    for role in &choreography.roles {
+                        black_box(project(choreography, role).unwrap());
+                    }
+                });
+            },
+        );
+    }
+    group.finish();
+}
+
+fn bench_expand(c: &mut Criterion) {
+    let mut group = c.benchmark_group("expand");
+    for fixture in fixtures() {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(fixture.name),
+            &fixture.source,
+            |b, source| b.iter(|| compile_choreography_with_extensions(black_box(source)).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+fn bench_expand_jobs(c: &mut Criterion) {
+    let registry = ExtensionRegistry::with_builtin_extensions();
+    let mut group = c.benchmark_group("expand_jobs");
+    for fixture in fixtures() {
+        for jobs in [1, 4] {
+            group.bench_with_input(
+                BenchmarkId::new(fixture.name, jobs),
+                &fixture.source,
+                |b, source| {
+                    b.iter(|| {
+                        parse_and_generate_with_jobs(black_box(source), &registry, jobs).unwrap()
+                    })
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn bench_expand_build(c: &mut Criterion) {
+    if std::env::var_os("RUMPSTEAK_BENCH_BUILD").is_none() {
+        return;
+    }
+    let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap();
+    let target = std::env::var_os("CARGO_TARGET_DIR")
+        .map_or_else(|| workspace.join("target"), PathBuf::from);
+    let scratch = ScratchCrate::new(target.join("bench-expansion"), workspace).unwrap();
+
+    let mut group = c.benchmark_group("expand_build");
+    group.sample_size(10);
+    for steps in [2, 16, 64] {
+        scratch.set_exchange(steps).unwrap();
+        // Build the dependencies outside the measurement
+        scratch.check().unwrap();
+        group.bench_with_input(BenchmarkId::from_parameter(steps), &steps, |b, _| {
+            b.iter_custom(|iterations| {
+                (0..iterations)
+                    .map(|_| {
+                        scratch.touch().unwrap();
+                        scratch.check().unwrap()
+                    })
+                    .sum::<Duration>()
+            });
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_parse,
+    bench_project,
+    bench_expand,
+    bench_expand_jobs,
+    bench_expand_build
+);
+criterion_main!(benches);
@@ -0,0 +1,219 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Runtime benchmarks: message throughput and round-trip latency
+//
+// `ChannelHandler` connects two roles within one task. The blocking
+// `ChannelTransport` connects them across threads of this process and
+// `TcpTransport` over a loopback connection, with the server role on a
+// thread of its own. Throughput is measured over batches of messages the
+// server acknowledges once per batch, latency as one request and reply.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use rumpsteak_aura_choreography::runtime::blocking::{
+    BlockingEndpoint, ChannelTransport, TcpTransport, Transport,
+};
+use rumpsteak_aura_choreography::runtime::harness::{take_role, ChannelHandler};
+use rumpsteak_aura_choreography::{Bytes, ChoreoHandler};
+use std::net::{TcpListener, TcpStream};
+use tokio::runtime::Runtime;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Role {
+    Client,
+    Server,
+}
+
+const ROLES: &[Role] = &[Role::Client, Role::Server];
+
+/// Payload sizes of the throughput benchmarks
+const SIZES: &[usize] = &[64, 4 << 10, 1 << 20];
+
+/// Messages the server receives before acknowledging them
+const BATCH: usize = 32;
+
+/// Payload size of the latency benchmarks
+const SMALL: usize = 64;
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap()
+}
+
+fn bench_channel_handler_throughput(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut mesh = ChannelHandler::mesh(ROLES);
+    let mut client = take_role(&mut mesh, Role::Client).unwrap();
+    let mut server = take_role(&mut mesh, Role::Server).unwrap();
+
+    let mut group = c.benchmark_group("throughput/channel_handler");
+    for &size in SIZES {
+        let payload = vec![0u8; size];
+        let bytes = Bytes::from(payload.clone());
+        group.throughput(Throughput::Bytes((size * BATCH) as u64));
+        group.bench_with_input(BenchmarkId::new("encoded", size), &payload, |b, payload| {
+            b.iter(|| {
+                runtime.block_on(async {
+                    for _ in 0..BATCH {
+                        client.send(&mut (), Role::Server, payload).await.unwrap();
+                    }
+                    for _ in 0..BATCH {
+                        let message: Vec<u8> = server.recv(&mut (), Role::Client).await.unwrap();
+                        black_box(message);
+                    }
+                });
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("bytes", size), &bytes, |b, bytes| {
+            b.iter(|| {
+                runtime.block_on(async {
+                    for _ in 0..BATCH {
+                        client
+                            .send_bytes(&mut (), Role::Server, bytes.clone())
+                            .await
+                            .unwrap();
+                    }
+                    for _ in 0..BATCH {
+                        black_box(server.recv_bytes(&mut (), Role::Client).await.unwrap());
+                    }
+                });
+            });
+        });
+    }
+    group.finish();
+}
+
+fn bench_channel_handler_latency(c: &mut Criterion) {
+    let runtime = runtime();
+    let mut mesh = ChannelHandler::mesh(ROLES);
+    let mut client = take_role(&mut mesh, Role::Client).unwrap();
+    let mut server = take_role(&mut mesh, Role::Server).unwrap();
+    let payload = vec![0u8; SMALL];
+
+    c.bench_function("latency/channel_handler", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                client.send(&mut (), Role::Server, &payload).await.unwrap();
+                let request: Vec<u8> = server.recv(&mut (), Role::Client).await.unwrap();
+                server.send(&mut (), Role::Client, &request).await.unwrap();
+                let reply: Vec<u8> = client.recv(&mut (), Role::Server).await.unwrap();
+                black_box(reply);
+            });
+        });
+    });
+}
+
+/// Client endpoint whose server acknowledges every `BATCH` messages on a
+/// thread of its own
+fn batch_server<T>(client: T, server: T) -> BlockingEndpoint<Role, T>
+where
+    T: Transport<Role> + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let mut endpoint = BlockingEndpoint::new(Role::Server, ROLES.to_vec(), server);
+        // Ends once the client is dropped
+        loop {
+            for _ in 0..BATCH {
+                if endpoint.recv::<Vec<u8>>(Role::Client).is_err() {
+                    return;
+                }
+            }
+            if endpoint.send(Role::Client, &()).is_err() {
+                return;
+            }
+        }
+    });
+    BlockingEndpoint::new(Role::Client, ROLES.to_vec(), client)
+}
+
+/// Client endpoint whose server replies to every message on a thread of its
+/// own
+fn echo_server<T>(client: T, server: T) -> BlockingEndpoint<Role, T>
+where
+    T: Transport<Role> + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let mut endpoint = BlockingEndpoint::new(Role::Server, ROLES.to_vec(), server);
+        while let Ok(request) = endpoint.recv::<Vec<u8>>(Role::Client) {
+            if endpoint.send(Role::Client, &request).is_err() {
+                return;
+            }
+        }
+    });
+    BlockingEndpoint::new(Role::Client, ROLES.to_vec(), client)
+}
+
+fn channel_pair() -> (ChannelTransport<Role>, ChannelTransport<Role>) {
+    let mut mesh = ChannelTransport::mesh(ROLES);
+    (
+        take_role(&mut mesh, Role::Client).unwrap(),
+        take_role(&mut mesh, Role::Server).unwrap(),
+    )
+}
+
+fn tcp_pair() -> (TcpTransport<Role>, TcpTransport<Role>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    client.set_nodelay(true).unwrap();
+    server.set_nodelay(true).unwrap();
+    (
+        TcpTransport::new().with_peer(Role::Server, client),
+        TcpTransport::new().with_peer(Role::Client, server),
+    )
+}
+
+fn bench_blocking_throughput<T>(c: &mut Criterion, name: &str, (client, server): (T, T))
+where
+    T: Transport<Role> + Send + 'static,
+{
+    let mut endpoint = batch_server(client, server);
+    let mut group = c.benchmark_group(format!("throughput/{name}"));
+    for &size in SIZES {
+        let payload = vec![0u8; size];
+        group.throughput(Throughput::Bytes((size * BATCH) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &payload, |b, payload| {
+            b.iter(|| {
+                for _ in 0..BATCH {
+                    endpoint.send(Role::Server, payload).unwrap();
+                }
+                endpoint.recv::<()>(Role::Server).unwrap();
+            });
+        });
+    }
+    group.finish();
+}
+
+fn bench_blocking_latency<T>(c: &mut Criterion, name: &str, (client, server): (T, T))
+where
+    T: Transport<Role> + Send + 'static,
+{
+    let mut endpoint = echo_server(client, server);
+    let payload = vec![0u8; SMALL];
+    c.bench_function(&format!("latency/{name}"), |b| {
+        b.iter(|| {
+            endpoint.send(Role::Server, &payload).unwrap();
+            black_box(endpoint.recv::<Vec<u8>>(Role::Server).unwrap());
+        });
+    });
+}
+
+fn bench_blocking_channel(c: &mut Criterion) {
+    bench_blocking_throughput(c, "blocking_channel", channel_pair());
+    bench_blocking_latency(c, "blocking_channel", channel_pair());
+}
+
+fn bench_tcp(c: &mut Criterion) {
+    bench_blocking_throughput(c, "tcp", tcp_pair());
+    bench_blocking_latency(c, "tcp", tcp_pair());
+}
+
+criterion_group!(
+    benches,
+    bench_channel_handler_throughput,
+    bench_channel_handler_latency,
+    bench_blocking_channel,
+    bench_tcp
+);
+criterion_main!(benches);
@@ -0,0 +1,85 @@
+// Fail when benchmarks got slower than a saved baseline
+//
+// Usage: bench-compare [--threshold <fraction>] [--dir <criterion dir>] <baseline>
+//
+// Save the baseline with `cargo bench -p rumpsteak-aura-bench -- --save-baseline main`
+// on the reference commit, run `cargo bench -p rumpsteak-aura-bench` on the
+// change, then run this. It prints the change of every benchmark and exits
+// with status 1 if any mean grew by more than the threshold, 10% by default.
+
+use rumpsteak_aura_bench::compare;
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+const USAGE: &str =
+    "usage: bench-compare [--threshold <fraction>] [--dir <criterion dir>] <baseline>";
+
+fn main() -> ExitCode {
+    let mut threshold = 0.1;
+    let mut dir = std::env::var_os("CARGO_TARGET_DIR")
+        .map_or_else(|| PathBuf::from("target"), PathBuf::from)
+        .join("criterion");
+    let mut baseline = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--threshold" => match args.next().and_then(|value| value.parse().ok()) {
+                Some(value) => threshold = value,
+                None => {
+                    eprintln!("{USAGE}");
+                    return ExitCode::from(2);
+                }
+            },
+            "--dir" => match args.next() {
+                Some(value) => dir = PathBuf::from(value),
+                None => {
+                    eprintln!("{USAGE}");
+                    return ExitCode::from(2);
+                }
+            },
+            _ if baseline.is_none() && !arg.starts_with('-') => baseline = Some(arg),
+            _ => {
+                eprintln!("{USAGE}");
+                return ExitCode::from(2);
+            }
+        }
+    }
+    let Some(baseline) = baseline else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+
+    let comparisons = match compare(&dir, &baseline) {
+        Ok(comparisons) => comparisons,
+        Err(error) => {
+            eprintln!("{error}");
+            return ExitCode::from(2);
+        }
+    };
+    let mut regressions = 0;
+    for comparison in &comparisons {
+        let flag = if comparison.is_regression(threshold) {
+            regressions += 1;
+            "  REGRESSED"
+        } else {
+            ""
+        };
+        println!(
+            "{:<48} {:>14.1} ns -> {:>14.1} ns  {:>+7.1}%{flag}",
+            comparison.benchmark,
+            comparison.baseline,
+            comparison.current,
+            comparison.change() * 100.0
+        );
+    }
+    if regressions > 0 {
+        eprintln!(
+            "{regressions} of {} benchmarks regressed by more than {:.0}%",
+            comparisons.len(),
+            threshold * 100.0
+        );
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
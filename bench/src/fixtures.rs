@@ -0,0 +1,151 @@
+// Protocols the benchmarks run on
+//
+// Each fixture stresses one dimension of the compiler: the number of steps,
+// the number of roles, nested choices and loops. Sizes are picked so the
+// largest fixtures take milliseconds rather than seconds to compile.
+
+use std::fmt::Write;
+
+/// A named protocol in the choreography DSL
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    pub name: &'static str,
+    pub source: String,
+}
+
+/// Every fixture, smallest first
+#[must_use]
+pub fn fixtures() -> Vec<Fixture> {
+    vec![
+        Fixture {
+            name: "ping_pong",
+            source: pipeline("PingPong", 2),
+        },
+        Fixture {
+            name: "pipeline_16",
+            source: pipeline("Pipeline", 16),
+        },
+        Fixture {
+            name: "pipeline_64",
+            source: pipeline("Pipeline", 64),
+        },
+        Fixture {
+            name: "choices_5",
+            source: choices(5),
+        },
+        Fixture {
+            name: "ring_loop_8",
+            source: ring_loop(8),
+        },
+        Fixture {
+            name: "sequence_1000",
+            source: sequence(8, 1000),
+        },
+    ]
+}
+
+/// `stages` roles passing a message along, the last one answering the first
+#[must_use]
+pub fn pipeline(name: &str, stages: usize) -> String {
+    let roles = role_names(stages);
+    let mut source = format!("choreography {name} {{\n    roles: {}\n", roles.join(", "));
+    for (index, pair) in roles.windows(2).enumerate() {
+        let _ = writeln!(source, "    {} -> {}: Hop{index}", pair[0], pair[1]);
+    }
+    let _ = writeln!(source, "    {} -> {}: Done", roles[stages - 1], roles[0]);
+    source.push_str("}\n");
+    source
+}
+
+/// A client and server with choices nested `depth` deep in both branches
+#[must_use]
+pub fn choices(depth: usize) -> String {
+    let mut source = String::from("choreography Choices {\n    roles: Client, Server\n");
+    nested_choice(&mut source, depth, 1);
+    source.push_str("}\n");
+    source
+}
+
+fn nested_choice(source: &mut String, depth: usize, level: usize) {
+    let indent = "    ".repeat(level);
+    if depth == 0 {
+        let _ = writeln!(source, "{indent}Server -> Client: Result");
+        return;
+    }
+    let _ = writeln!(source, "{indent}choice Client {{");
+    for side in ["Left", "Right"] {
+        let _ = writeln!(source, "{indent}    {}: {{", side.to_lowercase());
+        let _ = writeln!(source, "{indent}        Client -> Server: {side}{depth}");
+        nested_choice(source, depth - 1, level + 2);
+        let _ = writeln!(source, "{indent}    }}");
+    }
+    let _ = writeln!(source, "{indent}}}");
+}
+
+/// A token passed around a ring of `roles` roles until the first decides to
+/// stop
+#[must_use]
+pub fn ring_loop(roles: usize) -> String {
+    let names = role_names(roles);
+    let mut source = format!("choreography Ring {{\n    roles: {}\n", names.join(", "));
+    let _ = writeln!(source, "    loop (decides: {}) {{", names[0]);
+    for (index, from) in names.iter().enumerate() {
+        let to = &names[(index + 1) % roles];
+        let _ = writeln!(source, "        {from} -> {to}: Token");
+    }
+    source.push_str("    }\n}\n");
+    source
+}
+
+/// `statements` messages passed around a ring of `roles` roles, for the
+/// per-statement cost of parsing and projection
+#[must_use]
+pub fn sequence(roles: usize, statements: usize) -> String {
+    let names = role_names(roles);
+    let mut source = format!(
+        "choreography Sequence {{\n    roles: {}\n",
+        names.join(", ")
+    );
+    for index in 0..statements {
+        let from = &names[index % roles];
+        let to = &names[(index + 1) % roles];
+        let _ = writeln!(source, "    {from} -> {to}: Step{}", index % 16);
+    }
+    source.push_str("}\n");
+    source
+}
+
+fn role_names(count: usize) -> Vec<String> {
+    (0..count).map(|index| format!("Stage{index}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rumpsteak_aura_choreography::compiler::{parser::parse_choreography_str, projection};
+
+    #[test]
+    fn test_fixtures_compile() {
+        // Projection and code generation recurse once per statement, deeper
+        // than the default test thread stack allows in debug builds
+        std::thread::Builder::new()
+            .stack_size(64 * 1024 * 1024)
+            .spawn(compile_fixtures)
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    fn compile_fixtures() {
+        for fixture in fixtures() {
+            let choreography = parse_choreography_str(&fixture.source)
+                .unwrap_or_else(|e| panic!("{}: {e}", fixture.name));
+            choreography.validate().unwrap();
+            for role in &choreography.roles {
+                projection::project(&choreography, role).unwrap();
+            }
+            rumpsteak_aura_choreography::compile_choreography_with_extensions(&fixture.source)
+                .unwrap_or_else(|e| panic!("{}: {e}", fixture.name));
+        }
+    }
+}
@@ -0,0 +1,16 @@
+//! Benchmarks and performance regression checks
+//!
+//! The criterion benchmarks under `benches/` measure the compiler (parsing,
+//! projection and `choreography!` expansion) and the runtime (message
+//! throughput and latency over the in-process and TCP transports). This
+//! library holds what they share: the protocols they run on, the scratch
+//! crate timing expansion in a real build, and the comparison of a run
+//! against a saved baseline done by the `bench-compare` binary.
+
+pub mod fixtures;
+pub mod regression;
+pub mod scratch;
+
+pub use fixtures::{fixtures, Fixture};
+pub use regression::{compare, Comparison, RegressionError};
+pub use scratch::ScratchCrate;
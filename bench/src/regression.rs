@@ -0,0 +1,168 @@
+// Comparison of a benchmark run against a saved baseline
+//
+// Criterion writes the estimates of each benchmark to
+// `<criterion dir>/<group>/<benchmark>/<baseline>/estimates.json`, the latest
+// run under `new` and runs saved with `--save-baseline <name>` under
+// `<name>`. `compare` pairs every benchmark's baseline with its latest run,
+// so a change can be checked against `main` without reading criterion's
+// report by hand.
+
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// Baseline and latest mean time of one benchmark
+#[derive(Debug, Clone, PartialEq)]
+pub struct Comparison {
+    /// Path of the benchmark under the criterion directory, such as
+    /// `parse/pipeline_64`
+    pub benchmark: String,
+    /// Mean time per iteration in the baseline, in nanoseconds
+    pub baseline: f64,
+    /// Mean time per iteration in the latest run, in nanoseconds
+    pub current: f64,
+}
+
+impl Comparison {
+    /// Relative change of the mean, `0.1` for 10% slower
+    #[must_use]
+    pub fn change(&self) -> f64 {
+        self.current / self.baseline - 1.0
+    }
+
+    /// Whether the benchmark got slower by more than `threshold`
+    #[must_use]
+    pub fn is_regression(&self, threshold: f64) -> bool {
+        self.change() > threshold
+    }
+}
+
+/// Errors reading criterion's output
+#[derive(Debug, thiserror::Error)]
+pub enum RegressionError {
+    #[error("Failed to read {path}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Failed to parse {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("No benchmark under {dir} has both a '{baseline}' baseline and a latest run")]
+    NoBaseline { dir: PathBuf, baseline: String },
+}
+
+#[derive(Deserialize)]
+struct Estimates {
+    mean: Estimate,
+}
+
+#[derive(Deserialize)]
+struct Estimate {
+    point_estimate: f64,
+}
+
+/// Compare the latest run of every benchmark under `criterion_dir` with its
+/// run saved as `baseline`, sorted by benchmark
+pub fn compare(criterion_dir: &Path, baseline: &str) -> Result<Vec<Comparison>, RegressionError> {
+    let mut comparisons = Vec::new();
+    collect(criterion_dir, criterion_dir, baseline, &mut comparisons)?;
+    if comparisons.is_empty() {
+        return Err(RegressionError::NoBaseline {
+            dir: criterion_dir.to_path_buf(),
+            baseline: baseline.to_string(),
+        });
+    }
+    comparisons.sort_by(|a, b| a.benchmark.cmp(&b.benchmark));
+    Ok(comparisons)
+}
+
+fn collect(
+    root: &Path,
+    dir: &Path,
+    baseline: &str,
+    comparisons: &mut Vec<Comparison>,
+) -> Result<(), RegressionError> {
+    let saved = dir.join(baseline).join("estimates.json");
+    let latest = dir.join("new").join("estimates.json");
+    if saved.is_file() && latest.is_file() {
+        let benchmark = dir
+            .strip_prefix(root)
+            .unwrap_or(dir)
+            .to_string_lossy()
+            .replace('\\', "/");
+        comparisons.push(Comparison {
+            benchmark,
+            baseline: mean(&saved)?,
+            current: mean(&latest)?,
+        });
+        return Ok(());
+    }
+    let entries = std::fs::read_dir(dir).map_err(|source| RegressionError::Io {
+        path: dir.to_path_buf(),
+        source,
+    })?;
+    for entry in entries.flatten() {
+        let path = entry.path();
+        // Criterion's HTML report is not a benchmark
+        if path.is_dir() && entry.file_name() != "report" {
+            collect(root, &path, baseline, comparisons)?;
+        }
+    }
+    Ok(())
+}
+
+fn mean(path: &Path) -> Result<f64, RegressionError> {
+    let json = std::fs::read_to_string(path).map_err(|source| RegressionError::Io {
+        path: path.to_path_buf(),
+        source,
+    })?;
+    let estimates: Estimates =
+        serde_json::from_str(&json).map_err(|source| RegressionError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })?;
+    Ok(estimates.mean.point_estimate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_estimate(dir: &Path, benchmark: &str, baseline: &str, mean: f64) {
+        let dir = dir.join(benchmark).join(baseline);
+        std::fs::create_dir_all(&dir).unwrap();
+        let json = format!(
+            r#"{{"mean":{{"confidence_interval":{{"confidence_level":0.95,"lower_bound":{mean},"upper_bound":{mean}}},"point_estimate":{mean},"standard_error":0.0}}}}"#
+        );
+        std::fs::write(dir.join("estimates.json"), json).unwrap();
+    }
+
+    #[test]
+    fn test_compare_finds_regressions() {
+        let dir = tempfile::tempdir().unwrap();
+        write_estimate(dir.path(), "parse/ping_pong", "main", 100.0);
+        write_estimate(dir.path(), "parse/ping_pong", "new", 130.0);
+        write_estimate(dir.path(), "project/ping_pong", "main", 200.0);
+        write_estimate(dir.path(), "project/ping_pong", "new", 190.0);
+        // Without a baseline there is nothing to compare
+        write_estimate(dir.path(), "project/pipeline_64", "new", 500.0);
+
+        let comparisons = compare(dir.path(), "main").unwrap();
+        assert_eq!(comparisons.len(), 2);
+        assert_eq!(comparisons[0].benchmark, "parse/ping_pong");
+        assert!(comparisons[0].is_regression(0.1));
+        assert!(!comparisons[1].is_regression(0.1));
+        assert!((comparisons[1].change() + 0.05).abs() < 1e-9);
+
+        assert!(matches!(
+            compare(dir.path(), "other"),
+            Err(RegressionError::NoBaseline { .. })
+        ));
+    }
+}
@@ -0,0 +1,85 @@
+// Scratch crate timing `choreography!` expansion in a real build
+//
+// Like trybuild, the benchmark writes a crate invoking the macro next to the
+// workspace and builds it with cargo. Dependencies are built by the first
+// `check`; later ones rebuild only the scratch crate, so they measure the
+// expansion and the type checking of the generated session types.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// A crate whose only item is one `choreography!` invocation
+pub struct ScratchCrate {
+    dir: PathBuf,
+}
+
+impl ScratchCrate {
+    /// Create the crate in `dir`, depending on the workspace at `workspace`
+    pub fn new(dir: impl Into<PathBuf>, workspace: &Path) -> io::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(dir.join("src"))?;
+        let manifest = format!(
+            "[package]\nname = \"choreography-expansion\"\nversion = \"0.0.0\"\nedition = \"2021\"\npublish = false\n\n\
+             [dependencies]\nfutures = \"0.3\"\nrumpsteak-aura = {{ path = {:?} }}\nrumpsteak-aura-macros = {{ path = {:?} }}\n\n\
+             [workspace]\n",
+            workspace,
+            workspace.join("macros"),
+        );
+        std::fs::write(dir.join("Cargo.toml"), manifest)?;
+        // Build with the versions the workspace is tested against
+        let lockfile = workspace.join("Cargo.lock");
+        if lockfile.exists() {
+            std::fs::copy(lockfile, dir.join("Cargo.lock"))?;
+        }
+        Ok(Self { dir })
+    }
+
+    /// Make the crate's protocol a client and server exchanging `steps`
+    /// messages
+    ///
+    /// `rumpsteak_aura_macros::choreography!` connects two roles, so the
+    /// protocol grows in length rather than roles.
+    pub fn set_exchange(&self, steps: usize) -> io::Result<()> {
+        let sends: String = (0..steps)
+            .map(|index| {
+                let (from, to) = if index % 2 == 0 {
+                    ("Client", "Server")
+                } else {
+                    ("Server", "Client")
+                };
+                format!("        {from} -> {to}: Step{index};\n")
+            })
+            .collect();
+        let source = format!(
+            "rumpsteak_aura_macros::choreography! {{\n    protocol Exchange {{\n        roles: Client, Server;\n{sends}    }}\n}}\n"
+        );
+        std::fs::write(self.dir.join("src/lib.rs"), source)
+    }
+
+    /// Run `cargo check` on the crate, returning how long it took
+    pub fn check(&self) -> io::Result<Duration> {
+        let cargo = std::env::var_os("CARGO").unwrap_or_else(|| "cargo".into());
+        let start = Instant::now();
+        let output = Command::new(cargo)
+            .args(["check", "--quiet"])
+            .current_dir(&self.dir)
+            .env("CARGO_TARGET_DIR", self.dir.join("target"))
+            .output()?;
+        let elapsed = start.elapsed();
+        if !output.status.success() {
+            return Err(io::Error::other(
+                String::from_utf8_lossy(&output.stderr).into_owned(),
+            ));
+        }
+        Ok(elapsed)
+    }
+
+    /// Mark the crate's source as changed so the next `check` rebuilds it
+    pub fn touch(&self) -> io::Result<()> {
+        let path = self.dir.join("src/lib.rs");
+        let source = std::fs::read(&path)?;
+        std::fs::write(path, source)
+    }
+}
@@ -12,7 +12,7 @@
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
 use quote::format_ident;
 use rumpsteak_aura_choreography::{
-    ast::{Branch, Choreography, Condition, MessageType, Protocol, Role},
+    ast::{Branch, Choreography, Condition, MessageType, Protocol, Role, Span},
     compiler::{codegen::generate_session_type, projection::project},
     effects::{interpret, NoOpHandler, Program},
 };
@@ -50,9 +50,16 @@ fn create_simple_choreography() -> Choreography {
                 from_annotations: HashMap::new(),
                 to_annotations: HashMap::new(),
                 continuation: Box::new(Protocol::End),
+                span: Span::default(),
             }),
+            span: Span::default(),
         },
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     }
 }
 
@@ -86,6 +93,7 @@ fn create_complex_choreography() -> Choreography {
                         Branch {
                             label: format_ident!("Accept"),
                             guard: None,
+                            probability: None,
                             protocol: Protocol::Send {
                                 from: bob.clone(),
                                 to: charlie.clone(),
@@ -98,11 +106,14 @@ fn create_complex_choreography() -> Choreography {
                                 from_annotations: HashMap::new(),
                                 to_annotations: HashMap::new(),
                                 continuation: Box::new(Protocol::End),
+                                span: Span::default(),
                             },
+                            span: Span::default(),
                         },
                         Branch {
                             label: format_ident!("Reject"),
                             guard: None,
+                            probability: None,
                             protocol: Protocol::Send {
                                 from: bob.clone(),
                                 to: alice.clone(),
@@ -115,13 +126,23 @@ fn create_complex_choreography() -> Choreography {
                                 from_annotations: HashMap::new(),
                                 to_annotations: HashMap::new(),
                                 continuation: Box::new(Protocol::End),
+                                span: Span::default(),
                             },
+                            span: Span::default(),
                         },
                     ],
+                    span: Span::default(),
                 }),
+                span: Span::default(),
             }),
+            span: Span::default(),
         },
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     }
 }
 
@@ -287,6 +308,7 @@ fn bench_scaling(c: &mut Criterion) {
                 from_annotations: HashMap::new(),
                 to_annotations: HashMap::new(),
                 continuation: Box::new(protocol),
+                span: Span::default(),
             };
         }
 
@@ -297,6 +319,11 @@ fn bench_scaling(c: &mut Criterion) {
             roles: vec![alice, bob],
             protocol,
             attrs: HashMap::new(),
+            policy: None,
+            state: Vec::new(),
+            messages: Vec::new(),
+            unreachable: Vec::new(),
+            scopes: Vec::new(),
         };
 
         group.bench_with_input(
@@ -105,7 +105,7 @@ fn bench_choice_overhead(c: &mut Criterion) {
                     .unwrap();
 
                 let _received_label = bob_handler
-                    .offer(&mut bob_ep, BenchRole::Alice)
+                    .offer(&mut bob_ep, BenchRole::Alice, &[label])
                     .await
                     .unwrap();
             })
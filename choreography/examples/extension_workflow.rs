@@ -338,7 +338,12 @@ impl ChoreoHandler for WorkflowHandler {
         Ok(())
     }
 
-    async fn offer(&mut self, _ep: &mut Self::Endpoint, from: Self::Role) -> Result<Label> {
+    async fn offer(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        from: Self::Role,
+        _labels: &[Label],
+    ) -> Result<Label> {
         println!("[{:?}] Offering choice from {:?}", self.role, from);
         Ok(Label("default"))
     }
@@ -25,8 +25,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     for role in &choreography.roles {
         if let Some(size) = &role.array_size {
             println!("    - {} (array size: {})", role.name, quote::quote!(#size));
-        } else if role.index.is_some() {
-            println!("    - {}[{}]", role.name, role.index.as_ref().unwrap());
+        } else if let Some(index) = &role.index {
+            println!("    - {}[{}]", role.name, index);
         } else {
             println!("    - {}", role.name);
         }
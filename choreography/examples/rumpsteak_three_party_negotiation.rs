@@ -144,7 +144,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .await?;
     }
 
-    let buyer_decision = broker_handler.offer(&mut broker_ep, Role::Buyer).await?;
+    let buyer_decision = broker_handler
+        .offer(
+            &mut broker_ep,
+            Role::Buyer,
+            &[Label("accept"), Label("reject")],
+        )
+        .await?;
     println!("\nPhase 6: Broker processes decision");
 
     if buyer_decision.0 == "accept" {
@@ -0,0 +1,154 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Native roles serving a browser role over WebSocket
+//
+// The Gateway and Worker roles run here; the Browser role runs in WASM (see
+// examples/wasm-websocket) and connects over WebSocket. The browser asks a
+// question, the gateway forwards it to the worker over an in-process channel
+// and relays the answer back.
+//
+// Run with: cargo run --example websocket_gateway --features websocket
+// Pass `--self-test` to play the browser role from native code instead.
+
+use rumpsteak_aura_choreography::effects::{
+    handlers::rumpsteak::{RumpsteakEndpoint, RumpsteakHandler, RumpsteakSession, SimpleChannel},
+    ChoreoHandler, ChoreographyError, Result,
+};
+use rumpsteak_aura_choreography::runtime::provider::{DefaultRuntime, RuntimeProvider};
+use rumpsteak_aura_choreography::runtime::websocket::WebSocketTransport;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use tokio::net::TcpListener;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+// Must match the role and message types of the browser crate
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Role {
+    Browser,
+    Gateway,
+    Worker,
+}
+
+impl rumpsteak_aura::Role for Role {
+    type Message = Message;
+
+    fn seal(&mut self) {}
+    fn is_sealed(&self) -> bool {
+        false
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Message {
+    Question(String),
+    Answer(String),
+}
+
+impl rumpsteak_aura::Message<Box<dyn std::any::Any + Send>> for Message {
+    fn upcast(msg: Box<dyn std::any::Any + Send>) -> Self {
+        *msg.downcast::<Message>().unwrap()
+    }
+
+    fn downcast(self) -> std::result::Result<Box<dyn std::any::Any + Send>, Self> {
+        Ok(Box::new(self))
+    }
+}
+
+/// Run the Gateway and Worker roles for one connected browser
+async fn serve(browser: RumpsteakSession) -> Result<()> {
+    let mut gateway_ep = RumpsteakEndpoint::new(Role::Gateway);
+    let mut worker_ep = RumpsteakEndpoint::new(Role::Worker);
+    gateway_ep.register_session(Role::Browser, browser);
+    let (gateway_ch, worker_ch) = SimpleChannel::pair();
+    gateway_ep.register_session(
+        Role::Worker,
+        RumpsteakSession::from_simple_channel(gateway_ch),
+    );
+    worker_ep.register_session(
+        Role::Gateway,
+        RumpsteakSession::from_simple_channel(worker_ch),
+    );
+
+    let worker = tokio::spawn(async move {
+        let mut handler = RumpsteakHandler::<Role, Message>::new();
+        let question: Message = handler.recv(&mut worker_ep, Role::Gateway).await?;
+        let Message::Question(text) = question else {
+            return Err(ChoreographyError::ProtocolViolation(format!(
+                "expected a question, got {question:?}"
+            )));
+        };
+        let answer = Message::Answer(format!("'{text}' has {} characters", text.len()));
+        handler.send(&mut worker_ep, Role::Gateway, &answer).await
+    });
+
+    let mut handler = RumpsteakHandler::<Role, Message>::new();
+    let question: Message = handler.recv(&mut gateway_ep, Role::Browser).await?;
+    println!("Gateway: browser asked {question:?}");
+    handler
+        .send(&mut gateway_ep, Role::Worker, &question)
+        .await?;
+    let answer: Message = handler.recv(&mut gateway_ep, Role::Worker).await?;
+    handler
+        .send(&mut gateway_ep, Role::Browser, &answer)
+        .await?;
+    worker
+        .await
+        .map_err(|e| ChoreographyError::Transport(e.to_string()))?
+}
+
+/// The Browser role, played natively for `--self-test`
+async fn ask(address: SocketAddr, question: &str) -> Result<String> {
+    let stream = DefaultRuntime::connect_tcp(address)
+        .await
+        .map_err(|e| ChoreographyError::Transport(e.to_string()))?;
+    let session = WebSocketTransport::new()
+        .connect(&address.to_string(), "/", stream)
+        .await?;
+    let mut endpoint = RumpsteakEndpoint::new(Role::Browser);
+    endpoint.register_session(Role::Gateway, session);
+
+    let mut handler = RumpsteakHandler::<Role, Message>::new();
+    let question = Message::Question(question.to_string());
+    handler
+        .send(&mut endpoint, Role::Gateway, &question)
+        .await?;
+    match handler.recv(&mut endpoint, Role::Gateway).await? {
+        Message::Answer(answer) => Ok(answer),
+        other => Err(ChoreographyError::ProtocolViolation(format!(
+            "expected an answer, got {other:?}"
+        ))),
+    }
+}
+
+#[tokio::main]
+async fn main() -> std::result::Result<(), Box<dyn std::error::Error>> {
+    let self_test = std::env::args().any(|arg| arg == "--self-test");
+    let listener = TcpListener::bind("127.0.0.1:8081").await?;
+    let address = listener.local_addr()?;
+    println!("=== WebSocket Gateway on ws://{address} ===\n");
+
+    let browser = self_test.then(|| tokio::spawn(async move { ask(address, "ping").await }));
+
+    let transport = WebSocketTransport::new();
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        println!("Browser connected from {peer}");
+        match transport.accept(stream.compat()).await {
+            Ok(session) => {
+                if let Err(e) = serve(session).await {
+                    println!("Session failed: {e}");
+                }
+            }
+            Err(e) => println!("Handshake failed: {e}"),
+        }
+        if self_test {
+            break;
+        }
+    }
+
+    if let Some(browser) = browser {
+        println!("Browser: {}", browser.await??);
+    }
+    Ok(())
+}
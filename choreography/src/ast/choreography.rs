@@ -1,9 +1,24 @@
 // Choreography struct definition and validation
 
-use super::{Protocol, Role, ValidationError};
+use super::policy::policy_steps;
+use super::{
+    LocalType, Policy, Protocol, Role, RoleBoundsChecker, RoleState, Span, ValidationError, DOC,
+};
+use crate::compiler::atomic_commit::check_atomic_commit;
+use crate::compiler::info_flow::check_information_flow;
+use crate::compiler::message_usage::{check_orphan_messages, check_unused_messages};
+use crate::compiler::projection::ProjectionError;
 use proc_macro2::Ident;
 use std::collections::HashMap;
 
+/// Attribute prefix of the transport binding an `external` role, as in
+/// `external.Api = "http"`
+pub const EXTERNAL: &str = "external";
+
+/// Attribute listing the names used by statements and branches that a
+/// `#[cfg(...)]` condition disabled, separated by spaces
+pub const CFG_DISABLED: &str = "cfg_disabled";
+
 /// A complete choreographic protocol specification
 #[derive(Debug)]
 pub struct Choreography {
@@ -17,6 +32,19 @@ pub struct Choreography {
     pub protocol: Protocol,
     /// Metadata and attributes
     pub attrs: HashMap<String, String>,
+    /// Authorization policy, from the `policy` block
+    pub policy: Option<Policy>,
+    /// Local state of roles, from `state at Role { ... }` declarations
+    pub state: Vec<RoleState>,
+    /// Message types listed in `messages: ...`, with the location of each
+    /// name
+    pub messages: Vec<(Ident, Span)>,
+    /// Statements left out of `protocol` because they can never run, such
+    /// as those after a `loop` that is never left
+    pub unreachable: Vec<Span>,
+    /// Nested choreographies, from `choreography Name { private roles: ...;
+    /// ... }` statements, whose private roles `protocol` leaves out
+    pub scopes: Vec<Choreography>,
 }
 
 impl Choreography {
@@ -30,17 +58,90 @@ impl Choreography {
 
     /// Validate the choreography for correctness
     pub fn validate(&self) -> Result<(), ValidationError> {
+        match self.validate_all().into_iter().next() {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    /// Every validation error of the choreography, in the order `validate`
+    /// would find them
+    #[must_use]
+    pub fn validate_all(&self) -> Vec<ValidationError> {
         // Check all roles are used
-        for role in &self.roles {
-            if !self.protocol.mentions_role(role) {
-                return Err(ValidationError::UnusedRole(role.name.to_string()));
+        let mut errors: Vec<ValidationError> = self
+            .roles
+            .iter()
+            .filter(|role| {
+                !self.protocol.mentions_role(role)
+                    && !self.used_when_disabled(&role.name.to_string())
+            })
+            .map(|role| ValidationError::UnusedRole(role.name.to_string()))
+            .collect();
+
+        // Check protocol is well-formed
+        self.protocol
+            .collect_validation_errors(&self.roles, &mut errors);
+
+        // Check role indices stay within their family for every count it
+        // may have
+        let checker = RoleBoundsChecker::default();
+        let mut references = Vec::new();
+        role_references(&self.protocol, &mut references);
+        let mut out_of_bounds = Vec::new();
+        for reference in references {
+            let (Some(index), Some(family)) = (
+                &reference.index,
+                self.roles.iter().find(|role| role.name == reference.name),
+            ) else {
+                continue;
+            };
+            if let Err(error) = checker.check_reference(family, index) {
+                if !out_of_bounds.contains(&error) {
+                    out_of_bounds.push(error);
+                }
             }
         }
+        errors.extend(out_of_bounds.into_iter().map(ValidationError::RoleBounds));
 
-        // Check protocol is well-formed
-        self.protocol.validate(&self.roles)?;
+        // Check no role steps outside its policy
+        if let Some(policy) = &self.policy {
+            errors.extend(
+                policy_steps(&self.protocol)
+                    .into_iter()
+                    .filter(|step| !policy.permits(&step.role, step.permission))
+                    .map(|step| ValidationError::PolicyViolation {
+                        role: step.role.to_string(),
+                        permission: step.permission.to_string(),
+                        action: step.action.to_string(),
+                    }),
+            );
+        }
+
+        // Check labeled payload fields stay within their label
+        errors.extend(check_information_flow(self));
+
+        // Check every message sent is received, unless the errors above
+        // already explain why some are not
+        if errors.is_empty() {
+            errors.extend(check_orphan_messages(self));
+        }
+
+        // Check the two-phase commits of `atomic` blocks are safe
+        errors.extend(check_atomic_commit(self));
 
-        Ok(())
+        // Check declared messages are sent
+        errors.extend(check_unused_messages(self));
+        errors
+    }
+
+    /// Whether `name` is used by a statement or branch that a `#[cfg(...)]`
+    /// condition disabled, and so is used in another build
+    #[must_use]
+    pub fn used_when_disabled(&self, name: &str) -> bool {
+        self.attrs
+            .get(CFG_DISABLED)
+            .is_some_and(|names| names.split(' ').any(|used| used == name))
     }
 
     /// Get choreography-level attributes/annotations
@@ -121,6 +222,55 @@ impl Choreography {
         }
     }
 
+    /// Local type of `role`: what it is obligated to do in the protocol
+    ///
+    /// Render it with [`LocalType::to_pretty_string`] to review a role's part
+    /// without reading generated code.
+    ///
+    /// # Errors
+    ///
+    /// [`ProjectionError`] if the protocol cannot be projected onto `role`.
+    pub fn project(&self, role: &Role) -> Result<LocalType, ProjectionError> {
+        crate::compiler::projection::project(self, role)
+    }
+
+    /// Local types of every role, in declaration order
+    ///
+    /// # Errors
+    ///
+    /// The first [`ProjectionError`], if any role cannot be projected.
+    pub fn project_all(&self) -> Result<Vec<(Role, LocalType)>, ProjectionError> {
+        self.roles
+            .iter()
+            .map(|role| Ok((role.clone(), self.project(role)?)))
+            .collect()
+    }
+
+    /// Transport of `role` if it is declared `external`, such as `"http"`
+    pub fn external_transport(&self, role: &Ident) -> Option<&str> {
+        self.attrs
+            .get(&format!("{EXTERNAL}.{role}"))
+            .map(String::as_str)
+    }
+
+    /// `///` doc comment on the declaration of `role`
+    pub fn role_doc(&self, role: &Ident) -> Option<&str> {
+        self.attrs.get(&format!("{DOC}.{role}")).map(String::as_str)
+    }
+
+    /// The local state declared for `role`, if any
+    #[must_use]
+    pub fn role_state(&self, role: &Ident) -> Option<&RoleState> {
+        self.state.iter().find(|state| state.role == *role)
+    }
+
+    /// Roles implemented by a service outside the session
+    pub fn external_roles(&self) -> impl Iterator<Item = &Role> {
+        self.roles
+            .iter()
+            .filter(|role| self.external_transport(&role.name).is_some())
+    }
+
     /// Find all protocol nodes with a specific annotation
     pub fn find_nodes_with_annotation(&self, key: &str) -> Vec<&Protocol> {
         let mut nodes = Vec::new();
@@ -141,3 +291,44 @@ impl Choreography {
         self.attribute_count() + self.protocol.deep_annotation_count()
     }
 }
+
+/// Role references of `protocol`, with repeats
+fn role_references<'a>(protocol: &'a Protocol, references: &mut Vec<&'a Role>) {
+    match protocol {
+        Protocol::Send {
+            from,
+            to,
+            continuation,
+            ..
+        } => {
+            references.extend([from, to]);
+            role_references(continuation, references);
+        }
+        Protocol::Broadcast {
+            from,
+            to_all,
+            continuation,
+            ..
+        } => {
+            references.push(from);
+            references.extend(to_all);
+            role_references(continuation, references);
+        }
+        Protocol::Choice { role, branches, .. } => {
+            references.push(role);
+            for branch in branches {
+                role_references(&branch.protocol, references);
+            }
+        }
+        Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => {
+            role_references(body, references);
+        }
+        Protocol::Parallel { protocols, .. } => {
+            for protocol in protocols {
+                role_references(protocol, references);
+            }
+        }
+        Protocol::Extension { continuation, .. } => role_references(continuation, references),
+        Protocol::Var(_) | Protocol::End => {}
+    }
+}
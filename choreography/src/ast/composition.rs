@@ -0,0 +1,643 @@
+// Composition of whole choreographies
+//
+// Tools that assemble protocols from a library of fragments combine parsed
+// choreographies with three builders:
+//
+//     let order = login.then(payment)?;          // payment after login
+//     let sync = upload.par(index)?;             // both at once
+//     let pay = card.choice("Shop", vec![cash])?; // Shop picks `card` or `cash`
+//
+// The result keeps the name, namespace and attributes of the receiver, and
+// takes in the roles, attributes, policy rules and role state of the other
+// parts. A role, or its state, declared in several parts must be declared
+// alike. A message of another
+// part named like one of the receiver's but with a different payload is
+// renamed `<Part><Message>`, so generated message types stay distinct.
+//
+// Private roles of nested choreographies are already left out of each
+// part's protocol, so parts may use the same private role names freely;
+// their nested choreographies are kept side by side.
+//
+// Statements keep their spans, which point into the source each part was
+// parsed from.
+
+use super::{Branch, Choreography, MessageType, Protocol, RoleState, Span, DERIVED_FROM};
+use crate::compiler::handler_codegen::snake_case;
+use quote::format_ident;
+use std::collections::{HashMap, HashSet};
+
+/// Errors raised while composing choreographies
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum CompositionError {
+    #[error("Role {0} is declared differently in the composed choreographies")]
+    IncompatibleRole(String),
+
+    #[error("State of role {0} is declared differently in the composed choreographies")]
+    IncompatibleState(String),
+
+    #[error("Deciding role {0} is not a role of the composed choreographies")]
+    UndefinedDecider(String),
+
+    #[error("Branch {0} appears twice in the composed choice")]
+    DuplicateLabel(String),
+
+    #[error("Cannot continue after a {0}, which has no continuation")]
+    NoContinuation(&'static str),
+
+    #[error("Cannot copy extension statement {0} onto several endings")]
+    ExtensionCopy(&'static str),
+}
+
+impl Choreography {
+    /// Run `other` once this choreography has ended, on every path
+    ///
+    /// # Errors
+    ///
+    /// [`CompositionError`] if a role is declared differently in both, if a
+    /// path of this choreography ends in a loop or parallel block, or if
+    /// `other` holds an extension statement that would have to be copied
+    /// onto several endings.
+    pub fn then(mut self, other: Choreography) -> Result<Choreography, CompositionError> {
+        let mut known = messages(&mut self.protocol);
+        let next = self.absorb(other, &mut known)?;
+        let protocol = std::mem::replace(&mut self.protocol, Protocol::End);
+        self.protocol = if endings(&protocol)? > 1 {
+            append(protocol, &mut || copy(&next))?
+        } else {
+            let mut next = Some(next);
+            append(protocol, &mut || Ok(next.take().unwrap_or(Protocol::End)))?
+        };
+        Ok(self)
+    }
+
+    /// Run `other` in parallel with this choreography
+    ///
+    /// # Errors
+    ///
+    /// [`CompositionError::IncompatibleRole`] if a role is declared
+    /// differently in both.
+    pub fn par(mut self, other: Choreography) -> Result<Choreography, CompositionError> {
+        let mut known = messages(&mut self.protocol);
+        let other = self.absorb(other, &mut known)?;
+        let mut protocols = Vec::new();
+        for protocol in [std::mem::replace(&mut self.protocol, Protocol::End), other] {
+            match protocol {
+                Protocol::Parallel { protocols: p, .. } => protocols.extend(p),
+                protocol => protocols.push(protocol),
+            }
+        }
+        self.protocol = Protocol::Parallel {
+            protocols,
+            span: Span::default(),
+        };
+        Ok(self)
+    }
+
+    /// Let `decider` choose between this choreography and each of `others`
+    ///
+    /// Branches are labeled with the choreography names in snake case.
+    ///
+    /// # Errors
+    ///
+    /// [`CompositionError`] if a role is declared differently in two parts,
+    /// if `decider` is not a role of any part, or if two parts have the same
+    /// name.
+    pub fn choice(
+        mut self,
+        decider: &str,
+        others: Vec<Choreography>,
+    ) -> Result<Choreography, CompositionError> {
+        let mut known = messages(&mut self.protocol);
+        let mut branches = vec![(snake_case(&self.name.to_string()), Protocol::End)];
+        for other in others {
+            let label = snake_case(&other.name.to_string());
+            branches.push((label, self.absorb(other, &mut known)?));
+        }
+        branches[0].1 = std::mem::replace(&mut self.protocol, Protocol::End);
+
+        let mut labels = HashSet::new();
+        if let Some((label, _)) = branches.iter().find(|(label, _)| !labels.insert(label)) {
+            return Err(CompositionError::DuplicateLabel(label.clone()));
+        }
+        let role = self
+            .roles
+            .iter()
+            .find(|role| role.name == decider)
+            .cloned()
+            .ok_or_else(|| CompositionError::UndefinedDecider(decider.to_string()))?;
+
+        self.protocol = Protocol::Choice {
+            role,
+            branches: branches
+                .into_iter()
+                .map(|(label, protocol)| Branch {
+                    label: format_ident!("{}", label),
+                    guard: None,
+                    probability: None,
+                    protocol,
+                    span: Span::default(),
+                })
+                .collect(),
+            annotations: HashMap::new(),
+            span: Span::default(),
+        };
+        Ok(self)
+    }
+
+    /// Take in the roles, attributes, policy and state of `other`, and return its
+    /// protocol with the messages clashing with `known` ones renamed
+    ///
+    /// The messages of the returned protocol are added to `known`.
+    fn absorb(
+        &mut self,
+        other: Choreography,
+        known: &mut HashMap<String, MessageType>,
+    ) -> Result<Protocol, CompositionError> {
+        for role in other.roles {
+            match self.roles.iter().find(|known| known.name == role.name) {
+                Some(known) if known.param != role.param => {
+                    return Err(CompositionError::IncompatibleRole(role.name.to_string()));
+                }
+                Some(_) => {}
+                None => self.roles.push(role),
+            }
+        }
+        for (key, value) in other.attrs {
+            self.attrs.entry(key).or_insert(value);
+        }
+        match (&mut self.policy, other.policy) {
+            (Some(policy), Some(other)) => policy.rules.extend(other.rules),
+            (policy @ None, other) => *policy = other,
+            (Some(_), None) => {}
+        }
+        self.scopes.extend(other.scopes);
+        for state in other.state {
+            match self.role_state(&state.role) {
+                Some(known) if !same_state(known, &state) => {
+                    return Err(CompositionError::IncompatibleState(state.role.to_string()));
+                }
+                Some(_) => {}
+                None => self.state.push(state),
+            }
+        }
+
+        let mut renames = HashMap::new();
+        let mut protocol = other.protocol;
+        visit_messages(&mut protocol, &mut |message, _| {
+            let name = message.name.to_string();
+            if known.get(&name).is_some_and(|known| known != message) {
+                renames.insert(name, format!("{}{}", other.name, message.name));
+            }
+        });
+        if !renames.is_empty() {
+            visit_messages(&mut protocol, &mut |message, annotations| {
+                if let Some(renamed) = renames.get(&message.name.to_string()) {
+                    message.name = format_ident!("{}", renamed);
+                }
+                if let Some(sources) = annotations.get_mut(DERIVED_FROM) {
+                    *sources = rename_sources(sources, &renames);
+                }
+            });
+        }
+        known.extend(messages(&mut protocol));
+        Ok(protocol)
+    }
+}
+
+/// Whether two declarations of a role's state have the same fields
+fn same_state(a: &RoleState, b: &RoleState) -> bool {
+    a.fields.len() == b.fields.len()
+        && a.fields
+            .iter()
+            .zip(&b.fields)
+            .all(|(a, b)| a.name == b.name && a.ty.to_string() == b.ty.to_string())
+}
+
+/// The first definition of every message sent in `protocol`, by name
+fn messages(protocol: &mut Protocol) -> HashMap<String, MessageType> {
+    let mut messages = HashMap::new();
+    visit_messages(protocol, &mut |message, _| {
+        messages
+            .entry(message.name.to_string())
+            .or_insert_with(|| message.clone());
+    });
+    messages
+}
+
+/// `Message` and `Message.field` sources of a `derived_from` annotation,
+/// with renamed messages replaced
+fn rename_sources(sources: &str, renames: &HashMap<String, String>) -> String {
+    sources
+        .split(',')
+        .map(str::trim)
+        .map(|source| {
+            let (message, field) = match source.split_once('.') {
+                Some((message, field)) => (message, Some(field)),
+                None => (source, None),
+            };
+            let message = renames.get(message).map_or(message, String::as_str);
+            match field {
+                Some(field) => format!("{message}.{field}"),
+                None => message.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Call `visit` with the message and annotations of every send
+fn visit_messages(
+    protocol: &mut Protocol,
+    visit: &mut dyn FnMut(&mut MessageType, &mut HashMap<String, String>),
+) {
+    match protocol {
+        Protocol::Send {
+            message,
+            annotations,
+            continuation,
+            ..
+        }
+        | Protocol::Broadcast {
+            message,
+            annotations,
+            continuation,
+            ..
+        } => {
+            visit(message, annotations);
+            visit_messages(continuation, visit);
+        }
+        Protocol::Choice { branches, .. } => {
+            for branch in branches {
+                visit_messages(&mut branch.protocol, visit);
+            }
+        }
+        Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => visit_messages(body, visit),
+        Protocol::Parallel { protocols, .. } => {
+            for p in protocols {
+                visit_messages(p, visit);
+            }
+        }
+        Protocol::Extension { continuation, .. } => visit_messages(continuation, visit),
+        Protocol::Var(_) | Protocol::End => {}
+    }
+}
+
+/// Number of paths through `protocol` that reach its end
+fn endings(protocol: &Protocol) -> Result<usize, CompositionError> {
+    match protocol {
+        Protocol::Send { continuation, .. }
+        | Protocol::Broadcast { continuation, .. }
+        | Protocol::Extension { continuation, .. } => endings(continuation),
+        Protocol::Choice { branches, .. } => branches
+            .iter()
+            .map(|branch| endings(&branch.protocol))
+            .sum(),
+        Protocol::Rec { body, .. } => endings(body),
+        Protocol::Loop { .. } => Err(CompositionError::NoContinuation("loop")),
+        Protocol::Parallel { .. } => Err(CompositionError::NoContinuation("parallel block")),
+        Protocol::Var(_) => Ok(0),
+        Protocol::End => Ok(1),
+    }
+}
+
+/// `protocol` with every ending replaced by what `next` returns
+fn append(
+    protocol: Protocol,
+    next: &mut dyn FnMut() -> Result<Protocol, CompositionError>,
+) -> Result<Protocol, CompositionError> {
+    Ok(match protocol {
+        Protocol::Send {
+            from,
+            to,
+            message,
+            continuation,
+            annotations,
+            from_annotations,
+            to_annotations,
+            span,
+        } => Protocol::Send {
+            from,
+            to,
+            message,
+            continuation: Box::new(append(*continuation, next)?),
+            annotations,
+            from_annotations,
+            to_annotations,
+            span,
+        },
+        Protocol::Broadcast {
+            from,
+            to_all,
+            message,
+            continuation,
+            annotations,
+            from_annotations,
+            span,
+        } => Protocol::Broadcast {
+            from,
+            to_all,
+            message,
+            continuation: Box::new(append(*continuation, next)?),
+            annotations,
+            from_annotations,
+            span,
+        },
+        Protocol::Choice {
+            role,
+            branches,
+            annotations,
+            span,
+        } => Protocol::Choice {
+            role,
+            branches: branches
+                .into_iter()
+                .map(|branch| {
+                    Ok(Branch {
+                        protocol: append(branch.protocol, next)?,
+                        ..branch
+                    })
+                })
+                .collect::<Result<_, CompositionError>>()?,
+            annotations,
+            span,
+        },
+        Protocol::Rec { label, body, span } => Protocol::Rec {
+            label,
+            body: Box::new(append(*body, next)?),
+            span,
+        },
+        Protocol::Extension {
+            extension,
+            continuation,
+            annotations,
+            span,
+        } => Protocol::Extension {
+            extension,
+            continuation: Box::new(append(*continuation, next)?),
+            annotations,
+            span,
+        },
+        Protocol::Loop { .. } => return Err(CompositionError::NoContinuation("loop")),
+        Protocol::Parallel { .. } => {
+            return Err(CompositionError::NoContinuation("parallel block"))
+        }
+        Protocol::Var(label) => Protocol::Var(label),
+        Protocol::End => next()?,
+    })
+}
+
+/// A deep copy of `protocol`, which must not hold extension statements
+fn copy(protocol: &Protocol) -> Result<Protocol, CompositionError> {
+    Ok(match protocol {
+        Protocol::Send {
+            from,
+            to,
+            message,
+            continuation,
+            annotations,
+            from_annotations,
+            to_annotations,
+            span,
+        } => Protocol::Send {
+            from: from.clone(),
+            to: to.clone(),
+            message: message.clone(),
+            continuation: Box::new(copy(continuation)?),
+            annotations: annotations.clone(),
+            from_annotations: from_annotations.clone(),
+            to_annotations: to_annotations.clone(),
+            span: *span,
+        },
+        Protocol::Broadcast {
+            from,
+            to_all,
+            message,
+            continuation,
+            annotations,
+            from_annotations,
+            span,
+        } => Protocol::Broadcast {
+            from: from.clone(),
+            to_all: to_all.clone(),
+            message: message.clone(),
+            continuation: Box::new(copy(continuation)?),
+            annotations: annotations.clone(),
+            from_annotations: from_annotations.clone(),
+            span: *span,
+        },
+        Protocol::Choice {
+            role,
+            branches,
+            annotations,
+            span,
+        } => Protocol::Choice {
+            role: role.clone(),
+            branches: branches
+                .iter()
+                .map(|branch| {
+                    Ok(Branch {
+                        label: branch.label.clone(),
+                        guard: branch.guard.clone(),
+                        probability: branch.probability,
+                        protocol: copy(&branch.protocol)?,
+                        span: branch.span,
+                    })
+                })
+                .collect::<Result<_, CompositionError>>()?,
+            annotations: annotations.clone(),
+            span: *span,
+        },
+        Protocol::Loop {
+            condition,
+            body,
+            span,
+        } => Protocol::Loop {
+            condition: condition.clone(),
+            body: Box::new(copy(body)?),
+            span: *span,
+        },
+        Protocol::Parallel { protocols, span } => Protocol::Parallel {
+            protocols: protocols.iter().map(copy).collect::<Result<_, _>>()?,
+            span: *span,
+        },
+        Protocol::Rec { label, body, span } => Protocol::Rec {
+            label: label.clone(),
+            body: Box::new(copy(body)?),
+            span: *span,
+        },
+        Protocol::Var(label) => Protocol::Var(label.clone()),
+        Protocol::Extension { extension, .. } => {
+            return Err(CompositionError::ExtensionCopy(extension.type_name()))
+        }
+        Protocol::End => Protocol::End,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parser::parse_choreography_str;
+
+    fn parse(source: &str) -> Choreography {
+        parse_choreography_str(source).unwrap()
+    }
+
+    #[test]
+    fn test_then_appends_on_every_ending() {
+        let order = parse(
+            r#"
+            choreography Order {
+                roles: Buyer, Shop
+                Buyer -> Shop: Request
+                choice Shop {
+                    accept: { Shop -> Buyer: Offer }
+                    reject: { Shop -> Buyer: Refusal }
+                }
+            }
+            "#,
+        );
+        let receipt = parse(
+            r#"
+            choreography Receipt {
+                roles: Shop, Bank
+                Shop -> Bank: Receipt
+            }
+            "#,
+        );
+        let composed = order.then(receipt).unwrap();
+        assert_eq!(composed.name, "Order");
+        assert_eq!(composed.roles.len(), 3);
+        assert!(composed.validate().is_ok());
+
+        let Protocol::Send { continuation, .. } = &composed.protocol else {
+            panic!("expected a send");
+        };
+        let Protocol::Choice { branches, .. } = continuation.as_ref() else {
+            panic!("expected a choice");
+        };
+        for branch in branches {
+            let Protocol::Send { continuation, .. } = &branch.protocol else {
+                panic!("expected a send");
+            };
+            assert!(matches!(
+                continuation.as_ref(),
+                Protocol::Send { message, .. } if message.name == "Receipt"
+            ));
+        }
+    }
+
+    #[test]
+    fn test_clashing_messages_are_renamed() {
+        let first = parse(
+            r#"
+            choreography Login {
+                roles: Client, Server
+                Client -> Server: Request(user: String)
+                Server -> Client: Ack
+            }
+            "#,
+        );
+        let second = parse(
+            r#"
+            choreography Upload {
+                roles: Client, Server
+                Client -> Server: Request(data: Vec<u8>)
+                [@derived_from = "Request.data"]
+                Server -> Client: Ack
+            }
+            "#,
+        );
+        let composed = first.par(second).unwrap();
+        let Protocol::Parallel { protocols, .. } = &composed.protocol else {
+            panic!("expected a parallel block");
+        };
+        let Protocol::Send {
+            message,
+            continuation,
+            ..
+        } = &protocols[1]
+        else {
+            panic!("expected a send");
+        };
+        assert_eq!(message.name, "UploadRequest");
+        let Protocol::Send {
+            message,
+            annotations,
+            ..
+        } = continuation.as_ref()
+        else {
+            panic!("expected a send");
+        };
+        // Identical messages are shared
+        assert_eq!(message.name, "Ack");
+        assert_eq!(annotations[DERIVED_FROM], "UploadRequest.data");
+    }
+
+    #[test]
+    fn test_choice_and_role_compatibility() {
+        let card = || {
+            parse(
+                r#"
+                choreography Card {
+                    roles: Shop, Bank
+                    Shop -> Bank: Charge
+                }
+                "#,
+            )
+        };
+        let cash = parse(
+            r#"
+            choreography Cash {
+                roles: Shop, Till
+                Shop -> Till: Open
+            }
+            "#,
+        );
+        let composed = card().choice("Shop", vec![cash]).unwrap();
+        let Protocol::Choice { role, branches, .. } = &composed.protocol else {
+            panic!("expected a choice");
+        };
+        assert_eq!(role.name, "Shop");
+        let labels: Vec<_> = branches.iter().map(|b| b.label.to_string()).collect();
+        assert_eq!(labels, ["card", "cash"]);
+
+        assert_eq!(
+            card().choice("Customer", Vec::new()).unwrap_err(),
+            CompositionError::UndefinedDecider("Customer".to_string())
+        );
+        assert_eq!(
+            card().choice("Shop", vec![card()]).unwrap_err(),
+            CompositionError::DuplicateLabel("card".to_string())
+        );
+
+        let workers = parse(
+            r#"
+            choreography Fanout {
+                roles: Shop, Bank[3]
+                Shop -> Bank[*]: Charge
+            }
+            "#,
+        );
+        assert_eq!(
+            card().then(workers).unwrap_err(),
+            CompositionError::IncompatibleRole("Bank".to_string())
+        );
+
+        let tally = |ty: &str| {
+            parse(&format!(
+                r#"
+                choreography Tally {{
+                    roles: Shop, Bank
+                    state at Shop {{ total: {ty} }}
+                    Shop -> Bank: Charge
+                }}
+                "#
+            ))
+        };
+        assert_eq!(card().then(tally("u64")).unwrap().state.len(), 1);
+        assert_eq!(
+            tally("u64").then(tally("u32")).unwrap_err(),
+            CompositionError::IncompatibleState("Shop".to_string())
+        );
+    }
+}
@@ -1,7 +1,9 @@
 // Local session types after projection
 
+use super::protocol::Condition;
 use super::{MessageType, Role};
-use proc_macro2::Ident;
+use proc_macro2::{Ident, TokenStream};
+use std::fmt::{self, Write};
 use std::time::Duration;
 
 /// Local session type after projection
@@ -65,6 +67,111 @@ impl LocalType {
         self.check_well_formed(&mut vec![])
     }
 
+    /// Human-readable rendering of the obligations of the role
+    ///
+    /// One action per line, with the branches of selections, offers and
+    /// local choices, and the bodies of loops, recursions and timeouts
+    /// indented in braces:
+    ///
+    /// ```text
+    /// send Offer(u64) to Buyer
+    /// branch from Buyer {
+    ///     accept: {
+    ///         receive Pay(u64) from Buyer
+    ///     }
+    ///     reject: end
+    /// }
+    /// ```
+    #[must_use]
+    pub fn to_pretty_string(&self) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, 0);
+        out
+    }
+
+    fn write_pretty(&self, out: &mut String, depth: usize) {
+        let indent = "    ".repeat(depth);
+        let mut block = |header: String, write_body: &dyn Fn(&mut String)| {
+            let _ = writeln!(out, "{indent}{header} {{");
+            write_body(out);
+            let _ = writeln!(out, "{indent}}}");
+        };
+        match self {
+            LocalType::Send {
+                to,
+                message,
+                continuation,
+            } => {
+                let _ = writeln!(
+                    out,
+                    "{indent}send {} to {}",
+                    message_text(message),
+                    role_text(to)
+                );
+                if !matches!(**continuation, LocalType::End) {
+                    continuation.write_pretty(out, depth);
+                }
+            }
+            LocalType::Receive {
+                from,
+                message,
+                continuation,
+            } => {
+                let _ = writeln!(
+                    out,
+                    "{indent}receive {} from {}",
+                    message_text(message),
+                    role_text(from)
+                );
+                if !matches!(**continuation, LocalType::End) {
+                    continuation.write_pretty(out, depth);
+                }
+            }
+            LocalType::Select { to, branches } => {
+                block(format!("select to {}", role_text(to)), &|out| {
+                    write_branches(out, branches, depth + 1)
+                })
+            }
+            LocalType::Branch { from, branches } => {
+                block(format!("branch from {}", role_text(from)), &|out| {
+                    write_branches(out, branches, depth + 1)
+                })
+            }
+            LocalType::LocalChoice { branches } => block("choose".to_string(), &|out| {
+                write_branches(out, branches, depth + 1);
+            }),
+            LocalType::Loop { condition, body } => {
+                let condition = match condition {
+                    Some(Condition::Count(n)) => format!(" (count: {n})"),
+                    Some(Condition::RoleDecides(role)) => {
+                        format!(" (decides: {})", role_text(role))
+                    }
+                    Some(Condition::Custom(tokens)) => {
+                        format!(" (custom: {})", tokens_text(tokens))
+                    }
+                    None => String::new(),
+                };
+                block(format!("loop{condition}"), &|out| {
+                    body.write_pretty(out, depth + 1);
+                });
+            }
+            LocalType::Rec { label, body } => block(format!("rec {label}"), &|out| {
+                body.write_pretty(out, depth + 1);
+            }),
+            LocalType::Var(label) => {
+                let _ = writeln!(out, "{indent}continue {label}");
+            }
+            LocalType::Timeout { duration, body } => {
+                block(format!("timeout {duration:?}"), &|out| {
+                    body.write_pretty(out, depth + 1);
+                });
+            }
+            LocalType::End => {
+                let _ = writeln!(out, "{indent}end");
+            }
+        }
+    }
+
     fn check_well_formed(&self, rec_vars: &mut Vec<Ident>) -> bool {
         match self {
             LocalType::Send { continuation, .. } => continuation.check_well_formed(rec_vars),
@@ -91,3 +198,57 @@ impl LocalType {
         }
     }
 }
+
+impl fmt::Display for LocalType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.to_pretty_string().trim_end())
+    }
+}
+
+/// Branches as `label: end` or `label: { ... }`
+fn write_branches(out: &mut String, branches: &[(Ident, LocalType)], depth: usize) {
+    let indent = "    ".repeat(depth);
+    for (label, body) in branches {
+        if let LocalType::End = body {
+            let _ = writeln!(out, "{indent}{label}: end");
+        } else {
+            let _ = writeln!(out, "{indent}{label}: {{");
+            body.write_pretty(out, depth + 1);
+            let _ = writeln!(out, "{indent}}}");
+        }
+    }
+}
+
+pub(crate) fn role_text(role: &Role) -> String {
+    match &role.index {
+        Some(index) => format!("{}[{index}]", role.name),
+        None => role.name.to_string(),
+    }
+}
+
+fn message_text(message: &MessageType) -> String {
+    let mut text = message.name.to_string();
+    if let Some(annotation) = &message.type_annotation {
+        let _ = write!(text, "<{}>", tokens_text(annotation));
+    }
+    if let Some(payload) = &message.payload {
+        let _ = write!(text, "({})", tokens_text(payload));
+    }
+    text
+}
+
+/// Tokens of a type, without the spacing `TokenStream` puts around
+/// punctuation
+fn tokens_text(tokens: &TokenStream) -> String {
+    tokens
+        .to_string()
+        .replace(" :: ", "::")
+        .replace(" < ", "<")
+        .replace("< ", "<")
+        .replace(" <", "<")
+        .replace(" >", ">")
+        .replace(" ,", ",")
+        .replace("& ", "&")
+        .replace("( ", "(")
+        .replace(" )", ")")
+}
@@ -6,28 +6,49 @@
 /// Choreography definitions (global protocols with metadata)
 pub mod choreography;
 
+/// Sequential, parallel and choice composition of whole choreographies
+pub mod composition;
+
 /// Local types resulting from projection
 pub mod local_type;
 
 /// Message type definitions
 pub mod message;
 
+/// Authorization policies
+pub mod policy;
+
 /// Protocol combinators (global protocol constructs)
 pub mod protocol;
 
 /// Role definitions
 pub mod role;
 
+/// Role-local state declarations
+pub mod state;
+
+/// Source locations of AST nodes
+pub mod span;
+
 /// Validation errors and utilities
 pub mod validation;
 
 // Re-export core AST types explicitly for clarity
-pub use choreography::Choreography;
+pub use choreography::{Choreography, CFG_DISABLED, EXTERNAL};
+pub use composition::CompositionError;
 pub use local_type::LocalType;
 pub use message::MessageType;
-pub use protocol::{Branch, Condition, Protocol};
+pub use policy::{Permission, Policy, PolicyAction, PolicyRule, PolicyStep};
+pub use protocol::{
+    host_expr, Branch, Condition, Protocol, ABORT, ACK, ATOMIC, ATOMIC_ABORT, ATOMIC_COMMIT, BREAK,
+    COMMIT, CONFIDENTIAL, CONTINUE, DEADLINE_EXCEEDED, DEFAULT_MESSAGE, DELIVERED, DERIVED_FROM,
+    DOC, FAILED, HANDOVER, IN_TIME, ON_FAILURE, PREPARE, REASSIGN, STREAM, VOTE, WHILE, WITHIN,
+    WITHIN_EXCEEDED, WITHIN_IN_TIME,
+};
 pub use role::{
-    RangeExpr, Role, RoleBoundsChecker, RoleIndex, RoleParam, RoleRange, RoleValidationError,
-    RoleValidationResult, MAX_RANGE_SIZE, MAX_ROLE_COUNT, MAX_ROLE_INDEX,
+    RangeExpr, Role, RoleBoundsChecker, RoleIndex, RoleParam, RoleQuorum, RoleRange,
+    RoleValidationError, RoleValidationResult, MAX_RANGE_SIZE, MAX_ROLE_COUNT, MAX_ROLE_INDEX,
 };
+pub use span::Span;
+pub use state::{RoleState, StateField};
 pub use validation::ValidationError;
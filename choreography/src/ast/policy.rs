@@ -0,0 +1,285 @@
+// Authorization policy of a choreography
+//
+// An optional `policy` block after the role declarations states which roles
+// may start interactions and which may only answer them:
+//
+//     policy {
+//         Coordinator may initiate;
+//         Signer[*] may only respond;
+//     }
+//
+// A role initiates when it decides a choice or a loop, or sends before it
+// has received anything on the path. It responds when it sends after a
+// receipt. Roles the policy does not name may do both; a named role may only
+// do what its rules grant. Validation rejects protocols that break the
+// policy, and code generation guards the steps of named roles with the
+// permission they use, so a runtime `CapabilityProvider` can refuse them.
+
+use super::{Condition, Protocol, Role};
+use crate::runtime::guard::CapabilitySet;
+use proc_macro2::Ident;
+use std::collections::HashSet;
+use std::fmt;
+
+/// What a policy rule allows a role to do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Permission {
+    /// Decide choices and loops, and send unprompted
+    Initiate,
+    /// Send after having received
+    Respond,
+}
+
+impl Permission {
+    /// Both permissions
+    pub const ALL: [Permission; 2] = [Permission::Initiate, Permission::Respond];
+
+    /// The capability a guarded step using this permission requires
+    #[must_use]
+    pub fn capability(self) -> &'static str {
+        match self {
+            Permission::Initiate => "initiate",
+            Permission::Respond => "respond",
+        }
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.capability())
+    }
+}
+
+/// One `Role may [only] permission, ...;` line
+#[derive(Debug, Clone)]
+pub struct PolicyRule {
+    pub role: Ident,
+    /// Written `may only`: no other rule may grant the role more
+    pub only: bool,
+    pub permissions: Vec<Permission>,
+}
+
+/// The rules of a `policy` block
+#[derive(Debug, Clone, Default)]
+pub struct Policy {
+    pub rules: Vec<PolicyRule>,
+}
+
+impl Policy {
+    /// Whether the policy names `role`
+    #[must_use]
+    pub fn restricts(&self, role: &Ident) -> bool {
+        self.rules.iter().any(|rule| rule.role == *role)
+    }
+
+    /// Whether `role` may use `permission`
+    #[must_use]
+    pub fn permits(&self, role: &Ident, permission: Permission) -> bool {
+        !self.restricts(role)
+            || self
+                .rules
+                .iter()
+                .any(|rule| rule.role == *role && rule.permissions.contains(&permission))
+    }
+
+    /// The permissions of each of `roles` as capabilities, for the guards
+    /// code generation derives from the policy
+    #[must_use]
+    pub fn capabilities(&self, roles: &[Role]) -> CapabilitySet {
+        let mut capabilities = CapabilitySet::new();
+        for role in roles {
+            for permission in Permission::ALL {
+                if self.permits(&role.name, permission) {
+                    capabilities.insert(role.name.to_string(), permission.capability());
+                }
+            }
+        }
+        capabilities
+    }
+}
+
+/// A step of the protocol that uses a permission
+#[derive(Debug, Clone)]
+pub struct PolicyStep {
+    pub role: Ident,
+    pub permission: Permission,
+    pub action: PolicyAction,
+}
+
+/// What a `PolicyStep` does
+#[derive(Debug, Clone)]
+pub enum PolicyAction {
+    /// Send `message` to `to`
+    Send { to: Ident, message: String },
+    /// Decide a choice between `labels`, or a loop when empty
+    Decide { labels: Vec<String> },
+}
+
+impl fmt::Display for PolicyAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyAction::Send { to, message } => write!(f, "send {message} to {to}"),
+            PolicyAction::Decide { labels } if labels.is_empty() => f.write_str("decide a loop"),
+            PolicyAction::Decide { labels } => {
+                write!(f, "decide between {}", labels.join(", "))
+            }
+        }
+    }
+}
+
+/// Every step of `protocol` that uses a permission, in protocol order
+#[must_use]
+pub fn policy_steps(protocol: &Protocol) -> Vec<PolicyStep> {
+    let mut steps = Vec::new();
+    collect_steps(protocol, &HashSet::new(), &mut steps);
+    steps
+}
+
+fn collect_steps(protocol: &Protocol, received: &HashSet<Ident>, steps: &mut Vec<PolicyStep>) {
+    let sent = |from: &Role| {
+        if received.contains(&from.name) {
+            Permission::Respond
+        } else {
+            Permission::Initiate
+        }
+    };
+    match protocol {
+        Protocol::Send {
+            from,
+            to,
+            message,
+            continuation,
+            ..
+        } => {
+            steps.push(PolicyStep {
+                role: from.name.clone(),
+                permission: sent(from),
+                action: PolicyAction::Send {
+                    to: to.name.clone(),
+                    message: message.name.to_string(),
+                },
+            });
+            let mut received = received.clone();
+            received.insert(to.name.clone());
+            collect_steps(continuation, &received, steps);
+        }
+        Protocol::Broadcast {
+            from,
+            to_all,
+            message,
+            continuation,
+            ..
+        } => {
+            let permission = sent(from);
+            for to in to_all {
+                steps.push(PolicyStep {
+                    role: from.name.clone(),
+                    permission,
+                    action: PolicyAction::Send {
+                        to: to.name.clone(),
+                        message: message.name.to_string(),
+                    },
+                });
+            }
+            let mut received = received.clone();
+            received.extend(to_all.iter().map(|to| to.name.clone()));
+            collect_steps(continuation, &received, steps);
+        }
+        Protocol::Choice { role, branches, .. } => {
+            steps.push(PolicyStep {
+                role: role.name.clone(),
+                permission: Permission::Initiate,
+                action: PolicyAction::Decide {
+                    labels: branches.iter().map(|b| b.label.to_string()).collect(),
+                },
+            });
+            for branch in branches {
+                collect_steps(&branch.protocol, received, steps);
+            }
+        }
+        Protocol::Loop {
+            condition, body, ..
+        } => {
+            if let Some(Condition::RoleDecides(role)) = condition {
+                steps.push(PolicyStep {
+                    role: role.name.clone(),
+                    permission: Permission::Initiate,
+                    action: PolicyAction::Decide { labels: Vec::new() },
+                });
+            }
+            collect_steps(body, received, steps);
+        }
+        Protocol::Rec { body, .. } => collect_steps(body, received, steps),
+        Protocol::Parallel { protocols, .. } => {
+            for p in protocols {
+                collect_steps(p, received, steps);
+            }
+        }
+        Protocol::Extension { continuation, .. } => {
+            collect_steps(continuation, received, steps);
+        }
+        Protocol::Var(_) | Protocol::End => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parser::parse_choreography_str;
+    use crate::runtime::guard::CapabilityProvider;
+
+    #[test]
+    fn test_steps() {
+        let choreography = parse_choreography_str(
+            r#"
+            choreography Sign {
+                roles: Coordinator, Signer
+                Coordinator -> Signer: Request
+                Signer -> Coordinator: Signature
+                choice Coordinator {
+                    done: { Coordinator -> Signer: Done }
+                }
+            }
+            "#,
+        )
+        .unwrap();
+        let steps: Vec<_> = policy_steps(&choreography.protocol)
+            .into_iter()
+            .map(|step| (step.role.to_string(), step.permission))
+            .collect();
+        assert_eq!(
+            steps,
+            [
+                ("Coordinator".to_string(), Permission::Initiate),
+                ("Signer".to_string(), Permission::Respond),
+                ("Coordinator".to_string(), Permission::Initiate),
+                ("Coordinator".to_string(), Permission::Respond),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let choreography = parse_choreography_str(
+            r#"
+            choreography Sign {
+                roles: Coordinator, Signer, Auditor
+                policy {
+                    Coordinator may initiate, respond;
+                    Signer may only respond;
+                }
+                Coordinator -> Signer: Request
+                Signer -> Auditor: Signature
+            }
+            "#,
+        )
+        .unwrap();
+        let policy = choreography.policy.as_ref().unwrap();
+        let capabilities = policy.capabilities(&choreography.roles);
+        assert!(capabilities.has_capability("Coordinator", "initiate"));
+        assert!(capabilities.has_capability("Signer", "respond"));
+        assert!(!capabilities.has_capability("Signer", "initiate"));
+        // Unnamed roles are unrestricted
+        assert!(capabilities.has_capability("Auditor", "initiate"));
+    }
+}
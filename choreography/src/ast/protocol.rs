@@ -1,6 +1,6 @@
 // Protocol AST definitions
 
-use super::{MessageType, Role, ValidationError};
+use super::{MessageType, Role, Span, ValidationError};
 use proc_macro2::{Ident, TokenStream};
 use std::collections::HashMap;
 
@@ -19,6 +19,8 @@ pub enum Protocol {
         from_annotations: HashMap<String, String>,
         /// To role annotations
         to_annotations: HashMap<String, String>,
+        /// Location of the statement
+        span: Span,
     },
 
     /// Broadcast: A -> *: Message
@@ -31,6 +33,8 @@ pub enum Protocol {
         annotations: HashMap<String, String>,
         /// From role annotations
         from_annotations: HashMap<String, String>,
+        /// Location of the statement
+        span: Span,
     },
 
     /// Choice made by a role
@@ -39,19 +43,32 @@ pub enum Protocol {
         branches: Vec<Branch>,
         /// Statement-level annotations
         annotations: HashMap<String, String>,
+        /// Location of the statement
+        span: Span,
     },
 
     /// Loop construct
     Loop {
         condition: Option<Condition>,
         body: Box<Protocol>,
+        /// Location of the statement
+        span: Span,
     },
 
     /// Parallel composition
-    Parallel { protocols: Vec<Protocol> },
+    Parallel {
+        protocols: Vec<Protocol>,
+        /// Location of the statement
+        span: Span,
+    },
 
     /// Recursive protocol with label
-    Rec { label: Ident, body: Box<Protocol> },
+    Rec {
+        label: Ident,
+        body: Box<Protocol>,
+        /// Location of the statement
+        span: Span,
+    },
 
     /// Reference to recursive label
     Var(Ident),
@@ -64,18 +81,181 @@ pub enum Protocol {
         continuation: Box<Protocol>,
         /// Statement-level annotations
         annotations: HashMap<String, String>,
+        /// Location of the statement
+        span: Span,
     },
 
     /// Protocol termination
     End,
 }
 
+/// Annotation holding the `///` doc comment of a statement, one line per
+/// comment line
+///
+/// Code generation puts it on the message types, handler methods and
+/// decisions the statement gives rise to.
+pub const DOC: &str = "doc";
+
+/// Annotation on the choice that `A -> B: Msg or on failure { ... }` parses
+/// to, naming the role `B` whose failure the choice reacts to
+///
+/// `A` does not decide such a choice: it takes the [`DELIVERED`] branch if
+/// the send succeeded and the [`FAILED`] branch if it failed.
+pub const ON_FAILURE: &str = "on_failure";
+
+/// Branch of an `on_failure` choice taken when the send succeeded
+pub const DELIVERED: &str = "delivered";
+
+/// Branch of an `on_failure` choice running the recovery
+pub const FAILED: &str = "failed";
+
+/// Annotation marking the choice a `while at Role { ... }` loop parses to,
+/// naming the deciding role
+///
+/// `while at A { body } rest` becomes `rec L { choice A { Continue: { body;
+/// continue L } Break: { rest } } }`. `A` tells every other role which
+/// branch it took.
+pub const WHILE: &str = "while";
+
+/// Branch of a `while` choice running the body again
+pub const CONTINUE: &str = "Continue";
+
+/// Branch of a `while` choice leaving the loop
+pub const BREAK: &str = "Break";
+
+/// Annotation marking a send whose message goes as a sequence of chunks
+///
+/// Set by `A -> B: stream Message;`.
+pub const STREAM: &str = "stream";
+
+/// Annotation prefix of the information-flow label of a payload field,
+/// naming the roles allowed to see its value besides the sender
+///
+/// `A -> B: Charge(card: Card [confidential to = "B, C"])` sets
+/// `confidential.card` to `B, C` on the send.
+pub const CONFIDENTIAL: &str = "confidential";
+
+/// Annotation listing the messages, or `Message.field` payload fields, whose
+/// values the message of a send is derived from, as in
+/// `[@derived_from = "Charge.card"]`
+pub const DERIVED_FROM: &str = "derived_from";
+
+/// Annotation holding the `#{ ... }` host expression generated code builds
+/// the message of a send from, in place of `Message::default()`, as in
+/// `[@default = #{ Ping(config::FIRST_SEQ) }]`
+pub const DEFAULT_MESSAGE: &str = "default";
+
+/// The Rust expression inside a `#{ ... }` host escape, if `value` is one
+///
+/// Annotation values keep escapes verbatim; code generation splices the
+/// expression where the annotation takes effect.
+#[must_use]
+pub fn host_expr(value: &str) -> Option<&str> {
+    value
+        .trim()
+        .strip_prefix("#{")?
+        .strip_suffix('}')
+        .map(str::trim)
+}
+
+/// Annotation marking the send a role reassignment parses to, naming how
+/// the new holder of the role is chosen
+///
+/// `reassign Coordinator from Worker[*] by election;` parses to a
+/// [`HANDOVER`] send from `Coordinator` to `Worker[*]` with this annotation
+/// set to `election`.
+pub const REASSIGN: &str = "reassign";
+
+/// Message carrying a reassigned role to its candidates
+pub const HANDOVER: &str = "Handover";
+
+/// Annotation marking the steps an `atomic` block parses to, naming the
+/// block as in `Coordinator over Participant[*]`
+///
+/// `atomic at C over P { body }` becomes the body, then `C -> P: Prepare`,
+/// `P -> C: Vote(bool)` and a choice of `C` between [`ATOMIC_COMMIT`], in
+/// which `C -> P: Commit`, and [`ATOMIC_ABORT`], in which `C -> P: Abort`,
+/// each acknowledged with `P -> C: Ack`. The choice and every send of the
+/// protocol carry this annotation.
+pub const ATOMIC: &str = "atomic";
+
+/// Message asking the participants of an `atomic` block to prepare
+pub const PREPARE: &str = "Prepare";
+
+/// Message of a participant of an `atomic` block saying whether it can
+/// commit
+pub const VOTE: &str = "Vote";
+
+/// Message telling the participants of an `atomic` block to commit
+pub const COMMIT: &str = "Commit";
+
+/// Message telling the participants of an `atomic` block to abort
+pub const ABORT: &str = "Abort";
+
+/// Message of a participant of an `atomic` block acknowledging the decision
+pub const ACK: &str = "Ack";
+
+/// Branch of an `atomic` choice in which the participants commit
+pub const ATOMIC_COMMIT: &str = "commit";
+
+/// Branch of an `atomic` choice in which the participants abort
+pub const ATOMIC_ABORT: &str = "abort";
+
+/// Annotation holding the time limit of a `within` block as written, as in
+/// `30s`, on the send opening the block and on the choice closing it
+///
+/// `within 30s { body } or on deadline { recovery }` becomes the body, whose
+/// first send is from the block's timekeeper, then a choice of the
+/// timekeeper between [`WITHIN_IN_TIME`], in which it sends [`IN_TIME`] to
+/// every other role of the body, and [`WITHIN_EXCEEDED`], in which it sends
+/// [`DEADLINE_EXCEEDED`] to them and the recovery runs. Both branches go on
+/// with the statements after the block.
+pub const WITHIN: &str = "within";
+
+/// Message telling the roles of a `within` block that it ran in time
+pub const IN_TIME: &str = "InTime";
+
+/// Message telling the roles of a `within` block that it overran its time
+/// limit
+pub const DEADLINE_EXCEEDED: &str = "DeadlineExceeded";
+
+/// Branch of a `within` choice taken when the block ran in time
+pub const WITHIN_IN_TIME: &str = "in_time";
+
+/// Branch of a `within` choice taken when the block overran
+pub const WITHIN_EXCEEDED: &str = "deadline_exceeded";
+
 /// A branch in a choice
 #[derive(Debug)]
 pub struct Branch {
     pub label: Ident,
     pub guard: Option<TokenStream>,
+    /// Probability of the branch, from `label [prob = 0.9]: { ... }`
+    pub probability: Option<f64>,
     pub protocol: Protocol,
+    /// Location of the branch
+    pub span: Span,
+}
+
+impl Branch {
+    /// Probability of each of `branches` being taken
+    ///
+    /// Branches without a `prob` share what the others leave evenly; if
+    /// none has one, all branches are equally likely.
+    #[must_use]
+    pub fn probabilities(branches: &[Branch]) -> Vec<f64> {
+        let given: f64 = branches.iter().filter_map(|b| b.probability).sum();
+        let unset = branches.iter().filter(|b| b.probability.is_none()).count();
+        let share = if unset == 0 {
+            0.0
+        } else {
+            (1.0 - given).max(0.0) / unset as f64
+        };
+        branches
+            .iter()
+            .map(|b| b.probability.unwrap_or(share))
+            .collect()
+    }
 }
 
 /// Loop condition
@@ -90,6 +270,23 @@ pub enum Condition {
 }
 
 impl Protocol {
+    /// Location of the statement this node was parsed from
+    ///
+    /// `Var` and `End` have no source statement and return `Span::default()`.
+    #[must_use]
+    pub fn span(&self) -> Span {
+        match self {
+            Protocol::Send { span, .. }
+            | Protocol::Broadcast { span, .. }
+            | Protocol::Choice { span, .. }
+            | Protocol::Loop { span, .. }
+            | Protocol::Parallel { span, .. }
+            | Protocol::Rec { span, .. }
+            | Protocol::Extension { span, .. } => *span,
+            Protocol::Var(_) | Protocol::End => Span::default(),
+        }
+    }
+
     #[must_use]
     pub fn mentions_role(&self, role: &Role) -> bool {
         match self {
@@ -117,7 +314,7 @@ impl Protocol {
                 role: r, branches, ..
             } => r.matches_family(role) || branches.iter().any(|b| b.protocol.mentions_role(role)),
             Protocol::Loop { body, .. } => body.mentions_role(role),
-            Protocol::Parallel { protocols } => protocols.iter().any(|p| p.mentions_role(role)),
+            Protocol::Parallel { protocols, .. } => protocols.iter().any(|p| p.mentions_role(role)),
             Protocol::Rec { body, .. } => body.mentions_role(role),
             Protocol::Extension {
                 extension,
@@ -128,9 +325,19 @@ impl Protocol {
         }
     }
 
-    pub(crate) fn validate(&self, roles: &[Role]) -> Result<(), ValidationError> {
+    /// Push every validation error of this protocol onto `errors`
+    pub(crate) fn collect_validation_errors(
+        &self,
+        roles: &[Role],
+        errors: &mut Vec<ValidationError>,
+    ) {
         // Helper to check if a role instance matches any declared role family
         let role_is_declared = |r: &Role| roles.iter().any(|declared| r.matches_family(declared));
+        let check_declared = |r: &Role, errors: &mut Vec<ValidationError>| {
+            if !role_is_declared(r) {
+                errors.push(ValidationError::UndefinedRole(r.name.to_string()));
+            }
+        };
 
         match self {
             Protocol::Send {
@@ -139,13 +346,9 @@ impl Protocol {
                 continuation,
                 ..
             } => {
-                if !role_is_declared(from) {
-                    return Err(ValidationError::UndefinedRole(from.name.to_string()));
-                }
-                if !role_is_declared(to) {
-                    return Err(ValidationError::UndefinedRole(to.name.to_string()));
-                }
-                continuation.validate(roles)
+                check_declared(from, errors);
+                check_declared(to, errors);
+                continuation.collect_validation_errors(roles, errors);
             }
             Protocol::Broadcast {
                 from,
@@ -153,55 +356,102 @@ impl Protocol {
                 continuation,
                 ..
             } => {
-                if !role_is_declared(from) {
-                    return Err(ValidationError::UndefinedRole(from.name.to_string()));
-                }
+                check_declared(from, errors);
                 for to in to_all {
-                    if !role_is_declared(to) {
-                        return Err(ValidationError::UndefinedRole(to.name.to_string()));
-                    }
+                    check_declared(to, errors);
                 }
-                continuation.validate(roles)
+                continuation.collect_validation_errors(roles, errors);
             }
             Protocol::Choice { role, branches, .. } => {
-                if !role_is_declared(role) {
-                    return Err(ValidationError::UndefinedRole(role.name.to_string()));
-                }
-                // Validate each branch starts with the choosing role sending
-                for branch in branches {
-                    if let Protocol::Send { from, .. } = &branch.protocol {
-                        if from != role {
-                            return Err(ValidationError::InvalidChoice(role.name.to_string()));
-                        }
-                    } else {
-                        return Err(ValidationError::InvalidChoice(role.name.to_string()));
-                    }
+                check_declared(role, errors);
+                // Validate each branch starts with the choosing role sending,
+                // unless the outcome of a send or a `while` decides the branch
+                let starts_with_choice = |branch: &Branch| matches!(&branch.protocol, Protocol::Send { from, .. } if from == role);
+                if self.failure_of().is_none()
+                    && !self.is_while()
+                    && !branches.iter().all(starts_with_choice)
+                {
+                    errors.push(ValidationError::InvalidChoice(role.name.to_string()));
                 }
-                Ok(())
             }
-            Protocol::Loop { body, .. } => body.validate(roles),
-            Protocol::Parallel { protocols } => {
+            Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => {
+                body.collect_validation_errors(roles, errors);
+            }
+            Protocol::Parallel { protocols, .. } => {
                 for p in protocols {
-                    p.validate(roles)?;
+                    p.collect_validation_errors(roles, errors);
                 }
-                Ok(())
             }
-            Protocol::Rec { body, .. } => body.validate(roles),
             Protocol::Extension {
                 extension,
                 continuation,
                 ..
             } => {
                 // Validate the extension with the extension system's validation
-                extension.validate(roles).map_err(|e| {
-                    ValidationError::ExtensionError(format!("Extension validation failed: {}", e))
-                })?;
-                continuation.validate(roles)
+                if let Err(e) = extension.validate(roles) {
+                    errors.push(ValidationError::ExtensionError(format!(
+                        "Extension validation failed: {}",
+                        e
+                    )));
+                }
+                continuation.collect_validation_errors(roles, errors);
             }
-            Protocol::Var(_) | Protocol::End => Ok(()),
+            Protocol::Var(_) | Protocol::End => {}
         }
     }
 
+    /// The role whose failure this choice reacts to, if it is the choice of
+    /// an `or on failure` send
+    #[must_use]
+    pub fn failure_of(&self) -> Option<&str> {
+        match self {
+            Protocol::Choice { annotations, .. } => annotations.get(ON_FAILURE).map(String::as_str),
+            _ => None,
+        }
+    }
+
+    /// Whether this is the choice of a `while at Role` loop
+    #[must_use]
+    pub fn is_while(&self) -> bool {
+        matches!(self, Protocol::Choice { annotations, .. } if annotations.contains_key(WHILE))
+    }
+
+    /// `///` doc comment of the statement, see [`DOC`]
+    #[must_use]
+    pub fn doc(&self) -> Option<&str> {
+        self.get_annotation(DOC).map(String::as_str)
+    }
+
+    /// Whether this is a `stream` send
+    #[must_use]
+    pub fn is_stream(&self) -> bool {
+        matches!(self, Protocol::Send { annotations, .. } if annotations.contains_key(STREAM))
+    }
+
+    /// How the new holder of the role is chosen, if this is the send of a
+    /// `reassign` statement
+    #[must_use]
+    pub fn reassignment(&self) -> Option<&str> {
+        match self {
+            Protocol::Send { annotations, .. } => annotations.get(REASSIGN).map(String::as_str),
+            _ => None,
+        }
+    }
+
+    /// The `atomic` block this step belongs to, as in `Coordinator over
+    /// Participant[*]`, if it is a step of its two-phase commit
+    #[must_use]
+    pub fn atomic(&self) -> Option<&str> {
+        self.get_annotation(ATOMIC).map(String::as_str)
+    }
+
+    /// Time limit of the `within` block this step opens or closes, as
+    /// written in the block
+    #[must_use]
+    pub fn within(&self) -> Option<&str> {
+        self.get_annotation(WITHIN).map(String::as_str)
+    }
+
     /// Get statement-level annotations for this protocol node
     pub fn get_annotations(&self) -> &HashMap<String, String> {
         match self {
@@ -466,7 +716,7 @@ impl Protocol {
             Protocol::Loop { body, .. } => {
                 body.collect_nodes_with_annotation(key, nodes);
             }
-            Protocol::Parallel { protocols } => {
+            Protocol::Parallel { protocols, .. } => {
                 for protocol in protocols {
                     protocol.collect_nodes_with_annotation(key, nodes);
                 }
@@ -512,7 +762,7 @@ impl Protocol {
             Protocol::Loop { body, .. } => {
                 body.collect_nodes_with_annotation_value(key, value, nodes);
             }
-            Protocol::Parallel { protocols } => {
+            Protocol::Parallel { protocols, .. } => {
                 for protocol in protocols {
                     protocol.collect_nodes_with_annotation_value(key, value, nodes);
                 }
@@ -548,7 +798,7 @@ impl Protocol {
             Protocol::Loop { body, .. } => {
                 count += body.deep_annotation_count();
             }
-            Protocol::Parallel { protocols } => {
+            Protocol::Parallel { protocols, .. } => {
                 for protocol in protocols {
                     count += protocol.deep_annotation_count();
                 }
@@ -591,7 +841,7 @@ impl Protocol {
             Protocol::Loop { body, .. } => {
                 body.visit_annotated_nodes(f);
             }
-            Protocol::Parallel { protocols } => {
+            Protocol::Parallel { protocols, .. } => {
                 for protocol in protocols {
                     protocol.visit_annotated_nodes(f);
                 }
@@ -632,7 +882,7 @@ impl Protocol {
             Protocol::Loop { body, .. } => {
                 body.visit_annotated_nodes_mut(f);
             }
-            Protocol::Parallel { protocols } => {
+            Protocol::Parallel { protocols, .. } => {
                 for protocol in protocols {
                     protocol.visit_annotated_nodes_mut(f);
                 }
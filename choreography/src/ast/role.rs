@@ -26,6 +26,27 @@ pub enum RoleValidationError {
     #[error("Invalid range: start {start} >= end {end}")]
     InvalidRange { start: u32, end: u32 },
 
+    #[error("Invalid quorum: {threshold} of {of}")]
+    InvalidQuorum { threshold: u32, of: u32 },
+
+    #[error("Invalid multiplicity: at least {min} and at most {max} instances")]
+    InvalidMultiplicity { min: u32, max: u32 },
+
+    #[error("Role {role} has {count} instances, it takes {min} to {max}")]
+    MultiplicityViolation {
+        role: String,
+        count: u32,
+        min: u32,
+        max: u32,
+    },
+
+    #[error("{reference} is out of bounds when {role} has {count} instances")]
+    ReferenceOutOfBounds {
+        reference: String,
+        role: String,
+        count: u32,
+    },
+
     #[error("Runtime role count must be bounded for safety")]
     UnboundedRuntime,
 
@@ -45,6 +66,8 @@ pub enum RoleParam {
     Symbolic(String),
     /// Runtime determined: Worker[*]
     Runtime,
+    /// Runtime determined within bounds: Signer[3..=10]
+    Bounded { min: u32, max: u32 },
 }
 
 /// Role index expression for role references
@@ -58,6 +81,17 @@ pub enum RoleIndex {
     Wildcard,
     /// Range: Worker[0..3]
     Range(RoleRange),
+    /// Quorum: Signer[t of N] - any t of the first N instances
+    Quorum(Box<RoleQuorum>),
+}
+
+/// Quorum specification for role references
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RoleQuorum {
+    /// Number of instances whose messages are enough to proceed
+    pub threshold: RangeExpr,
+    /// Number of instances taking part
+    pub of: RangeExpr,
 }
 
 /// Role range specification for role references
@@ -281,7 +315,10 @@ impl Role {
     /// Check if this role has dynamic parameterization (runtime count)
     #[must_use]
     pub fn is_dynamic(&self) -> bool {
-        matches!(self.param, Some(RoleParam::Runtime))
+        matches!(
+            self.param,
+            Some(RoleParam::Runtime | RoleParam::Bounded { .. })
+        )
     }
 
     /// Check if this role has symbolic parameterization
@@ -290,6 +327,13 @@ impl Role {
         matches!(self.param, Some(RoleParam::Symbolic(_)))
     }
 
+    /// Check if this is the declaration of a role family, such as `Worker[N]`,
+    /// rather than a reference to one of its instances
+    #[must_use]
+    pub fn is_family(&self) -> bool {
+        self.param.is_some() && self.index.is_none()
+    }
+
     /// Check if this role reference uses a wildcard index
     #[must_use]
     pub fn is_wildcard(&self) -> bool {
@@ -443,6 +487,26 @@ impl RoleParam {
                 // This is enforced during code generation
                 Ok(())
             }
+            RoleParam::Bounded { min, max } => {
+                if min > max {
+                    return Err(RoleValidationError::InvalidMultiplicity {
+                        min: *min,
+                        max: *max,
+                    });
+                }
+                RoleParam::Static(*max).validate()
+            }
+        }
+    }
+
+    /// Instance counts the parameter allows, if they are known before the
+    /// session starts
+    #[must_use]
+    pub fn multiplicity(&self) -> Option<std::ops::RangeInclusive<u32>> {
+        match self {
+            RoleParam::Static(count) => Some(*count..=*count),
+            RoleParam::Bounded { min, max } => Some(*min..=*max),
+            RoleParam::Symbolic(_) | RoleParam::Runtime => None,
         }
     }
 
@@ -472,6 +536,12 @@ impl RoleParam {
                 }
                 Ok(())
             }
+            (RoleParam::Bounded { min, .. }, RoleIndex::Concrete(idx)) if *idx >= *min => {
+                Err(RoleValidationError::IndexOverflow {
+                    index: *idx,
+                    max: min.saturating_sub(1),
+                })
+            }
             _ => Ok(()), // Other combinations are valid or checked elsewhere
         }
     }
@@ -507,6 +577,7 @@ impl RoleIndex {
             }
             RoleIndex::Wildcard => Ok(()), // Wildcard is always valid
             RoleIndex::Range(range) => range.validate(),
+            RoleIndex::Quorum(quorum) => quorum.validate(),
         }
     }
 
@@ -522,6 +593,31 @@ impl RoleIndex {
     }
 }
 
+impl RoleQuorum {
+    /// Validate quorum for safety constraints
+    pub fn validate(&self) -> RoleValidationResult<()> {
+        if let RangeExpr::Concrete(of) = &self.of {
+            if *of > MAX_ROLE_COUNT {
+                return Err(RoleValidationError::CountOverflow {
+                    count: *of,
+                    max: MAX_ROLE_COUNT,
+                });
+            }
+        }
+        match (&self.threshold, &self.of) {
+            (RangeExpr::Concrete(threshold), RangeExpr::Concrete(of))
+                if *threshold == 0 || threshold > of =>
+            {
+                Err(RoleValidationError::InvalidQuorum {
+                    threshold: *threshold,
+                    of: *of,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
 impl RoleRange {
     /// Validate role range for safety constraints
     pub fn validate(&self) -> RoleValidationResult<()> {
@@ -628,6 +724,59 @@ impl RoleBoundsChecker {
         Ok(())
     }
 
+    /// Check that `count` instances of the role family `role` are within
+    /// the bounds of its declaration, as a session starts
+    pub fn check_multiplicity(&self, role: &Role, count: u32) -> RoleValidationResult<()> {
+        self.check_count(count)?;
+        match role.param.as_ref().and_then(RoleParam::multiplicity) {
+            Some(allowed) if !allowed.contains(&count) => {
+                Err(RoleValidationError::MultiplicityViolation {
+                    role: role.name.to_string(),
+                    count,
+                    min: *allowed.start(),
+                    max: *allowed.end(),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that `index`, referring to instances of the role family
+    /// `role`, stays within the family for every instance count its
+    /// declaration allows
+    ///
+    /// Families without a known count, `Worker[N]` and `Worker[*]`, are
+    /// only checked against the limits of the checker.
+    pub fn check_reference(&self, role: &Role, index: &RoleIndex) -> RoleValidationResult<()> {
+        let Some(allowed) = role.param.as_ref().and_then(RoleParam::multiplicity) else {
+            return index.validate();
+        };
+        for count in allowed {
+            self.check_count(count)?;
+            let within = match index {
+                RoleIndex::Concrete(index) => *index < count,
+                RoleIndex::Range(RoleRange {
+                    end: RangeExpr::Concrete(end),
+                    ..
+                }) => *end <= count,
+                RoleIndex::Quorum(quorum) => match (&quorum.threshold, &quorum.of) {
+                    (_, RangeExpr::Concrete(of)) => *of <= count,
+                    (RangeExpr::Concrete(threshold), _) => *threshold <= count,
+                    _ => true,
+                },
+                _ => true,
+            };
+            if !within {
+                return Err(RoleValidationError::ReferenceOutOfBounds {
+                    reference: format!("{}[{index}]", role.name),
+                    role: role.name.to_string(),
+                    count,
+                });
+            }
+        }
+        Ok(())
+    }
+
     /// Check if a runtime range is within bounds
     pub fn check_range(&self, start: u32, end: u32) -> RoleValidationResult<()> {
         if start >= end {
@@ -661,10 +810,17 @@ impl std::fmt::Display for RoleIndex {
             RoleIndex::Symbolic(name) => write!(f, "{}", name),
             RoleIndex::Wildcard => write!(f, "*"),
             RoleIndex::Range(range) => write!(f, "{}", range),
+            RoleIndex::Quorum(quorum) => write!(f, "{}", quorum),
         }
     }
 }
 
+impl std::fmt::Display for RoleQuorum {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} of {}", self.threshold, self.of)
+    }
+}
+
 impl std::fmt::Display for RoleRange {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}..{}", self.start, self.end)
@@ -686,6 +842,7 @@ impl std::fmt::Display for RoleParam {
             RoleParam::Static(count) => write!(f, "{}", count),
             RoleParam::Symbolic(name) => write!(f, "{}", name),
             RoleParam::Runtime => write!(f, "*"),
+            RoleParam::Bounded { min, max } => write!(f, "{}..={}", min, max),
         }
     }
 }
@@ -0,0 +1,121 @@
+//! Source locations of AST nodes
+
+/// Location of an AST node in the choreography source
+///
+/// `start` and `end` are byte offsets into the parsed input, `line` and
+/// `column` are 1-based and point at `start`. Nodes built in code rather than
+/// parsed carry `Span::default()`, which has no location.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Span {
+    /// Byte offset of the first character
+    pub start: usize,
+    /// Byte offset one past the last character
+    pub end: usize,
+    /// Line of the first character, starting at 1
+    pub line: usize,
+    /// Column of the first character, starting at 1
+    pub column: usize,
+}
+
+impl Span {
+    /// Span of a Pest match, without the whitespace Pest consumed after it
+    #[must_use]
+    pub fn from_pest(span: pest::Span<'_>) -> Self {
+        let (line, column) = span.start_pos().line_col();
+        Self {
+            start: span.start(),
+            end: span.start() + span.as_str().trim_end().len(),
+            line,
+            column,
+        }
+    }
+
+    /// Span covering `start..end` of `source`
+    ///
+    /// Offsets past the end of `source` are clamped to its length.
+    #[must_use]
+    pub fn from_offsets(source: &str, start: usize, end: usize) -> Self {
+        let end = end.min(source.len());
+        let start = start.min(end);
+        let before = &source[..start];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Self {
+            start,
+            end,
+            line,
+            column: source[line_start..start].chars().count() + 1,
+        }
+    }
+
+    /// Whether the span points into the source, false for nodes built in code
+    #[must_use]
+    pub fn is_known(&self) -> bool {
+        self.line > 0
+    }
+}
+
+/// Line starts of a source, so spans in it are located without rescanning
+/// the source up to them
+///
+/// [`Span::from_pest`] walks the input from its start on every call, which
+/// is quadratic over the statements of a large choreography.
+#[derive(Debug, Clone)]
+pub(crate) struct LineIndex {
+    /// Byte offset of the first character of every line
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub(crate) fn new(source: &str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { starts }
+    }
+
+    /// Span of a Pest match in the indexed source, as [`Span::from_pest`]
+    /// computes it
+    pub(crate) fn span(&self, span: pest::Span<'_>) -> Span {
+        let start = span.start();
+        let line = self.starts.partition_point(|&s| s <= start);
+        let line_start = self.starts[line - 1];
+        Span {
+            start,
+            end: start + span.as_str().trim_end().len(),
+            line,
+            column: span.get_input()[line_start..start].chars().count() + 1,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_offsets() {
+        let source = "roles: A, B\n  A -> B: Msg\n";
+        let span = Span::from_offsets(source, 14, 25);
+        assert_eq!((span.line, span.column), (2, 3));
+        assert_eq!(&source[span.start..span.end], "A -> B: Msg");
+        assert!(span.is_known());
+        assert!(!Span::default().is_known());
+
+        let clamped = Span::from_offsets(source, 100, 200);
+        assert_eq!((clamped.start, clamped.end), (source.len(), source.len()));
+    }
+
+    #[test]
+    fn test_line_index_matches_from_pest() {
+        let source = "roles: A, B\r\n  A -> B: Msg\n\n  \u{e9}B -> A: Ack\n";
+        let index = LineIndex::new(source);
+        for start in 0..source.len() {
+            if !source.is_char_boundary(start) {
+                continue;
+            }
+            let span = pest::Span::new(source, start, source.len()).unwrap();
+            assert_eq!(index.span(span), Span::from_pest(span), "offset {start}");
+        }
+    }
+}
@@ -0,0 +1,41 @@
+// Role-local state declarations
+//
+// A choreography may declare typed state a role keeps across its steps:
+//
+//     state at Coordinator { round: u32, commitments: Vec<Commitment> }
+//
+// Code generation emits a `CoordinatorState` struct with these fields. Its
+// effect programs see it as `state` in guard expressions, the endpoint
+// carries it between sessions, and handler callbacks of the role receive it
+// as `state: &mut CoordinatorState`.
+
+use proc_macro2::{Ident, TokenStream};
+use quote::format_ident;
+
+/// One `name: Type` field of a state declaration
+#[derive(Debug, Clone)]
+pub struct StateField {
+    pub name: Ident,
+    pub ty: TokenStream,
+}
+
+/// A `state at Role { ... }` declaration
+#[derive(Debug, Clone)]
+pub struct RoleState {
+    pub role: Ident,
+    pub fields: Vec<StateField>,
+}
+
+impl RoleState {
+    /// Name of the generated struct, `<Role>State`
+    #[must_use]
+    pub fn struct_name(&self) -> Ident {
+        format_ident!("{}State", self.role)
+    }
+
+    /// Name of the endpoint field holding this state, `<role>_state`
+    #[must_use]
+    pub fn endpoint_field(&self) -> Ident {
+        format_ident!("{}_state", self.role.to_string().to_lowercase())
+    }
+}
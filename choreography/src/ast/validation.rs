@@ -20,4 +20,61 @@ pub enum ValidationError {
 
     #[error("Extension error: {0}")]
     ExtensionError(String),
+
+    #[error(
+        "Role {role} would {action}, which needs '{permission}' but the policy does not grant it"
+    )]
+    PolicyViolation {
+        role: String,
+        permission: String,
+        action: String,
+    },
+
+    #[error("Confidential {field} reaches {role}, outside its label, via {path}")]
+    InformationLeak {
+        field: String,
+        role: String,
+        path: String,
+    },
+
+    #[error("Dependency {0} names a message the protocol never sends")]
+    UndefinedDependency(String),
+
+    #[error("Message {0} is declared in `messages` but never sent")]
+    UnusedMessage(String),
+
+    #[error("{from} sends {message} to {to}, which never receives it")]
+    OrphanMessage {
+        message: String,
+        from: String,
+        to: String,
+    },
+
+    #[error("{0}")]
+    RoleBounds(super::RoleValidationError),
+
+    #[error("Atomic block {atomic} is not a safe two-phase commit: {reason}")]
+    AtomicCommit { atomic: String, reason: String },
+}
+
+impl ValidationError {
+    /// Stable error code, listed in the API reference
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self {
+            ValidationError::UndefinedRole(_) => "RA0101",
+            ValidationError::UnboundVariable(_) => "RA0102",
+            ValidationError::InvalidChoice(_) => "RA0103",
+            ValidationError::Deadlock => "RA0104",
+            ValidationError::UnusedRole(_) => "RA0105",
+            ValidationError::ExtensionError(_) => "RA0106",
+            ValidationError::PolicyViolation { .. } => "RA0107",
+            ValidationError::InformationLeak { .. } => "RA0108",
+            ValidationError::UndefinedDependency(_) => "RA0109",
+            ValidationError::UnusedMessage(_) => "RA0110",
+            ValidationError::OrphanMessage { .. } => "RA0111",
+            ValidationError::RoleBounds(_) => "RA0112",
+            ValidationError::AtomicCommit { .. } => "RA0113",
+        }
+    }
 }
@@ -0,0 +1,535 @@
+// Command-line tools for choreographies
+//
+// Usage: choreo replay [--slow <ms>] <choreography> <trace>...
+//        choreo simulate [--seed <n>] <choreography>
+//        choreo diagram [--latency] <choreography>
+//        choreo lint <choreography>
+//        choreo analyze cost|latency <choreography>
+//        choreo golden [--unroll <n>] <choreography>
+//        choreo project <choreography> <role>
+//        choreo conform <local-type> <role> <capture>
+//        choreo debug <address>
+//
+// `replay` reads the traces or journals a session wrote (JSON lines, see
+// `runtime::trace`), checks every role's steps against the choreography and
+// prints the execution as a Mermaid sequence diagram. Steps that took at
+// least the slow threshold, 100 ms by default, are annotated with their
+// duration. Divergences, incomplete roles, and failed steps are highlighted
+// in the diagram and listed on stderr; the exit status is 1 if there is any.
+//
+// `simulate` steps through the choreography interactively. Each turn prints
+// what every role did so far and can do next, then the enabled actions. Pick
+// one by number, followed by the branch label, the payload stub, or `y`/`n`
+// for a loop, or give those when asked. `q` quits. Input may be piped in, one
+// turn per line. With `--seed`, the run is random instead: choices follow
+// their `[prob = p]` branch probabilities, and the steps taken are printed.
+//
+// `diagram` prints the choreography as a Mermaid sequence diagram. With
+// `--latency`, annotated sends show their latency and a closing note gives
+// the latency of the critical path.
+//
+// `lint` prints the warnings of `analysis::analyze`, such as code that never
+// runs, one per line and prefixed with its location when it has one. The
+// exit status is 1 if there is any.
+//
+// `analyze cost` prints the least, worst and expected `flow_cost` of every
+// role and the cost of every execution path, marking the critical one.
+// `analyze latency` prints the estimated latency of every execution path,
+// marking the slowest, and the expected latency.
+//
+// `golden` prints the golden traces of the choreography as JSON, one per
+// combination of choice branches, with loops that have no count and
+// recursion unrolled up to `--unroll` times, once by default.
+//
+// `project` prints the local type of a role, in the text form `conform`
+// reads back. `conform` checks the messages a role exchanged in a capture
+// (see `compiler::capture`) against such a local type, for participants
+// implemented outside Rust. It prints the first divergence, or that the
+// capture conforms; the exit status is 1 if it diverges.
+//
+// `debug` attaches to a session served by `runtime::debugger::Debugger`,
+// reads commands from stdin, one per line (`status`, `wait`, `step [role]`,
+// `pause`, `resume`), and prints the debugger's answer to each.
+
+use rumpsteak_aura_choreography::ast::Role;
+use rumpsteak_aura_choreography::compiler::{
+    analyze, analyze_flow_cost, analyze_latency, check_capture, generate_sequence_diagram,
+    generate_sequence_diagram_with_latency, golden_traces, golden_traces_json,
+    parse_choreography_file, parse_local_type, read_capture, replay, Action, Decision, Stepper,
+};
+use rumpsteak_aura_choreography::runtime::trace::{read_trace, TraceRecord};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
+const USAGE: &str = "usage: choreo replay [--slow <ms>] <choreography> <trace>...
+       choreo simulate [--seed <n>] <choreography>
+       choreo diagram [--latency] <choreography>
+       choreo lint <choreography>
+       choreo analyze cost|latency <choreography>
+       choreo golden [--unroll <n>] <choreography>
+       choreo project <choreography> <role>
+       choreo conform <local-type> <role> <capture>
+       choreo debug <address>";
+
+/// Bound on the steps of a random run, for protocols that loop forever
+const MAX_RANDOM_STEPS: usize = 10_000;
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("replay") => run_replay(args),
+        Some("simulate") => run_simulate(args),
+        Some("diagram") => run_diagram(args),
+        Some("lint") => run_lint(args),
+        Some("analyze") => run_analyze(args),
+        Some("golden") => run_golden(args),
+        Some("project") => run_project(args),
+        Some("conform") => run_conform(args),
+        Some("debug") => run_debug(args),
+        _ => {
+            eprintln!("{USAGE}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run_replay(mut args: impl Iterator<Item = String>) -> ExitCode {
+    let mut slow = Duration::from_millis(100);
+    let mut paths = Vec::new();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--slow" => match args.next().and_then(|value| value.parse().ok()) {
+                Some(ms) => slow = Duration::from_millis(ms),
+                None => {
+                    eprintln!("{USAGE}");
+                    return ExitCode::from(2);
+                }
+            },
+            _ if !arg.starts_with('-') => paths.push(PathBuf::from(arg)),
+            _ => {
+                eprintln!("{USAGE}");
+                return ExitCode::from(2);
+            }
+        }
+    }
+    if paths.len() < 2 {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    }
+
+    let choreography = match parse_choreography_file(&paths[0]) {
+        Ok(choreography) => choreography,
+        Err(error) => {
+            eprintln!("{}: {error}", paths[0].display());
+            return ExitCode::from(2);
+        }
+    };
+    let mut records = Vec::new();
+    for path in &paths[1..] {
+        match read_records(path) {
+            Ok(trace) => records.extend(trace),
+            Err(error) => {
+                eprintln!("{}: {error}", path.display());
+                return ExitCode::from(2);
+            }
+        }
+    }
+
+    let report = replay(&choreography, records);
+    print!("{}", report.to_mermaid(slow));
+
+    for (role, reason) in &report.unchecked_roles {
+        eprintln!("warning: {role} not checked: {reason}");
+    }
+    for divergence in report.divergences().chain(&report.incomplete) {
+        eprintln!("diverged: {divergence}");
+    }
+    let mut failed = false;
+    for record in report.failures() {
+        failed = true;
+        eprintln!(
+            "failed: {} {} step {}: {}",
+            record.role,
+            record.action,
+            record.seq,
+            record.error.as_deref().unwrap_or_default()
+        );
+    }
+    if report.diverged() || failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn read_records(path: &Path) -> Result<Vec<TraceRecord>, Box<dyn std::error::Error>> {
+    let file = std::fs::File::open(path)?;
+    Ok(read_trace(std::io::BufReader::new(file))?)
+}
+
+fn run_simulate(mut args: impl Iterator<Item = String>) -> ExitCode {
+    let mut seed = None;
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => match args.next().and_then(|value| value.parse::<u64>().ok()) {
+                Some(value) => seed = Some(value),
+                None => {
+                    eprintln!("{USAGE}");
+                    return ExitCode::from(2);
+                }
+            },
+            _ if !arg.starts_with('-') && path.is_none() => path = Some(arg),
+            _ => {
+                eprintln!("{USAGE}");
+                return ExitCode::from(2);
+            }
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+    let choreography = match parse_choreography_file(Path::new(&path)) {
+        Ok(choreography) => choreography,
+        Err(error) => {
+            eprintln!("{path}: {error}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let mut stepper = Stepper::new(&choreography);
+    if let Some(seed) = seed {
+        stepper.run_random(seed, MAX_RANDOM_STEPS);
+        for event in stepper.history() {
+            println!("{event}");
+        }
+        if stepper.is_done() {
+            println!("session complete");
+        } else {
+            println!("stopped after {} steps", stepper.history().len());
+        }
+        return ExitCode::SUCCESS;
+    }
+    let mut input = std::io::stdin().lock().lines();
+    let mut ask = |prompt: &str| -> Option<String> {
+        print!("{prompt} ");
+        std::io::stdout().flush().ok()?;
+        input.next()?.ok().map(|line| line.trim().to_string())
+    };
+    loop {
+        println!(
+            "\n-- {} after {} steps --",
+            choreography.name,
+            stepper.history().len()
+        );
+        for view in stepper.role_views() {
+            println!(
+                "{}: [{}] {}",
+                view.role,
+                view.history.join(" . "),
+                view.status
+            );
+        }
+        if stepper.is_done() {
+            println!("session complete");
+            return ExitCode::SUCCESS;
+        }
+        let actions = stepper.actions();
+        for (index, action) in actions.iter().enumerate() {
+            println!("  {}) {action}", index + 1);
+        }
+
+        let Some(line) = ask(">") else {
+            return ExitCode::SUCCESS;
+        };
+        if line == "q" {
+            return ExitCode::SUCCESS;
+        }
+        let (choice, rest) = line.split_once(' ').unwrap_or((&line, ""));
+        let Some(index) = choice
+            .parse::<usize>()
+            .ok()
+            .and_then(|choice| choice.checked_sub(1))
+            .filter(|&index| index < actions.len())
+        else {
+            println!("pick an action between 1 and {}", actions.len());
+            continue;
+        };
+        let mut answer = |prompt: &str| -> Option<String> {
+            if rest.is_empty() {
+                ask(prompt)
+            } else {
+                Some(rest.trim().to_string())
+            }
+        };
+
+        let decision = match &actions[index] {
+            Action::Send {
+                message, payload, ..
+            } => {
+                let prompt = match payload {
+                    Some(payload) => {
+                        format!("payload stub for {message}({payload}), empty for none:")
+                    }
+                    None => format!("payload stub for {message}, empty for none:"),
+                };
+                let Some(stub) = answer(&prompt) else {
+                    return ExitCode::SUCCESS;
+                };
+                Decision::Send((!stub.is_empty()).then_some(stub))
+            }
+            Action::Choose { .. } => {
+                let Some(label) = answer("branch:") else {
+                    return ExitCode::SUCCESS;
+                };
+                Decision::Branch(label)
+            }
+            Action::Repeat { .. } => {
+                let Some(again) = answer("loop again? [y/n]") else {
+                    return ExitCode::SUCCESS;
+                };
+                Decision::Repeat(matches!(again.as_str(), "y" | "yes"))
+            }
+        };
+        if let Err(error) = stepper.perform(index, decision) {
+            println!("{error}");
+        }
+    }
+}
+
+fn run_diagram(args: impl Iterator<Item = String>) -> ExitCode {
+    let args: Vec<String> = args.collect();
+    let (latency, path) = match args.as_slice() {
+        [flag, path] if flag == "--latency" => (true, path),
+        [path] if !path.starts_with('-') => (false, path),
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    match parse_choreography_file(Path::new(path)) {
+        Ok(choreography) if latency => {
+            print!("{}", generate_sequence_diagram_with_latency(&choreography));
+            ExitCode::SUCCESS
+        }
+        Ok(choreography) => {
+            print!("{}", generate_sequence_diagram(&choreography));
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("{path}: {error}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run_lint(mut args: impl Iterator<Item = String>) -> ExitCode {
+    let (Some(path), None) = (args.next(), args.next()) else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+    let choreography = match parse_choreography_file(Path::new(&path)) {
+        Ok(choreography) => choreography,
+        Err(error) => {
+            eprintln!("{path}: {error}");
+            return ExitCode::from(2);
+        }
+    };
+    let warnings = analyze(&choreography).warnings;
+    for warning in &warnings {
+        match warning.span() {
+            Some(span) => println!("{path}:{}:{}: warning: {warning}", span.line, span.column),
+            None => println!("{path}: warning: {warning}"),
+        }
+    }
+    if warnings.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::from(1)
+    }
+}
+
+fn run_analyze(mut args: impl Iterator<Item = String>) -> ExitCode {
+    let (Some(analysis), Some(path), None) = (args.next(), args.next(), args.next()) else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+    let choreography = match parse_choreography_file(Path::new(&path)) {
+        Ok(choreography) => choreography,
+        Err(error) => {
+            eprintln!("{path}: {error}");
+            return ExitCode::from(2);
+        }
+    };
+    let report = match analysis.as_str() {
+        "cost" => analyze_flow_cost(&choreography)
+            .map(|report| report.to_string())
+            .map_err(|error| error.to_string()),
+        "latency" => analyze_latency(&choreography)
+            .map(|report| report.to_string())
+            .map_err(|error| error.to_string()),
+        _ => {
+            eprintln!("{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+    match report {
+        Ok(report) => {
+            print!("{report}");
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("{path}: {error}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run_golden(mut args: impl Iterator<Item = String>) -> ExitCode {
+    let mut unroll = 1;
+    let mut path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--unroll" => match args.next().and_then(|value| value.parse().ok()) {
+                Some(value) => unroll = value,
+                None => {
+                    eprintln!("{USAGE}");
+                    return ExitCode::from(2);
+                }
+            },
+            _ if !arg.starts_with('-') && path.is_none() => path = Some(arg),
+            _ => {
+                eprintln!("{USAGE}");
+                return ExitCode::from(2);
+            }
+        }
+    }
+    let Some(path) = path else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+    match parse_choreography_file(Path::new(&path)) {
+        Ok(choreography) => {
+            println!(
+                "{}",
+                golden_traces_json(&golden_traces(&choreography, unroll))
+            );
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("{path}: {error}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run_project(mut args: impl Iterator<Item = String>) -> ExitCode {
+    let (Some(path), Some(name), None) = (args.next(), args.next(), args.next()) else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+    let choreography = match parse_choreography_file(Path::new(&path)) {
+        Ok(choreography) => choreography,
+        Err(error) => {
+            eprintln!("{path}: {error}");
+            return ExitCode::from(2);
+        }
+    };
+    let Some(role) = choreography.roles.iter().find(|role| role.name == name) else {
+        eprintln!("{path}: no role {name}");
+        return ExitCode::from(2);
+    };
+    match choreography.project(role) {
+        Ok(local_type) => {
+            print!("{}", local_type.to_pretty_string());
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("{path}: {error}");
+            ExitCode::from(2)
+        }
+    }
+}
+
+fn run_conform(mut args: impl Iterator<Item = String>) -> ExitCode {
+    let (Some(local_path), Some(name), Some(capture_path), None) =
+        (args.next(), args.next(), args.next(), args.next())
+    else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+    let local_type = match std::fs::read_to_string(&local_path)
+        .map_err(|error| error.to_string())
+        .and_then(|text| parse_local_type(&text).map_err(|error| error.to_string()))
+    {
+        Ok(local_type) => local_type,
+        Err(error) => {
+            eprintln!("{local_path}: {error}");
+            return ExitCode::from(2);
+        }
+    };
+    let Ok(role) = syn::parse_str(&name).map(Role::new) else {
+        eprintln!("{name} is not a role name");
+        return ExitCode::from(2);
+    };
+    let capture = match std::fs::File::open(&capture_path)
+        .map_err(|error| error.to_string())
+        .and_then(|file| read_capture(file).map_err(|error| error.to_string()))
+    {
+        Ok(capture) => capture,
+        Err(error) => {
+            eprintln!("{capture_path}: {error}");
+            return ExitCode::from(2);
+        }
+    };
+    match check_capture(&role, &local_type, &capture) {
+        Ok(checked) => {
+            println!("{name}: {checked} messages conform to {local_path}");
+            ExitCode::SUCCESS
+        }
+        Err(divergence) => {
+            println!("{capture_path}: {divergence}");
+            ExitCode::from(1)
+        }
+    }
+}
+
+fn run_debug(mut args: impl Iterator<Item = String>) -> ExitCode {
+    let (Some(address), None) = (args.next(), args.next()) else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+    let stream = match std::net::TcpStream::connect(&address) {
+        Ok(stream) => stream,
+        Err(error) => {
+            eprintln!("{address}: {error}");
+            return ExitCode::from(2);
+        }
+    };
+    let Ok(reader) = stream.try_clone() else {
+        eprintln!("{address}: cannot read the connection");
+        return ExitCode::from(2);
+    };
+    let mut writer = stream;
+    let mut replies = std::io::BufReader::new(reader).lines();
+    for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        if writeln!(writer, "{line}").is_err() {
+            eprintln!("{address}: connection closed");
+            return ExitCode::from(1);
+        }
+        match replies.next() {
+            Some(Ok(reply)) => println!("{reply}"),
+            _ => {
+                eprintln!("{address}: connection closed");
+                return ExitCode::from(1);
+            }
+        }
+    }
+    ExitCode::SUCCESS
+}
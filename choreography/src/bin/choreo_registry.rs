@@ -0,0 +1,52 @@
+// Protocol registry service
+//
+// Usage: choreo-registry [--lease <seconds>] <address>
+//
+// Serves a `runtime::discovery::ChoreographyRegistry` on `address`, where
+// services publish the protocols and roles they implement and session
+// initiators discover them. Publications not renewed within the lease, 60
+// seconds by default, are dropped. Runs until killed.
+
+use rumpsteak_aura_choreography::runtime::discovery::{ChoreographyRegistry, DEFAULT_LEASE};
+use std::process::ExitCode;
+use std::time::Duration;
+
+const USAGE: &str = "usage: choreo-registry [--lease <seconds>] <address>";
+
+fn main() -> ExitCode {
+    let mut lease = DEFAULT_LEASE;
+    let mut address = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--lease" => match args.next().and_then(|value| value.parse().ok()) {
+                Some(seconds) => lease = Duration::from_secs(seconds),
+                None => {
+                    eprintln!("{USAGE}");
+                    return ExitCode::from(2);
+                }
+            },
+            _ if !arg.starts_with('-') && address.is_none() => address = Some(arg),
+            _ => {
+                eprintln!("{USAGE}");
+                return ExitCode::from(2);
+            }
+        }
+    }
+    let Some(address) = address else {
+        eprintln!("{USAGE}");
+        return ExitCode::from(2);
+    };
+
+    let registry = ChoreographyRegistry::new().with_lease(lease);
+    match registry.serve(&address) {
+        Ok(bound) => eprintln!("registry listening on {bound}"),
+        Err(error) => {
+            eprintln!("{address}: {error}");
+            return ExitCode::from(2);
+        }
+    }
+    loop {
+        std::thread::park();
+    }
+}
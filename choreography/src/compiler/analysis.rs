@@ -1,7 +1,10 @@
 // Static analysis for choreographic protocols
 
-use crate::ast::{Choreography, Protocol, Role};
+use crate::ast::{Branch, Choreography, Condition, LocalType, Protocol, Role, Span};
+use crate::compiler::latency::{analyze_latency, send_latency, Latency};
+use crate::compiler::projection::project;
 use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Write};
 
 /// Analysis results for a choreography
 #[derive(Debug)]
@@ -29,7 +32,322 @@ pub enum AnalysisWarning {
     PotentialDeadlock(String),
     NoProgress(String),
     AsymmetricChoice(Role),
-    UnreachableCode(String),
+    /// Part of the protocol that can never run, see [`dead_code`]
+    UnreachableCode {
+        reason: String,
+        span: Span,
+    },
+    /// Role taking part in the protocol whose local type is nonetheless
+    /// empty
+    EmptyProjection(Role),
+    /// Different messages sent on the same channel by two threads of a
+    /// `parallel` block, see [`races`]
+    MessageRace {
+        from: String,
+        to: String,
+        messages: [String; 2],
+        spans: [Span; 2],
+    },
+    /// Role sending to different roles in two threads of a `parallel`
+    /// block, see [`races`]
+    OutputNondeterminism {
+        role: String,
+        sends: [(String, String); 2],
+        spans: [Span; 2],
+    },
+}
+
+impl AnalysisWarning {
+    /// Location the warning is about, if it has one
+    #[must_use]
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            AnalysisWarning::UnreachableCode { span, .. } if span.is_known() => Some(*span),
+            AnalysisWarning::MessageRace { spans, .. }
+            | AnalysisWarning::OutputNondeterminism { spans, .. }
+                if spans[0].is_known() =>
+            {
+                Some(spans[0])
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for AnalysisWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnalysisWarning::UnusedRole(role) => {
+                write!(f, "role {} takes no part in the protocol", role.name)
+            }
+            AnalysisWarning::PotentialDeadlock(reason) => {
+                write!(f, "potential deadlock: {reason}")
+            }
+            AnalysisWarning::NoProgress(reason) => write!(f, "no progress: {reason}"),
+            AnalysisWarning::AsymmetricChoice(role) => write!(
+                f,
+                "branches of the choice of {} start with sends to different roles",
+                role.name
+            ),
+            AnalysisWarning::UnreachableCode { reason, .. } => {
+                write!(f, "unreachable code: {reason}")
+            }
+            AnalysisWarning::EmptyProjection(role) => {
+                write!(f, "role {} has nothing to do in the protocol", role.name)
+            }
+            AnalysisWarning::MessageRace {
+                from,
+                to,
+                messages: [first, second],
+                spans,
+            } => write!(
+                f,
+                "race: {from} sends {first} and {second} to {to} in different `parallel` \
+                 threads, so {to} may receive them in either order{}",
+                other_line(spans[1])
+            ),
+            AnalysisWarning::OutputNondeterminism {
+                role,
+                sends: [(first_to, first), (second_to, second)],
+                spans,
+            } => write!(
+                f,
+                "nondeterminism: {role} sends {first} to {first_to} and {second} to \
+                 {second_to} in different `parallel` threads, in either order{}",
+                other_line(spans[1])
+            ),
+        }
+    }
+}
+
+/// ` (other send at line N)`, if the second send of a warning has a location
+fn other_line(span: Span) -> String {
+    if span.is_known() {
+        format!(" (other send at line {})", span.line)
+    } else {
+        String::new()
+    }
+}
+
+/// Potential races between the threads of `parallel` blocks
+///
+/// Two threads sending different messages on the same channel race: the
+/// receiver may get them in either order. A role sending to different roles
+/// in two threads produces its outputs in an order that depends on
+/// scheduling. Either may be intended, so both are reported once per pair
+/// of threads for the user to review.
+#[must_use]
+pub fn races(choreography: &Choreography) -> Vec<AnalysisWarning> {
+    let mut warnings = Vec::new();
+    collect_races(&choreography.protocol, &mut warnings);
+    warnings
+}
+
+/// A send of one thread: sender, receiver, message and location
+type ThreadSend = (String, String, String, Span);
+
+fn collect_races(protocol: &Protocol, warnings: &mut Vec<AnalysisWarning>) {
+    match protocol {
+        Protocol::Send { continuation, .. }
+        | Protocol::Broadcast { continuation, .. }
+        | Protocol::Extension { continuation, .. } => collect_races(continuation, warnings),
+        Protocol::Choice { branches, .. } => {
+            for branch in branches {
+                collect_races(&branch.protocol, warnings);
+            }
+        }
+        Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => collect_races(body, warnings),
+        Protocol::Parallel { protocols, .. } => {
+            let threads: Vec<Vec<ThreadSend>> = protocols
+                .iter()
+                .map(|thread| {
+                    let mut sends = Vec::new();
+                    collect_thread_sends(thread, &mut sends);
+                    sends
+                })
+                .collect();
+            for (index, first) in threads.iter().enumerate() {
+                for second in &threads[index + 1..] {
+                    thread_races(first, second, warnings);
+                }
+            }
+            for thread in protocols {
+                collect_races(thread, warnings);
+            }
+        }
+        Protocol::Var(_) | Protocol::End => {}
+    }
+}
+
+fn collect_thread_sends(protocol: &Protocol, sends: &mut Vec<ThreadSend>) {
+    match protocol {
+        Protocol::Send {
+            from,
+            to,
+            message,
+            continuation,
+            span,
+            ..
+        } => {
+            sends.push((
+                from.name.to_string(),
+                to.name.to_string(),
+                message.name.to_string(),
+                *span,
+            ));
+            collect_thread_sends(continuation, sends);
+        }
+        Protocol::Broadcast {
+            from,
+            to_all,
+            message,
+            continuation,
+            span,
+            ..
+        } => {
+            for to in to_all {
+                sends.push((
+                    from.name.to_string(),
+                    to.name.to_string(),
+                    message.name.to_string(),
+                    *span,
+                ));
+            }
+            collect_thread_sends(continuation, sends);
+        }
+        Protocol::Choice { branches, .. } => {
+            for branch in branches {
+                collect_thread_sends(&branch.protocol, sends);
+            }
+        }
+        Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => {
+            collect_thread_sends(body, sends);
+        }
+        Protocol::Parallel { protocols, .. } => {
+            for protocol in protocols {
+                collect_thread_sends(protocol, sends);
+            }
+        }
+        Protocol::Extension { continuation, .. } => collect_thread_sends(continuation, sends),
+        Protocol::Var(_) | Protocol::End => {}
+    }
+}
+
+/// Races between the sends of two threads of the same `parallel` block
+fn thread_races(first: &[ThreadSend], second: &[ThreadSend], warnings: &mut Vec<AnalysisWarning>) {
+    let mut racing = HashSet::new();
+    for (from, to, message, span) in first {
+        let race = second.iter().find(|(other_from, other_to, other, _)| {
+            other_from == from && other_to == to && other != message
+        });
+        if let Some((_, _, other, other_span)) = race {
+            if racing.insert((from, to)) {
+                warnings.push(AnalysisWarning::MessageRace {
+                    from: from.clone(),
+                    to: to.clone(),
+                    messages: [message.clone(), other.clone()],
+                    spans: [*span, *other_span],
+                });
+            }
+        }
+    }
+
+    // Roles already racing on a channel are not reported again
+    let mut reported: HashSet<&String> = racing.iter().map(|(from, _)| *from).collect();
+    for (from, to, message, span) in first {
+        if let Some((_, other_to, other, other_span)) = second
+            .iter()
+            .find(|(other_from, other_to, _, _)| other_from == from && other_to != to)
+        {
+            if reported.insert(from) {
+                warnings.push(AnalysisWarning::OutputNondeterminism {
+                    role: from.clone(),
+                    sends: [
+                        (to.clone(), message.clone()),
+                        (other_to.clone(), other.clone()),
+                    ],
+                    spans: [*span, *other_span],
+                });
+            }
+        }
+    }
+}
+
+/// Parts of `choreography` that can never run
+///
+/// These are statements after a `loop` that is never left, branches guarded
+/// by `false` or repeating the label of an earlier branch, bodies of loops
+/// running zero times, and roles that appear in the protocol but whose
+/// local type is empty.
+#[must_use]
+pub fn dead_code(choreography: &Choreography) -> Vec<AnalysisWarning> {
+    let mut warnings: Vec<AnalysisWarning> = choreography
+        .unreachable
+        .iter()
+        .map(|span| AnalysisWarning::UnreachableCode {
+            reason: "this statement follows a `loop` that is never left".to_string(),
+            span: *span,
+        })
+        .collect();
+    collect_dead_code(&choreography.protocol, &mut warnings);
+    warnings.extend(
+        choreography
+            .roles
+            .iter()
+            .filter(|role| choreography.protocol.mentions_role(role))
+            .filter(|role| matches!(project(choreography, role), Ok(LocalType::End)))
+            .map(|role| AnalysisWarning::EmptyProjection(role.clone())),
+    );
+    warnings
+}
+
+fn collect_dead_code(protocol: &Protocol, warnings: &mut Vec<AnalysisWarning>) {
+    match protocol {
+        Protocol::Send { continuation, .. }
+        | Protocol::Broadcast { continuation, .. }
+        | Protocol::Extension { continuation, .. } => collect_dead_code(continuation, warnings),
+        Protocol::Choice { role, branches, .. } => {
+            for (index, branch) in branches.iter().enumerate() {
+                let unreachable = |reason: String| AnalysisWarning::UnreachableCode {
+                    reason,
+                    span: branch.span,
+                };
+                if branch
+                    .guard
+                    .as_ref()
+                    .is_some_and(|guard| guard.to_string() == "false")
+                {
+                    warnings.push(unreachable(format!(
+                        "branch `{}` is guarded by `false`",
+                        branch.label
+                    )));
+                } else if branches[..index].iter().any(|b| b.label == branch.label) {
+                    warnings.push(unreachable(format!(
+                        "branch `{}` repeats the label of an earlier branch of the choice of {}",
+                        branch.label, role.name
+                    )));
+                }
+                collect_dead_code(&branch.protocol, warnings);
+            }
+        }
+        Protocol::Loop {
+            condition: Some(Condition::Count(0)),
+            span,
+            ..
+        } => warnings.push(AnalysisWarning::UnreachableCode {
+            reason: "the body of `loop (count: 0)` never runs".to_string(),
+            span: *span,
+        }),
+        Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => {
+            collect_dead_code(body, warnings);
+        }
+        Protocol::Parallel { protocols, .. } => {
+            for protocol in protocols {
+                collect_dead_code(protocol, warnings);
+            }
+        }
+        Protocol::Var(_) | Protocol::End => {}
+    }
 }
 
 /// Communication graph for visualization
@@ -101,6 +419,12 @@ impl<'a> Analyzer<'a> {
             }
         }
 
+        // Check for code that never runs
+        self.warnings.extend(dead_code(self.choreography));
+
+        // Check for races between parallel threads
+        self.warnings.extend(races(self.choreography));
+
         AnalysisResult {
             is_deadlock_free,
             has_progress,
@@ -171,7 +495,9 @@ impl<'a> Analyzer<'a> {
                     })
                     .collect();
 
-                if recipients.len() > 1 {
+                // The branches of an `or on failure` send or a `while` are
+                // told apart by a separate notification
+                if recipients.len() > 1 && protocol.failure_of().is_none() && !protocol.is_while() {
                     self.warnings
                         .push(AnalysisWarning::AsymmetricChoice(role.clone()));
                 }
@@ -185,7 +511,7 @@ impl<'a> Analyzer<'a> {
                 self.analyze_protocol(body);
             }
 
-            Protocol::Parallel { protocols } => {
+            Protocol::Parallel { protocols, .. } => {
                 for p in protocols {
                     self.analyze_protocol(p);
                 }
@@ -241,7 +567,7 @@ impl<'a> Analyzer<'a> {
             Protocol::Loop { body, .. } => {
                 Self::extract_dependencies(body, deps);
             }
-            Protocol::Parallel { protocols } => {
+            Protocol::Parallel { protocols, .. } => {
                 // Parallel branches don't create dependencies between them
                 for p in protocols {
                     Self::extract_dependencies(p, deps);
@@ -283,7 +609,9 @@ impl<'a> Analyzer<'a> {
                 // Check that loop body has communication (progress)
                 has_communication(body)
             }
-            Protocol::Parallel { protocols } => protocols.iter().all(Self::check_protocol_progress),
+            Protocol::Parallel { protocols, .. } => {
+                protocols.iter().all(Self::check_protocol_progress)
+            }
             Protocol::Rec { body, .. } => {
                 // Recursive protocols must have communication
                 has_communication(body)
@@ -361,7 +689,7 @@ fn has_communication(protocol: &Protocol) -> bool {
             branches.iter().any(|b| has_communication(&b.protocol))
         }
         Protocol::Loop { body, .. } => has_communication(body),
-        Protocol::Parallel { protocols } => protocols.iter().any(has_communication),
+        Protocol::Parallel { protocols, .. } => protocols.iter().any(has_communication),
         Protocol::Rec { body, .. } => has_communication(body),
         Protocol::Var(_) | Protocol::End => false,
 
@@ -392,3 +720,322 @@ pub fn generate_dot_graph(comm_graph: &CommunicationGraph) -> String {
     dot.push_str("}\n");
     dot
 }
+
+/// Generate a Mermaid sequence diagram of the choreography, for
+/// documentation
+///
+/// Choices become `alt` blocks. If any branch of a choice has a `prob`, every
+/// branch is labelled with its probability.
+#[must_use]
+pub fn generate_sequence_diagram(choreography: &Choreography) -> String {
+    sequence_diagram(choreography, false)
+}
+
+/// Generate the sequence diagram with the `latency` of every annotated send
+/// after its message, and a closing note giving the latency of the critical
+/// path
+///
+/// Invalid latencies are left out, see [`analyze_latency`] for the errors.
+#[must_use]
+pub fn generate_sequence_diagram_with_latency(choreography: &Choreography) -> String {
+    sequence_diagram(choreography, true)
+}
+
+fn sequence_diagram(choreography: &Choreography, latency: bool) -> String {
+    let mut diagram = String::from("sequenceDiagram\n");
+    for role in &choreography.roles {
+        let _ = writeln!(diagram, "    participant {}", role.name);
+    }
+    let everyone = match (choreography.roles.first(), choreography.roles.last()) {
+        (Some(first), Some(last)) if first != last => format!("{},{}", first.name, last.name),
+        (Some(first), _) => first.name.to_string(),
+        _ => String::new(),
+    };
+    write_sequence(&mut diagram, &choreography.protocol, 1, &everyone, latency);
+    if latency {
+        if let Ok(report) = analyze_latency(choreography) {
+            let _ = writeln!(
+                diagram,
+                "    Note over {everyone}: critical path {}",
+                report.worst_case()
+            );
+        }
+    }
+    diagram
+}
+
+fn write_sequence(
+    diagram: &mut String,
+    protocol: &Protocol,
+    depth: usize,
+    everyone: &str,
+    latency: bool,
+) {
+    let indent = "    ".repeat(depth);
+    let nested = |diagram: &mut String, protocol| {
+        write_sequence(diagram, protocol, depth + 1, everyone, latency)
+    };
+    let mut current = protocol;
+    loop {
+        let suffix = match send_latency(current) {
+            Ok(duration) if latency && !duration.is_zero() => {
+                format!(" ({})", Latency::Bounded(duration))
+            }
+            _ => String::new(),
+        };
+        current = match current {
+            Protocol::Send {
+                from,
+                to,
+                message,
+                continuation,
+                ..
+            } => {
+                let _ = writeln!(
+                    diagram,
+                    "{indent}{}->>{}: {}{suffix}",
+                    from.name, to.name, message.name
+                );
+                continuation
+            }
+            Protocol::Broadcast {
+                from,
+                to_all,
+                message,
+                continuation,
+                ..
+            } => {
+                for to in to_all {
+                    let _ = writeln!(
+                        diagram,
+                        "{indent}{}->>{}: {}{suffix}",
+                        from.name, to.name, message.name
+                    );
+                }
+                continuation
+            }
+            Protocol::Choice { role, branches, .. } => {
+                let labelled = branches.iter().any(|b| b.probability.is_some());
+                let probabilities = Branch::probabilities(branches);
+                for (index, (branch, probability)) in branches.iter().zip(probabilities).enumerate()
+                {
+                    let keyword = if index == 0 { "alt" } else { "else" };
+                    let _ = write!(diagram, "{indent}{keyword} {}: {}", role.name, branch.label);
+                    if labelled {
+                        let _ = write!(diagram, " ({:.0}%)", probability * 100.0);
+                    }
+                    diagram.push('\n');
+                    nested(diagram, &branch.protocol);
+                }
+                let _ = writeln!(diagram, "{indent}end");
+                return;
+            }
+            Protocol::Loop {
+                condition, body, ..
+            } => {
+                let label = match condition {
+                    Some(Condition::Count(n)) => format!("{n} times"),
+                    Some(Condition::RoleDecides(role)) => format!("while {} decides", role.name),
+                    Some(Condition::Custom(tokens)) => tokens.to_string(),
+                    None => "repeat".to_string(),
+                };
+                let _ = writeln!(diagram, "{indent}loop {label}");
+                nested(diagram, body);
+                let _ = writeln!(diagram, "{indent}end");
+                return;
+            }
+            Protocol::Parallel { protocols, .. } => {
+                for (index, protocol) in protocols.iter().enumerate() {
+                    let keyword = if index == 0 { "par" } else { "and" };
+                    let _ = writeln!(diagram, "{indent}{keyword}");
+                    nested(diagram, protocol);
+                }
+                let _ = writeln!(diagram, "{indent}end");
+                return;
+            }
+            Protocol::Rec { label, body, .. } => {
+                let _ = writeln!(diagram, "{indent}loop {label}");
+                nested(diagram, body);
+                let _ = writeln!(diagram, "{indent}end");
+                return;
+            }
+            Protocol::Var(label) => {
+                let _ = writeln!(diagram, "{indent}Note over {everyone}: continue {label}");
+                return;
+            }
+            Protocol::Extension { continuation, .. } => continuation,
+            Protocol::End => return,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parse_choreography_str;
+
+    #[test]
+    fn test_sequence_diagram_with_probabilities() {
+        let choreography = parse_choreography_str(
+            r"
+choreography Offer {
+    roles: Buyer, Seller
+    Seller -> Buyer: Quote
+    choice Buyer {
+        accept [prob = 0.75]: {
+            Buyer -> Seller: Accept
+        }
+        reject: {
+            Buyer -> Seller: Reject
+        }
+    }
+}
+",
+        )
+        .unwrap();
+
+        assert_eq!(
+            generate_sequence_diagram(&choreography),
+            "sequenceDiagram
+    participant Buyer
+    participant Seller
+    Seller->>Buyer: Quote
+    alt Buyer: accept (75%)
+        Buyer->>Seller: Accept
+    else Buyer: reject (25%)
+        Buyer->>Seller: Reject
+    end
+"
+        );
+    }
+
+    #[test]
+    fn test_dead_code() {
+        let choreography = parse_choreography_str(
+            r"
+choreography Market {
+    roles: Buyer, Seller
+    Buyer -> Seller: Hello
+    choice Seller {
+        offer: {
+            Seller -> Buyer: Offer
+            loop (count: 0) {
+                Buyer -> Seller: Ping
+            }
+        }
+        never when (false): {
+            Seller -> Buyer: Gift
+        }
+        offer: {
+            Seller -> Buyer: Discount
+        }
+    }
+    loop {
+        Seller -> Buyer: Tick
+    }
+    Buyer -> Seller: Bye
+}
+",
+        )
+        .unwrap();
+
+        let warnings: Vec<String> = dead_code(&choreography)
+            .iter()
+            .map(|warning| match warning.span() {
+                Some(span) => format!("{}: {warning}", span.line),
+                None => warning.to_string(),
+            })
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                "22: unreachable code: this statement follows a `loop` that is never left",
+                "8: unreachable code: the body of `loop (count: 0)` never runs",
+                "12: unreachable code: branch `never` is guarded by `false`",
+                "15: unreachable code: branch `offer` repeats the label of an earlier branch of the choice of Seller",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_races_between_parallel_threads() {
+        let choreography = parse_choreography_str(
+            r"
+choreography Sync {
+    roles: Client, Server, Log
+    parallel {
+        Client -> Server: Upload
+        Client -> Log: Started
+    |
+        Client -> Server: Cancel
+    |
+        Server -> Log: Stored
+    }
+}
+",
+        )
+        .unwrap();
+
+        let warnings: Vec<String> = races(&choreography)
+            .iter()
+            .map(|warning| format!("{}: {warning}", warning.span().unwrap().line))
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                "5: race: Client sends Upload and Cancel to Server in different `parallel` threads, so Server may receive them in either order (other send at line 8)",
+            ]
+        );
+
+        let choreography = parse_choreography_str(
+            r"
+choreography Fanout {
+    roles: Client, Server, Log
+    parallel {
+        Client -> Server: Upload
+    |
+        Client -> Log: Started
+    }
+}
+",
+        )
+        .unwrap();
+        let warnings: Vec<String> = races(&choreography)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                "nondeterminism: Client sends Upload to Server and Started to Log in different `parallel` threads, in either order (other send at line 7)",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sequence_diagram_with_latency() {
+        let choreography = parse_choreography_str(
+            r#"
+choreography Fetch {
+    roles: Client, Server
+    [@latency = "20ms"]
+    Client -> Server: Get
+    Server -> Client: Page
+}
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            generate_sequence_diagram_with_latency(&choreography),
+            "sequenceDiagram
+    participant Client
+    participant Server
+    Client->>Server: Get (20 ms)
+    Server->>Client: Page
+    Note over Client,Server: critical path 20 ms
+"
+        );
+        assert!(!generate_sequence_diagram(&choreography).contains("ms"));
+    }
+}
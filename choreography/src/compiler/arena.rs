@@ -0,0 +1,149 @@
+// Interned identifiers and flat statement storage for the parser
+//
+// The parser resolves each distinct identifier or role reference once, to a
+// `Symbol`, and keeps statements in a single `Arena` with every block a
+// range of it. Calls to protocol definitions refer to the definition's block
+// instead of copying it. `Ident`s, `Role`s and boxed nodes are only built
+// when the statements are lowered to the public `Protocol` AST, once per
+// node.
+
+use std::collections::HashMap;
+
+/// Handle to a value in an [`Interner`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct Symbol(u32);
+
+/// Values keyed by the source text they were built from
+#[derive(Debug)]
+pub(crate) struct Interner<T> {
+    symbols: HashMap<Box<str>, Symbol>,
+    values: Vec<T>,
+}
+
+impl<T> Default for Interner<T> {
+    fn default() -> Self {
+        Self {
+            symbols: HashMap::new(),
+            values: Vec::new(),
+        }
+    }
+}
+
+impl<T> Interner<T> {
+    /// Symbol of `text` if it was interned before
+    pub(crate) fn lookup(&self, text: &str) -> Option<Symbol> {
+        self.symbols.get(text).copied()
+    }
+
+    /// Intern `value` as the value of `text`
+    pub(crate) fn insert(&mut self, text: &str, value: T) -> Symbol {
+        let symbol = Symbol(self.values.len() as u32);
+        self.values.push(value);
+        self.symbols.insert(text.into(), symbol);
+        symbol
+    }
+
+    /// Symbol of `text`, building its value on first use
+    pub(crate) fn intern_with(&mut self, text: &str, value: impl FnOnce() -> T) -> Symbol {
+        match self.lookup(text) {
+            Some(symbol) => symbol,
+            None => self.insert(text, value()),
+        }
+    }
+
+    pub(crate) fn get(&self, symbol: Symbol) -> &T {
+        &self.values[symbol.0 as usize]
+    }
+}
+
+/// Contiguous run of items in an [`Arena`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Block {
+    start: u32,
+    end: u32,
+}
+
+/// Storage for blocks of items built depth first
+///
+/// Items of the innermost open block are pushed on a stack of pending items
+/// and moved into the arena in one piece when the block is closed, so blocks
+/// nested in it end up before it and every block stays contiguous.
+#[derive(Debug)]
+pub(crate) struct Arena<T> {
+    items: Vec<T>,
+    pending: Vec<T>,
+}
+
+impl<T> Default for Arena<T> {
+    fn default() -> Self {
+        Self {
+            items: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+}
+
+impl<T> Arena<T> {
+    /// Open a block, returning the mark to close it with
+    pub(crate) fn open(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Add an item to the innermost open block
+    pub(crate) fn push(&mut self, item: T) {
+        self.pending.push(item);
+    }
+
+    /// Close the block opened at `mark`
+    pub(crate) fn close(&mut self, mark: usize) -> Block {
+        let start = self.items.len() as u32;
+        self.items.extend(self.pending.drain(mark..));
+        Block {
+            start,
+            end: self.items.len() as u32,
+        }
+    }
+
+    pub(crate) fn get(&self, block: Block) -> &[T] {
+        &self.items[block.start as usize..block.end as usize]
+    }
+
+    pub(crate) fn get_mut(&mut self, block: Block) -> &mut [T] {
+        &mut self.items[block.start as usize..block.end as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interner_shares_values() {
+        let mut interner = Interner::default();
+        let a = interner.intern_with("Alice", || "Alice".to_string());
+        let b = interner.intern_with("Bob", || "Bob".to_string());
+        let again = interner.intern_with("Alice", || unreachable!());
+
+        assert_eq!(a, again);
+        assert_ne!(a, b);
+        assert_eq!(interner.get(b), "Bob");
+        assert_eq!(interner.lookup("Carol"), None);
+    }
+
+    #[test]
+    fn test_nested_blocks_stay_contiguous() {
+        let mut arena = Arena::default();
+        let outer = arena.open();
+        arena.push(1);
+        let inner = arena.open();
+        arena.push(10);
+        arena.push(11);
+        let inner = arena.close(inner);
+        arena.push(2);
+        let outer = arena.close(outer);
+
+        assert_eq!(arena.get(inner), &[10, 11]);
+        assert_eq!(arena.get(outer), &[1, 2]);
+        assert!(arena.get(Block::default()).is_empty());
+    }
+}
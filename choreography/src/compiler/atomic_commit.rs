@@ -0,0 +1,219 @@
+// Two-phase commit safety of `atomic` blocks
+//
+// `atomic at C over P { ... }` runs its body and then a two-phase commit
+// between the coordinator `C` and the participants `P`, whose steps carry
+// the `atomic` annotation (see `ast::ATOMIC`). Validation checks, along
+// every path through the protocol, that each commit on it is safe:
+//
+//     P votes only after C asked it to prepare
+//     C decides only once P has voted
+//     P is told the decision C took, `Commit` on the commit branch and
+//     `Abort` on the abort branch, and nothing else
+//     P acknowledges the decision before the path ends
+//
+// The generated drivers have C commit only if every vote was yes. Together
+// this is the safety property of two-phase commit: no participant commits
+// unless all voted to, and all of them learn the same decision.
+
+use crate::ast::{
+    Choreography, Protocol, ValidationError, ABORT, ACK, ATOMIC_ABORT, ATOMIC_COMMIT, COMMIT,
+    PREPARE, VOTE,
+};
+use std::collections::BTreeSet;
+
+/// How far a two-phase commit has got on a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    Prepared,
+    Voted,
+    /// Decided, and the participants are to be sent this message
+    Decided(&'static str),
+    Told,
+}
+
+/// Two-phase commits of `atomic` blocks that are not safe on some path
+#[must_use]
+pub fn check_atomic_commit(choreography: &Choreography) -> Vec<ValidationError> {
+    let mut violations = BTreeSet::new();
+    walk(&choreography.protocol, &mut Vec::new(), &mut violations);
+    violations
+        .into_iter()
+        .map(|(atomic, reason)| ValidationError::AtomicCommit { atomic, reason })
+        .collect()
+}
+
+/// Follow every path through `protocol`, with `open` the commits begun on
+/// the path so far
+fn walk<'a>(
+    protocol: &'a Protocol,
+    open: &mut Vec<(&'a str, Phase)>,
+    violations: &mut BTreeSet<(String, String)>,
+) {
+    match protocol {
+        Protocol::Send {
+            message,
+            continuation,
+            ..
+        } => {
+            if let Some(atomic) = protocol.atomic() {
+                let position = open.iter().position(|(open, _)| *open == atomic);
+                let phase = position.map(|i| open[i].1);
+                let name = message.name.to_string();
+                match (name.as_str(), phase) {
+                    (PREPARE, None) => open.push((atomic, Phase::Prepared)),
+                    (PREPARE, Some(_)) => {
+                        report(
+                            violations,
+                            atomic,
+                            "the commit starts again before it ended",
+                        );
+                    }
+                    (VOTE, Some(Phase::Prepared)) => open[position.unwrap()].1 = Phase::Voted,
+                    (VOTE, _) => report(
+                        violations,
+                        atomic,
+                        "the participants vote without being prepared",
+                    ),
+                    (COMMIT | ABORT, Some(Phase::Decided(decision))) if decision == name => {
+                        open[position.unwrap()].1 = Phase::Told;
+                    }
+                    (COMMIT | ABORT, Some(Phase::Decided(_))) => report(
+                        violations,
+                        atomic,
+                        &format!("the participants are sent {name} against the decision"),
+                    ),
+                    (COMMIT | ABORT, _) => report(
+                        violations,
+                        atomic,
+                        &format!("the participants are sent {name} before the decision"),
+                    ),
+                    (ACK, Some(Phase::Told)) => {
+                        open.remove(position.unwrap());
+                    }
+                    (ACK, _) => report(
+                        violations,
+                        atomic,
+                        "the participants acknowledge a decision they were not told",
+                    ),
+                    _ => {}
+                }
+            }
+            walk(continuation, open, violations);
+        }
+        Protocol::Broadcast { continuation, .. } | Protocol::Extension { continuation, .. } => {
+            walk(continuation, open, violations);
+        }
+        Protocol::Choice { branches, .. } => {
+            let atomic = protocol.atomic();
+            if let Some(atomic) = atomic {
+                if !open.contains(&(atomic, Phase::Voted)) {
+                    report(
+                        violations,
+                        atomic,
+                        "the coordinator decides before every vote is in",
+                    );
+                }
+            }
+            for branch in branches {
+                let mut open = open.clone();
+                if let Some(atomic) = atomic {
+                    let decision = match branch.label.to_string().as_str() {
+                        ATOMIC_COMMIT => COMMIT,
+                        ATOMIC_ABORT => ABORT,
+                        label => {
+                            report(
+                                violations,
+                                atomic,
+                                &format!("`{label}` is neither commit nor abort"),
+                            );
+                            continue;
+                        }
+                    };
+                    for (open, phase) in &mut open {
+                        if *open == atomic {
+                            *phase = Phase::Decided(decision);
+                        }
+                    }
+                }
+                walk(&branch.protocol, &mut open, violations);
+            }
+        }
+        Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => {
+            walk(body, &mut open.clone(), violations);
+        }
+        Protocol::Parallel { protocols, .. } => {
+            for protocol in protocols {
+                walk(protocol, &mut open.clone(), violations);
+            }
+        }
+        Protocol::Var(_) | Protocol::End => {
+            for (atomic, phase) in open.iter() {
+                let reason = match phase {
+                    Phase::Prepared => "the path ends before the participants voted",
+                    Phase::Voted => "the path ends before the coordinator decided",
+                    Phase::Decided(_) => "the path ends before the participants were told",
+                    Phase::Told => "the path ends before the participants acknowledged",
+                };
+                report(violations, atomic, reason);
+            }
+        }
+    }
+}
+
+fn report(violations: &mut BTreeSet<(String, String)>, atomic: &str, reason: &str) {
+    violations.insert((atomic.to_string(), reason.to_string()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parser::parse_choreography_str;
+
+    #[test]
+    fn test_atomic_blocks_are_safe() {
+        let choreography = parse_choreography_str(
+            r"
+choreography Ledger {
+    roles: Client, Coordinator, Participant[*]
+    Client -> Coordinator: Transfer
+    atomic at Coordinator over Participant[*] {
+        Coordinator -> Participant[*]: Write
+    }
+    Coordinator -> Client: Done
+}
+",
+        )
+        .unwrap();
+        assert!(check_atomic_commit(&choreography).is_empty());
+    }
+
+    #[test]
+    fn test_commit_without_votes_is_unsafe() {
+        // A decision written by hand with the annotation of an atomic block
+        let choreography = parse_choreography_str(
+            r#"
+choreography Ledger {
+    roles: Coordinator, Participant
+    [@atomic = "Coordinator over Participant"]
+    Coordinator -> Participant: Prepare
+    [@atomic = "Coordinator over Participant"]
+    Coordinator -> Participant: Commit
+}
+"#,
+        )
+        .unwrap();
+        let reasons: Vec<String> = check_atomic_commit(&choreography)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                "Atomic block Coordinator over Participant is not a safe two-phase commit: \
+                 the participants are sent Commit before the decision",
+                "Atomic block Coordinator over Participant is not a safe two-phase commit: \
+                 the path ends before the participants voted",
+            ]
+        );
+    }
+}
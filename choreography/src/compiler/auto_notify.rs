@@ -0,0 +1,237 @@
+// Notifications inserted by `#[auto_notify]`
+//
+// A role that takes no part in the first step of a choice but acts
+// differently depending on the branch cannot be projected: it has no way of
+// learning which branch was taken. `#[auto_notify]` in front of the DSL
+// string of a `choreography!` invocation fixes this by having the chooser
+// tell it. At the start of every branch `label` of such a choice it inserts
+//
+//     Chooser -> Role: <Label>Chosen
+//
+// for each uninformed role, in declaration order. Nested choices are fixed
+// before the choices enclosing them, and choices whose every role is already
+// informed are left alone, so only the messages projection needs are added.
+
+use crate::ast::{Branch, Choreography, MessageType, Protocol, Span, ON_FAILURE, WHILE};
+use crate::compiler::handler_codegen::variant_name;
+use crate::compiler::projection::uninformed_roles;
+use quote::format_ident;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Name of the macro attribute requesting notifications
+pub const AUTO_NOTIFY: &str = "auto_notify";
+
+/// A send inserted by [`auto_notify`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    /// Role making the choice
+    pub from: String,
+    /// Role told the branch
+    pub to: String,
+    /// Label of the branch the send starts
+    pub branch: String,
+    /// Name of the inserted message
+    pub message: String,
+    /// Location of the branch
+    pub span: Span,
+}
+
+impl fmt::Display for Notification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} -> {}: {} at the start of branch `{}`",
+            self.from, self.to, self.message, self.branch
+        )?;
+        if self.span.line > 0 {
+            write!(f, " (line {})", self.span.line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Insert the sends telling every uninformed role of `choreography` which
+/// branch of a choice was taken
+///
+/// Returns the inserted sends, innermost choices first.
+pub fn auto_notify(choreography: &mut Choreography) -> Vec<Notification> {
+    let protocol = std::mem::replace(&mut choreography.protocol, Protocol::End);
+    let mut inserted = Vec::new();
+    choreography.protocol = notify(protocol, choreography, &mut inserted);
+    inserted
+}
+
+fn notify(
+    protocol: Protocol,
+    choreography: &Choreography,
+    inserted: &mut Vec<Notification>,
+) -> Protocol {
+    let mut notify = |protocol| notify(protocol, choreography, inserted);
+    match protocol {
+        Protocol::Send {
+            from,
+            to,
+            message,
+            continuation,
+            annotations,
+            from_annotations,
+            to_annotations,
+            span,
+        } => Protocol::Send {
+            from,
+            to,
+            message,
+            continuation: Box::new(notify(*continuation)),
+            annotations,
+            from_annotations,
+            to_annotations,
+            span,
+        },
+        Protocol::Broadcast {
+            from,
+            to_all,
+            message,
+            continuation,
+            annotations,
+            from_annotations,
+            span,
+        } => Protocol::Broadcast {
+            from,
+            to_all,
+            message,
+            continuation: Box::new(notify(*continuation)),
+            annotations,
+            from_annotations,
+            span,
+        },
+        Protocol::Choice {
+            role,
+            branches,
+            annotations,
+            span,
+        } => {
+            let mut branches: Vec<Branch> = branches
+                .into_iter()
+                .map(|branch| Branch {
+                    protocol: notify(branch.protocol),
+                    ..branch
+                })
+                .collect();
+            // An `or on failure` send and a `while` tell the roles themselves
+            if !annotations.contains_key(ON_FAILURE) && !annotations.contains_key(WHILE) {
+                let uninformed = uninformed_roles(choreography, &role, &branches);
+                for branch in &mut branches {
+                    let message = format_ident!("{}Chosen", variant_name(&branch.label));
+                    inserted.extend(uninformed.iter().map(|to| Notification {
+                        from: role.name.to_string(),
+                        to: to.name.to_string(),
+                        branch: branch.label.to_string(),
+                        message: message.to_string(),
+                        span: branch.span,
+                    }));
+                    for to in uninformed.iter().rev() {
+                        let continuation = std::mem::replace(&mut branch.protocol, Protocol::End);
+                        branch.protocol = Protocol::Send {
+                            from: role.clone(),
+                            to: to.clone(),
+                            message: MessageType {
+                                name: message.clone(),
+                                type_annotation: None,
+                                payload: None,
+                            },
+                            continuation: Box::new(continuation),
+                            annotations: HashMap::new(),
+                            from_annotations: HashMap::new(),
+                            to_annotations: HashMap::new(),
+                            span: branch.span,
+                        };
+                    }
+                }
+            }
+            Protocol::Choice {
+                role,
+                branches,
+                annotations,
+                span,
+            }
+        }
+        Protocol::Loop {
+            condition,
+            body,
+            span,
+        } => Protocol::Loop {
+            condition,
+            body: Box::new(notify(*body)),
+            span,
+        },
+        Protocol::Parallel { protocols, span } => Protocol::Parallel {
+            protocols: protocols.into_iter().map(notify).collect(),
+            span,
+        },
+        Protocol::Rec { label, body, span } => Protocol::Rec {
+            label,
+            body: Box::new(notify(*body)),
+            span,
+        },
+        Protocol::Extension {
+            extension,
+            continuation,
+            annotations,
+            span,
+        } => Protocol::Extension {
+            extension,
+            continuation: Box::new(notify(*continuation)),
+            annotations,
+            span,
+        },
+        protocol @ (Protocol::Var(_) | Protocol::End) => protocol,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parser::parse_choreography_str;
+
+    #[test]
+    fn test_notifies_only_uninformed_roles() {
+        let mut choreography = parse_choreography_str(
+            r"
+choreography Review {
+    roles: Author, Editor, Printer, Archive
+    Author -> Editor: Draft
+    choice Editor {
+        accept: {
+            Editor -> Author: Accepted
+            Author -> Printer: Manuscript
+            Editor -> Archive: Record
+        }
+        reject: {
+            Editor -> Author: Rejected
+            Editor -> Archive: Record
+        }
+    }
+}
+",
+        )
+        .unwrap();
+        assert!(choreography.project_all().is_err());
+
+        let inserted = auto_notify(&mut choreography);
+        let inserted: Vec<String> = inserted.iter().map(ToString::to_string).collect();
+        // The Archive does the same either way, so it is not told
+        assert_eq!(
+            inserted,
+            [
+                "Editor -> Printer: AcceptChosen at the start of branch `accept` (line 6)",
+                "Editor -> Printer: RejectChosen at the start of branch `reject` (line 11)",
+            ]
+        );
+        choreography.validate().unwrap();
+        choreography.project_all().unwrap();
+
+        // Running it again finds nothing left to fix
+        assert!(auto_notify(&mut choreography).is_empty());
+    }
+}
@@ -0,0 +1,201 @@
+// Conformance of foreign implementations
+//
+// A participant written outside Rust cannot run under a conformance monitor,
+// but the messages it exchanged can be captured on the wire and checked
+// afterwards against its projected local type. A capture lists every
+// message seen, in the order it was seen, as a JSON array or as JSON lines:
+//
+//     {"time_unix_nano": 1700000000000000000, "from": "Buyer", "to": "Seller", "label": "Bid"}
+//     {"from": "Seller", "to": "Buyer", "label": "accept", "choice": true}
+//
+// `choice` marks a branch selection, whose label is the branch taken. The
+// checked role's messages become sends and receives, or selections and
+// branches, for a monitor of its local type; messages between other roles
+// are ignored. The first message the local type does not allow is the
+// divergence, and a capture ending before the local type may end is
+// incomplete.
+
+use crate::ast::{LocalType, Role};
+use crate::compiler::codegen::monitor_spec;
+use crate::runtime::monitor::{ConformanceMonitor, MonitorViolation, ObservedEvent};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io::Read;
+
+/// One message seen on the wire
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CapturedMessage {
+    /// When the message was seen, if the capture records it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_unix_nano: Option<u64>,
+    pub from: String,
+    pub to: String,
+    /// Message type name, or the branch label of a selection
+    pub label: String,
+    /// Whether the message selects a branch
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub choice: bool,
+}
+
+impl fmt::Display for CapturedMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.choice {
+            write!(f, "{} -> {}: [{}]", self.from, self.to, self.label)
+        } else {
+            write!(f, "{} -> {}: {}", self.from, self.to, self.label)
+        }
+    }
+}
+
+/// Errors raised while reading a capture
+#[derive(Debug, thiserror::Error)]
+pub enum CaptureError {
+    #[error("capture I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("capture line {line} is not a captured message: {reason}")]
+    Malformed { line: usize, reason: String },
+}
+
+/// Where a capture leaves the local type of the checked role
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CaptureDivergence {
+    /// Position in the capture of the message the local type does not
+    /// allow, from 0, or `None` if the capture ends too early
+    pub index: Option<usize>,
+    pub violation: MonitorViolation,
+}
+
+impl fmt::Display for CaptureDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.index {
+            Some(index) => write!(f, "message {index} of the capture diverges: "),
+            None => write!(f, "capture ends early: "),
+        }?;
+        write!(f, "{}", self.violation)
+    }
+}
+
+impl std::error::Error for CaptureDivergence {}
+
+/// Read a capture written as a JSON array or as JSON lines
+///
+/// # Errors
+///
+/// [`CaptureError`] if the capture cannot be read or an entry is not a
+/// captured message.
+pub fn read_capture(mut reader: impl Read) -> Result<Vec<CapturedMessage>, CaptureError> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    if text.trim_start().starts_with('[') {
+        return serde_json::from_str(&text).map_err(|error| CaptureError::Malformed {
+            line: error.line(),
+            reason: error.to_string(),
+        });
+    }
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line).map_err(|error| CaptureError::Malformed {
+                line: index + 1,
+                reason: error.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Check the messages `role` exchanged in `capture` against its local type
+///
+/// Returns how many messages of the capture were checked.
+///
+/// # Errors
+///
+/// The first [`CaptureDivergence`].
+pub fn check_capture(
+    role: &Role,
+    local_type: &LocalType,
+    capture: &[CapturedMessage],
+) -> Result<usize, CaptureDivergence> {
+    let name = role.name.to_string();
+    let mut monitor = ConformanceMonitor::new(monitor_spec(role, local_type));
+    let mut checked = 0;
+    for (index, message) in capture.iter().enumerate() {
+        let label = message.label.as_str();
+        let event = match (message.from == name, message.to == name, message.choice) {
+            (true, _, false) => ObservedEvent::sent(&message.to, label),
+            (true, _, true) => ObservedEvent::selected(&message.to, label),
+            (false, true, false) => ObservedEvent::received(&message.from, label),
+            (false, true, true) => ObservedEvent::branched(&message.from, label),
+            (false, false, _) => continue,
+        };
+        monitor
+            .observe(event)
+            .map_err(|violation| CaptureDivergence {
+                index: Some(index),
+                violation,
+            })?;
+        checked += 1;
+    }
+    monitor.finish().map_err(|violation| CaptureDivergence {
+        index: None,
+        violation,
+    })?;
+    Ok(checked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::local_type_parser::parse_local_type;
+    use quote::format_ident;
+
+    #[test]
+    fn test_check_capture_against_local_type() {
+        let seller = parse_local_type(
+            "\
+send Offer(u64) to Buyer
+branch from Buyer {
+    accept: {
+        receive Pay(u64) from Buyer
+    }
+    reject: end
+}
+",
+        )
+        .unwrap();
+        let role = Role::new(format_ident!("Seller"));
+        let capture = read_capture(
+            r#"
+{"from": "Seller", "to": "Buyer", "label": "Offer"}
+{"from": "Buyer", "to": "Bank", "label": "Withdraw"}
+{"from": "Buyer", "to": "Seller", "label": "accept", "choice": true}
+{"from": "Buyer", "to": "Seller", "label": "Pay"}
+"#
+            .as_bytes(),
+        )
+        .unwrap();
+        assert_eq!(check_capture(&role, &seller, &capture), Ok(3));
+
+        let error = check_capture(&role, &seller, &capture[..3]).unwrap_err();
+        assert_eq!(error.index, None);
+        assert!(matches!(
+            error.violation,
+            MonitorViolation::Incomplete { .. }
+        ));
+
+        let capture = read_capture(
+            r#"[
+                {"from": "Seller", "to": "Buyer", "label": "Offer"},
+                {"from": "Buyer", "to": "Seller", "label": "Pay"}
+            ]"#
+            .as_bytes(),
+        )
+        .unwrap();
+        let error = check_capture(&role, &seller, &capture).unwrap_err();
+        assert_eq!(error.index, Some(1));
+        assert!(error.to_string().starts_with(
+            "message 1 of the capture diverges: Seller: unexpected receive Pay (Buyer)"
+        ));
+    }
+}
@@ -0,0 +1,276 @@
+// Conditional statements
+//
+// `#[cfg(...)]` in front of a statement or a choice branch keeps it only in
+// builds whose configuration satisfies the predicate, written as in Rust:
+//
+//     #[cfg(feature = "premium")]
+//     Server -> Client: Priority;
+//
+// The parser resolves predicates against a `CfgSet`, the configuration
+// options that are set, and drops what is disabled before the protocol is
+// built, so validation, projection and code generation only see the active
+// variant. Without a `CfgSet` no option is set.
+//
+// A proc macro cannot read the features of the crate invoking it, so
+// `choreography!` lets the compiler decide: for a source with conditions it
+// expands to one copy of the invocation per combination of the options they
+// name, each behind the `#[cfg]` of its combination and marked with
+// `#[cfg_resolved(...)]`, the options set in it. Only the copy matching the
+// build survives, and it is expanded with those options.
+
+use proc_macro2::TokenStream;
+use quote::{quote, ToTokens};
+use std::collections::BTreeSet;
+use std::fmt;
+use syn::punctuated::Punctuated;
+use syn::{Meta, Token};
+
+/// Name of the macro attribute carrying the options of a resolved variant
+pub const CFG_RESOLVED: &str = "cfg_resolved";
+
+/// Expanding a source naming more options than this is refused, as every
+/// combination of them is a copy of the invocation
+pub const MAX_CFG_OPTIONS: usize = 8;
+
+/// A configuration option: `unix` or `feature = "premium"`
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CfgOption {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+impl CfgOption {
+    /// The option `feature = "name"`
+    pub fn feature(name: impl Into<String>) -> Self {
+        Self {
+            name: "feature".to_string(),
+            value: Some(name.into()),
+        }
+    }
+
+    fn from_meta(meta: &Meta) -> Result<Self, String> {
+        let name = |path: &syn::Path| {
+            path.get_ident()
+                .map(ToString::to_string)
+                .ok_or_else(|| "cfg options are single identifiers".to_string())
+        };
+        match meta {
+            Meta::Path(path) => Ok(Self {
+                name: name(path)?,
+                value: None,
+            }),
+            Meta::NameValue(option) => match &option.value {
+                syn::Expr::Lit(syn::ExprLit {
+                    lit: syn::Lit::Str(value),
+                    ..
+                }) => Ok(Self {
+                    name: name(&option.path)?,
+                    value: Some(value.value()),
+                }),
+                _ => Err("cfg option values are string literals".to_string()),
+            },
+            Meta::List(list) => Err(format!(
+                "unknown cfg predicate `{}`, expected `all`, `any` or `not`",
+                list.path.to_token_stream()
+            )),
+        }
+    }
+}
+
+impl fmt::Display for CfgOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{} = {value:?}", self.name),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+impl ToTokens for CfgOption {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let name = quote::format_ident!("{}", self.name);
+        tokens.extend(match &self.value {
+            Some(value) => quote! { #name = #value },
+            None => quote! { #name },
+        });
+    }
+}
+
+/// Configuration options set in a build
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CfgSet {
+    options: BTreeSet<CfgOption>,
+}
+
+impl CfgSet {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The set with the features `names` enabled
+    pub fn features<S: Into<String>>(names: impl IntoIterator<Item = S>) -> Self {
+        names.into_iter().map(CfgOption::feature).collect()
+    }
+
+    /// Set `option`
+    #[must_use]
+    pub fn with(mut self, option: CfgOption) -> Self {
+        self.options.insert(option);
+        self
+    }
+
+    #[must_use]
+    pub fn contains(&self, option: &CfgOption) -> bool {
+        self.options.contains(option)
+    }
+
+    /// Parse the arguments of `#[cfg_resolved(...)]`
+    pub(crate) fn from_attribute(attr: &syn::Attribute) -> syn::Result<Self> {
+        let options = attr
+            .meta
+            .require_list()?
+            .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)?;
+        options
+            .iter()
+            .map(|meta| {
+                CfgOption::from_meta(meta).map_err(|reason| syn::Error::new_spanned(meta, reason))
+            })
+            .collect()
+    }
+}
+
+impl FromIterator<CfgOption> for CfgSet {
+    fn from_iter<I: IntoIterator<Item = CfgOption>>(iter: I) -> Self {
+        Self {
+            options: iter.into_iter().collect(),
+        }
+    }
+}
+
+/// Predicate of a `#[cfg(...)]` condition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CfgPredicate {
+    Option(CfgOption),
+    All(Vec<CfgPredicate>),
+    Any(Vec<CfgPredicate>),
+    Not(Box<CfgPredicate>),
+}
+
+impl CfgPredicate {
+    /// Parse the predicate written between the parentheses of `#[cfg(...)]`
+    ///
+    /// # Errors
+    ///
+    /// Why `text` is not a cfg predicate.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let meta = syn::parse_str::<Meta>(text).map_err(|e| e.to_string())?;
+        Self::from_meta(&meta)
+    }
+
+    fn from_meta(meta: &Meta) -> Result<Self, String> {
+        let Meta::List(list) = meta else {
+            return CfgOption::from_meta(meta).map(Self::Option);
+        };
+        let operands = list
+            .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+            .map_err(|e| e.to_string())?
+            .iter()
+            .map(Self::from_meta)
+            .collect::<Result<Vec<_>, _>>()?;
+        match list.path.get_ident().map(ToString::to_string).as_deref() {
+            Some("all") => Ok(Self::All(operands)),
+            Some("any") => Ok(Self::Any(operands)),
+            Some("not") => match <[_; 1]>::try_from(operands) {
+                Ok([operand]) => Ok(Self::Not(Box::new(operand))),
+                Err(_) => Err("`not` takes exactly one predicate".to_string()),
+            },
+            _ => CfgOption::from_meta(meta).map(Self::Option),
+        }
+    }
+
+    /// Whether the predicate holds in a build setting `cfg`
+    #[must_use]
+    pub fn eval(&self, cfg: &CfgSet) -> bool {
+        match self {
+            Self::Option(option) => cfg.contains(option),
+            Self::All(operands) => operands.iter().all(|operand| operand.eval(cfg)),
+            Self::Any(operands) => operands.iter().any(|operand| operand.eval(cfg)),
+            Self::Not(operand) => !operand.eval(cfg),
+        }
+    }
+
+    /// Add the options the predicate names to `options`
+    pub fn collect_options(&self, options: &mut BTreeSet<CfgOption>) {
+        match self {
+            Self::Option(option) => {
+                options.insert(option.clone());
+            }
+            Self::All(operands) | Self::Any(operands) => {
+                for operand in operands {
+                    operand.collect_options(options);
+                }
+            }
+            Self::Not(operand) => operand.collect_options(options),
+        }
+    }
+}
+
+/// One copy of the `choreography!` invocation `input` per combination of
+/// `options`, each compiled only in the builds of its combination
+pub(crate) fn expand_variants(options: &BTreeSet<CfgOption>, input: &TokenStream) -> TokenStream {
+    let options: Vec<&CfgOption> = options.iter().collect();
+    (0..1usize << options.len())
+        .map(|combination| {
+            let is_set = |index: usize| combination >> index & 1 == 1;
+            let conditions = options.iter().enumerate().map(|(index, option)| {
+                if is_set(index) {
+                    quote! { #option }
+                } else {
+                    quote! { not(#option) }
+                }
+            });
+            let set = options
+                .iter()
+                .enumerate()
+                .filter(|(index, _)| is_set(*index))
+                .map(|(_, option)| option);
+            quote! {
+                #[cfg(all(#(#conditions),*))]
+                ::rumpsteak_aura_choreography::choreography! {
+                    #[cfg_resolved(#(#set),*)]
+                    #input
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_predicates_resolve_against_set_options() {
+        let premium =
+            CfgPredicate::parse(r#"all(feature = "premium", not(any(test, unix)))"#).unwrap();
+        assert!(premium.eval(&CfgSet::features(["premium"])));
+        assert!(
+            !premium.eval(&CfgSet::features(["premium"]).with(CfgOption {
+                name: "unix".to_string(),
+                value: None,
+            }))
+        );
+        assert!(!premium.eval(&CfgSet::new()));
+
+        let mut options = BTreeSet::new();
+        premium.collect_options(&mut options);
+        assert_eq!(options.len(), 3);
+        let variants = expand_variants(&options, &quote! { "" }).to_string();
+        assert_eq!(variants.matches("cfg_resolved").count(), 8);
+
+        assert!(CfgPredicate::parse("not(a, b)").is_err());
+        assert!(CfgPredicate::parse("feature = 1").is_err());
+        assert!(CfgPredicate::parse("some(test)").is_err());
+    }
+}
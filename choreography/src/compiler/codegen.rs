@@ -1,10 +1,21 @@
 // Code generation from projected local types to Rumpsteak session types
 
-use crate::ast::{Choreography, LocalType, MessageType, Protocol, Role};
+pub mod typescript;
+
+use crate::ast::local_type::role_text;
+use crate::ast::{
+    Choreography, Condition, LocalType, MessageType, Protocol, RangeExpr, Role, RoleIndex,
+    RoleParam,
+};
+use crate::compiler::compact_codegen::{generate_compact_session, generate_compact_support};
+use crate::compiler::handler_codegen::{snake_case, CodegenOptions};
 use crate::extensions::ProtocolExtension;
-use proc_macro2::{Ident, TokenStream};
+use crate::runtime::http::HttpRoute;
+use crate::runtime::monitor::{ActionKind, MonitorSpec, Transition};
+use crate::runtime::quorum::StragglerPolicy;
+use proc_macro2::{Ident, Literal, TokenStream};
 use quote::{format_ident, quote};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 /// Generate documentation comments from annotations
 fn generate_annotation_docs(annotations: &HashMap<String, String>) -> TokenStream {
@@ -26,6 +37,15 @@ fn generate_annotation_docs(annotations: &HashMap<String, String>) -> TokenStrea
     quote! { #(#doc_lines)* }
 }
 
+/// `#[doc]` attributes of a `///` comment from the DSL, one per line
+pub(crate) fn doc_attributes(doc: Option<&str>) -> TokenStream {
+    let lines = doc
+        .into_iter()
+        .flat_map(str::lines)
+        .map(|line| format!(" {line}"));
+    quote! { #(#[doc = #lines])* }
+}
+
 /// Generate metadata structure for annotations
 fn generate_annotation_metadata(name: &str, annotations: &HashMap<String, String>) -> TokenStream {
     if annotations.is_empty() {
@@ -125,13 +145,393 @@ pub fn generate_session_type(
     role: &Role,
     local_type: &LocalType,
     protocol_name: &str,
+) -> TokenStream {
+    generate_named_session_type(role, local_type, protocol_name, None)
+}
+
+/// Session type of `role`, one alias per state
+///
+/// The first state is named `<Role>_<Protocol>`, and every later send,
+/// receive, selection and branch after its position in the protocol, such as
+/// `Buyer_AwaitQuote`, so type errors name the step they are about. With
+/// `choreography`, each state is documented with the statement and line it
+/// comes from. Session types addressing instances of a role family stay
+/// nested, as the aliases cannot pass const parameters on.
+fn generate_named_session_type(
+    role: &Role,
+    local_type: &LocalType,
+    protocol_name: &str,
+    choreography: Option<&Choreography>,
 ) -> TokenStream {
     let type_name = format_ident!("{}_{}", role.name, protocol_name);
-    let inner_type = generate_type_expr(local_type);
 
-    quote! {
-        #[session]
-        type #type_name = #inner_type;
+    let mut indices = BTreeSet::new();
+    collect_symbolic_indices(local_type, &mut indices);
+    if !indices.is_empty() {
+        let inner_type = generate_type_expr(local_type);
+        let params = indices.iter().map(|index| index_param(index));
+        return quote! {
+            #[session]
+            type #type_name <#(const #params: usize),*> = #inner_type;
+        };
+    }
+
+    let mut states = NamedStates {
+        role,
+        sites: choreography.map(|c| StatementSites::new(&c.protocol)),
+        uses: HashMap::new(),
+        items: Vec::new(),
+    };
+    let entry = states.state(local_type, Some(type_name.clone()));
+    if !begins_with_step(local_type) {
+        states.items.insert(
+            0,
+            quote! {
+                #[session]
+                type #type_name = #entry;
+            },
+        );
+    }
+    let items = states.items;
+    quote! { #(#items)* }
+}
+
+/// Whether `local_type` begins with a send, receive, selection or branch,
+/// whose state then takes the name of the session type
+fn begins_with_step(local_type: &LocalType) -> bool {
+    match local_type {
+        LocalType::Send { .. }
+        | LocalType::Receive { .. }
+        | LocalType::Select { .. }
+        | LocalType::Branch { .. } => true,
+        LocalType::Rec { body, .. } | LocalType::Timeout { body, .. } => begins_with_step(body),
+        _ => false,
+    }
+}
+
+/// Lines of the statements the steps of a local type come from
+///
+/// Steps are matched with statements in protocol order: the n-th send of
+/// `Msg` from `A` to `B` in a local type comes from the n-th
+/// `A -> B: Msg`, and the n-th selection or branch on a choice of `A` from
+/// the n-th `choice A`.
+struct StatementSites {
+    lines: HashMap<String, Vec<usize>>,
+    seen: HashMap<String, usize>,
+}
+
+impl StatementSites {
+    fn new(protocol: &Protocol) -> Self {
+        let mut sites = Self {
+            lines: HashMap::new(),
+            seen: HashMap::new(),
+        };
+        sites.collect(protocol);
+        sites
+    }
+
+    fn collect(&mut self, protocol: &Protocol) {
+        let line = protocol.span().line;
+        match protocol {
+            Protocol::Send {
+                from,
+                to,
+                message,
+                continuation,
+                ..
+            } => {
+                self.add(send_statement(from, to, message), line);
+                self.collect(continuation);
+            }
+            Protocol::Broadcast {
+                from,
+                to_all,
+                message,
+                continuation,
+                ..
+            } => {
+                for to in to_all {
+                    self.add(send_statement(from, to, message), line);
+                }
+                self.collect(continuation);
+            }
+            Protocol::Choice { role, branches, .. } => {
+                self.add(choice_statement(role), line);
+                for branch in branches {
+                    self.collect(&branch.protocol);
+                }
+            }
+            Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => self.collect(body),
+            Protocol::Parallel { protocols, .. } => {
+                for protocol in protocols {
+                    self.collect(protocol);
+                }
+            }
+            Protocol::Extension { continuation, .. } => self.collect(continuation),
+            Protocol::Var(_) | Protocol::End => {}
+        }
+    }
+
+    fn add(&mut self, statement: String, line: usize) {
+        self.lines.entry(statement).or_default().push(line);
+    }
+
+    /// Line of the next step from `statement`, if the source is known
+    fn next(&mut self, statement: &str) -> Option<usize> {
+        let seen = self.seen.entry(statement.to_string()).or_default();
+        let line = self.lines.get(statement)?.get(*seen).copied();
+        *seen += 1;
+        line.filter(|line| *line > 0)
+    }
+}
+
+fn send_statement(from: &Role, to: &Role, message: &MessageType) -> String {
+    format!("{} -> {}: {}", role_text(from), role_text(to), message.name)
+}
+
+fn choice_statement(role: &Role) -> String {
+    format!("choice {}", role_text(role))
+}
+
+/// Builds the state aliases of one role's session type
+struct NamedStates<'a> {
+    role: &'a Role,
+    sites: Option<StatementSites>,
+    /// Times each state name was given, to number repeated positions
+    uses: HashMap<String, usize>,
+    items: Vec<TokenStream>,
+}
+
+impl NamedStates<'_> {
+    /// Type of `local_type`, naming its first state `name` or after its
+    /// position
+    fn state(&mut self, local_type: &LocalType, name: Option<Ident>) -> TokenStream {
+        match local_type {
+            LocalType::Send {
+                to,
+                message,
+                continuation,
+            } => {
+                let statement = send_statement(self.role, to, message);
+                let (name, doc, slot) =
+                    self.open(name, &format!("Send{}", message.name), &statement);
+                let to = role_type(to);
+                let message = &message.name;
+                let continuation = self.state(continuation, None);
+                self.items[slot] = quote! {
+                    #doc
+                    #[session]
+                    type #name = Send<#to, #message, #continuation>;
+                };
+                quote! { #name }
+            }
+            LocalType::Receive {
+                from,
+                message,
+                continuation,
+            } => {
+                let statement = send_statement(from, self.role, message);
+                let (name, doc, slot) =
+                    self.open(name, &format!("Await{}", message.name), &statement);
+                let from = role_type(from);
+                let message = &message.name;
+                let continuation = self.state(continuation, None);
+                self.items[slot] = quote! {
+                    #doc
+                    #[session]
+                    type #name = Receive<#from, #message, #continuation>;
+                };
+                quote! { #name }
+            }
+            LocalType::Select { to, branches } => {
+                let statement = choice_statement(self.role);
+                let position = format!("Choose{}", branch_names(branches));
+                let (name, doc, slot) = self.open(name, &position, &statement);
+                let choices = self.choices(&name, branches);
+                let to = role_type(to);
+                self.items[slot] = quote! {
+                    #doc
+                    #[session]
+                    type #name = Select<#to, #choices>;
+                };
+                quote! { #name }
+            }
+            LocalType::Branch { from, branches } => {
+                let statement = choice_statement(from);
+                let position = format!("Offer{}", branch_names(branches));
+                let (name, doc, slot) = self.open(name, &position, &statement);
+                let choices = self.choices(&name, branches);
+                let from = role_type(from);
+                self.items[slot] = quote! {
+                    #doc
+                    #[session]
+                    type #name = Branch<#from, #choices>;
+                };
+                quote! { #name }
+            }
+            LocalType::LocalChoice { branches } => {
+                let position = format!("Decide{}", branch_names(branches));
+                let name = self.fresh(&position);
+                let choices = self.choices(&name, branches);
+                quote! { LocalChoice<#choices> }
+            }
+            LocalType::Loop { body, .. } => {
+                let body = self.state(body, None);
+                quote! { Loop<#body> }
+            }
+            LocalType::Rec { body, .. } | LocalType::Timeout { body, .. } => self.state(body, name),
+            LocalType::Var(label) => quote! { #label },
+            LocalType::End => quote! { End },
+        }
+    }
+
+    /// Name, doc comment and item slot of a state, reserved before its
+    /// continuation so the aliases follow the protocol
+    fn open(
+        &mut self,
+        name: Option<Ident>,
+        position: &str,
+        statement: &str,
+    ) -> (Ident, TokenStream, usize) {
+        let name = name.unwrap_or_else(|| self.fresh(position));
+        let line = self.sites.as_mut().and_then(|sites| sites.next(statement));
+        let doc = match line {
+            Some(line) => format!(" `{statement}`, line {line}"),
+            None => format!(" `{statement}`"),
+        };
+        self.items.push(TokenStream::new());
+        (name, quote! { #[doc = #doc] }, self.items.len() - 1)
+    }
+
+    /// `<Role>_<position>`, numbered from the second use on
+    fn fresh(&mut self, position: &str) -> Ident {
+        let uses = self.uses.entry(position.to_string()).or_default();
+        *uses += 1;
+        match *uses {
+            1 => format_ident!("{}_{}", self.role.name, position),
+            n => format_ident!("{}_{}{}", self.role.name, position, n),
+        }
+    }
+
+    /// Enum of the branches of the choice state `name`
+    fn choices(&mut self, name: &Ident, branches: &[(Ident, LocalType)]) -> Ident {
+        let enum_name = format_ident!("{}Branches", name);
+        let slot = self.items.len();
+        self.items.push(TokenStream::new());
+        let variants: Vec<TokenStream> = branches
+            .iter()
+            .map(|(label, local_type)| {
+                let continuation = self.state(local_type, None);
+                quote! { #label(#label, #continuation) }
+            })
+            .collect();
+        self.items[slot] = quote! {
+            #[session]
+            enum #enum_name {
+                #(#variants),*
+            }
+        };
+        enum_name
+    }
+}
+
+/// Branch labels in upper camel case, joined
+fn branch_names(branches: &[(Ident, LocalType)]) -> String {
+    branches
+        .iter()
+        .map(|(label, _)| {
+            label
+                .to_string()
+                .split('_')
+                .map(|word| {
+                    let mut chars = word.chars();
+                    chars.next().map_or_else(String::new, |first| {
+                        first.to_uppercase().chain(chars).collect()
+                    })
+                })
+                .collect::<String>()
+        })
+        .collect()
+}
+
+/// Type of a role in a session type
+///
+/// An instance of a role family `Worker[N]` is the generic role struct
+/// `Worker<const I: usize>`: `Worker[i]` is `Worker<I>`, with `I` a const
+/// parameter of the session type, and `Worker[2]` is `Worker<2>`.
+fn role_type(role: &Role) -> TokenStream {
+    let name = &role.name;
+    match &role.index {
+        None => quote! { #name },
+        Some(RoleIndex::Symbolic(index)) => {
+            let param = index_param(index);
+            quote! { #name<#param> }
+        }
+        Some(RoleIndex::Concrete(index)) => {
+            let index = Literal::usize_unsuffixed(*index as usize);
+            quote! { #name<#index> }
+        }
+        Some(RoleIndex::Wildcard | RoleIndex::Range(_)) => {
+            let message = format!(
+                "session types address one instance of {name} at a time; \
+                 use a symbolic index such as `{name}[i]`"
+            );
+            quote! { compile_error!(#message) }
+        }
+        Some(RoleIndex::Quorum(quorum)) => {
+            let message = format!(
+                "a receive from {name}[{quorum}] has no session type; \
+                 gather it with `RoleAddressBook::gather_quorum`"
+            );
+            quote! { compile_error!(#message) }
+        }
+    }
+}
+
+/// Const parameter standing for the symbolic index `index`
+fn index_param(index: &str) -> Ident {
+    format_ident!("{}", index.to_uppercase())
+}
+
+/// Symbolic indices of the roles `local_type` communicates with
+fn collect_symbolic_indices(local_type: &LocalType, indices: &mut BTreeSet<String>) {
+    let mut peer = |role: &Role| {
+        if let Some(RoleIndex::Symbolic(index)) = &role.index {
+            indices.insert(index.clone());
+        }
+    };
+    match local_type {
+        LocalType::Send {
+            to, continuation, ..
+        } => {
+            peer(to);
+            collect_symbolic_indices(continuation, indices);
+        }
+        LocalType::Receive {
+            from, continuation, ..
+        } => {
+            peer(from);
+            collect_symbolic_indices(continuation, indices);
+        }
+        LocalType::Select { to: role, branches }
+        | LocalType::Branch {
+            from: role,
+            branches,
+        } => {
+            peer(role);
+            for (_, branch) in branches {
+                collect_symbolic_indices(branch, indices);
+            }
+        }
+        LocalType::LocalChoice { branches } => {
+            for (_, branch) in branches {
+                collect_symbolic_indices(branch, indices);
+            }
+        }
+        LocalType::Loop { body, .. }
+        | LocalType::Rec { body, .. }
+        | LocalType::Timeout { body, .. } => collect_symbolic_indices(body, indices),
+        LocalType::Var(_) | LocalType::End => {}
     }
 }
 
@@ -143,7 +543,7 @@ fn generate_type_expr(local_type: &LocalType) -> TokenStream {
             message,
             continuation,
         } => {
-            let to_name = &to.name;
+            let to_name = role_type(to);
             let msg_name = &message.name;
             let cont = generate_type_expr(continuation);
 
@@ -157,7 +557,7 @@ fn generate_type_expr(local_type: &LocalType) -> TokenStream {
             message,
             continuation,
         } => {
-            let from_name = &from.name;
+            let from_name = role_type(from);
             let msg_name = &message.name;
             let cont = generate_type_expr(continuation);
 
@@ -167,7 +567,7 @@ fn generate_type_expr(local_type: &LocalType) -> TokenStream {
         }
 
         LocalType::Select { to, branches } => {
-            let to_name = &to.name;
+            let to_name = role_type(to);
             let choice_type = generate_choice_enum(branches, true);
 
             quote! {
@@ -176,7 +576,7 @@ fn generate_type_expr(local_type: &LocalType) -> TokenStream {
         }
 
         LocalType::Branch { from, branches } => {
-            let from_name = &from.name;
+            let from_name = role_type(from);
             let choice_type = generate_choice_enum(branches, false);
 
             quote! {
@@ -298,7 +698,7 @@ pub fn generate_choreography_code(
     roles: &[Role],
     local_types: &[(Role, LocalType)],
 ) -> TokenStream {
-    let role_struct_defs = generate_role_structs(roles);
+    let role_struct_defs = generate_role_structs(roles, None);
     let session_type_defs = local_types
         .iter()
         .map(|(role, local_type)| generate_session_type(role, local_type, name));
@@ -309,6 +709,89 @@ pub fn generate_choreography_code(
     }
 }
 
+/// Generate complete Rumpsteak code for `choreography`, with compact state
+/// machines instead of session types under `@codegen(compact)`
+fn generate_code_for(
+    choreography: &Choreography,
+    local_types: &[(Role, LocalType)],
+) -> TokenStream {
+    let sessions = local_types
+        .iter()
+        .map(|(role, local_type)| generate_role_session(choreography, role, local_type));
+    generate_code_from_sessions(choreography, quote! { #(#sessions)* })
+}
+
+/// Role structs and shared items of `choreography` around the per-role
+/// `sessions` of [`generate_role_session`]
+pub(crate) fn generate_code_from_sessions(
+    choreography: &Choreography,
+    sessions: TokenStream,
+) -> TokenStream {
+    let role_struct_defs = generate_role_structs(&choreography.roles, Some(choreography));
+    let support = if is_compact(choreography) {
+        generate_compact_support(&choreography.name.to_string())
+    } else {
+        quote! {}
+    };
+    let http_routes = generate_http_routes(choreography);
+
+    quote! {
+        #role_struct_defs
+        #support
+        #http_routes
+        #sessions
+    }
+}
+
+/// Whether `choreography` asks for conformance monitors with
+/// `@codegen(monitor)`
+pub(crate) fn is_monitored(choreography: &Choreography) -> bool {
+    CodegenOptions::from_choreography(choreography).is_ok_and(|o| o.monitor)
+}
+
+/// Role families are always generated as session types
+fn is_compact(choreography: &Choreography) -> bool {
+    CodegenOptions::from_choreography(choreography).is_ok_and(|o| o.compact)
+        && !choreography.roles.iter().any(Role::is_family)
+}
+
+/// Session type of `role`, or its compact state machine under
+/// `@codegen(compact)`
+#[must_use]
+pub fn generate_role_session(
+    choreography: &Choreography,
+    role: &Role,
+    local_type: &LocalType,
+) -> TokenStream {
+    let name = choreography.name.to_string();
+    if is_compact(choreography) {
+        generate_compact_session(role, local_type, &name)
+    } else {
+        generate_named_session_type(role, local_type, &name, Some(choreography))
+    }
+}
+
+/// Session types of every role, or their compact state machines under
+/// `@codegen(compact)`
+fn generate_session_types(
+    choreography: &Choreography,
+    name: &str,
+    local_types: &[(Role, LocalType)],
+) -> TokenStream {
+    let support = if is_compact(choreography) {
+        generate_compact_support(name)
+    } else {
+        quote! {}
+    };
+    let sessions = local_types
+        .iter()
+        .map(|(role, local_type)| generate_role_session(choreography, role, local_type));
+    quote! {
+        #support
+        #(#sessions)*
+    }
+}
+
 /// Generate choreography code with extension support
 pub fn generate_choreography_code_with_extensions(
     choreography: &Choreography,
@@ -316,11 +799,7 @@ pub fn generate_choreography_code_with_extensions(
     extensions: &[Box<dyn ProtocolExtension>],
 ) -> TokenStream {
     // Generate base choreography code
-    let base_code = generate_choreography_code(
-        &choreography.name.to_string(),
-        &choreography.roles,
-        local_types,
-    );
+    let base_code = generate_code_for(choreography, local_types);
 
     // Generate extension-specific code
     let extension_code = generate_extension_code(extensions, choreography);
@@ -332,6 +811,22 @@ pub fn generate_choreography_code_with_extensions(
     }
 }
 
+/// Generate choreography code with extension support from the
+/// [`generate_role_session`] output of every role
+pub(crate) fn generate_choreography_code_from_sessions(
+    choreography: &Choreography,
+    sessions: TokenStream,
+    extensions: &[Box<dyn ProtocolExtension>],
+) -> TokenStream {
+    let base_code = generate_code_from_sessions(choreography, sessions);
+    let extension_code = generate_extension_code(extensions, choreography);
+
+    quote! {
+        #base_code
+        #extension_code
+    }
+}
+
 /// Generate code for protocol extensions
 fn generate_extension_code(
     extensions: &[Box<dyn ProtocolExtension>],
@@ -369,45 +864,72 @@ fn generate_extension_code(
     }
 }
 
-/// Generate role struct definitions
-fn generate_role_structs(roles: &[Role]) -> TokenStream {
-    let _n = roles.len();
+/// Generate role struct definitions, documented from `choreography` if given
+fn generate_role_structs(roles: &[Role], choreography: Option<&Choreography>) -> TokenStream {
     let role_names: Vec<&Ident> = roles.iter().map(|r| &r.name).collect();
 
-    // Generate Roles tuple struct
-    let roles_struct = quote! {
-        #[derive(Roles)]
-        struct Roles(#(#role_names),*);
+    // Role families have no single struct to put in the Roles tuple, so
+    // their endpoints are built from channel pairs by hand
+    let roles_struct = if roles.iter().any(Role::is_family) {
+        quote! {}
+    } else {
+        quote! {
+            #[derive(Roles)]
+            struct Roles(#(#role_names),*);
+        }
     };
 
+    // Named in the panic of a session abandoned in a debug build
+    let protocol = choreography.map(|c| {
+        let name = &c.name;
+        quote! { #[protocol(#name)] }
+    });
+
     // Generate individual role structs with routes
     let role_structs = roles.iter().enumerate().map(|(i, role)| {
         let role_name = &role.name;
+        let doc = doc_attributes(choreography.and_then(|c| c.role_doc(role_name)));
+        let generics = if role.is_family() {
+            quote! { <const I: usize> }
+        } else {
+            quote! {}
+        };
         let other_roles: Vec<_> = roles
             .iter()
             .enumerate()
             .filter(|(j, _)| i != *j)
-            .map(|(_, r)| &r.name)
+            .map(|(_, r)| r)
             .collect();
 
         if other_roles.is_empty() {
             // Single role (unusual but possible)
             quote! {
+                #doc
                 #[derive(Role)]
                 #[message(Label)]
-                struct #role_name;
+                #protocol
+                struct #role_name #generics;
             }
         } else {
             let routes = other_roles.iter().map(|other| {
-                quote! {
-                    #[route(#other)] Channel
+                let other_name = &other.name;
+                if other.is_family() {
+                    quote! {
+                        #[routes(#other_name)] Vec<Channel>
+                    }
+                } else {
+                    quote! {
+                        #[route(#other_name)] Channel
+                    }
                 }
             });
 
             quote! {
+                #doc
                 #[derive(Role)]
                 #[message(Label)]
-                struct #role_name(#(#routes),*);
+                #protocol
+                struct #role_name #generics (#(#routes),*);
             }
         }
     });
@@ -593,10 +1115,8 @@ pub fn generate_choreography_code_with_annotations(
     local_types: &[(Role, LocalType)],
     choreo: &Choreography,
 ) -> TokenStream {
-    let role_struct_defs = generate_role_structs(roles);
-    let session_type_defs = local_types
-        .iter()
-        .map(|(role, local_type)| generate_session_type(role, local_type, name));
+    let role_struct_defs = generate_role_structs(roles, Some(choreo));
+    let session_type_defs = generate_session_types(choreo, name, local_types);
 
     // Generate runtime annotation accessors for the protocol
     let protocol_annotation_access = generate_runtime_annotation_access(name, &choreo.protocol);
@@ -619,7 +1139,7 @@ pub fn generate_choreography_code_with_annotations(
 
     quote! {
         #role_struct_defs
-        #(#session_type_defs)*
+        #session_type_defs
         #protocol_annotation_access
         #(#role_metadata)*
 
@@ -646,7 +1166,10 @@ pub fn generate_dynamic_role_support(choreography: &Choreography) -> TokenStream
         .filter(|role| role.is_dynamic() || role.is_symbolic())
         .collect();
 
-    if dynamic_roles.is_empty() {
+    let mut quorum_sends = Vec::new();
+    collect_quorum_sends(&choreography.protocol, &mut quorum_sends);
+
+    if dynamic_roles.is_empty() && quorum_sends.is_empty() {
         return quote! {};
     }
 
@@ -663,6 +1186,17 @@ pub fn generate_dynamic_role_support(choreography: &Choreography) -> TokenStream
         let role_name = &role.name;
         let validation_fn_name =
             format_ident!("validate_{}_count", role_name.to_string().to_lowercase());
+        let multiplicity = match &role.param {
+            Some(RoleParam::Bounded { min, max }) => quote! {
+                if !(#min..=#max).contains(&count) {
+                    return Err(format!(
+                        "Role {} takes {} to {} instances, got {}",
+                        stringify!(#role_name), #min, #max, count
+                    ));
+                }
+            },
+            _ => quote! {},
+        };
 
         quote! {
             /// Validate role count for runtime bounds checking
@@ -677,6 +1211,8 @@ pub fn generate_dynamic_role_support(choreography: &Choreography) -> TokenStream
                     return Err("Role count cannot be zero".to_string());
                 }
 
+                #multiplicity
+
                 Ok(())
             }
         }
@@ -687,11 +1223,14 @@ pub fn generate_dynamic_role_support(choreography: &Choreography) -> TokenStream
         let role_name = &role.name;
         let map_fn_name = format_ident!("map_{}_instances", role_name.to_string().to_lowercase());
         let get_fn_name = format_ident!("get_{}_device", role_name.to_string().to_lowercase());
+        let validation_fn_name =
+            format_ident!("validate_{}_count", role_name.to_string().to_lowercase());
 
         quote! {
             /// Map role instances to device IDs
             pub fn #map_fn_name(&mut self, instances: Vec<DeviceId>) -> Result<(), String> {
                 let role_name = stringify!(#role_name);
+                Self::#validation_fn_name(instances.len() as u32)?;
 
                 // Validate instance count
                 if let Some(expected_count) = self.role_counts.get(role_name) {
@@ -703,38 +1242,81 @@ pub fn generate_dynamic_role_support(choreography: &Choreography) -> TokenStream
                     }
                 }
 
-                // Store mappings with bounds checking
-                for (index, device_id) in instances.into_iter().enumerate() {
-                    if index > crate::ast::role::MAX_ROLE_INDEX as usize {
-                        return Err(format!("Role index {} exceeds maximum", index));
-                    }
-
-                    let key = format!("{}[{}]", role_name, index);
-                    self.role_mappings.insert(key, device_id);
-                }
-
-                Ok(())
+                self.addresses
+                    .insert_all(role_name, instances)
+                    .map_err(|e| e.to_string())
             }
 
             /// Get device ID for a specific role instance
             pub fn #get_fn_name(&self, index: u32) -> Option<&DeviceId> {
-                if index > crate::ast::role::MAX_ROLE_INDEX {
-                    return None;
-                }
-
-                let key = format!("{}[{}]", stringify!(#role_name), index);
-                self.role_mappings.get(&key)
+                self.addresses.get(stringify!(#role_name), index)
             }
         }
     });
 
+    // Generate the bounds of each threshold receive
+    let mut quorum_names = BTreeSet::new();
+    let quorum_functions = quorum_sends.iter().filter_map(|protocol| {
+        let Protocol::Send { from, message, .. } = protocol else {
+            return None;
+        };
+        let Some(RoleIndex::Quorum(quorum)) = &from.index else {
+            return None;
+        };
+        let quorum_fn_name = format_ident!(
+            "{}_{}_quorum",
+            snake_case(&from.name.to_string()),
+            snake_case(&message.name.to_string())
+        );
+        if !quorum_names.insert(quorum_fn_name.to_string()) {
+            return None;
+        }
+        let role_name = from.name.to_string();
+        let doc = format!(
+            " Bounds of the receive of {} from {}[{quorum}]",
+            message.name, from.name
+        );
+        let bound = |expr: &RangeExpr| match expr {
+            RangeExpr::Concrete(value) => quote! { #value },
+            RangeExpr::Symbolic(name) => quote! {
+                self.get_index_binding(#name)
+                    .or_else(|| self.get_role_count(#name))
+                    .ok_or_else(|| format!("Quorum bound {} is not bound", #name))?
+            },
+        };
+        let threshold = bound(&quorum.threshold);
+        let of = bound(&quorum.of);
+        let stragglers = match protocol
+            .get_annotation("stragglers")
+            .and_then(|policy| policy.parse().ok())
+            .unwrap_or_default()
+        {
+            StragglerPolicy::Ignore => quote! { Ignore },
+            StragglerPolicy::CollectLate => quote! { CollectLate },
+            StragglerPolicy::Abort => quote! { Abort },
+        };
+
+        Some(quote! {
+            #[doc = #doc]
+            pub fn #quorum_fn_name(&self) -> Result<::rumpsteak_aura_choreography::runtime::quorum::QuorumSpec, String> {
+                Ok(::rumpsteak_aura_choreography::runtime::quorum::QuorumSpec {
+                    role: #role_name.to_string(),
+                    threshold: #threshold,
+                    of: #of,
+                    stragglers: ::rumpsteak_aura_choreography::runtime::quorum::StragglerPolicy::#stragglers,
+                })
+            }
+        })
+    });
+    let quorum_functions: Vec<_> = quorum_functions.collect();
+
     quote! {
         /// Dynamic protocol runtime for managing role bindings and device mappings
         pub struct #runtime_struct_name {
             /// Role count bindings (role_name -> count)
             role_counts: std::collections::HashMap<String, u32>,
-            /// Role to device mappings (role[index] -> device_id)
-            role_mappings: std::collections::HashMap<String, DeviceId>,
+            /// Device of each role instance
+            addresses: ::rumpsteak_aura_choreography::runtime::address_book::RoleAddressBook<DeviceId>,
             /// Index bindings for symbolic variables (var_name -> value)
             index_bindings: std::collections::HashMap<String, u32>,
         }
@@ -744,7 +1326,7 @@ pub fn generate_dynamic_role_support(choreography: &Choreography) -> TokenStream
             pub fn new() -> Self {
                 Self {
                     role_counts: std::collections::HashMap::new(),
-                    role_mappings: std::collections::HashMap::new(),
+                    addresses: ::rumpsteak_aura_choreography::runtime::address_book::RoleAddressBook::new(),
                     index_bindings: std::collections::HashMap::new(),
                 }
             }
@@ -784,37 +1366,26 @@ pub fn generate_dynamic_role_support(choreography: &Choreography) -> TokenStream
                 self.index_bindings.get(var_name).copied()
             }
 
-            /// Resolve a role expression to concrete device IDs
+            /// Resolve a role expression such as `Worker[*]`, `Worker[0..n]` or
+            /// `Worker[i]` to concrete device IDs, using the bound indices
             pub fn resolve_role_targets(&self, role_expr: &str) -> Result<Vec<DeviceId>, String> {
-                // Parse role expression like "Worker[*]", "Worker[0..3]", "Worker[i]"
-                if let Some(wildcard_pos) = role_expr.find("[*]") {
-                    let role_name = &role_expr[..wildcard_pos];
-
-                    // Get all instances of this role
-                    if let Some(count) = self.role_counts.get(role_name) {
-                        let mut targets = Vec::new();
-                        for i in 0..*count {
-                            if let Some(device_id) = self.get_device_by_role_and_index(role_name, i) {
-                                targets.push(device_id.clone());
-                            }
-                        }
-                        return Ok(targets);
-                    }
-                }
-
-                // Handle range expressions, concrete indices, symbolic variables, etc.
-                // This is a simplified implementation - a full parser would be more robust
-                Err(format!("Unsupported role expression: {}", role_expr))
+                let selector = ::rumpsteak_aura_choreography::runtime::address_book::RoleSelector::parse_with(
+                    role_expr,
+                    |name| self.get_index_binding(name).or_else(|| self.get_role_count(name)),
+                )
+                .map_err(|e| e.to_string())?;
+                let targets = self.addresses.resolve(&selector).map_err(|e| e.to_string())?;
+                Ok(targets.into_iter().map(|(_, device)| device.clone()).collect())
             }
 
-            /// Get device ID by role name and index
-            fn get_device_by_role_and_index(&self, role_name: &str, index: u32) -> Option<&DeviceId> {
-                let key = format!("{}[{}]", role_name, index);
-                self.role_mappings.get(&key)
+            /// Devices of all role instances, for addressing them over a handler
+            pub fn addresses(&self) -> &::rumpsteak_aura_choreography::runtime::address_book::RoleAddressBook<DeviceId> {
+                &self.addresses
             }
 
             #(#validation_functions)*
             #(#mapping_functions)*
+            #(#quorum_functions)*
         }
 
         impl Default for #runtime_struct_name {
@@ -827,13 +1398,102 @@ pub fn generate_dynamic_role_support(choreography: &Choreography) -> TokenStream
     }
 }
 
+/// Sends of `protocol` from a quorum such as `Signer[t of N]`
+fn collect_quorum_sends<'a>(protocol: &'a Protocol, sends: &mut Vec<&'a Protocol>) {
+    collect_sends(protocol, sends, &|from, _| {
+        matches!(from.index, Some(RoleIndex::Quorum(_)))
+    });
+}
+
+/// Sends of `protocol` whose sender and receiver satisfy `filter`
+fn collect_sends<'a>(
+    protocol: &'a Protocol,
+    sends: &mut Vec<&'a Protocol>,
+    filter: &impl Fn(&Role, &Role) -> bool,
+) {
+    match protocol {
+        Protocol::Send {
+            from,
+            to,
+            continuation,
+            ..
+        } => {
+            if filter(from, to) {
+                sends.push(protocol);
+            }
+            collect_sends(continuation, sends, filter);
+        }
+        Protocol::Broadcast { continuation, .. } | Protocol::Extension { continuation, .. } => {
+            collect_sends(continuation, sends, filter);
+        }
+        Protocol::Choice { branches, .. } => {
+            for branch in branches {
+                collect_sends(&branch.protocol, sends, filter);
+            }
+        }
+        Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => {
+            collect_sends(body, sends, filter);
+        }
+        Protocol::Parallel { protocols, .. } => {
+            for protocol in protocols {
+                collect_sends(protocol, sends, filter);
+            }
+        }
+        Protocol::Var(_) | Protocol::End => {}
+    }
+}
+
+/// Route table function of every role declared `external http`, such as
+/// `pub fn api_routes() -> RouteTable` for `Api`
+pub(crate) fn generate_http_routes(choreography: &Choreography) -> TokenStream {
+    let tables = choreography.external_roles().map(|role| {
+        let mut sends = Vec::new();
+        collect_sends(&choreography.protocol, &mut sends, &|from, to| {
+            from.name == role.name || to.name == role.name
+        });
+        let mut routes = BTreeMap::new();
+        for send in sends {
+            let Protocol::Send { message, .. } = send else {
+                continue;
+            };
+            let name = message.name.to_string();
+            let route = send
+                .get_annotation("route")
+                .and_then(|route| route.parse().ok())
+                .unwrap_or_else(|| HttpRoute::for_message(&name));
+            routes.entry(name).or_insert(route);
+        }
+        let entries = routes.iter().map(|(message, route)| {
+            let (method, path) = (&route.method, &route.path);
+            quote! {
+                .with_route(
+                    #message,
+                    ::rumpsteak_aura_choreography::runtime::http::HttpRoute::new(#method, #path),
+                )
+            }
+        });
+        let fn_name = format_ident!("{}_routes", snake_case(&role.name.to_string()));
+        let doc = format!(
+            " Routes of the messages exchanged with the external role {}",
+            role.name
+        );
+        quote! {
+            #[doc = #doc]
+            pub fn #fn_name() -> ::rumpsteak_aura_choreography::runtime::http::RouteTable {
+                ::rumpsteak_aura_choreography::runtime::http::RouteTable::new()
+                    #(#entries)*
+            }
+        }
+    });
+    quote! { #(#tables)* }
+}
+
 /// Generate enhanced choreography code with dynamic role support
 pub fn generate_choreography_code_with_dynamic_roles(
     choreography: &Choreography,
     local_types: &[(Role, LocalType)],
 ) -> TokenStream {
-    let name = choreography.name.to_string();
-    let base_code = generate_choreography_code(&name, &choreography.roles, local_types);
+    let base_code = generate_code_for(choreography, local_types);
     let dynamic_support = generate_dynamic_role_support(choreography);
 
     if dynamic_support.is_empty() {
@@ -851,3 +1511,277 @@ pub fn generate_choreography_code_with_dynamic_roles(
         }
     }
 }
+
+/// Loops with a fixed trip count up to this bound are unrolled in monitors;
+/// longer ones are monitored as unbounded loops.
+const MAX_UNROLLED_ITERATIONS: usize = 64;
+
+/// Automaton under construction for a single role's monitor
+#[derive(Default)]
+struct MonitorBuilder {
+    states: usize,
+    transitions: Vec<(usize, ActionKind, String, String, usize)>,
+    epsilons: Vec<(usize, usize)>,
+}
+
+impl MonitorBuilder {
+    fn state(&mut self) -> usize {
+        self.states += 1;
+        self.states - 1
+    }
+
+    fn step(&mut self, from: usize, kind: ActionKind, peer: &Role, label: &Ident) -> usize {
+        let to = self.state();
+        self.transitions
+            .push((from, kind, peer.name.to_string(), label.to_string(), to));
+        to
+    }
+
+    /// Add `local_type` starting in `state`; termination leads to `end`.
+    fn build(
+        &mut self,
+        local_type: &LocalType,
+        state: usize,
+        end: usize,
+        recs: &mut HashMap<String, usize>,
+    ) {
+        match local_type {
+            LocalType::Send {
+                to,
+                message,
+                continuation,
+            } => {
+                let next = self.step(state, ActionKind::Send, to, &message.name);
+                self.build(continuation, next, end, recs);
+            }
+            LocalType::Receive {
+                from,
+                message,
+                continuation,
+            } => {
+                let next = self.step(state, ActionKind::Receive, from, &message.name);
+                self.build(continuation, next, end, recs);
+            }
+            LocalType::Select { to, branches } => {
+                for (label, branch) in branches {
+                    let next = self.step(state, ActionKind::Select, to, label);
+                    self.build(branch, next, end, recs);
+                }
+            }
+            LocalType::Branch { from, branches } => {
+                for (label, branch) in branches {
+                    let next = self.step(state, ActionKind::Branch, from, label);
+                    self.build(branch, next, end, recs);
+                }
+            }
+            LocalType::LocalChoice { branches } => {
+                for (_, branch) in branches {
+                    self.build(branch, state, end, recs);
+                }
+            }
+            LocalType::Loop {
+                condition: Some(Condition::Count(n)),
+                body,
+            } if *n <= MAX_UNROLLED_ITERATIONS => {
+                let mut current = state;
+                for _ in 1..*n {
+                    let next = self.state();
+                    self.build(body, current, next, recs);
+                    current = next;
+                }
+                if *n == 0 {
+                    self.epsilons.push((state, end));
+                } else {
+                    self.build(body, current, end, recs);
+                }
+            }
+            LocalType::Loop { body, .. } => {
+                // Trip count unknown to the observer: zero or more iterations
+                self.build(body, state, state, recs);
+                self.epsilons.push((state, end));
+            }
+            LocalType::Rec { label, body } => {
+                let shadowed = recs.insert(label.to_string(), state);
+                self.build(body, state, end, recs);
+                match shadowed {
+                    Some(outer) => recs.insert(label.to_string(), outer),
+                    None => recs.remove(&label.to_string()),
+                };
+            }
+            LocalType::Var(label) => {
+                if let Some(target) = recs.get(&label.to_string()) {
+                    self.epsilons.push((state, *target));
+                }
+            }
+            LocalType::Timeout { body, .. } => self.build(body, state, end, recs),
+            LocalType::End => self.epsilons.push((state, end)),
+        }
+    }
+}
+
+/// Generate a runtime conformance monitor for one role's local type
+///
+/// The emitted `<Role>Monitor` wraps a
+/// `rumpsteak_aura_choreography::runtime::monitor::ConformanceMonitor` and
+/// flags any observed action that the projected local type does not allow.
+#[must_use]
+pub fn generate_monitor(role: &Role, local_type: &LocalType) -> TokenStream {
+    let monitor_name = format_ident!("{}Monitor", role.name);
+    let doc = format!(
+        "Runtime conformance monitor for the `{}` local type",
+        role.name
+    );
+    let spec = generate_monitor_spec(role, local_type);
+
+    quote! {
+        #[doc = #doc]
+        #[derive(Debug, Clone)]
+        pub struct #monitor_name(::rumpsteak_aura_choreography::runtime::monitor::ConformanceMonitor);
+
+        impl #monitor_name {
+            pub const SPEC: ::rumpsteak_aura_choreography::runtime::monitor::MonitorSpec = #spec;
+
+            #[must_use]
+            pub fn new() -> Self {
+                static SPEC: ::rumpsteak_aura_choreography::runtime::monitor::MonitorSpec =
+                    #monitor_name::SPEC;
+                Self(::rumpsteak_aura_choreography::runtime::monitor::ConformanceMonitor::new(&SPEC))
+            }
+        }
+
+        impl Default for #monitor_name {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        impl ::std::ops::Deref for #monitor_name {
+            type Target = ::rumpsteak_aura_choreography::runtime::monitor::ConformanceMonitor;
+
+            fn deref(&self) -> &Self::Target {
+                &self.0
+            }
+        }
+
+        impl ::std::ops::DerefMut for #monitor_name {
+            fn deref_mut(&mut self) -> &mut Self::Target {
+                &mut self.0
+            }
+        }
+    }
+}
+
+/// Generate the `MonitorSpec` expression of one role's local type
+pub(crate) fn generate_monitor_spec(role: &Role, local_type: &LocalType) -> TokenStream {
+    let mut builder = MonitorBuilder::default();
+    let initial = builder.state();
+    let accepting = builder.state();
+    builder.build(local_type, initial, accepting, &mut HashMap::new());
+
+    let role_name = role.name.to_string();
+    let transitions = builder
+        .transitions
+        .iter()
+        .map(|(from, kind, peer, label, to)| {
+            let kind = match kind {
+                ActionKind::Send => quote! { Send },
+                ActionKind::Receive => quote! { Receive },
+                ActionKind::Select => quote! { Select },
+                ActionKind::Branch => quote! { Branch },
+            };
+            quote! {
+                ::rumpsteak_aura_choreography::runtime::monitor::Transition {
+                    from: #from,
+                    kind: ::rumpsteak_aura_choreography::runtime::monitor::ActionKind::#kind,
+                    peer: #peer,
+                    label: #label,
+                    to: #to,
+                }
+            }
+        });
+    let epsilons = builder
+        .epsilons
+        .iter()
+        .map(|(from, to)| quote! { (#from, #to) });
+
+    quote! {
+        ::rumpsteak_aura_choreography::runtime::monitor::MonitorSpec {
+            role: #role_name,
+            initial: #initial,
+            accepting: #accepting,
+            transitions: &[#(#transitions),*],
+            epsilons: &[#(#epsilons),*],
+        }
+    }
+}
+
+/// Generate runtime conformance monitors for every projected role
+#[must_use]
+pub fn generate_monitors(local_types: &[(Role, LocalType)]) -> TokenStream {
+    let monitors = local_types
+        .iter()
+        .map(|(role, local_type)| generate_monitor(role, local_type));
+
+    quote! { #(#monitors)* }
+}
+
+/// Build the monitor automaton of one role's local type at run time
+///
+/// This is the spec `generate_monitor` emits, for tools that only get to see
+/// the choreography when they run. Monitors hold `&'static` specs, so the
+/// spec and its strings are leaked: build one per role and keep it.
+#[must_use]
+pub fn monitor_spec(role: &Role, local_type: &LocalType) -> &'static MonitorSpec {
+    let mut builder = MonitorBuilder::default();
+    let initial = builder.state();
+    let accepting = builder.state();
+    builder.build(local_type, initial, accepting, &mut HashMap::new());
+
+    let leak = |text: String| -> &'static str { Box::leak(text.into_boxed_str()) };
+    let transitions: Vec<Transition> = builder
+        .transitions
+        .into_iter()
+        .map(|(from, kind, peer, label, to)| Transition {
+            from,
+            kind,
+            peer: leak(peer),
+            label: leak(label),
+            to,
+        })
+        .collect();
+    Box::leak(Box::new(MonitorSpec {
+        role: leak(role.name.to_string()),
+        initial,
+        accepting,
+        transitions: Box::leak(transitions.into_boxed_slice()),
+        epsilons: Box::leak(builder.epsilons.into_boxed_slice()),
+    }))
+}
+
+/// The `monitors` module holding the `<Role>Monitor`s of [`generate_monitor`]
+pub(crate) fn generate_monitors_module(monitors: TokenStream) -> TokenStream {
+    quote! {
+        /// Runtime conformance monitors for untrusted endpoints
+        pub mod monitors {
+            #monitors
+        }
+    }
+}
+
+/// Generate choreography code together with runtime conformance monitors
+///
+/// Monitors are emitted in a `monitors` module next to the typed endpoints.
+/// `@codegen(monitor)` adds the same module to the output of
+/// `parse_and_generate_with_extensions`.
+pub fn generate_choreography_code_with_monitors(
+    choreography: &Choreography,
+    local_types: &[(Role, LocalType)],
+) -> TokenStream {
+    let base_code = generate_code_for(choreography, local_types);
+    let monitors = generate_monitors_module(generate_monitors(local_types));
+
+    quote! {
+        #base_code
+        #monitors
+    }
+}
@@ -0,0 +1,503 @@
+//! TypeScript export of role state machines
+//!
+//! Browser participants are usually written by frontend teams in TypeScript.
+//! `generate_typescript` gives them a module checked against the same
+//! projections as the Rust roles:
+//!
+//! - a `Role` union and an interface per message, `{ type, payload }`, with
+//!   the payload typed like its serde JSON form where the Rust type maps to
+//!   TypeScript, and `unknown` otherwise;
+//! - per role, a discriminated union of its states (`ShopState`), keyed by
+//!   `name` and telling the single action the state allows;
+//! - per role, the table of those states (`SHOP_STATES`) and a constructor
+//!   (`shopMachine()`) for the shared `Machine` runtime, which throws a
+//!   `ProtocolViolation` on any action the current state does not allow.
+//!
+//! The states are those of the compact state machines: loops and recursion
+//! are edges back to an earlier state, and a choice is made by sending its
+//! label in place of the branch's first message.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use proc_macro2::Ident;
+use thiserror::Error;
+
+use crate::ast::{Choreography, LocalType, MessageType, Role};
+use crate::compiler::compact_codegen::{state_machine, State, END};
+use crate::compiler::effects_codegen::infer_content_type;
+use crate::compiler::handler_codegen::snake_case;
+use crate::compiler::projection::{project, ProjectionError};
+
+/// Errors raised while exporting roles to TypeScript
+#[derive(Debug, Error)]
+pub enum TypeScriptError {
+    #[error("role {0} is not declared in the choreography")]
+    UnknownRole(String),
+
+    #[error("role family {0} has no single state machine to export")]
+    RoleFamily(String),
+
+    #[error("cannot project role {role}: {source}")]
+    Projection {
+        role: String,
+        #[source]
+        source: ProjectionError,
+    },
+}
+
+/// Runtime shared by the roles of a module
+const RUNTIME: &str = r#"export type Step =
+  | { readonly name: string; readonly kind: "send" | "receive"; readonly peer: Role; readonly message: Message["type"]; readonly next: string }
+  | { readonly name: string; readonly kind: "select" | "branch"; readonly peer: Role; readonly branches: { readonly [label: string]: string } }
+  | { readonly name: string; readonly kind: "choice"; readonly branches: { readonly [label: string]: string } }
+  | { readonly name: string; readonly kind: "end" };
+
+export class ProtocolViolation extends Error {
+  constructor(message: string) {
+    super(message);
+    this.name = "ProtocolViolation";
+  }
+}
+
+/** Follows a role through its states, refusing actions the protocol does not allow */
+export class Machine<S extends Step> {
+  private current: S;
+
+  constructor(private readonly states: readonly [S, ...S[]]) {
+    this.current = states[0];
+  }
+
+  get state(): S {
+    return this.current;
+  }
+
+  get done(): boolean {
+    return this.current.kind === "end";
+  }
+
+  send(to: Role, message: Message): void {
+    this.exchange("send", to, message.type);
+  }
+
+  receive(from: Role, message: Message): void {
+    this.exchange("receive", from, message.type);
+  }
+
+  select(to: Role, label: string): void {
+    this.take("select", to, label);
+  }
+
+  offer(from: Role, label: string): void {
+    this.take("branch", from, label);
+  }
+
+  choose(label: string): void {
+    this.take("choice", undefined, label);
+  }
+
+  private exchange(kind: "send" | "receive", peer: Role, message: string): void {
+    const step: Step = this.current;
+    if ((step.kind === "send" || step.kind === "receive") && step.kind === kind && step.peer === peer && step.message === message) {
+      this.goto(step.next);
+      return;
+    }
+    throw this.violation(`${kind} ${message} with ${peer}`);
+  }
+
+  private take(kind: "select" | "branch" | "choice", peer: Role | undefined, label: string): void {
+    const step: Step = this.current;
+    let next: string | undefined;
+    if (step.kind === "choice" && kind === "choice") {
+      next = step.branches[label];
+    } else if ((step.kind === "select" || step.kind === "branch") && step.kind === kind && step.peer === peer) {
+      next = step.branches[label];
+    }
+    if (next === undefined) {
+      throw this.violation(`${kind} ${label}`);
+    }
+    this.goto(next);
+  }
+
+  private goto(name: string): void {
+    const state = this.states.find((s) => s.name === name);
+    if (state === undefined) {
+      throw new ProtocolViolation(`no state ${name}`);
+    }
+    this.current = state;
+  }
+
+  private violation(action: string): ProtocolViolation {
+    return new ProtocolViolation(`cannot ${action} in state ${this.current.name} (${this.current.kind})`);
+  }
+}
+"#;
+
+/// TypeScript module for `roles` of `choreography`, or every role if `roles`
+/// is empty
+///
+/// # Errors
+///
+/// [`TypeScriptError`] if a role is not declared, is a role family, or
+/// cannot be projected.
+pub fn generate_typescript(
+    choreography: &Choreography,
+    roles: &[&str],
+) -> Result<String, TypeScriptError> {
+    let selected: Vec<&Role> = if roles.is_empty() {
+        choreography.roles.iter().collect()
+    } else {
+        roles
+            .iter()
+            .map(|name| {
+                choreography
+                    .roles
+                    .iter()
+                    .find(|role| role.name == name)
+                    .ok_or_else(|| TypeScriptError::UnknownRole((*name).to_string()))
+            })
+            .collect::<Result<_, _>>()?
+    };
+
+    let mut machines = Vec::new();
+    let mut messages = BTreeMap::new();
+    for role in selected {
+        if role.is_family() {
+            return Err(TypeScriptError::RoleFamily(role.name.to_string()));
+        }
+        let local = project(choreography, role).map_err(|source| TypeScriptError::Projection {
+            role: role.name.to_string(),
+            source,
+        })?;
+        collect_messages(&local, &mut messages);
+        machines.push((role, state_machine(&local)));
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "// Generated from choreography `{}` by rumpsteak-aura. Do not edit.\n",
+        choreography.name
+    );
+    let role_names: Vec<String> = choreography
+        .roles
+        .iter()
+        .map(|role| format!("\"{}\"", role.name))
+        .collect();
+    let _ = writeln!(out, "export type Role = {};\n", role_names.join(" | "));
+
+    for message in messages.values() {
+        let name = &message.name;
+        let payload = match &message.payload {
+            Some(payload) => payload.clone(),
+            None => infer_content_type(&name.to_string()),
+        };
+        let payload = syn::parse2::<syn::Type>(payload)
+            .map(|ty| typescript_type(&ty))
+            .unwrap_or_else(|_| "unknown".to_string());
+        let _ = writeln!(
+            out,
+            "export interface {name} {{\n  readonly type: \"{name}\";\n  readonly payload: {payload};\n}}\n"
+        );
+    }
+    let message_names: Vec<String> = messages.keys().cloned().collect();
+    let message_union = if message_names.is_empty() {
+        "never".to_string()
+    } else {
+        message_names.join(" | ")
+    };
+    let _ = writeln!(out, "export type Message = {message_union};\n");
+    out.push_str(RUNTIME);
+
+    for (role, states) in machines {
+        write_machine(&mut out, &role.name, &states);
+    }
+    Ok(out)
+}
+
+/// State type, state table and constructor of `role`
+fn write_machine(out: &mut String, role: &Ident, states: &[State]) {
+    let mut steps: Vec<Vec<(&str, Field)>> = states
+        .iter()
+        .enumerate()
+        .filter_map(|(index, state)| step(index, state))
+        .collect();
+    steps.push(vec![
+        ("name", Field::State(END)),
+        ("kind", Field::Text("end".into())),
+    ]);
+
+    let state_type = format!("{role}State");
+    let _ = writeln!(
+        out,
+        "\n/** States of {role}, each allowing a single action */\nexport type {state_type} ="
+    );
+    let types: Vec<String> = steps.iter().map(|fields| render(fields, true)).collect();
+    let _ = writeln!(out, "  | {};", types.join("\n  | "));
+
+    let table = snake_case(&role.to_string()).to_uppercase();
+    let _ = writeln!(
+        out,
+        "\nexport const {table}_STATES: readonly [{state_type}, ...{state_type}[]] = ["
+    );
+    for fields in &steps {
+        let _ = writeln!(out, "  {},", render(fields, false));
+    }
+    out.push_str("];\n");
+
+    let role_text = role.to_string();
+    let mut chars = role_text.chars();
+    let function = chars
+        .next()
+        .map(|first| first.to_lowercase().chain(chars).collect::<String>())
+        .unwrap_or_default();
+    let _ = writeln!(
+        out,
+        "\nexport function {function}Machine(): Machine<{state_type}> {{\n  return new Machine({table}_STATES);\n}}"
+    );
+}
+
+/// Value of a field of a state
+enum Field {
+    Text(String),
+    State(usize),
+    Branches(Vec<(String, usize)>),
+}
+
+/// Fields of state `index`, `None` for the loop entries `state_machine`
+/// resolves away
+fn step(index: usize, state: &State) -> Option<Vec<(&'static str, Field)>> {
+    let text = |value: &Ident| Field::Text(value.to_string());
+    let branches = |branches: &[(Ident, usize)]| {
+        Field::Branches(
+            branches
+                .iter()
+                .map(|(label, next)| (label.to_string(), *next))
+                .collect(),
+        )
+    };
+    let (kind, mut fields) = match state {
+        State::Send { to, message, next } => (
+            "send",
+            vec![
+                ("peer", text(to)),
+                ("message", text(message)),
+                ("next", Field::State(*next)),
+            ],
+        ),
+        State::Receive {
+            from,
+            message,
+            next,
+        } => (
+            "receive",
+            vec![
+                ("peer", text(from)),
+                ("message", text(message)),
+                ("next", Field::State(*next)),
+            ],
+        ),
+        State::Select { to, branches: arms } => (
+            "select",
+            vec![("peer", text(to)), ("branches", branches(arms))],
+        ),
+        State::Branch {
+            from,
+            branches: arms,
+        } => (
+            "branch",
+            vec![("peer", text(from)), ("branches", branches(arms))],
+        ),
+        State::LocalChoice { branches: arms } => ("choice", vec![("branches", branches(arms))]),
+        State::Jump(_) => return None,
+    };
+    fields.insert(0, ("kind", Field::Text(kind.into())));
+    fields.insert(0, ("name", Field::State(index)));
+    Some(fields)
+}
+
+/// Object literal of `fields`, or its type with every field read-only
+fn render(fields: &[(&str, Field)], as_type: bool) -> String {
+    let (prefix, separator) = if as_type {
+        ("readonly ", "; ")
+    } else {
+        ("", ", ")
+    };
+    let value = |field: &Field| match field {
+        Field::Text(text) => format!("\"{text}\""),
+        Field::State(index) => state_name(*index),
+        Field::Branches(branches) => {
+            let entries: Vec<String> = branches
+                .iter()
+                .map(|(label, next)| format!("{prefix}\"{label}\": {}", state_name(*next)))
+                .collect();
+            format!("{{ {} }}", entries.join(separator))
+        }
+    };
+    let entries: Vec<String> = fields
+        .iter()
+        .map(|(key, field)| format!("{prefix}{key}: {}", value(field)))
+        .collect();
+    format!("{{ {} }}", entries.join(separator))
+}
+
+fn state_name(index: usize) -> String {
+    if index == END {
+        "\"End\"".to_string()
+    } else {
+        format!("\"S{index}\"")
+    }
+}
+
+/// Every message sent or received in `local`, by name
+fn collect_messages(local: &LocalType, messages: &mut BTreeMap<String, MessageType>) {
+    match local {
+        LocalType::Send {
+            message,
+            continuation,
+            ..
+        }
+        | LocalType::Receive {
+            message,
+            continuation,
+            ..
+        } => {
+            messages
+                .entry(message.name.to_string())
+                .or_insert_with(|| message.clone());
+            collect_messages(continuation, messages);
+        }
+        LocalType::Select { branches, .. }
+        | LocalType::Branch { branches, .. }
+        | LocalType::LocalChoice { branches } => {
+            for (_, body) in branches {
+                collect_messages(body, messages);
+            }
+        }
+        LocalType::Loop { body, .. }
+        | LocalType::Rec { body, .. }
+        | LocalType::Timeout { body, .. } => collect_messages(body, messages),
+        LocalType::Var(_) | LocalType::End => {}
+    }
+}
+
+/// TypeScript type of the serde JSON form of `ty`
+fn typescript_type(ty: &syn::Type) -> String {
+    match ty {
+        syn::Type::Reference(reference) => typescript_type(&reference.elem),
+        syn::Type::Paren(paren) => typescript_type(&paren.elem),
+        syn::Type::Tuple(tuple) if tuple.elems.is_empty() => "null".to_string(),
+        syn::Type::Tuple(tuple) => {
+            let elems: Vec<String> = tuple.elems.iter().map(typescript_type).collect();
+            format!("[{}]", elems.join(", "))
+        }
+        syn::Type::Slice(slice) => format!("{}[]", element_type(&slice.elem)),
+        syn::Type::Array(array) => format!("{}[]", element_type(&array.elem)),
+        syn::Type::Path(path) => {
+            let Some(segment) = path.path.segments.last() else {
+                return "unknown".to_string();
+            };
+            let argument = || match &segment.arguments {
+                syn::PathArguments::AngleBracketed(args) => {
+                    args.args.iter().find_map(|arg| match arg {
+                        syn::GenericArgument::Type(ty) => Some(ty),
+                        _ => None,
+                    })
+                }
+                _ => None,
+            };
+            match segment.ident.to_string().as_str() {
+                "u8" | "u16" | "u32" | "u64" | "u128" | "usize" | "i8" | "i16" | "i32" | "i64"
+                | "i128" | "isize" | "f32" | "f64" => "number".to_string(),
+                "String" | "str" | "char" => "string".to_string(),
+                "bool" => "boolean".to_string(),
+                "Vec" | "VecDeque" | "HashSet" | "BTreeSet" => argument()
+                    .map(|ty| format!("{}[]", element_type(ty)))
+                    .unwrap_or_else(|| "unknown[]".to_string()),
+                "Option" => argument()
+                    .map(|ty| format!("{} | null", typescript_type(ty)))
+                    .unwrap_or_else(|| "unknown".to_string()),
+                "Box" | "Arc" | "Rc" => argument()
+                    .map(typescript_type)
+                    .unwrap_or_else(|| "unknown".to_string()),
+                _ => "unknown".to_string(),
+            }
+        }
+        _ => "unknown".to_string(),
+    }
+}
+
+/// Element type of an array, parenthesized if it is a union
+fn element_type(ty: &syn::Type) -> String {
+    let element = typescript_type(ty);
+    if element.contains(" | ") {
+        format!("({element})")
+    } else {
+        element
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parser::parse_choreography_str;
+
+    const SHOP: &str = r"
+choreography Shop {
+    roles: Buyer, Seller, Bank
+    Buyer -> Seller: Hello(String)
+    choice Buyer {
+        buy: {
+            Buyer -> Seller: Buy
+            Seller -> Buyer: Receipt(Vec<Option<u64>>)
+        }
+        leave: {
+            Buyer -> Seller: Leave
+        }
+    }
+}
+";
+
+    #[test]
+    fn test_exports_states_of_selected_roles() {
+        let choreography = parse_choreography_str(SHOP).unwrap();
+        let module = generate_typescript(&choreography, &["Buyer"]).unwrap();
+
+        assert!(module.contains(r#"export type Role = "Buyer" | "Seller" | "Bank";"#));
+        assert!(module.contains("readonly payload: string;"));
+        assert!(module.contains("readonly payload: (number | null)[];"));
+        assert!(module.contains("export type BuyerState ="));
+        assert!(module.contains(
+            r#"| { readonly name: "S0"; readonly kind: "send"; readonly peer: "Seller"; readonly message: "Hello"; readonly next: "S1" }"#
+        ));
+        assert!(module.contains(
+            r#"{ name: "S1", kind: "select", peer: "Seller", branches: { "buy": "S2", "leave": "End" } },"#
+        ));
+        assert!(module.contains("export const BUYER_STATES"));
+        assert!(module.contains("export function buyerMachine(): Machine<BuyerState>"));
+        assert!(!module.contains("SellerState"));
+    }
+
+    #[test]
+    fn test_rejects_unknown_roles_and_families() {
+        let choreography = parse_choreography_str(SHOP).unwrap();
+        assert!(matches!(
+            generate_typescript(&choreography, &["Courier"]),
+            Err(TypeScriptError::UnknownRole(role)) if role == "Courier"
+        ));
+
+        let workers = parse_choreography_str(
+            r"
+choreography Jobs {
+    roles: Master, Worker[3]
+    Master -> Worker[*]: Task
+}
+",
+        )
+        .unwrap();
+        assert!(matches!(
+            generate_typescript(&workers, &[]),
+            Err(TypeScriptError::RoleFamily(role)) if role == "Worker"
+        ));
+    }
+}
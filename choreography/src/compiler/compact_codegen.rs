@@ -0,0 +1,564 @@
+//! Compact state machines instead of nested session types
+//!
+//! With `@codegen(compact)` on a choreography, each role gets a state enum
+//! named like its session type (`Client_Auction`) and a single
+//! `run_<role>_<protocol>` driver loop, instead of a session type nesting one
+//! generic per step. Every send, receive and choice of the projection is a
+//! variant; loops and recursion are edges back to an earlier state, so the
+//! generated code grows linearly with the protocol.
+//!
+//! The driver routes messages over the role's channels and leaves contents
+//! and decisions to a `<Protocol>CompactHandler`:
+//!
+//! - `send` returns the message of a send state, or the label of a selection;
+//! - `receive` is given the message or label received in a state;
+//! - `choose` picks the branch of a local choice.
+//!
+//! As with the session types, a choice is made by sending a value of the
+//! type named like the branch (`buy`) in place of the branch's first message.
+//!
+//! Handlers work with the role's message type, so a message of the wrong
+//! type is only caught when the driver checks it against the state, and
+//! reported as `<Protocol>CompactError::Unexpected`. Loops repeat their body
+//! like the `Loop` session type does.
+
+use crate::ast::{LocalType, Role};
+use crate::compiler::handler_codegen::snake_case;
+use proc_macro2::{Ident, TokenStream};
+use quote::{format_ident, quote};
+use std::collections::HashMap;
+
+/// Target of a transition that ends the session
+pub(crate) const END: usize = usize::MAX;
+
+/// A state of a role's compact state machine
+#[derive(Debug, Clone)]
+pub(crate) enum State {
+    Send {
+        to: Ident,
+        message: Ident,
+        next: usize,
+    },
+    Receive {
+        from: Ident,
+        message: Ident,
+        next: usize,
+    },
+    Select {
+        to: Ident,
+        branches: Vec<(Ident, usize)>,
+    },
+    Branch {
+        from: Ident,
+        branches: Vec<(Ident, usize)>,
+    },
+    LocalChoice {
+        branches: Vec<(Ident, usize)>,
+    },
+    /// Placeholder for the entry of a loop or recursion, resolved away
+    Jump(usize),
+}
+
+impl State {
+    fn targets_mut(&mut self) -> Vec<&mut usize> {
+        match self {
+            State::Send { next, .. } | State::Receive { next, .. } | State::Jump(next) => {
+                vec![next]
+            }
+            State::Select { branches, .. }
+            | State::Branch { branches, .. }
+            | State::LocalChoice { branches } => {
+                branches.iter_mut().map(|(_, next)| next).collect()
+            }
+        }
+    }
+}
+
+/// Flatten `local_type` into states, in protocol order
+pub(crate) fn state_machine(local_type: &LocalType) -> Vec<State> {
+    let mut states = Vec::new();
+    let entry = compile(local_type, END, &mut states, &mut HashMap::new());
+
+    // Resolve every target through the loop entries to a real state
+    let resolve = |states: &[State], mut target: usize| {
+        let mut hops = 0;
+        while let Some(State::Jump(next)) = states.get(target) {
+            hops += 1;
+            if hops > states.len() {
+                // A loop without any action never communicates again
+                return END;
+            }
+            target = *next;
+        }
+        target
+    };
+    let mut resolved = states.clone();
+    for state in &mut resolved {
+        for target in state.targets_mut() {
+            *target = resolve(&states, *target);
+        }
+    }
+
+    // Drop the placeholders, keeping the entry first
+    let entry = resolve(&states, entry);
+    let mut order: Vec<usize> = (0..resolved.len())
+        .filter(|&i| !matches!(resolved[i], State::Jump(_)))
+        .collect();
+    if let Some(position) = order.iter().position(|&i| i == entry) {
+        order.remove(position);
+        order.insert(0, entry);
+    }
+    let renumber: HashMap<usize, usize> = order
+        .iter()
+        .enumerate()
+        .map(|(new, &old)| (old, new))
+        .collect();
+    order
+        .into_iter()
+        .map(|i| {
+            let mut state = resolved[i].clone();
+            for target in state.targets_mut() {
+                if *target != END {
+                    *target = renumber[target];
+                }
+            }
+            state
+        })
+        .collect()
+}
+
+/// Add the states of `local_type` continuing with `next`, returning its entry
+fn compile(
+    local_type: &LocalType,
+    next: usize,
+    states: &mut Vec<State>,
+    recs: &mut HashMap<String, usize>,
+) -> usize {
+    fn reserve(states: &mut Vec<State>) -> usize {
+        states.push(State::Jump(END));
+        states.len() - 1
+    }
+    let branches = |branches: &[(Ident, LocalType)],
+                    states: &mut Vec<State>,
+                    recs: &mut HashMap<String, usize>| {
+        branches
+            .iter()
+            .map(|(label, body)| (label.clone(), compile(body, next, states, recs)))
+            .collect()
+    };
+
+    match local_type {
+        LocalType::Send {
+            to,
+            message,
+            continuation,
+        } => {
+            let id = reserve(states);
+            let next = compile(continuation, next, states, recs);
+            states[id] = State::Send {
+                to: to.name.clone(),
+                message: message.name.clone(),
+                next,
+            };
+            id
+        }
+        LocalType::Receive {
+            from,
+            message,
+            continuation,
+        } => {
+            let id = reserve(states);
+            let next = compile(continuation, next, states, recs);
+            states[id] = State::Receive {
+                from: from.name.clone(),
+                message: message.name.clone(),
+                next,
+            };
+            id
+        }
+        LocalType::Select { to, branches: arms } => {
+            let id = reserve(states);
+            states[id] = State::Select {
+                to: to.name.clone(),
+                branches: branches(arms, states, recs),
+            };
+            id
+        }
+        LocalType::Branch {
+            from,
+            branches: arms,
+        } => {
+            // The selecting side sends the label in place of the first
+            // message of the branch, so it is not received again
+            let arms: Vec<(Ident, LocalType)> = arms
+                .iter()
+                .map(|(label, body)| match body {
+                    LocalType::Receive {
+                        from: sender,
+                        continuation,
+                        ..
+                    } if sender.name == from.name => (label.clone(), (**continuation).clone()),
+                    _ => (label.clone(), body.clone()),
+                })
+                .collect();
+            let id = reserve(states);
+            states[id] = State::Branch {
+                from: from.name.clone(),
+                branches: branches(&arms, states, recs),
+            };
+            id
+        }
+        LocalType::LocalChoice { branches: arms } => {
+            let id = reserve(states);
+            states[id] = State::LocalChoice {
+                branches: branches(arms, states, recs),
+            };
+            id
+        }
+        LocalType::Loop { body, .. } => {
+            let id = reserve(states);
+            let entry = compile(body, id, states, recs);
+            states[id] = State::Jump(entry);
+            id
+        }
+        LocalType::Rec { label, body } => {
+            let id = reserve(states);
+            let shadowed = recs.insert(label.to_string(), id);
+            let entry = compile(body, next, states, recs);
+            match shadowed {
+                Some(outer) => recs.insert(label.to_string(), outer),
+                None => recs.remove(&label.to_string()),
+            };
+            states[id] = State::Jump(entry);
+            id
+        }
+        LocalType::Var(label) => recs.get(&label.to_string()).copied().unwrap_or(END),
+        LocalType::Timeout { body, .. } => compile(body, next, states, recs),
+        LocalType::End => next,
+    }
+}
+
+/// Generate the handler trait and error type shared by the roles of a
+/// protocol
+#[must_use]
+pub fn generate_compact_support(protocol_name: &str) -> TokenStream {
+    let handler = format_ident!("{}CompactHandler", protocol_name);
+    let error = format_ident!("{}CompactError", protocol_name);
+
+    quote! {
+        /// Contents and decisions of the compact state machines
+        trait #handler<S, M> {
+            /// Message to send in `state`, or the label to select
+            fn send(&mut self, state: S) -> M;
+            /// Message or label received in `state`
+            fn receive(&mut self, state: S, message: M);
+            /// Index of the branch to take at a local choice between `labels`
+            fn choose(&mut self, state: S, labels: &[&'static str]) -> usize;
+        }
+
+        /// Errors of the compact drivers
+        #[derive(Debug)]
+        enum #error<S> {
+            /// The handler gave a message the state does not send, or chose
+            /// no branch
+            Unexpected(S),
+            /// Sending the message of the state failed
+            Send(S),
+            /// Receiving the message of the state failed
+            Receive(S, ::rumpsteak_aura::ReceiveError),
+        }
+
+        impl<S> #error<S> {
+            /// `message` if it holds an `L`
+            #[allow(dead_code)]
+            fn expect<M: ::rumpsteak_aura::Message<L>, L>(message: M) -> ::core::result::Result<M, M> {
+                M::downcast(message).map(M::upcast)
+            }
+        }
+    }
+}
+
+/// Generate the state enum and driver of `role`
+///
+/// Expects the items of [`generate_compact_support`] in the same module.
+#[must_use]
+pub fn generate_compact_session(
+    role: &Role,
+    local_type: &LocalType,
+    protocol_name: &str,
+) -> TokenStream {
+    let role_name = &role.name;
+    let state_type = format_ident!("{}_{}", role.name, protocol_name);
+    let handler = format_ident!("{}CompactHandler", protocol_name);
+    let error = format_ident!("{}CompactError", protocol_name);
+    let driver = format_ident!(
+        "run_{}_{}",
+        snake_case(&role.name.to_string()),
+        snake_case(protocol_name)
+    );
+    let states = state_machine(local_type);
+
+    let variant = |id: usize| -> TokenStream {
+        if id == END {
+            quote! { #state_type::End }
+        } else {
+            let name = format_ident!("S{}", id);
+            quote! { #state_type::#name }
+        }
+    };
+
+    let variants = states.iter().enumerate().map(|(id, state)| {
+        let name = format_ident!("S{}", id);
+        let labels = |branches: &[(Ident, usize)]| {
+            branches
+                .iter()
+                .map(|(label, _)| format!("`{label}`"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let doc = match state {
+            State::Send { to, message, .. } => format!("Send `{message}` to `{to}`"),
+            State::Receive { from, message, .. } => format!("Receive `{message}` from `{from}`"),
+            State::Select { to, branches } => format!("Select {} at `{to}`", labels(branches)),
+            State::Branch { from, branches } => {
+                format!("Branch on {} from `{from}`", labels(branches))
+            }
+            State::LocalChoice { branches } => format!("Choose between {}", labels(branches)),
+            State::Jump(_) => unreachable!("placeholders are resolved"),
+        };
+        quote! {
+            #[doc = #doc]
+            #name
+        }
+    });
+
+    let arms = states.iter().enumerate().map(|(id, state)| {
+        let current = variant(id);
+        let body = match state {
+            State::Send { to, message, next } => {
+                let next = variant(*next);
+                quote! {
+                    let message = #error::<#state_type>::expect::<_, #message>(handler.send(state))
+                        .map_err(|_| #error::Unexpected(state))?;
+                    ::futures::SinkExt::send(::rumpsteak_aura::Route::<#to>::route(role), message)
+                        .await
+                        .map_err(|_| #error::Send(state))?;
+                    #next
+                }
+            }
+            State::Receive {
+                from,
+                message,
+                next,
+            } => {
+                let next = variant(*next);
+                let receive = receive(from, &error);
+                quote! {
+                    let message = #receive;
+                    let message = #error::<#state_type>::expect::<_, #message>(message).map_err(|_| {
+                        #error::Receive(state, ::rumpsteak_aura::ReceiveError::UnexpectedType)
+                    })?;
+                    handler.receive(state, message);
+                    #next
+                }
+            }
+            State::Select { to, branches } => {
+                let matched = match_labels(branches, &variant, &error, &state_type, quote! {
+                    return Err(#error::Unexpected(state))
+                });
+                quote! {
+                    let message = handler.send(state);
+                    let (message, next) = #matched;
+                    ::futures::SinkExt::send(::rumpsteak_aura::Route::<#to>::route(role), message)
+                        .await
+                        .map_err(|_| #error::Send(state))?;
+                    next
+                }
+            }
+            State::Branch { from, branches } => {
+                let receive = receive(from, &error);
+                let matched = match_labels(branches, &variant, &error, &state_type, quote! {
+                    return Err(#error::Receive(state, ::rumpsteak_aura::ReceiveError::UnexpectedType))
+                });
+                quote! {
+                    let message = #receive;
+                    let (message, next) = #matched;
+                    handler.receive(state, message);
+                    next
+                }
+            }
+            State::LocalChoice { branches } => {
+                let labels = branches.iter().map(|(label, _)| label.to_string());
+                let choices = branches.iter().enumerate().map(|(i, (_, next))| {
+                    let next = variant(*next);
+                    quote! { #i => #next, }
+                });
+                quote! {
+                    match handler.choose(state, &[#(#labels),*]) {
+                        #(#choices)*
+                        _ => return Err(#error::Unexpected(state)),
+                    }
+                }
+            }
+            State::Jump(_) => unreachable!("placeholders are resolved"),
+        };
+        quote! {
+            #current => { #body }
+        }
+    });
+    let first = variant(if states.is_empty() { END } else { 0 });
+
+    quote! {
+        /// States of the compact state machine of this role
+        #[allow(non_camel_case_types)]
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        enum #state_type {
+            #(#variants,)*
+            /// The session is over
+            End,
+        }
+
+        /// Drive this role through the protocol until it ends
+        async fn #driver<H>(
+            role: &mut #role_name,
+            handler: &mut H,
+        ) -> ::core::result::Result<(), #error<#state_type>>
+        where
+            H: #handler<#state_type, <#role_name as ::rumpsteak_aura::Role>::Message>,
+        {
+            let mut state = #first;
+            loop {
+                state = match state {
+                    #(#arms)*
+                    #state_type::End => return Ok(()),
+                };
+            }
+        }
+    }
+}
+
+/// Next message on the route from `from`
+fn receive(from: &Ident, error: &Ident) -> TokenStream {
+    quote! {
+        ::futures::StreamExt::next(::rumpsteak_aura::Route::<#from>::route(role))
+            .await
+            .ok_or(#error::Receive(state, ::rumpsteak_aura::ReceiveError::EmptyStream))?
+    }
+}
+
+/// Match `message` against the label types of `branches` in order, giving
+/// the message and the state of the first branch it is a label of
+fn match_labels(
+    branches: &[(Ident, usize)],
+    variant: &dyn Fn(usize) -> TokenStream,
+    error: &Ident,
+    state_type: &Ident,
+    otherwise: TokenStream,
+) -> TokenStream {
+    branches
+        .iter()
+        .rev()
+        .enumerate()
+        .fold(quote! { #otherwise }, |rest, (i, (label, next))| {
+            let next = variant(*next);
+            // The last label leaves nothing to try the message against
+            let unmatched = if i == 0 {
+                quote! { _ }
+            } else {
+                quote! { message }
+            };
+            quote! {
+                match #error::<#state_type>::expect::<_, #label>(message) {
+                    Ok(message) => (message, #next),
+                    Err(#unmatched) => #rest,
+                }
+            }
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{parser::parse_choreography_str, projection::project};
+
+    fn local_type(source: &str, role: usize) -> (Role, LocalType) {
+        let choreography = parse_choreography_str(source).unwrap();
+        let role = choreography.roles[role].clone();
+        let local = project(&choreography, &role).unwrap();
+        (role, local)
+    }
+
+    #[test]
+    fn test_loops_jump_back() {
+        let (role, local) = local_type(
+            r"
+choreography Poll {
+    roles: Client, Server
+    Client -> Server: Hello
+    loop (decides: Client) {
+        Client -> Server: Ping
+        Server -> Client: Pong
+    }
+}
+",
+            1,
+        );
+        let states = state_machine(&local);
+        assert_eq!(states.len(), 3);
+        assert!(
+            matches!(&states[0], State::Receive { message, next: 1, .. } if message == "Hello")
+        );
+        assert!(matches!(&states[2], State::Send { message, next: 1, .. } if message == "Pong"));
+
+        let code = generate_compact_session(&role, &local, "Poll").to_string();
+        assert!(code.contains("enum Server_Poll"));
+        assert!(code.contains("async fn run_server_poll"));
+        assert!(!code.contains("Send <"));
+    }
+
+    #[test]
+    fn test_choices_branch_to_states() {
+        let (role, local) = local_type(
+            r"
+choreography Shop {
+    roles: Buyer, Seller
+    choice Buyer {
+        buy: {
+            Buyer -> Seller: Buy
+            Seller -> Buyer: Receipt
+        }
+        leave: {
+            Buyer -> Seller: Leave
+        }
+    }
+}
+",
+            0,
+        );
+        let states = state_machine(&local);
+        let State::Select { branches, .. } = &states[0] else {
+            panic!("expected a selection, got {:?}", states[0]);
+        };
+        assert_eq!(branches.len(), 2);
+
+        let code = generate_compact_session(&role, &local, "Shop").to_string();
+        assert!(code.contains("run_buyer_shop"));
+        assert!(code.contains("ShopCompactHandler"));
+    }
+
+    #[test]
+    fn test_wide_choice_in_loop() {
+        let branches: String = (0..12)
+            .map(|i| format!("b{i}: {{ A -> B: M{i}\n B -> A: R{i} }}\n"))
+            .collect();
+        let source = format!(
+            "choreography Wide {{\n roles: A, B\n loop (decides: A) {{ choice A {{ {branches} }} }}\n}}"
+        );
+        let (_, local) = local_type(&source, 0);
+        let states = state_machine(&local);
+        // The first send of a branch is its label
+        assert_eq!(states.len(), 13);
+        // Each branch ends back at the choice
+        assert!(matches!(&states[1], State::Receive { message, next: 0, .. } if message == "R0"));
+    }
+}
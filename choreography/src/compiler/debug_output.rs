@@ -0,0 +1,380 @@
+// Debug dumps of the choreography! macro
+//
+// `#[debug_output = "dir"]` in front of the DSL string of a `choreography!`
+// invocation makes the macro write what it worked from next to the code it
+// expands to, so large protocols can be debugged without `cargo expand`.
+// Every choreography gets its own directory under `dir`, holding
+//
+//   grammar.pest     the composed grammar the DSL was parsed with
+//   ast.json         the parsed choreography
+//   <Role>.txt       the local type of every role, pretty-printed
+//   generated.rs     the generated code, one statement per line
+//
+// A relative `dir` is taken from the root of the crate invoking the macro.
+
+use crate::ast::{Branch, Choreography, Condition, LocalType, MessageType, Protocol, Role, Span};
+use crate::compiler::grammar::{GrammarComposer, GrammarCompositionError};
+use proc_macro2::{Delimiter, Spacing, TokenStream, TokenTree};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Name of the macro attribute requesting a dump
+pub const DEBUG_OUTPUT: &str = "debug_output";
+
+/// Errors raised while writing a dump
+#[derive(Debug, Error)]
+pub enum DebugOutputError {
+    #[error("cannot write debug output to {path}: {source}")]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("cannot compose the grammar: {0}")]
+    Grammar(#[from] GrammarCompositionError),
+}
+
+/// Write the dump of `choreography` under `dir`
+///
+/// Returns the directory the files went to, `dir/<Name>`.
+///
+/// # Errors
+///
+/// [`DebugOutputError`] if a file cannot be written or the grammar cannot be
+/// composed.
+pub fn write_debug_output(
+    dir: &Path,
+    choreography: &Choreography,
+    local_types: &[(Role, LocalType)],
+    generated: &TokenStream,
+) -> Result<PathBuf, DebugOutputError> {
+    let dir = dir.join(choreography.name.to_string());
+    let write = |name: &str, contents: String| {
+        let path = dir.join(name);
+        std::fs::write(&path, contents).map_err(|source| DebugOutputError::Io { path, source })
+    };
+    std::fs::create_dir_all(&dir).map_err(|source| DebugOutputError::Io {
+        path: dir.clone(),
+        source,
+    })?;
+
+    write("grammar.pest", GrammarComposer::new().compose()?)?;
+    let ast = serde_json::to_string_pretty(&ast_to_json(choreography))
+        .unwrap_or_else(|error| format!("{{\"error\": \"{error}\"}}"));
+    write("ast.json", ast + "\n")?;
+    for (role, local_type) in local_types {
+        write(
+            &format!("{}.txt", role_text(role)),
+            local_type.to_pretty_string(),
+        )?;
+    }
+    write("generated.rs", render_tokens(generated))?;
+    Ok(dir)
+}
+
+/// JSON form of a parsed choreography
+///
+/// Statements are listed in order rather than nested through their
+/// continuations.
+#[must_use]
+pub fn ast_to_json(choreography: &Choreography) -> Value {
+    let attrs: Map<String, Value> = choreography
+        .attrs
+        .iter()
+        .map(|(key, value)| (key.clone(), Value::from(value.as_str())))
+        .collect();
+    json!({
+        "name": choreography.name.to_string(),
+        "namespace": choreography.namespace,
+        "roles": choreography
+            .roles
+            .iter()
+            .map(|role| role_json(role, choreography.role_doc(&role.name)))
+            .collect::<Vec<_>>(),
+        "attrs": attrs,
+        "protocol": protocol_json(&choreography.protocol),
+    })
+}
+
+fn protocol_json(protocol: &Protocol) -> Vec<Value> {
+    let mut statements = Vec::new();
+    let mut current = protocol;
+    loop {
+        let (statement, next) = match current {
+            Protocol::Send {
+                from,
+                to,
+                message,
+                continuation,
+                annotations,
+                span,
+                ..
+            } => (
+                json!({
+                    "kind": "send",
+                    "from": role_text(from),
+                    "to": role_text(to),
+                    "message": message_json(message),
+                    "annotations": annotations_json(annotations),
+                    "span": span_json(*span),
+                }),
+                Some(continuation),
+            ),
+            Protocol::Broadcast {
+                from,
+                to_all,
+                message,
+                continuation,
+                annotations,
+                span,
+                ..
+            } => (
+                json!({
+                    "kind": "broadcast",
+                    "from": role_text(from),
+                    "to": to_all.iter().map(role_text).collect::<Vec<_>>(),
+                    "message": message_json(message),
+                    "annotations": annotations_json(annotations),
+                    "span": span_json(*span),
+                }),
+                Some(continuation),
+            ),
+            Protocol::Choice {
+                role,
+                branches,
+                annotations,
+                span,
+            } => (
+                json!({
+                    "kind": "choice",
+                    "role": role_text(role),
+                    "branches": branches.iter().map(branch_json).collect::<Vec<_>>(),
+                    "annotations": annotations_json(annotations),
+                    "span": span_json(*span),
+                }),
+                None,
+            ),
+            Protocol::Loop {
+                condition,
+                body,
+                span,
+            } => (
+                json!({
+                    "kind": "loop",
+                    "condition": condition.as_ref().map(condition_json),
+                    "body": protocol_json(body),
+                    "span": span_json(*span),
+                }),
+                None,
+            ),
+            Protocol::Parallel { protocols, span } => (
+                json!({
+                    "kind": "parallel",
+                    "branches": protocols.iter().map(protocol_json).collect::<Vec<_>>(),
+                    "span": span_json(*span),
+                }),
+                None,
+            ),
+            Protocol::Rec { label, body, span } => (
+                json!({
+                    "kind": "rec",
+                    "label": label.to_string(),
+                    "body": protocol_json(body),
+                    "span": span_json(*span),
+                }),
+                None,
+            ),
+            Protocol::Var(label) => (
+                json!({ "kind": "continue", "label": label.to_string() }),
+                None,
+            ),
+            Protocol::Extension {
+                extension,
+                continuation,
+                annotations,
+                span,
+            } => (
+                json!({
+                    "kind": "extension",
+                    "type": extension.type_name(),
+                    "annotations": annotations_json(annotations),
+                    "span": span_json(*span),
+                }),
+                Some(continuation),
+            ),
+            Protocol::End => break,
+        };
+        statements.push(statement);
+        match next {
+            Some(next) => current = next,
+            None => break,
+        }
+    }
+    statements
+}
+
+fn branch_json(branch: &Branch) -> Value {
+    json!({
+        "label": branch.label.to_string(),
+        "guard": branch.guard.as_ref().map(ToString::to_string),
+        "probability": branch.probability,
+        "protocol": protocol_json(&branch.protocol),
+        "span": span_json(branch.span),
+    })
+}
+
+fn condition_json(condition: &Condition) -> Value {
+    match condition {
+        Condition::RoleDecides(role) => json!({ "decides": role_text(role) }),
+        Condition::Count(count) => json!({ "count": count }),
+        Condition::Custom(tokens) => json!({ "custom": tokens.to_string() }),
+    }
+}
+
+fn role_json(role: &Role, doc: Option<&str>) -> Value {
+    json!({
+        "name": role.name.to_string(),
+        "param": role.param.as_ref().map(ToString::to_string),
+        "doc": doc,
+    })
+}
+
+fn message_json(message: &MessageType) -> Value {
+    json!({
+        "name": message.name.to_string(),
+        "type": message.type_annotation.as_ref().map(ToString::to_string),
+        "payload": message.payload.as_ref().map(ToString::to_string),
+    })
+}
+
+fn annotations_json(annotations: &HashMap<String, String>) -> Value {
+    // Sorted so dumps of the same choreography compare equal
+    let sorted: std::collections::BTreeMap<_, _> = annotations.iter().collect();
+    json!(sorted)
+}
+
+fn span_json(span: Span) -> Value {
+    json!({ "line": span.line, "column": span.column })
+}
+
+fn role_text(role: &Role) -> String {
+    match &role.index {
+        Some(index) => format!("{}[{index}]", role.name),
+        None => role.name.to_string(),
+    }
+}
+
+/// Generated code with a statement or item per line and braces indented
+///
+/// Not rustfmt, but enough to read and search large expansions.
+#[must_use]
+pub fn render_tokens(tokens: &TokenStream) -> String {
+    let mut out = String::new();
+    write_tokens(&mut out, tokens.clone(), 0, &mut false);
+    out
+}
+
+fn write_tokens(out: &mut String, tokens: TokenStream, depth: usize, joint: &mut bool) {
+    for tree in tokens {
+        match tree {
+            TokenTree::Group(group) if group.delimiter() == Delimiter::Brace => {
+                push(out, depth, "{", joint);
+                out.push('\n');
+                write_tokens(out, group.stream(), depth + 1, joint);
+                if !out.ends_with('\n') {
+                    out.push('\n');
+                }
+                push(out, depth, "}", joint);
+                out.push('\n');
+            }
+            TokenTree::Punct(punct) if matches!(punct.as_char(), ';' | ',') => {
+                // Keep `};` and `},` together
+                let after_brace = out.ends_with("}\n");
+                if after_brace {
+                    out.pop();
+                }
+                out.push(punct.as_char());
+                if punct.as_char() == ';' || after_brace {
+                    out.push('\n');
+                }
+                *joint = false;
+            }
+            TokenTree::Punct(punct) => {
+                push(out, depth, &punct.to_string(), joint);
+                *joint = punct.spacing() == Spacing::Joint;
+            }
+            other => push(out, depth, &other.to_string(), joint),
+        }
+    }
+}
+
+fn push(out: &mut String, depth: usize, text: &str, joint: &mut bool) {
+    if out.is_empty() || out.ends_with('\n') {
+        out.push_str(&"    ".repeat(depth));
+    } else if !*joint {
+        out.push(' ');
+    }
+    out.push_str(text);
+    *joint = false;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parser::parse_choreography_str;
+    use crate::compiler::projection::project;
+    use quote::quote;
+
+    #[test]
+    fn test_dump_files() {
+        let choreography = parse_choreography_str(
+            r"
+choreography Auction {
+    roles: Bidder, Seller
+
+    Bidder -> Seller: Bid(u64)
+    choice Seller {
+        accept: {
+            Seller -> Bidder: Won
+        }
+        reject: {
+            Seller -> Bidder: Lost
+        }
+    }
+}
+",
+        )
+        .unwrap();
+        let local_types: Vec<_> = choreography
+            .roles
+            .iter()
+            .map(|role| (role.clone(), project(&choreography, role).unwrap()))
+            .collect();
+        let generated = quote! { pub struct Bid(pub u64); fn main() { let x = 1; } };
+
+        let temp = tempfile::tempdir().unwrap();
+        let dir = write_debug_output(temp.path(), &choreography, &local_types, &generated).unwrap();
+        assert_eq!(dir, temp.path().join("Auction"));
+
+        let grammar = std::fs::read_to_string(dir.join("grammar.pest")).unwrap();
+        assert!(grammar.contains("choreography"));
+
+        let ast: Value =
+            serde_json::from_str(&std::fs::read_to_string(dir.join("ast.json")).unwrap()).unwrap();
+        assert_eq!(ast["name"], "Auction");
+        assert_eq!(ast["protocol"][0]["kind"], "send");
+        assert_eq!(ast["protocol"][0]["message"]["payload"], "u64");
+        assert_eq!(ast["protocol"][1]["branches"][1]["label"], "reject");
+
+        let seller = std::fs::read_to_string(dir.join("Seller.txt")).unwrap();
+        assert!(seller.starts_with("receive Bid(u64) from Bidder"));
+        assert!(dir.join("Bidder.txt").exists());
+
+        assert_eq!(
+            std::fs::read_to_string(dir.join("generated.rs")).unwrap(),
+            "pub struct Bid (pub u64);\nfn main () {\n    let x = 1;\n}\n"
+        );
+    }
+}
@@ -0,0 +1,617 @@
+//! Source-mapped diagnostics
+//!
+//! Parse, validation and projection errors are turned into a [`Diagnostic`]
+//! carrying an error code, the location of the offending statement, labeled
+//! source snippets and a suggested fix. A diagnostic renders as a
+//! `path:line:column` report with the source underlined for command line
+//! tools, or becomes a `syn::Error` spanning the statement inside the string
+//! literal a macro was invoked with.
+//!
+//! Error codes are stable: `RA00xx` for parse errors, `RA01xx` for
+//! validation errors and `RA02xx` for projection errors.
+
+use super::parser::{ErrorSpan, ParseError};
+use super::projection::{LocatedProjectionError, ProjectionError};
+use crate::ast::{Choreography, Protocol, Span, ValidationError};
+use crate::CompilationError;
+use syn::LitStr;
+
+/// A message attached to a span of the source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// An error located in choreography source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Stable error code such as `RA0003`
+    pub code: Option<&'static str>,
+    /// One-line description of the error
+    pub message: String,
+    /// Location of the offending source, `None` if unknown
+    pub span: Option<Span>,
+    /// Annotated source snippets, the one at `span` being the primary label
+    pub labels: Vec<Label>,
+    /// Further explanation, possibly spanning several lines
+    pub notes: Vec<String>,
+    /// Suggested fix
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    #[must_use]
+    pub fn new(message: impl Into<String>, span: Option<Span>) -> Self {
+        Self {
+            code: None,
+            message: message.into(),
+            span,
+            labels: Vec::new(),
+            notes: Vec::new(),
+            help: None,
+        }
+    }
+
+    #[must_use]
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    #[must_use]
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    #[must_use]
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    #[must_use]
+    pub fn with_help(mut self, help: Option<String>) -> Self {
+        self.help = help;
+        self
+    }
+
+    /// Label the primary span, if known
+    fn with_primary_label(self, message: &str) -> Self {
+        match self.span {
+            Some(span) => self.with_label(span, message),
+            None => self,
+        }
+    }
+
+    /// Diagnostic for an error raised while parsing `source`
+    #[must_use]
+    pub fn from_parse_error(error: &ParseError, source: &str) -> Self {
+        let diagnostic = if let ParseError::Pest(error) = error {
+            let (start, end) = match error.location {
+                pest::error::InputLocation::Pos(pos) => (pos, pos + 1),
+                pest::error::InputLocation::Span(span) => span,
+            };
+            let message = error.variant.message().into_owned();
+            Self::new("Syntax error", Some(Span::from_offsets(source, start, end)))
+                .with_primary_label(&message)
+        } else {
+            // Located errors render as `\n<message>\n  --> ...`
+            let rendered = error.to_string();
+            let message = rendered
+                .lines()
+                .find(|line| !line.trim().is_empty())
+                .unwrap_or_default()
+                .to_string();
+            let diagnostic = Self::new(message, error_span(error).map(|s| to_span(s, source)));
+            match error {
+                ParseError::UndefinedRole { .. } => {
+                    diagnostic.with_primary_label("not declared in `roles`")
+                }
+                ParseError::UndefinedProtocol { .. } => {
+                    diagnostic.with_primary_label("no protocol with this name")
+                }
+                _ => diagnostic,
+            }
+        };
+        diagnostic
+            .with_code(error.code())
+            .with_help(error.get_suggestion())
+    }
+
+    /// Diagnostic for an error raised while validating `choreography`
+    ///
+    /// Validation errors carry no location, so the first statement involving
+    /// the offending role is highlighted.
+    #[must_use]
+    pub fn from_validation_error(error: &ValidationError, choreography: &Choreography) -> Self {
+        let (span, label, help) = match error {
+            ValidationError::UndefinedRole(role) => (
+                find_statement(&choreography.protocol, &|p| involves(p, role)),
+                "not declared in `roles`",
+                closest_match(role, choreography.roles.iter().map(|r| r.name.to_string()))
+                    .map(|name| format!("Did you mean '{name}'?")),
+            ),
+            ValidationError::InvalidChoice(role) => (
+                find_statement(
+                    &choreography.protocol,
+                    &|p| matches!(p, Protocol::Choice { role: chooser, .. } if chooser.name == role),
+                ),
+                "a branch does not start with a message from the choosing role",
+                Some(format!("start every branch with a message sent by {role}")),
+            ),
+            ValidationError::UnusedRole(role) => (
+                None,
+                "",
+                Some(format!("remove {role} from `roles`, or give it a message")),
+            ),
+            ValidationError::UnusedMessage(message) => (
+                choreography
+                    .messages
+                    .iter()
+                    .find(|(name, _)| name == message)
+                    .map(|(_, span)| *span),
+                "never sent",
+                Some(format!(
+                    "send {message} somewhere, or remove it from `messages`"
+                )),
+            ),
+            ValidationError::OrphanMessage { message, from, to } => (
+                find_statement(&choreography.protocol, &|p| sends(p, from, to, message)),
+                "never received",
+                Some(format!(
+                    "{to} follows another branch of the enclosing choice: have it receive the same \
+                     message from {from} in every branch, or tell it the branch first"
+                )),
+            ),
+            ValidationError::AtomicCommit { atomic, .. } => (
+                find_statement(&choreography.protocol, &|p| p.atomic() == Some(atomic)),
+                "not a safe two-phase commit",
+                Some("write the commit as an `atomic` block".to_string()),
+            ),
+            _ => (None, "", None),
+        };
+        Self::new(error.to_string(), span)
+            .with_code(error.code())
+            .with_primary_label(label)
+            .with_help(help)
+    }
+
+    #[must_use]
+    pub fn from_projection_error(error: &LocatedProjectionError) -> Self {
+        let mut diagnostic = Self::new(
+            error.error.to_string(),
+            Some(error.span).filter(Span::is_known),
+        )
+        .with_code(error.error.code())
+        .with_primary_label("cannot be projected")
+        .with_help(error.error.help());
+        if let ProjectionError::IndistinguishableBranches { role, branches, .. } = &error.error {
+            for branch in branches.iter().filter(|branch| branch.span.is_known()) {
+                diagnostic =
+                    diagnostic.with_label(branch.span, format!("{role} {}", branch.action));
+            }
+        }
+        match error.error.explain() {
+            Some(explanation) => diagnostic.with_note(explanation),
+            None => diagnostic,
+        }
+    }
+
+    /// Diagnostic for an error raised while compiling `source`
+    #[must_use]
+    pub fn from_compilation_error(error: &CompilationError, source: &str) -> Self {
+        match error {
+            CompilationError::ParseError(error) => Self::from_parse_error(error, source),
+            CompilationError::ProjectionError(error) => Self::from_projection_error(error),
+            other => Self::new(other.to_string(), None),
+        }
+    }
+
+    /// Render as `error[code]: <message>` followed by the labeled source
+    /// lines, notes and the suggested fix
+    #[must_use]
+    pub fn render(&self, path: &str, source: &str) -> String {
+        let mut output = match self.code {
+            Some(code) => format!("error[{code}]: {}\n", self.message),
+            None => format!("error: {}\n", self.message),
+        };
+
+        let mut markers: Vec<(Span, char, &str)> = self
+            .labels
+            .iter()
+            .map(|label| {
+                let marker = if Some(label.span) == self.span {
+                    '^'
+                } else {
+                    '-'
+                };
+                (label.span, marker, label.message.as_str())
+            })
+            .collect();
+        if let Some(span) = self.span {
+            if !markers.iter().any(|(s, ..)| *s == span) {
+                markers.push((span, '^', ""));
+            }
+        }
+        markers.sort_by_key(|(span, ..)| (span.line, span.column));
+
+        let width = markers
+            .iter()
+            .map(|(span, ..)| span.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let gutter = " ".repeat(width);
+        match self.span {
+            Some(span) => output.push_str(&format!(
+                "{gutter}--> {path}:{}:{}\n",
+                span.line, span.column
+            )),
+            None => output.push_str(&format!("{gutter}--> {path}\n")),
+        }
+
+        if !markers.is_empty() {
+            output.push_str(&format!("{gutter} |\n"));
+        }
+        let mut previous_line = 0;
+        for (span, marker, message) in &markers {
+            let line = source.lines().nth(span.line - 1).unwrap_or_default();
+            if span.line != previous_line {
+                output.push_str(&format!("{:>width$} | {line}\n", span.line));
+                previous_line = span.line;
+            }
+            let line_rest = line.chars().count().saturating_sub(span.column - 1);
+            let length = source[span.start..span.end]
+                .chars()
+                .take_while(|c| *c != '\n')
+                .count()
+                .clamp(1, line_rest.max(1));
+            let underline = format!(
+                "{}{}",
+                " ".repeat(span.column - 1),
+                marker.to_string().repeat(length)
+            );
+            let text = format!("{underline} {message}");
+            output.push_str(&format!("{gutter} | {}\n", text.trim_end()));
+        }
+
+        if !self.notes.is_empty() || self.help.is_some() {
+            output.push_str(&format!("{gutter} |\n"));
+        }
+        for note in &self.notes {
+            let indent = format!("\n{gutter}         ");
+            output.push_str(&format!(
+                "{gutter} = note: {}\n",
+                note.replace('\n', &indent)
+            ));
+        }
+        if let Some(help) = &self.help {
+            output.push_str(&format!("{gutter} = help: {help}\n"));
+        }
+        output
+    }
+
+    /// Error spanning the offending source inside `literal`
+    ///
+    /// Points at the statement itself when the compiler can resolve spans
+    /// inside a literal, and at the whole literal otherwise. The line and
+    /// column are part of the message either way.
+    #[must_use]
+    pub fn to_syn_error(&self, literal: &LitStr) -> syn::Error {
+        let mut message = match self.code {
+            Some(code) => format!("[{code}] {}", self.message),
+            None => self.message.clone(),
+        };
+        let target = match self.span {
+            Some(span) => {
+                message.push_str(&format!(
+                    " (line {}, column {} of the choreography)",
+                    span.line, span.column
+                ));
+                literal_subspan(literal, span).unwrap_or_else(|| literal.span())
+            }
+            None => literal.span(),
+        };
+        for note in &self.notes {
+            message.push_str(&format!("\nnote: {note}"));
+        }
+        if let Some(help) = &self.help {
+            message.push_str(&format!("\nhelp: {help}"));
+        }
+        syn::Error::new(target, message)
+    }
+}
+
+/// The candidate closest to `name` by edit distance, if close enough to be
+/// a likely typo
+pub fn closest_match<S: AsRef<str>>(
+    name: &str,
+    candidates: impl IntoIterator<Item = S>,
+) -> Option<S> {
+    let name = name.to_lowercase();
+    let threshold = (name.chars().count() / 3).max(1);
+    candidates
+        .into_iter()
+        .map(|candidate| {
+            let distance = edit_distance(&name, &candidate.as_ref().to_lowercase());
+            (distance, candidate)
+        })
+        .filter(|(distance, _)| *distance <= threshold)
+        .min_by(|(a, x), (b, y)| a.cmp(b).then_with(|| x.as_ref().cmp(y.as_ref())))
+        .map(|(_, candidate)| candidate)
+}
+
+/// Edit distance between `a` and `b` counting insertions, deletions,
+/// substitutions and swaps of adjacent characters
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = Vec::new();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for i in 1..=a.len() {
+        let mut next = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            next[j] = (row[j] + 1).min(next[j - 1] + 1).min(row[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                next[j] = next[j].min(previous[j - 2] + 1);
+            }
+        }
+        previous = std::mem::replace(&mut row, next);
+    }
+    row[b.len()]
+}
+
+/// Whether `protocol` is a statement naming the role `name` directly
+fn involves(protocol: &Protocol, name: &str) -> bool {
+    match protocol {
+        Protocol::Send { from, to, .. } => from.name == name || to.name == name,
+        Protocol::Broadcast { from, to_all, .. } => {
+            from.name == name || to_all.iter().any(|r| r.name == name)
+        }
+        Protocol::Choice { role, .. } => role.name == name,
+        _ => false,
+    }
+}
+
+/// Whether `protocol` is a send of `message` from `from` to `to`
+fn sends(protocol: &Protocol, from: &str, to: &str, message: &str) -> bool {
+    match protocol {
+        Protocol::Send {
+            from: sender,
+            to: receiver,
+            message: sent,
+            ..
+        } => sender.name == from && receiver.name == to && sent.name == message,
+        Protocol::Broadcast {
+            from: sender,
+            to_all,
+            message: sent,
+            ..
+        } => sender.name == from && to_all.iter().any(|r| r.name == to) && sent.name == message,
+        _ => false,
+    }
+}
+
+/// Span of the first statement of `protocol` matching `matches`
+fn find_statement(protocol: &Protocol, matches: &dyn Fn(&Protocol) -> bool) -> Option<Span> {
+    if matches(protocol) && protocol.span().is_known() {
+        return Some(protocol.span());
+    }
+    match protocol {
+        Protocol::Send { continuation, .. }
+        | Protocol::Broadcast { continuation, .. }
+        | Protocol::Extension { continuation, .. } => find_statement(continuation, matches),
+        Protocol::Choice { branches, .. } => branches
+            .iter()
+            .find_map(|b| find_statement(&b.protocol, matches)),
+        Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => find_statement(body, matches),
+        Protocol::Parallel { protocols, .. } => {
+            protocols.iter().find_map(|p| find_statement(p, matches))
+        }
+        Protocol::Var(_) | Protocol::End => None,
+    }
+}
+fn error_span(error: &ParseError) -> Option<&ErrorSpan> {
+    match error {
+        ParseError::Syntax { span, .. }
+        | ParseError::UndefinedRole { span, .. }
+        | ParseError::DuplicateRole { span, .. }
+        | ParseError::InvalidMessage { span, .. }
+        | ParseError::InvalidCondition { span, .. }
+        | ParseError::UndefinedProtocol { span, .. }
+        | ParseError::DuplicateProtocol { span, .. }
+        | ParseError::InvalidNamespace { span, .. }
+        | ParseError::InvalidAnnotation { span, .. }
+        | ParseError::DynamicRoleError { span, .. }
+        | ParseError::NamespaceConflict { span, .. }
+        | ParseError::RoleValidationError { span, .. }
+        | ParseError::AnnotationSyntaxError { span, .. }
+        | ParseError::RoleOverflowError { span, .. } => Some(span),
+        ParseError::Pest(_) | ParseError::EmptyChoreography | ParseError::GrammarComposition(_) => {
+            None
+        }
+    }
+}
+
+/// Byte offsets of a line/column `ErrorSpan`, whose columns count characters
+fn to_span(span: &ErrorSpan, source: &str) -> Span {
+    let offset = |line: usize, column: usize| {
+        let line_start: usize = source
+            .split_inclusive('\n')
+            .take(line.saturating_sub(1))
+            .map(str::len)
+            .sum();
+        let rest = &source[line_start.min(source.len())..];
+        line_start
+            + rest
+                .char_indices()
+                .nth(column.saturating_sub(1))
+                .map_or(rest.len(), |(i, _)| i)
+    };
+    let start = offset(span.line, span.column);
+    let end = offset(span.line_end, span.column_end).max(start);
+    Span::from_offsets(source, start, end)
+}
+
+/// Span of `span` inside the tokens of `literal`
+///
+/// Offsets into the literal value map directly onto its tokens only for raw
+/// strings and strings without escapes.
+fn literal_subspan(literal: &LitStr, span: Span) -> Option<proc_macro2::Span> {
+    let token = literal.token();
+    let text = token.to_string();
+    let prefix = text.find('"')? + 1;
+    let is_raw = text.starts_with('r');
+    if !is_raw && text.get(prefix..text.len() - 1)? != literal.value() {
+        return None;
+    }
+    token.subspan(prefix + span.start..prefix + span.end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parser::parse_choreography_str;
+    use crate::compiler::projection::project_located;
+    use crate::ExtensionRegistry;
+
+    #[test]
+    fn test_parse_error_location() {
+        let source = "choreography Demo {\n    roles: Alice, Bob\n    Alice -> Carol: Hello\n}\n";
+        let error = parse_choreography_str(source).unwrap_err();
+        let diagnostic = Diagnostic::from_parse_error(&error, source);
+        assert_eq!(diagnostic.message, "Undefined role 'Carol'");
+        let span = diagnostic.span.unwrap();
+        assert_eq!((span.line, span.column), (3, 14));
+        assert_eq!(&source[span.start..span.end], "Carol");
+
+        assert_eq!(diagnostic.code, Some("RA0003"));
+        assert_eq!(
+            diagnostic.render("demo.choreo", source),
+            "error[RA0003]: Undefined role 'Carol'\n\
+             \x20--> demo.choreo:3:14\n\
+             \x20 |\n\
+             3 |     Alice -> Carol: Hello\n\
+             \x20 |              ^^^^^ not declared in `roles`\n\
+             \x20 |\n\
+             \x20 = help: Add 'Carol' to the roles declaration, or check for typos\n"
+        );
+    }
+
+    #[test]
+    fn test_undeclared_role_suggestion() {
+        let source = "choreography Demo {\n    roles: Alice, Bob\n    Alice -> Bbo: Hello\n}\n";
+        let error = parse_choreography_str(source).unwrap_err();
+        let diagnostic = Diagnostic::from_parse_error(&error, source);
+        assert_eq!(diagnostic.help.as_deref(), Some("Did you mean 'Bob'?"));
+        assert!(diagnostic
+            .render("demo.choreo", source)
+            .ends_with("  = help: Did you mean 'Bob'?\n"));
+    }
+
+    #[test]
+    fn test_validation_error_location() {
+        let source = "choreography Demo {\n    roles: Alice, Bob\n    Alice -> Bob: Hello\n}\n";
+        let mut choreography = parse_choreography_str(source).unwrap();
+        choreography.roles.pop();
+        let error = choreography.validate().unwrap_err();
+        let diagnostic = Diagnostic::from_validation_error(&error, &choreography);
+        assert_eq!(diagnostic.code, Some("RA0101"));
+        assert_eq!(diagnostic.span.unwrap().line, 3);
+    }
+
+    #[test]
+    fn test_closest_match() {
+        let roles = ["Client", "Server", "Monitor"];
+        assert_eq!(closest_match("Sever", roles), Some("Server"));
+        assert_eq!(closest_match("client", roles), Some("Client"));
+        assert_eq!(closest_match("Database", roles), None);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("Bbo", "Bob"), 1);
+    }
+
+    #[test]
+    fn test_pest_error_location() {
+        let source = "choreography Demo {\n    roles: Alice, Bob\n    Alice -> : Hello\n}\n";
+        let error = parse_choreography_str(source).unwrap_err();
+        let span = Diagnostic::from_parse_error(&error, source).span.unwrap();
+        assert_eq!(span.line, 3);
+    }
+
+    #[test]
+    fn test_projection_error_location() {
+        let source =
+            "choreography Demo {\n    roles: Alice, Bob, Carol\n    Alice -> Bob: Hello\n    \
+                      [@guard_capability = \"admin\", @guard_role = \"Carol\"]\n    \
+                      Bob -> Alice: Reply\n}\n";
+        let choreography = parse_choreography_str(source).unwrap();
+        let error = project_located(
+            &choreography,
+            &choreography.roles[0],
+            &ExtensionRegistry::new(),
+        )
+        .unwrap_err();
+        let diagnostic = Diagnostic::from_projection_error(&error);
+        assert_eq!(diagnostic.code, Some("RA0209"));
+        assert!(diagnostic.help.unwrap().contains("Carol takes part in"));
+        let span = diagnostic.span.unwrap();
+        assert_eq!(span.line, 5);
+        assert_eq!(&source[span.start..span.end], "Bob -> Alice: Reply");
+    }
+
+    #[test]
+    fn test_projection_error_explanation() {
+        let source = "choreography Demo {\n    roles: Alice, Bob, Carol\n    choice Alice {\n        \
+                      yes: {\n            Alice -> Bob: Accept\n            Bob -> Carol: Notify\n        \
+                      }\n        no: {\n            Alice -> Bob: Reject\n        }\n    }\n}\n";
+        let choreography = parse_choreography_str(source).unwrap();
+        let error = project_located(
+            &choreography,
+            &choreography.roles[2],
+            &ExtensionRegistry::new(),
+        )
+        .unwrap_err();
+        let diagnostic = Diagnostic::from_projection_error(&error);
+        assert_eq!(diagnostic.code, Some("RA0210"));
+        assert_eq!(diagnostic.span.unwrap().line, 3);
+        assert_eq!(
+            diagnostic.labels[1].message,
+            "Carol receives Notify from Bob"
+        );
+        assert_eq!(diagnostic.labels[2].span.line, 8);
+
+        let rendered = diagnostic.render("demo.choreo", source);
+        assert!(rendered.contains(
+            "  = note: Carol does not take part in the choice, but acts differently per branch:\n\
+             \x20           in branch `yes`, Carol receives Notify from Bob\n"
+        ));
+        assert!(rendered.contains("  = help: have Alice send a message to Carol"));
+    }
+
+    #[test]
+    fn test_syn_error_mentions_location() {
+        let literal: LitStr = syn::parse_str("r#\"Alice -> Carol\"#").unwrap();
+        let diagnostic = Diagnostic::new(
+            "Undefined role 'Carol'",
+            Some(Span::from_offsets(&literal.value(), 9, 14)),
+        )
+        .with_code("RA0003")
+        .with_help(Some("Did you mean 'Alice'?".to_string()));
+        assert_eq!(
+            diagnostic.to_syn_error(&literal).to_string(),
+            "[RA0003] Undefined role 'Carol' (line 1, column 10 of the choreography)\n\
+             help: Did you mean 'Alice'?"
+        );
+        assert_eq!(
+            Diagnostic::new("empty", None)
+                .to_syn_error(&literal)
+                .to_string(),
+            "empty"
+        );
+    }
+}
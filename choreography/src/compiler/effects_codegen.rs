@@ -3,10 +3,27 @@
 // This module generates protocol implementations that build
 // effect programs using a free algebra approach.
 
-use crate::ast::{Choreography, Condition, MessageType, Protocol, Role};
+use crate::ast::policy::policy_steps;
+use crate::ast::{
+    host_expr, Choreography, Condition, MessageType, Permission, PolicyAction, Protocol, Role,
+    RoleState, CONFIDENTIAL, DEFAULT_MESSAGE,
+};
+use crate::compiler::codegen::{doc_attributes, generate_http_routes, generate_monitor_spec};
+use crate::compiler::flow_cost::{analyze_flow_cost, send_cost};
+use crate::compiler::handler_codegen::{
+    generate_blocking_conformance_tests, generate_blocking_fuzz_entry_points,
+    generate_blocking_handler_api, generate_blocking_test_harness, generate_conformance_tests,
+    generate_fuzz_entry_points, generate_handler_api, generate_test_harness, CodegenOptions,
+    CodegenStyle,
+};
+use crate::compiler::projection::statement_guard;
+use crate::extensions::{CodegenContext, CodegenHook, ExtensionRegistry, MessageSite};
+use crate::runtime::guard::GUARD_CAPABILITY;
+use crate::runtime::journal::{parse_retention, PERSONAL_DATA, RETAIN};
 use proc_macro2::TokenStream;
-use quote::{format_ident, quote};
-use std::collections::HashSet;
+use quote::{format_ident, quote, ToTokens};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
 /// Generate annotation-aware effect metadata for a protocol node
 fn generate_effect_metadata_from_annotations(protocol: &Protocol, _role: &Role) -> TokenStream {
@@ -23,12 +40,36 @@ fn generate_effect_metadata_from_annotations(protocol: &Protocol, _role: &Role)
                 "priority" => {
                     quote! { .with_priority(#value.parse().unwrap_or(0)) }
                 }
-                "timeout" => {
-                    quote! { .with_timeout(std::time::Duration::from_secs(#value.parse().unwrap_or(30))) }
-                }
+                "timeout" => match host_expr_tokens(value) {
+                    // A host expression is a `Duration`
+                    Some(duration) => quote! { .with_timeout(#duration) },
+                    None => {
+                        quote! { .with_timeout(std::time::Duration::from_secs(#value.parse().unwrap_or(30))) }
+                    }
+                },
                 "retry" => {
                     quote! { .with_retry(#value.parse().unwrap_or(1)) }
                 }
+                "journal_facts" | "retain" => {
+                    // Recorded by the `Journaled` middleware, see `generate_journal_points`
+                    quote! {}
+                }
+                "flow_cost" => {
+                    // Charged by the `Metered` middleware, see `generate_flow_charges`
+                    quote! {}
+                }
+                "doc" => {
+                    // Emitted as doc comments on the generated items
+                    quote! {}
+                }
+                DEFAULT_MESSAGE => {
+                    // Spliced into the send, see `message_value`
+                    quote! {}
+                }
+                "guard_capability" | "guard_role" => {
+                    // Enforced by the `Guarded` middleware, see `generate_guard_points`
+                    quote! {}
+                }
                 _ => {
                     // Generic annotation - add as metadata
                     quote! { .with_annotation(#key, #value) }
@@ -40,20 +81,112 @@ fn generate_effect_metadata_from_annotations(protocol: &Protocol, _role: &Role)
     quote! { #(#metadata_items)* }
 }
 
+/// Tokens of the `#{ ... }` host expression in `value`, or a
+/// `compile_error!` if it does not hold an expression
+pub(crate) fn host_expr_tokens(value: &str) -> Option<TokenStream> {
+    let expr = host_expr(value)?;
+    Some(match syn::parse_str::<syn::Expr>(expr) {
+        Ok(expr) => expr.to_token_stream(),
+        Err(err) => {
+            let message = format!("Invalid host expression `{expr}`: {err}");
+            quote! { compile_error!(#message) }
+        }
+    })
+}
+
+/// The message a send of `message` carries in generated code: its
+/// `@default` host expression, or `Message::default()`
+fn message_value(protocol: &Protocol, message: &MessageType) -> TokenStream {
+    let message_type = &message.name;
+    protocol
+        .get_annotations()
+        .get(DEFAULT_MESSAGE)
+        .and_then(|value| host_expr_tokens(value))
+        .unwrap_or_else(|| quote! { #message_type::default() })
+}
+
 /// Generate effect-based protocol implementation
 #[must_use]
 pub fn generate_effects_protocol(choreography: &Choreography) -> TokenStream {
+    generate_effects_protocol_with_hooks(choreography, &[])
+}
+
+/// Generate effect-based protocol implementation, letting the code generation
+/// hooks of `registry` add items and rewrite sends and receives
+#[must_use]
+pub fn generate_effects_protocol_with_extensions(
+    choreography: &Choreography,
+    registry: &ExtensionRegistry,
+) -> TokenStream {
+    generate_effects_protocol_with_hooks(choreography, registry.codegen_hooks())
+}
+
+fn generate_effects_protocol_with_hooks(
+    choreography: &Choreography,
+    hooks: &[Arc<dyn CodegenHook>],
+) -> TokenStream {
+    let options = match CodegenOptions::from_choreography(choreography) {
+        Ok(options) => options,
+        Err(err) => {
+            let message = err.to_string();
+            return quote! { compile_error!(#message); };
+        }
+    };
     let protocol_name = &choreography.name;
-    let roles = generate_role_enum(&choreography.roles);
+    let choreography_name = protocol_name.to_string();
+    let context = CodegenContext {
+        choreography_name: &choreography_name,
+        roles: &choreography.roles,
+        namespace: choreography.namespace.as_deref(),
+    };
+    if options.sync {
+        return generate_blocking_protocol(choreography, &options, &context, hooks);
+    }
+    let roles = generate_role_enum(choreography);
     let messages = generate_message_types(&choreography.protocol);
-    let role_functions = generate_role_functions(choreography);
-    let endpoint_type = generate_endpoint_type(protocol_name);
+    let http_routes = generate_http_routes(choreography);
+    let role_functions = generate_role_functions(choreography, &context, hooks);
+    let policy_capabilities = generate_policy_capabilities(choreography);
+    let state_types = generate_state_types(choreography);
+    let endpoint_type = generate_endpoint_type(choreography);
+    let hook_items = generate_hook_items(&context, hooks);
+    // Mocks, path-following and fuzzed roles implement the handler traits
+    let handler_api = match options.style {
+        CodegenStyle::States if !options.test_harness && !options.proptest && !options.fuzz => {
+            quote! {}
+        }
+        _ => generate_handler_api(choreography),
+    };
+    let test_harness = if options.test_harness {
+        generate_test_harness(choreography)
+    } else {
+        quote! {}
+    };
+    let conformance = if options.proptest {
+        generate_conformance_tests(choreography)
+    } else {
+        quote! {}
+    };
+    let fuzz = if options.fuzz {
+        generate_fuzz_entry_points(choreography)
+    } else {
+        quote! {}
+    };
 
     quote! {
         use rumpsteak_aura_choreography::{
             ChoreoHandler, Result, Label, Program, Effect,
-            interpret, InterpretResult, ProgramMessage
+            interpret, InterpretResult, ProgramMessage,
+            Cancellable, SessionHandle, Traced,
+            Instrumented, SessionMetrics, Journaled, Guarded, Metered, Debugged,
+            SessionResult, session_result
         };
+        use rumpsteak_aura_choreography::runtime::debugger::Debugger;
+        use rumpsteak_aura_choreography::runtime::flow::{FlowCharge, FlowMeter};
+        use rumpsteak_aura_choreography::runtime::guard::{CapabilityProvider, GuardPoint};
+        use rumpsteak_aura_choreography::runtime::introspect::{SessionProbe, SessionState};
+        use rumpsteak_aura_choreography::runtime::journal::{Journal, JournalPoint};
+        use rumpsteak_aura_choreography::runtime::monitor::{ActionKind, MonitorSpec};
         use serde::{Serialize, Deserialize};
 
         // Common message trait for this choreography
@@ -67,33 +200,207 @@ pub fn generate_effects_protocol(choreography: &Choreography) -> TokenStream {
 
         #roles
 
+        #state_types
+
         #endpoint_type
 
         #messages
 
+        #http_routes
+
         #role_functions
+
+        #policy_capabilities
+
+        #handler_api
+
+        #test_harness
+
+        #conformance
+
+        #fuzz
+
+        #hook_items
     }
 }
 
-fn generate_role_enum(roles: &[Role]) -> TokenStream {
-    let role_names: Vec<_> = roles.iter().map(|r| &r.name).collect();
+/// Blocking protocol implementation for `@codegen(sync)`
+///
+/// Programs can only be interpreted asynchronously, so the roles are driven
+/// through the blocking handler API alone.
+fn generate_blocking_protocol(
+    choreography: &Choreography,
+    options: &CodegenOptions,
+    context: &CodegenContext,
+    hooks: &[Arc<dyn CodegenHook>],
+) -> TokenStream {
+    let role_names: Vec<_> = choreography.roles.iter().map(|r| &r.name).collect();
+    let messages = generate_message_types(&choreography.protocol);
+    let state_types = generate_state_types(choreography);
+    let handler_api = generate_blocking_handler_api(choreography);
+    let test_harness = if options.test_harness {
+        generate_blocking_test_harness(choreography)
+    } else {
+        quote! {}
+    };
+    let conformance = if options.proptest {
+        generate_blocking_conformance_tests(choreography)
+    } else {
+        quote! {}
+    };
+    let fuzz = if options.fuzz {
+        generate_blocking_fuzz_entry_points(choreography)
+    } else {
+        quote! {}
+    };
+    let hook_items = generate_hook_items(context, hooks);
 
     quote! {
+        use rumpsteak_aura_choreography::Result;
+        use rumpsteak_aura_choreography::runtime::blocking::{BlockingEndpoint, Transport};
+        use serde::{Serialize, Deserialize};
+
         #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
         pub enum Role {
             #(#role_names),*
         }
 
+        /// Every role of the choreography
+        pub const ROLES: &[Role] = &[#(Role::#role_names),*];
+
+        #messages
+
+        #state_types
+
+        #handler_api
+
+        #test_harness
+
+        #conformance
+
+        #fuzz
+
+        #hook_items
+    }
+}
+
+/// Items contributed by code generation hooks
+pub(crate) fn generate_hook_items(
+    context: &CodegenContext,
+    hooks: &[Arc<dyn CodegenHook>],
+) -> TokenStream {
+    let items = hooks.iter().map(|hook| hook.module_items(context));
+    quote! { #(#items)* }
+}
+
+/// Apply the `wrap_send` hooks to a generated send
+fn wrap_send(
+    hooks: &[Arc<dyn CodegenHook>],
+    site: &MessageSite,
+    effect: TokenStream,
+) -> TokenStream {
+    hooks
+        .iter()
+        .fold(effect, |effect, hook| hook.wrap_send(site, effect))
+}
+
+/// Apply the `wrap_recv` hooks to a generated receive
+fn wrap_recv(
+    hooks: &[Arc<dyn CodegenHook>],
+    site: &MessageSite,
+    effect: TokenStream,
+) -> TokenStream {
+    hooks
+        .iter()
+        .fold(effect, |effect, hook| hook.wrap_recv(site, effect))
+}
+
+fn generate_role_enum(choreography: &Choreography) -> TokenStream {
+    let roles = &choreography.roles;
+    let role_names: Vec<_> = roles.iter().map(|r| &r.name).collect();
+    let role_docs = roles
+        .iter()
+        .map(|r| doc_attributes(choreography.role_doc(&r.name)));
+
+    quote! {
+        #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+        pub enum Role {
+            #(#role_docs #role_names),*
+        }
+
+        /// Every role of the choreography
+        pub const ROLES: &[Role] = &[#(Role::#role_names),*];
+
         impl rumpsteak::effects::RoleId for Role {}
     }
 }
 
-fn generate_endpoint_type(protocol_name: &proc_macro2::Ident) -> TokenStream {
-    let ep_name = format_ident!("{}Endpoint", protocol_name);
+/// `<Role>State` structs of the `state at Role { ... }` declarations
+fn generate_state_types(choreography: &Choreography) -> TokenStream {
+    let structs = choreography.state.iter().map(|state| {
+        let name = state.struct_name();
+        let doc = format!(
+            "Local state of the {} role of {}",
+            state.role, choreography.name
+        );
+        let field_names = state.fields.iter().map(|field| &field.name);
+        let field_types = state.fields.iter().map(|field| &field.ty);
+        quote! {
+            #[doc = #doc]
+            #[derive(Clone, Debug, Default)]
+            pub struct #name {
+                #(pub #field_names: #field_types),*
+            }
+        }
+    });
+    quote! { #(#structs)* }
+}
+
+fn generate_endpoint_type(choreography: &Choreography) -> TokenStream {
+    let ep_name = format_ident!("{}Endpoint", choreography.name);
+    let state_docs = choreography
+        .state
+        .iter()
+        .map(|state| format!("Local state of the {} role", state.role));
+    let state_fields: Vec<_> = choreography
+        .state
+        .iter()
+        .map(RoleState::endpoint_field)
+        .collect();
+    let state_types = choreography.state.iter().map(RoleState::struct_name);
 
     quote! {
         pub struct #ep_name {
-            // Protocol-specific endpoint state
+            /// Progress of the session run on this endpoint, fed by the
+            /// `run_<role>_observed` functions
+            pub session: SessionProbe,
+            #(
+                #[doc = #state_docs]
+                pub #state_fields: #state_types,
+            )*
+        }
+
+        impl #ep_name {
+            pub fn new() -> Self {
+                Self {
+                    session: SessionProbe::new(),
+                    #(#state_fields: Default::default(),)*
+                }
+            }
+
+            /// Where the session run on this endpoint is, `None` before a
+            /// `run_<role>_observed` function started it
+            ///
+            /// Clone `session` to watch a session while it runs.
+            pub fn session_state(&self) -> Option<SessionState> {
+                self.session.state()
+            }
+        }
+
+        impl Default for #ep_name {
+            fn default() -> Self {
+                Self::new()
+            }
         }
 
         impl rumpsteak::effects::Endpoint for #ep_name {}
@@ -102,9 +409,10 @@ fn generate_endpoint_type(protocol_name: &proc_macro2::Ident) -> TokenStream {
 
 fn generate_message_types(protocol: &Protocol) -> TokenStream {
     let mut message_types = HashSet::new();
+    let mut docs = HashMap::new();
 
     // Collect unique message types from protocol
-    collect_message_types(protocol, &mut message_types);
+    collect_message_types(protocol, &mut message_types, &mut docs);
 
     let message_structs: Vec<_> = message_types
         .into_iter()
@@ -116,7 +424,10 @@ fn generate_message_types(protocol: &Protocol) -> TokenStream {
                 infer_content_type(&msg_type.name.to_string())
             };
 
+            let doc = doc_attributes(docs.get(&type_name.to_string()).map(String::as_str));
+
             quote! {
+                #doc
                 #[derive(Clone, Debug, Serialize, Deserialize)]
                 pub struct #type_name(pub #content_type);
             }
@@ -128,7 +439,19 @@ fn generate_message_types(protocol: &Protocol) -> TokenStream {
     }
 }
 
-fn collect_message_types(protocol: &Protocol, message_types: &mut HashSet<MessageType>) {
+/// Collect the messages of `protocol`, with the doc comment of the first
+/// statement sending each one that has a doc comment
+fn collect_message_types(
+    protocol: &Protocol,
+    message_types: &mut HashSet<MessageType>,
+    docs: &mut HashMap<String, String>,
+) {
+    if let (Protocol::Send { message, .. } | Protocol::Broadcast { message, .. }, Some(doc)) =
+        (protocol, protocol.doc())
+    {
+        docs.entry(message.name.to_string())
+            .or_insert_with(|| doc.to_string());
+    }
     match protocol {
         Protocol::Send {
             message,
@@ -136,7 +459,7 @@ fn collect_message_types(protocol: &Protocol, message_types: &mut HashSet<Messag
             ..
         } => {
             message_types.insert(message.clone());
-            collect_message_types(continuation, message_types);
+            collect_message_types(continuation, message_types, docs);
         }
         Protocol::Broadcast {
             message,
@@ -144,33 +467,42 @@ fn collect_message_types(protocol: &Protocol, message_types: &mut HashSet<Messag
             ..
         } => {
             message_types.insert(message.clone());
-            collect_message_types(continuation, message_types);
+            collect_message_types(continuation, message_types, docs);
         }
         Protocol::Choice { branches, .. } => {
             for branch in branches {
-                collect_message_types(&branch.protocol, message_types);
+                collect_message_types(&branch.protocol, message_types, docs);
             }
         }
         Protocol::Loop { body, .. } => {
-            collect_message_types(body, message_types);
+            collect_message_types(body, message_types, docs);
         }
-        Protocol::Parallel { protocols } => {
+        Protocol::Parallel { protocols, .. } => {
             for p in protocols {
-                collect_message_types(p, message_types);
+                collect_message_types(p, message_types, docs);
             }
         }
         Protocol::Rec { body, .. } => {
-            collect_message_types(body, message_types);
+            collect_message_types(body, message_types, docs);
         }
         Protocol::Var(_) | Protocol::End => {}
 
         Protocol::Extension { continuation, .. } => {
-            collect_message_types(continuation, message_types);
+            collect_message_types(continuation, message_types, docs);
         }
     }
 }
 
-fn generate_role_functions(choreography: &Choreography) -> TokenStream {
+fn generate_role_functions(
+    choreography: &Choreography,
+    context: &CodegenContext,
+    hooks: &[Arc<dyn CodegenHook>],
+) -> TokenStream {
+    let flow_costs = analyze_flow_cost(choreography);
+    let extra_bounds: Vec<TokenStream> = hooks
+        .iter()
+        .flat_map(|hook| hook.handler_bounds(context))
+        .collect();
     choreography
         .roles
         .iter()
@@ -178,37 +510,600 @@ fn generate_role_functions(choreography: &Choreography) -> TokenStream {
             let role_name_str = role.name.to_string().to_lowercase();
             let program_fn_name = format_ident!("{}_program", role_name_str);
             let run_fn_name = format_ident!("run_{}", role_name_str);
+            let run_cancellable_fn_name = format_ident!("run_{}_cancellable", role_name_str);
+            let run_traced_fn_name = format_ident!("run_{}_traced", role_name_str);
+            let run_instrumented_fn_name = format_ident!("run_{}_instrumented", role_name_str);
+            let run_journaled_fn_name = format_ident!("run_{}_journaled", role_name_str);
+            let journal_points_name =
+                format_ident!("{}_JOURNAL_POINTS", role.name.to_string().to_uppercase());
+            let journal_points = generate_journal_points(&choreography.protocol, role);
+            let run_guarded_fn_name = format_ident!("run_{}_guarded", role_name_str);
+            let guard_points_name =
+                format_ident!("{}_GUARD_POINTS", role.name.to_string().to_uppercase());
+            let mut guard_points = generate_guard_points(&choreography.protocol, role);
+            guard_points.extend(generate_policy_points(choreography, role));
+            let run_metered_fn_name = format_ident!("run_{}_metered", role_name_str);
+            let run_debugged_fn_name = format_ident!("run_{}_debugged", role_name_str);
+            // Only roles whose local type projects can be followed
+            let observed = choreography.project(role).ok().map(|local_type| {
+                let spec = generate_monitor_spec(role, &local_type);
+                (
+                    format_ident!("run_{}_observed", role_name_str),
+                    format_ident!("{}_MONITOR_SPEC", role.name.to_string().to_uppercase()),
+                    spec,
+                )
+            });
+            let flow_charges_name =
+                format_ident!("{}_FLOW_CHARGES", role.name.to_string().to_uppercase());
+            let max_flow_cost_name =
+                format_ident!("{}_MAX_FLOW_COST", role.name.to_string().to_uppercase());
+            let flow_charges = generate_flow_charges(&choreography.protocol, role);
+            let max_flow_cost = match &flow_costs {
+                Ok(report) => match report.worst_case(&role.name.to_string()).as_bounded() {
+                    Some(cost) => quote! { Some(#cost) },
+                    None => quote! { None },
+                },
+                Err(err) => {
+                    let message = err.to_string();
+                    quote! { compile_error!(#message) }
+                }
+            };
+            let role_ident = &role.name;
+            let role_label = role.name.to_string();
             let protocol_name = &choreography.name;
             let endpoint_type = format_ident!("{}Endpoint", protocol_name);
+            let handler_bound = quote! {
+                ChoreoHandler<Role = Role, Endpoint = #endpoint_type> #(+ #extra_bounds)*
+            };
 
-            let body = generate_role_body(&choreography.protocol, role);
+            let body = generate_role_body(&choreography.protocol, role, hooks);
+            // Guards of a role with local state read it as `state`
+            let (program_attrs, program_params, program_args) =
+                match choreography.role_state(&role.name) {
+                    Some(state) => {
+                        let state_type = state.struct_name();
+                        let field = state.endpoint_field();
+                        (
+                            quote! { #[allow(unused_variables)] },
+                            quote! { state: &#state_type },
+                            quote! { &endpoint.#field },
+                        )
+                    }
+                    None => (quote! {}, quote! {}, quote! {}),
+                };
+            let observed_fn = observed.map(|(run_observed_fn_name, spec_name, spec)| {
+                quote! {
+                    /// Automaton of this role's local type
+                    pub const #spec_name: MonitorSpec = #spec;
+
+                    /// Run the program for this role, following its progress in `endpoint.session`
+                    pub async fn #run_observed_fn_name<H: #handler_bound>(
+                        handler: H,
+                        endpoint: &mut #endpoint_type,
+                    ) -> SessionResult<InterpretResult<Message>> {
+                        static SPEC: MonitorSpec = #spec_name;
+                        endpoint.session.start(&SPEC);
+                        let probe = std::sync::Arc::new(endpoint.session.clone());
+                        let mut handler = Instrumented::new(handler, Role::#role_ident, probe);
+                        let program = #program_fn_name(#program_args);
+                        session_result(#role_label, interpret(&mut handler, endpoint, program).await)
+                    }
+                }
+            });
+            let peers: Vec<_> = choreography
+                .roles
+                .iter()
+                .filter(|r| *r != role)
+                .map(|r| &r.name)
+                .collect();
 
             quote! {
                 /// Generate the choreographic program for this role
-                pub fn #program_fn_name() -> Program<Role, Message> {
+                #program_attrs
+                pub fn #program_fn_name(#program_params) -> Program<Role, Message> {
                     #body
                 }
 
                 /// Run the choreographic program for this role using a handler
-                pub async fn #run_fn_name<H: ChoreoHandler<Role = Role, Endpoint = #endpoint_type>>(
+                pub async fn #run_fn_name<H: #handler_bound>(
                     handler: &mut H,
                     endpoint: &mut #endpoint_type,
-                ) -> Result<InterpretResult<Message>> {
-                    let program = #program_fn_name();
-                    interpret(handler, endpoint, program).await
+                ) -> SessionResult<InterpretResult<Message>> {
+                    let program = #program_fn_name(#program_args);
+                    session_result(#role_label, interpret(handler, endpoint, program).await)
+                }
+
+                /// Run the program for this role until it completes or `session` is cancelled
+                ///
+                /// Peers are sent a cancellation frame, so they must also run cancellable.
+                pub async fn #run_cancellable_fn_name<H: #handler_bound>(
+                    handler: H,
+                    endpoint: &mut #endpoint_type,
+                    session: SessionHandle,
+                ) -> SessionResult<InterpretResult<Message>> {
+                    let mut handler = Cancellable::new(handler, session, vec![#(Role::#peers),*]);
+                    let program = #program_fn_name(#program_args);
+                    session_result(#role_label, interpret(&mut handler, endpoint, program).await)
+                }
+
+                /// Run the program for this role with a span per operation, tagged with `session_id`
+                ///
+                /// Trace context travels in message frames, so peers must also run traced.
+                pub async fn #run_traced_fn_name<H: #handler_bound>(
+                    handler: H,
+                    endpoint: &mut #endpoint_type,
+                    session_id: impl std::fmt::Display,
+                ) -> SessionResult<InterpretResult<Message>> {
+                    let mut handler = Traced::new(handler, Role::#role_ident, session_id);
+                    let program = #program_fn_name(#program_args);
+                    session_result(#role_label, interpret(&mut handler, endpoint, program).await)
+                }
+
+                /// Run the program for this role, reporting the session and every step to `metrics`
+                pub async fn #run_instrumented_fn_name<H: #handler_bound>(
+                    handler: H,
+                    endpoint: &mut #endpoint_type,
+                    metrics: std::sync::Arc<dyn SessionMetrics>,
+                ) -> SessionResult<InterpretResult<Message>> {
+                    let mut handler = Instrumented::new(handler, Role::#role_ident, metrics);
+                    let program = #program_fn_name(#program_args);
+                    handler.start_session();
+                    let result =
+                        session_result(#role_label, interpret(&mut handler, endpoint, program).await);
+                    handler.end_session(&result);
+                    result
+                }
+
+                /// Steps of this role annotated with `journal_facts`
+                pub const #journal_points_name: &[JournalPoint] = &[#(#journal_points),*];
+
+                /// Run the program for this role, writing a journal record at every annotated step
+                pub async fn #run_journaled_fn_name<H: #handler_bound>(
+                    handler: H,
+                    endpoint: &mut #endpoint_type,
+                    journal: Journal,
+                ) -> SessionResult<InterpretResult<Message>> {
+                    let mut handler = Journaled::new(handler, Role::#role_ident, journal, #journal_points_name);
+                    let program = #program_fn_name(#program_args);
+                    session_result(#role_label, interpret(&mut handler, endpoint, program).await)
+                }
+
+                /// Steps of this role guarded by `guard_capability` or the policy
+                pub const #guard_points_name: &[GuardPoint] = &[#(#guard_points),*];
+
+                /// Run the program for this role, refusing guarded steps it lacks the capability for
+                pub async fn #run_guarded_fn_name<H: #handler_bound>(
+                    handler: H,
+                    endpoint: &mut #endpoint_type,
+                    capabilities: std::sync::Arc<dyn CapabilityProvider>,
+                ) -> SessionResult<InterpretResult<Message>> {
+                    let mut handler = Guarded::new(handler, Role::#role_ident, capabilities, #guard_points_name);
+                    let program = #program_fn_name(#program_args);
+                    session_result(#role_label, interpret(&mut handler, endpoint, program).await)
+                }
+
+                /// Sends of this role annotated with `flow_cost`
+                pub const #flow_charges_name: &[FlowCharge] = &[#(#flow_charges),*];
+
+                /// Worst-case flow cost of this role, `None` if unbounded
+                pub const #max_flow_cost_name: Option<u64> = #max_flow_cost;
+
+                /// Run the program for this role, aborting once `meter` cannot cover the next send
+                pub async fn #run_metered_fn_name<H: #handler_bound>(
+                    handler: H,
+                    endpoint: &mut #endpoint_type,
+                    meter: FlowMeter,
+                ) -> SessionResult<InterpretResult<Message>> {
+                    let mut handler = Metered::new(handler, Role::#role_ident, meter, #flow_charges_name);
+                    let program = #program_fn_name(#program_args);
+                    session_result(#role_label, interpret(&mut handler, endpoint, program).await)
+                }
+
+                /// Run the program for this role, halting before each step while `debugger` is paused
+                pub async fn #run_debugged_fn_name<H: #handler_bound>(
+                    handler: H,
+                    endpoint: &mut #endpoint_type,
+                    debugger: Debugger,
+                ) -> SessionResult<InterpretResult<Message>> {
+                    let mut handler = Debugged::new(handler, Role::#role_ident, debugger);
+                    let program = #program_fn_name(#program_args);
+                    session_result(#role_label, interpret(&mut handler, endpoint, program).await)
+                }
+
+                #observed_fn
+            }
+        })
+        .collect()
+}
+
+/// Collect the `journal_facts` steps `role` takes part in as `JournalPoint`s
+fn generate_journal_points(protocol: &Protocol, role: &Role) -> Vec<TokenStream> {
+    let mut points = Vec::new();
+    collect_journal_points(protocol, role, &mut points);
+    points
+}
+
+/// Retention period in milliseconds of the journaled statement `protocol`
+/// sending `label`
+///
+/// Statements carrying personal data, marked `personal_data` or with a
+/// `confidential` payload field, must not be journaled without one.
+fn journal_retention(protocol: &Protocol, label: &str) -> std::result::Result<Option<u64>, String> {
+    let annotations = protocol.get_annotations();
+    match annotations.get(RETAIN) {
+        Some(value) => parse_retention(value)
+            .map(|retain| Some(u64::try_from(retain.as_millis()).unwrap_or(u64::MAX)))
+            .ok_or_else(|| {
+                format!("invalid retention period `{value}` on {label}: expected a count of d, h, m or s")
+            }),
+        None if annotations.contains_key(PERSONAL_DATA)
+            || annotations
+                .keys()
+                .any(|key| key.starts_with(&format!("{CONFIDENTIAL}."))) =>
+        {
+            Err(format!(
+                "{label} carries personal data and is journaled, so it needs a `retain` annotation"
+            ))
+        }
+        None => Ok(None),
+    }
+}
+
+fn collect_journal_points(protocol: &Protocol, role: &Role, points: &mut Vec<TokenStream>) {
+    let point = |action: TokenStream, peer: &Role, message: &crate::ast::MessageType| {
+        let facts = protocol.get_annotation("journal_facts")?;
+        let peer = peer.name.to_string();
+        let label = message.name.to_string();
+        let retain_ms = match journal_retention(protocol, &label) {
+            Ok(Some(retain_ms)) => quote! { Some(#retain_ms) },
+            Ok(None) => quote! { None },
+            Err(message) => return Some(quote! { compile_error!(#message) }),
+        };
+        Some(quote! {
+            JournalPoint { action: ActionKind::#action, peer: #peer, label: #label, facts: #facts, retain_ms: #retain_ms }
+        })
+    };
+
+    match protocol {
+        Protocol::Send {
+            from,
+            to,
+            message,
+            continuation,
+            ..
+        } => {
+            if from == role {
+                points.extend(point(quote! { Send }, to, message));
+            } else if to == role {
+                points.extend(point(quote! { Receive }, from, message));
+            }
+            collect_journal_points(continuation, role, points);
+        }
+        Protocol::Broadcast {
+            from,
+            to_all,
+            message,
+            continuation,
+            ..
+        } => {
+            if from == role {
+                for to in to_all {
+                    points.extend(point(quote! { Send }, to, message));
+                }
+            } else if to_all.contains(role) {
+                points.extend(point(quote! { Receive }, from, message));
+            }
+            collect_journal_points(continuation, role, points);
+        }
+        Protocol::Choice { branches, .. } => {
+            for branch in branches {
+                collect_journal_points(&branch.protocol, role, points);
+            }
+        }
+        Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => {
+            collect_journal_points(body, role, points);
+        }
+        Protocol::Parallel { protocols, .. } => {
+            for p in protocols {
+                collect_journal_points(p, role, points);
+            }
+        }
+        Protocol::Extension { continuation, .. } => {
+            collect_journal_points(continuation, role, points);
+        }
+        Protocol::Var(_) | Protocol::End => {}
+    }
+}
+
+/// Collect the `flow_cost` sends of `role` as `FlowCharge`s
+fn generate_flow_charges(protocol: &Protocol, role: &Role) -> Vec<TokenStream> {
+    let mut charges = Vec::new();
+    collect_flow_charges(protocol, role, &mut charges);
+    charges
+}
+
+fn collect_flow_charges(protocol: &Protocol, role: &Role, charges: &mut Vec<TokenStream>) {
+    let charge = |to: &Role, message: &MessageType| {
+        let peer = to.name.to_string();
+        let label = message.name.to_string();
+        match send_cost(protocol) {
+            Ok(0) => None,
+            Ok(cost) => Some(quote! { FlowCharge { peer: #peer, label: #label, cost: #cost } }),
+            Err(err) => {
+                let message = err.to_string();
+                Some(quote! { compile_error!(#message) })
+            }
+        }
+    };
+
+    match protocol {
+        Protocol::Send {
+            from,
+            to,
+            message,
+            continuation,
+            ..
+        } => {
+            if from == role {
+                charges.extend(charge(to, message));
+            }
+            collect_flow_charges(continuation, role, charges);
+        }
+        Protocol::Broadcast {
+            from,
+            to_all,
+            message,
+            continuation,
+            ..
+        } => {
+            if from == role {
+                charges.extend(to_all.iter().filter_map(|to| charge(to, message)));
+            }
+            collect_flow_charges(continuation, role, charges);
+        }
+        Protocol::Choice { branches, .. } => {
+            for branch in branches {
+                collect_flow_charges(&branch.protocol, role, charges);
+            }
+        }
+        Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => {
+            collect_flow_charges(body, role, charges);
+        }
+        Protocol::Parallel { protocols, .. } => {
+            for p in protocols {
+                collect_flow_charges(p, role, charges);
+            }
+        }
+        Protocol::Extension { continuation, .. } => {
+            collect_flow_charges(continuation, role, charges);
+        }
+        Protocol::Var(_) | Protocol::End => {}
+    }
+}
+
+/// The capabilities the policy grants each role, for `run_<role>_guarded`
+fn generate_policy_capabilities(choreography: &Choreography) -> TokenStream {
+    let Some(policy) = &choreography.policy else {
+        return quote! {};
+    };
+    let grants = choreography.roles.iter().flat_map(|role| {
+        let name = role.name.to_string();
+        Permission::ALL
+            .into_iter()
+            .filter(|permission| policy.permits(&role.name, *permission))
+            .map(move |permission| {
+                let capability = permission.capability();
+                quote! { .grant(#name, #capability) }
+            })
+    });
+    quote! {
+        /// Capabilities granted by the choreography's policy
+        pub fn policy_capabilities() -> rumpsteak_aura_choreography::runtime::guard::CapabilitySet {
+            rumpsteak_aura_choreography::runtime::guard::CapabilitySet::new() #(#grants)*
+        }
+    }
+}
+
+/// Guard the steps of `role` with the policy permission they use, if the
+/// policy names it
+fn generate_policy_points(choreography: &Choreography, role: &Role) -> Vec<TokenStream> {
+    if !choreography
+        .policy
+        .as_ref()
+        .is_some_and(|policy| policy.restricts(&role.name))
+    {
+        return Vec::new();
+    }
+    policy_steps(&choreography.protocol)
+        .into_iter()
+        .filter(|step| step.role == role.name)
+        .flat_map(|step| {
+            let capability = step.permission.capability();
+            match step.action {
+                PolicyAction::Send { to, message } => {
+                    let to = to.to_string();
+                    vec![quote! {
+                        GuardPoint { action: ActionKind::Send, peer: Some(#to), label: Some(#message), capability: #capability }
+                    }]
                 }
+                PolicyAction::Decide { labels } if labels.is_empty() => vec![quote! {
+                    GuardPoint { action: ActionKind::Select, peer: None, label: None, capability: #capability }
+                }],
+                PolicyAction::Decide { labels } => labels
+                    .iter()
+                    .map(|label| {
+                        quote! {
+                            GuardPoint { action: ActionKind::Select, peer: None, label: Some(#label), capability: #capability }
+                        }
+                    })
+                    .collect(),
             }
         })
         .collect()
 }
 
-fn generate_role_body(protocol: &Protocol, role: &Role) -> TokenStream {
-    generate_program_builder(protocol, role)
+/// Collect the `guard_capability` steps `role` must hold a capability for as `GuardPoint`s
+fn generate_guard_points(protocol: &Protocol, role: &Role) -> Vec<TokenStream> {
+    let mut points = Vec::new();
+    collect_guard_points(protocol, role, &mut points);
+    points
+}
+
+fn collect_guard_points(protocol: &Protocol, role: &Role, points: &mut Vec<TokenStream>) {
+    let point = |action: TokenStream,
+                 peer: Option<&Role>,
+                 label: Option<String>,
+                 capability: &str| {
+        let peer = match peer.map(|r| r.name.to_string()) {
+            Some(peer) => quote! { Some(#peer) },
+            None => quote! { None },
+        };
+        let label = match label {
+            Some(label) => quote! { Some(#label) },
+            None => quote! { None },
+        };
+        quote! {
+            GuardPoint { action: ActionKind::#action, peer: #peer, label: #label, capability: #capability }
+        }
+    };
+    // Statement-level guard, if it applies to this role
+    let guard = statement_guard(protocol)
+        .filter(|(_, guarded)| role.name == guarded)
+        .map(|(capability, _)| capability);
+
+    match protocol {
+        Protocol::Send {
+            from,
+            to,
+            message,
+            continuation,
+            from_annotations,
+            to_annotations,
+            ..
+        } => {
+            let label = message.name.to_string();
+            if from == role {
+                for capability in from_annotations
+                    .get(GUARD_CAPABILITY)
+                    .map(String::as_str)
+                    .into_iter()
+                    .chain(guard)
+                {
+                    points.push(point(
+                        quote! { Send },
+                        Some(to),
+                        Some(label.clone()),
+                        capability,
+                    ));
+                }
+            } else if to == role {
+                for capability in to_annotations
+                    .get(GUARD_CAPABILITY)
+                    .map(String::as_str)
+                    .into_iter()
+                    .chain(guard)
+                {
+                    points.push(point(
+                        quote! { Receive },
+                        Some(from),
+                        Some(label.clone()),
+                        capability,
+                    ));
+                }
+            }
+            collect_guard_points(continuation, role, points);
+        }
+        Protocol::Broadcast {
+            from,
+            to_all,
+            message,
+            continuation,
+            from_annotations,
+            ..
+        } => {
+            let label = message.name.to_string();
+            if from == role {
+                for capability in from_annotations
+                    .get(GUARD_CAPABILITY)
+                    .map(String::as_str)
+                    .into_iter()
+                    .chain(guard)
+                {
+                    for to in to_all {
+                        points.push(point(
+                            quote! { Send },
+                            Some(to),
+                            Some(label.clone()),
+                            capability,
+                        ));
+                    }
+                }
+            } else if to_all.contains(role) {
+                if let Some(capability) = guard {
+                    points.push(point(
+                        quote! { Receive },
+                        Some(from),
+                        Some(label.clone()),
+                        capability,
+                    ));
+                }
+            }
+            collect_guard_points(continuation, role, points);
+        }
+        Protocol::Choice {
+            role: chooser,
+            branches,
+            ..
+        } => {
+            if let Some(capability) = guard {
+                if chooser == role {
+                    // Generated programs pass the chooser itself to `choose`, so match any peer
+                    for branch in branches {
+                        points.push(point(
+                            quote! { Select },
+                            None,
+                            Some(branch.label.to_string()),
+                            capability,
+                        ));
+                    }
+                } else {
+                    points.push(point(quote! { Branch }, Some(chooser), None, capability));
+                }
+            }
+            for branch in branches {
+                collect_guard_points(&branch.protocol, role, points);
+            }
+        }
+        Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => {
+            collect_guard_points(body, role, points);
+        }
+        Protocol::Parallel { protocols, .. } => {
+            for p in protocols {
+                collect_guard_points(p, role, points);
+            }
+        }
+        Protocol::Extension { continuation, .. } => {
+            collect_guard_points(continuation, role, points);
+        }
+        Protocol::Var(_) | Protocol::End => {}
+    }
+}
+
+fn generate_role_body(
+    protocol: &Protocol,
+    role: &Role,
+    hooks: &[Arc<dyn CodegenHook>],
+) -> TokenStream {
+    generate_program_builder(protocol, role, hooks)
 }
 
 /// Generate program builder code for a protocol from the perspective of a specific role
-fn generate_program_builder(protocol: &Protocol, role: &Role) -> TokenStream {
-    let program_effects = generate_program_effects(protocol, role);
+fn generate_program_builder(
+    protocol: &Protocol,
+    role: &Role,
+    hooks: &[Arc<dyn CodegenHook>],
+) -> TokenStream {
+    let program_effects = generate_program_effects(protocol, role, hooks);
 
     quote! {
         use rumpsteak_aura_choreography::{Program, Effect, Label};
@@ -220,7 +1115,11 @@ fn generate_program_builder(protocol: &Protocol, role: &Role) -> TokenStream {
 }
 
 /// Generate effect builder calls for a protocol
-fn generate_program_effects(protocol: &Protocol, role: &Role) -> TokenStream {
+fn generate_program_effects(
+    protocol: &Protocol,
+    role: &Role,
+    hooks: &[Arc<dyn CodegenHook>],
+) -> TokenStream {
     match protocol {
         Protocol::End => {
             quote! {}
@@ -232,16 +1131,23 @@ fn generate_program_effects(protocol: &Protocol, role: &Role) -> TokenStream {
             continuation,
             ..
         } => {
-            let continuation_effects = generate_program_effects(continuation, role);
+            let continuation_effects = generate_program_effects(continuation, role, hooks);
 
             if from == role {
                 // This role is sending
-                let message_type = &message.name;
+                let value = message_value(protocol, message);
                 let to_ident = &to.name;
                 let send_metadata = generate_effect_metadata_from_annotations(protocol, role);
+                let site = MessageSite {
+                    role,
+                    peer: to,
+                    message,
+                    protocol,
+                };
+                let send = wrap_send(hooks, &site, quote! { .send(Role::#to_ident, #value) });
 
                 quote! {
-                    .send(Role::#to_ident, #message_type::default())
+                    #send
                     #send_metadata
                     #continuation_effects
                 }
@@ -250,9 +1156,20 @@ fn generate_program_effects(protocol: &Protocol, role: &Role) -> TokenStream {
                 let message_type = &message.name;
                 let from_ident = &from.name;
                 let recv_metadata = generate_effect_metadata_from_annotations(protocol, role);
+                let site = MessageSite {
+                    role,
+                    peer: from,
+                    message,
+                    protocol,
+                };
+                let recv = wrap_recv(
+                    hooks,
+                    &site,
+                    quote! { .recv::<#message_type>(Role::#from_ident) },
+                );
 
                 quote! {
-                    .recv::<#message_type>(Role::#from_ident)
+                    #recv
                     #recv_metadata
                     #continuation_effects
                 }
@@ -274,7 +1191,7 @@ fn generate_program_effects(protocol: &Protocol, role: &Role) -> TokenStream {
                 .iter()
                 .map(|branch| {
                     let label_str = branch.label.to_string();
-                    let branch_effects = generate_program_effects(&branch.protocol, role);
+                    let branch_effects = generate_program_effects(&branch.protocol, role, hooks);
 
                     quote! {
                         (Label(#label_str), Program::new()#branch_effects)
@@ -292,32 +1209,30 @@ fn generate_program_effects(protocol: &Protocol, role: &Role) -> TokenStream {
                     // Generate guard evaluation logic
                     let guard_checks: Vec<TokenStream> = branches
                         .iter()
-                        .map(|branch| {
+                        .filter_map(|branch| {
                             let label_str = branch.label.to_string();
-                            if let Some(ref guard) = branch.guard {
+                            branch.guard.as_ref().map(|guard| {
                                 quote! {
                                     if #guard {
                                         Label(#label_str)
                                     }
                                 }
-                            } else {
-                                quote! {
-                                    // No guard - default fallback
-                                    { Label(#label_str) }
-                                }
-                            }
+                            })
                         })
                         .collect();
 
-                    // Generate a choice selection expression using guards
-                    let first_label = branches
-                        .first()
+                    // The first branch without a guard is the fallback,
+                    // otherwise the first branch
+                    let fallback_label = branches
+                        .iter()
+                        .find(|b| b.guard.is_none())
+                        .or(branches.first())
                         .map(|b| b.label.to_string())
                         .unwrap_or_default();
                     quote! {
                         .choose(Role::#choice_role_name, {
                             // Evaluate guards to determine which branch to choose
-                            #(#guard_checks else)* Label(#first_label)
+                            #(#guard_checks else)* { Label(#fallback_label) }
                         })
                         .branch(Role::#choice_role_name, vec![#(#branch_programs),*])
                     }
@@ -341,8 +1256,10 @@ fn generate_program_effects(protocol: &Protocol, role: &Role) -> TokenStream {
                 }
             }
         }
-        Protocol::Loop { body, condition } => {
-            let body_effects = generate_program_effects(body, role);
+        Protocol::Loop {
+            body, condition, ..
+        } => {
+            let body_effects = generate_program_effects(body, role, hooks);
 
             // Generate Loop effect with runtime iteration control
             match condition {
@@ -397,20 +1314,20 @@ fn generate_program_effects(protocol: &Protocol, role: &Role) -> TokenStream {
                 }
             }
         }
-        Protocol::Parallel { protocols } => {
+        Protocol::Parallel { protocols, .. } => {
             // For simplicity, execute sequentially in program building
             let parallel_effects: Vec<TokenStream> = protocols
                 .iter()
-                .map(|p| generate_program_effects(p, role))
+                .map(|p| generate_program_effects(p, role, hooks))
                 .collect();
 
             quote! {
                 #(#parallel_effects)*
             }
         }
-        Protocol::Rec { label: _, body } => {
+        Protocol::Rec { label: _, body, .. } => {
             // For simplicity, treat recursion as a simple body
-            generate_program_effects(body, role)
+            generate_program_effects(body, role, hooks)
         }
         Protocol::Broadcast {
             from,
@@ -419,7 +1336,7 @@ fn generate_program_effects(protocol: &Protocol, role: &Role) -> TokenStream {
             continuation,
             ..
         } => {
-            let continuation_effects = generate_program_effects(continuation, role);
+            let continuation_effects = generate_program_effects(continuation, role, hooks);
             let message_type = &message.name;
 
             if from == role {
@@ -428,9 +1345,14 @@ fn generate_program_effects(protocol: &Protocol, role: &Role) -> TokenStream {
                     .iter()
                     .map(|to| {
                         let to_ident = &to.name;
-                        quote! {
-                            .send(Role::#to_ident, #message_type::default())
-                        }
+                        let site = MessageSite {
+                            role,
+                            peer: to,
+                            message,
+                            protocol,
+                        };
+                        let value = message_value(protocol, message);
+                        wrap_send(hooks, &site, quote! { .send(Role::#to_ident, #value) })
                     })
                     .collect();
 
@@ -441,9 +1363,20 @@ fn generate_program_effects(protocol: &Protocol, role: &Role) -> TokenStream {
             } else if to_all.contains(role) {
                 // This role is receiving the broadcast
                 let from_ident = &from.name;
+                let site = MessageSite {
+                    role,
+                    peer: from,
+                    message,
+                    protocol,
+                };
+                let recv = wrap_recv(
+                    hooks,
+                    &site,
+                    quote! { .recv::<#message_type>(Role::#from_ident) },
+                );
 
                 quote! {
-                    .recv::<#message_type>(Role::#from_ident)
+                    #recv
                     #continuation_effects
                 }
             } else {
@@ -474,9 +1407,8 @@ fn generate_program_effects(protocol: &Protocol, role: &Role) -> TokenStream {
             ..
         } => {
             // Generate code for the extension and then continue with the rest
-            let extension_effects =
-                extension.generate_code(&crate::extensions::CodegenContext::default());
-            let continuation_effects = generate_program_effects(continuation, role);
+            let extension_effects = extension.generate_code(&CodegenContext::default());
+            let continuation_effects = generate_program_effects(continuation, role, hooks);
 
             quote! {
                 #extension_effects
@@ -486,7 +1418,7 @@ fn generate_program_effects(protocol: &Protocol, role: &Role) -> TokenStream {
     }
 }
 
-fn infer_content_type(message_type: &str) -> TokenStream {
+pub(crate) fn infer_content_type(message_type: &str) -> TokenStream {
     // Simple heuristic - can be improved
     match message_type {
         s if s.contains("Request") => quote! { String },
@@ -514,6 +1446,11 @@ mod tests {
             ],
             protocol: Protocol::End,
             attrs: std::collections::HashMap::new(),
+            policy: None,
+            state: Vec::new(),
+            messages: Vec::new(),
+            unreachable: Vec::new(),
+            scopes: Vec::new(),
         };
 
         let code = generate_effects_protocol(&choreography);
@@ -524,5 +1461,255 @@ mod tests {
         assert!(code_str.contains("Server"));
         assert!(code_str.contains("run_client"));
         assert!(code_str.contains("run_server"));
+        assert!(code_str.contains("run_client_cancellable"));
+        assert!(code_str.contains("Cancellable :: new"));
+        assert!(code_str.contains("run_client_traced"));
+        assert!(code_str.contains("run_client_instrumented"));
+        assert!(code_str.contains("run_client_journaled"));
+        assert!(code_str.contains("CLIENT_JOURNAL_POINTS"));
+        assert!(code_str.contains("Instrumented :: new (handler , Role :: Client , metrics)"));
+        assert!(code_str.contains("handler . end_session (& result)"));
+        assert!(code_str.contains("-> SessionResult < InterpretResult < Message >>"));
+        assert!(code_str.contains(
+            "session_result (\"Client\" , interpret (handler , endpoint , program) . await)"
+        ));
+        assert!(code_str.contains("Traced :: new (handler , Role :: Client , session_id)"));
+        assert!(code_str.contains("pub fn new () -> Self"));
+    }
+
+    #[test]
+    fn test_session_introspection() {
+        let choreography = crate::compiler::parse_choreography_str(
+            r#"
+choreography Fetch {
+    roles: Client, Server
+    Client -> Server: Request
+    Server -> Client: Response
+}
+"#,
+        )
+        .unwrap();
+        let code = generate_effects_protocol(&choreography);
+        syn::parse2::<syn::File>(code.clone()).unwrap();
+        let code = code.to_string();
+        assert!(code.contains("pub session : SessionProbe ,"));
+        assert!(code.contains("pub fn session_state (& self) -> Option < SessionState >"));
+        assert!(code.contains("pub const CLIENT_MONITOR_SPEC : MonitorSpec"));
+        assert!(code.contains("label : \"Response\""));
+        assert!(code.contains("pub async fn run_server_observed"));
+        assert!(code.contains("endpoint . session . start (& SPEC)"));
+        assert!(code.contains("pub async fn run_client_debugged"));
+        assert!(code.contains("Debugged :: new (handler , Role :: Client , debugger)"));
+    }
+
+    #[test]
+    fn test_journal_points_from_annotations() {
+        let choreography = crate::compiler::parse_choreography_str(
+            r#"
+choreography Payment {
+    roles: Payer, Payee
+    [@journal_facts = "payment_sent"]
+    Payer -> Payee: Pay
+    Payee -> Payer: Receipt
+}
+"#,
+        )
+        .unwrap();
+
+        let payer = &choreography.roles[0];
+        let payee = &choreography.roles[1];
+        let payer_points = generate_journal_points(&choreography.protocol, payer);
+        let payee_points = generate_journal_points(&choreography.protocol, payee);
+        assert_eq!(payer_points.len(), 1);
+        assert_eq!(payee_points.len(), 1);
+        assert!(payer_points[0].to_string().contains("ActionKind :: Send"));
+        assert!(payee_points[0]
+            .to_string()
+            .contains("ActionKind :: Receive"));
+        assert!(payee_points[0].to_string().contains("\"payment_sent\""));
+
+        let code = generate_effects_protocol(&choreography).to_string();
+        assert!(!code.contains("with_annotation (\"journal_facts\""));
+    }
+
+    #[test]
+    fn test_host_expressions() {
+        let choreography = crate::compiler::parse_choreography_str(
+            r#"
+choreography Fetch {
+    roles: Client, Server
+    [@timeout = #{ config::REQUEST_TIMEOUT }, @default = #{ Request(config::FIRST_PAGE) }]
+    Client -> Server: Request
+}
+"#,
+        )
+        .unwrap();
+        let client = &choreography.roles[0];
+        let code = generate_program_effects(&choreography.protocol, client, &[]).to_string();
+        assert!(code.contains(". send (Role :: Server , Request (config :: FIRST_PAGE))"));
+        assert!(code.contains(". with_timeout (config :: REQUEST_TIMEOUT)"));
+        assert!(!code.contains("with_annotation"));
+    }
+
+    #[test]
+    fn test_role_state() {
+        let choreography = crate::compiler::parse_choreography_str(
+            r#"
+choreography Voting {
+    roles: Coordinator, Voter
+    state at Coordinator { round: u32, votes: Vec<Vote> }
+    choice Coordinator {
+        again when (state.round < 3): { Coordinator -> Voter: Proposal }
+        done: { Coordinator -> Voter: Close }
+    }
+}
+"#,
+        )
+        .unwrap();
+        let code = generate_effects_protocol(&choreography);
+        syn::parse2::<syn::File>(code.clone()).unwrap();
+        let code = code.to_string();
+        assert!(code.contains(
+            "pub struct CoordinatorState { pub round : u32 , pub votes : Vec < Vote > }"
+        ));
+        assert!(code.contains("pub coordinator_state : CoordinatorState ,"));
+        assert!(code.contains(
+            "pub fn coordinator_program (state : & CoordinatorState) -> Program < Role , Message >"
+        ));
+        assert!(code.contains("if state . round < 3"));
+        assert!(code.contains("coordinator_program (& endpoint . coordinator_state)"));
+        assert!(code.contains("pub fn voter_program () -> Program < Role , Message >"));
+    }
+
+    #[test]
+    fn test_journal_retention() {
+        let choreography = crate::compiler::parse_choreography_str(
+            r#"
+choreography Signup {
+    roles: User, Service
+    [@journal_facts = "signup", @retain = "30d"]
+    User -> Service: Register(email: String [confidential to = "Service"])
+    [@journal_facts = "welcome"]
+    Service -> User: Welcome
+}
+"#,
+        )
+        .unwrap();
+        let user = &choreography.roles[0];
+        let points = generate_journal_points(&choreography.protocol, user);
+        assert_eq!(points.len(), 2);
+        assert!(points[0]
+            .to_string()
+            .contains("retain_ms : Some (2592000000u64)"));
+        assert!(points[1].to_string().contains("retain_ms : None"));
+
+        for annotations in [
+            r#"@journal_facts = "signup", @personal_data"#,
+            r#"@journal_facts = "signup", @retain = "forever""#,
+        ] {
+            let choreography = crate::compiler::parse_choreography_str(&format!(
+                "choreography Signup {{
+    roles: User, Service
+    [{annotations}]
+    User -> Service: Register
+}}"
+            ))
+            .unwrap();
+            let points = generate_journal_points(&choreography.protocol, &choreography.roles[0]);
+            assert!(
+                points[0].to_string().contains("compile_error"),
+                "{annotations}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_guard_points_from_annotations() {
+        let choreography = crate::compiler::parse_choreography_str(
+            r#"
+choreography Approval {
+    roles: Client, Manager
+    Client[@guard_capability = "submit"] -> Manager: Request
+    [@guard_capability = "approve"]
+    choice Manager {
+        accept: {
+            Manager -> Client: Accepted
+        }
+        reject: {
+            Manager -> Client: Rejected
+        }
+    }
+}
+"#,
+        )
+        .unwrap();
+
+        let client = &choreography.roles[0];
+        let manager = &choreography.roles[1];
+        let client_points = generate_guard_points(&choreography.protocol, client);
+        let manager_points = generate_guard_points(&choreography.protocol, manager);
+        assert_eq!(client_points.len(), 1);
+        assert!(client_points[0].to_string().contains("\"submit\""));
+        assert_eq!(manager_points.len(), 2);
+        assert!(manager_points
+            .iter()
+            .all(|p| p.to_string().contains("ActionKind :: Select")));
+
+        let code = generate_effects_protocol(&choreography).to_string();
+        assert!(code.contains("run_manager_guarded"));
+        assert!(!code.contains("with_annotation (\"guard_capability\""));
+    }
+
+    #[test]
+    fn test_guard_points_from_policy() {
+        let choreography = crate::compiler::parse_choreography_str(
+            r#"
+choreography Signing {
+    roles: Coordinator, Signer
+    policy {
+        Signer may only respond;
+    }
+    Coordinator -> Signer: Request
+    Signer -> Coordinator: Signature
+}
+"#,
+        )
+        .unwrap();
+
+        let coordinator = &choreography.roles[0];
+        let signer = &choreography.roles[1];
+        assert!(generate_policy_points(&choreography, coordinator).is_empty());
+        let signer_points = generate_policy_points(&choreography, signer);
+        assert_eq!(signer_points.len(), 1);
+        assert!(signer_points[0].to_string().contains("\"respond\""));
+
+        let code = generate_effects_protocol(&choreography).to_string();
+        assert!(code.contains("fn policy_capabilities"));
+        assert!(code.contains(". grant (\"Signer\" , \"respond\")"));
+        assert!(!code.contains(". grant (\"Signer\" , \"initiate\")"));
+    }
+
+    #[test]
+    fn test_flow_charges_and_worst_case() {
+        let choreography = crate::compiler::parse_choreography_str(
+            r#"
+choreography Upload {
+    roles: Client, Server
+    loop (count: 4) {
+        Client[@flow_cost = 25] -> Server: Chunk
+    }
+}
+"#,
+        )
+        .unwrap();
+
+        let client = &choreography.roles[0];
+        let charges = generate_flow_charges(&choreography.protocol, client);
+        assert_eq!(charges.len(), 1);
+        assert!(charges[0].to_string().contains("cost : 25u64"));
+
+        let code = generate_effects_protocol(&choreography).to_string();
+        assert!(code.contains("CLIENT_MAX_FLOW_COST : Option < u64 > = Some (100u64)"));
+        assert!(code.contains("run_client_metered"));
     }
 }
@@ -0,0 +1,612 @@
+// Static flow-cost analysis
+//
+// Computes the worst-case flow cost of every role from `flow_cost`
+// annotations. A cost is charged to the sender of the annotated message, once
+// per recipient for broadcasts. It may be given on the statement or on the
+// sending role:
+//
+//     [@flow_cost = 100]
+//     Client -> Server: Upload
+//     Client[@flow_cost = 5] -> Server: Ping
+//
+// Every combination of choice branches is reported as a separate execution
+// path. Loops with a fixed count multiply the cost of their body; loops
+// without a bound and recursion are unbounded as soon as their body costs
+// anything. Choices inside a loop body are summarized by their most expensive
+// branch per role.
+//
+// Each path also carries its probability, the product of the `[prob = p]`
+// probabilities of the branches it takes, and the expected cost of a role
+// weighs its cost on every path by that probability.
+//
+// The least cost of a path assumes loops run as few times as they may: a
+// counted loop its count, a loop some role decides not at all, a recursion
+// once, always through the cheapest branches of its body. The report renders
+// as a table of least, worst and expected cost per role and of the cost of
+// every path, marking the most expensive one as critical. This is what
+// `choreo analyze cost` prints.
+
+use crate::ast::{Branch, Choreography, Condition, Protocol};
+use crate::runtime::flow::FLOW_COST;
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Flow cost of a role or path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FlowCost {
+    Bounded(u64),
+    Unbounded,
+}
+
+impl FlowCost {
+    pub const ZERO: FlowCost = FlowCost::Bounded(0);
+
+    /// Whether the cost is known to stay within `budget`
+    #[must_use]
+    pub fn fits(self, budget: u64) -> bool {
+        matches!(self, FlowCost::Bounded(cost) if cost <= budget)
+    }
+
+    #[must_use]
+    pub fn as_bounded(self) -> Option<u64> {
+        match self {
+            FlowCost::Bounded(cost) => Some(cost),
+            FlowCost::Unbounded => None,
+        }
+    }
+
+    fn add(self, other: FlowCost) -> FlowCost {
+        match (self, other) {
+            (FlowCost::Bounded(a), FlowCost::Bounded(b)) => a
+                .checked_add(b)
+                .map_or(FlowCost::Unbounded, FlowCost::Bounded),
+            _ => FlowCost::Unbounded,
+        }
+    }
+
+    fn times(self, n: u64) -> FlowCost {
+        match self {
+            FlowCost::Bounded(cost) => cost
+                .checked_mul(n)
+                .map_or(FlowCost::Unbounded, FlowCost::Bounded),
+            FlowCost::Unbounded if n == 0 => FlowCost::ZERO,
+            FlowCost::Unbounded => FlowCost::Unbounded,
+        }
+    }
+
+    /// Cost of repeating a body an unknown number of times
+    fn repeated(self) -> FlowCost {
+        if self == FlowCost::ZERO {
+            FlowCost::ZERO
+        } else {
+            FlowCost::Unbounded
+        }
+    }
+}
+
+impl fmt::Display for FlowCost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlowCost::Bounded(cost) => write!(f, "{cost}"),
+            FlowCost::Unbounded => write!(f, "unbounded"),
+        }
+    }
+}
+
+/// Cost of one execution path through the choreography
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathCost {
+    /// Choices taken along the path, as `Role.label`
+    pub choices: Vec<String>,
+    /// Probability of the path, see `Branch::probabilities`
+    pub probability: f64,
+    /// Cost charged to each role that sends on this path, with loops
+    /// running as often as they may
+    pub roles: BTreeMap<String, FlowCost>,
+    /// Cost charged to each role that sends on this path, with loops
+    /// running as rarely as they may
+    pub least: BTreeMap<String, FlowCost>,
+}
+
+impl PathCost {
+    /// Cost of the path summed over all roles
+    #[must_use]
+    pub fn total(&self) -> FlowCost {
+        self.roles
+            .values()
+            .fold(FlowCost::ZERO, |acc, c| acc.add(*c))
+    }
+
+    fn empty() -> Self {
+        Self {
+            choices: Vec::new(),
+            probability: 1.0,
+            roles: BTreeMap::new(),
+            least: BTreeMap::new(),
+        }
+    }
+
+    fn charge(&mut self, role: &str, cost: FlowCost) {
+        self.charge_range(role, cost, cost);
+    }
+
+    fn charge_range(&mut self, role: &str, least: FlowCost, most: FlowCost) {
+        for (costs, cost) in [(&mut self.least, least), (&mut self.roles, most)] {
+            let entry = costs.entry(role.to_string()).or_insert(FlowCost::ZERO);
+            *entry = entry.add(cost);
+        }
+    }
+
+    fn extend(&mut self, other: &PathCost) {
+        self.choices.extend(other.choices.iter().cloned());
+        self.probability *= other.probability;
+        for (role, cost) in &other.roles {
+            let least = other.least.get(role).copied().unwrap_or(FlowCost::ZERO);
+            self.charge_range(role, least, *cost);
+        }
+    }
+}
+
+/// Result of the flow-cost analysis
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlowCostReport {
+    /// Worst-case cost of each declared role over all paths
+    pub roles: BTreeMap<String, FlowCost>,
+    pub paths: Vec<PathCost>,
+}
+
+impl FlowCostReport {
+    #[must_use]
+    pub fn worst_case(&self, role: &str) -> FlowCost {
+        self.roles.get(role).copied().unwrap_or(FlowCost::ZERO)
+    }
+
+    /// Least cost of `role` over all paths
+    #[must_use]
+    pub fn best_case(&self, role: &str) -> FlowCost {
+        self.paths
+            .iter()
+            .map(|path| path.least.get(role).copied().unwrap_or(FlowCost::ZERO))
+            .min()
+            .unwrap_or(FlowCost::ZERO)
+    }
+
+    /// The most expensive path over all roles, the first of them on a tie
+    #[must_use]
+    pub fn critical_path(&self) -> Option<&PathCost> {
+        self.paths.iter().rev().max_by_key(|path| path.total())
+    }
+
+    /// Expected cost of `role` over all paths, `None` if a path the role
+    /// may take is unbounded for it
+    #[must_use]
+    pub fn expected(&self, role: &str) -> Option<f64> {
+        self.paths
+            .iter()
+            .filter(|path| path.probability > 0.0)
+            .try_fold(0.0, |total, path| {
+                match path.roles.get(role).copied().unwrap_or(FlowCost::ZERO) {
+                    FlowCost::Bounded(cost) => Some(total + path.probability * cost as f64),
+                    FlowCost::Unbounded => None,
+                }
+            })
+    }
+
+    /// Roles whose worst case may exceed `budget`
+    #[must_use]
+    pub fn over_budget(&self, budget: u64) -> Vec<&str> {
+        self.roles
+            .iter()
+            .filter(|(_, cost)| !cost.fits(budget))
+            .map(|(role, _)| role.as_str())
+            .collect()
+    }
+}
+
+impl fmt::Display for FlowCostReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut roles = vec![["role", "min", "max", "expected"]
+            .map(String::from)
+            .to_vec()];
+        for (role, worst) in &self.roles {
+            roles.push(vec![
+                role.clone(),
+                self.best_case(role).to_string(),
+                worst.to_string(),
+                self.expected(role)
+                    .map_or_else(|| "unbounded".to_string(), |cost| format!("{cost:.2}")),
+            ]);
+        }
+        write_table(f, &roles)?;
+        writeln!(f)?;
+
+        let critical = self.critical_path();
+        let mut paths = vec![["path", "probability", "cost", ""]
+            .map(String::from)
+            .to_vec()];
+        for path in &self.paths {
+            paths.push(vec![
+                if path.choices.is_empty() {
+                    "(no choices)".to_string()
+                } else {
+                    path.choices.join(", ")
+                },
+                format!("{:.2}", path.probability),
+                path.total().to_string(),
+                if critical.is_some_and(|critical| std::ptr::eq(critical, path)) {
+                    "critical".to_string()
+                } else {
+                    String::new()
+                },
+            ]);
+        }
+        write_table(f, &paths)
+    }
+}
+
+/// Write `rows` as left-aligned columns, two spaces apart
+pub(crate) fn write_table(f: &mut fmt::Formatter<'_>, rows: &[Vec<String>]) -> fmt::Result {
+    let mut widths = Vec::new();
+    for row in rows {
+        widths.resize(widths.len().max(row.len()), 0);
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in rows {
+        let mut line = String::new();
+        for (cell, width) in row.iter().zip(&widths) {
+            line.push_str(&format!("{cell:width$}  "));
+        }
+        writeln!(f, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+/// Errors raised by the flow-cost analysis
+#[derive(Debug, Clone, thiserror::Error, PartialEq, Eq)]
+pub enum FlowCostError {
+    #[error("Invalid flow_cost '{value}' on {statement}: expected a non-negative integer")]
+    InvalidCost { value: String, statement: String },
+}
+
+/// Compute worst-case flow costs per role and per execution path
+pub fn analyze_flow_cost(choreography: &Choreography) -> Result<FlowCostReport, FlowCostError> {
+    let paths = paths(&choreography.protocol)?;
+
+    let mut roles: BTreeMap<String, FlowCost> = choreography
+        .roles
+        .iter()
+        .map(|r| (r.name.to_string(), FlowCost::ZERO))
+        .collect();
+    for path in &paths {
+        for (role, cost) in &path.roles {
+            let worst = roles.entry(role.clone()).or_insert(FlowCost::ZERO);
+            *worst = (*worst).max(*cost);
+        }
+    }
+
+    Ok(FlowCostReport { roles, paths })
+}
+
+/// Cost charged to the sender of a send or broadcast for each recipient
+pub(crate) fn send_cost(protocol: &Protocol) -> Result<u64, FlowCostError> {
+    let (from_annotations, statement) = match protocol {
+        Protocol::Send {
+            from,
+            to,
+            message,
+            from_annotations,
+            ..
+        } => (
+            from_annotations,
+            format!("{} -> {}: {}", from.name, to.name, message.name),
+        ),
+        Protocol::Broadcast {
+            from,
+            message,
+            from_annotations,
+            ..
+        } => (
+            from_annotations,
+            format!("{} ->* : {}", from.name, message.name),
+        ),
+        _ => return Ok(0),
+    };
+
+    [
+        protocol.get_annotation(FLOW_COST),
+        from_annotations.get(FLOW_COST),
+    ]
+    .into_iter()
+    .flatten()
+    .try_fold(0u64, |total, value| {
+        value
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .and_then(|cost| total.checked_add(cost))
+            .ok_or_else(|| FlowCostError::InvalidCost {
+                value: value.clone(),
+                statement: statement.clone(),
+            })
+    })
+}
+
+fn paths(protocol: &Protocol) -> Result<Vec<PathCost>, FlowCostError> {
+    match protocol {
+        Protocol::Send {
+            from, continuation, ..
+        } => {
+            let cost = FlowCost::Bounded(send_cost(protocol)?);
+            let mut paths = paths(continuation)?;
+            for path in &mut paths {
+                path.charge(&from.name.to_string(), cost);
+            }
+            Ok(paths)
+        }
+        Protocol::Broadcast {
+            from,
+            to_all,
+            continuation,
+            ..
+        } => {
+            let cost = FlowCost::Bounded(send_cost(protocol)?).times(to_all.len() as u64);
+            let mut paths = paths(continuation)?;
+            for path in &mut paths {
+                path.charge(&from.name.to_string(), cost);
+            }
+            Ok(paths)
+        }
+        Protocol::Choice { role, branches, .. } => {
+            let mut result = Vec::new();
+            for (branch, probability) in branches.iter().zip(Branch::probabilities(branches)) {
+                for mut path in paths(&branch.protocol)? {
+                    path.choices
+                        .insert(0, format!("{}.{}", role.name, branch.label));
+                    path.probability *= probability;
+                    result.push(path);
+                }
+            }
+            if result.is_empty() {
+                result.push(PathCost::empty());
+            }
+            Ok(result)
+        }
+        Protocol::Loop {
+            condition, body, ..
+        } => {
+            let body = summarize(&paths(body)?);
+            let mut path = PathCost::empty();
+            for (role, cost) in &body.roles {
+                let least = body.least.get(role).copied().unwrap_or(FlowCost::ZERO);
+                let (least, most) = match condition {
+                    Some(Condition::Count(n)) => (least.times(*n as u64), cost.times(*n as u64)),
+                    // A loop without a condition never ends
+                    None => (least.repeated(), cost.repeated()),
+                    _ => (FlowCost::ZERO, cost.repeated()),
+                };
+                path.charge_range(role, least, most);
+            }
+            Ok(vec![path])
+        }
+        Protocol::Rec { body, .. } => {
+            let body = summarize(&paths(body)?);
+            let mut path = PathCost::empty();
+            for (role, cost) in &body.roles {
+                let least = body.least.get(role).copied().unwrap_or(FlowCost::ZERO);
+                path.charge_range(role, least, cost.repeated());
+            }
+            Ok(vec![path])
+        }
+        Protocol::Parallel { protocols, .. } => {
+            let mut result = vec![PathCost::empty()];
+            for protocol in protocols {
+                let branch_paths = paths(protocol)?;
+                result = result
+                    .iter()
+                    .flat_map(|prefix| {
+                        branch_paths.iter().map(move |path| {
+                            let mut combined = prefix.clone();
+                            combined.extend(path);
+                            combined
+                        })
+                    })
+                    .collect();
+            }
+            Ok(result)
+        }
+        Protocol::Extension { continuation, .. } => paths(continuation),
+        Protocol::Var(_) | Protocol::End => Ok(vec![PathCost::empty()]),
+    }
+}
+
+/// Collapse paths into one, keeping the most expensive and the least cost
+/// of each role
+fn summarize(paths: &[PathCost]) -> PathCost {
+    let mut summary = PathCost::empty();
+    for path in paths {
+        for (role, cost) in &path.roles {
+            let worst = summary.roles.entry(role.clone()).or_insert(FlowCost::ZERO);
+            *worst = (*worst).max(*cost);
+        }
+    }
+    for role in summary.roles.keys() {
+        let least = paths
+            .iter()
+            .map(|path| path.least.get(role).copied().unwrap_or(FlowCost::ZERO))
+            .min()
+            .unwrap_or(FlowCost::ZERO);
+        summary.least.insert(role.clone(), least);
+    }
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parse_choreography_str;
+
+    #[test]
+    fn test_worst_case_over_choice_paths() {
+        let choreography = parse_choreography_str(
+            r#"
+choreography Upload {
+    roles: Client, Server
+    Client[@flow_cost = 5] -> Server: Hello
+    choice Client {
+        big: {
+            [@flow_cost = 100]
+            Client -> Server: Large
+        }
+        small: {
+            [@flow_cost = 10]
+            Client -> Server: Small
+        }
+    }
+}
+"#,
+        )
+        .unwrap();
+
+        let report = analyze_flow_cost(&choreography).unwrap();
+        assert_eq!(report.paths.len(), 2);
+        assert_eq!(report.paths[0].choices, vec!["Client.big"]);
+        assert_eq!(report.paths[0].total(), FlowCost::Bounded(105));
+        assert_eq!(report.paths[1].total(), FlowCost::Bounded(15));
+        assert_eq!(report.worst_case("Client"), FlowCost::Bounded(105));
+        assert_eq!(report.worst_case("Server"), FlowCost::ZERO);
+        assert_eq!(report.over_budget(100), vec!["Client"]);
+    }
+
+    #[test]
+    fn test_expected_cost_from_branch_probabilities() {
+        let choreography = parse_choreography_str(
+            r#"
+choreography Upload {
+    roles: Client, Server
+    Client[@flow_cost = 5] -> Server: Hello
+    choice Client {
+        big [prob = 0.1]: {
+            [@flow_cost = 100]
+            Client -> Server: Large
+        }
+        small: {
+            [@flow_cost = 10]
+            Client -> Server: Small
+        }
+    }
+}
+"#,
+        )
+        .unwrap();
+
+        let report = analyze_flow_cost(&choreography).unwrap();
+        assert!((report.paths[0].probability - 0.1).abs() < 1e-9);
+        assert!((report.paths[1].probability - 0.9).abs() < 1e-9);
+        assert!((report.expected("Client").unwrap() - 24.0).abs() < 1e-9);
+        assert_eq!(report.expected("Server"), Some(0.0));
+    }
+
+    #[test]
+    fn test_loops_and_broadcasts() {
+        let choreography = parse_choreography_str(
+            r#"
+choreography Gossip {
+    roles: A, B, C
+    loop (count: 3) {
+        [@flow_cost = 2]
+        A ->* : Rumor
+    }
+}
+"#,
+        )
+        .unwrap();
+        let report = analyze_flow_cost(&choreography).unwrap();
+        assert_eq!(report.worst_case("A"), FlowCost::Bounded(12));
+
+        let choreography = parse_choreography_str(
+            r#"
+choreography Stream {
+    roles: A, B
+    loop (decides: A) {
+        [@flow_cost = 1]
+        A -> B: Chunk
+    }
+}
+"#,
+        )
+        .unwrap();
+        let report = analyze_flow_cost(&choreography).unwrap();
+        assert_eq!(report.worst_case("A"), FlowCost::Unbounded);
+        assert!(!report.worst_case("A").fits(u64::MAX));
+    }
+
+    #[test]
+    fn test_invalid_cost() {
+        let choreography = parse_choreography_str(
+            r#"
+choreography Bad {
+    roles: A, B
+    [@flow_cost = "lots"]
+    A -> B: Msg
+}
+"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            analyze_flow_cost(&choreography),
+            Err(FlowCostError::InvalidCost { .. })
+        ));
+    }
+
+    #[test]
+    fn test_report_with_least_cost_and_critical_path() {
+        let choreography = parse_choreography_str(
+            r#"
+choreography Sync {
+    roles: Client, Server
+    choice Server {
+        full [prob = 0.25]: {
+            [@flow_cost = 50]
+            Server -> Client: Snapshot
+            loop (count: 2) {
+                [@flow_cost = 4]
+                Client -> Server: Ack
+            }
+        }
+        delta: {
+            [@flow_cost = 5]
+            Server -> Client: Changes
+            loop (decides: Client) {
+                [@flow_cost = 3]
+                Client -> Server: Ping
+            }
+        }
+    }
+}
+"#,
+        )
+        .unwrap();
+
+        let report = analyze_flow_cost(&choreography).unwrap();
+        assert_eq!(report.best_case("Client"), FlowCost::ZERO);
+        assert_eq!(report.best_case("Server"), FlowCost::Bounded(5));
+        assert_eq!(
+            report.critical_path().unwrap().choices,
+            vec!["Server.delta"]
+        );
+        assert_eq!(
+            report.to_string(),
+            "\
+role    min  max        expected
+Client  0    unbounded  unbounded
+Server  5    50         16.25
+
+path          probability  cost
+Server.full   0.25         58
+Server.delta  0.75         unbounded  critical
+"
+        );
+    }
+}
@@ -0,0 +1,430 @@
+// Golden traces
+//
+// `golden_traces` enumerates representative executions of a choreography,
+// one per combination of choice branches, to be kept as JSON next to
+// integration tests. Every trace lists the choices it takes and the sends
+// and receives of every role in protocol order. A send is one step of its
+// sender followed by one step of its receiver; a broadcast sends to every
+// recipient before any of them receives.
+//
+// Loops with a count run exactly that many times. Other loops and recursion
+// are unrolled: each trace runs them between zero and `unroll` times, and a
+// recursion still going after `unroll` jumps back has no trace. Threads of a
+// `parallel` block are laid out one after another.
+//
+// `GoldenTrace::check_journal` checks the session journal of a live run
+// against a trace. Only steps annotated with `journal_facts` are journaled,
+// so those are the steps compared, role by role and in order: the order
+// between roles depends on scheduling and is not checked.
+
+use crate::ast::{Choreography, Condition, Protocol};
+use crate::runtime::journal::JournalRecord;
+use crate::runtime::monitor::ActionKind;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use thiserror::Error;
+
+/// Annotation whose steps the session journal records
+const JOURNAL_FACTS: &str = "journal_facts";
+
+/// One send or receive of a golden trace
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GoldenStep {
+    pub role: String,
+    /// `send` or `receive`
+    pub action: String,
+    pub peer: String,
+    /// Message type name
+    pub label: String,
+    /// Value of the `journal_facts` annotation, if the step is journaled
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub facts: Option<String>,
+}
+
+impl GoldenStep {
+    fn new(
+        role: &str,
+        action: ActionKind,
+        peer: &str,
+        label: &str,
+        facts: Option<&String>,
+    ) -> Self {
+        Self {
+            role: role.to_string(),
+            action: action.to_string(),
+            peer: peer.to_string(),
+            label: label.to_string(),
+            facts: facts.cloned(),
+        }
+    }
+}
+
+impl From<&JournalRecord> for GoldenStep {
+    fn from(record: &JournalRecord) -> Self {
+        Self {
+            role: record.role.clone(),
+            action: record.action.clone(),
+            peer: record.peer.clone(),
+            label: record.label.clone(),
+            facts: Some(record.facts.clone()),
+        }
+    }
+}
+
+impl fmt::Display for GoldenStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} ({})",
+            self.role, self.action, self.label, self.peer
+        )?;
+        if let Some(facts) = &self.facts {
+            write!(f, ": {facts}")?;
+        }
+        Ok(())
+    }
+}
+
+/// One representative execution of a choreography
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GoldenTrace {
+    /// Choices taken, as `Role.label`, and iterations of unrolled loops, as
+    /// `Role.loop(n)` or `loop(n)` when no role decides
+    pub choices: Vec<String>,
+    pub steps: Vec<GoldenStep>,
+}
+
+/// Difference between a journal and the golden trace it was checked against
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum GoldenMismatch {
+    #[error("journaled step {index} of {role} is `{found}`, expected `{expected}`")]
+    Step {
+        role: String,
+        index: usize,
+        expected: String,
+        found: String,
+    },
+
+    #[error("{role} journaled `{found}` after its last step in the trace")]
+    Extra { role: String, found: String },
+
+    #[error("{role} stopped before `{expected}`")]
+    Missing { role: String, expected: String },
+}
+
+impl GoldenTrace {
+    /// Steps of `role` that the session journal records
+    pub fn journaled_steps<'a>(&'a self, role: &'a str) -> impl Iterator<Item = &'a GoldenStep> {
+        self.steps
+            .iter()
+            .filter(move |step| step.role == role && step.facts.is_some())
+    }
+
+    /// Check the journal records of one session against this trace
+    ///
+    /// # Errors
+    ///
+    /// The first [`GoldenMismatch`] found, taking the roles in the order they
+    /// first appear in the trace and then in the journal.
+    pub fn check_journal<'a>(
+        &self,
+        records: impl IntoIterator<Item = &'a JournalRecord>,
+    ) -> Result<(), GoldenMismatch> {
+        let journaled: Vec<GoldenStep> = records.into_iter().map(GoldenStep::from).collect();
+        let mut roles: Vec<&str> = Vec::new();
+        for role in self
+            .steps
+            .iter()
+            .chain(&journaled)
+            .map(|step| step.role.as_str())
+        {
+            if !roles.contains(&role) {
+                roles.push(role);
+            }
+        }
+
+        for role in roles {
+            let mut expected = self.journaled_steps(role);
+            let mut found = journaled.iter().filter(|step| step.role == role);
+            for index in 0.. {
+                match (expected.next(), found.next()) {
+                    (Some(expected), Some(found)) if expected != found => {
+                        return Err(GoldenMismatch::Step {
+                            role: role.to_string(),
+                            index,
+                            expected: expected.to_string(),
+                            found: found.to_string(),
+                        })
+                    }
+                    (Some(_), Some(_)) => {}
+                    (None, Some(found)) => {
+                        return Err(GoldenMismatch::Extra {
+                            role: role.to_string(),
+                            found: found.to_string(),
+                        })
+                    }
+                    (Some(expected), None) => {
+                        return Err(GoldenMismatch::Missing {
+                            role: role.to_string(),
+                            expected: expected.to_string(),
+                        })
+                    }
+                    (None, None) => break,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Enumerate the golden traces of `choreography`, running every loop without
+/// a count and every recursion at most `unroll` times
+#[must_use]
+pub fn golden_traces(choreography: &Choreography, unroll: usize) -> Vec<GoldenTrace> {
+    traces(&choreography.protocol, unroll, &mut Vec::new())
+}
+
+/// Golden traces as pretty-printed JSON
+#[must_use]
+pub fn golden_traces_json(traces: &[GoldenTrace]) -> String {
+    serde_json::to_string_pretty(traces).unwrap_or_default()
+}
+
+/// Traces of `protocol`, with the bodies of the enclosing recursions and
+/// the jumps back each may still take
+fn traces<'a>(
+    protocol: &'a Protocol,
+    unroll: usize,
+    recursions: &mut Vec<(String, &'a Protocol, usize)>,
+) -> Vec<GoldenTrace> {
+    match protocol {
+        Protocol::Send {
+            from,
+            to,
+            message,
+            continuation,
+            ..
+        } => {
+            let facts = protocol.get_annotation(JOURNAL_FACTS);
+            let (from, to, label) = (
+                from.name.to_string(),
+                to.name.to_string(),
+                message.name.to_string(),
+            );
+            let prefix = [
+                GoldenStep::new(&from, ActionKind::Send, &to, &label, facts),
+                GoldenStep::new(&to, ActionKind::Receive, &from, &label, facts),
+            ];
+            prepend(&prefix, traces(continuation, unroll, recursions))
+        }
+        Protocol::Broadcast {
+            from,
+            to_all,
+            message,
+            continuation,
+            ..
+        } => {
+            let facts = protocol.get_annotation(JOURNAL_FACTS);
+            let (from, label) = (from.name.to_string(), message.name.to_string());
+            let sends = to_all.iter().map(|to| {
+                GoldenStep::new(&from, ActionKind::Send, &to.name.to_string(), &label, facts)
+            });
+            let receives = to_all.iter().map(|to| {
+                GoldenStep::new(
+                    &to.name.to_string(),
+                    ActionKind::Receive,
+                    &from,
+                    &label,
+                    facts,
+                )
+            });
+            let prefix: Vec<GoldenStep> = sends.chain(receives).collect();
+            prepend(&prefix, traces(continuation, unroll, recursions))
+        }
+        Protocol::Choice { role, branches, .. } => {
+            let mut result = Vec::new();
+            for branch in branches {
+                for mut trace in traces(&branch.protocol, unroll, recursions) {
+                    trace
+                        .choices
+                        .insert(0, format!("{}.{}", role.name, branch.label));
+                    result.push(trace);
+                }
+            }
+            result
+        }
+        Protocol::Loop {
+            condition, body, ..
+        } => {
+            let body = traces(body, unroll, recursions);
+            match condition {
+                Some(Condition::Count(n)) => repeat(&body, *n),
+                _ => {
+                    let decider = match condition {
+                        Some(Condition::RoleDecides(role)) => format!("{}.", role.name),
+                        _ => String::new(),
+                    };
+                    (0..=unroll)
+                        .flat_map(|n| {
+                            let choice = format!("{decider}loop({n})");
+                            repeat(&body, n).into_iter().map(move |mut trace| {
+                                trace.choices.insert(0, choice.clone());
+                                trace
+                            })
+                        })
+                        .collect()
+                }
+            }
+        }
+        Protocol::Rec { label, body, .. } => {
+            recursions.push((label.to_string(), body, unroll));
+            let result = traces(body, unroll, recursions);
+            recursions.pop();
+            result
+        }
+        Protocol::Var(label) => {
+            let Some(index) = recursions.iter().rposition(|(rec, _, _)| *label == *rec) else {
+                return vec![GoldenTrace::default()];
+            };
+            let (_, body, remaining) = recursions[index];
+            if remaining == 0 {
+                return Vec::new();
+            }
+            // The jump leaves any recursion nested inside the target
+            let mut enclosing = recursions[..index].to_vec();
+            enclosing.push((label.to_string(), body, remaining - 1));
+            traces(body, unroll, &mut enclosing)
+        }
+        Protocol::Parallel { protocols, .. } => {
+            let mut result = vec![GoldenTrace::default()];
+            for protocol in protocols {
+                result = concat(&result, &traces(protocol, unroll, recursions));
+            }
+            result
+        }
+        Protocol::Extension { continuation, .. } => traces(continuation, unroll, recursions),
+        Protocol::End => vec![GoldenTrace::default()],
+    }
+}
+
+fn prepend(prefix: &[GoldenStep], mut traces: Vec<GoldenTrace>) -> Vec<GoldenTrace> {
+    for trace in &mut traces {
+        trace.steps.splice(0..0, prefix.iter().cloned());
+    }
+    traces
+}
+
+/// Every trace of `first` followed by every trace of `second`
+fn concat(first: &[GoldenTrace], second: &[GoldenTrace]) -> Vec<GoldenTrace> {
+    first
+        .iter()
+        .flat_map(|head| {
+            second.iter().map(move |tail| {
+                let mut trace = head.clone();
+                trace.choices.extend(tail.choices.iter().cloned());
+                trace.steps.extend(tail.steps.iter().cloned());
+                trace
+            })
+        })
+        .collect()
+}
+
+/// Traces of running a body with traces `body` `n` times
+fn repeat(body: &[GoldenTrace], n: usize) -> Vec<GoldenTrace> {
+    (0..n).fold(vec![GoldenTrace::default()], |result, _| {
+        concat(&result, body)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parse_choreography_str;
+    use crate::runtime::journal::{Journal, MemorySink};
+
+    #[test]
+    fn test_golden_traces_and_journal_check() {
+        let choreography = parse_choreography_str(
+            r#"
+choreography Checkout {
+    roles: Buyer, Shop
+    [@journal_facts = "order"]
+    Buyer -> Shop: Order
+    choice Shop {
+        accept: {
+            Shop -> Buyer: Invoice
+            loop (decides: Buyer) {
+                Buyer -> Shop: Question
+                Shop -> Buyer: Answer
+            }
+        }
+        reject: {
+            [@journal_facts = "refusal"]
+            Shop -> Buyer: Refusal
+        }
+    }
+}
+"#,
+        )
+        .unwrap();
+
+        let traces = golden_traces(&choreography, 1);
+        let summary: Vec<String> = traces
+            .iter()
+            .map(|trace| {
+                let labels: Vec<&str> = trace
+                    .steps
+                    .iter()
+                    .filter(|step| step.action == "send")
+                    .map(|step| step.label.as_str())
+                    .collect();
+                format!("{}: {}", trace.choices.join(", "), labels.join(" "))
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                "Shop.accept, Buyer.loop(0): Order Invoice",
+                "Shop.accept, Buyer.loop(1): Order Invoice Question Answer",
+                "Shop.reject: Order Refusal",
+            ]
+        );
+
+        let json = golden_traces_json(&traces);
+        let read: Vec<GoldenTrace> = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, traces);
+
+        let sink = MemorySink::new();
+        let journal = Journal::open(sink.clone(), "s-1").unwrap();
+        journal
+            .record("Buyer", ActionKind::Send, "Shop", "Order", "order")
+            .unwrap();
+        journal
+            .record("Shop", ActionKind::Receive, "Buyer", "Order", "order")
+            .unwrap();
+        journal
+            .record("Shop", ActionKind::Send, "Buyer", "Refusal", "refusal")
+            .unwrap();
+        journal
+            .record("Buyer", ActionKind::Receive, "Shop", "Refusal", "refusal")
+            .unwrap();
+        let records = sink.records();
+
+        let reject = &traces[2];
+        assert_eq!(reject.check_journal(&records), Ok(()));
+        assert_eq!(
+            traces[0].check_journal(&records),
+            Err(GoldenMismatch::Extra {
+                role: "Buyer".to_string(),
+                found: "Buyer receive Refusal (Shop): refusal".to_string(),
+            })
+        );
+        assert_eq!(
+            reject.check_journal(&records[..3]),
+            Err(GoldenMismatch::Missing {
+                role: "Buyer".to_string(),
+                expected: "Buyer receive Refusal (Shop): refusal".to_string(),
+            })
+        );
+    }
+}
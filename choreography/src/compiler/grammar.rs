@@ -2,31 +2,153 @@
 //!
 //! This module provides a system for dynamically composing Pest grammars by merging
 //! the base choreographic grammar with extension-provided grammar rules.
-
-use crate::extensions::{ExtensionRegistry, GrammarExtension};
-use std::collections::HashSet;
+//!
+//! The base grammar declares named extension points, one rule per
+//! [`ExtensionPoint`]. Composition parses both sides with pest_meta and edits
+//! the rule ASTs rather than the grammar text.
+//!
+//! Composing and validating a grammar costs far more than parsing a
+//! protocol, so composed grammars and the parsers running their rules are
+//! cached for the whole process, keyed by the hash of the base grammar and
+//! the extension set. Without grammar extensions the parser skips
+//! composition and uses the grammar pest_derive compiled in.
+
+use crate::compiler::parser::Rule;
+use crate::extensions::{ExtensionPoint, ExtensionRegistry, GrammarExtension};
+use pest::iterators::{Pair, Pairs};
+use pest::{Atomicity, MatchDir, ParseResult, ParserState};
+use pest_meta::ast::{Expr, Rule as GrammarRule, RuleType};
+use pest_meta::optimizer::{OptimizedExpr, OptimizedRule};
+use pest_meta::parser::{self as meta_parser, Rule as MetaRule};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// The grammar pest_derive compiles the static parser from
+pub const BASE_GRAMMAR: &str = include_str!("choreography.pest");
+
+/// A composed grammar with the rules pest_meta optimized from it and the
+/// parser running them
+#[derive(Debug)]
+pub struct ComposedGrammar {
+    source: String,
+    rules: Vec<OptimizedRule>,
+    parser: RuleParser,
+}
+
+impl ComposedGrammar {
+    /// Grammar text of the base and extension rules
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The optimized rules a parser for the grammar runs
+    pub fn rules(&self) -> &[OptimizedRule] {
+        &self.rules
+    }
+
+    /// Parse `input` as `rule` with the composed rules
+    ///
+    /// Gives the pairs the static parser would for input without extension
+    /// syntax. What an extension rule matches is a pair of the extension
+    /// point it was reached through, such as `Rule::extension_statement`,
+    /// whose rule the returned [`ExtensionMatches`] name.
+    pub fn parse<'i>(
+        &self,
+        rule: Rule,
+        input: &'i str,
+    ) -> Result<(Pairs<'i, Rule>, ExtensionMatches), Box<pest::error::Error<Rule>>> {
+        let run = Run {
+            parser: &self.parser,
+            matched: RefCell::default(),
+        };
+        let pairs =
+            pest::state(input, |state| run.call(&format!("{rule:?}"), state)).map_err(Box::new)?;
+        let matched = run
+            .matched
+            .into_inner()
+            .into_iter()
+            .map(|(at, name)| (at, name.to_string()))
+            .collect();
+        Ok((pairs, ExtensionMatches(matched)))
+    }
+}
+
+/// Extension rules a composed grammar matched extension syntax with
+#[derive(Debug, Default)]
+pub struct ExtensionMatches(HashMap<(Rule, usize), String>);
+
+impl ExtensionMatches {
+    /// Rule that matched the extension point pair `pair`
+    pub fn rule(&self, pair: &Pair<Rule>) -> Option<&str> {
+        self.0
+            .get(&(pair.as_rule(), pair.as_span().start()))
+            .map(String::as_str)
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref COMPOSED_GRAMMARS: Mutex<HashMap<u64, Arc<ComposedGrammar>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Grammar of the base grammar with the extensions of `registry`
+///
+/// Composed once per extension set and shared by every registry with the
+/// same set.
+pub fn composed_grammar(
+    registry: &ExtensionRegistry,
+) -> Result<Arc<ComposedGrammar>, GrammarCompositionError> {
+    compose_cached(BASE_GRAMMAR, registry)
+}
+
+fn compose_cached(
+    base_grammar: &str,
+    registry: &ExtensionRegistry,
+) -> Result<Arc<ComposedGrammar>, GrammarCompositionError> {
+    let key = cache_key(base_grammar, registry);
+    let cache = || {
+        COMPOSED_GRAMMARS
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    };
+    if let Some(composed) = cache().get(&key) {
+        return Ok(composed.clone());
+    }
+    // Compose without holding the lock; a concurrent miss composes the same
+    // grammar and either result may be kept
+    let composed = Arc::new(compose(base_grammar, registry)?);
+    cache().insert(key, composed.clone());
+    Ok(composed)
+}
+
+fn cache_key(base_grammar: &str, registry: &ExtensionRegistry) -> u64 {
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = DefaultHasher::new();
+    base_grammar.hash(&mut hasher);
+    registry.grammar_hash().hash(&mut hasher);
+    hasher.finish()
+}
 
 /// Manages dynamic composition of Pest grammars with extensions
 pub struct GrammarComposer {
     base_grammar: String,
     extension_registry: ExtensionRegistry,
-    /// Cache for composed grammar to avoid recomputation
-    cached_grammar: Option<String>,
-    /// Hash of current extension state for cache invalidation
-    extension_hash: u64,
+    /// Composed grammar for the current extensions
+    cached_grammar: Option<Arc<ComposedGrammar>>,
 }
 
 impl GrammarComposer {
     /// Create a new grammar composer with the base grammar
     pub fn new() -> Self {
-        let base_grammar = include_str!("choreography.pest").to_string();
         Self {
-            base_grammar,
+            base_grammar: BASE_GRAMMAR.to_string(),
             extension_registry: ExtensionRegistry::new(),
             cached_grammar: None,
-            extension_hash: 0,
         }
     }
 
@@ -40,359 +162,523 @@ impl GrammarComposer {
     /// Invalidate the cached grammar and force recomputation
     fn invalidate_cache(&mut self) {
         self.cached_grammar = None;
-        self.extension_hash = self.compute_extension_hash();
     }
 
-    /// Compute a hash of current extensions for cache invalidation
-    fn compute_extension_hash(&self) -> u64 {
-        use std::collections::hash_map::DefaultHasher;
-        use std::hash::{Hash, Hasher};
-
-        let mut hasher = DefaultHasher::new();
-
-        // Hash extension count and IDs for simple cache invalidation
-        self.extension_registry
-            .grammar_extensions()
-            .count()
-            .hash(&mut hasher);
-        for ext in self.extension_registry.grammar_extensions() {
-            ext.extension_id().hash(&mut hasher);
-            ext.priority().hash(&mut hasher);
-        }
-
-        hasher.finish()
-    }
-
-    /// Register an extension from a trait reference
+    /// Register an extension given as a trait object
+    ///
+    /// Unlike `register_extension`, rule conflicts are reported instead of
+    /// ignored.
     pub fn register_extension_from_trait(
         &mut self,
-        _extension: &dyn GrammarExtension,
+        extension: Arc<dyn GrammarExtension>,
     ) -> Result<(), GrammarCompositionError> {
-        // For now, we can't register from trait references due to object safety
-        // In a real implementation, this would require cloning or different approach
-        Ok(())
+        let result = self
+            .extension_registry
+            .register_grammar_shared(extension)
+            .map_err(|e| GrammarCompositionError::ExtensionConflict(e.to_string()));
+        self.invalidate_cache();
+        result
     }
 
     /// Compose the final grammar including all registered extensions
     pub fn compose(&mut self) -> Result<String, GrammarCompositionError> {
-        // Check if we can use cached grammar
-        let current_hash = self.compute_extension_hash();
+        Ok(self.compose_grammar()?.source().to_string())
+    }
+
+    /// Compose the final grammar along with its optimized rules
+    ///
+    /// Composers with the same base grammar and extensions share the result.
+    pub fn compose_grammar(&mut self) -> Result<Arc<ComposedGrammar>, GrammarCompositionError> {
+        // Registering an extension invalidates the cached grammar
         if let Some(ref cached) = self.cached_grammar {
-            if current_hash == self.extension_hash {
-                return Ok(cached.clone());
-            }
+            return Ok(cached.clone());
         }
 
-        // Recompute grammar
-        let composed = self.compose_uncached()?;
-
-        // Cache the result
+        let composed = compose_cached(&self.base_grammar, &self.extension_registry)?;
         self.cached_grammar = Some(composed.clone());
-        self.extension_hash = current_hash;
-
         Ok(composed)
     }
 
-    /// Compose grammar without using cache (for internal use)
-    fn compose_uncached(&self) -> Result<String, GrammarCompositionError> {
-        let mut composed = self.base_grammar.clone();
+    /// Check if an extension rule exists
+    pub fn has_extension_rule(&self, rule_name: &str) -> bool {
+        self.extension_registry.can_handle(rule_name)
+    }
 
-        // Validate that we can safely extend the base grammar (cached validation)
-        self.validate_base_grammar_cached(&composed)?;
+    /// Get the number of registered extensions
+    pub fn extension_count(&self) -> usize {
+        self.extension_registry.grammar_extensions().count()
+    }
 
-        // Get all grammar extensions sorted by priority
-        let extension_rules = self.extension_registry.compose_grammar("");
+    /// Write the composed grammar to a file for debugging
+    pub fn write_composed_grammar<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+    ) -> Result<(), GrammarCompositionError> {
+        let composed = self.compose()?;
+        fs::write(path, composed).map_err(|e| {
+            GrammarCompositionError::IoError(format!("Failed to write grammar: {}", e))
+        })?;
+        Ok(())
+    }
+}
 
-        if !extension_rules.trim().is_empty() {
-            // Inject extension rules into the statement rule (optimized)
-            composed = self.inject_extension_rules_optimized(composed, &extension_rules)?;
+/// Compose `base_grammar` with the extensions of `registry`, uncached
+///
+/// Works on the rule ASTs pest_meta parses from the base grammar and the
+/// extensions, so layout and comments of the sources do not matter.
+/// Extension point rules get the alternatives registered for them. A rule
+/// an extension defines under the name of a base rule, or of a rule of an
+/// extension it requires, extends that rule with its body as a further
+/// alternative. Any other repeated definition is a duplicate.
+fn compose(
+    base_grammar: &str,
+    registry: &ExtensionRegistry,
+) -> Result<ComposedGrammar, GrammarCompositionError> {
+    let mut base_rules = parse_base_rules(base_grammar)?;
+    let conflict = |e: crate::extensions::ParseError| {
+        GrammarCompositionError::ExtensionConflict(e.to_string())
+    };
+
+    for point in ExtensionPoint::ALL {
+        let alternatives = registry
+            .extension_point_rules(point)
+            .map_err(conflict)?
+            .into_iter()
+            .map(|name| Expr::Ident(name.to_string()))
+            .collect();
+        if let Some(expr) = choice(alternatives) {
+            if let Some(rule) = base_rules.iter_mut().find(|r| r.name == point.rule_name()) {
+                rule.expr = expr;
+            }
         }
+    }
 
-        // Validate the final composed grammar (cached validation)
-        self.validate_composed_grammar_cached(&composed)?;
+    let mut extension_rules: Vec<GrammarRule> = Vec::new();
+    let mut owners: HashMap<String, &str> = HashMap::new();
+    for id in registry.resolution_order().map_err(conflict)? {
+        let Some(extension) = registry.grammar_extension(id) else {
+            continue;
+        };
+        let shadowed = registry.shadowed_rules(id);
+        let required = extension.requires();
+        let rules = parse_rules(extension.grammar_rules()).map_err(|e| {
+            GrammarCompositionError::SyntaxError(format!("extension '{}': {}", id, e))
+        })?;
 
-        Ok(composed)
+        for rule in rules {
+            if shadowed.contains(&rule.name.as_str()) {
+                continue;
+            }
+            if let Some(existing) = base_rules.iter_mut().find(|r| r.name == rule.name) {
+                extend_rule(existing, rule.expr);
+                continue;
+            }
+            match owners.get(rule.name.as_str()) {
+                None => {
+                    owners.insert(rule.name.clone(), id);
+                    extension_rules.push(rule);
+                }
+                Some(owner) if required.contains(owner) => {
+                    if let Some(existing) = extension_rules.iter_mut().find(|r| r.name == rule.name)
+                    {
+                        extend_rule(existing, rule.expr);
+                    }
+                }
+                Some(_) => return Err(GrammarCompositionError::DuplicateRule(rule.name)),
+            }
+        }
     }
 
-    /// Inject extension rules into the base grammar
-    #[allow(dead_code)]
-    fn inject_extension_rules(
-        &self,
-        mut base_grammar: String,
-        extension_rules: &str,
-    ) -> Result<String, GrammarCompositionError> {
-        // Find the statement rule and inject extension rules
-        let _statement_rule_start = base_grammar.find("annotated_stmt = {").ok_or(
-            GrammarCompositionError::InvalidBaseGrammar(
-                "Could not find annotated_stmt rule".to_string(),
-            ),
-        )?;
-
-        // Find the end of the statement alternatives
-        let alternatives_start = base_grammar
-            .find("annotation* ~ (send_stmt | broadcast_stmt")
-            .ok_or(GrammarCompositionError::InvalidBaseGrammar(
-                "Could not find statement alternatives".to_string(),
-            ))?;
-
-        let alternatives_end = base_grammar[alternatives_start..].find(")").ok_or(
-            GrammarCompositionError::InvalidBaseGrammar(
-                "Could not find end of statement alternatives".to_string(),
-            ),
-        )? + alternatives_start;
+    let mut composed = render_rules(&base_rules);
+    if !extension_rules.is_empty() {
+        composed.push_str("\n// Extension Rules\n");
+        composed.push_str(&render_rules(&extension_rules));
+    }
 
-        // Extract extension statement rules
-        let extension_statements = self.extract_extension_statements(extension_rules)?;
+    let rules = validate_composed_grammar(&composed)?;
+    let parser = RuleParser::new(&rules)?;
 
-        if !extension_statements.is_empty() {
-            // Insert extension statements into the alternatives
-            let before_end = &base_grammar[..alternatives_end];
-            let after_end = &base_grammar[alternatives_end..];
+    Ok(ComposedGrammar {
+        source: composed,
+        rules,
+        parser,
+    })
+}
 
-            let extension_alternatives = extension_statements.join(" | ");
-            base_grammar = format!("{} | {}{}", before_end, extension_alternatives, after_end);
+/// Parse the base grammar, checking that it declares every extension point
+fn parse_base_rules(grammar: &str) -> Result<Vec<GrammarRule>, GrammarCompositionError> {
+    let rules = parse_rules(grammar).map_err(GrammarCompositionError::InvalidBaseGrammar)?;
+    for point in ExtensionPoint::ALL {
+        if !rules.iter().any(|r| r.name == point.rule_name()) {
+            return Err(GrammarCompositionError::InvalidBaseGrammar(format!(
+                "Missing extension point: {}",
+                point.rule_name()
+            )));
         }
-
-        // Append extension rule definitions at the end
-        base_grammar.push('\n');
-        base_grammar.push_str("// Extension Rules\n");
-        base_grammar.push_str(extension_rules);
-
-        Ok(base_grammar)
     }
+    Ok(rules)
+}
 
-    /// Optimized version of inject_extension_rules with pre-compiled patterns
-    fn inject_extension_rules_optimized(
-        &self,
-        mut base_grammar: String,
-        extension_rules: &str,
-    ) -> Result<String, GrammarCompositionError> {
-        // Cache frequently used search patterns
-        static STATEMENT_RULE_PATTERN: &str = "annotated_stmt = {";
-        static ALTERNATIVES_PATTERN: &str = "annotation* ~ (send_stmt | broadcast_stmt";
-
-        let _statement_rule_start = base_grammar.find(STATEMENT_RULE_PATTERN).ok_or(
-            GrammarCompositionError::InvalidBaseGrammar(
-                "Could not find annotated_stmt rule".to_string(),
-            ),
-        )?;
+/// Validate the composed grammar the way pest_derive would
+///
+/// Catches undefined and duplicate rules, left recursion and repetitions
+/// of expressions that can match nothing.
+fn validate_composed_grammar(grammar: &str) -> Result<Vec<OptimizedRule>, GrammarCompositionError> {
+    pest_meta::parse_and_optimize(grammar)
+        .map(|(_, rules)| rules)
+        .map_err(|errors| GrammarCompositionError::SyntaxError(join_errors(&errors)))
+}
 
-        // Find the end of the statement alternatives (optimized search)
-        let alternatives_start = base_grammar.find(ALTERNATIVES_PATTERN).ok_or(
-            GrammarCompositionError::InvalidBaseGrammar(
-                "Could not find statement alternatives".to_string(),
-            ),
-        )?;
+/// Parse Pest grammar text into rule ASTs
+fn parse_rules(grammar: &str) -> Result<Vec<GrammarRule>, String> {
+    let pairs = meta_parser::parse(MetaRule::grammar_rules, grammar).map_err(|e| e.to_string())?;
+    meta_parser::consume_rules(pairs).map_err(|errors| join_errors(&errors))
+}
 
-        let alternatives_end = base_grammar[alternatives_start..].find(")").ok_or(
-            GrammarCompositionError::InvalidBaseGrammar(
-                "Could not find end of statement alternatives".to_string(),
-            ),
-        )? + alternatives_start;
+fn join_errors(errors: &[pest::error::Error<MetaRule>]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
-        // Extract extension statements with optimized parsing
-        let extension_statements = self.extract_extension_statements_optimized(extension_rules)?;
+/// Ordered choice of `alternatives`, `None` if there are none
+fn choice(alternatives: Vec<Expr>) -> Option<Expr> {
+    alternatives
+        .into_iter()
+        .rev()
+        .reduce(|rest, first| Expr::Choice(Box::new(first), Box::new(rest)))
+}
 
-        if !extension_statements.is_empty() {
-            // Use String capacity planning to reduce allocations
-            let extension_alternatives = extension_statements.join(" | ");
-            let estimated_size = base_grammar.len() + extension_alternatives.len() + 3;
+/// Add `addition` as the last alternative of `rule`
+fn extend_rule(rule: &mut GrammarRule, addition: Expr) {
+    let mut alternatives = Vec::new();
+    flatten_choice(
+        std::mem::replace(&mut rule.expr, Expr::Ident(String::new())),
+        &mut alternatives,
+    );
+    alternatives.push(addition);
+    if let Some(expr) = choice(alternatives) {
+        rule.expr = expr;
+    }
+}
 
-            let mut new_grammar = String::with_capacity(estimated_size);
-            new_grammar.push_str(&base_grammar[..alternatives_end]);
-            new_grammar.push_str(" | ");
-            new_grammar.push_str(&extension_alternatives);
-            new_grammar.push_str(&base_grammar[alternatives_end..]);
-            base_grammar = new_grammar;
+/// Collect the alternatives of a possibly nested choice in order
+fn flatten_choice(expr: Expr, alternatives: &mut Vec<Expr>) {
+    match expr {
+        Expr::Choice(first, rest) => {
+            flatten_choice(*first, alternatives);
+            flatten_choice(*rest, alternatives);
         }
+        other => alternatives.push(other),
+    }
+}
 
-        // Append extension rule definitions efficiently
-        base_grammar.reserve(extension_rules.len() + 30);
-        base_grammar.push('\n');
-        base_grammar.push_str("// Extension Rules\n");
-        base_grammar.push_str(extension_rules);
-
-        Ok(base_grammar)
+/// Render rule ASTs back into Pest grammar text
+fn render_rules(rules: &[GrammarRule]) -> String {
+    let mut grammar = String::new();
+    for rule in rules {
+        let modifier = match rule.ty {
+            RuleType::Normal => "",
+            RuleType::Silent => "_",
+            RuleType::Atomic => "@",
+            RuleType::CompoundAtomic => "$",
+            RuleType::NonAtomic => "!",
+        };
+        grammar.push_str(&format!(
+            "{} = {}{{ {} }}\n",
+            rule.name, modifier, rule.expr
+        ));
     }
+    grammar
+}
 
-    /// Extract statement rule names from extension grammar
-    #[allow(dead_code)]
-    fn extract_extension_statements(
-        &self,
-        extension_rules: &str,
-    ) -> Result<Vec<String>, GrammarCompositionError> {
-        let mut statements = Vec::new();
-
-        for line in extension_rules.lines() {
-            let line = line.trim();
-            if line.contains("=") && line.ends_with("_stmt = {") {
-                if let Some(rule_name) = line.split('=').next() {
-                    statements.push(rule_name.trim().to_string());
-                }
-            }
-        }
+/// Builtin rules [`RuleParser`] runs
+const BUILTINS: &[&str] = &[
+    "ANY",
+    "EOI",
+    "SOI",
+    "PEEK",
+    "PEEK_ALL",
+    "POP",
+    "POP_ALL",
+    "DROP",
+    "ASCII_DIGIT",
+    "ASCII_NONZERO_DIGIT",
+    "ASCII_BIN_DIGIT",
+    "ASCII_OCT_DIGIT",
+    "ASCII_HEX_DIGIT",
+    "ASCII_ALPHA_LOWER",
+    "ASCII_ALPHA_UPPER",
+    "ASCII_ALPHA",
+    "ASCII_ALPHANUMERIC",
+    "ASCII",
+    "NEWLINE",
+];
+
+type State<'i> = Box<ParserState<'i, Rule>>;
+
+/// Parser running the optimized rules of a composed grammar
+///
+/// Drives pest's `ParserState` the way the code pest_derive generates from
+/// the same rules does. Rules the static parser has make pairs of their
+/// [`Rule`]; rules extensions add have none and make no pairs of their own.
+/// Extension points with extension rules make a pair around whichever
+/// matched, although they are silent, so the syntax can be found in the
+/// tree.
+#[derive(Debug)]
+struct RuleParser {
+    rules: HashMap<String, ParserRule>,
+    whitespace: bool,
+    comment: bool,
+}
 
-        Ok(statements)
-    }
+#[derive(Debug)]
+struct ParserRule {
+    ty: RuleType,
+    expr: OptimizedExpr,
+    /// Pair the rule makes, if the static parser knows it
+    kind: Option<Rule>,
+    /// Extension rules tried in order, if the rule is an extension point
+    /// extensions added rules to
+    alternatives: Option<Vec<String>>,
+}
 
-    /// Optimized version of extract_extension_statements
-    fn extract_extension_statements_optimized(
-        &self,
-        extension_rules: &str,
-    ) -> Result<Vec<String>, GrammarCompositionError> {
-        let mut statements = Vec::new();
-
-        // Pre-allocate based on estimated number of rules
-        let estimated_rules = extension_rules.matches("_stmt = {").count();
-        statements.reserve(estimated_rules);
-
-        for line in extension_rules.lines() {
-            let line = line.trim();
-            if line.ends_with("_stmt = {") {
-                if let Some(equals_pos) = line.find('=') {
-                    let rule_name = line[..equals_pos].trim();
-                    statements.push(rule_name.to_string());
+impl RuleParser {
+    fn new(rules: &[OptimizedRule]) -> Result<Self, GrammarCompositionError> {
+        let kinds: HashMap<String, Rule> = Rule::all_rules()
+            .iter()
+            .map(|rule| (format!("{rule:?}"), *rule))
+            .collect();
+        let parser_rules: HashMap<String, ParserRule> = rules
+            .iter()
+            .map(|rule| {
+                let point = ExtensionPoint::ALL
+                    .iter()
+                    .any(|point| point.rule_name() == rule.name);
+                let parser_rule = ParserRule {
+                    ty: rule.ty,
+                    expr: rule.expr.clone(),
+                    kind: kinds.get(&rule.name).copied(),
+                    alternatives: point.then(|| extension_alternatives(&rule.expr)).flatten(),
+                };
+                (rule.name.clone(), parser_rule)
+            })
+            .collect();
+
+        // pest_meta has checked that every other name is a builtin
+        for rule in rules {
+            for expr in rule.expr.iter_top_down() {
+                if let OptimizedExpr::Ident(name) = expr {
+                    if !parser_rules.contains_key(&name) && !BUILTINS.contains(&name.as_str()) {
+                        return Err(GrammarCompositionError::SyntaxError(format!(
+                            "rule {} uses the builtin {}, which composed grammars do not support",
+                            rule.name, name
+                        )));
+                    }
                 }
             }
         }
 
-        Ok(statements)
+        Ok(Self {
+            whitespace: parser_rules.contains_key("WHITESPACE"),
+            comment: parser_rules.contains_key("COMMENT"),
+            rules: parser_rules,
+        })
     }
+}
 
-    /// Validate that the base grammar has the required extension points
-    #[allow(dead_code)]
-    fn validate_base_grammar(&self, grammar: &str) -> Result<(), GrammarCompositionError> {
-        let required_rules = [
-            "annotated_stmt = {",
-            "annotation* ~",
-            "send_stmt",
-            "broadcast_stmt",
-        ];
-
-        for rule in &required_rules {
-            if !grammar.contains(rule) {
-                return Err(GrammarCompositionError::InvalidBaseGrammar(format!(
-                    "Missing required rule: {}",
-                    rule
-                )));
-            }
+/// Names of the rules an extension point is a choice of, `None` if it is
+/// anything else
+fn extension_alternatives(expr: &OptimizedExpr) -> Option<Vec<String>> {
+    match expr {
+        OptimizedExpr::Ident(name) => Some(vec![name.clone()]),
+        OptimizedExpr::Choice(first, rest) => {
+            let mut names = extension_alternatives(first)?;
+            names.extend(extension_alternatives(rest)?);
+            Some(names)
         }
-
-        Ok(())
+        _ => None,
     }
+}
 
-    /// Optimized validation with static patterns and early exit
-    fn validate_base_grammar_cached(&self, grammar: &str) -> Result<(), GrammarCompositionError> {
-        // Use static patterns for better performance
-        const REQUIRED_PATTERNS: &[&str] = &[
-            "annotated_stmt = {",
-            "annotation* ~",
-            "send_stmt",
-            "broadcast_stmt",
-        ];
-
-        for &pattern in REQUIRED_PATTERNS {
-            if !grammar.contains(pattern) {
-                return Err(GrammarCompositionError::InvalidBaseGrammar(format!(
-                    "Missing required rule: {}",
-                    pattern
-                )));
-            }
-        }
-
-        Ok(())
-    }
+/// One parse with a [`RuleParser`]
+struct Run<'p> {
+    parser: &'p RuleParser,
+    /// Extension rule matched at each extension point pair, by the pair's
+    /// rule and start
+    matched: RefCell<HashMap<(Rule, usize), &'p str>>,
+}
 
-    /// Validate the composed grammar for common issues
-    #[allow(dead_code)]
-    fn validate_composed_grammar(&self, grammar: &str) -> Result<(), GrammarCompositionError> {
-        // Check for duplicate rule names
-        let mut rule_names = HashSet::new();
-
-        for line in grammar.lines() {
-            let line = line.trim();
-            if line.contains(" = {") && !line.starts_with("//") {
-                if let Some(rule_name) = line.split(" = {").next() {
-                    let rule_name = rule_name.trim();
-                    if rule_names.contains(rule_name) {
-                        return Err(GrammarCompositionError::DuplicateRule(
-                            rule_name.to_string(),
-                        ));
+impl<'p> Run<'p> {
+    fn call<'i>(&self, name: &str, state: State<'i>) -> ParseResult<State<'i>> {
+        let Some(rule) = self.parser.rules.get(name) else {
+            return builtin(name, state);
+        };
+
+        let body = |state: State<'i>| match (&rule.alternatives, rule.kind) {
+            (Some(alternatives), Some(kind)) => {
+                let start = state.position().pos();
+                let mut state = state;
+                for alternative in alternatives {
+                    match self.call(alternative, state) {
+                        Ok(state) => {
+                            self.matched
+                                .borrow_mut()
+                                .insert((kind, start), alternative.as_str());
+                            return Ok(state);
+                        }
+                        Err(failed) => state = failed,
                     }
-                    rule_names.insert(rule_name.to_string());
                 }
+                Err(state)
+            }
+            _ => self.expr(&rule.expr, state),
+        };
+        let body = |state: State<'i>| match (name, rule.ty) {
+            ("WHITESPACE" | "COMMENT", RuleType::Normal | RuleType::Silent) => {
+                state.atomic(Atomicity::Atomic, body)
+            }
+            _ => body(state),
+        };
+
+        match rule.ty {
+            RuleType::Silent if rule.alternatives.is_none() => body(state),
+            RuleType::Normal | RuleType::Silent => pair(rule.kind, state, body),
+            RuleType::Atomic => pair(rule.kind, state, |state| {
+                state.atomic(Atomicity::Atomic, body)
+            }),
+            RuleType::CompoundAtomic => state.atomic(Atomicity::CompoundAtomic, |state| {
+                pair(rule.kind, state, body)
+            }),
+            RuleType::NonAtomic => {
+                state.atomic(Atomicity::NonAtomic, |state| pair(rule.kind, state, body))
             }
         }
-
-        // Basic syntax validation (check balanced braces)
-        let open_braces = grammar.chars().filter(|&c| c == '{').count();
-        let close_braces = grammar.chars().filter(|&c| c == '}').count();
-
-        if open_braces != close_braces {
-            return Err(GrammarCompositionError::SyntaxError(
-                "Unbalanced braces in composed grammar".to_string(),
-            ));
-        }
-
-        Ok(())
     }
 
-    /// Optimized validation with pre-allocated HashSet and fast brace counting
-    fn validate_composed_grammar_cached(
-        &self,
-        grammar: &str,
-    ) -> Result<(), GrammarCompositionError> {
-        // Pre-allocate HashSet with estimated capacity
-        let estimated_rules = grammar.matches(" = {").count();
-        let mut rule_names = HashSet::with_capacity(estimated_rules);
-
-        for line in grammar.lines() {
-            let line = line.trim();
-            if line.contains(" = {") && !line.starts_with("//") {
-                if let Some(rule_name) = line.split(" = {").next() {
-                    let rule_name = rule_name.trim();
-                    if !rule_names.insert(rule_name) {
-                        return Err(GrammarCompositionError::DuplicateRule(
-                            rule_name.to_string(),
-                        ));
-                    }
-                }
+    fn expr<'i>(&self, expr: &OptimizedExpr, state: State<'i>) -> ParseResult<State<'i>> {
+        match expr {
+            OptimizedExpr::Str(string) => state.match_string(string),
+            OptimizedExpr::Insens(string) => state.match_insensitive(string),
+            OptimizedExpr::Range(start, end) => {
+                let first = |bound: &str| bound.chars().next().unwrap_or_default();
+                state.match_range(first(start)..first(end))
+            }
+            OptimizedExpr::Ident(name) => self.call(name, state),
+            OptimizedExpr::PeekSlice(start, end) => {
+                state.stack_match_peek_slice(*start, *end, MatchDir::BottomToTop)
+            }
+            OptimizedExpr::PosPred(inner) => state.lookahead(true, |state| self.expr(inner, state)),
+            OptimizedExpr::NegPred(inner) => {
+                state.lookahead(false, |state| self.expr(inner, state))
+            }
+            OptimizedExpr::Seq(first, second) => state.sequence(|state| {
+                self.expr(first, state)
+                    .and_then(|state| self.skip(state))
+                    .and_then(|state| self.expr(second, state))
+            }),
+            OptimizedExpr::Choice(first, second) => self
+                .expr(first, state)
+                .or_else(|state| self.expr(second, state)),
+            OptimizedExpr::Opt(inner) => state.optional(|state| self.expr(inner, state)),
+            OptimizedExpr::Rep(inner) => state.sequence(|state| {
+                state.optional(|state| {
+                    self.expr(inner, state).and_then(|state| {
+                        state.repeat(|state| {
+                            state.sequence(|state| {
+                                self.skip(state).and_then(|state| self.expr(inner, state))
+                            })
+                        })
+                    })
+                })
+            }),
+            OptimizedExpr::Skip(strings) => {
+                let strings: Vec<&str> = strings.iter().map(String::as_str).collect();
+                state.skip_until(&strings)
+            }
+            OptimizedExpr::Push(inner) => state.stack_push(|state| self.expr(inner, state)),
+            OptimizedExpr::RestoreOnErr(inner) => {
+                state.restore_on_err(|state| self.expr(inner, state))
             }
         }
-
-        // Optimized brace counting using iterator methods
-        let open_braces = grammar.chars().filter(|&c| c == '{').count();
-        let close_braces = grammar.chars().filter(|&c| c == '}').count();
-
-        if open_braces != close_braces {
-            return Err(GrammarCompositionError::SyntaxError(
-                "Unbalanced braces in composed grammar".to_string(),
-            ));
-        }
-
-        Ok(())
     }
 
-    /// Check if an extension rule exists
-    pub fn has_extension_rule(&self, rule_name: &str) -> bool {
-        self.extension_registry.can_handle(rule_name)
+    /// Implicit whitespace and comments between the parts of non-atomic
+    /// rules
+    fn skip<'i>(&self, state: State<'i>) -> ParseResult<State<'i>> {
+        if state.atomicity() != Atomicity::NonAtomic {
+            return Ok(state);
+        }
+        let whitespace = |state: State<'i>| {
+            if self.parser.whitespace {
+                state.repeat(|state| self.call("WHITESPACE", state))
+            } else {
+                Ok(state)
+            }
+        };
+        state.sequence(|state| {
+            whitespace(state).and_then(|state| {
+                if !self.parser.comment {
+                    return Ok(state);
+                }
+                state.repeat(|state| {
+                    state.sequence(|state| self.call("COMMENT", state).and_then(whitespace))
+                })
+            })
+        })
     }
+}
 
-    /// Get the number of registered extensions
-    pub fn extension_count(&self) -> usize {
-        self.extension_registry.grammar_extensions().count()
+/// Run `body` inside a pair of `kind`, or bare for rules without one
+fn pair<'i>(
+    kind: Option<Rule>,
+    state: State<'i>,
+    body: impl FnOnce(State<'i>) -> ParseResult<State<'i>>,
+) -> ParseResult<State<'i>> {
+    match kind {
+        Some(kind) => state.rule(kind, body),
+        None => body(state),
     }
+}
 
-    /// Write the composed grammar to a file for debugging
-    pub fn write_composed_grammar<P: AsRef<Path>>(
-        &mut self,
-        path: P,
-    ) -> Result<(), GrammarCompositionError> {
-        let composed = self.compose()?;
-        fs::write(path, composed).map_err(|e| {
-            GrammarCompositionError::IoError(format!("Failed to write grammar: {}", e))
-        })?;
-        Ok(())
+// Ranges are inclusive in pest, as in generated parsers
+#[allow(clippy::almost_complete_range)]
+fn builtin<'i>(name: &str, state: State<'i>) -> ParseResult<State<'i>> {
+    match name {
+        "ANY" => state.skip(1),
+        "EOI" => state.rule(Rule::EOI, |state| state.end_of_input()),
+        "SOI" => state.start_of_input(),
+        "PEEK" => state.stack_peek(),
+        "PEEK_ALL" => state.stack_match_peek(),
+        "POP" => state.stack_pop(),
+        "POP_ALL" => state.stack_match_pop(),
+        "DROP" => state.stack_drop(),
+        "ASCII_DIGIT" => state.match_range('0'..'9'),
+        "ASCII_NONZERO_DIGIT" => state.match_range('1'..'9'),
+        "ASCII_BIN_DIGIT" => state.match_range('0'..'1'),
+        "ASCII_OCT_DIGIT" => state.match_range('0'..'7'),
+        "ASCII_HEX_DIGIT" => state
+            .match_range('0'..'9')
+            .or_else(|state| state.match_range('a'..'f'))
+            .or_else(|state| state.match_range('A'..'F')),
+        "ASCII_ALPHA_LOWER" => state.match_range('a'..'z'),
+        "ASCII_ALPHA_UPPER" => state.match_range('A'..'Z'),
+        "ASCII_ALPHA" => state
+            .match_range('a'..'z')
+            .or_else(|state| state.match_range('A'..'Z')),
+        "ASCII_ALPHANUMERIC" => state
+            .match_range('a'..'z')
+            .or_else(|state| state.match_range('A'..'Z'))
+            .or_else(|state| state.match_range('0'..'9')),
+        "ASCII" => state.match_range('\x00'..'\x7f'),
+        "NEWLINE" => state
+            .match_string("\n")
+            .or_else(|state| state.match_string("\r\n"))
+            .or_else(|state| state.match_string("\r")),
+        // Rejected when the parser was built
+        _ => Err(state),
     }
 }
 
@@ -530,6 +816,42 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_register_from_trait_object() {
+        let extensions: Vec<Arc<dyn GrammarExtension>> = vec![Arc::new(TestExtension)];
+
+        let mut composer = GrammarComposer::new();
+        for extension in &extensions {
+            composer
+                .register_extension_from_trait(extension.clone())
+                .unwrap();
+        }
+        assert_eq!(composer.extension_count(), 1);
+        assert!(composer.has_extension_rule("timeout_stmt"));
+        assert!(composer.compose().unwrap().contains("timeout_stmt"));
+
+        // A second extension claiming the same rule at the same priority is rejected
+        #[derive(Debug)]
+        struct OtherTimeout;
+        impl GrammarExtension for OtherTimeout {
+            fn grammar_rules(&self) -> &'static str {
+                "timeout_stmt = { \"timeout\" ~ ident }"
+            }
+
+            fn statement_rules(&self) -> Vec<&'static str> {
+                vec!["timeout_stmt"]
+            }
+
+            fn extension_id(&self) -> &'static str {
+                "other_timeout"
+            }
+        }
+        let err = composer
+            .register_extension_from_trait(Arc::new(OtherTimeout))
+            .unwrap_err();
+        assert!(matches!(err, GrammarCompositionError::ExtensionConflict(_)));
+    }
+
     #[test]
     fn test_builder_pattern() {
         let composer = GrammarComposerBuilder::new()
@@ -545,15 +867,347 @@ mod tests {
         let mut composer = GrammarComposer::new();
 
         // Test base grammar validation
-        let valid_result = composer.validate_base_grammar(&composer.base_grammar);
+        let valid_result = parse_base_rules(&composer.base_grammar);
         assert!(valid_result.is_ok(), "Base grammar should be valid");
 
         // Test composed grammar validation
         let composed = composer.compose().unwrap();
-        let validation_result = composer.validate_composed_grammar(&composed);
+        let validation_result = validate_composed_grammar(&composed);
         assert!(
             validation_result.is_ok(),
             "Composed grammar should be valid"
         );
     }
+
+    #[derive(Debug)]
+    struct UnitExtension {
+        id: &'static str,
+        grammar: &'static str,
+        requires: Vec<&'static str>,
+    }
+
+    impl GrammarExtension for UnitExtension {
+        fn grammar_rules(&self) -> &'static str {
+            self.grammar
+        }
+
+        fn statement_rules(&self) -> Vec<&'static str> {
+            vec![]
+        }
+
+        fn expression_rules(&self) -> Vec<&'static str> {
+            vec!["duration"]
+        }
+
+        fn extension_id(&self) -> &'static str {
+            self.id
+        }
+
+        fn requires(&self) -> Vec<&'static str> {
+            self.requires.clone()
+        }
+    }
+
+    #[test]
+    fn test_extension_points_receive_alternatives() {
+        let mut composer = GrammarComposer::new();
+        composer.register_extension(TestExtension);
+        composer.register_extension(UnitExtension {
+            id: "units",
+            grammar: r#"duration = @{ ASCII_DIGIT+ ~ ("ms" | "s") }"#,
+            requires: vec![],
+        });
+
+        let composed = composer.compose().unwrap();
+        assert!(composed.contains("extension_statement = _{ timeout_stmt }"));
+        assert!(composed.contains("extension_expression = _{ duration }"));
+        assert!(composed.contains("extension_annotation = _{ (!ANY ~ ANY) }"));
+    }
+
+    #[derive(Debug)]
+    struct PolicyExtension;
+
+    impl GrammarExtension for PolicyExtension {
+        fn grammar_rules(&self) -> &'static str {
+            r#"
+policy_block = { "policy" ~ "{" ~ policy_rule* ~ "}" }
+policy_rule = { ident ~ "may" ~ ident }
+branch_weight = { "weight" ~ "(" ~ integer ~ ")" }
+witness_role = { "witness" ~ ident }
+deadline_annotation = { "@deadline" ~ "(" ~ integer ~ ")" }
+"#
+        }
+
+        fn statement_rules(&self) -> Vec<&'static str> {
+            vec![]
+        }
+
+        fn annotation_rules(&self) -> Vec<&'static str> {
+            vec!["deadline_annotation"]
+        }
+
+        fn choice_modifier_rules(&self) -> Vec<&'static str> {
+            vec!["branch_weight"]
+        }
+
+        fn role_declaration_rules(&self) -> Vec<&'static str> {
+            vec!["witness_role"]
+        }
+
+        fn item_rules(&self) -> Vec<&'static str> {
+            vec!["policy_block"]
+        }
+
+        fn extension_id(&self) -> &'static str {
+            "policy"
+        }
+    }
+
+    #[test]
+    fn test_non_statement_extension_points() {
+        let mut composer = GrammarComposer::new();
+        composer.register_extension(PolicyExtension);
+
+        let composed = composer.compose().unwrap();
+        assert!(composed.contains("extension_annotation = _{ deadline_annotation }"));
+        assert!(composed.contains("extension_choice_modifier = _{ branch_weight }"));
+        assert!(composed.contains("extension_role_declaration = _{ witness_role }"));
+        assert!(composed.contains("extension_item = _{ policy_block }"));
+        assert!(composed.contains("extension_statement = _{ (!ANY ~ ANY) }"));
+        // Helper rules are only defined, not injected anywhere
+        assert!(composed.contains("policy_rule = {"));
+        assert!(!composed.contains("_{ policy_rule }"));
+    }
+
+    #[derive(Debug)]
+    struct GreetingExtension {
+        id: &'static str,
+        grammar: &'static str,
+        rule: &'static str,
+        priority: u32,
+        requires: Vec<&'static str>,
+    }
+
+    impl GrammarExtension for GreetingExtension {
+        fn grammar_rules(&self) -> &'static str {
+            self.grammar
+        }
+
+        fn statement_rules(&self) -> Vec<&'static str> {
+            vec![self.rule]
+        }
+
+        fn priority(&self) -> u32 {
+            self.priority
+        }
+
+        fn extension_id(&self) -> &'static str {
+            self.id
+        }
+
+        fn requires(&self) -> Vec<&'static str> {
+            self.requires.clone()
+        }
+    }
+
+    /// Rule the parser of a composer's grammar parses the statement
+    /// `hello x` with
+    fn greeting_rule(composer: &mut GrammarComposer) -> String {
+        let composed = composer.compose_grammar().unwrap();
+        let input = "choreography Greeting { roles: A hello x; }";
+        let (pairs, matches) = composed.parse(Rule::choreography, input).unwrap();
+        let statement = pairs
+            .flatten()
+            .find(|pair| pair.as_rule() == Rule::extension_statement)
+            .unwrap();
+        matches.rule(&statement).unwrap().to_string()
+    }
+
+    fn greeting(id: &'static str, priority: u32, requires: Vec<&'static str>) -> GreetingExtension {
+        // Every rule matches `hello <ident>`, so only the order of the
+        // alternatives decides which one parses a greeting
+        let (rule, grammar) = match id {
+            "formal" => ("formal_stmt", r#"formal_stmt = { "hello" ~ ident }"#),
+            "casual" => (
+                "casual_stmt",
+                r#"casual_stmt = { "hello" ~ ident ~ ident? }"#,
+            ),
+            _ => ("loud_stmt", r#"loud_stmt = { "hello" ~ ident ~ "!"? }"#),
+        };
+        GreetingExtension {
+            id,
+            grammar,
+            rule,
+            priority,
+            requires,
+        }
+    }
+
+    #[test]
+    fn test_priority_orders_overlapping_alternatives() {
+        // Registration order must not matter
+        for ids in [["formal", "casual", "loud"], ["loud", "casual", "formal"]] {
+            let mut composer = GrammarComposer::new();
+            for id in ids {
+                let priority = match id {
+                    "casual" => 300,
+                    "formal" => 50,
+                    _ => 150,
+                };
+                composer.register_extension(greeting(id, priority, vec![]));
+            }
+            let composed = composer.compose().unwrap();
+            assert!(
+                composed
+                    .contains("extension_statement = _{ (casual_stmt | loud_stmt | formal_stmt) }"),
+                "{composed}"
+            );
+            assert_eq!(greeting_rule(&mut composer), "casual_stmt");
+        }
+
+        // Equal priorities fall back to the extension id
+        let mut composer = GrammarComposer::new();
+        composer.register_extension(greeting("loud", 100, vec![]));
+        composer.register_extension(greeting("formal", 100, vec![]));
+        let composed = composer.compose().unwrap();
+        assert!(composed.contains("extension_statement = _{ (formal_stmt | loud_stmt) }"));
+        assert_eq!(greeting_rule(&mut composer), "formal_stmt");
+    }
+
+    #[test]
+    fn test_priority_beats_dependency_order() {
+        // `casual` is composed after `formal`, which it requires, but its
+        // higher priority still puts its alternative first
+        let mut composer = GrammarComposer::new();
+        composer.register_extension(greeting("formal", 50, vec![]));
+        composer.register_extension(greeting("casual", 300, vec!["formal"]));
+        assert_eq!(
+            composer.extension_registry.resolution_order().unwrap(),
+            vec!["formal", "casual"]
+        );
+        let composed = composer.compose().unwrap();
+        assert!(composed.contains("extension_statement = _{ (casual_stmt | formal_stmt) }"));
+        assert_eq!(greeting_rule(&mut composer), "casual_stmt");
+    }
+
+    #[test]
+    fn test_reformatted_base_grammar() {
+        let mut composer = GrammarComposer::new();
+        composer.base_grammar = composer
+            .base_grammar
+            .replace(" = {", "={")
+            .replace(" | ", "\n    | ");
+        composer.register_extension(TestExtension);
+
+        let composed = composer.compose().unwrap();
+        assert!(composed.contains("extension_statement = _{ timeout_stmt }"));
+
+        composer.base_grammar = composer
+            .base_grammar
+            .replace("extension_expression", "value_hook");
+        composer.invalidate_cache();
+        assert!(matches!(
+            composer.compose(),
+            Err(GrammarCompositionError::InvalidBaseGrammar(_))
+        ));
+    }
+
+    #[test]
+    fn test_extensions_extend_existing_rules() {
+        let mut composer = GrammarComposer::new();
+        composer.register_extension(UnitExtension {
+            id: "units",
+            grammar: r#"
+                duration = @{ ASCII_DIGIT+ ~ ("ms" | "s") }
+                role_param_expr = { "any" }
+            "#,
+            requires: vec![],
+        });
+        composer.register_extension(UnitExtension {
+            id: "long_units",
+            grammar: r#"duration = @{ ASCII_DIGIT+ ~ ("h" | "d") }"#,
+            requires: vec!["units"],
+        });
+
+        let composed = composer.compose().unwrap();
+        assert!(composed
+            .contains(r#"role_param_expr = { (multiplicity | integer | ident | "*" | "any") }"#));
+        assert!(composed.contains(
+            r#"duration = @{ ((ASCII_DIGIT+ ~ ("ms" | "s")) | (ASCII_DIGIT+ ~ ("h" | "d"))) }"#
+        ));
+
+        // Without declaring the requirement the second definition is a duplicate
+        let mut composer = GrammarComposer::new();
+        composer.register_extension(UnitExtension {
+            id: "units",
+            grammar: r#"duration = @{ ASCII_DIGIT+ ~ "s" }"#,
+            requires: vec![],
+        });
+        composer.register_extension(UnitExtension {
+            id: "other_units",
+            grammar: r#"duration = @{ ASCII_DIGIT+ ~ "h" }"#,
+            requires: vec![],
+        });
+        assert!(matches!(
+            composer.compose(),
+            Err(GrammarCompositionError::DuplicateRule(rule)) if rule == "duration"
+        ));
+    }
+
+    #[test]
+    fn test_invalid_extension_grammar_is_rejected() {
+        let mut composer = GrammarComposer::new();
+        composer.register_extension(UnitExtension {
+            id: "broken",
+            grammar: "duration = { missing_rule }",
+            requires: vec![],
+        });
+        let err = composer.compose().unwrap_err();
+        assert!(matches!(err, GrammarCompositionError::SyntaxError(_)));
+        assert!(err.to_string().contains("missing_rule"));
+    }
+
+    #[test]
+    fn test_composers_share_composed_grammar() {
+        let mut first = GrammarComposer::new();
+        first.register_extension(greeting("formal", 1, vec![]));
+        first.register_extension(greeting("casual", 2, vec![]));
+        let mut second = GrammarComposer::new();
+        second.register_extension(greeting("casual", 2, vec![]));
+        second.register_extension(greeting("formal", 1, vec![]));
+
+        let composed = first.compose_grammar().unwrap();
+        assert!(Arc::ptr_eq(&composed, &second.compose_grammar().unwrap()));
+        assert!(composed
+            .rules()
+            .iter()
+            .any(|rule| rule.name == "formal_stmt"));
+
+        let mut other = GrammarComposer::new();
+        other.register_extension(greeting("casual", 3, vec![]));
+        other.register_extension(greeting("formal", 1, vec![]));
+        assert!(!Arc::ptr_eq(&composed, &other.compose_grammar().unwrap()));
+    }
+
+    #[test]
+    fn test_composed_grammar_for_registry() {
+        let registry = ExtensionRegistry::with_builtin_extensions();
+        let composed = composed_grammar(&registry).unwrap();
+        assert!(Arc::ptr_eq(
+            &composed,
+            &composed_grammar(&ExtensionRegistry::with_builtin_extensions()).unwrap()
+        ));
+        assert!(composed.source().contains("// Extension Rules"));
+
+        // The shared grammar parses with the parser built when it was composed
+        let (pairs, _) = composed
+            .parse(
+                Rule::choreography,
+                "choreography C { roles: A, B timeout 5s A { A -> B: M; } }",
+            )
+            .unwrap();
+        assert!(pairs
+            .flatten()
+            .any(|pair| pair.as_rule() == Rule::extension_statement));
+    }
 }
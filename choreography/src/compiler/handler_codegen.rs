@@ -0,0 +1,2502 @@
+//! Callback-style API generation
+//!
+//! With `@codegen(style = "handlers")` on a choreography, effect code
+//! generation also emits one `<Role>Handlers` trait per role and a
+//! `run_<role>_handlers` driver. The trait has a method per message the role
+//! sends (`make_<message>`) or receives (`on_<message>`), and per choice it
+//! makes (`choose_<labels>`) or is told about (`on_choice_<labels>`). The
+//! driver walks the protocol, calling the transport handler for
+//! communication and the trait for data and decisions, so users implement a
+//! trait instead of threading the session through their code.
+//!
+//! `@codegen(sync)` generates the same API without `async`, driven by a
+//! [`BlockingEndpoint`](crate::runtime::blocking::BlockingEndpoint), for
+//! programs without an async runtime.
+//!
+//! `@codegen(test_harness)` additionally emits a `Mock<Role>` per role,
+//! implementing the role's trait from a script
+//! (`MockServer::expect_receive::<Request>().then_send(Response { .. })`),
+//! and a `run_session!` macro running one implementation of every role
+//! against the others in this process. See
+//! [`runtime::harness`](crate::runtime::harness).
+//!
+//! `@codegen(proptest)` emits a `Path<Role>` per role, which takes the
+//! branches an execution path gives it and sends default message contents,
+//! and `check_conformance`, which runs every role along random paths and
+//! shrinks any that deadlock or fail. Requires the `proptest` feature; see
+//! `runtime::conformance`.
+//!
+//! A send with `or on failure { ... }` is matched on its result: if the
+//! transport reports the recipient as failed, the driver calls
+//! `on_<recipient>_failed` and runs the recovery steps. The sender tells the
+//! roles whose part differs between the outcomes which one happened, by
+//! sending them `"delivered"` or `"failed"`.
+//!
+//! A `stream` send is driven chunk by chunk: the sender's `next_<message>`
+//! returns chunks until `None`, each going out with `send_bytes` and an empty
+//! chunk ending the stream, and the receiver gets `on_<message>_chunk` per
+//! chunk, then `on_<message>_end`. Mocks and execution paths send empty
+//! streams.
+
+use crate::ast::{
+    Branch, Choreography, Condition, Protocol, Role, RoleState, ATOMIC_ABORT, ATOMIC_COMMIT,
+    DEFAULT_MESSAGE, DELIVERED, FAILED, PREPARE, VOTE, WITHIN_EXCEEDED, WITHIN_IN_TIME,
+};
+use crate::compiler::codegen::doc_attributes;
+use crate::compiler::effects_codegen::host_expr_tokens;
+use crate::compiler::projection::failure_notified;
+use crate::effects::middleware::deadlined::parse_time_limit;
+use proc_macro2::{Ident, TokenStream, TokenTree};
+use quote::{format_ident, quote};
+use std::collections::HashSet;
+
+/// Shape of the API generated for each role
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CodegenStyle {
+    /// Program builders whose interpretation returns the next state
+    #[default]
+    States,
+    /// Handler traits with a driver, in addition to the program builders
+    Handlers,
+}
+
+/// Options of the `@codegen(...)` annotation of a choreography
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CodegenOptions {
+    pub style: CodegenStyle,
+    /// Blocking code without an async runtime, `@codegen(sync)`
+    pub sync: bool,
+    /// Role mocks and a `run_session!` macro, `@codegen(test_harness)`
+    pub test_harness: bool,
+    /// Conformance checks along random execution paths, `@codegen(proptest)`
+    pub proptest: bool,
+    /// Entry points for fuzzing each role's receive path, `@codegen(fuzz)`
+    pub fuzz: bool,
+    /// State machines instead of nested session types, `@codegen(compact)`
+    pub compact: bool,
+    /// Runtime conformance monitors for every role, `@codegen(monitor)`
+    pub monitor: bool,
+}
+
+/// The `style` argument of a `@codegen` annotation is not a known style
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Unknown codegen style '{0}', expected \"states\" or \"handlers\"")]
+pub struct UnknownCodegenStyle(pub String);
+
+impl CodegenOptions {
+    /// Options given by the `@codegen(...)` annotation of `choreography`,
+    /// the defaults without one
+    pub fn from_choreography(choreography: &Choreography) -> Result<Self, UnknownCodegenStyle> {
+        let mut options = Self::default();
+        let Some(arguments) = choreography.get_attribute("codegen") else {
+            return Ok(options);
+        };
+        for argument in arguments.split(',').map(str::trim) {
+            match argument.split_once('=') {
+                Some((key, value)) if key.trim() == "style" => {
+                    options.style = match value.trim().trim_matches('"') {
+                        "states" => CodegenStyle::States,
+                        "handlers" => CodegenStyle::Handlers,
+                        other => return Err(UnknownCodegenStyle(other.to_string())),
+                    };
+                }
+                None if argument == "sync" => options.sync = true,
+                None if argument == "test_harness" => options.test_harness = true,
+                None if argument == "proptest" => options.proptest = true,
+                None if argument == "fuzz" => options.fuzz = true,
+                None if argument == "compact" => options.compact = true,
+                None if argument == "monitor" => options.monitor = true,
+                _ => {}
+            }
+        }
+        Ok(options)
+    }
+}
+
+/// Whether the generated API is async or blocking
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Async,
+    Blocking,
+}
+
+/// Generate the decision enums, handler traits and drivers of every role
+#[must_use]
+pub fn generate_handler_api(choreography: &Choreography) -> TokenStream {
+    generate_api(choreography, Target::Async)
+}
+
+/// Generate the decision enums, handler traits and drivers of every role
+/// without `async`, driving each role through a `BlockingEndpoint`
+#[must_use]
+pub fn generate_blocking_handler_api(choreography: &Choreography) -> TokenStream {
+    generate_api(choreography, Target::Blocking)
+}
+
+/// Generate a `Mock<Role>` per role and the `run_session!` macro
+///
+/// Expects the items of [`generate_handler_api`] in the same module.
+#[must_use]
+pub fn generate_test_harness(choreography: &Choreography) -> TokenStream {
+    generate_harness(choreography, Target::Async)
+}
+
+/// Generate a `Mock<Role>` per role and the `run_session!` macro for
+/// blocking code
+///
+/// Expects the items of [`generate_blocking_handler_api`] in the same module.
+#[must_use]
+pub fn generate_blocking_test_harness(choreography: &Choreography) -> TokenStream {
+    generate_harness(choreography, Target::Blocking)
+}
+
+/// Generate a path-following implementation of every role and
+/// `check_conformance`, driving them along random execution paths
+///
+/// Expects the items of [`generate_handler_api`] in the same module.
+#[must_use]
+pub fn generate_conformance_tests(choreography: &Choreography) -> TokenStream {
+    generate_conformance(choreography, Target::Async)
+}
+
+/// Generate conformance tests for blocking code
+///
+/// Expects the items of [`generate_blocking_handler_api`] in the same module.
+#[must_use]
+pub fn generate_blocking_conformance_tests(choreography: &Choreography) -> TokenStream {
+    generate_conformance(choreography, Target::Blocking)
+}
+
+/// Generate a `fuzz_<role>` function per role, running the role's driver on
+/// the choices and frames of fuzzer input
+///
+/// Expects the items of [`generate_handler_api`] in the same module.
+#[must_use]
+pub fn generate_fuzz_entry_points(choreography: &Choreography) -> TokenStream {
+    generate_fuzz(choreography, Target::Async)
+}
+
+/// Generate fuzzing entry points for blocking code
+///
+/// Expects the items of [`generate_blocking_handler_api`] in the same module.
+#[must_use]
+pub fn generate_blocking_fuzz_entry_points(choreography: &Choreography) -> TokenStream {
+    generate_fuzz(choreography, Target::Blocking)
+}
+
+/// Source of one cargo-fuzz target
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzTarget {
+    /// Target name, the file stem under `fuzz/fuzz_targets`
+    pub name: String,
+    pub source: String,
+}
+
+impl FuzzTarget {
+    /// Write the target to `<dir>/<name>.rs`
+    pub fn write_to(&self, dir: &std::path::Path) -> std::io::Result<std::path::PathBuf> {
+        let path = dir.join(format!("{}.rs", self.name));
+        std::fs::write(&path, &self.source)?;
+        Ok(path)
+    }
+}
+
+/// Generate a cargo-fuzz target per role, calling the `fuzz_<role>`
+/// function of `@codegen(fuzz)` code found at `module`, such as
+/// `my_crate::checkout`
+#[must_use]
+pub fn generate_fuzz_targets(choreography: &Choreography, module: &str) -> Vec<FuzzTarget> {
+    let protocol = snake_case(&choreography.name.to_string());
+    choreography
+        .roles
+        .iter()
+        .map(|role| FuzzTarget {
+            name: format!("{protocol}_{}", snake_case(&role.name.to_string())),
+            source: format!(
+                "#![no_main]\n\nlibfuzzer_sys::fuzz_target!(|data: &[u8]| {{\n    {module}::{}(data);\n}});\n",
+                fuzz_fn_name(role)
+            ),
+        })
+        .collect()
+}
+
+fn generate_api(choreography: &Choreography, target: Target) -> TokenStream {
+    let mut decisions = Vec::new();
+    collect_decision_enums(&choreography.protocol, &mut HashSet::new(), &mut decisions);
+    let roles = choreography
+        .roles
+        .iter()
+        .map(|role| generate_role_api(choreography, role, target));
+
+    quote! {
+        #(#decisions)*
+        #(#roles)*
+    }
+}
+
+fn generate_role_api(choreography: &Choreography, role: &Role, target: Target) -> TokenStream {
+    let mut api = RoleApi::new(choreography, role, target);
+    let body = api.drive(&choreography.protocol);
+    let asyncness = api.asyncness();
+    let methods = &api.methods;
+
+    let role_name = &role.name;
+    let protocol_name = &choreography.name;
+    let trait_name = format_ident!("{}Handlers", role_name);
+    let run_fn_name = run_fn_name(role);
+    let requirement = generate_requirement(choreography, role, &api.steps);
+    let finish_params = api.params(quote! {});
+    let finish_args = api.args(quote! {});
+    let allow_unused_state = api.allow_unused_state();
+    let state_param = api
+        .state
+        .as_ref()
+        .map(|state| quote! { state: &mut #state, });
+    let finish_doc = quote! {
+        /// Called after the role's last step
+        #asyncness fn finish(#finish_params) -> Result<()> {
+            Ok(())
+        }
+    };
+    let trait_doc = format!("Callbacks of the {role_name} role of {protocol_name}");
+    let role_doc = choreography.role_doc(role_name).map(|role_doc| {
+        let lines = doc_attributes(Some(role_doc));
+        quote! {
+            ///
+            #lines
+        }
+    });
+    let run_doc =
+        format!("Run the {role_name} role, calling `handlers` for its messages and choices");
+
+    if target == Target::Blocking {
+        return quote! {
+            #[doc = #trait_doc]
+            #role_doc
+            ///
+            /// The driver calls these as the protocol reaches the matching step.
+            #allow_unused_state
+            pub trait #trait_name {
+                #(#methods)*
+
+                #finish_doc
+            }
+
+            #[doc = #run_doc]
+            #[allow(unreachable_code, unused_variables)]
+            pub fn #run_fn_name<T, A>(
+                endpoint: &mut BlockingEndpoint<Role, T>,
+                handlers: &mut A,
+                #state_param
+            ) -> Result<()>
+            where
+                T: Transport<Role>,
+                A: #trait_name,
+            {
+                #body
+                handlers.finish(#finish_args)
+            }
+
+            #requirement
+        };
+    }
+
+    quote! {
+        #[doc = #trait_doc]
+        #role_doc
+        ///
+        /// The driver calls these as the protocol reaches the matching step.
+        #[rumpsteak_aura_choreography::async_trait]
+        #allow_unused_state
+        pub trait #trait_name: Send {
+            #(#methods)*
+
+            #finish_doc
+        }
+
+        #[doc = #run_doc]
+        #[allow(unreachable_code, unused_variables)]
+        pub async fn #run_fn_name<H, A>(
+            handler: &mut H,
+            endpoint: &mut H::Endpoint,
+            handlers: &mut A,
+            #state_param
+        ) -> Result<()>
+        where
+            H: ChoreoHandler<Role = Role>,
+            A: #trait_name,
+        {
+            #body
+            handlers.finish(#finish_args).await
+        }
+
+        #requirement
+    }
+}
+
+/// The error `#[implements_role(Protocol::Role)]` reports for a struct that
+/// does not implement the role's trait
+fn generate_requirement(choreography: &Choreography, role: &Role, steps: &[String]) -> TokenStream {
+    let role_name = &role.name;
+    let protocol_name = &choreography.name;
+    let name = format_ident!(
+        "__IMPLEMENTS_{}_{}",
+        protocol_name.to_string().to_uppercase(),
+        role_name.to_string().to_uppercase()
+    );
+    let mut message = format!(
+        "type does not implement the {role_name} role of {protocol_name}: \
+         it needs `impl {role_name}Handlers`"
+    );
+    if !steps.is_empty() {
+        message.push_str(&format!(" with {}", steps.join(", ")));
+    }
+    quote! {
+        #[doc(hidden)]
+        pub const #name: &str = #message;
+    }
+}
+
+/// One trait method with the generated implementations of it
+struct Method {
+    declaration: TokenStream,
+    mock: TokenStream,
+    path: TokenStream,
+}
+
+/// Trait methods and driver code of one role
+struct RoleApi<'a> {
+    choreography: &'a Choreography,
+    role: &'a Role,
+    target: Target,
+    methods: Vec<TokenStream>,
+    /// Implementations of `methods` playing the role from a `MockScript`
+    mock_methods: Vec<TokenStream>,
+    /// Implementations of `methods` following an `ExecutionPath`
+    path_methods: Vec<TokenStream>,
+    /// Steps repeating a message or choice share one method
+    method_names: HashSet<String>,
+    /// Names of the `methods` without a default body, in protocol order
+    steps: Vec<String>,
+    /// The role's `<Role>State` struct, passed to every method, if it
+    /// declares local state
+    state: Option<Ident>,
+    /// Deadlines of the `within` blocks this role keeps time for, innermost
+    /// last
+    within_deadlines: Vec<Ident>,
+    /// Number of `within` blocks this role kept time for
+    within_blocks: usize,
+}
+
+impl<'a> RoleApi<'a> {
+    fn new(choreography: &'a Choreography, role: &'a Role, target: Target) -> Self {
+        Self {
+            choreography,
+            role,
+            target,
+            methods: Vec::new(),
+            mock_methods: Vec::new(),
+            path_methods: Vec::new(),
+            method_names: HashSet::new(),
+            steps: Vec::new(),
+            state: choreography
+                .role_state(&role.name)
+                .map(RoleState::struct_name),
+            within_deadlines: Vec::new(),
+            within_blocks: 0,
+        }
+    }
+
+    /// Parameters of a method taking `params`, with the role's state last
+    fn params(&self, params: TokenStream) -> TokenStream {
+        let state = self
+            .state
+            .as_ref()
+            .map(|state| quote! { , state: &mut #state });
+        if params.is_empty() {
+            quote! { &mut self #state }
+        } else {
+            quote! { &mut self, #params #state }
+        }
+    }
+
+    /// Arguments of a call passing `args`, with the role's state last
+    fn args(&self, args: TokenStream) -> TokenStream {
+        match &self.state {
+            Some(_) if args.is_empty() => quote! { state },
+            Some(_) => quote! { #args, state },
+            None => args,
+        }
+    }
+
+    /// `#[allow(unused_variables)]` for trait items of a role with state,
+    /// whose default and generated bodies ignore it
+    fn allow_unused_state(&self) -> TokenStream {
+        match &self.state {
+            Some(_) => quote! { #[allow(unused_variables)] },
+            None => quote! {},
+        }
+    }
+
+    fn add_method(&mut self, name: &Ident, method: Method) {
+        if self.method_names.insert(name.to_string()) {
+            // Declarations without a default body end in `;`
+            let required = matches!(
+                method.declaration.clone().into_iter().last(),
+                Some(TokenTree::Punct(punct)) if punct.as_char() == ';'
+            );
+            if required {
+                self.steps.push(name.to_string());
+            }
+            self.methods.push(method.declaration);
+            self.mock_methods.push(method.mock);
+            self.path_methods.push(method.path);
+        }
+    }
+
+    /// `async` before trait methods
+    fn asyncness(&self) -> TokenStream {
+        match self.target {
+            Target::Async => quote! { async },
+            Target::Blocking => quote! {},
+        }
+    }
+
+    /// `.await` after calls
+    fn wait(&self) -> TokenStream {
+        match self.target {
+            Target::Async => quote! { .await },
+            Target::Blocking => quote! {},
+        }
+    }
+
+    fn send(&self, to: &Role, message_type: &Ident) -> TokenStream {
+        let send = self.send_result(to, message_type);
+        quote! { #send?; }
+    }
+
+    /// Expression sending `message`, of the protocol message `message_type`,
+    /// to `to`, as a `Result`
+    fn send_result(&self, to: &Role, message_type: &Ident) -> TokenStream {
+        let to = &to.name;
+        let name = message_type.to_string();
+        match self.target {
+            Target::Async => quote! {
+                handler.send_labelled(endpoint, Role::#to, #name, &message).await
+            },
+            Target::Blocking => quote! { endpoint.send(Role::#to, &message) },
+        }
+    }
+
+    /// Expression sending `payload` to `to` without encoding it, as a
+    /// `Result`
+    fn send_bytes(&self, to: &Role, payload: &TokenStream) -> TokenStream {
+        let to = &to.name;
+        match self.target {
+            Target::Async => quote! { handler.send_bytes(endpoint, Role::#to, #payload).await },
+            Target::Blocking => quote! { endpoint.send_bytes(Role::#to, #payload) },
+        }
+    }
+
+    /// Expression for the next chunk of a stream from `from`
+    fn recv_bytes(&self, from: &Role) -> TokenStream {
+        let from = &from.name;
+        match self.target {
+            Target::Async => quote! { handler.recv_bytes(endpoint, Role::#from).await? },
+            Target::Blocking => quote! { endpoint.recv_bytes(Role::#from)? },
+        }
+    }
+
+    /// Tell `to` the outcome `label` of an `or on failure` send
+    fn notify(&self, to: &Role, label: &str) -> TokenStream {
+        let to = &to.name;
+        match self.target {
+            Target::Async => quote! { handler.send(endpoint, Role::#to, &#label).await?; },
+            Target::Blocking => quote! { endpoint.send(Role::#to, &#label)?; },
+        }
+    }
+
+    /// Expression for the outcome of an `or on failure` send of `sender`, as
+    /// a `String`
+    fn notification(&self, sender: &Role) -> TokenStream {
+        let sender = &sender.name;
+        match self.target {
+            Target::Async => quote! { handler.recv::<String>(endpoint, Role::#sender).await? },
+            Target::Blocking => quote! { endpoint.recv::<String>(Role::#sender)? },
+        }
+    }
+
+    fn broadcast(&self, recipients: &[Role], message_type: &Ident) -> TokenStream {
+        let recipients = recipients.iter().map(|to| &to.name);
+        let name = message_type.to_string();
+        match self.target {
+            Target::Async => quote! {
+                for to in [#(Role::#recipients),*] {
+                    handler.send_labelled(endpoint, to, #name, &message).await?;
+                }
+            },
+            Target::Blocking => quote! {
+                endpoint.broadcast(&[#(Role::#recipients),*], &message)?;
+            },
+        }
+    }
+
+    fn recv(&self, from: &Role, message_type: &Ident) -> TokenStream {
+        let from = &from.name;
+        let name = message_type.to_string();
+        match self.target {
+            Target::Async => quote! {
+                let message = handler
+                    .recv_labelled::<#message_type>(endpoint, Role::#from, #name)
+                    .await?;
+            },
+            Target::Blocking => quote! {
+                let message = endpoint.recv::<#message_type>(Role::#from)?;
+            },
+        }
+    }
+
+    fn choose(&self, chooser: &Role, label: &str) -> TokenStream {
+        let chooser = &chooser.name;
+        match self.target {
+            Target::Async => quote! {
+                handler.choose(endpoint, Role::#chooser, Label(#label)).await?;
+            },
+            Target::Blocking => quote! { endpoint.choose(#label)?; },
+        }
+    }
+
+    /// Expression for the label of one of `branches` chosen by `chooser`, as
+    /// a `&str`
+    fn offer(&self, chooser: &Role, branches: &[Branch]) -> TokenStream {
+        let chooser = &chooser.name;
+        let labels = branches.iter().map(|branch| branch.label.to_string());
+        match self.target {
+            Target::Async => quote! {
+                handler.offer(endpoint, Role::#chooser, &[#(Label(#labels)),*]).await?.0
+            },
+            Target::Blocking => quote! { endpoint.offer(Role::#chooser)?.as_str() },
+        }
+    }
+
+    /// Driver statements running `protocol` for this role
+    fn drive(&mut self, protocol: &Protocol) -> TokenStream {
+        match protocol {
+            Protocol::Send {
+                from,
+                to,
+                message,
+                continuation,
+                ..
+            } => {
+                let wait = self.wait();
+                // The timekeeper of a `within` block starts its clock with
+                // the block's first send
+                let clock = match protocol.within() {
+                    Some(within) if from == self.role => self.start_clock(within),
+                    _ => quote! {},
+                };
+                if from == self.role && continuation.failure_of().is_some() {
+                    let make = if protocol.is_stream() {
+                        quote! {}
+                    } else {
+                        let make = self.make_method(&message.name, protocol);
+                        let make_args = self.args(quote! {});
+                        quote! { let message = handlers.#make(#make_args)#wait?; }
+                    };
+                    let send = self.drive_fallible_send(
+                        to,
+                        protocol.is_stream(),
+                        &message.name,
+                        continuation,
+                    );
+                    return quote! {
+                        #clock
+                        {
+                            #make
+                            #send
+                        }
+                    };
+                }
+                let vote = protocol.atomic().is_some() && message.name == VOTE;
+                let step = if protocol.is_stream() {
+                    self.drive_stream(from, to, &message.name)
+                } else if vote && from == self.role {
+                    let vote = self.vote_method(to);
+                    let vote_args = self.args(quote! {});
+                    let message_type = &message.name;
+                    let send = self.send(to, message_type);
+                    quote! {
+                        let message = #message_type(handlers.#vote(#vote_args)#wait?);
+                        #send
+                    }
+                } else if from == self.role {
+                    let make = self.make_method(&message.name, protocol);
+                    let make_args = self.args(quote! {});
+                    let send = self.send(to, &message.name);
+                    quote! {
+                        let message = handlers.#make(#make_args)#wait?;
+                        #send
+                    }
+                } else if to == self.role {
+                    let on = self.on_method(&message.name, protocol.doc());
+                    let on_args = self.args(quote! { message });
+                    let recv = self.recv(from, &message.name);
+                    let tally = if vote {
+                        quote! { all_voted_commit &= message.0; }
+                    } else {
+                        quote! {}
+                    };
+                    quote! {
+                        #recv
+                        #tally
+                        handlers.#on(#on_args)#wait?;
+                    }
+                } else {
+                    quote! {}
+                };
+                // The coordinator of an `atomic` block counts the votes from
+                // its `Prepare` on
+                let votes = if protocol.atomic().is_some()
+                    && message.name == PREPARE
+                    && from == self.role
+                {
+                    quote! { let mut all_voted_commit = true; }
+                } else {
+                    quote! {}
+                };
+                let continuation = self.drive(continuation);
+                quote! {
+                    #clock
+                    #votes
+                    { #step }
+                    #continuation
+                }
+            }
+            Protocol::Broadcast {
+                from,
+                to_all,
+                message,
+                continuation,
+                ..
+            } => {
+                let wait = self.wait();
+                let step = if from == self.role {
+                    let make = self.make_method(&message.name, protocol);
+                    let make_args = self.args(quote! {});
+                    let broadcast = self.broadcast(to_all, &message.name);
+                    quote! {
+                        let message = handlers.#make(#make_args)#wait?;
+                        #broadcast
+                    }
+                } else if to_all.contains(self.role) {
+                    let on = self.on_method(&message.name, protocol.doc());
+                    let on_args = self.args(quote! { message });
+                    let recv = self.recv(from, &message.name);
+                    quote! {
+                        #recv
+                        handlers.#on(#on_args)#wait?;
+                    }
+                } else {
+                    quote! {}
+                };
+                let continuation = self.drive(continuation);
+                quote! {
+                    { #step }
+                    #continuation
+                }
+            }
+            Protocol::Choice {
+                role: chooser,
+                branches,
+                ..
+            } => match protocol.failure_of() {
+                Some(_) => self.drive_failure_choice(chooser, protocol, branches),
+                None if protocol.atomic().is_some() && chooser == self.role => {
+                    self.drive_atomic_decision(chooser, branches)
+                }
+                None if protocol.within().is_some()
+                    && chooser == self.role
+                    && !self.within_deadlines.is_empty() =>
+                {
+                    self.drive_within_outcome(chooser, branches)
+                }
+                None => self.drive_choice(chooser, branches, protocol.doc()),
+            },
+            Protocol::Loop {
+                condition, body, ..
+            } => {
+                let body = self.drive(body);
+                match condition {
+                    Some(Condition::Count(n)) => quote! {
+                        for _ in 0..#n {
+                            #body
+                        }
+                    },
+                    // Like the program builders, other loops run once
+                    _ => quote! { { #body } },
+                }
+            }
+            Protocol::Parallel { protocols, .. } => {
+                // Run sequentially, like the program builders
+                let protocols = protocols.iter().map(|p| self.drive(p));
+                quote! { #(#protocols)* }
+            }
+            Protocol::Rec { label, body, .. } => {
+                let loop_label = loop_label(label);
+                let body = self.drive(body);
+                quote! {
+                    #loop_label: loop {
+                        #body
+                        break #loop_label;
+                    }
+                }
+            }
+            Protocol::Var(label) => {
+                let loop_label = loop_label(label);
+                quote! { continue #loop_label; }
+            }
+            // Extensions generate program builder calls, which a driver
+            // cannot run
+            Protocol::Extension { continuation, .. } => self.drive(continuation),
+            Protocol::End => quote! {},
+        }
+    }
+
+    fn drive_choice(
+        &mut self,
+        chooser: &Role,
+        branches: &[Branch],
+        statement_doc: Option<&str>,
+    ) -> TokenStream {
+        let labels = choice_labels(branches);
+        let mut alternatives: Vec<String> = branches
+            .iter()
+            .map(|branch| format!("`{}`", branch.label))
+            .collect();
+        let last = alternatives.pop().unwrap_or_default();
+        let alternatives = if alternatives.is_empty() {
+            last
+        } else {
+            format!("{} and {last}", alternatives.join(", "))
+        };
+        let decision = decision_enum_name(chooser, branches);
+        let chooser_name = &chooser.name;
+        let asyncness = self.asyncness();
+        let wait = self.wait();
+
+        if chooser == self.role {
+            let choose = format_ident!("choose_{}", labels);
+            let doc = method_doc(&format!("Decide between {alternatives}"), statement_doc);
+            let labels: Vec<String> = branches.iter().map(|b| b.label.to_string()).collect();
+            let variants: Vec<Ident> = branches
+                .iter()
+                .map(|branch| variant_name(&branch.label))
+                .collect();
+            let count = branches.len();
+            let params = self.params(quote! {});
+            let choose_args = self.args(quote! {});
+            let exits = branches
+                .iter()
+                .enumerate()
+                .filter(|(_, branch)| !continues(&branch.protocol, &mut Vec::new()))
+                .map(|(index, _)| index);
+            self.add_method(
+                &choose,
+                Method {
+                    declaration: quote! {
+                        #doc
+                        #asyncness fn #choose(#params) -> Result<#decision>;
+                    },
+                    mock: quote! {
+                        #asyncness fn #choose(#params) -> Result<#decision> {
+                            match self.script.on_choose()? {
+                                #(#labels => Ok(#decision::#variants),)*
+                                other => Err(self.script.unknown_label(other, &[#(#labels),*])),
+                            }
+                        }
+                    },
+                    path: quote! {
+                        #asyncness fn #choose(#params) -> Result<#decision> {
+                            const BRANCHES: &[#decision] = &[#(#decision::#variants),*];
+                            let branch = self.decisions.next(#count, &[#(#exits),*]);
+                            Ok(BRANCHES[branch])
+                        }
+                    },
+                },
+            );
+
+            let arms: Vec<TokenStream> = branches
+                .iter()
+                .map(|branch| {
+                    let variant = variant_name(&branch.label);
+                    let label = branch.label.to_string();
+                    let choose = self.choose(chooser, &label);
+                    let body = self.drive(&branch.protocol);
+                    quote! {
+                        #decision::#variant => {
+                            #choose
+                            #body
+                        }
+                    }
+                })
+                .collect();
+            quote! {
+                match handlers.#choose(#choose_args)#wait? {
+                    #(#arms)*
+                }
+            }
+        } else {
+            let on_choice = format_ident!("on_choice_{}", labels);
+            let doc = method_doc(
+                &format!("Called when {chooser_name} decided between {alternatives}"),
+                statement_doc,
+            );
+            let params = self.params(quote! { choice: #decision });
+            self.add_method(
+                &on_choice,
+                Method {
+                    declaration: quote! {
+                        #doc
+                        #asyncness fn #on_choice(#params) -> Result<()> {
+                            let _ = choice;
+                            Ok(())
+                        }
+                    },
+                    // Both follow whichever branch was chosen
+                    mock: quote! {},
+                    path: quote! {},
+                },
+            );
+            let arms: Vec<TokenStream> = branches
+                .iter()
+                .map(|branch| {
+                    let variant = variant_name(&branch.label);
+                    let label = branch.label.to_string();
+                    let body = self.drive(&branch.protocol);
+                    let on_choice_args = self.args(quote! { #decision::#variant });
+                    quote! {
+                        #label => {
+                            handlers.#on_choice(#on_choice_args)#wait?;
+                            #body
+                        }
+                    }
+                })
+                .collect();
+            let unexpected = format!("unexpected branch label '{{}}' from {chooser_name}");
+            let offer = self.offer(chooser, branches);
+            quote! {
+                match #offer {
+                    #(#arms)*
+                    other => {
+                        return Err(rumpsteak_aura_choreography::ChoreographyError::ProtocolViolation(
+                            format!(#unexpected, other),
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Commit if every participant voted to, and abort otherwise, as the
+    /// coordinator of an `atomic` block
+    fn drive_atomic_decision(&mut self, coordinator: &Role, branches: &[Branch]) -> TokenStream {
+        let mut decisions = Vec::new();
+        for label in [ATOMIC_COMMIT, ATOMIC_ABORT] {
+            let choose = self.choose(coordinator, label);
+            let body = branches
+                .iter()
+                .find(|branch| branch.label == label)
+                .map(|branch| self.drive(&branch.protocol))
+                .unwrap_or_default();
+            decisions.push(quote! {
+                #choose
+                #body
+            });
+        }
+        let (commit, abort) = (&decisions[0], &decisions[1]);
+        quote! {
+            if all_voted_commit {
+                #commit
+            } else {
+                #abort
+            }
+        }
+    }
+
+    /// Statement starting the clock of a `within` block with the time limit
+    /// `within`, as its timekeeper
+    fn start_clock(&mut self, within: &str) -> TokenStream {
+        let deadline = format_ident!("within_deadline_{}", self.within_blocks);
+        self.within_blocks += 1;
+        let limit = parse_time_limit(within).unwrap_or_default();
+        let millis = u64::try_from(limit.as_millis()).unwrap_or(u64::MAX);
+        self.within_deadlines.push(deadline.clone());
+        quote! {
+            let #deadline = rumpsteak_aura_choreography::runtime::Instant::now()
+                + std::time::Duration::from_millis(#millis);
+        }
+    }
+
+    /// Tell the roles of the innermost `within` block whether it ran in
+    /// time, as its timekeeper
+    fn drive_within_outcome(&mut self, timekeeper: &Role, branches: &[Branch]) -> TokenStream {
+        // The statements after the block, in both branches, may close
+        // enclosing blocks
+        let deadline = self.within_deadlines.pop().unwrap();
+        let mut outcomes = Vec::new();
+        for label in [WITHIN_IN_TIME, WITHIN_EXCEEDED] {
+            let choose = self.choose(timekeeper, label);
+            let body = branches
+                .iter()
+                .find(|branch| branch.label == label)
+                .map(|branch| self.drive(&branch.protocol))
+                .unwrap_or_default();
+            outcomes.push(quote! {
+                #choose
+                #body
+            });
+        }
+        self.within_deadlines.push(deadline.clone());
+        let (in_time, exceeded) = (&outcomes[0], &outcomes[1]);
+        quote! {
+            if rumpsteak_aura_choreography::runtime::Instant::now() <= #deadline {
+                #in_time
+            } else {
+                #exceeded
+            }
+        }
+    }
+
+    /// Send `message` to `to`, then run the branch of the failure choice
+    /// `choice` matching the outcome
+    fn drive_fallible_send(
+        &mut self,
+        to: &Role,
+        stream: bool,
+        message: &Ident,
+        choice: &Protocol,
+    ) -> TokenStream {
+        let (send, outcome) = if stream {
+            let send = self.send_stream(to, message);
+            (quote! { let sent = #send; }, quote! { sent })
+        } else {
+            (quote! {}, self.send_result(to, message))
+        };
+        let Protocol::Choice { branches, .. } = choice else {
+            return quote! { #send #outcome?; };
+        };
+        let wait = self.wait();
+        let notified = failure_notified(self.choreography, choice).unwrap_or_default();
+        let on_failed = self.on_failed_method(to);
+        let on_failed_args = self.args(quote! { error });
+        let mut outcomes = Vec::new();
+        for label in [DELIVERED, FAILED] {
+            let notify: Vec<TokenStream> = notified
+                .iter()
+                .map(|role| self.notify(role, label))
+                .collect();
+            let body = branches
+                .iter()
+                .find(|branch| branch.label == label)
+                .map(|branch| self.drive(&branch.protocol))
+                .unwrap_or_default();
+            outcomes.push(quote! {
+                #(#notify)*
+                #body
+            });
+        }
+        let (delivered, failed) = (&outcomes[0], &outcomes[1]);
+        quote! {
+            #send
+            match #outcome {
+                Ok(()) => {
+                    #delivered
+                }
+                Err(error) if error.is_peer_failure() => {
+                    handlers.#on_failed(#on_failed_args)#wait?;
+                    #failed
+                }
+                Err(error) => return Err(error),
+            }
+        }
+    }
+
+    /// Send or receive the chunks of a `stream` message from `from` to `to`
+    fn drive_stream(&mut self, from: &Role, to: &Role, message: &Ident) -> TokenStream {
+        if from == self.role {
+            let send = self.send_stream(to, message);
+            quote! { #send?; }
+        } else if to == self.role {
+            let wait = self.wait();
+            let (on_chunk, on_end) = self.on_chunk_methods(message);
+            let on_chunk_args = self.args(quote! { chunk });
+            let on_end_args = self.args(quote! {});
+            let recv = self.recv_bytes(from);
+            quote! {
+                loop {
+                    let chunk = #recv;
+                    if chunk.is_empty() {
+                        break;
+                    }
+                    handlers.#on_chunk(#on_chunk_args)#wait?;
+                }
+                handlers.#on_end(#on_end_args)#wait?;
+            }
+        } else {
+            quote! {}
+        }
+    }
+
+    /// Expression sending every chunk of a `stream` message to `to`, then the
+    /// empty chunk ending it, as a `Result`
+    fn send_stream(&mut self, to: &Role, message: &Ident) -> TokenStream {
+        let wait = self.wait();
+        let next = self.next_chunk_method(message);
+        let next_args = self.args(quote! {});
+        let send_chunk = self.send_bytes(to, &quote! { chunk });
+        let send_end = self.send_bytes(to, &quote! { rumpsteak_aura_choreography::Bytes::new() });
+        quote! {
+            {
+                let mut sent = Ok(());
+                while let Some(chunk) = handlers.#next(#next_args)#wait? {
+                    // An empty chunk would end the stream early
+                    if !chunk.is_empty() {
+                        sent = #send_chunk;
+                        if sent.is_err() {
+                            break;
+                        }
+                    }
+                }
+                match sent {
+                    Ok(()) => #send_end,
+                    error => error,
+                }
+            }
+        }
+    }
+
+    /// Run the choice following an `or on failure` send of `sender`
+    ///
+    /// Roles the sender notifies follow the outcome it reports; the others,
+    /// including the recipient, carry on as though the send was delivered.
+    fn drive_failure_choice(
+        &mut self,
+        sender: &Role,
+        choice: &Protocol,
+        branches: &[Branch],
+    ) -> TokenStream {
+        let notified = failure_notified(self.choreography, choice).unwrap_or_default();
+        if sender == self.role || !notified.iter().any(|role| role.name == self.role.name) {
+            return branches
+                .first()
+                .map(|delivered| self.drive(&delivered.protocol))
+                .unwrap_or_default();
+        }
+        let arms: Vec<TokenStream> = branches
+            .iter()
+            .map(|branch| {
+                let label = branch.label.to_string();
+                let body = self.drive(&branch.protocol);
+                quote! {
+                    #label => {
+                        #body
+                    }
+                }
+            })
+            .collect();
+        let sender_name = &sender.name;
+        let unexpected = format!("unexpected send outcome '{{}}' from {sender_name}");
+        let notification = self.notification(sender);
+        quote! {
+            match #notification.as_str() {
+                #(#arms)*
+                other => {
+                    return Err(rumpsteak_aura_choreography::ChoreographyError::ProtocolViolation(
+                        format!(#unexpected, other),
+                    ));
+                }
+            }
+        }
+    }
+
+    /// `on_<role>_failed`, deciding whether to recover when a send to `role`
+    /// fails
+    fn on_failed_method(&mut self, role: &Role) -> Ident {
+        let name = format_ident!("on_{}_failed", snake_case(&role.name.to_string()));
+        let doc = format!(
+            "Called when a send to {} fails; returning `Ok` runs the recovery steps",
+            role.name
+        );
+        let asyncness = self.asyncness();
+        let params = self.params(quote! {
+            error: rumpsteak_aura_choreography::ChoreographyError
+        });
+        self.add_method(
+            &name,
+            Method {
+                declaration: quote! {
+                    #[doc = #doc]
+                    #asyncness fn #name(#params) -> Result<()> {
+                        let _ = error;
+                        Ok(())
+                    }
+                },
+                // Both recover
+                mock: quote! {},
+                path: quote! {},
+            },
+        );
+        name
+    }
+
+    /// `vote`, deciding whether this role, a participant of an `atomic`
+    /// block of `coordinator`, can commit
+    fn vote_method(&mut self, coordinator: &Role) -> Ident {
+        let name = format_ident!("vote");
+        let doc = format!(
+            "Vote whether the transaction {} coordinates commits; `false` aborts it",
+            coordinator.name
+        );
+        let vote = format_ident!("{}", VOTE);
+        let asyncness = self.asyncness();
+        let params = self.params(quote! {});
+        self.add_method(
+            &name,
+            Method {
+                declaration: quote! {
+                    #[doc = #doc]
+                    #asyncness fn #name(#params) -> Result<bool>;
+                },
+                mock: quote! {
+                    #asyncness fn #name(#params) -> Result<bool> {
+                        self.script.on_send::<#vote>().map(|vote| vote.0)
+                    }
+                },
+                // Paths vote either way
+                path: quote! {
+                    #asyncness fn #name(#params) -> Result<bool> {
+                        Ok(self.decisions.next(2, &[]) == 0)
+                    }
+                },
+            },
+        );
+        name
+    }
+
+    /// `make_<message>`, producing a message this role sends
+    fn make_method(&mut self, message: &Ident, protocol: &Protocol) -> Ident {
+        let name = format_ident!("make_{}", snake_case(&message.to_string()));
+        let doc = method_doc(
+            &format!("Produce the {message} this role sends"),
+            protocol.doc(),
+        );
+        // Paths send the statement's `@default` host expression if it has one
+        let value = protocol
+            .get_annotations()
+            .get(DEFAULT_MESSAGE)
+            .and_then(|value| host_expr_tokens(value))
+            .unwrap_or_else(|| quote! { #message(Default::default()) });
+        let asyncness = self.asyncness();
+        let params = self.params(quote! {});
+        self.add_method(
+            &name,
+            Method {
+                declaration: quote! {
+                    #doc
+                    #asyncness fn #name(#params) -> Result<#message>;
+                },
+                mock: quote! {
+                    #asyncness fn #name(#params) -> Result<#message> {
+                        self.script.on_send::<#message>()
+                    }
+                },
+                path: quote! {
+                    #asyncness fn #name(#params) -> Result<#message> {
+                        Ok(#value)
+                    }
+                },
+            },
+        );
+        name
+    }
+
+    /// `next_<message>`, producing the chunks of a `stream` message
+    fn next_chunk_method(&mut self, message: &Ident) -> Ident {
+        let name = format_ident!("next_{}", snake_case(&message.to_string()));
+        let doc = format!("Produce the next chunk of the {message} stream, `None` once done");
+        let asyncness = self.asyncness();
+        let chunk = quote! { Result<Option<rumpsteak_aura_choreography::Bytes>> };
+        let params = self.params(quote! {});
+        self.add_method(
+            &name,
+            Method {
+                declaration: quote! {
+                    #[doc = #doc]
+                    #asyncness fn #name(#params) -> #chunk;
+                },
+                // Both send an empty stream
+                mock: quote! {
+                    #asyncness fn #name(#params) -> #chunk {
+                        Ok(None)
+                    }
+                },
+                path: quote! {
+                    #asyncness fn #name(#params) -> #chunk {
+                        Ok(None)
+                    }
+                },
+            },
+        );
+        name
+    }
+
+    /// `on_<message>_chunk` and `on_<message>_end`, handling the chunks of a
+    /// received `stream` message
+    fn on_chunk_methods(&mut self, message: &Ident) -> (Ident, Ident) {
+        let snake = snake_case(&message.to_string());
+        let on_chunk = format_ident!("on_{}_chunk", snake);
+        let on_end = format_ident!("on_{}_end", snake);
+        let chunk_doc = format!("Handle a received chunk of the {message} stream");
+        let end_doc = format!("Called once the {message} stream has ended");
+        let asyncness = self.asyncness();
+        let chunk = quote! { rumpsteak_aura_choreography::Bytes };
+        let chunk_params = self.params(quote! { chunk: #chunk });
+        let end_params = self.params(quote! {});
+        self.add_method(
+            &on_chunk,
+            Method {
+                declaration: quote! {
+                    #[doc = #chunk_doc]
+                    #asyncness fn #on_chunk(#chunk_params) -> Result<()>;
+                },
+                // Both ignore the chunks
+                mock: quote! {
+                    #asyncness fn #on_chunk(#chunk_params) -> Result<()> {
+                        let _ = chunk;
+                        Ok(())
+                    }
+                },
+                path: quote! {
+                    #asyncness fn #on_chunk(#chunk_params) -> Result<()> {
+                        let _ = chunk;
+                        Ok(())
+                    }
+                },
+            },
+        );
+        self.add_method(
+            &on_end,
+            Method {
+                declaration: quote! {
+                    #[doc = #end_doc]
+                    #asyncness fn #on_end(#end_params) -> Result<()> {
+                        Ok(())
+                    }
+                },
+                mock: quote! {},
+                path: quote! {},
+            },
+        );
+        (on_chunk, on_end)
+    }
+
+    /// `on_<message>`, handling a message this role receives
+    fn on_method(&mut self, message: &Ident, statement_doc: Option<&str>) -> Ident {
+        let name = format_ident!("on_{}", snake_case(&message.to_string()));
+        let doc = method_doc(&format!("Handle a received {message}"), statement_doc);
+        let asyncness = self.asyncness();
+        let params = self.params(quote! { message: #message });
+        self.add_method(
+            &name,
+            Method {
+                declaration: quote! {
+                    #doc
+                    #asyncness fn #name(#params) -> Result<()>;
+                },
+                mock: quote! {
+                    #asyncness fn #name(#params) -> Result<()> {
+                        self.script.on_receive(&message)
+                    }
+                },
+                path: quote! {
+                    #asyncness fn #name(#params) -> Result<()> {
+                        let _ = message;
+                        Ok(())
+                    }
+                },
+            },
+        );
+        name
+    }
+}
+
+/// Doc comment of a generated method, followed by the `///` comment of the
+/// statement it stems from
+fn method_doc(doc: &str, statement_doc: Option<&str>) -> TokenStream {
+    let statement_doc = statement_doc.map(|statement_doc| {
+        let lines = doc_attributes(Some(statement_doc));
+        quote! {
+            #[doc = ""]
+            #lines
+        }
+    });
+    quote! {
+        #[doc = #doc]
+        #statement_doc
+    }
+}
+
+fn generate_harness(choreography: &Choreography, target: Target) -> TokenStream {
+    let mocks = choreography
+        .roles
+        .iter()
+        .map(|role| generate_mock(choreography, role, target));
+    let run_session = generate_run_session(choreography, target);
+
+    quote! {
+        #(#mocks)*
+        #run_session
+    }
+}
+
+fn generate_mock(choreography: &Choreography, role: &Role, target: Target) -> TokenStream {
+    let mut api = RoleApi::new(choreography, role, target);
+    api.drive(&choreography.protocol);
+    let asyncness = api.asyncness();
+    let finish_params = api.params(quote! {});
+    let allow_unused_state = api.allow_unused_state();
+    let mock_methods = api.mock_methods;
+
+    let role_name = &role.name;
+    let mock_name = format_ident!("Mock{}", role_name);
+    let trait_name = format_ident!("{}Handlers", role_name);
+    let mock_label = mock_name.to_string();
+    let doc = format!(
+        "Scripted stand-in for the {role_name} role of {} in tests",
+        choreography.name
+    );
+    let async_trait = match target {
+        Target::Async => quote! { #[rumpsteak_aura_choreography::async_trait] },
+        Target::Blocking => quote! {},
+    };
+
+    quote! {
+        #[doc = #doc]
+        ///
+        /// Each step of the script must match the next step the protocol
+        /// takes for this role; the session fails with a protocol violation
+        /// otherwise, or when steps are left at the end.
+        pub struct #mock_name {
+            script: rumpsteak_aura_choreography::runtime::harness::MockScript,
+        }
+
+        impl #mock_name {
+            /// Mock with an empty script
+            #[must_use]
+            pub fn new() -> Self {
+                Self {
+                    script: rumpsteak_aura_choreography::runtime::harness::MockScript::new(
+                        #mock_label,
+                    ),
+                }
+            }
+
+            /// Mock expecting to receive an `M` first
+            #[must_use]
+            pub fn expect_receive<M: 'static>() -> Self {
+                Self::new().then_receive::<M>()
+            }
+
+            /// Then expect to receive an `M`
+            #[must_use]
+            pub fn then_receive<M: 'static>(mut self) -> Self {
+                self.script.receive::<M>();
+                self
+            }
+
+            /// Then expect to receive an `M` for which `check` holds
+            #[must_use]
+            pub fn then_receive_where<M: 'static>(
+                mut self,
+                check: impl Fn(&M) -> bool + Send + 'static,
+            ) -> Self {
+                self.script.receive_where(check);
+                self
+            }
+
+            /// Then send `message`
+            #[must_use]
+            pub fn then_send<M: Send + 'static>(mut self, message: M) -> Self {
+                self.script.send(message);
+                self
+            }
+
+            /// Then pick the branch labelled `label`
+            #[must_use]
+            pub fn then_choose(mut self, label: &'static str) -> Self {
+                self.script.choose(label);
+                self
+            }
+        }
+
+        impl Default for #mock_name {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        #async_trait
+        #allow_unused_state
+        impl #trait_name for #mock_name {
+            #(#mock_methods)*
+
+            #asyncness fn finish(#finish_params) -> Result<()> {
+                self.script.finish()
+            }
+        }
+    }
+}
+
+/// `run_session!`, running one implementation of every role, given as
+/// `Role => handlers`, in this process
+fn generate_run_session(choreography: &Choreography, target: Target) -> TokenStream {
+    let roles = &choreography.roles;
+    let role_names: Vec<_> = roles.iter().map(|role| &role.name).collect();
+    let run_fns: Vec<_> = roles.iter().map(run_fn_name).collect();
+    let initial_states: Vec<_> = roles
+        .iter()
+        .map(|role| initial_state_arg(choreography, role))
+        .collect();
+    let session = match target {
+        Target::Async => quote! {
+            let mut mesh =
+                rumpsteak_aura_choreography::runtime::harness::ChannelHandler::mesh(ROLES);
+            let roles = vec![$({
+                let handler = rumpsteak_aura_choreography::runtime::harness::take_role(
+                    &mut mesh,
+                    Role::$role,
+                );
+                let mut handlers = $handlers;
+                Box::pin(async move {
+                    let mut handler = handler?;
+                    run_session!(@run $role, &mut handler, &mut (), &mut handlers).await
+                }) as rumpsteak_aura_choreography::runtime::harness::RoleFuture<'_>
+            }),+];
+            rumpsteak_aura_choreography::runtime::harness::run_roles(roles)
+        },
+        Target::Blocking => quote! {
+            let mut mesh =
+                rumpsteak_aura_choreography::runtime::blocking::ChannelTransport::mesh(ROLES);
+            rumpsteak_aura_choreography::runtime::harness::run_blocking_roles(vec![$({
+                let mut handlers = $handlers;
+                rumpsteak_aura_choreography::runtime::harness::blocking_role(
+                    Role::$role,
+                    ROLES,
+                    rumpsteak_aura_choreography::runtime::harness::take_role(
+                        &mut mesh,
+                        Role::$role,
+                    ),
+                    move |endpoint| run_session!(@run $role, endpoint, &mut handlers),
+                )
+            }),+])
+        },
+    };
+    let usage = match target {
+        Target::Async => "`run_session! { Client => client, Server => MockServer::new() }.await`",
+        Target::Blocking => "`run_session! { Client => client, Server => MockServer::new() }`",
+    };
+    let doc = format!(
+        "Run one implementation of every role against the others in this process, as {usage}"
+    );
+
+    quote! {
+        #[doc = #doc]
+        #[allow(unused_macros)]
+        macro_rules! run_session {
+            #((@run #role_names, $($arg:expr),+) => {
+                #run_fns($($arg),+ #initial_states)
+            };)*
+            ($($role:ident => $handlers:expr),+ $(,)?) => {{
+                #session
+            }};
+        }
+    }
+}
+
+fn generate_conformance(choreography: &Choreography, target: Target) -> TokenStream {
+    let implementations = choreography
+        .roles
+        .iter()
+        .map(|role| generate_path_role(choreography, role, target));
+    let role_names: Vec<_> = choreography.roles.iter().map(|role| &role.name).collect();
+    let path_names: Vec<_> = role_names
+        .iter()
+        .map(|name| format_ident!("Path{}", name))
+        .collect();
+    let run_fns: Vec<_> = choreography.roles.iter().map(run_fn_name).collect();
+    let initial_states: Vec<_> = choreography
+        .roles
+        .iter()
+        .map(|role| initial_state_arg(choreography, role))
+        .collect();
+    let session = match target {
+        Target::Async => quote! {
+            let mut mesh =
+                rumpsteak_aura_choreography::runtime::harness::ChannelHandler::mesh(ROLES);
+            let mut roles: Vec<(
+                Role,
+                rumpsteak_aura_choreography::runtime::harness::RoleFuture<'static>,
+            )> = Vec::new();
+            #({
+                let handler = rumpsteak_aura_choreography::runtime::harness::take_role(
+                    &mut mesh,
+                    Role::#role_names,
+                );
+                let mut handlers = #path_names::new(path);
+                roles.push((
+                    Role::#role_names,
+                    Box::pin(async move {
+                        let mut handler = handler?;
+                        #run_fns(&mut handler, &mut (), &mut handlers #initial_states).await
+                    }),
+                ));
+            })*
+            rumpsteak_aura_choreography::runtime::conformance::run_async_session(roles)
+        },
+        Target::Blocking => quote! {
+            let mut mesh =
+                rumpsteak_aura_choreography::runtime::blocking::ChannelTransport::mesh(ROLES);
+            let mut roles = Vec::new();
+            #({
+                let mut handlers = #path_names::new(path);
+                roles.push((
+                    Role::#role_names,
+                    rumpsteak_aura_choreography::runtime::harness::blocking_role(
+                        Role::#role_names,
+                        ROLES,
+                        rumpsteak_aura_choreography::runtime::harness::take_role(
+                            &mut mesh,
+                            Role::#role_names,
+                        ),
+                        move |endpoint| #run_fns(endpoint, &mut handlers #initial_states),
+                    ),
+                ));
+            })*
+            rumpsteak_aura_choreography::runtime::conformance::run_blocking_session(
+                roles,
+                rumpsteak_aura_choreography::runtime::conformance::DEFAULT_DEADLINE,
+            )
+        },
+    };
+
+    quote! {
+        #(#implementations)*
+
+        /// Run every role along `path` in this process
+        pub fn run_execution_path(
+            path: &rumpsteak_aura_choreography::runtime::conformance::ExecutionPath<Role>,
+        ) -> std::result::Result<(), rumpsteak_aura_choreography::runtime::conformance::ConformanceError> {
+            #session
+        }
+
+        /// Run every role along `cases` random execution paths, failing with
+        /// the smallest path on which a role fails or the session deadlocks
+        pub fn check_conformance(
+            cases: u32,
+        ) -> std::result::Result<
+            (),
+            rumpsteak_aura_choreography::runtime::conformance::proptest::test_runner::TestError<
+                rumpsteak_aura_choreography::runtime::conformance::ExecutionPath<Role>,
+            >,
+        > {
+            rumpsteak_aura_choreography::runtime::conformance::check_paths(
+                ROLES,
+                cases,
+                run_execution_path,
+            )
+        }
+    }
+}
+
+/// `Path<Role>`, taking the branches an `ExecutionPath` gives and sending
+/// default message contents
+fn generate_path_role(choreography: &Choreography, role: &Role, target: Target) -> TokenStream {
+    let role_name = &role.name;
+    let path_name = format_ident!("Path{}", role_name);
+    let doc = format!(
+        "The {role_name} role of {} following an execution path, for conformance tests",
+        choreography.name
+    );
+    let implementation = generate_decided_role(
+        choreography,
+        role,
+        target,
+        &path_name,
+        quote! { rumpsteak_aura_choreography::runtime::conformance::Decisions },
+    );
+
+    quote! {
+        #[doc = #doc]
+        #implementation
+
+        impl #path_name {
+            #[must_use]
+            pub fn new(
+                path: &rumpsteak_aura_choreography::runtime::conformance::ExecutionPath<Role>,
+            ) -> Self {
+                Self {
+                    decisions: path.decisions(Role::#role_name),
+                }
+            }
+        }
+    }
+}
+
+/// A struct named `name` with a `decisions` field of type `decisions`,
+/// implementing the role's trait with the methods of `RoleApi::path_methods`
+fn generate_decided_role(
+    choreography: &Choreography,
+    role: &Role,
+    target: Target,
+    name: &Ident,
+    decisions: TokenStream,
+) -> TokenStream {
+    let mut api = RoleApi::new(choreography, role, target);
+    api.drive(&choreography.protocol);
+    // Roles making no choices never read their decisions
+    let allow_unused = if api
+        .method_names
+        .iter()
+        .any(|name| name.starts_with("choose_"))
+    {
+        quote! {}
+    } else {
+        quote! { #[allow(dead_code)] }
+    };
+    let allow_unused_state = api.allow_unused_state();
+    let path_methods = api.path_methods;
+    let trait_name = format_ident!("{}Handlers", role.name);
+    let async_trait = match target {
+        Target::Async => quote! { #[rumpsteak_aura_choreography::async_trait] },
+        Target::Blocking => quote! {},
+    };
+
+    quote! {
+        pub struct #name {
+            #allow_unused
+            decisions: #decisions,
+        }
+
+        #async_trait
+        #allow_unused_state
+        impl #trait_name for #name {
+            #(#path_methods)*
+        }
+    }
+}
+
+fn generate_fuzz(choreography: &Choreography, target: Target) -> TokenStream {
+    let mut labels = Vec::new();
+    collect_labels(&choreography.protocol, &mut labels);
+    let entry_points = choreography.roles.iter().map(|role| {
+        let role_name = &role.name;
+        let fuzz_name = format_ident!("Fuzz{}", role_name);
+        let fuzz_fn = fuzz_fn_name(role);
+        let run_fn = run_fn_name(role);
+        let initial_state = initial_state_arg(choreography, role);
+        let handlers_doc = format!(
+            "The {role_name} role of {} taking the branches and receiving the frames of fuzzer input",
+            choreography.name
+        );
+        let fn_doc = format!("Run the {role_name} role on fuzzer input, which must never panic");
+        let implementation = generate_decided_role(
+            choreography,
+            role,
+            target,
+            &fuzz_name,
+            quote! { rumpsteak_aura_choreography::runtime::fuzz::Choices },
+        );
+        let run = match target {
+            Target::Async => quote! {
+                let mut handler =
+                    rumpsteak_aura_choreography::runtime::fuzz::FuzzHandler::new(frames, FUZZ_LABELS);
+                let _ = rumpsteak_aura_choreography::runtime::fuzz::block_on(#run_fn(
+                    &mut handler,
+                    &mut (),
+                    &mut handlers
+                    #initial_state
+                ));
+            },
+            Target::Blocking => quote! {
+                let mut endpoint = rumpsteak_aura_choreography::runtime::blocking::BlockingEndpoint::new(
+                    Role::#role_name,
+                    ROLES.to_vec(),
+                    frames,
+                );
+                let _ = #run_fn(&mut endpoint, &mut handlers #initial_state);
+            },
+        };
+
+        quote! {
+            #[doc = #handlers_doc]
+            #implementation
+
+            #[doc = #fn_doc]
+            pub fn #fuzz_fn(data: &[u8]) {
+                let (decisions, frames) =
+                    rumpsteak_aura_choreography::runtime::fuzz::split_input(data);
+                let mut handlers = #fuzz_name { decisions };
+                // Malformed input fails the session with an error, which is fine
+                #run
+            }
+        }
+    });
+    let label_const = match target {
+        Target::Async => quote! {
+            /// Branch labels a fuzzed role accepts
+            const FUZZ_LABELS: &[&str] = &[#(#labels),*];
+        },
+        Target::Blocking => quote! {},
+    };
+
+    quote! {
+        #label_const
+
+        #(#entry_points)*
+    }
+}
+
+/// Labels of every branch, each once, in protocol order
+fn collect_labels(protocol: &Protocol, labels: &mut Vec<String>) {
+    match protocol {
+        Protocol::Choice { branches, .. } => {
+            for branch in branches {
+                let label = branch.label.to_string();
+                if !labels.contains(&label) {
+                    labels.push(label);
+                }
+                collect_labels(&branch.protocol, labels);
+            }
+        }
+        Protocol::Send { continuation, .. }
+        | Protocol::Broadcast { continuation, .. }
+        | Protocol::Extension { continuation, .. } => collect_labels(continuation, labels),
+        Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => collect_labels(body, labels),
+        Protocol::Parallel { protocols, .. } => {
+            for protocol in protocols {
+                collect_labels(protocol, labels);
+            }
+        }
+        Protocol::Var(_) | Protocol::End => {}
+    }
+}
+
+fn fuzz_fn_name(role: &Role) -> Ident {
+    format_ident!("fuzz_{}", snake_case(&role.name.to_string()))
+}
+
+/// `, &mut <Role>State::default()`, the state argument of a run function
+/// started from default state, if `role` declares state
+fn initial_state_arg(choreography: &Choreography, role: &Role) -> TokenStream {
+    match choreography.role_state(&role.name) {
+        Some(state) => {
+            let state = state.struct_name();
+            quote! { , &mut #state::default() }
+        }
+        None => quote! {},
+    }
+}
+
+fn run_fn_name(role: &Role) -> Ident {
+    format_ident!("run_{}_handlers", role.name.to_string().to_lowercase())
+}
+
+/// One enum per distinct choice, with a variant per branch
+fn collect_decision_enums(
+    protocol: &Protocol,
+    seen: &mut HashSet<String>,
+    enums: &mut Vec<TokenStream>,
+) {
+    match protocol {
+        Protocol::Choice { role, branches, .. } => {
+            let name = decision_enum_name(role, branches);
+            // The outcome of a send decides a failure choice
+            if protocol.failure_of().is_none() && seen.insert(name.to_string()) {
+                let variants: Vec<_> = branches
+                    .iter()
+                    .map(|branch| variant_name(&branch.label))
+                    .collect();
+                let labels: Vec<_> = branches
+                    .iter()
+                    .map(|branch| branch.label.to_string())
+                    .collect();
+                let chooser = role.name.to_string();
+                let function = format!("choose_{}", choice_labels(branches));
+                let doc = format!("Branches of the choice made by {}", role.name);
+                enums.push(quote! {
+                    #[doc = #doc]
+                    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+                    pub enum #name {
+                        #(#variants),*
+                    }
+                });
+                // Votes decide atomic blocks and the clock `within` blocks,
+                // so only handler decisions can be scripted
+                if protocol.atomic().is_none() && protocol.within().is_none() {
+                    enums.push(quote! {
+                        impl rumpsteak_aura_choreography::runtime::script::Decision for #name {
+                            const ROLE: &'static str = #chooser;
+                            const FUNCTION: &'static str = #function;
+                            const LABELS: &'static [&'static str] = &[#(#labels),*];
+
+                            fn from_label(label: &str) -> Option<Self> {
+                                match label {
+                                    #(#labels => Some(Self::#variants),)*
+                                    _ => None,
+                                }
+                            }
+
+                            fn label(self) -> &'static str {
+                                match self {
+                                    #(Self::#variants => #labels,)*
+                                }
+                            }
+                        }
+                    });
+                }
+            }
+            for branch in branches {
+                collect_decision_enums(&branch.protocol, seen, enums);
+            }
+        }
+        Protocol::Send { continuation, .. }
+        | Protocol::Broadcast { continuation, .. }
+        | Protocol::Extension { continuation, .. } => {
+            collect_decision_enums(continuation, seen, enums);
+        }
+        Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => {
+            collect_decision_enums(body, seen, enums);
+        }
+        Protocol::Parallel { protocols, .. } => {
+            for protocol in protocols {
+                collect_decision_enums(protocol, seen, enums);
+            }
+        }
+        Protocol::Var(_) | Protocol::End => {}
+    }
+}
+
+/// `<Chooser>Choice<Labels>`, such as `ServerChoiceAcceptReject`
+fn decision_enum_name(chooser: &Role, branches: &[Branch]) -> Ident {
+    let labels: String = branches
+        .iter()
+        .map(|branch| variant_name(&branch.label).to_string())
+        .collect();
+    format_ident!("{}Choice{}", chooser.name, labels)
+}
+
+/// Branch labels in snake case joined by `_or_`, such as `accept_or_reject`
+fn choice_labels(branches: &[Branch]) -> String {
+    branches
+        .iter()
+        .map(|branch| snake_case(&branch.label.to_string()))
+        .collect::<Vec<_>>()
+        .join("_or_")
+}
+
+/// `label` in upper camel case, such as `FastPath` for `fast_path`
+pub(crate) fn variant_name(label: &Ident) -> Ident {
+    let name: String = label
+        .to_string()
+        .split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect();
+    format_ident!("{}", name)
+}
+
+/// Whether `protocol` can continue a recursion it is nested in, that is
+/// reach a `continue` of a label not bound within it
+fn continues(protocol: &Protocol, bound: &mut Vec<Ident>) -> bool {
+    match protocol {
+        Protocol::Var(label) => !bound.contains(label),
+        Protocol::Rec { label, body, .. } => {
+            bound.push(label.clone());
+            let continues = continues(body, bound);
+            bound.pop();
+            continues
+        }
+        Protocol::Send { continuation, .. }
+        | Protocol::Broadcast { continuation, .. }
+        | Protocol::Extension { continuation, .. } => continues(continuation, bound),
+        Protocol::Choice { branches, .. } => branches
+            .iter()
+            .any(|branch| continues(&branch.protocol, bound)),
+        Protocol::Loop { body, .. } => continues(body, bound),
+        Protocol::Parallel { protocols, .. } => protocols.iter().any(|p| continues(p, bound)),
+        Protocol::End => false,
+    }
+}
+
+fn loop_label(label: &Ident) -> syn::Lifetime {
+    syn::Lifetime::new(
+        &format!("'rec_{}", snake_case(&label.to_string())),
+        proc_macro2::Span::call_site(),
+    )
+}
+
+pub(crate) fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() {
+            if i > 0 && !snake.ends_with('_') {
+                snake.push('_');
+            }
+            snake.extend(c.to_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::{MessageType, Span};
+    use crate::compiler::parser::parse_choreography_str;
+    use std::collections::HashMap;
+
+    const CHECKOUT: &str = r#"
+@codegen(style = "handlers")
+choreography Checkout {
+    roles: Client, Server
+    Client -> Server: PlaceOrder
+    choice Server {
+        accept: {
+            Server -> Client: OrderAccepted
+        }
+        reject: {
+            Server -> Client: OrderRejected
+        }
+    }
+}
+"#;
+
+    #[test]
+    fn test_style_from_annotation() {
+        let choreography = parse_choreography_str(CHECKOUT).unwrap();
+        assert_eq!(
+            CodegenOptions::from_choreography(&choreography),
+            Ok(CodegenOptions {
+                style: CodegenStyle::Handlers,
+                sync: false,
+                test_harness: false,
+                proptest: false,
+                fuzz: false,
+                compact: false,
+                monitor: false,
+            })
+        );
+
+        let mut plain = choreography;
+        plain.remove_attribute("codegen");
+        assert_eq!(
+            CodegenOptions::from_choreography(&plain),
+            Ok(CodegenOptions::default())
+        );
+
+        plain.set_attribute("codegen".to_string(), "sync".to_string());
+        assert!(CodegenOptions::from_choreography(&plain).unwrap().sync);
+
+        plain.set_attribute("codegen".to_string(), "sync, test_harness".to_string());
+        let options = CodegenOptions::from_choreography(&plain).unwrap();
+        assert!(options.sync && options.test_harness);
+
+        plain.set_attribute(
+            "codegen".to_string(),
+            "style=handlers, proptest".to_string(),
+        );
+        let options = CodegenOptions::from_choreography(&plain).unwrap();
+        assert!(options.proptest && !options.test_harness);
+
+        plain.set_attribute("codegen".to_string(), "sync, fuzz".to_string());
+        let options = CodegenOptions::from_choreography(&plain).unwrap();
+        assert!(options.sync && options.fuzz);
+
+        plain.set_attribute("codegen".to_string(), "compact".to_string());
+        assert!(CodegenOptions::from_choreography(&plain).unwrap().compact);
+
+        plain.set_attribute("codegen".to_string(), "compact, monitor".to_string());
+        let options = CodegenOptions::from_choreography(&plain).unwrap();
+        assert!(options.compact && options.monitor);
+
+        plain.set_attribute("codegen".to_string(), "style=callbacks".to_string());
+        assert_eq!(
+            CodegenOptions::from_choreography(&plain),
+            Err(UnknownCodegenStyle("callbacks".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_handler_api_methods() {
+        let choreography = parse_choreography_str(CHECKOUT).unwrap();
+        let code = generate_handler_api(&choreography);
+        syn::parse2::<syn::File>(code.clone()).unwrap();
+        let code = code.to_string();
+
+        assert!(code.contains("pub enum ServerChoiceAcceptReject { Accept , Reject }"));
+        assert!(code.contains(
+            "impl rumpsteak_aura_choreography :: runtime :: script :: Decision for ServerChoiceAcceptReject"
+        ));
+        assert!(code.contains("const FUNCTION : & 'static str = \"choose_accept_or_reject\" ;"));
+        assert!(code.contains("\"reject\" => Some (Self :: Reject) ,"));
+        assert!(code.contains("pub trait ClientHandlers"));
+        assert!(code.contains("async fn make_place_order (& mut self) -> Result < PlaceOrder >"));
+        assert!(code.contains("async fn on_order_accepted (& mut self , message : OrderAccepted)"));
+        assert!(code.contains("async fn on_choice_accept_or_reject"));
+        assert!(code.contains(
+            "async fn choose_accept_or_reject (& mut self) -> Result < ServerChoiceAcceptReject >"
+        ));
+        assert!(code.contains("pub async fn run_server_handlers"));
+    }
+
+    #[test]
+    fn test_role_requirement() {
+        let choreography = parse_choreography_str(CHECKOUT).unwrap();
+        let code = generate_handler_api(&choreography).to_string();
+        assert!(code.contains("pub const __IMPLEMENTS_CHECKOUT_CLIENT : & str"));
+        assert!(code.contains(
+            "type does not implement the Server role of Checkout: \
+             it needs `impl ServerHandlers` with on_place_order, choose_accept_or_reject"
+        ));
+    }
+
+    #[test]
+    fn test_doc_comments_reach_generated_code() {
+        let source = r#"
+@codegen(style = "handlers")
+choreography Checkout {
+    roles:
+        /// Places orders
+        Client,
+        Server
+
+    /// Ask for the items in the cart
+    /// at their current price
+    Client -> Server: PlaceOrder
+    //// Not a doc comment
+    /// Whether the order goes through
+    choice Server {
+        accept: {
+            Server -> Client: OrderAccepted
+        }
+        reject: {
+            Server -> Client: OrderRejected
+        }
+    }
+}
+"#;
+        let registry = crate::extensions::ExtensionRegistry::new();
+        assert!(crate::compiler::check_choreography(source, &registry).is_empty());
+        let choreography = parse_choreography_str(source).unwrap();
+        assert_eq!(
+            choreography.role_doc(&choreography.roles[0].name),
+            Some("Places orders")
+        );
+        assert_eq!(choreography.role_doc(&choreography.roles[1].name), None);
+        assert_eq!(
+            choreography.protocol.doc(),
+            Some("Ask for the items in the cart\nat their current price")
+        );
+
+        let code = crate::compiler::generate_effects_protocol(&choreography).to_string();
+        assert!(code.contains(
+            "# [doc = \" Ask for the items in the cart\"] # [doc = \" at their current price\"] # [derive (Clone , Debug , Serialize , Deserialize)] pub struct PlaceOrder"
+        ));
+        assert!(code.contains("# [doc = \" Places orders\"] Client"));
+        assert!(code.contains(
+            "# [doc = \"Produce the PlaceOrder this role sends\"] # [doc = \"\"] # [doc = \" Ask for the items in the cart\"]"
+        ));
+        assert!(code.contains(
+            "# [doc = \" Whether the order goes through\"] async fn choose_accept_or_reject"
+        ));
+        assert!(!code.contains("Not a doc comment"));
+    }
+
+    #[test]
+    fn test_failed_send_runs_recovery() {
+        let choreography = parse_choreography_str(
+            r"
+choreography Dispatch {
+    roles: Coordinator, Worker, Backup
+    Coordinator -> Worker: Task or on failure {
+        Coordinator -> Backup: Reassign
+    }
+}
+",
+        )
+        .unwrap();
+        let code = generate_handler_api(&choreography);
+        syn::parse2::<syn::File>(code.clone()).unwrap();
+        let code = code.to_string();
+
+        assert!(code.contains("Err (error) if error . is_peer_failure () =>"));
+        assert!(code.contains("handlers . on_worker_failed (error) . await ?"));
+        assert!(code.contains("handler . send (endpoint , Role :: Backup , & \"failed\")"));
+        assert!(code.contains("handler . recv :: < String > (endpoint , Role :: Coordinator)"));
+        // The outcome of the send decides, not a handler
+        assert!(!code.contains("choose_delivered_or_failed"));
+        assert!(!code.contains("CoordinatorChoiceDeliveredFailed"));
+
+        let blocking = generate_blocking_handler_api(&choreography).to_string();
+        assert!(blocking.contains("match endpoint . send (Role :: Worker , & message)"));
+    }
+
+    #[test]
+    fn test_atomic_block_asks_participants_to_vote() {
+        let choreography = parse_choreography_str(
+            r"
+choreography Ledger {
+    roles: Coordinator, Participant
+    atomic at Coordinator over Participant {
+        Coordinator -> Participant: Write
+    }
+}
+",
+        )
+        .unwrap();
+        let code = generate_handler_api(&choreography);
+        syn::parse2::<syn::File>(code.clone()).unwrap();
+        let code = code.to_string();
+
+        assert!(code.contains("async fn vote (& mut self) -> Result < bool > ;"));
+        assert!(code.contains("let message = Vote (handlers . vote () . await ?) ;"));
+        assert!(code.contains("all_voted_commit &= message . 0 ;"));
+        assert!(code.contains("if all_voted_commit {"));
+        // The votes decide, not the coordinator's handler
+        assert!(!code.contains("choose_commit_or_abort"));
+        assert!(code.contains("async fn on_choice_commit_or_abort"));
+    }
+
+    #[test]
+    fn test_within_block_reports_its_outcome() {
+        let choreography = parse_choreography_str(
+            r"
+choreography Lookup {
+    roles: Client, Server
+    within 30s {
+        Client -> Server: Query
+        Server -> Client: Answer
+    } or on deadline {
+        Client -> Server: Cancel
+    }
+}
+",
+        )
+        .unwrap();
+        let code = generate_handler_api(&choreography);
+        syn::parse2::<syn::File>(code.clone()).unwrap();
+        let code = code.to_string();
+
+        assert!(code.contains(
+            "let within_deadline_0 = rumpsteak_aura_choreography :: runtime :: Instant :: now () \
+             + std :: time :: Duration :: from_millis (30000u64) ;"
+        ));
+        assert!(code.contains(
+            "if rumpsteak_aura_choreography :: runtime :: Instant :: now () <= within_deadline_0 {"
+        ));
+        assert!(code
+            .contains("async fn on_deadline_exceeded (& mut self , message : DeadlineExceeded)"));
+        // The clock decides, not the timekeeper's handler
+        assert!(!code.contains("choose_in_time_or_deadline_exceeded"));
+        assert!(code.contains("async fn on_choice_in_time_or_deadline_exceeded"));
+    }
+
+    #[test]
+    fn test_while_loop_asks_decider() {
+        let choreography = parse_choreography_str(
+            r"
+choreography Gossip {
+    roles: Leader, Follower
+    while at Leader {
+        Leader -> Follower: Round
+    }
+    Leader -> Follower: Done
+}
+",
+        )
+        .unwrap();
+        let code = generate_handler_api(&choreography);
+        syn::parse2::<syn::File>(code.clone()).unwrap();
+        let code = code.to_string();
+        // The Leader decides each round; the Follower is told the outcome
+        assert!(code.contains(
+            "fn choose_continue_or_break (& mut self) -> Result < LeaderChoiceContinueBreak >"
+        ));
+        assert!(code.contains("'rec_while_55 : loop {"));
+        assert!(code.contains("continue 'rec_while_55 ;"));
+        assert!(code.contains(
+            "match handler . offer (endpoint , Role :: Leader , & [Label (\"Continue\") , Label (\"Break\")]) \
+             . await ? . 0 { \"Continue\" =>"
+        ));
+    }
+
+    #[test]
+    fn test_stream_sends_chunks() {
+        let choreography = parse_choreography_str(
+            r"
+choreography Upload {
+    roles: Alice, Bob
+    Alice -> Bob: stream FileChunk;
+    Bob -> Alice: Done;
+}
+",
+        )
+        .unwrap();
+        let code = generate_handler_api(&choreography);
+        syn::parse2::<syn::File>(code.clone()).unwrap();
+        let code = code.to_string();
+
+        assert!(code.contains(
+            "async fn next_file_chunk (& mut self) -> Result < Option < rumpsteak_aura_choreography :: Bytes >>"
+        ));
+        assert!(code.contains("handler . send_bytes (endpoint , Role :: Bob , chunk) . await"));
+        assert!(code.contains("handler . recv_bytes (endpoint , Role :: Alice) . await ?"));
+        assert!(code.contains("async fn on_file_chunk_chunk"));
+        assert!(
+            code.contains("async fn on_file_chunk_end (& mut self) -> Result < () > { Ok (()) }")
+        );
+        assert!(!code.contains("make_file_chunk"));
+
+        let blocking = generate_blocking_handler_api(&choreography).to_string();
+        assert!(blocking.contains("endpoint . send_bytes (Role :: Bob , chunk)"));
+        assert!(blocking.contains("endpoint . recv_bytes (Role :: Alice) ?"));
+    }
+
+    #[test]
+    fn test_recursion_drives_a_loop() {
+        let producer = Role::new(format_ident!("Producer"));
+        let consumer = Role::new(format_ident!("Consumer"));
+        let send = |message: &str, continuation: Protocol| Protocol::Send {
+            from: producer.clone(),
+            to: consumer.clone(),
+            message: MessageType {
+                name: format_ident!("{}", message),
+                type_annotation: None,
+                payload: None,
+            },
+            continuation: Box::new(continuation),
+            annotations: HashMap::new(),
+            from_annotations: HashMap::new(),
+            to_annotations: HashMap::new(),
+            span: Span::default(),
+        };
+        let branch = |label: &str, protocol: Protocol| Branch {
+            label: format_ident!("{}", label),
+            guard: None,
+            probability: None,
+            protocol,
+            span: Span::default(),
+        };
+        let choreography = Choreography {
+            name: format_ident!("Stream"),
+            namespace: None,
+            roles: vec![producer.clone(), consumer.clone()],
+            protocol: Protocol::Rec {
+                label: format_ident!("Next"),
+                body: Box::new(Protocol::Choice {
+                    role: producer.clone(),
+                    branches: vec![
+                        branch("more", send("Item", Protocol::Var(format_ident!("Next")))),
+                        branch("done", send("Done", Protocol::End)),
+                    ],
+                    annotations: HashMap::new(),
+                    span: Span::default(),
+                }),
+                span: Span::default(),
+            },
+            attrs: HashMap::new(),
+            policy: None,
+            state: Vec::new(),
+            messages: Vec::new(),
+            unreachable: Vec::new(),
+            scopes: Vec::new(),
+        };
+
+        let code = generate_handler_api(&choreography);
+        syn::parse2::<syn::File>(code.clone()).unwrap();
+        let code = code.to_string();
+        assert!(code.contains("'rec_next : loop"));
+        assert!(code.contains("continue 'rec_next ;"));
+        assert!(code.contains("\"more\" => { handlers . on_choice_more_or_done"));
+    }
+
+    #[test]
+    fn test_effects_protocol_includes_handlers() {
+        let choreography = parse_choreography_str(CHECKOUT).unwrap();
+        let code = crate::compiler::generate_effects_protocol(&choreography).to_string();
+        assert!(code.contains("pub fn client_program"));
+        assert!(code.contains("pub async fn run_client_handlers"));
+
+        let mut plain = choreography;
+        plain.remove_attribute("codegen");
+        let code = crate::compiler::generate_effects_protocol(&plain).to_string();
+        assert!(!code.contains("run_client_handlers"));
+    }
+
+    #[test]
+    fn test_blocking_api_has_no_async() {
+        let choreography = parse_choreography_str(CHECKOUT).unwrap();
+        let code = generate_blocking_handler_api(&choreography);
+        syn::parse2::<syn::File>(code.clone()).unwrap();
+        let code = code.to_string();
+        assert!(!code.contains("async"));
+        assert!(!code.contains("await"));
+        assert!(code.contains("fn make_place_order (& mut self) -> Result < PlaceOrder >"));
+        assert!(code.contains("pub fn run_client_handlers"));
+        assert!(code.contains("match endpoint . offer (Role :: Server) ? . as_str ()"));
+    }
+
+    #[test]
+    fn test_harness_mocks_every_role() {
+        let choreography = parse_choreography_str(CHECKOUT).unwrap();
+        let code = generate_test_harness(&choreography);
+        syn::parse2::<syn::File>(code.clone()).unwrap();
+        let code = code.to_string();
+        assert!(code.contains("pub struct MockClient"));
+        assert!(code.contains("impl ServerHandlers for MockServer"));
+        assert!(code.contains("self . script . on_send :: < OrderAccepted > ()"));
+        assert!(code.contains("\"accept\" => Ok (ServerChoiceAcceptReject :: Accept)"));
+        assert!(code.contains("macro_rules ! run_session"));
+        assert!(code.contains(
+            "(@ run Client , $ ($ arg : expr) , +) => { run_client_handlers ($ ($ arg) , +) }"
+        ));
+        assert!(code.contains("ChannelHandler :: mesh (ROLES)"));
+
+        let code = generate_blocking_test_harness(&choreography).to_string();
+        assert!(!code.contains("async"));
+        assert!(code.contains("ChannelTransport :: mesh (ROLES)"));
+    }
+
+    #[test]
+    fn test_effects_protocol_includes_harness() {
+        let mut choreography = parse_choreography_str(CHECKOUT).unwrap();
+        choreography.set_attribute("codegen".to_string(), "test_harness".to_string());
+        let code = crate::compiler::generate_effects_protocol(&choreography).to_string();
+        assert!(code.contains("pub trait ServerHandlers"));
+        assert!(code.contains("pub struct MockServer"));
+    }
+
+    #[test]
+    fn test_conformance_follows_paths() {
+        let choreography = parse_choreography_str(CHECKOUT).unwrap();
+        let code = generate_conformance_tests(&choreography);
+        syn::parse2::<syn::File>(code.clone()).unwrap();
+        let code = code.to_string();
+        assert!(code.contains("pub struct PathServer"));
+        assert!(code.contains("impl ServerHandlers for PathServer"));
+        assert!(code.contains("self . decisions . next (2usize , & [0usize , 1usize])"));
+        assert!(code.contains("Ok (PlaceOrder (Default :: default ()))"));
+        assert!(code.contains("pub fn check_conformance"));
+        assert!(code.contains("run_async_session (roles)"));
+
+        let code = generate_blocking_conformance_tests(&choreography).to_string();
+        assert!(!code.contains("async"));
+        assert!(code.contains("run_blocking_session"));
+    }
+
+    #[test]
+    fn test_fuzz_entry_point_per_role() {
+        let choreography = parse_choreography_str(CHECKOUT).unwrap();
+        let code = generate_fuzz_entry_points(&choreography);
+        syn::parse2::<syn::File>(code.clone()).unwrap();
+        let code = code.to_string();
+        assert!(code.contains("const FUZZ_LABELS : & [& str] = & [\"accept\" , \"reject\"]"));
+        assert!(code.contains("impl ClientHandlers for FuzzClient"));
+        assert!(code.contains("pub fn fuzz_server (data : & [u8])"));
+
+        let code = generate_blocking_fuzz_entry_points(&choreography).to_string();
+        assert!(!code.contains("async"));
+        assert!(code.contains("BlockingEndpoint :: new (Role :: Server ,"));
+
+        let targets = generate_fuzz_targets(&choreography, "shop::checkout");
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0].name, "checkout_client");
+        assert!(targets[0]
+            .source
+            .contains("shop::checkout::fuzz_client(data);"));
+    }
+
+    #[test]
+    fn test_role_state_reaches_callbacks() {
+        let choreography = parse_choreography_str(
+            r#"
+@codegen(style = "handlers")
+choreography Voting {
+    roles: Coordinator, Voter
+    state at Coordinator { round: u32, votes: Vec<Vote> }
+    Coordinator -> Voter: Proposal
+    Voter -> Coordinator: Vote
+}
+"#,
+        )
+        .unwrap();
+        let code = generate_handler_api(&choreography);
+        syn::parse2::<syn::File>(code.clone()).unwrap();
+        let code = code.to_string();
+        assert!(code.contains(
+            "async fn make_proposal (& mut self , state : & mut CoordinatorState) -> Result < Proposal >"
+        ));
+        assert!(code.contains(
+            "async fn on_vote (& mut self , message : Vote , state : & mut CoordinatorState)"
+        ));
+        assert!(code.contains("handlers : & mut A , state : & mut CoordinatorState ,"));
+        assert!(code.contains("handlers . finish (state) . await"));
+        // Roles without state keep their signatures
+        assert!(code
+            .contains("async fn on_proposal (& mut self , message : Proposal) -> Result < () >"));
+
+        let code = generate_test_harness(&choreography);
+        syn::parse2::<syn::File>(code.clone()).unwrap();
+        assert!(code.to_string().contains(
+            "run_coordinator_handlers ($ ($ arg) , + , & mut CoordinatorState :: default ())"
+        ));
+        for code in [
+            generate_conformance_tests(&choreography),
+            generate_fuzz_entry_points(&choreography),
+            generate_blocking_handler_api(&choreography),
+        ] {
+            syn::parse2::<syn::File>(code).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_continues_ignores_inner_recursion() {
+        let rec = |label: &str, body: Protocol| Protocol::Rec {
+            label: format_ident!("{}", label),
+            body: Box::new(body),
+            span: Default::default(),
+        };
+        let outer = Protocol::Var(format_ident!("Outer"));
+        let inner = Protocol::Var(format_ident!("Inner"));
+        assert!(continues(&outer, &mut Vec::new()));
+        assert!(!continues(&rec("Inner", inner), &mut Vec::new()));
+        assert!(continues(&rec("Inner", outer), &mut Vec::new()));
+        assert!(!continues(&Protocol::End, &mut Vec::new()));
+    }
+
+    #[test]
+    fn test_snake_case() {
+        assert_eq!(snake_case("PlaceOrder"), "place_order");
+        assert_eq!(snake_case("accept"), "accept");
+        assert_eq!(snake_case("Order_Id"), "order_id");
+    }
+}
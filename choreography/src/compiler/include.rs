@@ -0,0 +1,94 @@
+// Protocol fragments
+//
+// `include_protocol!` splices the statements of a fragment file into a
+// choreography body at the point it is written, renaming the roles of the
+// fragment to roles of the choreography:
+//
+//     include_protocol!("fragments/payments.choreo", Payer = Client, Payee = Bank)
+//
+// A fragment is a sequence of statements, without a `choreography` header,
+// and may include other fragments. Paths are relative to the manifest
+// directory of the crate being built, or to the current directory outside
+// of a Cargo build. The statements of a fragment are located at the
+// `include_protocol!` that splices them.
+//
+// Fragments are read before the choreography is parsed, so the statements
+// built from them can borrow their sources. `choreography!` makes the
+// invoking crate depend on every fragment it reads, so editing one expands
+// the macro again.
+
+use super::parser::{ChoreographyParser, Rule};
+use pest::iterators::Pair;
+use pest::Parser;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Location of the fragment written `path` in an `include_protocol!`
+#[must_use]
+pub fn fragment_path(path: &str) -> PathBuf {
+    let path = PathBuf::from(path);
+    match std::env::var_os("CARGO_MANIFEST_DIR") {
+        Some(root) if path.is_relative() => PathBuf::from(root).join(path),
+        _ => path,
+    }
+}
+
+/// Files of the fragments `input` includes that could be read
+pub(crate) fn fragment_files(input: &str) -> Vec<PathBuf> {
+    let Ok(pairs) = ChoreographyParser::parse(Rule::choreography, input) else {
+        return Vec::new();
+    };
+    Fragments::load(pairs)
+        .sources()
+        .map(|(path, _)| fragment_path(path))
+        .collect()
+}
+
+/// Sources of the fragments a choreography includes, keyed by the path
+/// written in the `include_protocol!`
+#[derive(Debug, Default)]
+pub(crate) struct Fragments {
+    sources: BTreeMap<String, Result<String, String>>,
+}
+
+impl Fragments {
+    /// Read the fragments included below `pairs` and, transitively, by them
+    pub(crate) fn load<'i>(pairs: impl IntoIterator<Item = Pair<'i, Rule>>) -> Self {
+        let mut fragments = Self::default();
+        let mut pending: Vec<String> = pairs.into_iter().flat_map(included).collect();
+        while let Some(path) = pending.pop() {
+            if fragments.sources.contains_key(&path) {
+                continue;
+            }
+            let source = std::fs::read_to_string(fragment_path(&path)).map_err(|e| e.to_string());
+            if let Ok(source) = &source {
+                if let Ok(pairs) = ChoreographyParser::parse(Rule::fragment, source) {
+                    pending.extend(pairs.flat_map(included));
+                }
+            }
+            fragments.sources.insert(path, source);
+        }
+        fragments
+    }
+
+    /// Source of the fragment written `path`, or why it could not be read
+    pub(crate) fn source(&self, path: &str) -> Option<&Result<String, String>> {
+        self.sources.get(path)
+    }
+
+    /// Sources of the fragments that could be read
+    pub(crate) fn sources(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.sources
+            .iter()
+            .filter_map(|(path, source)| Some((path.as_str(), source.as_deref().ok()?)))
+    }
+}
+
+/// Paths written in the `include_protocol!` statements below `pair`
+fn included(pair: Pair<'_, Rule>) -> impl Iterator<Item = String> + '_ {
+    pair.into_inner()
+        .flatten()
+        .filter(|pair| pair.as_rule() == Rule::include_stmt)
+        .filter_map(|include| include.into_inner().next())
+        .map(|path| path.as_str().trim_matches('"').to_string())
+}
@@ -0,0 +1,220 @@
+// Static information-flow analysis
+//
+// Payload fields may carry a confidentiality label naming the roles allowed
+// to see their value besides the sender:
+//
+//     Shop -> Bank: Charge(card: Card [confidential to = "Bank, Issuer"], amount: u64)
+//
+// A message derived from a labeled value declares the dependency on its
+// statement, naming a `Message.field` or a whole message:
+//
+//     [@derived_from = "Charge.card"]
+//     Bank -> Issuer: Authorization(token: Token)
+//
+// A message carries a labeled value if it is the labeled message or is
+// derived, directly or through other derived messages, from the labeled
+// field. Every send of such a message to a role outside the label set is a
+// leak, reported with the chain of messages the value took. The analysis
+// does not follow control flow: a message counts wherever it is sent.
+
+use crate::ast::{Choreography, Protocol, ValidationError, CONFIDENTIAL, DERIVED_FROM};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+
+/// A send statement, with one entry per recipient
+struct SendSite<'a> {
+    from: String,
+    to: Vec<String>,
+    message: String,
+    annotations: &'a HashMap<String, String>,
+}
+
+/// Every flow of a labeled value to a role outside its label set
+#[must_use]
+pub fn check_information_flow(choreography: &Choreography) -> Vec<ValidationError> {
+    let mut sites = Vec::new();
+    collect_sends(&choreography.protocol, &mut sites);
+    let messages: BTreeSet<&str> = sites.iter().map(|site| site.message.as_str()).collect();
+
+    // Derived message -> the `Message` or `Message.field` sources it names
+    let mut errors = Vec::new();
+    let mut sources: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for site in &sites {
+        for source in site
+            .annotations
+            .get(DERIVED_FROM)
+            .into_iter()
+            .flat_map(|list| list.split(','))
+            .map(str::trim)
+            .filter(|source| !source.is_empty())
+        {
+            let message = source.split('.').next().unwrap_or(source);
+            if !messages.contains(message) {
+                errors.push(ValidationError::UndefinedDependency(source.to_string()));
+            }
+            sources.entry(&site.message).or_default().insert(source);
+        }
+    }
+
+    let mut leaks = BTreeSet::new();
+    for site in &sites {
+        for (key, roles) in site.annotations {
+            let Some(field) = key
+                .strip_prefix(CONFIDENTIAL)
+                .and_then(|rest| rest.strip_prefix('.'))
+            else {
+                continue;
+            };
+            let mut allowed: BTreeSet<&str> = roles.split(',').map(str::trim).collect();
+            allowed.insert(&site.from);
+            let labeled = format!("{}.{field}", site.message);
+
+            for (message, path) in carriers(&site.message, field, &sources) {
+                for leak in &sites {
+                    if leak.message != message {
+                        continue;
+                    }
+                    for to in &leak.to {
+                        if !allowed.contains(to.as_str()) {
+                            leaks.insert((labeled.clone(), to.clone(), path.join(" -> ")));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    errors.extend(
+        leaks
+            .into_iter()
+            .map(|(field, role, path)| ValidationError::InformationLeak { field, role, path }),
+    );
+    errors
+}
+
+/// The messages carrying `message.field`, each with the chain of messages
+/// leading to it from the labeled one
+fn carriers(
+    message: &str,
+    field: &str,
+    sources: &BTreeMap<&str, BTreeSet<&str>>,
+) -> Vec<(String, Vec<String>)> {
+    let labeled = format!("{message}.{field}");
+    let mut found: Vec<(String, Vec<String>)> = vec![(message.to_string(), vec![labeled])];
+    let mut queue = VecDeque::from([0]);
+    while let Some(index) = queue.pop_front() {
+        let (carrier, path) = found[index].clone();
+        for (derived, names) in sources {
+            if found.iter().any(|(known, _)| known == derived) {
+                continue;
+            }
+            // Only the labeled field of the labeled message carries its value
+            let reads = names.iter().any(|source| match source.split_once('.') {
+                Some((source, source_field)) => {
+                    source == carrier && (carrier != message || source_field == field)
+                }
+                None => *source == carrier,
+            });
+            if reads {
+                let mut path = path.clone();
+                path.push((*derived).to_string());
+                found.push(((*derived).to_string(), path));
+                queue.push_back(found.len() - 1);
+            }
+        }
+    }
+    found
+}
+
+fn collect_sends<'a>(protocol: &'a Protocol, sites: &mut Vec<SendSite<'a>>) {
+    match protocol {
+        Protocol::Send {
+            from,
+            to,
+            message,
+            continuation,
+            annotations,
+            ..
+        } => {
+            sites.push(SendSite {
+                from: from.name.to_string(),
+                to: vec![to.name.to_string()],
+                message: message.name.to_string(),
+                annotations,
+            });
+            collect_sends(continuation, sites);
+        }
+        Protocol::Broadcast {
+            from,
+            to_all,
+            message,
+            continuation,
+            annotations,
+            ..
+        } => {
+            sites.push(SendSite {
+                from: from.name.to_string(),
+                to: to_all.iter().map(|to| to.name.to_string()).collect(),
+                message: message.name.to_string(),
+                annotations,
+            });
+            collect_sends(continuation, sites);
+        }
+        Protocol::Choice { branches, .. } => {
+            for branch in branches {
+                collect_sends(&branch.protocol, sites);
+            }
+        }
+        Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => collect_sends(body, sites),
+        Protocol::Parallel { protocols, .. } => {
+            for p in protocols {
+                collect_sends(p, sites);
+            }
+        }
+        Protocol::Extension { continuation, .. } => collect_sends(continuation, sites),
+        Protocol::Var(_) | Protocol::End => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parse_choreography_str;
+
+    #[test]
+    fn test_other_fields_do_not_carry_the_label() {
+        let choreography = parse_choreography_str(
+            r#"
+choreography Payment {
+    roles: Shop, Bank, Auditor
+    Shop -> Bank: Charge(card: String [confidential to = "Bank"], amount: u64)
+    [@derived_from = "Charge.amount"]
+    Bank -> Auditor: Report(total: u64)
+}
+"#,
+        )
+        .unwrap();
+        assert!(check_information_flow(&choreography).is_empty());
+    }
+
+    #[test]
+    fn test_derived_chain_is_reported() {
+        let choreography = parse_choreography_str(
+            r#"
+choreography Payment {
+    roles: Shop, Bank, Issuer, Auditor
+    Shop -> Bank: Charge(card: String [confidential to = "Bank, Issuer"], amount: u64)
+    [@derived_from = "Charge.card"]
+    Bank -> Issuer: Authorization(token: String)
+    [@derived_from = "Authorization"]
+    Issuer -> Auditor: Audit(entry: String)
+}
+"#,
+        )
+        .unwrap();
+        let errors = check_information_flow(&choreography);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(
+            errors[0].to_string(),
+            "Confidential Charge.card reaches Auditor, outside its label, via Charge.card -> Authorization -> Audit"
+        );
+    }
+}
@@ -0,0 +1,368 @@
+// Static latency estimation
+//
+// Sends may give the time their message takes to arrive:
+//
+//     [@latency = "50ms"]
+//     Client -> Server: Upload
+//
+// Latencies are given in milliseconds, seconds, minutes or hours: `50ms`,
+// `2s`, `1m`, `1h`. Sends without the annotation take no time. Statements of
+// a path add up, threads of a `parallel` block overlap so the slowest one
+// counts, a broadcast takes the latency of one send, and every combination of
+// choice branches is a separate path. Loops behave as in the flow-cost
+// analysis: a loop with a count multiplies the slowest path of its body, and
+// a loop without a bound or a recursion is unbounded once its body takes any
+// time.
+
+use crate::ast::{Branch, Choreography, Condition, Protocol};
+use crate::compiler::flow_cost::write_table;
+use std::fmt;
+use std::time::Duration;
+
+/// Annotation giving the latency of a send
+pub const LATENCY: &str = "latency";
+
+/// Parse the value of a [`LATENCY`] annotation
+#[must_use]
+pub fn parse_latency(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let digits = value.find(|c: char| !c.is_ascii_digit())?;
+    let count: u64 = value[..digits].parse().ok()?;
+    match value[digits..].trim() {
+        "ms" => Some(Duration::from_millis(count)),
+        "s" => Some(Duration::from_secs(count)),
+        "m" => Some(Duration::from_secs(count.checked_mul(60)?)),
+        "h" => Some(Duration::from_secs(count.checked_mul(3_600)?)),
+        _ => None,
+    }
+}
+
+/// Estimated latency of a path
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Latency {
+    Bounded(Duration),
+    Unbounded,
+}
+
+impl Latency {
+    pub const ZERO: Latency = Latency::Bounded(Duration::ZERO);
+
+    #[must_use]
+    pub fn as_bounded(self) -> Option<Duration> {
+        match self {
+            Latency::Bounded(latency) => Some(latency),
+            Latency::Unbounded => None,
+        }
+    }
+
+    fn add(self, other: Latency) -> Latency {
+        match (self, other) {
+            (Latency::Bounded(a), Latency::Bounded(b)) => a
+                .checked_add(b)
+                .map_or(Latency::Unbounded, Latency::Bounded),
+            _ => Latency::Unbounded,
+        }
+    }
+
+    fn times(self, n: u64) -> Latency {
+        match self {
+            Latency::Bounded(latency) => u32::try_from(n)
+                .ok()
+                .and_then(|n| latency.checked_mul(n))
+                .map_or(Latency::Unbounded, Latency::Bounded),
+            Latency::Unbounded if n == 0 => Latency::ZERO,
+            Latency::Unbounded => Latency::Unbounded,
+        }
+    }
+
+    /// Latency of repeating a body an unknown number of times
+    fn repeated(self) -> Latency {
+        if self == Latency::ZERO {
+            Latency::ZERO
+        } else {
+            Latency::Unbounded
+        }
+    }
+}
+
+impl fmt::Display for Latency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Latency::Bounded(latency) => {
+                let millis = latency.as_secs_f64() * 1_000.0;
+                if millis.fract() == 0.0 {
+                    write!(f, "{millis:.0} ms")
+                } else {
+                    write!(f, "{millis:.1} ms")
+                }
+            }
+            Latency::Unbounded => write!(f, "unbounded"),
+        }
+    }
+}
+
+/// Latency of one execution path through the choreography
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathLatency {
+    /// Choices taken along the path, as `Role.label`
+    pub choices: Vec<String>,
+    /// Probability of the path, see `Branch::probabilities`
+    pub probability: f64,
+    /// Time from the first send of the path to the arrival of its last
+    pub latency: Latency,
+}
+
+impl PathLatency {
+    fn empty() -> Self {
+        Self {
+            choices: Vec::new(),
+            probability: 1.0,
+            latency: Latency::ZERO,
+        }
+    }
+}
+
+/// Result of the latency estimation
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyReport {
+    pub paths: Vec<PathLatency>,
+}
+
+impl LatencyReport {
+    /// Latency of the slowest path
+    #[must_use]
+    pub fn worst_case(&self) -> Latency {
+        self.critical_path()
+            .map_or(Latency::ZERO, |path| path.latency)
+    }
+
+    /// The slowest path, the first of them on a tie
+    #[must_use]
+    pub fn critical_path(&self) -> Option<&PathLatency> {
+        self.paths.iter().rev().max_by_key(|path| path.latency)
+    }
+
+    /// Latency over all paths weighted by their probability, `None` if a
+    /// path that may be taken is unbounded
+    #[must_use]
+    pub fn expected(&self) -> Option<Duration> {
+        self.paths
+            .iter()
+            .filter(|path| path.probability > 0.0)
+            .try_fold(Duration::ZERO, |total, path| {
+                let latency = path.latency.as_bounded()?;
+                Some(total + latency.mul_f64(path.probability))
+            })
+    }
+}
+
+impl fmt::Display for LatencyReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let critical = self.critical_path();
+        let mut rows = vec![["path", "probability", "latency", ""]
+            .map(String::from)
+            .to_vec()];
+        for path in &self.paths {
+            rows.push(vec![
+                if path.choices.is_empty() {
+                    "(no choices)".to_string()
+                } else {
+                    path.choices.join(", ")
+                },
+                format!("{:.2}", path.probability),
+                path.latency.to_string(),
+                if critical.is_some_and(|critical| std::ptr::eq(critical, path)) {
+                    "critical".to_string()
+                } else {
+                    String::new()
+                },
+            ]);
+        }
+        write_table(f, &rows)?;
+        writeln!(f)?;
+        match self.expected() {
+            Some(expected) => writeln!(f, "expected: {}", Latency::Bounded(expected)),
+            None => writeln!(f, "expected: unbounded"),
+        }
+    }
+}
+
+/// Errors raised by the latency estimation
+#[derive(Debug, Clone, thiserror::Error, PartialEq, Eq)]
+pub enum LatencyError {
+    #[error(
+        "Invalid latency '{value}' on {statement}: expected a whole number of ms, s, m or h, like \"50ms\""
+    )]
+    InvalidLatency { value: String, statement: String },
+}
+
+/// Estimate the end-to-end latency of every execution path
+pub fn analyze_latency(choreography: &Choreography) -> Result<LatencyReport, LatencyError> {
+    Ok(LatencyReport {
+        paths: paths(&choreography.protocol)?,
+    })
+}
+
+/// Latency of a send or broadcast, zero if it has no [`LATENCY`] annotation
+pub fn send_latency(protocol: &Protocol) -> Result<Duration, LatencyError> {
+    let statement = match protocol {
+        Protocol::Send {
+            from, to, message, ..
+        } => format!("{} -> {}: {}", from.name, to.name, message.name),
+        Protocol::Broadcast { from, message, .. } => {
+            format!("{} ->* : {}", from.name, message.name)
+        }
+        _ => return Ok(Duration::ZERO),
+    };
+    match protocol.get_annotation(LATENCY) {
+        Some(value) => parse_latency(value).ok_or_else(|| LatencyError::InvalidLatency {
+            value: value.clone(),
+            statement,
+        }),
+        None => Ok(Duration::ZERO),
+    }
+}
+
+fn paths(protocol: &Protocol) -> Result<Vec<PathLatency>, LatencyError> {
+    match protocol {
+        Protocol::Send { continuation, .. } | Protocol::Broadcast { continuation, .. } => {
+            let latency = Latency::Bounded(send_latency(protocol)?);
+            let mut paths = paths(continuation)?;
+            for path in &mut paths {
+                path.latency = latency.add(path.latency);
+            }
+            Ok(paths)
+        }
+        Protocol::Choice { role, branches, .. } => {
+            let mut result = Vec::new();
+            for (branch, probability) in branches.iter().zip(Branch::probabilities(branches)) {
+                for mut path in paths(&branch.protocol)? {
+                    path.choices
+                        .insert(0, format!("{}.{}", role.name, branch.label));
+                    path.probability *= probability;
+                    result.push(path);
+                }
+            }
+            if result.is_empty() {
+                result.push(PathLatency::empty());
+            }
+            Ok(result)
+        }
+        Protocol::Loop {
+            condition, body, ..
+        } => {
+            let body = slowest(&paths(body)?);
+            let latency = match condition {
+                Some(Condition::Count(n)) => body.times(*n as u64),
+                _ => body.repeated(),
+            };
+            Ok(vec![PathLatency {
+                latency,
+                ..PathLatency::empty()
+            }])
+        }
+        Protocol::Rec { body, .. } => Ok(vec![PathLatency {
+            latency: slowest(&paths(body)?).repeated(),
+            ..PathLatency::empty()
+        }]),
+        Protocol::Parallel { protocols, .. } => {
+            let mut result = vec![PathLatency::empty()];
+            for protocol in protocols {
+                let thread_paths = paths(protocol)?;
+                result = result
+                    .iter()
+                    .flat_map(|prefix| {
+                        thread_paths.iter().map(move |path| {
+                            let mut combined = prefix.clone();
+                            combined.choices.extend(path.choices.iter().cloned());
+                            combined.probability *= path.probability;
+                            combined.latency = combined.latency.max(path.latency);
+                            combined
+                        })
+                    })
+                    .collect();
+            }
+            Ok(result)
+        }
+        Protocol::Extension { continuation, .. } => paths(continuation),
+        Protocol::Var(_) | Protocol::End => Ok(vec![PathLatency::empty()]),
+    }
+}
+
+/// Latency of the slowest of `paths`
+fn slowest(paths: &[PathLatency]) -> Latency {
+    paths
+        .iter()
+        .map(|path| path.latency)
+        .max()
+        .unwrap_or(Latency::ZERO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parse_choreography_str;
+
+    #[test]
+    fn test_critical_path_latency() {
+        let choreography = parse_choreography_str(
+            r#"
+choreography Fetch {
+    roles: Client, Cache, Origin
+    [@latency = "5ms"]
+    Client -> Cache: Get
+    choice Cache {
+        hit [prob = 0.75]: {
+            [@latency = "5ms"]
+            Cache -> Client: Hit
+        }
+        miss: {
+            parallel {
+                [@latency = "80ms"]
+                Cache -> Origin: Fetch
+            |
+                [@latency = "5ms"]
+                Cache -> Client: Wait
+            }
+        }
+    }
+}
+"#,
+        )
+        .unwrap();
+
+        let report = analyze_latency(&choreography).unwrap();
+        assert_eq!(
+            report.worst_case(),
+            Latency::Bounded(Duration::from_millis(85))
+        );
+        assert_eq!(report.critical_path().unwrap().choices, vec!["Cache.miss"]);
+        assert_eq!(
+            report.to_string(),
+            "\
+path        probability  latency
+Cache.hit   0.75         10 ms
+Cache.miss  0.25         85 ms    critical
+
+expected: 28.8 ms
+"
+        );
+
+        assert_eq!(parse_latency("2s"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_latency("fast"), None);
+        let choreography = parse_choreography_str(
+            r#"
+choreography Bad {
+    roles: A, B
+    [@latency = "50"]
+    A -> B: Msg
+}
+"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            analyze_latency(&choreography),
+            Err(LatencyError::InvalidLatency { .. })
+        ));
+    }
+}
@@ -0,0 +1,362 @@
+// Parser for the text form of local types
+//
+// Reads back what `LocalType::to_pretty_string` writes, so a projection can
+// be handed to a team implementing the role in another language and checked
+// against later:
+//
+//     send Offer(u64) to Buyer
+//     branch from Buyer {
+//         accept: {
+//             receive Pay(u64) from Buyer
+//         }
+//         reject: end
+//     }
+//
+// One action per line; blank lines and lines starting with `//` are skipped.
+// Selections, offers, local choices, loops, recursions and timeouts end the
+// sequence they are in, as they do in `LocalType`.
+
+use crate::ast::{Condition, LocalType, MessageType, Role, RoleIndex};
+use proc_macro2::{Ident, TokenStream};
+use std::time::Duration;
+use thiserror::Error;
+
+/// Error in the text of a local type
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("line {line}: {message}")]
+pub struct LocalTypeParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// Parse a local type written as by [`LocalType::to_pretty_string`]
+///
+/// # Errors
+///
+/// [`LocalTypeParseError`] with the line of the first malformed action.
+pub fn parse_local_type(text: &str) -> Result<LocalType, LocalTypeParseError> {
+    let mut parser = Parser {
+        lines: text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with("//"))
+            .collect(),
+        next: 0,
+    };
+    let local_type = parser.sequence()?;
+    match parser.peek() {
+        Some((line, text)) => Err(error(line, format!("unexpected `{text}`"))),
+        None => Ok(local_type),
+    }
+}
+
+struct Parser<'a> {
+    lines: Vec<(usize, &'a str)>,
+    next: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<(usize, &'a str)> {
+        self.lines.get(self.next).copied()
+    }
+
+    /// Line number to report at the end of the text
+    fn last_line(&self) -> usize {
+        self.lines.last().map_or(1, |(line, _)| *line)
+    }
+
+    /// Actions up to the `}` closing the enclosing block, which is left
+    /// unread
+    fn sequence(&mut self) -> Result<LocalType, LocalTypeParseError> {
+        let Some((line, text)) = self.peek() else {
+            return Ok(LocalType::End);
+        };
+        if text == "}" {
+            return Ok(LocalType::End);
+        }
+        self.next += 1;
+
+        if let Some(rest) = text.strip_prefix("send ") {
+            let (message, to) = split_last(rest, " to ")
+                .ok_or_else(|| error(line, "expected `send <message> to <role>`"))?;
+            return Ok(LocalType::Send {
+                to: role(line, to)?,
+                message: message_type(line, message)?,
+                continuation: Box::new(self.sequence()?),
+            });
+        }
+        if let Some(rest) = text.strip_prefix("receive ") {
+            let (message, from) = split_last(rest, " from ")
+                .ok_or_else(|| error(line, "expected `receive <message> from <role>`"))?;
+            return Ok(LocalType::Receive {
+                from: role(line, from)?,
+                message: message_type(line, message)?,
+                continuation: Box::new(self.sequence()?),
+            });
+        }
+
+        let local_type = if text == "end" {
+            LocalType::End
+        } else if let Some(label) = text.strip_prefix("continue ") {
+            LocalType::Var(ident(line, label)?)
+        } else if let Some(header) = text.strip_suffix('{').map(str::trim_end) {
+            self.block(line, header)?
+        } else {
+            return Err(error(line, format!("unknown action `{text}`")));
+        };
+        match self.peek() {
+            Some((next_line, next)) if next != "}" => Err(error(
+                next_line,
+                format!("`{next}` follows `{text}`, which ends its sequence"),
+            )),
+            _ => Ok(local_type),
+        }
+    }
+
+    /// The block opened on `line` by `header {`, up to its closing `}`
+    fn block(&mut self, line: usize, header: &str) -> Result<LocalType, LocalTypeParseError> {
+        let local_type = if let Some(to) = header.strip_prefix("select to ") {
+            LocalType::Select {
+                to: role(line, to)?,
+                branches: self.branches()?,
+            }
+        } else if let Some(from) = header.strip_prefix("branch from ") {
+            LocalType::Branch {
+                from: role(line, from)?,
+                branches: self.branches()?,
+            }
+        } else if header == "choose" {
+            LocalType::LocalChoice {
+                branches: self.branches()?,
+            }
+        } else if let Some(condition) = header.strip_prefix("loop") {
+            LocalType::Loop {
+                condition: loop_condition(line, condition.trim())?,
+                body: Box::new(self.sequence()?),
+            }
+        } else if let Some(label) = header.strip_prefix("rec ") {
+            LocalType::Rec {
+                label: ident(line, label)?,
+                body: Box::new(self.sequence()?),
+            }
+        } else if let Some(duration) = header.strip_prefix("timeout ") {
+            LocalType::Timeout {
+                duration: parse_duration(duration.trim())
+                    .ok_or_else(|| error(line, format!("invalid duration `{duration}`")))?,
+                body: Box::new(self.sequence()?),
+            }
+        } else {
+            return Err(error(line, format!("unknown block `{header} {{`")));
+        };
+        self.close(line)?;
+        Ok(local_type)
+    }
+
+    /// `label: end` and `label: { ... }` lines up to the closing `}`, which
+    /// is left unread
+    fn branches(&mut self) -> Result<Vec<(Ident, LocalType)>, LocalTypeParseError> {
+        let mut branches = Vec::new();
+        while let Some((line, text)) = self.peek() {
+            if text == "}" {
+                break;
+            }
+            self.next += 1;
+            let (label, body) = text
+                .split_once(':')
+                .ok_or_else(|| error(line, "expected `<label>: end` or `<label>: {`"))?;
+            let label = ident(line, label.trim())?;
+            match body.trim() {
+                "end" => branches.push((label, LocalType::End)),
+                "{" => {
+                    let body = self.sequence()?;
+                    self.close(line)?;
+                    branches.push((label, body));
+                }
+                _ => return Err(error(line, "expected `<label>: end` or `<label>: {`")),
+            }
+        }
+        Ok(branches)
+    }
+
+    /// Read the `}` closing the block opened on `line`
+    fn close(&mut self, line: usize) -> Result<(), LocalTypeParseError> {
+        match self.peek() {
+            Some((_, "}")) => {
+                self.next += 1;
+                Ok(())
+            }
+            Some((next_line, text)) => Err(error(next_line, format!("unexpected `{text}`"))),
+            None => Err(error(
+                self.last_line(),
+                format!("block opened on line {line} is not closed"),
+            )),
+        }
+    }
+}
+
+fn error(line: usize, message: impl Into<String>) -> LocalTypeParseError {
+    LocalTypeParseError {
+        line,
+        message: message.into(),
+    }
+}
+
+/// `text` split around the last occurrence of `separator`
+fn split_last<'a>(text: &'a str, separator: &str) -> Option<(&'a str, &'a str)> {
+    let (before, after) = text.rsplit_once(separator)?;
+    Some((before.trim(), after.trim()))
+}
+
+fn ident(line: usize, text: &str) -> Result<Ident, LocalTypeParseError> {
+    syn::parse_str(text).map_err(|_| error(line, format!("`{text}` is not an identifier")))
+}
+
+fn tokens(line: usize, text: &str) -> Result<TokenStream, LocalTypeParseError> {
+    text.parse()
+        .map_err(|_| error(line, format!("`{text}` is not a valid type")))
+}
+
+/// `Name`, `Name[0]`, `Name[i]` or `Name[*]`
+fn role(line: usize, text: &str) -> Result<Role, LocalTypeParseError> {
+    let Some((name, index)) = text.strip_suffix(']').and_then(|text| text.split_once('[')) else {
+        return Ok(Role::new(ident(line, text)?));
+    };
+    let index = match index.trim() {
+        "*" => RoleIndex::Wildcard,
+        index => match index.parse() {
+            Ok(index) => RoleIndex::Concrete(index),
+            Err(_) => RoleIndex::Symbolic(ident(line, index)?.to_string()),
+        },
+    };
+    Ok(Role::with_index(ident(line, name.trim())?, index))
+}
+
+/// `Name`, optionally followed by `<Annotation>` and `(Payload)`
+fn message_type(line: usize, text: &str) -> Result<MessageType, LocalTypeParseError> {
+    let name_end = text
+        .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .unwrap_or(text.len());
+    let name = ident(line, &text[..name_end])?;
+    let mut rest = &text[name_end..];
+
+    let mut type_annotation = None;
+    if rest.starts_with('<') {
+        let mut depth = 0;
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| {
+                match c {
+                    '<' => depth += 1,
+                    '>' => depth -= 1,
+                    _ => {}
+                }
+                depth == 0
+            })
+            .map(|(index, _)| index)
+            .ok_or_else(|| error(line, format!("unclosed `<` in `{text}`")))?;
+        type_annotation = Some(tokens(line, &rest[1..end])?);
+        rest = &rest[end + 1..];
+    }
+
+    let payload = match rest {
+        "" => None,
+        _ => match rest
+            .strip_prefix('(')
+            .and_then(|rest| rest.strip_suffix(')'))
+        {
+            Some(payload) => Some(tokens(line, payload)?),
+            None => return Err(error(line, format!("invalid message `{text}`"))),
+        },
+    };
+    Ok(MessageType {
+        name,
+        type_annotation,
+        payload,
+    })
+}
+
+/// Nothing, `(count: n)`, `(decides: Role)` or `(custom: expression)`
+fn loop_condition(line: usize, text: &str) -> Result<Option<Condition>, LocalTypeParseError> {
+    if text.is_empty() {
+        return Ok(None);
+    }
+    let invalid = || error(line, format!("invalid loop condition `{text}`"));
+    let (kind, value) = text
+        .strip_prefix('(')
+        .and_then(|text| text.strip_suffix(')'))
+        .and_then(|text| text.split_once(':'))
+        .ok_or_else(invalid)?;
+    let value = value.trim();
+    Ok(Some(match kind.trim() {
+        "count" => Condition::Count(value.parse().map_err(|_| invalid())?),
+        "decides" => Condition::RoleDecides(role(line, value)?),
+        "custom" => Condition::Custom(tokens(line, value)?),
+        _ => return Err(invalid()),
+    }))
+}
+
+/// A duration as `Duration`'s `Debug` writes it, such as `5s` or `1.5ms`
+fn parse_duration(text: &str) -> Option<Duration> {
+    let unit_start = text.find(|c: char| !(c.is_ascii_digit() || c == '.'))?;
+    let value: f64 = text[..unit_start].parse().ok()?;
+    let seconds = match &text[unit_start..] {
+        "s" => value,
+        "ms" => value / 1e3,
+        "µs" | "us" => value / 1e6,
+        "ns" => value / 1e9,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parse_choreography_str;
+
+    #[test]
+    fn test_parses_pretty_printed_projections() {
+        let choreography = parse_choreography_str(
+            r"
+choreography Auction {
+    roles: Seller, Buyer
+    Seller -> Buyer: Offer(u64)
+    rec Round {
+        choice Buyer {
+            bid: {
+                Buyer -> Seller: Bid(Vec<u64>)
+                continue Round
+            }
+            pass: {
+                Buyer -> Seller: Pass
+                loop (count: 2) {
+                    Seller -> Buyer: Notice<String>
+                }
+            }
+        }
+    }
+}
+",
+        )
+        .unwrap();
+        for (_, local_type) in choreography.project_all().unwrap() {
+            let text = local_type.to_pretty_string();
+            let parsed = parse_local_type(&text).unwrap();
+            assert_eq!(parsed.to_pretty_string(), text);
+        }
+
+        assert_eq!(
+            parse_local_type("send A to B\nbranch from B {\n    ok: end\n")
+                .unwrap_err()
+                .to_string(),
+            "line 3: block opened on line 2 is not closed"
+        );
+        assert_eq!(
+            parse_local_type("choose {\n    a: end\n}\nsend A to B")
+                .unwrap_err()
+                .to_string(),
+            "line 4: `send A to B` follows `choose {`, which ends its sequence"
+        );
+    }
+}
@@ -0,0 +1,144 @@
+// Unused and orphan message detection
+//
+// A choreography may list the message types it expects to exchange:
+//
+//     messages: Offer, Accept, Reject, Refund
+//
+// A listed message that no statement sends is unused, which usually means a
+// branch was forgotten or a message was renamed in one place only.
+//
+// Independently of such a list, a send is an orphan when the receiver's
+// local type never receives that message from that sender. This happens
+// when a role that does not make a choice receives different messages in
+// its branches: projection keeps one branch's continuation for it, so the
+// messages of the other branches arrive at a role that never reads them.
+
+use crate::ast::{Choreography, LocalType, Protocol, ValidationError};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Messages listed in `messages` that are never sent
+#[must_use]
+pub fn check_unused_messages(choreography: &Choreography) -> Vec<ValidationError> {
+    let mut sends = Vec::new();
+    collect_sends(&choreography.protocol, &mut sends);
+
+    let sent: HashSet<&str> = sends
+        .iter()
+        .map(|(_, _, message)| message.as_str())
+        .collect();
+    choreography
+        .messages
+        .iter()
+        .map(|(name, _)| name.to_string())
+        .filter(|name| !sent.contains(name.as_str()) && !choreography.used_when_disabled(name))
+        .map(ValidationError::UnusedMessage)
+        .collect()
+}
+
+/// Sends whose receiver never receives the message from the sender
+///
+/// Only meaningful for an otherwise valid protocol: an invalid choice, for
+/// instance, already projects without some of its messages.
+#[must_use]
+pub fn check_orphan_messages(choreography: &Choreography) -> Vec<ValidationError> {
+    let mut sends = Vec::new();
+    collect_sends(&choreography.protocol, &mut sends);
+
+    // Receives of each role, or `None` if it cannot be projected: the
+    // projection error is reported elsewhere
+    let mut receives: HashMap<&str, Option<HashSet<(String, String)>>> = HashMap::new();
+    let mut orphans = BTreeSet::new();
+    for (from, to, message) in &sends {
+        let received = receives.entry(to.as_str()).or_insert_with(|| {
+            let role = choreography.roles.iter().find(|role| role.name == to)?;
+            let local_type = choreography.project(role).ok()?;
+            let mut received = HashSet::new();
+            collect_receives(&local_type, &mut received);
+            Some(received)
+        });
+        if let Some(received) = received {
+            if !received.contains(&(from.clone(), message.clone())) {
+                orphans.insert((message.clone(), from.clone(), to.clone()));
+            }
+        }
+    }
+    orphans
+        .into_iter()
+        .map(|(message, from, to)| ValidationError::OrphanMessage { message, from, to })
+        .collect()
+}
+
+/// Every `(from, to, message)` sent in `protocol`, one per recipient
+fn collect_sends(protocol: &Protocol, sends: &mut Vec<(String, String, String)>) {
+    match protocol {
+        Protocol::Send {
+            from,
+            to,
+            message,
+            continuation,
+            ..
+        } => {
+            sends.push((
+                from.name.to_string(),
+                to.name.to_string(),
+                message.name.to_string(),
+            ));
+            collect_sends(continuation, sends);
+        }
+        Protocol::Broadcast {
+            from,
+            to_all,
+            message,
+            continuation,
+            ..
+        } => {
+            sends.extend(to_all.iter().map(|to| {
+                (
+                    from.name.to_string(),
+                    to.name.to_string(),
+                    message.name.to_string(),
+                )
+            }));
+            collect_sends(continuation, sends);
+        }
+        Protocol::Choice { branches, .. } => {
+            for branch in branches {
+                collect_sends(&branch.protocol, sends);
+            }
+        }
+        Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => collect_sends(body, sends),
+        Protocol::Parallel { protocols, .. } => {
+            for protocol in protocols {
+                collect_sends(protocol, sends);
+            }
+        }
+        Protocol::Extension { continuation, .. } => collect_sends(continuation, sends),
+        Protocol::Var(_) | Protocol::End => {}
+    }
+}
+
+/// Every `(from, message)` received in `local_type`
+fn collect_receives(local_type: &LocalType, received: &mut HashSet<(String, String)>) {
+    match local_type {
+        LocalType::Receive {
+            from,
+            message,
+            continuation,
+        } => {
+            received.insert((from.name.to_string(), message.name.to_string()));
+            collect_receives(continuation, received);
+        }
+        LocalType::Send { continuation, .. } => collect_receives(continuation, received),
+        LocalType::Select { branches, .. }
+        | LocalType::Branch { branches, .. }
+        | LocalType::LocalChoice { branches } => {
+            for (_, branch) in branches {
+                collect_receives(branch, received);
+            }
+        }
+        LocalType::Loop { body, .. }
+        | LocalType::Rec { body, .. }
+        | LocalType::Timeout { body, .. } => collect_receives(body, received),
+        LocalType::Var(_) | LocalType::End => {}
+    }
+}
@@ -4,30 +4,92 @@
 //! specifications into executable code.
 
 pub mod analysis;
+pub(crate) mod arena;
+pub mod atomic_commit;
+pub mod auto_notify;
+pub mod capture;
+pub mod cfg;
 pub mod codegen;
+pub mod compact_codegen;
+pub mod debug_output;
+pub mod diagnostics;
 pub mod effects_codegen;
 pub mod extension_parser;
+pub mod flow_cost;
+pub mod golden;
 pub mod grammar;
+pub mod handler_codegen;
+pub mod include;
+pub mod info_flow;
+pub mod latency;
+pub mod local_type_parser;
+pub mod message_usage;
+#[cfg(feature = "parallel")]
+pub(crate) mod parallel;
 pub mod parser;
 pub mod projection;
+pub mod recovery;
+pub mod replay;
+pub mod stdlib;
+pub mod stepper;
+pub mod upgrade;
+pub mod workspace;
 
 // Re-export compiler pipeline components explicitly
 pub use analysis::{
-    analyze, generate_dot_graph, AnalysisResult, AnalysisWarning, CommunicationGraph,
-    ParticipationInfo,
+    analyze, dead_code, generate_dot_graph, generate_sequence_diagram,
+    generate_sequence_diagram_with_latency, races, AnalysisResult, AnalysisWarning,
+    CommunicationGraph, ParticipationInfo,
 };
+pub use atomic_commit::check_atomic_commit;
+pub use auto_notify::{auto_notify, Notification, AUTO_NOTIFY};
+pub use capture::{check_capture, read_capture, CaptureDivergence, CaptureError, CapturedMessage};
+pub use cfg::{CfgOption, CfgPredicate, CfgSet};
 pub use codegen::{
-    generate_choreography_code, generate_choreography_code_with_namespacing, generate_helpers,
-    generate_role_implementations, generate_session_type,
+    generate_choreography_code, generate_choreography_code_with_monitors,
+    generate_choreography_code_with_namespacing, generate_helpers, generate_monitor,
+    generate_monitors, generate_role_implementations, generate_session_type, monitor_spec,
 };
-pub use effects_codegen::generate_effects_protocol;
+pub use debug_output::{
+    ast_to_json, render_tokens, write_debug_output, DebugOutputError, DEBUG_OUTPUT,
+};
+pub use diagnostics::Diagnostic;
+pub use effects_codegen::{generate_effects_protocol, generate_effects_protocol_with_extensions};
 pub use extension_parser::{
     create_standard_extension_parser, ExtensionParseError, ExtensionParser, ExtensionParserBuilder,
     ExtensionStats,
 };
+pub use flow_cost::{analyze_flow_cost, FlowCost, FlowCostError, FlowCostReport, PathCost};
+pub use golden::{golden_traces, golden_traces_json, GoldenMismatch, GoldenStep, GoldenTrace};
 pub use grammar::{GrammarComposer, GrammarComposerBuilder, GrammarCompositionError};
+pub use handler_codegen::{
+    generate_blocking_conformance_tests, generate_blocking_fuzz_entry_points,
+    generate_blocking_handler_api, generate_blocking_test_harness, generate_conformance_tests,
+    generate_fuzz_entry_points, generate_fuzz_targets, generate_handler_api, generate_test_harness,
+    CodegenOptions, CodegenStyle, FuzzTarget, UnknownCodegenStyle,
+};
+pub use info_flow::check_information_flow;
+pub use latency::{
+    analyze_latency, parse_latency, Latency, LatencyError, LatencyReport, PathLatency, LATENCY,
+};
+pub use local_type_parser::{parse_local_type, LocalTypeParseError};
+pub use message_usage::{check_orphan_messages, check_unused_messages};
 pub use parser::{
     choreography_macro, parse_choreography, parse_choreography_file, parse_choreography_str,
-    parse_dsl,
+    parse_choreography_str_with_cfg, parse_dsl,
+};
+pub use projection::{
+    project, project_located, project_with_extensions, validate_guards, BranchAction,
+    LocatedProjectionError, ProjectionError,
+};
+pub use recovery::{
+    check_choreography, check_choreography_with_cfg, parse_choreography_str_recovering,
+    RecoveredParse,
+};
+pub use replay::{replay, ReplayReport, ReplayStep};
+pub use stdlib::STANDARD_PROTOCOLS;
+pub use stepper::{Action, Decision, Event, RoleStatus, RoleView, StepError, Stepper};
+pub use upgrade::{
+    compare_versions, generate_upgrade_adapter, protocol_version, UpgradeError, VersionDiff,
 };
-pub use projection::{project, ProjectionError};
+pub use workspace::{Workspace, WorkspaceBuild, WorkspaceError};
@@ -0,0 +1,65 @@
+//! Per-role projection and code generation on a rayon thread pool
+//!
+//! Parsed choreographies and token streams cannot cross threads, so every
+//! worker parses the source itself, handles a contiguous chunk of roles and
+//! returns their generated sessions, and monitors under `@codegen(monitor)`,
+//! as source text.
+
+use rayon::prelude::*;
+
+use crate::compiler::codegen::{generate_monitor, generate_role_session, is_monitored};
+use crate::compiler::parser::parse_choreography_str_with_extensions;
+use crate::compiler::projection::project_located;
+use crate::extensions::ExtensionRegistry;
+
+/// Stack of every worker thread
+///
+/// Projection and code generation recurse once per statement, so long
+/// protocols need more than the 2 MiB rayon threads get by default.
+const WORKER_STACK_SIZE: usize = 64 * 1024 * 1024;
+
+/// Generate the sessions of the `role_count` roles of the choreography in
+/// `input` on up to `jobs` threads, in role order, each with its monitor if
+/// the choreography asks for them
+///
+/// Returns `None` if the pool cannot be built or any role fails to parse or
+/// project; callers fall back to the serial path to report the error.
+pub(crate) fn generate_role_sessions(
+    input: &str,
+    registry: &ExtensionRegistry,
+    role_count: usize,
+    jobs: usize,
+) -> Option<Vec<(String, Option<String>)>> {
+    let chunk_size = role_count.div_ceil(jobs).max(1);
+    let chunks: Vec<_> = (0..role_count)
+        .step_by(chunk_size)
+        .map(|start| start..(start + chunk_size).min(role_count))
+        .collect();
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .stack_size(WORKER_STACK_SIZE)
+        .build()
+        .ok()?;
+    let sessions = pool.install(|| {
+        chunks
+            .into_par_iter()
+            .map(|chunk| {
+                let (choreography, _) =
+                    parse_choreography_str_with_extensions(input, registry).ok()?;
+                chunk
+                    .map(|index| {
+                        let role = &choreography.roles[index];
+                        let local_type = project_located(&choreography, role, registry).ok()?;
+                        let session = generate_role_session(&choreography, role, &local_type);
+                        let monitor = is_monitored(&choreography)
+                            .then(|| generate_monitor(role, &local_type).to_string());
+                        Some((session.to_string(), monitor))
+                    })
+                    .collect::<Option<Vec<_>>>()
+            })
+            .collect::<Option<Vec<_>>>()
+    })?;
+
+    Some(sessions.into_iter().flatten().collect())
+}
@@ -2,17 +2,33 @@
 //
 // Full implementation using Pest grammar for parsing choreographic DSL
 
+use super::arena::{Arena, Block, Interner, Symbol};
+use super::cfg::{CfgOption, CfgPredicate, CfgSet, CFG_RESOLVED, MAX_CFG_OPTIONS};
+use super::diagnostics::closest_match;
+use super::grammar::ExtensionMatches;
+use super::include::Fragments;
+use super::stdlib::STANDARD_PROTOCOLS;
+use crate::ast::span::LineIndex;
 use crate::ast::{
-    Branch, Choreography, Condition, MessageType, Protocol, RangeExpr, Role, RoleIndex, RoleParam,
-    RoleRange,
+    host_expr, Branch, Choreography, Condition, MessageType, Permission, Policy, PolicyRule,
+    Protocol, RangeExpr, Role, RoleIndex, RoleParam, RoleQuorum, RoleRange, RoleState, Span,
+    StateField, ABORT, ACK, ATOMIC, ATOMIC_ABORT, ATOMIC_COMMIT, BREAK, CFG_DISABLED, COMMIT,
+    CONFIDENTIAL, CONTINUE, DEADLINE_EXCEEDED, DELIVERED, DOC, EXTERNAL, FAILED, HANDOVER, IN_TIME,
+    ON_FAILURE, PREPARE, REASSIGN, STREAM, VOTE, WHILE, WITHIN, WITHIN_EXCEEDED, WITHIN_IN_TIME,
 };
+use crate::effects::middleware::deadlined::parse_time_limit;
+use crate::extensions::annotations::{AnnotationSchema, AnnotationTarget};
 use crate::extensions::{ExtensionRegistry, ProtocolExtension};
+use crate::runtime::http::HttpRoute;
+use crate::runtime::quorum::StragglerPolicy;
+use pest::iterators::Pairs;
 use pest::Parser;
 use pest_derive::Parser;
-use proc_macro2::{Ident, Span, TokenStream};
-use quote::format_ident;
+use proc_macro2::{Ident, TokenStream};
+use quote::{format_ident, quote, ToTokens};
+use std::cell::RefCell;
 use std::collections::hash_map::DefaultHasher;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::hash::{Hash, Hasher};
 use std::sync::{Arc, RwLock};
 use syn::Result;
@@ -20,7 +36,7 @@ use thiserror::Error;
 
 #[derive(Parser)]
 #[grammar = "compiler/choreography.pest"]
-struct ChoreographyParser;
+pub(crate) struct ChoreographyParser;
 
 /// Span information for error reporting
 #[derive(Debug, Clone)]
@@ -100,7 +116,12 @@ pub enum ParseError {
     Syntax { span: ErrorSpan, message: String },
 
     #[error("{}", .span.format_error(&format!("Undefined role '{}'", .role)))]
-    UndefinedRole { role: String, span: ErrorSpan },
+    UndefinedRole {
+        role: String,
+        span: ErrorSpan,
+        /// Closest declared role name
+        suggestion: Option<String>,
+    },
 
     #[error("{}", .span.format_error(&format!("Duplicate role declaration '{}'", .role)))]
     DuplicateRole { role: String, span: ErrorSpan },
@@ -115,7 +136,12 @@ pub enum ParseError {
     InvalidCondition { message: String, span: ErrorSpan },
 
     #[error("{}", .span.format_error(&format!("Undefined protocol '{}'", .protocol)))]
-    UndefinedProtocol { protocol: String, span: ErrorSpan },
+    UndefinedProtocol {
+        protocol: String,
+        span: ErrorSpan,
+        /// Closest defined protocol name
+        suggestion: Option<String>,
+    },
 
     #[error("{}", .span.format_error(&format!("Duplicate protocol definition '{}'", .protocol)))]
     DuplicateProtocol { protocol: String, span: ErrorSpan },
@@ -160,6 +186,20 @@ fn format_pest_error(err: &pest::error::Error<Rule>) -> String {
 }
 
 impl ParseError {
+    fn undefined_role(
+        role: &str,
+        span: pest::Span,
+        input: &str,
+        declared_roles: &HashSet<String>,
+    ) -> Self {
+        ParseError::UndefinedRole {
+            role: role.to_string(),
+            span: ErrorSpan::from_pest_span(span, input),
+            suggestion: closest_match(role, declared_roles.iter().map(String::as_str))
+                .map(str::to_string),
+        }
+    }
+
     /// Create an enhanced error with detailed context
     pub fn with_detailed_context(self, context: &str) -> Self {
         match self {
@@ -221,6 +261,30 @@ impl ParseError {
         ParseError::RoleOverflowError { message, span }
     }
 
+    /// Stable error code, listed in the API reference
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseError::Pest(_) => "RA0001",
+            ParseError::Syntax { .. } => "RA0002",
+            ParseError::UndefinedRole { .. } => "RA0003",
+            ParseError::DuplicateRole { .. } => "RA0004",
+            ParseError::EmptyChoreography => "RA0005",
+            ParseError::InvalidMessage { .. } => "RA0006",
+            ParseError::InvalidCondition { .. } => "RA0007",
+            ParseError::UndefinedProtocol { .. } => "RA0008",
+            ParseError::DuplicateProtocol { .. } => "RA0009",
+            ParseError::InvalidNamespace { .. } => "RA0010",
+            ParseError::InvalidAnnotation { .. } => "RA0011",
+            ParseError::DynamicRoleError { .. } => "RA0012",
+            ParseError::NamespaceConflict { .. } => "RA0013",
+            ParseError::RoleValidationError { .. } => "RA0014",
+            ParseError::AnnotationSyntaxError { .. } => "RA0015",
+            ParseError::RoleOverflowError { .. } => "RA0016",
+            ParseError::GrammarComposition(_) => "RA0017",
+        }
+    }
+
     /// Check if this error is recoverable (allowing parsing to continue)
     pub fn is_recoverable(&self) -> bool {
         matches!(
@@ -253,9 +317,17 @@ impl ParseError {
     /// Get suggested fixes for common errors
     pub fn get_suggestion(&self) -> Option<String> {
         match self {
+            ParseError::UndefinedRole {
+                suggestion: Some(suggestion),
+                ..
+            } => Some(format!("Did you mean '{suggestion}'?")),
             ParseError::UndefinedRole { role, .. } => {
                 Some(format!("Add '{role}' to the roles declaration, or check for typos"))
             }
+            ParseError::UndefinedProtocol {
+                suggestion: Some(suggestion),
+                ..
+            } => Some(format!("Did you mean '{suggestion}'?")),
             ParseError::DuplicateRole { role, .. } => {
                 Some(format!("Remove duplicate declaration of role '{role}'"))
             }
@@ -292,10 +364,8 @@ fn parse_annotation_item(
 
     for inner in pair.into_inner() {
         match inner.as_rule() {
-            Rule::ident => {
-                if key.is_empty() {
-                    key = inner.as_str().to_string();
-                }
+            Rule::ident if key.is_empty() => {
+                key = inner.as_str().to_string();
             }
             Rule::annotation_value => {
                 value = inner.as_str().trim_matches('"').to_string();
@@ -346,10 +416,8 @@ fn parse_annotations(
 
                                 for part in arg_item.into_inner() {
                                     match part.as_rule() {
-                                        Rule::ident => {
-                                            if arg_key.is_empty() {
-                                                arg_key = part.as_str().to_string();
-                                            }
+                                        Rule::ident if arg_key.is_empty() => {
+                                            arg_key = part.as_str().to_string();
                                         }
                                         Rule::annotation_value => {
                                             arg_val = part.as_str().trim_matches('"').to_string();
@@ -390,6 +458,136 @@ fn parse_annotations(
     Ok(annotations)
 }
 
+/// Parse a `policy` block over the declared roles
+fn parse_policy_block(
+    pair: pest::iterators::Pair<Rule>,
+    declared_roles: &HashSet<String>,
+    input: &str,
+) -> std::result::Result<Policy, ParseError> {
+    let mut policy = Policy::default();
+    for rule_pair in pair.into_inner() {
+        let mut parts = rule_pair.into_inner();
+        let role_pair = parts.next().unwrap();
+        if !declared_roles.contains(role_pair.as_str()) {
+            return Err(ParseError::undefined_role(
+                role_pair.as_str(),
+                role_pair.as_span(),
+                input,
+                declared_roles,
+            ));
+        }
+        let role = format_ident!("{}", role_pair.as_str());
+
+        let mut only = false;
+        let mut permissions = Vec::new();
+        for part in parts {
+            match part.as_rule() {
+                Rule::policy_only => only = true,
+                Rule::permission => permissions.push(match part.as_str() {
+                    "initiate" => Permission::Initiate,
+                    _ => Permission::Respond,
+                }),
+                _ => {}
+            }
+        }
+
+        // A `may only` rule must be the role's whole grant
+        let conflicting = policy.rules.iter().any(|other| {
+            other.role == role && (only || other.only) && other.permissions != permissions
+        });
+        if conflicting {
+            return Err(ParseError::Syntax {
+                span: ErrorSpan::from_pest_span(role_pair.as_span(), input),
+                message: format!("policy rules for {role} conflict with its `may only` rule"),
+            });
+        }
+        policy.rules.push(PolicyRule {
+            role,
+            only,
+            permissions,
+        });
+    }
+    Ok(policy)
+}
+
+/// Parse a `state at Role { ... }` declaration over the declared roles
+fn parse_state_decl(
+    pair: pest::iterators::Pair<Rule>,
+    declared_roles: &HashSet<String>,
+    declared_state: &[RoleState],
+    input: &str,
+) -> std::result::Result<RoleState, ParseError> {
+    let mut parts = pair.into_inner();
+    let role_pair = parts.next().unwrap();
+    if !declared_roles.contains(role_pair.as_str()) {
+        return Err(ParseError::undefined_role(
+            role_pair.as_str(),
+            role_pair.as_span(),
+            input,
+            declared_roles,
+        ));
+    }
+    let role = format_ident!("{}", role_pair.as_str());
+    if declared_state.iter().any(|state| state.role == role) {
+        return Err(ParseError::Syntax {
+            span: ErrorSpan::from_pest_span(role_pair.as_span(), input),
+            message: format!("state of {role} is declared twice"),
+        });
+    }
+
+    let mut fields: Vec<StateField> = Vec::new();
+    for field_pair in parts {
+        let mut field_parts = field_pair.into_inner();
+        let name_pair = field_parts.next().unwrap();
+        let type_pair = field_parts.next().unwrap();
+        let name = format_ident!("{}", name_pair.as_str());
+        if fields.iter().any(|field| field.name == name) {
+            return Err(ParseError::Syntax {
+                span: ErrorSpan::from_pest_span(name_pair.as_span(), input),
+                message: format!("state field `{name}` of {role} is declared twice"),
+            });
+        }
+        let ty = syn::parse_str::<syn::Type>(type_pair.as_str())
+            .map_err(|e| ParseError::Syntax {
+                span: ErrorSpan::from_pest_span(type_pair.as_span(), input),
+                message: format!("Invalid state field type: {e}"),
+            })?
+            .to_token_stream();
+        fields.push(StateField { name, ty });
+    }
+    Ok(RoleState { role, fields })
+}
+
+/// Parse the `external <transport>` binding of `role`, returning the
+/// transport
+fn parse_external_binding(
+    pair: pest::iterators::Pair<Rule>,
+    role: &Role,
+    input: &str,
+) -> std::result::Result<String, ParseError> {
+    let transport = pair
+        .into_inner()
+        .find(|p| p.as_rule() == Rule::ident)
+        .unwrap();
+    let span = ErrorSpan::from_pest_span(transport.as_span(), input);
+    if transport.as_str() != "http" {
+        return Err(ParseError::Syntax {
+            span,
+            message: format!(
+                "unknown external transport `{}`, expected `http`",
+                transport.as_str()
+            ),
+        });
+    }
+    if role.is_family() {
+        return Err(ParseError::Syntax {
+            span,
+            message: format!("role family {} cannot be external", role.name),
+        });
+    }
+    Ok(transport.as_str().to_string())
+}
+
 /// Parse a role parameter using enhanced syntax
 fn parse_role_param(
     pair: pest::iterators::Pair<Rule>,
@@ -404,7 +602,27 @@ fn parse_role_param(
             // Check the content of the param_expr directly
             let param_content_str = param_expr.as_str().trim();
 
-            if param_content_str == "*" {
+            if let Some(multiplicity) = param_expr
+                .clone()
+                .into_inner()
+                .find(|part| part.as_rule() == Rule::multiplicity)
+            {
+                let mut bounds = multiplicity
+                    .into_inner()
+                    .map(|bound| bound.as_str().parse());
+                let (Some(Ok(min)), Some(Ok(max))) = (bounds.next(), bounds.next()) else {
+                    return Err(ParseError::Syntax {
+                        span: ErrorSpan::from_pest_span(param_expr.as_span(), input),
+                        message: "Invalid integer in role multiplicity".to_string(),
+                    });
+                };
+                let param = RoleParam::Bounded { min, max };
+                param.validate().map_err(|e| ParseError::Syntax {
+                    span: ErrorSpan::from_pest_span(param_expr.as_span(), input),
+                    message: format!("Role parameter validation failed: {}", e),
+                })?;
+                Ok(param)
+            } else if param_content_str == "*" {
                 // Runtime parameter
                 Ok(RoleParam::Runtime)
             } else if let Ok(count) = param_content_str.parse::<u32>() {
@@ -460,6 +678,7 @@ fn parse_role_index(
                         Ok(RoleIndex::Symbolic(symbolic_name))
                     }
                     Rule::range_expr => parse_range_expr(index_content, input),
+                    Rule::quorum_expr => parse_quorum_expr(index_content, input),
                     _ => {
                         // Check for "*" wildcard
                         let content_str = index_content.as_str();
@@ -553,6 +772,40 @@ fn parse_range_expr(
     Ok(RoleIndex::Range(range))
 }
 
+/// Parse a quorum expression (e.g., 2 of 3, t of N)
+fn parse_quorum_expr(
+    pair: pest::iterators::Pair<Rule>,
+    input: &str,
+) -> std::result::Result<RoleIndex, ParseError> {
+    let pair_span = pair.as_span();
+    let mut bounds = pair
+        .into_inner()
+        .filter(|bound| bound.as_rule() != Rule::of_keyword)
+        .map(|bound| match bound.as_rule() {
+            Rule::integer => bound
+                .as_str()
+                .parse::<u32>()
+                .map(RangeExpr::Concrete)
+                .map_err(|_| ParseError::Syntax {
+                    span: ErrorSpan::from_pest_span(bound.as_span(), input),
+                    message: "Invalid integer in quorum".to_string(),
+                }),
+            _ => Ok(RangeExpr::Symbolic(bound.as_str().to_string())),
+        });
+    let threshold = bounds.next().unwrap()?;
+    let of = bounds.next().unwrap()?;
+
+    let quorum = RoleQuorum { threshold, of };
+
+    // Validate the quorum
+    quorum.validate().map_err(|e| ParseError::Syntax {
+        span: ErrorSpan::from_pest_span(pair_span, input),
+        message: format!("Quorum validation failed: {}", e),
+    })?;
+
+    Ok(RoleIndex::Quorum(Box::new(quorum)))
+}
+
 /// Parse a namespace declaration from the AST
 fn parse_namespace_decl(
     pair: pest::iterators::Pair<Rule>,
@@ -627,30 +880,93 @@ pub fn parse_choreography_str(input: &str) -> std::result::Result<Choreography,
         .map(|(choreo, _)| choreo)
 }
 
+/// Whether `text` is exactly one statement
+pub(crate) fn is_complete_statement(text: &str) -> bool {
+    let text = text.trim();
+    ChoreographyParser::parse(Rule::annotated_stmt, text)
+        .ok()
+        .and_then(|mut pairs| pairs.next())
+        .is_some_and(|pair| pair.as_str().trim_end().len() == text.len())
+}
+
+/// Options named by the `#[cfg(...)]` conditions of `input`
+///
+/// Empty if `input` does not parse; its errors are reported when it is
+/// parsed for good.
+pub(crate) fn cfg_options(input: &str) -> BTreeSet<CfgOption> {
+    let mut options = BTreeSet::new();
+    let Ok(pairs) = ChoreographyParser::parse(Rule::choreography, input) else {
+        return options;
+    };
+    let fragments = Fragments::load(pairs.clone());
+    let fragments = fragments
+        .sources()
+        .filter_map(|(_, source)| ChoreographyParser::parse(Rule::fragment, source).ok());
+    for predicate in std::iter::once(pairs)
+        .chain(fragments)
+        .flat_map(Pairs::flatten)
+        .filter(|pair| pair.as_rule() == Rule::cfg_predicate)
+        .filter_map(|pair| CfgPredicate::parse(pair.as_str()).ok())
+    {
+        predicate.collect_options(&mut options);
+    }
+    options
+}
+
 /// Parse a choreographic protocol from a string with extension support
 pub fn parse_choreography_str_with_extensions(
     input: &str,
     registry: &ExtensionRegistry,
 ) -> std::result::Result<(Choreography, Vec<Box<dyn ProtocolExtension>>), ParseError> {
-    // Generate composed grammar if extensions are registered
-    let (preprocessed_input, pairs) = if registry.has_extensions() {
-        // Use dynamic grammar composition
-        let (preprocessed, pairs) = parse_with_dynamic_grammar(input, registry)?;
-        (Some(preprocessed), pairs)
+    parse_choreography_str_with_cfg(input, registry, &CfgSet::new())
+}
+
+/// Parse a choreographic protocol from a string, keeping the statements and
+/// branches whose `#[cfg(...)]` conditions hold with the options of `cfg`
+pub fn parse_choreography_str_with_cfg(
+    input: &str,
+    registry: &ExtensionRegistry,
+    cfg: &CfgSet,
+) -> std::result::Result<(Choreography, Vec<Box<dyn ProtocolExtension>>), ParseError> {
+    // Without grammar extensions the grammar is the one compiled in
+    let (preprocessed_input, pairs, matches) = if registry.has_grammar_extensions() {
+        let (preprocessed, pairs, matches) = parse_with_extensions(input, registry)?;
+        (Some(preprocessed), pairs, Some(matches))
     } else {
         (
             None,
             ChoreographyParser::parse(Rule::choreography, input).map_err(Box::new)?,
+            None,
         )
     };
 
+    for pair in pairs.clone() {
+        check_host_exprs(pair, input)?;
+    }
+
+    // Check annotations declared by extensions before building the AST
+    let schema = registry.annotation_schema();
+    if !schema.is_empty() {
+        for pair in pairs.clone() {
+            check_annotation_schema(pair, schema, input)?;
+        }
+    }
+
     let mut name = format_ident!("Unnamed");
     let mut namespace: Option<String> = None;
     let mut roles = Vec::new();
-    let mut declared_roles = HashSet::new();
-    let mut protocol_defs: HashMap<String, Vec<Statement>> = HashMap::new();
-    let mut statements = Vec::new();
+    let fragments = Fragments::load(pairs.clone());
+    let mut body = BodyParser::new(input, &fragments);
+    body.cfg = cfg.clone();
+    body.extensions = matches
+        .as_ref()
+        .map(|matches| ExtensionSyntax { registry, matches });
+    let point_pairs = pairs.clone();
+    let mut statements = Block::default();
     let mut attrs: HashMap<String, String> = HashMap::new();
+    let mut policy = None;
+    let mut state = Vec::new();
+    let mut messages: Vec<(Ident, Span)> = Vec::new();
 
     for pair in pairs {
         if pair.as_rule() == Rule::choreography {
@@ -672,31 +988,51 @@ pub fn parse_choreography_str_with_extensions(
                             if let Rule::role_list = role_pair.as_rule() {
                                 for role_decl in role_pair.into_inner() {
                                     if let Rule::role_decl = role_decl.as_rule() {
-                                        let mut inner_role = role_decl.into_inner();
+                                        let mut inner_role = role_decl.into_inner().peekable();
+                                        let mut doc = Vec::new();
+                                        while let Some(line) = inner_role
+                                            .next_if(|part| part.as_rule() == Rule::doc_comment)
+                                        {
+                                            doc.push(doc_line(line));
+                                        }
                                         let role_ident = inner_role.next().unwrap();
                                         let role_name = role_ident.as_str().trim();
                                         let span = role_ident.as_span();
 
-                                        // Check for role parameter
-                                        let role = if let Some(param_pair) = inner_role.next() {
-                                            if param_pair.as_rule() == Rule::role_param {
-                                                // Parse the enhanced parameter syntax
-                                                match parse_role_param(param_pair, role_name, input)
-                                                {
-                                                    Ok(param) => Role::with_param(
+                                        // Check for role parameter and external binding
+                                        let mut role = Role::new(format_ident!("{}", role_name));
+                                        for part in inner_role {
+                                            match part.as_rule() {
+                                                Rule::role_param => {
+                                                    // Parse the enhanced parameter syntax
+                                                    let param =
+                                                        parse_role_param(part, role_name, input)?;
+                                                    role = Role::with_param(
                                                         format_ident!("{}", role_name),
                                                         param,
-                                                    ),
-                                                    Err(e) => return Err(e),
+                                                    );
                                                 }
-                                            } else {
-                                                Role::new(format_ident!("{}", role_name))
+                                                Rule::external_binding => {
+                                                    let transport =
+                                                        parse_external_binding(part, &role, input)?;
+                                                    body.external_roles
+                                                        .insert(role_name.to_string());
+                                                    attrs.insert(
+                                                        format!("{EXTERNAL}.{role_name}"),
+                                                        transport,
+                                                    );
+                                                }
+                                                _ => {}
                                             }
-                                        } else {
-                                            Role::new(format_ident!("{}", role_name))
-                                        };
+                                        }
 
-                                        if !declared_roles.insert(role_name.to_string()) {
+                                        if !doc.is_empty() {
+                                            attrs.insert(
+                                                format!("{DOC}.{role_name}"),
+                                                doc.join("\n"),
+                                            );
+                                        }
+                                        if !body.declared_roles.insert(role_name.to_string()) {
                                             return Err(ParseError::DuplicateRole {
                                                 role: role_name.to_string(),
                                                 span: ErrorSpan::from_pest_span(span, input),
@@ -707,36 +1043,44 @@ pub fn parse_choreography_str_with_extensions(
                                 }
                             }
                         }
+                        body.role_decls.clone_from(&roles);
+                    }
+                    Rule::messages_decl => {
+                        for message in inner.into_inner() {
+                            let name = message.as_str().trim();
+                            if messages.iter().any(|(declared, _)| declared == name) {
+                                return Err(ParseError::Syntax {
+                                    span: ErrorSpan::from_pest_span(message.as_span(), input),
+                                    message: format!("message {name} is declared twice"),
+                                });
+                            }
+                            messages.push((
+                                format_ident!("{}", name),
+                                body.lines.span(message.as_span()),
+                            ));
+                        }
+                    }
+                    Rule::policy_block => {
+                        policy = Some(parse_policy_block(inner, &body.declared_roles, input)?);
+                    }
+                    Rule::state_decl => {
+                        let declaration =
+                            parse_state_decl(inner, &body.declared_roles, &state, input)?;
+                        state.push(declaration);
                     }
                     Rule::protocol_defs => {
                         for protocol_def in inner.into_inner() {
-                            if let Rule::protocol_def = protocol_def.as_rule() {
-                                let mut def_inner = protocol_def.into_inner();
-                                let proto_name_pair = def_inner.next().unwrap();
-                                let proto_name = proto_name_pair.as_str();
-                                let proto_span = proto_name_pair.as_span();
-
-                                if protocol_defs.contains_key(proto_name) {
-                                    return Err(ParseError::DuplicateProtocol {
-                                        protocol: proto_name.to_string(),
-                                        span: ErrorSpan::from_pest_span(proto_span, input),
-                                    });
+                            match protocol_def.as_rule() {
+                                Rule::protocol_def => body.parse_protocol_def(protocol_def)?,
+                                Rule::instantiate_def => {
+                                    body.parse_instantiate_def(protocol_def)?;
                                 }
-
-                                let body_pair = def_inner.next().unwrap();
-                                let body = parse_protocol_body(
-                                    body_pair,
-                                    &declared_roles,
-                                    input,
-                                    &protocol_defs,
-                                )?;
-                                protocol_defs.insert(proto_name.to_string(), body);
+                                _ => {}
                             }
                         }
                     }
                     Rule::protocol_body => {
-                        statements =
-                            parse_protocol_body(inner, &declared_roles, input, &protocol_defs)?;
+                        statements = body.parse_protocol_body(inner)?;
                     }
                     Rule::EOI => {}
                     _ => {}
@@ -749,16 +1093,27 @@ pub fn parse_choreography_str_with_extensions(
         return Err(ParseError::EmptyChoreography);
     }
 
-    let protocol = convert_statements_to_protocol(&statements, &roles);
+    let protocol = body.lower_visible(statements, &roles, &body.scopes)?;
+    let scopes = body
+        .scopes
+        .iter()
+        .map(|scope| body.scope_choreography(scope, &roles))
+        .collect::<std::result::Result<_, _>>()?;
+    if !body.disabled_names.is_empty() {
+        let names: Vec<&str> = body.disabled_names.iter().map(String::as_str).collect();
+        attrs.insert(CFG_DISABLED.to_string(), names.join(" "));
+    }
 
-    // Parse extension statements from the AST
-    let extensions = if registry.has_extensions() {
+    // Extension syntax outside statements, then the preprocessed extensions
+    let mut extensions = match body.extensions {
+        Some(syntax) => syntax.parse_points(point_pairs, &roles, input)?,
+        None => Vec::new(),
+    };
+    if registry.has_extensions() {
         let default_input = input.to_string();
         let extension_input = preprocessed_input.as_ref().unwrap_or(&default_input);
-        parse_extension_statements(extension_input, &roles, &namespace, registry)?
-    } else {
-        Vec::new()
-    };
+        extensions.extend(parse_extension_statements(extension_input, registry)?);
+    }
 
     Ok((
         Choreography {
@@ -767,697 +1122,2667 @@ pub fn parse_choreography_str_with_extensions(
             roles,
             protocol,
             attrs,
+            policy,
+            state,
+            messages,
+            unreachable: body.unreachable,
+            scopes,
         },
         extensions,
     ))
 }
 
-/// Parse protocol body into statements
-fn parse_protocol_body(
+/// Check that every `#{ ... }` escape below `pair` holds a Rust expression
+fn check_host_exprs(
     pair: pest::iterators::Pair<Rule>,
-    declared_roles: &HashSet<String>,
     input: &str,
-    protocol_defs: &HashMap<String, Vec<Statement>>,
-) -> std::result::Result<Vec<Statement>, ParseError> {
-    let mut statements = Vec::new();
-
-    for statement_pair in pair.into_inner() {
-        let statement = parse_statement(statement_pair, declared_roles, input, protocol_defs)?;
-        statements.push(statement);
+) -> std::result::Result<(), ParseError> {
+    for body in pair
+        .into_inner()
+        .flatten()
+        .filter(|pair| pair.as_rule() == Rule::host_expr_body)
+    {
+        syn::parse_str::<syn::Expr>(body.as_str()).map_err(|e| ParseError::Syntax {
+            span: ErrorSpan::from_pest_span(body.as_span(), input),
+            message: format!("Invalid host expression: {e}"),
+        })?;
     }
-
-    Ok(statements)
+    Ok(())
 }
 
-/// Parse a single statement
-fn parse_statement(
+/// Check the annotations below `pair` against the extension annotation schema
+fn check_annotation_schema(
     pair: pest::iterators::Pair<Rule>,
-    declared_roles: &HashSet<String>,
+    schema: &AnnotationSchema,
     input: &str,
-    protocol_defs: &HashMap<String, Vec<Statement>>,
-) -> std::result::Result<Statement, ParseError> {
-    // Handle annotated statements
-    if let Rule::annotated_stmt = pair.as_rule() {
-        let mut inner = pair.into_inner();
-        let mut annotations = HashMap::new();
-
-        // Parse all annotations
-        let mut stmt_pair = inner.next().unwrap();
-        while stmt_pair.as_rule() == Rule::annotation {
-            let annotation_map = parse_annotations(stmt_pair)?;
-            annotations.extend(annotation_map);
-            stmt_pair = inner.next().unwrap();
+) -> std::result::Result<(), ParseError> {
+    let check = |annotations: HashMap<String, String>,
+                 target: Option<AnnotationTarget>,
+                 span: pest::Span|
+     -> std::result::Result<(), ParseError> {
+        for (key, value) in annotations {
+            let result = match target {
+                Some(target) => schema.check(&key, &value, target),
+                None => schema
+                    .get(&key)
+                    .map(|_| Err("not allowed on this statement".to_string())),
+            };
+            if let Some(Err(reason)) = result {
+                return Err(ParseError::InvalidAnnotation {
+                    key: key.into(),
+                    value: value.into(),
+                    reason: reason.into(),
+                    span: ErrorSpan::from_pest_span(span, input),
+                });
+            }
         }
+        Ok(())
+    };
 
-        // Parse the statement and add annotations
-        let mut statement = parse_statement_inner(stmt_pair, declared_roles, input, protocol_defs)?;
-        add_annotations_to_statement(&mut statement, annotations);
-        return Ok(statement);
-    }
-
-    parse_statement_inner(pair, declared_roles, input, protocol_defs)
-}
-
-/// Add statement-level annotations to a parsed statement
-fn add_annotations_to_statement(statement: &mut Statement, annotations: HashMap<String, String>) {
-    match statement {
-        Statement::Send {
-            annotations: stmt_annotations,
-            ..
-        } => {
-            *stmt_annotations = annotations;
+    match pair.as_rule() {
+        Rule::choreography => {
+            for inner in pair.into_inner() {
+                if inner.as_rule() == Rule::annotation {
+                    let span = inner.as_span();
+                    check(
+                        parse_annotations(inner)?,
+                        Some(AnnotationTarget::Choreography),
+                        span,
+                    )?;
+                } else {
+                    check_annotation_schema(inner, schema, input)?;
+                }
+            }
         }
-        Statement::Broadcast {
-            annotations: stmt_annotations,
-            ..
-        } => {
-            *stmt_annotations = annotations;
+        Rule::annotated_stmt => {
+            let inner: Vec<_> = pair.into_inner().collect();
+            let target = inner.last().and_then(|stmt| match stmt.as_rule() {
+                Rule::send_stmt | Rule::reassign_stmt => Some(AnnotationTarget::Send),
+                Rule::broadcast_stmt => Some(AnnotationTarget::Broadcast),
+                Rule::choice_stmt => Some(AnnotationTarget::Choice),
+                _ => None,
+            });
+            for item in inner {
+                if item.as_rule() == Rule::annotation {
+                    let span = item.as_span();
+                    check(parse_annotations(item)?, target, span)?;
+                } else {
+                    check_annotation_schema(item, schema, input)?;
+                }
+            }
         }
-        Statement::Choice {
-            annotations: stmt_annotations,
-            ..
-        } => {
-            *stmt_annotations = annotations;
+        Rule::role_annotations => {
+            let span = pair.as_span();
+            let mut annotations = HashMap::new();
+            for list in pair.into_inner() {
+                for item in list.into_inner() {
+                    let (key, value) = parse_annotation_item(item)?;
+                    annotations.insert(key, value);
+                }
+            }
+            check(annotations, Some(AnnotationTarget::Role), span)?;
         }
         _ => {
-            // Other statement types don't support annotations yet
+            for inner in pair.into_inner() {
+                check_annotation_schema(inner, schema, input)?;
+            }
         }
     }
+    Ok(())
 }
 
-/// Parse the actual statement (without annotations)
-fn parse_statement_inner(
-    pair: pest::iterators::Pair<Rule>,
-    declared_roles: &HashSet<String>,
-    input: &str,
-    protocol_defs: &HashMap<String, Vec<Statement>>,
-) -> std::result::Result<Statement, ParseError> {
-    match pair.as_rule() {
-        Rule::send_stmt => parse_send_stmt(pair, declared_roles, input),
-        Rule::broadcast_stmt => parse_broadcast_stmt(pair, declared_roles, input),
-        Rule::choice_stmt => parse_choice_stmt(pair, declared_roles, input, protocol_defs),
-        Rule::loop_stmt => parse_loop_stmt(pair, declared_roles, input, protocol_defs),
-        Rule::parallel_stmt => parse_parallel_stmt(pair, declared_roles, input, protocol_defs),
-        Rule::rec_stmt => parse_rec_stmt(pair, declared_roles, input, protocol_defs),
-        Rule::call_stmt => parse_call_stmt(pair, declared_roles, input, protocol_defs),
-        _ => {
-            let span = pair.as_span();
-            Err(ParseError::Syntax {
-                span: ErrorSpan::from_pest_span(span, input),
-                message: format!("Unexpected statement type: {:?}", pair.as_rule()),
-            })
-        }
-    }
+/// Statements of a choreography being parsed, built in an arena and lowered
+/// to the protocol AST once the whole choreography is read
+struct BodyParser<'i> {
+    input: &'i str,
+    lines: LineIndex,
+    declared_roles: HashSet<String>,
+    /// Roles declared `external`, implemented by a service outside the session
+    external_roles: HashSet<String>,
+    /// Body of every protocol definition seen so far
+    protocol_defs: HashMap<String, Block>,
+    /// Parameterized protocol definitions seen so far
+    generic_defs: HashMap<String, GenericDef<'i>>,
+    /// Arguments of the parameters of the protocol being instantiated
+    arguments: HashMap<String, String>,
+    /// Number of the instantiation being parsed, from 1
+    instance: Option<usize>,
+    instances: usize,
+    /// Location of the `instantiate` of the standard protocol or the
+    /// `include_protocol!` being parsed, given to its statements
+    origin: Option<Span>,
+    /// Fragments `include_protocol!` may splice
+    fragments: &'i Fragments,
+    /// Paths of the fragments being spliced, outermost first
+    including: Vec<String>,
+    statements: Arena<Statement>,
+    /// Labels and message names
+    idents: Interner<Ident>,
+    /// Role references, keyed by their source text
+    roles: Interner<Role>,
+    /// Loops around the statement being parsed, innermost last
+    loops: Vec<LoopFrame>,
+    /// Statements after a loop that is never left
+    unreachable: Vec<Span>,
+    /// Options set for `#[cfg(...)]` conditions
+    cfg: CfgSet,
+    /// Names used by the statements and branches disabled by `cfg`
+    disabled_names: BTreeSet<String>,
+    /// Nested choreographies in the scope being parsed
+    scopes: Vec<Scope>,
+    /// Compensations of the `compensate` blocks completed before the
+    /// statement being lowered, earliest first
+    saga: RefCell<Vec<Block>>,
+    /// Extension syntax of the input, if it was parsed with the grammar
+    /// extensions of a registry
+    extensions: Option<ExtensionSyntax<'i>>,
+    /// Declared roles, given to extension parsers
+    role_decls: Vec<Role>,
 }
 
-/// Parse a role reference (e.g., A, Worker[0], Worker[i])
-fn parse_role_ref(
-    pair: pest::iterators::Pair<Rule>,
-    declared_roles: &HashSet<String>,
-    input: &str,
-) -> std::result::Result<Role, ParseError> {
-    let span = pair.as_span();
-    let mut inner = pair.into_inner();
+/// Extension syntax of an input parsed with a composed grammar, with the
+/// registry holding the parsers of the extensions
+#[derive(Clone, Copy)]
+struct ExtensionSyntax<'i> {
+    registry: &'i ExtensionRegistry,
+    matches: &'i ExtensionMatches,
+}
 
-    let role_ident = inner.next().unwrap();
-    let role_name = role_ident.as_str().trim();
+impl ExtensionSyntax<'_> {
+    /// Parse the syntax at the extension point pair `pair` with the parser
+    /// registered for the rule that matched it, returning the rule too
+    fn parse(
+        &self,
+        pair: &pest::iterators::Pair<Rule>,
+        roles: &[Role],
+        input: &str,
+    ) -> std::result::Result<(String, Box<dyn ProtocolExtension>), ParseError> {
+        let syntax_error = |message: String| ParseError::Syntax {
+            span: ErrorSpan::from_pest_span(pair.as_span(), input),
+            message,
+        };
+        let rule = self.matches.rule(pair).ok_or_else(|| {
+            syntax_error(format!("no extension rule matched {:?}", pair.as_rule()))
+        })?;
+        let extension = self
+            .parse_text(rule, pair.as_str(), roles, input)
+            .map_err(|e| syntax_error(format!("Invalid {rule}: {e}")))?;
+        Ok((rule.to_string(), extension))
+    }
 
-    // Check if the base role name is declared
-    if !declared_roles.contains(role_name) {
-        return Err(ParseError::UndefinedRole {
-            role: role_name.to_string(),
-            span: ErrorSpan::from_pest_span(span, input),
-        });
+    /// Parse the extension syntax at the points other than statements, such
+    /// as items and role declarations, in source order
+    fn parse_points(
+        &self,
+        pairs: pest::iterators::Pairs<Rule>,
+        roles: &[Role],
+        input: &str,
+    ) -> std::result::Result<Vec<Box<dyn ProtocolExtension>>, ParseError> {
+        pairs
+            .flatten()
+            .filter(|pair| {
+                matches!(
+                    pair.as_rule(),
+                    Rule::extension_item
+                        | Rule::extension_role_declaration
+                        | Rule::extension_choice_modifier
+                        | Rule::extension_annotation
+                        | Rule::extension_expression
+                )
+            })
+            .map(|pair| Ok(self.parse(&pair, roles, input)?.1))
+            .collect()
     }
 
-    // Check if there's an index
-    if let Some(index_pair) = inner.next() {
-        if index_pair.as_rule() == Rule::role_index {
-            // Parse the enhanced index syntax
-            match parse_role_index(index_pair, role_name, input) {
-                Ok(index) => {
-                    return Ok(Role::with_index(format_ident!("{}", role_name), index));
-                }
-                Err(e) => return Err(e),
+    /// Parse `text`, matched by the extension rule `rule`
+    fn parse_text(
+        &self,
+        rule: &str,
+        text: &str,
+        roles: &[Role],
+        input: &str,
+    ) -> std::result::Result<Box<dyn ProtocolExtension>, crate::extensions::ParseError> {
+        let parser = self.registry.find_parser(rule).ok_or_else(|| {
+            crate::extensions::ParseError::InvalidSyntax {
+                details: format!("no parser is registered for {rule}"),
             }
-        }
+        })?;
+        let context = crate::extensions::ParseContext {
+            declared_roles: roles,
+            input,
+        };
+        parser.parse_statement(rule, text, &context)
     }
-
-    // Simple role without index
-    Ok(Role::new(format_ident!("{}", role_name)))
 }
 
-/// Parse an annotated role (role_ref with optional role_annotations)
-fn parse_annotated_role(
-    pair: pest::iterators::Pair<Rule>,
-    declared_roles: &HashSet<String>,
-    input: &str,
-) -> std::result::Result<Role, ParseError> {
-    let mut inner = pair.into_inner();
-
-    // First part should be role_ref
-    let role_ref_pair = inner.next().unwrap();
-    let role = parse_role_ref(role_ref_pair, declared_roles, input)?;
+/// A loop whose body is being parsed, for resolving `break` and `continue`
+struct LoopFrame {
+    /// Label written after `rec`
+    name: Option<Symbol>,
+    /// Label of the recursion the loop lowers to
+    label: Symbol,
+    /// Whether `break` and `continue` may leave the loop, which they may
+    /// not for loops with a condition and parallel blocks
+    jumpable: bool,
+    /// Whether a `break` or `continue` targets the loop
+    jumped: bool,
+    /// Whether a `break` or `continue` leaves the loop
+    left: bool,
+}
 
-    // Check for optional role_annotations
-    if let Some(annotations_pair) = inner.next() {
-        if annotations_pair.as_rule() == Rule::role_annotations {
-            // Parse role-level annotations and add them to the role
-            // For now, we'll store them in the role's metadata (if the Role type supports it)
-            // Otherwise, we'll just ignore them for now and focus on getting the parsing to work
+impl LoopFrame {
+    fn new(name: Option<Symbol>, label: Symbol, jumpable: bool) -> Self {
+        Self {
+            name,
+            label,
+            jumpable,
+            jumped: false,
+            left: false,
         }
     }
-
-    Ok(role)
 }
 
-/// Parse send statement: A -> B: Message(payload)
-fn parse_send_stmt(
-    pair: pest::iterators::Pair<Rule>,
-    declared_roles: &HashSet<String>,
-    input: &str,
-) -> std::result::Result<Statement, ParseError> {
-    let mut inner = pair.into_inner();
+/// A nested choreography, whose statements also run as part of the
+/// enclosing one
+struct Scope {
+    name: Ident,
+    /// Roles only the nested choreography sees
+    private: Vec<Role>,
+    body: Block,
+    /// Nested choreographies in this one
+    scopes: Vec<Scope>,
+}
 
-    let from_pair = inner.next().unwrap();
-    let from = parse_annotated_role(from_pair, declared_roles, input)?;
+impl Scope {
+    /// Private roles of the nested choreographies in `scopes`, at any depth
+    fn hidden(scopes: &[Scope]) -> HashSet<String> {
+        scopes
+            .iter()
+            .flat_map(|scope| {
+                let private = scope.private.iter().map(|role| role.name.to_string());
+                private.chain(Scope::hidden(&scope.scopes))
+            })
+            .collect()
+    }
+}
 
-    let to_pair = inner.next().unwrap();
-    let to = parse_annotated_role(to_pair, declared_roles, input)?;
+/// A parameterized protocol definition, parsed when instantiated
+#[derive(Clone)]
+struct GenericDef<'i> {
+    /// Names of the parameters, with whether each is a count
+    params: Vec<(String, bool)>,
+    body: pest::iterators::Pair<'i, Rule>,
+}
 
-    let message = parse_message(inner.next().unwrap(), input)?;
+impl<'i> GenericDef<'i> {
+    fn new(
+        params: pest::iterators::Pair<'i, Rule>,
+        body: pest::iterators::Pair<'i, Rule>,
+        input: &str,
+    ) -> std::result::Result<Self, ParseError> {
+        let mut names: Vec<(String, bool)> = Vec::new();
+        for param in params.into_inner() {
+            let span = param.as_span();
+            let mut inner = param.into_inner();
+            let name = inner.next().unwrap().as_str().to_string();
+            let count = match inner.next() {
+                None => false,
+                Some(kind) if kind.as_str() == "usize" => true,
+                Some(kind) => {
+                    return Err(ParseError::Syntax {
+                        span: ErrorSpan::from_pest_span(kind.as_span(), input),
+                        message: format!(
+                            "unknown parameter kind `{}`, expected `usize` or none",
+                            kind.as_str()
+                        ),
+                    })
+                }
+            };
+            if names.iter().any(|(other, _)| *other == name) {
+                return Err(ParseError::Syntax {
+                    span: ErrorSpan::from_pest_span(span, input),
+                    message: format!("parameter {name} is declared twice"),
+                });
+            }
+            names.push((name, count));
+        }
+        Ok(Self {
+            params: names,
+            body,
+        })
+    }
+}
 
-    Ok(Statement::Send {
-        from,
-        to,
-        message,
-        annotations: HashMap::new(),
-        from_annotations: HashMap::new(),
-        to_annotations: HashMap::new(),
+/// The standard protocol `name`, if there is one
+fn standard_protocol(name: &str) -> Option<GenericDef<'static>> {
+    standard_protocol_defs().find_map(|def| {
+        let mut inner = def.into_inner();
+        if inner.next()?.as_str() != name {
+            return None;
+        }
+        GenericDef::new(inner.next()?, inner.next()?, "").ok()
     })
 }
 
-/// Parse broadcast statement: A ->* : Message(payload)
-fn parse_broadcast_stmt(
-    pair: pest::iterators::Pair<Rule>,
-    declared_roles: &HashSet<String>,
-    input: &str,
-) -> std::result::Result<Statement, ParseError> {
-    let mut inner = pair.into_inner();
+/// Names of the standard protocols
+fn standard_protocol_names() -> Vec<String> {
+    standard_protocol_defs()
+        .filter_map(|def| def.into_inner().next())
+        .map(|name| name.as_str().to_string())
+        .collect()
+}
 
-    let from_pair = inner.next().unwrap();
-    let from = parse_annotated_role(from_pair, declared_roles, input)?;
+/// Definitions of the standard protocols
+fn standard_protocol_defs() -> impl Iterator<Item = pest::iterators::Pair<'static, Rule>> {
+    STANDARD_PROTOCOLS
+        .iter()
+        .filter_map(|source| ChoreographyParser::parse(Rule::standard_protocols, source).ok())
+        .flat_map(Pairs::flatten)
+        .filter(|pair| pair.as_rule() == Rule::protocol_def)
+}
 
-    let message = parse_message(inner.next().unwrap(), input)?;
+impl<'i> BodyParser<'i> {
+    fn new(input: &'i str, fragments: &'i Fragments) -> Self {
+        Self {
+            input,
+            lines: LineIndex::new(input),
+            declared_roles: HashSet::new(),
+            external_roles: HashSet::new(),
+            protocol_defs: HashMap::new(),
+            generic_defs: HashMap::new(),
+            arguments: HashMap::new(),
+            instance: None,
+            instances: 0,
+            origin: None,
+            fragments,
+            including: Vec::new(),
+            statements: Arena::default(),
+            idents: Interner::default(),
+            roles: Interner::default(),
+            loops: Vec::new(),
+            unreachable: Vec::new(),
+            cfg: CfgSet::new(),
+            disabled_names: BTreeSet::new(),
+            scopes: Vec::new(),
+            saga: RefCell::new(Vec::new()),
+            extensions: None,
+            role_decls: Vec::new(),
+        }
+    }
 
-    Ok(Statement::Broadcast {
-        from,
-        message,
-        annotations: HashMap::new(),
-        from_annotations: HashMap::new(),
-    })
-}
+    fn ident(&mut self, text: &str) -> Symbol {
+        self.idents.intern_with(text, || format_ident!("{}", text))
+    }
 
-/// Parse choice statement
-fn parse_choice_stmt(
-    pair: pest::iterators::Pair<Rule>,
-    declared_roles: &HashSet<String>,
-    input: &str,
-    protocol_defs: &HashMap<String, Vec<Statement>>,
-) -> std::result::Result<Statement, ParseError> {
-    let mut inner = pair.into_inner();
+    /// Location of a statement, or of the `instantiate` of the standard
+    /// protocol it belongs to
+    fn span(&self, span: pest::Span) -> Span {
+        self.origin.unwrap_or_else(|| self.lines.span(span))
+    }
 
-    let role_pair = inner.next().unwrap();
-    let role = if role_pair.as_rule() == Rule::ident {
-        // Simple identifier without indexing
-        let role_name = role_pair.as_str().trim();
-        let role_span = role_pair.as_span();
-        if !declared_roles.contains(role_name) {
-            return Err(ParseError::UndefinedRole {
-                role: role_name.to_string(),
-                span: ErrorSpan::from_pest_span(role_span, input),
-            });
-        }
-        Role::new(format_ident!("{}", role_name))
-    } else {
-        // Role reference (potentially with indexing)
-        parse_role_ref(role_pair, declared_roles, input)?
-    };
+    /// `text`, or the argument given for it if it names a parameter of the
+    /// protocol being instantiated
+    fn resolve<'t>(&'t self, text: &'t str) -> &'t str {
+        self.arguments.get(text).map_or(text, String::as_str)
+    }
 
-    let mut branches = Vec::new();
-    for branch_pair in inner {
-        if let Rule::choice_branch = branch_pair.as_rule() {
-            let mut branch_inner = branch_pair.into_inner();
-            let label = format_ident!("{}", branch_inner.next().unwrap().as_str());
-
-            // Check for optional guard
-            let mut guard = None;
-            let next_item = branch_inner.next().unwrap();
-            let body = if let Rule::guard = next_item.as_rule() {
-                // Parse guard expression
-                let guard_span = next_item.as_span();
-                let mut guard_inner = next_item.into_inner();
-                let guard_expr = guard_inner.next().unwrap().as_str();
-                guard = Some(syn::parse_str::<TokenStream>(guard_expr).map_err(|e| {
-                    ParseError::Syntax {
-                        span: ErrorSpan::from_pest_span(guard_span, input),
-                        message: format!("Invalid guard expression: {e}"),
-                    }
-                })?);
-                // Body comes after guard
-                parse_protocol_body(
-                    branch_inner.next().unwrap(),
-                    declared_roles,
-                    input,
-                    protocol_defs,
-                )?
-            } else {
-                // No guard, next_item is the body
-                parse_protocol_body(next_item, declared_roles, input, protocol_defs)?
-            };
+    /// Label of the recursion a loop at `span` lowers to, distinct for
+    /// every instantiation of a parameterized protocol
+    fn loop_label(&self, kind: &str, span: pest::Span) -> String {
+        match self.instance {
+            Some(instance) => format!("{kind}_{}_{instance}", span.start()),
+            None => format!("{kind}_{}", span.start()),
+        }
+    }
 
-            branches.push(ChoiceBranch {
-                label,
-                guard,
-                statements: body,
+    fn check_new_protocol(
+        &self,
+        name: &str,
+        span: pest::Span,
+    ) -> std::result::Result<(), ParseError> {
+        if self.protocol_defs.contains_key(name) || self.generic_defs.contains_key(name) {
+            return Err(ParseError::DuplicateProtocol {
+                protocol: name.to_string(),
+                span: ErrorSpan::from_pest_span(span, self.input),
             });
         }
+        Ok(())
     }
 
-    Ok(Statement::Choice {
-        role,
-        branches,
-        annotations: HashMap::new(),
-    })
-}
+    /// Define the protocol of `protocol Name { ... }`, or keep the body of
+    /// a parameterized one until it is instantiated
+    fn parse_protocol_def(
+        &mut self,
+        pair: pest::iterators::Pair<'i, Rule>,
+    ) -> std::result::Result<(), ParseError> {
+        let mut inner = pair.into_inner();
+        let name_pair = inner.next().unwrap();
+        let name = name_pair.as_str();
+        self.check_new_protocol(name, name_pair.as_span())?;
+
+        let next = inner.next().unwrap();
+        if next.as_rule() == Rule::protocol_params {
+            let def = GenericDef::new(next, inner.next().unwrap(), self.input)?;
+            self.generic_defs.insert(name.to_string(), def);
+        } else {
+            let body = self.parse_protocol_body(next)?;
+            self.protocol_defs.insert(name.to_string(), body);
+        }
+        Ok(())
+    }
 
-/// Parse loop statement
-fn parse_loop_stmt(
-    pair: pest::iterators::Pair<Rule>,
-    declared_roles: &HashSet<String>,
-    input: &str,
-    protocol_defs: &HashMap<String, Vec<Statement>>,
-) -> std::result::Result<Statement, ParseError> {
-    let inner = pair.into_inner();
-
-    let mut condition = None;
-    let mut body = Vec::new();
-
-    for item in inner {
-        match item.as_rule() {
-            Rule::count_condition => {
-                let span = item.as_span();
-                let mut cond_inner = item.into_inner();
-                let count_pair = cond_inner.next().unwrap();
-                let count_str = count_pair.as_str();
-
-                // Try to parse as integer, otherwise treat as variable
-                if let Ok(count) = count_str.parse::<usize>() {
-                    condition = Some(Condition::Count(count));
+    /// Define the protocol of `instantiate Name<args> as Alias`, from a
+    /// parameterized definition or a standard protocol
+    fn parse_instantiate_def(
+        &mut self,
+        pair: pest::iterators::Pair<'i, Rule>,
+    ) -> std::result::Result<(), ParseError> {
+        let span = pair.as_span();
+        let mut inner = pair.into_inner().skip(1);
+        let name_pair = inner.next().unwrap();
+        let name = name_pair.as_str();
+        let mut args: Vec<_> = inner.collect();
+        let alias_pair = args.pop().unwrap();
+        let alias = alias_pair.as_str();
+        self.check_new_protocol(alias, alias_pair.as_span())?;
+
+        let (def, standard) = match self.generic_defs.get(name) {
+            Some(def) => (def.clone(), false),
+            None => match standard_protocol(name) {
+                Some(def) => (def, true),
+                None => {
+                    let standard = standard_protocol_names();
+                    return Err(ParseError::UndefinedProtocol {
+                        protocol: name.to_string(),
+                        span: ErrorSpan::from_pest_span(name_pair.as_span(), self.input),
+                        suggestion: closest_match(
+                            name,
+                            self.generic_defs
+                                .keys()
+                                .map(String::as_str)
+                                .chain(standard.iter().map(String::as_str)),
+                        )
+                        .map(str::to_string),
+                    });
+                }
+            },
+        };
+        if def.params.len() != args.len() {
+            return Err(ParseError::Syntax {
+                span: ErrorSpan::from_pest_span(span, self.input),
+                message: format!(
+                    "protocol {name} takes {} arguments, {} given",
+                    def.params.len(),
+                    args.len()
+                ),
+            });
+        }
+
+        let mut arguments = HashMap::new();
+        for ((param, count), arg) in def.params.iter().zip(&args) {
+            let is_number = arg
+                .clone()
+                .into_inner()
+                .next()
+                .is_some_and(|arg| arg.as_rule() == Rule::integer);
+            if is_number && !count {
+                return Err(ParseError::Syntax {
+                    span: ErrorSpan::from_pest_span(arg.as_span(), self.input),
+                    message: format!(
+                        "parameter {param} of {name} takes a role or message name, not a number"
+                    ),
+                });
+            }
+            arguments.insert(param.clone(), arg.as_str().to_string());
+        }
+
+        self.instances += 1;
+        let origin = standard.then(|| self.lines.span(span));
+        let outer = (
+            std::mem::replace(&mut self.arguments, arguments),
+            self.instance.replace(self.instances),
+            std::mem::replace(&mut self.origin, origin),
+        );
+        let body = self.parse_protocol_body(def.body);
+        (self.arguments, self.instance, self.origin) = outer;
+        let body = body.map_err(|error| {
+            if standard {
+                ParseError::Syntax {
+                    span: ErrorSpan::from_pest_span(span, self.input),
+                    message: format!(
+                        "standard protocol {name} does not accept these arguments: {error}"
+                    ),
+                }
+            } else {
+                error
+            }
+        })?;
+        self.protocol_defs.insert(alias.to_string(), body);
+        Ok(())
+    }
+
+    /// Parse protocol body into statements
+    fn parse_protocol_body(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<Block, ParseError> {
+        let mark = self.statements.open();
+
+        let mut jump = None;
+        let mut endless = false;
+        for statement_pair in pair.into_inner() {
+            if !self.is_enabled(&statement_pair)? {
+                continue;
+            }
+            if let Some(keyword) = jump {
+                return Err(ParseError::Syntax {
+                    span: ErrorSpan::from_pest_span(statement_pair.as_span(), self.input),
+                    message: format!("statement after `{keyword}` is never reached"),
+                });
+            }
+            if endless {
+                // Reported at the first statement never run
+                self.unreachable.push(self.span(statement_pair.as_span()));
+            }
+            let statement = self.parse_statement(statement_pair)?;
+            jump = match statement {
+                Statement::Break { .. } => Some("break"),
+                Statement::Continue { .. } => Some("continue"),
+                _ => None,
+            };
+            endless = matches!(statement, Statement::Loop { endless: true, .. });
+            self.statements.push(statement);
+        }
+
+        Ok(self.statements.close(mark))
+    }
+
+    /// Whether the `#[cfg(...)]` conditions of a statement or branch hold
+    fn is_enabled(
+        &mut self,
+        pair: &pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<bool, ParseError> {
+        let mut enabled = true;
+        for attr in pair
+            .clone()
+            .into_inner()
+            .filter(|part| part.as_rule() == Rule::cfg_attr)
+        {
+            let predicate = attr.into_inner().next().unwrap();
+            let parsed =
+                CfgPredicate::parse(predicate.as_str()).map_err(|e| ParseError::Syntax {
+                    span: ErrorSpan::from_pest_span(predicate.as_span(), self.input),
+                    message: format!("Invalid cfg predicate: {e}"),
+                })?;
+            enabled &= parsed.eval(&self.cfg);
+        }
+        if !enabled {
+            self.disabled_names.extend(
+                pair.clone()
+                    .into_inner()
+                    .flatten()
+                    .filter(|part| part.as_rule() == Rule::ident)
+                    .map(|part| part.as_str().to_string()),
+            );
+        }
+        Ok(enabled)
+    }
+
+    /// Parse a single statement
+    fn parse_statement(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<Statement, ParseError> {
+        // Handle annotated statements
+        if let Rule::annotated_stmt = pair.as_rule() {
+            let mut inner = pair.into_inner();
+            let mut annotations = HashMap::new();
+            let mut doc = Vec::new();
+
+            // Parse all annotations and doc comments
+            let mut stmt_pair = inner.next().unwrap();
+            loop {
+                match stmt_pair.as_rule() {
+                    Rule::annotation => annotations.extend(parse_annotations(stmt_pair)?),
+                    Rule::doc_comment => doc.push(doc_line(stmt_pair)),
+                    // Resolved by `is_enabled`
+                    Rule::cfg_attr => {}
+                    _ => break,
+                }
+                stmt_pair = inner.next().unwrap();
+            }
+            if !doc.is_empty() {
+                annotations.insert(DOC.to_string(), doc.join("\n"));
+            }
+
+            // Parse the statement and add annotations
+            let stmt_span = stmt_pair.as_span();
+            let mut statement = self.parse_statement_inner(stmt_pair)?;
+            if let Some(policy) = annotations.get("stragglers") {
+                self.check_stragglers(&statement, policy, stmt_span)?;
+            }
+            if let Some(route) = annotations.get("route") {
+                self.check_route(&statement, route, stmt_span)?;
+            }
+            add_annotations_to_statement(&mut statement, annotations);
+            return Ok(statement);
+        }
+
+        self.parse_statement_inner(pair)
+    }
+
+    /// Parse the actual statement (without annotations)
+    fn parse_statement_inner(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<Statement, ParseError> {
+        match pair.as_rule() {
+            Rule::send_stmt => self.parse_send_stmt(pair),
+            Rule::broadcast_stmt => self.parse_broadcast_stmt(pair),
+            Rule::choice_stmt => self.parse_choice_stmt(pair),
+            Rule::loop_stmt => self.parse_loop_stmt(pair),
+            Rule::while_stmt => self.parse_while_stmt(pair),
+            Rule::parallel_stmt => self.parse_parallel_stmt(pair),
+            Rule::rec_stmt => self.parse_rec_stmt(pair),
+            Rule::break_stmt => self.parse_jump_stmt(pair, "break"),
+            Rule::continue_stmt => self.parse_jump_stmt(pair, "continue"),
+            Rule::call_stmt => self.parse_call_stmt(pair),
+            Rule::include_stmt => self.parse_include_stmt(pair),
+            Rule::scope_stmt => self.parse_scope_stmt(pair),
+            Rule::compensate_stmt => self.parse_compensate_stmt(pair),
+            Rule::atomic_stmt => self.parse_atomic_stmt(pair),
+            Rule::within_stmt => self.parse_within_stmt(pair),
+            Rule::reassign_stmt => self.parse_reassign_stmt(pair),
+            Rule::extension_statement => self.parse_extension_stmt(pair),
+            _ => {
+                let span = pair.as_span();
+                Err(ParseError::Syntax {
+                    span: ErrorSpan::from_pest_span(span, self.input),
+                    message: format!("Unexpected statement type: {:?}", pair.as_rule()),
+                })
+            }
+        }
+    }
+
+    /// Parse a statement an extension added to the grammar
+    ///
+    /// The extension parses it here to report its errors, and again for
+    /// every copy lowering makes.
+    fn parse_extension_stmt(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<Statement, ParseError> {
+        let Some(extensions) = self.extensions else {
+            return Err(ParseError::Syntax {
+                span: ErrorSpan::from_pest_span(pair.as_span(), self.input),
+                message: "extension statement without grammar extensions".to_string(),
+            });
+        };
+        let (rule, _) = extensions.parse(&pair, &self.role_decls, self.input)?;
+        Ok(Statement::Extension {
+            rule,
+            text: pair.as_str().to_string(),
+            annotations: HashMap::new(),
+            span: self.span(pair.as_span()),
+        })
+    }
+
+    /// Check that the role named `role_name` was declared
+    fn check_declared(
+        &self,
+        role_name: &str,
+        span: pest::Span,
+    ) -> std::result::Result<(), ParseError> {
+        if self.declared_roles.contains(role_name) {
+            Ok(())
+        } else {
+            Err(ParseError::undefined_role(
+                role_name,
+                span,
+                self.input,
+                &self.declared_roles,
+            ))
+        }
+    }
+
+    /// Fail if `role` is a quorum, which only the sender of a message can be
+    fn reject_quorum(&self, role: Symbol, span: pest::Span) -> std::result::Result<(), ParseError> {
+        let role = self.roles.get(role);
+        match &role.index {
+            Some(RoleIndex::Quorum(quorum)) => Err(ParseError::Syntax {
+                span: ErrorSpan::from_pest_span(span, self.input),
+                message: format!(
+                    "{}[{quorum}] can only send: a quorum is the sender of a message",
+                    role.name
+                ),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Check a `@stragglers` annotation, which needs a send from a quorum
+    fn check_stragglers(
+        &self,
+        statement: &Statement,
+        policy: &str,
+        span: pest::Span,
+    ) -> std::result::Result<(), ParseError> {
+        let invalid = |reason: String| ParseError::InvalidAnnotation {
+            key: "stragglers".into(),
+            value: policy.into(),
+            reason: reason.into(),
+            span: ErrorSpan::from_pest_span(span, self.input),
+        };
+        match statement {
+            Statement::Send { from, .. }
+                if matches!(self.roles.get(*from).index, Some(RoleIndex::Quorum(_))) =>
+            {
+                policy
+                    .parse::<StragglerPolicy>()
+                    .map(|_| ())
+                    .map_err(|e| invalid(e.to_string()))
+            }
+            _ => Err(invalid(
+                "only a send from a quorum such as `Signer[t of N]` has stragglers".into(),
+            )),
+        }
+    }
+
+    /// Check a `@route` annotation, which needs a send to or from an
+    /// external role
+    fn check_route(
+        &self,
+        statement: &Statement,
+        route: &str,
+        span: pest::Span,
+    ) -> std::result::Result<(), ParseError> {
+        let invalid = |reason: String| ParseError::InvalidAnnotation {
+            key: "route".into(),
+            value: route.into(),
+            reason: reason.into(),
+            span: ErrorSpan::from_pest_span(span, self.input),
+        };
+        let is_external = |role: Symbol| {
+            self.external_roles
+                .contains(&self.roles.get(role).name.to_string())
+        };
+        match statement {
+            Statement::Send { from, to, .. } if is_external(*from) || is_external(*to) => route
+                .parse::<HttpRoute>()
+                .map(|_| ())
+                .map_err(|e| invalid(e.to_string())),
+            _ => Err(invalid(
+                "only a send to or from an `external` role has a route".into(),
+            )),
+        }
+    }
+
+    /// Parse a role reference (e.g., A, Worker[0], Worker[i])
+    fn parse_role_ref(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<Symbol, ParseError> {
+        let span = pair.as_span();
+        let source = pair.as_str().trim();
+        let mut inner = pair.into_inner();
+        let role_ident = inner.next().unwrap();
+        let role_name = self.resolve(role_ident.as_str()).to_string();
+        let role_name = role_name.as_str();
+        // The reference as written, with a parameter replaced by its argument
+        let text = format!("{role_name}{}", &source[role_ident.as_str().len()..]);
+        let text = text.as_str();
+        // Checked even for references seen before, which may have been to
+        // a private role of a nested choreography since left
+        self.check_declared(role_name, span)?;
+        if let Some(role) = self.roles.lookup(text) {
+            return Ok(role);
+        }
+
+        // Check if there's an index
+        if let Some(index_pair) = inner.next() {
+            if index_pair.as_rule() == Rule::role_index {
+                // Parse the enhanced index syntax
+                let index = parse_role_index(index_pair, role_name, self.input)?;
+                let role = Role::with_index(format_ident!("{}", role_name), index);
+                return Ok(self.roles.insert(text, role));
+            }
+        }
+
+        // Simple role without index
+        Ok(self
+            .roles
+            .insert(text, Role::new(format_ident!("{}", role_name))))
+    }
+
+    /// Parse an annotated role (role_ref with optional role_annotations)
+    fn parse_annotated_role(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<(Symbol, HashMap<String, String>), ParseError> {
+        let mut inner = pair.into_inner();
+
+        // First part should be role_ref
+        let role_ref_pair = inner.next().unwrap();
+        let role = self.parse_role_ref(role_ref_pair)?;
+
+        // Check for optional role_annotations: A[@key = value, other = value]
+        let mut annotations = HashMap::new();
+        if let Some(annotations_pair) = inner.next() {
+            if annotations_pair.as_rule() == Rule::role_annotations {
+                for list in annotations_pair.into_inner() {
+                    for item in list.into_inner() {
+                        let (key, value) = parse_annotation_item(item)?;
+                        annotations.insert(key, value);
+                    }
+                }
+            }
+        }
+
+        Ok((role, annotations))
+    }
+
+    /// Parse send statement: A -> B: Message(payload), optionally followed by
+    /// `or on failure { ... }`
+    fn parse_send_stmt(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<Statement, ParseError> {
+        let span = self.span(pair.as_span());
+        let mut inner = pair.into_inner();
+
+        let from_pair = inner.next().unwrap();
+        let (from, from_annotations) = self.parse_annotated_role(from_pair)?;
+
+        let to_pair = inner.next().unwrap();
+        let to_span = to_pair.as_span();
+        let (to, to_annotations) = self.parse_annotated_role(to_pair)?;
+        self.reject_quorum(to, to_span)?;
+
+        let mut annotations = HashMap::new();
+        let mut message_pair = inner.next().unwrap();
+        if message_pair.as_rule() == Rule::stream_marker {
+            annotations.insert(STREAM.to_string(), "true".to_string());
+            message_pair = inner.next().unwrap();
+        }
+        let message = self.parse_message(message_pair, &mut annotations)?;
+
+        let recovery = match inner.next() {
+            Some(on_failure) => {
+                let body = on_failure.into_inner().next().unwrap();
+                Some(self.parse_protocol_body(body)?)
+            }
+            None => None,
+        };
+
+        Ok(Statement::Send {
+            from,
+            to,
+            message,
+            annotations,
+            from_annotations,
+            to_annotations,
+            recovery,
+            span,
+        })
+    }
+
+    /// Parse role reassignment: reassign Coordinator from Worker[*] by election
+    ///
+    /// The role's current holder sends a [`HANDOVER`] to every candidate,
+    /// annotated with how the new holder is chosen.
+    fn parse_reassign_stmt(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<Statement, ParseError> {
+        let span = self.span(pair.as_span());
+        let mut inner = pair.into_inner().skip(1);
+
+        let role_pair = inner.next().unwrap();
+        let role_name = role_pair.as_str().trim();
+        self.check_declared(role_name, role_pair.as_span())?;
+        let role = self
+            .roles
+            .intern_with(role_name, || Role::new(format_ident!("{}", role_name)));
+
+        let candidates_pair = inner.next().unwrap();
+        let candidates_span = candidates_pair.as_span();
+        let candidates = self.parse_role_ref(candidates_pair)?;
+        self.reject_quorum(candidates, candidates_span)?;
+        if self.roles.get(candidates).name == role_name {
+            return Err(ParseError::Syntax {
+                span: ErrorSpan::from_pest_span(candidates_span, self.input),
+                message: format!("{role_name} cannot be reassigned to itself"),
+            });
+        }
+
+        let method = inner.next().unwrap().as_str().to_string();
+
+        Ok(Statement::Send {
+            from: role,
+            to: candidates,
+            message: MessageSpec {
+                name: self.ident(HANDOVER),
+                type_annotation: None,
+                payload: None,
+            },
+            annotations: HashMap::from([(REASSIGN.to_string(), method)]),
+            from_annotations: HashMap::new(),
+            to_annotations: HashMap::new(),
+            recovery: None,
+            span,
+        })
+    }
+
+    /// Parse broadcast statement: A ->* : Message(payload)
+    fn parse_broadcast_stmt(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<Statement, ParseError> {
+        let span = self.span(pair.as_span());
+        let mut inner = pair.into_inner();
+
+        let from_pair = inner.next().unwrap();
+        let from_span = from_pair.as_span();
+        let (from, from_annotations) = self.parse_annotated_role(from_pair)?;
+        self.reject_quorum(from, from_span)?;
+
+        let mut annotations = HashMap::new();
+        let message = self.parse_message(inner.next().unwrap(), &mut annotations)?;
+
+        Ok(Statement::Broadcast {
+            from,
+            message,
+            annotations,
+            from_annotations,
+            span,
+        })
+    }
+
+    /// Parse `[prob = p]` of a choice branch
+    fn parse_probability(
+        &self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<f64, ParseError> {
+        let span = pair.as_span();
+        let value = pair.into_inner().next().unwrap().as_str();
+        match value.parse::<f64>() {
+            Ok(probability) if probability <= 1.0 => Ok(probability),
+            _ => Err(ParseError::InvalidAnnotation {
+                key: "prob".into(),
+                value: value.into(),
+                reason: "expected a probability between 0 and 1".into(),
+                span: ErrorSpan::from_pest_span(span, self.input),
+            }),
+        }
+    }
+
+    /// Check that the probabilities of a choice's branches add up to 1, or
+    /// leave something for the branches without one
+    fn check_probabilities(
+        &self,
+        branches: &[ChoiceBranch],
+        span: pest::Span<'_>,
+    ) -> std::result::Result<(), ParseError> {
+        const TOLERANCE: f64 = 1e-6;
+        let given: f64 = branches.iter().filter_map(|b| b.probability).sum();
+        let unset = branches.iter().filter(|b| b.probability.is_none()).count();
+        let reason = if unset == 0 && (given - 1.0).abs() > TOLERANCE {
+            "branch probabilities must add up to 1"
+        } else if unset > 0 && given > 1.0 + TOLERANCE {
+            "branch probabilities add up to more than 1"
+        } else {
+            return Ok(());
+        };
+        Err(ParseError::InvalidAnnotation {
+            key: "prob".into(),
+            value: format!("{given}").into(),
+            reason: reason.into(),
+            span: ErrorSpan::from_pest_span(span, self.input),
+        })
+    }
+
+    /// Parse choice statement
+    fn parse_choice_stmt(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<Statement, ParseError> {
+        let span = self.span(pair.as_span());
+        let choice_span = pair.as_span();
+        let mut inner = pair.into_inner();
+
+        let role_pair = inner.next().unwrap();
+        let role = if role_pair.as_rule() == Rule::ident {
+            // Simple identifier without indexing
+            let role_name = self.resolve(role_pair.as_str().trim()).to_string();
+            let role_name = role_name.as_str();
+            self.check_declared(role_name, role_pair.as_span())?;
+            self.roles
+                .intern_with(role_name, || Role::new(format_ident!("{}", role_name)))
+        } else {
+            // Role reference (potentially with indexing)
+            let span = role_pair.as_span();
+            let role = self.parse_role_ref(role_pair)?;
+            self.reject_quorum(role, span)?;
+            role
+        };
+
+        let mut branches = Vec::new();
+        for branch_pair in inner {
+            if let Rule::choice_branch = branch_pair.as_rule() {
+                if !self.is_enabled(&branch_pair)? {
+                    continue;
+                }
+                let branch_span = self.span(branch_pair.as_span());
+                // Choice modifiers are extension syntax, parsed with the
+                // other extension points
+                let mut branch_inner = branch_pair.into_inner().filter(|part| {
+                    !matches!(
+                        part.as_rule(),
+                        Rule::cfg_attr | Rule::extension_choice_modifier
+                    )
+                });
+                let label = self.ident(branch_inner.next().unwrap().as_str());
+
+                let mut probability = None;
+                let mut next_item = branch_inner.next().unwrap();
+                if let Rule::branch_probability = next_item.as_rule() {
+                    probability = Some(self.parse_probability(next_item)?);
+                    next_item = branch_inner.next().unwrap();
+                }
+
+                // Check for optional guard
+                let mut guard = None;
+                let body = if let Rule::guard = next_item.as_rule() {
+                    // Parse guard expression
+                    let guard_span = next_item.as_span();
+                    let mut guard_inner = next_item.into_inner();
+                    let guard_pair = guard_inner.next().unwrap();
+                    let parsed = if let Rule::host_expr = guard_pair.as_rule() {
+                        // Checked to be an expression by `check_host_exprs`
+                        syn::parse_str::<syn::Expr>(host_expr(guard_pair.as_str()).unwrap_or(""))
+                            .map(|expr| expr.to_token_stream())
+                    } else {
+                        syn::parse_str::<TokenStream>(guard_pair.as_str())
+                    };
+                    guard = Some(parsed.map_err(|e| ParseError::Syntax {
+                        span: ErrorSpan::from_pest_span(guard_span, self.input),
+                        message: format!("Invalid guard expression: {e}"),
+                    })?);
+                    // Body comes after guard
+                    self.parse_protocol_body(branch_inner.next().unwrap())?
                 } else {
-                    // Parse as TokenStream for symbolic count
-                    let token_stream = syn::parse_str::<TokenStream>(count_str).map_err(|e| {
+                    // No guard, next_item is the body
+                    self.parse_protocol_body(next_item)?
+                };
+
+                branches.push(ChoiceBranch {
+                    label,
+                    guard,
+                    probability,
+                    body,
+                    span: branch_span,
+                });
+            }
+        }
+        if branches.is_empty() {
+            return Err(ParseError::Syntax {
+                span: ErrorSpan::from_pest_span(choice_span, self.input),
+                message: "every branch of the choice is disabled by its cfg condition".to_string(),
+            });
+        }
+        self.check_probabilities(&branches, choice_span)?;
+
+        Ok(Statement::Choice {
+            role,
+            branches,
+            annotations: HashMap::new(),
+            span,
+        })
+    }
+
+    /// Parse loop statement
+    fn parse_loop_stmt(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<Statement, ParseError> {
+        let span = self.span(pair.as_span());
+        let label = self.loop_label("loop", pair.as_span());
+        let label = self.ident(&label);
+        let inner = pair.into_inner();
+
+        let mut condition = None;
+        let mut body = Block::default();
+        let mut jumped = false;
+        let mut endless = false;
+
+        for item in inner {
+            match item.as_rule() {
+                Rule::count_condition => {
+                    let span = item.as_span();
+                    let mut cond_inner = item.into_inner();
+                    let count_pair = cond_inner.next().unwrap();
+                    let count_str = self.resolve(count_pair.as_str());
+
+                    // Try to parse as integer, otherwise treat as variable
+                    if let Ok(count) = count_str.parse::<usize>() {
+                        condition = Some(Condition::Count(count));
+                    } else {
+                        // Parse as TokenStream for symbolic count
+                        let token_stream =
+                            syn::parse_str::<TokenStream>(count_str).map_err(|e| {
+                                ParseError::InvalidCondition {
+                                    message: format!("Invalid count: {e}"),
+                                    span: ErrorSpan::from_pest_span(span, self.input),
+                                }
+                            })?;
+                        condition = Some(Condition::Custom(token_stream));
+                    }
+                }
+                Rule::role_decides_condition => {
+                    let mut cond_inner = item.into_inner();
+                    let role_pair = cond_inner.next().unwrap();
+                    let role_str = self.resolve(role_pair.as_str().trim()).to_string();
+                    let role_str = role_str.as_str();
+                    self.check_declared(role_str, role_pair.as_span())?;
+                    condition = Some(Condition::RoleDecides(Role::new(format_ident!(
+                        "{}", role_str
+                    ))));
+                }
+                Rule::custom_condition => {
+                    let span = item.as_span();
+                    let mut cond_inner = item.into_inner();
+                    let custom_str = cond_inner.next().unwrap().as_str();
+                    // Remove quotes from string
+                    let custom_str = custom_str.trim_matches('"');
+                    // Parse as TokenStream for Custom condition
+                    let token_stream = syn::parse_str::<TokenStream>(custom_str).map_err(|e| {
                         ParseError::InvalidCondition {
-                            message: format!("Invalid count: {e}"),
-                            span: ErrorSpan::from_pest_span(span, input),
+                            message: format!("Invalid custom condition: {e}"),
+                            span: ErrorSpan::from_pest_span(span, self.input),
                         }
                     })?;
                     condition = Some(Condition::Custom(token_stream));
                 }
+                Rule::protocol_body => {
+                    let frame = LoopFrame::new(None, label, condition.is_none());
+                    let (parsed, frame) = self.parse_loop_body(item, frame)?;
+                    body = parsed;
+                    jumped = frame.jumped;
+                    endless = condition.is_none() && !frame.left;
+                }
+                _ => {}
             }
-            Rule::role_decides_condition => {
-                let mut cond_inner = item.into_inner();
-                let role_pair = cond_inner.next().unwrap();
-                let role_str = role_pair.as_str().trim();
-                let role_span = role_pair.as_span();
-                if !declared_roles.contains(role_str) {
-                    return Err(ParseError::UndefinedRole {
-                        role: role_str.to_string(),
-                        span: ErrorSpan::from_pest_span(role_span, input),
-                    });
+        }
+
+        Ok(Statement::Loop {
+            condition,
+            body,
+            label: jumped.then_some(label),
+            endless,
+            span,
+        })
+    }
+
+    /// Parse `while at Role { ... }` statement
+    fn parse_while_stmt(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<Statement, ParseError> {
+        let span = self.span(pair.as_span());
+        // Offsets tell nested and sibling loops apart
+        let label = self.loop_label("while", pair.as_span());
+        let label = self.ident(&label);
+        // Skip the `while` keyword
+        let mut inner = pair.into_inner().skip(1);
+
+        let role_pair = inner.next().unwrap();
+        let role_name = self.resolve(role_pair.as_str().trim()).to_string();
+        let role_name = role_name.as_str();
+        self.check_declared(role_name, role_pair.as_span())?;
+        let role = self
+            .roles
+            .intern_with(role_name, || Role::new(format_ident!("{}", role_name)));
+        let frame = LoopFrame::new(None, label, true);
+        let (body, _) = self.parse_loop_body(inner.next().unwrap(), frame)?;
+
+        Ok(Statement::While {
+            role,
+            label,
+            body,
+            span,
+        })
+    }
+
+    /// Parse parallel statement
+    fn parse_parallel_stmt(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<Statement, ParseError> {
+        let span = self.span(pair.as_span());
+        let mut branches = Vec::new();
+
+        for branch_pair in pair.into_inner() {
+            if let Rule::parallel_branch = branch_pair.as_rule() {
+                for body_pair in branch_pair.into_inner() {
+                    if let Rule::protocol_body = body_pair.as_rule() {
+                        let label = self.ident("parallel");
+                        let frame = LoopFrame::new(None, label, false);
+                        branches.push(self.parse_loop_body(body_pair, frame)?.0);
+                    }
+                }
+            }
+        }
+
+        Ok(Statement::Parallel { branches, span })
+    }
+
+    /// Parse recursive statement
+    fn parse_rec_stmt(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<Statement, ParseError> {
+        let span = self.span(pair.as_span());
+        let mut inner = pair.into_inner();
+
+        let label_pair = inner.next().unwrap();
+        let label = self.ident(label_pair.as_str());
+        let frame = LoopFrame::new(Some(label), label, true);
+        let (body, frame) = self.parse_loop_body(inner.next().unwrap(), frame)?;
+        if !frame.left && !self.completes(body) {
+            return Err(ParseError::Syntax {
+                span: ErrorSpan::from_pest_span(label_pair.as_span(), self.input),
+                message: format!(
+                    "rec {} never ends: add a `break`, or write `loop {{ ... }}` for a loop that runs forever",
+                    label_pair.as_str()
+                ),
+            });
+        }
+
+        Ok(Statement::Rec { label, body, span })
+    }
+
+    /// Parse `pair` as the body of the loop `frame`, returning the frame as
+    /// the jumps in the body left it
+    fn parse_loop_body(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+        frame: LoopFrame,
+    ) -> std::result::Result<(Block, LoopFrame), ParseError> {
+        self.loops.push(frame);
+        let body = self.parse_protocol_body(pair);
+        let frame = self.loops.pop().unwrap();
+        Ok((body?, frame))
+    }
+
+    /// Parse `break` or `continue` statement, resolving the loop it targets
+    fn parse_jump_stmt(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+        keyword: &str,
+    ) -> std::result::Result<Statement, ParseError> {
+        let error_span = ErrorSpan::from_pest_span(pair.as_span(), self.input);
+        // Skip the keyword
+        let name = pair
+            .into_inner()
+            .nth(1)
+            .map(|name| name.as_str().to_string());
+        let name_symbol = name.as_deref().map(|name| self.ident(name));
+
+        let error = |message: String| ParseError::Syntax {
+            span: error_span.clone(),
+            message,
+        };
+        let mut target = None;
+        for (index, frame) in self.loops.iter().enumerate().rev() {
+            if !frame.jumpable {
+                return Err(error(format!(
+                    "`{keyword}` cannot leave a `parallel` block, a `loop` with a condition, \
+                     a compensation, an `atomic` block or a `within` block"
+                )));
+            }
+            if name_symbol.is_none() || frame.name == name_symbol {
+                target = Some(index);
+                break;
+            }
+        }
+        let Some(target) = target else {
+            return Err(error(match name {
+                Some(name) => format!("`{keyword} {name}` is not inside `rec {name}`"),
+                None => format!("`{keyword}` is not inside a loop"),
+            }));
+        };
+
+        // Loops inside the target are left, and so is the target on a break
+        let is_break = keyword == "break";
+        for frame in &mut self.loops[target + 1..] {
+            frame.left = true;
+        }
+        let frame = &mut self.loops[target];
+        frame.jumped = true;
+        frame.left |= is_break;
+        let label = frame.label;
+        Ok(if is_break {
+            Statement::Break { label }
+        } else {
+            Statement::Continue { label }
+        })
+    }
+
+    /// Whether running `block` can reach its end rather than always jumping
+    /// out of it
+    fn completes(&self, block: Block) -> bool {
+        self.statements
+            .get(block)
+            .iter()
+            .all(|statement| match statement {
+                Statement::Break { .. } | Statement::Continue { .. } => false,
+                Statement::Choice { branches, .. } => {
+                    branches.iter().any(|branch| self.completes(branch.body))
+                }
+                Statement::Compensate { body, .. } => self.completes(*body),
+                _ => true,
+            })
+    }
+
+    /// Parse protocol call statement
+    fn parse_call_stmt(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<Statement, ParseError> {
+        let mut inner = pair.into_inner();
+        let proto_name_pair = inner.next().unwrap();
+        let proto_name = proto_name_pair.as_str();
+        let span = proto_name_pair.as_span();
+
+        if self.generic_defs.contains_key(proto_name) || standard_protocol(proto_name).is_some() {
+            return Err(ParseError::Syntax {
+                span: ErrorSpan::from_pest_span(span, self.input),
+                message: format!(
+                    "protocol {proto_name} takes parameters, `instantiate` it and call the instance"
+                ),
+            });
+        }
+
+        // Look up the protocol definition
+        let body =
+            self.protocol_defs
+                .get(proto_name)
+                .ok_or_else(|| ParseError::UndefinedProtocol {
+                    protocol: proto_name.to_string(),
+                    span: ErrorSpan::from_pest_span(span, self.input),
+                    suggestion: closest_match(
+                        proto_name,
+                        self.protocol_defs.keys().map(String::as_str),
+                    )
+                    .map(str::to_string),
+                })?;
+
+        // Return a Call statement that will be inlined when lowering
+        Ok(Statement::Call { body: *body })
+    }
+
+    /// Splice the statements of a fragment, renaming its roles as mapped
+    fn parse_include_stmt(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<Statement, ParseError> {
+        let (span, input) = (pair.as_span(), self.input);
+        let error = |message: String| ParseError::Syntax {
+            span: ErrorSpan::from_pest_span(span, input),
+            message,
+        };
+        let mut inner = pair.into_inner();
+        let path = inner.next().unwrap().as_str().trim_matches('"').to_string();
+        let mut arguments = HashMap::new();
+        for mapping in inner {
+            let mut names = mapping.into_inner();
+            let (from, to) = (names.next().unwrap(), names.next().unwrap());
+            let to = self.resolve(to.as_str()).to_string();
+            if arguments.insert(from.as_str().to_string(), to).is_some() {
+                return Err(error(format!("role {} is mapped twice", from.as_str())));
+            }
+        }
+        if self.including.contains(&path) {
+            return Err(error(format!("fragment {path} includes itself")));
+        }
+        let fragments = self.fragments;
+        let source = match fragments.source(&path) {
+            Some(Ok(source)) => source.as_str(),
+            Some(Err(reason)) => {
+                return Err(error(format!("cannot read fragment {path}: {reason}")))
+            }
+            None => return Err(error(format!("cannot read fragment {path}"))),
+        };
+        let body = ChoreographyParser::parse(Rule::fragment, source)
+            .map_err(|e| error(format!("in fragment {path}: {}", format_pest_error(&e))))?
+            .next()
+            .and_then(|fragment| fragment.into_inner().next())
+            .unwrap();
+
+        self.instances += 1;
+        let origin = self.span(span);
+        let outer = (
+            std::mem::replace(&mut self.input, source),
+            std::mem::replace(&mut self.arguments, arguments),
+            self.instance.replace(self.instances),
+            self.origin.replace(origin),
+        );
+        self.including.push(path.clone());
+        let body = self.parse_protocol_body(body);
+        self.including.pop();
+        (self.input, self.arguments, self.instance, self.origin) = outer;
+        let body = body.map_err(|e| error(format!("in fragment {path}: {e}")))?;
+        Ok(Statement::Call { body })
+    }
+
+    /// Parse `choreography Name { private roles: ...; ... }`, whose
+    /// statements run in place and whose private roles are only declared
+    /// inside it
+    fn parse_scope_stmt(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<Statement, ParseError> {
+        let mut inner = pair.into_inner().skip(1);
+        let name = format_ident!("{}", inner.next().unwrap().as_str());
+        let mut private = Vec::new();
+        for role in inner.next().unwrap().into_inner() {
+            let role_name = role.as_str();
+            if !self.declared_roles.insert(role_name.to_string()) {
+                return Err(ParseError::DuplicateRole {
+                    role: role_name.to_string(),
+                    span: ErrorSpan::from_pest_span(role.as_span(), self.input),
+                });
+            }
+            private.push(Role::new(format_ident!("{}", role_name)));
+        }
+
+        let outer = std::mem::take(&mut self.scopes);
+        let body = self.parse_protocol_body(inner.next().unwrap());
+        let scopes = std::mem::replace(&mut self.scopes, outer);
+        for role in &private {
+            self.declared_roles.remove(&role.name.to_string());
+        }
+        let body = body?;
+        self.scopes.push(Scope {
+            name,
+            private,
+            body,
+            scopes,
+        });
+        Ok(Statement::Call { body })
+    }
+
+    /// Parse `compensate { ... } with { ... }`, whose steps run in place and
+    /// whose compensation undoes them if a later send fails
+    fn parse_compensate_stmt(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<Statement, ParseError> {
+        let mut inner = pair.into_inner().skip(1);
+        let body = self.parse_protocol_body(inner.next().unwrap())?;
+        // The compensation runs on the failed branch of a later send, out of
+        // any loop around it
+        let label = self.ident("compensation");
+        let frame = LoopFrame::new(None, label, false);
+        let (compensation, _) = self.parse_loop_body(inner.next().unwrap(), frame)?;
+        Ok(Statement::Compensate { body, compensation })
+    }
+
+    /// Parse `atomic at Coordinator over Participant { ... }`, whose body
+    /// runs in place followed by the two-phase commit described at
+    /// [`ATOMIC`]
+    fn parse_atomic_stmt(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<Statement, ParseError> {
+        let span = self.span(pair.as_span());
+        let mut inner = pair.into_inner().skip(1);
+
+        let coordinator_pair = inner.next().unwrap();
+        let coordinator_name = self.resolve(coordinator_pair.as_str().trim()).to_string();
+        let coordinator_name = coordinator_name.as_str();
+        self.check_declared(coordinator_name, coordinator_pair.as_span())?;
+        let coordinator = self.roles.intern_with(coordinator_name, || {
+            Role::new(format_ident!("{}", coordinator_name))
+        });
+
+        let participants_pair = inner.next().unwrap();
+        let participants_span = participants_pair.as_span();
+        let participants = self.parse_role_ref(participants_pair)?;
+        self.reject_quorum(participants, participants_span)?;
+        let role = self.roles.get(participants);
+        if role.name == coordinator_name {
+            return Err(ParseError::Syntax {
+                span: ErrorSpan::from_pest_span(participants_span, self.input),
+                message: format!("{coordinator_name} cannot take part in its own atomic block"),
+            });
+        }
+        let atomic = match &role.index {
+            Some(index) => format!("{coordinator_name} over {}[{index}]", role.name),
+            None => format!("{coordinator_name} over {}", role.name),
+        };
+
+        // Leaving the body early would skip the commit
+        let label = self.ident("atomic");
+        let frame = LoopFrame::new(None, label, false);
+        let (body, _) = self.parse_loop_body(inner.next().unwrap(), frame)?;
+
+        let step = |parser: &mut Self, from, to, message: &str, payload| Statement::Send {
+            from,
+            to,
+            message: MessageSpec {
+                name: parser.ident(message),
+                type_annotation: None,
+                payload,
+            },
+            annotations: HashMap::from([(ATOMIC.to_string(), atomic.clone())]),
+            from_annotations: HashMap::new(),
+            to_annotations: HashMap::new(),
+            recovery: None,
+            span,
+        };
+        let mut branches = Vec::new();
+        for (label, decision) in [(ATOMIC_COMMIT, COMMIT), (ATOMIC_ABORT, ABORT)] {
+            let mark = self.statements.open();
+            let decide = step(self, coordinator, participants, decision, None);
+            self.statements.push(decide);
+            let ack = step(self, participants, coordinator, ACK, None);
+            self.statements.push(ack);
+            branches.push(ChoiceBranch {
+                label: self.ident(label),
+                guard: None,
+                probability: None,
+                body: self.statements.close(mark),
+                span,
+            });
+        }
+
+        let mark = self.statements.open();
+        self.statements.push(Statement::Call { body });
+        let prepare = step(self, coordinator, participants, PREPARE, None);
+        self.statements.push(prepare);
+        let vote = step(self, participants, coordinator, VOTE, Some(quote! { bool }));
+        self.statements.push(vote);
+        self.statements.push(Statement::Choice {
+            role: coordinator,
+            branches,
+            annotations: HashMap::from([(ATOMIC.to_string(), atomic.clone())]),
+            span,
+        });
+        Ok(Statement::Call {
+            body: self.statements.close(mark),
+        })
+    }
+
+    /// Parse `within 30s { ... } or on deadline { ... }`, whose body runs in
+    /// place followed by the outcome described at [`WITHIN`]
+    fn parse_within_stmt(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+    ) -> std::result::Result<Statement, ParseError> {
+        let span = self.span(pair.as_span());
+        let mut inner = pair.into_inner().skip(1);
+
+        let limit_pair = inner.next().unwrap();
+        let within = limit_pair.as_str().to_string();
+        if !parse_time_limit(&within).is_some_and(|limit| !limit.is_zero()) {
+            return Err(ParseError::Syntax {
+                span: ErrorSpan::from_pest_span(limit_pair.as_span(), self.input),
+                message: format!("`{within}` is not a time limit a block can run within"),
+            });
+        }
+
+        // Leaving the body early would skip the outcome
+        let body_pair = inner.next().unwrap();
+        let body_span = body_pair.as_span();
+        let label = self.ident("within");
+        let frame = LoopFrame::new(None, label, false);
+        let (body, _) = self.parse_loop_body(body_pair, frame)?;
+        let recovery = match inner.next() {
+            Some(on_deadline) => {
+                let label = self.ident("deadline");
+                let frame = LoopFrame::new(None, label, false);
+                Some(
+                    self.parse_loop_body(on_deadline.into_inner().next().unwrap(), frame)?
+                        .0,
+                )
+            }
+            None => None,
+        };
+
+        // The sender of the first message keeps the time
+        let timekeeper = match self.statements.get_mut(body).first_mut() {
+            Some(Statement::Send {
+                from, annotations, ..
+            }) => {
+                annotations.insert(WITHIN.to_string(), within.clone());
+                *from
+            }
+            _ => {
+                return Err(ParseError::Syntax {
+                    span: ErrorSpan::from_pest_span(body_span, self.input),
+                    message: "a `within` block must start with a send, whose sender keeps \
+                              its time"
+                        .to_string(),
+                });
+            }
+        };
+        let mut participants = Vec::new();
+        self.block_roles(body, &mut participants);
+        participants.retain(|role| *role != timekeeper);
+
+        let mut branches = Vec::new();
+        for (label, outcome, recovery) in [
+            (WITHIN_IN_TIME, IN_TIME, None),
+            (WITHIN_EXCEEDED, DEADLINE_EXCEEDED, recovery),
+        ] {
+            let mark = self.statements.open();
+            for participant in &participants {
+                let message = MessageSpec {
+                    name: self.ident(outcome),
+                    type_annotation: None,
+                    payload: None,
+                };
+                self.statements.push(Statement::Send {
+                    from: timekeeper,
+                    to: *participant,
+                    message,
+                    annotations: HashMap::new(),
+                    from_annotations: HashMap::new(),
+                    to_annotations: HashMap::new(),
+                    recovery: None,
+                    span,
+                });
+            }
+            if let Some(recovery) = recovery {
+                self.statements.push(Statement::Call { body: recovery });
+            }
+            branches.push(ChoiceBranch {
+                label: self.ident(label),
+                guard: None,
+                probability: None,
+                body: self.statements.close(mark),
+                span,
+            });
+        }
+
+        let mark = self.statements.open();
+        self.statements.push(Statement::Call { body });
+        self.statements.push(Statement::Choice {
+            role: timekeeper,
+            branches,
+            annotations: HashMap::from([(WITHIN.to_string(), within)]),
+            span,
+        });
+        Ok(Statement::Call {
+            body: self.statements.close(mark),
+        })
+    }
+
+    /// Append the roles sending, receiving or choosing in `block` to
+    /// `roles`, in the order they first appear
+    fn block_roles(&self, block: Block, roles: &mut Vec<Symbol>) {
+        fn add(roles: &mut Vec<Symbol>, role: Symbol) {
+            if !roles.contains(&role) {
+                roles.push(role);
+            }
+        }
+        for statement in self.statements.get(block) {
+            match statement {
+                Statement::Send {
+                    from, to, recovery, ..
+                } => {
+                    add(roles, *from);
+                    add(roles, *to);
+                    if let Some(recovery) = recovery {
+                        self.block_roles(*recovery, roles);
+                    }
+                }
+                Statement::Broadcast { from, .. } => add(roles, *from),
+                Statement::Choice { role, branches, .. } => {
+                    add(roles, *role);
+                    for branch in branches {
+                        self.block_roles(branch.body, roles);
+                    }
+                }
+                Statement::While { role, body, .. } => {
+                    add(roles, *role);
+                    self.block_roles(*body, roles);
+                }
+                Statement::Loop { body, .. }
+                | Statement::Rec { body, .. }
+                | Statement::Call { body } => self.block_roles(*body, roles),
+                Statement::Parallel { branches, .. } => {
+                    for branch in branches {
+                        self.block_roles(*branch, roles);
+                    }
+                }
+                Statement::Compensate { body, .. } => self.block_roles(*body, roles),
+                Statement::Break { .. }
+                | Statement::Continue { .. }
+                | Statement::Extension { .. } => {}
+            }
+        }
+    }
+
+    /// The choreography of `scope`, nested in one with `roles`
+    ///
+    /// It has the roles of the enclosing choreography it uses and its
+    /// private roles.
+    fn scope_choreography(
+        &self,
+        scope: &Scope,
+        roles: &[Role],
+    ) -> std::result::Result<Choreography, ParseError> {
+        let mut roles = roles.to_vec();
+        roles.extend(scope.private.iter().cloned());
+        let protocol = self.lower_visible(scope.body, &roles, &scope.scopes)?;
+        let scopes = scope
+            .scopes
+            .iter()
+            .map(|nested| self.scope_choreography(nested, &roles))
+            .collect::<std::result::Result<_, _>>()?;
+        roles.retain(|role| scope.private.contains(role) || protocol.mentions_role(role));
+        Ok(Choreography {
+            name: scope.name.clone(),
+            namespace: None,
+            roles,
+            protocol,
+            attrs: HashMap::new(),
+            policy: None,
+            state: Vec::new(),
+            messages: Vec::new(),
+            unreachable: Vec::new(),
+            scopes,
+        })
+    }
+
+    /// Lower `block`, leaving out the private roles of the nested
+    /// choreographies `scopes` in it
+    fn lower_visible(
+        &self,
+        block: Block,
+        roles: &[Role],
+        scopes: &[Scope],
+    ) -> std::result::Result<Protocol, ParseError> {
+        let protocol = self.lower(block, roles);
+        let hidden = Scope::hidden(scopes);
+        if hidden.is_empty() {
+            return Ok(protocol);
+        }
+        hide_roles(protocol, &hidden).map_err(|(span, message)| {
+            let start = span.start.min(self.input.len());
+            let span = pest::Span::new(self.input, start, span.end.clamp(start, self.input.len()))
+                .expect("span within the choreography");
+            ParseError::Syntax {
+                span: ErrorSpan::from_pest_span(span, self.input),
+                message,
+            }
+        })
+    }
+
+    /// Parse message specification
+    ///
+    /// Information-flow labels of payload fields go to `annotations`.
+    fn parse_message(
+        &mut self,
+        pair: pest::iterators::Pair<Rule>,
+        annotations: &mut HashMap<String, String>,
+    ) -> std::result::Result<MessageSpec, ParseError> {
+        let mut inner = pair.into_inner();
+
+        let name = self.resolve(inner.next().unwrap().as_str()).to_string();
+        let name = self.ident(&name);
+
+        let mut type_annotation = None;
+        let mut payload = None;
+
+        for part in inner {
+            match part.as_rule() {
+                Rule::message_type => {
+                    // Parse the type annotation
+                    let type_str = part.as_str();
+                    // Remove angle brackets
+                    let type_str = type_str.trim_start_matches('<').trim_end_matches('>');
+                    type_annotation = syn::parse_str::<TokenStream>(type_str).ok();
+                }
+                Rule::payload => {
+                    // Parse the payload
+                    let payload_str = part.as_str();
+                    let payload_str = payload_str.trim_matches('(').trim_matches(')');
+                    let payload_str =
+                        self.parse_payload_labels(payload_str, part.as_span(), annotations)?;
+                    payload = syn::parse_str::<TokenStream>(&payload_str).ok();
+                }
+                _ => {}
+            }
+        }
+
+        Ok(MessageSpec {
+            name,
+            type_annotation,
+            payload,
+        })
+    }
+
+    /// Strip the `[confidential to = "..."]` labels from the fields of
+    /// `payload`, recording each as a [`CONFIDENTIAL`] annotation
+    fn parse_payload_labels(
+        &self,
+        payload: &str,
+        span: pest::Span,
+        annotations: &mut HashMap<String, String>,
+    ) -> std::result::Result<String, ParseError> {
+        let mut fields = Vec::new();
+        for field in split_payload_fields(payload) {
+            let label_start = field
+                .match_indices('[')
+                .map(|(i, _)| i)
+                .find(|&i| field[i + 1..].trim_start().starts_with(CONFIDENTIAL));
+            let Some(start) = label_start else {
+                fields.push(field);
+                continue;
+            };
+
+            let invalid = |reason: &str| ParseError::InvalidAnnotation {
+                key: CONFIDENTIAL.into(),
+                value: field.trim().into(),
+                reason: reason.into(),
+                span: ErrorSpan::from_pest_span(span, self.input),
+            };
+            let label = field[start + 1..]
+                .trim_end()
+                .strip_suffix(']')
+                .ok_or_else(|| invalid("the label must end the field"))?;
+            let roles = label
+                .trim_start()
+                .trim_start_matches(CONFIDENTIAL)
+                .trim_start()
+                .strip_prefix("to")
+                .and_then(|rest| rest.trim_start().strip_prefix('='))
+                .map(|rest| rest.trim().trim_matches('"'))
+                .ok_or_else(|| invalid("expected `confidential to = \"Role, ...\"`"))?;
+            let roles: Vec<_> = roles
+                .split(',')
+                .map(str::trim)
+                .filter(|r| !r.is_empty())
+                .collect();
+            if roles.is_empty() {
+                return Err(invalid("the label must name at least one role"));
+            }
+            for role in &roles {
+                self.check_declared(role, span)?;
+            }
+
+            let field = field[..start].trim_end();
+            let name = field.split(':').next().unwrap_or_default().trim();
+            if name.is_empty() {
+                return Err(invalid("only named fields can be labeled"));
+            }
+            annotations.insert(format!("{CONFIDENTIAL}.{name}"), roles.join(", "));
+            fields.push(field);
+        }
+        Ok(fields.join(","))
+    }
+
+    /// Convert the statements of `block` to protocol AST
+    fn lower(&self, block: Block, roles: &[Role]) -> Protocol {
+        let mut statements = Vec::new();
+        self.inline_calls(block, &mut statements);
+        self.lower_statements(&statements, roles)
+    }
+
+    /// Append the statements of `block` to `statements`, replacing calls
+    /// with the statements of the called protocol
+    ///
+    /// The steps of a `compensate` block are appended too, followed by the
+    /// block itself, which marks where they complete.
+    fn inline_calls<'s>(&'s self, block: Block, statements: &mut Vec<&'s Statement>) {
+        for statement in self.statements.get(block) {
+            match statement {
+                Statement::Call { body } => self.inline_calls(*body, statements),
+                Statement::Compensate { body, .. } => {
+                    self.inline_calls(*body, statements);
+                    statements.push(statement);
+                }
+                _ => statements.push(statement),
+            }
+        }
+    }
+
+    fn message(&self, message: &MessageSpec) -> MessageType {
+        MessageType {
+            name: self.idents.get(message.name).clone(),
+            type_annotation: message.type_annotation.clone(),
+            payload: message.payload.clone(),
+        }
+    }
+
+    /// Convert inlined statements to protocol AST
+    fn lower_statements(&self, statements: &[&Statement], roles: &[Role]) -> Protocol {
+        let mut current = Protocol::End;
+
+        // Compensations completed before each statement, for the sends that
+        // may fail in it
+        let outer = self.saga.borrow().clone();
+        let mut sagas = Vec::with_capacity(statements.len());
+        let mut saga = outer.clone();
+        for statement in statements {
+            sagas.push(saga.clone());
+            if let Statement::Compensate { compensation, .. } = statement {
+                saga.push(*compensation);
+            }
+        }
+
+        // Build protocol from back to front
+        for (index, statement) in statements.iter().enumerate().rev() {
+            *self.saga.borrow_mut() = std::mem::take(&mut sagas[index]);
+            current = match statement {
+                Statement::Send {
+                    from,
+                    to,
+                    message,
+                    annotations,
+                    from_annotations,
+                    to_annotations,
+                    recovery,
+                    span,
+                } => {
+                    let from = self.roles.get(*from);
+                    let to = self.roles.get(*to);
+                    Protocol::Send {
+                        from: from.clone(),
+                        to: to.clone(),
+                        message: self.message(message),
+                        continuation: Box::new(match recovery {
+                            Some(recovery) => self.failure_choice(
+                                from,
+                                to,
+                                current,
+                                *recovery,
+                                &statements[index + 1..],
+                                roles,
+                                *span,
+                            ),
+                            None => current,
+                        }),
+                        annotations: annotations.clone(),
+                        from_annotations: from_annotations.clone(),
+                        to_annotations: to_annotations.clone(),
+                        span: *span,
+                    }
+                }
+                Statement::Broadcast {
+                    from,
+                    message,
+                    annotations,
+                    from_annotations,
+                    span,
+                } => {
+                    let from = self.roles.get(*from);
+                    // Resolve to all roles except the sender
+                    let to_all = roles
+                        .iter()
+                        .filter(|r| r.name != from.name)
+                        .cloned()
+                        .collect();
+
+                    Protocol::Broadcast {
+                        from: from.clone(),
+                        to_all,
+                        message: self.message(message),
+                        continuation: Box::new(current),
+                        annotations: annotations.clone(),
+                        from_annotations: from_annotations.clone(),
+                        span: *span,
+                    }
+                }
+                Statement::Choice {
+                    role,
+                    branches,
+                    annotations,
+                    span,
+                } => {
+                    // The decision of an `atomic` block and the outcome of a
+                    // `within` block go on with the statements after the block
+                    let atomic =
+                        annotations.contains_key(ATOMIC) || annotations.contains_key(WITHIN);
+                    let rest = || self.lower_statements(&statements[index + 1..], roles);
+                    Protocol::Choice {
+                        role: self.roles.get(*role).clone(),
+                        branches: branches
+                            .iter()
+                            .map(|b| {
+                                let protocol = self.lower(b.body, roles);
+                                Branch {
+                                    label: self.idents.get(b.label).clone(),
+                                    guard: b.guard.clone(),
+                                    probability: b.probability,
+                                    protocol: if atomic {
+                                        map_ends(protocol, &mut |end| match end {
+                                            Protocol::End => rest(),
+                                            end => end,
+                                        })
+                                    } else {
+                                        protocol
+                                    },
+                                    span: b.span,
+                                }
+                            })
+                            .collect(),
+                        annotations: annotations.clone(),
+                        span: *span,
+                    }
+                }
+                Statement::Loop {
+                    condition,
+                    body,
+                    label: None,
+                    span,
+                    ..
+                } => Protocol::Loop {
+                    condition: condition.clone(),
+                    body: Box::new(self.lower(*body, roles)),
+                    span: *span,
+                },
+                // A loop left by `break` or `continue` is a recursion that
+                // goes round again at the end of its body
+                Statement::Loop {
+                    body,
+                    label: Some(label),
+                    span,
+                    ..
+                } => {
+                    let label = self.idents.get(*label);
+                    let rest = || self.lower_statements(&statements[index + 1..], roles);
+                    Protocol::Rec {
+                        label: label.clone(),
+                        body: Box::new(loop_exits(self.lower(*body, roles), label, true, &rest)),
+                        span: *span,
+                    }
+                }
+                Statement::While {
+                    role,
+                    label,
+                    body,
+                    span,
+                } => {
+                    let label = self.idents.get(*label);
+                    let rest = || self.lower_statements(&statements[index + 1..], roles);
+                    let body = loop_exits(self.lower(*body, roles), label, true, &rest);
+                    while_loop(self.roles.get(*role), label, body, current, *span)
+                }
+                Statement::Parallel { branches, span } => Protocol::Parallel {
+                    protocols: branches.iter().map(|b| self.lower(*b, roles)).collect(),
+                    span: *span,
+                },
+                Statement::Rec { label, body, span } => {
+                    let label = self.idents.get(*label);
+                    let rest = || self.lower_statements(&statements[index + 1..], roles);
+                    Protocol::Rec {
+                        label: label.clone(),
+                        body: Box::new(loop_exits(self.lower(*body, roles), label, false, &rest)),
+                        span: *span,
+                    }
+                }
+                Statement::Break { label, .. } => {
+                    Protocol::Var(break_label(self.idents.get(*label)))
+                }
+                Statement::Continue { label, .. } => Protocol::Var(self.idents.get(*label).clone()),
+                Statement::Call { .. } => {
+                    // This should not happen after inlining
+                    current
+                }
+                // The steps were inlined before it
+                Statement::Compensate { .. } => current,
+                Statement::Extension {
+                    rule,
+                    text,
+                    annotations,
+                    span,
+                } => Protocol::Extension {
+                    extension: self.extension(rule, text),
+                    continuation: Box::new(current),
+                    annotations: annotations.clone(),
+                    span: *span,
+                },
+            };
+        }
+
+        *self.saga.borrow_mut() = outer;
+        current
+    }
+
+    /// Extension an extension statement parses to
+    ///
+    /// Extensions cannot be cloned, so the statement is parsed again for
+    /// every copy lowering makes of it.
+    fn extension(&self, rule: &str, text: &str) -> Box<dyn ProtocolExtension> {
+        self.extensions
+            .and_then(|extensions| {
+                extensions
+                    .parse_text(rule, text, &self.role_decls, self.input)
+                    .ok()
+            })
+            .expect("extension statement parsed when it was read")
+    }
+
+    /// The choice the sender of an `or on failure` send makes once the send
+    /// has succeeded or failed
+    ///
+    /// Both branches go on with the statements after the send, the failed
+    /// branch after running the recovery statements. Within a saga, the
+    /// failed branch instead runs the recovery statements and then the
+    /// compensations of the completed `compensate` blocks, latest first,
+    /// and ends there.
+    #[allow(clippy::too_many_arguments)]
+    fn failure_choice(
+        &self,
+        from: &Role,
+        to: &Role,
+        delivered: Protocol,
+        recovery: Block,
+        rest: &[&Statement],
+        roles: &[Role],
+        span: Span,
+    ) -> Protocol {
+        let saga = std::mem::take(&mut *self.saga.borrow_mut());
+        let mut failed = Vec::new();
+        self.inline_calls(recovery, &mut failed);
+        if saga.is_empty() {
+            failed.extend_from_slice(rest);
+        }
+        for compensation in saga.iter().rev() {
+            self.inline_calls(*compensation, &mut failed);
+        }
+        let failed = self.lower_statements(&failed, roles);
+        *self.saga.borrow_mut() = saga;
+        Protocol::Choice {
+            role: from.clone(),
+            branches: vec![
+                Branch {
+                    label: format_ident!("{}", DELIVERED),
+                    guard: None,
+                    probability: None,
+                    protocol: delivered,
+                    span,
+                },
+                Branch {
+                    label: format_ident!("{}", FAILED),
+                    guard: None,
+                    probability: None,
+                    protocol: failed,
+                    span,
+                },
+            ],
+            annotations: HashMap::from([(ON_FAILURE.to_string(), to.name.to_string())]),
+            span,
+        }
+    }
+}
+
+/// `protocol` with each ending and `continue` replaced by `f` of it
+///
+/// Loops and parallel blocks have no ending of their own to replace.
+fn map_ends(protocol: Protocol, f: &mut dyn FnMut(Protocol) -> Protocol) -> Protocol {
+    match protocol {
+        Protocol::Send {
+            from,
+            to,
+            message,
+            continuation,
+            annotations,
+            from_annotations,
+            to_annotations,
+            span,
+        } => Protocol::Send {
+            from,
+            to,
+            message,
+            continuation: Box::new(map_ends(*continuation, f)),
+            annotations,
+            from_annotations,
+            to_annotations,
+            span,
+        },
+        Protocol::Broadcast {
+            from,
+            to_all,
+            message,
+            continuation,
+            annotations,
+            from_annotations,
+            span,
+        } => Protocol::Broadcast {
+            from,
+            to_all,
+            message,
+            continuation: Box::new(map_ends(*continuation, f)),
+            annotations,
+            from_annotations,
+            span,
+        },
+        Protocol::Choice {
+            role,
+            branches,
+            annotations,
+            span,
+        } => Protocol::Choice {
+            role,
+            branches: branches
+                .into_iter()
+                .map(|branch| Branch {
+                    protocol: map_ends(branch.protocol, f),
+                    ..branch
+                })
+                .collect(),
+            annotations,
+            span,
+        },
+        Protocol::Rec { label, body, span } => Protocol::Rec {
+            label,
+            body: Box::new(map_ends(*body, f)),
+            span,
+        },
+        Protocol::Extension {
+            extension,
+            continuation,
+            annotations,
+            span,
+        } => Protocol::Extension {
+            extension,
+            continuation: Box::new(map_ends(*continuation, f)),
+            annotations,
+            span,
+        },
+        Protocol::End | Protocol::Var(_) => f(protocol),
+        protocol @ (Protocol::Loop { .. } | Protocol::Parallel { .. }) => protocol,
+    }
+}
+
+/// Name `break` out of the recursion `label` lowers to until the loop is
+/// closed by [`loop_exits`]
+fn break_label(label: &Ident) -> Ident {
+    format_ident!("break_{}", label)
+}
+
+/// Body of the loop `label` with its `break`s going on with `rest`, and its
+/// endings going round again if it `repeats` and on with `rest` otherwise
+fn loop_exits(
+    body: Protocol,
+    label: &Ident,
+    repeats: bool,
+    rest: &dyn Fn() -> Protocol,
+) -> Protocol {
+    let exit = break_label(label);
+    map_ends(body, &mut |end| match end {
+        Protocol::End if repeats => Protocol::Var(label.clone()),
+        Protocol::End => rest(),
+        Protocol::Var(var) if var == exit => rest(),
+        end => end,
+    })
+}
+
+/// The recursion `label` a `while at role { body }` statement followed by
+/// `rest` lowers to, see [`WHILE`]
+fn while_loop(role: &Role, label: &Ident, body: Protocol, rest: Protocol, span: Span) -> Protocol {
+    let branch = |name: &str, protocol| Branch {
+        label: format_ident!("{}", name),
+        guard: None,
+        probability: None,
+        protocol,
+        span,
+    };
+    Protocol::Rec {
+        label: label.clone(),
+        body: Box::new(Protocol::Choice {
+            role: role.clone(),
+            branches: vec![branch(CONTINUE, body), branch(BREAK, rest)],
+            annotations: HashMap::from([(WHILE.to_string(), role.name.to_string())]),
+            span,
+        }),
+        span,
+    }
+}
+
+/// `protocol` as seen by the roles outside the nested choreographies that
+/// declare the `hidden` roles private
+///
+/// Messages to and from a hidden role are left out. A choice made by a
+/// hidden role is left out too, which it can only be if every branch is
+/// left out entirely: otherwise the outer roles would depend on a decision
+/// they cannot see. Fails with the location and reason otherwise.
+fn hide_roles(
+    protocol: Protocol,
+    hidden: &HashSet<String>,
+) -> std::result::Result<Protocol, (Span, String)> {
+    let is_hidden = |role: &Role| hidden.contains(&role.name.to_string());
+    Ok(match protocol {
+        Protocol::Send {
+            from,
+            to,
+            continuation,
+            ..
+        } if is_hidden(&from) || is_hidden(&to) => hide_roles(*continuation, hidden)?,
+        Protocol::Send {
+            from,
+            to,
+            message,
+            continuation,
+            annotations,
+            from_annotations,
+            to_annotations,
+            span,
+        } => Protocol::Send {
+            from,
+            to,
+            message,
+            continuation: Box::new(hide_roles(*continuation, hidden)?),
+            annotations,
+            from_annotations,
+            to_annotations,
+            span,
+        },
+        Protocol::Broadcast {
+            from, continuation, ..
+        } if is_hidden(&from) => hide_roles(*continuation, hidden)?,
+        Protocol::Broadcast {
+            from,
+            to_all,
+            message,
+            continuation,
+            annotations,
+            from_annotations,
+            span,
+        } => Protocol::Broadcast {
+            from,
+            to_all: to_all.into_iter().filter(|to| !is_hidden(to)).collect(),
+            message,
+            continuation: Box::new(hide_roles(*continuation, hidden)?),
+            annotations,
+            from_annotations,
+            span,
+        },
+        Protocol::Choice {
+            role,
+            branches,
+            span,
+            ..
+        } if is_hidden(&role) => {
+            for branch in branches {
+                if !matches!(hide_roles(branch.protocol, hidden)?, Protocol::End) {
+                    return Err((
+                        span,
+                        format!(
+                            "choice by private role {} decides what roles outside its \
+                             choreography do",
+                            role.name
+                        ),
+                    ));
+                }
+            }
+            Protocol::End
+        }
+        Protocol::Choice {
+            role,
+            branches,
+            annotations,
+            span,
+        } => Protocol::Choice {
+            role,
+            branches: branches
+                .into_iter()
+                .map(|branch| {
+                    Ok(Branch {
+                        protocol: hide_roles(branch.protocol, hidden)?,
+                        ..branch
+                    })
+                })
+                .collect::<std::result::Result<_, _>>()?,
+            annotations,
+            span,
+        },
+        Protocol::Loop {
+            condition,
+            body,
+            span,
+        } => match hide_roles(*body, hidden)? {
+            Protocol::End => Protocol::End,
+            body => Protocol::Loop {
+                condition,
+                body: Box::new(body),
+                span,
+            },
+        },
+        Protocol::Parallel { protocols, span } => {
+            let mut kept = Vec::new();
+            for protocol in protocols {
+                match hide_roles(protocol, hidden)? {
+                    Protocol::End => {}
+                    protocol => kept.push(protocol),
                 }
-                condition = Some(Condition::RoleDecides(Role::new(format_ident!(
-                    "{}", role_str
-                ))));
-            }
-            Rule::custom_condition => {
-                let span = item.as_span();
-                let mut cond_inner = item.into_inner();
-                let custom_str = cond_inner.next().unwrap().as_str();
-                // Remove quotes from string
-                let custom_str = custom_str.trim_matches('"');
-                // Parse as TokenStream for Custom condition
-                let token_stream = syn::parse_str::<TokenStream>(custom_str).map_err(|e| {
-                    ParseError::InvalidCondition {
-                        message: format!("Invalid custom condition: {e}"),
-                        span: ErrorSpan::from_pest_span(span, input),
-                    }
-                })?;
-                condition = Some(Condition::Custom(token_stream));
             }
-            Rule::protocol_body => {
-                body = parse_protocol_body(item, declared_roles, input, protocol_defs)?;
+            match kept.len() {
+                0 => Protocol::End,
+                1 => kept.remove(0),
+                _ => Protocol::Parallel {
+                    protocols: kept,
+                    span,
+                },
             }
-            _ => {}
         }
-    }
-
-    Ok(Statement::Loop { condition, body })
-}
-
-/// Parse parallel statement
-fn parse_parallel_stmt(
-    pair: pest::iterators::Pair<Rule>,
-    declared_roles: &HashSet<String>,
-    input: &str,
-    protocol_defs: &HashMap<String, Vec<Statement>>,
-) -> std::result::Result<Statement, ParseError> {
-    let mut branches = Vec::new();
-
-    for branch_pair in pair.into_inner() {
-        if let Rule::parallel_branch = branch_pair.as_rule() {
-            for body_pair in branch_pair.into_inner() {
-                if let Rule::protocol_body = body_pair.as_rule() {
-                    let body =
-                        parse_protocol_body(body_pair, declared_roles, input, protocol_defs)?;
-                    branches.push(body);
-                }
-            }
+        Protocol::Rec { label, body, span } => Protocol::Rec {
+            label,
+            body: Box::new(hide_roles(*body, hidden)?),
+            span,
+        },
+        Protocol::Extension {
+            extension, span, ..
+        } if hidden
+            .iter()
+            .any(|name| extension.mentions_role(&Role::new(format_ident!("{}", name)))) =>
+        {
+            return Err((
+                span,
+                format!(
+                    "{} statement involves a private role of a nested choreography",
+                    extension.type_name()
+                ),
+            ))
         }
-    }
-
-    Ok(Statement::Parallel { branches })
-}
-
-/// Parse recursive statement
-fn parse_rec_stmt(
-    pair: pest::iterators::Pair<Rule>,
-    declared_roles: &HashSet<String>,
-    input: &str,
-    protocol_defs: &HashMap<String, Vec<Statement>>,
-) -> std::result::Result<Statement, ParseError> {
-    let mut inner = pair.into_inner();
-
-    let label = format_ident!("{}", inner.next().unwrap().as_str());
-    let body = parse_protocol_body(inner.next().unwrap(), declared_roles, input, protocol_defs)?;
-
-    Ok(Statement::Rec { label, body })
-}
-
-/// Parse protocol call statement
-fn parse_call_stmt(
-    pair: pest::iterators::Pair<Rule>,
-    _declared_roles: &HashSet<String>,
-    input: &str,
-    protocol_defs: &HashMap<String, Vec<Statement>>,
-) -> std::result::Result<Statement, ParseError> {
-    let mut inner = pair.into_inner();
-    let proto_name_pair = inner.next().unwrap();
-    let proto_name = proto_name_pair.as_str();
-    let span = proto_name_pair.as_span();
-
-    // Look up the protocol definition
-    let proto_statements =
-        protocol_defs
-            .get(proto_name)
-            .ok_or_else(|| ParseError::UndefinedProtocol {
-                protocol: proto_name.to_string(),
-                span: ErrorSpan::from_pest_span(span, input),
-            })?;
-
-    // Return a Call statement that will be inlined later
-    Ok(Statement::Call {
-        name: format_ident!("{}", proto_name),
-        statements: proto_statements.clone(),
+        Protocol::Extension {
+            extension,
+            continuation,
+            annotations,
+            span,
+        } => Protocol::Extension {
+            extension,
+            continuation: Box::new(hide_roles(*continuation, hidden)?),
+            annotations,
+            span,
+        },
+        protocol @ (Protocol::Var(_) | Protocol::End) => protocol,
     })
 }
 
-/// Parse message specification
-fn parse_message(
-    pair: pest::iterators::Pair<Rule>,
-    _input: &str,
-) -> std::result::Result<MessageSpec, ParseError> {
-    let _span = pair.as_span();
-    let mut inner = pair.into_inner();
-
-    let name = format_ident!("{}", inner.next().unwrap().as_str());
-
-    let mut type_annotation = None;
-    let mut payload = None;
+/// Add statement-level annotations to a parsed statement
+/// Text of a `///` comment line, without the space after the slashes
+fn doc_line(pair: pest::iterators::Pair<Rule>) -> String {
+    let text = pair
+        .into_inner()
+        .next()
+        .map_or("", |text| text.as_str().trim_end());
+    text.strip_prefix(' ').unwrap_or(text).to_string()
+}
 
-    for part in inner {
-        match part.as_rule() {
-            Rule::message_type => {
-                // Parse the type annotation
-                let type_str = part.as_str();
-                // Remove angle brackets
-                let type_str = type_str.trim_start_matches('<').trim_end_matches('>');
-                type_annotation = syn::parse_str::<TokenStream>(type_str).ok();
-            }
-            Rule::payload => {
-                // Parse the payload
-                let payload_str = part.as_str();
-                let payload_str = payload_str.trim_matches('(').trim_matches(')');
-                payload = syn::parse_str::<TokenStream>(payload_str).ok();
-            }
-            _ => {}
+fn add_annotations_to_statement(statement: &mut Statement, annotations: HashMap<String, String>) {
+    match statement {
+        Statement::Send {
+            annotations: stmt_annotations,
+            ..
+        } => {
+            // Keep what the statement itself set, such as `stream`
+            stmt_annotations.extend(annotations);
+        }
+        Statement::Broadcast {
+            annotations: stmt_annotations,
+            ..
+        } => {
+            *stmt_annotations = annotations;
+        }
+        Statement::Choice {
+            annotations: stmt_annotations,
+            ..
+        }
+        | Statement::Extension {
+            annotations: stmt_annotations,
+            ..
+        } => {
+            *stmt_annotations = annotations;
+        }
+        _ => {
+            // Other statement types don't support annotations yet
         }
     }
-
-    Ok(MessageSpec {
-        name,
-        type_annotation,
-        payload,
-    })
 }
 
 /// Choreography statement types
-#[derive(Debug, Clone)]
-#[allow(clippy::large_enum_variant)] // Statement enum is internal to parser; performance impact is minimal
+///
+/// Roles and identifiers are symbols of the [`BodyParser`] interners and
+/// nested statements are blocks of its arena.
+#[derive(Debug)]
 enum Statement {
     Send {
-        from: Role,
-        to: Role,
+        from: Symbol,
+        to: Symbol,
         message: MessageSpec,
         annotations: HashMap<String, String>,
         from_annotations: HashMap<String, String>,
         to_annotations: HashMap<String, String>,
+        /// Statements run instead of the rest of the protocol if the send fails
+        recovery: Option<Block>,
+        span: Span,
     },
     Broadcast {
-        from: Role,
+        from: Symbol,
         message: MessageSpec,
         annotations: HashMap<String, String>,
         from_annotations: HashMap<String, String>,
+        span: Span,
     },
     Choice {
-        role: Role,
+        role: Symbol,
         branches: Vec<ChoiceBranch>,
         annotations: HashMap<String, String>,
+        span: Span,
     },
     Loop {
         condition: Option<Condition>,
-        body: Vec<Statement>,
+        body: Block,
+        /// Label of the recursion the loop lowers to, if `break` or
+        /// `continue` targets it
+        label: Option<Symbol>,
+        /// Whether the loop has no condition and no `break`, so never ends
+        endless: bool,
+        span: Span,
+    },
+    /// Loop whose `role` decides before each iteration whether to run it
+    While {
+        role: Symbol,
+        label: Symbol,
+        body: Block,
+        span: Span,
     },
     Parallel {
-        branches: Vec<Vec<Statement>>,
+        branches: Vec<Block>,
+        span: Span,
     },
     Rec {
-        label: Ident,
-        body: Vec<Statement>,
+        label: Symbol,
+        body: Block,
+        span: Span,
+    },
+    /// `break` out of the loop lowering to the recursion `label`
+    Break {
+        label: Symbol,
+    },
+    /// `continue` with the loop lowering to the recursion `label`
+    Continue {
+        label: Symbol,
     },
+    /// Call of a protocol definition, whose body is inlined when lowering
     Call {
-        #[allow(dead_code)]
-        name: Ident,
-        statements: Vec<Statement>,
+        body: Block,
+    },
+    /// Steps inlined when lowering, undone by `compensation` if a send
+    /// after them fails
+    Compensate {
+        body: Block,
+        compensation: Block,
+    },
+    /// Statement an extension added to the grammar, matched by its `rule`
+    Extension {
+        rule: String,
+        text: String,
+        annotations: HashMap<String, String>,
+        span: Span,
     },
 }
 
 /// Choice branch in choreography
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 struct ChoiceBranch {
-    label: Ident,
+    label: Symbol,
     guard: Option<TokenStream>,
-    statements: Vec<Statement>,
+    probability: Option<f64>,
+    body: Block,
+    span: Span,
 }
 
 /// Message specification with optional payload
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 struct MessageSpec {
-    name: Ident,
+    name: Symbol,
     type_annotation: Option<TokenStream>,
     payload: Option<TokenStream>,
 }
 
-/// Convert statements to protocol AST
-fn convert_statements_to_protocol(statements: &[Statement], roles: &[Role]) -> Protocol {
-    if statements.is_empty() {
-        return Protocol::End;
-    }
-
-    // First, inline all Call statements
-    let inlined = inline_calls(statements);
-
-    let mut current = Protocol::End;
-
-    // Build protocol from back to front
-    for statement in inlined.iter().rev() {
-        current = match statement {
-            Statement::Send {
-                from,
-                to,
-                message,
-                annotations,
-                from_annotations,
-                to_annotations,
-            } => Protocol::Send {
-                from: from.clone(),
-                to: to.clone(),
-                message: MessageType {
-                    name: message.name.clone(),
-                    type_annotation: message.type_annotation.clone(),
-                    payload: message.payload.clone(),
-                },
-                continuation: Box::new(current),
-                annotations: annotations.clone(),
-                from_annotations: from_annotations.clone(),
-                to_annotations: to_annotations.clone(),
-            },
-            Statement::Broadcast {
-                from,
-                message,
-                annotations,
-                from_annotations,
-            } => {
-                // Resolve to all roles except the sender
-                let to_all = roles
-                    .iter()
-                    .filter(|r| r.name != from.name)
-                    .cloned()
-                    .collect();
-
-                Protocol::Broadcast {
-                    from: from.clone(),
-                    to_all,
-                    message: MessageType {
-                        name: message.name.clone(),
-                        type_annotation: message.type_annotation.clone(),
-                        payload: message.payload.clone(),
-                    },
-                    continuation: Box::new(current),
-                    annotations: annotations.clone(),
-                    from_annotations: from_annotations.clone(),
-                }
-            }
-            Statement::Choice {
-                role,
-                branches,
-                annotations,
-            } => Protocol::Choice {
-                role: role.clone(),
-                branches: branches
-                    .iter()
-                    .map(|b| Branch {
-                        label: b.label.clone(),
-                        guard: b.guard.clone(),
-                        protocol: convert_statements_to_protocol(&b.statements, roles),
-                    })
-                    .collect(),
-                annotations: annotations.clone(),
-            },
-            Statement::Loop { condition, body } => Protocol::Loop {
-                condition: condition.clone(),
-                body: Box::new(convert_statements_to_protocol(body, roles)),
-            },
-            Statement::Parallel { branches } => Protocol::Parallel {
-                protocols: branches
-                    .iter()
-                    .map(|b| convert_statements_to_protocol(b, roles))
-                    .collect(),
-            },
-            Statement::Rec { label, body } => Protocol::Rec {
-                label: label.clone(),
-                body: Box::new(convert_statements_to_protocol(body, roles)),
-            },
-            Statement::Call { .. } => {
-                // This should not happen after inlining
-                current
-            }
-        };
-    }
-
-    current
-}
-
-/// Inline all Call statements by replacing them with their definitions
-fn inline_calls(statements: &[Statement]) -> Vec<Statement> {
-    let mut result = Vec::new();
-
-    for statement in statements {
-        match statement {
-            Statement::Call { statements, .. } => {
-                // Recursively inline the called protocol's statements
-                result.extend(inline_calls(statements));
-            }
-            Statement::Choice { role, branches, .. } => {
-                // Inline calls within choice branches
-                let new_branches = branches
-                    .iter()
-                    .map(|b| ChoiceBranch {
-                        label: b.label.clone(),
-                        guard: b.guard.clone(),
-                        statements: inline_calls(&b.statements),
-                    })
-                    .collect();
-                result.push(Statement::Choice {
-                    role: role.clone(),
-                    branches: new_branches,
-                    annotations: HashMap::new(),
-                });
-            }
-            Statement::Loop { condition, body } => {
-                // Inline calls within loop body
-                result.push(Statement::Loop {
-                    condition: condition.clone(),
-                    body: inline_calls(body),
-                });
-            }
-            Statement::Parallel { branches } => {
-                // Inline calls within parallel branches
-                let new_branches = branches.iter().map(|b| inline_calls(b)).collect();
-                result.push(Statement::Parallel {
-                    branches: new_branches,
-                });
-            }
-            Statement::Rec { label, body } => {
-                // Inline calls within recursive body
-                result.push(Statement::Rec {
-                    label: label.clone(),
-                    body: inline_calls(body),
-                });
-            }
-            _ => {
-                // Other statements remain unchanged
-                result.push(statement.clone());
+/// Split the text of a payload at the commas between its fields
+fn split_payload_fields(payload: &str) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let mut depth = 0usize;
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in payload.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '<' | '[' | '(' if !quoted => depth += 1,
+            '>' | ']' | ')' if !quoted => depth = depth.saturating_sub(1),
+            ',' if !quoted && depth == 0 => {
+                fields.push(&payload[start..i]);
+                start = i + 1;
             }
+            _ => {}
         }
     }
-
-    result
+    fields.push(&payload[start..]);
+    fields
 }
 
-/// Parse with dynamic grammar composition for extensions
-fn parse_with_dynamic_grammar<'a>(
+/// Parse with the grammar composed from the grammar extensions of
+/// `registry`
+///
+/// Also returns the input with the syntax of preprocessing extensions
+/// rewritten, and the extension rules that matched.
+fn parse_with_extensions<'a>(
     input: &'a str,
     registry: &ExtensionRegistry,
-) -> std::result::Result<(String, pest::iterators::Pairs<'a, Rule>), ParseError> {
-    // Clean and elegant approach: preprocess extension syntax then parse
+) -> std::result::Result<(String, pest::iterators::Pairs<'a, Rule>, ExtensionMatches), ParseError> {
+    let grammar = crate::compiler::grammar::composed_grammar(registry)?;
+    let (pairs, matches) = grammar.parse(Rule::choreography, input)?;
     let preprocessed = preprocess_extension_syntax(input, registry)?;
 
-    // Parse the original input for now, but return the preprocessed version
-    // so the caller can handle extensions based on the preprocessing
-    let pairs = ChoreographyParser::parse(Rule::choreography, input).map_err(Box::new)?;
-
-    Ok((preprocessed, pairs))
+    Ok((preprocessed, pairs, matches))
 }
 
 /// Create a dynamic parser from composed grammar
@@ -1555,37 +3880,21 @@ fn transform_aura_annotations(input: &str) -> std::result::Result<String, ParseE
     Ok(result)
 }
 
-/// Parse extension statements from the parsed AST
+/// Parse the extensions preprocessing rewrote into comments
+///
+/// Extension statements are parsed into the protocol, as
+/// [`Protocol::Extension`] nodes.
 fn parse_extension_statements(
     input: &str,
-    roles: &[Role],
-    namespace: &Option<String>,
     registry: &ExtensionRegistry,
 ) -> std::result::Result<Vec<Box<dyn ProtocolExtension>>, ParseError> {
     let mut extensions = Vec::new();
 
-    // Create parse context
-    let _context = ParseContext::new(roles.to_vec(), namespace.clone());
-
     // Parse Aura-style annotation comments if Aura extensions are registered
     if registry.has_extension("aura_annotations") {
         extensions.extend(parse_aura_annotation_comments(input)?);
     }
 
-    // Keep existing timeout support for compatibility
-    if registry.has_extension("timeout") {
-        use crate::extensions::timeout::TimeoutProtocol;
-        use std::time::Duration;
-
-        let timeout_ext = TimeoutProtocol {
-            duration: Duration::from_secs(30),
-            role_names: roles.iter().map(|r| r.name.to_string()).collect(),
-            body_repr: "default".to_string(),
-        };
-
-        extensions.push(Box::new(timeout_ext) as Box<dyn ProtocolExtension>);
-    }
-
     Ok(extensions)
 }
 
@@ -1725,14 +4034,20 @@ fn create_aura_annotated_send(
 /// Parse a choreographic protocol from a token stream (for macro use)
 /// This is a compatibility function that wraps the string parser
 pub fn parse_choreography(input: TokenStream) -> Result<Choreography> {
+    parse_choreography_with_cfg(input, &CfgSet::new())
+}
+
+fn parse_choreography_with_cfg(input: TokenStream, cfg: &CfgSet) -> Result<Choreography> {
     use syn::LitStr;
 
     // Try to parse as a string literal (for DSL syntax)
     if let Ok(lit_str) = syn::parse2::<LitStr>(input.clone()) {
         // Parse the DSL string
         let dsl_content = lit_str.value();
-        return parse_choreography_str(&dsl_content).map_err(|e| {
-            syn::Error::new(lit_str.span(), format!("Choreography parse error: {e}"))
+        let parsed = parse_choreography_str_with_cfg(&dsl_content, &ExtensionRegistry::new(), cfg);
+        return parsed.map(|(choreography, _)| choreography).map_err(|e| {
+            let diagnostic = super::diagnostics::Diagnostic::from_parse_error(&e, &dsl_content);
+            diagnostic.to_syn_error(&lit_str)
         });
     }
 
@@ -1777,27 +4092,204 @@ pub fn parse_dsl(input: &str) -> std::result::Result<Choreography, ParseError> {
 #[doc(hidden)]
 #[must_use]
 pub fn choreography_macro(input: TokenStream) -> TokenStream {
-    let choreography = match parse_choreography(input) {
+    let invocation = input.clone();
+    let (attributes, input) = match split_macro_attributes(input) {
+        Ok(split) => split,
+        Err(e) => return e.to_compile_error(),
+    };
+    let literal = syn::parse2::<syn::LitStr>(input.clone()).ok();
+
+    // Let the compiler pick the variant of the invoking crate's build
+    let cfg = match (attributes.cfg, &literal) {
+        (Some(cfg), _) => cfg,
+        (None, Some(literal)) => {
+            let options = cfg_options(&literal.value());
+            if options.len() > MAX_CFG_OPTIONS {
+                return syn::Error::new(
+                    literal.span(),
+                    format!(
+                        "cfg conditions name {} options, at most {MAX_CFG_OPTIONS} are supported",
+                        options.len()
+                    ),
+                )
+                .to_compile_error();
+            }
+            if !options.is_empty() {
+                return super::cfg::expand_variants(&options, &invocation);
+            }
+            CfgSet::new()
+        }
+        (None, None) => CfgSet::new(),
+    };
+
+    // Report every syntax and validation error at once
+    if let Some(literal) = &literal {
+        let diagnostics = super::recovery::check_choreography_with_cfg(
+            &literal.value(),
+            &ExtensionRegistry::new(),
+            &cfg,
+        );
+        let mut errors = diagnostics.iter().map(|d| d.to_syn_error(literal));
+        if let Some(mut error) = errors.next() {
+            errors.for_each(|e| error.combine(e));
+            return error.to_compile_error();
+        }
+    }
+    let mut choreography = match parse_choreography_with_cfg(input, &cfg) {
         Ok(c) => c,
         Err(e) => return e.to_compile_error(),
     };
+    let notified = if attributes.auto_notify {
+        super::auto_notify::auto_notify(&mut choreography)
+    } else {
+        Vec::new()
+    };
 
     // Validate the choreography
     if let Err(e) = choreography.validate() {
-        return syn::Error::new(Span::call_site(), e.to_string()).to_compile_error();
+        let diagnostic = super::diagnostics::Diagnostic::from_validation_error(&e, &choreography);
+        let error = match &literal {
+            Some(literal) => diagnostic.to_syn_error(literal),
+            None => syn::Error::new(proc_macro2::Span::call_site(), e.to_string()),
+        };
+        return error.to_compile_error();
     }
 
     // Project to local types
     let mut local_types = Vec::new();
+    let registry = ExtensionRegistry::new();
     for role in &choreography.roles {
-        match super::projection::project(&choreography, role) {
+        match super::projection::project_located(&choreography, role, &registry) {
             Ok(local_type) => local_types.push((role.clone(), local_type)),
-            Err(e) => return syn::Error::new(Span::call_site(), e.to_string()).to_compile_error(),
+            Err(e) => {
+                let diagnostic = super::diagnostics::Diagnostic::from_projection_error(&e);
+                let error = match &literal {
+                    Some(literal) => diagnostic.to_syn_error(literal),
+                    None => syn::Error::new(proc_macro2::Span::call_site(), e.to_string()),
+                };
+                return error.to_compile_error();
+            }
         }
     }
 
     // Generate code with namespace support
-    super::codegen::generate_choreography_code_with_namespacing(&choreography, &local_types)
+    let mut generated =
+        super::codegen::generate_choreography_code_with_namespacing(&choreography, &local_types);
+    if attributes.auto_notify {
+        let name = format_ident!(
+            "{}_AUTO_NOTIFIED",
+            choreography.name.to_string().to_uppercase()
+        );
+        let doc = format!("Sends `#[auto_notify]` inserted into {}", choreography.name);
+        let notified = notified.iter().map(ToString::to_string);
+        generated.extend(quote::quote! {
+            #[doc = #doc]
+            pub const #name: &[&str] = &[#(#notified),*];
+        });
+    }
+
+    // Expand again when a fragment changes
+    if let Some(literal) = &literal {
+        for file in super::include::fragment_files(&literal.value()) {
+            let file = file.display().to_string();
+            generated.extend(quote::quote! {
+                const _: &[u8] = include_bytes!(#file);
+            });
+        }
+    }
+
+    // Warn about code that never runs, pointing at the DSL string
+    if let Some(literal) = &literal {
+        for warning in super::analysis::dead_code(&choreography) {
+            let note = match warning.span() {
+                Some(span) => format!("{warning} (line {})", span.line),
+                None => warning.to_string(),
+            };
+            generated.extend(quote::quote_spanned! {literal.span()=>
+                const _: () = {
+                    #[deprecated(note = #note)]
+                    #[allow(non_camel_case_types)]
+                    struct choreography_warning;
+                    let _ = choreography_warning;
+                };
+            });
+        }
+    }
+
+    if let Some((dir, span)) = attributes.debug_output {
+        if let Err(e) =
+            super::debug_output::write_debug_output(&dir, &choreography, &local_types, &generated)
+        {
+            return syn::Error::new(span, e.to_string()).to_compile_error();
+        }
+    }
+    generated
+}
+
+/// Outer attributes of a `choreography!` invocation
+#[derive(Default)]
+struct MacroAttributes {
+    /// `#[debug_output = "dir"]`, with `dir` resolved against the invoking
+    /// crate
+    debug_output: Option<(std::path::PathBuf, proc_macro2::Span)>,
+    /// `#[auto_notify]`
+    auto_notify: bool,
+    /// `#[cfg_resolved(...)]`, added when expanding the variants of a
+    /// source with cfg conditions
+    cfg: Option<CfgSet>,
+}
+
+/// Split the outer attributes off the macro input
+fn split_macro_attributes(input: TokenStream) -> Result<(MacroAttributes, TokenStream)> {
+    let (attrs, rest) = syn::parse::Parser::parse2(
+        |stream: syn::parse::ParseStream| {
+            let attrs = stream.call(syn::Attribute::parse_outer)?;
+            let rest: TokenStream = stream.parse()?;
+            Ok((attrs, rest))
+        },
+        input,
+    )?;
+
+    let mut attributes = MacroAttributes::default();
+    for attr in attrs {
+        if attr.path().is_ident(super::auto_notify::AUTO_NOTIFY) {
+            attr.meta.require_path_only()?;
+            attributes.auto_notify = true;
+            continue;
+        }
+        if attr.path().is_ident(CFG_RESOLVED) {
+            attributes.cfg = Some(CfgSet::from_attribute(&attr)?);
+            continue;
+        }
+        if !attr.path().is_ident(super::debug_output::DEBUG_OUTPUT) {
+            return Err(syn::Error::new_spanned(
+                attr.path(),
+                "unknown choreography! attribute, expected `debug_output` or `auto_notify`",
+            ));
+        }
+        let dir = match &attr.meta {
+            syn::Meta::NameValue(syn::MetaNameValue {
+                value:
+                    syn::Expr::Lit(syn::ExprLit {
+                        lit: syn::Lit::Str(dir),
+                        ..
+                    }),
+                ..
+            }) => dir,
+            _ => {
+                return Err(syn::Error::new_spanned(
+                    &attr,
+                    "expected `#[debug_output = \"dir\"]`",
+                ))
+            }
+        };
+        let mut path = std::path::PathBuf::from(dir.value());
+        if let (true, Some(root)) = (path.is_relative(), std::env::var_os("CARGO_MANIFEST_DIR")) {
+            path = std::path::Path::new(&root).join(path);
+        }
+        attributes.debug_output = Some((path, dir.span()));
+    }
+    Ok((attributes, rest))
 }
 
 #[cfg(test)]
@@ -1906,4 +4398,280 @@ choreography LoopProtocol {
         let result = parse_choreography_str(input);
         assert!(result.is_ok(), "Failed to parse: {:?}", result.err());
     }
+
+    #[test]
+    fn test_statements_carry_spans() {
+        let input = r"
+choreography Spanned {
+    roles: Client, Server
+
+    Client -> Server: Request
+    choice Server {
+        accept: {
+            Server -> Client: Ok
+        }
+    }
+}
+";
+
+        let choreography = parse_choreography_str(input).unwrap();
+        let Protocol::Send {
+            span, continuation, ..
+        } = &choreography.protocol
+        else {
+            panic!("expected a send");
+        };
+        assert_eq!(&input[span.start..span.end], "Client -> Server: Request");
+        assert_eq!((span.line, span.column), (5, 5));
+
+        let Protocol::Choice { span, branches, .. } = continuation.as_ref() else {
+            panic!("expected a choice");
+        };
+        assert_eq!(span.line, 6);
+        assert_eq!(branches[0].span.line, 7);
+        assert_eq!(branches[0].protocol.span().line, 8);
+        assert!(!Protocol::End.span().is_known());
+    }
+
+    #[test]
+    fn test_parse_on_failure() {
+        let input = r"
+choreography Dispatch {
+    roles: Coordinator, Worker, Backup
+    Coordinator -> Worker: Task or on failure {
+        Coordinator -> Backup: Reassign
+    }
+    Worker -> Coordinator: Result
+}
+";
+
+        let choreography = parse_choreography_str(input).unwrap();
+        let Protocol::Send { continuation, .. } = &choreography.protocol else {
+            panic!("expected a send");
+        };
+        assert_eq!(continuation.failure_of(), Some("Worker"));
+        let Protocol::Choice { role, branches, .. } = continuation.as_ref() else {
+            panic!("expected a failure choice");
+        };
+        assert_eq!(role.name, "Coordinator");
+        assert_eq!(branches[0].label, DELIVERED);
+        assert_eq!(branches[1].label, FAILED);
+
+        // Both branches go on with the rest of the protocol, the failed one
+        // after the recovery
+        let Protocol::Send { message, .. } = &branches[0].protocol else {
+            panic!("expected the delivered branch to continue");
+        };
+        assert_eq!(message.name, "Result");
+        let Protocol::Send {
+            message,
+            continuation,
+            ..
+        } = &branches[1].protocol
+        else {
+            panic!("expected the recovery");
+        };
+        assert_eq!(message.name, "Reassign");
+        assert!(matches!(
+            continuation.as_ref(),
+            Protocol::Send { message, .. } if message.name == "Result"
+        ));
+        assert!(choreography.validate().is_ok());
+    }
+
+    #[test]
+    fn test_parse_stream_send() {
+        let input = r"
+choreography Upload {
+    roles: Alice, Bob
+    Alice -> Bob: stream FileChunk;
+    Bob -> Alice: stream;
+}
+";
+
+        let choreography = parse_choreography_str(input).unwrap();
+        let Protocol::Send {
+            message,
+            continuation,
+            ..
+        } = &choreography.protocol
+        else {
+            panic!("expected a send");
+        };
+        assert!(choreography.protocol.is_stream());
+        assert_eq!(message.name, "FileChunk");
+        // A message may still be called `stream`
+        let Protocol::Send { message, .. } = continuation.as_ref() else {
+            panic!("expected a second send");
+        };
+        assert_eq!(message.name, "stream");
+        assert!(!continuation.is_stream());
+    }
+
+    #[test]
+    fn test_macro_debug_output() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_str().unwrap();
+        let dsl = "choreography Ping { roles: A, B  A -> B: Ping }";
+        let input = quote::quote! { #[debug_output = #dir] #dsl };
+
+        let output = choreography_macro(input).to_string();
+        assert!(!output.contains("compile_error"));
+        for file in ["grammar.pest", "ast.json", "A.txt", "B.txt", "generated.rs"] {
+            assert!(temp.path().join("Ping").join(file).exists(), "{file}");
+        }
+
+        let input = quote::quote! { #[dump = #dir] #dsl };
+        assert!(choreography_macro(input)
+            .to_string()
+            .contains("unknown choreography! attribute"));
+    }
+
+    #[test]
+    fn test_macro_auto_notify() {
+        let dsl = r"
+choreography Review {
+    roles: Author, Editor, Printer
+    Author -> Editor: Draft
+    choice Editor {
+        accept: {
+            Editor -> Author: Accepted
+            Author -> Printer: Manuscript
+        }
+        reject: {
+            Editor -> Author: Rejected
+        }
+    }
+}
+";
+        let output = choreography_macro(quote::quote! { #dsl }).to_string();
+        assert!(output.contains("compile_error"));
+
+        let output = choreography_macro(quote::quote! { #[auto_notify] #dsl }).to_string();
+        assert!(!output.contains("compile_error"), "{output}");
+        assert!(output.contains(
+            "pub const REVIEW_AUTO_NOTIFIED : & [& str] = & [\"Editor -> Printer: AcceptChosen at the start of branch `accept` (line 6)\" , \"Editor -> Printer: RejectChosen at the start of branch `reject` (line 10)\"]"
+        ));
+
+        let output = choreography_macro(quote::quote! { #[auto_notify(all)] #dsl }).to_string();
+        assert!(output.contains("compile_error"));
+    }
+
+    #[test]
+    fn test_parse_parameterized_protocol() {
+        let dsl = r"
+choreography Payments {
+    roles: Client, Bank
+    protocol Resend<N: usize, Msg> {
+        loop (count: N) {
+            Client -> Bank: Msg
+        }
+    }
+    instantiate Resend<3, Payment> as PaymentResend;
+    instantiate Resend<2, Refund> as RefundResend;
+    call PaymentResend
+    call RefundResend
+}
+";
+        let choreography = parse_choreography_str(dsl).unwrap();
+        let Protocol::Loop {
+            condition: Some(Condition::Count(3)),
+            body,
+            ..
+        } = &choreography.protocol
+        else {
+            panic!("expected a loop of 3, got {:?}", choreography.protocol);
+        };
+        assert!(matches!(
+            body.as_ref(),
+            Protocol::Send { message, .. } if message.name == "Payment"
+        ));
+        assert!(choreography.validate().is_ok());
+
+        let error = |replace: &str, with: &str| {
+            parse_choreography_str(&dsl.replace(replace, with))
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(error("<2, Refund>", "<2>").contains("takes 2 arguments, 1 given"));
+        assert!(error("<2, Refund>", "<2, 5>").contains("not a number"));
+        assert!(error("call RefundResend", "call Resend").contains("takes parameters"));
+        assert!(error("Resend<2", "Resnd<2").contains("Undefined protocol 'Resnd'"));
+    }
+
+    #[test]
+    fn test_cfg_conditions_select_a_variant() {
+        let dsl = r#"
+choreography Checkout {
+    roles: Client, Server, Auditor
+    messages: Order, Receipt, Log, Priority
+    Client -> Server: Order
+    #[cfg(feature = "premium")]
+    Server -> Client: Priority
+    choice Server {
+        #[cfg(not(feature = "premium"))]
+        basic: {
+            Server -> Client: Receipt
+        }
+        #[cfg(feature = "premium")]
+        audited: {
+            Server -> Auditor: Log
+            Server -> Client: Receipt
+        }
+    }
+}
+"#;
+        let registry = ExtensionRegistry::new();
+        let (basic, _) = parse_choreography_str_with_cfg(dsl, &registry, &CfgSet::new()).unwrap();
+        assert!(!basic.protocol.mentions_role(&basic.roles[2]));
+        assert!(basic.validate().is_ok());
+
+        let premium = CfgSet::features(["premium"]);
+        let (premium, _) = parse_choreography_str_with_cfg(dsl, &registry, &premium).unwrap();
+        assert!(premium.protocol.mentions_role(&premium.roles[2]));
+        assert!(premium.validate().is_ok());
+
+        // The macro leaves the choice of variant to the compiler
+        let output = choreography_macro(quote::quote! { #dsl }).to_string();
+        assert!(output.contains(
+            "# [cfg (all (not (feature = \"premium\")))] :: rumpsteak_aura_choreography :: choreography ! { # [cfg_resolved ()]"
+        ), "{output}");
+        assert!(output.contains("# [cfg_resolved (feature = \"premium\")]"));
+        let output =
+            choreography_macro(quote::quote! { #[cfg_resolved(feature = "premium")] #dsl })
+                .to_string();
+        assert!(!output.contains("compile_error"), "{output}");
+        assert!(output.contains("Priority"));
+
+        let error = parse_choreography_str_with_cfg(
+            &dsl.replace(
+                "#[cfg(not(feature = \"premium\"))]",
+                "#[cfg(feature = \"gold\")]",
+            ),
+            &registry,
+            &CfgSet::new(),
+        )
+        .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("every branch of the choice is disabled"));
+    }
+
+    #[test]
+    fn test_macro_warns_about_dead_code() {
+        let dsl = r"
+choreography Ping {
+    roles: A, B
+    loop {
+        A -> B: Ping
+    }
+    B -> A: Pong
+}
+";
+        let output = choreography_macro(quote::quote! { #dsl }).to_string();
+        assert!(!output.contains("compile_error"), "{output}");
+        assert!(output.contains(
+            "# [deprecated (note = \"unreachable code: this statement follows a `loop` that is never left (line 7)\")]"
+        ));
+    }
 }
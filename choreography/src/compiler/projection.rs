@@ -2,14 +2,52 @@
 
 use crate::ast::{
     Branch, Choreography, LocalType, MessageType, Protocol, RangeExpr, Role, RoleIndex, RoleParam,
-    RoleRange,
+    RoleRange, Span,
 };
+use crate::extensions::{self, ExtensionRegistry, ProjectionHook};
+use crate::runtime::guard::{GUARD_CAPABILITY, GUARD_ROLE};
 use std::collections::HashMap;
+use std::sync::Arc;
 
 /// Project a choreography to a local session type for a specific role
 pub fn project(choreography: &Choreography, role: &Role) -> Result<LocalType, ProjectionError> {
+    project_located_with_hooks(choreography, role, &[]).map_err(|e| e.error)
+}
+
+/// Project a choreography, running the projection hooks of `registry` on
+/// every protocol node
+pub fn project_with_extensions(
+    choreography: &Choreography,
+    role: &Role,
+    registry: &ExtensionRegistry,
+) -> Result<LocalType, ProjectionError> {
+    project_located(choreography, role, registry).map_err(|e| e.error)
+}
+
+/// Project a choreography like [`project_with_extensions`], reporting the
+/// location of the statement whose projection failed
+pub fn project_located(
+    choreography: &Choreography,
+    role: &Role,
+    registry: &ExtensionRegistry,
+) -> Result<LocalType, LocatedProjectionError> {
+    project_located_with_hooks(choreography, role, registry.projection_hooks())
+}
+
+fn project_located_with_hooks(
+    choreography: &Choreography,
+    role: &Role,
+    hooks: &[Arc<dyn ProjectionHook>],
+) -> Result<LocalType, LocatedProjectionError> {
+    check_guards(&choreography.protocol)?;
     let mut context = ProjectionContext::new(choreography, role);
-    context.project_protocol(&choreography.protocol)
+    context.hooks = hooks;
+    context
+        .project_protocol(&choreography.protocol)
+        .map_err(|error| LocatedProjectionError {
+            error,
+            span: context.error_span.unwrap_or_default(),
+        })
 }
 
 /// Errors that can occur during projection
@@ -38,39 +76,300 @@ pub enum ProjectionError {
 
     #[error("Wildcard role index requires specialized projection context")]
     WildcardProjection,
+
+    #[error("Capability guard '{capability}' names role {role}, which does not take part in {statement}")]
+    MisplacedGuard {
+        capability: String,
+        role: String,
+        statement: String,
+    },
+
+    #[error("Role family {role} cannot be projected once for all its instances: {reason}")]
+    InstanceSpecific { role: String, reason: String },
+
+    #[error("Role {role} cannot tell which branch of the choice made by {chooser} was taken")]
+    IndistinguishableBranches {
+        role: String,
+        chooser: String,
+        /// Two branches in which `role` acts differently
+        branches: Vec<BranchAction>,
+    },
+}
+
+/// First observable action of a role in one branch of a choice
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchAction {
+    pub label: String,
+    /// What the role does first, such as `receives Receipt from Bob`
+    pub action: String,
+    /// Location of the branch, `Span::default()` if not parsed from source
+    pub span: Span,
+}
+
+impl ProjectionError {
+    /// Stable error code, listed in the API reference
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProjectionError::NonParticipantChoice => "RA0201",
+            ProjectionError::UnsupportedParallel(_) => "RA0202",
+            ProjectionError::InconsistentParallel => "RA0203",
+            ProjectionError::UnboundVariable(_) => "RA0204",
+            ProjectionError::DynamicRoleProjection { .. } => "RA0205",
+            ProjectionError::UnboundSymbolic { .. } => "RA0206",
+            ProjectionError::RangeProjection => "RA0207",
+            ProjectionError::WildcardProjection => "RA0208",
+            ProjectionError::MisplacedGuard { .. } => "RA0209",
+            ProjectionError::IndistinguishableBranches { .. } => "RA0210",
+            ProjectionError::InstanceSpecific { .. } => "RA0211",
+        }
+    }
+
+    /// How the choreography could be changed to avoid the error
+    #[must_use]
+    pub fn help(&self) -> Option<String> {
+        match self {
+            ProjectionError::InconsistentParallel => Some(
+                "parallel branches must not both send to or receive from the same role".to_string(),
+            ),
+            ProjectionError::DynamicRoleProjection { .. } => {
+                Some("bind the role count with a concrete value before projecting".to_string())
+            }
+            ProjectionError::UnboundSymbolic { param } => Some(format!(
+                "bind '{param}' to a concrete value before projecting"
+            )),
+            ProjectionError::MisplacedGuard { role, .. } => Some(format!(
+                "attach the guard to a statement {role} takes part in, or change `guard_role`"
+            )),
+            ProjectionError::InstanceSpecific { role, .. } => Some(format!(
+                "refer to {role} with a symbolic index such as `{role}[i]`, \
+                 or declare the instance as a role of its own"
+            )),
+            ProjectionError::IndistinguishableBranches { role, chooser, .. } => Some(format!(
+                "have {chooser} send a message to {role} at the start of each branch, \
+                 so that {role} learns which branch was taken"
+            )),
+            _ => None,
+        }
+    }
+
+    /// Why the choreography cannot be projected, in terms of the global
+    /// protocol
+    ///
+    /// Multi-line; `None` for errors the message already explains.
+    #[must_use]
+    pub fn explain(&self) -> Option<String> {
+        match self {
+            ProjectionError::IndistinguishableBranches {
+                role,
+                chooser,
+                branches,
+            } => {
+                let mut explanation = format!(
+                    "{role} does not take part in the choice, but acts differently per branch:"
+                );
+                for branch in branches {
+                    explanation.push_str(&format!(
+                        "\n  in branch `{}`, {role} {}",
+                        branch.label, branch.action
+                    ));
+                }
+                explanation.push_str(&format!(
+                    "\nno message from {chooser} tells {role} which of these to do"
+                ));
+                Some(explanation)
+            }
+            ProjectionError::InconsistentParallel => Some(
+                "messages of the two branches could arrive in any order, so the role \
+                 could not tell which branch a message belongs to"
+                    .to_string(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// A projection error with the location of the statement it arose at
+#[derive(Debug, thiserror::Error)]
+#[error("{error}")]
+pub struct LocatedProjectionError {
+    pub error: ProjectionError,
+    /// Innermost statement whose projection failed, `Span::default()` if the
+    /// failing nodes were not parsed from source
+    pub span: Span,
+}
+
+/// Capability and guarded role of a statement-level `guard_capability` annotation
+///
+/// The guard applies to the role named by `guard_role`, or else to the sender
+/// of a message or the role making a choice.
+pub(crate) fn statement_guard(protocol: &Protocol) -> Option<(&str, String)> {
+    let capability = protocol.get_annotation(GUARD_CAPABILITY)?;
+    let role = match (protocol.get_annotation(GUARD_ROLE), protocol) {
+        (Some(role), _) => role.clone(),
+        (None, Protocol::Send { from, .. } | Protocol::Broadcast { from, .. }) => {
+            from.name.to_string()
+        }
+        (None, Protocol::Choice { role, .. }) => role.name.to_string(),
+        (None, _) => return None,
+    };
+    Some((capability.as_str(), role))
+}
+
+/// Check that every statement-level capability guard names a role involved in
+/// the statement it is attached to
+///
+/// Guards on role annotations (`A[@guard_capability = "..."] -> B: M`) are
+/// attached to a participant by construction and need no check.
+pub fn validate_guards(protocol: &Protocol) -> Result<(), ProjectionError> {
+    check_guards(protocol).map_err(|e| e.error)
+}
+
+fn check_guards(protocol: &Protocol) -> Result<(), LocatedProjectionError> {
+    if let Some((capability, role)) = statement_guard(protocol) {
+        let (involved, statement) = match protocol {
+            Protocol::Send {
+                from, to, message, ..
+            } => (
+                from.name == role || to.name == role,
+                format!("{} -> {}: {}", from.name, to.name, message.name),
+            ),
+            Protocol::Broadcast {
+                from,
+                to_all,
+                message,
+                ..
+            } => (
+                from.name == role || to_all.iter().any(|r| r.name == role),
+                format!("{} ->* : {}", from.name, message.name),
+            ),
+            Protocol::Choice {
+                role: chooser,
+                branches,
+                ..
+            } => (
+                chooser.name == role
+                    || branches
+                        .iter()
+                        .any(|b| protocol_mentions(&b.protocol, &role)),
+                format!("choice at {}", chooser.name),
+            ),
+            _ => (true, String::new()),
+        };
+        if !involved {
+            return Err(LocatedProjectionError {
+                error: ProjectionError::MisplacedGuard {
+                    capability: capability.to_string(),
+                    role,
+                    statement,
+                },
+                span: protocol.span(),
+            });
+        }
+    }
+
+    match protocol {
+        Protocol::Send { continuation, .. }
+        | Protocol::Broadcast { continuation, .. }
+        | Protocol::Extension { continuation, .. } => check_guards(continuation),
+        Protocol::Choice { branches, .. } => {
+            branches.iter().try_for_each(|b| check_guards(&b.protocol))
+        }
+        Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => check_guards(body),
+        Protocol::Parallel { protocols, .. } => protocols.iter().try_for_each(check_guards),
+        Protocol::Var(_) | Protocol::End => Ok(()),
+    }
+}
+
+/// Whether the role named `name` sends or receives anywhere in `protocol`
+fn protocol_mentions(protocol: &Protocol, name: &str) -> bool {
+    match protocol {
+        Protocol::Send {
+            from,
+            to,
+            continuation,
+            ..
+        } => from.name == name || to.name == name || protocol_mentions(continuation, name),
+        Protocol::Broadcast {
+            from,
+            to_all,
+            continuation,
+            ..
+        } => {
+            from.name == name
+                || to_all.iter().any(|r| r.name == name)
+                || protocol_mentions(continuation, name)
+        }
+        Protocol::Choice { role, branches, .. } => {
+            role.name == name
+                || branches
+                    .iter()
+                    .any(|b| protocol_mentions(&b.protocol, name))
+        }
+        Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => protocol_mentions(body, name),
+        Protocol::Parallel { protocols, .. } => {
+            protocols.iter().any(|p| protocol_mentions(p, name))
+        }
+        Protocol::Extension { continuation, .. } => protocol_mentions(continuation, name),
+        Protocol::Var(_) | Protocol::End => false,
+    }
 }
 
 /// Context for projection algorithm
 struct ProjectionContext<'a> {
     role: &'a Role,
+    /// All roles of the choreography
+    roles: &'a [Role],
+    /// Extension hooks run on every node
+    hooks: &'a [Arc<dyn ProjectionHook>],
     /// Bindings for symbolic role parameters (e.g., N -> 5)
     role_bindings: HashMap<String, u32>,
     /// Bindings for symbolic index variables (e.g., i -> 2)
     #[allow(dead_code)]
     index_bindings: HashMap<String, u32>,
+    /// Span of the innermost statement whose projection failed
+    error_span: Option<Span>,
 }
 
 impl<'a> ProjectionContext<'a> {
-    fn new(_choreography: &'a Choreography, role: &'a Role) -> Self {
+    fn new(choreography: &'a Choreography, role: &'a Role) -> Self {
         ProjectionContext {
             role,
+            roles: &choreography.roles,
+            hooks: &[],
             role_bindings: HashMap::new(),
             index_bindings: HashMap::new(),
+            error_span: None,
         }
     }
 
     /// Create a new context with dynamic role bindings
     #[allow(dead_code)]
     fn with_bindings(
-        _choreography: &'a Choreography,
+        choreography: &'a Choreography,
         role: &'a Role,
         role_bindings: HashMap<String, u32>,
         index_bindings: HashMap<String, u32>,
     ) -> Self {
         ProjectionContext {
             role,
+            roles: &choreography.roles,
+            hooks: &[],
             role_bindings,
             index_bindings,
+            error_span: None,
+        }
+    }
+
+    /// Context projecting the same choreography onto another role
+    fn for_role(&self, role: &'a Role) -> Self {
+        ProjectionContext {
+            role,
+            roles: self.roles,
+            hooks: self.hooks,
+            role_bindings: self.role_bindings.clone(),
+            index_bindings: self.index_bindings.clone(),
+            error_span: None,
         }
     }
 
@@ -86,10 +385,37 @@ impl<'a> ProjectionContext<'a> {
             return Ok(true);
         }
 
+        // A family is projected once, as the generic instance `Worker[i]`
+        if self.role.is_family() && protocol_role.param.is_none() {
+            return self.matches_family_instance(protocol_role);
+        }
+
         // Handle dynamic role matching
         self.matches_dynamic_role(protocol_role)
     }
 
+    /// Check if the generic instance of the projected role family takes the
+    /// part of the given instance reference
+    ///
+    /// A symbolic index or a wildcard stands for every instance, so the
+    /// generic instance takes part, and so does a quorum, to which every
+    /// instance sends. A concrete index or a range singles out some
+    /// instances, whose local type then differs from the others.
+    fn matches_family_instance(&self, protocol_role: &Role) -> Result<bool, ProjectionError> {
+        match &protocol_role.index {
+            Some(RoleIndex::Symbolic(_) | RoleIndex::Wildcard | RoleIndex::Quorum(_)) => Ok(true),
+            Some(RoleIndex::Concrete(index)) => Err(ProjectionError::InstanceSpecific {
+                role: self.role.name.to_string(),
+                reason: format!(
+                    "the choreography singles out {}[{index}]",
+                    protocol_role.name
+                ),
+            }),
+            Some(RoleIndex::Range(_)) => Err(ProjectionError::RangeProjection),
+            None => Ok(true),
+        }
+    }
+
     /// Check if the projection role matches a dynamic protocol role
     fn matches_dynamic_role(&self, protocol_role: &Role) -> Result<bool, ProjectionError> {
         match (&self.role.param, &protocol_role.param) {
@@ -137,6 +463,12 @@ impl<'a> ProjectionContext<'a> {
                     role: protocol_role.name.to_string(),
                 })
             }
+            // Bounded vs Bounded: must have the same bounds
+            (Some(RoleParam::Bounded { .. }), Some(RoleParam::Bounded { .. })) => {
+                Ok(self.role.param == protocol_role.param)
+            }
+            (Some(RoleParam::Bounded { .. }), Some(_))
+            | (Some(_), Some(RoleParam::Bounded { .. })) => Ok(false),
             // One parameterized, one not: no match
             (Some(_), None) | (None, Some(_)) => Ok(false),
             // Both None: already handled above
@@ -211,6 +543,42 @@ impl<'a> ProjectionContext<'a> {
     }
 
     fn project_protocol(&mut self, protocol: &Protocol) -> Result<LocalType, ProjectionError> {
+        let result = self.project_hooked(protocol);
+        // Errors propagate outwards, so the first known span is the innermost one
+        if result.is_err() && self.error_span.is_none() && protocol.span().is_known() {
+            self.error_span = Some(protocol.span());
+        }
+        result
+    }
+
+    fn project_hooked(&mut self, protocol: &Protocol) -> Result<LocalType, ProjectionError> {
+        if self.hooks.is_empty() {
+            return self.project_node(protocol);
+        }
+
+        let hooks = self.hooks;
+        let context = extensions::ProjectionContext {
+            all_roles: self.roles,
+            current_role: self.role,
+        };
+        let mut replaced = None;
+        for hook in hooks {
+            replaced = hook.before_project(protocol, &context)?;
+            if replaced.is_some() {
+                break;
+            }
+        }
+        let mut local_type = match replaced {
+            Some(local_type) => local_type,
+            None => self.project_node(protocol)?,
+        };
+        for hook in hooks {
+            local_type = hook.after_project(protocol, &context, local_type)?;
+        }
+        Ok(local_type)
+    }
+
+    fn project_node(&mut self, protocol: &Protocol) -> Result<LocalType, ProjectionError> {
         match protocol {
             Protocol::Send {
                 from,
@@ -232,27 +600,37 @@ impl<'a> ProjectionContext<'a> {
                 role: choice_role,
                 branches,
                 ..
-            } => self.project_choice(choice_role, branches),
+            } => match protocol.failure_of() {
+                Some(failed) => self.project_failure_choice(choice_role, failed, branches),
+                None if protocol.is_while() => self.project_while_choice(choice_role, branches),
+                None => self.project_choice(choice_role, branches),
+            },
 
-            Protocol::Loop { condition, body } => self.project_loop(condition.as_ref(), body),
+            Protocol::Loop {
+                condition, body, ..
+            } => self.project_loop(condition.as_ref(), body),
 
-            Protocol::Parallel { protocols } => self.project_parallel(protocols),
+            Protocol::Parallel { protocols, .. } => self.project_parallel(protocols),
 
-            Protocol::Rec { label, body } => self.project_rec(label, body),
+            Protocol::Rec { label, body, .. } => self.project_rec(label, body),
 
             Protocol::Var(label) => self.project_var(label),
 
             Protocol::End => Ok(LocalType::End),
 
             Protocol::Extension {
-                extension: _,
-                continuation: _,
-                annotations: _,
+                extension,
+                continuation,
+                ..
             } => {
                 // Delegate projection to the extension implementation
-                // Note: We would need to convert between ProjectionContext types here
-                // For now, just return a placeholder
-                Ok(LocalType::End)
+                let context = extensions::ProjectionContext {
+                    all_roles: self.roles,
+                    current_role: self.role,
+                };
+                let local_type = extension.project(self.role, &context)?;
+                let continuation = self.project_protocol(continuation)?;
+                Ok(self.sequential_merge(local_type, continuation))
             }
         }
     }
@@ -276,6 +654,13 @@ impl<'a> ProjectionContext<'a> {
         let is_sender = self.role_matches(from)?;
         let is_receiver = self.role_matches(to)?;
 
+        if is_sender && is_receiver && self.role.is_family() {
+            return Err(ProjectionError::InstanceSpecific {
+                role: self.role.name.to_string(),
+                reason: "its instances message each other".to_string(),
+            });
+        }
+
         if is_sender {
             // We are the sender
             Ok(LocalType::Send {
@@ -449,11 +834,138 @@ impl<'a> ProjectionContext<'a> {
                 })
             } else {
                 // Not involved in the choice - merge continuations
-                self.merge_choice_continuations(branches)
+                self.merge_choice_continuations(choice_role, branches)
+            }
+        }
+    }
+
+    /// Project the choice of an `or on failure` send onto the local type for
+    /// this role
+    ///
+    /// # Projection Rules
+    /// - If `role == choice_role`: Project as a `Select` to each notified role
+    ///   in turn, or as `LocalChoice` if no role needs notifying
+    /// - If `role` is notified: Project as `Branch` from `choice_role`
+    /// - Otherwise: Project the `delivered` branch
+    ///
+    /// # Implementation Notes
+    /// A role is notified when its projections of the two branches differ.
+    /// The role whose failure the choice reacts to is never notified, since
+    /// it is presumed to have crashed; if it has not, it carries on as though
+    /// its message was delivered.
+    fn project_failure_choice(
+        &mut self,
+        choice_role: &Role,
+        failed: &str,
+        branches: &[Branch],
+    ) -> Result<LocalType, ProjectionError> {
+        let notified = self.failure_notified(choice_role, failed, branches)?;
+        self.project_notified_choice(choice_role, &notified, branches)
+    }
+
+    /// Project the decision of a `while at` loop onto the local type for
+    /// this role
+    ///
+    /// # Projection Rules
+    /// - If `role == choice_role`: Project as a `Select` to every other role
+    ///   in turn, in declaration order
+    /// - Otherwise: Project as `Branch` from `choice_role`
+    fn project_while_choice(
+        &mut self,
+        choice_role: &Role,
+        branches: &[Branch],
+    ) -> Result<LocalType, ProjectionError> {
+        let notified: Vec<Role> = self
+            .roles
+            .iter()
+            .filter(|role| role.name != choice_role.name)
+            .cloned()
+            .collect();
+        self.project_notified_choice(choice_role, &notified, branches)
+    }
+
+    /// Project a choice whose decision `choice_role` sends to each of
+    /// `notified`; roles not notified follow the first branch
+    fn project_notified_choice(
+        &mut self,
+        choice_role: &Role,
+        notified: &[Role],
+        branches: &[Branch],
+    ) -> Result<LocalType, ProjectionError> {
+        if self.role_matches(choice_role)? {
+            let mut local_branches = Vec::new();
+            for branch in branches {
+                let mut local_type = self.project_protocol(&branch.protocol)?;
+                // Every notified role after the first is told on its own
+                for to in notified.iter().skip(1).rev() {
+                    local_type = LocalType::Select {
+                        to: to.clone(),
+                        branches: vec![(branch.label.clone(), local_type)],
+                    };
+                }
+                local_branches.push((branch.label.clone(), local_type));
+            }
+            return Ok(match notified.first() {
+                Some(to) => LocalType::Select {
+                    to: to.clone(),
+                    branches: local_branches,
+                },
+                None => LocalType::LocalChoice {
+                    branches: local_branches,
+                },
+            });
+        }
+
+        let mut is_notified = false;
+        for role in notified {
+            if self.role_matches(role)? {
+                is_notified = true;
+                break;
+            }
+        }
+        if is_notified {
+            let mut local_branches = Vec::new();
+            for branch in branches {
+                let local_type = self.project_protocol(&branch.protocol)?;
+                local_branches.push((branch.label.clone(), local_type));
+            }
+            Ok(LocalType::Branch {
+                from: choice_role.clone(),
+                branches: local_branches,
+            })
+        } else {
+            match branches.first() {
+                Some(delivered) => self.project_protocol(&delivered.protocol),
+                None => Ok(LocalType::End),
             }
         }
     }
 
+    /// Roles that must be told whether the send before a failure choice was
+    /// delivered, in declaration order
+    fn failure_notified(
+        &self,
+        choice_role: &Role,
+        failed: &str,
+        branches: &[Branch],
+    ) -> Result<Vec<Role>, ProjectionError> {
+        let mut notified = Vec::new();
+        for role in self.roles {
+            if role.name == choice_role.name || role.name == failed {
+                continue;
+            }
+            let mut context = self.for_role(role);
+            let mut projections = Vec::new();
+            for branch in branches {
+                projections.push(context.project_protocol(&branch.protocol)?);
+            }
+            if projections.windows(2).any(|w| w[0] != w[1]) {
+                notified.push(role.clone());
+            }
+        }
+        Ok(notified)
+    }
+
     /// Project a loop operation onto the local type for this role
     ///
     /// # Projection Rules
@@ -635,10 +1147,7 @@ impl<'a> ProjectionContext<'a> {
             } => LocalType::Send {
                 to,
                 message,
-                continuation: Box::new(Self::append_continuation_static(
-                    *cont,
-                    continuation.clone(),
-                )),
+                continuation: Box::new(Self::append_continuation_static(*cont, continuation)),
             },
             LocalType::Receive {
                 from,
@@ -647,10 +1156,7 @@ impl<'a> ProjectionContext<'a> {
             } => LocalType::Receive {
                 from,
                 message,
-                continuation: Box::new(Self::append_continuation_static(
-                    *cont,
-                    continuation.clone(),
-                )),
+                continuation: Box::new(Self::append_continuation_static(*cont, continuation)),
             },
             LocalType::End => continuation,
             // For other types, just return as-is with continuation appended at the end
@@ -682,6 +1188,7 @@ impl<'a> ProjectionContext<'a> {
 
     fn merge_choice_continuations(
         &mut self,
+        choice_role: &Role,
         branches: &[Branch],
     ) -> Result<LocalType, ProjectionError> {
         // Project each branch and find where we rejoin
@@ -698,20 +1205,187 @@ impl<'a> ProjectionContext<'a> {
                 .next()
                 .expect("projections must be non-empty when windows returned true"))
         } else {
-            // Different projections per branch
-            // Find common suffix (merge point) if one exists
-            self.find_merge_point(projections)
+            // Different projections per branch, which is only sound if the
+            // role can tell the branches apart by what it receives first
+            for (i, first) in projections.iter().enumerate() {
+                for (j, second) in projections.iter().enumerate().skip(i + 1) {
+                    if !distinguishable(first, second) {
+                        return Err(ProjectionError::IndistinguishableBranches {
+                            role: self.role.name.to_string(),
+                            chooser: choice_role.name.to_string(),
+                            branches: vec![
+                                branch_action(&branches[i], first),
+                                branch_action(&branches[j], second),
+                            ],
+                        });
+                    }
+                }
+            }
+            self.find_merge_point(branches, projections)
         }
     }
 
-    fn find_merge_point(&self, projections: Vec<LocalType>) -> Result<LocalType, ProjectionError> {
-        // Look for a common continuation across all branches
-        // Use the first non-End projection as representative
-        // Advanced: find least common continuation across all branches
-        Ok(projections
-            .into_iter()
-            .find(|p| p != &LocalType::End)
-            .unwrap_or(LocalType::End))
+    /// Whether this role, not making the choice between `branches`, neither
+    /// receives its first message nor can tell the branches apart later
+    fn cannot_tell(&mut self, branches: &[Branch]) -> Result<bool, ProjectionError> {
+        for branch in branches {
+            if let Protocol::Send { to, .. } = &branch.protocol {
+                if self.role_matches(to)? {
+                    return Ok(false);
+                }
+            }
+        }
+        let mut projections = Vec::new();
+        for branch in branches {
+            projections.push(self.project_protocol(&branch.protocol)?);
+        }
+        Ok(projections.iter().enumerate().any(|(i, first)| {
+            projections[i + 1..]
+                .iter()
+                .any(|second| !distinguishable(first, second))
+        }))
+    }
+
+    /// Join the differing projections of a choice this role does not make
+    /// into one branch on the role telling them apart
+    ///
+    /// The caller checked that every two of them start by receiving
+    /// different messages, or different labels, from the same role. Branches
+    /// projecting to the same local type are kept once.
+    fn find_merge_point(
+        &self,
+        branches: &[Branch],
+        projections: Vec<LocalType>,
+    ) -> Result<LocalType, ProjectionError> {
+        let mut sender = None;
+        let mut merged: Vec<(proc_macro2::Ident, LocalType)> = Vec::new();
+        for (branch, projection) in branches.iter().zip(projections) {
+            match projection {
+                LocalType::Branch { from, branches } => {
+                    sender = Some(from);
+                    for (label, local_type) in branches {
+                        if !merged.iter().any(|(known, _)| *known == label) {
+                            merged.push((label, local_type));
+                        }
+                    }
+                }
+                LocalType::Receive { ref from, .. } => {
+                    sender = Some(from.clone());
+                    if !merged.iter().any(|(_, known)| *known == projection) {
+                        merged.push((branch.label.clone(), projection));
+                    }
+                }
+                _ => return Err(ProjectionError::NonParticipantChoice),
+            }
+        }
+        match sender {
+            Some(from) => Ok(LocalType::Branch {
+                from,
+                branches: merged,
+            }),
+            None => Ok(LocalType::End),
+        }
+    }
+}
+
+/// Roles of `choreography` other than `choice_role` that cannot tell which
+/// of `branches` it chose, in declaration order
+///
+/// Such a role makes projection fail with
+/// [`ProjectionError::IndistinguishableBranches`]. Roles whose projection
+/// fails for another reason are left out.
+pub(crate) fn uninformed_roles(
+    choreography: &Choreography,
+    choice_role: &Role,
+    branches: &[Branch],
+) -> Vec<Role> {
+    choreography
+        .roles
+        .iter()
+        .filter(|role| role.name != choice_role.name)
+        .filter(|role| {
+            ProjectionContext::new(choreography, role)
+                .cannot_tell(branches)
+                .unwrap_or(false)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Roles the sender of an `or on failure` send tells whether it was
+/// delivered, for the failure choice `protocol` of `choreography`
+pub(crate) fn failure_notified(
+    choreography: &Choreography,
+    protocol: &Protocol,
+) -> Result<Vec<Role>, ProjectionError> {
+    match (protocol, protocol.failure_of()) {
+        (Protocol::Choice { role, branches, .. }, Some(failed)) => {
+            ProjectionContext::new(choreography, role).failure_notified(role, failed, branches)
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Whether a role not involved in a choice can tell two of its branches
+/// apart: both projections must start by receiving different messages, or
+/// different choice labels, from the same role
+fn distinguishable(first: &LocalType, second: &LocalType) -> bool {
+    match (first, second) {
+        _ if first == second => true,
+        (
+            LocalType::Receive {
+                from: from1,
+                message: msg1,
+                ..
+            },
+            LocalType::Receive {
+                from: from2,
+                message: msg2,
+                ..
+            },
+        ) => from1 == from2 && msg1.name != msg2.name,
+        (
+            LocalType::Branch {
+                from: from1,
+                branches: branches1,
+            },
+            LocalType::Branch {
+                from: from2,
+                branches: branches2,
+            },
+        ) => {
+            from1 == from2
+                && !branches1
+                    .iter()
+                    .any(|(label, _)| branches2.iter().any(|(other, _)| label == other))
+        }
+        _ => false,
+    }
+}
+
+fn branch_action(branch: &Branch, projection: &LocalType) -> BranchAction {
+    BranchAction {
+        label: branch.label.to_string(),
+        action: first_action(projection),
+        span: branch.span,
+    }
+}
+
+/// Description of the first thing a local type does
+fn first_action(local_type: &LocalType) -> String {
+    match local_type {
+        LocalType::Send { to, message, .. } => format!("sends {} to {}", message.name, to.name),
+        LocalType::Receive { from, message, .. } => {
+            format!("receives {} from {}", message.name, from.name)
+        }
+        LocalType::Select { to, .. } => format!("chooses a branch and tells {}", to.name),
+        LocalType::Branch { from, .. } => format!("waits for {} to choose a branch", from.name),
+        LocalType::LocalChoice { .. } => "makes a local choice".to_string(),
+        LocalType::Loop { body, .. }
+        | LocalType::Rec { body, .. }
+        | LocalType::Timeout { body, .. } => first_action(body),
+        LocalType::Var(label) => format!("continues with {label}"),
+        LocalType::End => "does nothing".to_string(),
     }
 }
 
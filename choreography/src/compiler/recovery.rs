@@ -0,0 +1,321 @@
+//! Error recovery for multi-error reporting
+//!
+//! Pest stops at the first syntax error. To report every error of a
+//! choreography at once, the erroneous statement is blanked out and the
+//! source parsed again, until it parses or no recovery point is found.
+//! Statements end at a newline or `;` outside braces, or before the closing
+//! brace of the enclosing block; a statement opening a block extends to its
+//! matching closing brace. Blanking replaces characters with spaces, so the
+//! lines and columns of later errors still match the original source.
+
+use super::cfg::CfgSet;
+use super::diagnostics::Diagnostic;
+use super::parser::{is_complete_statement, parse_choreography_str_with_cfg, ParseError};
+use crate::ast::{Choreography, ValidationError};
+use crate::extensions::ExtensionRegistry;
+use std::ops::Range;
+
+/// Give up after this many errors
+const MAX_ERRORS: usize = 32;
+
+/// Outcome of [`parse_choreography_str_recovering`]
+#[derive(Debug)]
+pub struct RecoveredParse {
+    /// The choreography without its erroneous statements, `None` if an
+    /// error could not be recovered from
+    pub choreography: Option<Choreography>,
+    /// Errors in order of discovery
+    pub errors: Vec<ParseError>,
+    /// Byte ranges of the source skipped to recover from `errors`
+    pub skipped: Vec<Range<usize>>,
+}
+
+/// Parse `input`, skipping erroneous statements so that all syntax errors
+/// are reported
+#[must_use]
+pub fn parse_choreography_str_recovering(
+    input: &str,
+    registry: &ExtensionRegistry,
+) -> RecoveredParse {
+    recover(input, registry, &CfgSet::new())
+}
+
+fn recover(input: &str, registry: &ExtensionRegistry, cfg: &CfgSet) -> RecoveredParse {
+    let mut source = input.to_string();
+    let mut errors = Vec::new();
+    let mut skipped = Vec::new();
+    loop {
+        let error = match parse_choreography_str_with_cfg(&source, registry, cfg) {
+            Ok((choreography, _)) => {
+                return RecoveredParse {
+                    choreography: Some(choreography),
+                    errors,
+                    skipped,
+                }
+            }
+            Err(error) => error,
+        };
+        let region = Diagnostic::from_parse_error(&error, &source)
+            .span
+            .and_then(|span| recovery_region(&source, span.start));
+        errors.push(error);
+        match region {
+            Some(region) if errors.len() < MAX_ERRORS => {
+                blank(&mut source, region.clone());
+                skipped.push(region);
+            }
+            _ => {
+                return RecoveredParse {
+                    choreography: None,
+                    errors,
+                    skipped,
+                }
+            }
+        }
+    }
+}
+
+/// Diagnostics for every syntax and validation error of `input`
+///
+/// Validation runs on the choreography left after skipping erroneous
+/// statements. Errors that skipping may have caused are not reported: unused
+/// roles once anything was skipped, and errors in statements enclosing a
+/// skipped range.
+#[must_use]
+pub fn check_choreography(input: &str, registry: &ExtensionRegistry) -> Vec<Diagnostic> {
+    check_choreography_with_cfg(input, registry, &CfgSet::new())
+}
+
+/// Diagnostics for every syntax and validation error of the variant of
+/// `input` selected by the options of `cfg`
+#[must_use]
+pub fn check_choreography_with_cfg(
+    input: &str,
+    registry: &ExtensionRegistry,
+    cfg: &CfgSet,
+) -> Vec<Diagnostic> {
+    let recovered = recover(input, registry, cfg);
+    let mut diagnostics: Vec<Diagnostic> = recovered
+        .errors
+        .iter()
+        .map(|error| Diagnostic::from_parse_error(error, input))
+        .collect();
+    if let Some(choreography) = &recovered.choreography {
+        let skipped = &recovered.skipped;
+        let encloses_skipped = |diagnostic: &Diagnostic| {
+            diagnostic.span.is_some_and(|span| {
+                skipped
+                    .iter()
+                    .any(|range| span.start <= range.start && range.end <= span.end)
+            })
+        };
+        for error in choreography.validate_all() {
+            if !skipped.is_empty() && matches!(error, ValidationError::UnusedRole(_)) {
+                continue;
+            }
+            let diagnostic = Diagnostic::from_validation_error(&error, choreography);
+            if !encloses_skipped(&diagnostic) {
+                diagnostics.push(diagnostic);
+            }
+        }
+    }
+    diagnostics
+}
+
+/// Range of the statement containing `offset`, `None` outside any block or
+/// in the roles declaration
+fn recovery_region(source: &str, offset: usize) -> Option<Range<usize>> {
+    let offset = offset.min(source.len());
+    if brace_depth(&source[..offset]) == 0 {
+        return None;
+    }
+
+    let start = statement_start(source, offset);
+    if source[start..].trim_start().starts_with("roles") {
+        return None;
+    }
+
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut end = source.len();
+    for (i, c) in source[start..].char_indices() {
+        let i = start + i;
+        match c {
+            '"' => in_string = !in_string,
+            _ if in_string => {}
+            '{' => depth += 1,
+            '}' if depth == 0 => {
+                end = i;
+                break;
+            }
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    end = i + 1;
+                    break;
+                }
+            }
+            '\n' | ';' if depth == 0 && !source[start..i].trim().is_empty() => {
+                end = i + c.len_utf8();
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    // Nothing left to skip, recovery would loop
+    if source[start..end].trim().is_empty() {
+        return None;
+    }
+    Some(start..end)
+}
+
+/// Start of the statement an error at `offset` belongs to
+///
+/// An error at the first token of a line usually means the statement on the
+/// previous line is incomplete, so that statement is blamed unless it is
+/// complete on its own.
+fn statement_start(source: &str, offset: usize) -> usize {
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let before = &source[line_start..offset];
+    if let Some(i) = before.rfind(['{', '}', ';']) {
+        return line_start + i + 1;
+    }
+    if !before.trim().is_empty() || line_start == 0 {
+        return line_start;
+    }
+
+    let previous = source[..line_start].trim_end();
+    let previous_start = previous.rfind('\n').map_or(0, |i| i + 1);
+    let previous_line = &previous[previous_start..];
+    let ends_statement = previous_line.ends_with(['{', '}', ';']);
+    if ends_statement || is_complete_statement(previous_line) {
+        line_start
+    } else {
+        previous_start
+    }
+}
+
+/// Nesting depth of braces at the end of `text`, ignoring string literals
+fn brace_depth(text: &str) -> usize {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    for c in text.chars() {
+        match c {
+            '"' => in_string = !in_string,
+            '{' if !in_string => depth += 1,
+            '}' if !in_string => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    depth
+}
+
+/// Replace `region` with spaces, keeping newlines and byte lengths so
+/// positions are preserved
+fn blank(source: &mut String, region: Range<usize>) {
+    let blanked: String = source[region.clone()]
+        .chars()
+        .map(|c| match c {
+            '\n' => "\n".to_string(),
+            c => " ".repeat(c.len_utf8()),
+        })
+        .collect();
+    source.replace_range(region, &blanked);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(input: &str) -> Vec<Diagnostic> {
+        check_choreography(input, &ExtensionRegistry::new())
+    }
+
+    #[test]
+    fn test_reports_every_syntax_error() {
+        let input = r"
+choreography Broken {
+    roles: Client, Server
+    Client -> : Request
+    Server -> Client: Response
+    Server -> Client Ack
+    choice Client {
+        done: {
+            Client -> Sever: Bye
+        }
+    }
+}
+";
+        let diagnostics = check(input);
+        let lines: Vec<usize> = diagnostics.iter().map(|d| d.span.unwrap().line).collect();
+        assert_eq!(lines, vec![4, 6, 9]);
+        assert_eq!(diagnostics[2].code, Some("RA0003"));
+        assert_eq!(
+            diagnostics[2].help.as_deref(),
+            Some("Did you mean 'Server'?")
+        );
+    }
+
+    #[test]
+    fn test_incomplete_statement_is_blamed() {
+        let input = "choreography Broken {\n    roles: A, B\n    A -> B\n    B -> A: Reply\n}\n";
+        let recovered = parse_choreography_str_recovering(input, &ExtensionRegistry::new());
+        assert_eq!(recovered.errors.len(), 1);
+        // Only the incomplete statement is skipped
+        let choreography = recovered.choreography.unwrap();
+        assert_eq!(choreography.protocol.span().line, 4);
+    }
+
+    #[test]
+    fn test_validation_errors_follow_syntax_errors() {
+        let input = r"
+choreography Broken {
+    roles: A, B
+    A -> B: Hello;
+    A -> : Oops
+    choice A {
+        left: {
+            B -> A: Wrong
+        }
+    }
+}
+";
+        let diagnostics = check(input);
+        let codes: Vec<_> = diagnostics.iter().filter_map(|d| d.code).collect();
+        assert_eq!(codes, vec!["RA0001", "RA0103"]);
+    }
+
+    #[test]
+    fn test_unrecoverable_error_stops() {
+        let input = "choreography Broken {\n    roles: A, A\n    A -> A: Hello\n}\n";
+        let recovered = parse_choreography_str_recovering(input, &ExtensionRegistry::new());
+        assert!(recovered.choreography.is_none());
+        assert_eq!(recovered.errors.len(), 1);
+    }
+
+    #[test]
+    fn test_blanking_preserves_offsets() {
+        let mut source = "A -> B: Grüße\nB -> A: Ok".to_string();
+        let length = source.len();
+        blank(&mut source, 0..15);
+        assert_eq!(source.len(), length);
+        assert!(source.ends_with("\nB -> A: Ok"));
+    }
+
+    #[test]
+    fn test_macro_reports_all_errors() {
+        let input = quote::quote! {
+            r#"
+choreography Broken {
+    roles: A, B
+    A -> : Hello
+    B -> C: Reply
+}
+"#
+        };
+        let output = crate::compiler::parser::choreography_macro(input).to_string();
+        assert_eq!(output.matches("compile_error").count(), 2);
+        assert!(output.contains("[RA0003] Undefined role 'C'"));
+    }
+}
@@ -0,0 +1,419 @@
+// Replay of recorded executions
+//
+// `replay` checks a recorded trace (see `runtime::trace`) against the
+// choreography it ran: the steps of every role are fed, in order, to a
+// conformance monitor of the role's projection. The first step a role takes
+// outside its local type is its divergence; later steps of that role are
+// shown but not checked. A role whose trace stops before its local type may
+// end is reported as incomplete.
+//
+// `ReplayReport::to_mermaid` renders the result as a Mermaid sequence
+// diagram. Steps are ordered by the time they ended, so a message is drawn
+// when it was sent and a receive is drawn after the send it matched. A send
+// and the receive matching it share one solid arrow; a message traced on one
+// side only is drawn dashed. Every arrow is stamped with the time since the
+// first step, steps that took at least the slow threshold get a note with
+// their duration, and divergences and failures are boxed in red.
+
+use crate::ast::Choreography;
+use crate::compiler::codegen::monitor_spec;
+use crate::compiler::projection::project;
+use crate::runtime::monitor::{ActionKind, ConformanceMonitor, MonitorViolation, ObservedEvent};
+use crate::runtime::trace::TraceRecord;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Background of divergent and failed steps
+const HIGHLIGHT: &str = "rgb(255, 221, 221)";
+
+/// One recorded step and what the replay found about it
+#[derive(Debug, Clone)]
+pub struct ReplayStep {
+    pub record: TraceRecord,
+    /// Set on the first step of a role that its local type does not allow
+    pub divergence: Option<MonitorViolation>,
+    /// Whether the step was checked; steps after a divergence, failed steps,
+    /// and steps of roles without a projection are not
+    pub checked: bool,
+}
+
+/// Result of replaying a trace against its choreography
+#[derive(Debug, Clone)]
+pub struct ReplayReport {
+    /// Roles of the choreography followed by any other role in the trace
+    pub participants: Vec<String>,
+    /// Steps ordered by the time they ended
+    pub steps: Vec<ReplayStep>,
+    /// Roles whose trace stops before their local type may end
+    pub incomplete: Vec<MonitorViolation>,
+    /// Traced roles that could not be checked, with the reason
+    pub unchecked_roles: Vec<(String, String)>,
+}
+
+impl ReplayReport {
+    /// Whether some role diverged from the protocol or did not complete it
+    #[must_use]
+    pub fn diverged(&self) -> bool {
+        !self.incomplete.is_empty() || self.divergences().next().is_some()
+    }
+
+    /// Divergences, in the order of the steps
+    pub fn divergences(&self) -> impl Iterator<Item = &MonitorViolation> {
+        self.steps
+            .iter()
+            .filter_map(|step| step.divergence.as_ref())
+    }
+
+    /// Failed steps
+    pub fn failures(&self) -> impl Iterator<Item = &TraceRecord> {
+        self.steps
+            .iter()
+            .map(|step| &step.record)
+            .filter(|record| record.error.is_some())
+    }
+
+    /// Mermaid sequence diagram of the execution, noting every step that took
+    /// at least `slow`
+    #[must_use]
+    pub fn to_mermaid(&self, slow: Duration) -> String {
+        let mut out = String::from("sequenceDiagram\n");
+        for participant in &self.participants {
+            let _ = writeln!(out, "    participant {participant}");
+        }
+
+        let origin = self
+            .steps
+            .iter()
+            .map(|step| step.record.start_time_unix_nano)
+            .min()
+            .unwrap_or(0);
+        let matched = match_messages(&self.steps);
+        for (index, step) in self.steps.iter().enumerate() {
+            let record = &step.record;
+            let highlighted = step.divergence.is_some() || record.error.is_some();
+            if highlighted {
+                let _ = writeln!(out, "    rect {HIGHLIGHT}");
+            }
+
+            let at = format_duration(Duration::from_nanos(
+                record.end_time_unix_nano.saturating_sub(origin),
+            ));
+            let label = match record.kind() {
+                Some(ActionKind::Select | ActionKind::Branch) => format!("[{}]", record.label),
+                _ => record.label.clone(),
+            };
+            match record.kind() {
+                _ if record.error.is_some() => {}
+                Some(ActionKind::Send | ActionKind::Select) => {
+                    let arrow = if matched.contains_key(&index) {
+                        "->>"
+                    } else {
+                        "-->>"
+                    };
+                    let _ = writeln!(
+                        out,
+                        "    {}{arrow}{}: +{at} {label}",
+                        record.role, record.peer
+                    );
+                }
+                Some(ActionKind::Receive | ActionKind::Branch) => {
+                    if !matched.values().any(|&receive| receive == index) {
+                        let _ =
+                            writeln!(out, "    {}-->>{}: +{at} {label}", record.peer, record.role);
+                    }
+                }
+                None => {
+                    let _ = writeln!(
+                        out,
+                        "    Note over {}: +{at} {} {label}",
+                        record.role, record.action
+                    );
+                }
+            }
+
+            if let Some(error) = &record.error {
+                let _ = writeln!(
+                    out,
+                    "    Note over {}: +{at} {} failed: {}",
+                    record.role,
+                    record.action,
+                    escape(error)
+                );
+            }
+            if let Some(divergence) = &step.divergence {
+                let _ = writeln!(
+                    out,
+                    "    Note over {}: diverged: {}",
+                    record.role,
+                    escape(&divergence.to_string())
+                );
+            }
+            if record.error.is_none() && record.duration() >= slow {
+                let _ = writeln!(
+                    out,
+                    "    Note over {}: {} {} took {}",
+                    record.role,
+                    record.action,
+                    record.label,
+                    format_duration(record.duration())
+                );
+            }
+            if highlighted {
+                out.push_str("    end\n");
+            }
+        }
+
+        for incomplete in &self.incomplete {
+            if let MonitorViolation::Incomplete { role, .. } = incomplete {
+                let _ = writeln!(
+                    out,
+                    "    Note over {role}: {}",
+                    escape(&incomplete.to_string())
+                );
+            }
+        }
+        out
+    }
+}
+
+/// Check `records` against the projections of `choreography`
+///
+/// Roles are matched by name, so families and unknown roles in the trace are
+/// listed in `unchecked_roles` rather than failing the replay.
+#[must_use]
+pub fn replay(choreography: &Choreography, records: Vec<TraceRecord>) -> ReplayReport {
+    let mut participants: Vec<String> = choreography
+        .roles
+        .iter()
+        .map(|role| role.name.to_string())
+        .collect();
+    let mut by_role: BTreeMap<String, Vec<TraceRecord>> = BTreeMap::new();
+    for record in records {
+        if !participants.contains(&record.role) {
+            participants.push(record.role.clone());
+        }
+        if !record.peer.is_empty() && !participants.contains(&record.peer) {
+            participants.push(record.peer.clone());
+        }
+        by_role.entry(record.role.clone()).or_default().push(record);
+    }
+
+    let mut steps = Vec::new();
+    let mut incomplete = Vec::new();
+    let mut unchecked_roles = Vec::new();
+    for (role_name, mut records) in by_role {
+        records.sort_by_key(|record| record.seq);
+        let mut monitor = match monitor_for(choreography, &role_name) {
+            Ok(monitor) => Some(monitor),
+            Err(reason) => {
+                unchecked_roles.push((role_name.clone(), reason));
+                None
+            }
+        };
+        let mut diverged = false;
+        for record in records {
+            let mut step = ReplayStep {
+                record,
+                divergence: None,
+                checked: false,
+            };
+            if let (Some(monitor), false, None) = (&mut monitor, diverged, &step.record.error) {
+                if let Some(event) = observed(&step.record) {
+                    step.checked = true;
+                    if let Err(violation) = monitor.observe(event) {
+                        step.divergence = Some(violation);
+                        diverged = true;
+                    }
+                }
+            }
+            steps.push(step);
+        }
+        if let (Some(monitor), false) = (&monitor, diverged) {
+            if let Err(violation) = monitor.finish() {
+                incomplete.push(violation);
+            }
+        }
+    }
+
+    steps.sort_by_key(|step| (step.record.end_time_unix_nano, step.record.seq));
+    ReplayReport {
+        participants,
+        steps,
+        incomplete,
+        unchecked_roles,
+    }
+}
+
+/// Monitor of the role called `name`, or why there is none
+fn monitor_for(choreography: &Choreography, name: &str) -> Result<ConformanceMonitor, String> {
+    let role = choreography
+        .roles
+        .iter()
+        .find(|role| role.name == name)
+        .ok_or_else(|| "not a role of the choreography".to_string())?;
+    if role.is_family() {
+        return Err("role families are not replayed".to_string());
+    }
+    let local = project(choreography, role).map_err(|error| error.to_string())?;
+    Ok(ConformanceMonitor::new(monitor_spec(role, &local)))
+}
+
+fn observed(record: &TraceRecord) -> Option<ObservedEvent> {
+    let (peer, label) = (record.peer.as_str(), record.label.as_str());
+    Some(match record.kind()? {
+        ActionKind::Send => ObservedEvent::sent(peer, label),
+        ActionKind::Receive => ObservedEvent::received(peer, label),
+        ActionKind::Select => ObservedEvent::selected(peer, label),
+        ActionKind::Branch => ObservedEvent::branched(peer, label),
+    })
+}
+
+/// Receive matching each send, by index into `steps`
+///
+/// The n-th message from one role to another with a given label is received
+/// by the n-th receive of that label on the other side.
+fn match_messages(steps: &[ReplayStep]) -> HashMap<usize, usize> {
+    let mut by_seq: Vec<usize> = (0..steps.len()).collect();
+    by_seq.sort_by_key(|&index| (&steps[index].record.role, steps[index].record.seq));
+
+    let mut sent: HashMap<(&str, &str, bool, &str), VecDeque<usize>> = HashMap::new();
+    let mut received: HashMap<(&str, &str, bool, &str), VecDeque<usize>> = HashMap::new();
+    for index in by_seq {
+        let record = &steps[index].record;
+        if record.error.is_some() {
+            continue;
+        }
+        let (role, peer, label) = (
+            record.role.as_str(),
+            record.peer.as_str(),
+            record.label.as_str(),
+        );
+        match record.kind() {
+            Some(ActionKind::Send) => sent.entry((role, peer, false, label)),
+            Some(ActionKind::Select) => sent.entry((role, peer, true, label)),
+            Some(ActionKind::Receive) => received.entry((peer, role, false, label)),
+            Some(ActionKind::Branch) => received.entry((peer, role, true, label)),
+            None => continue,
+        }
+        .or_default()
+        .push_back(index);
+    }
+
+    let mut matched = HashMap::new();
+    for (key, sends) in sent {
+        if let Some(receives) = received.get_mut(&key) {
+            for send in sends {
+                let Some(receive) = receives.pop_front() else {
+                    break;
+                };
+                matched.insert(send, receive);
+            }
+        }
+    }
+    matched
+}
+
+fn format_duration(duration: Duration) -> String {
+    let micros = duration.as_micros();
+    if micros >= 1_000_000 {
+        format!("{:.2} s", duration.as_secs_f64())
+    } else if micros >= 1_000 {
+        format!("{:.1} ms", duration.as_secs_f64() * 1_000.0)
+    } else {
+        format!("{micros} µs")
+    }
+}
+
+/// Text safe to put in a Mermaid message or note
+fn escape(text: &str) -> String {
+    text.replace(';', ",").replace('#', "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parser::parse_choreography_str;
+
+    const PING: &str = r"
+choreography Ping {
+    roles: Client, Server
+    Client -> Server: Ping
+    Server -> Client: Pong
+}
+";
+
+    fn record(
+        role: &str,
+        seq: u64,
+        action: &str,
+        peer: &str,
+        label: &str,
+        end_ms: u64,
+    ) -> TraceRecord {
+        TraceRecord {
+            session_id: "s".into(),
+            role: role.into(),
+            seq,
+            action: action.into(),
+            peer: peer.into(),
+            label: label.into(),
+            start_time_unix_nano: 0,
+            end_time_unix_nano: end_ms * 1_000_000,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_conforming_trace_is_drawn_as_matched_messages() {
+        let choreography = parse_choreography_str(PING).unwrap();
+        let trace = vec![
+            record("Server", 1, "send", "Client", "Pong", 3),
+            record("Client", 0, "send", "Server", "Ping", 1),
+            record("Server", 0, "receive", "Client", "Ping", 2),
+            record("Client", 1, "receive", "Server", "Pong", 250),
+        ];
+        let report = replay(&choreography, trace);
+        assert!(!report.diverged());
+        assert!(report.steps.iter().all(|step| step.checked));
+
+        let diagram = report.to_mermaid(Duration::from_millis(100));
+        assert!(diagram.starts_with("sequenceDiagram\n    participant Client\n"));
+        assert!(diagram
+            .contains("    Client->>Server: +1.0 ms Ping\n    Server->>Client: +3.0 ms Pong\n"));
+        assert!(diagram.contains("    Note over Client: receive Pong took 250.0 ms\n"));
+        assert!(!diagram.contains("-->>"));
+        assert!(!diagram.contains("rect"));
+    }
+
+    #[test]
+    fn test_divergence_and_incomplete_roles_are_highlighted() {
+        let choreography = parse_choreography_str(PING).unwrap();
+        let trace = vec![
+            record("Client", 0, "send", "Server", "Ping", 1),
+            record("Server", 0, "send", "Client", "Pong", 2),
+        ];
+        let report = replay(&choreography, trace);
+        assert!(report.diverged());
+        let divergences: Vec<_> = report.divergences().collect();
+        assert!(matches!(
+            divergences.as_slice(),
+            [MonitorViolation::Unexpected {
+                role: "Server",
+                step: 0,
+                ..
+            }]
+        ));
+        assert!(matches!(
+            report.incomplete.as_slice(),
+            [MonitorViolation::Incomplete { role: "Client", .. }]
+        ));
+
+        let diagram = report.to_mermaid(Duration::from_secs(1));
+        assert!(diagram.contains("    Client-->>Server: +1.0 ms Ping\n"));
+        assert!(diagram.contains(&format!(
+            "    rect {HIGHLIGHT}\n    Server-->>Client: +2.0 ms Pong\n    Note over Server: diverged: Server: unexpected send Pong (Client) at step 0"
+        )));
+        assert!(diagram.contains("    Note over Client: Client: session closed before completion"));
+    }
+}
@@ -0,0 +1,49 @@
+// Standard library of parameterized protocols
+//
+// A protocol definition may take parameters, written after its name:
+//
+//     protocol Retry<N: usize, Msg, Sender, Receiver> { ... }
+//
+// A parameter stands for a role, a message or, declared `: usize`, a count.
+// The definition is not checked on its own: `instantiate` substitutes the
+// arguments for the parameters and parses the body then, defining a plain
+// protocol to `call`.
+//
+//     instantiate Retry<3, Payment, Client, Bank> as PaymentRetry;
+//
+// The protocol templates of the core crate can be instantiated by any
+// choreography, unless it defines a protocol of the same name. Their
+// statements are located at the `instantiate` that uses them.
+
+/// Sources of the parameterized protocols every choreography can
+/// instantiate, the templates of [`rumpsteak_aura::protocols`]
+pub const STANDARD_PROTOCOLS: &[&str] = rumpsteak_aura::protocols::ALL;
+
+#[cfg(test)]
+mod tests {
+    use crate::compiler::parser::parse_choreography_str;
+
+    #[test]
+    fn test_standard_protocols_instantiate_and_project() {
+        let choreography = parse_choreography_str(
+            r"
+choreography Checkout {
+    roles: Client, Bank
+    instantiate RequestResponse<Client, Bank, Quote, Price> as AskPrice;
+    instantiate Retry<3, Payment, Client, Bank> as PaymentRetry;
+    instantiate TwoPhaseCommit<Bank, Client> as Settle;
+    call AskPrice
+    call PaymentRetry
+    call Settle
+}
+",
+        )
+        .unwrap();
+        assert!(choreography.validate().is_ok());
+        for role in &choreography.roles {
+            assert!(choreography.project(role).is_ok());
+        }
+        // Located at the instantiation of `RequestResponse`
+        assert_eq!(choreography.protocol.span().line, 4);
+    }
+}
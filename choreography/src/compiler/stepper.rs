@@ -0,0 +1,891 @@
+// Step-through execution of a global protocol
+//
+// A `Stepper` walks a choreography one interaction at a time, with every
+// decision left to the caller: which of the enabled interactions happens
+// next, which branch a choosing role takes, whether a loop runs again, and
+// what stub stands for each message payload. No role code runs, so a
+// protocol can be explored before any of its roles is implemented.
+//
+// Interactions are synchronous: a send is also its receive. The branches of
+// a `parallel` block run as separate threads whose interactions interleave
+// in whatever order the caller picks; the block ends when all of them have.
+// Loops with a fixed count repeat on their own, other loops ask for a
+// decision before every iteration, including the first. Recursion jumps back
+// on its own; it ends through a choice.
+//
+// After each step, `role_view` gives a role's local account of the session:
+// the actions it took so far in session-type notation and what it can do
+// next.
+//
+// `run_random` makes the decisions itself from a seed, taking each choice
+// branch with its `[prob = p]` probability.
+
+use crate::ast::{Branch, Choreography, Condition, MessageType, Protocol, Role};
+use crate::runtime::sim::SplitMix64;
+use proc_macro2::Ident;
+use std::fmt;
+use thiserror::Error;
+
+/// Bound on the control steps taken without any interaction, after which a
+/// thread is considered stuck in a loop that never communicates
+const MAX_SILENT_STEPS: usize = 10_000;
+
+/// Errors raised by a step the protocol does not allow
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum StepError {
+    #[error("no action {0} is enabled")]
+    NoSuchAction(usize),
+
+    #[error("action {action} is {expected}, not {given}")]
+    WrongDecision {
+        action: usize,
+        expected: &'static str,
+        given: &'static str,
+    },
+
+    #[error("{role} has no branch {label}")]
+    UnknownBranch { role: String, label: String },
+}
+
+/// Interaction or decision the protocol allows next
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// `from` sends `message` to every role of `to`
+    Send {
+        from: String,
+        to: Vec<String>,
+        message: String,
+        /// Payload type, if the message declares one
+        payload: Option<String>,
+    },
+    /// `role` picks one of `branches`
+    Choose {
+        role: String,
+        /// Labels, each with its guard if it has one
+        branches: Vec<(String, Option<String>)>,
+    },
+    /// Whether a loop runs (again), decided by `role` if the loop names one
+    Repeat { role: Option<String> },
+}
+
+impl Action {
+    fn kind(&self) -> &'static str {
+        match self {
+            Action::Send { .. } => "a send",
+            Action::Choose { .. } => "a choice",
+            Action::Repeat { .. } => "a loop decision",
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Send {
+                from,
+                to,
+                message,
+                payload,
+            } => {
+                write!(f, "{from} -> {}: {message}", to.join(", "))?;
+                if let Some(payload) = payload {
+                    write!(f, "({payload})")?;
+                }
+                Ok(())
+            }
+            Action::Choose { role, branches } => {
+                let labels: Vec<String> = branches
+                    .iter()
+                    .map(|(label, guard)| match guard {
+                        Some(guard) => format!("{label} [{guard}]"),
+                        None => label.clone(),
+                    })
+                    .collect();
+                write!(f, "{role} chooses {{ {} }}", labels.join(" | "))
+            }
+            Action::Repeat { role: Some(role) } => write!(f, "{role} decides whether to loop"),
+            Action::Repeat { role: None } => write!(f, "loop again or stop"),
+        }
+    }
+}
+
+/// What the caller decides for an action
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Decision {
+    /// Perform a send, with a stub standing for its payload
+    Send(Option<String>),
+    /// Take the branch with this label
+    Branch(String),
+    /// Run the loop (again) or leave it
+    Repeat(bool),
+}
+
+impl Decision {
+    fn kind(&self) -> &'static str {
+        match self {
+            Decision::Send(_) => "a send",
+            Decision::Branch(_) => "a choice",
+            Decision::Repeat(_) => "a loop decision",
+        }
+    }
+}
+
+/// Step taken so far
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Sent {
+        from: String,
+        to: Vec<String>,
+        message: String,
+        payload: Option<String>,
+    },
+    Chose {
+        role: String,
+        label: String,
+    },
+    Repeated {
+        role: Option<String>,
+        again: bool,
+    },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Sent {
+                from,
+                to,
+                message,
+                payload,
+            } => {
+                write!(f, "{from} -> {}: {message}", to.join(", "))?;
+                if let Some(payload) = payload {
+                    write!(f, "({payload})")?;
+                }
+                Ok(())
+            }
+            Event::Chose { role, label } => write!(f, "{role} chose {label}"),
+            Event::Repeated { role, again } => {
+                let decision = if *again { "loop again" } else { "stop" };
+                match role {
+                    Some(role) => write!(f, "{role} decided to {decision}"),
+                    None => write!(f, "decided to {decision}"),
+                }
+            }
+        }
+    }
+}
+
+/// A role's local account of the session so far
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoleView {
+    pub role: String,
+    /// Actions taken, e.g. `Seller!Offer(10)`, `Buyer?Offer`, `+accept`
+    pub history: Vec<String>,
+    pub status: RoleStatus,
+}
+
+/// What a role can do next
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RoleStatus {
+    /// Takes part in an enabled action, described from its side
+    Ready(Vec<String>),
+    /// Has more to do once other roles progress
+    Waiting,
+    /// Takes no further part in the session
+    Done,
+}
+
+impl fmt::Display for RoleStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RoleStatus::Ready(actions) => write!(f, "{}", actions.join(" or ")),
+            RoleStatus::Waiting => write!(f, "waiting"),
+            RoleStatus::Done => write!(f, "done"),
+        }
+    }
+}
+
+/// Interactive walk through a choreography
+pub struct Stepper<'a> {
+    choreography: &'a Choreography,
+    threads: Vec<Thread<'a>>,
+    history: Vec<Event>,
+}
+
+struct Thread<'a> {
+    node: &'a Protocol,
+    loops: Vec<LoopFrame<'a>>,
+    /// Enclosing recursions: label, body, and loop depth at their entry
+    recs: Vec<(&'a Ident, &'a Protocol, usize)>,
+    /// The innermost loop waits for a decision to run again
+    deciding: bool,
+    /// Thread this one was forked from by a `parallel` block
+    parent: Option<usize>,
+    /// Forked threads still running; this thread resumes when none is
+    children: usize,
+    finished: bool,
+}
+
+struct LoopFrame<'a> {
+    body: &'a Protocol,
+    condition: Option<&'a Condition>,
+    /// Iterations still to run of a loop with a fixed count
+    remaining: Option<usize>,
+}
+
+impl<'a> Stepper<'a> {
+    #[must_use]
+    pub fn new(choreography: &'a Choreography) -> Self {
+        let mut stepper = Self {
+            choreography,
+            threads: vec![Thread {
+                node: &choreography.protocol,
+                loops: Vec::new(),
+                recs: Vec::new(),
+                deciding: false,
+                parent: None,
+                children: 0,
+                finished: false,
+            }],
+            history: Vec::new(),
+        };
+        stepper.settle_all();
+        stepper
+    }
+
+    /// Whether the session has ended
+    #[must_use]
+    pub fn is_done(&self) -> bool {
+        self.threads.iter().all(|thread| thread.finished)
+    }
+
+    /// Steps taken so far
+    #[must_use]
+    pub fn history(&self) -> &[Event] {
+        &self.history
+    }
+
+    /// Enabled actions; `perform` takes an index into this list
+    #[must_use]
+    pub fn actions(&self) -> Vec<Action> {
+        self.enabled()
+            .into_iter()
+            .map(|index| self.action_of(&self.threads[index]))
+            .collect()
+    }
+
+    /// Take the enabled action `index` as `decision` says
+    ///
+    /// # Errors
+    ///
+    /// [`StepError`] if there is no such action or `decision` does not fit
+    /// it. Nothing changes then.
+    pub fn perform(&mut self, index: usize, decision: Decision) -> Result<(), StepError> {
+        let thread_index = *self
+            .enabled()
+            .get(index)
+            .ok_or(StepError::NoSuchAction(index))?;
+        let action = self.action_of(&self.threads[thread_index]);
+        let wrong = |decision: &Decision| StepError::WrongDecision {
+            action: index,
+            expected: action.kind(),
+            given: decision.kind(),
+        };
+
+        let thread = &mut self.threads[thread_index];
+        let event = match (&action, decision) {
+            (
+                Action::Send {
+                    from, to, message, ..
+                },
+                Decision::Send(payload),
+            ) => {
+                if let Protocol::Send { continuation, .. }
+                | Protocol::Broadcast { continuation, .. } = thread.node
+                {
+                    thread.node = continuation;
+                }
+                Event::Sent {
+                    from: from.clone(),
+                    to: to.clone(),
+                    message: message.clone(),
+                    payload,
+                }
+            }
+            (Action::Choose { role, .. }, Decision::Branch(label)) => {
+                let Protocol::Choice { branches, .. } = thread.node else {
+                    return Err(StepError::NoSuchAction(index));
+                };
+                let branch = branches
+                    .iter()
+                    .find(|branch| branch.label == label)
+                    .ok_or_else(|| StepError::UnknownBranch {
+                        role: role.clone(),
+                        label: label.clone(),
+                    })?;
+                thread.node = &branch.protocol;
+                Event::Chose {
+                    role: role.clone(),
+                    label,
+                }
+            }
+            (Action::Repeat { role }, Decision::Repeat(again)) => {
+                thread.deciding = false;
+                if again {
+                    if let Some(frame) = thread.loops.last() {
+                        thread.node = frame.body;
+                    }
+                } else {
+                    thread.loops.pop();
+                    thread.node = &Protocol::End;
+                }
+                Event::Repeated {
+                    role: role.clone(),
+                    again,
+                }
+            }
+            (_, decision) => return Err(wrong(&decision)),
+        };
+        self.history.push(event);
+        self.settle_all();
+        Ok(())
+    }
+
+    /// Take up to `max_steps` random steps, drawn from `seed`
+    ///
+    /// Every step picks one of the enabled actions with equal chance. A
+    /// choice takes each branch with its probability, see
+    /// [`Branch::probabilities`], a loop without a count runs again with
+    /// probability 1/2, and sends carry no payload stub. The same seed takes
+    /// the same steps. Returns the number of steps taken.
+    pub fn run_random(&mut self, seed: u64, max_steps: usize) -> usize {
+        let mut rng = SplitMix64(seed);
+        let mut steps = 0;
+        while steps < max_steps && !self.is_done() {
+            let actions = self.actions();
+            if actions.is_empty() {
+                break;
+            }
+            let index = (rng.next() % actions.len() as u64) as usize;
+            let decision = match &actions[index] {
+                Action::Send { .. } => Decision::Send(None),
+                Action::Choose { .. } => Decision::Branch(self.pick_branch(index, rng.next_f64())),
+                Action::Repeat { .. } => Decision::Repeat(rng.next() % 2 == 0),
+            };
+            if self.perform(index, decision).is_err() {
+                break;
+            }
+            steps += 1;
+        }
+        steps
+    }
+
+    /// Label of the branch of choice `index` that `sample`, uniform in
+    /// `[0, 1)`, falls on
+    fn pick_branch(&self, index: usize, sample: f64) -> String {
+        let thread = &self.threads[self.enabled()[index]];
+        let Protocol::Choice { branches, .. } = thread.node else {
+            return String::new();
+        };
+        let mut total = 0.0;
+        for (branch, probability) in branches.iter().zip(Branch::probabilities(branches)) {
+            total += probability;
+            if sample < total {
+                return branch.label.to_string();
+            }
+        }
+        branches
+            .last()
+            .map(|branch| branch.label.to_string())
+            .unwrap_or_default()
+    }
+
+    /// Local account of `role`, by role name
+    #[must_use]
+    pub fn role_view(&self, role: &str) -> RoleView {
+        let history = self
+            .history
+            .iter()
+            .filter_map(|event| local_action(event, role))
+            .collect();
+
+        let ready: Vec<String> = self
+            .actions()
+            .iter()
+            .filter_map(|action| local_option(action, role))
+            .collect();
+        let status = if !ready.is_empty() {
+            RoleStatus::Ready(ready)
+        } else if self.involves_later(role) {
+            RoleStatus::Waiting
+        } else {
+            RoleStatus::Done
+        };
+        RoleView {
+            role: role.to_string(),
+            history,
+            status,
+        }
+    }
+
+    /// Local accounts of every role of the choreography
+    #[must_use]
+    pub fn role_views(&self) -> Vec<RoleView> {
+        self.choreography
+            .roles
+            .iter()
+            .map(|role| self.role_view(&role.name.to_string()))
+            .collect()
+    }
+
+    /// Threads waiting for an action, in a stable order
+    fn enabled(&self) -> Vec<usize> {
+        (0..self.threads.len())
+            .filter(|&index| {
+                let thread = &self.threads[index];
+                !thread.finished && thread.children == 0
+            })
+            .collect()
+    }
+
+    fn action_of(&self, thread: &Thread<'a>) -> Action {
+        if thread.deciding {
+            let role = thread.loops.last().and_then(|frame| match frame.condition {
+                Some(Condition::RoleDecides(role)) => Some(role_name(role)),
+                _ => None,
+            });
+            return Action::Repeat { role };
+        }
+        match thread.node {
+            Protocol::Send {
+                from, to, message, ..
+            } => Action::Send {
+                from: role_name(from),
+                to: vec![role_name(to)],
+                message: message.name.to_string(),
+                payload: payload_type(message),
+            },
+            Protocol::Broadcast {
+                from,
+                to_all,
+                message,
+                ..
+            } => Action::Send {
+                from: role_name(from),
+                to: to_all.iter().map(role_name).collect(),
+                message: message.name.to_string(),
+                payload: payload_type(message),
+            },
+            Protocol::Choice { role, branches, .. } => Action::Choose {
+                role: role_name(role),
+                branches: branches
+                    .iter()
+                    .map(|branch| {
+                        (
+                            branch.label.to_string(),
+                            branch.guard.as_ref().map(ToString::to_string),
+                        )
+                    })
+                    .collect(),
+            },
+            // Settled threads rest on an interaction or a loop decision
+            _ => Action::Repeat { role: None },
+        }
+    }
+
+    /// Advance every thread through control flow up to its next action
+    fn settle_all(&mut self) {
+        let mut index = 0;
+        while index < self.threads.len() {
+            self.settle(index);
+            index += 1;
+        }
+    }
+
+    fn settle(&mut self, index: usize) {
+        let mut silent = 0;
+        loop {
+            let thread = &mut self.threads[index];
+            if thread.finished || thread.deciding || thread.children > 0 {
+                return;
+            }
+            silent += 1;
+            if silent > MAX_SILENT_STEPS {
+                self.finish(index);
+                return;
+            }
+            match thread.node {
+                Protocol::Send { .. } | Protocol::Broadcast { .. } | Protocol::Choice { .. } => {
+                    return
+                }
+                Protocol::Extension { continuation, .. } => thread.node = continuation,
+                Protocol::Rec { label, body, .. } => {
+                    thread.recs.push((label, body, thread.loops.len()));
+                    thread.node = body;
+                }
+                Protocol::Var(label) => {
+                    match thread.recs.iter().rev().find(|(rec, ..)| *rec == label) {
+                        Some(&(_, body, depth)) => {
+                            thread.loops.truncate(depth);
+                            thread.node = body;
+                        }
+                        None => thread.node = &Protocol::End,
+                    }
+                }
+                Protocol::Loop {
+                    condition, body, ..
+                } => match condition {
+                    Some(Condition::Count(0)) => thread.node = &Protocol::End,
+                    Some(Condition::Count(n)) => {
+                        thread.loops.push(LoopFrame {
+                            body,
+                            condition: condition.as_ref(),
+                            remaining: Some(n - 1),
+                        });
+                        thread.node = body;
+                    }
+                    _ => {
+                        thread.loops.push(LoopFrame {
+                            body,
+                            condition: condition.as_ref(),
+                            remaining: None,
+                        });
+                        thread.node = body;
+                        thread.deciding = true;
+                    }
+                },
+                Protocol::Parallel { protocols, .. } => {
+                    thread.node = &Protocol::End;
+                    thread.children = protocols.len();
+                    for protocol in protocols {
+                        self.threads.push(Thread {
+                            node: protocol,
+                            loops: Vec::new(),
+                            recs: Vec::new(),
+                            deciding: false,
+                            parent: Some(index),
+                            children: 0,
+                            finished: false,
+                        });
+                    }
+                }
+                Protocol::End => match thread.loops.last_mut() {
+                    None => {
+                        self.finish(index);
+                        return;
+                    }
+                    Some(frame) => match &mut frame.remaining {
+                        Some(0) => {
+                            thread.loops.pop();
+                        }
+                        Some(remaining) => {
+                            *remaining -= 1;
+                            thread.node = frame.body;
+                        }
+                        None => thread.deciding = true,
+                    },
+                },
+            }
+        }
+    }
+
+    /// Mark thread `index` finished and resume its parent once all of the
+    /// parent's threads are
+    fn finish(&mut self, index: usize) {
+        self.threads[index].finished = true;
+        if let Some(parent) = self.threads[index].parent {
+            self.threads[parent].children -= 1;
+            if self.threads[parent].children == 0 {
+                self.settle(parent);
+            }
+        }
+    }
+
+    /// Whether `role` appears in what the unfinished threads may still run
+    fn involves_later(&self, role: &str) -> bool {
+        self.threads
+            .iter()
+            .filter(|thread| !thread.finished)
+            .any(|thread| {
+                involves(thread.node, role)
+                    || thread.loops.iter().any(|frame| involves(frame.body, role))
+                    || thread.recs.iter().any(|(_, body, _)| involves(body, role))
+            })
+    }
+}
+
+/// Name of a role, with its index if it has one
+fn role_name(role: &Role) -> String {
+    match &role.index {
+        Some(index) => format!("{}[{index}]", role.name),
+        None => role.name.to_string(),
+    }
+}
+
+fn payload_type(message: &MessageType) -> Option<String> {
+    message.payload.as_ref().map(ToString::to_string)
+}
+
+/// Name of `role` without its index, for matching role views
+fn base_name(role: &str) -> &str {
+    role.split('[').next().unwrap_or(role)
+}
+
+/// `event` as seen by `role`, in session-type notation
+fn local_action(event: &Event, role: &str) -> Option<String> {
+    match event {
+        Event::Sent {
+            from,
+            to,
+            message,
+            payload,
+        } => {
+            let payload = payload
+                .as_ref()
+                .map(|payload| format!("({payload})"))
+                .unwrap_or_default();
+            if base_name(from) == role {
+                Some(format!("{}!{message}{payload}", to.join(",")))
+            } else if to.iter().any(|to| base_name(to) == role) {
+                Some(format!("{from}?{message}{payload}"))
+            } else {
+                None
+            }
+        }
+        Event::Chose {
+            role: chooser,
+            label,
+        } => (base_name(chooser) == role).then(|| format!("+{label}")),
+        Event::Repeated {
+            role: Some(decider),
+            again,
+        } => (base_name(decider) == role).then(|| {
+            if *again {
+                "loop".to_string()
+            } else {
+                "stop".to_string()
+            }
+        }),
+        Event::Repeated { role: None, .. } => None,
+    }
+}
+
+/// `action` as an option of `role`, if it takes part
+fn local_option(action: &Action, role: &str) -> Option<String> {
+    match action {
+        Action::Send {
+            from, to, message, ..
+        } => {
+            if base_name(from) == role {
+                Some(format!("send {message} to {}", to.join(", ")))
+            } else if to.iter().any(|to| base_name(to) == role) {
+                Some(format!("receive {message} from {from}"))
+            } else {
+                None
+            }
+        }
+        Action::Choose {
+            role: chooser,
+            branches,
+        } => (base_name(chooser) == role).then(|| {
+            let labels: Vec<&str> = branches.iter().map(|(label, _)| label.as_str()).collect();
+            format!("choose {}", labels.join(" or "))
+        }),
+        Action::Repeat {
+            role: Some(decider),
+        } => (base_name(decider) == role).then(|| "decide whether to loop".to_string()),
+        Action::Repeat { role: None } => None,
+    }
+}
+
+/// Whether `role` takes part in `protocol`
+fn involves(protocol: &Protocol, role: &str) -> bool {
+    match protocol {
+        Protocol::Send {
+            from,
+            to,
+            continuation,
+            ..
+        } => from.name == role || to.name == role || involves(continuation, role),
+        Protocol::Broadcast {
+            from,
+            to_all,
+            continuation,
+            ..
+        } => {
+            from.name == role
+                || to_all.iter().any(|to| to.name == role)
+                || involves(continuation, role)
+        }
+        Protocol::Choice {
+            role: chooser,
+            branches,
+            ..
+        } => {
+            chooser.name == role
+                || branches
+                    .iter()
+                    .any(|branch| involves(&branch.protocol, role))
+        }
+        Protocol::Loop {
+            condition, body, ..
+        } => {
+            matches!(condition, Some(Condition::RoleDecides(decider)) if decider.name == role)
+                || involves(body, role)
+        }
+        Protocol::Parallel { protocols, .. } => {
+            protocols.iter().any(|protocol| involves(protocol, role))
+        }
+        Protocol::Rec { body, .. } => involves(body, role),
+        Protocol::Extension { continuation, .. } => involves(continuation, role),
+        Protocol::Var(_) | Protocol::End => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::parser::parse_choreography_str;
+
+    #[test]
+    fn test_steps_through_choice_and_loop() {
+        let choreography = parse_choreography_str(
+            r"
+choreography Auction {
+    roles: Seller, Buyer, Bank
+    Seller -> Buyer: Offer(u64)
+    loop (decides: Buyer) {
+        Buyer -> Seller: Bid(u64)
+    }
+}
+",
+        )
+        .unwrap();
+        let mut stepper = Stepper::new(&choreography);
+        assert_eq!(
+            stepper.actions(),
+            vec![Action::Send {
+                from: "Seller".into(),
+                to: vec!["Buyer".into()],
+                message: "Offer".into(),
+                payload: Some("u64".into()),
+            }]
+        );
+        assert_eq!(
+            stepper.role_view("Buyer").status,
+            RoleStatus::Ready(vec!["receive Offer from Seller".into()])
+        );
+        assert_eq!(stepper.role_view("Bank").status, RoleStatus::Done);
+
+        stepper
+            .perform(0, Decision::Send(Some("10".into())))
+            .unwrap();
+        assert_eq!(
+            stepper.actions(),
+            vec![Action::Repeat {
+                role: Some("Buyer".into())
+            }]
+        );
+        assert_eq!(
+            stepper.perform(0, Decision::Branch("bid".into())),
+            Err(StepError::WrongDecision {
+                action: 0,
+                expected: "a loop decision",
+                given: "a choice",
+            })
+        );
+        stepper.perform(0, Decision::Repeat(true)).unwrap();
+        stepper.perform(0, Decision::Send(None)).unwrap();
+        stepper.perform(0, Decision::Repeat(false)).unwrap();
+        assert!(stepper.is_done());
+        assert_eq!(
+            stepper.role_view("Buyer").history,
+            vec!["Seller?Offer(10)", "loop", "Seller!Bid", "stop"]
+        );
+        assert_eq!(stepper.role_view("Seller").status, RoleStatus::Done);
+    }
+
+    #[test]
+    fn test_choice_branches_and_parallel_threads() {
+        let choreography = parse_choreography_str(
+            r"
+choreography Fork {
+    roles: A, B, C
+    choice A {
+        go: {
+            parallel {
+                A -> B: Left
+                |
+                A -> C: Right
+            }
+        }
+        halt: {
+            A -> B: Stop
+        }
+    }
+}
+",
+        )
+        .unwrap();
+        let mut stepper = Stepper::new(&choreography);
+        assert_eq!(
+            stepper.perform(0, Decision::Branch("wait".into())),
+            Err(StepError::UnknownBranch {
+                role: "A".into(),
+                label: "wait".into(),
+            })
+        );
+        stepper.perform(0, Decision::Branch("go".into())).unwrap();
+        let actions: Vec<String> = stepper.actions().iter().map(ToString::to_string).collect();
+        assert_eq!(actions, vec!["A -> B: Left", "A -> C: Right"]);
+
+        stepper.perform(1, Decision::Send(None)).unwrap();
+        assert_eq!(stepper.role_view("C").status, RoleStatus::Done);
+        assert!(!stepper.is_done());
+        stepper.perform(0, Decision::Send(None)).unwrap();
+        assert!(stepper.is_done());
+        assert_eq!(
+            stepper.role_view("A").history,
+            vec!["+go", "C!Right", "B!Left"]
+        );
+        assert_eq!(
+            stepper.perform(0, Decision::Send(None)),
+            Err(StepError::NoSuchAction(0))
+        );
+    }
+
+    #[test]
+    fn test_random_run_follows_branch_probabilities() {
+        let choreography = parse_choreography_str(
+            r"
+choreography Offer {
+    roles: Buyer, Seller
+    choice Buyer {
+        accept [prob = 1.0]: {
+            Buyer -> Seller: Accept
+        }
+        reject [prob = 0]: {
+            Buyer -> Seller: Reject
+        }
+    }
+}
+",
+        )
+        .unwrap();
+
+        for seed in 0..20 {
+            let mut stepper = Stepper::new(&choreography);
+            assert_eq!(stepper.run_random(seed, 100), 2);
+            assert!(stepper.is_done());
+            assert_eq!(stepper.history()[0].to_string(), "Buyer chose accept");
+        }
+
+        let run = |seed| {
+            let mut stepper = Stepper::new(&choreography);
+            stepper.run_random(seed, 100);
+            stepper.history().to_vec()
+        };
+        assert_eq!(run(7), run(7));
+    }
+}
@@ -0,0 +1,464 @@
+//! Compatibility of adjacent protocol versions
+//!
+//! A choreography declares its version with `@protocol(version = n)`,
+//! version 1 without one. [`compare_versions`] walks two versions of a protocol side by
+//! side and reports how the newer one differs. The only compatible difference
+//! is a branch added to a choice: endpoints of the new version can then take
+//! part in sessions of the previous one, as long as their choosers leave the
+//! added branches alone. Any other change, such as a message added, removed
+//! or retyped, a branch removed or a role renamed, needs every endpoint to
+//! move at once.
+//!
+//! [`generate_upgrade_adapter`] emits the `UpgradeSpec` of two compatible
+//! adjacent versions, which `runtime::upgrade::VersionAdapter` and the
+//! version negotiation run on. `Workspace::upgrade` generates it along with
+//! the newer protocol.
+
+use crate::ast::{Choreography, Condition, Protocol};
+use crate::compiler::handler_codegen::snake_case;
+use crate::compiler::parser::parse_choreography_str;
+use crate::runtime::bootstrap::protocol_hash;
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+
+/// Version declared by `@protocol(version = n)`, 1 without one
+///
+/// # Errors
+///
+/// The version is not a number.
+pub fn protocol_version(choreography: &Choreography) -> Result<u32, UpgradeError> {
+    let Some(arguments) = choreography.get_attribute("protocol") else {
+        return Ok(1);
+    };
+    for argument in arguments.split(',') {
+        if let Some((key, value)) = argument.split_once('=') {
+            if key.trim() == "version" {
+                let value = value.trim();
+                return value
+                    .parse()
+                    .map_err(|_| UpgradeError::InvalidVersion(value.to_string()));
+            }
+        }
+    }
+    Ok(1)
+}
+
+/// How a version of a protocol differs from the previous one
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VersionDiff {
+    /// Choosing role and label of each branch the previous version lacks
+    pub added_branches: Vec<(String, String)>,
+}
+
+/// Reasons two versions of a protocol cannot run side by side
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum UpgradeError {
+    #[error("cannot parse version: {0}")]
+    Parse(String),
+
+    #[error("invalid protocol version '{0}'")]
+    InvalidVersion(String),
+
+    #[error("{current} is not a version of {previous}")]
+    Renamed { previous: String, current: String },
+
+    #[error("version {current} of {protocol} does not follow version {previous}")]
+    NotAdjacent {
+        protocol: String,
+        previous: u32,
+        current: u32,
+    },
+
+    #[error("roles changed from {previous:?} to {current:?}")]
+    RolesChanged {
+        previous: Vec<String>,
+        current: Vec<String>,
+    },
+
+    #[error("line {line}: {reason}")]
+    Incompatible { line: usize, reason: String },
+}
+
+/// Differences of `current` from `previous`, if every endpoint of
+/// `current` can run sessions of `previous`
+///
+/// # Errors
+///
+/// The protocols have different names or roles, or differ in anything
+/// but added branches.
+pub fn compare_versions(
+    previous: &Choreography,
+    current: &Choreography,
+) -> Result<VersionDiff, UpgradeError> {
+    if previous.name != current.name {
+        return Err(UpgradeError::Renamed {
+            previous: previous.name.to_string(),
+            current: current.name.to_string(),
+        });
+    }
+    let role_names = |choreography: &Choreography| {
+        let mut names: Vec<String> = choreography
+            .roles
+            .iter()
+            .map(|role| role.name.to_string())
+            .collect();
+        names.sort();
+        names
+    };
+    let (previous_roles, current_roles) = (role_names(previous), role_names(current));
+    if previous_roles != current_roles {
+        return Err(UpgradeError::RolesChanged {
+            previous: previous_roles,
+            current: current_roles,
+        });
+    }
+
+    let mut diff = VersionDiff::default();
+    compare(&previous.protocol, &current.protocol, &mut diff)?;
+    Ok(diff)
+}
+
+fn compare(
+    previous: &Protocol,
+    current: &Protocol,
+    diff: &mut VersionDiff,
+) -> Result<(), UpgradeError> {
+    match (previous, current) {
+        (
+            Protocol::Send {
+                from,
+                to,
+                message,
+                continuation,
+                ..
+            },
+            Protocol::Send {
+                from: new_from,
+                to: new_to,
+                message: new_message,
+                continuation: new_continuation,
+                ..
+            },
+        ) if from == new_from && to == new_to && message == new_message => {
+            compare(continuation, new_continuation, diff)
+        }
+        (
+            Protocol::Broadcast {
+                from,
+                to_all,
+                message,
+                continuation,
+                ..
+            },
+            Protocol::Broadcast {
+                from: new_from,
+                to_all: new_to_all,
+                message: new_message,
+                continuation: new_continuation,
+                ..
+            },
+        ) if from == new_from && to_all == new_to_all && message == new_message => {
+            compare(continuation, new_continuation, diff)
+        }
+        (
+            Protocol::Choice { role, branches, .. },
+            Protocol::Choice {
+                role: new_role,
+                branches: new_branches,
+                span,
+                ..
+            },
+        ) if role == new_role => {
+            for branch in branches {
+                let Some(new_branch) = new_branches.iter().find(|b| b.label == branch.label) else {
+                    return Err(UpgradeError::Incompatible {
+                        line: span.line,
+                        reason: format!("branch {} of {} was removed", branch.label, role.name),
+                    });
+                };
+                compare(&branch.protocol, &new_branch.protocol, diff)?;
+            }
+            for new_branch in new_branches {
+                if !branches.iter().any(|b| b.label == new_branch.label) {
+                    diff.added_branches
+                        .push((role.name.to_string(), new_branch.label.to_string()));
+                }
+            }
+            Ok(())
+        }
+        (
+            Protocol::Loop {
+                condition, body, ..
+            },
+            Protocol::Loop {
+                condition: new_condition,
+                body: new_body,
+                ..
+            },
+        ) if same_condition(condition.as_ref(), new_condition.as_ref()) => {
+            compare(body, new_body, diff)
+        }
+        (
+            Protocol::Parallel { protocols, .. },
+            Protocol::Parallel {
+                protocols: new_protocols,
+                ..
+            },
+        ) if protocols.len() == new_protocols.len() => protocols
+            .iter()
+            .zip(new_protocols)
+            .try_for_each(|(previous, current)| compare(previous, current, diff)),
+        (
+            Protocol::Rec { label, body, .. },
+            Protocol::Rec {
+                label: new_label,
+                body: new_body,
+                ..
+            },
+        ) if label == new_label => compare(body, new_body, diff),
+        (Protocol::Var(label), Protocol::Var(new_label)) if label == new_label => Ok(()),
+        (
+            Protocol::Extension {
+                extension,
+                continuation,
+                ..
+            },
+            Protocol::Extension {
+                extension: new_extension,
+                continuation: new_continuation,
+                ..
+            },
+        ) if extension.type_name() == new_extension.type_name() => {
+            compare(continuation, new_continuation, diff)
+        }
+        (Protocol::End, Protocol::End) => Ok(()),
+        _ => {
+            let line = match current.span().line {
+                0 => previous.span().line,
+                line => line,
+            };
+            Err(UpgradeError::Incompatible {
+                line,
+                reason: format!("{} became {}", describe(previous), describe(current)),
+            })
+        }
+    }
+}
+
+fn same_condition(previous: Option<&Condition>, current: Option<&Condition>) -> bool {
+    match (previous, current) {
+        (None, None) => true,
+        (Some(Condition::Count(n)), Some(Condition::Count(m))) => n == m,
+        (Some(Condition::RoleDecides(role)), Some(Condition::RoleDecides(new_role))) => {
+            role == new_role
+        }
+        (Some(Condition::Custom(tokens)), Some(Condition::Custom(new_tokens))) => {
+            tokens.to_string() == new_tokens.to_string()
+        }
+        _ => false,
+    }
+}
+
+/// Statement at the head of `protocol`, for error messages
+fn describe(protocol: &Protocol) -> String {
+    match protocol {
+        Protocol::Send {
+            from, to, message, ..
+        } => format!("{} -> {}: {}", from.name, to.name, message.name),
+        Protocol::Broadcast { from, message, .. } => {
+            format!("{} -> *: {}", from.name, message.name)
+        }
+        Protocol::Choice { role, .. } => format!("choice at {}", role.name),
+        Protocol::Loop { .. } => "a loop".to_string(),
+        Protocol::Parallel { .. } => "a parallel block".to_string(),
+        Protocol::Rec { label, .. } => format!("rec {label}"),
+        Protocol::Var(label) => format!("continue {label}"),
+        Protocol::Extension { extension, .. } => extension.type_name().to_string(),
+        Protocol::End => "the end of the protocol".to_string(),
+    }
+}
+
+/// `<PROTOCOL>_UPGRADE_FROM_V<n>` for upgrades of `protocol` from version `n`
+fn spec_name(protocol: &str, previous: u32) -> proc_macro2::Ident {
+    format_ident!(
+        "{}_UPGRADE_FROM_V{}",
+        snake_case(protocol).to_uppercase(),
+        previous
+    )
+}
+
+/// `UpgradeSpec` from the protocol of `previous_source` to the next version,
+/// in `current_source`
+///
+/// Hashes are those of `ProtocolDescriptor::from_source`, so negotiation
+/// matches endpoints bootstrapped from the same sources.
+///
+/// # Errors
+///
+/// A source does not parse, the versions are not adjacent, or they are
+/// incompatible, see [`compare_versions`].
+pub fn generate_upgrade_adapter(
+    previous_source: &str,
+    current_source: &str,
+) -> Result<TokenStream, UpgradeError> {
+    let parse =
+        |source| parse_choreography_str(source).map_err(|e| UpgradeError::Parse(e.to_string()));
+    let (previous, current) = (parse(previous_source)?, parse(current_source)?);
+    let diff = compare_versions(&previous, &current)?;
+    let (previous_version, current_version) =
+        (protocol_version(&previous)?, protocol_version(&current)?);
+    if previous_version.checked_add(1) != Some(current_version) {
+        return Err(UpgradeError::NotAdjacent {
+            protocol: current.name.to_string(),
+            previous: previous_version,
+            current: current_version,
+        });
+    }
+
+    let protocol = current.name.to_string();
+    let name = spec_name(&protocol, previous_version);
+    let previous_hash = protocol_hash(previous_source);
+    let current_hash = protocol_hash(current_source);
+    let added = diff.added_branches.iter().map(|(chooser, label)| {
+        quote! {
+            rumpsteak_aura_choreography::runtime::upgrade::AddedBranch {
+                chooser: #chooser,
+                label: #label,
+            }
+        }
+    });
+    let doc = format!(
+        "Changes of {protocol} from version {previous_version} to {current_version}, for \
+         endpoints of version {current_version} in sessions of either"
+    );
+    Ok(quote! {
+        #[doc = #doc]
+        pub static #name: rumpsteak_aura_choreography::runtime::upgrade::UpgradeSpec =
+            rumpsteak_aura_choreography::runtime::upgrade::UpgradeSpec {
+                protocol: #protocol,
+                previous: #previous_version,
+                previous_hash: #previous_hash,
+                current: #current_version,
+                current_hash: #current_hash,
+                added_branches: &[#(#added),*],
+            };
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V1: &str = r"
+@protocol(version = 1)
+choreography Checkout {
+    roles: Client, Server
+    Client -> Server: PlaceOrder
+    choice Server {
+        accept: {
+            Server -> Client: OrderAccepted
+        }
+        reject: {
+            Server -> Client: OrderRejected
+        }
+    }
+}
+";
+
+    const V2: &str = r"
+@protocol(version = 2)
+choreography Checkout {
+    roles: Client, Server
+    Client -> Server: PlaceOrder
+    choice Server {
+        accept: {
+            Server -> Client: OrderAccepted
+        }
+        reject: {
+            Server -> Client: OrderRejected
+        }
+        backorder: {
+            Server -> Client: OrderDelayed
+        }
+    }
+}
+";
+
+    fn parse(source: &str) -> Choreography {
+        parse_choreography_str(source).unwrap()
+    }
+
+    #[test]
+    fn test_added_branches_are_compatible() {
+        let (v1, v2) = (parse(V1), parse(V2));
+        assert_eq!(protocol_version(&v2), Ok(2));
+        let diff = compare_versions(&v1, &v2).unwrap();
+        assert_eq!(
+            diff.added_branches,
+            [("Server".to_string(), "backorder".to_string())]
+        );
+        assert_eq!(compare_versions(&v1, &v1).unwrap(), VersionDiff::default());
+    }
+
+    #[test]
+    fn test_other_changes_are_incompatible() {
+        let v1 = parse(V1);
+        // Going back removes the branch
+        assert!(matches!(
+            compare_versions(&parse(V2), &v1),
+            Err(UpgradeError::Incompatible { reason, .. })
+                if reason == "branch backorder of Server was removed"
+        ));
+
+        let retyped = parse(&V1.replace(
+            "Client -> Server: PlaceOrder",
+            "Client -> Server: PlaceOrder(u64)",
+        ));
+        assert!(matches!(
+            compare_versions(&v1, &retyped),
+            Err(UpgradeError::Incompatible { line: 5, .. })
+        ));
+
+        let extended = parse(&V1.replace(
+            "Server -> Client: OrderRejected",
+            "Server -> Client: OrderRejected\n            Client -> Server: Ack",
+        ));
+        assert_eq!(
+            compare_versions(&v1, &extended),
+            Err(UpgradeError::Incompatible {
+                line: 12,
+                reason: "the end of the protocol became Client -> Server: Ack".to_string(),
+            })
+        );
+
+        let renamed = parse(&V1.replace("Client", "Buyer"));
+        assert!(matches!(
+            compare_versions(&v1, &renamed),
+            Err(UpgradeError::RolesChanged { .. })
+        ));
+    }
+
+    #[test]
+    fn test_adapter_lists_added_branches() {
+        let code = generate_upgrade_adapter(V1, V2).unwrap();
+        syn::parse2::<syn::File>(code.clone()).unwrap();
+        let code = code.to_string();
+        assert!(code.contains("pub static CHECKOUT_UPGRADE_FROM_V1"));
+        assert!(code.contains("chooser : \"Server\" , label : \"backorder\""));
+        assert!(code.contains(&format!("previous_hash : {}u64", protocol_hash(V1))));
+
+        let err = generate_upgrade_adapter(
+            V1,
+            &V2.replace("@protocol(version = 2)", "@protocol(version = 3)"),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            UpgradeError::NotAdjacent {
+                protocol: "Checkout".to_string(),
+                previous: 1,
+                current: 3,
+            }
+        );
+    }
+}
@@ -0,0 +1,616 @@
+// Incremental compilation of a directory of `.choreo` files
+//
+// Meant for build scripts: every protocol under the root is compiled to a
+// Rust file at the same relative path under the output directory, and a
+// cache next to the outputs records a content hash per protocol. Later
+// builds only re-parse, re-project and re-generate protocols whose hash
+// changed.
+//
+// The hash covers the protocol source, the sources it depends on, the
+// compiler version and the grammar extensions of the registry. A protocol
+// depends on the shared message modules its message types name: for
+// `Order<messages::Order>` the files `messages.rs`, `messages/mod.rs` and
+// deeper path segments are looked up next to the protocol and under the
+// root. Modules living elsewhere are registered with `message_module`, and
+// dependencies that cannot be seen in the source with `dependency`.
+//
+// A protocol registered with `upgrade` also gets the `UpgradeSpec` from the
+// previous version of its choreography, so endpoints built from it can run
+// sessions of either version (see `compiler::upgrade`).
+//
+//     // build.rs
+//     let out_dir = std::env::var("OUT_DIR").unwrap();
+//     Workspace::new("protocols", out_dir)
+//         .build()?
+//         .emit_rerun_if_changed();
+
+use crate::ast::{Choreography, Protocol};
+use crate::compiler::parser::parse_choreography_str_with_extensions;
+use crate::compiler::upgrade::generate_upgrade_adapter;
+use crate::extensions::ExtensionRegistry;
+use crate::CompilationError;
+use proc_macro2::{TokenStream, TokenTree};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// File the workspace keeps its cache in, inside the output directory
+pub const CACHE_FILE: &str = ".rumpsteak-workspace.json";
+
+/// Extension of protocol sources
+pub const PROTOCOL_EXTENSION: &str = "choreo";
+
+/// A directory of protocols compiled incrementally
+pub struct Workspace {
+    root: PathBuf,
+    out_dir: PathBuf,
+    registry: ExtensionRegistry,
+    message_modules: BTreeMap<String, PathBuf>,
+    dependencies: BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+    upgrades: BTreeMap<PathBuf, PathBuf>,
+    jobs: usize,
+}
+
+impl Workspace {
+    /// Compile the protocols under `root` into `out_dir` with the builtin
+    /// extensions
+    pub fn new(root: impl Into<PathBuf>, out_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            out_dir: out_dir.into(),
+            registry: ExtensionRegistry::with_builtin_extensions(),
+            message_modules: BTreeMap::new(),
+            dependencies: BTreeMap::new(),
+            upgrades: BTreeMap::new(),
+            jobs: 1,
+        }
+    }
+
+    /// Parse and generate with `registry` instead of the builtin extensions
+    #[must_use]
+    pub fn with_registry(mut self, registry: ExtensionRegistry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Treat `path` as the source of the module message types refer to as
+    /// `name`
+    #[must_use]
+    pub fn message_module(mut self, name: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        self.message_modules.insert(name.into(), path.into());
+        self
+    }
+
+    /// Recompile `protocol` whenever `path` changes
+    ///
+    /// `protocol` is relative to the root.
+    #[must_use]
+    pub fn dependency(mut self, protocol: impl Into<PathBuf>, path: impl Into<PathBuf>) -> Self {
+        self.dependencies
+            .entry(protocol.into())
+            .or_default()
+            .insert(path.into());
+        self
+    }
+
+    /// Generate the upgrade of `protocol` from the version in `previous`
+    ///
+    /// Both paths are relative to the root. The build fails if the versions
+    /// are not adjacent or not compatible.
+    #[must_use]
+    pub fn upgrade(mut self, protocol: impl Into<PathBuf>, previous: impl Into<PathBuf>) -> Self {
+        let (protocol, previous) = (protocol.into(), previous.into());
+        self = self.dependency(protocol.clone(), previous.clone());
+        self.upgrades.insert(protocol, previous);
+        self
+    }
+
+    /// Project and generate the roles of each protocol on up to `jobs`
+    /// threads
+    ///
+    /// Takes effect with the `parallel` feature; protocols with many roles
+    /// benefit most. The output does not depend on `jobs`.
+    #[must_use]
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs.max(1);
+        self
+    }
+
+    /// Output file of `protocol`, a path relative to the root
+    pub fn output_path(&self, protocol: &Path) -> PathBuf {
+        self.out_dir.join(protocol.with_extension("rs"))
+    }
+
+    /// Compile every protocol whose sources changed since the last build
+    ///
+    /// Outputs of protocols that were deleted are removed. The cache is
+    /// written even if a protocol fails to compile, so the protocols compiled
+    /// before it are not compiled again.
+    pub fn build(&self) -> Result<WorkspaceBuild, WorkspaceError> {
+        let mut cache = self.load_cache()?;
+        let mut protocols = Vec::new();
+        find_protocols(&self.root, &mut protocols).map_err(|e| io_error(&self.root, e))?;
+        protocols.sort();
+
+        let mut report = WorkspaceBuild {
+            compiled: Vec::new(),
+            unchanged: Vec::new(),
+            removed: Vec::new(),
+            inputs: vec![self.root.clone()],
+        };
+
+        let present: BTreeSet<PathBuf> = protocols
+            .iter()
+            .filter_map(|path| path.strip_prefix(&self.root).ok())
+            .map(Path::to_path_buf)
+            .collect();
+        let stale: Vec<PathBuf> = cache
+            .entries
+            .keys()
+            .filter(|protocol| !present.contains(*protocol))
+            .cloned()
+            .collect();
+        for protocol in stale {
+            cache.entries.remove(&protocol);
+            let output = self.output_path(&protocol);
+            match fs::remove_file(&output) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(io_error(&output, e)),
+            }
+            report.removed.push(protocol);
+        }
+
+        let mut result = Ok(());
+        for protocol in present {
+            match self.build_protocol(&protocol, &mut cache) {
+                Ok(compiled) => {
+                    if let Some(entry) = cache.entries.get(&protocol) {
+                        report.inputs.extend(
+                            entry
+                                .dependencies
+                                .iter()
+                                .filter(|path| !path.starts_with(&self.root))
+                                .cloned(),
+                        );
+                    }
+                    if compiled {
+                        report.compiled.push(protocol);
+                    } else {
+                        report.unchanged.push(protocol);
+                    }
+                }
+                Err(e) => {
+                    cache.entries.remove(&protocol);
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+
+        self.save_cache(&cache)?;
+        report.inputs.sort();
+        report.inputs.dedup();
+        result.map(|()| report)
+    }
+
+    /// Compile `protocol` unless its cached hash is current, returning
+    /// whether it was compiled
+    fn build_protocol(&self, protocol: &Path, cache: &mut Cache) -> Result<bool, WorkspaceError> {
+        let path = self.root.join(protocol);
+        let source = fs::read_to_string(&path).map_err(|e| io_error(&path, e))?;
+        let output = self.output_path(protocol);
+
+        if let Some(entry) = cache.entries.get(protocol) {
+            if output.exists()
+                && self.hash(&source, &entry.dependencies).ok().as_ref() == Some(&entry.hash)
+            {
+                return Ok(false);
+            }
+        }
+
+        let (choreography, extensions) =
+            parse_choreography_str_with_extensions(&source, &self.registry).map_err(|e| {
+                WorkspaceError::Compile {
+                    path: path.clone(),
+                    source: Box::new(CompilationError::ParseError(e)),
+                }
+            })?;
+        let mut generated = crate::generate_parsed_with_jobs(
+            &source,
+            &choreography,
+            &extensions,
+            &self.registry,
+            self.jobs,
+        )
+        .map_err(|e| WorkspaceError::Compile {
+            path: path.clone(),
+            source: Box::new(e),
+        })?;
+        if let Some(previous) = self.upgrades.get(protocol) {
+            let previous = self.root.join(previous);
+            let previous_source =
+                fs::read_to_string(&previous).map_err(|e| io_error(&previous, e))?;
+            let adapter = generate_upgrade_adapter(&previous_source, &source).map_err(|e| {
+                WorkspaceError::Compile {
+                    path: path.clone(),
+                    source: Box::new(CompilationError::CodegenError(e.to_string())),
+                }
+            })?;
+            generated.push('\n');
+            generated.push_str(&adapter.to_string());
+        }
+
+        let dependencies = self.dependencies_of(protocol, &choreography);
+        let hash = self.hash(&source, &dependencies)?;
+
+        if let Some(parent) = output.parent() {
+            fs::create_dir_all(parent).map_err(|e| io_error(parent, e))?;
+        }
+        fs::write(&output, generated).map_err(|e| io_error(&output, e))?;
+        cache
+            .entries
+            .insert(protocol.to_path_buf(), CacheEntry { hash, dependencies });
+        Ok(true)
+    }
+
+    /// Files other than the source that the output of `protocol` depends on
+    fn dependencies_of(&self, protocol: &Path, choreography: &Choreography) -> Vec<PathBuf> {
+        let mut paths = Vec::new();
+        collect_type_paths(&choreography.protocol, &mut paths);
+
+        let protocol_dir = self
+            .root
+            .join(protocol)
+            .parent()
+            .map_or_else(|| self.root.clone(), Path::to_path_buf);
+        let mut dependencies = BTreeSet::new();
+        for segments in paths {
+            if let Some(module) = self.message_modules.get(&segments[0]) {
+                dependencies.insert(module.clone());
+                continue;
+            }
+            for dir in [&protocol_dir, &self.root] {
+                let mut module_dir = dir.clone();
+                for segment in &segments {
+                    for candidate in [
+                        module_dir.join(format!("{segment}.rs")),
+                        module_dir.join(segment).join("mod.rs"),
+                    ] {
+                        if candidate.is_file() {
+                            dependencies.insert(candidate);
+                        }
+                    }
+                    module_dir.push(segment);
+                }
+            }
+        }
+        if let Some(declared) = self.dependencies.get(protocol) {
+            dependencies.extend(declared.iter().map(|path| self.root.join(path)));
+        }
+        dependencies.into_iter().collect()
+    }
+
+    /// Content hash of a protocol and its dependencies
+    fn hash(&self, source: &str, dependencies: &[PathBuf]) -> Result<String, WorkspaceError> {
+        let mut hasher = Sha256::new();
+        hasher.update(env!("CARGO_PKG_VERSION"));
+        hasher.update(self.registry.grammar_hash().to_le_bytes());
+        hasher.update((source.len() as u64).to_le_bytes());
+        hasher.update(source);
+        for dependency in dependencies {
+            let contents = fs::read(dependency).map_err(|e| io_error(dependency, e))?;
+            hasher.update(dependency.to_string_lossy().as_bytes());
+            hasher.update((contents.len() as u64).to_le_bytes());
+            hasher.update(&contents);
+        }
+        Ok(hex::encode(hasher.finalize()))
+    }
+
+    fn load_cache(&self) -> Result<Cache, WorkspaceError> {
+        let path = self.out_dir.join(CACHE_FILE);
+        match fs::read_to_string(&path) {
+            // An unreadable cache only costs a full build
+            Ok(contents) => Ok(serde_json::from_str(&contents).unwrap_or_default()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Cache::default()),
+            Err(e) => Err(io_error(&path, e)),
+        }
+    }
+
+    fn save_cache(&self, cache: &Cache) -> Result<(), WorkspaceError> {
+        fs::create_dir_all(&self.out_dir).map_err(|e| io_error(&self.out_dir, e))?;
+        let path = self.out_dir.join(CACHE_FILE);
+        let contents = serde_json::to_string_pretty(cache)
+            .map_err(|e| io_error(&path, io::Error::other(e)))?;
+        fs::write(&path, contents).map_err(|e| io_error(&path, e))
+    }
+}
+
+/// Outcome of `Workspace::build`
+///
+/// Protocols are given relative to the root.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WorkspaceBuild {
+    /// Protocols parsed and generated by this build
+    pub compiled: Vec<PathBuf>,
+    /// Protocols whose previous output was kept
+    pub unchanged: Vec<PathBuf>,
+    /// Protocols deleted since the last build, whose output was removed
+    pub removed: Vec<PathBuf>,
+    /// The root and every dependency outside it
+    pub inputs: Vec<PathBuf>,
+}
+
+impl WorkspaceBuild {
+    /// Print `cargo:rerun-if-changed` for every input, from a build script
+    pub fn emit_rerun_if_changed(&self) {
+        for input in &self.inputs {
+            println!("cargo:rerun-if-changed={}", input.display());
+        }
+    }
+}
+
+/// Errors of `Workspace::build`
+#[derive(Debug, thiserror::Error)]
+pub enum WorkspaceError {
+    #[error("{}: {source}", .path.display())]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("{}: {source}", .path.display())]
+    Compile {
+        path: PathBuf,
+        #[source]
+        source: Box<CompilationError>,
+    },
+}
+
+fn io_error(path: &Path, source: io::Error) -> WorkspaceError {
+    WorkspaceError::Io {
+        path: path.to_path_buf(),
+        source,
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Cache {
+    entries: BTreeMap<PathBuf, CacheEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    hash: String,
+    dependencies: Vec<PathBuf>,
+}
+
+/// Collect the `.choreo` files under `dir`
+fn find_protocols(dir: &Path, protocols: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            find_protocols(&path, protocols)?;
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext == PROTOCOL_EXTENSION)
+        {
+            protocols.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Module paths named in message types, without the final segment
+fn collect_type_paths(protocol: &Protocol, paths: &mut Vec<Vec<String>>) {
+    match protocol {
+        Protocol::Send {
+            message,
+            continuation,
+            ..
+        }
+        | Protocol::Broadcast {
+            message,
+            continuation,
+            ..
+        } => {
+            for tokens in [&message.type_annotation, &message.payload]
+                .into_iter()
+                .flatten()
+            {
+                collect_tokens_paths(tokens.clone(), paths);
+            }
+            collect_type_paths(continuation, paths);
+        }
+        Protocol::Choice { branches, .. } => {
+            for branch in branches {
+                collect_type_paths(&branch.protocol, paths);
+            }
+        }
+        Protocol::Loop { body, .. } | Protocol::Rec { body, .. } => {
+            collect_type_paths(body, paths);
+        }
+        Protocol::Parallel { protocols, .. } => {
+            for protocol in protocols {
+                collect_type_paths(protocol, paths);
+            }
+        }
+        Protocol::Extension { continuation, .. } => {
+            collect_type_paths(continuation, paths);
+        }
+        Protocol::Var(_) | Protocol::End => {}
+    }
+}
+
+fn collect_tokens_paths(tokens: TokenStream, paths: &mut Vec<Vec<String>>) {
+    fn finish(segments: &mut Vec<String>, paths: &mut Vec<Vec<String>>) {
+        if segments.len() > 1 {
+            segments.pop();
+            paths.push(std::mem::take(segments));
+        }
+        segments.clear();
+    }
+
+    let mut segments: Vec<String> = Vec::new();
+    let mut colons = 0;
+    for token in tokens {
+        match token {
+            TokenTree::Ident(ident) if segments.is_empty() || colons == 2 => {
+                segments.push(ident.to_string());
+                colons = 0;
+            }
+            TokenTree::Punct(punct) if punct.as_char() == ':' && !segments.is_empty() => {
+                colons += 1;
+            }
+            TokenTree::Group(group) => {
+                finish(&mut segments, paths);
+                colons = 0;
+                collect_tokens_paths(group.stream(), paths);
+            }
+            other => {
+                finish(&mut segments, paths);
+                colons = 0;
+                if let TokenTree::Ident(ident) = other {
+                    segments.push(ident.to_string());
+                }
+            }
+        }
+    }
+    finish(&mut segments, paths);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+
+    fn ping(message: &str) -> String {
+        format!(
+            "choreography Ping {{\n    roles: Client, Server\n    Client -> Server: {message}\n}}\n"
+        )
+    }
+
+    #[test]
+    fn test_only_changed_protocols_are_compiled() {
+        let root = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        write(&root.path().join("ping.choreo"), &ping("Ping"));
+        write(
+            &root.path().join("orders/checkout.choreo"),
+            &ping("Order<messages::Order>"),
+        );
+        write(&root.path().join("messages.rs"), "pub struct Order;");
+        let workspace = Workspace::new(root.path(), out.path());
+
+        let first = workspace.build().unwrap();
+        assert_eq!(first.compiled.len(), 2);
+        assert!(out.path().join("orders/checkout.rs").is_file());
+
+        let second = workspace.build().unwrap();
+        assert!(second.compiled.is_empty());
+        assert_eq!(second.unchanged.len(), 2);
+
+        write(&root.path().join("ping.choreo"), &ping("Pong"));
+        let third = workspace.build().unwrap();
+        assert_eq!(third.compiled, vec![PathBuf::from("ping.choreo")]);
+
+        write(&root.path().join("messages.rs"), "pub struct Order(u64);");
+        let fourth = workspace.build().unwrap();
+        assert_eq!(
+            fourth.compiled,
+            vec![PathBuf::from("orders/checkout.choreo")]
+        );
+
+        fs::remove_file(root.path().join("ping.choreo")).unwrap();
+        let fifth = workspace.build().unwrap();
+        assert_eq!(fifth.removed, vec![PathBuf::from("ping.choreo")]);
+        assert!(!out.path().join("ping.rs").exists());
+    }
+
+    #[test]
+    fn test_failed_protocol_is_reported_and_retried() {
+        let root = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        write(&root.path().join("a.choreo"), &ping("Ping"));
+        write(&root.path().join("b.choreo"), "choreography Broken {");
+        let workspace = Workspace::new(root.path(), out.path());
+
+        let err = workspace.build().unwrap_err();
+        assert!(
+            matches!(err, WorkspaceError::Compile { ref path, .. } if path.ends_with("b.choreo"))
+        );
+
+        write(&root.path().join("b.choreo"), &ping("Pong"));
+        let build = workspace.build().unwrap();
+        assert_eq!(build.compiled, vec![PathBuf::from("b.choreo")]);
+        assert_eq!(build.unchanged, vec![PathBuf::from("a.choreo")]);
+    }
+
+    #[test]
+    fn test_declared_dependencies() {
+        let root = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let shared = tempfile::tempdir().unwrap();
+        write(&root.path().join("ping.choreo"), &ping("Ping<wire::Ping>"));
+        write(&shared.path().join("wire.rs"), "pub struct Ping;");
+        write(&root.path().join("notes.txt"), "v1");
+        let workspace = Workspace::new(root.path(), out.path())
+            .message_module("wire", shared.path().join("wire.rs"))
+            .dependency("ping.choreo", "notes.txt");
+
+        let first = workspace.build().unwrap();
+        assert!(first.inputs.contains(&shared.path().join("wire.rs")));
+
+        write(&shared.path().join("wire.rs"), "pub struct Ping(u8);");
+        assert_eq!(workspace.build().unwrap().compiled.len(), 1);
+        write(&root.path().join("notes.txt"), "v2");
+        assert_eq!(workspace.build().unwrap().compiled.len(), 1);
+        assert!(workspace.build().unwrap().compiled.is_empty());
+    }
+
+    #[test]
+    fn test_upgrade_from_previous_version() {
+        let root = tempfile::tempdir().unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let version = |version: u32, message: &str| {
+            format!("@protocol(version = {version})\n{}", ping(message))
+        };
+        write(&root.path().join("v1/ping.choreo"), &version(1, "Ping"));
+        write(&root.path().join("ping.choreo"), &version(2, "Ping"));
+        let workspace =
+            Workspace::new(root.path(), out.path()).upgrade("ping.choreo", "v1/ping.choreo");
+
+        workspace.build().unwrap();
+        let generated = fs::read_to_string(out.path().join("ping.rs")).unwrap();
+        assert!(generated.contains("PING_UPGRADE_FROM_V1"));
+
+        write(&root.path().join("v1/ping.choreo"), &version(1, "Pong"));
+        let err = workspace.build().unwrap_err();
+        assert!(
+            matches!(err, WorkspaceError::Compile { ref path, .. } if path.ends_with("ping.choreo"))
+        );
+    }
+
+    #[test]
+    fn test_type_paths() {
+        let mut paths = Vec::new();
+        let tokens: TokenStream = "a::b::C, Vec<d::E>, F, ::g::H".parse().unwrap();
+        collect_tokens_paths(tokens, &mut paths);
+        assert_eq!(
+            paths,
+            vec![
+                vec!["a".to_string(), "b".to_string()],
+                vec!["d".to_string()],
+                vec!["g".to_string()],
+            ]
+        );
+    }
+}
@@ -4,7 +4,7 @@
 // that can be analyzed, transformed, and interpreted separately from execution.
 
 use crate::effects::extension::ExtensionEffect;
-use crate::effects::{Label, RoleId};
+use crate::effects::{Label, RoleId, SessionError, SessionResult};
 use std::any::TypeId;
 use std::collections::HashSet;
 use std::time::Duration;
@@ -489,6 +489,23 @@ pub struct InterpretResult<M> {
 
     /// Final state of the interpreter
     pub final_state: InterpreterState,
+
+    /// Why the program failed or timed out, and where
+    pub error: Option<SessionError>,
+}
+
+impl<M> InterpretResult<M> {
+    /// The result, or the error if the program did not complete
+    ///
+    /// # Errors
+    ///
+    /// The error that stopped the program.
+    pub fn into_result(self) -> SessionResult<Self> {
+        match self.error {
+            Some(error) if self.final_state != InterpreterState::Completed => Err(error),
+            _ => Ok(self),
+        }
+    }
 }
 
 /// State of the program interpreter
@@ -29,6 +29,7 @@
 //! ```
 
 use async_trait::async_trait;
+use bytes::Bytes;
 use serde::{de::DeserializeOwned, Serialize};
 use std::fmt::Debug;
 use std::time::Duration;
@@ -50,6 +51,25 @@ impl<T: Copy + Eq + std::hash::Hash + Debug + Send + Sync + 'static> RoleId for
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
 pub struct Label(pub &'static str);
 
+impl Label {
+    /// The label among `labels` named `name`, for a branch name received at
+    /// runtime
+    ///
+    /// Names outside of `labels` are protocol violations.
+    pub fn resolve(name: &str, labels: &[Label]) -> Result<Self> {
+        labels
+            .iter()
+            .copied()
+            .find(|label| label.0 == name)
+            .ok_or_else(|| {
+                ChoreographyError::ProtocolViolation(format!(
+                    "unexpected branch {name:?}, expected one of {:?}",
+                    labels.iter().map(|label| label.0).collect::<Vec<_>>()
+                ))
+            })
+    }
+}
+
 /// Session endpoint trait
 ///
 /// Represents the runtime-specific connection state (e.g., Rumpsteak channel bundle).
@@ -79,6 +99,54 @@ pub enum ChoreographyError {
     /// Referenced role not found in the choreography
     #[error("Role {0:?} not found in this choreography")]
     UnknownRole(String),
+
+    /// Serialized message exceeds the configured size limit
+    #[error("Message of {size} bytes exceeds limit of {max} bytes")]
+    MessageTooLarge { size: usize, max: usize },
+
+    /// Session was cancelled locally or by a peer
+    #[error("Session cancelled")]
+    Cancelled,
+
+    /// Audit journal could not record a step
+    #[error("Journal error: {0}")]
+    Journal(String),
+
+    /// Outbox store could not persist or acknowledge a send
+    #[error("Outbox error: {0}")]
+    Outbox(String),
+
+    /// Decision script could not pick a branch
+    #[error("Decision script failed: {0}")]
+    Script(String),
+
+    /// Role lacks the capability required by a guarded step
+    #[error("Guard denied: {0}")]
+    GuardDenied(#[from] crate::runtime::guard::GuardDenied),
+
+    /// Flow budget cannot cover the next send, so the session was aborted
+    #[error("Flow budget exceeded: {0}")]
+    BudgetExceeded(#[from] crate::runtime::flow::FlowBudgetExceeded),
+
+    /// Peer crashed or was declared failed, so it will not take part further
+    #[error("Peer failed: {0}")]
+    PeerFailed(String),
+
+    /// Role instances could not be resolved to addresses
+    #[error("Addressing failed: {0}")]
+    Address(#[from] crate::runtime::address_book::AddressBookError),
+}
+
+impl ChoreographyError {
+    /// Whether the error means the peer could not be reached, so that an
+    /// `or on failure` send takes its recovery branch
+    #[must_use]
+    pub fn is_peer_failure(&self) -> bool {
+        matches!(
+            self,
+            Self::Transport(_) | Self::Timeout(_) | Self::PeerFailed(_)
+        )
+    }
 }
 
 /// Result type for choreography operations
@@ -139,6 +207,36 @@ pub trait ChoreoHandler: Send {
         from: Self::Role,
     ) -> Result<M>;
 
+    /// Send a message the choreography names `label`
+    ///
+    /// Generated code sends through this method so that middleware keyed on
+    /// message names, such as guards, journals and flow charges, matches the
+    /// name in the protocol rather than the Rust type that reaches it, which
+    /// framing middleware replaces with its frame. Middleware forwards
+    /// `label` to the handler it wraps; the default implementation drops it.
+    async fn send_labelled<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        label: &str,
+        msg: &M,
+    ) -> Result<()> {
+        let _ = label;
+        self.send(ep, to, msg).await
+    }
+
+    /// Receive a message the choreography names `label`, see
+    /// [`send_labelled`](ChoreoHandler::send_labelled)
+    async fn recv_labelled<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        label: &str,
+    ) -> Result<M> {
+        let _ = label;
+        self.recv(ep, from).await
+    }
+
     /// Internal choice: broadcast a label selection
     ///
     /// Used by the choosing role to inform others of the selected branch.
@@ -163,11 +261,19 @@ pub trait ChoreoHandler: Send {
     ///
     /// * `ep` - The session endpoint
     /// * `from` - The role that made the choice
+    /// * `labels` - The labels of the branches offered at this step
     ///
     /// # Returns
     ///
-    /// The label selected by the choosing role
-    async fn offer(&mut self, ep: &mut Self::Endpoint, from: Self::Role) -> Result<Label>;
+    /// The label selected by the choosing role. Handlers that receive it by
+    /// name resolve it against `labels` with [`Label::resolve`], so a name
+    /// outside of them is a protocol violation.
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label>;
 
     /// Execute a future with a timeout
     ///
@@ -206,6 +312,42 @@ pub trait ChoreoHandler: Send {
         Ok(())
     }
 
+    /// Send a buffer to a specific role as it is
+    ///
+    /// For large payloads and the chunks of streamed messages. The default
+    /// implementation sends the buffer like any message; handlers that pass
+    /// frames between roles in one process override it to hand over the
+    /// reference-counted buffer without copying it.
+    async fn send_bytes(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        payload: Bytes,
+    ) -> Result<()> {
+        self.send(ep, to, &payload).await
+    }
+
+    /// Receive a buffer sent with [`send_bytes`](ChoreoHandler::send_bytes)
+    async fn recv_bytes(&mut self, ep: &mut Self::Endpoint, from: Self::Role) -> Result<Bytes> {
+        self.recv(ep, from).await
+    }
+
+    /// Receive the first message to arrive from any of `from`, with its sender
+    ///
+    /// Used to gather a quorum without waiting on slow peers. The default
+    /// implementation receives from the first role of `from`, so a gather
+    /// over a handler that does not override it takes messages in order.
+    async fn recv_any<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: &[Self::Role],
+    ) -> Result<(Self::Role, M)> {
+        let &sender = from.first().ok_or_else(|| {
+            ChoreographyError::ProtocolViolation("receive from an empty set of roles".into())
+        })?;
+        Ok((sender, self.recv(ep, sender).await?))
+    }
+
     /// Send messages to multiple recipients in parallel
     ///
     /// Default implementation sends sequentially. Override for true parallelism.
@@ -306,7 +448,12 @@ impl<R: RoleId + 'static> ChoreoHandler for NoOpHandler<R> {
         Ok(())
     }
 
-    async fn offer(&mut self, _ep: &mut Self::Endpoint, _from: Self::Role) -> Result<Label> {
+    async fn offer(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        _from: Self::Role,
+        _labels: &[Label],
+    ) -> Result<Label> {
         Err(ChoreographyError::Transport(
             "NoOpHandler cannot offer".into(),
         ))
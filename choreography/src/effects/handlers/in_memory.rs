@@ -171,7 +171,12 @@ impl<R: RoleId + 'static> ChoreoHandler for InMemoryHandler<R> {
         Ok(())
     }
 
-    async fn offer(&mut self, _ep: &mut Self::Endpoint, from: Self::Role) -> Result<Label> {
+    async fn offer(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        from: Self::Role,
+        _labels: &[Label],
+    ) -> Result<Label> {
         tracing::trace!(?from, "InMemoryHandler: waiting for choice");
 
         // Get the choice receiver for choices from 'from' to 'self.role'
@@ -213,31 +218,7 @@ impl<R: RoleId + 'static> ChoreoHandler for InMemoryHandler<R> {
         F: std::future::Future<Output = Result<T>> + Send,
     {
         if at == self.role {
-            // Platform-specific timeout implementation
-            #[cfg(not(target_arch = "wasm32"))]
-            {
-                match tokio::time::timeout(dur, body).await {
-                    Ok(result) => result,
-                    Err(_) => Err(ChoreographyError::Timeout(dur)),
-                }
-            }
-
-            #[cfg(target_arch = "wasm32")]
-            {
-                // Use wasm_timer for WASM compatibility
-                use futures::future::{select, Either};
-                use futures::pin_mut;
-                use wasm_timer::Delay;
-
-                let timeout = Delay::new(dur);
-                pin_mut!(body);
-                pin_mut!(timeout);
-
-                match select(body, timeout).await {
-                    Either::Left((result, _)) => result,
-                    Either::Right(_) => Err(ChoreographyError::Timeout(dur)),
-                }
-            }
+            crate::runtime::timeout(dur, body).await
         } else {
             body.await
         }
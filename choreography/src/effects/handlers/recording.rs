@@ -100,7 +100,12 @@ impl<R: RoleId + 'static> ChoreoHandler for RecordingHandler<R> {
         Ok(())
     }
 
-    async fn offer(&mut self, _ep: &mut Self::Endpoint, from: Self::Role) -> Result<Label> {
+    async fn offer(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        from: Self::Role,
+        _labels: &[Label],
+    ) -> Result<Label> {
         self.events
             .lock()
             .unwrap_or_else(std::sync::PoisonError::into_inner)
@@ -14,11 +14,15 @@
 // - RumpsteakHandler: implements ChoreoHandler over either transport.
 
 use async_trait::async_trait;
-use futures::{channel::mpsc, future::BoxFuture, SinkExt, StreamExt};
+use futures::{
+    channel::mpsc,
+    future::{select_all, BoxFuture},
+    SinkExt, StreamExt,
+};
 use serde::{de::DeserializeOwned, Serialize};
 use std::{collections::HashMap, fmt::Debug, marker::PhantomData, time::Duration};
 
-use crate::effects::{ChoreoHandler, ChoreographyError, Label, Result, RoleId};
+use crate::effects::{ChoreoHandler, ChoreographyError, Label, Result, RoleId, SessionPolicy};
 use rumpsteak_aura::{
     channel::{Bidirectional, Pair},
     Message, Role,
@@ -117,6 +121,10 @@ pub trait SessionTypeDynamic: Send {
     }
 
     /// Receive a serialized message.
+    ///
+    /// `recv_any` and deadlines drop receives that have not completed, so
+    /// dropping the returned future must keep whatever part of a message it
+    /// has read for the next receive.
     fn recv(&mut self) -> BoxFuture<'_, Result<SessionUpdate<Vec<u8>>>> {
         unsupported("recv", self.type_name())
     }
@@ -127,6 +135,9 @@ pub trait SessionTypeDynamic: Send {
     }
 
     /// Offer a branch selection.
+    ///
+    /// Like [`recv`](Self::recv), dropping the future must not lose a
+    /// partly read label.
     fn offer(&mut self) -> BoxFuture<'_, Result<SessionUpdate<String>>> {
         unsupported("offer", self.type_name())
     }
@@ -348,11 +359,81 @@ enum ChannelState {
     Session(RumpsteakSession),
 }
 
+impl ChannelState {
+    async fn send(&mut self, data: Vec<u8>) -> Result<SessionUpdate<()>> {
+        match self {
+            ChannelState::Simple(channel) => {
+                channel.send(data).await.map_err(|e| {
+                    ChoreographyError::Transport(format!("SimpleChannel send failed: {e}"))
+                })?;
+                Ok(SessionUpdate::new(()))
+            }
+            ChannelState::Session(session) => session.send(data).await,
+        }
+    }
+
+    async fn recv(&mut self) -> Result<SessionUpdate<Vec<u8>>> {
+        match self {
+            ChannelState::Simple(channel) => {
+                let bytes = channel.recv().await.map_err(|e| {
+                    ChoreographyError::Transport(format!("SimpleChannel recv failed: {e}"))
+                })?;
+                Ok(SessionUpdate::new(bytes))
+            }
+            ChannelState::Session(session) => session.recv().await,
+        }
+    }
+
+    async fn choose(&mut self, label: &str) -> Result<SessionUpdate<()>> {
+        match self {
+            ChannelState::Simple(channel) => {
+                let serialized = bincode::serialize(label).map_err(|e| {
+                    ChoreographyError::Transport(format!("Label serialization failed: {e}"))
+                })?;
+                channel.send(serialized).await.map_err(|e| {
+                    ChoreographyError::Transport(format!("Choice send failed: {e}"))
+                })?;
+                Ok(SessionUpdate::new(()))
+            }
+            ChannelState::Session(session) => session.choose(label).await,
+        }
+    }
+
+    async fn offer(&mut self) -> Result<SessionUpdate<String>> {
+        match self {
+            ChannelState::Simple(channel) => {
+                let serialized = channel.recv().await.map_err(|e| {
+                    ChoreographyError::Transport(format!("Choice receive failed: {e}"))
+                })?;
+                let label: String = bincode::deserialize(&serialized).map_err(|e| {
+                    ChoreographyError::Transport(format!("Label deserialization failed: {e}"))
+                })?;
+                Ok(SessionUpdate::new(label))
+            }
+            ChannelState::Session(session) => session.offer().await,
+        }
+    }
+}
+
 struct ChannelRecord {
     state: ChannelState,
     metadata: SessionMetadata,
 }
 
+impl ChannelRecord {
+    /// Fold a completed operation into the peer's metadata.
+    fn apply<T>(&mut self, update: SessionUpdate<T>, default_description: &str) -> T {
+        self.metadata.operation_count += 1;
+        self.metadata.state_description = update
+            .description
+            .unwrap_or_else(|| default_description.to_string());
+        if update.is_complete {
+            self.metadata.is_complete = true;
+        }
+        update.output
+    }
+}
+
 /// Endpoint that manages per-peer channels/sessions plus metadata.
 pub struct RumpsteakEndpoint<R>
 where
@@ -360,6 +441,7 @@ where
 {
     local_role: R,
     channels: HashMap<R, ChannelRecord>,
+    policy: SessionPolicy,
 }
 
 impl<R> RumpsteakEndpoint<R>
@@ -367,12 +449,26 @@ where
     R: Role + Eq + std::hash::Hash + Clone + Debug,
 {
     pub fn new(local_role: R) -> Self {
+        Self::with_policy(local_role, SessionPolicy::default())
+    }
+
+    /// Create an endpoint whose operations follow `policy`.
+    pub fn with_policy(local_role: R, policy: SessionPolicy) -> Self {
         Self {
             local_role,
             channels: HashMap::new(),
+            policy,
         }
     }
 
+    pub fn policy(&self) -> &SessionPolicy {
+        &self.policy
+    }
+
+    pub fn set_policy(&mut self, policy: SessionPolicy) {
+        self.policy = policy;
+    }
+
     /// Register a legacy `SimpleChannel` for a peer.
     pub fn register_channel(&mut self, peer: R, channel: SimpleChannel) {
         tracing::debug!(?peer, "Registering SimpleChannel session");
@@ -397,12 +493,10 @@ where
         );
     }
 
-    fn take_record(&mut self, peer: &R) -> Option<ChannelRecord> {
-        self.channels.remove(peer)
-    }
-
-    fn put_record(&mut self, peer: R, record: ChannelRecord) {
-        self.channels.insert(peer, record);
+    fn record_mut(&mut self, peer: &R) -> Result<&mut ChannelRecord> {
+        self.channels.get_mut(peer).ok_or_else(|| {
+            ChoreographyError::Transport(format!("No channel registered for peer: {peer:?}"))
+        })
     }
 
     pub fn has_channel(&self, peer: &R) -> bool {
@@ -471,33 +565,6 @@ where
             _phantom: PhantomData,
         }
     }
-
-    async fn with_channel_operation<T, F, Fut>(
-        ep: &mut RumpsteakEndpoint<R>,
-        peer: &R,
-        default_description: &str,
-        f: F,
-    ) -> Result<T>
-    where
-        F: FnOnce(ChannelState) -> Fut,
-        Fut: std::future::Future<Output = Result<(T, ChannelState, Option<String>, bool)>>,
-    {
-        let mut record = ep.take_record(peer).ok_or_else(|| {
-            ChoreographyError::Transport(format!("No channel registered for peer: {peer:?}"))
-        })?;
-
-        let (result, next_state, description, completed) = f(record.state).await?;
-        record.state = next_state;
-        record.metadata.operation_count += 1;
-        record.metadata.state_description =
-            description.unwrap_or_else(|| default_description.to_string());
-        if completed {
-            record.metadata.is_complete = true;
-        }
-
-        ep.put_record(peer.clone(), record);
-        Ok(result)
-    }
 }
 
 impl<R, M> Default for RumpsteakHandler<R, M>
@@ -526,27 +593,17 @@ where
     ) -> Result<()> {
         let serialized = bincode::serialize(msg)
             .map_err(|e| ChoreographyError::Transport(format!("Serialization failed: {e}")))?;
+        ep.policy.check_size(serialized.len())?;
 
-        Self::with_channel_operation(ep, &to, "Send", |state| async move {
-            match state {
-                ChannelState::Simple(mut channel) => {
-                    channel.send(serialized).await.map_err(|e| {
-                        ChoreographyError::Transport(format!("SimpleChannel send failed: {e}"))
-                    })?;
-                    Ok(((), ChannelState::Simple(channel), None, false))
-                }
-                ChannelState::Session(mut session) => {
-                    let update = session.send(serialized).await?;
-                    Ok((
-                        (),
-                        ChannelState::Session(session),
-                        update.description,
-                        update.is_complete,
-                    ))
-                }
-            }
-        })
-        .await
+        let policy = ep.policy.clone();
+        let record = ep.record_mut(&to)?;
+        let update = policy
+            .run_with_retry(&mut record.state, |state| {
+                Box::pin(state.send(serialized.clone()))
+            })
+            .await?;
+        record.apply(update, "Send");
+        Ok(())
     }
 
     async fn recv<Msg: DeserializeOwned + Send>(
@@ -554,32 +611,55 @@ where
         ep: &mut Self::Endpoint,
         from: Self::Role,
     ) -> Result<Msg> {
-        Self::with_channel_operation(ep, &from, "Recv", |state| async move {
-            match state {
-                ChannelState::Simple(mut channel) => {
-                    let serialized = channel.recv().await.map_err(|e| {
-                        ChoreographyError::Transport(format!("SimpleChannel recv failed: {e}"))
-                    })?;
-                    let msg = bincode::deserialize(&serialized).map_err(|e| {
-                        ChoreographyError::Transport(format!("Deserialization failed: {e}"))
-                    })?;
-                    Ok((msg, ChannelState::Simple(channel), None, false))
-                }
-                ChannelState::Session(mut session) => {
-                    let update = session.recv().await?;
-                    let msg = bincode::deserialize(&update.output).map_err(|e| {
-                        ChoreographyError::Transport(format!("Deserialization failed: {e}"))
-                    })?;
-                    Ok((
-                        msg,
-                        ChannelState::Session(session),
-                        update.description,
-                        update.is_complete,
-                    ))
-                }
-            }
-        })
-        .await
+        let policy = ep.policy.clone();
+        let record = ep.record_mut(&from)?;
+        let update = policy.run(record.state.recv()).await?;
+        let serialized = record.apply(update, "Recv");
+        policy.check_size(serialized.len())?;
+        bincode::deserialize(&serialized)
+            .map_err(|e| ChoreographyError::Transport(format!("Deserialization failed: {e}")))
+    }
+
+    async fn recv_any<Msg: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: &[Self::Role],
+    ) -> Result<(Self::Role, Msg)> {
+        if from.is_empty() {
+            return Err(ChoreographyError::ProtocolViolation(
+                "receive from an empty set of roles".into(),
+            ));
+        }
+        for peer in from {
+            ep.record_mut(peer)?;
+        }
+
+        // A receive only takes the next message off its channel, so the
+        // receives that lose the race leave their channels untouched
+        let policy = ep.policy.clone();
+        let receives: Vec<_> = ep
+            .channels
+            .iter_mut()
+            .filter(|(peer, _)| from.contains(peer))
+            .map(|(peer, record)| {
+                Box::pin(async move {
+                    let update = record.state.recv().await;
+                    (*peer, record, update)
+                })
+            })
+            .collect();
+        let (sender, record, update) = policy
+            .run(async move {
+                let ((sender, record, update), _, _) = select_all(receives).await;
+                Ok((sender, record, update?))
+            })
+            .await?;
+
+        let serialized = record.apply(update, "Recv");
+        policy.check_size(serialized.len())?;
+        let msg = bincode::deserialize(&serialized)
+            .map_err(|e| ChoreographyError::Transport(format!("Deserialization failed: {e}")))?;
+        Ok((sender, msg))
     }
 
     async fn choose(
@@ -588,58 +668,26 @@ where
         who: Self::Role,
         label: Label,
     ) -> Result<()> {
-        let label_str = label.0.to_string();
-        Self::with_channel_operation(ep, &who, "Choose", |state| async move {
-            match state {
-                ChannelState::Simple(mut channel) => {
-                    let serialized = bincode::serialize(&label_str).map_err(|e| {
-                        ChoreographyError::Transport(format!("Label serialization failed: {e}"))
-                    })?;
-                    channel.send(serialized).await.map_err(|e| {
-                        ChoreographyError::Transport(format!("Choice send failed: {e}"))
-                    })?;
-                    Ok(((), ChannelState::Simple(channel), None, false))
-                }
-                ChannelState::Session(mut session) => {
-                    let update = session.choose(&label_str).await?;
-                    Ok((
-                        (),
-                        ChannelState::Session(session),
-                        update.description,
-                        update.is_complete,
-                    ))
-                }
-            }
-        })
-        .await
-    }
-
-    async fn offer(&mut self, ep: &mut Self::Endpoint, from: Self::Role) -> Result<Label> {
-        Self::with_channel_operation(ep, &from, "Offer", |state| async move {
-            match state {
-                ChannelState::Simple(mut channel) => {
-                    let serialized = channel.recv().await.map_err(|e| {
-                        ChoreographyError::Transport(format!("Choice receive failed: {e}"))
-                    })?;
-                    let label_string: String = bincode::deserialize(&serialized).map_err(|e| {
-                        ChoreographyError::Transport(format!("Label deserialization failed: {e}"))
-                    })?;
-                    let leaked: &'static str = Box::leak(label_string.into_boxed_str());
-                    Ok((Label(leaked), ChannelState::Simple(channel), None, false))
-                }
-                ChannelState::Session(mut session) => {
-                    let update = session.offer().await?;
-                    let leaked: &'static str = Box::leak(update.output.into_boxed_str());
-                    Ok((
-                        Label(leaked),
-                        ChannelState::Session(session),
-                        update.description,
-                        update.is_complete,
-                    ))
-                }
-            }
-        })
-        .await
+        let policy = ep.policy.clone();
+        let record = ep.record_mut(&who)?;
+        let update = policy
+            .run_with_retry(&mut record.state, |state| Box::pin(state.choose(label.0)))
+            .await?;
+        record.apply(update, "Choose");
+        Ok(())
+    }
+
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        let policy = ep.policy.clone();
+        let record = ep.record_mut(&from)?;
+        let update = policy.run(record.state.offer()).await?;
+        let label = record.apply(update, "Offer");
+        Label::resolve(&label, labels)
     }
 
     async fn with_timeout<F, T>(
@@ -652,28 +700,6 @@ where
     where
         F: std::future::Future<Output = Result<T>> + Send,
     {
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            match tokio::time::timeout(dur, body).await {
-                Ok(result) => result,
-                Err(_) => Err(ChoreographyError::Timeout(dur)),
-            }
-        }
-
-        #[cfg(target_arch = "wasm32")]
-        {
-            use futures::future::{select, Either};
-            use futures::pin_mut;
-            use wasm_timer::Delay;
-
-            let timeout = Delay::new(dur);
-            pin_mut!(body);
-            pin_mut!(timeout);
-
-            match select(body, timeout).await {
-                Either::Left((result, _)) => result,
-                Either::Right(_) => Err(ChoreographyError::Timeout(dur)),
-            }
-        }
+        crate::runtime::timeout(dur, body).await
     }
 }
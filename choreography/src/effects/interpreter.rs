@@ -10,8 +10,11 @@ use std::any::TypeId;
 use std::collections::HashMap;
 
 use crate::effects::algebra::{Effect, InterpretResult, InterpreterState, Program, ProgramMessage};
+use crate::effects::middleware::type_label;
 use crate::effects::registry::ExtensibleHandler;
-use crate::effects::{ChoreoHandler, ChoreographyError, Result, RoleId};
+use crate::effects::{
+    ChoreoHandler, ChoreographyError, Result, RoleId, SessionContext, SessionError, SessionResult,
+};
 
 /// Interpret a choreographic program using a concrete handler
 pub async fn interpret<H, R, M>(
@@ -55,6 +58,8 @@ struct Interpreter<M> {
     type_registry: HashMap<TypeId, String>,
     /// Track the last received label from an Offer effect
     last_label: Option<crate::effects::Label>,
+    /// Labels of the Branch effect following the current Offer effect
+    offered_labels: Vec<crate::effects::Label>,
 }
 
 impl<M> Interpreter<M> {
@@ -63,6 +68,7 @@ impl<M> Interpreter<M> {
             received_values: Vec::new(),
             type_registry: HashMap::new(),
             last_label: None,
+            offered_labels: Vec::new(),
         }
     }
 
@@ -78,37 +84,57 @@ impl<M> Interpreter<M> {
         R: RoleId,
         M: ProgramMessage + Serialize + DeserializeOwned + 'static,
     {
-        for effect in program.effects {
-            match self.execute_effect(handler, endpoint, effect).await {
-                Ok(()) => continue,
-                Err(ChoreographyError::Timeout(_)) => {
-                    return Ok(InterpretResult {
-                        received_values: self.received_values.clone(),
-                        final_state: InterpreterState::Timeout,
-                    });
-                }
-                Err(e) => {
-                    return Ok(InterpretResult {
-                        received_values: self.received_values.clone(),
-                        final_state: InterpreterState::Failed(e.to_string()),
-                    });
-                }
+        let mut effects = program.effects.into_iter().peekable();
+        while let Some(effect) = effects.next() {
+            self.expect_branches(&effect, effects.peek());
+            if let Err(error) = self.execute_effect(handler, endpoint, effect).await {
+                return Ok(stopped(self.received_values.clone(), error));
             }
         }
 
         Ok(InterpretResult {
             received_values: self.received_values.clone(),
             final_state: InterpreterState::Completed,
+            error: None,
         })
     }
 
+    /// Remember the labels of `next` if `effect` is the offer selecting one
+    /// of its branches
+    fn expect_branches<R: RoleId>(&mut self, effect: &Effect<R, M>, next: Option<&Effect<R, M>>) {
+        if let (Effect::Offer { .. }, Some(Effect::Branch { branches, .. })) = (effect, next) {
+            self.offered_labels = branches.iter().map(|(label, _)| *label).collect();
+        }
+    }
+
+    /// Run a nested program, failing with the error that stopped it
+    async fn run_nested<H, R>(
+        &mut self,
+        handler: &mut H,
+        endpoint: &mut H::Endpoint,
+        program: Program<R, M>,
+    ) -> SessionResult<()>
+    where
+        H: ChoreoHandler<Role = R> + Send,
+        R: RoleId,
+        M: ProgramMessage + Serialize + DeserializeOwned + 'static,
+    {
+        let mut result = self
+            .run(handler, endpoint, program)
+            .await
+            .map_err(SessionError::from)?;
+        self.received_values
+            .extend(std::mem::take(&mut result.received_values));
+        result.into_result().map(drop)
+    }
+
     #[async_recursion]
     async fn execute_effect<H, R>(
         &mut self,
         handler: &mut H,
         endpoint: &mut H::Endpoint,
         effect: Effect<R, M>,
-    ) -> Result<()>
+    ) -> SessionResult<()>
     where
         H: ChoreoHandler<Role = R> + Send,
         R: RoleId,
@@ -116,7 +142,14 @@ impl<M> Interpreter<M> {
     {
         match effect {
             Effect::Send { to, msg } => {
-                handler.send(endpoint, to, &msg).await?;
+                let name = message_name(&msg);
+                handler
+                    .send_labelled(endpoint, to, &name, &msg)
+                    .await
+                    .map_err(|e| {
+                        let context = step("send to", to).expecting([name]);
+                        SessionError::new(&e, context)
+                    })?;
             }
 
             Effect::Recv { from, msg_type } => {
@@ -125,25 +158,32 @@ impl<M> Interpreter<M> {
                 tracing::debug!(?from, ?msg_type, "recv effect - type casting required");
 
                 // Attempt to receive as the expected type M
-                match self
-                    .try_recv_as_type::<H, R, M>(handler, endpoint, from)
+                let value = self
+                    .try_recv_as_type::<H, R, M>(handler, endpoint, from, type_label(msg_type))
                     .await
-                {
-                    Ok(value) => {
-                        self.received_values.push(value);
-                    }
-                    Err(e) => return Err(e),
-                }
+                    .map_err(|e| {
+                        let context = step("receive from", from).expecting([type_label(msg_type)]);
+                        SessionError::new(&e, context)
+                    })?;
+                self.received_values.push(value);
             }
 
             Effect::Choose { at, label } => {
-                handler.choose(endpoint, at, label).await?;
+                handler.choose(endpoint, at, label).await.map_err(|e| {
+                    SessionError::new(&e, step("select to", at).expecting([label.0]))
+                })?;
                 // Store the chosen label for subsequent Branch effects
                 self.last_label = Some(label);
             }
 
             Effect::Offer { from } => {
-                let label = handler.offer(endpoint, from).await?;
+                let labels = std::mem::take(&mut self.offered_labels);
+                let label = handler.offer(endpoint, from, &labels).await.map_err(|e| {
+                    SessionError::new(
+                        &e,
+                        step("branch from", from).expecting(labels.iter().map(|label| label.0)),
+                    )
+                })?;
                 // Store the received label for control flow decisions in subsequent Branch effects
                 tracing::debug!(?from, ?label, "Received offer label");
                 self.last_label = Some(label);
@@ -161,11 +201,16 @@ impl<M> Interpreter<M> {
                     branch_count = branches.len(),
                     "Executing branch effect"
                 );
+                let context = step("branch from", choosing_role)
+                    .expecting(branches.iter().map(|(label, _)| label.0));
 
                 // Get the label from the last Choose/Offer effect
                 let label = self.last_label.ok_or_else(|| {
-                    ChoreographyError::ProtocolViolation(
-                        "Branch effect requires a preceding Choose or Offer effect".to_string(),
+                    SessionError::new(
+                        &ChoreographyError::ProtocolViolation(
+                            "Branch effect requires a preceding Choose or Offer effect".to_string(),
+                        ),
+                        context.clone(),
                     )
                 })?;
 
@@ -174,35 +219,22 @@ impl<M> Interpreter<M> {
                     .iter()
                     .find(|(branch_label, _)| branch_label == &label)
                     .ok_or_else(|| {
-                        ChoreographyError::ProtocolViolation(format!(
-                            "No branch found for label {label:?}"
-                        ))
+                        SessionError::new(
+                            &ChoreographyError::ProtocolViolation(format!(
+                                "No branch found for label {label:?}"
+                            )),
+                            context.received(label.0),
+                        )
                     })?;
 
                 tracing::debug!(selected_label = ?label, "Executing selected branch");
 
                 // Execute the selected branch
-                let result = self
-                    .run(handler, endpoint, selected_branch.1.clone())
+                self.run_nested(handler, endpoint, selected_branch.1.clone())
                     .await?;
-                self.received_values.extend(result.received_values);
 
                 // Clear the label after use
                 self.last_label = None;
-
-                if !matches!(result.final_state, InterpreterState::Completed) {
-                    match result.final_state {
-                        InterpreterState::Failed(msg) => {
-                            return Err(ChoreographyError::Transport(msg));
-                        }
-                        InterpreterState::Timeout => {
-                            return Err(ChoreographyError::Timeout(
-                                std::time::Duration::from_secs(0),
-                            ));
-                        }
-                        InterpreterState::Completed => {}
-                    }
-                }
             }
 
             Effect::Loop { iterations, body } => {
@@ -212,75 +244,20 @@ impl<M> Interpreter<M> {
                 let count = iterations.unwrap_or(1); // Default to 1 iteration if None
                 for iteration in 0..count {
                     tracing::debug!(iteration, "Loop iteration");
-                    let result = self.run(handler, endpoint, (*body).clone()).await?;
-                    self.received_values.extend(result.received_values);
-
-                    if !matches!(result.final_state, InterpreterState::Completed) {
-                        match result.final_state {
-                            InterpreterState::Failed(msg) => {
-                                return Err(ChoreographyError::Transport(msg));
-                            }
-                            InterpreterState::Timeout => {
-                                return Err(ChoreographyError::Timeout(
-                                    std::time::Duration::from_secs(0),
-                                ));
-                            }
-                            InterpreterState::Completed => {}
-                        }
-                    }
+                    self.run_nested(handler, endpoint, (*body).clone()).await?;
                 }
             }
 
             Effect::Timeout { at, dur, body } => {
                 // Execute the body with a timeout
                 tracing::debug!(?at, ?dur, "Executing timeout effect");
-
-                #[cfg(not(target_arch = "wasm32"))]
-                let timeout_result = {
-                    tokio::time::timeout(dur, Box::pin(self.run(handler, endpoint, *body))).await
-                };
-
-                #[cfg(target_arch = "wasm32")]
-                let timeout_result = {
-                    use futures::future::{select, Either};
-                    use futures::pin_mut;
-                    use wasm_timer::Delay;
-
-                    let body_future = Box::pin(self.run(handler, endpoint, *body));
-                    let timeout = Delay::new(dur);
-                    pin_mut!(body_future);
-                    pin_mut!(timeout);
-
-                    match select(body_future, timeout).await {
-                        Either::Left((result, _)) => Ok(result),
-                        Either::Right(_) => Err(()),
-                    }
-                };
-
-                match timeout_result {
-                    Ok(Ok(result)) => {
-                        // Success - merge the results
-                        self.received_values.extend(result.received_values);
-                        if !matches!(result.final_state, InterpreterState::Completed) {
-                            // Propagate non-completed state by updating our state
-                            // and returning an error for Failed/Timeout
-                            match result.final_state {
-                                InterpreterState::Failed(msg) => {
-                                    return Err(ChoreographyError::Transport(msg));
-                                }
-                                InterpreterState::Timeout => {
-                                    return Err(ChoreographyError::Timeout(dur));
-                                }
-                                InterpreterState::Completed => {}
-                            }
-                        }
-                    }
-                    Ok(Err(e)) => return Err(e),
-                    Err(_) => {
-                        // Timeout occurred
-                        return Err(ChoreographyError::Timeout(dur));
-                    }
-                }
+                let mut result =
+                    crate::runtime::timeout(dur, Box::pin(self.run(handler, endpoint, *body)))
+                        .await
+                        .map_err(|e| SessionError::new(&e, step("wait for", at)))?;
+                self.received_values
+                    .extend(std::mem::take(&mut result.received_values));
+                result.into_result()?;
             }
 
             Effect::Parallel { programs } => {
@@ -292,20 +269,7 @@ impl<M> Interpreter<M> {
                 // Try to execute in parallel, fall back to sequential if needed
                 // Sequential execution is still correct, just less performant
                 for program in programs {
-                    let result = self.run(handler, endpoint, program).await?;
-                    self.received_values.extend(result.received_values);
-
-                    match result.final_state {
-                        InterpreterState::Failed(msg) => {
-                            return Err(ChoreographyError::Transport(msg));
-                        }
-                        InterpreterState::Timeout => {
-                            return Err(ChoreographyError::Timeout(
-                                std::time::Duration::from_secs(0),
-                            ));
-                        }
-                        InterpreterState::Completed => {}
-                    }
+                    self.run_nested(handler, endpoint, program).await?;
                 }
             }
 
@@ -340,13 +304,14 @@ impl<M> Interpreter<M> {
         handler: &mut H,
         endpoint: &mut H::Endpoint,
         from: R,
+        label: &str,
     ) -> Result<T>
     where
         H: ChoreoHandler<Role = R>,
         R: RoleId,
         T: DeserializeOwned + Send,
     {
-        handler.recv(endpoint, from).await
+        handler.recv_labelled(endpoint, from, label).await
     }
 }
 
@@ -376,27 +341,18 @@ impl<M> ExtensibleInterpreter<M> {
         R: RoleId,
         M: ProgramMessage + Serialize + DeserializeOwned + 'static,
     {
-        for effect in program.effects {
-            match self.execute_effect(handler, endpoint, effect).await {
-                Ok(()) => continue,
-                Err(ChoreographyError::Timeout(_)) => {
-                    return Ok(InterpretResult {
-                        received_values: self.base.received_values.clone(),
-                        final_state: InterpreterState::Timeout,
-                    });
-                }
-                Err(e) => {
-                    return Ok(InterpretResult {
-                        received_values: self.base.received_values.clone(),
-                        final_state: InterpreterState::Failed(e.to_string()),
-                    });
-                }
+        let mut effects = program.effects.into_iter().peekable();
+        while let Some(effect) = effects.next() {
+            self.base.expect_branches(&effect, effects.peek());
+            if let Err(error) = self.execute_effect(handler, endpoint, effect).await {
+                return Ok(stopped(self.base.received_values.clone(), error));
             }
         }
 
         Ok(InterpretResult {
             received_values: self.base.received_values.clone(),
             final_state: InterpreterState::Completed,
+            error: None,
         })
     }
 
@@ -406,7 +362,7 @@ impl<M> ExtensibleInterpreter<M> {
         handler: &mut H,
         endpoint: &mut <H as ExtensibleHandler>::Endpoint,
         effect: Effect<R, M>,
-    ) -> Result<()>
+    ) -> SessionResult<()>
     where
         H: ChoreoHandler<Role = R, Endpoint = <H as ExtensibleHandler>::Endpoint>
             + ExtensibleHandler
@@ -426,7 +382,14 @@ impl<M> ExtensibleInterpreter<M> {
                 .extension_registry()
                 .handle(endpoint, ext.as_ref())
                 .await
-                .map_err(|e| ChoreographyError::Transport(e.to_string()))?;
+                .map_err(|e| {
+                    let error = ChoreographyError::Transport(e.to_string());
+                    let context = SessionContext {
+                        state: Some(format!("extension {}", ext.type_name())),
+                        ..SessionContext::default()
+                    };
+                    SessionError::new(&error, context)
+                })?;
 
             return Ok(());
         }
@@ -436,6 +399,35 @@ impl<M> ExtensibleInterpreter<M> {
     }
 }
 
+/// Result of a program stopped by `error`, with the messages received
+/// before it
+fn stopped<M>(received_values: Vec<M>, error: SessionError) -> InterpretResult<M> {
+    let final_state = match &error {
+        SessionError::Timeout { .. } => InterpreterState::Timeout,
+        error => InterpreterState::Failed(error.to_string()),
+    };
+    InterpretResult {
+        received_values,
+        final_state,
+        error: Some(error),
+    }
+}
+
+/// Context of a failure at the step `action` with `peer`
+fn step<R: RoleId>(action: &str, peer: R) -> SessionContext {
+    SessionContext::step(format!("{action} {peer:?}"), format!("{peer:?}"))
+}
+
+/// Name of the variant or type of `msg`, which its `Debug` output starts with
+fn message_name(msg: &impl std::fmt::Debug) -> String {
+    let debug = format!("{msg:?}");
+    debug
+        .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
 /// Extension trait to add program interpretation to handlers
 #[async_trait]
 pub trait ChoreoHandlerExt: ChoreoHandler + Sized {
@@ -560,6 +552,7 @@ pub mod testing {
             &mut self,
             _ep: &mut Self::Endpoint,
             from: Self::Role,
+            _labels: &[crate::effects::Label],
         ) -> Result<crate::effects::Label> {
             self.recorded_operations.push(MockOperation::Offer { from });
 
@@ -622,6 +615,37 @@ mod tests {
         assert_eq!(result.received_values.len(), 1);
     }
 
+    #[tokio::test]
+    async fn test_failures_carry_their_step() {
+        let program = Program::new()
+            .send(TestRole::Bob, TestMessage("hello".into()))
+            .recv::<TestMessage>(TestRole::Bob)
+            .end();
+        let mut handler = testing::MockHandler::new(TestRole::Alice);
+        let result = interpret(&mut handler, &mut (), program).await;
+        let error = crate::effects::session_result("Alice", result).unwrap_err();
+        let SessionError::Transport { context, .. } = &error else {
+            panic!("expected a transport failure, got {error:?}");
+        };
+        assert_eq!(context.role.as_deref(), Some("Alice"));
+        assert_eq!(context.state.as_deref(), Some("receive from Bob"));
+        assert_eq!(context.expected, ["TestMessage"]);
+
+        let program: Program<TestRole, TestMessage> = Program::new()
+            .offer(TestRole::Bob)
+            .branch(TestRole::Bob, vec![(Label("accept"), Program::new().end())])
+            .end();
+        let mut handler = testing::MockHandler::new(TestRole::Alice);
+        handler.add_response(testing::MockResponse::Label("reject".into()));
+        let result = interpret(&mut handler, &mut (), program).await.unwrap();
+        assert!(matches!(result.final_state, InterpreterState::Failed(_)));
+        let Some(SessionError::ProtocolViolation { context, .. }) = result.error else {
+            panic!("expected a protocol violation");
+        };
+        assert_eq!(context.expected, ["accept"]);
+        assert_eq!(context.received.as_deref(), Some("reject"));
+    }
+
     #[test]
     fn test_program_analysis() {
         let program = Program::new()
@@ -0,0 +1,292 @@
+// Cancellation middleware for effect handlers
+//
+// Adds cooperative cancellation to any handler. A `SessionHandle` is shared
+// between the code driving the protocol and whoever may want to stop it.
+// Once `cancel()` is called, pending and future operations resolve to
+// `ChoreographyError::Cancelled`, and a cancellation frame is sent to every
+// peer so they stop waiting as well.
+//
+// Messages and branch labels are wrapped in a small frame so that a
+// cancellation notice can travel on the same channel as protocol data, and
+// reaches a peer whether it waits in `recv` or in `offer`. Both sides of a
+// connection must therefore use `Cancellable`.
+
+use async_trait::async_trait;
+use futures::channel::oneshot;
+use futures::future::{select, Either, FutureExt, Shared};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::debug;
+
+use super::{recv_as, send_as};
+use crate::effects::{ChoreoHandler, ChoreographyError, Label, Result};
+
+struct HandleInner {
+    cancelled: AtomicBool,
+    trigger: Mutex<Option<oneshot::Sender<()>>>,
+    signal: Shared<oneshot::Receiver<()>>,
+}
+
+/// Shared cancellation handle for a running session
+#[derive(Clone)]
+pub struct SessionHandle {
+    inner: Arc<HandleInner>,
+}
+
+impl std::fmt::Debug for SessionHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionHandle")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+impl Default for SessionHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionHandle {
+    #[must_use]
+    pub fn new() -> Self {
+        let (tx, rx) = oneshot::channel();
+        Self {
+            inner: Arc::new(HandleInner {
+                cancelled: AtomicBool::new(false),
+                trigger: Mutex::new(Some(tx)),
+                signal: rx.shared(),
+            }),
+        }
+    }
+
+    /// Cancel the session. Idempotent.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        let trigger = self
+            .inner
+            .trigger
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .take();
+        if let Some(tx) = trigger {
+            let _ = tx.send(());
+        }
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the session has been cancelled.
+    pub async fn cancelled(&self) {
+        let _ = self.inner.signal.clone().await;
+    }
+}
+
+/// Wire frame used by `Cancellable`
+#[derive(Serialize, Deserialize)]
+enum Frame<M> {
+    Data(M),
+    Cancel,
+    Label(String),
+}
+
+/// Cancellation middleware
+pub struct Cancellable<H: ChoreoHandler> {
+    inner: H,
+    handle: SessionHandle,
+    peers: Vec<H::Role>,
+    peers_notified: bool,
+}
+
+impl<H: ChoreoHandler> Cancellable<H> {
+    /// Wrap `inner`. `peers` are notified when the session is cancelled.
+    pub fn new(inner: H, handle: SessionHandle, peers: Vec<H::Role>) -> Self {
+        Self {
+            inner,
+            handle,
+            peers,
+            peers_notified: false,
+        }
+    }
+
+    pub fn handle(&self) -> &SessionHandle {
+        &self.handle
+    }
+
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+
+    async fn send_step<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        label: Option<&str>,
+        msg: &M,
+    ) -> Result<()> {
+        match race(
+            &self.handle,
+            send_as(&mut self.inner, ep, to, label, &Frame::Data(msg)),
+        )
+        .await
+        {
+            Some(result) => result,
+            None => Err(self.abort(ep).await),
+        }
+    }
+
+    async fn recv_step<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        from: H::Role,
+        label: Option<&str>,
+    ) -> Result<M> {
+        match race(
+            &self.handle,
+            recv_as::<H, Frame<M>>(&mut self.inner, ep, from, label),
+        )
+        .await
+        {
+            Some(Ok(Frame::Data(msg))) => Ok(msg),
+            Some(Ok(Frame::Cancel)) => Err(self.peer_cancelled(ep, from).await),
+            Some(Ok(Frame::Label(label))) => Err(ChoreographyError::ProtocolViolation(format!(
+                "expected a message from {from:?}, received branch label {label}"
+            ))),
+            Some(Err(e)) => Err(e),
+            None => Err(self.abort(ep).await),
+        }
+    }
+
+    /// Send cancellation frames to all peers (once) and produce the error.
+    async fn abort(&mut self, ep: &mut H::Endpoint) -> ChoreographyError {
+        if !self.peers_notified {
+            self.peers_notified = true;
+            for peer in self.peers.clone() {
+                // Best effort: the peer may already be gone.
+                if let Err(e) = self.inner.send(ep, peer, &Frame::<()>::Cancel).await {
+                    debug!(?peer, %e, "failed to deliver cancellation frame");
+                }
+            }
+        }
+        ChoreographyError::Cancelled
+    }
+
+    /// Cancel our side after `from` cancelled the session
+    async fn peer_cancelled(&mut self, ep: &mut H::Endpoint, from: H::Role) -> ChoreographyError {
+        debug!(?from, "peer cancelled the session");
+        self.handle.cancel();
+        self.abort(ep).await
+    }
+}
+
+/// Run `op` unless the session is cancelled first.
+async fn race<T>(
+    handle: &SessionHandle,
+    op: impl Future<Output = Result<T>> + Send,
+) -> Option<Result<T>> {
+    if handle.is_cancelled() {
+        return None;
+    }
+    let cancelled = handle.cancelled();
+    futures::pin_mut!(op, cancelled);
+    match select(op, cancelled).await {
+        Either::Left((result, _)) => Some(result),
+        Either::Right(_) => None,
+    }
+}
+
+#[async_trait]
+impl<H: ChoreoHandler + Send> ChoreoHandler for Cancellable<H> {
+    type Role = H::Role;
+    type Endpoint = H::Endpoint;
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, None, msg).await
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+    ) -> Result<M> {
+        self.recv_step(ep, from, None).await
+    }
+
+    async fn send_labelled<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        label: &str,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, Some(label), msg).await
+    }
+
+    async fn recv_labelled<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        label: &str,
+    ) -> Result<M> {
+        self.recv_step(ep, from, Some(label)).await
+    }
+
+    async fn choose(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        who: Self::Role,
+        label: Label,
+    ) -> Result<()> {
+        let frame = Frame::<()>::Label(label.0.to_string());
+        match race(&self.handle, self.inner.send(ep, who, &frame)).await {
+            Some(result) => result,
+            None => Err(self.abort(ep).await),
+        }
+    }
+
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        match race(&self.handle, self.inner.recv::<Frame<()>>(ep, from)).await {
+            Some(Ok(Frame::Label(label))) => Label::resolve(&label, labels),
+            Some(Ok(Frame::Cancel)) => Err(self.peer_cancelled(ep, from).await),
+            Some(Ok(Frame::Data(()))) => Err(ChoreographyError::ProtocolViolation(format!(
+                "expected a branch label from {from:?}, received a message"
+            ))),
+            Some(Err(e)) => Err(e),
+            None => Err(self.abort(ep).await),
+        }
+    }
+
+    async fn with_timeout<F, T>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        at: Self::Role,
+        dur: Duration,
+        body: F,
+    ) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>> + Send,
+    {
+        // `T` need not be `Send`, so the outcome is taken apart before
+        // awaiting anything else
+        if let Some(result) = race(&self.handle, self.inner.with_timeout(ep, at, dur, body)).await {
+            return result;
+        }
+        Err(self.abort(ep).await)
+    }
+}
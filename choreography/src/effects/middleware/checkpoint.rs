@@ -0,0 +1,544 @@
+// Checkpointing middleware for crash recovery
+//
+// Persists the progress of a role after every completed operation so the
+// role can be restarted and pick up where it left off.
+//
+// A checkpoint contains:
+// - a journal of every completed operation (sends, received payloads,
+//   choices), which is the role's position in its local type;
+// - per-peer sequence numbers for sent and received messages and labels;
+// - a bounded outbox of recently sent payloads and labels for
+//   retransmission.
+//
+// Recovery: `Checkpointing::resume_from_checkpoint` loads the last checkpoint
+// and the role's program is run again from the start. Operations covered by
+// the journal are replayed locally (sends are suppressed, receives and offers
+// return the journaled values) and execution goes live at the first operation
+// the journal does not cover. `resync` asks peers to retransmit anything sent
+// after the last message we persisted; duplicates are dropped by sequence
+// number. Programs must be deterministic for replay to be valid.
+//
+// Messages and branch labels share one sequence per peer on the wire, so a
+// choice lost in a crash is retransmitted like any message, and all peers
+// must use `Checkpointing`.
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::debug;
+
+use super::{recv_as, send_as};
+use crate::effects::{ChoreoHandler, ChoreographyError, Label, Result};
+
+/// Default number of sent payloads retained per peer for retransmission
+pub const DEFAULT_OUTBOX_LIMIT: usize = 64;
+
+/// One completed operation in a role's execution
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JournalEntry<R> {
+    Sent { to: R, seq: u64 },
+    Received { from: R, seq: u64, payload: Vec<u8> },
+    Chose { to: R, seq: u64, label: String },
+    Offered { from: R, seq: u64, label: String },
+}
+
+/// Payload or branch label kept in the outbox for retransmission
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Outgoing {
+    Message(Vec<u8>),
+    Label(String),
+}
+
+/// Persisted progress of a single role
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Checkpoint<R: Eq + Hash> {
+    /// Completed operations, in execution order
+    pub journal: Vec<JournalEntry<R>>,
+    /// Sequence number of the last message or label sent to each peer
+    pub sent: HashMap<R, u64>,
+    /// Sequence number of the last message or label received from each peer
+    pub received: HashMap<R, u64>,
+    /// Recently sent payloads and labels per peer, kept for retransmission
+    pub outbox: HashMap<R, VecDeque<(u64, Outgoing)>>,
+}
+
+impl<R: Eq + Hash> Default for Checkpoint<R> {
+    fn default() -> Self {
+        Self {
+            journal: Vec::new(),
+            sent: HashMap::new(),
+            received: HashMap::new(),
+            outbox: HashMap::new(),
+        }
+    }
+}
+
+impl<R: Eq + Hash> Checkpoint<R> {
+    /// Number of completed operations
+    #[must_use]
+    pub fn step(&self) -> usize {
+        self.journal.len()
+    }
+}
+
+/// Storage backend for checkpoints
+pub trait Checkpointer<R: Eq + Hash>: Send + Sync {
+    /// Persist `checkpoint`, replacing any previous one
+    fn save(&self, checkpoint: &Checkpoint<R>) -> Result<()>;
+
+    /// Load the most recent checkpoint, if any
+    fn load(&self) -> Result<Option<Checkpoint<R>>>;
+
+    /// Discard the stored checkpoint, e.g. once the session has completed
+    fn clear(&self) -> Result<()>;
+}
+
+fn encode<R: Eq + Hash + Serialize>(checkpoint: &Checkpoint<R>) -> Result<Vec<u8>> {
+    bincode::serialize(checkpoint).map_err(|e| ChoreographyError::Serialization(e.to_string()))
+}
+
+fn decode<R: Eq + Hash + DeserializeOwned>(bytes: &[u8]) -> Result<Checkpoint<R>> {
+    bincode::deserialize(bytes).map_err(|e| ChoreographyError::Serialization(e.to_string()))
+}
+
+/// Checkpointer that keeps the latest checkpoint in memory
+///
+/// Clones share storage, so a clone outlives a "crashed" handler in tests.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryCheckpointer {
+    stored: Arc<Mutex<Option<Vec<u8>>>>,
+}
+
+impl MemoryCheckpointer {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn stored(&self) -> std::sync::MutexGuard<'_, Option<Vec<u8>>> {
+        self.stored
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+impl<R: Eq + Hash + Serialize + DeserializeOwned> Checkpointer<R> for MemoryCheckpointer {
+    fn save(&self, checkpoint: &Checkpoint<R>) -> Result<()> {
+        *self.stored() = Some(encode(checkpoint)?);
+        Ok(())
+    }
+
+    fn load(&self) -> Result<Option<Checkpoint<R>>> {
+        self.stored().as_deref().map(decode).transpose()
+    }
+
+    fn clear(&self) -> Result<()> {
+        *self.stored() = None;
+        Ok(())
+    }
+}
+
+/// Checkpointer that writes to a file, replacing it atomically
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug, Clone)]
+pub struct FileCheckpointer {
+    path: std::path::PathBuf,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileCheckpointer {
+    pub fn new(path: impl Into<std::path::PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<R: Eq + Hash + Serialize + DeserializeOwned> Checkpointer<R> for FileCheckpointer {
+    fn save(&self, checkpoint: &Checkpoint<R>) -> Result<()> {
+        let bytes = encode(checkpoint)?;
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, bytes)
+            .and_then(|()| std::fs::rename(&tmp, &self.path))
+            .map_err(|e| ChoreographyError::Transport(format!("Checkpoint write failed: {e}")))
+    }
+
+    fn load(&self) -> Result<Option<Checkpoint<R>>> {
+        match std::fs::read(&self.path) {
+            Ok(bytes) => decode(&bytes).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(ChoreographyError::Transport(format!(
+                "Checkpoint read failed: {e}"
+            ))),
+        }
+    }
+
+    fn clear(&self) -> Result<()> {
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(ChoreographyError::Transport(format!(
+                "Checkpoint removal failed: {e}"
+            ))),
+        }
+    }
+}
+
+/// Wire frame used by `Checkpointing`
+#[derive(Serialize, Deserialize)]
+enum Frame {
+    Data { seq: u64, payload: Vec<u8> },
+    Resync { next: u64 },
+    Label { seq: u64, label: String },
+}
+
+impl Frame {
+    fn carrying(seq: u64, outgoing: Outgoing) -> Self {
+        match outgoing {
+            Outgoing::Message(payload) => Frame::Data { seq, payload },
+            Outgoing::Label(label) => Frame::Label { seq, label },
+        }
+    }
+}
+
+/// Checkpointing middleware
+pub struct Checkpointing<H: ChoreoHandler, C> {
+    inner: H,
+    checkpointer: C,
+    state: Checkpoint<H::Role>,
+    replay_pos: usize,
+    outbox_limit: usize,
+}
+
+impl<H, C> Checkpointing<H, C>
+where
+    H: ChoreoHandler,
+    H::Role: Serialize + DeserializeOwned,
+    C: Checkpointer<H::Role>,
+{
+    /// Start a fresh session, discarding any stored checkpoint
+    pub fn new(inner: H, checkpointer: C) -> Result<Self> {
+        checkpointer.clear()?;
+        Ok(Self::from_state(inner, checkpointer, Checkpoint::default()))
+    }
+
+    /// Restart from the stored checkpoint (or from scratch if there is none)
+    ///
+    /// Run the role's program again from the beginning; already completed
+    /// operations are replayed from the journal. Call [`resync`](Self::resync)
+    /// once the transport to each peer is re-established.
+    pub fn resume_from_checkpoint(inner: H, checkpointer: C) -> Result<Self> {
+        let state = checkpointer.load()?.unwrap_or_default();
+        debug!(step = state.step(), "resuming from checkpoint");
+        Ok(Self::from_state(inner, checkpointer, state))
+    }
+
+    fn from_state(inner: H, checkpointer: C, state: Checkpoint<H::Role>) -> Self {
+        Self {
+            inner,
+            checkpointer,
+            state,
+            replay_pos: 0,
+            outbox_limit: DEFAULT_OUTBOX_LIMIT,
+        }
+    }
+
+    /// Number of sent payloads and labels retained per peer for
+    /// retransmission
+    #[must_use]
+    pub fn with_outbox_limit(mut self, limit: usize) -> Self {
+        self.outbox_limit = limit;
+        self
+    }
+
+    pub fn checkpoint(&self) -> &Checkpoint<H::Role> {
+        &self.state
+    }
+
+    /// Whether operations are still being served from the journal
+    pub fn is_replaying(&self) -> bool {
+        self.replay_pos < self.state.journal.len()
+    }
+
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+
+    async fn send_step<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        label: Option<&str>,
+        msg: &M,
+    ) -> Result<()> {
+        if let Some(entry) = self.next_replayed() {
+            return match entry {
+                JournalEntry::Sent { to: peer, .. } if peer == to => Ok(()),
+                other => Err(diverged("send", &other)),
+            };
+        }
+
+        let payload =
+            bincode::serialize(msg).map_err(|e| ChoreographyError::Serialization(e.to_string()))?;
+        let seq = self
+            .transmit(ep, to, label, Outgoing::Message(payload))
+            .await?;
+        self.record(JournalEntry::Sent { to, seq })
+    }
+
+    async fn recv_step<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        from: H::Role,
+        label: Option<&str>,
+    ) -> Result<M> {
+        if let Some(entry) = self.next_replayed() {
+            return match entry {
+                JournalEntry::Received {
+                    from: peer,
+                    payload,
+                    ..
+                } if peer == from => bincode::deserialize(&payload)
+                    .map_err(|e| ChoreographyError::Serialization(e.to_string())),
+                other => Err(diverged("recv", &other)),
+            };
+        }
+
+        let (seq, payload) = match self.receive(ep, from, label).await? {
+            (seq, Outgoing::Message(payload)) => (seq, payload),
+            (_, Outgoing::Label(label)) => {
+                return Err(ChoreographyError::ProtocolViolation(format!(
+                    "expected a message from {from:?}, received branch label {label}"
+                )))
+            }
+        };
+        let msg = bincode::deserialize(&payload)
+            .map_err(|e| ChoreographyError::Serialization(e.to_string()))?;
+        self.state.received.insert(from, seq);
+        self.record(JournalEntry::Received { from, seq, payload })?;
+        Ok(msg)
+    }
+
+    /// Ask each peer to retransmit messages we have not persisted
+    pub async fn resync(&mut self, ep: &mut H::Endpoint, peers: &[H::Role]) -> Result<()> {
+        for &peer in peers {
+            let next = self.state.received.get(&peer).copied().unwrap_or(0) + 1;
+            debug!(?peer, next, "requesting retransmission");
+            self.inner.send(ep, peer, &Frame::Resync { next }).await?;
+        }
+        Ok(())
+    }
+
+    fn next_replayed(&mut self) -> Option<JournalEntry<H::Role>> {
+        let entry = self.state.journal.get(self.replay_pos).cloned();
+        if entry.is_some() {
+            self.replay_pos += 1;
+        }
+        entry
+    }
+
+    fn record(&mut self, entry: JournalEntry<H::Role>) -> Result<()> {
+        self.state.journal.push(entry);
+        self.replay_pos = self.state.journal.len();
+        self.checkpointer.save(&self.state)
+    }
+
+    async fn retransmit(&mut self, ep: &mut H::Endpoint, peer: H::Role, next: u64) -> Result<()> {
+        let pending: Vec<(u64, Outgoing)> = self
+            .state
+            .outbox
+            .get(&peer)
+            .map(|queue| {
+                queue
+                    .iter()
+                    .filter(|(seq, _)| *seq >= next)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default();
+        debug!(?peer, next, count = pending.len(), "retransmitting");
+        for (seq, outgoing) in pending {
+            self.inner
+                .send(ep, peer, &Frame::carrying(seq, outgoing))
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Send `outgoing`, a message named `name` if known, to `to` under the
+    /// next sequence number and keep it in the outbox
+    async fn transmit(
+        &mut self,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        name: Option<&str>,
+        outgoing: Outgoing,
+    ) -> Result<u64> {
+        let seq = self.state.sent.get(&to).copied().unwrap_or(0) + 1;
+        let frame = Frame::carrying(seq, outgoing.clone());
+        send_as(&mut self.inner, ep, to, name, &frame).await?;
+
+        self.state.sent.insert(to, seq);
+        let outbox = self.state.outbox.entry(to).or_default();
+        outbox.push_back((seq, outgoing));
+        while outbox.len() > self.outbox_limit {
+            outbox.pop_front();
+        }
+        Ok(seq)
+    }
+
+    /// Next message or label from `from` not received before, serving
+    /// retransmission requests meanwhile
+    async fn receive(
+        &mut self,
+        ep: &mut H::Endpoint,
+        from: H::Role,
+        name: Option<&str>,
+    ) -> Result<(u64, Outgoing)> {
+        loop {
+            let (seq, incoming) = match recv_as::<H, Frame>(&mut self.inner, ep, from, name).await?
+            {
+                Frame::Data { seq, payload } => (seq, Outgoing::Message(payload)),
+                Frame::Label { seq, label } => (seq, Outgoing::Label(label)),
+                Frame::Resync { next } => {
+                    self.retransmit(ep, from, next).await?;
+                    continue;
+                }
+            };
+            let last = self.state.received.get(&from).copied().unwrap_or(0);
+            if seq <= last {
+                debug!(?from, seq, "dropping duplicate frame");
+                continue;
+            }
+            return Ok((seq, incoming));
+        }
+    }
+}
+
+fn diverged<R: std::fmt::Debug>(expected: &str, found: &JournalEntry<R>) -> ChoreographyError {
+    ChoreographyError::ProtocolViolation(format!(
+        "Replay diverged from checkpoint: expected {expected}, journal has {found:?}"
+    ))
+}
+
+#[async_trait]
+impl<H, C> ChoreoHandler for Checkpointing<H, C>
+where
+    H: ChoreoHandler + Send,
+    H::Role: Serialize + DeserializeOwned,
+    C: Checkpointer<H::Role>,
+{
+    type Role = H::Role;
+    type Endpoint = H::Endpoint;
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, None, msg).await
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+    ) -> Result<M> {
+        self.recv_step(ep, from, None).await
+    }
+
+    async fn send_labelled<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        label: &str,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, Some(label), msg).await
+    }
+
+    async fn recv_labelled<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        label: &str,
+    ) -> Result<M> {
+        self.recv_step(ep, from, Some(label)).await
+    }
+
+    async fn choose(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        who: Self::Role,
+        label: Label,
+    ) -> Result<()> {
+        if let Some(entry) = self.next_replayed() {
+            return match entry {
+                JournalEntry::Chose {
+                    to: peer,
+                    label: chosen,
+                    ..
+                } if peer == who && chosen == label.0 => Ok(()),
+                other => Err(diverged("choose", &other)),
+            };
+        }
+
+        let seq = self
+            .transmit(ep, who, None, Outgoing::Label(label.0.to_string()))
+            .await?;
+        self.record(JournalEntry::Chose {
+            to: who,
+            seq,
+            label: label.0.to_string(),
+        })
+    }
+
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        if let Some(entry) = self.next_replayed() {
+            return match entry {
+                JournalEntry::Offered {
+                    from: peer, label, ..
+                } if peer == from => Label::resolve(&label, labels),
+                other => Err(diverged("offer", &other)),
+            };
+        }
+
+        let (seq, label) = match self.receive(ep, from, None).await? {
+            (seq, Outgoing::Label(label)) => (seq, label),
+            (_, Outgoing::Message(_)) => {
+                return Err(ChoreographyError::ProtocolViolation(format!(
+                    "expected a branch label from {from:?}, received a message"
+                )))
+            }
+        };
+        let chosen = Label::resolve(&label, labels)?;
+        self.state.received.insert(from, seq);
+        self.record(JournalEntry::Offered { from, seq, label })?;
+        Ok(chosen)
+    }
+
+    async fn with_timeout<F, T>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        at: Self::Role,
+        dur: Duration,
+        body: F,
+    ) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>> + Send,
+    {
+        self.inner.with_timeout(ep, at, dur, body).await
+    }
+}
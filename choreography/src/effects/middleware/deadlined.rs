@@ -0,0 +1,260 @@
+// Deadline propagation middleware for effect handlers
+//
+// A `within 30s { ... }` block bounds the wall-clock time its steps take
+// across every role in it. The block's timekeeper, the role sending its
+// first message, starts the clock, and `Deadlined` carries the deadline, in
+// milliseconds since the Unix epoch, in a frame next to every payload. A
+// role receiving a frame adopts its deadline unless it holds an earlier one,
+// so the deadline reaches every role the block's messages reach, directly
+// or through other roles.
+//
+// The block ends at a role once it sends or receives the timekeeper's
+// `InTime` or `DeadlineExceeded` outcome. Until then `remaining` and
+// `is_exceeded` let handlers cut their work short. Steps are not cut off at
+// the deadline: a block that overran is reported by the timekeeper, and
+// every role of it takes the `deadline_exceeded` branch. Both sides of a
+// connection must use `Deadlined`.
+//
+// Openers and outcomes are told by the message name given to `send_labelled`
+// and `recv_labelled`. A plain `send` or `recv` fails while this role keeps
+// time for a block or is in one, as it could open or end it.
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::{recv_as, send_as};
+use crate::ast::{DEADLINE_EXCEEDED, IN_TIME};
+use crate::effects::{ChoreoHandler, ChoreographyError, Label, Result};
+
+/// Time limit of a `within` block as written, as in `500ms`, `30s`, `5m`
+/// or `1h`
+#[must_use]
+pub fn parse_time_limit(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let digits = value.find(|c: char| !c.is_ascii_digit())?;
+    let count: u64 = value[..digits].parse().ok()?;
+    match &value[digits..] {
+        "ms" => Some(Duration::from_millis(count)),
+        "s" => Some(Duration::from_secs(count)),
+        "m" => count.checked_mul(60).map(Duration::from_secs),
+        "h" => count.checked_mul(3_600).map(Duration::from_secs),
+        _ => None,
+    }
+}
+
+/// Wire frame used by `Deadlined`
+#[derive(Serialize, Deserialize)]
+struct DeadlinedFrame<M> {
+    /// Deadline of the sender, in milliseconds since the Unix epoch
+    deadline: Option<u64>,
+    payload: M,
+}
+
+/// Deadline propagation middleware
+pub struct Deadlined<H> {
+    inner: H,
+    /// Time limits of the blocks this role keeps time for, by the label of
+    /// the message opening them
+    openers: HashMap<String, Duration>,
+    deadline: Option<SystemTime>,
+}
+
+impl<H: ChoreoHandler> Deadlined<H> {
+    pub fn new(inner: H) -> Self {
+        Self {
+            inner,
+            openers: HashMap::new(),
+            deadline: None,
+        }
+    }
+
+    /// Start the clock of a block with the time limit `limit` when this
+    /// role sends a `message`, as the timekeeper of `within` blocks opening
+    /// with it
+    #[must_use]
+    pub fn within(mut self, message: &str, limit: Duration) -> Self {
+        self.openers.insert(message.to_string(), limit);
+        self
+    }
+
+    /// Start the clock of a block with the time limit `limit`, unless an
+    /// earlier deadline holds
+    pub fn enter(&mut self, limit: Duration) {
+        // At the precision of the frames, so every role holds the same one
+        self.adopt(from_millis(to_millis(crate::runtime::now() + limit)));
+    }
+
+    /// End the block this role is in
+    pub fn leave(&mut self) {
+        self.deadline = None;
+    }
+
+    /// Deadline of the block this role is in
+    pub fn deadline(&self) -> Option<SystemTime> {
+        self.deadline
+    }
+
+    /// Time left until the deadline, zero once it passed
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline.map(|deadline| {
+            deadline
+                .duration_since(crate::runtime::now())
+                .unwrap_or(Duration::ZERO)
+        })
+    }
+
+    /// Whether the block this role is in overran its deadline
+    pub fn is_exceeded(&self) -> bool {
+        self.remaining() == Some(Duration::ZERO)
+    }
+
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+
+    /// Fail a step whose message name is unknown if it could open or end a
+    /// block
+    fn check_named(&self, label: Option<&str>) -> Result<()> {
+        if label.is_none() && (self.deadline.is_some() || !self.openers.is_empty()) {
+            return Err(ChoreographyError::ProtocolViolation(
+                "step of a `within` block has no message name".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn send_step<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        label: Option<&str>,
+        msg: &M,
+    ) -> Result<()> {
+        self.check_named(label)?;
+        if let Some(label) = label {
+            if is_outcome(label) {
+                self.leave();
+            } else if let Some(limit) = self.openers.get(label).copied() {
+                if self.deadline.is_none() {
+                    self.enter(limit);
+                }
+            }
+        }
+        let frame = DeadlinedFrame {
+            deadline: self.deadline.map(to_millis),
+            payload: msg,
+        };
+        send_as(&mut self.inner, ep, to, label, &frame).await
+    }
+
+    async fn recv_step<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        from: H::Role,
+        label: Option<&str>,
+    ) -> Result<M> {
+        self.check_named(label)?;
+        let frame: DeadlinedFrame<M> = recv_as(&mut self.inner, ep, from, label).await?;
+        if label.is_some_and(is_outcome) {
+            self.leave();
+        } else if let Some(deadline) = frame.deadline {
+            self.adopt(from_millis(deadline));
+        }
+        Ok(frame.payload)
+    }
+
+    fn adopt(&mut self, deadline: SystemTime) {
+        self.deadline = Some(match self.deadline {
+            Some(held) => held.min(deadline),
+            None => deadline,
+        });
+    }
+}
+
+fn to_millis(time: SystemTime) -> u64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    u64::try_from(since_epoch.as_millis()).unwrap_or(u64::MAX)
+}
+
+fn from_millis(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+fn is_outcome(label: &str) -> bool {
+    label == IN_TIME || label == DEADLINE_EXCEEDED
+}
+
+#[async_trait]
+impl<H: ChoreoHandler + Send> ChoreoHandler for Deadlined<H> {
+    type Role = H::Role;
+    type Endpoint = H::Endpoint;
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, None, msg).await
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+    ) -> Result<M> {
+        self.recv_step(ep, from, None).await
+    }
+
+    async fn send_labelled<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        label: &str,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, Some(label), msg).await
+    }
+
+    async fn recv_labelled<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        label: &str,
+    ) -> Result<M> {
+        self.recv_step(ep, from, Some(label)).await
+    }
+
+    async fn choose(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        who: Self::Role,
+        label: Label,
+    ) -> Result<()> {
+        self.inner.choose(ep, who, label).await
+    }
+
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        self.inner.offer(ep, from, labels).await
+    }
+
+    async fn with_timeout<F, T>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        at: Self::Role,
+        dur: Duration,
+        body: F,
+    ) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>> + Send,
+    {
+        self.inner.with_timeout(ep, at, dur, body).await
+    }
+}
@@ -0,0 +1,163 @@
+// Step debugging middleware for effect handlers
+//
+// Stops before every send, receive, selection and offer at a checkpoint of a
+// `Debugger`, which holds the step back while the debugger is paused until
+// it is stepped or resumed. See `runtime::debugger` for the controls and the
+// socket protocol `choreo debug` attaches with.
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+use std::time::Duration;
+
+use super::{message_label, recv_as, send_as};
+use crate::effects::{ChoreoHandler, Label, Result};
+use crate::runtime::debugger::{Debugger, PendingStep};
+use crate::runtime::monitor::ActionKind;
+
+/// Step debugging middleware
+pub struct Debugged<H> {
+    inner: H,
+    role: String,
+    debugger: Debugger,
+    steps: usize,
+}
+
+impl<H: ChoreoHandler> Debugged<H> {
+    /// Wrap `inner`, which runs `role`, halting its steps under `debugger`
+    pub fn new(inner: H, role: H::Role, debugger: Debugger) -> Self {
+        Self {
+            inner,
+            role: format!("{role:?}"),
+            debugger,
+            steps: 0,
+        }
+    }
+
+    pub fn debugger(&self) -> &Debugger {
+        &self.debugger
+    }
+
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+
+    async fn send_step<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        label: Option<&str>,
+        msg: &M,
+    ) -> Result<()> {
+        self.checkpoint(
+            ActionKind::Send,
+            to,
+            Some(label.unwrap_or(message_label::<M>())),
+        )
+        .await;
+        send_as(&mut self.inner, ep, to, label, msg).await
+    }
+
+    async fn recv_step<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        from: H::Role,
+        label: Option<&str>,
+    ) -> Result<M> {
+        self.checkpoint(
+            ActionKind::Receive,
+            from,
+            Some(label.unwrap_or(message_label::<M>())),
+        )
+        .await;
+        recv_as(&mut self.inner, ep, from, label).await
+    }
+
+    /// Wait until the debugger lets this role take its next step
+    async fn checkpoint(&mut self, kind: ActionKind, peer: H::Role, label: Option<&str>) {
+        let step = PendingStep {
+            role: self.role.clone(),
+            index: self.steps,
+            kind,
+            peer: format!("{peer:?}"),
+            label: label.map(str::to_string),
+        };
+        self.steps += 1;
+        self.debugger.checkpoint(step).await;
+    }
+}
+
+#[async_trait]
+impl<H: ChoreoHandler + Send> ChoreoHandler for Debugged<H> {
+    type Role = H::Role;
+    type Endpoint = H::Endpoint;
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, None, msg).await
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+    ) -> Result<M> {
+        self.recv_step(ep, from, None).await
+    }
+
+    async fn send_labelled<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        label: &str,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, Some(label), msg).await
+    }
+
+    async fn recv_labelled<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        label: &str,
+    ) -> Result<M> {
+        self.recv_step(ep, from, Some(label)).await
+    }
+
+    async fn choose(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        who: Self::Role,
+        label: Label,
+    ) -> Result<()> {
+        self.checkpoint(ActionKind::Select, who, Some(label.0))
+            .await;
+        self.inner.choose(ep, who, label).await
+    }
+
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        self.checkpoint(ActionKind::Branch, from, None).await;
+        self.inner.offer(ep, from, labels).await
+    }
+
+    async fn with_timeout<F, T>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        at: Self::Role,
+        dur: Duration,
+        body: F,
+    ) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>> + Send,
+    {
+        self.inner.with_timeout(ep, at, dur, body).await
+    }
+}
@@ -0,0 +1,316 @@
+// Distributed tracing middleware for effect handlers
+//
+// Opens a `tracing` span for every operation, tagged with the session ID, the
+// local role, the peer, the message label, and the chosen or offered branch.
+// All spans of one role hang off a per-session span.
+//
+// Messages travel in a frame that carries a W3C `traceparent` string next to
+// the payload. With the `otel` feature enabled the sender injects the
+// OpenTelemetry context of its send span, and the receiver parents its session
+// span on the first context it sees and links later receive spans to their
+// senders. With a subscriber exporting to OpenTelemetry, a whole choreography
+// execution therefore shows up as a single distributed trace. Without the
+// feature the frame is sent with an empty context, so peers with and without
+// `otel` stay wire compatible. Both sides of a connection must use `Traced`.
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::time::Duration;
+use tracing::{field, info_span, Instrument, Span};
+
+use super::{message_label, recv_as, send_as};
+use crate::effects::{ChoreoHandler, Label, Result};
+
+/// Wire frame used by `Traced`
+#[derive(Serialize, Deserialize)]
+struct TracedFrame<M> {
+    traceparent: Option<String>,
+    payload: M,
+}
+
+/// Distributed tracing middleware
+pub struct Traced<H: ChoreoHandler> {
+    inner: H,
+    role: H::Role,
+    session_id: String,
+    session_span: Option<Span>,
+}
+
+impl<H: ChoreoHandler> Traced<H> {
+    /// Wrap `inner`, which runs `role` in the session identified by `session_id`
+    pub fn new(inner: H, role: H::Role, session_id: impl std::fmt::Display) -> Self {
+        Self {
+            inner,
+            role,
+            session_id: session_id.to_string(),
+            session_span: None,
+        }
+    }
+
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// The per-session span, once the first operation has run
+    pub fn session_span(&self) -> Option<&Span> {
+        self.session_span.as_ref()
+    }
+
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+
+    async fn send_step<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        label: Option<&str>,
+        msg: &M,
+    ) -> Result<()> {
+        let session = self.session(None);
+        let span = info_span!(
+            parent: &session,
+            "choreo.send",
+            session_id = %self.session_id,
+            role = ?self.role,
+            peer = ?to,
+            label = label.unwrap_or(message_label::<M>()),
+        );
+        let frame = TracedFrame {
+            traceparent: propagation::inject(&span),
+            payload: msg,
+        };
+        send_as(&mut self.inner, ep, to, label, &frame)
+            .instrument(span)
+            .await
+    }
+
+    async fn recv_step<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        from: H::Role,
+        label: Option<&str>,
+    ) -> Result<M> {
+        let frame: TracedFrame<M> = recv_as(&mut self.inner, ep, from, label).await?;
+        let remote = frame.traceparent.as_deref();
+        let session = self.session(remote);
+        let span = info_span!(
+            parent: &session,
+            "choreo.recv",
+            session_id = %self.session_id,
+            role = ?self.role,
+            peer = ?from,
+            label = label.unwrap_or(message_label::<M>()),
+        );
+        if let Some(traceparent) = remote {
+            propagation::link(&span, traceparent);
+        }
+        span.in_scope(|| tracing::debug!("received"));
+        Ok(frame.payload)
+    }
+
+    /// Session span, created on first use and parented on `remote` if given
+    fn session(&mut self, remote: Option<&str>) -> Span {
+        if let Some(span) = &self.session_span {
+            return span.clone();
+        }
+        let span = info_span!(
+            "choreo.session",
+            session_id = %self.session_id,
+            role = ?self.role,
+        );
+        if let Some(traceparent) = remote {
+            propagation::adopt(&span, traceparent);
+        }
+        self.session_span = Some(span.clone());
+        span
+    }
+}
+
+#[async_trait]
+impl<H: ChoreoHandler + Send> ChoreoHandler for Traced<H> {
+    type Role = H::Role;
+    type Endpoint = H::Endpoint;
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, None, msg).await
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+    ) -> Result<M> {
+        self.recv_step(ep, from, None).await
+    }
+
+    async fn send_labelled<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        label: &str,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, Some(label), msg).await
+    }
+
+    async fn recv_labelled<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        label: &str,
+    ) -> Result<M> {
+        self.recv_step(ep, from, Some(label)).await
+    }
+
+    async fn choose(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        who: Self::Role,
+        label: Label,
+    ) -> Result<()> {
+        let session = self.session(None);
+        let span = info_span!(
+            parent: &session,
+            "choreo.choose",
+            session_id = %self.session_id,
+            role = ?self.role,
+            peer = ?who,
+            branch = label.0,
+        );
+        self.inner.choose(ep, who, label).instrument(span).await
+    }
+
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        let session = self.session(None);
+        let span = info_span!(
+            parent: &session,
+            "choreo.offer",
+            session_id = %self.session_id,
+            role = ?self.role,
+            peer = ?from,
+            branch = field::Empty,
+        );
+        let label = self
+            .inner
+            .offer(ep, from, labels)
+            .instrument(span.clone())
+            .await?;
+        span.record("branch", label.0);
+        Ok(label)
+    }
+
+    async fn with_timeout<F, T>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        at: Self::Role,
+        dur: Duration,
+        body: F,
+    ) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>> + Send,
+    {
+        self.inner.with_timeout(ep, at, dur, body).await
+    }
+}
+
+#[cfg(feature = "otel")]
+mod propagation {
+    use opentelemetry::trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+    };
+    use opentelemetry::Context;
+    use tracing::Span;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    /// Encode the OpenTelemetry context of `span` as a W3C `traceparent`
+    pub(super) fn inject(span: &Span) -> Option<String> {
+        let cx = span.context();
+        let sc = cx.span().span_context().clone();
+        sc.is_valid().then(|| {
+            format!(
+                "00-{:032x}-{:016x}-{:02x}",
+                sc.trace_id(),
+                sc.span_id(),
+                sc.trace_flags().to_u8()
+            )
+        })
+    }
+
+    /// Parse a W3C `traceparent` into a remote span context
+    pub(super) fn extract(traceparent: &str) -> Option<SpanContext> {
+        let mut parts = traceparent.split('-');
+        let version = parts.next()?;
+        let trace_id = TraceId::from_hex(parts.next()?).ok()?;
+        let span_id = SpanId::from_hex(parts.next()?).ok()?;
+        let flags = u8::from_str_radix(parts.next()?, 16).ok()?;
+        if version != "00" || parts.next().is_some() {
+            return None;
+        }
+        let sc = SpanContext::new(
+            trace_id,
+            span_id,
+            TraceFlags::new(flags),
+            true,
+            TraceState::default(),
+        );
+        sc.is_valid().then_some(sc)
+    }
+
+    pub(super) fn adopt(span: &Span, traceparent: &str) {
+        if let Some(sc) = extract(traceparent) {
+            // Fails only without an OpenTelemetry layer, where there is nothing to join.
+            let _ = span.set_parent(Context::new().with_remote_span_context(sc));
+        }
+    }
+
+    pub(super) fn link(span: &Span, traceparent: &str) {
+        if let Some(sc) = extract(traceparent) {
+            span.add_link(sc);
+        }
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+mod propagation {
+    use tracing::Span;
+
+    pub(super) fn inject(_span: &Span) -> Option<String> {
+        None
+    }
+
+    pub(super) fn adopt(_span: &Span, _traceparent: &str) {}
+
+    pub(super) fn link(_span: &Span, _traceparent: &str) {}
+}
+
+#[cfg(all(test, feature = "otel"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_traceparent_roundtrip() {
+        let sc = propagation::extract("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+            .expect("valid traceparent");
+        assert_eq!(
+            sc.trace_id(),
+            opentelemetry::trace::TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap()
+        );
+        assert!(sc.is_remote());
+        assert!(
+            propagation::extract("00-00000000000000000000000000000000-00f067aa0ba902b7-01")
+                .is_none()
+        );
+        assert!(propagation::extract("garbage").is_none());
+    }
+}
@@ -0,0 +1,220 @@
+// End-to-end encryption middleware for effect handlers
+//
+// Seals every message and branch label for its recipient role with a
+// `SecureCodec` before handing it to the inner handler, and opens what the
+// inner handler receives, so transports, brokers and relays in between only
+// carry ciphertext. Each payload is bound to the session, to whether it is a
+// message or a label, and to its sequence number among the payloads one role
+// sends another. A receiver only opens the next payload it expects, so a
+// replayed, reordered, dropped or altered payload fails to open. Both ends
+// of a link must use `Encrypted`, with the same session ID.
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use super::{recv_as, send_as};
+use crate::effects::{ChoreoHandler, ChoreographyError, Label, Result};
+use crate::runtime::secure::SecureCodec;
+
+/// What a sealed payload holds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum Kind {
+    Message,
+    Label,
+}
+
+/// Wire frame used by `Encrypted`
+#[derive(Serialize, Deserialize)]
+struct Sealed {
+    kind: Kind,
+    payload: Vec<u8>,
+}
+
+/// Encryption middleware
+pub struct Encrypted<H> {
+    inner: H,
+    role: String,
+    session_id: String,
+    codec: SecureCodec,
+    /// Sequence number of the last payload sealed for each peer
+    sent: HashMap<String, u64>,
+    /// Sequence number of the last payload opened from each peer
+    received: HashMap<String, u64>,
+}
+
+impl<H: ChoreoHandler> Encrypted<H> {
+    /// Wrap `inner`, which runs `role` in `session_id`, sealing its messages
+    /// with `codec`
+    ///
+    /// Roles are named by their `Debug` form in the codec's key provider.
+    pub fn new(inner: H, role: H::Role, session_id: impl fmt::Display, codec: SecureCodec) -> Self {
+        Self {
+            inner,
+            role: format!("{role:?}"),
+            session_id: session_id.to_string(),
+            codec,
+            sent: HashMap::new(),
+            received: HashMap::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+
+    /// Associated data binding a payload to its kind, session and place
+    fn context(&self, kind: Kind, seq: u64) -> Vec<u8> {
+        let mut context = Vec::with_capacity(17 + self.session_id.len());
+        context.push(kind as u8);
+        context.extend_from_slice(&(self.session_id.len() as u64).to_be_bytes());
+        context.extend_from_slice(self.session_id.as_bytes());
+        context.extend_from_slice(&seq.to_be_bytes());
+        context
+    }
+
+    /// Seal `plaintext` for `to` under the next sequence number and send it
+    async fn transmit(
+        &mut self,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        label: Option<&str>,
+        kind: Kind,
+        plaintext: &[u8],
+    ) -> Result<()> {
+        let peer = format!("{to:?}");
+        let seq = self.sent.get(&peer).copied().unwrap_or(0) + 1;
+        let payload = self
+            .codec
+            .seal(&self.role, &peer, &self.context(kind, seq), plaintext)?;
+        send_as(&mut self.inner, ep, to, label, &Sealed { kind, payload }).await?;
+        self.sent.insert(peer, seq);
+        Ok(())
+    }
+
+    /// Open the next payload from `from`, which must hold a `kind`
+    async fn receive(
+        &mut self,
+        ep: &mut H::Endpoint,
+        from: H::Role,
+        label: Option<&str>,
+        kind: Kind,
+    ) -> Result<Vec<u8>> {
+        let sealed: Sealed = recv_as(&mut self.inner, ep, from, label).await?;
+        if sealed.kind != kind {
+            return Err(ChoreographyError::ProtocolViolation(format!(
+                "expected a {kind:?} from {from:?}, received a {:?}",
+                sealed.kind
+            )));
+        }
+        let peer = format!("{from:?}");
+        let seq = self.received.get(&peer).copied().unwrap_or(0) + 1;
+        let plaintext =
+            self.codec
+                .open(&peer, &self.role, &self.context(kind, seq), &sealed.payload)?;
+        self.received.insert(peer, seq);
+        Ok(plaintext)
+    }
+
+    async fn send_step<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        label: Option<&str>,
+        msg: &M,
+    ) -> Result<()> {
+        let payload =
+            bincode::serialize(msg).map_err(|e| ChoreographyError::Serialization(e.to_string()))?;
+        self.transmit(ep, to, label, Kind::Message, &payload).await
+    }
+
+    async fn recv_step<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        from: H::Role,
+        label: Option<&str>,
+    ) -> Result<M> {
+        let payload = self.receive(ep, from, label, Kind::Message).await?;
+        bincode::deserialize(&payload).map_err(|e| ChoreographyError::Serialization(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl<H: ChoreoHandler + Send> ChoreoHandler for Encrypted<H> {
+    type Role = H::Role;
+    type Endpoint = H::Endpoint;
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, None, msg).await
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+    ) -> Result<M> {
+        self.recv_step(ep, from, None).await
+    }
+
+    async fn send_labelled<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        label: &str,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, Some(label), msg).await
+    }
+
+    async fn recv_labelled<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        label: &str,
+    ) -> Result<M> {
+        self.recv_step(ep, from, Some(label)).await
+    }
+
+    async fn choose(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        who: Self::Role,
+        label: Label,
+    ) -> Result<()> {
+        self.transmit(ep, who, None, Kind::Label, label.0.as_bytes())
+            .await
+    }
+
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        let label = self.receive(ep, from, None, Kind::Label).await?;
+        let label = String::from_utf8(label).map_err(|_| {
+            ChoreographyError::ProtocolViolation(format!("branch label from {from:?} is not UTF-8"))
+        })?;
+        Label::resolve(&label, labels)
+    }
+
+    async fn with_timeout<F, T>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        at: Self::Role,
+        dur: Duration,
+        body: F,
+    ) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>> + Send,
+    {
+        self.inner.with_timeout(ep, at, dur, body).await
+    }
+}
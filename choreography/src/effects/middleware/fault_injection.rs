@@ -9,6 +9,8 @@ use serde::{de::DeserializeOwned, Serialize};
 #[cfg(feature = "test-utils")]
 use std::time::Duration;
 
+#[cfg(feature = "test-utils")]
+use super::send_as;
 #[cfg(feature = "test-utils")]
 use crate::effects::{ChoreoHandler, Label, Result};
 
@@ -40,15 +42,12 @@ impl<H> FaultInjection<H> {
 }
 
 #[cfg(feature = "test-utils")]
-#[async_trait]
-impl<H: ChoreoHandler + Send> ChoreoHandler for FaultInjection<H> {
-    type Role = H::Role;
-    type Endpoint = H::Endpoint;
-
-    async fn send<M: Serialize + Send + Sync>(
+impl<H: ChoreoHandler> FaultInjection<H> {
+    async fn send_step<M: Serialize + Send + Sync>(
         &mut self,
-        ep: &mut Self::Endpoint,
-        to: Self::Role,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        label: Option<&str>,
         msg: &M,
     ) -> Result<()> {
         use rand::Rng;
@@ -57,16 +56,7 @@ impl<H: ChoreoHandler + Send> ChoreoHandler for FaultInjection<H> {
         if let Some((min, max)) = self.delay_range {
             let delay_ms = self.rng.gen_range(min.as_millis()..=max.as_millis());
             let delay = Duration::from_millis(delay_ms as u64);
-
-            #[cfg(not(target_arch = "wasm32"))]
-            {
-                tokio::time::sleep(delay).await;
-            }
-
-            #[cfg(target_arch = "wasm32")]
-            {
-                wasm_timer::Delay::new(delay).await.ok();
-            }
+            crate::runtime::sleep(delay).await;
         }
 
         // Inject random failure
@@ -76,7 +66,23 @@ impl<H: ChoreoHandler + Send> ChoreoHandler for FaultInjection<H> {
             ));
         }
 
-        self.inner.send(ep, to, msg).await
+        send_as(&mut self.inner, ep, to, label, msg).await
+    }
+}
+
+#[cfg(feature = "test-utils")]
+#[async_trait]
+impl<H: ChoreoHandler + Send> ChoreoHandler for FaultInjection<H> {
+    type Role = H::Role;
+    type Endpoint = H::Endpoint;
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, None, msg).await
     }
 
     async fn recv<M: DeserializeOwned + Send>(
@@ -87,6 +93,25 @@ impl<H: ChoreoHandler + Send> ChoreoHandler for FaultInjection<H> {
         self.inner.recv(ep, from).await
     }
 
+    async fn send_labelled<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        label: &str,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, Some(label), msg).await
+    }
+
+    async fn recv_labelled<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        label: &str,
+    ) -> Result<M> {
+        self.inner.recv_labelled(ep, from, label).await
+    }
+
     async fn choose(
         &mut self,
         ep: &mut Self::Endpoint,
@@ -96,8 +121,13 @@ impl<H: ChoreoHandler + Send> ChoreoHandler for FaultInjection<H> {
         self.inner.choose(ep, who, label).await
     }
 
-    async fn offer(&mut self, ep: &mut Self::Endpoint, from: Self::Role) -> Result<Label> {
-        self.inner.offer(ep, from).await
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        self.inner.offer(ep, from, labels).await
     }
 
     async fn with_timeout<F, T>(
@@ -0,0 +1,165 @@
+// Capability guard middleware for effect handlers
+//
+// Consults a `CapabilityProvider` before every send, receive, selection, or
+// offer that matches one of the guard points generated from
+// `guard_capability` annotations. If the role lacks a required capability the
+// step fails with `ChoreographyError::GuardDenied` before the inner handler is
+// called, so nothing reaches the transport. Unguarded steps pass through.
+//
+// Messages are matched by the name given to `send_labelled` and
+// `recv_labelled`, as generated code does. A plain `send` or `recv` has no
+// name, so every guard on its peer applies: guards fail closed.
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{recv_as, send_as};
+use crate::effects::{ChoreoHandler, Label, Result};
+use crate::runtime::guard::{CapabilityProvider, GuardDenied, GuardPoint};
+use crate::runtime::monitor::ActionKind;
+
+/// Capability guard middleware
+pub struct Guarded<H> {
+    inner: H,
+    role: String,
+    capabilities: Arc<dyn CapabilityProvider>,
+    points: &'static [GuardPoint],
+}
+
+impl<H: ChoreoHandler> Guarded<H> {
+    /// Wrap `inner`, which runs `role`, checking the steps listed in `points`
+    pub fn new(
+        inner: H,
+        role: H::Role,
+        capabilities: Arc<dyn CapabilityProvider>,
+        points: &'static [GuardPoint],
+    ) -> Self {
+        Self {
+            inner,
+            role: format!("{role:?}"),
+            capabilities,
+            points,
+        }
+    }
+
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+
+    async fn send_step<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        label: Option<&str>,
+        msg: &M,
+    ) -> Result<()> {
+        self.check(ActionKind::Send, &to, label)?;
+        send_as(&mut self.inner, ep, to, label, msg).await
+    }
+
+    async fn recv_step<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        from: H::Role,
+        label: Option<&str>,
+    ) -> Result<M> {
+        self.check(ActionKind::Receive, &from, label)?;
+        recv_as(&mut self.inner, ep, from, label).await
+    }
+
+    fn check(&self, action: ActionKind, peer: &H::Role, label: Option<&str>) -> Result<()> {
+        let peer = format!("{peer:?}");
+        let denied = self.points.iter().find(|p| {
+            p.applies_to(action, &peer, label)
+                && !self.capabilities.has_capability(&self.role, p.capability)
+        });
+        match denied {
+            Some(point) => Err(GuardDenied {
+                role: self.role.clone(),
+                capability: point.capability.to_string(),
+                action,
+                peer,
+                label: label.map(str::to_string),
+            }
+            .into()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl<H: ChoreoHandler + Send> ChoreoHandler for Guarded<H> {
+    type Role = H::Role;
+    type Endpoint = H::Endpoint;
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, None, msg).await
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+    ) -> Result<M> {
+        self.recv_step(ep, from, None).await
+    }
+
+    async fn send_labelled<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        label: &str,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, Some(label), msg).await
+    }
+
+    async fn recv_labelled<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        label: &str,
+    ) -> Result<M> {
+        self.recv_step(ep, from, Some(label)).await
+    }
+
+    async fn choose(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        who: Self::Role,
+        label: Label,
+    ) -> Result<()> {
+        self.check(ActionKind::Select, &who, Some(label.0))?;
+        self.inner.choose(ep, who, label).await
+    }
+
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        self.check(ActionKind::Branch, &from, None)?;
+        self.inner.offer(ep, from, labels).await
+    }
+
+    async fn with_timeout<F, T>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        at: Self::Role,
+        dur: Duration,
+        body: F,
+    ) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>> + Send,
+    {
+        self.inner.with_timeout(ep, at, dur, body).await
+    }
+}
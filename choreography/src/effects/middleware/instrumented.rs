@@ -0,0 +1,205 @@
+// Session metrics middleware for effect handlers
+//
+// Reports every operation to a `SessionMetrics` implementation: message
+// labels per peer, branch selections, step latencies, failures, and expired
+// timeouts. `start_session` and `end_session` report the session around them. Unlike
+// `Metrics`, which keeps simple in-process counters, this middleware feeds an
+// external sink so operators can see which protocol paths are hot and where
+// sessions stall.
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+use super::{message_label, recv_as, send_as};
+use crate::effects::{
+    ChoreoHandler, ChoreographyError, Label, Result, SessionMetrics, SessionResult,
+};
+use crate::runtime::monitor::ActionKind;
+use crate::runtime::Instant;
+
+/// Session metrics middleware
+pub struct Instrumented<H> {
+    inner: H,
+    role: String,
+    metrics: Arc<dyn SessionMetrics>,
+    started: Option<Instant>,
+}
+
+impl<H: ChoreoHandler> Instrumented<H> {
+    /// Wrap `inner`, which runs `role`, reporting to `metrics`
+    pub fn new(inner: H, role: H::Role, metrics: Arc<dyn SessionMetrics>) -> Self {
+        Self {
+            inner,
+            role: format!("{role:?}"),
+            metrics,
+            started: None,
+        }
+    }
+
+    pub fn metrics(&self) -> &Arc<dyn SessionMetrics> {
+        &self.metrics
+    }
+
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+
+    async fn send_step<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        label: Option<&str>,
+        msg: &M,
+    ) -> Result<()> {
+        let start = Instant::now();
+        let result = send_as(&mut self.inner, ep, to, label, msg).await;
+        if result.is_ok() {
+            self.metrics.message_sent(
+                &self.role,
+                &format!("{to:?}"),
+                label.unwrap_or(message_label::<M>()),
+            );
+        }
+        self.finish(ActionKind::Send, start, &result);
+        result
+    }
+
+    async fn recv_step<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        from: H::Role,
+        label: Option<&str>,
+    ) -> Result<M> {
+        let start = Instant::now();
+        let result = recv_as(&mut self.inner, ep, from, label).await;
+        if result.is_ok() {
+            self.metrics.message_received(
+                &self.role,
+                &format!("{from:?}"),
+                label.unwrap_or(message_label::<M>()),
+            );
+        }
+        self.finish(ActionKind::Receive, start, &result);
+        result
+    }
+
+    /// Report that the session of this role started
+    pub fn start_session(&mut self) {
+        self.started = Some(Instant::now());
+        self.metrics.session_started(&self.role);
+    }
+
+    /// Report that the session of this role ended with `result`
+    pub fn end_session<T>(&mut self, result: &SessionResult<T>) {
+        let duration = self
+            .started
+            .take()
+            .map_or(Duration::ZERO, |started| started.elapsed());
+        self.metrics
+            .session_ended(&self.role, duration, result.as_ref().err());
+    }
+
+    /// Report latency or failure of a finished step
+    fn finish<T>(&self, step: ActionKind, start: Instant, result: &Result<T>) {
+        match result {
+            Ok(_) => self
+                .metrics
+                .step_completed(&self.role, step, start.elapsed()),
+            Err(e) => self.metrics.step_failed(&self.role, step, e),
+        }
+    }
+}
+
+#[async_trait]
+impl<H: ChoreoHandler + Send> ChoreoHandler for Instrumented<H> {
+    type Role = H::Role;
+    type Endpoint = H::Endpoint;
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, None, msg).await
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+    ) -> Result<M> {
+        self.recv_step(ep, from, None).await
+    }
+
+    async fn send_labelled<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        label: &str,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, Some(label), msg).await
+    }
+
+    async fn recv_labelled<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        label: &str,
+    ) -> Result<M> {
+        self.recv_step(ep, from, Some(label)).await
+    }
+
+    async fn choose(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        who: Self::Role,
+        label: Label,
+    ) -> Result<()> {
+        let start = Instant::now();
+        let result = self.inner.choose(ep, who, label).await;
+        if result.is_ok() {
+            self.metrics
+                .branch_selected(&self.role, &format!("{who:?}"), label.0);
+        }
+        self.finish(ActionKind::Select, start, &result);
+        result
+    }
+
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        let start = Instant::now();
+        let result = self.inner.offer(ep, from, labels).await;
+        if let Ok(label) = &result {
+            self.metrics
+                .branch_offered(&self.role, &format!("{from:?}"), label.0);
+        }
+        self.finish(ActionKind::Branch, start, &result);
+        result
+    }
+
+    async fn with_timeout<F, T>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        at: Self::Role,
+        dur: Duration,
+        body: F,
+    ) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>> + Send,
+    {
+        let result = self.inner.with_timeout(ep, at, dur, body).await;
+        if let Err(ChoreographyError::Timeout(after)) = &result {
+            self.metrics
+                .timed_out(&self.role, &format!("{at:?}"), *after);
+        }
+        result
+    }
+}
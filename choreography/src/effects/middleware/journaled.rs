@@ -0,0 +1,192 @@
+// Journal middleware for effect handlers
+//
+// Writes a hash-chained audit record for every completed send or receive that
+// matches one of the journal points generated from `[@journal_facts = "..."]`
+// annotations. Steps without an annotation pass through untouched. A step
+// whose record cannot be written fails with `ChoreographyError::Journal`, so
+// no annotated step completes without leaving an audit trail. Sends at points
+// with a retention period also log the message as JSON; receives never do,
+// the sender's record holds it.
+//
+// Messages are matched by the name given to `send_labelled` and
+// `recv_labelled`. A plain `send` or `recv` to a peer with journal points
+// for that direction fails, as it cannot be told whether it must be recorded.
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+use std::time::Duration;
+
+use super::{recv_as, send_as};
+use crate::effects::{ChoreoHandler, ChoreographyError, Label, Result};
+use crate::runtime::journal::{Journal, JournalPoint};
+use crate::runtime::monitor::ActionKind;
+
+/// Journal middleware
+pub struct Journaled<H> {
+    inner: H,
+    role: String,
+    journal: Journal,
+    points: &'static [JournalPoint],
+}
+
+impl<H: ChoreoHandler> Journaled<H> {
+    /// Wrap `inner`, which runs `role`, journaling the steps listed in `points`
+    pub fn new(inner: H, role: H::Role, journal: Journal, points: &'static [JournalPoint]) -> Self {
+        Self {
+            inner,
+            role: format!("{role:?}"),
+            journal,
+            points,
+        }
+    }
+
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+
+    /// Fail a step whose message name is unknown if steps to `peer` are
+    /// journaled
+    fn check_named(&self, action: ActionKind, peer: &H::Role, label: Option<&str>) -> Result<()> {
+        let peer = format!("{peer:?}");
+        if label.is_none()
+            && self
+                .points
+                .iter()
+                .any(|p| p.action == action && p.peer == peer)
+        {
+            return Err(ChoreographyError::Journal(format!(
+                "{action:?} with {peer} has journal points but no message name"
+            )));
+        }
+        Ok(())
+    }
+
+    fn record<M: Serialize>(
+        &self,
+        action: ActionKind,
+        peer: &H::Role,
+        label: Option<&str>,
+        msg: Option<&M>,
+    ) -> Result<()> {
+        let Some(label) = label else {
+            return Ok(());
+        };
+        let peer = format!("{peer:?}");
+        for point in self
+            .points
+            .iter()
+            .filter(|p| p.action == action && p.peer == peer && p.label == label)
+        {
+            let payload = match msg.filter(|_| point.retain_ms.is_some()) {
+                Some(msg) => Some(
+                    serde_json::to_string(msg)
+                        .map_err(|e| ChoreographyError::Serialization(e.to_string()))?,
+                ),
+                None => None,
+            };
+            self.journal.record_point(&self.role, point, payload)?;
+        }
+        Ok(())
+    }
+
+    async fn send_step<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        label: Option<&str>,
+        msg: &M,
+    ) -> Result<()> {
+        self.check_named(ActionKind::Send, &to, label)?;
+        send_as(&mut self.inner, ep, to, label, msg).await?;
+        self.record(ActionKind::Send, &to, label, Some(msg))
+    }
+
+    async fn recv_step<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        from: H::Role,
+        label: Option<&str>,
+    ) -> Result<M> {
+        self.check_named(ActionKind::Receive, &from, label)?;
+        let msg = recv_as(&mut self.inner, ep, from, label).await?;
+        self.record::<()>(ActionKind::Receive, &from, label, None)?;
+        Ok(msg)
+    }
+}
+
+#[async_trait]
+impl<H: ChoreoHandler + Send> ChoreoHandler for Journaled<H> {
+    type Role = H::Role;
+    type Endpoint = H::Endpoint;
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, None, msg).await
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+    ) -> Result<M> {
+        self.recv_step(ep, from, None).await
+    }
+
+    async fn send_labelled<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        label: &str,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, Some(label), msg).await
+    }
+
+    async fn recv_labelled<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        label: &str,
+    ) -> Result<M> {
+        self.recv_step(ep, from, Some(label)).await
+    }
+
+    async fn choose(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        who: Self::Role,
+        label: Label,
+    ) -> Result<()> {
+        self.inner.choose(ep, who, label).await
+    }
+
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        self.inner.offer(ep, from, labels).await
+    }
+
+    async fn with_timeout<F, T>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        at: Self::Role,
+        dur: Duration,
+        body: F,
+    ) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>> + Send,
+    {
+        self.inner.with_timeout(ep, at, dur, body).await
+    }
+}
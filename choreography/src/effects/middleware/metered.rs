@@ -0,0 +1,160 @@
+// Flow-cost metering middleware for effect handlers
+//
+// Spends the cost of every send that matches one of the flow charges generated
+// from `flow_cost` annotations from a `FlowMeter`. The charge is taken before
+// the inner handler runs. A send whose cost no longer fits fails with
+// `ChoreographyError::BudgetExceeded`, so a session never goes over budget.
+// Receives, choices, and offers are free.
+//
+// Sends are matched by the message name given to `send_labelled`. A plain
+// `send` to a peer with flow charges fails, as its cost cannot be told.
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+use std::time::Duration;
+
+use super::send_as;
+use crate::effects::{ChoreoHandler, ChoreographyError, Label, Result};
+use crate::runtime::flow::{FlowBudgetExceeded, FlowCharge, FlowMeter};
+
+/// Flow-cost metering middleware
+pub struct Metered<H> {
+    inner: H,
+    role: String,
+    meter: FlowMeter,
+    charges: &'static [FlowCharge],
+}
+
+impl<H: ChoreoHandler> Metered<H> {
+    /// Wrap `inner`, which runs `role`, spending `charges` from `meter`
+    pub fn new(inner: H, role: H::Role, meter: FlowMeter, charges: &'static [FlowCharge]) -> Self {
+        Self {
+            inner,
+            role: format!("{role:?}"),
+            meter,
+            charges,
+        }
+    }
+
+    pub fn meter(&self) -> &FlowMeter {
+        &self.meter
+    }
+
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+
+    fn charge(&self, peer: &H::Role, label: Option<&str>) -> Result<()> {
+        let peer = format!("{peer:?}");
+        let Some(label) = label else {
+            if self.charges.iter().any(|c| c.peer == peer) {
+                return Err(ChoreographyError::ProtocolViolation(format!(
+                    "send to {peer} has flow charges but no message name"
+                )));
+            }
+            return Ok(());
+        };
+        let cost: u64 = self
+            .charges
+            .iter()
+            .filter(|c| c.peer == peer && c.label == label)
+            .map(|c| c.cost)
+            .sum();
+        if cost == 0 || self.meter.try_spend(cost).is_some() {
+            return Ok(());
+        }
+        Err(FlowBudgetExceeded {
+            role: self.role.clone(),
+            peer,
+            label: label.to_string(),
+            cost,
+            remaining: self.meter.remaining(),
+            budget: self.meter.budget(),
+        }
+        .into())
+    }
+
+    async fn send_step<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        label: Option<&str>,
+        msg: &M,
+    ) -> Result<()> {
+        self.charge(&to, label)?;
+        send_as(&mut self.inner, ep, to, label, msg).await
+    }
+}
+
+#[async_trait]
+impl<H: ChoreoHandler + Send> ChoreoHandler for Metered<H> {
+    type Role = H::Role;
+    type Endpoint = H::Endpoint;
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, None, msg).await
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+    ) -> Result<M> {
+        self.inner.recv(ep, from).await
+    }
+
+    async fn send_labelled<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        label: &str,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, Some(label), msg).await
+    }
+
+    async fn recv_labelled<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        label: &str,
+    ) -> Result<M> {
+        self.inner.recv_labelled(ep, from, label).await
+    }
+
+    async fn choose(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        who: Self::Role,
+        label: Label,
+    ) -> Result<()> {
+        self.inner.choose(ep, who, label).await
+    }
+
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        self.inner.offer(ep, from, labels).await
+    }
+
+    async fn with_timeout<F, T>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        at: Self::Role,
+        dur: Duration,
+        body: F,
+    ) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>> + Send,
+    {
+        self.inner.with_timeout(ep, at, dur, body).await
+    }
+}
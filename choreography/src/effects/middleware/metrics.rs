@@ -6,6 +6,7 @@ use async_trait::async_trait;
 use serde::{de::DeserializeOwned, Serialize};
 use std::time::Duration;
 
+use super::{recv_as, send_as};
 use crate::effects::{ChoreoHandler, Label, Result};
 
 /// Metrics collection middleware
@@ -40,18 +41,15 @@ impl<H> Metrics<H> {
     }
 }
 
-#[async_trait]
-impl<H: ChoreoHandler + Send> ChoreoHandler for Metrics<H> {
-    type Role = H::Role;
-    type Endpoint = H::Endpoint;
-
-    async fn send<M: Serialize + Send + Sync>(
+impl<H: ChoreoHandler> Metrics<H> {
+    async fn send_step<M: Serialize + Send + Sync>(
         &mut self,
-        ep: &mut Self::Endpoint,
-        to: Self::Role,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        label: Option<&str>,
         msg: &M,
     ) -> Result<()> {
-        let result = self.inner.send(ep, to, msg).await;
+        let result = send_as(&mut self.inner, ep, to, label, msg).await;
         if result.is_ok() {
             self.send_count
                 .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
@@ -62,12 +60,13 @@ impl<H: ChoreoHandler + Send> ChoreoHandler for Metrics<H> {
         result
     }
 
-    async fn recv<M: DeserializeOwned + Send>(
+    async fn recv_step<M: DeserializeOwned + Send>(
         &mut self,
-        ep: &mut Self::Endpoint,
-        from: Self::Role,
+        ep: &mut H::Endpoint,
+        from: H::Role,
+        label: Option<&str>,
     ) -> Result<M> {
-        let result = self.inner.recv(ep, from).await;
+        let result = recv_as(&mut self.inner, ep, from, label).await;
         if result.is_ok() {
             self.recv_count
                 .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
@@ -77,6 +76,48 @@ impl<H: ChoreoHandler + Send> ChoreoHandler for Metrics<H> {
         }
         result
     }
+}
+
+#[async_trait]
+impl<H: ChoreoHandler + Send> ChoreoHandler for Metrics<H> {
+    type Role = H::Role;
+    type Endpoint = H::Endpoint;
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, None, msg).await
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+    ) -> Result<M> {
+        self.recv_step(ep, from, None).await
+    }
+
+    async fn send_labelled<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        label: &str,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, Some(label), msg).await
+    }
+
+    async fn recv_labelled<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        label: &str,
+    ) -> Result<M> {
+        self.recv_step(ep, from, Some(label)).await
+    }
 
     async fn choose(
         &mut self,
@@ -87,8 +128,13 @@ impl<H: ChoreoHandler + Send> ChoreoHandler for Metrics<H> {
         self.inner.choose(ep, who, label).await
     }
 
-    async fn offer(&mut self, ep: &mut Self::Endpoint, from: Self::Role) -> Result<Label> {
-        self.inner.offer(ep, from).await
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        self.inner.offer(ep, from, labels).await
     }
 
     async fn with_timeout<F, T>(
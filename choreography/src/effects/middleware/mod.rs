@@ -1,20 +1,116 @@
 // Middleware Implementations
 //
 // This module contains composable middleware layers that can wrap effect handlers
-// to add cross-cutting concerns like tracing, metrics, retries, and fault injection.
+// to add cross-cutting concerns like tracing, distributed tracing, metrics,
+// capability guards, flow-cost budgets, audit journaling, persistent outboxes,
+// end-to-end encryption, retries, cancellation, checkpointing, step debugging,
+// deadline propagation and fault injection.
 //
 // Middleware follows the decorator pattern, wrapping inner handlers and forwarding
-// operations while adding additional behavior.
+// operations while adding additional behavior. Message names given to
+// `send_labelled` and `recv_labelled` are forwarded along with the message,
+// also by middleware that wraps it in a frame, so that guards, journals and
+// flow charges deeper in the stack still see them.
 
+pub mod cancellation;
+pub mod checkpoint;
+pub mod deadlined;
+pub mod debugged;
+pub mod distributed_trace;
+#[cfg(feature = "secure")]
+pub mod encrypted;
 pub mod fault_injection;
+pub mod guarded;
+pub mod instrumented;
+pub mod journaled;
+pub mod metered;
 pub mod metrics;
+pub mod outboxed;
 pub mod retry;
 pub mod trace;
 
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::effects::{ChoreoHandler, Result};
+
 // Re-export middleware types for convenience
+pub use cancellation::{Cancellable, SessionHandle};
+pub use checkpoint::{Checkpoint, Checkpointer, Checkpointing, MemoryCheckpointer};
+pub use deadlined::Deadlined;
+pub use debugged::Debugged;
+pub use distributed_trace::Traced;
+pub use guarded::Guarded;
+pub use instrumented::Instrumented;
+pub use journaled::Journaled;
+pub use metered::Metered;
 pub use metrics::Metrics;
+pub use outboxed::Outboxed;
 pub use retry::Retry;
 pub use trace::Trace;
 
+#[cfg(not(target_arch = "wasm32"))]
+pub use checkpoint::FileCheckpointer;
+
+#[cfg(feature = "secure")]
+pub use encrypted::Encrypted;
+
 #[cfg(feature = "test-utils")]
 pub use fault_injection::FaultInjection;
+
+/// Send `msg` through `inner`, under its message name if it is known
+pub(crate) async fn send_as<H, M>(
+    inner: &mut H,
+    ep: &mut H::Endpoint,
+    to: H::Role,
+    label: Option<&str>,
+    msg: &M,
+) -> Result<()>
+where
+    H: ChoreoHandler,
+    M: Serialize + Send + Sync,
+{
+    match label {
+        Some(label) => inner.send_labelled(ep, to, label, msg).await,
+        None => inner.send(ep, to, msg).await,
+    }
+}
+
+/// Receive through `inner`, under the message name if it is known
+pub(crate) async fn recv_as<H, M>(
+    inner: &mut H,
+    ep: &mut H::Endpoint,
+    from: H::Role,
+    label: Option<&str>,
+) -> Result<M>
+where
+    H: ChoreoHandler,
+    M: DeserializeOwned + Send,
+{
+    match label {
+        Some(label) => inner.recv_labelled(ep, from, label).await,
+        None => inner.recv(ep, from).await,
+    }
+}
+
+/// Short name of a message type, used as its label in traces and metrics
+pub(crate) fn message_label<M>() -> &'static str {
+    type_label(std::any::type_name::<M>())
+}
+
+/// Short name of the type with the full name `name`
+pub(crate) fn type_label(name: &str) -> &str {
+    let base = name.split('<').next().unwrap_or(name);
+    base.rsplit("::").next().unwrap_or(base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_label() {
+        assert_eq!(message_label::<String>(), "String");
+        assert_eq!(message_label::<Vec<u8>>(), "Vec");
+        assert_eq!(message_label::<u32>(), "u32");
+    }
+}
@@ -0,0 +1,218 @@
+// Outbox middleware for effect handlers
+//
+// Persists every send to an `OutboxStore` before handing it to the inner
+// handler and acknowledges it once the inner send returns, so a message is
+// never lost between the role deciding to send it and the transport taking
+// it. Receives drop messages at or below the highest sequence number already
+// delivered from the peer, so retransmissions after a restart are not seen
+// twice. See `runtime::outbox` for the store side.
+//
+// Messages carry sequence numbers on the wire, so both ends of a link must
+// use `Outboxed`. Branch labels pass through untouched.
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
+use tracing::debug;
+
+use super::{message_label, recv_as, send_as};
+use crate::effects::{ChoreoHandler, ChoreographyError, Label, Result};
+use crate::runtime::outbox::{OutboxMessage, OutboxStore};
+
+/// Wire frame used by `Outboxed`
+#[derive(Serialize, Deserialize)]
+struct Sequenced {
+    seq: u64,
+    payload: Vec<u8>,
+}
+
+/// Outbox middleware
+pub struct Outboxed<H> {
+    inner: H,
+    role: String,
+    session_id: String,
+    store: Box<dyn OutboxStore>,
+    next_seq: u64,
+}
+
+impl<H: ChoreoHandler> Outboxed<H> {
+    /// Wrap `inner`, which runs `role` in `session_id`, persisting its sends
+    /// to `store`
+    ///
+    /// Sequence numbers continue after the last send `store` holds for the
+    /// role, so a restarted role reopens its store with the same session ID.
+    pub fn new(
+        inner: H,
+        role: H::Role,
+        session_id: impl fmt::Display,
+        mut store: impl OutboxStore + 'static,
+    ) -> Result<Self> {
+        let role = format!("{role:?}");
+        let session_id = session_id.to_string();
+        let next_seq = store.last_seq(&session_id, &role)?.unwrap_or(0) + 1;
+        Ok(Self {
+            inner,
+            role,
+            session_id,
+            store: Box::new(store),
+            next_seq,
+        })
+    }
+
+    /// Transmit the sends persisted but never acknowledged, such as the one
+    /// in flight when the process stopped
+    ///
+    /// Call once the transport to each of `peers` is re-established. Returns
+    /// the number of messages transmitted.
+    pub async fn flush(&mut self, ep: &mut H::Endpoint, peers: &[H::Role]) -> Result<usize> {
+        let pending = self.store.pending(&self.session_id, &self.role)?;
+        for message in &pending {
+            let peer = peers
+                .iter()
+                .copied()
+                .find(|peer| format!("{peer:?}") == message.peer)
+                .ok_or_else(|| ChoreographyError::UnknownRole(message.peer.clone()))?;
+            debug!(peer = %message.peer, seq = message.seq, "retransmitting pending send");
+            self.transmit(ep, peer, None, message.seq, message.payload.clone())
+                .await?;
+        }
+        Ok(pending.len())
+    }
+
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+
+    async fn send_step<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        label: Option<&str>,
+        msg: &M,
+    ) -> Result<()> {
+        let payload =
+            bincode::serialize(msg).map_err(|e| ChoreographyError::Serialization(e.to_string()))?;
+        let seq = self.next_seq;
+        self.store.persist(&OutboxMessage {
+            session_id: self.session_id.clone(),
+            role: self.role.clone(),
+            seq,
+            peer: format!("{to:?}"),
+            label: label.unwrap_or(message_label::<M>()).to_string(),
+            payload: payload.clone(),
+        })?;
+        self.next_seq += 1;
+        self.transmit(ep, to, label, seq, payload).await
+    }
+
+    async fn recv_step<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        from: H::Role,
+        label: Option<&str>,
+    ) -> Result<M> {
+        let peer = format!("{from:?}");
+        loop {
+            let frame: Sequenced = recv_as(&mut self.inner, ep, from, label).await?;
+            let last = self
+                .store
+                .last_delivered(&self.session_id, &self.role, &peer)?;
+            if last.is_some_and(|last| frame.seq <= last) {
+                debug!(%peer, seq = frame.seq, "dropping message delivered before");
+                continue;
+            }
+            let msg = bincode::deserialize(&frame.payload)
+                .map_err(|e| ChoreographyError::Serialization(e.to_string()))?;
+            self.store
+                .mark_delivered(&self.session_id, &self.role, &peer, frame.seq)?;
+            return Ok(msg);
+        }
+    }
+
+    async fn transmit(
+        &mut self,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        label: Option<&str>,
+        seq: u64,
+        payload: Vec<u8>,
+    ) -> Result<()> {
+        send_as(&mut self.inner, ep, to, label, &Sequenced { seq, payload }).await?;
+        self.store.acknowledge(&self.session_id, &self.role, seq)?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<H: ChoreoHandler + Send> ChoreoHandler for Outboxed<H> {
+    type Role = H::Role;
+    type Endpoint = H::Endpoint;
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, None, msg).await
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+    ) -> Result<M> {
+        self.recv_step(ep, from, None).await
+    }
+
+    async fn send_labelled<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        label: &str,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, Some(label), msg).await
+    }
+
+    async fn recv_labelled<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        label: &str,
+    ) -> Result<M> {
+        self.recv_step(ep, from, Some(label)).await
+    }
+
+    async fn choose(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        who: Self::Role,
+        label: Label,
+    ) -> Result<()> {
+        self.inner.choose(ep, who, label).await
+    }
+
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        self.inner.offer(ep, from, labels).await
+    }
+
+    async fn with_timeout<F, T>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        at: Self::Role,
+        dur: Duration,
+        body: F,
+    ) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>> + Send,
+    {
+        self.inner.with_timeout(ep, at, dur, body).await
+    }
+}
@@ -7,6 +7,7 @@ use serde::{de::DeserializeOwned, Serialize};
 use std::time::Duration;
 use tracing::debug;
 
+use super::send_as;
 use crate::effects::{ChoreoHandler, Label, Result};
 
 /// Retry middleware with exponential backoff
@@ -35,40 +36,43 @@ impl<H> Retry<H> {
     }
 }
 
-#[async_trait]
-impl<H: ChoreoHandler + Send> ChoreoHandler for Retry<H> {
-    type Role = H::Role;
-    type Endpoint = H::Endpoint;
-
-    async fn send<M: Serialize + Send + Sync>(
+impl<H: ChoreoHandler> Retry<H> {
+    async fn send_step<M: Serialize + Send + Sync>(
         &mut self,
-        ep: &mut Self::Endpoint,
-        to: Self::Role,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        label: Option<&str>,
         msg: &M,
     ) -> Result<()> {
         let mut retries = 0;
         loop {
-            match self.inner.send(ep, to, msg).await {
+            match send_as(&mut self.inner, ep, to, label, msg).await {
                 Ok(()) => return Ok(()),
                 Err(_e) if retries < self.max_retries => {
                     retries += 1;
                     let delay = self.base_delay * (1 << (retries - 1));
                     debug!(?to, ?retries, ?delay, "send failed, retrying");
-                    // Platform-specific sleep
-                    #[cfg(not(target_arch = "wasm32"))]
-                    {
-                        tokio::time::sleep(delay).await;
-                    }
-
-                    #[cfg(target_arch = "wasm32")]
-                    {
-                        wasm_timer::Delay::new(delay).await.ok();
-                    }
+                    crate::runtime::sleep(delay).await;
                 }
                 Err(e) => return Err(e),
             }
         }
     }
+}
+
+#[async_trait]
+impl<H: ChoreoHandler + Send> ChoreoHandler for Retry<H> {
+    type Role = H::Role;
+    type Endpoint = H::Endpoint;
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, None, msg).await
+    }
 
     async fn recv<M: DeserializeOwned + Send>(
         &mut self,
@@ -79,6 +83,25 @@ impl<H: ChoreoHandler + Send> ChoreoHandler for Retry<H> {
         self.inner.recv(ep, from).await
     }
 
+    async fn send_labelled<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        label: &str,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, Some(label), msg).await
+    }
+
+    async fn recv_labelled<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        label: &str,
+    ) -> Result<M> {
+        self.inner.recv_labelled(ep, from, label).await
+    }
+
     async fn choose(
         &mut self,
         ep: &mut Self::Endpoint,
@@ -88,8 +111,13 @@ impl<H: ChoreoHandler + Send> ChoreoHandler for Retry<H> {
         self.inner.choose(ep, who, label).await
     }
 
-    async fn offer(&mut self, ep: &mut Self::Endpoint, from: Self::Role) -> Result<Label> {
-        self.inner.offer(ep, from).await
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        self.inner.offer(ep, from, labels).await
     }
 
     async fn with_timeout<F, T>(
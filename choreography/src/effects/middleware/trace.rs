@@ -4,10 +4,12 @@
 
 use async_trait::async_trait;
 use serde::{de::DeserializeOwned, Serialize};
-use std::time::{Duration, Instant};
+use std::time::Duration;
 use tracing::{debug, trace, warn};
 
+use super::{recv_as, send_as};
 use crate::effects::{ChoreoHandler, Label, Result};
+use crate::runtime::Instant;
 
 /// Tracing middleware that logs all choreographic operations
 #[derive(Clone)]
@@ -29,20 +31,17 @@ impl<H> Trace<H> {
     }
 }
 
-#[async_trait]
-impl<H: ChoreoHandler + Send> ChoreoHandler for Trace<H> {
-    type Role = H::Role;
-    type Endpoint = H::Endpoint;
-
-    async fn send<M: Serialize + Send + Sync>(
+impl<H: ChoreoHandler> Trace<H> {
+    async fn send_step<M: Serialize + Send + Sync>(
         &mut self,
-        ep: &mut Self::Endpoint,
-        to: Self::Role,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        label: Option<&str>,
         msg: &M,
     ) -> Result<()> {
         let start = Instant::now();
         trace!(prefix = %self.prefix, ?to, "send: start");
-        let result = self.inner.send(ep, to, msg).await;
+        let result = send_as(&mut self.inner, ep, to, label, msg).await;
         let duration = start.elapsed();
         match &result {
             Ok(()) => debug!(prefix = %self.prefix, ?to, ?duration, "send: success"),
@@ -51,14 +50,15 @@ impl<H: ChoreoHandler + Send> ChoreoHandler for Trace<H> {
         result
     }
 
-    async fn recv<M: DeserializeOwned + Send>(
+    async fn recv_step<M: DeserializeOwned + Send>(
         &mut self,
-        ep: &mut Self::Endpoint,
-        from: Self::Role,
+        ep: &mut H::Endpoint,
+        from: H::Role,
+        label: Option<&str>,
     ) -> Result<M> {
         let start = Instant::now();
         trace!(prefix = %self.prefix, ?from, "recv: start");
-        let result = self.inner.recv(ep, from).await;
+        let result = recv_as(&mut self.inner, ep, from, label).await;
         let duration = start.elapsed();
         match &result {
             Ok(_) => debug!(prefix = %self.prefix, ?from, ?duration, "recv: success"),
@@ -66,6 +66,48 @@ impl<H: ChoreoHandler + Send> ChoreoHandler for Trace<H> {
         }
         result
     }
+}
+
+#[async_trait]
+impl<H: ChoreoHandler + Send> ChoreoHandler for Trace<H> {
+    type Role = H::Role;
+    type Endpoint = H::Endpoint;
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, None, msg).await
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+    ) -> Result<M> {
+        self.recv_step(ep, from, None).await
+    }
+
+    async fn send_labelled<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        label: &str,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, Some(label), msg).await
+    }
+
+    async fn recv_labelled<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        label: &str,
+    ) -> Result<M> {
+        self.recv_step(ep, from, Some(label)).await
+    }
 
     async fn choose(
         &mut self,
@@ -77,9 +119,14 @@ impl<H: ChoreoHandler + Send> ChoreoHandler for Trace<H> {
         self.inner.choose(ep, who, label).await
     }
 
-    async fn offer(&mut self, ep: &mut Self::Endpoint, from: Self::Role) -> Result<Label> {
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
         trace!(prefix = %self.prefix, ?from, "offer: waiting");
-        let label = self.inner.offer(ep, from).await?;
+        let label = self.inner.offer(ep, from, labels).await?;
         debug!(prefix = %self.prefix, ?from, ?label, "offer: received");
         Ok(label)
     }
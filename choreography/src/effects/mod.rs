@@ -12,7 +12,10 @@ pub mod handler;
 pub mod handlers;
 pub mod interpreter;
 pub mod middleware;
+pub mod policy;
 pub mod registry;
+pub mod session_error;
+pub mod session_metrics;
 
 // Re-export core effect system types explicitly
 pub use algebra::{
@@ -24,14 +27,23 @@ pub use handler::{
     RoleId,
 };
 pub use interpreter::{interpret, interpret_extensible};
+pub use policy::SessionPolicy;
 pub use registry::{ExtensibleHandler, ExtensionRegistry};
+pub use session_error::{session_result, SessionContext, SessionError, SessionResult};
+pub use session_metrics::SessionMetrics;
+
+#[cfg(feature = "metrics")]
+pub use session_metrics::GlobalMetrics;
 
 // Re-export handler implementations for convenience
 pub use handlers::{InMemoryHandler, RecordedEvent, RecordingHandler};
 pub use handlers::{RumpsteakEndpoint, RumpsteakHandler, RumpsteakSession, SimpleChannel};
 
 // Re-export middleware for convenience
-pub use middleware::{Metrics, Retry, Trace};
+pub use middleware::{
+    Cancellable, Checkpointer, Checkpointing, Guarded, Instrumented, MemoryCheckpointer, Metered,
+    Metrics, Retry, SessionHandle, Trace, Traced,
+};
 
 #[cfg(feature = "test-utils")]
 pub use middleware::FaultInjection;
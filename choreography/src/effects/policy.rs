@@ -0,0 +1,241 @@
+// Session policy: deadlines, retries, and message size limits
+//
+// A `SessionPolicy` is attached to an endpoint when it is constructed and is
+// applied by the handler to every operation on that endpoint:
+//
+// - default_timeout: deadline for each send/recv/choose/offer.
+// - retries: sends that fail with a transport error are retried with
+//   exponential backoff. The built-in sessions raise a transport error
+//   either before the message is handed to the channel, or when the
+//   connection fails partway through it, after which they refuse every
+//   later frame, so a retry never delivers it twice; custom sessions must
+//   do the same. A send that timed out may already have been delivered, or
+//   is finished by the next operation on the channel, so timeouts are only
+//   retried when `with_timeout_retries` opts in, for protocols whose
+//   receivers tolerate duplicates. Receives are never retried; one that
+//   timed out leaves what it read of a message for the next receive.
+// - max_message_size: outgoing and incoming payloads above the limit are
+//   rejected with `ChoreographyError::MessageTooLarge`.
+//
+// The default policy imposes no limits, matching the behaviour of endpoints
+// built without one.
+
+use futures::future::BoxFuture;
+use std::future::Future;
+use std::time::Duration;
+
+use crate::effects::{ChoreographyError, Result};
+use crate::runtime::{sleep, timeout};
+
+/// Deadline, retry, and size limits applied to endpoint operations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionPolicy {
+    /// Deadline applied to each individual operation
+    pub default_timeout: Option<Duration>,
+    /// Number of retries for sends that fail with a transient error
+    pub max_retries: usize,
+    /// Delay before the first retry; doubled on every further attempt
+    pub base_backoff: Duration,
+    /// Upper bound for the backoff delay
+    pub max_backoff: Duration,
+    /// Largest serialized payload accepted in either direction
+    pub max_message_size: Option<usize>,
+    /// Whether sends that time out are retried too, possibly delivering a
+    /// message twice
+    pub retry_timeouts: bool,
+}
+
+impl Default for SessionPolicy {
+    fn default() -> Self {
+        Self {
+            default_timeout: None,
+            max_retries: 0,
+            base_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            max_message_size: None,
+            retry_timeouts: false,
+        }
+    }
+}
+
+impl SessionPolicy {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.default_timeout = Some(timeout);
+        self
+    }
+
+    #[must_use]
+    pub fn with_retries(mut self, max_retries: usize, base_backoff: Duration) -> Self {
+        self.max_retries = max_retries;
+        self.base_backoff = base_backoff;
+        self
+    }
+
+    #[must_use]
+    pub fn with_max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    #[must_use]
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = Some(max_message_size);
+        self
+    }
+
+    /// Retry sends that time out as well, for protocols whose receivers
+    /// tolerate a message arriving twice
+    #[must_use]
+    pub fn with_timeout_retries(mut self) -> Self {
+        self.retry_timeouts = true;
+        self
+    }
+
+    /// Backoff before retry number `attempt` (starting at 1)
+    #[must_use]
+    pub fn backoff(&self, attempt: usize) -> Duration {
+        let shift = attempt.saturating_sub(1).min(31) as u32;
+        self.base_backoff
+            .saturating_mul(1u32 << shift)
+            .min(self.max_backoff)
+    }
+
+    /// Whether an error is worth retrying: transport failures, which are
+    /// raised before anything is sent
+    #[must_use]
+    pub fn is_transient(error: &ChoreographyError) -> bool {
+        matches!(error, ChoreographyError::Transport(_))
+    }
+
+    /// Whether a send that failed with `error` is retried under this policy
+    fn is_retryable(&self, error: &ChoreographyError) -> bool {
+        Self::is_transient(error)
+            || (self.retry_timeouts && matches!(error, ChoreographyError::Timeout(_)))
+    }
+
+    /// Reject payloads above the configured limit
+    pub fn check_size(&self, size: usize) -> Result<()> {
+        match self.max_message_size {
+            Some(max) if size > max => Err(ChoreographyError::MessageTooLarge { size, max }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Run a single operation under the configured deadline
+    pub async fn run<T>(&self, op: impl Future<Output = Result<T>>) -> Result<T> {
+        match self.default_timeout {
+            Some(dur) => timeout(dur, op).await,
+            None => op.await,
+        }
+    }
+
+    /// Run an operation against `target`, retrying transient failures with backoff
+    ///
+    /// `op` is invoked once per attempt and must be safe to repeat.
+    pub async fn run_with_retry<C, T, F>(&self, target: &mut C, mut op: F) -> Result<T>
+    where
+        C: ?Sized,
+        F: for<'a> FnMut(&'a mut C) -> BoxFuture<'a, Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match self.run(op(target)).await {
+                Err(e) if attempt < self.max_retries && self.is_retryable(&e) => {
+                    attempt += 1;
+                    let delay = self.backoff(attempt);
+                    tracing::debug!(attempt, ?delay, error = %e, "transient failure, retrying");
+                    sleep(delay).await;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_is_exponential_and_capped() {
+        let policy = SessionPolicy::new()
+            .with_retries(5, Duration::from_millis(100))
+            .with_max_backoff(Duration::from_millis(350));
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(350));
+        assert_eq!(policy.backoff(64), Duration::from_millis(350));
+    }
+
+    #[test]
+    fn test_check_size() {
+        let policy = SessionPolicy::new().with_max_message_size(4);
+        assert!(policy.check_size(4).is_ok());
+        assert!(matches!(
+            policy.check_size(5),
+            Err(ChoreographyError::MessageTooLarge { size: 5, max: 4 })
+        ));
+        assert!(SessionPolicy::new().check_size(usize::MAX).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_run_applies_deadline() {
+        let policy = SessionPolicy::new().with_timeout(Duration::from_millis(10));
+        let result: Result<()> = policy
+            .run(async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok(())
+            })
+            .await;
+        assert!(matches!(result, Err(ChoreographyError::Timeout(_))));
+    }
+
+    #[tokio::test]
+    async fn test_retry_only_transient_errors() {
+        let policy = SessionPolicy::new().with_retries(2, Duration::from_millis(1));
+
+        let mut calls = 0usize;
+        let result: Result<()> = policy
+            .run_with_retry(&mut calls, |calls| {
+                *calls += 1;
+                Box::pin(async { Err(ChoreographyError::Transport("flaky".into())) })
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 3);
+
+        let mut calls = 0usize;
+        let result: Result<()> = policy
+            .run_with_retry(&mut calls, |calls| {
+                *calls += 1;
+                Box::pin(async { Err(ChoreographyError::ProtocolViolation("bad".into())) })
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[tokio::test]
+    async fn test_timeouts_are_only_retried_when_opted_in() {
+        fn timed_out(calls: &mut usize) -> BoxFuture<'_, Result<()>> {
+            *calls += 1;
+            Box::pin(async { Err(ChoreographyError::Timeout(Duration::from_millis(10))) })
+        }
+
+        let policy = SessionPolicy::new().with_retries(2, Duration::from_millis(1));
+        let mut calls = 0usize;
+        assert!(policy.run_with_retry(&mut calls, timed_out).await.is_err());
+        assert_eq!(calls, 1);
+
+        let policy = policy.with_timeout_retries();
+        let mut calls = 0usize;
+        assert!(policy.run_with_retry(&mut calls, timed_out).await.is_err());
+        assert_eq!(calls, 3);
+    }
+}
@@ -0,0 +1,278 @@
+// Session failures
+//
+// `SessionError` sorts the ways a running session fails into a few classes
+// applications can branch on, instead of matching on error strings, and says
+// where the session was when it failed: the role, the step it was taking,
+// the peer of that step, the message types or branch labels it expected,
+// and the label it received instead, where they are known.
+//
+// Handlers keep reporting the finer-grained `ChoreographyError`, which
+// `SessionError::new` classifies. The interpreter attaches the step to the
+// error, and the generated `run_<role>` functions the role, so that a
+// failed session comes back as `Err(SessionError)`.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::effects::{ChoreographyError, InterpretResult};
+
+/// Result of running a session
+pub type SessionResult<T> = std::result::Result<T, SessionError>;
+
+/// Where a session was when it failed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SessionContext {
+    /// Role that failed, if known
+    pub role: Option<String>,
+    /// The step the role was taking, as in `receive from Server`
+    pub state: Option<String>,
+    /// Peer of that step
+    pub peer: Option<String>,
+    /// Message types or branch labels the step admitted
+    pub expected: Vec<String>,
+    /// Message type or branch label received instead, if one arrived
+    pub received: Option<String>,
+}
+
+impl SessionContext {
+    /// Context of a failure of `role` at an unknown step
+    #[must_use]
+    pub fn role(role: impl Into<String>) -> Self {
+        Self {
+            role: Some(role.into()),
+            ..Self::default()
+        }
+    }
+
+    /// Context of a failure at `state`, with `peer`
+    #[must_use]
+    pub fn step(state: impl Into<String>, peer: impl Into<String>) -> Self {
+        Self {
+            state: Some(state.into()),
+            peer: Some(peer.into()),
+            ..Self::default()
+        }
+    }
+
+    #[must_use]
+    pub fn expecting(mut self, expected: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.expected = expected.into_iter().map(Into::into).collect();
+        self
+    }
+
+    #[must_use]
+    pub fn received(mut self, received: impl Into<String>) -> Self {
+        self.received = Some(received.into());
+        self
+    }
+}
+
+impl fmt::Display for SessionContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(role) = &self.role {
+            parts.push(role.clone());
+        }
+        if let Some(state) = &self.state {
+            parts.push(format!("at `{state}`"));
+        }
+        if !self.expected.is_empty() {
+            parts.push(format!("expecting [{}]", self.expected.join(", ")));
+        }
+        if let Some(received) = &self.received {
+            parts.push(format!("received {received}"));
+        }
+        f.write_str(&parts.join(" "))
+    }
+}
+
+/// Why a session failed, and where
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionError {
+    /// A peer did not follow the protocol, or a step does not fit it
+    ProtocolViolation {
+        context: Box<SessionContext>,
+        message: String,
+    },
+    /// A message or label could not be delivered, or a peer failed
+    Transport {
+        context: Box<SessionContext>,
+        message: String,
+    },
+    /// A step took longer than its timeout
+    Timeout {
+        context: Box<SessionContext>,
+        after: Duration,
+    },
+    /// The session was cancelled locally or by a peer
+    Cancelled { context: Box<SessionContext> },
+    /// A guard, capability or flow budget refused a step
+    GuardDenied {
+        context: Box<SessionContext>,
+        message: String,
+    },
+    /// A message could not be encoded or decoded
+    CodecError {
+        context: Box<SessionContext>,
+        message: String,
+    },
+}
+
+impl SessionError {
+    /// Classify `error`, which happened at `context`
+    #[must_use]
+    pub fn new(error: &ChoreographyError, context: SessionContext) -> Self {
+        let context = Box::new(context);
+        let message = error.to_string();
+        match error {
+            ChoreographyError::ProtocolViolation(_)
+            | ChoreographyError::UnknownRole(_)
+            | ChoreographyError::Script(_) => Self::ProtocolViolation { context, message },
+            ChoreographyError::Transport(_)
+            | ChoreographyError::PeerFailed(_)
+            | ChoreographyError::Address(_)
+            | ChoreographyError::Journal(_)
+            | ChoreographyError::Outbox(_) => Self::Transport { context, message },
+            ChoreographyError::Timeout(after) => Self::Timeout {
+                context,
+                after: *after,
+            },
+            ChoreographyError::Cancelled => Self::Cancelled { context },
+            ChoreographyError::GuardDenied(_) | ChoreographyError::BudgetExceeded(_) => {
+                Self::GuardDenied { context, message }
+            }
+            ChoreographyError::Serialization(_) | ChoreographyError::MessageTooLarge { .. } => {
+                Self::CodecError { context, message }
+            }
+        }
+    }
+
+    /// Where the session was when it failed
+    #[must_use]
+    pub fn context(&self) -> &SessionContext {
+        match self {
+            Self::ProtocolViolation { context, .. }
+            | Self::Transport { context, .. }
+            | Self::Timeout { context, .. }
+            | Self::Cancelled { context }
+            | Self::GuardDenied { context, .. }
+            | Self::CodecError { context, .. } => context,
+        }
+    }
+
+    fn context_mut(&mut self) -> &mut SessionContext {
+        match self {
+            Self::ProtocolViolation { context, .. }
+            | Self::Transport { context, .. }
+            | Self::Timeout { context, .. }
+            | Self::Cancelled { context }
+            | Self::GuardDenied { context, .. }
+            | Self::CodecError { context, .. } => context,
+        }
+    }
+
+    /// Attribute the failure to `role`, unless it already names one
+    #[must_use]
+    pub fn with_role(mut self, role: impl Into<String>) -> Self {
+        let context = self.context_mut();
+        if context.role.is_none() {
+            context.role = Some(role.into());
+        }
+        self
+    }
+
+    /// Name of the class of the failure, as in `timeout`
+    #[must_use]
+    pub fn class(&self) -> &'static str {
+        match self {
+            Self::ProtocolViolation { .. } => "protocol violation",
+            Self::Transport { .. } => "transport failure",
+            Self::Timeout { .. } => "timeout",
+            Self::Cancelled { .. } => "cancelled",
+            Self::GuardDenied { .. } => "guard denied",
+            Self::CodecError { .. } => "codec error",
+        }
+    }
+
+    /// Whether retrying the session may succeed: transport failures and
+    /// timeouts
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Transport { .. } | Self::Timeout { .. })
+    }
+}
+
+/// What running the program of `role` came to: its result if it completed,
+/// or else the error that stopped it, attributed to `role`
+///
+/// # Errors
+///
+/// The error that stopped the program.
+pub fn session_result<M>(
+    role: &str,
+    result: crate::effects::Result<InterpretResult<M>>,
+) -> SessionResult<InterpretResult<M>> {
+    result
+        .map_err(SessionError::from)
+        .and_then(InterpretResult::into_result)
+        .map_err(|error| error.with_role(role))
+}
+
+impl From<ChoreographyError> for SessionError {
+    fn from(error: ChoreographyError) -> Self {
+        Self::new(&error, SessionContext::default())
+    }
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let context = self.context().to_string();
+        if !context.is_empty() {
+            write!(f, "{context}: ")?;
+        }
+        match self {
+            Self::ProtocolViolation { message, .. }
+            | Self::Transport { message, .. }
+            | Self::GuardDenied { message, .. }
+            | Self::CodecError { message, .. } => f.write_str(message),
+            Self::Timeout { after, .. } => write!(f, "timed out after {after:?}"),
+            Self::Cancelled { .. } => f.write_str("session cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for SessionError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_are_classified_with_their_context() {
+        let context = SessionContext::step("receive from Server", "Server")
+            .expecting(["Response"])
+            .received("Reject");
+        let error = SessionError::new(
+            &ChoreographyError::Serialization("bad frame".to_string()),
+            context,
+        )
+        .with_role("Client")
+        .with_role("Server");
+        assert!(matches!(error, SessionError::CodecError { .. }));
+        assert_eq!(error.context().role.as_deref(), Some("Client"));
+        assert_eq!(
+            error.to_string(),
+            "Client at `receive from Server` expecting [Response] received Reject: \
+             Serialization error: bad frame"
+        );
+
+        let error = SessionError::from(ChoreographyError::Timeout(Duration::from_secs(2)));
+        assert_eq!(error.class(), "timeout");
+        assert!(error.is_transient());
+        assert_eq!(error.to_string(), "timed out after 2s");
+        assert!(matches!(
+            SessionError::from(ChoreographyError::Cancelled),
+            SessionError::Cancelled { .. }
+        ));
+    }
+}
@@ -0,0 +1,200 @@
+// Session metrics hooks
+//
+// `SessionMetrics` receives one callback per protocol step: which message
+// labels flow between which roles, which branches are taken, and how long
+// each step took. Generated code wires an implementation in through the
+// `Instrumented` middleware. Every hook has an empty default, so an
+// implementation only overrides what it needs.
+//
+// With the `metrics` feature, `GlobalMetrics` forwards all hooks to the
+// recorder installed for the `metrics` crate, from where any of its exporters
+// (Prometheus, StatsD, ...) can publish them.
+
+use std::time::Duration;
+
+use crate::effects::{ChoreographyError, SessionError};
+use crate::runtime::monitor::ActionKind;
+
+/// Per-step observability hooks for a running session
+pub trait SessionMetrics: Send + Sync {
+    /// `role` sent a message labelled `label` to `to`
+    fn message_sent(&self, _role: &str, _to: &str, _label: &str) {}
+
+    /// `role` received a message labelled `label` from `from`
+    fn message_received(&self, _role: &str, _from: &str, _label: &str) {}
+
+    /// `role` selected `branch` and told `to`
+    fn branch_selected(&self, _role: &str, _to: &str, _branch: &str) {}
+
+    /// `role` was offered `branch` by `from`
+    fn branch_offered(&self, _role: &str, _from: &str, _branch: &str) {}
+
+    /// A step of `role` completed after `latency`
+    ///
+    /// For receives and offers this includes the time spent waiting for the
+    /// peer, which is where stalled sessions show up.
+    fn step_completed(&self, _role: &str, _step: ActionKind, _latency: Duration) {}
+
+    /// A step of `role` failed
+    fn step_failed(&self, _role: &str, _step: ActionKind, _error: &ChoreographyError) {}
+
+    /// A session of `role` started
+    fn session_started(&self, _role: &str) {}
+
+    /// A session of `role` ended after `duration`, with the error that ended
+    /// it if it failed
+    fn session_ended(&self, _role: &str, _duration: Duration, _error: Option<&SessionError>) {}
+
+    /// A timeout of `after` that `role` set on steps with `at` expired
+    fn timed_out(&self, _role: &str, _at: &str, _after: Duration) {}
+}
+
+/// Discards all metrics
+impl SessionMetrics for () {}
+
+/// Forwards session metrics to the global `metrics` recorder
+///
+/// Emitted series:
+///
+/// - `choreo_messages_sent_total{role, peer, label}`
+/// - `choreo_messages_received_total{role, peer, label}`
+/// - `choreo_branches_total{role, peer, branch, direction}` where `direction`
+///   is `selected` or `offered`
+/// - `choreo_step_duration_seconds{role, step}` (histogram)
+/// - `choreo_step_failures_total{role, step}`
+/// - `choreo_active_sessions{role}` (gauge)
+/// - `choreo_sessions_total{role, outcome}` where `outcome` is `completed`
+///   or `failed`
+/// - `choreo_timeouts_total{role}`
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GlobalMetrics;
+
+#[cfg(feature = "metrics")]
+impl GlobalMetrics {
+    #[must_use]
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl SessionMetrics for GlobalMetrics {
+    fn message_sent(&self, role: &str, to: &str, label: &str) {
+        metrics::counter!(
+            "choreo_messages_sent_total",
+            "role" => role.to_owned(),
+            "peer" => to.to_owned(),
+            "label" => label.to_owned(),
+        )
+        .increment(1);
+    }
+
+    fn message_received(&self, role: &str, from: &str, label: &str) {
+        metrics::counter!(
+            "choreo_messages_received_total",
+            "role" => role.to_owned(),
+            "peer" => from.to_owned(),
+            "label" => label.to_owned(),
+        )
+        .increment(1);
+    }
+
+    fn branch_selected(&self, role: &str, to: &str, branch: &str) {
+        metrics::counter!(
+            "choreo_branches_total",
+            "role" => role.to_owned(),
+            "peer" => to.to_owned(),
+            "branch" => branch.to_owned(),
+            "direction" => "selected",
+        )
+        .increment(1);
+    }
+
+    fn branch_offered(&self, role: &str, from: &str, branch: &str) {
+        metrics::counter!(
+            "choreo_branches_total",
+            "role" => role.to_owned(),
+            "peer" => from.to_owned(),
+            "branch" => branch.to_owned(),
+            "direction" => "offered",
+        )
+        .increment(1);
+    }
+
+    fn step_completed(&self, role: &str, step: ActionKind, latency: Duration) {
+        metrics::histogram!(
+            "choreo_step_duration_seconds",
+            "role" => role.to_owned(),
+            "step" => step.to_string(),
+        )
+        .record(latency.as_secs_f64());
+    }
+
+    fn step_failed(&self, role: &str, step: ActionKind, _error: &ChoreographyError) {
+        metrics::counter!(
+            "choreo_step_failures_total",
+            "role" => role.to_owned(),
+            "step" => step.to_string(),
+        )
+        .increment(1);
+    }
+
+    fn session_started(&self, role: &str) {
+        metrics::gauge!("choreo_active_sessions", "role" => role.to_owned()).increment(1.0);
+    }
+
+    fn session_ended(&self, role: &str, _duration: Duration, error: Option<&SessionError>) {
+        metrics::gauge!("choreo_active_sessions", "role" => role.to_owned()).decrement(1.0);
+        metrics::counter!(
+            "choreo_sessions_total",
+            "role" => role.to_owned(),
+            "outcome" => if error.is_some() { "failed" } else { "completed" },
+        )
+        .increment(1);
+    }
+
+    fn timed_out(&self, role: &str, _at: &str, _after: Duration) {
+        metrics::counter!("choreo_timeouts_total", "role" => role.to_owned()).increment(1);
+    }
+}
+
+#[cfg(all(test, feature = "metrics"))]
+mod tests {
+    use super::*;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    #[test]
+    fn test_global_metrics_records_series() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        metrics::with_local_recorder(&recorder, || {
+            let m = GlobalMetrics::new();
+            m.message_sent("Alice", "Bob", "Ping");
+            m.message_sent("Alice", "Bob", "Ping");
+            m.branch_offered("Alice", "Bob", "accept");
+            m.step_completed("Alice", ActionKind::Send, Duration::from_millis(5));
+        });
+
+        let snapshot = snapshotter.snapshot().into_vec();
+        let value = |name: &str| {
+            snapshot
+                .iter()
+                .find(|(key, ..)| key.key().name() == name)
+                .map(|(.., value)| value)
+        };
+        assert_eq!(
+            value("choreo_messages_sent_total"),
+            Some(&DebugValue::Counter(2))
+        );
+        assert_eq!(
+            value("choreo_branches_total"),
+            Some(&DebugValue::Counter(1))
+        );
+        assert!(matches!(
+            value("choreo_step_duration_seconds"),
+            Some(DebugValue::Histogram(samples)) if samples.len() == 1
+        ));
+    }
+}
@@ -4,11 +4,13 @@
 //! Extensions can add new grammar rules, custom statement parsers, and protocol behaviors
 //! while maintaining compatibility with the core choreographic infrastructure.
 
-use crate::ast::{LocalType, Role};
+use crate::ast::{LocalType, MessageType, Protocol, Role};
 use crate::compiler::projection::ProjectionError;
+use annotations::{AnnotationSchema, AnnotationSpec};
 use std::any::{Any, TypeId};
 use std::collections::HashMap;
 use std::fmt::Debug;
+use std::sync::Arc;
 
 /// Documentation for an extension
 #[derive(Debug, Clone)]
@@ -41,6 +43,51 @@ pub struct ExtensionExample {
     pub expected_output: Option<String>,
 }
 
+/// Named place in the base grammar that extensions add alternatives to
+///
+/// Each point is a rule of `choreography.pest` that matches nothing on its
+/// own. Grammar composition replaces its body with a choice of the rules
+/// extensions register for it, tried before the built-in alternatives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExtensionPoint {
+    /// Alternative to the built-in statements, after any annotations
+    Statement,
+    /// Alternative to `@name(...)` and `[@name = value]` annotations
+    Annotation,
+    /// Alternative to literal annotation values
+    Expression,
+    /// Modifier between a choice branch label and its `:`
+    ChoiceModifier,
+    /// Alternative to a plain role in the `roles:` list
+    RoleDeclaration,
+    /// Item between the role declarations and the protocol body
+    Item,
+}
+
+impl ExtensionPoint {
+    pub const ALL: [ExtensionPoint; 6] = [
+        ExtensionPoint::Statement,
+        ExtensionPoint::Annotation,
+        ExtensionPoint::Expression,
+        ExtensionPoint::ChoiceModifier,
+        ExtensionPoint::RoleDeclaration,
+        ExtensionPoint::Item,
+    ];
+
+    /// Name of the base grammar rule standing for this point
+    #[must_use]
+    pub fn rule_name(self) -> &'static str {
+        match self {
+            ExtensionPoint::Statement => "extension_statement",
+            ExtensionPoint::Annotation => "extension_annotation",
+            ExtensionPoint::Expression => "extension_expression",
+            ExtensionPoint::ChoiceModifier => "extension_choice_modifier",
+            ExtensionPoint::RoleDeclaration => "extension_role_declaration",
+            ExtensionPoint::Item => "extension_item",
+        }
+    }
+}
+
 /// Trait for adding new grammar rules to the choreographic DSL
 pub trait GrammarExtension: Send + Sync + Debug {
     /// Return the Pest grammar rules this extension provides
@@ -49,6 +96,48 @@ pub trait GrammarExtension: Send + Sync + Debug {
     /// List of statement rule names this extension handles
     fn statement_rules(&self) -> Vec<&'static str>;
 
+    /// Annotation forms this extension adds, such as `@retry(3)`
+    fn annotation_rules(&self) -> Vec<&'static str> {
+        vec![]
+    }
+
+    /// Annotation value forms this extension adds, such as `5s`
+    fn expression_rules(&self) -> Vec<&'static str> {
+        vec![]
+    }
+
+    /// Choice branch modifiers this extension adds, such as `weight(3)` in
+    /// `fast weight(3): { ... }`
+    fn choice_modifier_rules(&self) -> Vec<&'static str> {
+        vec![]
+    }
+
+    /// Role declaration forms this extension adds to the `roles:` list
+    fn role_declaration_rules(&self) -> Vec<&'static str> {
+        vec![]
+    }
+
+    /// Items this extension adds after the role declarations, such as a
+    /// `policy { ... }` block
+    fn item_rules(&self) -> Vec<&'static str> {
+        vec![]
+    }
+
+    /// Rule names this extension adds at `point`
+    ///
+    /// Dispatches to the method for each point. Override it only to compute
+    /// the rules of all points in one place.
+    fn extension_point_rules(&self, point: ExtensionPoint) -> Vec<&'static str> {
+        match point {
+            ExtensionPoint::Statement => self.statement_rules(),
+            ExtensionPoint::Annotation => self.annotation_rules(),
+            ExtensionPoint::Expression => self.expression_rules(),
+            ExtensionPoint::ChoiceModifier => self.choice_modifier_rules(),
+            ExtensionPoint::RoleDeclaration => self.role_declaration_rules(),
+            ExtensionPoint::Item => self.item_rules(),
+        }
+    }
+
     /// Priority for conflict resolution (higher = more precedence)
     fn priority(&self) -> u32 {
         100
@@ -56,6 +145,23 @@ pub trait GrammarExtension: Send + Sync + Debug {
 
     /// Extension identifier for debugging and registration
     fn extension_id(&self) -> &'static str;
+
+    /// Annotations this extension understands, checked by the parser
+    fn annotation_specs(&self) -> Vec<AnnotationSpec> {
+        vec![]
+    }
+
+    /// Ids of extensions that must be registered alongside this one
+    ///
+    /// Required extensions are composed into the grammar before this one.
+    fn requires(&self) -> Vec<&'static str> {
+        vec![]
+    }
+
+    /// Ids of extensions this one cannot be registered together with
+    fn conflicts_with(&self) -> Vec<&'static str> {
+        vec![]
+    }
 }
 
 /// Trait for self-documenting extensions
@@ -128,10 +234,97 @@ pub trait ProtocolExtension: Send + Sync + Debug {
     fn type_id(&self) -> TypeId;
 }
 
+/// Hook into the projection of every protocol node
+///
+/// Hooks run for plain sends, choices, and loops as well as for extension
+/// statements, in registration order. `before_project` may take over the
+/// projection of a node; `after_project` receives the local type the node
+/// projected to, continuation included, and may wrap or rewrite it.
+pub trait ProjectionHook: Send + Sync + Debug {
+    /// Unique identifier for this hook
+    fn hook_id(&self) -> &'static str;
+
+    /// Called before a node is projected
+    ///
+    /// Returning `Some` replaces the core projection of the node and its
+    /// continuation. The remaining `before_project` hooks are skipped, but
+    /// `after_project` hooks still run.
+    fn before_project(
+        &self,
+        _protocol: &Protocol,
+        _context: &ProjectionContext,
+    ) -> Result<Option<LocalType>, ProjectionError> {
+        Ok(None)
+    }
+
+    /// Called with the projection of a node
+    fn after_project(
+        &self,
+        _protocol: &Protocol,
+        _context: &ProjectionContext,
+        local_type: LocalType,
+    ) -> Result<LocalType, ProjectionError> {
+        Ok(local_type)
+    }
+}
+
+/// Message exchange the code generator is emitting an effect for
+#[derive(Debug)]
+pub struct MessageSite<'a> {
+    /// Role whose program is being generated
+    pub role: &'a Role,
+    /// Role on the other side of the exchange
+    pub peer: &'a Role,
+    pub message: &'a MessageType,
+    /// Statement the exchange comes from, with its annotations
+    pub protocol: &'a Protocol,
+}
+
+/// Hook into effect program generation
+///
+/// Lets an extension add items to the generated module and rewrite the
+/// generated sends and receives, e.g. to emit guard checks or journal writes
+/// without changing the core code generator. Hooks run in registration order,
+/// each wrapping the output of the previous one.
+pub trait CodegenHook: Send + Sync + Debug {
+    /// Unique identifier for this hook
+    fn hook_id(&self) -> &'static str;
+
+    /// Items appended to the generated module, such as helper types or impl blocks
+    fn module_items(&self, _context: &CodegenContext) -> proc_macro2::TokenStream {
+        proc_macro2::TokenStream::new()
+    }
+
+    /// Extra bounds on the handler type `H` of every generated `run_*` function
+    fn handler_bounds(&self, _context: &CodegenContext) -> Vec<proc_macro2::TokenStream> {
+        Vec::new()
+    }
+
+    /// Rewrite the program builder call generated for a send, e.g.
+    /// `.send(Role::Server, Request::default())`
+    fn wrap_send(
+        &self,
+        _site: &MessageSite,
+        effect: proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        effect
+    }
+
+    /// Rewrite the program builder call generated for a receive, e.g.
+    /// `.recv::<Request>(Role::Client)`
+    fn wrap_recv(
+        &self,
+        _site: &MessageSite,
+        effect: proc_macro2::TokenStream,
+    ) -> proc_macro2::TokenStream {
+        effect
+    }
+}
+
 /// Registry for managing DSL extensions with conflict resolution
 #[derive(Debug, Default)]
 pub struct ExtensionRegistry {
-    grammar_extensions: HashMap<String, Box<dyn GrammarExtension>>,
+    grammar_extensions: HashMap<String, Arc<dyn GrammarExtension>>,
     statement_parsers: HashMap<String, Box<dyn StatementParser>>,
     rule_to_parser: HashMap<String, String>,
     /// Track rule conflicts for resolution
@@ -140,6 +333,12 @@ pub struct ExtensionRegistry {
     extension_dependencies: HashMap<String, Vec<String>>,
     /// Extension version information for compatibility checking
     extension_versions: HashMap<String, String>,
+    /// Annotation specs declared by registered extensions
+    annotation_schema: AnnotationSchema,
+    /// Hooks run during projection, in registration order
+    projection_hooks: Vec<Arc<dyn ProjectionHook>>,
+    /// Hooks run during code generation, in registration order
+    codegen_hooks: Vec<Arc<dyn CodegenHook>>,
 }
 
 impl ExtensionRegistry {
@@ -152,45 +351,102 @@ impl ExtensionRegistry {
     pub fn register_grammar<T: GrammarExtension + 'static>(
         &mut self,
         extension: T,
+    ) -> Result<(), ParseError> {
+        self.register_grammar_shared(Arc::new(extension))
+    }
+
+    /// Register a grammar extension given as a trait object
+    ///
+    /// Use this for extension sets assembled at runtime, such as extensions
+    /// loaded from other crates or kept in a `Vec<Arc<dyn GrammarExtension>>`.
+    /// The same extension can be shared between several registries.
+    pub fn register_grammar_shared(
+        &mut self,
+        extension: Arc<dyn GrammarExtension>,
     ) -> Result<(), ParseError> {
         let id = extension.extension_id().to_string();
         let rules = extension.statement_rules();
         let priority = extension.priority();
+        let specs = extension.annotation_specs();
+
+        // Reject annotation keys already declared differently by another extension
+        for spec in &specs {
+            if let Some(owner) = self.annotation_schema.conflict(spec) {
+                return Err(ParseError::Conflict {
+                    message: format!(
+                        "Extension '{}' declares annotation '@{}' differently than '{}'",
+                        id, spec.key, owner
+                    ),
+                });
+            }
+        }
+
+        // Reject extensions declared incompatible, in either direction
+        let conflicts = extension.conflicts_with();
+        for (other_id, other) in &self.grammar_extensions {
+            if *other_id != id
+                && (conflicts.contains(&other_id.as_str())
+                    || other.conflicts_with().contains(&id.as_str()))
+            {
+                let other_rules = other.statement_rules();
+                return Err(ParseError::ExtensionConflict {
+                    extension: id,
+                    other: other_id.clone(),
+                    rules: rules
+                        .iter()
+                        .filter(|rule| other_rules.contains(rule))
+                        .map(|rule| rule.to_string())
+                        .collect(),
+                });
+            }
+        }
 
-        // Check for conflicts and resolve by priority
+        // Resolve overlapping rules by priority before changing any state
+        let mut claimed = Vec::new();
         for rule in &rules {
-            if let Some(existing_id) = self.rule_to_parser.get(*rule) {
-                let existing_priority = self
-                    .grammar_extensions
-                    .get(existing_id)
-                    .map(|e| e.priority())
-                    .unwrap_or(0);
+            match self.rule_to_parser.get(*rule) {
+                Some(existing_id) if *existing_id != id => {
+                    let existing_priority = self
+                        .grammar_extensions
+                        .get(existing_id)
+                        .map(|e| e.priority())
+                        .unwrap_or(0);
 
-                if priority > existing_priority {
-                    // New extension wins, record conflict
-                    self.rule_conflicts
-                        .entry(rule.to_string())
-                        .or_default()
-                        .push(existing_id.clone());
-                    self.rule_to_parser.insert(rule.to_string(), id.clone());
-                } else if priority == existing_priority {
-                    // Equal priority - this is a conflict
-                    return Err(ParseError::PriorityConflict {
-                        extension1: existing_id.clone(),
-                        extension2: id.clone(),
-                        priority1: existing_priority,
-                        priority2: priority,
-                        rule: rule.to_string(),
-                    });
+                    if priority > existing_priority {
+                        // New extension wins, record conflict
+                        claimed.push((*rule, Some(existing_id.clone())));
+                    } else if priority == existing_priority {
+                        // Equal priority - this is a conflict
+                        return Err(ParseError::PriorityConflict {
+                            extension1: existing_id.clone(),
+                            extension2: id.clone(),
+                            priority1: existing_priority,
+                            priority2: priority,
+                            rule: rule.to_string(),
+                        });
+                    }
+                    // Lower priority - existing extension wins
                 }
-                // Lower priority - existing extension wins
-            } else {
-                self.rule_to_parser.insert(rule.to_string(), id.clone());
+                _ => claimed.push((*rule, None)),
+            }
+        }
+        for (rule, shadowed) in claimed {
+            if let Some(existing_id) = shadowed {
+                self.rule_conflicts
+                    .entry(rule.to_string())
+                    .or_default()
+                    .push(existing_id);
             }
+            self.rule_to_parser.insert(rule.to_string(), id.clone());
         }
 
-        self.grammar_extensions
-            .insert(id.clone(), Box::new(extension));
+        for required in extension.requires() {
+            self.add_dependency(&id, required);
+        }
+        for spec in specs {
+            self.annotation_schema.declare(spec, &id).ok();
+        }
+        self.grammar_extensions.insert(id.clone(), extension);
         // Set default version if not specified
         self.extension_versions
             .entry(id)
@@ -219,29 +475,169 @@ impl ExtensionRegistry {
         self.statement_parsers.insert(parser_id, Box::new(parser));
     }
 
+    /// Register a projection hook
+    pub fn register_projection_hook<T: ProjectionHook + 'static>(&mut self, hook: T) {
+        self.projection_hooks.push(Arc::new(hook));
+    }
+
+    /// Projection hooks in registration order
+    pub fn projection_hooks(&self) -> &[Arc<dyn ProjectionHook>] {
+        &self.projection_hooks
+    }
+
+    /// Register a code generation hook
+    pub fn register_codegen_hook<T: CodegenHook + 'static>(&mut self, hook: T) {
+        self.codegen_hooks.push(Arc::new(hook));
+    }
+
+    /// Code generation hooks in registration order
+    pub fn codegen_hooks(&self) -> &[Arc<dyn CodegenHook>] {
+        &self.codegen_hooks
+    }
+
     /// Get all grammar rules from registered extensions
-    pub fn compose_grammar(&self, base_grammar: &str) -> String {
+    ///
+    /// Extensions are composed in `resolution_order`. Definitions of statement
+    /// rules taken over by a higher-priority extension are left out, so the
+    /// composed grammar defines every rule once.
+    pub fn compose_grammar(&self, base_grammar: &str) -> Result<String, ParseError> {
         let mut composed = base_grammar.to_string();
 
-        // Sort extensions by priority (highest first)
-        let mut extensions: Vec<_> = self.grammar_extensions.values().collect();
-        extensions.sort_by_key(|b| std::cmp::Reverse(b.priority()));
+        for id in self.resolution_order()? {
+            let extension = &self.grammar_extensions[id];
+            let shadowed = self.shadowed_rules(id);
 
-        for extension in extensions {
             composed.push('\n');
-            composed.push_str(extension.grammar_rules());
+            if shadowed.is_empty() {
+                composed.push_str(extension.grammar_rules());
+                continue;
+            }
+            for (name, definition) in split_grammar_rules(extension.grammar_rules()) {
+                if !shadowed.contains(&name) {
+                    composed.push_str(definition);
+                    composed.push('\n');
+                }
+            }
+        }
+
+        Ok(composed)
+    }
+
+    /// Statement rules of `extension_id` taken over by a higher-priority extension
+    pub fn shadowed_rules(&self, extension_id: &str) -> Vec<&'static str> {
+        self.grammar_extensions
+            .get(extension_id)
+            .map(|extension| {
+                extension
+                    .statement_rules()
+                    .into_iter()
+                    .filter(|rule| {
+                        self.rule_to_parser
+                            .get(*rule)
+                            .is_some_and(|owner| owner != extension_id)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Rules added at `point` by all extensions, highest priority first
+    ///
+    /// Pest tries alternatives in order, so this order decides which
+    /// extension wins when the rules of several match the same input. Unlike
+    /// `resolution_order`, a dependency does not move an extension ahead of
+    /// higher-priority ones. Equal priorities are ordered by extension id.
+    /// Shadowed statement rules are left out and each rule is listed once.
+    pub fn extension_point_rules(
+        &self,
+        point: ExtensionPoint,
+    ) -> Result<Vec<&'static str>, ParseError> {
+        let mut order = self.resolution_order()?;
+        order.sort_by_key(|id| {
+            (
+                std::cmp::Reverse(self.grammar_extensions[*id].priority()),
+                *id,
+            )
+        });
+
+        let mut rules: Vec<&'static str> = Vec::new();
+        for id in order {
+            let shadowed = self.shadowed_rules(id);
+            for rule in self.grammar_extensions[id].extension_point_rules(point) {
+                if !shadowed.contains(&rule) && !rules.contains(&rule) {
+                    rules.push(rule);
+                }
+            }
         }
+        Ok(rules)
+    }
+
+    /// Registered extension ids with every extension after the ones it requires
+    ///
+    /// Extensions that do not depend on each other are ordered by priority,
+    /// highest first, then by id.
+    pub fn resolution_order(&self) -> Result<Vec<&str>, ParseError> {
+        self.validate_dependencies_of_registered()?;
+
+        let mut remaining: Vec<&str> = self.grammar_extensions.keys().map(String::as_str).collect();
+        remaining.sort_by_key(|id| {
+            (
+                std::cmp::Reverse(self.grammar_extensions[*id].priority()),
+                *id,
+            )
+        });
+
+        let mut order: Vec<&str> = Vec::with_capacity(remaining.len());
+        while !remaining.is_empty() {
+            let ready = remaining.iter().position(|id| {
+                self.extension_dependencies
+                    .get(*id)
+                    .map_or(true, |required| {
+                        required.iter().all(|r| order.contains(&r.as_str()))
+                    })
+            });
+            match ready {
+                Some(index) => order.push(remaining.remove(index)),
+                None => {
+                    return Err(ParseError::DependencyCycle {
+                        extensions: remaining.iter().map(|id| id.to_string()).collect(),
+                    })
+                }
+            }
+        }
+        Ok(order)
+    }
 
-        composed
+    /// Annotation specs declared by all registered extensions
+    pub fn annotation_schema(&self) -> &AnnotationSchema {
+        &self.annotation_schema
     }
 
     /// Find parser for a given rule name
+    ///
+    /// Rules added at points other than statements are parsed by the parser
+    /// registered under the id of the extension adding them.
     pub fn find_parser(&self, rule_name: &str) -> Option<&dyn StatementParser> {
-        if let Some(parser_id) = self.rule_to_parser.get(rule_name) {
-            self.statement_parsers.get(parser_id).map(|p| p.as_ref())
-        } else {
-            None
-        }
+        let parser_id = match self.rule_to_parser.get(rule_name) {
+            Some(parser_id) => parser_id.as_str(),
+            None => self.point_rule_owner(rule_name)?,
+        };
+        self.statement_parsers.get(parser_id).map(|p| p.as_ref())
+    }
+
+    /// Extension adding `rule_name` at a point other than statements, the
+    /// highest-priority one if several do
+    fn point_rule_owner(&self, rule_name: &str) -> Option<&str> {
+        self.grammar_extensions
+            .iter()
+            .filter(|(_, extension)| {
+                ExtensionPoint::ALL.iter().any(|point| {
+                    *point != ExtensionPoint::Statement
+                        && extension.extension_point_rules(*point).contains(&rule_name)
+                })
+            })
+            .min_by_key(|(id, extension)| (std::cmp::Reverse(extension.priority()), *id))
+            .map(|(id, _)| id.as_str())
     }
 
     /// Check if a rule is handled by an extension
@@ -254,11 +650,52 @@ impl ExtensionRegistry {
         !self.grammar_extensions.is_empty() || !self.statement_parsers.is_empty()
     }
 
+    /// Check if any extension adds to the grammar
+    ///
+    /// Without one the statically derived parser handles every input.
+    pub fn has_grammar_extensions(&self) -> bool {
+        !self.grammar_extensions.is_empty()
+    }
+
+    /// Hash of the registered grammar extensions and their rules
+    ///
+    /// Registries with the same extension set have the same hash whatever
+    /// order the extensions were registered in, so it keys caches of
+    /// composed grammars.
+    pub fn grammar_hash(&self) -> u64 {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = DefaultHasher::new();
+        let mut ids: Vec<&String> = self.grammar_extensions.keys().collect();
+        ids.sort();
+        for id in ids {
+            let extension = &self.grammar_extensions[id];
+            id.hash(&mut hasher);
+            extension.priority().hash(&mut hasher);
+            extension.requires().hash(&mut hasher);
+            extension.grammar_rules().hash(&mut hasher);
+            for point in ExtensionPoint::ALL {
+                extension.extension_point_rules(point).hash(&mut hasher);
+            }
+        }
+        // Which extension owns a contested rule decides what is shadowed
+        let mut owners: Vec<(&String, &String)> = self.rule_to_parser.iter().collect();
+        owners.sort();
+        owners.hash(&mut hasher);
+        hasher.finish()
+    }
+
     /// Get all grammar extensions
     pub fn grammar_extensions(&self) -> impl Iterator<Item = &dyn GrammarExtension> {
         self.grammar_extensions.values().map(|e| e.as_ref())
     }
 
+    /// Shared handle to a registered grammar extension
+    pub fn grammar_extension(&self, extension_id: &str) -> Option<Arc<dyn GrammarExtension>> {
+        self.grammar_extensions.get(extension_id).cloned()
+    }
+
     /// Check if a specific extension is registered
     pub fn has_extension(&self, extension_id: &str) -> bool {
         self.grammar_extensions.contains_key(extension_id)
@@ -276,10 +713,13 @@ impl ExtensionRegistry {
 
     /// Add dependency between extensions
     pub fn add_dependency(&mut self, dependent: &str, required: &str) {
-        self.extension_dependencies
+        let requirements = self
+            .extension_dependencies
             .entry(dependent.to_string())
-            .or_default()
-            .push(required.to_string());
+            .or_default();
+        if !requirements.iter().any(|r| r == required) {
+            requirements.push(required.to_string());
+        }
     }
 
     /// Validate all extension dependencies are satisfied
@@ -297,6 +737,26 @@ impl ExtensionRegistry {
         Ok(())
     }
 
+    /// Check the requirements of registered extensions only
+    fn validate_dependencies_of_registered(&self) -> Result<(), ParseError> {
+        for dependent in self.grammar_extensions.keys() {
+            for required in self
+                .extension_dependencies
+                .get(dependent)
+                .into_iter()
+                .flatten()
+            {
+                if !self.grammar_extensions.contains_key(required) {
+                    return Err(ParseError::MissingDependency {
+                        extension: dependent.clone(),
+                        dependency: required.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
     /// Get all rule conflicts for debugging
     pub fn get_conflicts(&self) -> &HashMap<String, Vec<String>> {
         &self.rule_conflicts
@@ -517,6 +977,17 @@ pub enum ParseError {
         rule: String,
     },
 
+    #[error("Extension conflict: '{extension}' cannot be registered together with '{other}'{}", competing_rules(.rules))]
+    ExtensionConflict {
+        extension: String,
+        other: String,
+        /// Statement rules both extensions define
+        rules: Vec<String>,
+    },
+
+    #[error("Extension dependency cycle between {}", .extensions.join(", "))]
+    DependencyCycle { extensions: Vec<String> },
+
     #[error("Missing dependency: Extension '{extension}' requires '{dependency}' which is not registered. Please register the required extension first.")]
     MissingDependency {
         extension: String,
@@ -534,6 +1005,72 @@ pub enum ParseError {
     IncompatibleExtensions { details: String },
 }
 
+fn competing_rules(rules: &[String]) -> String {
+    if rules.is_empty() {
+        String::new()
+    } else {
+        format!(" (competing rules: {})", rules.join(", "))
+    }
+}
+
+/// Split Pest grammar text into its top-level rule definitions
+///
+/// Returns each rule name with its full definition. Comments before a rule
+/// are kept with it.
+fn split_grammar_rules(grammar: &str) -> Vec<(&str, &str)> {
+    let mut rules = Vec::new();
+    let mut start = None;
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut in_comment = false;
+    let mut escaped = false;
+    let mut chars = grammar.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        if in_comment {
+            in_comment = c != '\n';
+            continue;
+        }
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        if c.is_whitespace() {
+            continue;
+        }
+        if depth == 0 && start.is_none() {
+            start = Some(i);
+        }
+        match c {
+            '/' if chars.peek().map(|(_, next)| *next) == Some('/') => in_comment = true,
+            '"' => in_string = true,
+            '{' => depth += 1,
+            '}' => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    if let Some(s) = start.take() {
+                        let definition = &grammar[s..=i];
+                        let name = definition
+                            .lines()
+                            .find(|line| !line.trim_start().starts_with("//"))
+                            .and_then(|line| line.split('=').next())
+                            .unwrap_or_default()
+                            .trim();
+                        rules.push((name, definition));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    rules
+}
+
 /// Validation errors for protocol extensions
 #[derive(Debug, thiserror::Error)]
 pub enum ExtensionValidationError {
@@ -562,7 +1099,11 @@ pub trait RegisterExtension {
     fn register_all(registry: &mut ExtensionRegistry);
 }
 
+pub mod annotations;
+pub mod define;
 pub mod discovery;
+#[cfg(all(feature = "dynamic-extensions", unix))]
+pub mod dynamic;
 /// Built-in extensions
 pub mod timeout;
 
@@ -600,7 +1141,7 @@ mod tests {
 
         // Test grammar composition
         let base = "basic_rule = { \"test\" }";
-        let composed = registry.compose_grammar(base);
+        let composed = registry.compose_grammar(base).unwrap();
         assert!(composed.contains("basic_rule"));
         assert!(composed.contains("timeout_stmt"));
     }
@@ -711,6 +1252,150 @@ mod tests {
             .any(|(name, version)| name == "mock_timeout" && version == "1.0.0"));
     }
 
+    /// Extension with configurable id, rules, priority, and relations
+    #[derive(Debug)]
+    struct RelatedExt {
+        id: &'static str,
+        grammar: &'static str,
+        rules: Vec<&'static str>,
+        priority: u32,
+        requires: Vec<&'static str>,
+        conflicts_with: Vec<&'static str>,
+    }
+
+    impl RelatedExt {
+        fn new(id: &'static str, grammar: &'static str, rules: Vec<&'static str>) -> Self {
+            Self {
+                id,
+                grammar,
+                rules,
+                priority: 100,
+                requires: vec![],
+                conflicts_with: vec![],
+            }
+        }
+    }
+
+    impl GrammarExtension for RelatedExt {
+        fn grammar_rules(&self) -> &'static str {
+            self.grammar
+        }
+        fn statement_rules(&self) -> Vec<&'static str> {
+            self.rules.clone()
+        }
+        fn priority(&self) -> u32 {
+            self.priority
+        }
+        fn extension_id(&self) -> &'static str {
+            self.id
+        }
+        fn requires(&self) -> Vec<&'static str> {
+            self.requires.clone()
+        }
+        fn conflicts_with(&self) -> Vec<&'static str> {
+            self.conflicts_with.clone()
+        }
+    }
+
+    #[test]
+    fn test_shadowed_rules_left_out_of_grammar() {
+        let mut registry = ExtensionRegistry::new();
+        let mut low = RelatedExt::new(
+            "low",
+            "// Low priority retry\nretry_stmt = { \"retry\" ~ retry_count }\nretry_count = @{ ASCII_DIGIT+ }",
+            vec!["retry_stmt"],
+        );
+        low.priority = 50;
+        let mut high = RelatedExt::new(
+            "high",
+            "retry_stmt = { \"retry\" ~ \"{\" ~ protocol_body ~ \"}\" }",
+            vec!["retry_stmt"],
+        );
+        high.priority = 200;
+        registry.register_grammar(low).unwrap();
+        registry.register_grammar(high).unwrap();
+
+        let composed = registry.compose_grammar("").unwrap();
+        assert_eq!(composed.matches("retry_stmt =").count(), 1);
+        assert!(composed.contains("protocol_body"));
+        assert!(composed.contains("retry_count = @{ ASCII_DIGIT+ }"));
+        assert_eq!(registry.get_parser_for_rule("retry_stmt"), Some("high"));
+    }
+
+    #[test]
+    fn test_declared_conflict_lists_competing_rules() {
+        let mut registry = ExtensionRegistry::new();
+        let mut sync = RelatedExt::new(
+            "sync_retry",
+            "retry_stmt = { \"retry\" }\nbackoff_stmt = { \"backoff\" }",
+            vec!["retry_stmt", "backoff_stmt"],
+        );
+        sync.conflicts_with = vec!["async_retry"];
+        registry.register_grammar(sync).unwrap();
+
+        let mut other = RelatedExt::new(
+            "async_retry",
+            "retry_stmt = { \"retry\" ~ \"async\" }",
+            vec!["retry_stmt"],
+        );
+        other.priority = 300;
+        let err = registry.register_grammar(other).unwrap_err();
+        assert!(
+            matches!(err, ParseError::ExtensionConflict { ref rules, .. } if rules == &["retry_stmt"])
+        );
+        assert_eq!(
+            err.to_string(),
+            "Extension conflict: 'async_retry' cannot be registered together with 'sync_retry' (competing rules: retry_stmt)"
+        );
+        // The rejected extension left no trace
+        assert_eq!(
+            registry.get_parser_for_rule("retry_stmt"),
+            Some("sync_retry")
+        );
+        assert!(registry.get_conflicts().is_empty());
+    }
+
+    #[test]
+    fn test_resolution_order_follows_requirements() {
+        let mut registry = ExtensionRegistry::new();
+        let mut retry = RelatedExt::new("retry", "retry_stmt = { \"retry\" }", vec!["retry_stmt"]);
+        retry.requires = vec!["timeout"];
+        retry.priority = 500;
+        registry.register_grammar(retry).unwrap();
+        assert!(matches!(
+            registry.resolution_order(),
+            Err(ParseError::MissingDependency { .. })
+        ));
+
+        registry
+            .register_grammar(RelatedExt::new(
+                "timeout",
+                "timeout_stmt = { \"timeout\" }",
+                vec!["timeout_stmt"],
+            ))
+            .unwrap();
+        registry
+            .register_grammar(RelatedExt::new(
+                "audit",
+                "audit_stmt = { \"audit\" }",
+                vec![],
+            ))
+            .unwrap();
+        assert_eq!(
+            registry.resolution_order().unwrap(),
+            vec!["audit", "timeout", "retry"]
+        );
+        let composed = registry.compose_grammar("").unwrap();
+        assert!(composed.find("timeout_stmt").unwrap() < composed.find("retry_stmt").unwrap());
+
+        registry.add_dependency("timeout", "retry");
+        let err = registry.resolution_order().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Extension dependency cycle between retry, timeout"
+        );
+    }
+
     #[test]
     fn test_parse_context() {
         use proc_macro2::Span;
@@ -0,0 +1,313 @@
+//! Typed annotation schemas for extensions
+//!
+//! An extension declares the annotations it understands as `AnnotationSpec`s
+//! through [`GrammarExtension::annotation_specs`](super::GrammarExtension::annotation_specs).
+//! The registry collects them into an `AnnotationSchema`, and the parser checks
+//! every declared annotation for its value type and placement, reporting
+//! violations as `InvalidAnnotation` errors with a source span. Annotations no
+//! extension declares are passed through untouched.
+//!
+//! After parsing, extensions read structured `AnnotationValue`s through
+//! [`AnnotationSchema::typed_annotations`] instead of raw strings.
+
+use crate::ast::Protocol;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Type of an annotation value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnnotationValueType {
+    /// Any value, quoted or not
+    String,
+    /// Signed 64-bit integer
+    Integer,
+    /// `true` or `false`
+    Boolean,
+    /// Identifier such as a role or capability name
+    Ident,
+    /// One of a fixed set of words
+    OneOf(&'static [&'static str]),
+}
+
+impl AnnotationValueType {
+    /// Parse a raw annotation value into a typed one
+    pub fn parse(self, raw: &str) -> Result<AnnotationValue, String> {
+        let raw = raw.trim().trim_matches('"');
+        match self {
+            AnnotationValueType::String => Ok(AnnotationValue::String(raw.to_string())),
+            AnnotationValueType::Integer => raw
+                .parse()
+                .map(AnnotationValue::Integer)
+                .map_err(|_| "expected an integer".to_string()),
+            AnnotationValueType::Boolean => match raw {
+                "true" => Ok(AnnotationValue::Boolean(true)),
+                "false" => Ok(AnnotationValue::Boolean(false)),
+                _ => Err("expected `true` or `false`".to_string()),
+            },
+            AnnotationValueType::Ident => {
+                let mut chars = raw.chars();
+                let valid = chars
+                    .next()
+                    .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+                if valid {
+                    Ok(AnnotationValue::Ident(raw.to_string()))
+                } else {
+                    Err("expected an identifier".to_string())
+                }
+            }
+            AnnotationValueType::OneOf(options) => {
+                if options.contains(&raw) {
+                    Ok(AnnotationValue::Ident(raw.to_string()))
+                } else {
+                    Err(format!("expected one of: {}", options.join(", ")))
+                }
+            }
+        }
+    }
+}
+
+/// Structured annotation value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnnotationValue {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Ident(String),
+}
+
+impl AnnotationValue {
+    #[must_use]
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            AnnotationValue::String(s) | AnnotationValue::Ident(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            AnnotationValue::Integer(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            AnnotationValue::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+}
+
+/// Place an annotation can be attached to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AnnotationTarget {
+    /// Top-level annotation before `choreography`
+    Choreography,
+    /// `[@key = value] A -> B: M`
+    Send,
+    /// `[@key = value] A ->* : M`
+    Broadcast,
+    /// `[@key = value] choice A { ... }`
+    Choice,
+    /// `A[@key = value] -> B: M`
+    Role,
+}
+
+impl fmt::Display for AnnotationTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnnotationTarget::Choreography => write!(f, "choreographies"),
+            AnnotationTarget::Send => write!(f, "send statements"),
+            AnnotationTarget::Broadcast => write!(f, "broadcast statements"),
+            AnnotationTarget::Choice => write!(f, "choice statements"),
+            AnnotationTarget::Role => write!(f, "roles"),
+        }
+    }
+}
+
+/// Declaration of an annotation understood by an extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AnnotationSpec {
+    /// Annotation key without the leading `@`
+    pub key: &'static str,
+    pub value_type: AnnotationValueType,
+    pub allowed_on: &'static [AnnotationTarget],
+}
+
+impl AnnotationSpec {
+    #[must_use]
+    pub const fn new(
+        key: &'static str,
+        value_type: AnnotationValueType,
+        allowed_on: &'static [AnnotationTarget],
+    ) -> Self {
+        Self {
+            key,
+            value_type,
+            allowed_on,
+        }
+    }
+
+    /// Check a raw value found on `target`
+    pub fn check(&self, raw: &str, target: AnnotationTarget) -> Result<AnnotationValue, String> {
+        if !self.allowed_on.contains(&target) {
+            let allowed: Vec<String> = self.allowed_on.iter().map(ToString::to_string).collect();
+            return Err(format!(
+                "not allowed on {target}, only on {}",
+                allowed.join(", ")
+            ));
+        }
+        self.value_type.parse(raw)
+    }
+}
+
+/// Annotation specs declared by all registered extensions
+#[derive(Debug, Clone, Default)]
+pub struct AnnotationSchema {
+    specs: HashMap<&'static str, (AnnotationSpec, String)>,
+}
+
+impl AnnotationSchema {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a spec declared by `extension_id`
+    ///
+    /// Two extensions may declare the same key only with identical specs. On
+    /// a mismatch the id of the extension that declared the key first is
+    /// returned.
+    pub fn declare(&mut self, spec: AnnotationSpec, extension_id: &str) -> Result<(), String> {
+        if let Some(owner) = self.conflict(&spec) {
+            return Err(owner.to_string());
+        }
+        self.specs
+            .entry(spec.key)
+            .or_insert_with(|| (spec, extension_id.to_string()));
+        Ok(())
+    }
+
+    /// Extension that declared the key of `spec` with a different spec
+    #[must_use]
+    pub fn conflict(&self, spec: &AnnotationSpec) -> Option<&str> {
+        self.specs
+            .get(spec.key)
+            .filter(|(existing, _)| existing != spec)
+            .map(|(_, owner)| owner.as_str())
+    }
+
+    #[must_use]
+    pub fn get(&self, key: &str) -> Option<&AnnotationSpec> {
+        self.specs.get(key).map(|(spec, _)| spec)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.specs.is_empty()
+    }
+
+    /// Check a raw annotation, returning `None` for undeclared keys
+    pub fn check(
+        &self,
+        key: &str,
+        raw: &str,
+        target: AnnotationTarget,
+    ) -> Option<Result<AnnotationValue, String>> {
+        self.get(key).map(|spec| spec.check(raw, target))
+    }
+
+    /// Declared statement-level annotations of `protocol` as typed values
+    ///
+    /// Values that do not match their spec are skipped. They cannot occur in
+    /// a choreography parsed with this schema.
+    #[must_use]
+    pub fn typed_annotations(&self, protocol: &Protocol) -> HashMap<String, AnnotationValue> {
+        self.typed(protocol.get_annotations())
+    }
+
+    /// Declared role annotations of the sender of `protocol` as typed values
+    #[must_use]
+    pub fn typed_from_annotations(&self, protocol: &Protocol) -> HashMap<String, AnnotationValue> {
+        protocol
+            .get_from_annotations()
+            .map(|annotations| self.typed(annotations))
+            .unwrap_or_default()
+    }
+
+    /// Declared role annotations of the receiver of `protocol` as typed values
+    #[must_use]
+    pub fn typed_to_annotations(&self, protocol: &Protocol) -> HashMap<String, AnnotationValue> {
+        protocol
+            .get_to_annotations()
+            .map(|annotations| self.typed(annotations))
+            .unwrap_or_default()
+    }
+
+    fn typed(&self, annotations: &HashMap<String, String>) -> HashMap<String, AnnotationValue> {
+        annotations
+            .iter()
+            .filter_map(|(key, raw)| {
+                let spec = self.get(key)?;
+                let value = spec.value_type.parse(raw).ok()?;
+                Some((key.clone(), value))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const COST: AnnotationSpec = AnnotationSpec::new(
+        "cost",
+        AnnotationValueType::Integer,
+        &[AnnotationTarget::Send, AnnotationTarget::Role],
+    );
+
+    #[test]
+    fn test_value_types() {
+        assert_eq!(
+            AnnotationValueType::Integer.parse("\"42\""),
+            Ok(AnnotationValue::Integer(42))
+        );
+        assert!(AnnotationValueType::Integer.parse("lots").is_err());
+        assert_eq!(
+            AnnotationValueType::Boolean.parse("true"),
+            Ok(AnnotationValue::Boolean(true))
+        );
+        assert!(AnnotationValueType::Ident.parse("send message").is_err());
+        assert!(AnnotationValueType::OneOf(&["low", "high"])
+            .parse("medium")
+            .is_err());
+    }
+
+    #[test]
+    fn test_spec_placement() {
+        assert_eq!(
+            COST.check("5", AnnotationTarget::Send),
+            Ok(AnnotationValue::Integer(5))
+        );
+        assert_eq!(
+            COST.check("5", AnnotationTarget::Choice),
+            Err("not allowed on choice statements, only on send statements, roles".to_string())
+        );
+    }
+
+    #[test]
+    fn test_conflicting_declarations() {
+        let mut schema = AnnotationSchema::new();
+        schema.declare(COST, "billing").unwrap();
+        schema.declare(COST, "metering").unwrap();
+        let other = AnnotationSpec::new("cost", AnnotationValueType::String, &[]);
+        assert_eq!(schema.declare(other, "pricing"), Err("billing".to_string()));
+        assert!(schema
+            .check("priority", "1", AnnotationTarget::Send)
+            .is_none());
+    }
+}
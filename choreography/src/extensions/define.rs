@@ -0,0 +1,384 @@
+//! Declarative extension authoring
+//!
+//! [`define_extension!`](crate::define_extension) generates the
+//! `GrammarExtension`, `StatementParser` and `ProtocolExtension` impls of a
+//! statement extension from a grammar snippet and three closures. Parsed
+//! statements are stored as [`DefinedStatement`]s holding the data returned
+//! by the parse closure.
+
+use super::{CodegenContext, ExtensionValidationError, ProjectionContext, ProtocolExtension};
+use crate::ast::{LocalType, Role};
+use crate::compiler::projection::ProjectionError;
+use std::any::{Any, TypeId};
+use std::fmt::Debug;
+
+#[doc(hidden)]
+pub use proc_macro2::TokenStream as __TokenStream;
+
+/// Behaviour of an extension written with `define_extension!`
+pub trait DefinedExtension: Send + Sync + Debug + 'static {
+    /// What the parse closure produces for one statement
+    type Data: Send + Sync + Debug + 'static;
+
+    /// Roles a statement involves, `None` if it concerns every role
+    fn roles(_data: &Self::Data) -> Option<Vec<String>> {
+        None
+    }
+
+    fn project(
+        data: &Self::Data,
+        role: &Role,
+        context: &ProjectionContext,
+    ) -> Result<LocalType, ProjectionError>;
+
+    fn generate_code(data: &Self::Data, context: &CodegenContext) -> proc_macro2::TokenStream;
+}
+
+/// Statement parsed by an extension written with `define_extension!`
+#[derive(Debug)]
+pub struct DefinedStatement<E: DefinedExtension> {
+    pub data: E::Data,
+}
+
+impl<E: DefinedExtension> DefinedStatement<E> {
+    #[must_use]
+    pub fn new(data: E::Data) -> Self {
+        Self { data }
+    }
+}
+
+impl<E: DefinedExtension> ProtocolExtension for DefinedStatement<E> {
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<E::Data>()
+    }
+
+    fn mentions_role(&self, role: &Role) -> bool {
+        E::roles(&self.data).map_or(true, |roles| roles.iter().any(|name| role.name == name))
+    }
+
+    fn validate(&self, roles: &[Role]) -> Result<(), ExtensionValidationError> {
+        let Some(mentioned) = E::roles(&self.data) else {
+            return Ok(());
+        };
+        match mentioned
+            .into_iter()
+            .find(|name| !roles.iter().any(|role| role.name == name))
+        {
+            Some(role) => Err(ExtensionValidationError::UndeclaredRole { role }),
+            None => Ok(()),
+        }
+    }
+
+    fn project(
+        &self,
+        role: &Role,
+        context: &ProjectionContext,
+    ) -> Result<LocalType, ProjectionError> {
+        E::project(&self.data, role, context)
+    }
+
+    fn generate_code(&self, context: &CodegenContext) -> proc_macro2::TokenStream {
+        E::generate_code(&self.data, context)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn type_id(&self) -> TypeId {
+        TypeId::of::<Self>()
+    }
+}
+
+/// Define a statement extension from a grammar snippet and closures
+///
+/// Generates a unit struct implementing `GrammarExtension`, `StatementParser`
+/// and [`DefinedExtension`], plus a `register` function adding both the
+/// grammar and the parser to an `ExtensionRegistry`. `priority` and `roles`
+/// are optional. The closures must not capture anything.
+///
+/// ```
+/// use quote::quote;
+/// use rumpsteak_aura_choreography::ast::LocalType;
+/// use rumpsteak_aura_choreography::define_extension;
+/// use rumpsteak_aura_choreography::extensions::ParseError;
+///
+/// #[derive(Debug)]
+/// pub struct Checkpoint {
+///     pub label: String,
+/// }
+///
+/// define_extension! {
+///     /// `checkpoint <label>` statements
+///     pub struct CheckpointExtension {
+///         id: "checkpoint",
+///         grammar: r#"checkpoint_stmt = { "checkpoint" ~ ident }"#,
+///         statements: ["checkpoint_stmt"],
+///         priority: 120,
+///         data: Checkpoint,
+///         parse: |_rule, content, _context| {
+///             let label = content.trim().strip_prefix("checkpoint").ok_or_else(|| {
+///                 ParseError::InvalidSyntax { details: content.to_string() }
+///             })?;
+///             Ok(Checkpoint { label: label.trim().to_string() })
+///         },
+///         project: |_data, _role, _context| Ok(LocalType::End),
+///         codegen: |data, _context| {
+///             let label = &data.label;
+///             quote! { .checkpoint(#label) }
+///         },
+///     }
+/// }
+///
+/// let mut registry = rumpsteak_aura_choreography::ExtensionRegistry::new();
+/// CheckpointExtension::register(&mut registry).unwrap();
+/// assert!(registry.can_handle("checkpoint_stmt"));
+/// ```
+#[macro_export]
+macro_rules! define_extension {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            id: $id:literal,
+            grammar: $grammar:expr,
+            statements: [$($rule:literal),* $(,)?],
+            $(priority: $priority:expr,)?
+            data: $data:ty,
+            $(roles: $roles:expr,)?
+            parse: $parse:expr,
+            project: $project:expr,
+            codegen: $codegen:expr $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, Default)]
+        $vis struct $name;
+
+        impl $name {
+            /// Register the grammar and statement parser of this extension
+            $vis fn register(
+                registry: &mut $crate::extensions::ExtensionRegistry,
+            ) -> ::std::result::Result<(), $crate::extensions::ParseError> {
+                registry.register_grammar($name)?;
+                registry.register_parser($name, $id.to_string());
+                Ok(())
+            }
+        }
+
+        impl $crate::extensions::GrammarExtension for $name {
+            fn grammar_rules(&self) -> &'static str {
+                $grammar
+            }
+
+            fn statement_rules(&self) -> ::std::vec::Vec<&'static str> {
+                ::std::vec![$($rule),*]
+            }
+
+            $(fn priority(&self) -> u32 {
+                $priority
+            })?
+
+            fn extension_id(&self) -> &'static str {
+                $id
+            }
+        }
+
+        impl $crate::extensions::StatementParser for $name {
+            fn can_parse(&self, rule_name: &str) -> bool {
+                $(rule_name == $rule ||)* false
+            }
+
+            fn supported_rules(&self) -> ::std::vec::Vec<::std::string::String> {
+                ::std::vec![$(::std::string::String::from($rule)),*]
+            }
+
+            fn parse_statement(
+                &self,
+                rule_name: &str,
+                content: &str,
+                context: &$crate::extensions::ParseContext,
+            ) -> ::std::result::Result<
+                ::std::boxed::Box<dyn $crate::extensions::ProtocolExtension>,
+                $crate::extensions::ParseError,
+            > {
+                let parse: fn(
+                    &str,
+                    &str,
+                    &$crate::extensions::ParseContext,
+                ) -> ::std::result::Result<$data, $crate::extensions::ParseError> = $parse;
+                let data = parse(rule_name, content, context)?;
+                Ok(::std::boxed::Box::new(
+                    $crate::extensions::define::DefinedStatement::<$name>::new(data),
+                ))
+            }
+        }
+
+        impl $crate::extensions::define::DefinedExtension for $name {
+            type Data = $data;
+
+            $(fn roles(data: &$data) -> ::std::option::Option<::std::vec::Vec<::std::string::String>> {
+                let roles: fn(&$data) -> ::std::vec::Vec<::std::string::String> = $roles;
+                Some(roles(data))
+            })?
+
+            fn project(
+                data: &$data,
+                role: &$crate::ast::Role,
+                context: &$crate::extensions::ProjectionContext,
+            ) -> ::std::result::Result<$crate::ast::LocalType, $crate::compiler::ProjectionError> {
+                let project: fn(
+                    &$data,
+                    &$crate::ast::Role,
+                    &$crate::extensions::ProjectionContext,
+                ) -> ::std::result::Result<$crate::ast::LocalType, $crate::compiler::ProjectionError> =
+                    $project;
+                project(data, role, context)
+            }
+
+            fn generate_code(
+                data: &$data,
+                context: &$crate::extensions::CodegenContext,
+            ) -> $crate::extensions::define::__TokenStream {
+                let codegen: fn(
+                    &$data,
+                    &$crate::extensions::CodegenContext,
+                ) -> $crate::extensions::define::__TokenStream = $codegen;
+                codegen(data, context)
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{LocalType, MessageType, Role};
+    use crate::extensions::{
+        CodegenContext, ExtensionRegistry, ExtensionValidationError, GrammarExtension,
+        ParseContext, ParseError, ProjectionContext,
+    };
+    use quote::{format_ident, quote};
+
+    #[derive(Debug)]
+    struct Ping {
+        from: String,
+        to: String,
+    }
+
+    crate::define_extension! {
+        /// `ping A -> B`, a send without payload
+        struct PingExtension {
+            id: "ping",
+            grammar: r#"ping_stmt = { "ping" ~ ident ~ "->" ~ ident }"#,
+            statements: ["ping_stmt"],
+            priority: 150,
+            data: Ping,
+            roles: |ping| vec![ping.from.clone(), ping.to.clone()],
+            parse: |_rule, content, _context| {
+                let rest = content.trim().strip_prefix("ping").ok_or_else(|| {
+                    ParseError::InvalidSyntax {
+                        details: format!("not a ping: {}", content),
+                    }
+                })?;
+                let (from, to) = rest.split_once("->").ok_or_else(|| ParseError::InvalidSyntax {
+                    details: "expected `->`".to_string(),
+                })?;
+                Ok(Ping {
+                    from: from.trim().to_string(),
+                    to: to.trim().to_string(),
+                })
+            },
+            project: |ping, role, _context| {
+                let message = MessageType {
+                    name: format_ident!("Ping"),
+                    type_annotation: None,
+                    payload: None,
+                };
+                let peer = |name: &str| Role::new(format_ident!("{}", name));
+                Ok(if role.name == ping.from {
+                    LocalType::Send {
+                        to: peer(&ping.to),
+                        message,
+                        continuation: Box::new(LocalType::End),
+                    }
+                } else if role.name == ping.to {
+                    LocalType::Receive {
+                        from: peer(&ping.from),
+                        message,
+                        continuation: Box::new(LocalType::End),
+                    }
+                } else {
+                    LocalType::End
+                })
+            },
+            codegen: |ping, _context| {
+                let to = format_ident!("{}", ping.to);
+                quote! { .ping(Role::#to) }
+            },
+        }
+    }
+
+    #[test]
+    fn test_generated_extension() {
+        let mut registry = ExtensionRegistry::new();
+        PingExtension::register(&mut registry).unwrap();
+        assert_eq!(PingExtension.priority(), 150);
+        assert!(registry.can_handle("ping_stmt"));
+
+        let roles = vec![
+            Role::new(format_ident!("Alice")),
+            Role::new(format_ident!("Bob")),
+        ];
+        let parser = registry.find_parser("ping_stmt").unwrap();
+        let statement = parser
+            .parse_statement(
+                "ping_stmt",
+                "ping Alice -> Bob",
+                &ParseContext {
+                    declared_roles: &roles,
+                    input: "",
+                },
+            )
+            .unwrap();
+        assert!(statement.mentions_role(&roles[1]));
+        assert!(statement.validate(&roles).is_ok());
+        assert!(matches!(
+            statement.validate(&roles[..1]),
+            Err(ExtensionValidationError::UndeclaredRole { role }) if role == "Bob"
+        ));
+
+        let context = ProjectionContext {
+            all_roles: &roles,
+            current_role: &roles[1],
+        };
+        assert!(matches!(
+            statement.project(&roles[1], &context).unwrap(),
+            LocalType::Receive { from, .. } if from.name == "Alice"
+        ));
+        assert_eq!(
+            statement
+                .generate_code(&CodegenContext::default())
+                .to_string(),
+            ". ping (Role :: Bob)"
+        );
+    }
+
+    #[test]
+    fn test_parse_errors_surface() {
+        let parser = PingExtension;
+        let err = crate::extensions::StatementParser::parse_statement(
+            &parser,
+            "ping_stmt",
+            "ping Alice Bob",
+            &ParseContext {
+                declared_roles: &[],
+                input: "",
+            },
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("expected `->`"));
+    }
+}
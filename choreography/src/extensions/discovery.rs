@@ -8,6 +8,7 @@ use super::{ExtensionRegistry, GrammarExtension, ParseError};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 /// Extension metadata for discovery and versioning
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,7 +31,7 @@ pub struct ExtensionMetadata {
 #[derive(Debug)]
 pub struct ExtensionPackage {
     pub metadata: ExtensionMetadata,
-    pub extension: Box<dyn GrammarExtension>,
+    pub extension: Arc<dyn GrammarExtension>,
     pub source_path: Option<PathBuf>,
 }
 
@@ -79,7 +80,7 @@ impl ExtensionDiscovery {
 
         let package = ExtensionPackage {
             metadata,
-            extension,
+            extension: Arc::from(extension),
             source_path: None,
         };
 
@@ -151,12 +152,16 @@ impl ExtensionDiscovery {
         // Register extensions in dependency order
         for ext_name in resolved {
             if let Some(package) = self.discovered_extensions.get(&ext_name) {
-                // Clone the extension (this requires extensions to be cloneable or
-                // we need a different approach for ownership)
-                registry.register_grammar_legacy(ClonableExtensionWrapper::new(
-                    &*package.extension,
-                    &package.metadata,
-                ));
+                let extension = match package.metadata.priority {
+                    Some(priority) if priority != package.extension.priority() => {
+                        Arc::new(PrioritizedExtension {
+                            inner: package.extension.clone(),
+                            priority,
+                        })
+                    }
+                    _ => package.extension.clone(),
+                };
+                registry.register_grammar_shared(extension)?;
 
                 // Add dependencies to registry
                 for dep in &package.metadata.dependencies {
@@ -213,7 +218,7 @@ impl ExtensionDiscovery {
                 })?;
 
             // For now, we'll create a placeholder extension since we can't load dynamic libraries
-            let extension = Box::new(PlaceholderExtension::new(&metadata));
+            let extension = Arc::new(PlaceholderExtension::new(&metadata));
 
             let package = ExtensionPackage {
                 metadata: metadata.clone(),
@@ -361,43 +366,24 @@ impl ExtensionDiscovery {
     }
 }
 
-/// Wrapper to make extensions cloneable for registry management
-#[derive(Debug, Clone)]
-struct ClonableExtensionWrapper {
-    #[allow(dead_code)]
-    id: String,
-    #[allow(dead_code)]
-    rules: Vec<String>,
-    #[allow(dead_code)]
-    grammar: String,
+/// Extension whose priority is overridden by its package metadata
+#[derive(Debug)]
+struct PrioritizedExtension {
+    inner: Arc<dyn GrammarExtension>,
     priority: u32,
 }
 
-impl ClonableExtensionWrapper {
-    fn new(extension: &dyn GrammarExtension, metadata: &ExtensionMetadata) -> Self {
-        Self {
-            id: metadata.name.clone(),
-            rules: extension
-                .statement_rules()
-                .iter()
-                .map(|s| s.to_string())
-                .collect(),
-            grammar: extension.grammar_rules().to_string(),
-            priority: metadata.priority.unwrap_or(extension.priority()),
-        }
-    }
-}
-
-impl GrammarExtension for ClonableExtensionWrapper {
+impl GrammarExtension for PrioritizedExtension {
     fn grammar_rules(&self) -> &'static str {
-        // This is a limitation - we need to box the string or use a different approach
-        // For now, return empty string
-        ""
+        self.inner.grammar_rules()
     }
 
     fn statement_rules(&self) -> Vec<&'static str> {
-        // Another limitation - return empty for now
-        vec![]
+        self.inner.statement_rules()
+    }
+
+    fn extension_point_rules(&self, point: super::ExtensionPoint) -> Vec<&'static str> {
+        self.inner.extension_point_rules(point)
     }
 
     fn priority(&self) -> u32 {
@@ -405,8 +391,19 @@ impl GrammarExtension for ClonableExtensionWrapper {
     }
 
     fn extension_id(&self) -> &'static str {
-        // This needs a different approach for dynamic extensions
-        "cloneable_wrapper"
+        self.inner.extension_id()
+    }
+
+    fn annotation_specs(&self) -> Vec<super::AnnotationSpec> {
+        self.inner.annotation_specs()
+    }
+
+    fn requires(&self) -> Vec<&'static str> {
+        self.inner.requires()
+    }
+
+    fn conflicts_with(&self) -> Vec<&'static str> {
+        self.inner.conflicts_with()
     }
 }
 
@@ -550,4 +547,36 @@ mod tests {
         assert!(resolved.contains(&"base".to_string()));
         assert!(resolved.contains(&"dependent".to_string()));
     }
+
+    #[test]
+    fn test_create_registry_keeps_extension_grammar() {
+        let mut discovery = ExtensionDiscovery::new();
+        let metadata = ExtensionMetadata {
+            name: "aura_annotations".to_string(),
+            version: "1.0.0".to_string(),
+            description: "Aura annotations".to_string(),
+            author: "Test".to_string(),
+            dependencies: vec![],
+            required_rumpsteak_version: None,
+            priority: Some(250),
+            overview: None,
+            syntax_guide: None,
+            use_cases: None,
+            keywords: None,
+        };
+        discovery
+            .register_extension(metadata, Box::new(AuraAnnotationExtension))
+            .unwrap();
+
+        let registry = discovery
+            .create_registry(&["aura_annotations".to_string()])
+            .unwrap();
+        let extension = registry.grammar_extension("aura_annotations").unwrap();
+        assert_eq!(extension.priority(), 250);
+        assert!(registry.can_handle("aura_annotation_stmt"));
+        assert!(registry
+            .compose_grammar("")
+            .unwrap()
+            .contains("aura_annotation_list"));
+    }
 }
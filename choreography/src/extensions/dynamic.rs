@@ -0,0 +1,661 @@
+//! Runtime-loadable extensions from cdylib plugins
+//!
+//! A plugin is a shared library exporting a small C ABI. All data crosses the
+//! boundary as NUL-terminated JSON, so plugins can be built with any compiler
+//! version, or in another language entirely:
+//!
+//! - `rumpsteak_extension_descriptor() -> *const c_char` returns a
+//!   [`PluginDescriptor`]. The string stays owned by the plugin and is copied
+//!   right away.
+//! - `rumpsteak_extension_parse(request: *const c_char) -> *mut c_char` is
+//!   required when the descriptor lists rules at any extension point. It
+//!   takes a [`ParseRequest`] and returns a [`ParseResponse`].
+//! - `rumpsteak_extension_free(response: *mut c_char)` releases a string
+//!   returned by `rumpsteak_extension_parse`.
+//!
+//! Plugins are never unloaded. Their descriptor strings are leaked to satisfy
+//! the `&'static str` signatures of [`GrammarExtension`], which is fine for
+//! the handful of plugins a CLI or language server loads at startup.
+//!
+//! Statements parsed by a plugin become [`PluginStatement`]s. They carry no
+//! communication of their own and project to no local actions, so plugins
+//! suit policies and metadata that tools and hooks read, not new message
+//! patterns.
+
+use super::{
+    CodegenContext, ExtensionPoint, ExtensionRegistry, ExtensionValidationError, GrammarExtension,
+    ParseContext, ParseError, ProjectionContext, ProtocolExtension, StatementParser,
+};
+use crate::ast::{LocalType, Role};
+use crate::compiler::projection::ProjectionError;
+use serde::{Deserialize, Serialize};
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::path::Path;
+use thiserror::Error;
+
+/// ABI version this crate loads, checked against `PluginDescriptor::abi_version`
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+pub const DESCRIPTOR_SYMBOL: &str = "rumpsteak_extension_descriptor";
+pub const PARSE_SYMBOL: &str = "rumpsteak_extension_parse";
+pub const FREE_SYMBOL: &str = "rumpsteak_extension_free";
+
+type DescriptorFn = unsafe extern "C" fn() -> *const c_char;
+type ParseFn = unsafe extern "C" fn(*const c_char) -> *mut c_char;
+type FreeFn = unsafe extern "C" fn(*mut c_char);
+
+/// Grammar extension described by a plugin
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PluginDescriptor {
+    pub abi_version: u32,
+    pub extension_id: String,
+    #[serde(default)]
+    pub version: Option<String>,
+    /// Pest rules, as returned by `GrammarExtension::grammar_rules`
+    pub grammar_rules: String,
+    #[serde(default)]
+    pub statement_rules: Vec<String>,
+    #[serde(default)]
+    pub annotation_rules: Vec<String>,
+    #[serde(default)]
+    pub expression_rules: Vec<String>,
+    #[serde(default)]
+    pub choice_modifier_rules: Vec<String>,
+    #[serde(default)]
+    pub role_declaration_rules: Vec<String>,
+    #[serde(default)]
+    pub item_rules: Vec<String>,
+    #[serde(default = "default_priority")]
+    pub priority: u32,
+    #[serde(default)]
+    pub requires: Vec<String>,
+    #[serde(default)]
+    pub conflicts_with: Vec<String>,
+}
+
+fn default_priority() -> u32 {
+    100
+}
+
+/// Statement handed to `rumpsteak_extension_parse`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ParseRequest {
+    pub rule: String,
+    pub content: String,
+    /// Names of the declared roles
+    pub roles: Vec<String>,
+}
+
+/// Answer of `rumpsteak_extension_parse`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParseResponse {
+    Statement {
+        /// Plugin-defined statement kind
+        kind: String,
+        /// Roles the statement involves
+        #[serde(default)]
+        roles: Vec<String>,
+        /// Plugin-defined attributes
+        #[serde(default)]
+        attributes: HashMap<String, String>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+/// Errors raised while loading a plugin
+#[derive(Debug, Error)]
+pub enum PluginError {
+    #[error("Failed to load plugin '{path}': {reason}")]
+    Load { path: String, reason: String },
+
+    #[error("Plugin '{path}' does not export `{symbol}`")]
+    MissingSymbol { path: String, symbol: &'static str },
+
+    #[error("Invalid plugin descriptor: {0}")]
+    InvalidDescriptor(String),
+
+    #[error("Plugin '{extension}' targets ABI version {found}, expected {PLUGIN_ABI_VERSION}")]
+    AbiMismatch { extension: String, found: u32 },
+
+    #[error(transparent)]
+    Registration(#[from] ParseError),
+}
+
+impl PluginDescriptor {
+    /// Parse and check a descriptor
+    pub fn from_json(json: &str) -> Result<Self, PluginError> {
+        let descriptor: Self = serde_json::from_str(json)
+            .map_err(|e| PluginError::InvalidDescriptor(e.to_string()))?;
+        if descriptor.abi_version != PLUGIN_ABI_VERSION {
+            return Err(PluginError::AbiMismatch {
+                extension: descriptor.extension_id,
+                found: descriptor.abi_version,
+            });
+        }
+        if descriptor.extension_id.is_empty() {
+            return Err(PluginError::InvalidDescriptor(
+                "extension_id must not be empty".to_string(),
+            ));
+        }
+        Ok(descriptor)
+    }
+
+    /// Serialize for returning from `rumpsteak_extension_descriptor`
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    /// Rules the plugin parses, those it adds at every extension point
+    fn parsed_rules(&self) -> Vec<String> {
+        [
+            &self.statement_rules,
+            &self.annotation_rules,
+            &self.expression_rules,
+            &self.choice_modifier_rules,
+            &self.role_declaration_rules,
+            &self.item_rules,
+        ]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect()
+    }
+
+    /// Grammar extension backed by this descriptor
+    #[must_use]
+    pub fn into_extension(self) -> DynamicGrammarExtension {
+        fn leak_all(rules: Vec<String>) -> Vec<&'static str> {
+            rules.into_iter().map(leak).collect()
+        }
+
+        let mut points = HashMap::new();
+        points.insert(ExtensionPoint::Statement, leak_all(self.statement_rules));
+        points.insert(ExtensionPoint::Annotation, leak_all(self.annotation_rules));
+        points.insert(ExtensionPoint::Expression, leak_all(self.expression_rules));
+        points.insert(
+            ExtensionPoint::ChoiceModifier,
+            leak_all(self.choice_modifier_rules),
+        );
+        points.insert(
+            ExtensionPoint::RoleDeclaration,
+            leak_all(self.role_declaration_rules),
+        );
+        points.insert(ExtensionPoint::Item, leak_all(self.item_rules));
+
+        DynamicGrammarExtension {
+            extension_id: leak(self.extension_id),
+            version: self.version,
+            grammar_rules: leak(self.grammar_rules),
+            points,
+            priority: self.priority,
+            requires: leak_all(self.requires),
+            conflicts_with: leak_all(self.conflicts_with),
+        }
+    }
+}
+
+fn leak(s: String) -> &'static str {
+    Box::leak(s.into_boxed_str())
+}
+
+/// Grammar extension loaded from a plugin descriptor
+#[derive(Debug, Clone)]
+pub struct DynamicGrammarExtension {
+    extension_id: &'static str,
+    version: Option<String>,
+    grammar_rules: &'static str,
+    points: HashMap<ExtensionPoint, Vec<&'static str>>,
+    priority: u32,
+    requires: Vec<&'static str>,
+    conflicts_with: Vec<&'static str>,
+}
+
+impl DynamicGrammarExtension {
+    #[must_use]
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+}
+
+impl GrammarExtension for DynamicGrammarExtension {
+    fn grammar_rules(&self) -> &'static str {
+        self.grammar_rules
+    }
+
+    fn statement_rules(&self) -> Vec<&'static str> {
+        self.extension_point_rules(ExtensionPoint::Statement)
+    }
+
+    fn extension_point_rules(&self, point: ExtensionPoint) -> Vec<&'static str> {
+        self.points.get(&point).cloned().unwrap_or_default()
+    }
+
+    fn priority(&self) -> u32 {
+        self.priority
+    }
+
+    fn extension_id(&self) -> &'static str {
+        self.extension_id
+    }
+
+    fn requires(&self) -> Vec<&'static str> {
+        self.requires.clone()
+    }
+
+    fn conflicts_with(&self) -> Vec<&'static str> {
+        self.conflicts_with.clone()
+    }
+}
+
+/// Statement parser calling into a plugin
+#[derive(Debug)]
+pub struct DynamicStatementParser {
+    rules: Vec<String>,
+    parse: ParseFn,
+    free: FreeFn,
+}
+
+impl DynamicStatementParser {
+    fn call(&self, request: &ParseRequest) -> Result<ParseResponse, ParseError> {
+        let request = serde_json::to_string(request)
+            .ok()
+            .and_then(|json| CString::new(json).ok())
+            .ok_or_else(|| ParseError::InvalidSyntax {
+                details: "statement cannot be passed to the plugin".to_string(),
+            })?;
+
+        // SAFETY: the plugin contract requires `parse` to accept a
+        // NUL-terminated string and to return either null or a NUL-terminated
+        // string that stays valid until it is passed to `free`.
+        let response = unsafe {
+            let raw = (self.parse)(request.as_ptr());
+            if raw.is_null() {
+                return Err(ParseError::Syntax {
+                    message: "plugin returned no parse result".to_string(),
+                });
+            }
+            let json = CStr::from_ptr(raw).to_string_lossy().into_owned();
+            (self.free)(raw);
+            json
+        };
+
+        serde_json::from_str(&response).map_err(|e| ParseError::Syntax {
+            message: format!("invalid plugin parse result: {}", e),
+        })
+    }
+}
+
+// SAFETY: the function pointers refer to code of a library that is never
+// unloaded, and the plugin contract requires them to be thread-safe.
+unsafe impl Send for DynamicStatementParser {}
+unsafe impl Sync for DynamicStatementParser {}
+
+impl StatementParser for DynamicStatementParser {
+    fn can_parse(&self, rule_name: &str) -> bool {
+        self.rules.iter().any(|rule| rule == rule_name)
+    }
+
+    fn supported_rules(&self) -> Vec<String> {
+        self.rules.clone()
+    }
+
+    fn parse_statement(
+        &self,
+        rule_name: &str,
+        content: &str,
+        context: &ParseContext,
+    ) -> Result<Box<dyn ProtocolExtension>, ParseError> {
+        let request = ParseRequest {
+            rule: rule_name.to_string(),
+            content: content.to_string(),
+            roles: context
+                .declared_roles
+                .iter()
+                .map(|role| role.name.to_string())
+                .collect(),
+        };
+        match self.call(&request)? {
+            ParseResponse::Statement {
+                kind,
+                roles,
+                attributes,
+            } => Ok(Box::new(PluginStatement {
+                kind,
+                roles,
+                attributes,
+            })),
+            ParseResponse::Error { message } => Err(ParseError::Syntax { message }),
+        }
+    }
+}
+
+/// Statement parsed by a plugin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginStatement {
+    pub kind: String,
+    pub roles: Vec<String>,
+    pub attributes: HashMap<String, String>,
+}
+
+impl ProtocolExtension for PluginStatement {
+    fn type_name(&self) -> &'static str {
+        "PluginStatement"
+    }
+
+    fn mentions_role(&self, role: &Role) -> bool {
+        self.roles.iter().any(|name| role.name == name)
+    }
+
+    fn validate(&self, roles: &[Role]) -> Result<(), ExtensionValidationError> {
+        match self
+            .roles
+            .iter()
+            .find(|name| !roles.iter().any(|role| role.name == name))
+        {
+            Some(role) => Err(ExtensionValidationError::UndeclaredRole { role: role.clone() }),
+            None => Ok(()),
+        }
+    }
+
+    fn project(
+        &self,
+        _role: &Role,
+        _context: &ProjectionContext,
+    ) -> Result<LocalType, ProjectionError> {
+        Ok(LocalType::End)
+    }
+
+    fn generate_code(&self, _context: &CodegenContext) -> proc_macro2::TokenStream {
+        proc_macro2::TokenStream::new()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn type_id(&self) -> TypeId {
+        TypeId::of::<Self>()
+    }
+}
+
+/// Load the plugin at `path` and register its extension with `registry`
+///
+/// Returns the plugin descriptor. The library stays loaded for the rest of the
+/// process, also when registration fails.
+pub fn load_plugin(
+    registry: &mut ExtensionRegistry,
+    path: impl AsRef<Path>,
+) -> Result<PluginDescriptor, PluginError> {
+    let path = path.as_ref();
+    let library = Library::open(path)?;
+
+    let describe: DescriptorFn = library.symbol(DESCRIPTOR_SYMBOL)?;
+    // SAFETY: the plugin contract requires the descriptor function to return
+    // a NUL-terminated string that stays valid while the library is loaded.
+    let json = unsafe {
+        let raw = describe();
+        if raw.is_null() {
+            return Err(PluginError::InvalidDescriptor(
+                "descriptor function returned null".to_string(),
+            ));
+        }
+        CStr::from_ptr(raw).to_string_lossy().into_owned()
+    };
+    let descriptor = PluginDescriptor::from_json(&json)?;
+
+    let rules = descriptor.parsed_rules();
+    let parser = if rules.is_empty() {
+        None
+    } else {
+        Some(DynamicStatementParser {
+            rules,
+            parse: library.symbol(PARSE_SYMBOL)?,
+            free: library.symbol(FREE_SYMBOL)?,
+        })
+    };
+
+    register(registry, &descriptor, parser)?;
+    Ok(descriptor)
+}
+
+/// Register the extension of a plugin and the parser of its rules
+fn register(
+    registry: &mut ExtensionRegistry,
+    descriptor: &PluginDescriptor,
+    parser: Option<DynamicStatementParser>,
+) -> Result<(), PluginError> {
+    registry.register_grammar(descriptor.clone().into_extension())?;
+    if let Some(parser) = parser {
+        registry.register_parser(parser, descriptor.extension_id.clone());
+    }
+    Ok(())
+}
+
+/// Handle of a shared library that is never closed
+struct Library {
+    handle: *mut c_void,
+    path: String,
+}
+
+impl Library {
+    fn open(path: &Path) -> Result<Self, PluginError> {
+        let display = path.display().to_string();
+        let c_path = CString::new(display.clone()).map_err(|_| PluginError::Load {
+            path: display.clone(),
+            reason: "path contains a NUL byte".to_string(),
+        })?;
+        // SAFETY: `c_path` is NUL-terminated. Loading runs the library's
+        // initializers, which is the point of loading a plugin.
+        let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if handle.is_null() {
+            return Err(PluginError::Load {
+                path: display,
+                reason: last_dl_error(),
+            });
+        }
+        Ok(Self {
+            handle,
+            path: display,
+        })
+    }
+
+    /// Look up a function symbol
+    ///
+    /// `F` must be an `extern "C"` function pointer type matching the symbol.
+    fn symbol<F: Copy>(&self, name: &'static str) -> Result<F, PluginError> {
+        let missing = || PluginError::MissingSymbol {
+            path: self.path.clone(),
+            symbol: name,
+        };
+        let c_name = CString::new(name).map_err(|_| missing())?;
+        // SAFETY: `handle` came from a successful `dlopen` and is never closed.
+        let address = unsafe { libc::dlsym(self.handle, c_name.as_ptr()) };
+        if address.is_null() {
+            return Err(missing());
+        }
+        // SAFETY: callers only instantiate `F` with function pointer types,
+        // which have the size of a data pointer on every platform with dlsym.
+        Ok(unsafe { std::mem::transmute_copy::<*mut c_void, F>(&address) })
+    }
+}
+
+fn last_dl_error() -> String {
+    // SAFETY: `dlerror` returns null or a NUL-terminated thread-local string.
+    unsafe {
+        let error = libc::dlerror();
+        if error.is_null() {
+            "unknown error".to_string()
+        } else {
+            CStr::from_ptr(error).to_string_lossy().into_owned()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DESCRIPTOR: &str = r#"{
+        "abi_version": 1,
+        "extension_id": "policy",
+        "version": "0.2.0",
+        "grammar_rules": "policy_block = { \"policy\" ~ \"{\" ~ ident* ~ \"}\" }",
+        "item_rules": ["policy_block"],
+        "priority": 150
+    }"#;
+
+    #[test]
+    fn test_descriptor_becomes_extension() {
+        let descriptor = PluginDescriptor::from_json(DESCRIPTOR).unwrap();
+        assert_eq!(
+            PluginDescriptor::from_json(&descriptor.to_json()).unwrap(),
+            descriptor
+        );
+
+        let extension = descriptor.into_extension();
+        assert_eq!(extension.extension_id(), "policy");
+        assert_eq!(extension.version(), Some("0.2.0"));
+        assert_eq!(extension.priority(), 150);
+        assert_eq!(
+            extension.extension_point_rules(ExtensionPoint::Item),
+            vec!["policy_block"]
+        );
+        assert!(extension.statement_rules().is_empty());
+
+        let mut registry = ExtensionRegistry::new();
+        registry.register_grammar(extension).unwrap();
+        assert_eq!(
+            registry
+                .extension_point_rules(ExtensionPoint::Item)
+                .unwrap(),
+            vec!["policy_block"]
+        );
+    }
+
+    #[test]
+    fn test_invalid_descriptors_are_rejected() {
+        let newer = DESCRIPTOR.replace("\"abi_version\": 1", "\"abi_version\": 2");
+        assert!(matches!(
+            PluginDescriptor::from_json(&newer),
+            Err(PluginError::AbiMismatch { found: 2, .. })
+        ));
+        assert!(matches!(
+            PluginDescriptor::from_json("{\"abi_version\": 1}"),
+            Err(PluginError::InvalidDescriptor(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_response_format() {
+        let response: ParseResponse = serde_json::from_str(
+            r#"{"statement": {"kind": "policy", "roles": ["Alice"], "attributes": {"mode": "strict"}}}"#,
+        )
+        .unwrap();
+        assert!(matches!(response, ParseResponse::Statement { ref kind, .. } if kind == "policy"));
+        let error: ParseResponse =
+            serde_json::from_str(r#"{"error": {"message": "bad policy"}}"#).unwrap();
+        assert_eq!(
+            error,
+            ParseResponse::Error {
+                message: "bad policy".to_string()
+            }
+        );
+    }
+
+    /// Stand-in for the `rumpsteak_extension_parse` of an audit plugin
+    unsafe extern "C" fn parse_audit(request: *const c_char) -> *mut c_char {
+        let request: ParseRequest =
+            serde_json::from_str(&CStr::from_ptr(request).to_string_lossy()).unwrap();
+        let role = request.content.trim_start_matches("audit").trim();
+        let response = ParseResponse::Statement {
+            kind: request.rule,
+            roles: vec![role.to_string()],
+            attributes: HashMap::new(),
+        };
+        CString::new(serde_json::to_string(&response).unwrap())
+            .unwrap()
+            .into_raw()
+    }
+
+    unsafe extern "C" fn free_response(response: *mut c_char) {
+        drop(CString::from_raw(response));
+    }
+
+    #[test]
+    fn test_plugin_statements_parse() {
+        let descriptor = PluginDescriptor::from_json(
+            r#"{
+                "abi_version": 1,
+                "extension_id": "audit",
+                "grammar_rules": "audit_stmt = { \"audit\" ~ ident }",
+                "statement_rules": ["audit_stmt"]
+            }"#,
+        )
+        .unwrap();
+        let parser = DynamicStatementParser {
+            rules: descriptor.parsed_rules(),
+            parse: parse_audit,
+            free: free_response,
+        };
+        let mut registry = ExtensionRegistry::new();
+        register(&mut registry, &descriptor, Some(parser)).unwrap();
+
+        let (choreography, _) = crate::compiler::parser::parse_choreography_str_with_extensions(
+            r#"
+            choreography Audited {
+                roles: Alice, Bob
+                Alice -> Bob: Request;
+                audit Bob;
+            }
+        "#,
+            &registry,
+        )
+        .unwrap();
+
+        let crate::ast::Protocol::Send { continuation, .. } = choreography.protocol else {
+            panic!("expected a send first");
+        };
+        let crate::ast::Protocol::Extension { extension, .. } = *continuation else {
+            panic!("expected the audit statement after the send");
+        };
+        let statement = extension
+            .as_any()
+            .downcast_ref::<PluginStatement>()
+            .unwrap();
+        assert_eq!(statement.kind, "audit_stmt");
+        assert_eq!(statement.roles, ["Bob"]);
+    }
+
+    #[test]
+    fn test_loading_failures() {
+        let mut registry = ExtensionRegistry::new();
+        assert!(matches!(
+            load_plugin(&mut registry, "/nonexistent/libplugin.so"),
+            Err(PluginError::Load { .. })
+        ));
+        assert!(!registry.has_extensions());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_library_without_plugin_symbols() {
+        let mut registry = ExtensionRegistry::new();
+        assert!(matches!(
+            load_plugin(&mut registry, "libc.so.6"),
+            Err(PluginError::MissingSymbol {
+                symbol: DESCRIPTOR_SYMBOL,
+                ..
+            })
+        ));
+        assert!(!registry.has_extensions());
+    }
+}
@@ -24,22 +24,43 @@ pub use compiler::{
     create_standard_extension_parser, ExtensionParseError, ExtensionParser, ExtensionParserBuilder,
     GrammarComposer, GrammarComposerBuilder, GrammarCompositionError,
 };
-pub use effects::middleware::{Metrics, Retry, Trace};
+pub use effects::middleware::{
+    Cancellable, Deadlined, Debugged, Guarded, Instrumented, Journaled, Metered, Metrics, Outboxed,
+    Retry, SessionHandle, Trace, Traced,
+};
 pub use effects::NoOpHandler;
+pub use effects::SessionMetrics;
+pub use effects::SessionPolicy;
 pub use effects::{
     interpret, ChoreoHandler, ChoreoHandlerExt, ChoreographyError, Effect, Endpoint,
     InterpretResult, InterpreterState, Label, Program, ProgramMessage, Result, RoleId,
 };
+pub use effects::{session_result, SessionContext, SessionError, SessionResult};
 pub use effects::{InMemoryHandler, RecordedEvent, RecordingHandler};
 pub use effects::{RumpsteakEndpoint, RumpsteakHandler, SimpleChannel};
+pub use extensions::annotations::{
+    AnnotationSchema, AnnotationSpec, AnnotationTarget, AnnotationValue, AnnotationValueType,
+};
 pub use extensions::{
-    CodegenContext, ExtensionRegistry, ExtensionValidationError, GrammarExtension, ParseContext,
-    ParseError, ProjectionContext, ProtocolExtension, StatementParser,
+    CodegenContext, CodegenHook, ExtensionPoint, ExtensionRegistry, ExtensionValidationError,
+    GrammarExtension, MessageSite, ParseContext, ParseError, ProjectionContext, ProjectionHook,
+    ProtocolExtension, StatementParser,
 };
-pub use runtime::{spawn, spawn_local};
+pub use runtime::spawn;
+#[cfg(any(target_arch = "wasm32", feature = "tokio"))]
+pub use runtime::spawn_local;
+
+#[cfg(feature = "metrics")]
+pub use effects::GlobalMetrics;
 
 // Re-export macros from rumpsteak-macros
-pub use rumpsteak_aura_macros::choreography;
+pub use rumpsteak_aura_macros::{choreography, implements_role};
+
+// Handler traits generated with `@codegen(style = "handlers")` use this
+pub use async_trait::async_trait;
+
+// Streamed messages and `ChoreoHandler::send_bytes` carry their chunks as this
+pub use bytes::Bytes;
 
 // High-level API functions for extension-aware compilation
 
@@ -48,32 +69,155 @@ pub fn parse_and_generate_with_extensions(
     input: &str,
     extension_registry: &ExtensionRegistry,
 ) -> std::result::Result<proc_macro2::TokenStream, CompilationError> {
-    use compiler::codegen::generate_choreography_code_with_extensions;
     use compiler::parser::parse_choreography_str_with_extensions;
-    use compiler::projection::project;
 
     let (choreography, extensions) =
         parse_choreography_str_with_extensions(input, extension_registry)
             .map_err(CompilationError::ParseError)?;
+    generate_parsed_with_extensions(&choreography, &extensions, extension_registry)
+}
 
-    // Validate the choreography
-    choreography
-        .validate()
-        .map_err(|e| CompilationError::ValidationError(e.to_string()))?;
+/// Validate, project and generate code for an already parsed choreography
+pub(crate) fn generate_parsed_with_extensions(
+    choreography: &Choreography,
+    extensions: &[Box<dyn ProtocolExtension>],
+    extension_registry: &ExtensionRegistry,
+) -> std::result::Result<proc_macro2::TokenStream, CompilationError> {
+    use compiler::codegen::{generate_monitor, generate_role_session, is_monitored};
+    use compiler::projection::project_located;
+
+    validate_parsed(choreography)?;
 
-    // Project to local types
-    let mut local_types = Vec::new();
+    // Project and generate each role
+    let mut sessions = proc_macro2::TokenStream::new();
+    let mut monitors = proc_macro2::TokenStream::new();
     for role in &choreography.roles {
-        let local_type = project(&choreography, role)
-            .map_err(|e| CompilationError::ProjectionError(e.to_string()))?;
-        local_types.push((role.clone(), local_type));
+        let local_type = project_located(choreography, role, extension_registry)?;
+        sessions.extend(generate_role_session(choreography, role, &local_type));
+        if is_monitored(choreography) {
+            monitors.extend(generate_monitor(role, &local_type));
+        }
+    }
+
+    Ok(assemble_generated(
+        choreography,
+        sessions,
+        monitors,
+        extensions,
+        extension_registry,
+    ))
+}
+
+/// Parse and generate choreography code, projecting and generating roles
+/// on up to `jobs` threads
+///
+/// Roles are only spread over threads with the `parallel` feature and
+/// `jobs > 1`; the output is identical to
+/// [`parse_and_generate_with_extensions`] either way.
+pub fn parse_and_generate_with_jobs(
+    input: &str,
+    extension_registry: &ExtensionRegistry,
+    jobs: usize,
+) -> std::result::Result<String, CompilationError> {
+    use compiler::parser::parse_choreography_str_with_extensions;
+
+    let (choreography, extensions) =
+        parse_choreography_str_with_extensions(input, extension_registry)
+            .map_err(CompilationError::ParseError)?;
+    generate_parsed_with_jobs(input, &choreography, &extensions, extension_registry, jobs)
+}
+
+/// [`generate_parsed_with_extensions`] on up to `jobs` threads for the
+/// choreography parsed from `input`
+pub(crate) fn generate_parsed_with_jobs(
+    input: &str,
+    choreography: &Choreography,
+    extensions: &[Box<dyn ProtocolExtension>],
+    extension_registry: &ExtensionRegistry,
+    jobs: usize,
+) -> std::result::Result<String, CompilationError> {
+    #[cfg(feature = "parallel")]
+    if jobs > 1 {
+        validate_parsed(choreography)?;
+        if let Some(sessions) = compiler::parallel::generate_role_sessions(
+            input,
+            extension_registry,
+            choreography.roles.len(),
+            jobs,
+        ) {
+            let parse = |code: &str| {
+                code.parse::<proc_macro2::TokenStream>()
+                    .map_err(|e| CompilationError::CodegenError(e.to_string()))
+            };
+            let mut tokens = proc_macro2::TokenStream::new();
+            let mut monitors = proc_macro2::TokenStream::new();
+            for (session, monitor) in sessions {
+                tokens.extend(parse(&session)?);
+                if let Some(monitor) = monitor {
+                    monitors.extend(parse(&monitor)?);
+                }
+            }
+            let generated = assemble_generated(
+                choreography,
+                tokens,
+                monitors,
+                extensions,
+                extension_registry,
+            );
+            return Ok(generated.to_string());
+        }
+        // A role failed to project: the serial path reports the error
     }
+    #[cfg(not(feature = "parallel"))]
+    let _ = (input, jobs);
+
+    generate_parsed_with_extensions(choreography, extensions, extension_registry)
+        .map(|generated| generated.to_string())
+}
+
+fn validate_parsed(choreography: &Choreography) -> std::result::Result<(), CompilationError> {
+    choreography
+        .validate()
+        .map_err(|e| CompilationError::ValidationError(e.to_string()))
+}
+
+/// Surround the generated role `sessions` with the role structs, extension
+/// code, the role `monitors` under `@codegen(monitor)` and the items
+/// contributed by code generation hooks
+fn assemble_generated(
+    choreography: &Choreography,
+    sessions: proc_macro2::TokenStream,
+    monitors: proc_macro2::TokenStream,
+    extensions: &[Box<dyn ProtocolExtension>],
+    extension_registry: &ExtensionRegistry,
+) -> proc_macro2::TokenStream {
+    use compiler::codegen::{
+        generate_choreography_code_from_sessions, generate_monitors_module, is_monitored,
+    };
+    use compiler::effects_codegen::generate_hook_items;
 
-    // Generate code with extensions
     let generated_code =
-        generate_choreography_code_with_extensions(&choreography, &local_types, &extensions);
+        generate_choreography_code_from_sessions(choreography, sessions, extensions);
+
+    // Add items contributed by code generation hooks
+    let choreography_name = choreography.name.to_string();
+    let context = CodegenContext {
+        choreography_name: &choreography_name,
+        roles: &choreography.roles,
+        namespace: choreography.namespace.as_deref(),
+    };
+    let hook_items = generate_hook_items(&context, extension_registry.codegen_hooks());
+    let monitors = if is_monitored(choreography) {
+        generate_monitors_module(monitors)
+    } else {
+        quote::quote! {}
+    };
 
-    Ok(generated_code)
+    quote::quote! {
+        #generated_code
+        #monitors
+        #hook_items
+    }
 }
 
 /// Convenience function for compiling choreography with built-in extensions
@@ -105,7 +249,7 @@ pub enum CompilationError {
     ValidationError(String),
 
     #[error("Projection error: {0}")]
-    ProjectionError(String),
+    ProjectionError(#[from] compiler::projection::LocatedProjectionError),
 
     #[error("Code generation error: {0}")]
     CodegenError(String),
@@ -149,4 +293,61 @@ mod tests {
         assert!(program.has_timeouts());
         assert!(program.has_parallel());
     }
+
+    #[test]
+    fn test_generate_with_jobs_matches_serial() {
+        let input = r#"
+            choreography Auction {
+                roles: Auctioneer, A, B, C, D, E
+
+                Auctioneer -> A: Offer
+                Auctioneer -> B: Offer
+                Auctioneer -> C: Offer
+                Auctioneer -> D: Offer
+                Auctioneer -> E: Offer
+                A -> Auctioneer: Bid
+                B -> Auctioneer: Bid
+                C -> Auctioneer: Bid
+                D -> Auctioneer: Bid
+                E -> Auctioneer: Bid
+            }
+        "#;
+        let registry = ExtensionRegistry::with_builtin_extensions();
+        let serial = parse_and_generate_with_extensions(input, &registry)
+            .unwrap()
+            .to_string();
+
+        for jobs in [1, 2, 4, 16] {
+            assert_eq!(
+                parse_and_generate_with_jobs(input, &registry, jobs).unwrap(),
+                serial
+            );
+        }
+    }
+
+    #[test]
+    fn test_generate_with_jobs_reports_projection_errors() {
+        let input = r#"
+            choreography Broken {
+                roles: A, B, C
+
+                choice A {
+                    left: {
+                        A -> B: Left
+                        B -> C: Ping
+                    }
+                    right: {
+                        A -> B: Right
+                        C -> B: Pong
+                    }
+                }
+            }
+        "#;
+        let registry = ExtensionRegistry::with_builtin_extensions();
+
+        assert!(matches!(
+            parse_and_generate_with_jobs(input, &registry, 4),
+            Err(CompilationError::ProjectionError(_))
+        ));
+    }
 }
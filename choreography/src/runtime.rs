@@ -1,36 +1,35 @@
 // Runtime abstraction layer for cross-platform async execution
 //
-// Provides platform-specific implementations for spawning tasks and running futures.
-// Supports both native (Tokio) and WASM (wasm-bindgen-futures) targets.
+// Provides executor-independent helpers for spawning tasks, sleeping and
+// bounding futures with a deadline, and for reading the clock. They delegate to the `DefaultRuntime`
+// selected by the `tokio`, `async-std` or `smol` feature on native targets,
+// and to wasm-bindgen-futures and wasm-timer on WASM targets.
 
 use std::future::Future;
+use std::time::{Duration, SystemTime};
+
+use crate::effects::{ChoreographyError, Result};
+use provider::{DefaultRuntime, RuntimeProvider};
 
 /// Marker trait for runtime implementations (not used as trait object)
 pub trait AsyncRuntime: Send + Sync + 'static {}
 
 /// Helper function to spawn a task using platform-specific runtime
 ///
-/// On native targets, uses `tokio::spawn`.
+/// On native targets, uses the executor of the `DefaultRuntime`.
 /// On WASM targets, uses `wasm_bindgen_futures::spawn_local`.
 pub fn spawn<F>(future: F)
 where
     F: Future<Output = ()> + Send + 'static,
 {
-    #[cfg(not(target_arch = "wasm32"))]
-    {
-        tokio::spawn(future);
-    }
-
-    #[cfg(target_arch = "wasm32")]
-    {
-        wasm_bindgen_futures::spawn_local(future);
-    }
+    DefaultRuntime::spawn(future);
 }
 
 /// Helper function to spawn a local task using platform-specific runtime
 ///
 /// On native targets, uses `tokio::task::spawn_local`.
 /// On WASM targets, uses `wasm_bindgen_futures::spawn_local`.
+#[cfg(any(target_arch = "wasm32", feature = "tokio"))]
 pub fn spawn_local<F>(future: F)
 where
     F: Future<Output = ()> + 'static,
@@ -45,3 +44,116 @@ where
         wasm_bindgen_futures::spawn_local(future);
     }
 }
+
+/// Wait for `duration` on the `DefaultRuntime`'s timer
+pub async fn sleep(duration: Duration) {
+    DefaultRuntime::sleep(duration).await;
+}
+
+/// Current wall-clock time
+///
+/// `SystemTime::now` panics on wasm32-unknown-unknown, so WASM targets read
+/// the clock through wasm-timer.
+#[must_use]
+pub fn now() -> SystemTime {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        SystemTime::now()
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    {
+        let since_epoch = wasm_timer::SystemTime::now()
+            .duration_since(wasm_timer::UNIX_EPOCH)
+            .unwrap_or_default();
+        std::time::UNIX_EPOCH + since_epoch
+    }
+}
+
+/// Monotonic clock instant
+///
+/// Like [`now`], WASM targets read the clock through wasm-timer, as
+/// `std::time::Instant::now` panics on wasm32-unknown-unknown.
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::Instant;
+#[cfg(target_arch = "wasm32")]
+pub use wasm_timer::Instant;
+
+/// Run `future`, failing with `ChoreographyError::Timeout` if it has not
+/// completed within `duration`
+pub async fn timeout<T>(duration: Duration, future: impl Future<Output = Result<T>>) -> Result<T> {
+    use futures::future::{select, Either};
+    use futures::pin_mut;
+
+    let delay = sleep(duration);
+    pin_mut!(future);
+    pin_mut!(delay);
+
+    match select(future, delay).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(ChoreographyError::Timeout(duration)),
+    }
+}
+
+/// Run a session from JavaScript, as a `Promise`
+///
+/// Exported `#[wasm_bindgen]` functions return this so the browser can await
+/// a role's program: the promise resolves with the session's output and
+/// rejects with the error message.
+#[cfg(target_arch = "wasm32")]
+pub fn session_promise<F, T>(future: F) -> js_sys::Promise
+where
+    F: Future<Output = crate::effects::Result<T>> + 'static,
+    T: Into<wasm_bindgen::JsValue>,
+{
+    wasm_bindgen_futures::future_to_promise(async move {
+        future
+            .await
+            .map(Into::into)
+            .map_err(|e| wasm_bindgen::JsValue::from_str(&e.to_string()))
+    })
+}
+
+pub mod actor;
+pub mod address_book;
+pub mod blocking;
+pub mod bootstrap;
+pub mod chaos;
+#[cfg(feature = "proptest")]
+pub mod conformance;
+pub mod debugger;
+pub mod dedup;
+pub mod discovery;
+pub mod flow;
+pub mod fuzz;
+pub mod group;
+pub mod guard;
+pub mod harness;
+pub mod heartbeat;
+pub mod http;
+pub mod introspect;
+pub mod journal;
+pub mod monitor;
+pub mod outbox;
+pub mod prometheus;
+pub mod provider;
+pub mod quorum;
+pub mod reassign;
+pub mod recording;
+pub mod registry;
+pub mod scheduler;
+pub mod script;
+#[cfg(feature = "secure")]
+pub mod secure;
+pub mod sim;
+pub mod trace;
+pub mod upgrade;
+
+#[cfg(all(feature = "tls", not(target_arch = "wasm32")))]
+pub mod tls;
+
+#[cfg(all(feature = "tower", not(target_arch = "wasm32")))]
+pub mod tower;
+
+#[cfg(feature = "websocket")]
+pub mod websocket;
@@ -0,0 +1,338 @@
+// Roles as actors
+//
+// Lets applications built from actors play roles of a choreography without
+// restructuring them around channels. Each role is an actor, and its
+// messages travel as `Envelope`s between actor addresses: a send is a
+// message told to the peer's actor, and the peer's handler for envelopes
+// delivers them to the `Mailbox` of the session it runs.
+//
+// The session itself is driven by the generated role code over an
+// `ActorHandler` and the role's `ActorEndpoint`, which owns the receiving
+// end of the mailbox. Actors process messages in the order they arrive,
+// while the protocol fixes which peer and which message come next, so the
+// endpoint keeps envelopes that arrived early, per sender, until the
+// protocol gets to them. An envelope out of step with the protocol, such as
+// a choice where a message was due or a message of another type, is a
+// protocol violation.
+//
+// Any address that can be told an envelope is an `ActorRef`: futures
+// channels are, as are `actix::Recipient`s with the `actix` feature, which
+// also provides a `RoleActor` running a mailbox as an actix actor.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::channel::mpsc;
+use futures::StreamExt;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::effects::middleware::message_label;
+use crate::effects::{ChoreoHandler, ChoreographyError, Label, Result, RoleId};
+
+/// What an envelope carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvelopeKind {
+    /// A message, whose label is the name of its type
+    Message,
+    /// The label of a branch the sender chose
+    Choice,
+}
+
+/// A protocol step sent from one role's actor to another's
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Envelope<R> {
+    pub from: R,
+    pub kind: EnvelopeKind,
+    pub label: String,
+    /// Message serialized with bincode, empty for choices
+    pub payload: Vec<u8>,
+}
+
+/// Address of a role's actor, which envelopes are told to
+pub trait ActorRef<R>: Send + Sync {
+    /// Deliver `envelope` to the actor, without waiting for it to be handled
+    ///
+    /// # Errors
+    ///
+    /// The actor stopped or its mailbox is full.
+    fn tell(&self, envelope: Envelope<R>) -> Result<()>;
+}
+
+impl<R: Send> ActorRef<R> for mpsc::UnboundedSender<Envelope<R>> {
+    fn tell(&self, envelope: Envelope<R>) -> Result<()> {
+        self.unbounded_send(envelope)
+            .map_err(|_| ChoreographyError::Transport("actor mailbox closed".into()))
+    }
+}
+
+/// Delivers envelopes to the session of an `ActorEndpoint`, for the
+/// message handler of the role's actor
+#[derive(Debug, Clone)]
+pub struct Mailbox<R> {
+    sender: mpsc::UnboundedSender<Envelope<R>>,
+}
+
+impl<R> Mailbox<R> {
+    /// Hand `envelope` to the session; returns whether it is still running
+    pub fn deliver(&self, envelope: Envelope<R>) -> bool {
+        self.sender.unbounded_send(envelope).is_ok()
+    }
+}
+
+impl<R: Send> ActorRef<R> for Mailbox<R> {
+    fn tell(&self, envelope: Envelope<R>) -> Result<()> {
+        self.sender.tell(envelope)
+    }
+}
+
+/// Endpoint of a role played by an actor
+pub struct ActorEndpoint<R> {
+    role: R,
+    peers: HashMap<R, Box<dyn ActorRef<R>>>,
+    mailbox: Mailbox<R>,
+    inbox: mpsc::UnboundedReceiver<Envelope<R>>,
+    /// Envelopes that arrived before the protocol got to them, by sender
+    early: HashMap<R, VecDeque<Envelope<R>>>,
+}
+
+impl<R: fmt::Debug> fmt::Debug for ActorEndpoint<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ActorEndpoint")
+            .field("role", &self.role)
+            .field("peers", &self.peers.keys().collect::<Vec<_>>())
+            .field(
+                "early",
+                &self.early.values().map(VecDeque::len).sum::<usize>(),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl<R: RoleId> ActorEndpoint<R> {
+    #[must_use]
+    pub fn new(role: R) -> Self {
+        let (sender, inbox) = mpsc::unbounded();
+        Self {
+            role,
+            peers: HashMap::new(),
+            mailbox: Mailbox { sender },
+            inbox,
+            early: HashMap::new(),
+        }
+    }
+
+    /// Send to `peer` by telling its actor at `address`
+    #[must_use]
+    pub fn with_peer(mut self, peer: R, address: impl ActorRef<R> + 'static) -> Self {
+        self.peers.insert(peer, Box::new(address));
+        self
+    }
+
+    pub fn role(&self) -> R {
+        self.role
+    }
+
+    /// Mailbox the role's actor delivers the envelopes it is told to
+    #[must_use]
+    pub fn mailbox(&self) -> Mailbox<R> {
+        self.mailbox.clone()
+    }
+
+    fn tell(&self, to: R, kind: EnvelopeKind, label: &str, payload: Vec<u8>) -> Result<()> {
+        let peer = self
+            .peers
+            .get(&to)
+            .ok_or_else(|| ChoreographyError::UnknownRole(format!("{to:?}")))?;
+        peer.tell(Envelope {
+            from: self.role,
+            kind,
+            label: label.to_string(),
+            payload,
+        })
+    }
+
+    /// Next envelope from any of `from`, keeping the others for later
+    async fn next_from(&mut self, from: &[R]) -> Result<Envelope<R>> {
+        for sender in from {
+            if let Some(envelope) = self.early.get_mut(sender).and_then(VecDeque::pop_front) {
+                return Ok(envelope);
+            }
+        }
+        loop {
+            let envelope = self.inbox.next().await.ok_or_else(|| {
+                ChoreographyError::Transport("actor mailbox closed while waiting".into())
+            })?;
+            if from.contains(&envelope.from) {
+                return Ok(envelope);
+            }
+            tracing::trace!(from = ?envelope.from, label = %envelope.label, "envelope kept for later");
+            self.early
+                .entry(envelope.from)
+                .or_default()
+                .push_back(envelope);
+        }
+    }
+}
+
+/// Handler playing a role through its actor's `ActorEndpoint`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ActorHandler<R> {
+    _role: std::marker::PhantomData<R>,
+}
+
+impl<R> ActorHandler<R> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            _role: std::marker::PhantomData,
+        }
+    }
+}
+
+/// Payload of `envelope` if it is the message `M`
+fn open<R: fmt::Debug, M: DeserializeOwned>(envelope: &Envelope<R>) -> Result<M> {
+    let expected = message_label::<M>();
+    if envelope.kind != EnvelopeKind::Message || envelope.label != expected {
+        return Err(ChoreographyError::ProtocolViolation(format!(
+            "expected {expected} from {:?}, received {:?} {}",
+            envelope.from, envelope.kind, envelope.label
+        )));
+    }
+    bincode::deserialize(&envelope.payload)
+        .map_err(|e| ChoreographyError::Serialization(e.to_string()))
+}
+
+#[async_trait]
+impl<R: RoleId> ChoreoHandler for ActorHandler<R> {
+    type Role = R;
+    type Endpoint = ActorEndpoint<R>;
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        let payload =
+            bincode::serialize(msg).map_err(|e| ChoreographyError::Serialization(e.to_string()))?;
+        ep.tell(to, EnvelopeKind::Message, message_label::<M>(), payload)
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+    ) -> Result<M> {
+        let envelope = ep.next_from(&[from]).await?;
+        open(&envelope)
+    }
+
+    async fn recv_any<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: &[Self::Role],
+    ) -> Result<(Self::Role, M)> {
+        let envelope = ep.next_from(from).await?;
+        Ok((envelope.from, open(&envelope)?))
+    }
+
+    async fn choose(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        _who: Self::Role,
+        label: Label,
+    ) -> Result<()> {
+        let peers: Vec<R> = ep.peers.keys().copied().collect();
+        for peer in peers {
+            ep.tell(peer, EnvelopeKind::Choice, label.0, Vec::new())?;
+        }
+        Ok(())
+    }
+
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        let envelope = ep.next_from(&[from]).await?;
+        if envelope.kind != EnvelopeKind::Choice {
+            return Err(ChoreographyError::ProtocolViolation(format!(
+                "expected a choice from {from:?}, received {}",
+                envelope.label
+            )));
+        }
+        Label::resolve(&envelope.label, labels)
+    }
+
+    async fn with_timeout<F, T>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        at: Self::Role,
+        dur: Duration,
+        body: F,
+    ) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>> + Send,
+    {
+        if at == ep.role {
+            crate::runtime::timeout(dur, body).await
+        } else {
+            body.await
+        }
+    }
+}
+
+#[cfg(feature = "actix")]
+mod actix_actors {
+    use super::{ActorRef, Envelope, Mailbox};
+    use crate::effects::{ChoreographyError, Result};
+
+    impl<R: Send + 'static> actix::Message for Envelope<R> {
+        type Result = ();
+    }
+
+    impl<R: Send + 'static> ActorRef<R> for actix::Recipient<Envelope<R>> {
+        fn tell(&self, envelope: Envelope<R>) -> Result<()> {
+            self.try_send(envelope)
+                .map_err(|e| ChoreographyError::Transport(format!("actor unreachable: {e}")))
+        }
+    }
+
+    /// Actix actor of a role, delivering the envelopes it is told to the
+    /// session of its mailbox
+    ///
+    /// Actors with state of their own implement `Handler<Envelope<R>>` the
+    /// same way instead.
+    #[derive(Debug)]
+    pub struct RoleActor<R> {
+        mailbox: Mailbox<R>,
+    }
+
+    impl<R: Send + Unpin + 'static> RoleActor<R> {
+        #[must_use]
+        pub fn new(mailbox: Mailbox<R>) -> Self {
+            Self { mailbox }
+        }
+    }
+
+    impl<R: Send + Unpin + 'static> actix::Actor for RoleActor<R> {
+        type Context = actix::Context<Self>;
+    }
+
+    impl<R: Send + Unpin + 'static> actix::Handler<Envelope<R>> for RoleActor<R> {
+        type Result = ();
+
+        fn handle(&mut self, envelope: Envelope<R>, ctx: &mut Self::Context) {
+            if !self.mailbox.deliver(envelope) {
+                // The session ended, and with it the role
+                actix::ActorContext::stop(ctx);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "actix")]
+pub use actix_actors::RoleActor;
@@ -0,0 +1,636 @@
+// Addresses of role instances
+//
+// Roles declared as a family, such as `Worker[N]`, have one participant per
+// instance. A `RoleAddressBook` maps each `(role, index)` pair to whatever
+// reaches that participant: the role identifier of a `ChoreoHandler`, a
+// device ID, or a transport address. A plain role is stored as instance 0.
+//
+// Statements address instances with the selectors of the DSL, which resolve
+// against the book:
+//
+//     Worker[2]       one instance
+//     Worker[*]       every instance
+//     Worker[0..n]    the instances in a range
+//
+// `send_to`, `broadcast` and `gather_from` run the resolved steps over a
+// handler, so code generated for parameterized roles does not wire up each
+// instance by hand. `gather_quorum` receives from `Signer[t of N]`, and
+// `hand_over` and `take_over` move a role to another participant.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
+
+use crate::ast::role::MAX_ROLE_INDEX;
+use crate::effects::{ChoreoHandler, ChoreographyError, Result};
+use crate::runtime::quorum::{self, Quorum, QuorumSpec, StragglerPolicy};
+use crate::runtime::reassign::{Election, Handover};
+
+/// Instances of a role picked out by a [`RoleSelector`]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Instances {
+    /// `Worker[i]`
+    One(u32),
+    /// `Worker[*]`
+    All,
+    /// `Worker[start..end]`, end exclusive
+    Range(Range<u32>),
+}
+
+/// Reference to one or more instances of a role
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RoleSelector {
+    pub role: String,
+    pub instances: Instances,
+}
+
+impl RoleSelector {
+    /// `role[index]`, or the role itself for index 0 of a plain role
+    #[must_use]
+    pub fn one(role: impl Into<String>, index: u32) -> Self {
+        Self {
+            role: role.into(),
+            instances: Instances::One(index),
+        }
+    }
+
+    /// `role[*]`
+    #[must_use]
+    pub fn all(role: impl Into<String>) -> Self {
+        Self {
+            role: role.into(),
+            instances: Instances::All,
+        }
+    }
+
+    /// `role[start..end]`
+    #[must_use]
+    pub fn range(role: impl Into<String>, range: Range<u32>) -> Self {
+        Self {
+            role: role.into(),
+            instances: Instances::Range(range),
+        }
+    }
+
+    /// Parse a selector in DSL syntax, looking up symbolic indices and range
+    /// bounds with `binding`
+    ///
+    /// `Worker` alone selects instance 0.
+    ///
+    /// # Errors
+    ///
+    /// [`AddressBookError::InvalidSelector`] if `expr` is not a selector, or
+    /// [`AddressBookError::UnboundIndex`] if `binding` does not know a
+    /// symbolic name.
+    pub fn parse_with(
+        expr: &str,
+        binding: impl Fn(&str) -> Option<u32>,
+    ) -> std::result::Result<Self, AddressBookError> {
+        let invalid = || AddressBookError::InvalidSelector(expr.to_string());
+        let expr = expr.trim();
+
+        let Some(open) = expr.find('[') else {
+            if expr.is_empty() || !expr.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                return Err(invalid());
+            }
+            return Ok(Self::one(expr, 0));
+        };
+        let role = expr[..open].trim();
+        let index = expr[open + 1..]
+            .strip_suffix(']')
+            .ok_or_else(invalid)?
+            .trim();
+        if role.is_empty() {
+            return Err(invalid());
+        }
+
+        let value = |text: &str| -> std::result::Result<u32, AddressBookError> {
+            let text = text.trim();
+            if text.is_empty() {
+                return Err(invalid());
+            }
+            if text.chars().all(|c| c.is_ascii_digit()) {
+                return text.parse().map_err(|_| invalid());
+            }
+            binding(text).ok_or_else(|| AddressBookError::UnboundIndex(text.to_string()))
+        };
+
+        let instances = if index == "*" {
+            Instances::All
+        } else if let Some((start, end)) = index.split_once("..") {
+            Instances::Range(value(start)?..value(end)?)
+        } else {
+            Instances::One(value(index)?)
+        };
+        Ok(Self {
+            role: role.to_string(),
+            instances,
+        })
+    }
+}
+
+impl FromStr for RoleSelector {
+    type Err = AddressBookError;
+
+    fn from_str(expr: &str) -> std::result::Result<Self, Self::Err> {
+        Self::parse_with(expr, |_| None)
+    }
+}
+
+impl fmt::Display for RoleSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.instances {
+            Instances::One(index) => write!(f, "{}[{index}]", self.role),
+            Instances::All => write!(f, "{}[*]", self.role),
+            Instances::Range(range) => write!(f, "{}[{}..{}]", self.role, range.start, range.end),
+        }
+    }
+}
+
+/// A selector could not be parsed or resolved
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum AddressBookError {
+    #[error("'{0}' is not a role selector")]
+    InvalidSelector(String),
+
+    #[error("Symbolic index '{0}' is not bound")]
+    UnboundIndex(String),
+
+    #[error("No address for role {0}")]
+    UnknownRole(String),
+
+    #[error("No address for {role}[{index}]")]
+    MissingInstance { role: String, index: u32 },
+
+    #[error("Role index {index} exceeds maximum allowed {max}")]
+    IndexOverflow { index: u32, max: u32 },
+
+    #[error("{0} selects more than one instance")]
+    NotSingle(String),
+
+    #[error("No candidate was elected to take over {0}")]
+    NotElected(String),
+}
+
+/// Addresses of the instances of every role, keyed by role name and index
+#[derive(Debug, Clone)]
+pub struct RoleAddressBook<A> {
+    roles: HashMap<String, BTreeMap<u32, A>>,
+}
+
+impl<A> Default for RoleAddressBook<A> {
+    fn default() -> Self {
+        Self {
+            roles: HashMap::new(),
+        }
+    }
+}
+
+impl<A> RoleAddressBook<A> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the address of `role[index]`, returning the one it replaces
+    ///
+    /// # Errors
+    ///
+    /// [`AddressBookError::IndexOverflow`] past `MAX_ROLE_INDEX`.
+    pub fn insert(
+        &mut self,
+        role: impl Into<String>,
+        index: u32,
+        address: A,
+    ) -> std::result::Result<Option<A>, AddressBookError> {
+        if index > MAX_ROLE_INDEX {
+            return Err(AddressBookError::IndexOverflow {
+                index,
+                max: MAX_ROLE_INDEX,
+            });
+        }
+        Ok(self
+            .roles
+            .entry(role.into())
+            .or_default()
+            .insert(index, address))
+    }
+
+    /// Add the address of a plain role, as instance 0
+    pub fn insert_role(&mut self, role: impl Into<String>, address: A) -> Option<A> {
+        self.roles
+            .entry(role.into())
+            .or_default()
+            .insert(0, address)
+    }
+
+    /// Replace the instances of `role` with `addresses`, numbered from 0
+    ///
+    /// # Errors
+    ///
+    /// [`AddressBookError::IndexOverflow`] for more than `MAX_ROLE_INDEX + 1`
+    /// addresses, leaving the book unchanged.
+    pub fn insert_all(
+        &mut self,
+        role: impl Into<String>,
+        addresses: impl IntoIterator<Item = A>,
+    ) -> std::result::Result<(), AddressBookError> {
+        let mut instances = BTreeMap::new();
+        for (index, address) in addresses.into_iter().enumerate() {
+            let index = u32::try_from(index).unwrap_or(u32::MAX);
+            if index > MAX_ROLE_INDEX {
+                return Err(AddressBookError::IndexOverflow {
+                    index,
+                    max: MAX_ROLE_INDEX,
+                });
+            }
+            instances.insert(index, address);
+        }
+        self.roles.insert(role.into(), instances);
+        Ok(())
+    }
+
+    /// Remove the address of `role[index]`
+    pub fn remove(&mut self, role: &str, index: u32) -> Option<A> {
+        let instances = self.roles.get_mut(role)?;
+        let address = instances.remove(&index);
+        if instances.is_empty() {
+            self.roles.remove(role);
+        }
+        address
+    }
+
+    #[must_use]
+    pub fn get(&self, role: &str, index: u32) -> Option<&A> {
+        self.roles.get(role)?.get(&index)
+    }
+
+    /// Number of instances of `role` with an address
+    #[must_use]
+    pub fn count(&self, role: &str) -> usize {
+        self.roles.get(role).map_or(0, BTreeMap::len)
+    }
+
+    /// Addresses of the instances `selector` picks out, in index order
+    ///
+    /// # Errors
+    ///
+    /// [`AddressBookError::UnknownRole`] if the role has no instances, or
+    /// [`AddressBookError::MissingInstance`] if a selected index has no
+    /// address. `Worker[*]` selects whichever instances are present.
+    pub fn resolve(
+        &self,
+        selector: &RoleSelector,
+    ) -> std::result::Result<Vec<(u32, &A)>, AddressBookError> {
+        let instances = self
+            .roles
+            .get(&selector.role)
+            .ok_or_else(|| AddressBookError::UnknownRole(selector.role.clone()))?;
+        let lookup = |index: u32| {
+            instances
+                .get(&index)
+                .map(|address| (index, address))
+                .ok_or_else(|| AddressBookError::MissingInstance {
+                    role: selector.role.clone(),
+                    index,
+                })
+        };
+
+        match &selector.instances {
+            Instances::One(index) => Ok(vec![lookup(*index)?]),
+            Instances::All => Ok(instances.iter().map(|(&i, a)| (i, a)).collect()),
+            Instances::Range(range) => range.clone().map(lookup).collect(),
+        }
+    }
+
+    /// Address of the single instance `selector` picks out
+    ///
+    /// # Errors
+    ///
+    /// As [`resolve`](Self::resolve), and
+    /// [`AddressBookError::NotSingle`] if more than one instance is selected.
+    pub fn resolve_one(
+        &self,
+        selector: &RoleSelector,
+    ) -> std::result::Result<&A, AddressBookError> {
+        match self.resolve(selector)?.as_slice() {
+            [(_, address)] => Ok(address),
+            _ => Err(AddressBookError::NotSingle(selector.to_string())),
+        }
+    }
+}
+
+impl<A: Copy> RoleAddressBook<A> {
+    /// Bind `role` to the address of instance `elected` of `candidates`,
+    /// returning that address
+    ///
+    /// # Errors
+    ///
+    /// [`AddressBookError::MissingInstance`] if the instance has no address.
+    pub fn reassign(
+        &mut self,
+        role: &str,
+        candidates: &str,
+        elected: u32,
+    ) -> std::result::Result<A, AddressBookError> {
+        let address =
+            *self
+                .get(candidates, elected)
+                .ok_or_else(|| AddressBookError::MissingInstance {
+                    role: candidates.to_string(),
+                    index: elected,
+                })?;
+        self.insert_role(role, address);
+        Ok(address)
+    }
+
+    /// Send `msg` to the single instance `to` selects, such as `Worker[i]`
+    ///
+    /// # Errors
+    ///
+    /// [`ChoreographyError::Address`](crate::effects::ChoreographyError::Address)
+    /// if `to` does not resolve to one instance, or the handler's error.
+    pub async fn send_to<H, M>(
+        &self,
+        handler: &mut H,
+        ep: &mut H::Endpoint,
+        to: &RoleSelector,
+        msg: &M,
+    ) -> Result<()>
+    where
+        H: ChoreoHandler<Role = A>,
+        M: Serialize + Send + Sync,
+    {
+        let to = *self.resolve_one(to)?;
+        handler.send(ep, to, msg).await
+    }
+
+    /// Send `msg` to every instance `to` selects, such as `Worker[*]`
+    ///
+    /// # Errors
+    ///
+    /// [`ChoreographyError::Address`](crate::effects::ChoreographyError::Address)
+    /// if `to` does not resolve, or the handler's error.
+    pub async fn broadcast<H, M>(
+        &self,
+        handler: &mut H,
+        ep: &mut H::Endpoint,
+        to: &RoleSelector,
+        msg: &M,
+    ) -> Result<()>
+    where
+        H: ChoreoHandler<Role = A>,
+        M: Serialize + Send + Sync,
+    {
+        let recipients: Vec<A> = self.resolve(to)?.into_iter().map(|(_, &a)| a).collect();
+        handler.broadcast(ep, &recipients, msg).await
+    }
+
+    /// Receive one message from every instance `from` selects, such as
+    /// `Worker[0..n]`, in index order
+    ///
+    /// # Errors
+    ///
+    /// [`ChoreographyError::Address`](crate::effects::ChoreographyError::Address)
+    /// if `from` does not resolve, or the handler's error.
+    pub async fn gather_from<H, M>(
+        &self,
+        handler: &mut H,
+        ep: &mut H::Endpoint,
+        from: &RoleSelector,
+    ) -> Result<Vec<(u32, M)>>
+    where
+        H: ChoreoHandler<Role = A>,
+        M: DeserializeOwned + Send,
+    {
+        let senders: Vec<(u32, A)> = self
+            .resolve(from)?
+            .into_iter()
+            .map(|(i, &a)| (i, a))
+            .collect();
+        let mut messages = Vec::with_capacity(senders.len());
+        for (index, sender) in senders {
+            messages.push((index, handler.recv(ep, sender).await?));
+        }
+        Ok(messages)
+    }
+
+    /// Receive from the first `spec.threshold` of the `spec.of` instances of
+    /// `spec.role` to answer
+    ///
+    /// Under [`StragglerPolicy::Abort`], the instances that had not answered
+    /// are removed from the book.
+    ///
+    /// # Errors
+    ///
+    /// [`ChoreographyError::Address`](crate::effects::ChoreographyError::Address)
+    /// if an instance below `spec.of` has no address, or as
+    /// [`quorum::gather`].
+    pub async fn gather_quorum<H, M>(
+        &mut self,
+        handler: &mut H,
+        ep: &mut H::Endpoint,
+        spec: &QuorumSpec,
+    ) -> Result<Quorum<A, M>>
+    where
+        H: ChoreoHandler<Role = A>,
+        M: DeserializeOwned + Send,
+    {
+        let senders: Vec<(u32, A)> = self
+            .resolve(&RoleSelector::range(&spec.role, 0..spec.of))?
+            .into_iter()
+            .map(|(i, &a)| (i, a))
+            .collect();
+        let quorum = quorum::gather(
+            handler,
+            ep,
+            &senders,
+            spec.threshold as usize,
+            spec.stragglers,
+        )
+        .await?;
+        if spec.stragglers == StragglerPolicy::Abort {
+            for &(index, _) in quorum.stragglers() {
+                self.remove(&spec.role, index);
+            }
+        }
+        Ok(quorum)
+    }
+
+    /// Hand `role` over to one of the instances `candidates` selects, as its
+    /// current holder
+    ///
+    /// `election` picks the new holder. Every candidate is told the outcome
+    /// and the elected one also gets `state`, then the book binds `role` to
+    /// the elected candidate. Returns the elected index.
+    ///
+    /// # Errors
+    ///
+    /// [`ChoreographyError::Address`](crate::effects::ChoreographyError::Address)
+    /// if `candidates` does not resolve or `election` elects none of them,
+    /// or the handler's error.
+    pub async fn hand_over<H, S, E>(
+        &mut self,
+        handler: &mut H,
+        ep: &mut H::Endpoint,
+        role: &str,
+        candidates: &RoleSelector,
+        election: &mut E,
+        state: &S,
+    ) -> Result<u32>
+    where
+        H: ChoreoHandler<Role = A>,
+        S: Serialize + Send + Sync,
+        E: Election<A>,
+    {
+        let resolved = self.resolve(candidates)?;
+        let elected = election
+            .elect(role, &resolved)
+            .filter(|elected| resolved.iter().any(|&(index, _)| index == *elected))
+            .ok_or_else(|| AddressBookError::NotElected(role.to_string()))?;
+        let recipients: Vec<(u32, A)> = resolved.into_iter().map(|(i, &a)| (i, a)).collect();
+
+        for (index, candidate) in recipients {
+            let handover = Handover {
+                role: role.to_string(),
+                candidates: candidates.role.clone(),
+                elected,
+                state: (index == elected).then_some(state),
+            };
+            handler.send(ep, candidate, &handover).await?;
+        }
+        self.reassign(role, &candidates.role, elected)?;
+        Ok(elected)
+    }
+
+    /// Receive the outcome of a reassignment of `role`, as a candidate
+    ///
+    /// The book binds `role` to the elected candidate. The elected candidate
+    /// finds the role's endpoint state in the returned handover.
+    ///
+    /// # Errors
+    ///
+    /// [`ChoreographyError::Address`](crate::effects::ChoreographyError::Address)
+    /// if `role` or the elected candidate has no address,
+    /// [`ChoreographyError::ProtocolViolation`](crate::effects::ChoreographyError::ProtocolViolation)
+    /// if the handover is for another role, or the handler's error.
+    pub async fn take_over<H, S>(
+        &mut self,
+        handler: &mut H,
+        ep: &mut H::Endpoint,
+        role: &str,
+    ) -> Result<Handover<S>>
+    where
+        H: ChoreoHandler<Role = A>,
+        S: DeserializeOwned + Send,
+    {
+        let holder = *self.resolve_one(&RoleSelector::one(role, 0))?;
+        let handover: Handover<S> = handler.recv(ep, holder).await?;
+        if handover.role != role {
+            return Err(ChoreographyError::ProtocolViolation(format!(
+                "expected a handover of {role}, got one of {}",
+                handover.role
+            )));
+        }
+        self.reassign(role, &handover.candidates, handover.elected)?;
+        Ok(handover)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn book() -> RoleAddressBook<&'static str> {
+        let mut book = RoleAddressBook::new();
+        book.insert_role("Master", "10.0.0.1");
+        book.insert_all("Worker", ["10.0.1.0", "10.0.1.1", "10.0.1.2"])
+            .unwrap();
+        book
+    }
+
+    #[test]
+    fn test_parse_selectors() {
+        let bindings = |name: &str| (name == "n").then_some(2);
+
+        assert_eq!(
+            "Worker[1]".parse::<RoleSelector>().unwrap(),
+            RoleSelector::one("Worker", 1)
+        );
+        assert_eq!(
+            "Worker[*]".parse::<RoleSelector>().unwrap(),
+            RoleSelector::all("Worker")
+        );
+        assert_eq!(
+            RoleSelector::parse_with("Worker[0..n]", bindings).unwrap(),
+            RoleSelector::range("Worker", 0..2)
+        );
+        assert_eq!(
+            "Master".parse::<RoleSelector>().unwrap(),
+            RoleSelector::one("Master", 0)
+        );
+        assert_eq!(
+            "Worker[i]".parse::<RoleSelector>(),
+            Err(AddressBookError::UnboundIndex("i".to_string()))
+        );
+        assert!(matches!(
+            "Worker[1".parse::<RoleSelector>(),
+            Err(AddressBookError::InvalidSelector(_))
+        ));
+        assert_eq!(
+            RoleSelector::range("Worker", 0..2).to_string(),
+            "Worker[0..2]"
+        );
+    }
+
+    #[test]
+    fn test_resolve_selectors() {
+        let book = book();
+
+        assert_eq!(
+            book.resolve(&RoleSelector::all("Worker")).unwrap(),
+            vec![(0, &"10.0.1.0"), (1, &"10.0.1.1"), (2, &"10.0.1.2")]
+        );
+        assert_eq!(
+            book.resolve(&RoleSelector::range("Worker", 1..3)).unwrap(),
+            vec![(1, &"10.0.1.1"), (2, &"10.0.1.2")]
+        );
+        assert_eq!(
+            book.resolve_one(&"Master".parse().unwrap()),
+            Ok(&"10.0.0.1")
+        );
+        assert_eq!(
+            book.resolve(&RoleSelector::range("Worker", 2..4)),
+            Err(AddressBookError::MissingInstance {
+                role: "Worker".to_string(),
+                index: 3
+            })
+        );
+        assert_eq!(
+            book.resolve_one(&RoleSelector::all("Worker")),
+            Err(AddressBookError::NotSingle("Worker[*]".to_string()))
+        );
+        assert!(matches!(
+            book.resolve(&RoleSelector::all("Client")),
+            Err(AddressBookError::UnknownRole(_))
+        ));
+    }
+
+    #[test]
+    fn test_insert_bounds_and_remove() {
+        let mut book = book();
+
+        assert!(matches!(
+            book.insert("Worker", MAX_ROLE_INDEX + 1, "far"),
+            Err(AddressBookError::IndexOverflow { .. })
+        ));
+        assert_eq!(book.insert("Worker", 1, "10.0.1.9"), Ok(Some("10.0.1.1")));
+        assert_eq!(book.remove("Worker", 0), Some("10.0.1.0"));
+        assert_eq!(book.count("Worker"), 2);
+        assert_eq!(book.get("Worker", 1), Some(&"10.0.1.9"));
+    }
+}
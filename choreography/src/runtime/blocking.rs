@@ -0,0 +1,284 @@
+// Blocking endpoints for synchronous generated code
+//
+// Code generated with `@codegen(sync)` drives each role through a
+// `BlockingEndpoint` instead of an async `ChoreoHandler`. The endpoint
+// serializes messages and branch labels with bincode and moves the frames
+// over a `Transport`: `ChannelTransport` connects roles in one process with
+// `std::sync::mpsc` channels, `TcpTransport` connects processes with
+// length-prefixed frames over `std::net::TcpStream`s. Neither needs an async
+// runtime.
+
+use crate::effects::{ChoreographyError, Result};
+use bytes::Bytes;
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// Moves frames between the roles of a session, blocking until done
+pub trait Transport<R> {
+    fn send(&mut self, to: R, frame: Vec<u8>) -> Result<()>;
+
+    /// Next frame from `from`, blocking until one arrives
+    fn recv(&mut self, from: R) -> Result<Vec<u8>>;
+}
+
+/// One role's side of a session run by synchronous generated code
+pub struct BlockingEndpoint<R, T> {
+    role: R,
+    /// Roles told about the choices this role makes
+    peers: Vec<R>,
+    transport: T,
+}
+
+impl<R: Copy + Eq + std::fmt::Debug, T: Transport<R>> BlockingEndpoint<R, T> {
+    #[must_use]
+    pub fn new(role: R, peers: Vec<R>, transport: T) -> Self {
+        Self {
+            role,
+            peers,
+            transport,
+        }
+    }
+
+    #[must_use]
+    pub fn role(&self) -> R {
+        self.role
+    }
+
+    pub fn send<M: Serialize>(&mut self, to: R, msg: &M) -> Result<()> {
+        let frame =
+            bincode::serialize(msg).map_err(|e| ChoreographyError::Serialization(e.to_string()))?;
+        self.transport.send(to, frame)
+    }
+
+    pub fn recv<M: DeserializeOwned>(&mut self, from: R) -> Result<M> {
+        let frame = self.transport.recv(from)?;
+        bincode::deserialize(&frame).map_err(|e| ChoreographyError::Serialization(e.to_string()))
+    }
+
+    /// Send `payload` as the frame itself, without encoding it
+    ///
+    /// The receiver must take it with `recv_bytes`.
+    pub fn send_bytes(&mut self, to: R, payload: Bytes) -> Result<()> {
+        self.transport.send(to, Vec::from(payload))
+    }
+
+    /// Frame sent by `from` with `send_bytes`
+    pub fn recv_bytes(&mut self, from: R) -> Result<Bytes> {
+        self.transport.recv(from).map(Bytes::from)
+    }
+
+    /// Send the same message to each of `recipients`
+    pub fn broadcast<M: Serialize>(&mut self, recipients: &[R], msg: &M) -> Result<()> {
+        for &recipient in recipients {
+            self.send(recipient, msg)?;
+        }
+        Ok(())
+    }
+
+    /// Tell every peer which branch this role chose
+    pub fn choose(&mut self, label: &str) -> Result<()> {
+        for peer in self.peers.clone() {
+            if peer != self.role {
+                self.send(peer, &label)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Branch label chosen by `from`
+    pub fn offer(&mut self, from: R) -> Result<String> {
+        self.recv(from)
+    }
+
+    /// The transport, for closing connections once the session is over
+    pub fn into_transport(self) -> T {
+        self.transport
+    }
+}
+
+/// In-process transport over `std::sync::mpsc` channels
+pub struct ChannelTransport<R> {
+    senders: HashMap<R, Sender<Vec<u8>>>,
+    receivers: HashMap<R, Receiver<Vec<u8>>>,
+}
+
+impl<R: Copy + Eq + Hash + std::fmt::Debug> ChannelTransport<R> {
+    /// Transports connecting each of `roles` to every other
+    #[must_use]
+    pub fn mesh(roles: &[R]) -> HashMap<R, Self> {
+        let mut transports: HashMap<R, Self> = roles
+            .iter()
+            .map(|&role| {
+                let transport = Self {
+                    senders: HashMap::new(),
+                    receivers: HashMap::new(),
+                };
+                (role, transport)
+            })
+            .collect();
+        for &from in roles {
+            for &to in roles {
+                if from == to {
+                    continue;
+                }
+                let (sender, receiver) = channel();
+                if let Some(transport) = transports.get_mut(&from) {
+                    transport.senders.insert(to, sender);
+                }
+                if let Some(transport) = transports.get_mut(&to) {
+                    transport.receivers.insert(from, receiver);
+                }
+            }
+        }
+        transports
+    }
+}
+
+impl<R: Copy + Eq + Hash + std::fmt::Debug> Transport<R> for ChannelTransport<R> {
+    fn send(&mut self, to: R, frame: Vec<u8>) -> Result<()> {
+        self.senders
+            .get(&to)
+            .ok_or_else(|| ChoreographyError::UnknownRole(format!("{to:?}")))?
+            .send(frame)
+            .map_err(|_| ChoreographyError::Transport(format!("channel to {to:?} closed")))
+    }
+
+    fn recv(&mut self, from: R) -> Result<Vec<u8>> {
+        self.receivers
+            .get(&from)
+            .ok_or_else(|| ChoreographyError::UnknownRole(format!("{from:?}")))?
+            .recv()
+            .map_err(|_| ChoreographyError::Transport(format!("channel from {from:?} closed")))
+    }
+}
+
+/// Transport over one TCP connection per peer
+///
+/// Frames are prefixed with their length as a big-endian `u32`.
+pub struct TcpTransport<R> {
+    streams: HashMap<R, TcpStream>,
+}
+
+impl<R: Copy + Eq + Hash + std::fmt::Debug> TcpTransport<R> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            streams: HashMap::new(),
+        }
+    }
+
+    /// Use `stream` for all frames to and from `peer`
+    #[must_use]
+    pub fn with_peer(mut self, peer: R, stream: TcpStream) -> Self {
+        self.streams.insert(peer, stream);
+        self
+    }
+
+    fn stream(&mut self, peer: R) -> Result<&mut TcpStream> {
+        self.streams
+            .get_mut(&peer)
+            .ok_or_else(|| ChoreographyError::UnknownRole(format!("{peer:?}")))
+    }
+}
+
+impl<R: Copy + Eq + Hash + std::fmt::Debug> Default for TcpTransport<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: Copy + Eq + Hash + std::fmt::Debug> Transport<R> for TcpTransport<R> {
+    fn send(&mut self, to: R, frame: Vec<u8>) -> Result<()> {
+        let length =
+            u32::try_from(frame.len()).map_err(|_| ChoreographyError::MessageTooLarge {
+                size: frame.len(),
+                max: u32::MAX as usize,
+            })?;
+        let stream = self.stream(to)?;
+        stream
+            .write_all(&length.to_be_bytes())
+            .and_then(|()| stream.write_all(&frame))
+            .and_then(|()| stream.flush())
+            .map_err(|e| ChoreographyError::Transport(format!("send to {to:?} failed: {e}")))
+    }
+
+    fn recv(&mut self, from: R) -> Result<Vec<u8>> {
+        let stream = self.stream(from)?;
+        let mut length = [0; 4];
+        stream
+            .read_exact(&mut length)
+            .map_err(|e| ChoreographyError::Transport(format!("recv from {from:?} failed: {e}")))?;
+        let mut frame = vec![0; u32::from_be_bytes(length) as usize];
+        stream
+            .read_exact(&mut frame)
+            .map_err(|e| ChoreographyError::Transport(format!("recv from {from:?} failed: {e}")))?;
+        Ok(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    enum Role {
+        Alice,
+        Bob,
+        Carol,
+    }
+
+    #[test]
+    fn test_channel_mesh() {
+        let roles = [Role::Alice, Role::Bob, Role::Carol];
+        let mut transports = ChannelTransport::mesh(&roles);
+        let mut alice = BlockingEndpoint::new(
+            Role::Alice,
+            roles.to_vec(),
+            transports.remove(&Role::Alice).unwrap(),
+        );
+        let mut bob = BlockingEndpoint::new(
+            Role::Bob,
+            roles.to_vec(),
+            transports.remove(&Role::Bob).unwrap(),
+        );
+        let mut carol = BlockingEndpoint::new(
+            Role::Carol,
+            roles.to_vec(),
+            transports.remove(&Role::Carol).unwrap(),
+        );
+
+        alice.send(Role::Bob, &(7u32, "hello".to_string())).unwrap();
+        alice.choose("accept").unwrap();
+        assert_eq!(
+            bob.recv::<(u32, String)>(Role::Alice).unwrap(),
+            (7, "hello".to_string())
+        );
+        assert_eq!(bob.offer(Role::Alice).unwrap(), "accept");
+        assert_eq!(carol.offer(Role::Alice).unwrap(), "accept");
+    }
+
+    #[test]
+    fn test_tcp_frames() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let transport = TcpTransport::new().with_peer(Role::Alice, stream);
+            let mut bob = BlockingEndpoint::new(Role::Bob, vec![Role::Alice], transport);
+            let request: String = bob.recv(Role::Alice).unwrap();
+            bob.send(Role::Alice, &request.len()).unwrap();
+        });
+
+        let transport =
+            TcpTransport::new().with_peer(Role::Bob, TcpStream::connect(address).unwrap());
+        let mut alice = BlockingEndpoint::new(Role::Alice, vec![Role::Bob], transport);
+        alice.send(Role::Bob, &"ping".to_string()).unwrap();
+        assert_eq!(alice.recv::<usize>(Role::Bob).unwrap(), 4);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,477 @@
+// Session establishment and role assignment
+//
+// Before a choreography starts, one participant (the initiator) distributes a
+// fresh session ID, the identity of the protocol being run, and the roster of
+// role assignments to every other participant. Each participant checks that it
+// compiled the same protocol version, acknowledges its assigned role, and waits
+// for the initiator's go-ahead. Only when every participant has acknowledged
+// does the initiator release the session.
+//
+// Roles declared with bounds on their instance count, such as
+// `Signer[3..=10]`, are checked before anything is sent: the initiator
+// refuses a roster assigning too few or too many instances.
+//
+// The handshake runs over any `ChoreoHandler`, so the same channels that will
+// carry the choreography (in-memory, SimpleChannel, TLS, ...) carry bootstrap.
+//
+// Message flow (initiator I, participant P):
+//   I -> P: Assign { session_id, protocol, local_role, roster }
+//   P -> I: Ack { session_id } | Reject { session_id, reason }
+//   I -> P: Start { session_id } | Abort { session_id, reason }
+
+use std::ops::RangeInclusive;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::effects::{ChoreoHandler, ChoreographyError, RoleId};
+
+/// Identity of a compiled protocol: its name and a hash of its definition.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ProtocolDescriptor {
+    pub name: String,
+    pub hash: u64,
+}
+
+impl ProtocolDescriptor {
+    #[must_use]
+    pub fn new(name: impl Into<String>, hash: u64) -> Self {
+        Self {
+            name: name.into(),
+            hash,
+        }
+    }
+
+    /// Describe a protocol by its DSL source. See [`protocol_hash`].
+    #[must_use]
+    pub fn from_source(name: impl Into<String>, source: &str) -> Self {
+        Self::new(name, protocol_hash(source))
+    }
+}
+
+impl std::fmt::Display for ProtocolDescriptor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}#{:016x}", self.name, self.hash)
+    }
+}
+
+/// Stable hash of a choreography's DSL source.
+///
+/// Whitespace is normalised so formatting changes do not alter the hash.
+/// Uses 64-bit FNV-1a, which is stable across platforms and compiler versions.
+#[must_use]
+pub fn protocol_hash(source: &str) -> u64 {
+    const OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let mut hash = OFFSET;
+    for (i, token) in source.split_whitespace().enumerate() {
+        if i > 0 {
+            hash = (hash ^ u64::from(b' ')).wrapping_mul(PRIME);
+        }
+        for byte in token.bytes() {
+            hash = (hash ^ u64::from(byte)).wrapping_mul(PRIME);
+        }
+    }
+    hash
+}
+
+/// Binds a protocol role to the participant that plays it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RoleAssignment<R> {
+    pub role: R,
+    /// Opaque participant identifier, e.g. a node ID or network address.
+    pub participant: String,
+}
+
+/// Everything a participant learns during bootstrap.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SessionAssignment<R> {
+    pub session_id: Uuid,
+    pub protocol: ProtocolDescriptor,
+    /// Role played by the participant holding this assignment.
+    pub local_role: R,
+    /// Complete roster, including the initiator.
+    pub roster: Vec<RoleAssignment<R>>,
+}
+
+impl<R: RoleId> SessionAssignment<R> {
+    /// Participant assigned to `role`, if any.
+    #[must_use]
+    pub fn participant_of(&self, role: &R) -> Option<&str> {
+        self.roster
+            .iter()
+            .find(|a| &a.role == role)
+            .map(|a| a.participant.as_str())
+    }
+
+    /// All roles other than the local one.
+    pub fn peers(&self) -> impl Iterator<Item = R> + '_ {
+        self.roster
+            .iter()
+            .map(|a| a.role)
+            .filter(move |role| role != &self.local_role)
+    }
+}
+
+/// Wire messages exchanged during bootstrap.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum BootstrapMessage<R> {
+    Assign(SessionAssignment<R>),
+    Ack { session_id: Uuid },
+    Reject { session_id: Uuid, reason: String },
+    Start { session_id: Uuid },
+    Abort { session_id: Uuid, reason: String },
+}
+
+/// Errors raised while establishing a session.
+#[derive(Debug, Error)]
+pub enum BootstrapError {
+    #[error("Transport error during bootstrap: {0}")]
+    Transport(#[from] ChoreographyError),
+
+    #[error("Protocol mismatch: expected {expected}, peer has {found}")]
+    ProtocolMismatch { expected: String, found: String },
+
+    #[error("Role {role} is assigned more than once")]
+    DuplicateRole { role: String },
+
+    #[error("Role {role} has no assigned participant")]
+    UnassignedRole { role: String },
+
+    #[error("Role {role} has {count} assigned instances, it takes {min} to {max}")]
+    Multiplicity {
+        role: String,
+        count: u32,
+        min: u32,
+        max: u32,
+    },
+
+    #[error("Participant for role {role} rejected the session: {reason}")]
+    Rejected { role: String, reason: String },
+
+    #[error("Session aborted by initiator: {reason}")]
+    Aborted { reason: String },
+
+    #[error("Unexpected bootstrap message: {0}")]
+    UnexpectedMessage(String),
+}
+
+/// Drives the handshake from the initiating participant.
+#[derive(Debug, Clone)]
+pub struct SessionInitiator<R> {
+    protocol: ProtocolDescriptor,
+    local_role: R,
+    roster: Vec<RoleAssignment<R>>,
+    session_id: Uuid,
+    multiplicities: Vec<Multiplicity<R>>,
+}
+
+/// Bounds on the number of assigned instances of a role family
+#[derive(Debug, Clone)]
+struct Multiplicity<R> {
+    role: String,
+    bounds: RangeInclusive<u32>,
+    is_instance: fn(&R) -> bool,
+}
+
+impl<R> SessionInitiator<R>
+where
+    R: RoleId + Serialize + DeserializeOwned,
+{
+    /// Start a new session for `protocol`, played locally as `local_role`.
+    #[must_use]
+    pub fn new(protocol: ProtocolDescriptor, local_role: R) -> Self {
+        Self {
+            protocol,
+            local_role,
+            roster: Vec::new(),
+            session_id: Uuid::new_v4(),
+            multiplicities: Vec::new(),
+        }
+    }
+
+    /// Assign `role` to `participant`. The initiator must assign itself too.
+    #[must_use]
+    pub fn assign(mut self, role: R, participant: impl Into<String>) -> Self {
+        self.roster.push(RoleAssignment {
+            role,
+            participant: participant.into(),
+        });
+        self
+    }
+
+    /// Require between `bounds` assigned instances of the role family
+    /// `role`, those for which `is_instance` holds, as declared by
+    /// `Signer[3..=10]`.
+    #[must_use]
+    pub fn with_multiplicity(
+        mut self,
+        role: impl Into<String>,
+        bounds: RangeInclusive<u32>,
+        is_instance: fn(&R) -> bool,
+    ) -> Self {
+        self.multiplicities.push(Multiplicity {
+            role: role.into(),
+            bounds,
+            is_instance,
+        });
+        self
+    }
+
+    /// Use a caller-chosen session ID instead of a random one.
+    #[must_use]
+    pub fn with_session_id(mut self, session_id: Uuid) -> Self {
+        self.session_id = session_id;
+        self
+    }
+
+    #[must_use]
+    pub fn session_id(&self) -> Uuid {
+        self.session_id
+    }
+
+    /// Check the roster before anything is sent.
+    pub fn validate(&self) -> Result<(), BootstrapError> {
+        for (i, assignment) in self.roster.iter().enumerate() {
+            if self.roster[..i].iter().any(|a| a.role == assignment.role) {
+                return Err(BootstrapError::DuplicateRole {
+                    role: format!("{:?}", assignment.role),
+                });
+            }
+        }
+        if !self.roster.iter().any(|a| a.role == self.local_role) {
+            return Err(BootstrapError::UnassignedRole {
+                role: format!("{:?}", self.local_role),
+            });
+        }
+        for multiplicity in &self.multiplicities {
+            let count = self
+                .roster
+                .iter()
+                .filter(|a| (multiplicity.is_instance)(&a.role))
+                .count() as u32;
+            if !multiplicity.bounds.contains(&count) {
+                return Err(BootstrapError::Multiplicity {
+                    role: multiplicity.role.clone(),
+                    count,
+                    min: *multiplicity.bounds.start(),
+                    max: *multiplicity.bounds.end(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the handshake with every assigned peer.
+    ///
+    /// On success every participant has acknowledged and been told to start.
+    /// If any participant rejects, the remaining ones are sent `Abort`.
+    pub async fn establish<H>(
+        &self,
+        handler: &mut H,
+        endpoint: &mut H::Endpoint,
+    ) -> Result<SessionAssignment<R>, BootstrapError>
+    where
+        H: ChoreoHandler<Role = R>,
+    {
+        self.validate()?;
+
+        let local = self.assignment_for(self.local_role);
+        let peers: Vec<R> = local.peers().collect();
+
+        for peer in &peers {
+            let message = BootstrapMessage::Assign(self.assignment_for(*peer));
+            handler.send(endpoint, *peer, &message).await?;
+        }
+        tracing::debug!(session_id = %self.session_id, peers = peers.len(), "Bootstrap assignments sent");
+
+        let mut failure = None;
+        for peer in &peers {
+            let reply: BootstrapMessage<R> = handler.recv(endpoint, *peer).await?;
+            match reply {
+                BootstrapMessage::Ack { session_id } if session_id == self.session_id => {}
+                BootstrapMessage::Reject { reason, .. } => {
+                    failure.get_or_insert(BootstrapError::Rejected {
+                        role: format!("{peer:?}"),
+                        reason,
+                    });
+                }
+                other => {
+                    failure.get_or_insert(BootstrapError::UnexpectedMessage(format!(
+                        "{other:?} from {peer:?}"
+                    )));
+                }
+            }
+        }
+
+        let outcome: BootstrapMessage<R> = match &failure {
+            None => BootstrapMessage::Start {
+                session_id: self.session_id,
+            },
+            Some(err) => BootstrapMessage::Abort {
+                session_id: self.session_id,
+                reason: err.to_string(),
+            },
+        };
+        for peer in &peers {
+            handler.send(endpoint, *peer, &outcome).await?;
+        }
+
+        match failure {
+            None => {
+                tracing::debug!(session_id = %self.session_id, "Bootstrap complete");
+                Ok(local)
+            }
+            Some(err) => Err(err),
+        }
+    }
+
+    fn assignment_for(&self, role: R) -> SessionAssignment<R> {
+        SessionAssignment {
+            session_id: self.session_id,
+            protocol: self.protocol.clone(),
+            local_role: role,
+            roster: self.roster.clone(),
+        }
+    }
+}
+
+/// Joins a session announced by an initiator.
+#[derive(Debug, Clone)]
+pub struct SessionParticipant {
+    protocol: ProtocolDescriptor,
+}
+
+impl SessionParticipant {
+    /// Participate in sessions of `protocol` only.
+    #[must_use]
+    pub fn new(protocol: ProtocolDescriptor) -> Self {
+        Self { protocol }
+    }
+
+    /// Wait for an assignment from `initiator`, verify it, and wait for `Start`.
+    pub async fn join<H, R>(
+        &self,
+        handler: &mut H,
+        endpoint: &mut H::Endpoint,
+        initiator: R,
+    ) -> Result<SessionAssignment<R>, BootstrapError>
+    where
+        H: ChoreoHandler<Role = R>,
+        R: RoleId + Serialize + DeserializeOwned,
+    {
+        let message: BootstrapMessage<R> = handler.recv(endpoint, initiator).await?;
+        let assignment = match message {
+            BootstrapMessage::Assign(assignment) => assignment,
+            other => {
+                return Err(BootstrapError::UnexpectedMessage(format!("{other:?}")));
+            }
+        };
+        let session_id = assignment.session_id;
+
+        if let Err(err) = self.check(&assignment) {
+            let reject = BootstrapMessage::<R>::Reject {
+                session_id,
+                reason: err.to_string(),
+            };
+            handler.send(endpoint, initiator, &reject).await?;
+            // Drain the initiator's Abort so the channel is left clean.
+            let _: BootstrapMessage<R> = handler.recv(endpoint, initiator).await?;
+            return Err(err);
+        }
+
+        handler
+            .send(
+                endpoint,
+                initiator,
+                &BootstrapMessage::<R>::Ack { session_id },
+            )
+            .await?;
+
+        let outcome: BootstrapMessage<R> = handler.recv(endpoint, initiator).await?;
+        match outcome {
+            BootstrapMessage::Start { session_id: id } if id == session_id => {
+                tracing::debug!(%session_id, role = ?assignment.local_role, "Joined session");
+                Ok(assignment)
+            }
+            BootstrapMessage::Abort { reason, .. } => Err(BootstrapError::Aborted { reason }),
+            other => Err(BootstrapError::UnexpectedMessage(format!("{other:?}"))),
+        }
+    }
+
+    fn check<R: RoleId>(&self, assignment: &SessionAssignment<R>) -> Result<(), BootstrapError> {
+        if assignment.protocol != self.protocol {
+            return Err(BootstrapError::ProtocolMismatch {
+                expected: self.protocol.to_string(),
+                found: assignment.protocol.to_string(),
+            });
+        }
+        if assignment.participant_of(&assignment.local_role).is_none() {
+            return Err(BootstrapError::UnassignedRole {
+                role: format!("{:?}", assignment.local_role),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protocol_hash_ignores_formatting() {
+        let compact = "choreography P { roles: A, B A -> B: Msg }";
+        let spaced = "choreography P {\n    roles: A, B\n\n    A -> B: Msg\n}\n";
+        assert_eq!(protocol_hash(compact), protocol_hash(spaced));
+        assert_ne!(
+            protocol_hash(compact),
+            protocol_hash("choreography P { roles: A, B B -> A: Msg }")
+        );
+    }
+
+    #[test]
+    fn test_initiator_validates_roster() {
+        let protocol = ProtocolDescriptor::new("P", 1);
+
+        let missing_self = SessionInitiator::new(protocol.clone(), 0u8).assign(1, "b");
+        assert!(matches!(
+            missing_self.validate(),
+            Err(BootstrapError::UnassignedRole { .. })
+        ));
+
+        let duplicate = SessionInitiator::new(protocol, 0u8)
+            .assign(0, "a")
+            .assign(1, "b")
+            .assign(1, "c");
+        assert!(matches!(
+            duplicate.validate(),
+            Err(BootstrapError::DuplicateRole { .. })
+        ));
+
+        // Signer[2..=3], played by roles 1 and up
+        let signers = |count: u8| {
+            (1..=count)
+                .fold(
+                    SessionInitiator::new(ProtocolDescriptor::new("P", 1), 0u8).assign(0, "a"),
+                    |initiator, signer| initiator.assign(signer, format!("s{signer}")),
+                )
+                .with_multiplicity("Signer", 2..=3, |role| *role > 0)
+                .validate()
+        };
+        assert!(signers(2).is_ok());
+        assert!(signers(3).is_ok());
+        assert!(matches!(
+            signers(1),
+            Err(BootstrapError::Multiplicity {
+                count: 1,
+                min: 2,
+                max: 3,
+                ..
+            })
+        ));
+        assert!(signers(4).is_err());
+    }
+}
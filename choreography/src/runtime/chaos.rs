@@ -0,0 +1,286 @@
+// Fault injection for blocking transports
+//
+// `FaultInjectingTransport` wraps a role's `runtime::blocking::Transport`
+// and disturbs the frames the role sends: it may delay, drop, duplicate or
+// reorder them, so timeouts, retries and the other failure-handling
+// constructs of a choreography can be exercised without a flaky network.
+//
+// Faults come from two sources. Each fault has a probability, rolled per
+// send with a seeded generator so a failing run can be reproduced from its
+// seed. A schedule gives the faults of chosen sends, counted from 0 over
+// every send of the role, and replaces the random roll for those sends.
+//
+// Reordering holds a frame back until the next frame to the same peer has
+// gone, and only applies to peers allowed with `allow_reorder`, as most
+// transports promise order on a link. Held frames are released before the
+// role waits on a receive, so a reordering never deadlocks the session.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+use std::time::Duration;
+
+use crate::effects::Result;
+use crate::runtime::blocking::Transport;
+use crate::runtime::sim::SplitMix64;
+
+/// Fault applied to one send
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportFault {
+    /// The frame goes out after the duration
+    Delay(Duration),
+    /// The frame goes out after the next frame to the same peer
+    Reorder,
+    /// The frame goes out twice
+    Duplicate,
+    /// The frame never goes out
+    Drop,
+}
+
+/// Fault applied to a send of the wrapped role
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InjectedFault<R> {
+    /// Position of the send among the role's sends, from 0
+    pub send: usize,
+    pub to: R,
+    pub fault: TransportFault,
+}
+
+/// `Transport` injecting faults into the frames `inner` sends
+pub struct FaultInjectingTransport<R, T> {
+    inner: T,
+    rng: SplitMix64,
+    delay: f64,
+    max_delay: Duration,
+    reorder: f64,
+    duplicate: f64,
+    drop: f64,
+    reorderable: HashSet<R>,
+    schedule: BTreeMap<usize, Vec<TransportFault>>,
+    sends: usize,
+    held: HashMap<R, Vec<u8>>,
+    injected: Vec<InjectedFault<R>>,
+}
+
+impl<R: Copy + Eq + Hash, T> FaultInjectingTransport<R, T> {
+    /// Wrap `inner`, injecting no faults until configured, with random
+    /// faults drawn from `seed`
+    #[must_use]
+    pub fn new(inner: T, seed: u64) -> Self {
+        Self {
+            inner,
+            rng: SplitMix64(seed),
+            delay: 0.0,
+            max_delay: Duration::ZERO,
+            reorder: 0.0,
+            duplicate: 0.0,
+            drop: 0.0,
+            reorderable: HashSet::new(),
+            schedule: BTreeMap::new(),
+            sends: 0,
+            held: HashMap::new(),
+            injected: Vec::new(),
+        }
+    }
+
+    /// Delay a send with `probability`, by up to `max`
+    #[must_use]
+    pub fn with_delay(mut self, probability: f64, max: Duration) -> Self {
+        self.delay = probability;
+        self.max_delay = max;
+        self
+    }
+
+    /// Reorder a send to an allowed peer with `probability`
+    #[must_use]
+    pub fn with_reorder(mut self, probability: f64) -> Self {
+        self.reorder = probability;
+        self
+    }
+
+    /// Duplicate a send with `probability`
+    #[must_use]
+    pub fn with_duplicate(mut self, probability: f64) -> Self {
+        self.duplicate = probability;
+        self
+    }
+
+    /// Drop a send with `probability`
+    #[must_use]
+    pub fn with_drop(mut self, probability: f64) -> Self {
+        self.drop = probability;
+        self
+    }
+
+    /// Allow frames to `peer` to be reordered
+    #[must_use]
+    pub fn allow_reorder(mut self, peer: R) -> Self {
+        self.reorderable.insert(peer);
+        self
+    }
+
+    /// Apply the scripted faults of `schedule`, keyed by send position,
+    /// instead of random faults on those sends
+    #[must_use]
+    pub fn with_schedule(
+        mut self,
+        schedule: impl IntoIterator<Item = (usize, TransportFault)>,
+    ) -> Self {
+        for (send, fault) in schedule {
+            self.schedule.entry(send).or_default().push(fault);
+        }
+        self
+    }
+
+    /// Faults injected so far, in order
+    #[must_use]
+    pub fn injected(&self) -> &[InjectedFault<R>] {
+        &self.injected
+    }
+
+    /// The wrapped transport
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Faults of the next send, to `to`
+    fn next_faults(&mut self, to: R) -> Vec<TransportFault> {
+        let send = self.sends;
+        self.sends += 1;
+        let faults = match self.schedule.remove(&send) {
+            Some(faults) => faults,
+            None => {
+                let mut faults = Vec::new();
+                if self.roll(self.delay) {
+                    let nanos = self.max_delay.as_nanos() as f64 * self.rng.next_f64();
+                    faults.push(TransportFault::Delay(Duration::from_nanos(nanos as u64)));
+                }
+                if self.roll(self.drop) {
+                    faults.push(TransportFault::Drop);
+                } else if self.roll(self.duplicate) {
+                    faults.push(TransportFault::Duplicate);
+                } else if self.reorderable.contains(&to) && self.roll(self.reorder) {
+                    faults.push(TransportFault::Reorder);
+                }
+                faults
+            }
+        };
+        self.injected.extend(
+            faults
+                .iter()
+                .map(|&fault| InjectedFault { send, to, fault }),
+        );
+        faults
+    }
+
+    fn roll(&mut self, probability: f64) -> bool {
+        probability > 0.0 && self.rng.next_f64() < probability
+    }
+}
+
+impl<R: Copy + Eq + Hash, T: Transport<R>> FaultInjectingTransport<R, T> {
+    /// Send the frames held back for reordering
+    ///
+    /// # Errors
+    ///
+    /// The first error of the wrapped transport.
+    pub fn release_held(&mut self) -> Result<()> {
+        for (to, frame) in std::mem::take(&mut self.held) {
+            self.inner.send(to, frame)?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Copy + Eq + Hash, T: Transport<R>> Transport<R> for FaultInjectingTransport<R, T> {
+    fn send(&mut self, to: R, frame: Vec<u8>) -> Result<()> {
+        let faults = self.next_faults(to);
+        for fault in &faults {
+            if let TransportFault::Delay(delay) = fault {
+                std::thread::sleep(*delay);
+            }
+        }
+        if faults.contains(&TransportFault::Drop) {
+            return Ok(());
+        }
+        if faults.contains(&TransportFault::Reorder) && !self.held.contains_key(&to) {
+            self.held.insert(to, frame);
+            return Ok(());
+        }
+        if faults.contains(&TransportFault::Duplicate) {
+            self.inner.send(to, frame.clone())?;
+        }
+        self.inner.send(to, frame)?;
+        match self.held.remove(&to) {
+            Some(held) => self.inner.send(to, held),
+            None => Ok(()),
+        }
+    }
+
+    fn recv(&mut self, from: R) -> Result<Vec<u8>> {
+        self.release_held()?;
+        self.inner.recv(from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::blocking::ChannelTransport;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    enum Role {
+        Client,
+        Server,
+    }
+
+    #[test]
+    fn test_scripted_and_random_faults() {
+        let roles = [Role::Client, Role::Server];
+        let mut transports = ChannelTransport::mesh(&roles);
+        let mut client = FaultInjectingTransport::new(transports.remove(&Role::Client).unwrap(), 7)
+            .allow_reorder(Role::Server)
+            .with_schedule([
+                (0, TransportFault::Drop),
+                (1, TransportFault::Duplicate),
+                (2, TransportFault::Reorder),
+                (4, TransportFault::Delay(Duration::from_millis(1))),
+                (5, TransportFault::Reorder),
+            ]);
+        let mut server = transports.remove(&Role::Server).unwrap();
+
+        for frame in 0..5u8 {
+            client.send(Role::Server, vec![frame]).unwrap();
+        }
+        let received: Vec<Vec<u8>> = (0..5).map(|_| server.recv(Role::Client).unwrap()).collect();
+        assert_eq!(received, [[1], [1], [3], [2], [4]]);
+        assert_eq!(client.injected().len(), 4);
+
+        // A frame held back goes out before the role waits
+        client.send(Role::Server, vec![5]).unwrap();
+        server.send(Role::Client, vec![9]).unwrap();
+        assert_eq!(client.recv(Role::Server).unwrap(), [9]);
+        assert_eq!(server.recv(Role::Client).unwrap(), [5]);
+
+        // Random faults reproduce from the seed
+        let faults = |seed| {
+            let mut transports = ChannelTransport::mesh(&roles);
+            let mut client =
+                FaultInjectingTransport::new(transports.remove(&Role::Client).unwrap(), seed)
+                    .with_drop(0.3)
+                    .with_duplicate(0.3)
+                    .with_reorder(1.0);
+            for frame in 0..32u8 {
+                client.send(Role::Server, vec![frame]).unwrap();
+            }
+            client.injected().to_vec()
+        };
+        let injected = faults(42);
+        assert_eq!(injected, faults(42));
+        assert!(injected
+            .iter()
+            .any(|fault| fault.fault == TransportFault::Drop));
+        assert!(injected
+            .iter()
+            .all(|fault| fault.fault != TransportFault::Reorder));
+    }
+}
@@ -0,0 +1,337 @@
+// Property-based protocol conformance
+//
+// `@codegen(proptest)` generates a path-following implementation of every
+// role and drives them against each other along random execution paths. An
+// `ExecutionPath` lists, for each role, the branch it takes at each of the
+// choices it makes; a recursive protocol repeats as long as the chosen
+// branches continue it. Once a role's decisions run out it takes a branch
+// that leaves the recursion, so every path terminates.
+//
+// A run passes when every role finishes without an error. Async sessions run
+// on a local executor until no role can make progress, so a deadlock is
+// detected exactly; blocking sessions run on threads and count as deadlocked
+// when they miss a deadline.
+
+use proptest::prelude::*;
+use proptest::test_runner::{Config, TestError, TestRunner};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::effects::{ChoreographyError, RoleId};
+use crate::runtime::harness::{BlockingRole, RoleFuture};
+
+pub use proptest;
+
+/// Decisions per role in generated paths
+pub const MAX_DECISIONS: usize = 16;
+
+/// How long a blocking session may run before it counts as deadlocked
+pub const DEFAULT_DEADLINE: Duration = Duration::from_secs(5);
+
+/// Branches each role takes at the choices it makes, in order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionPath<R> {
+    decisions: Vec<(R, Vec<usize>)>,
+}
+
+impl<R: RoleId> ExecutionPath<R> {
+    /// Path on which every role leaves each choice as early as it can
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            decisions: Vec::new(),
+        }
+    }
+
+    /// Have `role` take branch `decisions[i]` at its `i`-th choice, modulo
+    /// the number of branches
+    #[must_use]
+    pub fn with_decisions(mut self, role: R, decisions: Vec<usize>) -> Self {
+        self.decisions.retain(|(r, _)| *r != role);
+        self.decisions.push((role, decisions));
+        self
+    }
+
+    /// The decisions of `role`, consumed as it makes choices
+    #[must_use]
+    pub fn decisions(&self, role: R) -> Decisions {
+        let remaining = self
+            .decisions
+            .iter()
+            .find(|(r, _)| *r == role)
+            .map(|(_, decisions)| decisions.iter().copied().collect())
+            .unwrap_or_default();
+        Decisions { remaining }
+    }
+}
+
+impl<R: RoleId> Default for ExecutionPath<R> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The remaining decisions of one role
+#[derive(Debug, Clone, Default)]
+pub struct Decisions {
+    remaining: VecDeque<usize>,
+}
+
+impl Decisions {
+    /// Branch to take at a choice of `branches` branches, of which `exits`
+    /// leave the enclosing recursion
+    pub fn next(&mut self, branches: usize, exits: &[usize]) -> usize {
+        match self.remaining.pop_front() {
+            Some(decision) => decision % branches.max(1),
+            None => exits.first().copied().unwrap_or(0),
+        }
+    }
+}
+
+/// Random paths of up to `max_decisions` decisions per role
+///
+/// Failing paths shrink towards fewer decisions and lower branch indices.
+pub fn execution_paths<R: RoleId>(
+    roles: &[R],
+    max_decisions: usize,
+) -> BoxedStrategy<ExecutionPath<R>> {
+    let roles = roles.to_vec();
+    proptest::collection::vec(
+        proptest::collection::vec(any::<usize>(), 0..=max_decisions),
+        roles.len(),
+    )
+    .prop_map(move |decisions| ExecutionPath {
+        decisions: roles.iter().copied().zip(decisions).collect(),
+    })
+    .boxed()
+}
+
+/// Why a session did not run to completion
+#[derive(Debug, Error)]
+pub enum ConformanceError {
+    #[error("session deadlocked with {} still waiting", .waiting.join(", "))]
+    Deadlock { waiting: Vec<String> },
+
+    #[error("{role} failed: {error}")]
+    RoleFailed {
+        role: String,
+        error: Box<ChoreographyError>,
+    },
+
+    #[error("a role panicked while {} had not finished", .unfinished.join(", "))]
+    Panicked { unfinished: Vec<String> },
+}
+
+/// Run the roles of an async session until all finish or none can progress
+///
+/// Fails with the first error a role reports, or with a deadlock naming the
+/// roles left waiting.
+pub fn run_async_session<R: RoleId>(
+    roles: Vec<(R, RoleFuture<'static>)>,
+) -> Result<(), ConformanceError> {
+    use futures::executor::LocalPool;
+    use futures::task::LocalSpawnExt;
+
+    let mut pool = LocalPool::new();
+    let spawner = pool.spawner();
+    let outcomes = Rc::new(RefCell::new(Vec::new()));
+    let all: Vec<R> = roles.iter().map(|(role, _)| *role).collect();
+    for (role, future) in roles {
+        let outcomes = Rc::clone(&outcomes);
+        spawner
+            .spawn_local(async move {
+                let outcome = future.await;
+                outcomes.borrow_mut().push((role, outcome));
+            })
+            .map_err(|e| ConformanceError::RoleFailed {
+                role: format!("{role:?}"),
+                error: Box::new(ChoreographyError::Transport(e.to_string())),
+            })?;
+    }
+    pool.run_until_stalled();
+
+    let outcomes = outcomes.replace(Vec::new());
+    let finished: Vec<R> = outcomes.iter().map(|(role, _)| *role).collect();
+    if let Some((role, Err(error))) = outcomes.into_iter().find(|(_, o)| o.is_err()) {
+        return Err(ConformanceError::RoleFailed {
+            role: format!("{role:?}"),
+            error: Box::new(error),
+        });
+    }
+    let waiting: Vec<String> = all
+        .iter()
+        .filter(|role| !finished.contains(role))
+        .map(|role| format!("{role:?}"))
+        .collect();
+    if waiting.is_empty() {
+        Ok(())
+    } else {
+        Err(ConformanceError::Deadlock { waiting })
+    }
+}
+
+/// Run the roles of a blocking session on threads, failing if they have not
+/// all finished within `deadline`
+///
+/// Threads of a deadlocked session stay blocked; they hold no locks.
+pub fn run_blocking_session<R: RoleId>(
+    roles: Vec<(R, BlockingRole<'static>)>,
+    deadline: Duration,
+) -> Result<(), ConformanceError> {
+    let (outcomes, results) = channel();
+    let mut waiting: Vec<R> = roles.iter().map(|(role, _)| *role).collect();
+    for (role, run) in roles {
+        let outcomes = outcomes.clone();
+        std::thread::spawn(move || {
+            run(&|outcome| {
+                let _ = outcomes.send((role, outcome));
+            });
+        });
+    }
+    drop(outcomes);
+
+    let names = |roles: &[R]| roles.iter().map(|role| format!("{role:?}")).collect();
+    let start = Instant::now();
+    while !waiting.is_empty() {
+        match results.recv_timeout(deadline.saturating_sub(start.elapsed())) {
+            Ok((role, Ok(()))) => waiting.retain(|r| *r != role),
+            Ok((role, Err(error))) => {
+                return Err(ConformanceError::RoleFailed {
+                    role: format!("{role:?}"),
+                    error: Box::new(error),
+                })
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                return Err(ConformanceError::Deadlock {
+                    waiting: names(&waiting),
+                })
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err(ConformanceError::Panicked {
+                    unfinished: names(&waiting),
+                })
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Run `session` along `cases` random paths, shrinking the first failure
+/// to a minimal path
+pub fn check_paths<R, F>(
+    roles: &[R],
+    cases: u32,
+    session: F,
+) -> Result<(), TestError<ExecutionPath<R>>>
+where
+    R: RoleId,
+    F: Fn(&ExecutionPath<R>) -> Result<(), ConformanceError>,
+{
+    let mut runner = TestRunner::new(Config {
+        cases,
+        failure_persistence: None,
+        ..Config::default()
+    });
+    runner.run(&execution_paths(roles, MAX_DECISIONS), |path| {
+        session(&path).map_err(|e| TestCaseError::fail(e.to_string()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::blocking::ChannelTransport;
+    use crate::runtime::harness::{blocking_role, take_role, ChannelHandler};
+    use crate::ChoreoHandler;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    enum Role {
+        Client,
+        Server,
+    }
+
+    const ROLES: &[Role] = &[Role::Client, Role::Server];
+
+    #[test]
+    fn test_decisions_fall_back_to_exits() {
+        let path = ExecutionPath::new().with_decisions(Role::Server, vec![3, 1]);
+        let mut decisions = path.decisions(Role::Server);
+        assert_eq!(decisions.next(2, &[1]), 1);
+        assert_eq!(decisions.next(2, &[1]), 1);
+        assert_eq!(decisions.next(2, &[1]), 1);
+        assert_eq!(path.decisions(Role::Client).next(3, &[]), 0);
+    }
+
+    #[test]
+    fn test_async_deadlock_names_waiting_roles() {
+        let mut mesh = ChannelHandler::mesh(ROLES);
+        let roles: Vec<(Role, RoleFuture<'static>)> = ROLES
+            .iter()
+            .map(|&role| {
+                let mut handler = take_role(&mut mesh, role).unwrap();
+                let peer = if role == Role::Client {
+                    Role::Server
+                } else {
+                    Role::Client
+                };
+                let future: RoleFuture<'static> =
+                    Box::pin(async move { handler.recv::<u32>(&mut (), peer).await.map(drop) });
+                (role, future)
+            })
+            .collect();
+        // Each role keeps its own senders alive, so both wait forever
+        let err = run_async_session(roles).unwrap_err();
+        assert!(matches!(err, ConformanceError::Deadlock { ref waiting } if waiting.len() == 2));
+    }
+
+    #[test]
+    fn test_blocking_session_reports_failure() {
+        let mut mesh = ChannelTransport::mesh(ROLES);
+        let roles = vec![
+            (
+                Role::Client,
+                blocking_role(
+                    Role::Client,
+                    ROLES,
+                    take_role(&mut mesh, Role::Client),
+                    |endpoint| endpoint.send(Role::Server, &1u32),
+                ),
+            ),
+            (
+                Role::Server,
+                blocking_role(
+                    Role::Server,
+                    ROLES,
+                    take_role(&mut mesh, Role::Server),
+                    |endpoint| endpoint.recv::<String>(Role::Client).map(drop),
+                ),
+            ),
+        ];
+        let err = run_blocking_session(roles, DEFAULT_DEADLINE).unwrap_err();
+        assert!(matches!(err, ConformanceError::RoleFailed { ref role, .. } if role == "Server"));
+    }
+
+    #[test]
+    fn test_check_paths_shrinks_failures() {
+        let result = check_paths(ROLES, 64, |path| {
+            if path.decisions(Role::Server).next(2, &[0]) == 1 {
+                Err(ConformanceError::Deadlock {
+                    waiting: vec!["Client".to_string()],
+                })
+            } else {
+                Ok(())
+            }
+        });
+        match result {
+            Err(TestError::Fail(_, path)) => {
+                assert_eq!(path.decisions(Role::Server).next(2, &[0]), 1);
+                assert_eq!(path.decisions(Role::Client).next(2, &[0]), 0);
+            }
+            other => panic!("expected a failing path, got {other:?}"),
+        }
+    }
+}
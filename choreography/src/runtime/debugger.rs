@@ -0,0 +1,344 @@
+// Step debugging
+//
+// A `Debugger` halts the roles of a session before each of their protocol
+// steps while it is paused, so the interaction can be walked through one
+// step at a time. Roles run through the `Debugged` middleware, or the
+// generated `run_<role>_debugged`, which stop at a checkpoint before every
+// send, receive, selection and offer. One debugger may be shared by every
+// role of an in-process session.
+//
+// While paused, each role waits at its next step. `halted` lists the steps
+// the roles wait at, `step` lets them take those steps and stop at the next
+// ones, and `resume` lets them run freely until `pause` is called again.
+//
+// `serve` exposes the debugger on a local socket, one command per line, for
+// `choreo debug <address>` to attach:
+//
+//     status            `paused` or `running`, then the halted steps
+//     wait              status once a role is halted
+//     step [role]       let the halted roles, or just `role`, take one step
+//     pause | resume
+//
+// Every command is answered with one line, `error: ...` for a bad one.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::future::poll_fn;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Poll, Waker};
+
+use crate::runtime::monitor::ActionKind;
+
+/// Protocol step a role is about to take
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingStep {
+    pub role: String,
+    /// Number of steps the role took before this one
+    pub index: usize,
+    pub kind: ActionKind,
+    pub peer: String,
+    /// Message type or branch label, unknown for an offer until it arrives
+    pub label: Option<String>,
+}
+
+impl fmt::Display for PendingStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let preposition = match self.kind {
+            ActionKind::Send | ActionKind::Select => "to",
+            ActionKind::Receive | ActionKind::Branch => "from",
+        };
+        write!(f, "{}: {}", self.role, self.kind)?;
+        if let Some(label) = &self.label {
+            write!(f, " {label}")?;
+        }
+        write!(f, " {preposition} {} (step {})", self.peer, self.index)
+    }
+}
+
+#[derive(Default)]
+struct DebugState {
+    paused: bool,
+    /// Steps the roles wait at, by role
+    halted: BTreeMap<String, PendingStep>,
+    /// Halted roles let through by `step`, to stop again at their next step
+    permits: BTreeSet<String>,
+    /// Roles at a checkpoint and debugger clients waiting for a change
+    waiters: Vec<Waker>,
+}
+
+/// Pause, step and resume control over the roles of a session, shared by
+/// its clones
+#[derive(Clone, Default)]
+pub struct Debugger {
+    state: Arc<Mutex<DebugState>>,
+}
+
+impl fmt::Debug for Debugger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.lock();
+        f.debug_struct("Debugger")
+            .field("paused", &state.paused)
+            .field("halted", &state.halted.len())
+            .finish()
+    }
+}
+
+impl Debugger {
+    /// A debugger that lets roles run until paused
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A debugger that halts every role at its first step
+    #[must_use]
+    pub fn paused() -> Self {
+        let debugger = Self::new();
+        debugger.pause();
+        debugger
+    }
+
+    /// Halt every role at its next step
+    pub fn pause(&self) {
+        self.update(|state| state.paused = true);
+    }
+
+    /// Let the roles run freely
+    pub fn resume(&self) {
+        self.update(|state| {
+            state.paused = false;
+            state.halted.clear();
+            state.permits.clear();
+        });
+    }
+
+    /// Let every halted role take the step it waits at, and halt it again
+    /// at its next one
+    pub fn step(&self) {
+        self.update(|state| {
+            let halted = std::mem::take(&mut state.halted);
+            state.permits.extend(halted.into_keys());
+        });
+    }
+
+    /// Let `role`, if halted, take the step it waits at; returns whether
+    /// it was halted
+    pub fn step_role(&self, role: &str) -> bool {
+        let mut stepped = false;
+        self.update(|state| {
+            if state.halted.remove(role).is_some() {
+                state.permits.insert(role.to_string());
+                stepped = true;
+            }
+        });
+        stepped
+    }
+
+    #[must_use]
+    pub fn is_paused(&self) -> bool {
+        self.lock().paused
+    }
+
+    /// Steps the halted roles wait at, by role name
+    #[must_use]
+    pub fn halted(&self) -> Vec<PendingStep> {
+        self.lock().halted.values().cloned().collect()
+    }
+
+    /// Wait until a role is halted, and return the halted steps
+    pub async fn wait_halted(&self) -> Vec<PendingStep> {
+        poll_fn(|cx| {
+            let mut state = self.lock();
+            if state.halted.is_empty() {
+                state.waiters.push(cx.waker().clone());
+                Poll::Pending
+            } else {
+                Poll::Ready(state.halted.values().cloned().collect())
+            }
+        })
+        .await
+    }
+
+    /// Wait before `step` until the debugger lets its role take it
+    pub async fn checkpoint(&self, step: PendingStep) {
+        poll_fn(|cx| {
+            let mut state = self.lock();
+            if !state.paused || state.permits.remove(&step.role) {
+                state.halted.remove(&step.role);
+                return Poll::Ready(());
+            }
+            if !state.halted.contains_key(&step.role) {
+                state.halted.insert(step.role.clone(), step.clone());
+                wake(&mut state);
+            }
+            state.waiters.push(cx.waker().clone());
+            Poll::Pending
+        })
+        .await;
+    }
+
+    /// Answer one line of the debugger's socket protocol
+    pub fn command(&self, line: &str) -> String {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next(), words.next()) {
+            (Some("status"), None, _) => self.status(),
+            (Some("wait"), None, _) => {
+                futures::executor::block_on(self.wait_halted());
+                self.status()
+            }
+            (Some("step"), None, _) => {
+                self.step();
+                "ok".to_string()
+            }
+            (Some("step"), Some(role), None) => {
+                if self.step_role(role) {
+                    "ok".to_string()
+                } else {
+                    format!("error: {role} is not halted")
+                }
+            }
+            (Some("pause"), None, _) => {
+                self.pause();
+                "ok".to_string()
+            }
+            (Some("resume" | "continue"), None, _) => {
+                self.resume();
+                "ok".to_string()
+            }
+            _ => format!("error: unknown command `{}`", line.trim()),
+        }
+    }
+
+    /// `paused` or `running`, then the halted steps
+    fn status(&self) -> String {
+        let state = self.lock();
+        let mut status = if state.paused { "paused" } else { "running" }.to_string();
+        for step in state.halted.values() {
+            status.push_str("; ");
+            status.push_str(&step.to_string());
+        }
+        status
+    }
+
+    /// Accept debugger clients on `address`, in a background thread, and
+    /// return the address bound
+    ///
+    /// # Errors
+    ///
+    /// The error binding `address`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn serve(
+        &self,
+        address: impl std::net::ToSocketAddrs,
+    ) -> std::io::Result<std::net::SocketAddr> {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind(address)?;
+        let bound = listener.local_addr()?;
+        let debugger = self.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let debugger = debugger.clone();
+                std::thread::spawn(move || {
+                    let Ok(reader) = stream.try_clone() else {
+                        return;
+                    };
+                    let mut writer = stream;
+                    for line in BufReader::new(reader).lines().map_while(Result::ok) {
+                        let reply = debugger.command(&line);
+                        if writeln!(writer, "{reply}").is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        Ok(bound)
+    }
+
+    fn update(&self, f: impl FnOnce(&mut DebugState)) {
+        let mut state = self.lock();
+        f(&mut state);
+        wake(&mut state);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, DebugState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn wake(state: &mut DebugState) {
+    std::mem::take(&mut state.waiters)
+        .into_iter()
+        .for_each(Waker::wake);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(role: &str, index: usize) -> PendingStep {
+        PendingStep {
+            role: role.to_string(),
+            index,
+            kind: ActionKind::Send,
+            peer: "Server".to_string(),
+            label: Some("Request".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pause_step_and_resume() {
+        let debugger = Debugger::paused();
+        let client = {
+            let debugger = debugger.clone();
+            tokio::spawn(async move {
+                for index in 0..3 {
+                    debugger.checkpoint(step("Client", index)).await;
+                }
+            })
+        };
+        let halted = debugger.wait_halted().await;
+        assert_eq!(halted, [step("Client", 0)]);
+        assert_eq!(
+            halted[0].to_string(),
+            "Client: send Request to Server (step 0)"
+        );
+
+        assert!(!debugger.step_role("Server"));
+        debugger.step();
+        while debugger.halted().first().map(|s| s.index) != Some(1) {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(
+            debugger.command("status"),
+            "paused; Client: send Request to Server (step 1)"
+        );
+
+        debugger.resume();
+        client.await.unwrap();
+        assert_eq!(debugger.command("status"), "running");
+    }
+
+    #[test]
+    fn test_socket_commands() {
+        use std::io::{BufRead, BufReader, Write};
+
+        let debugger = Debugger::new();
+        let address = debugger.serve("127.0.0.1:0").unwrap();
+        let stream = std::net::TcpStream::connect(address).unwrap();
+        let mut replies = BufReader::new(stream.try_clone().unwrap()).lines();
+        let mut writer = stream;
+        let mut command = |line: &str| {
+            writeln!(writer, "{line}").unwrap();
+            replies.next().unwrap().unwrap()
+        };
+        assert_eq!(command("pause"), "ok");
+        assert!(debugger.is_paused());
+        assert_eq!(command("status"), "paused");
+        assert_eq!(command("step Client"), "error: Client is not halted");
+        assert_eq!(command("jump"), "error: unknown command `jump`");
+        assert_eq!(command("resume"), "ok");
+        assert_eq!(command("status"), "running");
+    }
+}
@@ -0,0 +1,303 @@
+// Message deduplication for at-least-once transports
+//
+// Broker-backed transports may deliver a frame more than once. Wrapping a
+// role's transport here prefixes every frame the role sends with a sequence
+// number, increasing over the session, and drops frames on receipt whose
+// number the receiver has already seen, so generated endpoints never run a
+// step twice because of a redelivery.
+//
+// Key pieces:
+// - DedupWindow: the numbers recently seen from one sender. Numbers too old
+//   to be in the window are taken as redeliveries.
+// - DedupSession::wrap: the async side, around a `RumpsteakSession`.
+// - Deduplicated: the blocking side, around a `runtime::blocking::Transport`.
+//
+// Both ends of a link must be wrapped: the sequence number is an 8-byte
+// big-endian prefix of the frame.
+
+use futures::future::BoxFuture;
+use std::collections::{BTreeSet, HashMap};
+use std::hash::Hash;
+
+use crate::effects::handlers::rumpsteak::{SessionTypeDynamic, SessionUpdate};
+use crate::effects::{ChoreographyError, Result, RumpsteakSession};
+use crate::runtime::blocking::Transport;
+
+/// Sequence numbers remembered per sender unless set otherwise
+pub const DEFAULT_DEDUP_WINDOW: u64 = 1024;
+
+const SEQ_LEN: usize = 8;
+
+/// Sequence numbers recently received from one sender
+#[derive(Debug, Clone)]
+pub struct DedupWindow {
+    size: u64,
+    highest: Option<u64>,
+    seen: BTreeSet<u64>,
+}
+
+impl DedupWindow {
+    /// Window remembering the last `size` numbers, at least one
+    #[must_use]
+    pub fn new(size: u64) -> Self {
+        Self {
+            size: size.max(1),
+            highest: None,
+            seen: BTreeSet::new(),
+        }
+    }
+
+    /// Record `seq`, returning `false` if it is a redelivery
+    pub fn accept(&mut self, seq: u64) -> bool {
+        if let Some(highest) = self.highest {
+            if seq.saturating_add(self.size) <= highest || self.seen.contains(&seq) {
+                return false;
+            }
+        }
+        self.seen.insert(seq);
+        let highest = self.highest.map_or(seq, |highest| highest.max(seq));
+        self.highest = Some(highest);
+        let floor = highest.saturating_sub(self.size - 1);
+        self.seen = self.seen.split_off(&floor);
+        true
+    }
+}
+
+impl Default for DedupWindow {
+    fn default() -> Self {
+        Self::new(DEFAULT_DEDUP_WINDOW)
+    }
+}
+
+fn sequence(seq: u64, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(SEQ_LEN + payload.len());
+    frame.extend_from_slice(&seq.to_be_bytes());
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn unsequence(mut frame: Vec<u8>) -> Result<(u64, Vec<u8>)> {
+    if frame.len() < SEQ_LEN {
+        return Err(ChoreographyError::Transport(format!(
+            "frame of {} bytes has no sequence number",
+            frame.len()
+        )));
+    }
+    let payload = frame.split_off(SEQ_LEN);
+    let mut seq = [0; SEQ_LEN];
+    seq.copy_from_slice(&frame);
+    Ok((u64::from_be_bytes(seq), payload))
+}
+
+/// Deduplicating `SessionTypeDynamic` around the session with one peer
+pub struct DedupSession {
+    inner: RumpsteakSession,
+    next_seq: u64,
+    window: DedupWindow,
+}
+
+impl DedupSession {
+    /// Deduplicate `session`, remembering the last `window` numbers received
+    #[must_use]
+    pub fn wrap(session: RumpsteakSession, window: u64) -> RumpsteakSession {
+        RumpsteakSession::new(Box::new(Self {
+            inner: session,
+            next_seq: 0,
+            window: DedupWindow::new(window),
+        }))
+    }
+
+    async fn send_frame(&mut self, payload: &[u8]) -> Result<SessionUpdate<()>> {
+        let frame = sequence(self.next_seq, payload);
+        let update = self.inner.send(frame).await?;
+        self.next_seq += 1;
+        Ok(update)
+    }
+
+    async fn recv_frame(&mut self) -> Result<SessionUpdate<Vec<u8>>> {
+        loop {
+            let update = self.inner.recv().await?;
+            let (seq, payload) = unsequence(update.output)?;
+            if self.window.accept(seq) {
+                return Ok(SessionUpdate {
+                    output: payload,
+                    description: update.description,
+                    is_complete: update.is_complete,
+                });
+            }
+            tracing::debug!(seq, "dropped redelivered frame");
+        }
+    }
+}
+
+impl SessionTypeDynamic for DedupSession {
+    fn type_name(&self) -> &'static str {
+        "DedupSession"
+    }
+
+    fn send(&mut self, data: Vec<u8>) -> BoxFuture<'_, Result<SessionUpdate<()>>> {
+        Box::pin(async move { self.send_frame(&data).await })
+    }
+
+    fn recv(&mut self) -> BoxFuture<'_, Result<SessionUpdate<Vec<u8>>>> {
+        Box::pin(self.recv_frame())
+    }
+
+    fn choose(&mut self, label: &str) -> BoxFuture<'_, Result<SessionUpdate<()>>> {
+        let label = label.to_string();
+        Box::pin(async move {
+            let bytes = bincode::serialize(&label).map_err(|e| {
+                ChoreographyError::Transport(format!("Label serialization failed: {e}"))
+            })?;
+            Ok(self.send_frame(&bytes).await?.with_description("Choose"))
+        })
+    }
+
+    fn offer(&mut self) -> BoxFuture<'_, Result<SessionUpdate<String>>> {
+        Box::pin(async move {
+            let update = self.recv_frame().await?;
+            let label: String = bincode::deserialize(&update.output).map_err(|e| {
+                ChoreographyError::Transport(format!("Label deserialization failed: {e}"))
+            })?;
+            Ok(SessionUpdate::new(label).with_description("Offer"))
+        })
+    }
+}
+
+/// Deduplicating `Transport` for blocking endpoints
+///
+/// Sequence numbers are shared by all of the role's peers and windows are
+/// kept per peer.
+pub struct Deduplicated<R, T> {
+    inner: T,
+    next_seq: u64,
+    window_size: u64,
+    windows: HashMap<R, DedupWindow>,
+}
+
+impl<R: Copy + Eq + Hash, T: Transport<R>> Deduplicated<R, T> {
+    /// Deduplicate `inner` with windows of `DEFAULT_DEDUP_WINDOW` numbers
+    #[must_use]
+    pub fn new(inner: T) -> Self {
+        Self {
+            inner,
+            next_seq: 0,
+            window_size: DEFAULT_DEDUP_WINDOW,
+            windows: HashMap::new(),
+        }
+    }
+
+    /// Remember the last `size` numbers received from each peer
+    #[must_use]
+    pub fn with_window(mut self, size: u64) -> Self {
+        self.window_size = size;
+        self
+    }
+
+    /// The wrapped transport
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<R: Copy + Eq + Hash + std::fmt::Debug, T: Transport<R>> Transport<R> for Deduplicated<R, T> {
+    fn send(&mut self, to: R, frame: Vec<u8>) -> Result<()> {
+        self.inner.send(to, sequence(self.next_seq, &frame))?;
+        self.next_seq += 1;
+        Ok(())
+    }
+
+    fn recv(&mut self, from: R) -> Result<Vec<u8>> {
+        loop {
+            let (seq, payload) = unsequence(self.inner.recv(from)?)?;
+            let size = self.window_size;
+            let window = self
+                .windows
+                .entry(from)
+                .or_insert_with(|| DedupWindow::new(size));
+            if window.accept(seq) {
+                return Ok(payload);
+            }
+            tracing::debug!(?from, seq, "dropped redelivered frame");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::blocking::{BlockingEndpoint, ChannelTransport};
+    use futures::channel::mpsc::unbounded;
+    use futures::StreamExt;
+
+    #[test]
+    fn test_window() {
+        let mut window = DedupWindow::new(4);
+        assert!(window.accept(0));
+        assert!(window.accept(2));
+        assert!(!window.accept(2));
+        assert!(window.accept(1));
+        assert!(window.accept(6));
+        // 2 is still in the window, 1 and 0 are too old to tell
+        assert!(!window.accept(2));
+        assert!(!window.accept(0));
+        assert!(window.accept(5));
+    }
+
+    /// Transport delivering every frame twice
+    struct Redelivering<R>(ChannelTransport<R>);
+
+    impl<R: Copy + Eq + Hash + std::fmt::Debug> Transport<R> for Redelivering<R> {
+        fn send(&mut self, to: R, frame: Vec<u8>) -> Result<()> {
+            self.0.send(to, frame.clone())?;
+            self.0.send(to, frame)
+        }
+
+        fn recv(&mut self, from: R) -> Result<Vec<u8>> {
+            self.0.recv(from)
+        }
+    }
+
+    #[test]
+    fn test_blocking_redelivery() {
+        let roles = ["alice", "bob"];
+        let mut transports = ChannelTransport::mesh(&roles);
+        let mut endpoint = |role| {
+            let transport = Redelivering(transports.remove(&role).unwrap());
+            BlockingEndpoint::new(role, roles.to_vec(), Deduplicated::new(transport))
+        };
+        let mut alice = endpoint("alice");
+        let mut bob = endpoint("bob");
+
+        alice.send("bob", &1u32).unwrap();
+        alice.choose("next").unwrap();
+        alice.send("bob", &2u32).unwrap();
+        assert_eq!(bob.recv::<u32>("alice").unwrap(), 1);
+        assert_eq!(bob.offer("alice").unwrap(), "next");
+        assert_eq!(bob.recv::<u32>("alice").unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_session_redelivery() {
+        let (alice_tx, mut wire) = unbounded::<Vec<u8>>();
+        let (_, alice_rx) = unbounded::<Vec<u8>>();
+        let (bob_tx, _) = unbounded::<Vec<u8>>();
+        let (redelivered, bob_rx) = unbounded::<Vec<u8>>();
+        let mut alice =
+            DedupSession::wrap(RumpsteakSession::from_sink_stream(alice_tx, alice_rx), 8);
+        let mut bob = DedupSession::wrap(RumpsteakSession::from_sink_stream(bob_tx, bob_rx), 8);
+
+        alice.send(b"hello".to_vec()).await.unwrap();
+        alice.choose("accept").await.unwrap();
+        for _ in 0..2 {
+            let frame = wire.next().await.unwrap();
+            redelivered.unbounded_send(frame.clone()).unwrap();
+            redelivered.unbounded_send(frame).unwrap();
+        }
+        redelivered.unbounded_send(vec![1, 2]).unwrap();
+
+        assert_eq!(bob.recv().await.unwrap().output, b"hello");
+        assert_eq!(bob.offer().await.unwrap().output, "accept");
+        assert!(bob.recv().await.is_err());
+    }
+}
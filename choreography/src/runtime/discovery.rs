@@ -0,0 +1,493 @@
+// Protocol registry and discovery
+//
+// Services publish which protocols they implement to a
+// `ChoreographyRegistry`: the protocol's descriptor, a version label, the
+// roles they can play and the address they are reached at. Before a session
+// starts, the initiator looks up a service for every other role and assigns
+// the role to its address, so rosters need not be configured by hand.
+//
+// Compatibility is decided by the protocol hash, not the version label: a
+// service compiled from a different definition of the protocol is never
+// returned by `locate`, and the lookup fails with `Incompatible`, naming the
+// versions that were found, instead of letting the session's bootstrap
+// reject it later.
+//
+// Publications are leases. A service that stops renewing its record, by
+// publishing it again, drops out of lookups once the lease runs out, and
+// `withdraw` removes it at once.
+//
+// `serve` exposes a registry on a socket, for services in other processes
+// and the `choreo-registry` binary (`registry` feature). Each line is a JSON
+// `RegistryRequest`, answered with one line holding a `RegistryResponse`.
+// `DiscoveryClient` speaks this protocol.
+
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::effects::RoleId;
+use crate::runtime::bootstrap::{ProtocolDescriptor, RoleAssignment};
+use crate::runtime::Instant;
+
+/// Lease of a publication that is not renewed, by default
+pub const DEFAULT_LEASE: Duration = Duration::from_secs(60);
+
+/// What a service publishes about a protocol it implements
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ServiceRecord {
+    /// Name of the service, unique per protocol
+    pub service: String,
+    /// Where the service is reached, assigned as its participant
+    pub address: String,
+    pub protocol: ProtocolDescriptor,
+    /// Release of the service's implementation, for operators only
+    pub version: String,
+    /// Roles of the protocol the service plays
+    pub roles: Vec<String>,
+}
+
+impl ServiceRecord {
+    #[must_use]
+    pub fn new(
+        service: impl Into<String>,
+        address: impl Into<String>,
+        protocol: ProtocolDescriptor,
+    ) -> Self {
+        Self {
+            service: service.into(),
+            address: address.into(),
+            protocol,
+            version: String::new(),
+            roles: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_version(mut self, version: impl Into<String>) -> Self {
+        self.version = version.into();
+        self
+    }
+
+    /// Offer to play `role`
+    #[must_use]
+    pub fn with_role(mut self, role: impl Into<String>) -> Self {
+        self.roles.push(role.into());
+        self
+    }
+
+    pub fn plays(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+}
+
+/// Errors raised while discovering peers
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum DiscoveryError {
+    #[error("no service plays {role} in {protocol}")]
+    NotFound { protocol: String, role: String },
+
+    #[error("no service plays {role} in {expected}; found incompatible {found:?}")]
+    Incompatible {
+        role: String,
+        expected: String,
+        /// Protocol and version of each service found
+        found: Vec<String>,
+    },
+
+    #[error("registry unreachable: {0}")]
+    Io(String),
+
+    #[error("registry refused the request: {0}")]
+    Refused(String),
+}
+
+/// Request to a registry, one JSON line on its socket
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub enum RegistryRequest {
+    /// Publish or renew `record`
+    Publish {
+        record: ServiceRecord,
+    },
+    Withdraw {
+        service: String,
+        protocol: String,
+    },
+    /// Services implementing any version of the protocol named `protocol`,
+    /// playing `role` if given
+    Lookup {
+        protocol: String,
+        role: Option<String>,
+    },
+}
+
+/// Answer of a registry, one JSON line on its socket
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum RegistryResponse {
+    /// Published, until the lease of `lease_ms` runs out unless renewed
+    Published {
+        lease_ms: u64,
+    },
+    Withdrawn {
+        found: bool,
+    },
+    Found {
+        records: Vec<ServiceRecord>,
+    },
+    Error {
+        message: String,
+    },
+}
+
+struct Publication {
+    record: ServiceRecord,
+    expires: Instant,
+}
+
+/// Services and the protocols they implement, shared by clones
+#[derive(Clone)]
+pub struct ChoreographyRegistry {
+    lease: Duration,
+    /// Keyed by protocol name, then service
+    publications: Arc<Mutex<BTreeMap<(String, String), Publication>>>,
+}
+
+impl std::fmt::Debug for ChoreographyRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChoreographyRegistry")
+            .field("lease", &self.lease)
+            .field("publications", &self.lock().len())
+            .finish()
+    }
+}
+
+impl Default for ChoreographyRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChoreographyRegistry {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            lease: DEFAULT_LEASE,
+            publications: Arc::default(),
+        }
+    }
+
+    /// Expire publications not renewed within `lease`
+    #[must_use]
+    pub fn with_lease(mut self, lease: Duration) -> Self {
+        self.lease = lease;
+        self
+    }
+
+    /// Publish `record`, replacing the service's earlier record for the
+    /// same protocol, and return its lease
+    pub fn publish(&self, record: ServiceRecord) -> Duration {
+        let key = (record.protocol.name.clone(), record.service.clone());
+        tracing::debug!(service = %record.service, protocol = %record.protocol, "service published");
+        self.lock().insert(
+            key,
+            Publication {
+                record,
+                expires: Instant::now() + self.lease,
+            },
+        );
+        self.lease
+    }
+
+    /// Remove the record of `service` for `protocol`, returning whether
+    /// there was one
+    pub fn withdraw(&self, service: &str, protocol: &str) -> bool {
+        self.lock()
+            .remove(&(protocol.to_string(), service.to_string()))
+            .is_some()
+    }
+
+    /// Live records for any version of `protocol`, of services playing
+    /// `role` if given, ordered by service
+    pub fn lookup(&self, protocol: &str, role: Option<&str>) -> Vec<ServiceRecord> {
+        let now = Instant::now();
+        let mut publications = self.lock();
+        publications.retain(|_, publication| publication.expires > now);
+        publications
+            .values()
+            .map(|publication| &publication.record)
+            .filter(|record| record.protocol.name == protocol)
+            .filter(|record| role.map_or(true, |role| record.plays(role)))
+            .cloned()
+            .collect()
+    }
+
+    /// Service playing `role` in exactly `protocol`
+    ///
+    /// # Errors
+    ///
+    /// No service plays the role, or only services of another definition
+    /// of the protocol do.
+    pub fn locate(
+        &self,
+        protocol: &ProtocolDescriptor,
+        role: &str,
+    ) -> Result<ServiceRecord, DiscoveryError> {
+        compatible(self.lookup(&protocol.name, Some(role)), protocol, role)
+    }
+
+    /// Answer `request`
+    pub fn handle(&self, request: RegistryRequest) -> RegistryResponse {
+        match request {
+            RegistryRequest::Publish { record } => {
+                let lease = self.publish(record);
+                RegistryResponse::Published {
+                    lease_ms: u64::try_from(lease.as_millis()).unwrap_or(u64::MAX),
+                }
+            }
+            RegistryRequest::Withdraw { service, protocol } => RegistryResponse::Withdrawn {
+                found: self.withdraw(&service, &protocol),
+            },
+            RegistryRequest::Lookup { protocol, role } => RegistryResponse::Found {
+                records: self.lookup(&protocol, role.as_deref()),
+            },
+        }
+    }
+
+    /// Answer one line of the registry's socket protocol
+    pub fn command(&self, line: &str) -> String {
+        let response = match serde_json::from_str(line) {
+            Ok(request) => self.handle(request),
+            Err(e) => RegistryResponse::Error {
+                message: format!("malformed request: {e}"),
+            },
+        };
+        serde_json::to_string(&response).unwrap_or_default()
+    }
+
+    /// Accept registry clients on `address`, in a background thread, and
+    /// return the address bound
+    ///
+    /// # Errors
+    ///
+    /// The error binding `address`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn serve(
+        &self,
+        address: impl std::net::ToSocketAddrs,
+    ) -> std::io::Result<std::net::SocketAddr> {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind(address)?;
+        let bound = listener.local_addr()?;
+        let registry = self.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let registry = registry.clone();
+                std::thread::spawn(move || {
+                    let Ok(reader) = stream.try_clone() else {
+                        return;
+                    };
+                    let mut writer = stream;
+                    for line in BufReader::new(reader).lines().map_while(Result::ok) {
+                        let reply = registry.command(&line);
+                        if writeln!(writer, "{reply}").is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        Ok(bound)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, BTreeMap<(String, String), Publication>> {
+        self.publications
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// First of `records` compiled from exactly `protocol`
+fn compatible(
+    records: Vec<ServiceRecord>,
+    protocol: &ProtocolDescriptor,
+    role: &str,
+) -> Result<ServiceRecord, DiscoveryError> {
+    if records.is_empty() {
+        return Err(DiscoveryError::NotFound {
+            protocol: protocol.name.clone(),
+            role: role.to_string(),
+        });
+    }
+    let found = records
+        .iter()
+        .map(|record| {
+            format!(
+                "{} {} ({})",
+                record.protocol, record.version, record.service
+            )
+        })
+        .collect();
+    records
+        .into_iter()
+        .find(|record| record.protocol == *protocol)
+        .ok_or_else(|| DiscoveryError::Incompatible {
+            role: role.to_string(),
+            expected: protocol.to_string(),
+            found,
+        })
+}
+
+/// Connection to a registry served by `ChoreographyRegistry::serve`
+///
+/// Requests block until the registry answers.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug)]
+pub struct DiscoveryClient {
+    writer: std::net::TcpStream,
+    replies: std::io::Lines<std::io::BufReader<std::net::TcpStream>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl DiscoveryClient {
+    /// Connect to the registry at `address`
+    ///
+    /// # Errors
+    ///
+    /// The registry cannot be reached.
+    pub fn connect(address: impl std::net::ToSocketAddrs) -> Result<Self, DiscoveryError> {
+        use std::io::BufRead;
+
+        let io = |e: std::io::Error| DiscoveryError::Io(e.to_string());
+        let writer = std::net::TcpStream::connect(address).map_err(io)?;
+        let reader = writer.try_clone().map_err(io)?;
+        Ok(Self {
+            writer,
+            replies: std::io::BufReader::new(reader).lines(),
+        })
+    }
+
+    /// Send `request` and wait for the answer
+    ///
+    /// # Errors
+    ///
+    /// The connection failed, or the registry could not read the request.
+    pub fn request(
+        &mut self,
+        request: &RegistryRequest,
+    ) -> Result<RegistryResponse, DiscoveryError> {
+        use std::io::Write;
+
+        let line = serde_json::to_string(request).map_err(|e| DiscoveryError::Io(e.to_string()))?;
+        writeln!(self.writer, "{line}").map_err(|e| DiscoveryError::Io(e.to_string()))?;
+        let reply = self
+            .replies
+            .next()
+            .ok_or_else(|| DiscoveryError::Io("registry closed the connection".into()))?
+            .map_err(|e| DiscoveryError::Io(e.to_string()))?;
+        match serde_json::from_str(&reply) {
+            Ok(RegistryResponse::Error { message }) => Err(DiscoveryError::Refused(message)),
+            Ok(response) => Ok(response),
+            Err(e) => Err(DiscoveryError::Io(format!("malformed answer: {e}"))),
+        }
+    }
+
+    /// Publish or renew `record`, returning its lease
+    ///
+    /// # Errors
+    ///
+    /// See [`DiscoveryClient::request`].
+    pub fn publish(&mut self, record: ServiceRecord) -> Result<Duration, DiscoveryError> {
+        match self.request(&RegistryRequest::Publish { record })? {
+            RegistryResponse::Published { lease_ms } => Ok(Duration::from_millis(lease_ms)),
+            other => Err(unexpected(&other)),
+        }
+    }
+
+    /// Remove the record of `service` for `protocol`, returning whether
+    /// there was one
+    ///
+    /// # Errors
+    ///
+    /// See [`DiscoveryClient::request`].
+    pub fn withdraw(&mut self, service: &str, protocol: &str) -> Result<bool, DiscoveryError> {
+        let request = RegistryRequest::Withdraw {
+            service: service.to_string(),
+            protocol: protocol.to_string(),
+        };
+        match self.request(&request)? {
+            RegistryResponse::Withdrawn { found } => Ok(found),
+            other => Err(unexpected(&other)),
+        }
+    }
+
+    /// Live records for any version of `protocol`, of services playing
+    /// `role` if given
+    ///
+    /// # Errors
+    ///
+    /// See [`DiscoveryClient::request`].
+    pub fn lookup(
+        &mut self,
+        protocol: &str,
+        role: Option<&str>,
+    ) -> Result<Vec<ServiceRecord>, DiscoveryError> {
+        let request = RegistryRequest::Lookup {
+            protocol: protocol.to_string(),
+            role: role.map(ToString::to_string),
+        };
+        match self.request(&request)? {
+            RegistryResponse::Found { records } => Ok(records),
+            other => Err(unexpected(&other)),
+        }
+    }
+
+    /// Service playing `role` in exactly `protocol`
+    ///
+    /// # Errors
+    ///
+    /// No service plays the role, only services of another definition of
+    /// the protocol do, or the request failed.
+    pub fn locate(
+        &mut self,
+        protocol: &ProtocolDescriptor,
+        role: &str,
+    ) -> Result<ServiceRecord, DiscoveryError> {
+        let records = self.lookup(&protocol.name, Some(role))?;
+        compatible(records, protocol, role)
+    }
+
+    /// Assignment of each of `roles` to the address of a service playing it
+    /// in exactly `protocol`, for `SessionInitiator::assign`
+    ///
+    /// Roles are looked up by their `Debug` name.
+    ///
+    /// # Errors
+    ///
+    /// The first role no compatible service plays, or a failed request.
+    pub fn roster<R: RoleId>(
+        &mut self,
+        protocol: &ProtocolDescriptor,
+        roles: &[R],
+    ) -> Result<Vec<RoleAssignment<R>>, DiscoveryError> {
+        roles
+            .iter()
+            .map(|role| {
+                let record = self.locate(protocol, &format!("{role:?}"))?;
+                Ok(RoleAssignment {
+                    role: *role,
+                    participant: record.address,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn unexpected(response: &RegistryResponse) -> DiscoveryError {
+    DiscoveryError::Io(format!("unexpected answer {response:?}"))
+}
@@ -0,0 +1,118 @@
+// Flow-cost metering
+//
+// Sends annotated with `flow_cost` spend units from a budget. Codegen lists the
+// charged sends of every role as `FlowCharge`s, and the `Metered` middleware
+// spends each charge from a `FlowMeter` before the message leaves. Once a
+// charge no longer fits, the send fails with `FlowBudgetExceeded` and the
+// session is aborted without the message being sent.
+//
+// The static counterpart is `compiler::flow_cost::analyze_flow_cost`, which
+// computes the worst-case spend of each role ahead of time.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use thiserror::Error;
+
+/// Annotation giving the flow cost of a send
+pub const FLOW_COST: &str = "flow_cost";
+
+/// Charged send of one role
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlowCharge {
+    pub peer: &'static str,
+    /// Message type name
+    pub label: &'static str,
+    pub cost: u64,
+}
+
+/// A charge did not fit into the remaining budget
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+#[error("{role} needs {cost} flow units to send {label} ({peer}), but only {remaining} of {budget} remain")]
+pub struct FlowBudgetExceeded {
+    pub role: String,
+    pub peer: String,
+    pub label: String,
+    pub cost: u64,
+    pub remaining: u64,
+    pub budget: u64,
+}
+
+/// Shared flow budget
+///
+/// Clones spend from the same budget, so one meter can cover several roles or
+/// sessions.
+#[derive(Debug, Clone)]
+pub struct FlowMeter {
+    budget: u64,
+    spent: Arc<AtomicU64>,
+}
+
+impl FlowMeter {
+    #[must_use]
+    pub fn new(budget: u64) -> Self {
+        Self {
+            budget,
+            spent: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    #[must_use]
+    pub fn budget(&self) -> u64 {
+        self.budget
+    }
+
+    #[must_use]
+    pub fn spent(&self) -> u64 {
+        self.spent.load(Ordering::Acquire)
+    }
+
+    #[must_use]
+    pub fn remaining(&self) -> u64 {
+        self.budget.saturating_sub(self.spent())
+    }
+
+    /// Spend `cost` units, returning the remaining budget, or `None` and
+    /// spending nothing if the cost does not fit
+    pub fn try_spend(&self, cost: u64) -> Option<u64> {
+        self.spent
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |spent| {
+                spent
+                    .checked_add(cost)
+                    .filter(|total| *total <= self.budget)
+            })
+            .ok()
+            .map(|previous| self.budget - previous - cost)
+    }
+
+    /// Return all spent units to the budget
+    pub fn reset(&self) {
+        self.spent.store(0, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meter_spends_until_exhausted() {
+        let meter = FlowMeter::new(100);
+        assert_eq!(meter.try_spend(60), Some(40));
+        assert_eq!(meter.try_spend(50), None);
+        assert_eq!(meter.spent(), 60);
+        assert_eq!(meter.try_spend(40), Some(0));
+        assert_eq!(meter.try_spend(1), None);
+
+        meter.reset();
+        assert_eq!(meter.remaining(), 100);
+    }
+
+    #[test]
+    fn test_clones_share_budget() {
+        let meter = FlowMeter::new(10);
+        let other = meter.clone();
+        assert_eq!(other.try_spend(7), Some(3));
+        assert_eq!(meter.remaining(), 3);
+        assert_eq!(meter.try_spend(u64::MAX), None);
+    }
+}
@@ -0,0 +1,246 @@
+// Fuzzing the receive path
+//
+// `@codegen(fuzz)` generates a `fuzz_<role>` function per role that runs the
+// role's driver on frames cut from arbitrary bytes, and
+// `compiler::generate_fuzz_targets` emits cargo-fuzz targets calling them.
+// Frames are decoded with bincode as on the real transports, so a malformed
+// frame must come back as a `ChoreographyError`; a panic while decoding or
+// driving the role is a bug for the fuzzer to report.
+//
+// The input starts with a byte giving the number of decision bytes that
+// follow, which pick the branches the role itself chooses. The rest is a
+// sequence of frames, each prefixed with its length as a little-endian
+// `u16`. Sent frames are discarded, and a receive past the end of the input
+// fails with `ChoreographyError::Transport`.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::effects::{ChoreoHandler, ChoreographyError, Label, Result, RoleId};
+use crate::runtime::blocking::Transport;
+
+pub use futures::executor::block_on;
+
+/// Split fuzzer input into the role's choices and the frames it receives
+#[must_use]
+pub fn split_input(data: &[u8]) -> (Choices, Frames) {
+    let (count, rest) = data.split_first().map_or((0, data), |(&n, rest)| (n, rest));
+    let (decisions, frames) = rest.split_at(usize::from(count).min(rest.len()));
+    (
+        Choices {
+            remaining: decisions.iter().copied().collect(),
+        },
+        Frames {
+            data: frames.to_vec(),
+            offset: 0,
+        },
+    )
+}
+
+/// Branches a fuzzed role takes at the choices it makes
+#[derive(Debug, Clone, Default)]
+pub struct Choices {
+    remaining: VecDeque<u8>,
+}
+
+impl Choices {
+    /// Branch to take at a choice of `branches` branches, of which `exits`
+    /// leave the enclosing recursion
+    pub fn next(&mut self, branches: usize, exits: &[usize]) -> usize {
+        match self.remaining.pop_front() {
+            Some(decision) => usize::from(decision) % branches.max(1),
+            None => exits.first().copied().unwrap_or(0),
+        }
+    }
+}
+
+/// Length-prefixed frames delivered to a fuzzed role, whoever it receives from
+#[derive(Debug, Clone, Default)]
+pub struct Frames {
+    data: Vec<u8>,
+    offset: usize,
+}
+
+impl Frames {
+    /// The next frame, truncated if the input ends within it
+    pub fn next_frame(&mut self) -> Result<Vec<u8>> {
+        let rest = &self.data[self.offset..];
+        if rest.len() < 2 {
+            return Err(ChoreographyError::Transport(
+                "fuzz input exhausted".to_string(),
+            ));
+        }
+        let (prefix, rest) = rest.split_at(2);
+        let len = usize::from(u16::from_le_bytes([prefix[0], prefix[1]])).min(rest.len());
+        let frame = rest[..len].to_vec();
+        self.offset += 2 + len;
+        Ok(frame)
+    }
+}
+
+impl<R> Transport<R> for Frames {
+    fn send(&mut self, _to: R, _frame: Vec<u8>) -> Result<()> {
+        Ok(())
+    }
+
+    fn recv(&mut self, _from: R) -> Result<Vec<u8>> {
+        self.next_frame()
+    }
+}
+
+/// `ChoreoHandler` feeding a role the frames of fuzzer input
+///
+/// Received labels must be among `labels`, the labels of the choreography;
+/// any other label fails with `ChoreographyError::ProtocolViolation`.
+pub struct FuzzHandler<R> {
+    frames: Frames,
+    labels: &'static [&'static str],
+    _role: std::marker::PhantomData<R>,
+}
+
+impl<R> FuzzHandler<R> {
+    #[must_use]
+    pub fn new(frames: Frames, labels: &'static [&'static str]) -> Self {
+        Self {
+            frames,
+            labels,
+            _role: std::marker::PhantomData,
+        }
+    }
+}
+
+#[async_trait]
+impl<R: RoleId> ChoreoHandler for FuzzHandler<R> {
+    type Role = R;
+    type Endpoint = ();
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        _to: Self::Role,
+        _msg: &M,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        _from: Self::Role,
+    ) -> Result<M> {
+        let frame = self.frames.next_frame()?;
+        bincode::deserialize(&frame).map_err(|e| ChoreographyError::Serialization(e.to_string()))
+    }
+
+    async fn choose(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        _who: Self::Role,
+        _label: Label,
+    ) -> Result<()> {
+        Ok(())
+    }
+
+    async fn offer(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        from: Self::Role,
+        _labels: &[Label],
+    ) -> Result<Label> {
+        let frame = self.frames.next_frame()?;
+        let label: String = bincode::deserialize(&frame)
+            .map_err(|e| ChoreographyError::Serialization(e.to_string()))?;
+        // Known labels are static, so nothing is leaked per input
+        self.labels
+            .iter()
+            .copied()
+            .find(|known| *known == label)
+            .map(Label)
+            .ok_or_else(|| {
+                ChoreographyError::ProtocolViolation(format!(
+                    "{from:?} chose unknown branch {label:?}"
+                ))
+            })
+    }
+
+    async fn with_timeout<F, T>(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        _at: Self::Role,
+        _dur: Duration,
+        body: F,
+    ) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>> + Send,
+    {
+        body.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    enum Role {
+        Client,
+    }
+
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut frame = (payload.len() as u16).to_le_bytes().to_vec();
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    #[test]
+    fn test_split_input() {
+        let mut data = vec![2, 5, 1];
+        data.extend(frame(b"ab"));
+        data.extend([9, 0, b'c']);
+        let (mut choices, mut frames) = split_input(&data);
+        assert_eq!(choices.next(2, &[0]), 1);
+        assert_eq!(choices.next(2, &[0]), 1);
+        assert_eq!(choices.next(2, &[0]), 0);
+        assert_eq!(frames.next_frame().unwrap(), b"ab");
+        // Truncated by the end of the input
+        assert_eq!(frames.next_frame().unwrap(), b"c");
+        assert!(matches!(
+            frames.next_frame(),
+            Err(ChoreographyError::Transport(_))
+        ));
+
+        let (_, mut frames) = split_input(&[200, 1]);
+        assert!(frames.next_frame().is_err());
+    }
+
+    #[test]
+    fn test_malformed_frames_are_errors() {
+        let label = bincode::serialize("accept").unwrap();
+        let unknown = bincode::serialize("refund").unwrap();
+        let mut data = vec![0];
+        data.extend(frame(&[0xff]));
+        data.extend(frame(&label));
+        data.extend(frame(&unknown));
+        let (_, frames) = split_input(&data);
+        let mut handler = FuzzHandler::<Role>::new(frames, &["accept", "reject"]);
+        let labels = [Label("accept"), Label("reject")];
+
+        block_on(async {
+            let malformed = handler.recv::<String>(&mut (), Role::Client).await;
+            assert!(matches!(
+                malformed,
+                Err(ChoreographyError::Serialization(_))
+            ));
+            let label = handler.offer(&mut (), Role::Client, &labels).await.unwrap();
+            assert_eq!(label, Label("accept"));
+            let unknown = handler.offer(&mut (), Role::Client, &labels).await;
+            assert!(matches!(
+                unknown,
+                Err(ChoreographyError::ProtocolViolation(_))
+            ));
+        });
+    }
+}
@@ -0,0 +1,269 @@
+// Coordinated sessions
+//
+// A session often drives others: an auction session starts one negotiation
+// session per bidder, and a negotiation may start sessions of its own. A
+// `SessionGroup` runs such child sessions as tasks in a structured way: the
+// group's owner spawns them under keys, such as the bidder, collects their
+// results with `next` or `join`, and cancels them with the group.
+//
+// Each child gets a `ChildSession` naming the group and its key, for
+// correlating logs and messages, and its own `SessionHandle`, to drive its
+// role through `Cancellable`. Cancelling the group, or the parent session a
+// group was created `within`, cancels every child, which in turn cancels
+// groups created within it, so cancellation reaches the whole tree. With
+// `fail_fast`, the first child to fail cancels its siblings as well.
+//
+// Cancellation is cooperative: a child's result is whatever its future
+// returns after its handle was cancelled, typically
+// `ChoreographyError::Cancelled`, so `join` waits for every child to stop.
+
+use std::fmt;
+use std::future::Future;
+
+use futures::channel::oneshot;
+use futures::future::{select, Either};
+use futures::stream::FuturesUnordered;
+use futures::{pin_mut, StreamExt};
+use uuid::Uuid;
+
+use crate::effects::SessionHandle;
+
+/// A child session of a `SessionGroup`, as given to the child
+#[derive(Debug, Clone)]
+pub struct ChildSession<K> {
+    /// ID of the group the child belongs to
+    pub group: Uuid,
+    /// ID of the group of the child session the group was started from,
+    /// for groups made with `ChildSession::group`
+    pub parent: Option<Uuid>,
+    pub key: K,
+    /// Cancelled when the group or the parent session is
+    pub handle: SessionHandle,
+}
+
+impl<K> ChildSession<K> {
+    /// A group of sessions of this child, cancelled with it
+    #[must_use]
+    pub fn group<L, T, E>(&self) -> SessionGroup<L, T, E> {
+        let mut group = SessionGroup::within(&self.handle);
+        group.parent = Some(self.group);
+        group
+    }
+}
+
+/// Results of the children of a `SessionGroup`
+#[derive(Debug)]
+pub struct GroupResults<K, T, E> {
+    /// Result of every child, in the order they completed
+    pub results: Vec<(K, Result<T, E>)>,
+}
+
+impl<K, T, E> GroupResults<K, T, E> {
+    /// Children that succeeded, with their outputs
+    pub fn succeeded(&self) -> impl Iterator<Item = (&K, &T)> {
+        self.results
+            .iter()
+            .filter_map(|(key, result)| result.as_ref().ok().map(|output| (key, output)))
+    }
+
+    /// Children that failed, with their errors
+    pub fn failed(&self) -> impl Iterator<Item = (&K, &E)> {
+        self.results
+            .iter()
+            .filter_map(|(key, result)| result.as_ref().err().map(|error| (key, error)))
+    }
+
+    #[must_use]
+    pub fn all_succeeded(&self) -> bool {
+        self.results.iter().all(|(_, result)| result.is_ok())
+    }
+
+    /// Outputs of all children, or the first failure to complete
+    ///
+    /// # Errors
+    ///
+    /// The key and error of the first child that failed.
+    pub fn into_result(self) -> Result<Vec<(K, T)>, (K, E)> {
+        self.results
+            .into_iter()
+            .map(|(key, result)| match result {
+                Ok(output) => Ok((key, output)),
+                Err(error) => Err((key, error)),
+            })
+            .collect()
+    }
+}
+
+/// Child sessions spawned, cancelled and joined together
+pub struct SessionGroup<K, T, E> {
+    id: Uuid,
+    parent: Option<Uuid>,
+    handle: SessionHandle,
+    /// Handle of the session the group was created within
+    within: Option<SessionHandle>,
+    fail_fast: bool,
+    /// Results of the children, sent as they complete
+    children: FuturesUnordered<oneshot::Receiver<(K, Result<T, E>)>>,
+}
+
+impl<K, T, E> fmt::Debug for SessionGroup<K, T, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionGroup")
+            .field("id", &self.id)
+            .field("parent", &self.parent)
+            .field("cancelled", &self.is_cancelled())
+            .field("fail_fast", &self.fail_fast)
+            .field("pending", &self.pending())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<K, T, E> Default for SessionGroup<K, T, E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, T, E> SessionGroup<K, T, E> {
+    /// A group cancelled only through `cancel`
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            parent: None,
+            handle: SessionHandle::new(),
+            within: None,
+            fail_fast: false,
+            children: FuturesUnordered::new(),
+        }
+    }
+
+    /// A group cancelled when the session of `parent` is
+    #[must_use]
+    pub fn within(parent: &SessionHandle) -> Self {
+        Self {
+            within: Some(parent.clone()),
+            ..Self::new()
+        }
+    }
+
+    /// Cancel the other children once one fails
+    #[must_use]
+    pub fn fail_fast(mut self) -> Self {
+        self.fail_fast = true;
+        self
+    }
+
+    #[must_use]
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    /// Children spawned whose results were not collected yet
+    #[must_use]
+    pub fn pending(&self) -> usize {
+        self.children.len()
+    }
+
+    /// Cancel every child. Idempotent.
+    pub fn cancel(&self) {
+        self.handle.cancel();
+    }
+
+    /// Whether the group or its parent session was cancelled
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.handle.is_cancelled()
+            || self
+                .within
+                .as_ref()
+                .is_some_and(SessionHandle::is_cancelled)
+    }
+}
+
+impl<K, T, E> SessionGroup<K, T, E>
+where
+    K: Clone + fmt::Debug + Send + 'static,
+    T: Send + 'static,
+    E: fmt::Display + Send + 'static,
+{
+    /// Spawn the child session `key`, run by the future `session` makes
+    /// from its `ChildSession`
+    ///
+    /// A child spawned once the group was cancelled starts with its handle
+    /// cancelled.
+    pub fn spawn<F, Fut>(&mut self, key: K, session: F)
+    where
+        F: FnOnce(ChildSession<K>) -> Fut,
+        Fut: Future<Output = Result<T, E>> + Send + 'static,
+    {
+        let child = ChildSession {
+            group: self.id,
+            parent: self.parent,
+            key: key.clone(),
+            handle: SessionHandle::new(),
+        };
+        let handle = child.handle.clone();
+        if self.is_cancelled() {
+            handle.cancel();
+        }
+        let running = session(child);
+        let group = self.handle.clone();
+        let within = self.within.clone();
+        let fail_fast = self.fail_fast;
+        let (sender, completed) = oneshot::channel();
+        self.children.push(completed);
+        crate::runtime::spawn(async move {
+            pin_mut!(running);
+            let cancelled = async {
+                match &within {
+                    Some(parent) => {
+                        let parent = parent.cancelled();
+                        let group = group.cancelled();
+                        pin_mut!(parent, group);
+                        select(parent, group).await;
+                    }
+                    None => group.cancelled().await,
+                }
+            };
+            pin_mut!(cancelled);
+            let result = match select(running, cancelled).await {
+                Either::Left((result, _)) => result,
+                Either::Right(((), running)) => {
+                    tracing::debug!(?key, "child session cancelled with its group");
+                    handle.cancel();
+                    running.await
+                }
+            };
+            if let Err(error) = &result {
+                tracing::debug!(?key, %error, "child session failed");
+                if fail_fast {
+                    group.cancel();
+                }
+            }
+            let _ = sender.send((key, result));
+        });
+    }
+
+    /// Result of the next child to complete, or `None` once every child
+    /// spawned has been collected
+    ///
+    /// Children that panicked have no result and are skipped.
+    pub async fn next(&mut self) -> Option<(K, Result<T, E>)> {
+        while let Some(completed) = self.children.next().await {
+            if let Ok(completed) = completed {
+                return Some(completed);
+            }
+        }
+        None
+    }
+
+    /// Wait for every child, collecting their results
+    pub async fn join(mut self) -> GroupResults<K, T, E> {
+        let mut results = Vec::with_capacity(self.pending());
+        while let Some(completed) = self.next().await {
+            results.push(completed);
+        }
+        GroupResults { results }
+    }
+}
@@ -0,0 +1,199 @@
+// Capability guards
+//
+// Protocol steps annotated with `guard_capability` may only run when the role
+// performing them holds the named capability. Codegen turns every annotation
+// into a `GuardPoint` for the guarded role, and the `Guarded` middleware asks a
+// `CapabilityProvider` before executing a matching step. A refused step fails
+// with `GuardDenied` without touching the transport.
+//
+// A guard on a role annotation applies to that role:
+//
+//     Client[@guard_capability = "submit"] -> Server: Order;
+//     Client -> Server[@guard_capability = "fulfil"]: Order;
+//
+// A statement annotation guards the sender or the choosing role, unless
+// `guard_role` names another participant of the statement:
+//
+//     [@guard_capability = "approve"]
+//     choice Manager { ... }
+//
+//     [@guard_capability = "audit", @guard_role = "Auditor"]
+//     choice Manager { ... }
+
+use std::collections::{HashMap, HashSet};
+use thiserror::Error;
+
+use crate::runtime::monitor::ActionKind;
+
+/// Annotation naming the capability a step requires
+pub const GUARD_CAPABILITY: &str = "guard_capability";
+
+/// Statement annotation naming the guarded role
+pub const GUARD_ROLE: &str = "guard_role";
+
+/// Source of the capabilities held by each role
+pub trait CapabilityProvider: Send + Sync {
+    /// Whether `role` currently holds `capability`
+    fn has_capability(&self, role: &str, capability: &str) -> bool;
+}
+
+impl<F> CapabilityProvider for F
+where
+    F: Fn(&str, &str) -> bool + Send + Sync,
+{
+    fn has_capability(&self, role: &str, capability: &str) -> bool {
+        self(role, capability)
+    }
+}
+
+/// Fixed capability grants, keyed by role name
+#[derive(Debug, Clone, Default)]
+pub struct CapabilitySet {
+    grants: HashMap<String, HashSet<String>>,
+}
+
+impl CapabilitySet {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Grant `capability` to `role`
+    #[must_use]
+    pub fn grant(mut self, role: impl Into<String>, capability: impl Into<String>) -> Self {
+        self.insert(role, capability);
+        self
+    }
+
+    pub fn insert(&mut self, role: impl Into<String>, capability: impl Into<String>) {
+        self.grants
+            .entry(role.into())
+            .or_default()
+            .insert(capability.into());
+    }
+
+    /// Withdraw `capability` from `role`, returning whether it was held
+    pub fn revoke(&mut self, role: &str, capability: &str) -> bool {
+        self.grants
+            .get_mut(role)
+            .is_some_and(|caps| caps.remove(capability))
+    }
+}
+
+impl CapabilityProvider for CapabilitySet {
+    fn has_capability(&self, role: &str, capability: &str) -> bool {
+        self.grants
+            .get(role)
+            .is_some_and(|caps| caps.contains(capability))
+    }
+}
+
+/// Guarded protocol step of one role
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GuardPoint {
+    pub action: ActionKind,
+    /// Peer of the step, `None` for any
+    pub peer: Option<&'static str>,
+    /// Message type or branch label, `None` for any
+    pub label: Option<&'static str>,
+    pub capability: &'static str,
+}
+
+impl GuardPoint {
+    /// Whether the step `action` with `peer` and `label` is guarded by this point
+    ///
+    /// A step whose label is not known matches points of every label, so
+    /// that it is refused rather than let through unchecked.
+    #[must_use]
+    pub fn applies_to(&self, action: ActionKind, peer: &str, label: Option<&str>) -> bool {
+        self.action == action
+            && self.peer.map_or(true, |expected| expected == peer)
+            && match (self.label, label) {
+                (Some(expected), Some(actual)) => expected == actual,
+                (None, _) | (Some(_), None) => true,
+            }
+    }
+}
+
+/// A guarded step was refused because the role lacks its capability
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+#[error("{role} lacks capability '{capability}' required to {action} {}", describe_step(.peer, .label.as_deref()))]
+pub struct GuardDenied {
+    pub role: String,
+    pub capability: String,
+    pub action: ActionKind,
+    pub peer: String,
+    /// Message type or branch label, unknown before an offer
+    pub label: Option<String>,
+}
+
+fn describe_step(peer: &str, label: Option<&str>) -> String {
+    match label {
+        Some(label) => format!("{label} ({peer})"),
+        None => format!("({peer})"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capability_set() {
+        let mut caps = CapabilitySet::new().grant("Client", "submit");
+        assert!(caps.has_capability("Client", "submit"));
+        assert!(!caps.has_capability("Client", "approve"));
+        assert!(!caps.has_capability("Server", "submit"));
+
+        assert!(caps.revoke("Client", "submit"));
+        assert!(!caps.revoke("Client", "submit"));
+        assert!(!caps.has_capability("Client", "submit"));
+    }
+
+    #[test]
+    fn test_guard_point_matching() {
+        let point = GuardPoint {
+            action: ActionKind::Send,
+            peer: Some("Server"),
+            label: Some("Order"),
+            capability: "submit",
+        };
+        assert!(point.applies_to(ActionKind::Send, "Server", Some("Order")));
+        assert!(!point.applies_to(ActionKind::Send, "Server", Some("Cancel")));
+        assert!(!point.applies_to(ActionKind::Receive, "Server", Some("Order")));
+        assert!(point.applies_to(ActionKind::Send, "Server", None));
+
+        let any = GuardPoint {
+            peer: None,
+            label: None,
+            ..point
+        };
+        assert!(any.applies_to(ActionKind::Send, "Client", Some("Cancel")));
+    }
+
+    #[test]
+    fn test_guard_denied_message() {
+        let err = GuardDenied {
+            role: "Client".into(),
+            capability: "submit".into(),
+            action: ActionKind::Send,
+            peer: "Server".into(),
+            label: Some("Order".into()),
+        };
+        assert_eq!(
+            err.to_string(),
+            "Client lacks capability 'submit' required to send Order (Server)"
+        );
+        let err = GuardDenied {
+            role: "Worker".into(),
+            capability: "audit".into(),
+            action: ActionKind::Branch,
+            peer: "Manager".into(),
+            label: None,
+        };
+        assert_eq!(
+            err.to_string(),
+            "Worker lacks capability 'audit' required to branch (Manager)"
+        );
+    }
+}
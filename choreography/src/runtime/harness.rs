@@ -0,0 +1,564 @@
+// Support for generated test harnesses
+//
+// `@codegen(test_harness)` generates a `Mock<Role>` per role and a
+// `run_session!` macro. A mock plays its role from a `MockScript`: the
+// messages it expects to receive, the messages it sends and the branches it
+// picks, in protocol order. The generated handler trait implementation of
+// each mock consumes the script as the driver walks the protocol, so a test
+// gives only the values and the projection decides who they go to.
+//
+// `run_session!` connects the roles of one session in this process:
+// async sessions through `ChannelHandler`, blocking ones through
+// `ChannelTransport`, with one thread per role.
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::future::{select_all, try_join_all, LocalBoxFuture};
+use futures::StreamExt;
+use serde::{de::DeserializeOwned, Serialize};
+use std::any::{type_name, Any};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::sync::mpsc::channel;
+use std::time::Duration;
+
+use crate::effects::{ChoreoHandler, ChoreographyError, Label, Result, RoleId};
+use crate::runtime::blocking::{BlockingEndpoint, Transport};
+
+/// Check run on a message a mock receives
+type Check = Box<dyn Fn(&dyn Any) -> bool + Send>;
+
+enum Step {
+    Receive {
+        message: &'static str,
+        check: Check,
+    },
+    Send {
+        message: &'static str,
+        value: Box<dyn Any + Send>,
+    },
+    Choose(&'static str),
+}
+
+impl std::fmt::Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Step::Receive { message, .. } => write!(f, "receive {message}"),
+            Step::Send { message, .. } => write!(f, "send {message}"),
+            Step::Choose(label) => write!(f, "choose `{label}`"),
+        }
+    }
+}
+
+/// Steps a mock role expects to take, in order
+pub struct MockScript {
+    role: &'static str,
+    steps: VecDeque<Step>,
+}
+
+impl MockScript {
+    /// Empty script for the mock named `role`, used in error messages
+    #[must_use]
+    pub fn new(role: &'static str) -> Self {
+        Self {
+            role,
+            steps: VecDeque::new(),
+        }
+    }
+
+    /// Expect to receive any `M`
+    pub fn receive<M: 'static>(&mut self) {
+        self.receive_where(|_: &M| true);
+    }
+
+    /// Expect to receive an `M` for which `check` holds
+    pub fn receive_where<M: 'static>(&mut self, check: impl Fn(&M) -> bool + Send + 'static) {
+        self.steps.push_back(Step::Receive {
+            message: short_type_name::<M>(),
+            check: Box::new(move |message| message.downcast_ref::<M>().is_some_and(&check)),
+        });
+    }
+
+    /// Send `message` when the protocol reaches a send of its type
+    pub fn send<M: Send + 'static>(&mut self, message: M) {
+        self.steps.push_back(Step::Send {
+            message: short_type_name::<M>(),
+            value: Box::new(message),
+        });
+    }
+
+    /// Pick the branch labelled `label` at the next choice
+    pub fn choose(&mut self, label: &'static str) {
+        self.steps.push_back(Step::Choose(label));
+    }
+
+    /// Consume the next step, which must receive `message`
+    pub fn on_receive<M: 'static>(&mut self, message: &M) -> Result<()> {
+        match self.steps.pop_front() {
+            Some(Step::Receive { check, .. }) if check(message) => Ok(()),
+            Some(Step::Receive {
+                message: expected, ..
+            }) if expected == short_type_name::<M>() => {
+                Err(self.violation(format!("received a {expected} its check rejected")))
+            }
+            step => Err(self.unexpected(step, &format!("receive {}", short_type_name::<M>()))),
+        }
+    }
+
+    /// Consume the next step, which must send an `M`
+    pub fn on_send<M: 'static>(&mut self) -> Result<M> {
+        match self.steps.pop_front() {
+            Some(Step::Send { message, value }) => match value.downcast::<M>() {
+                Ok(value) => Ok(*value),
+                Err(value) => {
+                    let step = Step::Send { message, value };
+                    Err(self.unexpected(Some(step), &format!("send {}", short_type_name::<M>())))
+                }
+            },
+            step => Err(self.unexpected(step, &format!("send {}", short_type_name::<M>()))),
+        }
+    }
+
+    /// Consume the next step, which must choose a branch
+    pub fn on_choose(&mut self) -> Result<&'static str> {
+        match self.steps.pop_front() {
+            Some(Step::Choose(label)) => Ok(label),
+            step => Err(self.unexpected(step, "choose a branch")),
+        }
+    }
+
+    /// Fail if steps are left once the role is done
+    pub fn finish(&self) -> Result<()> {
+        match self.steps.front() {
+            None => Ok(()),
+            Some(step) => Err(self.violation(format!(
+                "finished with {} step(s) left, the next being {step}",
+                self.steps.len()
+            ))),
+        }
+    }
+
+    /// Error for a choice label no branch of the protocol has
+    #[must_use]
+    pub fn unknown_label(&self, label: &str, expected: &[&str]) -> ChoreographyError {
+        self.violation(format!(
+            "chose `{label}`, but the branches are {}",
+            expected
+                .iter()
+                .map(|label| format!("`{label}`"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+    }
+
+    fn unexpected(&self, step: Option<Step>, protocol: &str) -> ChoreographyError {
+        match step {
+            Some(step) => self.violation(format!(
+                "expected to {step}, but the protocol is to {protocol}"
+            )),
+            None => self.violation(format!(
+                "ran out of steps, but the protocol is to {protocol}"
+            )),
+        }
+    }
+
+    fn violation(&self, message: String) -> ChoreographyError {
+        ChoreographyError::ProtocolViolation(format!("{} {message}", self.role))
+    }
+}
+
+/// Last path segment of a type name, `Request` for `my_crate::Request`
+fn short_type_name<T>() -> &'static str {
+    let name = type_name::<T>();
+    let base = name.split('<').next().unwrap_or(name);
+    base.rsplit("::").next().unwrap_or(name)
+}
+
+/// Handler connecting the roles of an async session in one process
+///
+/// Messages and branch labels are bincode-encoded and share one unbounded
+/// channel per ordered pair of roles. `choose` sends the label to every
+/// other role. Buffers sent with `send_bytes` are handed over without being
+/// encoded or copied.
+pub struct ChannelHandler<R> {
+    role: R,
+    senders: HashMap<R, UnboundedSender<Frame>>,
+    receivers: HashMap<R, UnboundedReceiver<Frame>>,
+}
+
+/// What a `ChannelHandler` passes between roles
+enum Frame {
+    Encoded(Vec<u8>),
+    Raw(Bytes),
+}
+
+impl Frame {
+    fn decode<M: DeserializeOwned>(self) -> Result<M> {
+        let encoded = match self {
+            Frame::Encoded(frame) => frame,
+            // Received by a `recv` rather than `recv_bytes`, as when
+            // middleware without `recv_bytes` wraps the receiving handler
+            Frame::Raw(payload) => bincode::serialize(&payload)
+                .map_err(|e| ChoreographyError::Serialization(e.to_string()))?,
+        };
+        bincode::deserialize(&encoded).map_err(|e| ChoreographyError::Serialization(e.to_string()))
+    }
+}
+
+impl<R: RoleId> ChannelHandler<R> {
+    /// Handlers connecting each of `roles` to every other
+    #[must_use]
+    pub fn mesh(roles: &[R]) -> HashMap<R, Self> {
+        let mut handlers: HashMap<R, Self> = roles
+            .iter()
+            .map(|&role| {
+                let handler = Self {
+                    role,
+                    senders: HashMap::new(),
+                    receivers: HashMap::new(),
+                };
+                (role, handler)
+            })
+            .collect();
+        for &from in roles {
+            for &to in roles {
+                if from == to {
+                    continue;
+                }
+                let (sender, receiver) = unbounded();
+                if let Some(handler) = handlers.get_mut(&from) {
+                    handler.senders.insert(to, sender);
+                }
+                if let Some(handler) = handlers.get_mut(&to) {
+                    handler.receivers.insert(from, receiver);
+                }
+            }
+        }
+        handlers
+    }
+
+    fn send_frame(&self, to: R, frame: Frame) -> Result<()> {
+        self.senders
+            .get(&to)
+            .ok_or_else(|| ChoreographyError::UnknownRole(format!("{to:?}")))?
+            .unbounded_send(frame)
+            .map_err(|_| ChoreographyError::Transport(format!("channel to {to:?} closed")))
+    }
+
+    async fn recv_frame(&mut self, from: R) -> Result<Frame> {
+        self.receivers
+            .get_mut(&from)
+            .ok_or_else(|| ChoreographyError::UnknownRole(format!("{from:?}")))?
+            .next()
+            .await
+            .ok_or_else(|| ChoreographyError::Transport(format!("channel from {from:?} closed")))
+    }
+}
+
+#[async_trait]
+impl<R: RoleId> ChoreoHandler for ChannelHandler<R> {
+    type Role = R;
+    type Endpoint = ();
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        let frame =
+            bincode::serialize(msg).map_err(|e| ChoreographyError::Serialization(e.to_string()))?;
+        self.send_frame(to, Frame::Encoded(frame))
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        from: Self::Role,
+    ) -> Result<M> {
+        self.recv_frame(from).await?.decode()
+    }
+
+    async fn recv_any<M: DeserializeOwned + Send>(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        from: &[Self::Role],
+    ) -> Result<(Self::Role, M)> {
+        if let Some(peer) = from.iter().find(|peer| !self.receivers.contains_key(peer)) {
+            return Err(ChoreographyError::UnknownRole(format!("{peer:?}")));
+        }
+        let receives: Vec<_> = self
+            .receivers
+            .iter_mut()
+            .filter(|(peer, _)| from.contains(peer))
+            .map(|(&peer, receiver)| Box::pin(async move { (peer, receiver.next().await) }))
+            .collect();
+        if receives.is_empty() {
+            return Err(ChoreographyError::ProtocolViolation(
+                "receive from an empty set of roles".into(),
+            ));
+        }
+        let ((sender, frame), _, _) = select_all(receives).await;
+        let frame = frame.ok_or_else(|| {
+            ChoreographyError::Transport(format!("channel from {sender:?} closed"))
+        })?;
+        Ok((sender, frame.decode()?))
+    }
+
+    async fn send_bytes(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        to: Self::Role,
+        payload: Bytes,
+    ) -> Result<()> {
+        self.send_frame(to, Frame::Raw(payload))
+    }
+
+    async fn recv_bytes(&mut self, _ep: &mut Self::Endpoint, from: Self::Role) -> Result<Bytes> {
+        match self.recv_frame(from).await? {
+            Frame::Raw(payload) => Ok(payload),
+            frame => frame.decode(),
+        }
+    }
+
+    async fn choose(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        _who: Self::Role,
+        label: Label,
+    ) -> Result<()> {
+        let frame = bincode::serialize(label.0)
+            .map_err(|e| ChoreographyError::Serialization(e.to_string()))?;
+        for &peer in self.senders.keys() {
+            self.send_frame(peer, Frame::Encoded(frame.clone()))?;
+        }
+        Ok(())
+    }
+
+    async fn offer(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        let label: String = self.recv_frame(from).await?.decode()?;
+        Label::resolve(&label, labels)
+    }
+
+    async fn with_timeout<F, T>(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        at: Self::Role,
+        dur: Duration,
+        body: F,
+    ) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>> + Send,
+    {
+        if at == self.role {
+            crate::runtime::timeout(dur, body).await
+        } else {
+            body.await
+        }
+    }
+}
+
+/// Take the connection of `role` out of a mesh, failing if a session names
+/// a role twice or one outside the choreography
+pub fn take_role<R: Eq + Hash + Debug, T>(mesh: &mut HashMap<R, T>, role: R) -> Result<T> {
+    mesh.remove(&role).ok_or_else(|| {
+        ChoreographyError::UnknownRole(format!("{role:?} is given twice or not a role"))
+    })
+}
+
+/// One role of an async session, see [`run_roles`]
+pub type RoleFuture<'a> = LocalBoxFuture<'a, Result<()>>;
+
+/// Drive every role of an async session concurrently on the current task
+///
+/// Fails with the first error a role reports; the other roles are dropped.
+pub async fn run_roles(roles: Vec<RoleFuture<'_>>) -> Result<()> {
+    try_join_all(roles).await.map(|_| ())
+}
+
+/// One role of a blocking session, see [`blocking_role`]
+///
+/// Called with a function receiving the role's outcome.
+pub type BlockingRole<'a> = Box<dyn FnOnce(&dyn Fn(Result<()>)) + Send + 'a>;
+
+/// Run `driver` for `role` over `transport` when the session starts
+///
+/// The outcome is reported before the endpoint is dropped, so a role that
+/// fails is reported before the peers that stop because it went away.
+pub fn blocking_role<'a, R, T, F>(
+    role: R,
+    roles: &[R],
+    transport: Result<T>,
+    driver: F,
+) -> BlockingRole<'a>
+where
+    R: Copy + Eq + Debug + Send + 'a,
+    T: Transport<R> + Send + 'a,
+    F: FnOnce(&mut BlockingEndpoint<R, T>) -> Result<()> + Send + 'a,
+{
+    let peers = roles.to_vec();
+    Box::new(move |report| match transport {
+        Ok(transport) => {
+            let mut endpoint = BlockingEndpoint::new(role, peers, transport);
+            report(driver(&mut endpoint));
+        }
+        Err(err) => report(Err(err)),
+    })
+}
+
+/// Run every role of a blocking session on its own thread
+///
+/// Fails with the first error a role reports. Panics in a role propagate.
+pub fn run_blocking_roles(roles: Vec<BlockingRole<'_>>) -> Result<()> {
+    let (outcomes, results) = channel();
+    std::thread::scope(|scope| {
+        for role in roles {
+            let outcomes = outcomes.clone();
+            scope.spawn(move || {
+                role(&|outcome| {
+                    let _ = outcomes.send(outcome);
+                });
+            });
+        }
+        drop(outcomes);
+        results.iter().find(Result::is_err).unwrap_or(Ok(()))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::blocking::ChannelTransport;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    enum Role {
+        Client,
+        Server,
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Request(u32);
+
+    #[derive(Debug, PartialEq)]
+    struct Response(u32);
+
+    #[test]
+    fn test_script_in_order() {
+        let mut script = MockScript::new("MockServer");
+        script.receive_where(|request: &Request| request.0 > 1);
+        script.choose("accept");
+        script.send(Response(7));
+
+        script.on_receive(&Request(2)).unwrap();
+        assert_eq!(script.on_choose().unwrap(), "accept");
+        assert_eq!(script.on_send::<Response>().unwrap(), Response(7));
+        script.finish().unwrap();
+    }
+
+    #[test]
+    fn test_script_mismatch() {
+        let mut script = MockScript::new("MockServer");
+        script.send(Response(7));
+        let err = script.on_receive(&Request(2)).unwrap_err().to_string();
+        assert!(err.contains("MockServer expected to send Response"));
+        assert!(err.contains("the protocol is to receive Request"));
+
+        let mut script = MockScript::new("MockServer");
+        script.receive_where(|request: &Request| request.0 > 1);
+        assert!(script.on_receive(&Request(0)).is_err());
+
+        let mut script = MockScript::new("MockServer");
+        script.choose("accept");
+        assert!(script
+            .finish()
+            .unwrap_err()
+            .to_string()
+            .contains("1 step(s) left"));
+    }
+
+    #[tokio::test]
+    async fn test_channel_handler_choose_reaches_every_peer() {
+        let mut mesh = ChannelHandler::mesh(&[Role::Client, Role::Server]);
+        let mut client = take_role(&mut mesh, Role::Client).unwrap();
+        let mut server = take_role(&mut mesh, Role::Server).unwrap();
+        assert!(take_role(&mut mesh, Role::Server).is_err());
+
+        client
+            .choose(&mut (), Role::Client, Label("accept"))
+            .await
+            .unwrap();
+        client.send(&mut (), Role::Server, &5u32).await.unwrap();
+        assert_eq!(
+            server
+                .offer(&mut (), Role::Client, &[Label("accept"), Label("reject")])
+                .await
+                .unwrap(),
+            Label("accept")
+        );
+        assert_eq!(server.recv::<u32>(&mut (), Role::Client).await.unwrap(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_channel_handler_passes_bytes_without_copying() {
+        let mut mesh = ChannelHandler::mesh(&[Role::Client, Role::Server]);
+        let mut client = take_role(&mut mesh, Role::Client).unwrap();
+        let mut server = take_role(&mut mesh, Role::Server).unwrap();
+
+        let payload = Bytes::from(vec![7u8; 1 << 20]);
+        client
+            .send_bytes(&mut (), Role::Server, payload.clone())
+            .await
+            .unwrap();
+        let received = server.recv_bytes(&mut (), Role::Client).await.unwrap();
+        assert_eq!(received.as_ptr(), payload.as_ptr());
+
+        // Either side may still use the encoded path
+        client
+            .send_bytes(&mut (), Role::Server, Bytes::from_static(b"abc"))
+            .await
+            .unwrap();
+        assert_eq!(
+            server.recv::<Vec<u8>>(&mut (), Role::Client).await.unwrap(),
+            b"abc"
+        );
+        client
+            .send(&mut (), Role::Server, &b"def".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(
+            server.recv_bytes(&mut (), Role::Client).await.unwrap(),
+            Bytes::from_static(b"def")
+        );
+    }
+
+    #[test]
+    fn test_blocking_roles_report_the_failing_role() {
+        let roles = [Role::Client, Role::Server];
+        let mut mesh = ChannelTransport::mesh(&roles);
+        let result = run_blocking_roles(vec![
+            blocking_role(
+                Role::Client,
+                &roles,
+                take_role(&mut mesh, Role::Client),
+                |endpoint| endpoint.recv::<u32>(Role::Server).map(drop),
+            ),
+            blocking_role(
+                Role::Server,
+                &roles,
+                take_role(&mut mesh, Role::Server),
+                |_| Err(ChoreographyError::ProtocolViolation("server failed".into())),
+            ),
+        ]);
+        assert!(matches!(
+            result,
+            Err(ChoreographyError::ProtocolViolation(_))
+        ));
+    }
+}
@@ -0,0 +1,398 @@
+// Heartbeat failure detection
+//
+// A `FailureDetector` wraps each peer's channel in a session that sends a
+// heartbeat every `interval` next to the protocol's own frames and watches
+// what arrives from the peer. A peer silent for longer than `timeout`, or
+// whose channel closes, is suspected: subscribers receive
+// `DetectorEvent::PeerSuspected`, sends to it fail and a pending receive
+// from it fails instead of waiting out the session's timeout. Both errors are
+// `ChoreographyError::PeerFailed`, so sends with `or on failure` recover. A
+// suspected peer that is heard again is cleared with
+// `DetectorEvent::PeerRecovered`.
+//
+// Every frame starts with a tag byte, 0 for protocol data and 1 for a
+// heartbeat, so both ends of a channel must use a detector. Heartbeats are
+// sent and received by tasks started with `runtime::spawn`, which end when
+// the session is dropped.
+
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
+use std::time::Duration;
+
+use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
+use futures::future::{self, BoxFuture, Either};
+use futures::lock::Mutex as AsyncMutex;
+use futures::{Sink, SinkExt, Stream, StreamExt};
+
+use crate::effects::handlers::rumpsteak::{RumpsteakSession, SessionTypeDynamic, SessionUpdate};
+use crate::effects::{ChoreographyError, Result, RoleId};
+
+const DATA: u8 = 0;
+const HEARTBEAT: u8 = 1;
+
+/// How often heartbeats are sent and how long a peer may stay silent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatConfig {
+    pub interval: Duration,
+    /// Silence after which a peer is suspected, normally a few intervals
+    pub timeout: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+/// Change in what the detector believes about a peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetectorEvent<R> {
+    PeerSuspected(R),
+    /// A suspected peer was heard from again
+    PeerRecovered(R),
+}
+
+/// Suspects peers that stop sending heartbeats
+#[derive(Clone)]
+pub struct FailureDetector<R> {
+    config: HeartbeatConfig,
+    state: Arc<Mutex<DetectorState<R>>>,
+}
+
+struct DetectorState<R> {
+    suspected: Vec<R>,
+    subscribers: Vec<UnboundedSender<DetectorEvent<R>>>,
+}
+
+impl<R: RoleId> FailureDetector<R> {
+    #[must_use]
+    pub fn new(config: HeartbeatConfig) -> Self {
+        Self {
+            config,
+            state: Arc::new(Mutex::new(DetectorState {
+                suspected: Vec::new(),
+                subscribers: Vec::new(),
+            })),
+        }
+    }
+
+    #[must_use]
+    pub fn config(&self) -> HeartbeatConfig {
+        self.config
+    }
+
+    /// Events from now on, for every peer of the detector
+    pub fn subscribe(&self) -> UnboundedReceiver<DetectorEvent<R>> {
+        let (sender, receiver) = unbounded();
+        lock(&self.state).subscribers.push(sender);
+        receiver
+    }
+
+    pub fn is_suspected(&self, peer: R) -> bool {
+        lock(&self.state).suspected.contains(&peer)
+    }
+
+    /// Peers currently suspected, in the order they were suspected
+    pub fn suspected(&self) -> Vec<R> {
+        lock(&self.state).suspected.clone()
+    }
+
+    /// Session with `peer` over a channel of frames, exchanging heartbeats
+    /// with the detector at the other end
+    ///
+    /// Register the result with `RumpsteakEndpoint::register_session`.
+    pub fn session<S, T>(&self, peer: R, sender: S, receiver: T) -> RumpsteakSession
+    where
+        S: Sink<Vec<u8>> + Unpin + Send + 'static,
+        S::Error: std::fmt::Display + Send + 'static,
+        T: Stream<Item = Vec<u8>> + Unpin + Send + 'static,
+    {
+        let sender = Arc::new(AsyncMutex::new(sender));
+        let (incoming, frames) = unbounded();
+        crate::runtime::spawn(send_heartbeats(
+            Arc::downgrade(&sender),
+            self.config.interval,
+        ));
+        crate::runtime::spawn(watch(self.clone(), peer, receiver, incoming));
+        RumpsteakSession::new(Box::new(HeartbeatSession {
+            peer,
+            detector: self.clone(),
+            sender,
+            frames,
+        }))
+    }
+
+    /// Record that `peer` was heard from
+    fn heard(&self, peer: R) {
+        let mut state = lock(&self.state);
+        if let Some(index) = state.suspected.iter().position(|r| *r == peer) {
+            state.suspected.remove(index);
+            state.publish(DetectorEvent::PeerRecovered(peer));
+        }
+    }
+
+    /// Suspect `peer`, returning whether it was not suspected already
+    fn suspect(&self, peer: R) -> bool {
+        let mut state = lock(&self.state);
+        if state.suspected.contains(&peer) {
+            return false;
+        }
+        tracing::warn!(?peer, "peer suspected by failure detector");
+        state.suspected.push(peer);
+        state.publish(DetectorEvent::PeerSuspected(peer));
+        true
+    }
+}
+
+impl<R: Copy> DetectorState<R> {
+    fn publish(&mut self, event: DetectorEvent<R>) {
+        self.subscribers
+            .retain(|subscriber| subscriber.unbounded_send(event).is_ok());
+    }
+}
+
+/// What the watcher of a channel passes on to its session
+enum Incoming {
+    Frame(Vec<u8>),
+    Suspected,
+    Closed,
+}
+
+async fn send_heartbeats<S>(sender: Weak<AsyncMutex<S>>, interval: Duration)
+where
+    S: Sink<Vec<u8>> + Unpin + Send,
+{
+    loop {
+        crate::runtime::sleep(interval).await;
+        let Some(sender) = sender.upgrade() else {
+            return;
+        };
+        let mut sender = sender.lock().await;
+        if sender.send(vec![HEARTBEAT]).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn watch<R, T>(
+    detector: FailureDetector<R>,
+    peer: R,
+    mut receiver: T,
+    incoming: UnboundedSender<Incoming>,
+) where
+    R: RoleId,
+    T: Stream<Item = Vec<u8>> + Unpin + Send,
+{
+    let timeout = detector.config.timeout;
+    while !incoming.is_closed() {
+        let silence = crate::runtime::sleep(timeout);
+        futures::pin_mut!(silence);
+        match future::select(receiver.next(), silence).await {
+            Either::Left((Some(frame), _)) => {
+                detector.heard(peer);
+                match frame.split_first() {
+                    Some((&DATA, payload)) => {
+                        let _ = incoming.unbounded_send(Incoming::Frame(payload.to_vec()));
+                    }
+                    Some((&HEARTBEAT, _)) => {}
+                    _ => tracing::debug!(?peer, "dropping untagged frame"),
+                }
+            }
+            Either::Left((None, _)) => {
+                detector.suspect(peer);
+                let _ = incoming.unbounded_send(Incoming::Closed);
+                return;
+            }
+            Either::Right(_) => {
+                if detector.suspect(peer) {
+                    let _ = incoming.unbounded_send(Incoming::Suspected);
+                }
+            }
+        }
+    }
+}
+
+struct HeartbeatSession<R, S> {
+    peer: R,
+    detector: FailureDetector<R>,
+    sender: Arc<AsyncMutex<S>>,
+    frames: UnboundedReceiver<Incoming>,
+}
+
+impl<R: RoleId, S> HeartbeatSession<R, S>
+where
+    S: Sink<Vec<u8>> + Unpin + Send,
+    S::Error: std::fmt::Display,
+{
+    fn suspected(&self) -> ChoreographyError {
+        ChoreographyError::PeerFailed(format!("{:?} suspected by the failure detector", self.peer))
+    }
+
+    async fn send_frame(&mut self, payload: Vec<u8>) -> Result<()> {
+        if self.detector.is_suspected(self.peer) {
+            return Err(self.suspected());
+        }
+        let mut frame = Vec::with_capacity(payload.len() + 1);
+        frame.push(DATA);
+        frame.extend(payload);
+        self.sender
+            .lock()
+            .await
+            .send(frame)
+            .await
+            .map_err(|e| ChoreographyError::Transport(format!("Channel send failed: {e}")))
+    }
+
+    async fn recv_frame(&mut self) -> Result<Vec<u8>> {
+        loop {
+            match self.frames.next().await {
+                Some(Incoming::Frame(frame)) => return Ok(frame),
+                // Heard again since, so keep waiting
+                Some(Incoming::Suspected) if !self.detector.is_suspected(self.peer) => {}
+                Some(Incoming::Suspected) => return Err(self.suspected()),
+                Some(Incoming::Closed) | None => {
+                    return Err(ChoreographyError::Transport(
+                        "Channel closed while receiving".into(),
+                    ))
+                }
+            }
+        }
+    }
+}
+
+impl<R: RoleId, S> SessionTypeDynamic for HeartbeatSession<R, S>
+where
+    S: Sink<Vec<u8>> + Unpin + Send + 'static,
+    S::Error: std::fmt::Display + Send,
+{
+    fn type_name(&self) -> &'static str {
+        "HeartbeatSession"
+    }
+
+    fn send(&mut self, data: Vec<u8>) -> BoxFuture<'_, Result<SessionUpdate<()>>> {
+        Box::pin(async move {
+            self.send_frame(data).await?;
+            Ok(SessionUpdate::new(()).with_description("Send"))
+        })
+    }
+
+    fn recv(&mut self) -> BoxFuture<'_, Result<SessionUpdate<Vec<u8>>>> {
+        Box::pin(async move {
+            let frame = self.recv_frame().await?;
+            Ok(SessionUpdate::new(frame).with_description("Recv"))
+        })
+    }
+
+    fn choose(&mut self, label: &str) -> BoxFuture<'_, Result<SessionUpdate<()>>> {
+        let label = label.to_string();
+        Box::pin(async move {
+            let frame = bincode::serialize(&label).map_err(|e| {
+                ChoreographyError::Transport(format!("Label serialization failed: {e}"))
+            })?;
+            self.send_frame(frame).await?;
+            Ok(SessionUpdate::new(()).with_description("Choose"))
+        })
+    }
+
+    fn offer(&mut self) -> BoxFuture<'_, Result<SessionUpdate<String>>> {
+        Box::pin(async move {
+            let frame = self.recv_frame().await?;
+            let label: String = bincode::deserialize(&frame).map_err(|e| {
+                ChoreographyError::Transport(format!("Label deserialization failed: {e}"))
+            })?;
+            Ok(SessionUpdate::new(label).with_description("Offer"))
+        })
+    }
+}
+
+fn lock<R>(state: &Mutex<DetectorState<R>>) -> MutexGuard<'_, DetectorState<R>> {
+    // The state stays consistent even if a subscriber panicked while holding it
+    state
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+#[cfg(all(test, feature = "tokio", not(target_arch = "wasm32")))]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    enum Role {
+        Client,
+        Server,
+    }
+
+    const FAST: HeartbeatConfig = HeartbeatConfig {
+        interval: Duration::from_millis(5),
+        timeout: Duration::from_millis(50),
+    };
+
+    /// Sessions of the Client and Server over in-memory channels
+    fn pair(
+        client: &FailureDetector<Role>,
+        server: &FailureDetector<Role>,
+    ) -> (RumpsteakSession, RumpsteakSession) {
+        let (to_server, from_client) = unbounded::<Vec<u8>>();
+        let (to_client, from_server) = unbounded::<Vec<u8>>();
+        (
+            client.session(Role::Server, to_server, from_server),
+            server.session(Role::Client, to_client, from_client),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_live_peers_exchange_messages() {
+        let client = FailureDetector::new(FAST);
+        let server = FailureDetector::new(FAST);
+        let (mut client_session, mut server_session) = pair(&client, &server);
+
+        // Heartbeats keep an idle peer from being suspected
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert!(client.suspected().is_empty());
+
+        client_session.send(b"ping".to_vec()).await.unwrap();
+        assert_eq!(server_session.recv().await.unwrap().output, b"ping");
+        server_session.choose("accept").await.unwrap();
+        assert_eq!(client_session.offer().await.unwrap().output, "accept");
+    }
+
+    #[tokio::test]
+    async fn test_silent_peer_is_suspected() {
+        let client = FailureDetector::new(FAST);
+        let mut events = client.subscribe();
+        let (to_server, _from_client) = unbounded::<Vec<u8>>();
+        // Keep the channel open but never send on it
+        let (_to_client, from_server) = unbounded::<Vec<u8>>();
+        let mut session = client.session(Role::Server, to_server, from_server);
+
+        let error = session.recv().await.unwrap_err();
+        assert!(matches!(error, ChoreographyError::PeerFailed(_)));
+        assert_eq!(
+            events.next().await,
+            Some(DetectorEvent::PeerSuspected(Role::Server))
+        );
+        assert_eq!(client.suspected(), vec![Role::Server]);
+        assert!(session
+            .send(b"late".to_vec())
+            .await
+            .unwrap_err()
+            .is_peer_failure());
+    }
+
+    #[tokio::test]
+    async fn test_closed_channel_is_suspected() {
+        let client = FailureDetector::new(FAST);
+        let server = FailureDetector::new(FAST);
+        let mut events = client.subscribe();
+        let (mut client_session, server_session) = pair(&client, &server);
+
+        drop(server_session);
+        assert_eq!(
+            events.next().await,
+            Some(DetectorEvent::PeerSuspected(Role::Server))
+        );
+        assert!(client_session.recv().await.is_err());
+    }
+}
@@ -0,0 +1,558 @@
+// External HTTP roles
+//
+// A role declared `Api external http` is a service outside the session, such
+// as a third-party API, that speaks plain HTTP rather than a session
+// transport. The roles that talk to it wrap their handler in an
+// `HttpRoleHandler`, which maps the session operations involving that role
+// onto HTTP and passes every other one to the wrapped handler:
+//
+//     send to Api      a request on the route of the message, with the
+//                      message as its JSON body
+//     recv from Api    the JSON body of the last response, or else the next
+//                      webhook the service posts back
+//     offer from Api   the same, holding the label as a JSON string
+//
+// A message is routed by `POST /<message in snake_case>` unless its
+// statement carries a `[@route = "GET /status"]` annotation. The macro
+// generates the `RouteTable` of every external role from the choreography.
+//
+// Key pieces:
+// - RouteTable: message names and their routes.
+// - HttpRoleHandler: the adapter, opening one connection per request through
+//   `DefaultRuntime::connect_tcp`.
+// - WebhookSink: answers requests the service makes to the local roles, on a
+//   `futures` byte stream accepted by the application's listener.
+//
+// Requests are HTTP/1.1 with `Connection: close`. Chunked bodies and TLS are
+// not supported.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::channel::mpsc;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use futures::StreamExt;
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
+
+use crate::compiler::handler_codegen::snake_case;
+use crate::effects::middleware::{message_label, recv_as, send_as};
+use crate::effects::{ChoreoHandler, ChoreographyError, Label, Result};
+use crate::runtime::provider::{DefaultRuntime, RuntimeProvider};
+
+/// Default upper bound for a request or response body (16 MiB).
+pub const DEFAULT_MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// Longest request or status line and headers accepted.
+const MAX_HEAD_SIZE: usize = 8 * 1024;
+
+/// Errors raised while exchanging messages with an external role.
+#[derive(Debug, Error)]
+pub enum HttpError {
+    #[error("HTTP I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Malformed HTTP message: {0}")]
+    Malformed(String),
+
+    #[error("HTTP {status} {reason}")]
+    Status { status: u16, reason: String },
+
+    #[error("No message is routed to {0}")]
+    UnknownRoute(HttpRoute),
+
+    #[error("Body of {size} bytes exceeds maximum of {max} bytes")]
+    BodyTooLarge { size: usize, max: usize },
+
+    #[error("HTTP role handler closed")]
+    Closed,
+}
+
+impl From<HttpError> for ChoreographyError {
+    fn from(err: HttpError) -> Self {
+        ChoreographyError::Transport(err.to_string())
+    }
+}
+
+/// Method and path a message travels on
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HttpRoute {
+    pub method: String,
+    pub path: String,
+}
+
+impl HttpRoute {
+    #[must_use]
+    pub fn new(method: impl Into<String>, path: impl Into<String>) -> Self {
+        Self {
+            method: method.into(),
+            path: path.into(),
+        }
+    }
+
+    /// Route of a message without a `@route` annotation:
+    /// `POST /<message in snake_case>`
+    #[must_use]
+    pub fn for_message(message: &str) -> Self {
+        Self::new("POST", format!("/{}", snake_case(message)))
+    }
+}
+
+/// Error for a `@route` value that is not `[METHOD] /path`
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("invalid route `{0}`, expected `/path` or `METHOD /path`")]
+pub struct ParseRouteError(pub String);
+
+impl FromStr for HttpRoute {
+    type Err = ParseRouteError;
+
+    /// Parse `/path`, routed by POST, or `METHOD /path`
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || ParseRouteError(s.to_string());
+        let (method, path) = match s.trim().split_once(char::is_whitespace) {
+            Some((method, path)) => (method, path.trim()),
+            None => ("POST", s.trim()),
+        };
+        if method.is_empty() || !method.chars().all(|c| c.is_ascii_uppercase()) {
+            return Err(invalid());
+        }
+        if !path.starts_with('/') || path.contains(char::is_whitespace) {
+            return Err(invalid());
+        }
+        Ok(Self::new(method, path))
+    }
+}
+
+impl fmt::Display for HttpRoute {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.method, self.path)
+    }
+}
+
+/// Routes of the messages exchanged with an external role
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RouteTable {
+    routes: HashMap<String, HttpRoute>,
+}
+
+impl RouteTable {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Route `message` by `route`
+    #[must_use]
+    pub fn with_route(mut self, message: impl Into<String>, route: HttpRoute) -> Self {
+        self.routes.insert(message.into(), route);
+        self
+    }
+
+    /// Route of `message`, the default one if the table has none
+    #[must_use]
+    pub fn route(&self, message: &str) -> HttpRoute {
+        self.routes
+            .get(message)
+            .cloned()
+            .unwrap_or_else(|| HttpRoute::for_message(message))
+    }
+
+    /// Message routed by `route`, if any
+    #[must_use]
+    pub fn message(&self, route: &HttpRoute) -> Option<&str> {
+        self.routes
+            .iter()
+            .find(|(_, r)| *r == route)
+            .map(|(message, _)| message.as_str())
+    }
+}
+
+/// Handler of a role that talks to an external HTTP role
+///
+/// Operations on `role` go over HTTP, all others to the wrapped handler.
+pub struct HttpRoleHandler<H: ChoreoHandler> {
+    inner: H,
+    role: H::Role,
+    address: SocketAddr,
+    host: String,
+    routes: RouteTable,
+    max_body_size: usize,
+    /// Bodies of responses not received yet
+    responses: VecDeque<Vec<u8>>,
+    webhooks: mpsc::UnboundedReceiver<Vec<u8>>,
+    sink: WebhookSink,
+}
+
+impl<H: ChoreoHandler> HttpRoleHandler<H> {
+    /// Reach the external `role` at `address`, routing its messages by
+    /// `routes`
+    pub fn new(inner: H, role: H::Role, address: SocketAddr, routes: RouteTable) -> Self {
+        let (sender, webhooks) = mpsc::unbounded();
+        Self {
+            inner,
+            role,
+            address,
+            host: address.to_string(),
+            sink: WebhookSink {
+                sender,
+                routes: routes.clone(),
+                max_body_size: DEFAULT_MAX_BODY_SIZE,
+            },
+            routes,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            responses: VecDeque::new(),
+            webhooks,
+        }
+    }
+
+    /// `Host` header of requests, the address by default
+    #[must_use]
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.host = host.into();
+        self
+    }
+
+    /// Largest response or webhook body accepted
+    #[must_use]
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self.sink.max_body_size = max_body_size;
+        self
+    }
+
+    /// Sink for the webhooks of the external role, to serve from the
+    /// application's listener
+    #[must_use]
+    pub fn webhook_sink(&self) -> WebhookSink {
+        self.sink.clone()
+    }
+
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut H {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+
+    async fn send_step<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        label: Option<&str>,
+        msg: &M,
+    ) -> Result<()> {
+        if to != self.role {
+            return send_as(&mut self.inner, ep, to, label, msg).await;
+        }
+        let body =
+            serde_json::to_vec(msg).map_err(|e| ChoreographyError::Serialization(e.to_string()))?;
+        let route = self.routes.route(label.unwrap_or(message_label::<M>()));
+        self.request(&route, &body).await?;
+        Ok(())
+    }
+
+    async fn recv_step<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        from: H::Role,
+        label: Option<&str>,
+    ) -> Result<M> {
+        if from != self.role {
+            return recv_as(&mut self.inner, ep, from, label).await;
+        }
+        let body = self.next_body().await?;
+        serde_json::from_slice(&body).map_err(|e| ChoreographyError::Serialization(e.to_string()))
+    }
+
+    /// Send `body` on `route`, keeping a non-empty response body for the
+    /// next receive
+    async fn request(
+        &mut self,
+        route: &HttpRoute,
+        body: &[u8],
+    ) -> std::result::Result<(), HttpError> {
+        let mut io = DefaultRuntime::connect_tcp(self.address).await?;
+        let head = format!(
+            "{} {} HTTP/1.1\r\n\
+             Host: {}\r\n\
+             Content-Type: application/json\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n",
+            route.method,
+            route.path,
+            self.host,
+            body.len()
+        );
+        io.write_all(head.as_bytes()).await?;
+        io.write_all(body).await?;
+        io.flush().await?;
+
+        let head = read_head(&mut io).await?;
+        let mut lines = head.lines();
+        let status_line = lines.next().unwrap_or_default();
+        let mut parts = status_line.splitn(3, ' ');
+        let status = parts
+            .nth(1)
+            .and_then(|s| s.parse::<u16>().ok())
+            .ok_or_else(|| HttpError::Malformed(format!("status line '{status_line}'")))?;
+        let reason = parts.next().unwrap_or_default().to_string();
+        let headers = parse_headers(lines);
+        if !(200..300).contains(&status) {
+            return Err(HttpError::Status { status, reason });
+        }
+        let response = match content_length(&headers)? {
+            Some(length) => read_body(&mut io, length, self.max_body_size).await?,
+            None => read_to_close(&mut io, self.max_body_size).await?,
+        };
+        tracing::debug!(%route, status, "external role answered");
+        if !response.is_empty() {
+            self.responses.push_back(response);
+        }
+        Ok(())
+    }
+
+    /// Next response body, or else the next webhook
+    async fn next_body(&mut self) -> std::result::Result<Vec<u8>, HttpError> {
+        if let Some(body) = self.responses.pop_front() {
+            return Ok(body);
+        }
+        self.webhooks.next().await.ok_or(HttpError::Closed)
+    }
+}
+
+#[async_trait]
+impl<H: ChoreoHandler> ChoreoHandler for HttpRoleHandler<H> {
+    type Role = H::Role;
+    type Endpoint = H::Endpoint;
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, None, msg).await
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+    ) -> Result<M> {
+        self.recv_step(ep, from, None).await
+    }
+
+    async fn send_labelled<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        label: &str,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, Some(label), msg).await
+    }
+
+    async fn recv_labelled<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        label: &str,
+    ) -> Result<M> {
+        self.recv_step(ep, from, Some(label)).await
+    }
+
+    /// The external role learns of a choice from the requests that follow
+    /// it, so only the wrapped handler is told.
+    async fn choose(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        who: Self::Role,
+        label: Label,
+    ) -> Result<()> {
+        self.inner.choose(ep, who, label).await
+    }
+
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        if from != self.role {
+            return self.inner.offer(ep, from, labels).await;
+        }
+        let body = self.next_body().await?;
+        let label: String = serde_json::from_slice(&body)
+            .map_err(|e| ChoreographyError::Serialization(e.to_string()))?;
+        Label::resolve(&label, labels)
+    }
+
+    async fn with_timeout<F, T>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        at: Self::Role,
+        dur: Duration,
+        body: F,
+    ) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>> + Send,
+    {
+        self.inner.with_timeout(ep, at, dur, body).await
+    }
+
+    async fn recv_any<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: &[Self::Role],
+    ) -> Result<(Self::Role, M)> {
+        if from.contains(&self.role) {
+            let role = self.role;
+            return Ok((role, self.recv(ep, role).await?));
+        }
+        self.inner.recv_any(ep, from).await
+    }
+}
+
+/// Receives the webhooks of an external role for an [`HttpRoleHandler`]
+#[derive(Debug, Clone)]
+pub struct WebhookSink {
+    sender: mpsc::UnboundedSender<Vec<u8>>,
+    routes: RouteTable,
+    max_body_size: usize,
+}
+
+impl WebhookSink {
+    /// Answer one webhook request made over `io`, returning the name of the
+    /// message it carried
+    ///
+    /// Requests on a route of no message are answered `404 Not Found`.
+    pub async fn accept<IO>(&self, mut io: IO) -> std::result::Result<String, HttpError>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let (route, length) = read_request_head(&mut io).await?;
+        let Some(message) = self.routes.message(&route) else {
+            respond(&mut io, "404 Not Found").await?;
+            return Err(HttpError::UnknownRoute(route));
+        };
+        let body = read_body(&mut io, length, self.max_body_size).await?;
+        if self.sender.unbounded_send(body).is_err() {
+            respond(&mut io, "503 Service Unavailable").await?;
+            return Err(HttpError::Closed);
+        }
+        respond(&mut io, "202 Accepted").await?;
+        tracing::debug!(%route, message, "webhook received");
+        Ok(message.to_string())
+    }
+}
+
+/// Route of the request whose head is read from `io`, and the length of
+/// its body
+pub(crate) async fn read_request_head<IO: AsyncRead + Unpin>(
+    io: &mut IO,
+) -> std::result::Result<(HttpRoute, usize), HttpError> {
+    let head = read_head(io).await?;
+    let mut lines = head.lines();
+    let request_line = lines.next().unwrap_or_default();
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(HttpError::Malformed(format!(
+            "request line '{request_line}'"
+        )));
+    };
+    let route = HttpRoute::new(method, path);
+    let length = content_length(&parse_headers(lines))?.unwrap_or(0);
+    Ok((route, length))
+}
+
+pub(crate) async fn respond<IO: AsyncWrite + Unpin>(
+    io: &mut IO,
+    status: &str,
+) -> std::result::Result<(), HttpError> {
+    let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    io.write_all(response.as_bytes()).await?;
+    io.flush().await?;
+    Ok(())
+}
+
+async fn read_head<IO: AsyncRead + Unpin>(io: &mut IO) -> std::result::Result<String, HttpError> {
+    let mut head = Vec::new();
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= MAX_HEAD_SIZE {
+            return Err(HttpError::Malformed(format!(
+                "head exceeds {MAX_HEAD_SIZE} bytes"
+            )));
+        }
+        let mut byte = [0u8; 1];
+        io.read_exact(&mut byte).await?;
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).map_err(|_| HttpError::Malformed("head is not UTF-8".to_string()))
+}
+
+fn parse_headers<'a>(lines: impl Iterator<Item = &'a str>) -> Vec<(&'a str, &'a str)> {
+    lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim(), value.trim()))
+        .collect()
+}
+
+/// `Content-Length` of a message, rejecting chunked bodies
+fn content_length(headers: &[(&str, &str)]) -> std::result::Result<Option<usize>, HttpError> {
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|&(_, value)| value)
+    };
+    if header("Transfer-Encoding").is_some_and(|v| v.eq_ignore_ascii_case("chunked")) {
+        return Err(HttpError::Malformed(
+            "chunked bodies are not supported".to_string(),
+        ));
+    }
+    header("Content-Length")
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| HttpError::Malformed(format!("Content-Length '{value}'")))
+        })
+        .transpose()
+}
+
+pub(crate) async fn read_body<IO: AsyncRead + Unpin>(
+    io: &mut IO,
+    length: usize,
+    max: usize,
+) -> std::result::Result<Vec<u8>, HttpError> {
+    if length > max {
+        return Err(HttpError::BodyTooLarge { size: length, max });
+    }
+    let mut body = vec![0u8; length];
+    io.read_exact(&mut body).await?;
+    Ok(body)
+}
+
+async fn read_to_close<IO: AsyncRead + Unpin>(
+    io: &mut IO,
+    max: usize,
+) -> std::result::Result<Vec<u8>, HttpError> {
+    let mut body = Vec::new();
+    let mut limited = io.take(max as u64 + 1);
+    limited.read_to_end(&mut body).await?;
+    if body.len() > max {
+        return Err(HttpError::BodyTooLarge {
+            size: body.len(),
+            max,
+        });
+    }
+    Ok(body)
+}
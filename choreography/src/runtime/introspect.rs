@@ -0,0 +1,295 @@
+// Session introspection
+//
+// A `SessionProbe` follows a running role through its local type, so code
+// outside the session can ask where it is: the state it waits in, the
+// message or branch labels it admits next, the peer it waits on, and how
+// long the session has run. Generated endpoints hold one in their `session`
+// field and `run_<role>_observed` feeds it; it is a `SessionMetrics`, so any
+// handler wrapped in `Instrumented` can feed it too.
+//
+// The probe is shared: clones see the same session. Dashboards poll
+// `state()` and tests await `wait_for_state()` from another task while the
+// role runs.
+
+use serde::Serialize;
+use std::fmt;
+use std::future::poll_fn;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Poll, Waker};
+use std::time::Duration;
+
+use crate::effects::{ChoreographyError, SessionMetrics};
+use crate::runtime::monitor::{ActionKind, ConformanceMonitor, MonitorSpec, ObservedEvent};
+use crate::runtime::Instant;
+
+/// Name of the state of a session whose local type has nothing left to do
+pub const END: &str = "end";
+
+/// Where a running session is in its local type
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SessionState {
+    pub role: String,
+    /// The actions the role waits to take, as in `receive from Server`, or
+    /// [`END`]
+    pub state: String,
+    /// Message types and branch labels admitted next
+    pub expected: Vec<String>,
+    /// Peer of the next action, if every action admitted next is with it
+    pub peer: Option<String>,
+    /// Time since the session started
+    pub elapsed: Duration,
+    /// Actions taken so far
+    pub steps: usize,
+    /// Why the last step failed or did not follow the local type, if it did
+    pub error: Option<String>,
+}
+
+impl SessionState {
+    /// Whether the local type has nothing left to do
+    #[must_use]
+    pub fn is_end(&self) -> bool {
+        self.state == END
+    }
+
+    /// Whether `label` is admitted next
+    #[must_use]
+    pub fn expects(&self, label: &str) -> bool {
+        self.expected.iter().any(|expected| expected == label)
+    }
+}
+
+impl fmt::Display for SessionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} at `{}` after {} steps in {:?}",
+            self.role, self.state, self.steps, self.elapsed
+        )?;
+        if !self.expected.is_empty() {
+            write!(f, ", expecting one of [{}]", self.expected.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+struct Progress {
+    monitor: ConformanceMonitor,
+    started: Instant,
+    error: Option<String>,
+}
+
+#[derive(Default)]
+struct ProbeState {
+    progress: Option<Progress>,
+    /// Tasks in `wait_for_state`, woken on every change
+    waiters: Vec<Waker>,
+}
+
+/// Progress of a session through its local type, shared by its clones
+#[derive(Clone, Default)]
+pub struct SessionProbe {
+    state: Arc<Mutex<ProbeState>>,
+}
+
+impl fmt::Debug for SessionProbe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("SessionProbe").field(&self.state()).finish()
+    }
+}
+
+impl SessionProbe {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Follow a new session of the local type `spec`
+    pub fn start(&self, spec: &'static MonitorSpec) {
+        self.update(|state| {
+            state.progress = Some(Progress {
+                monitor: ConformanceMonitor::new(spec),
+                started: Instant::now(),
+                error: None,
+            });
+        });
+    }
+
+    /// Where the session is, `None` before it started
+    #[must_use]
+    pub fn state(&self) -> Option<SessionState> {
+        self.lock().progress.as_ref().map(Progress::state)
+    }
+
+    /// Wait until the session is in a state `predicate` holds for, and
+    /// return that state
+    ///
+    /// Meant for tests that drive a role from another task. It does not time
+    /// out, so bound it with `runtime::timeout` where the state may never
+    /// come.
+    pub async fn wait_for_state(&self, predicate: impl Fn(&SessionState) -> bool) -> SessionState {
+        poll_fn(|cx| {
+            let mut state = self.lock();
+            match state.progress.as_ref().map(Progress::state) {
+                Some(session) if predicate(&session) => Poll::Ready(session),
+                _ => {
+                    state.waiters.push(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+        .await
+    }
+
+    /// Record `event` if the session started
+    fn observe(&self, event: ObservedEvent) {
+        self.update(|state| {
+            if let Some(progress) = &mut state.progress {
+                progress.error = progress
+                    .monitor
+                    .observe(event)
+                    .err()
+                    .map(|violation| violation.to_string());
+            }
+        });
+    }
+
+    fn update(&self, f: impl FnOnce(&mut ProbeState)) {
+        let waiters = {
+            let mut state = self.lock();
+            f(&mut state);
+            std::mem::take(&mut state.waiters)
+        };
+        waiters.into_iter().for_each(Waker::wake);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ProbeState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Progress {
+    fn state(&self) -> SessionState {
+        let mut actions: Vec<String> = Vec::new();
+        let mut expected: Vec<String> = Vec::new();
+        let mut peers: Vec<&str> = Vec::new();
+        for transition in self.monitor.next() {
+            let preposition = match transition.kind {
+                ActionKind::Send | ActionKind::Select => "to",
+                ActionKind::Receive | ActionKind::Branch => "from",
+            };
+            let action = format!("{} {preposition} {}", transition.kind, transition.peer);
+            if !actions.contains(&action) {
+                actions.push(action);
+            }
+            if !expected.iter().any(|label| label == transition.label) {
+                expected.push(transition.label.to_string());
+            }
+            if !peers.contains(&transition.peer) {
+                peers.push(transition.peer);
+            }
+        }
+        SessionState {
+            role: self.monitor.spec().role.to_string(),
+            state: if actions.is_empty() {
+                END.to_string()
+            } else {
+                actions.join(" or ")
+            },
+            expected,
+            peer: match peers[..] {
+                [peer] => Some(peer.to_string()),
+                _ => None,
+            },
+            elapsed: self.started.elapsed(),
+            steps: self.monitor.steps(),
+            error: self.error.clone(),
+        }
+    }
+}
+
+impl SessionMetrics for SessionProbe {
+    fn message_sent(&self, _role: &str, to: &str, label: &str) {
+        self.observe(ObservedEvent::sent(to, label));
+    }
+
+    fn message_received(&self, _role: &str, from: &str, label: &str) {
+        self.observe(ObservedEvent::received(from, label));
+    }
+
+    fn branch_selected(&self, _role: &str, to: &str, branch: &str) {
+        self.observe(ObservedEvent::selected(to, branch));
+    }
+
+    fn branch_offered(&self, _role: &str, from: &str, branch: &str) {
+        self.observe(ObservedEvent::branched(from, branch));
+    }
+
+    fn step_failed(&self, _role: &str, _step: ActionKind, error: &ChoreographyError) {
+        self.update(|state| {
+            if let Some(progress) = &mut state.progress {
+                progress.error = Some(error.to_string());
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::monitor::Transition;
+
+    // Client: Server!Request . Server?Response
+    static CLIENT: MonitorSpec = MonitorSpec {
+        role: "Client",
+        initial: 0,
+        accepting: 2,
+        transitions: &[
+            Transition {
+                from: 0,
+                kind: ActionKind::Send,
+                peer: "Server",
+                label: "Request",
+                to: 1,
+            },
+            Transition {
+                from: 1,
+                kind: ActionKind::Receive,
+                peer: "Server",
+                label: "Response",
+                to: 2,
+            },
+        ],
+        epsilons: &[],
+    };
+
+    #[tokio::test]
+    async fn test_probe_follows_the_local_type() {
+        let probe = SessionProbe::new();
+        assert_eq!(probe.state(), None);
+        probe.start(&CLIENT);
+        let state = probe.state().unwrap();
+        assert_eq!(state.state, "send to Server");
+        assert_eq!(state.expected, ["Request"]);
+        assert_eq!(state.peer.as_deref(), Some("Server"));
+
+        let waiting = {
+            let probe = probe.clone();
+            tokio::spawn(async move { probe.wait_for_state(|s| s.expects("Response")).await })
+        };
+        probe.message_sent("Client", "Server", "Request");
+        let state = waiting.await.unwrap();
+        assert_eq!(state.state, "receive from Server");
+        assert_eq!(state.steps, 1);
+
+        probe.message_sent("Client", "Server", "Request");
+        assert!(probe.state().unwrap().error.unwrap().contains("unexpected"));
+        probe.message_received("Client", "Server", "Response");
+        let state = probe.state().unwrap();
+        assert!(state.is_end());
+        assert_eq!(state.error, None);
+        assert_eq!(
+            state.to_string().split(" in ").next(),
+            Some("Client at `end` after 2 steps")
+        );
+    }
+}
@@ -0,0 +1,802 @@
+// Session event journal
+//
+// Append-only audit log for protocol steps annotated with
+// `[@journal_facts = "..."]`. Every record carries the SHA-256 hash of its
+// predecessor, so any edit, deletion, or reordering of stored records breaks
+// the chain and is caught by `verify_chain`.
+//
+// Records are written through a `JournalSink`. `MemorySink` keeps them in
+// memory for tests, `FileSink` appends JSON lines to a file, and `SqliteSink`
+// (feature `sqlite`) stores them in a table. Custom sinks implement the trait
+// directly. A sink that already holds records reports its last one through
+// `head`, and the journal continues the chain from there.
+//
+// A step annotated with `[@retain = "30d"]` as well also logs the message it
+// sends, to be kept for that long. The chain covers the SHA-256 digest of
+// the payload rather than the payload itself, so `Journal::redact_expired`
+// can erase payloads past their retention without breaking it. Statements
+// carrying personal data, marked `@personal_data` or with a `confidential`
+// payload field, must have a retention period to be journaled.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use std::time::UNIX_EPOCH;
+use thiserror::Error;
+
+use crate::effects::ChoreographyError;
+use crate::runtime::monitor::ActionKind;
+
+/// Hash used as `prev_hash` of the first record
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Annotation giving how long a journaled step keeps its payload, as a
+/// number of days, hours, minutes or seconds: `30d`, `12h`, `15m`, `90s`
+pub const RETAIN: &str = "retain";
+
+/// Annotation marking a statement whose message carries personal data
+pub const PERSONAL_DATA: &str = "personal_data";
+
+/// Parse the value of a [`RETAIN`] annotation
+#[must_use]
+pub fn parse_retention(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let unit = value.chars().last()?;
+    let count: u64 = value[..value.len() - unit.len_utf8()].trim().parse().ok()?;
+    let seconds = match unit {
+        'd' => count.checked_mul(86_400)?,
+        'h' => count.checked_mul(3_600)?,
+        'm' => count.checked_mul(60)?,
+        's' => count,
+        _ => return None,
+    };
+    Some(Duration::from_secs(seconds))
+}
+
+/// Errors raised by the journal and its sinks
+#[derive(Debug, Error)]
+pub enum JournalError {
+    #[error("journal I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("journal encoding error: {0}")]
+    Encoding(String),
+
+    #[error("journal sink error: {0}")]
+    Sink(String),
+
+    #[error("journal chain broken at record {seq}: {reason}")]
+    ChainBroken { seq: u64, reason: String },
+}
+
+impl From<JournalError> for ChoreographyError {
+    fn from(err: JournalError) -> Self {
+        ChoreographyError::Journal(err.to_string())
+    }
+}
+
+/// One audited protocol step
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JournalRecord {
+    pub seq: u64,
+    /// Milliseconds since the Unix epoch
+    pub timestamp_ms: u64,
+    pub session_id: String,
+    pub role: String,
+    /// `send`, `receive`, `select`, or `branch`
+    pub action: String,
+    pub peer: String,
+    pub label: String,
+    /// Value of the `journal_facts` annotation
+    pub facts: String,
+    /// Hex SHA-256 of the previous record
+    pub prev_hash: String,
+    /// Hex SHA-256 of this record
+    pub hash: String,
+    /// Payload of a step annotated with `retain`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retained: Option<RetainedPayload>,
+}
+
+/// Message logged by a journaled step until its retention period ends
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetainedPayload {
+    /// JSON of the message, `None` once redacted
+    pub payload: Option<String>,
+    /// Hex SHA-256 of the payload
+    pub digest: String,
+    /// Milliseconds since the Unix epoch after which the payload is redacted
+    pub expires_at_ms: u64,
+}
+
+impl RetainedPayload {
+    fn new(payload: String, expires_at_ms: u64) -> Self {
+        Self {
+            digest: hex::encode(Sha256::digest(payload.as_bytes())),
+            payload: Some(payload),
+            expires_at_ms,
+        }
+    }
+
+    /// Whether the payload is held and due for redaction at `now_ms`
+    #[must_use]
+    pub fn is_expired(&self, now_ms: u64) -> bool {
+        self.payload.is_some() && self.expires_at_ms <= now_ms
+    }
+}
+
+impl JournalRecord {
+    /// Hash over all fields except `hash` itself
+    #[must_use]
+    pub fn compute_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.prev_hash.as_bytes());
+        hasher.update(self.seq.to_be_bytes());
+        hasher.update(self.timestamp_ms.to_be_bytes());
+        for field in [
+            &self.session_id,
+            &self.role,
+            &self.action,
+            &self.peer,
+            &self.label,
+            &self.facts,
+        ] {
+            hasher.update((field.len() as u64).to_be_bytes());
+            hasher.update(field.as_bytes());
+        }
+        // Records without a payload hash as they did before retention
+        if let Some(retained) = &self.retained {
+            hasher.update(retained.digest.as_bytes());
+            hasher.update(retained.expires_at_ms.to_be_bytes());
+        }
+        hex::encode(hasher.finalize())
+    }
+
+    /// Erase the payload if its retention period has ended at `now_ms`,
+    /// returning whether it was erased
+    pub fn redact(&mut self, now_ms: u64) -> bool {
+        match &mut self.retained {
+            Some(retained) if retained.is_expired(now_ms) => {
+                retained.payload = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for JournalRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{} {} {} {} {} ({}): {}",
+            self.seq, self.session_id, self.role, self.action, self.label, self.peer, self.facts
+        )
+    }
+}
+
+/// Check that `records` form an unbroken chain starting at the genesis hash
+pub fn verify_chain<'a>(
+    records: impl IntoIterator<Item = &'a JournalRecord>,
+) -> Result<(), JournalError> {
+    let mut prev_hash = GENESIS_HASH.to_string();
+    for (expected_seq, record) in records.into_iter().enumerate() {
+        let broken = |reason: &str| JournalError::ChainBroken {
+            seq: record.seq,
+            reason: reason.to_string(),
+        };
+        if record.seq != expected_seq as u64 {
+            return Err(broken("sequence gap"));
+        }
+        if record.prev_hash != prev_hash {
+            return Err(broken("previous hash mismatch"));
+        }
+        if record.hash != record.compute_hash() {
+            return Err(broken("record hash mismatch"));
+        }
+        if let Some(RetainedPayload {
+            payload: Some(payload),
+            digest,
+            ..
+        }) = &record.retained
+        {
+            if hex::encode(Sha256::digest(payload.as_bytes())) != *digest {
+                return Err(broken("payload digest mismatch"));
+            }
+        }
+        prev_hash.clone_from(&record.hash);
+    }
+    Ok(())
+}
+
+/// Storage backend for journal records
+pub trait JournalSink: Send {
+    /// Durably append one record
+    fn append(&mut self, record: &JournalRecord) -> Result<(), JournalError>;
+
+    /// Last stored record, used to continue the chain
+    fn head(&mut self) -> Result<Option<JournalRecord>, JournalError> {
+        Ok(None)
+    }
+
+    /// Erase the payloads whose retention period has ended at `now_ms`,
+    /// returning how many were erased
+    fn redact(&mut self, _now_ms: u64) -> Result<usize, JournalError> {
+        Err(JournalError::Sink(
+            "this sink cannot redact payloads".to_string(),
+        ))
+    }
+}
+
+/// In-memory sink; clones share the same storage
+#[derive(Debug, Clone, Default)]
+pub struct MemorySink {
+    records: Arc<Mutex<Vec<JournalRecord>>>,
+}
+
+impl MemorySink {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn records(&self) -> Vec<JournalRecord> {
+        self.records
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl JournalSink for MemorySink {
+    fn append(&mut self, record: &JournalRecord) -> Result<(), JournalError> {
+        self.records
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .push(record.clone());
+        Ok(())
+    }
+
+    fn head(&mut self) -> Result<Option<JournalRecord>, JournalError> {
+        Ok(self.records().last().cloned())
+    }
+
+    fn redact(&mut self, now_ms: u64) -> Result<usize, JournalError> {
+        let mut records = self
+            .records
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        Ok(records
+            .iter_mut()
+            .map(|record| record.redact(now_ms))
+            .filter(|redacted| *redacted)
+            .count())
+    }
+}
+
+/// Sink appending one JSON record per line to a file
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Debug)]
+pub struct FileSink {
+    path: std::path::PathBuf,
+    file: std::fs::File,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FileSink {
+    /// Open `path` for appending, creating it if needed
+    pub fn open(path: impl Into<std::path::PathBuf>) -> Result<Self, JournalError> {
+        let path = path.into();
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        Ok(Self { path, file })
+    }
+
+    /// Read all records stored at `path`
+    pub fn read(path: impl AsRef<std::path::Path>) -> Result<Vec<JournalRecord>, JournalError> {
+        let content = std::fs::read_to_string(path)?;
+        content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line).map_err(|e| JournalError::Encoding(e.to_string()))
+            })
+            .collect()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl JournalSink for FileSink {
+    fn append(&mut self, record: &JournalRecord) -> Result<(), JournalError> {
+        use std::io::Write;
+
+        let mut line =
+            serde_json::to_vec(record).map_err(|e| JournalError::Encoding(e.to_string()))?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+
+    fn head(&mut self) -> Result<Option<JournalRecord>, JournalError> {
+        Ok(Self::read(&self.path)?.pop())
+    }
+
+    /// Rewrites the file through a temporary one next to it, so a crash
+    /// leaves either the old or the new file in place
+    fn redact(&mut self, now_ms: u64) -> Result<usize, JournalError> {
+        use std::io::Write;
+
+        let mut records = Self::read(&self.path)?;
+        let redacted = records
+            .iter_mut()
+            .map(|record| record.redact(now_ms))
+            .filter(|redacted| *redacted)
+            .count();
+        if redacted == 0 {
+            return Ok(0);
+        }
+
+        let mut content = Vec::new();
+        for record in &records {
+            serde_json::to_writer(&mut content, record)
+                .map_err(|e| JournalError::Encoding(e.to_string()))?;
+            content.push(b'\n');
+        }
+        let tmp = self.path.with_extension("redact");
+        let mut file = std::fs::File::create(&tmp)?;
+        file.write_all(&content)?;
+        file.sync_data()?;
+        std::fs::rename(&tmp, &self.path)?;
+        self.file = std::fs::OpenOptions::new().append(true).open(&self.path)?;
+        Ok(redacted)
+    }
+}
+
+/// Sink storing records in an SQLite table
+#[cfg(feature = "sqlite")]
+pub struct SqliteSink {
+    conn: rusqlite::Connection,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteSink {
+    /// Use `conn`, creating the `journal` table if it does not exist
+    pub fn new(conn: rusqlite::Connection) -> Result<Self, JournalError> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS journal (
+                seq INTEGER PRIMARY KEY,
+                timestamp_ms INTEGER NOT NULL,
+                session_id TEXT NOT NULL,
+                role TEXT NOT NULL,
+                action TEXT NOT NULL,
+                peer TEXT NOT NULL,
+                label TEXT NOT NULL,
+                facts TEXT NOT NULL,
+                prev_hash TEXT NOT NULL,
+                hash TEXT NOT NULL,
+                payload TEXT,
+                payload_digest TEXT,
+                expires_at_ms INTEGER
+            )",
+        )
+        .map_err(sqlite_error)?;
+        Ok(Self { conn })
+    }
+
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<Self, JournalError> {
+        Self::new(rusqlite::Connection::open(path).map_err(sqlite_error)?)
+    }
+
+    /// All records in sequence order
+    pub fn records(&self) -> Result<Vec<JournalRecord>, JournalError> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT seq, timestamp_ms, session_id, role, action, peer, label, facts,
+                        prev_hash, hash, payload, payload_digest, expires_at_ms
+                 FROM journal ORDER BY seq",
+            )
+            .map_err(sqlite_error)?;
+        let rows = stmt.query_map([], row_to_record).map_err(sqlite_error)?;
+        rows.collect::<Result<_, _>>().map_err(sqlite_error)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_error(err: rusqlite::Error) -> JournalError {
+    JournalError::Sink(err.to_string())
+}
+
+#[cfg(feature = "sqlite")]
+fn row_to_record(row: &rusqlite::Row<'_>) -> rusqlite::Result<JournalRecord> {
+    Ok(JournalRecord {
+        seq: row.get::<_, i64>(0)? as u64,
+        timestamp_ms: row.get::<_, i64>(1)? as u64,
+        session_id: row.get(2)?,
+        role: row.get(3)?,
+        action: row.get(4)?,
+        peer: row.get(5)?,
+        label: row.get(6)?,
+        facts: row.get(7)?,
+        prev_hash: row.get(8)?,
+        hash: row.get(9)?,
+        retained: match row.get::<_, Option<String>>(11)? {
+            Some(digest) => Some(RetainedPayload {
+                payload: row.get(10)?,
+                digest,
+                expires_at_ms: row.get::<_, i64>(12)? as u64,
+            }),
+            None => None,
+        },
+    })
+}
+
+#[cfg(feature = "sqlite")]
+impl JournalSink for SqliteSink {
+    fn append(&mut self, record: &JournalRecord) -> Result<(), JournalError> {
+        self.conn
+            .execute(
+                "INSERT INTO journal
+                    (seq, timestamp_ms, session_id, role, action, peer, label, facts, prev_hash, hash,
+                     payload, payload_digest, expires_at_ms)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+                rusqlite::params![
+                    record.seq as i64,
+                    record.timestamp_ms as i64,
+                    record.session_id,
+                    record.role,
+                    record.action,
+                    record.peer,
+                    record.label,
+                    record.facts,
+                    record.prev_hash,
+                    record.hash,
+                    record.retained.as_ref().and_then(|r| r.payload.as_deref()),
+                    record.retained.as_ref().map(|r| r.digest.as_str()),
+                    record.retained.as_ref().map(|r| r.expires_at_ms as i64),
+                ],
+            )
+            .map_err(sqlite_error)?;
+        Ok(())
+    }
+
+    fn head(&mut self) -> Result<Option<JournalRecord>, JournalError> {
+        use rusqlite::OptionalExtension;
+
+        self.conn
+            .query_row(
+                "SELECT seq, timestamp_ms, session_id, role, action, peer, label, facts,
+                        prev_hash, hash, payload, payload_digest, expires_at_ms
+                 FROM journal ORDER BY seq DESC LIMIT 1",
+                [],
+                row_to_record,
+            )
+            .optional()
+            .map_err(sqlite_error)
+    }
+
+    fn redact(&mut self, now_ms: u64) -> Result<usize, JournalError> {
+        self.conn
+            .execute(
+                "UPDATE journal SET payload = NULL
+                 WHERE payload IS NOT NULL AND expires_at_ms <= ?1",
+                [now_ms as i64],
+            )
+            .map_err(sqlite_error)
+    }
+}
+
+struct JournalInner {
+    sink: Box<dyn JournalSink>,
+    next_seq: u64,
+    head_hash: String,
+}
+
+/// Hash-chained journal shared by the roles of a process
+#[derive(Clone)]
+pub struct Journal {
+    session_id: String,
+    inner: Arc<Mutex<JournalInner>>,
+}
+
+impl fmt::Debug for Journal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Journal")
+            .field("session_id", &self.session_id)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Journal {
+    /// Open a journal on `sink`, continuing after any record it already holds
+    pub fn open(
+        mut sink: impl JournalSink + 'static,
+        session_id: impl fmt::Display,
+    ) -> Result<Self, JournalError> {
+        let (next_seq, head_hash) = match sink.head()? {
+            Some(head) => (head.seq + 1, head.hash),
+            None => (0, GENESIS_HASH.to_string()),
+        };
+        Ok(Self {
+            session_id: session_id.to_string(),
+            inner: Arc::new(Mutex::new(JournalInner {
+                sink: Box::new(sink),
+                next_seq,
+                head_hash,
+            })),
+        })
+    }
+
+    #[must_use]
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Append a record for a completed step
+    pub fn record(
+        &self,
+        role: &str,
+        action: ActionKind,
+        peer: &str,
+        label: &str,
+        facts: &str,
+    ) -> Result<JournalRecord, JournalError> {
+        self.append(role, action, peer, label, facts, None)
+    }
+
+    /// Append the record of `role` for the step at `point`, logging
+    /// `payload` if the point has a retention period
+    pub fn record_point(
+        &self,
+        role: &str,
+        point: &JournalPoint,
+        payload: Option<String>,
+    ) -> Result<JournalRecord, JournalError> {
+        let retained = point.retain_ms.zip(payload);
+        self.append(
+            role,
+            point.action,
+            point.peer,
+            point.label,
+            point.facts,
+            retained,
+        )
+    }
+
+    /// Erase the logged payloads whose retention period has ended,
+    /// returning how many were erased
+    ///
+    /// Call it periodically: payloads stay in the sink until it runs.
+    pub fn redact_expired(&self) -> Result<usize, JournalError> {
+        let now_ms = now_ms();
+        self.inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+            .sink
+            .redact(now_ms)
+    }
+
+    fn append(
+        &self,
+        role: &str,
+        action: ActionKind,
+        peer: &str,
+        label: &str,
+        facts: &str,
+        retained: Option<(u64, String)>,
+    ) -> Result<JournalRecord, JournalError> {
+        let mut inner = self
+            .inner
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let timestamp_ms = now_ms();
+        let retained = retained.map(|(retain_ms, payload)| {
+            RetainedPayload::new(payload, timestamp_ms.saturating_add(retain_ms))
+        });
+        let mut record = JournalRecord {
+            seq: inner.next_seq,
+            timestamp_ms,
+            session_id: self.session_id.clone(),
+            role: role.to_string(),
+            action: action.to_string(),
+            peer: peer.to_string(),
+            label: label.to_string(),
+            facts: facts.to_string(),
+            prev_hash: inner.head_hash.clone(),
+            hash: String::new(),
+            retained,
+        };
+        record.hash = record.compute_hash();
+
+        inner.sink.append(&record)?;
+        inner.next_seq += 1;
+        inner.head_hash.clone_from(&record.hash);
+        Ok(record)
+    }
+}
+
+fn now_ms() -> u64 {
+    crate::runtime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Annotated step at which generated code writes a journal record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JournalPoint {
+    pub action: ActionKind,
+    pub peer: &'static str,
+    /// Message type name
+    pub label: &'static str,
+    pub facts: &'static str,
+    /// Milliseconds the payload of a send is kept, from the `retain`
+    /// annotation; `None` logs no payload
+    pub retain_ms: Option<u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn journal_with(sink: MemorySink) -> Journal {
+        let journal = Journal::open(sink, "s-1").unwrap();
+        journal
+            .record("Alice", ActionKind::Send, "Bob", "Pay", "payment_sent")
+            .unwrap();
+        journal
+            .record("Alice", ActionKind::Receive, "Bob", "Receipt", "receipt")
+            .unwrap();
+        journal
+    }
+
+    #[test]
+    fn test_records_form_chain() {
+        let sink = MemorySink::new();
+        journal_with(sink.clone());
+        let records = sink.records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].prev_hash, GENESIS_HASH);
+        assert_eq!(records[1].prev_hash, records[0].hash);
+        assert!(verify_chain(&records).is_ok());
+    }
+
+    #[test]
+    fn test_tampering_is_detected() {
+        let sink = MemorySink::new();
+        journal_with(sink.clone());
+
+        let mut records = sink.records();
+        records[0].facts = "nothing happened".into();
+        assert!(matches!(
+            verify_chain(&records),
+            Err(JournalError::ChainBroken { seq: 0, .. })
+        ));
+
+        let mut records = sink.records();
+        records.remove(0);
+        assert!(verify_chain(&records).is_err());
+    }
+
+    #[test]
+    fn test_reopen_continues_chain() {
+        let sink = MemorySink::new();
+        journal_with(sink.clone());
+        let journal = Journal::open(sink.clone(), "s-2").unwrap();
+        let record = journal
+            .record("Alice", ActionKind::Send, "Bob", "Close", "closed")
+            .unwrap();
+        assert_eq!(record.seq, 2);
+        assert!(verify_chain(&sink.records()).is_ok());
+    }
+
+    const RETAINED: JournalPoint = JournalPoint {
+        action: ActionKind::Send,
+        peer: "Bob",
+        label: "Signup",
+        facts: "signup",
+        retain_ms: Some(0),
+    };
+
+    #[test]
+    fn test_parse_retention() {
+        assert_eq!(
+            parse_retention("30d"),
+            Some(Duration::from_secs(30 * 86_400))
+        );
+        assert_eq!(
+            parse_retention(" 12h"),
+            Some(Duration::from_secs(12 * 3_600))
+        );
+        assert_eq!(parse_retention("0s"), Some(Duration::ZERO));
+        for bad in ["", "d", "30", "30w", "-1d"] {
+            assert_eq!(parse_retention(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_redaction_keeps_chain() {
+        let mut sink = MemorySink::new();
+        let journal = Journal::open(sink.clone(), "s-1").unwrap();
+        journal
+            .record_point(
+                "Alice",
+                &RETAINED,
+                Some(r#"{"email":"a@example.com"}"#.into()),
+            )
+            .unwrap();
+        journal
+            .record_point(
+                "Alice",
+                &JournalPoint {
+                    retain_ms: Some(u64::MAX),
+                    ..RETAINED
+                },
+                Some("{}".into()),
+            )
+            .unwrap();
+        assert!(verify_chain(&sink.records()).is_ok());
+
+        assert_eq!(journal.redact_expired().unwrap(), 1);
+        let records = sink.records();
+        assert_eq!(records[0].retained.as_ref().unwrap().payload, None);
+        assert!(records[1].retained.as_ref().unwrap().payload.is_some());
+        assert!(verify_chain(&records).is_ok());
+        assert_eq!(sink.redact(u64::MAX - 1).unwrap(), 0);
+
+        // The digest still pins the payload that was logged
+        let mut records = sink.records();
+        records[1].retained.as_mut().unwrap().payload = Some("[]".into());
+        assert!(matches!(
+            verify_chain(&records),
+            Err(JournalError::ChainBroken { seq: 1, .. })
+        ));
+    }
+
+    #[test]
+    fn test_file_sink_redaction() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal");
+        let journal = Journal::open(FileSink::open(&path).unwrap(), "s-1").unwrap();
+        journal
+            .record_point("Alice", &RETAINED, Some("\"secret\"".into()))
+            .unwrap();
+        assert_eq!(journal.redact_expired().unwrap(), 1);
+        journal
+            .record("Alice", ActionKind::Send, "Bob", "Bye", "bye")
+            .unwrap();
+
+        let records = FileSink::read(&path).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].retained.as_ref().unwrap().payload, None);
+        assert!(verify_chain(&records).is_ok());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_sink() {
+        let journal = Journal::open(
+            SqliteSink::new(rusqlite::Connection::open_in_memory().unwrap()).unwrap(),
+            "s-1",
+        )
+        .unwrap();
+        journal
+            .record("Alice", ActionKind::Send, "Bob", "Pay", "payment_sent")
+            .unwrap();
+        journal
+            .record_point("Alice", &RETAINED, Some("1".into()))
+            .unwrap();
+        assert_eq!(journal.redact_expired().unwrap(), 1);
+        let mut inner = journal.inner.lock().unwrap();
+        let head = inner.sink.head().unwrap().unwrap();
+        assert_eq!(head.seq, 1);
+        assert_eq!(head.hash, head.compute_hash());
+        assert_eq!(head.retained.unwrap().payload, None);
+    }
+}
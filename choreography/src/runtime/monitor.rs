@@ -0,0 +1,359 @@
+// Runtime conformance monitors
+//
+// A monitor watches the message stream of an endpoint that is not trusted to
+// follow its projected local type, e.g. a peer implemented outside of Rust or
+// an endpoint built with a different version of the choreography. Every
+// observed send, receive, selection, and branch is checked against the local
+// type and the first deviation is reported as a `MonitorViolation`.
+//
+// Local types are compiled by codegen into a `MonitorSpec`: a small
+// nondeterministic automaton with labelled transitions and epsilon edges.
+// Loops with an unknown trip count and recursion both become cycles, so the
+// monitor tracks the set of states the endpoint could be in and only fails
+// when no state admits the observed action.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use thiserror::Error;
+
+/// Kind of action performed by the monitored endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ActionKind {
+    /// Message sent to `peer`
+    Send,
+    /// Message received from `peer`
+    Receive,
+    /// Branch label selected and sent to `peer`
+    Select,
+    /// Branch label offered by `peer`
+    Branch,
+}
+
+impl fmt::Display for ActionKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ActionKind::Send => write!(f, "send"),
+            ActionKind::Receive => write!(f, "receive"),
+            ActionKind::Select => write!(f, "select"),
+            ActionKind::Branch => write!(f, "branch"),
+        }
+    }
+}
+
+/// Labelled transition of a monitor automaton
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transition {
+    pub from: usize,
+    pub kind: ActionKind,
+    pub peer: &'static str,
+    /// Message type name or branch label
+    pub label: &'static str,
+    pub to: usize,
+}
+
+/// Automaton compiled from a projected local type
+#[derive(Debug, Clone, Copy)]
+pub struct MonitorSpec {
+    /// Role whose local type the automaton describes
+    pub role: &'static str,
+    pub initial: usize,
+    /// State reached once the local type has terminated
+    pub accepting: usize,
+    pub transitions: &'static [Transition],
+    /// Silent edges introduced by loops, recursion, and local choices
+    pub epsilons: &'static [(usize, usize)],
+}
+
+/// Action observed on the monitored endpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObservedEvent {
+    pub kind: ActionKind,
+    pub peer: String,
+    pub label: String,
+}
+
+impl ObservedEvent {
+    pub fn sent(to: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::new(ActionKind::Send, to, message)
+    }
+
+    pub fn received(from: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::new(ActionKind::Receive, from, message)
+    }
+
+    pub fn selected(to: impl Into<String>, label: impl Into<String>) -> Self {
+        Self::new(ActionKind::Select, to, label)
+    }
+
+    pub fn branched(from: impl Into<String>, label: impl Into<String>) -> Self {
+        Self::new(ActionKind::Branch, from, label)
+    }
+
+    fn new(kind: ActionKind, peer: impl Into<String>, label: impl Into<String>) -> Self {
+        Self {
+            kind,
+            peer: peer.into(),
+            label: label.into(),
+        }
+    }
+}
+
+impl fmt::Display for ObservedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} ({})", self.kind, self.label, self.peer)
+    }
+}
+
+/// Deviation from the projected local type
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum MonitorViolation {
+    #[error("{role}: unexpected {event} at step {step}, expected one of [{}]", .expected.join(", "))]
+    Unexpected {
+        role: &'static str,
+        step: usize,
+        event: ObservedEvent,
+        expected: Vec<String>,
+    },
+
+    #[error("{role}: {event} observed after the protocol terminated")]
+    AfterEnd {
+        role: &'static str,
+        event: ObservedEvent,
+    },
+
+    #[error("{role}: session closed before completion, expected one of [{}]", .expected.join(", "))]
+    Incomplete {
+        role: &'static str,
+        expected: Vec<String>,
+    },
+}
+
+/// Checks an observed action stream against a `MonitorSpec`
+#[derive(Debug, Clone)]
+pub struct ConformanceMonitor {
+    spec: &'static MonitorSpec,
+    current: BTreeSet<usize>,
+    steps: usize,
+}
+
+impl ConformanceMonitor {
+    #[must_use]
+    pub fn new(spec: &'static MonitorSpec) -> Self {
+        let current = closure(spec, std::iter::once(spec.initial));
+        Self {
+            spec,
+            current,
+            steps: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn spec(&self) -> &'static MonitorSpec {
+        self.spec
+    }
+
+    /// Number of actions accepted so far
+    #[must_use]
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    /// Check one action. On a violation the monitor state is left unchanged.
+    pub fn observe(&mut self, event: ObservedEvent) -> Result<(), MonitorViolation> {
+        let targets: Vec<usize> = self
+            .spec
+            .transitions
+            .iter()
+            .filter(|t| {
+                self.current.contains(&t.from)
+                    && t.kind == event.kind
+                    && t.peer == event.peer
+                    && t.label == event.label
+            })
+            .map(|t| t.to)
+            .collect();
+
+        if targets.is_empty() {
+            let expected = self.expected();
+            return Err(if expected.is_empty() {
+                MonitorViolation::AfterEnd {
+                    role: self.spec.role,
+                    event,
+                }
+            } else {
+                MonitorViolation::Unexpected {
+                    role: self.spec.role,
+                    step: self.steps,
+                    event,
+                    expected,
+                }
+            });
+        }
+
+        self.current = closure(self.spec, targets);
+        self.steps += 1;
+        Ok(())
+    }
+
+    /// Whether the local type may terminate at this point
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.current.contains(&self.spec.accepting)
+    }
+
+    /// Check that the session ended in a terminal state
+    pub fn finish(&self) -> Result<(), MonitorViolation> {
+        if self.is_complete() {
+            Ok(())
+        } else {
+            Err(MonitorViolation::Incomplete {
+                role: self.spec.role,
+                expected: self.expected(),
+            })
+        }
+    }
+
+    /// Actions admitted in the current state, formatted for diagnostics
+    #[must_use]
+    pub fn expected(&self) -> Vec<String> {
+        let expected: BTreeSet<String> = self
+            .next()
+            .map(|t| format!("{} {} ({})", t.kind, t.label, t.peer))
+            .collect();
+        expected.into_iter().collect()
+    }
+
+    /// Transitions admitted in the current state
+    pub fn next(&self) -> impl Iterator<Item = &'static Transition> + '_ {
+        self.spec
+            .transitions
+            .iter()
+            .filter(|t| self.current.contains(&t.from))
+    }
+
+    /// Return to the initial state
+    pub fn reset(&mut self) {
+        *self = Self::new(self.spec);
+    }
+}
+
+fn closure(spec: &MonitorSpec, states: impl IntoIterator<Item = usize>) -> BTreeSet<usize> {
+    let mut set = BTreeSet::new();
+    let mut stack: Vec<usize> = states.into_iter().collect();
+    while let Some(state) = stack.pop() {
+        if set.insert(state) {
+            stack.extend(
+                spec.epsilons
+                    .iter()
+                    .filter(|(from, _)| *from == state)
+                    .map(|(_, to)| *to),
+            );
+        }
+    }
+    set
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Client: rec Loop { Server!Request . Server&{ More: Server?Chunk . Loop, Done: end } }
+    static CLIENT: MonitorSpec = MonitorSpec {
+        role: "Client",
+        initial: 0,
+        accepting: 3,
+        transitions: &[
+            Transition {
+                from: 0,
+                kind: ActionKind::Send,
+                peer: "Server",
+                label: "Request",
+                to: 1,
+            },
+            Transition {
+                from: 1,
+                kind: ActionKind::Branch,
+                peer: "Server",
+                label: "More",
+                to: 2,
+            },
+            Transition {
+                from: 2,
+                kind: ActionKind::Receive,
+                peer: "Server",
+                label: "Chunk",
+                to: 4,
+            },
+            Transition {
+                from: 1,
+                kind: ActionKind::Branch,
+                peer: "Server",
+                label: "Done",
+                to: 5,
+            },
+        ],
+        epsilons: &[(4, 0), (5, 3)],
+    };
+
+    #[test]
+    fn test_accepts_conforming_trace() {
+        let mut monitor = ConformanceMonitor::new(&CLIENT);
+        for _ in 0..3 {
+            monitor
+                .observe(ObservedEvent::sent("Server", "Request"))
+                .unwrap();
+            monitor
+                .observe(ObservedEvent::branched("Server", "More"))
+                .unwrap();
+            monitor
+                .observe(ObservedEvent::received("Server", "Chunk"))
+                .unwrap();
+        }
+        monitor
+            .observe(ObservedEvent::sent("Server", "Request"))
+            .unwrap();
+        assert!(!monitor.is_complete());
+        monitor
+            .observe(ObservedEvent::branched("Server", "Done"))
+            .unwrap();
+        assert!(monitor.finish().is_ok());
+        assert_eq!(monitor.steps(), 11);
+    }
+
+    #[test]
+    fn test_flags_deviation_and_keeps_state() {
+        let mut monitor = ConformanceMonitor::new(&CLIENT);
+        let err = monitor
+            .observe(ObservedEvent::received("Server", "Chunk"))
+            .unwrap_err();
+        assert!(matches!(err, MonitorViolation::Unexpected { step: 0, .. }));
+        assert_eq!(
+            err.to_string(),
+            "Client: unexpected receive Chunk (Server) at step 0, expected one of [send Request (Server)]"
+        );
+
+        // The monitor can still accept the correct action afterwards
+        monitor
+            .observe(ObservedEvent::sent("Server", "Request"))
+            .unwrap();
+        assert!(matches!(
+            monitor.finish(),
+            Err(MonitorViolation::Incomplete { .. })
+        ));
+    }
+
+    #[test]
+    fn test_rejects_actions_after_end() {
+        let mut monitor = ConformanceMonitor::new(&CLIENT);
+        monitor
+            .observe(ObservedEvent::sent("Server", "Request"))
+            .unwrap();
+        monitor
+            .observe(ObservedEvent::branched("Server", "Done"))
+            .unwrap();
+        assert!(matches!(
+            monitor.observe(ObservedEvent::sent("Server", "Request")),
+            Err(MonitorViolation::AfterEnd { .. })
+        ));
+    }
+}
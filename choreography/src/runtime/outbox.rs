@@ -0,0 +1,541 @@
+// Persistent outbox for exactly-once sends
+//
+// Business-critical protocols cannot lose a payment or apply it twice when a
+// process restarts mid-session. The `Outboxed` middleware gives their sends
+// exactly-once delivery through an `OutboxStore`:
+//
+// 1. the message is persisted, under the next sequence number of the sending
+//    role, before anything goes on the wire;
+// 2. it is transmitted;
+// 3. it is acknowledged in the store.
+//
+// After a restart, `Outboxed::flush` transmits whatever was persisted but
+// never acknowledged, and sequence numbers continue from the store. The
+// receiving side records the highest number delivered from each peer in its
+// own store, so retransmissions of messages it already handed to the role
+// are dropped.
+//
+// `MemoryOutbox` keeps everything in memory for tests, `SqliteOutbox`
+// (feature `sqlite`) and `PostgresOutbox` (feature `postgres`) store it in
+// tables. Custom stores implement the trait directly.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+use crate::effects::ChoreographyError;
+
+/// Errors raised by outbox stores
+#[derive(Debug, Error)]
+pub enum OutboxError {
+    #[error("outbox store error: {0}")]
+    Store(String),
+}
+
+impl From<OutboxError> for ChoreographyError {
+    fn from(err: OutboxError) -> Self {
+        ChoreographyError::Outbox(err.to_string())
+    }
+}
+
+/// A send persisted before transmission
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboxMessage {
+    pub session_id: String,
+    /// Sending role
+    pub role: String,
+    /// Position among the sends of `role` in the session, from 1
+    pub seq: u64,
+    pub peer: String,
+    /// Message type name
+    pub label: String,
+    /// Encoded message
+    pub payload: Vec<u8>,
+}
+
+/// Storage backend for the outbox
+///
+/// Everything is keyed by session and role, so one store can serve all the
+/// roles of a process.
+pub trait OutboxStore: Send {
+    /// Durably store `message` before it is transmitted
+    fn persist(&mut self, message: &OutboxMessage) -> Result<(), OutboxError>;
+
+    /// Mark send `seq` of `role` as transmitted
+    fn acknowledge(&mut self, session_id: &str, role: &str, seq: u64) -> Result<(), OutboxError>;
+
+    /// Sends of `role` persisted but not acknowledged, in sequence order
+    fn pending(&mut self, session_id: &str, role: &str) -> Result<Vec<OutboxMessage>, OutboxError>;
+
+    /// Highest sequence number persisted for `role`
+    fn last_seq(&mut self, session_id: &str, role: &str) -> Result<Option<u64>, OutboxError>;
+
+    /// Record that `role` was handed message `seq` from `peer`
+    fn mark_delivered(
+        &mut self,
+        session_id: &str,
+        role: &str,
+        peer: &str,
+        seq: u64,
+    ) -> Result<(), OutboxError>;
+
+    /// Highest sequence number from `peer` handed to `role`
+    fn last_delivered(
+        &mut self,
+        session_id: &str,
+        role: &str,
+        peer: &str,
+    ) -> Result<Option<u64>, OutboxError>;
+}
+
+type StreamKey = (String, String);
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    /// Persisted sends and whether they were acknowledged, per session and role
+    sent: HashMap<StreamKey, Vec<(OutboxMessage, bool)>>,
+    /// Highest delivered sequence number, per session, role and peer
+    delivered: HashMap<(String, String, String), u64>,
+}
+
+/// In-memory store; clones share the same storage
+#[derive(Debug, Clone, Default)]
+pub struct MemoryOutbox {
+    state: Arc<Mutex<MemoryState>>,
+}
+
+impl MemoryOutbox {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, MemoryState> {
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+fn key(session_id: &str, role: &str) -> StreamKey {
+    (session_id.to_string(), role.to_string())
+}
+
+impl OutboxStore for MemoryOutbox {
+    fn persist(&mut self, message: &OutboxMessage) -> Result<(), OutboxError> {
+        self.state()
+            .sent
+            .entry(key(&message.session_id, &message.role))
+            .or_default()
+            .push((message.clone(), false));
+        Ok(())
+    }
+
+    fn acknowledge(&mut self, session_id: &str, role: &str, seq: u64) -> Result<(), OutboxError> {
+        let mut state = self.state();
+        let entry = state
+            .sent
+            .get_mut(&key(session_id, role))
+            .and_then(|sent| sent.iter_mut().find(|(message, _)| message.seq == seq))
+            .ok_or_else(|| OutboxError::Store(format!("no send {seq} of {role} to acknowledge")))?;
+        entry.1 = true;
+        Ok(())
+    }
+
+    fn pending(&mut self, session_id: &str, role: &str) -> Result<Vec<OutboxMessage>, OutboxError> {
+        Ok(self
+            .state()
+            .sent
+            .get(&key(session_id, role))
+            .map(|sent| {
+                sent.iter()
+                    .filter(|(_, acknowledged)| !acknowledged)
+                    .map(|(message, _)| message.clone())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    fn last_seq(&mut self, session_id: &str, role: &str) -> Result<Option<u64>, OutboxError> {
+        Ok(self
+            .state()
+            .sent
+            .get(&key(session_id, role))
+            .and_then(|sent| sent.iter().map(|(message, _)| message.seq).max()))
+    }
+
+    fn mark_delivered(
+        &mut self,
+        session_id: &str,
+        role: &str,
+        peer: &str,
+        seq: u64,
+    ) -> Result<(), OutboxError> {
+        let mut state = self.state();
+        let last = state
+            .delivered
+            .entry((session_id.to_string(), role.to_string(), peer.to_string()))
+            .or_insert(seq);
+        *last = (*last).max(seq);
+        Ok(())
+    }
+
+    fn last_delivered(
+        &mut self,
+        session_id: &str,
+        role: &str,
+        peer: &str,
+    ) -> Result<Option<u64>, OutboxError> {
+        Ok(self
+            .state()
+            .delivered
+            .get(&(session_id.to_string(), role.to_string(), peer.to_string()))
+            .copied())
+    }
+}
+
+/// Store keeping the outbox in SQLite tables
+#[cfg(feature = "sqlite")]
+pub struct SqliteOutbox {
+    conn: rusqlite::Connection,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteOutbox {
+    /// Use `conn`, creating the `outbox` and `outbox_delivered` tables if
+    /// they do not exist
+    pub fn new(conn: rusqlite::Connection) -> Result<Self, OutboxError> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS outbox (
+                session_id TEXT NOT NULL,
+                role TEXT NOT NULL,
+                seq INTEGER NOT NULL,
+                peer TEXT NOT NULL,
+                label TEXT NOT NULL,
+                payload BLOB NOT NULL,
+                acknowledged INTEGER NOT NULL DEFAULT 0,
+                PRIMARY KEY (session_id, role, seq)
+            );
+            CREATE TABLE IF NOT EXISTS outbox_delivered (
+                session_id TEXT NOT NULL,
+                role TEXT NOT NULL,
+                peer TEXT NOT NULL,
+                seq INTEGER NOT NULL,
+                PRIMARY KEY (session_id, role, peer)
+            )",
+        )
+        .map_err(sqlite_error)?;
+        Ok(Self { conn })
+    }
+
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<Self, OutboxError> {
+        Self::new(rusqlite::Connection::open(path).map_err(sqlite_error)?)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_error(err: rusqlite::Error) -> OutboxError {
+    OutboxError::Store(err.to_string())
+}
+
+#[cfg(feature = "sqlite")]
+impl OutboxStore for SqliteOutbox {
+    fn persist(&mut self, message: &OutboxMessage) -> Result<(), OutboxError> {
+        self.conn
+            .execute(
+                "INSERT INTO outbox (session_id, role, seq, peer, label, payload)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                rusqlite::params![
+                    message.session_id,
+                    message.role,
+                    message.seq as i64,
+                    message.peer,
+                    message.label,
+                    message.payload,
+                ],
+            )
+            .map_err(sqlite_error)?;
+        Ok(())
+    }
+
+    fn acknowledge(&mut self, session_id: &str, role: &str, seq: u64) -> Result<(), OutboxError> {
+        self.conn
+            .execute(
+                "UPDATE outbox SET acknowledged = 1
+                 WHERE session_id = ?1 AND role = ?2 AND seq = ?3",
+                rusqlite::params![session_id, role, seq as i64],
+            )
+            .map_err(sqlite_error)?;
+        Ok(())
+    }
+
+    fn pending(&mut self, session_id: &str, role: &str) -> Result<Vec<OutboxMessage>, OutboxError> {
+        let mut stmt = self
+            .conn
+            .prepare(
+                "SELECT seq, peer, label, payload FROM outbox
+                 WHERE session_id = ?1 AND role = ?2 AND acknowledged = 0
+                 ORDER BY seq",
+            )
+            .map_err(sqlite_error)?;
+        let rows = stmt
+            .query_map(rusqlite::params![session_id, role], |row| {
+                Ok(OutboxMessage {
+                    session_id: session_id.to_string(),
+                    role: role.to_string(),
+                    seq: row.get::<_, i64>(0)? as u64,
+                    peer: row.get(1)?,
+                    label: row.get(2)?,
+                    payload: row.get(3)?,
+                })
+            })
+            .map_err(sqlite_error)?;
+        rows.collect::<Result<_, _>>().map_err(sqlite_error)
+    }
+
+    fn last_seq(&mut self, session_id: &str, role: &str) -> Result<Option<u64>, OutboxError> {
+        self.conn
+            .query_row(
+                "SELECT MAX(seq) FROM outbox WHERE session_id = ?1 AND role = ?2",
+                rusqlite::params![session_id, role],
+                |row| row.get::<_, Option<i64>>(0),
+            )
+            .map(|seq| seq.map(|seq| seq as u64))
+            .map_err(sqlite_error)
+    }
+
+    fn mark_delivered(
+        &mut self,
+        session_id: &str,
+        role: &str,
+        peer: &str,
+        seq: u64,
+    ) -> Result<(), OutboxError> {
+        self.conn
+            .execute(
+                "INSERT INTO outbox_delivered (session_id, role, peer, seq)
+                 VALUES (?1, ?2, ?3, ?4)
+                 ON CONFLICT (session_id, role, peer)
+                 DO UPDATE SET seq = MAX(seq, excluded.seq)",
+                rusqlite::params![session_id, role, peer, seq as i64],
+            )
+            .map_err(sqlite_error)?;
+        Ok(())
+    }
+
+    fn last_delivered(
+        &mut self,
+        session_id: &str,
+        role: &str,
+        peer: &str,
+    ) -> Result<Option<u64>, OutboxError> {
+        use rusqlite::OptionalExtension;
+
+        self.conn
+            .query_row(
+                "SELECT seq FROM outbox_delivered
+                 WHERE session_id = ?1 AND role = ?2 AND peer = ?3",
+                rusqlite::params![session_id, role, peer],
+                |row| row.get::<_, i64>(0),
+            )
+            .optional()
+            .map(|seq| seq.map(|seq| seq as u64))
+            .map_err(sqlite_error)
+    }
+}
+
+/// Store keeping the outbox in PostgreSQL tables
+#[cfg(feature = "postgres")]
+pub struct PostgresOutbox {
+    client: postgres::Client,
+}
+
+#[cfg(feature = "postgres")]
+impl PostgresOutbox {
+    /// Use `client`, creating the `outbox` and `outbox_delivered` tables if
+    /// they do not exist
+    pub fn new(mut client: postgres::Client) -> Result<Self, OutboxError> {
+        client
+            .batch_execute(
+                "CREATE TABLE IF NOT EXISTS outbox (
+                    session_id TEXT NOT NULL,
+                    role TEXT NOT NULL,
+                    seq BIGINT NOT NULL,
+                    peer TEXT NOT NULL,
+                    label TEXT NOT NULL,
+                    payload BYTEA NOT NULL,
+                    acknowledged BOOLEAN NOT NULL DEFAULT FALSE,
+                    PRIMARY KEY (session_id, role, seq)
+                );
+                CREATE TABLE IF NOT EXISTS outbox_delivered (
+                    session_id TEXT NOT NULL,
+                    role TEXT NOT NULL,
+                    peer TEXT NOT NULL,
+                    seq BIGINT NOT NULL,
+                    PRIMARY KEY (session_id, role, peer)
+                )",
+            )
+            .map_err(postgres_error)?;
+        Ok(Self { client })
+    }
+
+    /// Connect with a libpq-style connection string, without TLS
+    pub fn connect(params: &str) -> Result<Self, OutboxError> {
+        Self::new(postgres::Client::connect(params, postgres::NoTls).map_err(postgres_error)?)
+    }
+}
+
+#[cfg(feature = "postgres")]
+fn postgres_error(err: postgres::Error) -> OutboxError {
+    OutboxError::Store(err.to_string())
+}
+
+#[cfg(feature = "postgres")]
+impl OutboxStore for PostgresOutbox {
+    fn persist(&mut self, message: &OutboxMessage) -> Result<(), OutboxError> {
+        self.client
+            .execute(
+                "INSERT INTO outbox (session_id, role, seq, peer, label, payload)
+                 VALUES ($1, $2, $3, $4, $5, $6)",
+                &[
+                    &message.session_id,
+                    &message.role,
+                    &(message.seq as i64),
+                    &message.peer,
+                    &message.label,
+                    &message.payload,
+                ],
+            )
+            .map_err(postgres_error)?;
+        Ok(())
+    }
+
+    fn acknowledge(&mut self, session_id: &str, role: &str, seq: u64) -> Result<(), OutboxError> {
+        self.client
+            .execute(
+                "UPDATE outbox SET acknowledged = TRUE
+                 WHERE session_id = $1 AND role = $2 AND seq = $3",
+                &[&session_id, &role, &(seq as i64)],
+            )
+            .map_err(postgres_error)?;
+        Ok(())
+    }
+
+    fn pending(&mut self, session_id: &str, role: &str) -> Result<Vec<OutboxMessage>, OutboxError> {
+        let rows = self
+            .client
+            .query(
+                "SELECT seq, peer, label, payload FROM outbox
+                 WHERE session_id = $1 AND role = $2 AND NOT acknowledged
+                 ORDER BY seq",
+                &[&session_id, &role],
+            )
+            .map_err(postgres_error)?;
+        Ok(rows
+            .iter()
+            .map(|row| OutboxMessage {
+                session_id: session_id.to_string(),
+                role: role.to_string(),
+                seq: row.get::<_, i64>(0) as u64,
+                peer: row.get(1),
+                label: row.get(2),
+                payload: row.get(3),
+            })
+            .collect())
+    }
+
+    fn last_seq(&mut self, session_id: &str, role: &str) -> Result<Option<u64>, OutboxError> {
+        let row = self
+            .client
+            .query_one(
+                "SELECT MAX(seq) FROM outbox WHERE session_id = $1 AND role = $2",
+                &[&session_id, &role],
+            )
+            .map_err(postgres_error)?;
+        Ok(row.get::<_, Option<i64>>(0).map(|seq| seq as u64))
+    }
+
+    fn mark_delivered(
+        &mut self,
+        session_id: &str,
+        role: &str,
+        peer: &str,
+        seq: u64,
+    ) -> Result<(), OutboxError> {
+        self.client
+            .execute(
+                "INSERT INTO outbox_delivered (session_id, role, peer, seq)
+                 VALUES ($1, $2, $3, $4)
+                 ON CONFLICT (session_id, role, peer)
+                 DO UPDATE SET seq = GREATEST(outbox_delivered.seq, excluded.seq)",
+                &[&session_id, &role, &peer, &(seq as i64)],
+            )
+            .map_err(postgres_error)?;
+        Ok(())
+    }
+
+    fn last_delivered(
+        &mut self,
+        session_id: &str,
+        role: &str,
+        peer: &str,
+    ) -> Result<Option<u64>, OutboxError> {
+        let row = self
+            .client
+            .query_opt(
+                "SELECT seq FROM outbox_delivered
+                 WHERE session_id = $1 AND role = $2 AND peer = $3",
+                &[&session_id, &role, &peer],
+            )
+            .map_err(postgres_error)?;
+        Ok(row.map(|row| row.get::<_, i64>(0) as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(seq: u64) -> OutboxMessage {
+        OutboxMessage {
+            session_id: "s-1".into(),
+            role: "Buyer".into(),
+            seq,
+            peer: "Seller".into(),
+            label: "Pay".into(),
+            payload: vec![seq as u8],
+        }
+    }
+
+    fn exercise(store: &mut impl OutboxStore) {
+        assert_eq!(store.last_seq("s-1", "Buyer").unwrap(), None);
+        store.persist(&message(1)).unwrap();
+        store.persist(&message(2)).unwrap();
+        store.acknowledge("s-1", "Buyer", 1).unwrap();
+        assert_eq!(store.pending("s-1", "Buyer").unwrap(), vec![message(2)]);
+        assert_eq!(store.last_seq("s-1", "Buyer").unwrap(), Some(2));
+        assert!(store.pending("s-1", "Seller").unwrap().is_empty());
+
+        assert_eq!(
+            store.last_delivered("s-1", "Seller", "Buyer").unwrap(),
+            None
+        );
+        store.mark_delivered("s-1", "Seller", "Buyer", 2).unwrap();
+        store.mark_delivered("s-1", "Seller", "Buyer", 1).unwrap();
+        assert_eq!(
+            store.last_delivered("s-1", "Seller", "Buyer").unwrap(),
+            Some(2)
+        );
+    }
+
+    #[test]
+    fn test_memory_outbox() {
+        exercise(&mut MemoryOutbox::new());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_outbox() {
+        exercise(&mut SqliteOutbox::new(rusqlite::Connection::open_in_memory().unwrap()).unwrap());
+    }
+}
@@ -0,0 +1,493 @@
+// Prometheus exporter
+//
+// `PrometheusExporter` keeps the session metrics of every protocol a process
+// runs and renders them in the Prometheus text exposition format, without
+// going through a global recorder. `protocol(name)` returns the
+// `SessionMetrics` of one protocol, to hand to `Instrumented` or the
+// generated `run_<role>_instrumented`, which labels its series with the
+// protocol and the role:
+//
+//     choreo_active_sessions{protocol, role}                  gauge
+//     choreo_sessions_total{protocol, role, outcome}          completed | failed
+//     choreo_session_duration_seconds{protocol, role}         histogram
+//     choreo_steps_total{protocol, role, step}
+//     choreo_step_duration_seconds{protocol, role, step}      histogram
+//     choreo_step_failures_total{protocol, role, step}
+//     choreo_messages_total{protocol, role, peer, label, direction}
+//     choreo_branches_total{protocol, role, peer, branch, direction}
+//     choreo_timeouts_total{protocol, role, peer}
+//
+// Steps per second are `rate(choreo_steps_total[1m])`, and the timeouts per
+// ended session are `rate(choreo_timeouts_total[5m])` divided by
+// `rate(choreo_sessions_total[5m])`.
+//
+// `serve` answers `GET /metrics` on a local address for a Prometheus server
+// to scrape; `render` gives the same text to serve from an existing one.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use crate::effects::{ChoreographyError, SessionError, SessionMetrics};
+use crate::runtime::monitor::ActionKind;
+
+/// Bucket bounds of the duration histograms in seconds, those of the
+/// Prometheus client libraries
+pub const DEFAULT_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Family {
+    ActiveSessions,
+    Sessions,
+    SessionDuration,
+    Steps,
+    StepDuration,
+    StepFailures,
+    Messages,
+    Branches,
+    Timeouts,
+}
+
+impl Family {
+    fn name(self) -> &'static str {
+        match self {
+            Self::ActiveSessions => "choreo_active_sessions",
+            Self::Sessions => "choreo_sessions_total",
+            Self::SessionDuration => "choreo_session_duration_seconds",
+            Self::Steps => "choreo_steps_total",
+            Self::StepDuration => "choreo_step_duration_seconds",
+            Self::StepFailures => "choreo_step_failures_total",
+            Self::Messages => "choreo_messages_total",
+            Self::Branches => "choreo_branches_total",
+            Self::Timeouts => "choreo_timeouts_total",
+        }
+    }
+
+    fn help(self) -> &'static str {
+        match self {
+            Self::ActiveSessions => "Sessions running",
+            Self::Sessions => "Sessions ended, by outcome",
+            Self::SessionDuration => "Duration of ended sessions",
+            Self::Steps => "Protocol steps taken, completed or failed",
+            Self::StepDuration => "Duration of completed protocol steps",
+            Self::StepFailures => "Protocol steps that failed",
+            Self::Messages => "Messages sent and received",
+            Self::Branches => "Branches selected and offered",
+            Self::Timeouts => "Timeouts that expired",
+        }
+    }
+
+    fn kind(self) -> &'static str {
+        match self {
+            Self::ActiveSessions => "gauge",
+            Self::SessionDuration | Self::StepDuration => "histogram",
+            _ => "counter",
+        }
+    }
+}
+
+/// Label names and values of a series
+type Labels = Vec<(&'static str, String)>;
+
+enum Series {
+    Counter(u64),
+    Gauge(i64),
+    Histogram {
+        /// Observations in each bucket, not cumulative
+        buckets: Vec<u64>,
+        sum: f64,
+        count: u64,
+    },
+}
+
+#[derive(Default)]
+struct Registry {
+    series: BTreeMap<Family, BTreeMap<Labels, Series>>,
+}
+
+/// Session metrics of the protocols a process runs, in the Prometheus text
+/// format, shared by its clones
+#[derive(Clone)]
+pub struct PrometheusExporter {
+    registry: Arc<Mutex<Registry>>,
+    buckets: Arc<[f64]>,
+}
+
+impl Default for PrometheusExporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for PrometheusExporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrometheusExporter")
+            .field("buckets", &self.buckets)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PrometheusExporter {
+    /// An exporter with the [`DEFAULT_BUCKETS`]
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_buckets(DEFAULT_BUCKETS)
+    }
+
+    /// An exporter whose duration histograms have the bucket bounds
+    /// `buckets`, in seconds
+    #[must_use]
+    pub fn with_buckets(buckets: &[f64]) -> Self {
+        let mut buckets = buckets.to_vec();
+        buckets.sort_by(f64::total_cmp);
+        buckets.dedup();
+        Self {
+            registry: Arc::default(),
+            buckets: buckets.into(),
+        }
+    }
+
+    /// Session metrics of the protocol `name`
+    #[must_use]
+    pub fn protocol(&self, name: impl Into<String>) -> ProtocolMetrics {
+        ProtocolMetrics {
+            exporter: self.clone(),
+            protocol: name.into(),
+        }
+    }
+
+    /// Every series, in the Prometheus text exposition format
+    #[must_use]
+    pub fn render(&self) -> String {
+        let registry = self.lock();
+        let mut text = String::new();
+        for (family, series) in &registry.series {
+            let name = family.name();
+            let _ = writeln!(text, "# HELP {name} {}", family.help());
+            let _ = writeln!(text, "# TYPE {name} {}", family.kind());
+            for (labels, value) in series {
+                match value {
+                    Series::Counter(count) => {
+                        let _ = writeln!(text, "{name}{} {count}", render_labels(labels, None));
+                    }
+                    Series::Gauge(value) => {
+                        let _ = writeln!(text, "{name}{} {value}", render_labels(labels, None));
+                    }
+                    Series::Histogram {
+                        buckets,
+                        sum,
+                        count,
+                    } => {
+                        let mut cumulative = 0;
+                        for (bound, observed) in self.buckets.iter().zip(buckets) {
+                            cumulative += observed;
+                            let le = render_labels(labels, Some(&bound.to_string()));
+                            let _ = writeln!(text, "{name}_bucket{le} {cumulative}");
+                        }
+                        let le = render_labels(labels, Some("+Inf"));
+                        let _ = writeln!(text, "{name}_bucket{le} {count}");
+                        let labels = render_labels(labels, None);
+                        let _ = writeln!(text, "{name}_sum{labels} {sum}");
+                        let _ = writeln!(text, "{name}_count{labels} {count}");
+                    }
+                }
+            }
+        }
+        text
+    }
+
+    /// Answer `GET /metrics` on `address`, in a background thread, and
+    /// return the address bound
+    ///
+    /// # Errors
+    ///
+    /// The error binding `address`.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn serve(
+        &self,
+        address: impl std::net::ToSocketAddrs,
+    ) -> std::io::Result<std::net::SocketAddr> {
+        let listener = std::net::TcpListener::bind(address)?;
+        let bound = listener.local_addr()?;
+        let exporter = self.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                // A scraper that fails mid-request only loses its scrape
+                let _ = exporter.answer(stream);
+            }
+        });
+        Ok(bound)
+    }
+
+    /// Answer one scrape request on `stream`
+    #[cfg(not(target_arch = "wasm32"))]
+    fn answer(&self, stream: std::net::TcpStream) -> std::io::Result<()> {
+        use std::io::{BufRead, BufReader, Write};
+
+        stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut request = String::new();
+        reader.read_line(&mut request)?;
+        // Skip the headers, the request has no body
+        let mut header = String::new();
+        while reader.read_line(&mut header)? > 0 && !header.trim().is_empty() {
+            header.clear();
+        }
+        let mut words = request.split_whitespace();
+        let (status, body) = match (words.next(), words.next()) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.render()),
+            (Some("GET"), _) => ("404 Not Found", String::new()),
+            _ => ("405 Method Not Allowed", String::new()),
+        };
+        let mut writer = stream;
+        write!(
+            writer,
+            "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        )?;
+        writer.flush()
+    }
+
+    fn update(&self, family: Family, labels: Labels, f: impl FnOnce(&mut Series)) {
+        let mut registry = self.lock();
+        let series = registry
+            .series
+            .entry(family)
+            .or_default()
+            .entry(labels)
+            .or_insert_with(|| match family.kind() {
+                "gauge" => Series::Gauge(0),
+                "histogram" => Series::Histogram {
+                    buckets: vec![0; self.buckets.len()],
+                    sum: 0.0,
+                    count: 0,
+                },
+                _ => Series::Counter(0),
+            });
+        f(series);
+    }
+
+    fn increment(&self, family: Family, labels: Labels) {
+        self.update(family, labels, |series| match series {
+            Series::Counter(count) => *count += 1,
+            Series::Gauge(value) => *value += 1,
+            Series::Histogram { .. } => {}
+        });
+    }
+
+    fn observe(&self, family: Family, labels: Labels, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = self.buckets.iter().position(|bound| seconds <= *bound);
+        self.update(family, labels, |series| {
+            if let Series::Histogram {
+                buckets,
+                sum,
+                count,
+            } = series
+            {
+                if let Some(bucket) = bucket {
+                    buckets[bucket] += 1;
+                }
+                *sum += seconds;
+                *count += 1;
+            }
+        });
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Registry> {
+        self.registry.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// `{name="value",...}` with `le` last, or nothing without labels
+fn render_labels(labels: &Labels, le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = labels
+        .iter()
+        .map(|(name, value)| format!("{name}=\"{}\"", escape(value)))
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{le}\""));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Session metrics of one protocol, recorded in a [`PrometheusExporter`]
+#[derive(Clone, Debug)]
+pub struct ProtocolMetrics {
+    exporter: PrometheusExporter,
+    protocol: String,
+}
+
+impl ProtocolMetrics {
+    fn labels(&self, role: &str, more: &[(&'static str, &str)]) -> Labels {
+        let mut labels = vec![
+            ("protocol", self.protocol.clone()),
+            ("role", role.to_string()),
+        ];
+        labels.extend(more.iter().map(|(name, value)| (*name, value.to_string())));
+        labels
+    }
+
+    fn step(&self, role: &str, step: ActionKind) -> Labels {
+        self.labels(role, &[("step", &step.to_string())])
+    }
+}
+
+impl SessionMetrics for ProtocolMetrics {
+    fn message_sent(&self, role: &str, to: &str, label: &str) {
+        let labels = self.labels(
+            role,
+            &[("peer", to), ("label", label), ("direction", "sent")],
+        );
+        self.exporter.increment(Family::Messages, labels);
+    }
+
+    fn message_received(&self, role: &str, from: &str, label: &str) {
+        let labels = self.labels(
+            role,
+            &[("peer", from), ("label", label), ("direction", "received")],
+        );
+        self.exporter.increment(Family::Messages, labels);
+    }
+
+    fn branch_selected(&self, role: &str, to: &str, branch: &str) {
+        let labels = self.labels(
+            role,
+            &[("peer", to), ("branch", branch), ("direction", "selected")],
+        );
+        self.exporter.increment(Family::Branches, labels);
+    }
+
+    fn branch_offered(&self, role: &str, from: &str, branch: &str) {
+        let labels = self.labels(
+            role,
+            &[("peer", from), ("branch", branch), ("direction", "offered")],
+        );
+        self.exporter.increment(Family::Branches, labels);
+    }
+
+    fn step_completed(&self, role: &str, step: ActionKind, latency: Duration) {
+        self.exporter
+            .increment(Family::Steps, self.step(role, step));
+        self.exporter
+            .observe(Family::StepDuration, self.step(role, step), latency);
+    }
+
+    fn step_failed(&self, role: &str, step: ActionKind, _error: &ChoreographyError) {
+        self.exporter
+            .increment(Family::Steps, self.step(role, step));
+        self.exporter
+            .increment(Family::StepFailures, self.step(role, step));
+    }
+
+    fn session_started(&self, role: &str) {
+        self.exporter
+            .increment(Family::ActiveSessions, self.labels(role, &[]));
+    }
+
+    fn session_ended(&self, role: &str, duration: Duration, error: Option<&SessionError>) {
+        self.exporter
+            .update(Family::ActiveSessions, self.labels(role, &[]), |series| {
+                if let Series::Gauge(value) = series {
+                    *value -= 1;
+                }
+            });
+        let outcome = if error.is_some() {
+            "failed"
+        } else {
+            "completed"
+        };
+        self.exporter
+            .increment(Family::Sessions, self.labels(role, &[("outcome", outcome)]));
+        self.exporter
+            .observe(Family::SessionDuration, self.labels(role, &[]), duration);
+    }
+
+    fn timed_out(&self, role: &str, at: &str, _after: Duration) {
+        self.exporter
+            .increment(Family::Timeouts, self.labels(role, &[("peer", at)]));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_protocol_series() {
+        let exporter = PrometheusExporter::with_buckets(&[0.01, 0.1]);
+        let payment = exporter.protocol("Payment");
+        payment.session_started("Client");
+        payment.session_started("Client");
+        payment.message_sent("Client", "Bank", "Pay");
+        payment.step_completed("Client", ActionKind::Send, Duration::from_millis(5));
+        payment.step_completed("Client", ActionKind::Send, Duration::from_millis(50));
+        payment.branch_offered("Client", "Bank", "accept");
+        payment.timed_out("Client", "Bank", Duration::from_secs(1));
+        payment.session_ended(
+            "Client",
+            Duration::from_secs(1),
+            Some(&SessionError::from(ChoreographyError::Timeout(
+                Duration::from_secs(1),
+            ))),
+        );
+        exporter
+            .protocol("Audit")
+            .message_received("Log", "Client", "Entry \"1\"");
+
+        let text = exporter.render();
+        for line in [
+            "# TYPE choreo_active_sessions gauge",
+            "choreo_active_sessions{protocol=\"Payment\",role=\"Client\"} 1",
+            "choreo_sessions_total{protocol=\"Payment\",role=\"Client\",outcome=\"failed\"} 1",
+            "choreo_steps_total{protocol=\"Payment\",role=\"Client\",step=\"send\"} 2",
+            "# TYPE choreo_step_duration_seconds histogram",
+            "choreo_step_duration_seconds_bucket{protocol=\"Payment\",role=\"Client\",step=\"send\",le=\"0.01\"} 1",
+            "choreo_step_duration_seconds_bucket{protocol=\"Payment\",role=\"Client\",step=\"send\",le=\"0.1\"} 2",
+            "choreo_step_duration_seconds_bucket{protocol=\"Payment\",role=\"Client\",step=\"send\",le=\"+Inf\"} 2",
+            "choreo_step_duration_seconds_count{protocol=\"Payment\",role=\"Client\",step=\"send\"} 2",
+            "choreo_session_duration_seconds_bucket{protocol=\"Payment\",role=\"Client\",le=\"0.1\"} 0",
+            "choreo_branches_total{protocol=\"Payment\",role=\"Client\",peer=\"Bank\",branch=\"accept\",direction=\"offered\"} 1",
+            "choreo_timeouts_total{protocol=\"Payment\",role=\"Client\",peer=\"Bank\"} 1",
+            "choreo_messages_total{protocol=\"Audit\",role=\"Log\",peer=\"Client\",label=\"Entry \\\"1\\\"\",direction=\"received\"} 1",
+        ] {
+            assert!(text.lines().any(|l| l == line), "missing {line} in\n{text}");
+        }
+        assert!(!text.contains("choreo_step_failures_total"));
+    }
+
+    #[test]
+    fn test_serve_metrics_over_http() {
+        use std::io::{Read, Write};
+
+        let exporter = PrometheusExporter::new();
+        exporter.protocol("Ping").session_started("Alice");
+        let address = exporter.serve("127.0.0.1:0").unwrap();
+        let get = |path: &str| {
+            let mut stream = std::net::TcpStream::connect(address).unwrap();
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let response = get("/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        assert!(response.contains("text/plain; version=0.0.4"));
+        assert!(response.ends_with("choreo_active_sessions{protocol=\"Ping\",role=\"Alice\"} 1\n"));
+        assert!(get("/").starts_with("HTTP/1.1 404"));
+    }
+}
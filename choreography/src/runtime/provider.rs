@@ -0,0 +1,254 @@
+// Executor abstraction
+//
+// Runtime utilities spawn tasks, wait on timers and open TCP connections
+// through a `RuntimeProvider` instead of calling one executor directly. The
+// `tokio` (default), `async-std` and `smol` features each provide one, and
+// `DefaultRuntime` names the provider the crate was built with. When several
+// features are enabled, tokio takes precedence over async-std, and async-std
+// over smol. WASM targets always use wasm-bindgen-futures and wasm-timer.
+//
+// Connections use the `futures` I/O traits, which async-std and smol streams
+// implement directly; tokio streams are adapted with `tokio_util::compat`.
+
+use futures::io::{AsyncRead, AsyncWrite};
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Spawning, timers and TCP connections of one async executor
+pub trait RuntimeProvider {
+    /// Connected TCP stream
+    type TcpStream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    /// Run `future` to completion in the background
+    fn spawn<F>(future: F)
+    where
+        F: Future<Output = ()> + Send + 'static;
+
+    /// Complete after `duration`
+    fn sleep(duration: Duration) -> impl Future<Output = ()> + Send;
+
+    /// Open a TCP connection to `address`
+    fn connect_tcp(address: SocketAddr)
+        -> impl Future<Output = io::Result<Self::TcpStream>> + Send;
+}
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "tokio"))]
+pub type DefaultRuntime = TokioRuntime;
+
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    not(feature = "tokio"),
+    feature = "async-std"
+))]
+pub type DefaultRuntime = AsyncStdRuntime;
+
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    not(feature = "tokio"),
+    not(feature = "async-std"),
+    feature = "smol"
+))]
+pub type DefaultRuntime = SmolRuntime;
+
+#[cfg(target_arch = "wasm32")]
+pub type DefaultRuntime = WasmRuntime;
+
+#[cfg(all(
+    not(target_arch = "wasm32"),
+    not(any(feature = "tokio", feature = "async-std", feature = "smol"))
+))]
+compile_error!("enable one of the `tokio`, `async-std` or `smol` features to select an executor");
+
+/// Provider backed by the tokio runtime the caller is running in
+#[cfg(all(not(target_arch = "wasm32"), feature = "tokio"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioRuntime;
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "tokio"))]
+impl RuntimeProvider for TokioRuntime {
+    type TcpStream = tokio_util::compat::Compat<tokio::net::TcpStream>;
+
+    fn spawn<F>(future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        tokio::spawn(future);
+    }
+
+    fn sleep(duration: Duration) -> impl Future<Output = ()> + Send {
+        tokio::time::sleep(duration)
+    }
+
+    fn connect_tcp(
+        address: SocketAddr,
+    ) -> impl Future<Output = io::Result<Self::TcpStream>> + Send {
+        use tokio_util::compat::TokioAsyncReadCompatExt;
+
+        async move {
+            let stream = tokio::net::TcpStream::connect(address).await?;
+            Ok(stream.compat())
+        }
+    }
+}
+
+/// Provider backed by the global async-std executor
+#[cfg(all(not(target_arch = "wasm32"), feature = "async-std"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncStdRuntime;
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "async-std"))]
+impl RuntimeProvider for AsyncStdRuntime {
+    type TcpStream = async_std::net::TcpStream;
+
+    fn spawn<F>(future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        // Dropping the handle detaches the task
+        async_std::task::spawn(future);
+    }
+
+    fn sleep(duration: Duration) -> impl Future<Output = ()> + Send {
+        async_std::task::sleep(duration)
+    }
+
+    fn connect_tcp(
+        address: SocketAddr,
+    ) -> impl Future<Output = io::Result<Self::TcpStream>> + Send {
+        async_std::net::TcpStream::connect(address)
+    }
+}
+
+/// Provider backed by the global smol executor
+#[cfg(all(not(target_arch = "wasm32"), feature = "smol"))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SmolRuntime;
+
+#[cfg(all(not(target_arch = "wasm32"), feature = "smol"))]
+impl RuntimeProvider for SmolRuntime {
+    type TcpStream = smol::net::TcpStream;
+
+    fn spawn<F>(future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        smol::spawn(future).detach();
+    }
+
+    async fn sleep(duration: Duration) {
+        smol::Timer::after(duration).await;
+    }
+
+    fn connect_tcp(
+        address: SocketAddr,
+    ) -> impl Future<Output = io::Result<Self::TcpStream>> + Send {
+        smol::net::TcpStream::connect(address)
+    }
+}
+
+/// Provider for browsers, which have no TCP sockets
+#[cfg(target_arch = "wasm32")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WasmRuntime;
+
+/// Stream type of providers that cannot open TCP connections
+#[cfg(target_arch = "wasm32")]
+#[derive(Debug)]
+pub enum NoTcpStream {}
+
+#[cfg(target_arch = "wasm32")]
+impl AsyncRead for NoTcpStream {
+    fn poll_read(
+        self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+        _: &mut [u8],
+    ) -> std::task::Poll<io::Result<usize>> {
+        match *self {}
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl AsyncWrite for NoTcpStream {
+    fn poll_write(
+        self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+        _: &[u8],
+    ) -> std::task::Poll<io::Result<usize>> {
+        match *self {}
+    }
+
+    fn poll_flush(
+        self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        match *self {}
+    }
+
+    fn poll_close(
+        self: std::pin::Pin<&mut Self>,
+        _: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<io::Result<()>> {
+        match *self {}
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl RuntimeProvider for WasmRuntime {
+    type TcpStream = NoTcpStream;
+
+    fn spawn<F>(future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        wasm_bindgen_futures::spawn_local(future);
+    }
+
+    async fn sleep(duration: Duration) {
+        let _ = wasm_timer::Delay::new(duration).await;
+    }
+
+    fn connect_tcp(_: SocketAddr) -> impl Future<Output = io::Result<Self::TcpStream>> + Send {
+        futures::future::ready(Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "browsers cannot open TCP connections, use runtime::websocket",
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_default_runtime() {
+        let (tx, rx) = futures::channel::oneshot::channel();
+        DefaultRuntime::spawn(async move {
+            DefaultRuntime::sleep(Duration::from_millis(1)).await;
+            let _ = tx.send(());
+        });
+        rx.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_connect_tcp() {
+        use futures::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            tokio::io::AsyncWriteExt::write_all(&mut stream, b"hello")
+                .await
+                .unwrap();
+        });
+
+        let mut stream = DefaultRuntime::connect_tcp(address).await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+        stream.close().await.unwrap();
+        server.await.unwrap();
+    }
+}
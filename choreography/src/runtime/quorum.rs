@@ -0,0 +1,198 @@
+// Threshold receives
+//
+// A statement such as `Signer[t of N] -> Coordinator: Share;` lets the
+// coordinator proceed once any `t` of the `N` signers have sent their share.
+// `gather` takes the first `t` messages to arrive, using the handler's
+// `recv_any`, and the statement's `@stragglers` annotation decides what
+// becomes of the instances that have not answered by then:
+//
+//     ignore          their messages are left unread (the default)
+//     collect_late    `Quorum::collect_late` receives them afterwards
+//     abort           they take no further part in the session
+//
+// `RoleAddressBook::gather_quorum` runs a gather over the instances of a role
+// family and drops aborted stragglers from the book, so later sends and
+// broadcasts skip them.
+
+use std::fmt;
+use std::str::FromStr;
+
+use serde::de::DeserializeOwned;
+use thiserror::Error;
+
+use crate::effects::{ChoreoHandler, ChoreographyError, Result};
+
+/// What happens to the instances that answer after the quorum is reached
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum StragglerPolicy {
+    /// Leave their messages unread
+    #[default]
+    Ignore,
+    /// Keep them pending, for [`Quorum::collect_late`]
+    CollectLate,
+    /// Exclude them from the rest of the session
+    Abort,
+}
+
+/// Error for an unknown `@stragglers` value
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+#[error("unknown straggler policy `{0}`, expected `ignore`, `collect_late` or `abort`")]
+pub struct ParseStragglerPolicyError(pub String);
+
+impl FromStr for StragglerPolicy {
+    type Err = ParseStragglerPolicyError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(Self::Ignore),
+            "collect_late" => Ok(Self::CollectLate),
+            "abort" => Ok(Self::Abort),
+            _ => Err(ParseStragglerPolicyError(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for StragglerPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ignore => write!(f, "ignore"),
+            Self::CollectLate => write!(f, "collect_late"),
+            Self::Abort => write!(f, "abort"),
+        }
+    }
+}
+
+/// A threshold receive with its bounds resolved: `role[threshold of of]`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QuorumSpec {
+    /// Role family the messages come from
+    pub role: String,
+    /// Number of messages to wait for
+    pub threshold: u32,
+    /// Number of instances taking part, `0..of`
+    pub of: u32,
+    pub stragglers: StragglerPolicy,
+}
+
+/// Messages of a reached quorum, with the instances that had not answered
+#[derive(Debug)]
+pub struct Quorum<A, M> {
+    responses: Vec<(u32, M)>,
+    stragglers: Vec<(u32, A)>,
+    policy: StragglerPolicy,
+}
+
+impl<A, M> Quorum<A, M> {
+    /// Instance indices and messages, in order of arrival
+    #[must_use]
+    pub fn responses(&self) -> &[(u32, M)] {
+        &self.responses
+    }
+
+    #[must_use]
+    pub fn into_responses(self) -> Vec<(u32, M)> {
+        self.responses
+    }
+
+    /// Instances that had not answered when the quorum was reached
+    #[must_use]
+    pub fn stragglers(&self) -> &[(u32, A)] {
+        &self.stragglers
+    }
+
+    #[must_use]
+    pub fn policy(&self) -> StragglerPolicy {
+        self.policy
+    }
+
+    /// Receive the messages of the stragglers, in order of arrival
+    ///
+    /// # Errors
+    ///
+    /// [`ChoreographyError::ProtocolViolation`] unless the policy is
+    /// [`StragglerPolicy::CollectLate`], or the handler's error.
+    pub async fn collect_late<H>(
+        &mut self,
+        handler: &mut H,
+        ep: &mut H::Endpoint,
+    ) -> Result<Vec<(u32, M)>>
+    where
+        H: ChoreoHandler<Role = A>,
+        M: DeserializeOwned + Send,
+    {
+        if self.policy != StragglerPolicy::CollectLate {
+            return Err(ChoreographyError::ProtocolViolation(format!(
+                "stragglers are only collected under `collect_late`, not `{}`",
+                self.policy
+            )));
+        }
+        let count = self.stragglers.len();
+        receive_first(handler, ep, &mut self.stragglers, count).await
+    }
+}
+
+/// Receive from `from` until `threshold` messages have arrived
+///
+/// Messages are taken in order of arrival when the handler overrides
+/// [`recv_any`](ChoreoHandler::recv_any), and in the order of `from`
+/// otherwise.
+///
+/// # Errors
+///
+/// [`ChoreographyError::ProtocolViolation`] if `threshold` is zero or more
+/// than there are instances, or the handler's error.
+pub async fn gather<H, M>(
+    handler: &mut H,
+    ep: &mut H::Endpoint,
+    from: &[(u32, H::Role)],
+    threshold: usize,
+    policy: StragglerPolicy,
+) -> Result<Quorum<H::Role, M>>
+where
+    H: ChoreoHandler,
+    M: DeserializeOwned + Send,
+{
+    if threshold == 0 || threshold > from.len() {
+        return Err(ChoreographyError::ProtocolViolation(format!(
+            "cannot wait for {threshold} of {} instances",
+            from.len()
+        )));
+    }
+    let mut stragglers = from.to_vec();
+    let responses = receive_first(handler, ep, &mut stragglers, threshold).await?;
+    Ok(Quorum {
+        responses,
+        stragglers,
+        policy,
+    })
+}
+
+/// Receive `count` messages from the instances of `pending`, removing each
+/// sender from it
+async fn receive_first<H, M>(
+    handler: &mut H,
+    ep: &mut H::Endpoint,
+    pending: &mut Vec<(u32, H::Role)>,
+    count: usize,
+) -> Result<Vec<(u32, M)>>
+where
+    H: ChoreoHandler,
+    M: DeserializeOwned + Send,
+{
+    let mut messages = Vec::with_capacity(count);
+    while messages.len() < count {
+        let roles: Vec<H::Role> = pending.iter().map(|&(_, role)| role).collect();
+        let (sender, message) = handler.recv_any(ep, &roles).await?;
+        let position = pending
+            .iter()
+            .position(|&(_, role)| role == sender)
+            .ok_or_else(|| {
+                ChoreographyError::ProtocolViolation(format!(
+                    "received from {sender:?}, which was not asked"
+                ))
+            })?;
+        let (index, _) = pending.remove(position);
+        messages.push((index, message));
+    }
+    Ok(messages)
+}
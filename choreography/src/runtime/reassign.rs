@@ -0,0 +1,65 @@
+// Role reassignment
+//
+// `reassign Coordinator from Worker[*] by election;` hands the role
+// `Coordinator` to one of the `Worker` instances mid-session. The statement
+// is a send of a `Handover` from the current holder of the role to every
+// candidate:
+//
+//     holder      RoleAddressBook::hand_over elects a candidate, sends each
+//                 candidate the outcome and the elected one the role's
+//                 endpoint state, and rebinds the role to it
+//     candidate   RoleAddressBook::take_over receives the outcome and
+//                 rebinds the role the same way
+//
+// Statements after the reassignment address the role through the book, so
+// they reach the new holder, which resumes the role from the state it was
+// handed. Roles that are not candidates learn of the change by calling
+// `RoleAddressBook::reassign` when the protocol tells them.
+
+use serde::{Deserialize, Serialize};
+
+/// Choice of the candidate taking over a role
+pub trait Election<A> {
+    /// Index of the elected candidate, or `None` if none can take the role
+    fn elect(&mut self, role: &str, candidates: &[(u32, &A)]) -> Option<u32>;
+}
+
+/// Elects the candidate with the lowest index
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LowestIndex;
+
+impl<A> Election<A> for LowestIndex {
+    fn elect(&mut self, _role: &str, candidates: &[(u32, &A)]) -> Option<u32> {
+        candidates.iter().map(|&(index, _)| index).min()
+    }
+}
+
+impl<A, F> Election<A> for F
+where
+    F: FnMut(&str, &[(u32, &A)]) -> Option<u32>,
+{
+    fn elect(&mut self, role: &str, candidates: &[(u32, &A)]) -> Option<u32> {
+        self(role, candidates)
+    }
+}
+
+/// Message of a role reassignment, sent to every candidate
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Handover<S> {
+    /// Role handed over
+    pub role: String,
+    /// Role family the candidates belong to
+    pub candidates: String,
+    /// Index of the elected candidate
+    pub elected: u32,
+    /// Endpoint state of the role, for the elected candidate only
+    pub state: Option<S>,
+}
+
+impl<S> Handover<S> {
+    /// Whether the instance `index` of the candidates takes over the role
+    #[must_use]
+    pub fn is_elected(&self, index: u32) -> bool {
+        self.elected == index
+    }
+}
@@ -0,0 +1,275 @@
+// Record and replay of transport frames
+//
+// `RecordingTransport` wraps a role's `runtime::blocking::Transport` and
+// logs every frame the role sends or receives, with the time it went through
+// and the peer, as JSON lines. Frames are stored hex-encoded, exactly as
+// they crossed the transport.
+//
+// `ReplayTransport` stands in for the peers of such a session: it hands the
+// role the frames it received, peer by peer and in their recorded order, so
+// the role can be rerun and debugged on its own, with no other role or
+// network involved. Frames the role sends are compared with the recording,
+// and the first one that differs fails the send, pointing at where the rerun
+// departs from the captured session.
+//
+// Peers are recorded by their `Debug` form, as in journals and traces.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, Write};
+use std::time::UNIX_EPOCH;
+use thiserror::Error;
+
+use crate::effects::{ChoreographyError, Result};
+use crate::runtime::blocking::Transport;
+use crate::runtime::monitor::ActionKind;
+
+/// Errors raised while reading a frame log
+#[derive(Debug, Error)]
+pub enum FrameLogError {
+    #[error("frame log I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("frame log line {line} is not a frame record: {reason}")]
+    Malformed { line: usize, reason: String },
+}
+
+/// One frame as it crossed the transport
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameRecord {
+    pub time_unix_nano: u64,
+    /// `send` or `receive`
+    pub action: String,
+    pub peer: String,
+    /// Hex of the frame
+    pub frame: String,
+}
+
+impl FrameRecord {
+    /// The frame bytes
+    pub fn bytes(&self) -> std::result::Result<Vec<u8>, hex::FromHexError> {
+        hex::decode(&self.frame)
+    }
+}
+
+/// Read a frame log written by [`RecordingTransport`]
+pub fn read_frames(reader: impl BufRead) -> std::result::Result<Vec<FrameRecord>, FrameLogError> {
+    let mut records = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(&line).map_err(|error| FrameLogError::Malformed {
+            line: index + 1,
+            reason: error.to_string(),
+        })?;
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// `Transport` logging every frame that goes through `inner`
+pub struct RecordingTransport<T> {
+    inner: T,
+    log: Box<dyn Write + Send>,
+}
+
+impl<T> RecordingTransport<T> {
+    /// Record the frames of `inner` to `log`, one JSON line each
+    pub fn new(inner: T, log: impl Write + Send + 'static) -> Self {
+        Self {
+            inner,
+            log: Box::new(log),
+        }
+    }
+
+    /// Record the frames of `inner` to a new file at `path`
+    pub fn create(inner: T, path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let file = std::fs::File::create(path)?;
+        Ok(Self::new(inner, std::io::BufWriter::new(file)))
+    }
+
+    /// The wrapped transport
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn record(&mut self, action: ActionKind, peer: String, frame: &[u8]) -> Result<()> {
+        let record = FrameRecord {
+            time_unix_nano: now_unix_nano(),
+            action: action.to_string(),
+            peer,
+            frame: hex::encode(frame),
+        };
+        let line = serde_json::to_string(&record)
+            .map_err(|e| ChoreographyError::Serialization(e.to_string()))?;
+        writeln!(self.log, "{line}")
+            .and_then(|()| self.log.flush())
+            .map_err(|e| ChoreographyError::Transport(format!("frame log write failed: {e}")))
+    }
+}
+
+impl<R: Copy + std::fmt::Debug, T: Transport<R>> Transport<R> for RecordingTransport<T> {
+    fn send(&mut self, to: R, frame: Vec<u8>) -> Result<()> {
+        self.record(ActionKind::Send, format!("{to:?}"), &frame)?;
+        self.inner.send(to, frame)
+    }
+
+    fn recv(&mut self, from: R) -> Result<Vec<u8>> {
+        let frame = self.inner.recv(from)?;
+        self.record(ActionKind::Receive, format!("{from:?}"), &frame)?;
+        Ok(frame)
+    }
+}
+
+/// `Transport` playing back the peers' side of a recorded session
+#[derive(Debug, Clone, Default)]
+pub struct ReplayTransport {
+    /// Frames still to be sent and received, per peer
+    sends: HashMap<String, VecDeque<Vec<u8>>>,
+    receives: HashMap<String, VecDeque<Vec<u8>>>,
+}
+
+impl ReplayTransport {
+    /// Replay `records`, as read by [`read_frames`]
+    ///
+    /// # Errors
+    ///
+    /// `ChoreographyError::Transport` if a record is neither a send nor a
+    /// receive or its frame is not hex.
+    pub fn new(records: impl IntoIterator<Item = FrameRecord>) -> Result<Self> {
+        let mut replay = Self::default();
+        for record in records {
+            let frame = record.bytes().map_err(|e| {
+                ChoreographyError::Transport(format!("recorded frame is not hex: {e}"))
+            })?;
+            let queues = match record.action.as_str() {
+                "send" => &mut replay.sends,
+                "receive" => &mut replay.receives,
+                action => {
+                    return Err(ChoreographyError::Transport(format!(
+                        "recorded action {action} is neither send nor receive"
+                    )))
+                }
+            };
+            queues.entry(record.peer).or_default().push_back(frame);
+        }
+        Ok(replay)
+    }
+
+    /// Replay the frame log at `path`
+    ///
+    /// # Errors
+    ///
+    /// `ChoreographyError::Transport` if the log cannot be read.
+    pub fn open(path: impl AsRef<std::path::Path>) -> Result<Self> {
+        let records = std::fs::File::open(path)
+            .map_err(FrameLogError::from)
+            .and_then(|file| read_frames(std::io::BufReader::new(file)))
+            .map_err(|e| ChoreographyError::Transport(e.to_string()))?;
+        Self::new(records)
+    }
+
+    /// Whether the role sent and received every recorded frame
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.sends
+            .values()
+            .chain(self.receives.values())
+            .all(VecDeque::is_empty)
+    }
+}
+
+impl<R: std::fmt::Debug> Transport<R> for ReplayTransport {
+    fn send(&mut self, to: R, frame: Vec<u8>) -> Result<()> {
+        let peer = format!("{to:?}");
+        match self.sends.get_mut(&peer).and_then(VecDeque::pop_front) {
+            Some(recorded) if recorded == frame => Ok(()),
+            Some(recorded) => Err(ChoreographyError::Transport(format!(
+                "frame sent to {peer} differs from the recording: {} instead of {}",
+                hex::encode(&frame),
+                hex::encode(recorded)
+            ))),
+            None => Err(ChoreographyError::Transport(format!(
+                "the recording has no further frame sent to {peer}"
+            ))),
+        }
+    }
+
+    fn recv(&mut self, from: R) -> Result<Vec<u8>> {
+        let peer = format!("{from:?}");
+        self.receives
+            .get_mut(&peer)
+            .and_then(VecDeque::pop_front)
+            .ok_or_else(|| {
+                ChoreographyError::Transport(format!(
+                    "the recording has no further frame from {peer}"
+                ))
+            })
+    }
+}
+
+fn now_unix_nano() -> u64 {
+    crate::runtime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::blocking::{BlockingEndpoint, ChannelTransport};
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    enum Role {
+        Client,
+        Server,
+    }
+
+    #[test]
+    fn test_record_and_replay_one_role() {
+        let log = tempfile::NamedTempFile::new().unwrap();
+        let roles = [Role::Client, Role::Server];
+        let mut transports = ChannelTransport::mesh(&roles);
+        let recording =
+            RecordingTransport::create(transports.remove(&Role::Client).unwrap(), log.path())
+                .unwrap();
+        let mut client = BlockingEndpoint::new(Role::Client, roles.to_vec(), recording);
+        let mut server = BlockingEndpoint::new(
+            Role::Server,
+            roles.to_vec(),
+            transports.remove(&Role::Server).unwrap(),
+        );
+
+        client.send(Role::Server, &"GET /".to_string()).unwrap();
+        let request: String = server.recv(Role::Client).unwrap();
+        server.send(Role::Client, &request.len()).unwrap();
+        assert_eq!(client.recv::<usize>(Role::Server).unwrap(), 5);
+        drop(client);
+
+        let records = read_frames(std::io::BufReader::new(log.reopen().unwrap())).unwrap();
+        let actions: Vec<(&str, &str)> = records
+            .iter()
+            .map(|record| (record.action.as_str(), record.peer.as_str()))
+            .collect();
+        assert_eq!(actions, [("send", "Server"), ("receive", "Server")]);
+
+        // The client runs again against the recording alone
+        let replay = ReplayTransport::open(log.path()).unwrap();
+        let mut client = BlockingEndpoint::new(Role::Client, roles.to_vec(), replay);
+        client.send(Role::Server, &"GET /".to_string()).unwrap();
+        assert_eq!(client.recv::<usize>(Role::Server).unwrap(), 5);
+        assert!(client.into_transport().is_finished());
+
+        let mut client = BlockingEndpoint::new(
+            Role::Client,
+            roles.to_vec(),
+            ReplayTransport::new(records).unwrap(),
+        );
+        assert!(client
+            .send(Role::Server, &"GET /other".to_string())
+            .is_err());
+    }
+}
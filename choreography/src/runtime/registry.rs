@@ -0,0 +1,386 @@
+// Live session tracking
+//
+// `SessionRegistry` keeps the sessions a process is running, so long-lived
+// services can see what is in flight and reclaim sessions whose peers went
+// quiet. Each session is opened as a `TrackedSession`, which the code
+// driving the role keeps for as long as the session runs: sending and
+// receiving touch it, and it ends with `complete` or `cancel`.
+//
+// Sessions idle for longer than the registry's idle timeout are removed by
+// `collect_idle`, which also cancels their `SessionHandle`, if one was
+// attached, so the role stops waiting on a peer that will not answer.
+//
+// With the `leak-detection` feature, a `TrackedSession` dropped before its
+// local type may end, without being completed or cancelled, is reported as
+// a `SessionLeak`, along with the state of its local type: the steps taken
+// and the actions it was waiting for. Attach a `MonitorSpec` to get the
+// state; without one, every drop before `complete` is a leak.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use crate::effects::SessionHandle;
+use crate::runtime::monitor::{ConformanceMonitor, MonitorSpec, MonitorViolation, ObservedEvent};
+use crate::runtime::Instant;
+
+/// Identifier of a session in its registry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SessionId(pub u64);
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "session {}", self.0)
+    }
+}
+
+/// Snapshot of a live session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionInfo {
+    pub id: SessionId,
+    pub protocol: String,
+    pub role: String,
+    /// Time since the session was last touched
+    pub idle: Duration,
+    /// Actions the local type accepted, if a monitor is attached
+    pub steps: usize,
+    /// Actions the local type admits next, empty without a monitor
+    pub expected: Vec<String>,
+}
+
+/// Session dropped mid-protocol without being completed or cancelled,
+/// reported with the `leak-detection` feature
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionLeak {
+    pub session: SessionInfo,
+}
+
+impl fmt::Display for SessionLeak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let session = &self.session;
+        write!(
+            f,
+            "{} ({} of {}) dropped after {} steps",
+            session.id, session.role, session.protocol, session.steps
+        )?;
+        if !session.expected.is_empty() {
+            write!(f, ", expecting one of [{}]", session.expected.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+struct Entry {
+    protocol: String,
+    role: String,
+    last_active: Instant,
+    monitor: Option<ConformanceMonitor>,
+    handle: Option<SessionHandle>,
+}
+
+impl Entry {
+    fn info(&self, id: u64) -> SessionInfo {
+        SessionInfo {
+            id: SessionId(id),
+            protocol: self.protocol.clone(),
+            role: self.role.clone(),
+            idle: self.last_active.elapsed(),
+            steps: self.monitor.as_ref().map_or(0, ConformanceMonitor::steps),
+            expected: self
+                .monitor
+                .as_ref()
+                .map_or_else(Vec::new, ConformanceMonitor::expected),
+        }
+    }
+
+    /// Whether dropping the session now abandons it mid-protocol
+    #[cfg(feature = "leak-detection")]
+    fn is_abandoned(&self) -> bool {
+        !self
+            .handle
+            .as_ref()
+            .is_some_and(SessionHandle::is_cancelled)
+            && !self
+                .monitor
+                .as_ref()
+                .is_some_and(ConformanceMonitor::is_complete)
+    }
+}
+
+#[derive(Default)]
+struct RegistryState {
+    next_id: u64,
+    live: BTreeMap<u64, Entry>,
+    #[cfg(feature = "leak-detection")]
+    leaks: Vec<SessionLeak>,
+}
+
+/// Sessions running in this process
+#[derive(Clone, Default)]
+pub struct SessionRegistry {
+    idle_timeout: Option<Duration>,
+    state: Arc<Mutex<RegistryState>>,
+}
+
+impl fmt::Debug for SessionRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionRegistry")
+            .field("idle_timeout", &self.idle_timeout)
+            .field("live", &self.lock().live.len())
+            .finish()
+    }
+}
+
+impl SessionRegistry {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Let `collect_idle` remove sessions idle for longer than `timeout`
+    #[must_use]
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Start tracking a session of `role` in `protocol`
+    pub fn open(&self, protocol: impl Into<String>, role: impl Into<String>) -> TrackedSession {
+        let mut state = self.lock();
+        let id = state.next_id;
+        state.next_id += 1;
+        state.live.insert(
+            id,
+            Entry {
+                protocol: protocol.into(),
+                role: role.into(),
+                last_active: Instant::now(),
+                monitor: None,
+                handle: None,
+            },
+        );
+        TrackedSession {
+            id,
+            registry: self.clone(),
+        }
+    }
+
+    /// The live sessions, oldest first
+    #[must_use]
+    pub fn live(&self) -> Vec<SessionInfo> {
+        self.lock()
+            .live
+            .iter()
+            .map(|(id, entry)| entry.info(*id))
+            .collect()
+    }
+
+    /// Remove the sessions idle for longer than the idle timeout, cancelling
+    /// their handles, and return them
+    pub fn collect_idle(&self) -> Vec<SessionInfo> {
+        let Some(timeout) = self.idle_timeout else {
+            return Vec::new();
+        };
+        let mut state = self.lock();
+        let idle: Vec<u64> = state
+            .live
+            .iter()
+            .filter(|(_, entry)| entry.last_active.elapsed() > timeout)
+            .map(|(id, _)| *id)
+            .collect();
+        idle.into_iter()
+            .filter_map(|id| state.live.remove(&id).map(|entry| (id, entry)))
+            .map(|(id, entry)| {
+                tracing::debug!(session = id, role = %entry.role, "idle session collected");
+                if let Some(handle) = &entry.handle {
+                    handle.cancel();
+                }
+                entry.info(id)
+            })
+            .collect()
+    }
+
+    /// Sessions dropped mid-protocol so far
+    #[cfg(feature = "leak-detection")]
+    #[must_use]
+    pub fn leaks(&self) -> Vec<SessionLeak> {
+        self.lock().leaks.clone()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, RegistryState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn with_entry<T>(&self, id: u64, f: impl FnOnce(&mut Entry) -> T) -> Option<T> {
+        self.lock().live.get_mut(&id).map(f)
+    }
+}
+
+/// A session tracked by a [`SessionRegistry`], for as long as it is held
+#[derive(Debug)]
+pub struct TrackedSession {
+    id: u64,
+    registry: SessionRegistry,
+}
+
+impl TrackedSession {
+    #[must_use]
+    pub fn id(&self) -> SessionId {
+        SessionId(self.id)
+    }
+
+    /// Follow the session's local type with a monitor of `spec`
+    #[must_use]
+    pub fn with_monitor(self, spec: &'static MonitorSpec) -> Self {
+        self.registry.with_entry(self.id, |entry| {
+            entry.monitor = Some(ConformanceMonitor::new(spec));
+        });
+        self
+    }
+
+    /// Cancel `handle` when the session is collected as idle or cancelled
+    #[must_use]
+    pub fn with_handle(self, handle: SessionHandle) -> Self {
+        self.registry.with_entry(self.id, |entry| {
+            entry.handle = Some(handle);
+        });
+        self
+    }
+
+    /// Record activity, postponing the idle timeout
+    pub fn touch(&self) {
+        self.registry.with_entry(self.id, |entry| {
+            entry.last_active = Instant::now();
+        });
+    }
+
+    /// Record an action of the role, checking it against the monitor if
+    /// one is attached
+    ///
+    /// # Errors
+    ///
+    /// The [`MonitorViolation`] if the local type does not admit `event`.
+    pub fn observe(&self, event: ObservedEvent) -> Result<(), MonitorViolation> {
+        self.registry
+            .with_entry(self.id, |entry| {
+                entry.last_active = Instant::now();
+                match &mut entry.monitor {
+                    Some(monitor) => monitor.observe(event),
+                    None => Ok(()),
+                }
+            })
+            .unwrap_or(Ok(()))
+    }
+
+    /// Whether the registry collected the session as idle
+    #[must_use]
+    pub fn is_expired(&self) -> bool {
+        !self.registry.lock().live.contains_key(&self.id)
+    }
+
+    /// End the session normally
+    pub fn complete(self) {
+        self.registry.lock().live.remove(&self.id);
+    }
+
+    /// End the session by cancelling it, and its handle if attached
+    pub fn cancel(self) {
+        if let Some(entry) = self.registry.lock().live.remove(&self.id) {
+            if let Some(handle) = entry.handle {
+                handle.cancel();
+            }
+        }
+    }
+}
+
+impl Drop for TrackedSession {
+    fn drop(&mut self) {
+        let entry = self.registry.lock().live.remove(&self.id);
+        #[cfg(feature = "leak-detection")]
+        if let Some(entry) = entry.filter(Entry::is_abandoned) {
+            let leak = SessionLeak {
+                session: entry.info(self.id),
+            };
+            tracing::warn!(%leak, "session dropped mid-protocol");
+            self.registry.lock().leaks.push(leak);
+        }
+        #[cfg(not(feature = "leak-detection"))]
+        let _ = entry;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::monitor::{ActionKind, Transition};
+
+    // Client: Server!Request . Server?Response
+    static CLIENT: MonitorSpec = MonitorSpec {
+        role: "Client",
+        initial: 0,
+        accepting: 2,
+        transitions: &[
+            Transition {
+                from: 0,
+                kind: ActionKind::Send,
+                peer: "Server",
+                label: "Request",
+                to: 1,
+            },
+            Transition {
+                from: 1,
+                kind: ActionKind::Receive,
+                peer: "Server",
+                label: "Response",
+                to: 2,
+            },
+        ],
+        epsilons: &[],
+    };
+
+    #[test]
+    fn test_idle_sessions_and_leaks() {
+        let registry = SessionRegistry::new().with_idle_timeout(Duration::from_millis(5));
+        let handle = SessionHandle::new();
+        let idle = registry.open("Fetch", "Client").with_handle(handle.clone());
+        let finished = registry.open("Fetch", "Client").with_monitor(&CLIENT);
+        let leaked = registry.open("Fetch", "Client").with_monitor(&CLIENT);
+        assert_eq!(registry.live().len(), 3);
+
+        std::thread::sleep(Duration::from_millis(10));
+        for session in [&finished, &leaked] {
+            session
+                .observe(ObservedEvent::sent("Server", "Request"))
+                .unwrap();
+        }
+        let collected = registry.collect_idle();
+        assert_eq!(
+            collected.iter().map(|info| info.id).collect::<Vec<_>>(),
+            [idle.id()]
+        );
+        assert!(idle.is_expired());
+        assert!(handle.is_cancelled());
+        drop(idle);
+
+        finished
+            .observe(ObservedEvent::received("Server", "Response"))
+            .unwrap();
+        drop(finished);
+        let live = registry.live();
+        assert_eq!(live.len(), 1);
+        assert_eq!(live[0].expected, ["receive Response (Server)"]);
+        drop(leaked);
+        assert!(registry.live().is_empty());
+
+        #[cfg(feature = "leak-detection")]
+        {
+            let leaks = registry.leaks();
+            assert_eq!(leaks.len(), 1);
+            assert_eq!(
+                leaks[0].to_string(),
+                "session 2 (Client of Fetch) dropped after 1 steps, expecting one of [receive Response (Server)]"
+            );
+        }
+    }
+}
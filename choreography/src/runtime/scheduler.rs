@@ -0,0 +1,550 @@
+// Scheduled session initiation
+//
+// A `Scheduler` starts sessions of one choreography over and over, for
+// recurring ceremonies such as a daily key rotation. It starts one at the
+// times of its `Schedule`s and whenever a trigger fires. Triggers come from
+// a `TriggerHandle`, which any task may clone, or as webhooks answered by a
+// `WebhookTrigger` on connections the application's listener accepts.
+//
+// Every session is built from the scheduler's `SessionConfig`: the protocol,
+// the role played locally, the roster of participants and a function making
+// the local role's endpoint. For each firing the scheduler draws a fresh
+// session ID, makes the endpoint for that session's assignment and passes a
+// `Launch` to the session function, which typically runs the bootstrap
+// handshake with `Launch::initiator` and then the role. Sessions run as
+// spawned tasks, so a slow one does not hold back the next.
+//
+// Schedules are in UTC:
+//
+//     Schedule::Every(Duration::from_secs(900))    every 15 minutes from the start
+//     Schedule::cron("0 9 * * 1-5")?               at 09:00 on weekdays
+//
+// A cron expression has the five fields minute, hour, day of month, month
+// and day of week (0 or 7 for Sunday). A field is `*` or a list of numbers
+// and ranges `a-b`, either optionally stepped with `/n`. As in cron, a day
+// matches if its day of month or its day of week does when both are
+// restricted.
+
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::channel::mpsc;
+use futures::future::{select, Either};
+use futures::io::{AsyncRead, AsyncWrite};
+use futures::{pin_mut, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
+use time::OffsetDateTime;
+use uuid::Uuid;
+
+use crate::effects::RoleId;
+use crate::runtime::bootstrap::{
+    ProtocolDescriptor, RoleAssignment, SessionAssignment, SessionInitiator,
+};
+use crate::runtime::http::{
+    read_body, read_request_head, respond, HttpError, HttpRoute, DEFAULT_MAX_BODY_SIZE,
+};
+
+/// Errors in a schedule
+#[derive(Debug, Error)]
+pub enum ScheduleError {
+    #[error("Invalid cron expression '{expression}': {reason}")]
+    Cron { expression: String, reason: String },
+}
+
+/// When a scheduler starts sessions on its own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Schedule {
+    /// At a fixed interval from the scheduler's start
+    Every(Duration),
+    /// At the times a cron expression matches
+    Cron(Cron),
+}
+
+impl Schedule {
+    /// Schedule for the cron expression `expression`
+    ///
+    /// # Errors
+    ///
+    /// The expression does not have five valid fields.
+    pub fn cron(expression: &str) -> Result<Self, ScheduleError> {
+        Cron::parse(expression).map(Self::Cron)
+    }
+
+    /// First time after `after` the schedule fires at, if any
+    #[must_use]
+    pub fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        match self {
+            Self::Every(interval) if interval.is_zero() => None,
+            Self::Every(interval) => after.checked_add(*interval),
+            Self::Cron(cron) => cron.next_after(after),
+        }
+    }
+}
+
+/// Parsed cron expression, matched in UTC
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    expression: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// Whether the day of month and the day of week are both restricted
+    either_day: bool,
+}
+
+impl Cron {
+    /// Parse the five fields of `expression`
+    ///
+    /// # Errors
+    ///
+    /// The expression does not have five valid fields.
+    pub fn parse(expression: &str) -> Result<Self, ScheduleError> {
+        let error = |reason: String| ScheduleError::Cron {
+            expression: expression.to_string(),
+            reason,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(error(format!("expected 5 fields, found {}", fields.len())));
+        };
+        let mut weekdays = cron_field(weekday, 0, 7).map_err(error)?;
+        // Both 0 and 7 are Sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+        Ok(Self {
+            expression: expression.to_string(),
+            minutes: cron_field(minute, 0, 59).map_err(error)?,
+            hours: cron_field(hour, 0, 23).map_err(error)?,
+            days: cron_field(day, 1, 31).map_err(error)?,
+            months: cron_field(month, 1, 12).map_err(error)?,
+            weekdays,
+            either_day: day != "*" && weekday != "*",
+        })
+    }
+
+    /// First whole minute after `after` the expression matches, looking up
+    /// to five years ahead
+    #[must_use]
+    pub fn next_after(&self, after: SystemTime) -> Option<SystemTime> {
+        const MINUTE: u64 = 60;
+        const HOUR: u64 = 60 * MINUTE;
+        const DAY: u64 = 24 * HOUR;
+        let start = after.duration_since(UNIX_EPOCH).ok()?.as_secs() / MINUTE * MINUTE + MINUTE;
+        let end = start + 5 * 366 * DAY;
+        let mut time = start;
+        while time < end {
+            let date = OffsetDateTime::from_unix_timestamp(i64::try_from(time).ok()?).ok()?;
+            let day_matches = if self.either_day {
+                has(self.days, date.day())
+                    || has(self.weekdays, date.weekday().number_days_from_sunday())
+            } else {
+                has(self.days, date.day())
+                    && has(self.weekdays, date.weekday().number_days_from_sunday())
+            };
+            if !has(self.months, u8::from(date.month())) || !day_matches {
+                time = time - time % DAY + DAY;
+            } else if !has(self.hours, date.hour()) {
+                time = time - time % HOUR + HOUR;
+            } else if !has(self.minutes, date.minute()) {
+                time += MINUTE;
+            } else {
+                return Some(UNIX_EPOCH + Duration::from_secs(time));
+            }
+        }
+        None
+    }
+}
+
+impl fmt::Display for Cron {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.expression)
+    }
+}
+
+fn has(set: u64, value: u8) -> bool {
+    set & (1 << value) != 0
+}
+
+/// Values between `min` and `max` one field of a cron expression matches,
+/// as a bit set
+fn cron_field(field: &str, min: u8, max: u8) -> Result<u64, String> {
+    let number = |text: &str| -> Result<u8, String> {
+        match text.parse::<u8>() {
+            Ok(value) if (min..=max).contains(&value) => Ok(value),
+            _ => Err(format!("'{text}' is not a value from {min} to {max}")),
+        }
+    };
+    let mut set = 0;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => match step.parse::<u8>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("'{step}' is not a step")),
+            },
+            None => (item, 1),
+        };
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (number(first)?, number(last)?),
+            None => (number(range)?, number(range)?),
+        };
+        if first > last {
+            return Err(format!("'{range}' is an empty range"));
+        }
+        for value in (first..=last).step_by(usize::from(step)) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+/// What started a session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Trigger {
+    /// A schedule, at the time it was due
+    Scheduled { at: SystemTime },
+    /// A `TriggerHandle` or a webhook, naming where it came from and
+    /// carrying a payload
+    External { source: String, payload: Vec<u8> },
+}
+
+/// Makes the local role's endpoint for a session from its assignment
+type EndpointFn<R, E> = Arc<dyn Fn(&SessionAssignment<R>) -> E + Send + Sync>;
+
+/// Template of the sessions a `Scheduler` starts
+pub struct SessionConfig<R, E> {
+    protocol: ProtocolDescriptor,
+    local_role: R,
+    roster: Vec<RoleAssignment<R>>,
+    endpoint: EndpointFn<R, E>,
+}
+
+impl<R: RoleId, E> SessionConfig<R, E> {
+    /// Sessions of `protocol` played locally as `local_role`, whose endpoint
+    /// for each session `endpoint` makes from its assignment
+    pub fn new(
+        protocol: ProtocolDescriptor,
+        local_role: R,
+        endpoint: impl Fn(&SessionAssignment<R>) -> E + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            protocol,
+            local_role,
+            roster: Vec::new(),
+            endpoint: Arc::new(endpoint),
+        }
+    }
+
+    /// Assign `role` to `participant` in every session, the local role too
+    #[must_use]
+    pub fn assign(mut self, role: R, participant: impl Into<String>) -> Self {
+        self.roster.push(RoleAssignment {
+            role,
+            participant: participant.into(),
+        });
+        self
+    }
+
+    /// Assignment of the local role in the session `session_id`
+    #[must_use]
+    pub fn assignment(&self, session_id: Uuid) -> SessionAssignment<R> {
+        SessionAssignment {
+            session_id,
+            protocol: self.protocol.clone(),
+            local_role: self.local_role,
+            roster: self.roster.clone(),
+        }
+    }
+
+    /// Endpoint of the local role for the session of `assignment`
+    pub fn endpoint(&self, assignment: &SessionAssignment<R>) -> E {
+        (self.endpoint)(assignment)
+    }
+}
+
+impl<R: fmt::Debug, E> fmt::Debug for SessionConfig<R, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionConfig")
+            .field("protocol", &self.protocol)
+            .field("local_role", &self.local_role)
+            .field("roster", &self.roster)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A session a `Scheduler` started, for the session function to run
+#[derive(Debug)]
+pub struct Launch<R, E> {
+    /// Number of sessions the scheduler started before this one
+    pub index: u64,
+    pub trigger: Trigger,
+    pub assignment: SessionAssignment<R>,
+    pub endpoint: E,
+}
+
+impl<R, E> Launch<R, E>
+where
+    R: RoleId + Serialize + DeserializeOwned,
+{
+    /// Initiator of the bootstrap handshake of this session
+    #[must_use]
+    pub fn initiator(&self) -> SessionInitiator<R> {
+        let assignment = &self.assignment;
+        assignment.roster.iter().fold(
+            SessionInitiator::new(assignment.protocol.clone(), assignment.local_role)
+                .with_session_id(assignment.session_id),
+            |initiator, a| initiator.assign(a.role, a.participant.clone()),
+        )
+    }
+}
+
+#[derive(Debug)]
+enum Command {
+    Fire(Trigger),
+    Stop,
+}
+
+/// Fires the triggers of a `Scheduler`, shared by its clones
+#[derive(Debug, Clone)]
+pub struct TriggerHandle {
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl TriggerHandle {
+    /// Start a session, saying it was started by `source` with `payload`;
+    /// returns whether the scheduler is still running
+    pub fn fire(&self, source: impl Into<String>, payload: Vec<u8>) -> bool {
+        let trigger = Trigger::External {
+            source: source.into(),
+            payload,
+        };
+        self.commands.unbounded_send(Command::Fire(trigger)).is_ok()
+    }
+
+    /// Stop the scheduler; sessions it started keep running
+    pub fn stop(&self) {
+        let _ = self.commands.unbounded_send(Command::Stop);
+    }
+}
+
+/// Starts a session for every webhook request, for a `Scheduler`
+#[derive(Debug, Clone)]
+pub struct WebhookTrigger {
+    handle: TriggerHandle,
+    max_body_size: usize,
+}
+
+impl WebhookTrigger {
+    #[must_use]
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Answer one webhook request made over `io`, starting a session with
+    /// the route as its source and the body as its payload; returns the
+    /// route
+    ///
+    /// Requests are answered `202 Accepted`, or `503 Service Unavailable`
+    /// once the scheduler stopped.
+    ///
+    /// # Errors
+    ///
+    /// The request could not be read or answered, or the scheduler stopped.
+    pub async fn accept<IO>(&self, mut io: IO) -> Result<HttpRoute, HttpError>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let (route, length) = read_request_head(&mut io).await?;
+        let body = read_body(&mut io, length, self.max_body_size).await?;
+        if !self.handle.fire(route.to_string(), body) {
+            respond(&mut io, "503 Service Unavailable").await?;
+            return Err(HttpError::Closed);
+        }
+        respond(&mut io, "202 Accepted").await?;
+        tracing::debug!(%route, "session triggered by webhook");
+        Ok(route)
+    }
+}
+
+/// Starts sessions of one choreography on schedules and triggers
+pub struct Scheduler<R, E> {
+    config: SessionConfig<R, E>,
+    schedules: Vec<Schedule>,
+    handle: TriggerHandle,
+    commands: mpsc::UnboundedReceiver<Command>,
+}
+
+impl<R: fmt::Debug, E> fmt::Debug for Scheduler<R, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scheduler")
+            .field("config", &self.config)
+            .field("schedules", &self.schedules)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<R, E> Scheduler<R, E>
+where
+    R: RoleId,
+    E: Send + 'static,
+{
+    /// A scheduler starting sessions from `config`, on triggers only until
+    /// given a schedule
+    pub fn new(config: SessionConfig<R, E>) -> Self {
+        let (sender, commands) = mpsc::unbounded();
+        Self {
+            config,
+            schedules: Vec::new(),
+            handle: TriggerHandle { commands: sender },
+            commands,
+        }
+    }
+
+    /// Also start a session at the times of `schedule`
+    #[must_use]
+    pub fn with_schedule(mut self, schedule: Schedule) -> Self {
+        self.schedules.push(schedule);
+        self
+    }
+
+    /// Handle firing triggers of this scheduler and stopping it
+    #[must_use]
+    pub fn handle(&self) -> TriggerHandle {
+        self.handle.clone()
+    }
+
+    /// Webhook endpoint firing triggers of this scheduler
+    #[must_use]
+    pub fn webhook(&self) -> WebhookTrigger {
+        WebhookTrigger {
+            handle: self.handle(),
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+        }
+    }
+
+    /// Start a session with `session` whenever a schedule is due or a
+    /// trigger fires, until stopped through a `TriggerHandle`; returns the
+    /// number of sessions started
+    ///
+    /// A session failing is logged, and does not stop the scheduler.
+    pub async fn run<F, Fut, T, Err>(mut self, session: F) -> u64
+    where
+        F: Fn(Launch<R, E>) -> Fut,
+        Fut: Future<Output = Result<T, Err>> + Send + 'static,
+        Err: fmt::Display,
+    {
+        let mut due: Vec<Option<SystemTime>> = {
+            let now = crate::runtime::now();
+            self.schedules.iter().map(|s| s.next_after(now)).collect()
+        };
+        let mut started = 0;
+        loop {
+            let next = due.iter().flatten().min().copied();
+            let timer = async {
+                match next {
+                    Some(at) => {
+                        let wait = at.duration_since(crate::runtime::now()).unwrap_or_default();
+                        crate::runtime::sleep(wait).await;
+                    }
+                    None => futures::future::pending().await,
+                }
+            };
+            pin_mut!(timer);
+            let mut triggers = Vec::new();
+            match select(self.commands.next(), timer).await {
+                Either::Left((Some(Command::Fire(trigger)), _)) => triggers.push(trigger),
+                Either::Left((Some(Command::Stop) | None, _)) => return started,
+                Either::Right(_) => {
+                    let now = crate::runtime::now();
+                    for (schedule, due) in self.schedules.iter().zip(&mut due) {
+                        if let Some(at) = due.filter(|at| *at <= now) {
+                            triggers.push(Trigger::Scheduled { at });
+                            *due = schedule.next_after(at.max(now));
+                        }
+                    }
+                }
+            }
+            for trigger in triggers {
+                let assignment = self.config.assignment(Uuid::new_v4());
+                let launch = Launch {
+                    index: started,
+                    trigger,
+                    endpoint: self.config.endpoint(&assignment),
+                    assignment,
+                };
+                let session_id = launch.assignment.session_id;
+                let running = session(launch);
+                crate::runtime::spawn(async move {
+                    if let Err(error) = running.await {
+                        tracing::warn!(%session_id, %error, "scheduled session failed");
+                    }
+                });
+                started += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024 in `month` at `day`, `hour`:`minute` UTC
+    fn at(month: u8, day: u8, hour: u8, minute: u8) -> SystemTime {
+        let month = time::Month::try_from(month).unwrap();
+        let date = time::Date::from_calendar_date(2024, month, day).unwrap();
+        let time = date.with_hms(hour, minute, 0).unwrap().assume_utc();
+        UNIX_EPOCH + Duration::from_secs(time.unix_timestamp().unsigned_abs())
+    }
+
+    #[test]
+    fn test_cron_finds_the_next_matching_minute() {
+        // 2024-03-01 is a Friday
+        let weekdays = Schedule::cron("30 9 * * 1-5").unwrap();
+        assert_eq!(weekdays.next_after(at(3, 1, 8, 0)), Some(at(3, 1, 9, 30)));
+        assert_eq!(weekdays.next_after(at(3, 1, 9, 30)), Some(at(3, 4, 9, 30)));
+
+        let quarterly = Schedule::cron("*/20 0 1 1,4,7,10 *").unwrap();
+        assert_eq!(quarterly.next_after(at(3, 1, 0, 0)), Some(at(4, 1, 0, 0)));
+        assert_eq!(quarterly.next_after(at(4, 1, 0, 0)), Some(at(4, 1, 0, 20)));
+
+        // The 13th or any Sunday
+        let either = Schedule::cron("0 12 13 * 7").unwrap();
+        assert_eq!(either.next_after(at(3, 1, 0, 0)), Some(at(3, 3, 12, 0)));
+        assert_eq!(either.next_after(at(3, 11, 0, 0)), Some(at(3, 13, 12, 0)));
+
+        assert_eq!(
+            Schedule::cron("0 0 30 2 *")
+                .unwrap()
+                .next_after(at(3, 1, 0, 0)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_invalid_cron_expressions_are_rejected() {
+        for expression in [
+            "* * * *",
+            "60 * * * *",
+            "* * 0 * *",
+            "5-1 * * * *",
+            "*/0 * * * *",
+        ] {
+            assert!(matches!(
+                Schedule::cron(expression),
+                Err(ScheduleError::Cron { .. })
+            ));
+        }
+        assert_eq!(
+            Schedule::cron("0 24 * * *").unwrap_err().to_string(),
+            "Invalid cron expression '0 24 * * *': '24' is not a value from 0 to 23"
+        );
+    }
+}
@@ -0,0 +1,354 @@
+// Scripted decisions
+//
+// Lets the branch a role chooses, and the capabilities its guarded steps
+// require, be decided by a script instead of compiled code, so decision logic
+// of a long-running deployment can change without regenerating or rebuilding
+// the roles. A `DecisionScript` is the script engine: it calls a function of
+// the script named after the choice and returns the label it picked.
+//
+// Every decision enum generated for the handler API implements `Decision`,
+// which names the choosing role, the script function and the labels of the
+// choice. A `choose_*` handler method delegates to the script with `decide`,
+// passing whatever context the script needs:
+//
+//     async fn choose_accept_or_reject(&mut self) -> Result<ServerChoiceAcceptReject> {
+//         Ok(decide(&self.script, &self.order)?)
+//     }
+//
+// with the script defining one function per choice, taking the choosing role
+// and the context, and returning a label of the choice:
+//
+//     fn choose_accept_or_reject(role, order) {
+//         if order.total > 1000 { "reject" } else { "accept" }
+//     }
+//
+// A label outside the choice is an error rather than a branch. Capability
+// guards go through the script's `allow(role, capability)` function when a
+// `ScriptGuard` is the `CapabilityProvider` of the `Guarded` middleware; a
+// script that cannot answer denies the step.
+//
+// The `rhai` feature provides `RhaiScript`, which reloads its script file when
+// it changes. A script that no longer compiles is reported and the previous
+// one stays in use, so an edit in progress never stops running sessions.
+// Other engines, such as Lua, implement `DecisionScript` the same way.
+
+use std::sync::Arc;
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::effects::ChoreographyError;
+use crate::runtime::guard::CapabilityProvider;
+
+/// Script function deciding capability guards
+pub const ALLOW_FUNCTION: &str = "allow";
+
+/// Branches of a choice, generated for each decision enum
+pub trait Decision: Sized + Copy {
+    /// Role making the choice
+    const ROLE: &'static str;
+    /// Script function deciding the choice, named like its handler method
+    const FUNCTION: &'static str;
+    /// Labels of the branches, in declaration order
+    const LABELS: &'static [&'static str];
+
+    /// Branch labelled `label`, if the choice has one
+    fn from_label(label: &str) -> Option<Self>;
+
+    /// Label of the branch
+    fn label(self) -> &'static str;
+}
+
+/// Errors raised by decision scripts
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum ScriptError {
+    #[error("script does not compile: {0}")]
+    Compile(String),
+
+    #[error("cannot read script {path}: {reason}")]
+    Read { path: String, reason: String },
+
+    #[error("script has no function {0}")]
+    MissingFunction(String),
+
+    #[error("context cannot be passed to the script: {0}")]
+    Context(String),
+
+    #[error("{function} failed: {reason}")]
+    Call { function: String, reason: String },
+
+    #[error("{function} chose {label:?}, not one of {expected:?}")]
+    UnknownLabel {
+        function: String,
+        label: String,
+        expected: Vec<String>,
+    },
+}
+
+impl From<ScriptError> for ChoreographyError {
+    fn from(err: ScriptError) -> Self {
+        ChoreographyError::Script(err.to_string())
+    }
+}
+
+/// Script engine deciding choices and guards
+pub trait DecisionScript: Send + Sync {
+    /// Call `function` of the script with `role` and `context`, returning
+    /// the label it picked
+    ///
+    /// # Errors
+    ///
+    /// The script has no such function, it failed, or it returned no label.
+    fn choose(
+        &self,
+        function: &str,
+        role: &str,
+        context: &serde_json::Value,
+    ) -> Result<String, ScriptError>;
+
+    /// Whether `role` holds `capability`, according to the script's `allow`
+    /// function
+    ///
+    /// # Errors
+    ///
+    /// The script has no `allow` function, it failed, or it returned no
+    /// boolean.
+    fn allow(&self, role: &str, capability: &str) -> Result<bool, ScriptError>;
+}
+
+impl<S: DecisionScript + ?Sized> DecisionScript for Arc<S> {
+    fn choose(
+        &self,
+        function: &str,
+        role: &str,
+        context: &serde_json::Value,
+    ) -> Result<String, ScriptError> {
+        (**self).choose(function, role, context)
+    }
+
+    fn allow(&self, role: &str, capability: &str) -> Result<bool, ScriptError> {
+        (**self).allow(role, capability)
+    }
+}
+
+/// Branch of `D` that `script` picks given `context`
+///
+/// # Errors
+///
+/// The script failed, or picked a label that is not a branch of `D`.
+pub fn decide<D: Decision, S: DecisionScript + ?Sized>(
+    script: &S,
+    context: &impl Serialize,
+) -> Result<D, ScriptError> {
+    let context = serde_json::to_value(context).map_err(|e| ScriptError::Context(e.to_string()))?;
+    let label = script.choose(D::FUNCTION, D::ROLE, &context)?;
+    let decision = D::from_label(&label).ok_or_else(|| ScriptError::UnknownLabel {
+        function: D::FUNCTION.to_string(),
+        label: label.clone(),
+        expected: D::LABELS.iter().map(ToString::to_string).collect(),
+    })?;
+    tracing::debug!(role = D::ROLE, function = D::FUNCTION, %label, "script decided");
+    Ok(decision)
+}
+
+/// Capability provider asking a decision script
+#[derive(Debug, Clone)]
+pub struct ScriptGuard<S> {
+    script: S,
+}
+
+impl<S: DecisionScript> ScriptGuard<S> {
+    #[must_use]
+    pub fn new(script: S) -> Self {
+        Self { script }
+    }
+}
+
+impl<S: DecisionScript> CapabilityProvider for ScriptGuard<S> {
+    fn has_capability(&self, role: &str, capability: &str) -> bool {
+        self.script.allow(role, capability).unwrap_or_else(|err| {
+            tracing::warn!(role, capability, %err, "script could not decide guard, denying");
+            false
+        })
+    }
+}
+
+#[cfg(feature = "rhai")]
+mod rhai_script {
+    use std::path::{Path, PathBuf};
+    use std::sync::{Arc, Mutex, RwLock};
+    use std::time::SystemTime;
+
+    use rhai::{Dynamic, Engine, Scope, AST};
+
+    use super::{DecisionScript, ScriptError, ALLOW_FUNCTION};
+
+    /// File a script was loaded from, and its modification time then
+    #[derive(Debug)]
+    struct Watched {
+        path: PathBuf,
+        modified: Mutex<Option<SystemTime>>,
+    }
+
+    /// Decision script written in Rhai
+    #[derive(Debug)]
+    pub struct RhaiScript {
+        engine: Engine,
+        ast: RwLock<Arc<AST>>,
+        file: Option<Watched>,
+    }
+
+    impl RhaiScript {
+        /// Script compiled from `source`
+        ///
+        /// # Errors
+        ///
+        /// The script does not compile.
+        pub fn new(source: &str) -> Result<Self, ScriptError> {
+            Self::with_engine(Engine::new(), source)
+        }
+
+        /// Script compiled from `source` by `engine`, which may limit the
+        /// script or register functions for it
+        ///
+        /// # Errors
+        ///
+        /// The script does not compile.
+        pub fn with_engine(engine: Engine, source: &str) -> Result<Self, ScriptError> {
+            let ast = compile(&engine, source)?;
+            Ok(Self {
+                engine,
+                ast: RwLock::new(Arc::new(ast)),
+                file: None,
+            })
+        }
+
+        /// Script loaded from the file at `path`, reloaded whenever the file
+        /// changes
+        ///
+        /// # Errors
+        ///
+        /// The file cannot be read or does not compile.
+        pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ScriptError> {
+            let path = path.as_ref().to_path_buf();
+            let modified = modified(&path)?;
+            let mut script = Self::new(&read(&path)?)?;
+            script.file = Some(Watched {
+                path,
+                modified: Mutex::new(modified),
+            });
+            Ok(script)
+        }
+
+        /// Replace the script with `source`, keeping the current one if
+        /// `source` does not compile
+        ///
+        /// Decisions already running finish with the script they started with.
+        ///
+        /// # Errors
+        ///
+        /// `source` does not compile.
+        pub fn reload(&self, source: &str) -> Result<(), ScriptError> {
+            let ast = compile(&self.engine, source)?;
+            *self.ast.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(ast);
+            Ok(())
+        }
+
+        /// Reload the script file if it changed since it was last loaded,
+        /// returning whether it did
+        ///
+        /// Decisions refresh the script themselves, so this only reports
+        /// reload errors earlier.
+        ///
+        /// # Errors
+        ///
+        /// The file cannot be read or no longer compiles; the current
+        /// script stays in use.
+        pub fn refresh(&self) -> Result<bool, ScriptError> {
+            let Some(file) = &self.file else {
+                return Ok(false);
+            };
+            let modified = modified(&file.path)?;
+            let mut loaded = file.modified.lock().unwrap_or_else(|e| e.into_inner());
+            if modified == *loaded {
+                return Ok(false);
+            }
+            // Not retried until the file changes again
+            *loaded = modified;
+            self.reload(&read(&file.path)?)?;
+            tracing::info!(path = %file.path.display(), "decision script reloaded");
+            Ok(true)
+        }
+
+        fn call(&self, function: &str, args: [Dynamic; 2]) -> Result<Dynamic, ScriptError> {
+            if let Err(err) = self.refresh() {
+                tracing::warn!(%err, "keeping the previous decision script");
+            }
+            let ast = Arc::clone(&self.ast.read().unwrap_or_else(|e| e.into_inner()));
+            let defined = ast
+                .iter_functions()
+                .any(|f| f.name == function && f.params.len() == args.len());
+            if !defined {
+                return Err(ScriptError::MissingFunction(function.to_string()));
+            }
+            self.engine
+                .call_fn::<Dynamic>(&mut Scope::new(), &ast, function, args)
+                .map_err(|e| ScriptError::Call {
+                    function: function.to_string(),
+                    reason: e.to_string(),
+                })
+        }
+    }
+
+    impl DecisionScript for RhaiScript {
+        fn choose(
+            &self,
+            function: &str,
+            role: &str,
+            context: &serde_json::Value,
+        ) -> Result<String, ScriptError> {
+            let context = rhai::serde::to_dynamic(context)
+                .map_err(|e| ScriptError::Context(e.to_string()))?;
+            let label = self.call(function, [role.into(), context])?;
+            label
+                .into_immutable_string()
+                .map(|label| label.to_string())
+                .map_err(|kind| ScriptError::Call {
+                    function: function.to_string(),
+                    reason: format!("returned {kind}, not a label"),
+                })
+        }
+
+        fn allow(&self, role: &str, capability: &str) -> Result<bool, ScriptError> {
+            let allowed = self.call(ALLOW_FUNCTION, [role.into(), capability.into()])?;
+            allowed.as_bool().map_err(|kind| ScriptError::Call {
+                function: ALLOW_FUNCTION.to_string(),
+                reason: format!("returned {kind}, not a boolean"),
+            })
+        }
+    }
+
+    fn compile(engine: &Engine, source: &str) -> Result<AST, ScriptError> {
+        engine
+            .compile(source)
+            .map_err(|e| ScriptError::Compile(e.to_string()))
+    }
+
+    fn read(path: &Path) -> Result<String, ScriptError> {
+        std::fs::read_to_string(path).map_err(|e| ScriptError::Read {
+            path: path.display().to_string(),
+            reason: e.to_string(),
+        })
+    }
+
+    fn modified(path: &Path) -> Result<Option<SystemTime>, ScriptError> {
+        let metadata = std::fs::metadata(path).map_err(|e| ScriptError::Read {
+            path: path.display().to_string(),
+            reason: e.to_string(),
+        })?;
+        Ok(metadata.modified().ok())
+    }
+}
+
+#[cfg(feature = "rhai")]
+pub use rhai_script::RhaiScript;
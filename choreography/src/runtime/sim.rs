@@ -0,0 +1,760 @@
+// Deterministic simulation
+//
+// A `Simulation` runs every role of a session on one thread against a
+// virtual clock. Roles talk through `SimHandler`s, which put each message in
+// flight with a delay drawn from the link's `Latency` and a seeded generator,
+// so a seed reproduces the same interleaving, timings and trace on every run.
+// Messages between two roles arrive in the order they were sent; messages on
+// different links may overtake each other.
+//
+// The scheduler polls roles until none can progress, then advances the clock
+// straight to the next delivery or timer. Nothing waits in real time, so
+// long timeouts cost nothing. When no event is left and roles are still
+// waiting, the run ends with those roles reported as blocked.
+//
+// `Fault::Crash` stops a role at its k-th communication step: that step and
+// every later one fail, and messages to the role are dropped on arrival.
+// Sends to a role that has already crashed fail with
+// `ChoreographyError::PeerFailed`, which sends with `or on failure` recover
+// from.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::future::{self, Either};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::effects::{ChoreoHandler, ChoreographyError, Label, Result, RoleId};
+use crate::runtime::harness::RoleFuture;
+
+/// Delay of messages on a link
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Latency {
+    Fixed(Duration),
+    /// Uniformly distributed between `min` and `max`, inclusive
+    Uniform {
+        min: Duration,
+        max: Duration,
+    },
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Latency::Fixed(Duration::ZERO)
+    }
+}
+
+/// A failure to inject into a simulated session
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault<R> {
+    /// `role` crashes at its `step`-th send, receive, choice or offer,
+    /// counting from 1
+    Crash { role: R, step: usize },
+}
+
+/// Something that happened in a simulated session, at virtual time `at`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimEvent<R> {
+    Sent {
+        at: Duration,
+        from: R,
+        to: R,
+        deliver_at: Duration,
+    },
+    Delivered {
+        at: Duration,
+        from: R,
+        to: R,
+    },
+    /// The recipient had crashed when the message arrived
+    Dropped {
+        at: Duration,
+        from: R,
+        to: R,
+    },
+    Crashed {
+        at: Duration,
+        role: R,
+        step: usize,
+    },
+}
+
+/// Outcome of a simulated session
+#[derive(Debug)]
+pub struct SimReport<R> {
+    /// Result of every role that finished, in the order they finished
+    pub outcomes: Vec<(R, Result<()>)>,
+    /// Roles still waiting when no event was left
+    pub blocked: Vec<R>,
+    /// Virtual time at the end of the run
+    pub elapsed: Duration,
+    pub trace: Vec<SimEvent<R>>,
+}
+
+impl<R: RoleId> SimReport<R> {
+    /// Whether every role finished without an error
+    #[must_use]
+    pub fn is_success(&self) -> bool {
+        self.blocked.is_empty() && self.outcomes.iter().all(|(_, outcome)| outcome.is_ok())
+    }
+
+    /// Result of `role`, if it finished
+    #[must_use]
+    pub fn outcome(&self, role: R) -> Option<&Result<()>> {
+        self.outcomes
+            .iter()
+            .find(|(r, _)| *r == role)
+            .map(|(_, outcome)| outcome)
+    }
+}
+
+/// Single-threaded session with virtual time, seeded delays and faults
+pub struct Simulation<R> {
+    state: Arc<Mutex<SimState<R>>>,
+}
+
+impl<R: RoleId> Simulation<R> {
+    /// Simulation whose random delays are drawn from `seed`
+    #[must_use]
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(SimState {
+                now: Duration::ZERO,
+                rng: SplitMix64(seed),
+                latency: Latency::default(),
+                links: Vec::new(),
+                faults: Vec::new(),
+                steps: Vec::new(),
+                crashed: Vec::new(),
+                sequence: 0,
+                in_flight: Vec::new(),
+                inboxes: HashMap::new(),
+                channel_clock: HashMap::new(),
+                receivers: Vec::new(),
+                timers: Vec::new(),
+                trace: Vec::new(),
+            })),
+        }
+    }
+
+    /// Delay of every link without its own latency
+    #[must_use]
+    pub fn with_latency(self, latency: Latency) -> Self {
+        self.lock().latency = latency;
+        self
+    }
+
+    /// Delay of messages from `from` to `to`
+    #[must_use]
+    pub fn with_link_latency(self, from: R, to: R, latency: Latency) -> Self {
+        {
+            let mut state = self.lock();
+            state.links.retain(|(f, t, _)| (*f, *t) != (from, to));
+            state.links.push((from, to, latency));
+        }
+        self
+    }
+
+    #[must_use]
+    pub fn with_fault(self, fault: Fault<R>) -> Self {
+        self.lock().faults.push(fault);
+        self
+    }
+
+    /// A handler for each of `roles`, connected through this simulation
+    #[must_use]
+    pub fn mesh(&self, roles: &[R]) -> HashMap<R, SimHandler<R>> {
+        roles
+            .iter()
+            .map(|&role| {
+                let handler = SimHandler {
+                    role,
+                    peers: roles.iter().copied().filter(|&r| r != role).collect(),
+                    state: Arc::clone(&self.state),
+                };
+                (role, handler)
+            })
+            .collect()
+    }
+
+    /// Complete once the virtual clock has advanced by `duration`
+    pub fn sleep(&self, duration: Duration) -> impl Future<Output = ()> + Send + Unpin {
+        sleep(&self.state, duration)
+    }
+
+    /// Current virtual time
+    #[must_use]
+    pub fn now(&self) -> Duration {
+        self.lock().now
+    }
+
+    /// Run `roles` until all finish or none can progress
+    pub fn run(self, roles: Vec<(R, RoleFuture<'static>)>) -> SimReport<R> {
+        use futures::executor::LocalPool;
+        use futures::task::LocalSpawnExt;
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let mut pool = LocalPool::new();
+        let spawner = pool.spawner();
+        let outcomes = Rc::new(RefCell::new(Vec::new()));
+        let all: Vec<R> = roles.iter().map(|(role, _)| *role).collect();
+        for (role, future) in roles {
+            let finished = Rc::clone(&outcomes);
+            let task = async move {
+                let outcome = future.await;
+                finished.borrow_mut().push((role, outcome));
+            };
+            if let Err(e) = spawner.spawn_local(task) {
+                outcomes
+                    .borrow_mut()
+                    .push((role, Err(ChoreographyError::Transport(e.to_string()))));
+            }
+        }
+
+        loop {
+            pool.run_until_stalled();
+            if outcomes.borrow().len() == all.len() {
+                break;
+            }
+            let wakers = self.lock().advance();
+            match wakers {
+                Some(wakers) => wakers.into_iter().for_each(Waker::wake),
+                None => break,
+            }
+        }
+
+        let outcomes = outcomes.replace(Vec::new());
+        let blocked = all
+            .into_iter()
+            .filter(|role| !outcomes.iter().any(|(r, _)| r == role))
+            .collect();
+        let mut state = self.lock();
+        SimReport {
+            outcomes,
+            blocked,
+            elapsed: state.now,
+            trace: std::mem::take(&mut state.trace),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, SimState<R>> {
+        lock(&self.state)
+    }
+}
+
+/// `ChoreoHandler` of one role in a [`Simulation`], with `()` as endpoint
+pub struct SimHandler<R> {
+    role: R,
+    /// Roles told about the choices this role makes
+    peers: Vec<R>,
+    state: Arc<Mutex<SimState<R>>>,
+}
+
+impl<R: RoleId> SimHandler<R> {
+    fn send_frame(&self, to: R, frame: Vec<u8>) -> Result<()> {
+        let mut state = lock(&self.state);
+        state.step(self.role)?;
+        if state.crashed.contains(&to) {
+            return Err(ChoreographyError::PeerFailed(format!("{to:?} crashed")));
+        }
+        state.send(self.role, to, frame);
+        Ok(())
+    }
+
+    async fn recv_frame(&self, from: R) -> Result<Vec<u8>> {
+        lock(&self.state).step(self.role)?;
+        let to = self.role;
+        future::poll_fn(|cx| lock(&self.state).poll_recv(from, to, cx)).await
+    }
+}
+
+#[async_trait]
+impl<R: RoleId> ChoreoHandler for SimHandler<R> {
+    type Role = R;
+    type Endpoint = ();
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        let frame =
+            bincode::serialize(msg).map_err(|e| ChoreographyError::Serialization(e.to_string()))?;
+        self.send_frame(to, frame)
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        from: Self::Role,
+    ) -> Result<M> {
+        let frame = self.recv_frame(from).await?;
+        bincode::deserialize(&frame).map_err(|e| ChoreographyError::Serialization(e.to_string()))
+    }
+
+    async fn choose(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        _who: Self::Role,
+        label: Label,
+    ) -> Result<()> {
+        let frame = bincode::serialize(label.0)
+            .map_err(|e| ChoreographyError::Serialization(e.to_string()))?;
+        let mut state = lock(&self.state);
+        state.step(self.role)?;
+        for &peer in &self.peers {
+            state.send(self.role, peer, frame.clone());
+        }
+        Ok(())
+    }
+
+    async fn offer(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        let frame = self.recv_frame(from).await?;
+        let label: String = bincode::deserialize(&frame)
+            .map_err(|e| ChoreographyError::Serialization(e.to_string()))?;
+        Label::resolve(&label, labels)
+    }
+
+    async fn with_timeout<F, T>(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        at: Self::Role,
+        dur: Duration,
+        body: F,
+    ) -> Result<T>
+    where
+        F: Future<Output = Result<T>> + Send,
+    {
+        if at != self.role {
+            return body.await;
+        }
+        match future::select(Box::pin(body), sleep(&self.state, dur)).await {
+            Either::Left((result, _)) => result,
+            Either::Right(((), _)) => Err(ChoreographyError::Timeout(dur)),
+        }
+    }
+}
+
+struct InFlight<R> {
+    deliver_at: Duration,
+    sequence: u64,
+    from: R,
+    to: R,
+    frame: Vec<u8>,
+}
+
+struct SimState<R> {
+    now: Duration,
+    rng: SplitMix64,
+    latency: Latency,
+    links: Vec<(R, R, Latency)>,
+    faults: Vec<Fault<R>>,
+    /// Communication steps taken by each role
+    steps: Vec<(R, usize)>,
+    crashed: Vec<R>,
+    /// Orders messages and timers falling due at the same time
+    sequence: u64,
+    in_flight: Vec<InFlight<R>>,
+    inboxes: HashMap<(R, R), VecDeque<Vec<u8>>>,
+    /// Latest delivery time per link, which later messages may not precede
+    channel_clock: HashMap<(R, R), Duration>,
+    receivers: Vec<((R, R), Waker)>,
+    timers: Vec<(Duration, u64, Waker)>,
+    trace: Vec<SimEvent<R>>,
+}
+
+impl<R: RoleId> SimState<R> {
+    /// Count a step of `role`, failing if it has crashed
+    fn step(&mut self, role: R) -> Result<()> {
+        if self.crashed.contains(&role) {
+            return Err(crashed(role));
+        }
+        let step = match self.steps.iter_mut().find(|(r, _)| *r == role) {
+            Some((_, steps)) => {
+                *steps += 1;
+                *steps
+            }
+            None => {
+                self.steps.push((role, 1));
+                1
+            }
+        };
+        let crash = self
+            .faults
+            .iter()
+            .any(|Fault::Crash { role: r, step: s }| *r == role && *s == step);
+        if crash {
+            self.crashed.push(role);
+            self.trace.push(SimEvent::Crashed {
+                at: self.now,
+                role,
+                step,
+            });
+            return Err(crashed(role));
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, from: R, to: R, frame: Vec<u8>) {
+        let latency = self
+            .links
+            .iter()
+            .find(|(f, t, _)| (*f, *t) == (from, to))
+            .map_or(self.latency, |(_, _, latency)| *latency);
+        let delay = match latency {
+            Latency::Fixed(delay) => delay,
+            Latency::Uniform { min, max } => {
+                let span = max.saturating_sub(min).as_nanos() as u64;
+                min + Duration::from_nanos(self.rng.next() % span.saturating_add(1))
+            }
+        };
+        // Keep each link in order
+        let clock = self.channel_clock.entry((from, to)).or_default();
+        let deliver_at = (self.now + delay).max(*clock);
+        *clock = deliver_at;
+        self.sequence += 1;
+        self.in_flight.push(InFlight {
+            deliver_at,
+            sequence: self.sequence,
+            from,
+            to,
+            frame,
+        });
+        self.trace.push(SimEvent::Sent {
+            at: self.now,
+            from,
+            to,
+            deliver_at,
+        });
+    }
+
+    fn poll_recv(&mut self, from: R, to: R, cx: &mut Context<'_>) -> Poll<Result<Vec<u8>>> {
+        if let Some(frame) = self
+            .inboxes
+            .get_mut(&(from, to))
+            .and_then(VecDeque::pop_front)
+        {
+            return Poll::Ready(Ok(frame));
+        }
+        self.receivers.retain(|(key, _)| *key != (from, to));
+        self.receivers.push(((from, to), cx.waker().clone()));
+        Poll::Pending
+    }
+
+    /// Move the clock to the next event and fire every event due then,
+    /// returning the tasks to wake, or `None` if no event is left
+    fn advance(&mut self) -> Option<Vec<Waker>> {
+        let next_message = self.in_flight.iter().map(|m| m.deliver_at).min();
+        let next_timer = self.timers.iter().map(|(deadline, _, _)| *deadline).min();
+        self.now = match (next_message, next_timer) {
+            (Some(m), Some(t)) => m.min(t),
+            (Some(at), None) | (None, Some(at)) => at,
+            (None, None) => return None,
+        };
+
+        let mut wakers = Vec::new();
+        let (mut due, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.in_flight)
+            .into_iter()
+            .partition(|m| m.deliver_at <= self.now);
+        self.in_flight = pending;
+        due.sort_by_key(|m| (m.deliver_at, m.sequence));
+        for message in due {
+            let (from, to) = (message.from, message.to);
+            if self.crashed.contains(&to) {
+                self.trace.push(SimEvent::Dropped {
+                    at: self.now,
+                    from,
+                    to,
+                });
+                continue;
+            }
+            self.inboxes
+                .entry((from, to))
+                .or_default()
+                .push_back(message.frame);
+            self.trace.push(SimEvent::Delivered {
+                at: self.now,
+                from,
+                to,
+            });
+            if let Some(index) = self
+                .receivers
+                .iter()
+                .position(|(key, _)| *key == (from, to))
+            {
+                wakers.push(self.receivers.remove(index).1);
+            }
+        }
+
+        let now = self.now;
+        let (mut due, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.timers)
+            .into_iter()
+            .partition(|(deadline, _, _)| *deadline <= now);
+        self.timers = pending;
+        due.sort_by_key(|(deadline, sequence, _)| (*deadline, *sequence));
+        wakers.extend(due.into_iter().map(|(_, _, waker)| waker));
+        Some(wakers)
+    }
+}
+
+fn crashed<R: Debug>(role: R) -> ChoreographyError {
+    ChoreographyError::Transport(format!("{role:?} crashed"))
+}
+
+fn lock<R>(state: &Mutex<SimState<R>>) -> MutexGuard<'_, SimState<R>> {
+    // The state stays consistent even if a role panicked while holding it
+    state
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+}
+
+fn sleep<R: RoleId>(state: &Arc<Mutex<SimState<R>>>, duration: Duration) -> Sleep<R> {
+    let mut guard = lock(state);
+    guard.sequence += 1;
+    Sleep {
+        state: Arc::clone(state),
+        deadline: guard.now + duration,
+        sequence: guard.sequence,
+    }
+}
+
+/// Timer on the virtual clock
+struct Sleep<R> {
+    state: Arc<Mutex<SimState<R>>>,
+    deadline: Duration,
+    sequence: u64,
+}
+
+impl<R: RoleId> Future for Sleep<R> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut state = lock(&self.state);
+        if state.now >= self.deadline {
+            return Poll::Ready(());
+        }
+        let sequence = self.sequence;
+        state.timers.retain(|(_, s, _)| *s != sequence);
+        state
+            .timers
+            .push((self.deadline, sequence, cx.waker().clone()));
+        Poll::Pending
+    }
+}
+
+impl<R> Drop for Sleep<R> {
+    fn drop(&mut self) {
+        let sequence = self.sequence;
+        lock(&self.state).timers.retain(|(_, s, _)| *s != sequence);
+    }
+}
+
+/// Small seeded generator, so runs reproduce across platforms and versions
+pub(crate) struct SplitMix64(pub(crate) u64);
+
+impl SplitMix64 {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in `[0, 1)`
+    pub(crate) fn next_f64(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::harness::take_role;
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    enum Role {
+        Client,
+        Server,
+        Logger,
+    }
+
+    const ROLES: &[Role] = &[Role::Client, Role::Server, Role::Logger];
+
+    /// Client sends a request, Server replies and both notify Logger
+    fn session(sim: &Simulation<Role>) -> Vec<(Role, RoleFuture<'static>)> {
+        let mut mesh = sim.mesh(ROLES);
+        let mut client = take_role(&mut mesh, Role::Client).unwrap();
+        let mut server = take_role(&mut mesh, Role::Server).unwrap();
+        let mut logger = take_role(&mut mesh, Role::Logger).unwrap();
+        vec![
+            (
+                Role::Client,
+                Box::pin(async move {
+                    client.send(&mut (), Role::Server, &1u32).await?;
+                    let reply: u32 = client.recv(&mut (), Role::Server).await?;
+                    client.send(&mut (), Role::Logger, &reply).await
+                }),
+            ),
+            (
+                Role::Server,
+                Box::pin(async move {
+                    let request: u32 = server.recv(&mut (), Role::Client).await?;
+                    server.send(&mut (), Role::Logger, &request).await?;
+                    server.send(&mut (), Role::Client, &(request + 1)).await
+                }),
+            ),
+            (
+                Role::Logger,
+                Box::pin(async move {
+                    let _: u32 = logger.recv(&mut (), Role::Server).await?;
+                    let _: u32 = logger.recv(&mut (), Role::Client).await?;
+                    Ok(())
+                }),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_virtual_time() {
+        let sim = Simulation::new(0).with_latency(Latency::Fixed(Duration::from_millis(10)));
+        let roles = session(&sim);
+        let report = sim.run(roles);
+        assert!(report.is_success());
+        assert_eq!(report.elapsed, Duration::from_millis(30));
+    }
+
+    #[test]
+    fn test_seed_reproduces_trace() {
+        let latency = Latency::Uniform {
+            min: Duration::from_millis(1),
+            max: Duration::from_millis(50),
+        };
+        let run = |seed| {
+            let sim = Simulation::new(seed).with_latency(latency);
+            let roles = session(&sim);
+            sim.run(roles)
+        };
+        let first = run(7);
+        assert!(first.is_success());
+        assert_eq!(first.trace, run(7).trace);
+        assert_ne!(first.trace, run(8).trace);
+    }
+
+    #[test]
+    fn test_links_reorder_messages() {
+        let sim = Simulation::new(0)
+            .with_link_latency(
+                Role::Server,
+                Role::Logger,
+                Latency::Fixed(Duration::from_millis(100)),
+            )
+            .with_latency(Latency::Fixed(Duration::from_millis(1)));
+        let roles = session(&sim);
+        let report = sim.run(roles);
+        assert!(report.is_success());
+        let delivered: Vec<_> = report
+            .trace
+            .iter()
+            .filter_map(|event| match event {
+                SimEvent::Delivered { from, to, .. } if *to == Role::Logger => Some(*from),
+                _ => None,
+            })
+            .collect();
+        // The Client's notification overtakes the Server's earlier one
+        assert_eq!(delivered, vec![Role::Client, Role::Server]);
+    }
+
+    #[test]
+    fn test_crash_fault() {
+        let sim = Simulation::new(0)
+            .with_latency(Latency::Fixed(Duration::from_millis(1)))
+            .with_fault(Fault::Crash {
+                role: Role::Server,
+                step: 2,
+            });
+        let roles = session(&sim);
+        let report = sim.run(roles);
+        assert!(!report.is_success());
+        assert!(matches!(
+            report.outcome(Role::Server),
+            Some(Err(ChoreographyError::Transport(_)))
+        ));
+        assert_eq!(report.blocked, vec![Role::Client, Role::Logger]);
+        assert!(report.trace.contains(&SimEvent::Crashed {
+            at: Duration::from_millis(1),
+            role: Role::Server,
+            step: 2,
+        }));
+    }
+
+    #[test]
+    fn test_send_to_crashed_peer_fails() {
+        let sim = Simulation::new(0).with_fault(Fault::Crash {
+            role: Role::Server,
+            step: 1,
+        });
+        let mut mesh = sim.mesh(&[Role::Client, Role::Server]);
+        let mut client = take_role(&mut mesh, Role::Client).unwrap();
+        let mut server = take_role(&mut mesh, Role::Server).unwrap();
+        let roles: Vec<(Role, RoleFuture<'static>)> = vec![
+            (
+                Role::Server,
+                Box::pin(async move { server.recv::<u32>(&mut (), Role::Client).await.map(drop) }),
+            ),
+            (
+                Role::Client,
+                Box::pin(async move {
+                    let error = client.send(&mut (), Role::Server, &1u32).await.unwrap_err();
+                    assert!(matches!(error, ChoreographyError::PeerFailed(_)));
+                    assert!(error.is_peer_failure());
+                    Ok(())
+                }),
+            ),
+        ];
+        let report = sim.run(roles);
+        assert!(matches!(report.outcome(Role::Client), Some(Ok(()))));
+    }
+
+    #[test]
+    fn test_timeout_in_virtual_time() {
+        let sim = Simulation::new(0);
+        let mut mesh = sim.mesh(&[Role::Client, Role::Server]);
+        let mut client = take_role(&mut mesh, Role::Client).unwrap();
+        let roles: Vec<(Role, RoleFuture<'static>)> = vec![
+            (
+                Role::Client,
+                Box::pin(async move {
+                    // A receive that nothing answers, on a second handler
+                    // because `with_timeout` borrows the first
+                    let mut idle = take_role(&mut mesh, Role::Server)?;
+                    let never = async move { idle.recv::<u32>(&mut (), Role::Client).await };
+                    client
+                        .with_timeout(&mut (), Role::Client, Duration::from_secs(3600), never)
+                        .await
+                        .map(drop)
+                }),
+            ),
+            (Role::Server, Box::pin(async { Ok(()) })),
+        ];
+        let report = sim.run(roles);
+        assert!(matches!(
+            report.outcome(Role::Client),
+            Some(Err(ChoreographyError::Timeout(_)))
+        ));
+        assert_eq!(report.elapsed, Duration::from_secs(3600));
+    }
+}
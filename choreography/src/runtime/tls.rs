@@ -0,0 +1,725 @@
+// TLS transport with role-bound peer identities
+//
+// Wraps any tokio byte stream in TLS (via rustls) and exposes the encrypted
+// connection as a `RumpsteakSession`, so it can be registered on a
+// `RumpsteakEndpoint` like any other transport.
+//
+// Key pieces:
+// - PeerIdentity: how a certificate is recognised (DNS name or SHA-256 pin).
+// - RoleIdentityMap: binds each protocol role to the identities it may present.
+// - TlsTransport: runs the TLS handshake, checks the peer certificate against
+//   the role the connection was opened for, and frames messages on the stream.
+//
+// Certificate chain validation is left to the rustls configs supplied by the
+// caller; the identity map adds the role-level check on top of it. Servers
+// must be configured to request client certificates, otherwise accepted
+// connections are rejected for lacking a peer identity.
+
+use futures::future::BoxFuture;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ServerConfig};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_rustls::{TlsAcceptor, TlsConnector, TlsStream};
+
+use crate::effects::handlers::rumpsteak::{SessionTypeDynamic, SessionUpdate};
+use crate::effects::{ChoreographyError, RoleId, RumpsteakSession};
+
+/// Default upper bound for a single framed message (16 MiB).
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Bytes read from the stream at a time.
+const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+/// Errors raised while establishing or using a TLS session.
+#[derive(Debug, Error)]
+pub enum TlsError {
+    #[error("TLS I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("TLS transport has no {side} configuration")]
+    NotConfigured { side: &'static str },
+
+    #[error("Peer for role {role} did not present a certificate")]
+    MissingPeerCertificate { role: String },
+
+    #[error("No identities are bound to role {role}")]
+    UnboundRole { role: String },
+
+    #[error("Peer certificate (sha256 {fingerprint}) is not bound to role {role}")]
+    IdentityMismatch { role: String, fingerprint: String },
+
+    #[error("Invalid certificate fingerprint: {0}")]
+    InvalidFingerprint(String),
+
+    #[error("Frame of {size} bytes exceeds maximum of {max} bytes")]
+    FrameTooLarge { size: usize, max: usize },
+
+    #[error("TLS session failed on an earlier write")]
+    Failed,
+}
+
+impl From<TlsError> for ChoreographyError {
+    fn from(err: TlsError) -> Self {
+        ChoreographyError::Transport(err.to_string())
+    }
+}
+
+/// Identity a peer certificate must match to be accepted for a role.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum PeerIdentity {
+    /// Certificate must be valid for this DNS name (SAN check).
+    DnsName(String),
+    /// SHA-256 digest of the DER-encoded end-entity certificate.
+    Fingerprint([u8; 32]),
+}
+
+impl PeerIdentity {
+    /// Pin the exact certificate given in DER form.
+    #[must_use]
+    pub fn fingerprint_of(cert_der: &[u8]) -> Self {
+        PeerIdentity::Fingerprint(Sha256::digest(cert_der).into())
+    }
+
+    /// Parse a hex-encoded SHA-256 fingerprint (colons are ignored).
+    pub fn from_hex_fingerprint(hex_str: &str) -> Result<Self, TlsError> {
+        let cleaned: String = hex_str.chars().filter(|c| *c != ':').collect();
+        let bytes =
+            hex::decode(&cleaned).map_err(|e| TlsError::InvalidFingerprint(e.to_string()))?;
+        let digest: [u8; 32] = bytes.try_into().map_err(|bytes: Vec<u8>| {
+            TlsError::InvalidFingerprint(format!("expected 32 bytes, got {}", bytes.len()))
+        })?;
+        Ok(PeerIdentity::Fingerprint(digest))
+    }
+
+    /// Check whether the certificate satisfies this identity.
+    #[must_use]
+    pub fn matches(&self, cert: &CertificateDer<'_>) -> bool {
+        match self {
+            PeerIdentity::Fingerprint(expected) => {
+                let digest: [u8; 32] = Sha256::digest(cert.as_ref()).into();
+                &digest == expected
+            }
+            PeerIdentity::DnsName(name) => {
+                let Ok(server_name) = ServerName::try_from(name.as_str()) else {
+                    return false;
+                };
+                webpki::EndEntityCert::try_from(cert)
+                    .and_then(|ee| ee.verify_is_valid_for_subject_name(&server_name))
+                    .is_ok()
+            }
+        }
+    }
+}
+
+/// Binds protocol roles to the certificate identities they may present.
+#[derive(Debug, Clone)]
+pub struct RoleIdentityMap<R: RoleId> {
+    identities: HashMap<R, Vec<PeerIdentity>>,
+}
+
+impl<R: RoleId> Default for RoleIdentityMap<R> {
+    fn default() -> Self {
+        Self {
+            identities: HashMap::new(),
+        }
+    }
+}
+
+impl<R: RoleId> RoleIdentityMap<R> {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Builder-style variant of [`insert`](Self::insert).
+    #[must_use]
+    pub fn bind(mut self, role: R, identity: PeerIdentity) -> Self {
+        self.insert(role, identity);
+        self
+    }
+
+    /// Allow `identity` to act as `role`. A role may have several identities.
+    pub fn insert(&mut self, role: R, identity: PeerIdentity) {
+        let entry = self.identities.entry(role).or_default();
+        if !entry.contains(&identity) {
+            entry.push(identity);
+        }
+    }
+
+    /// Identities bound to `role`, empty if none.
+    #[must_use]
+    pub fn identities(&self, role: &R) -> &[PeerIdentity] {
+        self.identities.get(role).map_or(&[], Vec::as_slice)
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.identities.is_empty()
+    }
+
+    /// Verify that `cert` may act as `role`.
+    pub fn verify(&self, role: &R, cert: &CertificateDer<'_>) -> Result<(), TlsError> {
+        let bound = self.identities(role);
+        if bound.is_empty() {
+            return Err(TlsError::UnboundRole {
+                role: format!("{role:?}"),
+            });
+        }
+        if bound.iter().any(|identity| identity.matches(cert)) {
+            Ok(())
+        } else {
+            Err(TlsError::IdentityMismatch {
+                role: format!("{role:?}"),
+                fingerprint: hex::encode(Sha256::digest(cert.as_ref())),
+            })
+        }
+    }
+}
+
+/// Establishes TLS connections whose peers are checked against a role map.
+#[derive(Clone)]
+pub struct TlsTransport<R: RoleId> {
+    identities: Arc<RoleIdentityMap<R>>,
+    connector: Option<TlsConnector>,
+    acceptor: Option<TlsAcceptor>,
+    max_frame_size: usize,
+}
+
+impl<R: RoleId> std::fmt::Debug for TlsTransport<R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsTransport")
+            .field("identities", &self.identities)
+            .field("client", &self.connector.is_some())
+            .field("server", &self.acceptor.is_some())
+            .field("max_frame_size", &self.max_frame_size)
+            .finish()
+    }
+}
+
+impl<R: RoleId> TlsTransport<R> {
+    #[must_use]
+    pub fn new(identities: RoleIdentityMap<R>) -> Self {
+        Self {
+            identities: Arc::new(identities),
+            connector: None,
+            acceptor: None,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+
+    /// Config used when this side dials a peer.
+    #[must_use]
+    pub fn with_client_config(mut self, config: Arc<ClientConfig>) -> Self {
+        self.connector = Some(TlsConnector::from(config));
+        self
+    }
+
+    /// Config used when this side accepts a peer. It should require client
+    /// certificates so the peer's role can be verified.
+    #[must_use]
+    pub fn with_server_config(mut self, config: Arc<ServerConfig>) -> Self {
+        self.acceptor = Some(TlsAcceptor::from(config));
+        self
+    }
+
+    #[must_use]
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    #[must_use]
+    pub fn identities(&self) -> &RoleIdentityMap<R> {
+        &self.identities
+    }
+
+    /// Dial `peer` over `io`, verifying that the server acts as `peer`.
+    pub async fn connect<IO>(
+        &self,
+        peer: R,
+        server_name: ServerName<'static>,
+        io: IO,
+    ) -> Result<RumpsteakSession, TlsError>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let connector = self
+            .connector
+            .as_ref()
+            .ok_or(TlsError::NotConfigured { side: "client" })?;
+        let stream = connector.connect(server_name, io).await?;
+        let (_, connection) = stream.get_ref();
+        self.verify_peer(&peer, connection.peer_certificates())?;
+        tracing::debug!(?peer, "TLS session established (client)");
+        Ok(self.wrap_stream(stream.into()))
+    }
+
+    /// Accept a connection from `peer` over `io`, verifying its client certificate.
+    pub async fn accept<IO>(&self, peer: R, io: IO) -> Result<RumpsteakSession, TlsError>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let acceptor = self
+            .acceptor
+            .as_ref()
+            .ok_or(TlsError::NotConfigured { side: "server" })?;
+        let stream = acceptor.accept(io).await?;
+        let (_, connection) = stream.get_ref();
+        self.verify_peer(&peer, connection.peer_certificates())?;
+        tracing::debug!(?peer, "TLS session established (server)");
+        Ok(self.wrap_stream(stream.into()))
+    }
+
+    fn verify_peer(
+        &self,
+        peer: &R,
+        certs: Option<&[CertificateDer<'static>]>,
+    ) -> Result<(), TlsError> {
+        let leaf =
+            certs
+                .and_then(<[_]>::first)
+                .ok_or_else(|| TlsError::MissingPeerCertificate {
+                    role: format!("{peer:?}"),
+                })?;
+        self.identities.verify(peer, leaf)
+    }
+
+    fn wrap_stream<IO>(&self, stream: TlsStream<IO>) -> RumpsteakSession
+    where
+        IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        RumpsteakSession::new(Box::new(TlsSession::new(stream, self.max_frame_size)))
+    }
+}
+
+/// Length-prefixed framing over an established TLS stream.
+///
+/// Bytes move through `received` and `unsent`, so a send or receive dropped
+/// midway, by `recv_any` or a deadline, leaves whole frames on the stream:
+/// the next receive parses what was read, and the next operation finishes
+/// writing what was not. Once a write fails the session refuses further
+/// frames, so a retried send cannot follow part of its first attempt.
+struct TlsSession<IO> {
+    stream: TlsStream<IO>,
+    max_frame_size: usize,
+    received: Vec<u8>,
+    unsent: Vec<u8>,
+    failed: bool,
+}
+
+impl<IO> TlsSession<IO>
+where
+    IO: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    fn new(stream: TlsStream<IO>, max_frame_size: usize) -> Self {
+        Self {
+            stream,
+            max_frame_size,
+            received: Vec::new(),
+            unsent: Vec::new(),
+            failed: false,
+        }
+    }
+
+    async fn write_frame(&mut self, data: &[u8]) -> Result<(), TlsError> {
+        if data.len() > self.max_frame_size {
+            return Err(TlsError::FrameTooLarge {
+                size: data.len(),
+                max: self.max_frame_size,
+            });
+        }
+        let len = u32::try_from(data.len()).map_err(|_| TlsError::FrameTooLarge {
+            size: data.len(),
+            max: u32::MAX as usize,
+        })?;
+        // Frames of sends dropped midway go out first
+        self.flush_unsent().await?;
+        self.unsent.extend_from_slice(&len.to_be_bytes());
+        self.unsent.extend_from_slice(data);
+        self.flush_unsent().await
+    }
+
+    /// Write out `unsent`, failing the session if the stream fails
+    async fn flush_unsent(&mut self) -> Result<(), TlsError> {
+        if self.failed {
+            return Err(TlsError::Failed);
+        }
+        let result = async {
+            while !self.unsent.is_empty() {
+                let written = self.stream.write(&self.unsent).await?;
+                if written == 0 {
+                    return Err(std::io::ErrorKind::WriteZero.into());
+                }
+                self.unsent.drain(..written);
+            }
+            self.stream.flush().await
+        }
+        .await;
+        if result.is_err() {
+            self.failed = true;
+        }
+        Ok(result?)
+    }
+
+    async fn read_frame(&mut self) -> Result<Vec<u8>, TlsError> {
+        // The peer may be waiting for a frame a dropped send left behind
+        self.flush_unsent().await?;
+        let mut chunk = [0u8; READ_CHUNK_SIZE];
+        loop {
+            if let Some(frame) = self.take_frame()? {
+                return Ok(frame);
+            }
+            // Bytes are kept as soon as they are read, so the read is the
+            // only point where this future may be dropped
+            let read = self.stream.read(&mut chunk).await?;
+            if read == 0 {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+            self.received.extend_from_slice(&chunk[..read]);
+        }
+    }
+
+    /// Take the first frame off `received` if it has fully arrived
+    fn take_frame(&mut self) -> Result<Option<Vec<u8>>, TlsError> {
+        let Some(&[a, b, c, d]) = self.received.get(..4) else {
+            return Ok(None);
+        };
+        let size = u32::from_be_bytes([a, b, c, d]) as usize;
+        if size > self.max_frame_size {
+            return Err(TlsError::FrameTooLarge {
+                size,
+                max: self.max_frame_size,
+            });
+        }
+        if self.received.len() < 4 + size {
+            return Ok(None);
+        }
+        let frame = self.received[4..4 + size].to_vec();
+        self.received.drain(..4 + size);
+        Ok(Some(frame))
+    }
+}
+
+impl<IO> SessionTypeDynamic for TlsSession<IO>
+where
+    IO: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    fn type_name(&self) -> &'static str {
+        "TlsSession"
+    }
+
+    fn send(&mut self, data: Vec<u8>) -> BoxFuture<'_, crate::effects::Result<SessionUpdate<()>>> {
+        Box::pin(async move {
+            self.write_frame(&data).await?;
+            Ok(SessionUpdate::new(()).with_description("Send"))
+        })
+    }
+
+    fn recv(&mut self) -> BoxFuture<'_, crate::effects::Result<SessionUpdate<Vec<u8>>>> {
+        Box::pin(async move {
+            let bytes = self.read_frame().await?;
+            Ok(SessionUpdate::new(bytes).with_description("Recv"))
+        })
+    }
+
+    fn choose(&mut self, label: &str) -> BoxFuture<'_, crate::effects::Result<SessionUpdate<()>>> {
+        let label = label.to_string();
+        Box::pin(async move {
+            let bytes = bincode::serialize(&label).map_err(|e| {
+                ChoreographyError::Transport(format!("Label serialization failed: {e}"))
+            })?;
+            self.write_frame(&bytes).await?;
+            Ok(SessionUpdate::new(()).with_description("Choose"))
+        })
+    }
+
+    fn offer(&mut self) -> BoxFuture<'_, crate::effects::Result<SessionUpdate<String>>> {
+        Box::pin(async move {
+            let bytes = self.read_frame().await?;
+            let label: String = bincode::deserialize(&bytes).map_err(|e| {
+                ChoreographyError::Transport(format!("Label deserialization failed: {e}"))
+            })?;
+            Ok(SessionUpdate::new(label).with_description("Offer"))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+    use rustls::server::WebPkiClientVerifier;
+    use rustls::RootCertStore;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    enum Role {
+        Client,
+        Server,
+    }
+
+    struct Issued {
+        cert: CertificateDer<'static>,
+        key: PrivateKeyDer<'static>,
+    }
+
+    struct Pki {
+        roots: Arc<RootCertStore>,
+        ca_cert: rcgen::Certificate,
+        ca_key: KeyPair,
+    }
+
+    impl Pki {
+        fn new() -> Self {
+            let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            let ca_key = KeyPair::generate().unwrap();
+            let ca_cert = params.self_signed(&ca_key).unwrap();
+            let mut roots = RootCertStore::empty();
+            roots.add(ca_cert.der().clone()).unwrap();
+            Self {
+                roots: Arc::new(roots),
+                ca_cert,
+                ca_key,
+            }
+        }
+
+        fn issue(&self, name: &str) -> Issued {
+            let params = CertificateParams::new(vec![name.to_string()]).unwrap();
+            let key = KeyPair::generate().unwrap();
+            let cert = params.signed_by(&key, &self.ca_cert, &self.ca_key).unwrap();
+            Issued {
+                cert: cert.der().clone(),
+                key: PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der())),
+            }
+        }
+
+        fn client_config(&self, identity: &Issued) -> Arc<ClientConfig> {
+            Arc::new(
+                ClientConfig::builder()
+                    .with_root_certificates(self.roots.clone())
+                    .with_client_auth_cert(vec![identity.cert.clone()], identity.key.clone_key())
+                    .unwrap(),
+            )
+        }
+
+        fn server_config(&self, identity: &Issued) -> Arc<ServerConfig> {
+            let verifier = WebPkiClientVerifier::builder(self.roots.clone())
+                .build()
+                .unwrap();
+            Arc::new(
+                ServerConfig::builder()
+                    .with_client_cert_verifier(verifier)
+                    .with_single_cert(vec![identity.cert.clone()], identity.key.clone_key())
+                    .unwrap(),
+            )
+        }
+    }
+
+    fn server_name(name: &str) -> ServerName<'static> {
+        ServerName::try_from(name.to_string()).unwrap()
+    }
+
+    /// Both ends of a handshaken connection, as bare sessions so tests can
+    /// write partial frames on the stream
+    async fn session_pair() -> (
+        TlsSession<tokio::io::DuplexStream>,
+        TlsSession<tokio::io::DuplexStream>,
+    ) {
+        let pki = Pki::new();
+        let connector = TlsConnector::from(pki.client_config(&pki.issue("client.test")));
+        let acceptor = TlsAcceptor::from(pki.server_config(&pki.issue("server.test")));
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let (client, server) = tokio::join!(
+            connector.connect(server_name("server.test"), client_io),
+            acceptor.accept(server_io),
+        );
+        (
+            TlsSession::new(client.unwrap().into(), DEFAULT_MAX_FRAME_SIZE),
+            TlsSession::new(server.unwrap().into(), DEFAULT_MAX_FRAME_SIZE),
+        )
+    }
+
+    #[test]
+    fn test_fingerprint_identity() {
+        let pki = Pki::new();
+        let alice = pki.issue("alice.test");
+        let bob = pki.issue("bob.test");
+
+        let pinned = PeerIdentity::fingerprint_of(alice.cert.as_ref());
+        assert!(pinned.matches(&alice.cert));
+        assert!(!pinned.matches(&bob.cert));
+
+        let PeerIdentity::Fingerprint(digest) = pinned.clone() else {
+            unreachable!()
+        };
+        let parsed = PeerIdentity::from_hex_fingerprint(&hex::encode(digest)).unwrap();
+        assert_eq!(parsed, pinned);
+        assert!(PeerIdentity::from_hex_fingerprint("abcd").is_err());
+    }
+
+    #[test]
+    fn test_role_identity_map_verify() {
+        let pki = Pki::new();
+        let alice = pki.issue("alice.test");
+        let map =
+            RoleIdentityMap::new().bind(Role::Client, PeerIdentity::DnsName("alice.test".into()));
+
+        assert!(map.verify(&Role::Client, &alice.cert).is_ok());
+        assert!(matches!(
+            map.verify(&Role::Server, &alice.cert),
+            Err(TlsError::UnboundRole { .. })
+        ));
+
+        let other = pki.issue("mallory.test");
+        assert!(matches!(
+            map.verify(&Role::Client, &other.cert),
+            Err(TlsError::IdentityMismatch { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_mutual_tls_session_round_trip() {
+        let pki = Pki::new();
+        let client_id = pki.issue("client.test");
+        let server_id = pki.issue("server.test");
+
+        let identities = RoleIdentityMap::new()
+            .bind(Role::Client, PeerIdentity::DnsName("client.test".into()))
+            .bind(Role::Server, PeerIdentity::DnsName("server.test".into()));
+
+        let client =
+            TlsTransport::new(identities.clone()).with_client_config(pki.client_config(&client_id));
+        let server =
+            TlsTransport::new(identities).with_server_config(pki.server_config(&server_id));
+
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let (client_session, server_session) = tokio::join!(
+            client.connect(Role::Server, server_name("server.test"), client_io),
+            server.accept(Role::Client, server_io),
+        );
+        let mut client_session = client_session.unwrap();
+        let mut server_session = server_session.unwrap();
+
+        client_session.send(b"hello".to_vec()).await.unwrap();
+        let received = server_session.recv().await.unwrap();
+        assert_eq!(received.output, b"hello".to_vec());
+
+        server_session.choose("Accept").await.unwrap();
+        let label = client_session.offer().await.unwrap();
+        assert_eq!(label.output, "Accept");
+    }
+
+    #[tokio::test]
+    async fn test_receive_losing_a_race_keeps_its_partial_frame() {
+        use futures::future::{select, Either};
+
+        let (mut slow, mut slow_server) = session_pair().await;
+        let (mut fast, mut fast_server) = session_pair().await;
+
+        // Half of a frame arrives on one session, a whole one on the other
+        slow.stream.write_all(&5u32.to_be_bytes()).await.unwrap();
+        slow.stream.write_all(b"he").await.unwrap();
+        slow.stream.flush().await.unwrap();
+        fast.send(b"fast".to_vec()).await.unwrap();
+
+        // Race the receives as `recv_any` does, dropping the loser
+        let winner = match select(slow_server.recv(), fast_server.recv()).await {
+            Either::Left(_) => panic!("the partial frame completed"),
+            Either::Right((update, _)) => update.unwrap(),
+        };
+        assert_eq!(winner.output, b"fast".to_vec());
+
+        slow.stream.write_all(b"llo").await.unwrap();
+        slow.stream.flush().await.unwrap();
+        let received = slow_server.recv().await.unwrap();
+        assert_eq!(received.output, b"hello".to_vec());
+    }
+
+    #[tokio::test]
+    async fn test_timed_out_operations_leave_whole_frames() {
+        use crate::runtime::timeout;
+        use std::time::Duration;
+
+        let (mut client, mut server) = session_pair().await;
+
+        // A receive that times out keeps the half of a frame it read
+        client.stream.write_all(&5u32.to_be_bytes()).await.unwrap();
+        client.stream.write_all(b"he").await.unwrap();
+        client.stream.flush().await.unwrap();
+        let dropped = timeout(Duration::from_millis(20), server.recv()).await;
+        assert!(matches!(dropped, Err(ChoreographyError::Timeout(_))));
+        client.stream.write_all(b"llo").await.unwrap();
+        client.stream.flush().await.unwrap();
+        assert_eq!(server.recv().await.unwrap().output, b"hello".to_vec());
+
+        // A send that times out while the peer is not reading is finished by
+        // the next operation
+        let large = vec![7u8; 256 * 1024];
+        let dropped = timeout(Duration::from_millis(20), server.send(large.clone())).await;
+        assert!(matches!(dropped, Err(ChoreographyError::Timeout(_))));
+        let replying = tokio::spawn(async move {
+            let received = client.recv().await.unwrap().output;
+            client.send(b"done".to_vec()).await.unwrap();
+            received
+        });
+        assert_eq!(server.recv().await.unwrap().output, b"done".to_vec());
+        assert_eq!(replying.await.unwrap(), large);
+    }
+
+    #[tokio::test]
+    async fn test_failed_write_refuses_later_frames() {
+        let (client, mut server) = session_pair().await;
+        drop(client);
+        assert!(server.send(b"lost".to_vec()).await.is_err());
+
+        // A retry is refused rather than written after the failed frame
+        let retried = server.send(b"lost".to_vec()).await;
+        assert!(
+            matches!(&retried, Err(ChoreographyError::Transport(e)) if e.contains("earlier write")),
+            "{retried:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rejects_peer_bound_to_other_role() {
+        let pki = Pki::new();
+        let client_id = pki.issue("client.test");
+        let server_id = pki.issue("server.test");
+
+        // The server certificate is valid, but it is bound to the client role only.
+        let identities = RoleIdentityMap::new()
+            .bind(Role::Client, PeerIdentity::DnsName("server.test".into()))
+            .bind(Role::Server, PeerIdentity::DnsName("elsewhere.test".into()));
+
+        let client =
+            TlsTransport::new(identities.clone()).with_client_config(pki.client_config(&client_id));
+        let server =
+            TlsTransport::new(identities).with_server_config(pki.server_config(&server_id));
+
+        let (client_io, server_io) = tokio::io::duplex(64 * 1024);
+        let (client_result, _server_result) = tokio::join!(
+            client.connect(Role::Server, server_name("server.test"), client_io),
+            server.accept(Role::Client, server_io),
+        );
+        assert!(matches!(
+            client_result,
+            Err(TlsError::IdentityMismatch { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_missing_configuration() {
+        let transport: TlsTransport<Role> = TlsTransport::new(RoleIdentityMap::new());
+        let (io, _other) = tokio::io::duplex(1024);
+        let result = transport.accept(Role::Client, io).await;
+        assert!(matches!(
+            result,
+            Err(TlsError::NotConfigured { side: "server" })
+        ));
+    }
+}
@@ -0,0 +1,578 @@
+// Roles hosted in HTTP services
+//
+// Lets a web service, such as an axum app, play a role whose peer is an
+// HTTP client. The service runs each session's role as a task over a
+// `HostedRoleHandler`, and the `SessionLayer` of its `SessionHost` hands the
+// client's requests to those sessions:
+//
+//     recv from Client    the JSON body of the next request carrying the
+//                         session's ID in `x-session-id`
+//     offer from Client   the same, holding the label as a JSON string
+//     send to Client      the response to that request, with the message
+//                         name in `x-message`, or else the response to the
+//                         client's next `GET` without a message
+//
+// A request names its message in `x-message`, or by its route in the
+// session host's `RouteTable`. Requests must fit the session's state
+// machine: the session takes them in order as it receives from the client,
+// and refuses one carrying another message than the one it receives with
+// `409 Conflict`, as it does requests still waiting when it ends. Requests
+// for unknown sessions are refused with `404 Not Found`. A request the role
+// answers by receiving again is answered `202 Accepted`. Requests without a
+// session ID go to the wrapped service.
+//
+// The client learns of a choice from the messages that follow it, so only
+// the wrapped handler is told. Everything else goes to the wrapped handler
+// too, as with `HttpRoleHandler`.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::channel::oneshot;
+use http::{header, HeaderValue, Method, Request, Response, StatusCode};
+use http_body::Body;
+use http_body_util::{BodyExt, Limited};
+use serde::{de::DeserializeOwned, Serialize};
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::effects::middleware::{message_label, recv_as, send_as};
+use crate::effects::{ChoreoHandler, ChoreographyError, Label, Result};
+use crate::runtime::http::{HttpRoute, RouteTable, DEFAULT_MAX_BODY_SIZE};
+
+/// Header carrying the ID of the session a request belongs to
+pub const SESSION_HEADER: &str = "x-session-id";
+
+/// Header naming the message a request or response carries
+pub const MESSAGE_HEADER: &str = "x-message";
+
+/// Response from a session to the client
+struct Outbound {
+    status: StatusCode,
+    message: Option<String>,
+    body: Bytes,
+}
+
+impl Outbound {
+    fn error(status: StatusCode, reason: impl fmt::Display) -> Self {
+        Self {
+            status,
+            message: None,
+            body: Bytes::from(reason.to_string()),
+        }
+    }
+
+    fn empty(status: StatusCode) -> Self {
+        Self {
+            status,
+            message: None,
+            body: Bytes::new(),
+        }
+    }
+
+    fn into_response<B: From<Bytes>>(self) -> Response<B> {
+        let mut response = Response::new(B::from(self.body));
+        *response.status_mut() = self.status;
+        if let Some(message) = self.message.and_then(|m| HeaderValue::try_from(m).ok()) {
+            response.headers_mut().insert(MESSAGE_HEADER, message);
+            response.headers_mut().insert(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/json"),
+            );
+        }
+        response
+    }
+}
+
+/// Request from the client, handed to a session
+struct Inbound {
+    message: Option<String>,
+    route: HttpRoute,
+    body: Bytes,
+    reply: oneshot::Sender<Outbound>,
+}
+
+impl Inbound {
+    /// Whether the request carries `expected`, or any choice if `None`,
+    /// answering it `409 Conflict` if not
+    fn fits(self, session_id: &str, expected: Option<&str>) -> Option<Self> {
+        match expected {
+            Some(expected) if self.message.as_deref() != Some(expected) => {
+                let received = self.message.unwrap_or_else(|| self.route.to_string());
+                let _ = self.reply.send(Outbound::error(
+                    StatusCode::CONFLICT,
+                    format!("session {session_id} receives {expected}, not {received}"),
+                ));
+                None
+            }
+            _ => Some(self),
+        }
+    }
+}
+
+/// A receive a session is waiting in
+struct Waiter {
+    /// Message received, `None` for a choice
+    expected: Option<String>,
+    sender: oneshot::Sender<Inbound>,
+}
+
+#[derive(Default)]
+struct Slot {
+    waiter: Option<Waiter>,
+    /// Requests the session did not receive yet
+    requests: VecDeque<Inbound>,
+    /// Messages sent while no request was open
+    outbox: VecDeque<Outbound>,
+    /// `GET` requests waiting for a message
+    polls: VecDeque<oneshot::Sender<Outbound>>,
+}
+
+/// Sessions of roles hosted in an HTTP service, by session ID
+#[derive(Clone)]
+pub struct SessionHost {
+    routes: RouteTable,
+    max_body_size: usize,
+    slots: Arc<Mutex<HashMap<String, Slot>>>,
+}
+
+impl fmt::Debug for SessionHost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionHost")
+            .field("routes", &self.routes)
+            .field("max_body_size", &self.max_body_size)
+            .field("sessions", &self.lock().len())
+            .finish()
+    }
+}
+
+impl SessionHost {
+    /// Host sessions whose client's messages are routed by `routes`
+    #[must_use]
+    pub fn new(routes: RouteTable) -> Self {
+        Self {
+            routes,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
+            slots: Arc::default(),
+        }
+    }
+
+    /// Largest request body accepted
+    #[must_use]
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    /// Layer handing the requests of sessions to this host
+    #[must_use]
+    pub fn layer(&self) -> SessionLayer {
+        SessionLayer { host: self.clone() }
+    }
+
+    /// Play a role in the session `session_id`, talking to the HTTP client
+    /// `client` and to every other role through `inner`
+    ///
+    /// The session is hosted until the handler is dropped. IDs of sessions
+    /// hosted at the same time must differ.
+    pub fn host<H: ChoreoHandler>(
+        &self,
+        session_id: impl Into<String>,
+        inner: H,
+        client: H::Role,
+    ) -> HostedRoleHandler<H> {
+        let session_id = session_id.into();
+        self.lock().insert(session_id.clone(), Slot::default());
+        HostedRoleHandler {
+            inner,
+            client,
+            session_id,
+            host: self.clone(),
+            reply: None,
+        }
+    }
+
+    /// IDs of the sessions hosted
+    #[must_use]
+    pub fn sessions(&self) -> Vec<String> {
+        self.lock().keys().cloned().collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Slot>> {
+        self.slots.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Answer a request of the session `session_id`
+    async fn serve<B>(&self, session_id: String, request: Request<B>) -> Outbound
+    where
+        B: Body,
+        B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let (parts, body) = request.into_parts();
+        let route = HttpRoute::new(parts.method.as_str(), parts.uri.path());
+        let message = match parts.headers.get(MESSAGE_HEADER) {
+            Some(value) => value.to_str().ok().map(str::to_string),
+            None => self.routes.message(&route).map(str::to_string),
+        };
+        let body = match Limited::new(body, self.max_body_size).collect().await {
+            Ok(collected) => collected.to_bytes(),
+            Err(error) => return Outbound::error(StatusCode::PAYLOAD_TOO_LARGE, error),
+        };
+
+        let (reply, answer) = oneshot::channel();
+        {
+            let mut slots = self.lock();
+            let Some(slot) = slots.get_mut(&session_id) else {
+                return Outbound::error(StatusCode::NOT_FOUND, format!("no session {session_id}"));
+            };
+            if message.is_none() && parts.method == Method::GET {
+                if let Some(outbound) = slot.outbox.pop_front() {
+                    return outbound;
+                }
+                slot.polls.push_back(reply);
+            } else {
+                let request = Inbound {
+                    message,
+                    route,
+                    body,
+                    reply,
+                };
+                match slot.waiter.take() {
+                    Some(waiter) => match request.fits(&session_id, waiter.expected.as_deref()) {
+                        Some(request) => {
+                            tracing::debug!(session = %session_id, message = ?request.message, "request handed to session");
+                            let _ = waiter.sender.send(request);
+                        }
+                        None => slot.waiter = Some(waiter),
+                    },
+                    None => slot.requests.push_back(request),
+                }
+            }
+        }
+        // Answered without a message when the session moves on, or ends
+        answer.await.unwrap_or_else(|_| {
+            Outbound::empty(if parts.method == Method::GET {
+                StatusCode::NO_CONTENT
+            } else {
+                StatusCode::ACCEPTED
+            })
+        })
+    }
+}
+
+/// Tower layer handing the requests of hosted sessions to a `SessionHost`
+#[derive(Debug, Clone)]
+pub struct SessionLayer {
+    host: SessionHost,
+}
+
+impl<S> Layer<S> for SessionLayer {
+    type Service = SessionService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        SessionService {
+            inner,
+            host: self.host.clone(),
+        }
+    }
+}
+
+/// Service of a `SessionLayer`, passing requests without a session ID to
+/// the wrapped service
+#[derive(Debug, Clone)]
+pub struct SessionService<S> {
+    inner: S,
+    host: SessionHost,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for SessionService<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+    ReqBody: Body + Send + 'static,
+    ReqBody::Data: Send,
+    ReqBody::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    ResBody: From<Bytes> + Send + 'static,
+    S::Error: Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = S::Error;
+    type Future =
+        Pin<Box<dyn Future<Output = std::result::Result<Self::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<ReqBody>) -> Self::Future {
+        let session_id = request
+            .headers()
+            .get(SESSION_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let Some(session_id) = session_id else {
+            return Box::pin(self.inner.call(request));
+        };
+        let host = self.host.clone();
+        Box::pin(async move { Ok(host.serve(session_id, request).await.into_response()) })
+    }
+}
+
+/// Handler of a role hosted in an HTTP service, for one session
+///
+/// Operations on the client go through the session's requests, all others
+/// to the wrapped handler.
+pub struct HostedRoleHandler<H: ChoreoHandler> {
+    inner: H,
+    client: H::Role,
+    session_id: String,
+    host: SessionHost,
+    /// Request last received, not answered yet
+    reply: Option<oneshot::Sender<Outbound>>,
+}
+
+impl<H: ChoreoHandler> fmt::Debug for HostedRoleHandler<H> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostedRoleHandler")
+            .field("client", &self.client)
+            .field("session_id", &self.session_id)
+            .field("open_request", &self.reply.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<H: ChoreoHandler> HostedRoleHandler<H> {
+    pub fn session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut H {
+        &mut self.inner
+    }
+
+    fn closed(&self) -> ChoreographyError {
+        ChoreographyError::Transport(format!("session {} is no longer hosted", self.session_id))
+    }
+
+    /// Answer the open request with `outbound`, or else the client's next
+    /// poll
+    fn deliver(&mut self, mut outbound: Outbound) -> Result<()> {
+        if let Some(reply) = self.reply.take() {
+            return reply.send(outbound).map_err(|_| {
+                ChoreographyError::Transport(format!(
+                    "client of session {} hung up",
+                    self.session_id
+                ))
+            });
+        }
+        let mut slots = self.host.lock();
+        let slot = slots
+            .get_mut(&self.session_id)
+            .ok_or_else(|| self.closed())?;
+        while let Some(poll) = slot.polls.pop_front() {
+            match poll.send(outbound) {
+                Ok(()) => return Ok(()),
+                Err(unsent) => outbound = unsent,
+            }
+        }
+        slot.outbox.push_back(outbound);
+        Ok(())
+    }
+
+    /// Next request of the client, carrying `expected` or else a choice
+    async fn next_request(&mut self, expected: Option<&str>) -> Result<Inbound> {
+        // The request received before is answered without a message
+        self.reply = None;
+        let (sender, request) = oneshot::channel();
+        {
+            let mut slots = self.host.lock();
+            let slot = slots
+                .get_mut(&self.session_id)
+                .ok_or_else(|| self.closed())?;
+            while let Some(request) = slot.requests.pop_front() {
+                if let Some(request) = request.fits(&self.session_id, expected) {
+                    return Ok(request);
+                }
+            }
+            slot.waiter = Some(Waiter {
+                expected: expected.map(str::to_string),
+                sender,
+            });
+        }
+        request.await.map_err(|_| self.closed())
+    }
+
+    /// Payload of `request`, answering it `400 Bad Request` if it is not a
+    /// `T`
+    fn open<T: DeserializeOwned>(&mut self, request: Inbound) -> Result<T> {
+        match serde_json::from_slice(&request.body) {
+            Ok(payload) => {
+                self.reply = Some(request.reply);
+                Ok(payload)
+            }
+            Err(error) => {
+                let _ = request
+                    .reply
+                    .send(Outbound::error(StatusCode::BAD_REQUEST, &error));
+                Err(ChoreographyError::Serialization(error.to_string()))
+            }
+        }
+    }
+}
+
+impl<H: ChoreoHandler> Drop for HostedRoleHandler<H> {
+    fn drop(&mut self) {
+        let Some(slot) = self.host.lock().remove(&self.session_id) else {
+            return;
+        };
+        for request in slot.requests {
+            let _ = request.reply.send(Outbound::error(
+                StatusCode::CONFLICT,
+                format!("session {} ended", self.session_id),
+            ));
+        }
+    }
+}
+
+impl<H: ChoreoHandler> HostedRoleHandler<H> {
+    async fn send_step<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        to: H::Role,
+        label: Option<&str>,
+        msg: &M,
+    ) -> Result<()> {
+        if to != self.client {
+            return send_as(&mut self.inner, ep, to, label, msg).await;
+        }
+        let body =
+            serde_json::to_vec(msg).map_err(|e| ChoreographyError::Serialization(e.to_string()))?;
+        self.deliver(Outbound {
+            status: StatusCode::OK,
+            message: Some(label.unwrap_or(message_label::<M>()).to_string()),
+            body: Bytes::from(body),
+        })
+    }
+
+    async fn recv_step<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut H::Endpoint,
+        from: H::Role,
+        label: Option<&str>,
+    ) -> Result<M> {
+        if from != self.client {
+            return recv_as(&mut self.inner, ep, from, label).await;
+        }
+        let request = self
+            .next_request(Some(label.unwrap_or(message_label::<M>())))
+            .await?;
+        self.open(request)
+    }
+}
+
+#[async_trait]
+impl<H: ChoreoHandler + Send> ChoreoHandler for HostedRoleHandler<H> {
+    type Role = H::Role;
+    type Endpoint = H::Endpoint;
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, None, msg).await
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+    ) -> Result<M> {
+        self.recv_step(ep, from, None).await
+    }
+
+    async fn send_labelled<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        label: &str,
+        msg: &M,
+    ) -> Result<()> {
+        self.send_step(ep, to, Some(label), msg).await
+    }
+
+    async fn recv_labelled<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        label: &str,
+    ) -> Result<M> {
+        self.recv_step(ep, from, Some(label)).await
+    }
+
+    async fn choose(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        who: Self::Role,
+        label: Label,
+    ) -> Result<()> {
+        self.inner.choose(ep, who, label).await
+    }
+
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        if from != self.client {
+            return self.inner.offer(ep, from, labels).await;
+        }
+        let request = self.next_request(None).await?;
+        let label: String = self.open(request)?;
+        // Clients may only name the branches offered here
+        Label::resolve(&label, labels).map_err(|error| {
+            if let Some(reply) = self.reply.take() {
+                let _ = reply.send(Outbound::error(StatusCode::BAD_REQUEST, &error));
+            }
+            error
+        })
+    }
+
+    async fn with_timeout<F, T>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        at: Self::Role,
+        dur: Duration,
+        body: F,
+    ) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>> + Send,
+    {
+        self.inner.with_timeout(ep, at, dur, body).await
+    }
+
+    async fn recv_any<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: &[Self::Role],
+    ) -> Result<(Self::Role, M)> {
+        if from.contains(&self.client) {
+            let client = self.client;
+            return Ok((client, self.recv(ep, client).await?));
+        }
+        self.inner.recv_any(ep, from).await
+    }
+}
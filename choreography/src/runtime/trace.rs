@@ -0,0 +1,247 @@
+// Execution trace records
+//
+// A trace is the list of steps a session actually took, one `TraceRecord`
+// per send, receive, selection, and branch of each role, stored as JSON
+// lines. Timing fields use the OpenTelemetry span field names and Unix
+// nanoseconds, so a record maps one to one onto a span named
+// `choreo.<action>` and traces can be moved between both worlds.
+//
+// `TraceRecorder` is a `SessionMetrics` implementation that writes the trace
+// of a running session; wire it in through the `Instrumented` middleware or
+// the generated `run_<role>_instrumented`. `read_trace` reads traces back,
+// and also accepts session journals, whose records become zero-length steps.
+// `compiler::replay` checks a trace against its choreography and renders it.
+
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::UNIX_EPOCH;
+use thiserror::Error;
+
+use crate::effects::{ChoreographyError, SessionMetrics};
+use crate::runtime::journal::JournalRecord;
+use crate::runtime::monitor::ActionKind;
+
+/// Errors raised while reading a trace
+#[derive(Debug, Error)]
+pub enum TraceError {
+    #[error("trace I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("trace line {line} is neither a trace nor a journal record: {reason}")]
+    Malformed { line: usize, reason: String },
+}
+
+/// One step of a session as it ran
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceRecord {
+    pub session_id: String,
+    pub role: String,
+    /// Position of the step among the steps of `role`
+    pub seq: u64,
+    /// `send`, `receive`, `select`, or `branch`
+    pub action: String,
+    pub peer: String,
+    /// Message type or branch label
+    pub label: String,
+    pub start_time_unix_nano: u64,
+    /// For receives and offers, this includes the time spent waiting for the
+    /// peer
+    pub end_time_unix_nano: u64,
+    /// Set if the step failed, in which case `peer` and `label` may be empty
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl TraceRecord {
+    /// Time the step took
+    #[must_use]
+    pub fn duration(&self) -> Duration {
+        Duration::from_nanos(
+            self.end_time_unix_nano
+                .saturating_sub(self.start_time_unix_nano),
+        )
+    }
+
+    /// Name of the OpenTelemetry span of the step
+    #[must_use]
+    pub fn span_name(&self) -> String {
+        format!("choreo.{}", self.action)
+    }
+
+    /// Kind of the step, `None` for an unknown action
+    #[must_use]
+    pub fn kind(&self) -> Option<ActionKind> {
+        match self.action.as_str() {
+            "send" => Some(ActionKind::Send),
+            "receive" => Some(ActionKind::Receive),
+            "select" => Some(ActionKind::Select),
+            "branch" => Some(ActionKind::Branch),
+            _ => None,
+        }
+    }
+}
+
+impl From<JournalRecord> for TraceRecord {
+    fn from(record: JournalRecord) -> Self {
+        let time = record.timestamp_ms.saturating_mul(1_000_000);
+        Self {
+            session_id: record.session_id,
+            role: record.role,
+            seq: record.seq,
+            action: record.action,
+            peer: record.peer,
+            label: record.label,
+            start_time_unix_nano: time,
+            end_time_unix_nano: time,
+            error: None,
+        }
+    }
+}
+
+/// Read a trace of JSON lines, each a `TraceRecord` or a `JournalRecord`
+///
+/// # Errors
+///
+/// [`TraceError`] if reading fails or a line is neither record.
+pub fn read_trace(reader: impl BufRead) -> Result<Vec<TraceRecord>, TraceError> {
+    let mut records = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = match serde_json::from_str::<TraceRecord>(&line) {
+            Ok(record) => record,
+            Err(error) => serde_json::from_str::<JournalRecord>(&line)
+                .map(TraceRecord::from)
+                .map_err(|_| TraceError::Malformed {
+                    line: index + 1,
+                    reason: error.to_string(),
+                })?,
+        };
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Records the trace of a session
+///
+/// Records are kept in memory and, with `with_writer`, also written as JSON
+/// lines as soon as each step ends. A failed write is logged and stops
+/// further writes; the in-memory trace stays complete.
+pub struct TraceRecorder {
+    session_id: String,
+    state: Mutex<RecorderState>,
+}
+
+#[derive(Default)]
+struct RecorderState {
+    /// Peer and label of the step in progress, per role
+    pending: std::collections::HashMap<String, (String, String)>,
+    /// Steps recorded so far, per role
+    steps: std::collections::HashMap<String, u64>,
+    records: Vec<TraceRecord>,
+    writer: Option<Box<dyn Write + Send>>,
+}
+
+impl TraceRecorder {
+    #[must_use]
+    pub fn new(session_id: impl Into<String>) -> Self {
+        Self {
+            session_id: session_id.into(),
+            state: Mutex::new(RecorderState::default()),
+        }
+    }
+
+    /// Also write every record to `writer`
+    #[must_use]
+    pub fn with_writer(self, writer: impl Write + Send + 'static) -> Self {
+        self.lock().writer = Some(Box::new(writer));
+        self
+    }
+
+    /// Records so far, in the order the steps ended
+    #[must_use]
+    pub fn records(&self) -> Vec<TraceRecord> {
+        self.lock().records.clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, RecorderState> {
+        self.state
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+
+    fn pend(&self, role: &str, peer: &str, label: &str) {
+        self.lock()
+            .pending
+            .insert(role.to_string(), (peer.to_string(), label.to_string()));
+    }
+
+    fn record(&self, role: &str, step: ActionKind, latency: Duration, error: Option<String>) {
+        let end = now_unix_nanos();
+        let start = end.saturating_sub(u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX));
+        let mut state = self.lock();
+        let (peer, label) = state.pending.remove(role).unwrap_or_default();
+        let seq = state.steps.entry(role.to_string()).or_insert(0);
+        let record = TraceRecord {
+            session_id: self.session_id.clone(),
+            role: role.to_string(),
+            seq: *seq,
+            action: step.to_string(),
+            peer,
+            label,
+            start_time_unix_nano: start,
+            end_time_unix_nano: end,
+            error,
+        };
+        *seq += 1;
+
+        if let Some(writer) = state.writer.as_mut() {
+            let written = serde_json::to_string(&record)
+                .map_err(std::io::Error::from)
+                .and_then(|line| writeln!(writer, "{line}"))
+                .and_then(|()| writer.flush());
+            if let Err(error) = written {
+                tracing::warn!(session = %self.session_id, %error, "trace writer failed");
+                state.writer = None;
+            }
+        }
+        state.records.push(record);
+    }
+}
+
+impl SessionMetrics for TraceRecorder {
+    fn message_sent(&self, role: &str, to: &str, label: &str) {
+        self.pend(role, to, label);
+    }
+
+    fn message_received(&self, role: &str, from: &str, label: &str) {
+        self.pend(role, from, label);
+    }
+
+    fn branch_selected(&self, role: &str, to: &str, branch: &str) {
+        self.pend(role, to, branch);
+    }
+
+    fn branch_offered(&self, role: &str, from: &str, branch: &str) {
+        self.pend(role, from, branch);
+    }
+
+    fn step_completed(&self, role: &str, step: ActionKind, latency: Duration) {
+        self.record(role, step, latency, None);
+    }
+
+    fn step_failed(&self, role: &str, step: ActionKind, error: &ChoreographyError) {
+        self.record(role, step, Duration::ZERO, Some(error.to_string()));
+    }
+}
+
+fn now_unix_nanos() -> u64 {
+    crate::runtime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX))
+        .unwrap_or(0)
+}
@@ -0,0 +1,387 @@
+// Protocol version negotiation and upgrades
+//
+// Rolling out a new version of a choreography to a long-running deployment
+// means endpoints on the old and the new version meet for a while. Before a
+// session starts, a `VersionNegotiator` agrees on the version it runs: the
+// initiator offers every version it supports, each peer answers with the
+// ones it shares, and the initiator picks the highest version common to all
+// of them, or aborts the session if there is none. Versions are shared only
+// if their protocol hashes match, so a peer built from another definition
+// of "version 2" does not count as supporting it.
+//
+// Endpoints on the new version take part in sessions negotiated at the
+// previous one through a `VersionAdapter`. Code generation emits the
+// `UpgradeSpec` of adjacent versions whose difference is compatible (see
+// `compiler::upgrade`): the new version only adds branches to choices. An
+// offering role of the new version handles every branch the previous one
+// has, so the adapter only has to keep the new version's choosers from
+// picking branches their peers do not know. Such a choice is refused before
+// anything is sent.
+//
+// Message flow (initiator I, peer P):
+//   I -> P: Offer { versions }
+//   P -> I: Supported { versions }
+//   I -> P: Selected { version } | Abort { reason }
+
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::effects::{ChoreoHandler, ChoreographyError, Label, Result, RoleId};
+use crate::runtime::bootstrap::ProtocolDescriptor;
+
+/// A version of a protocol and the definition it was compiled from
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ProtocolVersion {
+    pub version: u32,
+    pub descriptor: ProtocolDescriptor,
+}
+
+impl ProtocolVersion {
+    #[must_use]
+    pub fn new(version: u32, descriptor: ProtocolDescriptor) -> Self {
+        Self {
+            version,
+            descriptor,
+        }
+    }
+}
+
+impl std::fmt::Display for ProtocolVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} v{}", self.descriptor, self.version)
+    }
+}
+
+/// Wire messages exchanged during negotiation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum NegotiationMessage {
+    Offer { versions: Vec<ProtocolVersion> },
+    Supported { versions: Vec<u32> },
+    Selected { version: u32 },
+    Abort { reason: String },
+}
+
+/// Errors raised while negotiating a version
+#[derive(Debug, Error)]
+pub enum NegotiationError {
+    #[error("Transport error during negotiation: {0}")]
+    Transport(#[from] ChoreographyError),
+
+    #[error("Role {role} supports none of the versions {offered:?}")]
+    NoCommonVersion { role: String, offered: Vec<u32> },
+
+    #[error("Negotiation aborted by initiator: {reason}")]
+    Aborted { reason: String },
+
+    #[error("Unexpected negotiation message: {0}")]
+    UnexpectedMessage(String),
+}
+
+/// Versions of a protocol an endpoint can run
+#[derive(Debug, Clone, Default)]
+pub struct VersionNegotiator {
+    versions: Vec<ProtocolVersion>,
+}
+
+impl VersionNegotiator {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run sessions of `descriptor` as `version`
+    #[must_use]
+    pub fn support(mut self, version: u32, descriptor: ProtocolDescriptor) -> Self {
+        self.versions
+            .push(ProtocolVersion::new(version, descriptor));
+        self
+    }
+
+    pub fn versions(&self) -> &[ProtocolVersion] {
+        &self.versions
+    }
+
+    /// Agree with `peers` on the highest version all of them support
+    ///
+    /// Every peer is told the outcome. If one of them shares no version
+    /// left, the others are sent `Abort`.
+    pub async fn negotiate<H, R>(
+        &self,
+        handler: &mut H,
+        endpoint: &mut H::Endpoint,
+        peers: &[R],
+    ) -> std::result::Result<ProtocolVersion, NegotiationError>
+    where
+        H: ChoreoHandler<Role = R>,
+        R: RoleId + Serialize + DeserializeOwned,
+    {
+        let offer = NegotiationMessage::Offer {
+            versions: self.versions.clone(),
+        };
+        for peer in peers {
+            handler.send(endpoint, *peer, &offer).await?;
+        }
+
+        let mut common: Vec<u32> = self.versions.iter().map(|v| v.version).collect();
+        let mut failure = None;
+        for peer in peers {
+            let reply: NegotiationMessage = handler.recv(endpoint, *peer).await?;
+            match reply {
+                NegotiationMessage::Supported { versions } => {
+                    common.retain(|version| versions.contains(version));
+                    if common.is_empty() {
+                        failure.get_or_insert(NegotiationError::NoCommonVersion {
+                            role: format!("{peer:?}"),
+                            offered: self.versions.iter().map(|v| v.version).collect(),
+                        });
+                    }
+                }
+                other => {
+                    failure.get_or_insert(NegotiationError::UnexpectedMessage(format!(
+                        "{other:?} from {peer:?}"
+                    )));
+                }
+            }
+        }
+
+        let selected = common
+            .into_iter()
+            .max()
+            .and_then(|version| self.find(version));
+        let outcome = match (&failure, &selected) {
+            (None, Some(selected)) => NegotiationMessage::Selected {
+                version: selected.version,
+            },
+            (Some(err), _) => NegotiationMessage::Abort {
+                reason: err.to_string(),
+            },
+            (None, None) => NegotiationMessage::Abort {
+                reason: "no version offered".to_string(),
+            },
+        };
+        for peer in peers {
+            handler.send(endpoint, *peer, &outcome).await?;
+        }
+
+        match (failure, selected) {
+            (None, Some(selected)) => {
+                tracing::debug!(version = %selected, "protocol version negotiated");
+                Ok(selected)
+            }
+            (Some(err), _) => Err(err),
+            (None, None) => Err(NegotiationError::Aborted {
+                reason: "no version offered".to_string(),
+            }),
+        }
+    }
+
+    /// Answer the offer of `initiator` and wait for the version it selects
+    pub async fn accept<H, R>(
+        &self,
+        handler: &mut H,
+        endpoint: &mut H::Endpoint,
+        initiator: R,
+    ) -> std::result::Result<ProtocolVersion, NegotiationError>
+    where
+        H: ChoreoHandler<Role = R>,
+        R: RoleId + Serialize + DeserializeOwned,
+    {
+        let offer: NegotiationMessage = handler.recv(endpoint, initiator).await?;
+        let NegotiationMessage::Offer { versions } = offer else {
+            return Err(NegotiationError::UnexpectedMessage(format!("{offer:?}")));
+        };
+        let shared = versions
+            .iter()
+            .filter(|offered| self.versions.contains(offered))
+            .map(|offered| offered.version)
+            .collect();
+        handler
+            .send(
+                endpoint,
+                initiator,
+                &NegotiationMessage::Supported { versions: shared },
+            )
+            .await?;
+
+        let outcome: NegotiationMessage = handler.recv(endpoint, initiator).await?;
+        match outcome {
+            NegotiationMessage::Selected { version } => self.find(version).ok_or_else(|| {
+                NegotiationError::UnexpectedMessage(format!("unsupported version {version}"))
+            }),
+            NegotiationMessage::Abort { reason } => Err(NegotiationError::Aborted { reason }),
+            other => Err(NegotiationError::UnexpectedMessage(format!("{other:?}"))),
+        }
+    }
+
+    fn find(&self, version: u32) -> Option<ProtocolVersion> {
+        self.versions.iter().find(|v| v.version == version).cloned()
+    }
+}
+
+/// Branch a version added to a choice
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddedBranch {
+    /// Role making the choice
+    pub chooser: &'static str,
+    pub label: &'static str,
+}
+
+/// Differences between adjacent versions of a protocol, generated for
+/// compatible ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpgradeSpec {
+    pub protocol: &'static str,
+    pub previous: u32,
+    pub previous_hash: u64,
+    pub current: u32,
+    pub current_hash: u64,
+    /// Branches the previous version does not have
+    pub added_branches: &'static [AddedBranch],
+}
+
+impl UpgradeSpec {
+    #[must_use]
+    pub fn previous_version(&self) -> ProtocolVersion {
+        ProtocolVersion::new(
+            self.previous,
+            ProtocolDescriptor::new(self.protocol, self.previous_hash),
+        )
+    }
+
+    #[must_use]
+    pub fn current_version(&self) -> ProtocolVersion {
+        ProtocolVersion::new(
+            self.current,
+            ProtocolDescriptor::new(self.protocol, self.current_hash),
+        )
+    }
+
+    /// Negotiator for endpoints of the current version, which also run
+    /// sessions of the previous one through a `VersionAdapter`
+    #[must_use]
+    pub fn negotiator(&self) -> VersionNegotiator {
+        let previous = self.previous_version();
+        let current = self.current_version();
+        VersionNegotiator::new()
+            .support(previous.version, previous.descriptor)
+            .support(current.version, current.descriptor)
+    }
+
+    /// Whether `label` is a branch of `chooser` the previous version lacks
+    pub fn is_added(&self, chooser: &str, label: &str) -> bool {
+        self.added_branches
+            .iter()
+            .any(|added| added.chooser == chooser && added.label == label)
+    }
+}
+
+/// Runs a role of the current version of a protocol in a session
+/// negotiated at the version `spec` upgrades from, or at the current one
+pub struct VersionAdapter<H> {
+    inner: H,
+    spec: &'static UpgradeSpec,
+    version: u32,
+}
+
+impl<H: ChoreoHandler> VersionAdapter<H> {
+    /// Wrap `inner` for a session running `negotiated`
+    pub fn new(inner: H, spec: &'static UpgradeSpec, negotiated: &ProtocolVersion) -> Self {
+        Self {
+            inner,
+            spec,
+            version: negotiated.version,
+        }
+    }
+
+    /// Version the session runs
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn into_inner(self) -> H {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<H: ChoreoHandler + Send> ChoreoHandler for VersionAdapter<H> {
+    type Role = H::Role;
+    type Endpoint = H::Endpoint;
+
+    async fn send<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        msg: &M,
+    ) -> Result<()> {
+        self.inner.send(ep, to, msg).await
+    }
+
+    async fn recv<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+    ) -> Result<M> {
+        self.inner.recv(ep, from).await
+    }
+
+    async fn send_labelled<M: Serialize + Send + Sync>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        to: Self::Role,
+        label: &str,
+        msg: &M,
+    ) -> Result<()> {
+        self.inner.send_labelled(ep, to, label, msg).await
+    }
+
+    async fn recv_labelled<M: DeserializeOwned + Send>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        label: &str,
+    ) -> Result<M> {
+        self.inner.recv_labelled(ep, from, label).await
+    }
+
+    async fn choose(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        who: Self::Role,
+        label: Label,
+    ) -> Result<()> {
+        let chooser = format!("{who:?}");
+        if self.version == self.spec.previous && self.spec.is_added(&chooser, label.0) {
+            return Err(ChoreographyError::ProtocolViolation(format!(
+                "branch {} of {chooser} was added in version {}, the session runs version {}",
+                label.0, self.spec.current, self.version
+            )));
+        }
+        self.inner.choose(ep, who, label).await
+    }
+
+    async fn offer(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        from: Self::Role,
+        labels: &[Label],
+    ) -> Result<Label> {
+        self.inner.offer(ep, from, labels).await
+    }
+
+    async fn with_timeout<F, T>(
+        &mut self,
+        ep: &mut Self::Endpoint,
+        at: Self::Role,
+        dur: Duration,
+        body: F,
+    ) -> Result<T>
+    where
+        F: std::future::Future<Output = Result<T>> + Send,
+    {
+        self.inner.with_timeout(ep, at, dur, body).await
+    }
+}
@@ -0,0 +1,668 @@
+// WebSocket transport for browser roles
+//
+// Browsers can only reach other processes over WebSocket, so a role compiled
+// to `wasm32-unknown-unknown` talks to native roles through this transport.
+// Every protocol frame is one binary WebSocket message and branch labels are
+// bincode-encoded strings, as with the other `RumpsteakSession` transports,
+// so both ends can be registered on a `RumpsteakEndpoint` unchanged.
+//
+// Key pieces:
+// - WebSocketTransport::accept (native): answers the opening handshake of a
+//   browser, or any RFC 6455 client, on a `futures` byte stream such as
+//   `RuntimeProvider::TcpStream`.
+// - WebSocketTransport::connect (native): dials a WebSocket server, for
+//   native roles that join a session hosted elsewhere.
+// - WebSocketTransport::open (wasm32): opens a `web_sys::WebSocket`. The
+//   socket stays on a local task and frames cross channels, so the session
+//   is `Send` like every other one.
+//
+// Extensions and subprotocols are not negotiated, and text messages are
+// delivered as their UTF-8 bytes.
+
+use thiserror::Error;
+
+use crate::effects::ChoreographyError;
+
+/// Default upper bound for a single message (16 MiB).
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 16 * 1024 * 1024;
+
+/// Errors raised while establishing or using a WebSocket session.
+#[derive(Debug, Error)]
+pub enum WebSocketError {
+    #[error("WebSocket I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("WebSocket handshake failed: {0}")]
+    Handshake(String),
+
+    #[error("WebSocket protocol violation: {0}")]
+    Protocol(String),
+
+    #[error("Frame of {size} bytes exceeds maximum of {max} bytes")]
+    FrameTooLarge { size: usize, max: usize },
+
+    #[error("WebSocket connection closed")]
+    Closed,
+
+    #[error("WebSocket session failed on an earlier write")]
+    Failed,
+}
+
+impl From<WebSocketError> for ChoreographyError {
+    fn from(err: WebSocketError) -> Self {
+        ChoreographyError::Transport(err.to_string())
+    }
+}
+
+/// Opens WebSocket connections and exposes them as sessions.
+#[derive(Debug, Clone)]
+pub struct WebSocketTransport {
+    #[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+    max_frame_size: usize,
+}
+
+impl WebSocketTransport {
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+
+    /// Largest message accepted from the peer. Browsers enforce their own
+    /// limit, so only native connections check it.
+    #[must_use]
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+}
+
+impl Default for WebSocketTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use base64::{engine::general_purpose::STANDARD, Engine as _};
+    use futures::future::BoxFuture;
+    use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+    use sha1::{Digest, Sha1};
+
+    use super::{WebSocketError, WebSocketTransport};
+    use crate::effects::handlers::rumpsteak::{SessionTypeDynamic, SessionUpdate};
+    use crate::effects::{ChoreographyError, RumpsteakSession};
+
+    /// Appended to the client key to form the accept hash (RFC 6455, 1.3).
+    const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+    /// Longest handshake accepted before the connection is rejected.
+    const MAX_HANDSHAKE_SIZE: usize = 8 * 1024;
+
+    /// Bytes read from the connection at a time.
+    const READ_CHUNK_SIZE: usize = 8 * 1024;
+
+    const OPCODE_CONTINUATION: u8 = 0x0;
+    const OPCODE_TEXT: u8 = 0x1;
+    const OPCODE_BINARY: u8 = 0x2;
+    const OPCODE_CLOSE: u8 = 0x8;
+    const OPCODE_PING: u8 = 0x9;
+    const OPCODE_PONG: u8 = 0xA;
+
+    impl WebSocketTransport {
+        /// Answer the opening handshake of a client connected over `io`.
+        pub async fn accept<IO>(&self, mut io: IO) -> Result<RumpsteakSession, WebSocketError>
+        where
+            IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        {
+            let request = read_head(&mut io).await?;
+            let mut lines = request.lines();
+            let request_line = lines.next().unwrap_or_default();
+            if !request_line.starts_with("GET ") {
+                return Err(WebSocketError::Handshake(format!(
+                    "expected a GET request, got '{request_line}'"
+                )));
+            }
+            let headers: Vec<(&str, &str)> = lines.filter_map(|l| l.split_once(':')).collect();
+            let header = |name: &str| {
+                headers
+                    .iter()
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case(name))
+                    .map(|(_, value)| value.trim())
+            };
+            if !header("Upgrade").is_some_and(|v| v.eq_ignore_ascii_case("websocket")) {
+                return Err(WebSocketError::Handshake(
+                    "request does not upgrade to websocket".to_string(),
+                ));
+            }
+            let key = header("Sec-WebSocket-Key").ok_or_else(|| {
+                WebSocketError::Handshake("missing Sec-WebSocket-Key header".to_string())
+            })?;
+
+            let response = format!(
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                 Upgrade: websocket\r\n\
+                 Connection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(key)
+            );
+            io.write_all(response.as_bytes()).await?;
+            io.flush().await?;
+            tracing::debug!("WebSocket session established (server)");
+            Ok(self.wrap_stream(io, false))
+        }
+
+        /// Open a WebSocket to `path` on `host` over the connected `io`.
+        pub async fn connect<IO>(
+            &self,
+            host: &str,
+            path: &str,
+            mut io: IO,
+        ) -> Result<RumpsteakSession, WebSocketError>
+        where
+            IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        {
+            let key = STANDARD.encode(uuid::Uuid::new_v4().as_bytes());
+            let request = format!(
+                "GET {path} HTTP/1.1\r\n\
+                 Host: {host}\r\n\
+                 Upgrade: websocket\r\n\
+                 Connection: Upgrade\r\n\
+                 Sec-WebSocket-Key: {key}\r\n\
+                 Sec-WebSocket-Version: 13\r\n\r\n"
+            );
+            io.write_all(request.as_bytes()).await?;
+            io.flush().await?;
+
+            let response = read_head(&mut io).await?;
+            let mut lines = response.lines();
+            let status_line = lines.next().unwrap_or_default();
+            if status_line.split_whitespace().nth(1) != Some("101") {
+                return Err(WebSocketError::Handshake(format!(
+                    "server answered '{status_line}'"
+                )));
+            }
+            let expected = accept_key(&key);
+            let accepted = lines
+                .filter_map(|l| l.split_once(':'))
+                .any(|(name, value)| {
+                    name.trim().eq_ignore_ascii_case("Sec-WebSocket-Accept")
+                        && value.trim() == expected
+                });
+            if !accepted {
+                return Err(WebSocketError::Handshake(
+                    "server did not accept the WebSocket key".to_string(),
+                ));
+            }
+            tracing::debug!(host, path, "WebSocket session established (client)");
+            Ok(self.wrap_stream(io, true))
+        }
+
+        fn wrap_stream<IO>(&self, stream: IO, masked: bool) -> RumpsteakSession
+        where
+            IO: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        {
+            RumpsteakSession::new(Box::new(WebSocketSession::new(
+                stream,
+                masked,
+                self.max_frame_size,
+            )))
+        }
+    }
+
+    /// `Sec-WebSocket-Accept` value answering the client key `key`.
+    fn accept_key(key: &str) -> String {
+        let mut hasher = Sha1::new();
+        hasher.update(key.as_bytes());
+        hasher.update(ACCEPT_GUID.as_bytes());
+        STANDARD.encode(hasher.finalize())
+    }
+
+    /// Read an HTTP head up to and including the blank line, and no further,
+    /// so frames sent right after the handshake stay in the stream.
+    async fn read_head<IO: AsyncRead + Unpin>(io: &mut IO) -> Result<String, WebSocketError> {
+        let mut head = Vec::new();
+        while !head.ends_with(b"\r\n\r\n") {
+            if head.len() >= MAX_HANDSHAKE_SIZE {
+                return Err(WebSocketError::Handshake(format!(
+                    "handshake exceeds {MAX_HANDSHAKE_SIZE} bytes"
+                )));
+            }
+            let mut byte = [0u8; 1];
+            io.read_exact(&mut byte).await?;
+            head.push(byte[0]);
+        }
+        String::from_utf8(head)
+            .map_err(|_| WebSocketError::Handshake("handshake is not UTF-8".to_string()))
+    }
+
+    /// Message framing over an upgraded connection. Clients mask what they
+    /// send, servers do not (RFC 6455, 5.3).
+    ///
+    /// Bytes move through `received` and `unsent`, so a send or receive
+    /// dropped midway, by `recv_any` or a deadline, leaves whole frames on
+    /// the connection: the next receive parses what was read, and the next
+    /// operation finishes writing what was not. Once a write fails the
+    /// session refuses further frames, so a retried send cannot follow part
+    /// of its first attempt.
+    struct WebSocketSession<IO> {
+        stream: IO,
+        masked: bool,
+        max_frame_size: usize,
+        received: Vec<u8>,
+        fragments: Vec<u8>,
+        unsent: Vec<u8>,
+        failed: bool,
+    }
+
+    /// One frame taken off the connection
+    struct Frame {
+        fin: bool,
+        opcode: u8,
+        payload: Vec<u8>,
+    }
+
+    impl<IO> WebSocketSession<IO>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        fn new(stream: IO, masked: bool, max_frame_size: usize) -> Self {
+            Self {
+                stream,
+                masked,
+                max_frame_size,
+                received: Vec::new(),
+                fragments: Vec::new(),
+                unsent: Vec::new(),
+                failed: false,
+            }
+        }
+
+        async fn write_frame(&mut self, opcode: u8, payload: &[u8]) -> Result<(), WebSocketError> {
+            // Frames of sends dropped midway go out first
+            self.flush_unsent().await?;
+            let mut frame = Vec::with_capacity(payload.len() + 14);
+            frame.push(0x80 | opcode);
+            let mask_bit = if self.masked { 0x80 } else { 0 };
+            match payload.len() {
+                len @ 0..=125 => frame.push(mask_bit | len as u8),
+                len @ 126..=0xFFFF => {
+                    frame.push(mask_bit | 126);
+                    frame.extend_from_slice(&(len as u16).to_be_bytes());
+                }
+                len => {
+                    frame.push(mask_bit | 127);
+                    frame.extend_from_slice(&(len as u64).to_be_bytes());
+                }
+            }
+            if self.masked {
+                let mut mask = [0u8; 4];
+                mask.copy_from_slice(&uuid::Uuid::new_v4().as_bytes()[..4]);
+                frame.extend_from_slice(&mask);
+                frame.extend(payload.iter().zip(mask.iter().cycle()).map(|(b, m)| b ^ m));
+            } else {
+                frame.extend_from_slice(payload);
+            }
+            self.unsent = frame;
+            self.flush_unsent().await
+        }
+
+        /// Write out `unsent`, failing the session if the connection fails
+        async fn flush_unsent(&mut self) -> Result<(), WebSocketError> {
+            if self.failed {
+                return Err(WebSocketError::Failed);
+            }
+            let result = async {
+                while !self.unsent.is_empty() {
+                    let written = self.stream.write(&self.unsent).await?;
+                    if written == 0 {
+                        return Err(std::io::ErrorKind::WriteZero.into());
+                    }
+                    self.unsent.drain(..written);
+                }
+                self.stream.flush().await
+            }
+            .await;
+            if result.is_err() {
+                self.failed = true;
+            }
+            Ok(result?)
+        }
+
+        /// Next data message, answering pings and reassembling fragments
+        async fn read_message(&mut self) -> Result<Vec<u8>, WebSocketError> {
+            // The peer may be waiting for a frame a dropped send left behind
+            self.flush_unsent().await?;
+            let mut chunk = [0u8; READ_CHUNK_SIZE];
+            loop {
+                while let Some(frame) = self.take_frame()? {
+                    match frame.opcode {
+                        OPCODE_PING => self.write_frame(OPCODE_PONG, &frame.payload).await?,
+                        OPCODE_PONG => {}
+                        OPCODE_CLOSE => {
+                            // Echo the close; the peer may already be gone
+                            let _ = self.write_frame(OPCODE_CLOSE, &frame.payload).await;
+                            return Err(WebSocketError::Closed);
+                        }
+                        OPCODE_TEXT | OPCODE_BINARY | OPCODE_CONTINUATION => {
+                            self.fragments.extend_from_slice(&frame.payload);
+                            if frame.fin {
+                                return Ok(std::mem::take(&mut self.fragments));
+                            }
+                        }
+                        other => {
+                            return Err(WebSocketError::Protocol(format!(
+                                "unknown opcode {other:#x}"
+                            )))
+                        }
+                    }
+                }
+                // Bytes are kept as soon as they are read, so the read is the
+                // only point where this future may be dropped
+                let read = self.stream.read(&mut chunk).await?;
+                if read == 0 {
+                    return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+                }
+                self.received.extend_from_slice(&chunk[..read]);
+            }
+        }
+
+        /// Take the first frame off `received` if it has fully arrived
+        fn take_frame(&mut self) -> Result<Option<Frame>, WebSocketError> {
+            let Some(&[first, second]) = self.received.get(..2) else {
+                return Ok(None);
+            };
+            let (len, mut offset) = match second & 0x7F {
+                126 => {
+                    let Some(&[a, b]) = self.received.get(2..4) else {
+                        return Ok(None);
+                    };
+                    (u64::from(u16::from_be_bytes([a, b])), 4)
+                }
+                127 => {
+                    let Some(bytes) = self.received.get(2..10) else {
+                        return Ok(None);
+                    };
+                    let mut len = [0u8; 8];
+                    len.copy_from_slice(bytes);
+                    (u64::from_be_bytes(len), 10)
+                }
+                len => (u64::from(len), 2),
+            };
+            let size = usize::try_from(len).unwrap_or(usize::MAX);
+            if size.saturating_add(self.fragments.len()) > self.max_frame_size {
+                return Err(WebSocketError::FrameTooLarge {
+                    size,
+                    max: self.max_frame_size,
+                });
+            }
+            let mut mask = None;
+            if second & 0x80 != 0 {
+                let Some(&[a, b, c, d]) = self.received.get(offset..offset + 4) else {
+                    return Ok(None);
+                };
+                mask = Some([a, b, c, d]);
+                offset += 4;
+            }
+            let Some(payload) = self.received.get(offset..offset + size) else {
+                return Ok(None);
+            };
+            let mut payload = payload.to_vec();
+            if let Some(mask) = mask {
+                for (byte, m) in payload.iter_mut().zip(mask.iter().cycle()) {
+                    *byte ^= m;
+                }
+            }
+            self.received.drain(..offset + size);
+            Ok(Some(Frame {
+                fin: first & 0x80 != 0,
+                opcode: first & 0x0F,
+                payload,
+            }))
+        }
+    }
+
+    impl<IO> SessionTypeDynamic for WebSocketSession<IO>
+    where
+        IO: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        fn type_name(&self) -> &'static str {
+            "WebSocketSession"
+        }
+
+        fn send(
+            &mut self,
+            data: Vec<u8>,
+        ) -> BoxFuture<'_, crate::effects::Result<SessionUpdate<()>>> {
+            Box::pin(async move {
+                self.write_frame(OPCODE_BINARY, &data).await?;
+                Ok(SessionUpdate::new(()).with_description("Send"))
+            })
+        }
+
+        fn recv(&mut self) -> BoxFuture<'_, crate::effects::Result<SessionUpdate<Vec<u8>>>> {
+            Box::pin(async move {
+                let bytes = self.read_message().await?;
+                Ok(SessionUpdate::new(bytes).with_description("Recv"))
+            })
+        }
+
+        fn choose(
+            &mut self,
+            label: &str,
+        ) -> BoxFuture<'_, crate::effects::Result<SessionUpdate<()>>> {
+            let label = label.to_string();
+            Box::pin(async move {
+                let bytes = bincode::serialize(&label).map_err(|e| {
+                    ChoreographyError::Transport(format!("Label serialization failed: {e}"))
+                })?;
+                self.write_frame(OPCODE_BINARY, &bytes).await?;
+                Ok(SessionUpdate::new(()).with_description("Choose"))
+            })
+        }
+
+        fn offer(&mut self) -> BoxFuture<'_, crate::effects::Result<SessionUpdate<String>>> {
+            Box::pin(async move {
+                let bytes = self.read_message().await?;
+                let label: String = bincode::deserialize(&bytes).map_err(|e| {
+                    ChoreographyError::Transport(format!("Label deserialization failed: {e}"))
+                })?;
+                Ok(SessionUpdate::new(label).with_description("Offer"))
+            })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+
+        fn duplex(
+            max_buf_size: usize,
+        ) -> (
+            Compat<tokio::io::DuplexStream>,
+            Compat<tokio::io::DuplexStream>,
+        ) {
+            let (a, b) = tokio::io::duplex(max_buf_size);
+            (a.compat(), b.compat())
+        }
+
+        #[test]
+        fn test_accept_key() {
+            // Example from RFC 6455, section 1.3
+            assert_eq!(
+                accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+                "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+            );
+        }
+
+        #[tokio::test]
+        async fn test_session_over_duplex() {
+            // Large enough to hold a whole message, so sends need no reader
+            let (client_io, server_io) = duplex(128 * 1024);
+            let transport = WebSocketTransport::new();
+            let server = transport.clone();
+            let accepting = tokio::spawn(async move { server.accept(server_io).await });
+            let mut client = transport
+                .connect("localhost", "/session", client_io)
+                .await
+                .unwrap();
+            let mut server = accepting.await.unwrap().unwrap();
+
+            let large = vec![7u8; 70_000];
+            client.send(large.clone()).await.unwrap();
+            assert_eq!(server.recv().await.unwrap().output, large);
+            server.choose("accept").await.unwrap();
+            assert_eq!(client.offer().await.unwrap().output, "accept");
+        }
+
+        #[tokio::test]
+        async fn test_dropped_operations_leave_whole_frames() {
+            use crate::runtime::timeout;
+            use std::time::Duration;
+
+            let (mut peer, io) = duplex(16);
+            let mut session =
+                WebSocketSession::new(io, false, super::super::DEFAULT_MAX_FRAME_SIZE);
+
+            // A receive dropped after half of a frame keeps that half
+            peer.write_all(&[0x82, 5, b'h', b'e']).await.unwrap();
+            let dropped = timeout(Duration::from_millis(20), session.recv()).await;
+            assert!(matches!(dropped, Err(ChoreographyError::Timeout(_))));
+            peer.write_all(b"llo").await.unwrap();
+            assert_eq!(session.recv().await.unwrap().output, b"hello".to_vec());
+
+            // A send dropped while the peer is not reading is finished by
+            // the next operation
+            let dropped = timeout(Duration::from_millis(20), session.send(vec![1; 100])).await;
+            assert!(matches!(dropped, Err(ChoreographyError::Timeout(_))));
+            let reading = tokio::spawn(async move {
+                let mut frame = vec![0u8; 102];
+                peer.read_exact(&mut frame).await.unwrap();
+                peer.write_all(&[0x82, 1, 7]).await.unwrap();
+                frame
+            });
+            assert_eq!(session.recv().await.unwrap().output, vec![7]);
+            let frame = reading.await.unwrap();
+            assert_eq!(frame[..2], [0x82, 100]);
+            assert!(frame[2..].iter().all(|&byte| byte == 1));
+        }
+
+        #[tokio::test]
+        async fn test_rejects_plain_http() {
+            let (mut client_io, server_io) = duplex(1024);
+            let accepting =
+                tokio::spawn(async move { WebSocketTransport::new().accept(server_io).await });
+            client_io
+                .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+                .await
+                .unwrap();
+            assert!(matches!(
+                accepting.await.unwrap(),
+                Err(WebSocketError::Handshake(_))
+            ));
+        }
+
+        #[tokio::test]
+        async fn test_oversized_message() {
+            let (client_io, server_io) = duplex(1024);
+            let server = WebSocketTransport::new().with_max_frame_size(8);
+            let accepting = tokio::spawn(async move { server.accept(server_io).await });
+            let mut client = WebSocketTransport::new()
+                .connect("localhost", "/", client_io)
+                .await
+                .unwrap();
+            let mut server = accepting.await.unwrap().unwrap();
+            client.send(vec![0; 9]).await.unwrap();
+            assert!(server.recv().await.is_err());
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+mod browser {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use futures::channel::{mpsc, oneshot};
+    use futures::StreamExt;
+    use wasm_bindgen::closure::Closure;
+    use wasm_bindgen::JsCast;
+    use web_sys::{BinaryType, CloseEvent, Event, MessageEvent, WebSocket};
+
+    use super::{WebSocketError, WebSocketTransport};
+    use crate::effects::RumpsteakSession;
+
+    impl WebSocketTransport {
+        /// Open a browser WebSocket to `url` and wait until it is connected.
+        pub async fn open(&self, url: &str) -> Result<RumpsteakSession, WebSocketError> {
+            let socket = WebSocket::new(url)
+                .map_err(|e| WebSocketError::Handshake(format!("{url}: {e:?}")))?;
+            socket.set_binary_type(BinaryType::Arraybuffer);
+
+            let (opened_tx, opened_rx) = oneshot::channel::<Result<(), String>>();
+            let opened_tx = Rc::new(RefCell::new(Some(opened_tx)));
+            let on_open = {
+                let opened_tx = Rc::clone(&opened_tx);
+                Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+                    if let Some(tx) = opened_tx.borrow_mut().take() {
+                        let _ = tx.send(Ok(()));
+                    }
+                })
+            };
+            let on_error = Closure::<dyn FnMut(Event)>::new(move |_: Event| {
+                if let Some(tx) = opened_tx.borrow_mut().take() {
+                    let _ = tx.send(Err("connection failed".to_string()));
+                }
+            });
+            socket.set_onopen(Some(on_open.as_ref().unchecked_ref()));
+            socket.set_onerror(Some(on_error.as_ref().unchecked_ref()));
+            let opened = opened_rx.await;
+            socket.set_onopen(None);
+            socket.set_onerror(None);
+            opened
+                .map_err(|_| WebSocketError::Closed)?
+                .map_err(|e| WebSocketError::Handshake(format!("{url}: {e}")))?;
+
+            let (incoming_tx, incoming_rx) = mpsc::unbounded::<Vec<u8>>();
+            let on_message = {
+                let incoming_tx = incoming_tx.clone();
+                Closure::<dyn FnMut(MessageEvent)>::new(move |event: MessageEvent| {
+                    let data = event.data();
+                    let bytes = match data.dyn_into::<js_sys::ArrayBuffer>() {
+                        Ok(buffer) => js_sys::Uint8Array::new(&buffer).to_vec(),
+                        Err(data) => data.as_string().unwrap_or_default().into_bytes(),
+                    };
+                    let _ = incoming_tx.unbounded_send(bytes);
+                })
+            };
+            // Closing the channel ends the stream, so a pending receive fails
+            let on_close = Closure::<dyn FnMut(CloseEvent)>::new(move |_: CloseEvent| {
+                incoming_tx.close_channel();
+            });
+            socket.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+            socket.set_onclose(Some(on_close.as_ref().unchecked_ref()));
+
+            // The socket is not `Send`, so it stays on this task; dropping the
+            // session ends the loop and closes the connection.
+            let (outgoing_tx, mut outgoing_rx) = mpsc::unbounded::<Vec<u8>>();
+            wasm_bindgen_futures::spawn_local(async move {
+                while let Some(frame) = outgoing_rx.next().await {
+                    if let Err(e) = socket.send_with_u8_array(&frame) {
+                        tracing::warn!("WebSocket send failed: {e:?}");
+                        break;
+                    }
+                }
+                socket.set_onmessage(None);
+                socket.set_onclose(None);
+                let _ = socket.close();
+                drop((on_message, on_close));
+            });
+
+            tracing::debug!(url, "WebSocket session established (browser)");
+            Ok(RumpsteakSession::from_sink_stream(outgoing_tx, incoming_rx))
+        }
+    }
+}
@@ -0,0 +1,54 @@
+// Debug-build diagnostics for session types dropped mid-protocol
+
+#![cfg(debug_assertions)]
+
+use futures::channel::mpsc::{UnboundedReceiver, UnboundedSender};
+use futures::executor;
+use rumpsteak_aura::{
+    channel::Bidirectional, session, try_session, End, Message, Receive, Role, Roles, Send,
+};
+use std::error::Error;
+
+type Channel = Bidirectional<UnboundedSender<Label>, UnboundedReceiver<Label>>;
+
+#[derive(Roles)]
+struct Roles(Client, Server);
+
+#[derive(Role)]
+#[message(Label)]
+#[protocol(Adder)]
+struct Client(#[route(Server)] Channel);
+
+#[derive(Role)]
+#[message(Label)]
+#[protocol(Adder)]
+struct Server(#[route(Client)] Channel);
+
+#[derive(Message)]
+enum Label {
+    Add(Add),
+    Sum(Sum),
+}
+
+struct Add;
+
+struct Sum;
+
+#[session]
+type ClientAdder = Send<Server, Add, Receive<Server, Sum, End>>;
+
+#[test]
+#[should_panic(
+    expected = "Client of Adder dropped at `receive Sum from Server` without completing the session"
+)]
+fn test_abandoned_session_names_protocol_role_and_step() {
+    let Roles(mut client, _server) = Roles::default();
+    executor::block_on(async {
+        let _ = try_session(&mut client, |s: ClientAdder<'_, _>| async {
+            let s = s.send(Add).await?;
+            drop(s);
+            Err::<((), End<'_, _>), Box<dyn Error>>("gave up".into())
+        })
+        .await;
+    });
+}
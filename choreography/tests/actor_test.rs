@@ -0,0 +1,197 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for roles played by actors
+
+use futures::channel::mpsc;
+use futures::StreamExt;
+use rumpsteak_aura_choreography::runtime::actor::{
+    ActorEndpoint, ActorHandler, Envelope, EnvelopeKind,
+};
+use rumpsteak_aura_choreography::{ChoreoHandler, ChoreographyError, Label};
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Role {
+    Buyer,
+    Seller,
+    Shipper,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+struct Quote(u32);
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+struct Slot(String);
+
+/// An application actor: it handles envelopes by delivering them to the
+/// session it plays its role in
+fn spawn_actor(endpoint: &ActorEndpoint<Role>) -> mpsc::UnboundedSender<Envelope<Role>> {
+    let (address, mut messages) = mpsc::unbounded::<Envelope<Role>>();
+    let mailbox = endpoint.mailbox();
+    tokio::spawn(async move {
+        while let Some(envelope) = messages.next().await {
+            if !mailbox.deliver(envelope) {
+                break;
+            }
+        }
+    });
+    address
+}
+
+#[tokio::test]
+async fn test_envelopes_are_taken_in_protocol_order() {
+    let buyer = ActorEndpoint::new(Role::Buyer);
+    let seller = ActorEndpoint::new(Role::Seller);
+    let shipper = ActorEndpoint::new(Role::Shipper);
+    let (to_buyer, to_seller, to_shipper) = (
+        spawn_actor(&buyer),
+        spawn_actor(&seller),
+        spawn_actor(&shipper),
+    );
+    let mut buyer = buyer
+        .with_peer(Role::Seller, to_seller.clone())
+        .with_peer(Role::Shipper, to_shipper.clone());
+    let mut seller = seller.with_peer(Role::Buyer, to_buyer.clone());
+    let mut shipper = shipper.with_peer(Role::Buyer, to_buyer);
+
+    // The shipper's slot reaches the buyer's actor before the seller's quote
+    let mut handler = ActorHandler::new();
+    handler
+        .send(&mut shipper, Role::Buyer, &Slot("tuesday".into()))
+        .await
+        .unwrap();
+    handler
+        .send(&mut seller, Role::Buyer, &Quote(90))
+        .await
+        .unwrap();
+
+    let quote: Quote = handler.recv(&mut buyer, Role::Seller).await.unwrap();
+    assert_eq!(quote, Quote(90));
+    let slot: Slot = handler.recv(&mut buyer, Role::Shipper).await.unwrap();
+    assert_eq!(slot, Slot("tuesday".into()));
+
+    // Choices reach every peer of the chooser
+    handler
+        .choose(&mut buyer, Role::Buyer, Label("accept"))
+        .await
+        .unwrap();
+    let Label(label) = handler
+        .offer(&mut seller, Role::Buyer, &[Label("accept")])
+        .await
+        .unwrap();
+    assert_eq!(label, "accept");
+    let Label(label) = handler
+        .offer(&mut shipper, Role::Buyer, &[Label("accept")])
+        .await
+        .unwrap();
+    assert_eq!(label, "accept");
+
+    handler
+        .send(&mut seller, Role::Buyer, &Quote(80))
+        .await
+        .unwrap();
+    handler
+        .send(&mut shipper, Role::Buyer, &Quote(5))
+        .await
+        .unwrap();
+    let mut quotes = Vec::new();
+    for _ in 0..2 {
+        let (from, Quote(quote)) = handler
+            .recv_any(&mut buyer, &[Role::Seller, Role::Shipper])
+            .await
+            .unwrap();
+        quotes.push((quote, from));
+    }
+    quotes.sort_unstable_by_key(|(quote, _)| *quote);
+    assert_eq!(quotes, [(5, Role::Shipper), (80, Role::Seller)]);
+}
+
+#[tokio::test]
+async fn test_out_of_step_envelopes_are_violations() {
+    let mut buyer = ActorEndpoint::new(Role::Buyer);
+    let to_buyer = spawn_actor(&buyer);
+    let mut seller = ActorEndpoint::new(Role::Seller).with_peer(Role::Buyer, to_buyer.clone());
+    let mut handler = ActorHandler::new();
+
+    handler
+        .send(&mut seller, Role::Buyer, &Quote(90))
+        .await
+        .unwrap();
+    let result: Result<Slot, _> = handler.recv(&mut buyer, Role::Seller).await;
+    assert!(matches!(
+        result,
+        Err(ChoreographyError::ProtocolViolation(_))
+    ));
+
+    to_buyer
+        .unbounded_send(Envelope {
+            from: Role::Seller,
+            kind: EnvelopeKind::Choice,
+            label: "reject".into(),
+            payload: Vec::new(),
+        })
+        .unwrap();
+    let result: Result<Quote, _> = handler.recv(&mut buyer, Role::Seller).await;
+    assert!(matches!(
+        result,
+        Err(ChoreographyError::ProtocolViolation(_))
+    ));
+
+    // Choices of branches the protocol does not offer are refused
+    to_buyer
+        .unbounded_send(Envelope {
+            from: Role::Seller,
+            kind: EnvelopeKind::Choice,
+            label: "haggle".into(),
+            payload: Vec::new(),
+        })
+        .unwrap();
+    let result = handler
+        .offer(
+            &mut buyer,
+            Role::Seller,
+            &[Label("accept"), Label("reject")],
+        )
+        .await;
+    assert!(matches!(
+        result,
+        Err(ChoreographyError::ProtocolViolation(_))
+    ));
+
+    let result = handler.send(&mut seller, Role::Shipper, &Quote(1)).await;
+    assert!(matches!(result, Err(ChoreographyError::UnknownRole(_))));
+}
+
+#[cfg(feature = "actix")]
+#[test]
+fn test_roles_run_as_actix_actors() {
+    use actix::Actor;
+    use rumpsteak_aura_choreography::runtime::actor::RoleActor;
+
+    actix::System::new().block_on(async {
+        let buyer = ActorEndpoint::new(Role::Buyer);
+        let seller = ActorEndpoint::new(Role::Seller);
+        let buyer_actor = RoleActor::new(buyer.mailbox()).start();
+        let seller_actor = RoleActor::new(seller.mailbox()).start();
+        let mut buyer = buyer.with_peer(Role::Seller, seller_actor.recipient());
+        let mut seller = seller.with_peer(Role::Buyer, buyer_actor.recipient());
+
+        let mut handler = ActorHandler::new();
+        handler
+            .send(&mut seller, Role::Buyer, &Quote(42))
+            .await
+            .unwrap();
+        let quote: Quote = handler.recv(&mut buyer, Role::Seller).await.unwrap();
+        assert_eq!(quote, Quote(42));
+        handler
+            .choose(&mut buyer, Role::Buyer, Label("accept"))
+            .await
+            .unwrap();
+        let Label(label) = handler
+            .offer(&mut seller, Role::Buyer, &[Label("accept")])
+            .await
+            .unwrap();
+        assert_eq!(label, "accept");
+    });
+}
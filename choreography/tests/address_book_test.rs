@@ -0,0 +1,95 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for addressing role instances through a RoleAddressBook
+
+use rumpsteak_aura_choreography::runtime::address_book::{
+    AddressBookError, RoleAddressBook, RoleSelector,
+};
+use rumpsteak_aura_choreography::{ChoreographyError, InMemoryHandler};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Role {
+    Master,
+    Worker(u32),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct Task(u32);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct Answer(u32);
+
+fn address_book(workers: u32) -> RoleAddressBook<Role> {
+    let mut book = RoleAddressBook::new();
+    book.insert_role("Master", Role::Master);
+    book.insert_all("Worker", (0..workers).map(Role::Worker))
+        .unwrap();
+    book
+}
+
+#[tokio::test]
+async fn test_broadcast_and_gather_over_instances() {
+    let channels = Arc::new(Mutex::new(HashMap::new()));
+    let choices = Arc::new(Mutex::new(HashMap::new()));
+    let book = address_book(3);
+
+    let mut master =
+        InMemoryHandler::with_channels(Role::Master, channels.clone(), choices.clone());
+    book.broadcast(&mut master, &mut (), &RoleSelector::all("Worker"), &Task(7))
+        .await
+        .unwrap();
+
+    for i in 0..3 {
+        let mut worker =
+            InMemoryHandler::with_channels(Role::Worker(i), channels.clone(), choices.clone());
+        let master = "Master".parse().unwrap();
+        let Task(task) = book
+            .gather_from::<_, Task>(&mut worker, &mut (), &master)
+            .await
+            .unwrap()
+            .remove(0)
+            .1;
+        book.send_to(&mut worker, &mut (), &master, &Answer(task + i))
+            .await
+            .unwrap();
+    }
+
+    let answers: Vec<(u32, Answer)> = book
+        .gather_from(&mut master, &mut (), &"Worker[1..3]".parse().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(answers, vec![(1, Answer(8)), (2, Answer(9))]);
+}
+
+#[tokio::test]
+async fn test_unresolved_selector_fails_before_sending() {
+    let book = address_book(2);
+    let mut master = InMemoryHandler::new(Role::Master);
+
+    let error = book
+        .send_to(
+            &mut master,
+            &mut (),
+            &RoleSelector::one("Worker", 5),
+            &Task(1),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        ChoreographyError::Address(AddressBookError::MissingInstance { index: 5, .. })
+    ));
+
+    let error = book
+        .send_to(&mut master, &mut (), &RoleSelector::all("Worker"), &Task(1))
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        error,
+        ChoreographyError::Address(AddressBookError::NotSingle(_))
+    ));
+}
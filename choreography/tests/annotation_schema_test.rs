@@ -0,0 +1,172 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for extension annotation schemas
+
+use rumpsteak_aura_choreography::compiler::parser::{
+    parse_choreography_str_with_extensions, ParseError,
+};
+use rumpsteak_aura_choreography::extensions::{self, ExtensionRegistry, GrammarExtension};
+use rumpsteak_aura_choreography::{
+    AnnotationSpec, AnnotationTarget, AnnotationValue, AnnotationValueType,
+};
+
+#[derive(Debug)]
+struct QosExtension;
+
+impl GrammarExtension for QosExtension {
+    fn grammar_rules(&self) -> &'static str {
+        ""
+    }
+
+    fn statement_rules(&self) -> Vec<&'static str> {
+        vec![]
+    }
+
+    fn extension_id(&self) -> &'static str {
+        "qos"
+    }
+
+    fn annotation_specs(&self) -> Vec<AnnotationSpec> {
+        vec![
+            AnnotationSpec::new(
+                "retries",
+                AnnotationValueType::Integer,
+                &[AnnotationTarget::Send, AnnotationTarget::Broadcast],
+            ),
+            AnnotationSpec::new(
+                "priority",
+                AnnotationValueType::OneOf(&["low", "high"]),
+                &[AnnotationTarget::Send, AnnotationTarget::Role],
+            ),
+            AnnotationSpec::new(
+                "reliable",
+                AnnotationValueType::Boolean,
+                &[AnnotationTarget::Choreography],
+            ),
+        ]
+    }
+}
+
+#[derive(Debug)]
+struct OtherQosExtension;
+
+impl GrammarExtension for OtherQosExtension {
+    fn grammar_rules(&self) -> &'static str {
+        ""
+    }
+
+    fn statement_rules(&self) -> Vec<&'static str> {
+        vec![]
+    }
+
+    fn extension_id(&self) -> &'static str {
+        "other_qos"
+    }
+
+    fn annotation_specs(&self) -> Vec<AnnotationSpec> {
+        vec![AnnotationSpec::new(
+            "retries",
+            AnnotationValueType::String,
+            &[AnnotationTarget::Send],
+        )]
+    }
+}
+
+fn registry() -> ExtensionRegistry {
+    let mut registry = ExtensionRegistry::new();
+    registry.register_grammar(QosExtension).unwrap();
+    registry
+}
+
+#[test]
+fn test_declared_annotations_are_typed() {
+    let registry = registry();
+    let (choreography, _) = parse_choreography_str_with_extensions(
+        r#"
+@reliable(true)
+choreography Delivery {
+    roles: Sender, Receiver
+    [@retries = 3, @note = "untyped"]
+    Sender[@priority = high] -> Receiver: Packet
+}
+"#,
+        &registry,
+    )
+    .unwrap();
+
+    let schema = registry.annotation_schema();
+    let typed = schema.typed_annotations(&choreography.protocol);
+    assert_eq!(typed.get("retries"), Some(&AnnotationValue::Integer(3)));
+    // Undeclared annotations stay available as raw strings only
+    assert!(!typed.contains_key("note"));
+    assert_eq!(
+        choreography.protocol.get_annotation("note"),
+        Some(&"untyped".to_string())
+    );
+    assert_eq!(
+        schema
+            .typed_from_annotations(&choreography.protocol)
+            .get("priority")
+            .and_then(AnnotationValue::as_str),
+        Some("high")
+    );
+}
+
+#[test]
+fn test_wrong_value_type_is_rejected() {
+    let input = r#"
+choreography Delivery {
+    roles: Sender, Receiver
+    [@retries = "many"]
+    Sender -> Receiver: Packet
+}
+"#;
+    let err = parse_choreography_str_with_extensions(input, &registry()).unwrap_err();
+    match err {
+        ParseError::InvalidAnnotation {
+            key, reason, span, ..
+        } => {
+            assert_eq!(&*key, "retries");
+            assert_eq!(&*reason, "expected an integer");
+            assert_eq!(span.line, 4);
+        }
+        other => panic!("expected InvalidAnnotation, got {other:?}"),
+    }
+}
+
+#[test]
+fn test_wrong_placement_is_rejected() {
+    let input = r#"
+choreography Delivery {
+    roles: Sender, Receiver
+    [@retries = 2]
+    choice Sender {
+        fast: {
+            Sender -> Receiver: Packet
+        }
+    }
+}
+"#;
+    let err = parse_choreography_str_with_extensions(input, &registry()).unwrap_err();
+    assert!(err.to_string().contains(
+        "not allowed on choice statements, only on send statements, broadcast statements"
+    ));
+
+    let input = r#"
+choreography Delivery {
+    roles: Sender, Receiver
+    Sender -> Receiver[@priority = urgent]: Packet
+}
+"#;
+    let err = parse_choreography_str_with_extensions(input, &registry()).unwrap_err();
+    assert!(err.to_string().contains("expected one of: low, high"));
+}
+
+#[test]
+fn test_conflicting_specs_are_rejected() {
+    let mut registry = registry();
+    let err = registry.register_grammar(OtherQosExtension).unwrap_err();
+    assert!(matches!(err, extensions::ParseError::Conflict { .. }));
+    assert!(!registry.has_extension("other_qos"));
+}
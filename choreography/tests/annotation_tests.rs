@@ -6,8 +6,8 @@
 // This test suite verifies that annotations work correctly throughout
 // the choreography compilation pipeline.
 
-use proc_macro2::{Ident, Span};
-use rumpsteak_aura_choreography::ast::{Branch, Choreography, MessageType, Protocol, Role};
+use proc_macro2::Ident;
+use rumpsteak_aura_choreography::ast::{Branch, Choreography, MessageType, Protocol, Role, Span};
 use rumpsteak_aura_choreography::compiler::{
     generate_choreography_code_with_namespacing, parse_choreography_str,
 };
@@ -15,7 +15,7 @@ use std::collections::HashMap;
 
 // Helper to create identifiers
 fn ident(s: &str) -> Ident {
-    Ident::new(s, Span::call_site())
+    Ident::new(s, proc_macro2::Span::call_site())
 }
 
 // Helper to create a message type
@@ -40,6 +40,7 @@ fn test_protocol_annotation_storage() {
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     // Test setting annotations
@@ -83,6 +84,7 @@ fn test_protocol_annotation_api() {
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     // Test has_annotation
@@ -126,6 +128,7 @@ fn test_protocol_annotation_validation() {
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     protocol.set_annotation("priority".to_string(), "high".to_string());
@@ -156,6 +159,7 @@ fn test_protocol_annotation_merging() {
         annotations: HashMap::from([("priority".to_string(), "high".to_string())]),
         from_annotations: HashMap::from([("timeout".to_string(), "30s".to_string())]),
         to_annotations: HashMap::from([("retry".to_string(), "3".to_string())]),
+        span: Span::default(),
     };
 
     let protocol2 = Protocol::Send {
@@ -175,6 +179,7 @@ fn test_protocol_annotation_merging() {
             ("retry".to_string(), "5".to_string()),
             ("backoff".to_string(), "exponential".to_string()),
         ]),
+        span: Span::default(),
     };
 
     protocol1.merge_annotations_from(&protocol2);
@@ -216,6 +221,7 @@ fn test_protocol_annotation_filtering() {
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     protocol.set_annotation("timeout_seconds".to_string(), "30".to_string());
@@ -237,6 +243,11 @@ fn test_choreography_annotation_api() {
         roles: vec![Role::new(ident("Alice")), Role::new(ident("Bob"))],
         protocol: Protocol::End,
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     // Test setting and getting choreography attributes
@@ -274,6 +285,7 @@ fn test_protocol_traversal_with_annotations() {
         annotations: HashMap::from([("inner".to_string(), "true".to_string())]),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     let outer_protocol = Protocol::Send {
@@ -288,10 +300,12 @@ fn test_protocol_traversal_with_annotations() {
             annotations: HashMap::from([("inner".to_string(), "true".to_string())]),
             from_annotations: HashMap::new(),
             to_annotations: HashMap::new(),
+            span: Span::default(),
         }),
         annotations: HashMap::from([("outer".to_string(), "true".to_string())]),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     // Test deep annotation count
@@ -327,6 +341,7 @@ fn test_choice_annotation_support() {
     let branch1 = Branch {
         label: ident("accept"),
         guard: None,
+        probability: None,
         protocol: Protocol::Send {
             from: alice.clone(),
             to: bob.clone(),
@@ -335,12 +350,15 @@ fn test_choice_annotation_support() {
             annotations: HashMap::new(),
             from_annotations: HashMap::new(),
             to_annotations: HashMap::new(),
+            span: Span::default(),
         },
+        span: Span::default(),
     };
 
     let branch2 = Branch {
         label: ident("reject"),
         guard: None,
+        probability: None,
         protocol: Protocol::Send {
             from: alice.clone(),
             to: bob.clone(),
@@ -349,13 +367,16 @@ fn test_choice_annotation_support() {
             annotations: HashMap::new(),
             from_annotations: HashMap::new(),
             to_annotations: HashMap::new(),
+            span: Span::default(),
         },
+        span: Span::default(),
     };
 
     let mut choice = Protocol::Choice {
         role: alice.clone(),
         branches: vec![branch1, branch2],
         annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     // Test Choice annotation support
@@ -380,6 +401,7 @@ fn test_broadcast_annotation_support() {
         continuation: Box::new(Protocol::End),
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     // Test Broadcast annotation support
@@ -451,8 +473,14 @@ fn test_code_generation_with_annotations() {
             annotations: HashMap::from([("priority".to_string(), "high".to_string())]),
             from_annotations: HashMap::new(),
             to_annotations: HashMap::new(),
+            span: Span::default(),
         },
         attrs: HashMap::from([("version".to_string(), "1.0".to_string())]),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     // Test that code generation includes annotation metadata
@@ -480,6 +508,7 @@ fn test_annotation_different_types() {
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     // Test different annotation value types
@@ -0,0 +1,103 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for the session bootstrap handshake
+
+mod common;
+
+use common::{connect, roles};
+use rumpsteak_aura_choreography::effects::handlers::rumpsteak::{
+    RumpsteakEndpoint, RumpsteakHandler,
+};
+use rumpsteak_aura_choreography::runtime::bootstrap::{
+    BootstrapError, ProtocolDescriptor, SessionInitiator, SessionParticipant,
+};
+use serde::{Deserialize, Serialize};
+
+roles!(Node { Coordinator, Worker1, Worker2 }: Msg);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Msg;
+
+const SOURCE: &str = "choreography Fanout { roles: Coordinator, Worker1, Worker2 }";
+
+fn star_endpoints() -> (
+    RumpsteakEndpoint<Node>,
+    RumpsteakEndpoint<Node>,
+    RumpsteakEndpoint<Node>,
+) {
+    let mut coordinator = RumpsteakEndpoint::new(Node::Coordinator);
+    let mut worker1 = RumpsteakEndpoint::new(Node::Worker1);
+    let mut worker2 = RumpsteakEndpoint::new(Node::Worker2);
+
+    connect(&mut coordinator, &mut worker1);
+    connect(&mut coordinator, &mut worker2);
+
+    (coordinator, worker1, worker2)
+}
+
+fn initiator(protocol: ProtocolDescriptor) -> SessionInitiator<Node> {
+    SessionInitiator::new(protocol, Node::Coordinator)
+        .assign(Node::Coordinator, "10.0.0.1:7000")
+        .assign(Node::Worker1, "10.0.0.2:7000")
+        .assign(Node::Worker2, "10.0.0.3:7000")
+}
+
+#[tokio::test]
+async fn test_bootstrap_distributes_assignments() {
+    let protocol = ProtocolDescriptor::from_source("Fanout", SOURCE);
+    let (mut coord_ep, mut w1_ep, mut w2_ep) = star_endpoints();
+
+    let init = initiator(protocol.clone());
+    let participant = SessionParticipant::new(protocol);
+
+    let mut coord_handler = RumpsteakHandler::<Node, Msg>::new();
+    let mut w1_handler = RumpsteakHandler::<Node, Msg>::new();
+    let mut w2_handler = RumpsteakHandler::<Node, Msg>::new();
+
+    let (coord, w1, w2) = tokio::join!(
+        init.establish(&mut coord_handler, &mut coord_ep),
+        participant.join(&mut w1_handler, &mut w1_ep, Node::Coordinator),
+        participant.join(&mut w2_handler, &mut w2_ep, Node::Coordinator),
+    );
+    let (coord, w1, w2) = (coord.unwrap(), w1.unwrap(), w2.unwrap());
+
+    assert_eq!(coord.session_id, init.session_id());
+    assert_eq!(w1.session_id, init.session_id());
+    assert_eq!(w2.session_id, init.session_id());
+
+    assert_eq!(coord.local_role, Node::Coordinator);
+    assert_eq!(w1.local_role, Node::Worker1);
+    assert_eq!(w2.local_role, Node::Worker2);
+
+    assert_eq!(w1.participant_of(&Node::Worker2), Some("10.0.0.3:7000"));
+    assert_eq!(w1.peers().count(), 2);
+}
+
+#[tokio::test]
+async fn test_bootstrap_aborts_on_protocol_mismatch() {
+    let protocol = ProtocolDescriptor::from_source("Fanout", SOURCE);
+    let stale = ProtocolDescriptor::from_source(
+        "Fanout",
+        "choreography Fanout { roles: Coordinator, Worker1 }",
+    );
+    let (mut coord_ep, mut w1_ep, mut w2_ep) = star_endpoints();
+
+    let init = initiator(protocol.clone());
+
+    let mut coord_handler = RumpsteakHandler::<Node, Msg>::new();
+    let mut w1_handler = RumpsteakHandler::<Node, Msg>::new();
+    let mut w2_handler = RumpsteakHandler::<Node, Msg>::new();
+
+    let current = SessionParticipant::new(protocol);
+    let outdated = SessionParticipant::new(stale);
+    let (coord, w1, w2) = tokio::join!(
+        init.establish(&mut coord_handler, &mut coord_ep),
+        current.join(&mut w1_handler, &mut w1_ep, Node::Coordinator),
+        outdated.join(&mut w2_handler, &mut w2_ep, Node::Coordinator),
+    );
+
+    assert!(matches!(coord, Err(BootstrapError::Rejected { .. })));
+    assert!(matches!(w1, Err(BootstrapError::Aborted { .. })));
+    assert!(matches!(w2, Err(BootstrapError::ProtocolMismatch { .. })));
+}
@@ -0,0 +1,162 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for cooperative session cancellation
+
+mod common;
+
+use common::{endpoints, roles};
+use rumpsteak_aura_choreography::effects::handlers::rumpsteak::{
+    RumpsteakEndpoint, RumpsteakHandler,
+};
+use rumpsteak_aura_choreography::{
+    Cancellable, ChoreoHandler, ChoreographyError, Label, SessionHandle,
+};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+roles!(TestRole { Alice, Bob }: TestMessage);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct TestMessage(u32);
+
+type Handler = Cancellable<RumpsteakHandler<TestRole, TestMessage>>;
+
+fn setup() -> (
+    (Handler, RumpsteakEndpoint<TestRole>),
+    (Handler, RumpsteakEndpoint<TestRole>),
+) {
+    let (alice_ep, bob_ep) = endpoints(TestRole::Alice, TestRole::Bob);
+
+    let alice = Cancellable::new(
+        RumpsteakHandler::new(),
+        SessionHandle::new(),
+        vec![TestRole::Bob],
+    );
+    let bob = Cancellable::new(
+        RumpsteakHandler::new(),
+        SessionHandle::new(),
+        vec![TestRole::Alice],
+    );
+    ((alice, alice_ep), (bob, bob_ep))
+}
+
+#[tokio::test]
+async fn test_messages_pass_through_until_cancelled() {
+    let ((mut alice, mut alice_ep), (mut bob, mut bob_ep)) = setup();
+
+    alice
+        .send(&mut alice_ep, TestRole::Bob, &TestMessage(7))
+        .await
+        .unwrap();
+    let msg: TestMessage = bob.recv(&mut bob_ep, TestRole::Alice).await.unwrap();
+    assert_eq!(msg, TestMessage(7));
+
+    alice.handle().cancel();
+    let result = alice
+        .send(&mut alice_ep, TestRole::Bob, &TestMessage(8))
+        .await;
+    assert!(matches!(result, Err(ChoreographyError::Cancelled)));
+}
+
+#[tokio::test]
+async fn test_cancel_resolves_pending_recv() {
+    let ((_alice, _alice_ep), (mut bob, mut bob_ep)) = setup();
+    let handle = bob.handle().clone();
+
+    let canceller = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        handle.cancel();
+    });
+
+    let result = bob.recv::<TestMessage>(&mut bob_ep, TestRole::Alice).await;
+    assert!(matches!(result, Err(ChoreographyError::Cancelled)));
+    canceller.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_cancellation_propagates_to_peer() {
+    let ((mut alice, mut alice_ep), (mut bob, mut bob_ep)) = setup();
+
+    let bob_task = tokio::spawn(async move {
+        let result = bob.recv::<TestMessage>(&mut bob_ep, TestRole::Alice).await;
+        (result, bob.handle().is_cancelled())
+    });
+
+    alice.handle().cancel();
+    let result = alice
+        .recv::<TestMessage>(&mut alice_ep, TestRole::Bob)
+        .await;
+    assert!(matches!(result, Err(ChoreographyError::Cancelled)));
+
+    let (bob_result, bob_cancelled) = bob_task.await.unwrap();
+    assert!(matches!(bob_result, Err(ChoreographyError::Cancelled)));
+    assert!(bob_cancelled);
+}
+
+#[tokio::test]
+async fn test_cancellation_reaches_peer_waiting_in_offer() {
+    let ((mut alice, mut alice_ep), (mut bob, mut bob_ep)) = setup();
+
+    alice
+        .choose(&mut alice_ep, TestRole::Bob, Label("accept"))
+        .await
+        .unwrap();
+    assert_eq!(
+        bob.offer(
+            &mut bob_ep,
+            TestRole::Alice,
+            &[Label("accept"), Label("reject")]
+        )
+        .await
+        .unwrap(),
+        Label("accept")
+    );
+
+    let bob_task = tokio::spawn(async move {
+        let result = bob
+            .offer(
+                &mut bob_ep,
+                TestRole::Alice,
+                &[Label("accept"), Label("reject")],
+            )
+            .await;
+        (result, bob.handle().is_cancelled())
+    });
+
+    alice.handle().cancel();
+    let result = alice
+        .choose(&mut alice_ep, TestRole::Bob, Label("reject"))
+        .await;
+    assert!(matches!(result, Err(ChoreographyError::Cancelled)));
+
+    let (bob_result, bob_cancelled) = bob_task.await.unwrap();
+    assert!(matches!(bob_result, Err(ChoreographyError::Cancelled)));
+    assert!(bob_cancelled);
+}
+
+#[tokio::test]
+async fn test_cancel_interrupts_timed_body() {
+    let ((mut alice, mut alice_ep), _bob) = setup();
+    let handle = alice.handle().clone();
+
+    let canceller = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        handle.cancel();
+    });
+
+    let body = async {
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        Ok(())
+    };
+    let result = alice
+        .with_timeout(
+            &mut alice_ep,
+            TestRole::Alice,
+            Duration::from_secs(120),
+            body,
+        )
+        .await;
+    assert!(matches!(result, Err(ChoreographyError::Cancelled)));
+    canceller.await.unwrap();
+}
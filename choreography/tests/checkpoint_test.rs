@@ -0,0 +1,234 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for checkpointing and crash recovery
+
+mod common;
+
+use common::{connect, roles};
+use rumpsteak_aura_choreography::effects::handlers::rumpsteak::{
+    RumpsteakEndpoint, RumpsteakHandler,
+};
+use rumpsteak_aura_choreography::effects::middleware::checkpoint::{
+    Checkpoint, FileCheckpointer, JournalEntry,
+};
+use rumpsteak_aura_choreography::effects::{
+    Checkpointer, Checkpointing, ChoreoHandler, Label, MemoryCheckpointer,
+};
+use serde::{Deserialize, Serialize};
+
+roles!(TestRole { Alice, Bob }: TestMessage);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct TestMessage(u32);
+
+type Inner = RumpsteakHandler<TestRole, TestMessage>;
+
+#[tokio::test]
+async fn test_checkpoint_written_after_each_step() {
+    let mut alice_ep = RumpsteakEndpoint::new(TestRole::Alice);
+    let mut bob_ep = RumpsteakEndpoint::new(TestRole::Bob);
+    connect(&mut alice_ep, &mut bob_ep);
+
+    let store = MemoryCheckpointer::new();
+    let mut alice = Checkpointing::new(Inner::new(), store.clone()).unwrap();
+    let mut bob = Checkpointing::new(Inner::new(), MemoryCheckpointer::new()).unwrap();
+
+    alice
+        .send(&mut alice_ep, TestRole::Bob, &TestMessage(1))
+        .await
+        .unwrap();
+    let _: TestMessage = bob.recv(&mut bob_ep, TestRole::Alice).await.unwrap();
+
+    let saved = Checkpointer::<TestRole>::load(&store).unwrap().unwrap();
+    assert_eq!(saved.step(), 1);
+    assert_eq!(
+        saved.journal[0],
+        JournalEntry::Sent {
+            to: TestRole::Bob,
+            seq: 1
+        }
+    );
+    assert_eq!(saved.sent.get(&TestRole::Bob), Some(&1));
+}
+
+#[tokio::test]
+async fn test_resume_replays_journal_and_resyncs() {
+    let mut alice_ep = RumpsteakEndpoint::new(TestRole::Alice);
+    let mut bob_ep = RumpsteakEndpoint::new(TestRole::Bob);
+    connect(&mut alice_ep, &mut bob_ep);
+
+    let bob_store = MemoryCheckpointer::new();
+    let mut alice = Checkpointing::new(Inner::new(), MemoryCheckpointer::new()).unwrap();
+    let mut bob = Checkpointing::new(Inner::new(), bob_store.clone()).unwrap();
+
+    // Alice sends two messages; Bob processes only the first before crashing.
+    alice
+        .send(&mut alice_ep, TestRole::Bob, &TestMessage(1))
+        .await
+        .unwrap();
+    alice
+        .send(&mut alice_ep, TestRole::Bob, &TestMessage(2))
+        .await
+        .unwrap();
+    let first: TestMessage = bob.recv(&mut bob_ep, TestRole::Alice).await.unwrap();
+    assert_eq!(first, TestMessage(1));
+
+    // Crash: Bob's handler, endpoint, and in-flight message 2 are lost.
+    drop(bob);
+    drop(bob_ep);
+
+    // Reconnect and resume Bob from its checkpoint.
+    let mut bob_ep = RumpsteakEndpoint::new(TestRole::Bob);
+    connect(&mut alice_ep, &mut bob_ep);
+    let mut bob = Checkpointing::resume_from_checkpoint(Inner::new(), bob_store).unwrap();
+    assert!(bob.is_replaying());
+
+    let alice_task = tokio::spawn(async move {
+        // Alice is waiting for Bob's reply and serves the resync request meanwhile.
+        let reply: TestMessage = alice.recv(&mut alice_ep, TestRole::Bob).await.unwrap();
+        reply
+    });
+
+    bob.resync(&mut bob_ep, &[TestRole::Alice]).await.unwrap();
+
+    // Bob re-runs its program from the start.
+    let replayed: TestMessage = bob.recv(&mut bob_ep, TestRole::Alice).await.unwrap();
+    assert_eq!(replayed, TestMessage(1));
+    assert!(!bob.is_replaying());
+
+    let second: TestMessage = bob.recv(&mut bob_ep, TestRole::Alice).await.unwrap();
+    assert_eq!(second, TestMessage(2));
+
+    bob.send(&mut bob_ep, TestRole::Alice, &TestMessage(3))
+        .await
+        .unwrap();
+    assert_eq!(alice_task.await.unwrap(), TestMessage(3));
+}
+
+#[tokio::test]
+async fn test_replay_divergence_is_reported() {
+    let store = MemoryCheckpointer::new();
+    let mut alice_ep = RumpsteakEndpoint::new(TestRole::Alice);
+    let mut bob_ep = RumpsteakEndpoint::new(TestRole::Bob);
+    connect(&mut alice_ep, &mut bob_ep);
+
+    let mut alice = Checkpointing::new(Inner::new(), store.clone()).unwrap();
+    alice
+        .send(&mut alice_ep, TestRole::Bob, &TestMessage(1))
+        .await
+        .unwrap();
+    drop(alice);
+
+    // A resumed run that receives where the journal recorded a send has diverged.
+    let mut alice = Checkpointing::resume_from_checkpoint(Inner::new(), store).unwrap();
+    let result = alice
+        .recv::<TestMessage>(&mut alice_ep, TestRole::Bob)
+        .await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_lost_choice_is_retransmitted_on_resync() {
+    let mut alice_ep = RumpsteakEndpoint::new(TestRole::Alice);
+    let mut bob_ep = RumpsteakEndpoint::new(TestRole::Bob);
+    connect(&mut alice_ep, &mut bob_ep);
+
+    let bob_store = MemoryCheckpointer::new();
+    let mut alice = Checkpointing::new(Inner::new(), MemoryCheckpointer::new()).unwrap();
+    let mut bob = Checkpointing::new(Inner::new(), bob_store.clone()).unwrap();
+
+    alice
+        .send(&mut alice_ep, TestRole::Bob, &TestMessage(1))
+        .await
+        .unwrap();
+    let _: TestMessage = bob.recv(&mut bob_ep, TestRole::Alice).await.unwrap();
+    alice
+        .choose(&mut alice_ep, TestRole::Bob, Label("commit"))
+        .await
+        .unwrap();
+
+    // Bob crashes before taking the choice
+    drop(bob);
+    drop(bob_ep);
+    let mut bob_ep = RumpsteakEndpoint::new(TestRole::Bob);
+    connect(&mut alice_ep, &mut bob_ep);
+    let mut bob = Checkpointing::resume_from_checkpoint(Inner::new(), bob_store).unwrap();
+
+    // Alice waits for Bob's choice and serves the resync request meanwhile
+    let alice_task = tokio::spawn(async move {
+        alice
+            .offer(&mut alice_ep, TestRole::Bob, &[Label("ack")])
+            .await
+            .unwrap()
+    });
+
+    bob.resync(&mut bob_ep, &[TestRole::Alice]).await.unwrap();
+    let _: TestMessage = bob.recv(&mut bob_ep, TestRole::Alice).await.unwrap();
+    let label = bob
+        .offer(
+            &mut bob_ep,
+            TestRole::Alice,
+            &[Label("commit"), Label("abort")],
+        )
+        .await
+        .unwrap();
+    assert_eq!(label, Label("commit"));
+    assert_eq!(
+        bob.checkpoint().journal[1],
+        JournalEntry::Offered {
+            from: TestRole::Alice,
+            seq: 2,
+            label: "commit".into(),
+        }
+    );
+
+    bob.choose(&mut bob_ep, TestRole::Alice, Label("ack"))
+        .await
+        .unwrap();
+    assert_eq!(alice_task.await.unwrap(), Label("ack"));
+}
+
+#[tokio::test]
+async fn test_replayed_choice_to_another_peer_diverges() {
+    let store = MemoryCheckpointer::new();
+    let mut alice_ep = RumpsteakEndpoint::new(TestRole::Alice);
+    let mut bob_ep = RumpsteakEndpoint::new(TestRole::Bob);
+    connect(&mut alice_ep, &mut bob_ep);
+
+    let mut alice = Checkpointing::new(Inner::new(), store.clone()).unwrap();
+    alice
+        .choose(&mut alice_ep, TestRole::Bob, Label("commit"))
+        .await
+        .unwrap();
+    drop(alice);
+
+    let mut alice = Checkpointing::resume_from_checkpoint(Inner::new(), store).unwrap();
+    let result = alice
+        .choose(&mut alice_ep, TestRole::Alice, Label("commit"))
+        .await;
+    assert!(result.is_err());
+}
+
+#[test]
+fn test_file_checkpointer_round_trip() {
+    let dir = tempfile::tempdir().unwrap();
+    let store = FileCheckpointer::new(dir.path().join("alice.ckpt"));
+    assert!(Checkpointer::<TestRole>::load(&store).unwrap().is_none());
+
+    let mut checkpoint = Checkpoint::default();
+    checkpoint.journal.push(JournalEntry::Chose {
+        to: TestRole::Bob,
+        seq: 4,
+        label: "Commit".into(),
+    });
+    checkpoint.sent.insert(TestRole::Bob, 4);
+    store.save(&checkpoint).unwrap();
+
+    let loaded: Checkpoint<TestRole> = store.load().unwrap().unwrap();
+    assert_eq!(loaded.step(), 1);
+    assert_eq!(loaded.sent.get(&TestRole::Bob), Some(&4));
+
+    Checkpointer::<TestRole>::clear(&store).unwrap();
+    assert!(Checkpointer::<TestRole>::load(&store).unwrap().is_none());
+}
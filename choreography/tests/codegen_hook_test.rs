@@ -0,0 +1,116 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for extension code generation hooks
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote};
+use rumpsteak_aura_choreography::compiler::{
+    generate_effects_protocol, generate_effects_protocol_with_extensions, parse_choreography_str,
+};
+use rumpsteak_aura_choreography::{
+    parse_and_generate_with_extensions, CodegenContext, CodegenHook, ExtensionRegistry, MessageSite,
+};
+
+/// Checks a capability before every annotated send and logs every receive
+#[derive(Debug)]
+struct AuditHook;
+
+impl CodegenHook for AuditHook {
+    fn hook_id(&self) -> &'static str {
+        "audit"
+    }
+
+    fn module_items(&self, context: &CodegenContext) -> TokenStream {
+        let name = format_ident!("{}AuditLog", context.choreography_name);
+        quote! {
+            #[derive(Debug, Default)]
+            pub struct #name;
+        }
+    }
+
+    fn handler_bounds(&self, _context: &CodegenContext) -> Vec<TokenStream> {
+        vec![quote! { AuditSink }]
+    }
+
+    fn wrap_send(&self, site: &MessageSite, effect: TokenStream) -> TokenStream {
+        match site.protocol.get_annotation("audit") {
+            Some(tag) => quote! { .with_annotation("audit_before", #tag) #effect },
+            None => effect,
+        }
+    }
+
+    fn wrap_recv(&self, site: &MessageSite, effect: TokenStream) -> TokenStream {
+        let peer = site.peer.name.to_string();
+        quote! { #effect .with_annotation("received_from", #peer) }
+    }
+}
+
+/// Runs after `AuditHook` and sees its output
+#[derive(Debug)]
+struct OuterHook;
+
+impl CodegenHook for OuterHook {
+    fn hook_id(&self) -> &'static str {
+        "outer"
+    }
+
+    fn wrap_send(&self, _site: &MessageSite, effect: TokenStream) -> TokenStream {
+        quote! { .with_annotation("outer", "start") #effect }
+    }
+}
+
+const CHOREOGRAPHY: &str = r#"
+choreography Transfer {
+    roles: Bank, Customer
+    [@audit = "withdrawal"]
+    Bank -> Customer: Cash
+    Customer -> Bank: Receipt
+}
+"#;
+
+#[test]
+fn test_hooks_rewrite_effects() {
+    let choreography = parse_choreography_str(CHOREOGRAPHY).unwrap();
+    let mut registry = ExtensionRegistry::new();
+    registry.register_codegen_hook(AuditHook);
+    registry.register_codegen_hook(OuterHook);
+
+    let code = generate_effects_protocol_with_extensions(&choreography, &registry).to_string();
+    assert!(code.contains("pub struct TransferAuditLog"));
+    assert!(code.contains("Endpoint = TransferEndpoint > + AuditSink"));
+    assert!(code.contains(
+        ". with_annotation (\"outer\" , \"start\") . with_annotation (\"audit_before\" , \"withdrawal\") . send (Role :: Customer , Cash :: default ())"
+    ));
+    assert!(code.contains(
+        ". recv :: < Receipt > (Role :: Customer) . with_annotation (\"received_from\" , \"Customer\")"
+    ));
+    // The unannotated send is only wrapped by the outer hook
+    assert!(code.contains(
+        ". with_annotation (\"outer\" , \"start\") . send (Role :: Bank , Receipt :: default ())"
+    ));
+}
+
+#[test]
+fn test_no_hooks_matches_plain_generation() {
+    let choreography = parse_choreography_str(CHOREOGRAPHY).unwrap();
+    let with_registry =
+        generate_effects_protocol_with_extensions(&choreography, &ExtensionRegistry::new())
+            .to_string();
+    let plain = generate_effects_protocol(&choreography).to_string();
+    // Message types are emitted in hash order, so compare content rather than layout
+    assert_eq!(with_registry.len(), plain.len());
+    assert!(with_registry.contains("Endpoint = TransferEndpoint > > ("));
+    assert!(with_registry.contains(". send (Role :: Customer , Cash :: default ())"));
+}
+
+#[test]
+fn test_module_items_reach_extension_pipeline() {
+    let mut registry = ExtensionRegistry::new();
+    registry.register_codegen_hook(AuditHook);
+
+    let code = parse_and_generate_with_extensions(CHOREOGRAPHY, &registry)
+        .unwrap()
+        .to_string();
+    assert!(code.contains("pub struct TransferAuditLog"));
+}
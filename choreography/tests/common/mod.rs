@@ -0,0 +1,152 @@
+// Fixtures shared by the integration tests that run roles over in-process
+// `SimpleChannel`s
+//
+// A test declares its role enum and the message type the roles exchange with
+// `roles!`, and links endpoints of two of its roles with `endpoints` or
+// `connect`. Tests of middleware configured by generated effect code take its
+// configuration from the code generated for their protocol with
+// `generated_guard_points`. Not every test uses every fixture.
+#![allow(dead_code)]
+
+use rumpsteak_aura_choreography::ast::Choreography;
+use rumpsteak_aura_choreography::effects::handlers::rumpsteak::{RumpsteakEndpoint, SimpleChannel};
+use rumpsteak_aura_choreography::generate_effects_protocol;
+use rumpsteak_aura_choreography::runtime::guard::GuardPoint;
+use rumpsteak_aura_choreography::runtime::monitor::ActionKind;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+/// Declare the role enum `$name` with the variants `$role`, whose roles
+/// exchange `$message`s through a `RumpsteakHandler`
+///
+/// The roles have no session state to seal, and `$message` is passed through
+/// the handler as a `Box<dyn Any + Send>`.
+macro_rules! roles {
+    ($name:ident { $($role:ident),+ $(,)? }: $message:ident) => {
+        #[derive(
+            Clone, Copy, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize,
+        )]
+        enum $name {
+            $($role),+
+        }
+
+        impl rumpsteak_aura::Role for $name {
+            type Message = $message;
+
+            fn seal(&mut self) {}
+
+            fn is_sealed(&self) -> bool {
+                false
+            }
+        }
+
+        impl rumpsteak_aura::Message<Box<dyn std::any::Any + Send>> for $message {
+            fn upcast(msg: Box<dyn std::any::Any + Send>) -> Self {
+                *msg.downcast::<$message>().unwrap()
+            }
+
+            fn downcast(self) -> Result<Box<dyn std::any::Any + Send>, Self> {
+                Ok(Box::new(self))
+            }
+        }
+    };
+}
+
+pub(crate) use roles;
+
+/// Link the endpoints `a` and `b` with a `SimpleChannel`
+pub fn connect<R>(a: &mut RumpsteakEndpoint<R>, b: &mut RumpsteakEndpoint<R>)
+where
+    R: rumpsteak_aura::Role + Eq + Hash + Clone + Debug,
+{
+    let (a_side, b_side) = SimpleChannel::pair();
+    a.register_channel(b.local_role().clone(), a_side);
+    b.register_channel(a.local_role().clone(), b_side);
+}
+
+/// Endpoints of the roles `a` and `b`, linked with each other
+pub fn endpoints<R>(a: R, b: R) -> (RumpsteakEndpoint<R>, RumpsteakEndpoint<R>)
+where
+    R: rumpsteak_aura::Role + Eq + Hash + Clone + Debug,
+{
+    let mut a = RumpsteakEndpoint::new(a);
+    let mut b = RumpsteakEndpoint::new(b);
+    connect(&mut a, &mut b);
+    (a, b)
+}
+
+/// The `<ROLE>_GUARD_POINTS` that generated effect code emits for `role` of
+/// `choreography`
+///
+/// The points are read back from the generated code and live for the rest of
+/// the test, as `Guarded` takes them with a `'static` lifetime.
+pub fn generated_guard_points(choreography: &Choreography, role: &str) -> &'static [GuardPoint] {
+    let code: syn::File = syn::parse2(generate_effects_protocol(choreography)).unwrap();
+    let name = format!("{}_GUARD_POINTS", role.to_uppercase());
+    let points = code
+        .items
+        .iter()
+        .find_map(|item| match item {
+            syn::Item::Const(item) if item.ident == name => Some(&*item.expr),
+            _ => None,
+        })
+        .unwrap_or_else(|| panic!("no {name} in the generated code"));
+    let syn::Expr::Reference(syn::ExprReference { expr: points, .. }) = points else {
+        panic!("{name} is not a slice reference");
+    };
+    let syn::Expr::Array(points) = &**points else {
+        panic!("{name} is not an array");
+    };
+    let points: Vec<_> = points.elems.iter().map(guard_point).collect();
+    Box::leak(points.into_boxed_slice())
+}
+
+/// `GuardPoint` of the struct expression `point`
+fn guard_point(point: &syn::Expr) -> GuardPoint {
+    let syn::Expr::Struct(point) = point else {
+        panic!("guard point is not a struct expression");
+    };
+    let field = |name: &str| {
+        point
+            .fields
+            .iter()
+            .find(|field| matches!(&field.member, syn::Member::Named(ident) if ident == name))
+            .map(|field| &field.expr)
+            .unwrap_or_else(|| panic!("guard point has no {name}"))
+    };
+    let action = match field("action") {
+        syn::Expr::Path(path) => path.path.segments.last().unwrap().ident.to_string(),
+        other => panic!("unexpected guard action {other:?}"),
+    };
+    GuardPoint {
+        action: match action.as_str() {
+            "Send" => ActionKind::Send,
+            "Receive" => ActionKind::Receive,
+            "Select" => ActionKind::Select,
+            "Branch" => ActionKind::Branch,
+            other => panic!("unexpected guard action {other}"),
+        },
+        peer: optional_str(field("peer")),
+        label: optional_str(field("label")),
+        capability: str_literal(field("capability")),
+    }
+}
+
+/// `None`, or `Some` of the string literal in `expr`
+fn optional_str(expr: &syn::Expr) -> Option<&'static str> {
+    match expr {
+        syn::Expr::Path(_) => None,
+        syn::Expr::Call(call) => Some(str_literal(&call.args[0])),
+        other => panic!("unexpected optional string {other:?}"),
+    }
+}
+
+fn str_literal(expr: &syn::Expr) -> &'static str {
+    match expr {
+        syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Str(literal),
+            ..
+        }) => Box::leak(literal.value().into_boxed_str()),
+        other => panic!("expected a string literal, got {other:?}"),
+    }
+}
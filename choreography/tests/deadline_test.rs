@@ -0,0 +1,145 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for the deadline propagation middleware
+
+mod common;
+
+use common::{endpoints, roles};
+use rumpsteak_aura_choreography::effects::handlers::rumpsteak::{
+    RumpsteakEndpoint, RumpsteakHandler,
+};
+use rumpsteak_aura_choreography::effects::middleware::deadlined::parse_time_limit;
+use rumpsteak_aura_choreography::{ChoreoHandler, ChoreographyError, Deadlined};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+roles!(TestRole { Alice, Bob }: Query);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct Query(u32);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct InTime;
+
+type Handler = Deadlined<RumpsteakHandler<TestRole, Query>>;
+
+fn setup(
+    alice: Handler,
+    bob: Handler,
+) -> (
+    (Handler, RumpsteakEndpoint<TestRole>),
+    (Handler, RumpsteakEndpoint<TestRole>),
+) {
+    let (alice_ep, bob_ep) = endpoints(TestRole::Alice, TestRole::Bob);
+    ((alice, alice_ep), (bob, bob_ep))
+}
+
+#[tokio::test]
+async fn test_deadline_travels_with_the_block() {
+    let ((mut alice, mut alice_ep), (mut bob, mut bob_ep)) = setup(
+        Deadlined::new(RumpsteakHandler::new()).within("Query", Duration::from_secs(30)),
+        Deadlined::new(RumpsteakHandler::new()),
+    );
+
+    // Alice opens the block with its first message, and Bob adopts its deadline
+    alice
+        .send_labelled(&mut alice_ep, TestRole::Bob, "Query", &Query(1))
+        .await
+        .unwrap();
+    let deadline = alice.deadline().unwrap();
+    let query: Query = bob
+        .recv_labelled(&mut bob_ep, TestRole::Alice, "Query")
+        .await
+        .unwrap();
+    assert_eq!(query, Query(1));
+    assert_eq!(bob.deadline(), Some(deadline));
+    assert!(bob.remaining().unwrap() > Duration::from_secs(29));
+    assert!(!bob.is_exceeded());
+
+    // A later message of the block keeps the clock running
+    bob.send_labelled(&mut bob_ep, TestRole::Alice, "Query", &Query(2))
+        .await
+        .unwrap();
+    let _: Query = alice
+        .recv_labelled(&mut alice_ep, TestRole::Bob, "Query")
+        .await
+        .unwrap();
+    assert_eq!(alice.deadline(), Some(deadline));
+
+    // The outcome ends the block on both sides
+    alice
+        .send_labelled(&mut alice_ep, TestRole::Bob, "InTime", &InTime)
+        .await
+        .unwrap();
+    assert_eq!(alice.deadline(), None);
+    let _: InTime = bob
+        .recv_labelled(&mut bob_ep, TestRole::Alice, "InTime")
+        .await
+        .unwrap();
+    assert_eq!(bob.deadline(), None);
+}
+
+#[tokio::test]
+async fn test_earlier_deadline_holds() {
+    let ((mut alice, mut alice_ep), (mut bob, mut bob_ep)) = setup(
+        Deadlined::new(RumpsteakHandler::new()),
+        Deadlined::new(RumpsteakHandler::new()),
+    );
+    alice.enter(Duration::from_secs(60));
+    bob.enter(Duration::ZERO);
+    alice
+        .send_labelled(&mut alice_ep, TestRole::Bob, "Query", &Query(1))
+        .await
+        .unwrap();
+    let _: Query = bob
+        .recv_labelled(&mut bob_ep, TestRole::Alice, "Query")
+        .await
+        .unwrap();
+    assert!(bob.is_exceeded());
+    assert_eq!(bob.remaining(), Some(Duration::ZERO));
+}
+
+#[tokio::test]
+async fn test_unnamed_steps_are_refused_in_a_block() {
+    let ((mut alice, mut alice_ep), (mut bob, mut bob_ep)) = setup(
+        Deadlined::new(RumpsteakHandler::new()).within("Query", Duration::from_secs(30)),
+        Deadlined::new(RumpsteakHandler::new()),
+    );
+
+    // Without a name Alice cannot tell whether the message opens the block
+    let err = alice
+        .send(&mut alice_ep, TestRole::Bob, &Query(1))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ChoreographyError::ProtocolViolation(_)));
+
+    // Nor can Bob tell whether the outcome ends the block
+    alice
+        .send_labelled(&mut alice_ep, TestRole::Bob, "Query", &Query(1))
+        .await
+        .unwrap();
+    let _: Query = bob
+        .recv_labelled(&mut bob_ep, TestRole::Alice, "Query")
+        .await
+        .unwrap();
+    alice
+        .send_labelled(&mut alice_ep, TestRole::Bob, "InTime", &InTime)
+        .await
+        .unwrap();
+    let err = bob
+        .recv::<InTime>(&mut bob_ep, TestRole::Alice)
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ChoreographyError::ProtocolViolation(_)));
+}
+
+#[test]
+fn test_time_limits() {
+    assert_eq!(parse_time_limit("250ms"), Some(Duration::from_millis(250)));
+    assert_eq!(parse_time_limit("30s"), Some(Duration::from_secs(30)));
+    assert_eq!(parse_time_limit("5m"), Some(Duration::from_secs(300)));
+    assert_eq!(parse_time_limit("1h"), Some(Duration::from_secs(3_600)));
+    assert_eq!(parse_time_limit("2d"), None);
+    assert_eq!(parse_time_limit("s"), None);
+}
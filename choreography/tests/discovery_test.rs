@@ -0,0 +1,127 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for protocol discovery through a registry
+
+use std::time::Duration;
+
+use rumpsteak_aura_choreography::runtime::bootstrap::{ProtocolDescriptor, RoleAssignment};
+use rumpsteak_aura_choreography::runtime::discovery::{
+    ChoreographyRegistry, DiscoveryClient, DiscoveryError, RegistryResponse, ServiceRecord,
+};
+
+const COMMIT: &str = "
+choreography TwoPhaseCommit {
+    roles: Coordinator, Participant, Auditor
+    Coordinator -> Participant: Prepare
+    Participant -> Coordinator: Vote
+    Coordinator -> Auditor: Outcome
+}
+";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Role {
+    Participant,
+    Auditor,
+}
+
+#[test]
+fn test_initiator_discovers_compatible_peers() {
+    let protocol = ProtocolDescriptor::from_source("TwoPhaseCommit", COMMIT);
+    let older = ProtocolDescriptor::from_source("TwoPhaseCommit", "choreography TwoPhaseCommit {}");
+    let registry = ChoreographyRegistry::new();
+    let address = registry.serve("127.0.0.1:0").unwrap();
+
+    let mut ledger = DiscoveryClient::connect(address).unwrap();
+    let lease = ledger
+        .publish(
+            ServiceRecord::new("ledger", "10.0.0.2:7000", protocol.clone())
+                .with_version("2.1.0")
+                .with_role("Participant"),
+        )
+        .unwrap();
+    assert_eq!(lease, Duration::from_secs(60));
+    let mut audit = DiscoveryClient::connect(address).unwrap();
+    audit
+        .publish(
+            ServiceRecord::new("audit", "10.0.0.3:7000", older.clone())
+                .with_version("1.4.0")
+                .with_role("Auditor"),
+        )
+        .unwrap();
+
+    let mut initiator = DiscoveryClient::connect(address).unwrap();
+    assert_eq!(initiator.lookup("TwoPhaseCommit", None).unwrap().len(), 2);
+    let roster = initiator.roster(&protocol, &[Role::Participant]).unwrap();
+    assert_eq!(
+        roster,
+        [RoleAssignment {
+            role: Role::Participant,
+            participant: "10.0.0.2:7000".to_string(),
+        }]
+    );
+
+    // The auditor was built from another definition of the protocol
+    let err = initiator
+        .roster(&protocol, &[Role::Participant, Role::Auditor])
+        .unwrap_err();
+    assert_eq!(
+        err,
+        DiscoveryError::Incompatible {
+            role: "Auditor".to_string(),
+            expected: protocol.to_string(),
+            found: vec![format!("{older} 1.4.0 (audit)")],
+        }
+    );
+
+    // Until it upgrades, and the old record is replaced
+    audit
+        .publish(
+            ServiceRecord::new("audit", "10.0.0.3:7001", protocol.clone()).with_role("Auditor"),
+        )
+        .unwrap();
+    let record = initiator.locate(&protocol, "Auditor").unwrap();
+    assert_eq!(record.address, "10.0.0.3:7001");
+
+    assert!(audit.withdraw("audit", "TwoPhaseCommit").unwrap());
+    assert!(!audit.withdraw("audit", "TwoPhaseCommit").unwrap());
+    assert_eq!(
+        initiator.locate(&protocol, "Auditor"),
+        Err(DiscoveryError::NotFound {
+            protocol: "TwoPhaseCommit".to_string(),
+            role: "Auditor".to_string(),
+        })
+    );
+}
+
+#[test]
+fn test_publications_expire_unless_renewed() {
+    let protocol = ProtocolDescriptor::from_source("TwoPhaseCommit", COMMIT);
+    let registry = ChoreographyRegistry::new().with_lease(Duration::from_millis(50));
+    let record =
+        ServiceRecord::new("ledger", "10.0.0.2:7000", protocol.clone()).with_role("Participant");
+
+    registry.publish(record.clone());
+    assert_eq!(registry.locate(&protocol, "Participant").unwrap(), record);
+    std::thread::sleep(Duration::from_millis(80));
+    assert!(registry.lookup("TwoPhaseCommit", None).is_empty());
+
+    registry.publish(record);
+    assert_eq!(
+        registry.lookup("TwoPhaseCommit", Some("Participant")).len(),
+        1
+    );
+    assert!(registry
+        .lookup("TwoPhaseCommit", Some("Auditor"))
+        .is_empty());
+}
+
+#[test]
+fn test_malformed_requests_are_answered_with_errors() {
+    let registry = ChoreographyRegistry::new();
+    let reply: RegistryResponse = serde_json::from_str(&registry.command("lookup")).unwrap();
+    assert!(matches!(reply, RegistryResponse::Error { .. }));
+
+    let reply = registry.command(r#"{"request":"lookup","protocol":"TwoPhaseCommit","role":null}"#);
+    assert_eq!(reply, r#"{"response":"found","records":[]}"#);
+}
@@ -0,0 +1,103 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for the distributed tracing middleware
+
+mod common;
+
+use common::{endpoints, roles};
+use rumpsteak_aura_choreography::effects::handlers::rumpsteak::{
+    RumpsteakEndpoint, RumpsteakHandler,
+};
+use rumpsteak_aura_choreography::{ChoreoHandler, Label, Traced};
+use serde::{Deserialize, Serialize};
+
+roles!(TestRole { Alice, Bob }: Ping);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct Ping(u32);
+
+type Handler = Traced<RumpsteakHandler<TestRole, Ping>>;
+
+fn setup() -> (
+    (Handler, RumpsteakEndpoint<TestRole>),
+    (Handler, RumpsteakEndpoint<TestRole>),
+) {
+    let (alice_ep, bob_ep) = endpoints(TestRole::Alice, TestRole::Bob);
+
+    let alice = Traced::new(RumpsteakHandler::new(), TestRole::Alice, "session-1");
+    let bob = Traced::new(RumpsteakHandler::new(), TestRole::Bob, "session-1");
+    ((alice, alice_ep), (bob, bob_ep))
+}
+
+async fn exchange(
+    (alice, alice_ep): &mut (Handler, RumpsteakEndpoint<TestRole>),
+    (bob, bob_ep): &mut (Handler, RumpsteakEndpoint<TestRole>),
+) {
+    alice.send(alice_ep, TestRole::Bob, &Ping(1)).await.unwrap();
+    let ping: Ping = bob.recv(bob_ep, TestRole::Alice).await.unwrap();
+    assert_eq!(ping, Ping(1));
+
+    bob.choose(bob_ep, TestRole::Alice, Label("pong"))
+        .await
+        .unwrap();
+    let label = alice
+        .offer(alice_ep, TestRole::Bob, &[Label("pong")])
+        .await
+        .unwrap();
+    assert_eq!(label, Label("pong"));
+
+    bob.send(bob_ep, TestRole::Alice, &Ping(2)).await.unwrap();
+    let pong: Ping = alice.recv(alice_ep, TestRole::Bob).await.unwrap();
+    assert_eq!(pong, Ping(2));
+}
+
+#[tokio::test]
+async fn test_payloads_and_labels_pass_through() {
+    let (mut alice, mut bob) = setup();
+    exchange(&mut alice, &mut bob).await;
+    assert_eq!(alice.0.session_id(), "session-1");
+    assert!(alice.0.session_span().is_some());
+}
+
+#[cfg(feature = "otel")]
+#[tokio::test]
+async fn test_execution_forms_single_trace() {
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider};
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let exporter = InMemorySpanExporter::default();
+    let provider = SdkTracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+    let guard = tracing::subscriber::set_default(subscriber);
+
+    let (mut alice, mut bob) = setup();
+    exchange(&mut alice, &mut bob).await;
+    drop((alice, bob));
+    drop(guard);
+    provider.force_flush().unwrap();
+
+    let spans = exporter.get_finished_spans().unwrap();
+    let named = |name: &'static str| spans.iter().filter(move |s| s.name == name);
+    assert_eq!(named("choreo.session").count(), 2);
+    assert_eq!(named("choreo.send").count(), 2);
+    assert_eq!(named("choreo.recv").count(), 2);
+
+    let trace_id = spans[0].span_context.trace_id();
+    assert!(spans.iter().all(|s| s.span_context.trace_id() == trace_id));
+
+    // Bob's session joins the trace under Alice's first send
+    let first_send = named("choreo.send")
+        .min_by_key(|s| s.start_time)
+        .unwrap()
+        .span_context
+        .span_id();
+    assert!(
+        named("choreo.session").any(|s| s.parent_span_is_remote && s.parent_span_id == first_send)
+    );
+    assert!(named("choreo.recv").all(|s| s.links.len() == 1));
+}
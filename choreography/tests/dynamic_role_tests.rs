@@ -8,16 +8,20 @@
 //! - Dynamic role projection  
 //! - Runtime role binding and validation
 //! - Overflow protection and security constraints
+//! - Role families projected once for all their instances
 
 use rumpsteak_aura_choreography::{
     ast::{
         Choreography, LocalType, MessageType, Protocol, RangeExpr, Role, RoleIndex, RoleParam,
-        RoleRange, RoleValidationError, MAX_RANGE_SIZE, MAX_ROLE_COUNT, MAX_ROLE_INDEX,
+        RoleQuorum, RoleRange, RoleValidationError, Span, MAX_RANGE_SIZE, MAX_ROLE_COUNT,
+        MAX_ROLE_INDEX,
     },
     compiler::{
         codegen::{generate_choreography_code_with_dynamic_roles, generate_dynamic_role_support},
+        parser::parse_choreography_str,
         projection::{project, ProjectionError},
     },
+    parse_and_generate_with_extensions, ExtensionRegistry,
 };
 // Removed unused import
 use quote::{format_ident, quote};
@@ -145,6 +149,7 @@ fn test_dynamic_role_projection() {
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     // Test projection for coordinator (should be Send)
@@ -164,8 +169,14 @@ fn test_dynamic_role_projection() {
             annotations: HashMap::new(),
             from_annotations: HashMap::new(),
             to_annotations: HashMap::new(),
+            span: Span::default(),
         },
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     // Project for coordinator - should work but require runtime bindings for dynamic target
@@ -225,6 +236,11 @@ fn test_dynamic_role_code_generation() {
         roles: vec![coordinator.clone(), signers.clone(), workers.clone()],
         protocol: Protocol::End,
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     // Generate dynamic role support
@@ -395,6 +411,11 @@ fn test_integration_dynamic_roles() {
         roles: vec![coordinator.clone(), signers.clone()],
         protocol: Protocol::End, // Simplified for test
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     // Test that choreography with namespace and dynamic roles works
@@ -416,3 +437,242 @@ fn test_integration_dynamic_roles() {
     assert!(code_str.contains("dynamic"));
     assert!(code_str.contains("bind_role_count"));
 }
+
+const FARM: &str = r"
+choreography Farm {
+    roles: Master, Worker[N]
+
+    Master -> Worker[i]: Task
+    Worker[i] -> Master: Answer
+}
+";
+
+#[test]
+fn test_role_family_projected_once() {
+    let choreography = parse_choreography_str(FARM).unwrap();
+    let master = &choreography.roles[0];
+    let worker = &choreography.roles[1];
+    assert!(worker.is_family());
+
+    // Every instance takes the part of `Worker[i]`
+    let LocalType::Receive {
+        from, continuation, ..
+    } = project(&choreography, worker).unwrap()
+    else {
+        panic!("expected Worker to receive first");
+    };
+    assert_eq!(from.name, "Master");
+    assert!(matches!(*continuation, LocalType::Send { ref to, .. } if to.name == "Master"));
+
+    let LocalType::Send { to, .. } = project(&choreography, master).unwrap() else {
+        panic!("expected Master to send first");
+    };
+    assert_eq!(to.index, Some(RoleIndex::Symbolic("i".to_string())));
+}
+
+#[test]
+fn test_role_family_instance_specific() {
+    let choreography =
+        parse_choreography_str(&FARM.replace("Worker[i] ->", "Worker[0] ->")).unwrap();
+
+    let error = project(&choreography, &choreography.roles[1]).unwrap_err();
+    assert!(
+        matches!(error, ProjectionError::InstanceSpecific { ref role, .. } if role == "Worker")
+    );
+    assert_eq!(error.code(), "RA0211");
+    assert!(error.to_string().contains("singles out Worker[0]"));
+
+    // Instances messaging each other have no single local type either
+    let choreography =
+        parse_choreography_str(&FARM.replace("-> Master:", "-> Worker[*]:")).unwrap();
+    let error = project(&choreography, &choreography.roles[1]).unwrap_err();
+    assert!(matches!(error, ProjectionError::InstanceSpecific { .. }));
+}
+
+#[test]
+fn test_role_family_generates_one_endpoint() {
+    let code = parse_and_generate_with_extensions(FARM, &ExtensionRegistry::new())
+        .unwrap()
+        .to_string();
+
+    assert!(code.contains("struct Worker < const I : usize > (# [route (Master)] Channel)"));
+    assert!(code.contains("struct Master (# [routes (Worker)] Vec < Channel >)"));
+    assert!(code.contains("type Master_Farm < const I : usize > = Send < Worker < I > , Task"));
+    assert!(code.contains("type Worker_Farm = Receive < Master , Task"));
+    // The role family has no single struct to put in a Roles tuple
+    assert!(!code.contains("derive (Roles)"));
+}
+
+const CEREMONY: &str = r#"
+choreography Ceremony {
+    roles: Coordinator, Signer[N]
+
+    Coordinator -> Signer[*]: Request
+    [@stragglers = "collect_late"]
+    Signer[t of N] -> Coordinator: Share
+}
+"#;
+
+#[test]
+fn test_quorum_receive() {
+    let choreography = parse_choreography_str(CEREMONY).unwrap();
+    let coordinator = &choreography.roles[0];
+    let signer = &choreography.roles[1];
+
+    // Every signer sends its share, the coordinator waits for a quorum
+    let LocalType::Receive { continuation, .. } = project(&choreography, signer).unwrap() else {
+        panic!("expected Signer to receive first");
+    };
+    assert!(matches!(*continuation, LocalType::Send { ref to, .. } if to.name == "Coordinator"));
+
+    let LocalType::Send { continuation, .. } = project(&choreography, coordinator).unwrap() else {
+        panic!("expected Coordinator to send first");
+    };
+    let LocalType::Receive { from, .. } = *continuation else {
+        panic!("expected Coordinator to receive the shares");
+    };
+    assert_eq!(
+        from.index,
+        Some(RoleIndex::Quorum(Box::new(RoleQuorum {
+            threshold: RangeExpr::Symbolic("t".to_string()),
+            of: RangeExpr::Symbolic("N".to_string()),
+        })))
+    );
+
+    let code = generate_dynamic_role_support(&choreography).to_string();
+    assert!(code.contains("pub fn signer_share_quorum"));
+    assert!(code.contains("StragglerPolicy :: CollectLate"));
+}
+
+#[test]
+fn test_quorum_validation() {
+    let invalid = [
+        ("Signer[t of N] ->", "Signer[4 of 3] ->"),
+        ("Signer[t of N] ->", "Signer[0 of 3] ->"),
+        ("Coordinator -> Signer[*]", "Coordinator -> Signer[t of N]"),
+        ("\"collect_late\"", "\"wait\""),
+        ("Signer[t of N] ->", "Signer[0] ->"),
+    ];
+    for (from, to) in invalid {
+        let source = CEREMONY.replace(from, to);
+        assert!(parse_choreography_str(&source).is_err(), "{to} parsed");
+    }
+}
+
+#[test]
+fn test_reassign_parses_to_handover() {
+    let choreography = parse_choreography_str(
+        r"
+choreography Failover {
+    roles: Coordinator, Worker[N]
+
+    Coordinator -> Worker[*]: Task
+    reassign Coordinator from Worker[*] by election;
+    Worker[i] -> Coordinator: Answer
+}
+",
+    )
+    .unwrap();
+
+    let Protocol::Send { continuation, .. } = &choreography.protocol else {
+        panic!("expected the task send first");
+    };
+    let handover = continuation.as_ref();
+    let Protocol::Send {
+        from, to, message, ..
+    } = handover
+    else {
+        panic!("expected the reassignment to parse to a send");
+    };
+    assert_eq!(from.name, "Coordinator");
+    assert_eq!(to.index, Some(RoleIndex::Wildcard));
+    assert_eq!(message.name, "Handover");
+    assert_eq!(handover.reassignment(), Some("election"));
+
+    // Every candidate receives the outcome of the election
+    let LocalType::Receive { continuation, .. } =
+        project(&choreography, &choreography.roles[1]).unwrap()
+    else {
+        panic!("expected Worker to receive first");
+    };
+    assert!(
+        matches!(*continuation, LocalType::Receive { ref message, .. } if message.name == "Handover")
+    );
+
+    let error = parse_choreography_str(
+        "choreography Failover { roles: Coordinator, Worker[N] reassign Coordinator from Coordinator by election }",
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains("cannot be reassigned to itself"));
+}
+
+const BOUNDED_CEREMONY: &str = r#"
+choreography Ceremony {
+    roles: Coordinator, Signer[3..=10]
+
+    Coordinator -> Signer[*]: Request
+    Signer[2 of 3] -> Coordinator: Share
+}
+"#;
+
+#[test]
+fn test_bounded_multiplicity() {
+    use rumpsteak_aura_choreography::ast::role::RoleBoundsChecker;
+
+    let choreography = parse_choreography_str(BOUNDED_CEREMONY).unwrap();
+    let signer = &choreography.roles[1];
+    assert_eq!(signer.param, Some(RoleParam::Bounded { min: 3, max: 10 }));
+    assert!(signer.is_dynamic());
+    choreography.validate().unwrap();
+    project(&choreography, &choreography.roles[0]).unwrap();
+    project(&choreography, signer).unwrap();
+
+    // Started with too few or too many signers
+    let checker = RoleBoundsChecker::default();
+    assert!(checker.check_multiplicity(signer, 3).is_ok());
+    assert!(checker.check_multiplicity(signer, 10).is_ok());
+    assert!(matches!(
+        checker.check_multiplicity(signer, 2),
+        Err(RoleValidationError::MultiplicityViolation {
+            count: 2,
+            min: 3,
+            max: 10,
+            ..
+        })
+    ));
+    let code = generate_dynamic_role_support(&choreography).to_string();
+    assert!(code.contains("(3u32 ..= 10u32) . contains (& count)"));
+    assert!(code.contains("Self :: validate_signer_count (instances . len () as u32) ?"));
+
+    // Indices must hold with as few signers as allowed
+    for (from, to) in [
+        ("Signer[*]", "Signer[3]"),
+        ("Signer[2 of 3]", "Signer[2 of 4]"),
+        ("Signer[*]", "Signer[1..4]"),
+    ] {
+        let choreography = parse_choreography_str(&BOUNDED_CEREMONY.replace(from, to)).unwrap();
+        let errors = choreography.validate_all();
+        assert!(
+            errors.iter().any(|error| error.code() == "RA0112"
+                && error.to_string().contains("when Signer has 3 instances")),
+            "{to}: {errors:?}"
+        );
+    }
+
+    // Static families are checked alike
+    let choreography = parse_choreography_str(
+        &BOUNDED_CEREMONY
+            .replace("Signer[3..=10]", "Signer[3]")
+            .replace("Signer[*]", "Signer[3]"),
+    )
+    .unwrap();
+    assert_eq!(
+        choreography.validate().unwrap_err().to_string(),
+        "Signer[3] is out of bounds when Signer has 3 instances"
+    );
+
+    for bounds in ["Signer[5..=3]", "Signer[3..=10001]"] {
+        let source = BOUNDED_CEREMONY.replace("Signer[3..=10]", bounds);
+        assert!(parse_choreography_str(&source).is_err(), "{bounds} parsed");
+    }
+}
@@ -1096,3 +1096,308 @@ mod regression_tests {
         assert_eq!(choreography.roles.len(), 3);
     }
 }
+
+#[cfg(test)]
+mod extension_syntax_tests {
+    use super::*;
+    use rumpsteak_aura_choreography::compiler::parser::parse_choreography_str_with_extensions;
+    use rumpsteak_aura_choreography::define_extension;
+    use rumpsteak_aura_choreography::extensions::define::DefinedStatement;
+
+    #[derive(Debug)]
+    pub struct Checkpoint {
+        pub label: String,
+    }
+
+    define_extension! {
+        /// `checkpoint <label>` statements
+        pub struct CheckpointExtension {
+            id: "checkpoint",
+            grammar: r#"checkpoint_stmt = { "checkpoint" ~ ident }"#,
+            statements: ["checkpoint_stmt"],
+            priority: 120,
+            data: Checkpoint,
+            parse: |_rule, content, _context| {
+                let label = content.trim().strip_prefix("checkpoint").ok_or_else(|| {
+                    ParseError::InvalidSyntax { details: content.to_string() }
+                })?;
+                Ok(Checkpoint { label: label.trim().to_string() })
+            },
+            project: |_data, _role, _context| Ok(LocalType::End),
+            codegen: |data, _context| {
+                let label = &data.label;
+                quote::quote! { .checkpoint(#label) }
+            },
+        }
+    }
+
+    #[test]
+    fn test_extension_statement_parses_into_protocol() {
+        let mut registry = ExtensionRegistry::new();
+        CheckpointExtension::register(&mut registry).unwrap();
+
+        let (choreography, _) = parse_choreography_str_with_extensions(
+            r#"
+            choreography Checkpointed {
+                roles: A, B
+
+                A -> B: Hello;
+                checkpoint first;
+                B -> A: Bye;
+            }
+        "#,
+            &registry,
+        )
+        .unwrap();
+
+        let Protocol::Send { continuation, .. } = choreography.protocol else {
+            panic!("expected a send first");
+        };
+        let Protocol::Extension {
+            extension,
+            continuation,
+            ..
+        } = *continuation
+        else {
+            panic!("expected the checkpoint after the first send");
+        };
+        let checkpoint = extension
+            .as_any()
+            .downcast_ref::<DefinedStatement<CheckpointExtension>>()
+            .unwrap();
+        assert_eq!(checkpoint.data.label, "first");
+        assert!(matches!(
+            *continuation,
+            Protocol::Send { ref message, .. } if message.name == "Bye"
+        ));
+    }
+
+    #[test]
+    fn test_extension_statement_needs_its_extension() {
+        let mut registry = ExtensionRegistry::new();
+        CheckpointExtension::register(&mut registry).unwrap();
+
+        // Without the extension the statement does not parse at all
+        let input = r#"
+            choreography Checkpointed {
+                roles: A, B
+                checkpoint first
+            }
+        "#;
+        assert!(parse_choreography_str(input).is_err());
+        assert!(parse_choreography_str_with_extensions(input, &registry).is_ok());
+    }
+
+    /// Adds syntax at every extension point other than statements
+    #[derive(Debug)]
+    struct MarkerExtension;
+
+    impl GrammarExtension for MarkerExtension {
+        fn grammar_rules(&self) -> &'static str {
+            r#"
+            deadline_item = { "deadline" ~ integer ~ ";" }
+            observer_role = { "observer" ~ ident }
+            weight_modifier = { "weight" ~ "(" ~ integer ~ ")" }
+            retry_annotation = { "@retry" ~ "(" ~ integer ~ ")" }
+            seconds_value = @{ integer ~ "s" }
+            "#
+        }
+
+        fn statement_rules(&self) -> Vec<&'static str> {
+            vec![]
+        }
+
+        fn item_rules(&self) -> Vec<&'static str> {
+            vec!["deadline_item"]
+        }
+
+        fn role_declaration_rules(&self) -> Vec<&'static str> {
+            vec!["observer_role"]
+        }
+
+        fn choice_modifier_rules(&self) -> Vec<&'static str> {
+            vec!["weight_modifier"]
+        }
+
+        fn annotation_rules(&self) -> Vec<&'static str> {
+            vec!["retry_annotation"]
+        }
+
+        fn expression_rules(&self) -> Vec<&'static str> {
+            vec!["seconds_value"]
+        }
+
+        fn extension_id(&self) -> &'static str {
+            "markers"
+        }
+    }
+
+    /// Syntax matched at an extension point, by the rule that matched it
+    #[derive(Debug)]
+    struct Marker {
+        rule: String,
+        text: String,
+    }
+
+    #[derive(Debug)]
+    struct MarkerParser;
+
+    impl StatementParser for MarkerParser {
+        fn can_parse(&self, rule_name: &str) -> bool {
+            self.supported_rules().iter().any(|rule| rule == rule_name)
+        }
+
+        fn supported_rules(&self) -> Vec<String> {
+            [
+                "deadline_item",
+                "observer_role",
+                "weight_modifier",
+                "retry_annotation",
+                "seconds_value",
+            ]
+            .map(String::from)
+            .to_vec()
+        }
+
+        fn parse_statement(
+            &self,
+            rule_name: &str,
+            content: &str,
+            _context: &ParseContext,
+        ) -> Result<Box<dyn ProtocolExtension>, ParseError> {
+            Ok(Box::new(Marker {
+                rule: rule_name.to_string(),
+                text: content.to_string(),
+            }))
+        }
+    }
+
+    impl ProtocolExtension for Marker {
+        fn type_name(&self) -> &'static str {
+            "Marker"
+        }
+
+        fn mentions_role(&self, _role: &Role) -> bool {
+            false
+        }
+
+        fn validate(&self, _roles: &[Role]) -> Result<(), ExtensionValidationError> {
+            Ok(())
+        }
+
+        fn project(
+            &self,
+            _role: &Role,
+            _context: &ProjectionContext,
+        ) -> Result<LocalType, ProjectionError> {
+            Ok(LocalType::End)
+        }
+
+        fn generate_code(&self, _context: &CodegenContext) -> proc_macro2::TokenStream {
+            quote::quote! {}
+        }
+
+        fn as_any(&self) -> &dyn std::any::Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn std::any::Any {
+            self
+        }
+
+        fn type_id(&self) -> std::any::TypeId {
+            std::any::TypeId::of::<Self>()
+        }
+    }
+
+    /// Rule and text of the markers `input` parses to
+    fn markers(input: &str) -> Vec<(String, String)> {
+        let mut registry = ExtensionRegistry::new();
+        registry.register_grammar(MarkerExtension).unwrap();
+        registry.register_parser(MarkerParser, "markers".to_string());
+
+        let (_, extensions) = parse_choreography_str_with_extensions(input, &registry).unwrap();
+        extensions
+            .iter()
+            .map(|extension| {
+                let marker = extension.as_any().downcast_ref::<Marker>().unwrap();
+                (marker.rule.clone(), marker.text.clone())
+            })
+            .collect()
+    }
+
+    fn marker(rule: &str, text: &str) -> (String, String) {
+        (rule.to_string(), text.to_string())
+    }
+
+    #[test]
+    fn test_extension_item_parses() {
+        let input = r#"
+            choreography Deadline {
+                roles: A, B
+                deadline 30;
+                A -> B: Hello;
+            }
+        "#;
+        assert_eq!(markers(input), [marker("deadline_item", "deadline 30;")]);
+    }
+
+    #[test]
+    fn test_extension_role_declaration_parses() {
+        let input = r#"
+            choreography Observed {
+                roles: A, observer Log, B
+                A -> B: Hello;
+            }
+        "#;
+        assert_eq!(markers(input), [marker("observer_role", "observer Log")]);
+    }
+
+    #[test]
+    fn test_extension_choice_modifier_parses() {
+        let input = r#"
+            choreography Weighted {
+                roles: A, B
+                choice A {
+                    fast weight(3): {
+                        A -> B: Fast;
+                    }
+                    slow: {
+                        A -> B: Slow;
+                    }
+                }
+            }
+        "#;
+        let mut registry = ExtensionRegistry::new();
+        registry.register_grammar(MarkerExtension).unwrap();
+        registry.register_parser(MarkerParser, "markers".to_string());
+        let (choreography, _) = parse_choreography_str_with_extensions(input, &registry).unwrap();
+        let Protocol::Choice { branches, .. } = choreography.protocol else {
+            panic!("expected a choice");
+        };
+        assert_eq!(branches.len(), 2);
+        assert_eq!(branches[0].label, "fast");
+
+        assert_eq!(markers(input), [marker("weight_modifier", "weight(3)")]);
+    }
+
+    #[test]
+    fn test_extension_annotation_and_expression_parse() {
+        let input = r#"
+            choreography Retried {
+                roles: A, B
+                @retry(3)
+                A -> B: Hello;
+                [@timeout = 5s]
+                B -> A: Bye;
+            }
+        "#;
+        assert_eq!(
+            markers(input),
+            [
+                marker("retry_annotation", "@retry(3)"),
+                marker("seconds_value", "5s"),
+            ]
+        );
+    }
+}
@@ -124,7 +124,12 @@ impl ChoreoHandler for TestHandler {
         Ok(())
     }
 
-    async fn offer(&mut self, _ep: &mut Self::Endpoint, _from: Self::Role) -> Result<Label> {
+    async fn offer(
+        &mut self,
+        _ep: &mut Self::Endpoint,
+        _from: Self::Role,
+        _labels: &[Label],
+    ) -> Result<Label> {
         Ok(Label("default"))
     }
 
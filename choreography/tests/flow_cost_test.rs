@@ -0,0 +1,127 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for flow-cost budgets
+
+mod common;
+
+use common::{endpoints, roles};
+use rumpsteak_aura_choreography::compiler::{analyze_flow_cost, parse_choreography_str, FlowCost};
+use rumpsteak_aura_choreography::effects::handlers::rumpsteak::{
+    RumpsteakEndpoint, RumpsteakHandler,
+};
+use rumpsteak_aura_choreography::runtime::flow::{FlowCharge, FlowMeter};
+use rumpsteak_aura_choreography::{ChoreoHandler, ChoreographyError, Metered};
+use serde::{Deserialize, Serialize};
+
+roles!(TestRole { Client, Server }: Chunk);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct Chunk(u32);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct Ack;
+
+// What generated code emits for `Client[@flow_cost = 40] -> Server: Chunk`
+const CLIENT_CHARGES: &[FlowCharge] = &[FlowCharge {
+    peer: "Server",
+    label: "Chunk",
+    cost: 40,
+}];
+
+type Client = Metered<RumpsteakHandler<TestRole, Chunk>>;
+type Server = RumpsteakHandler<TestRole, Chunk>;
+
+fn setup(
+    meter: FlowMeter,
+) -> (
+    (Client, RumpsteakEndpoint<TestRole>),
+    (Server, RumpsteakEndpoint<TestRole>),
+) {
+    let (client_ep, server_ep) = endpoints(TestRole::Client, TestRole::Server);
+
+    let client = Metered::new(
+        RumpsteakHandler::new(),
+        TestRole::Client,
+        meter,
+        CLIENT_CHARGES,
+    );
+    ((client, client_ep), (RumpsteakHandler::new(), server_ep))
+}
+
+#[tokio::test]
+async fn test_session_aborts_when_budget_is_spent() {
+    let meter = FlowMeter::new(100);
+    let ((mut client, mut client_ep), (mut server, mut server_ep)) = setup(meter.clone());
+
+    for i in 0..2 {
+        client
+            .send_labelled(&mut client_ep, TestRole::Server, "Chunk", &Chunk(i))
+            .await
+            .unwrap();
+        let chunk: Chunk = server.recv(&mut server_ep, TestRole::Client).await.unwrap();
+        assert_eq!(chunk, Chunk(i));
+    }
+    assert_eq!(meter.remaining(), 20);
+
+    let err = client
+        .send_labelled(&mut client_ep, TestRole::Server, "Chunk", &Chunk(2))
+        .await
+        .unwrap_err();
+    match err {
+        ChoreographyError::BudgetExceeded(exceeded) => {
+            assert_eq!(exceeded.role, "Client");
+            assert_eq!(exceeded.cost, 40);
+            assert_eq!(exceeded.remaining, 20);
+            assert_eq!(exceeded.budget, 100);
+        }
+        other => panic!("expected BudgetExceeded, got {other:?}"),
+    }
+    assert_eq!(meter.spent(), 80);
+}
+
+#[tokio::test]
+async fn test_unannotated_sends_are_free() {
+    let meter = FlowMeter::new(0);
+    let ((mut client, mut client_ep), (mut server, mut server_ep)) = setup(meter.clone());
+
+    client
+        .send_labelled(&mut client_ep, TestRole::Server, "Ack", &Ack)
+        .await
+        .unwrap();
+    let _: Ack = server.recv(&mut server_ep, TestRole::Client).await.unwrap();
+    assert_eq!(meter.spent(), 0);
+}
+
+#[tokio::test]
+async fn test_unnamed_sends_to_charged_peers_are_refused() {
+    let meter = FlowMeter::new(100);
+    let ((mut client, mut client_ep), _) = setup(meter.clone());
+
+    let err = client
+        .send(&mut client_ep, TestRole::Server, &Chunk(0))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ChoreographyError::ProtocolViolation(_)));
+    assert_eq!(meter.spent(), 0);
+}
+
+#[test]
+fn test_static_worst_case_matches_runtime_budget() {
+    let choreography = parse_choreography_str(
+        r#"
+choreography Upload {
+    roles: Client, Server
+    loop (count: 2) {
+        Client[@flow_cost = 40] -> Server: Chunk
+    }
+}
+"#,
+    )
+    .unwrap();
+
+    let report = analyze_flow_cost(&choreography).unwrap();
+    assert_eq!(report.worst_case("Client"), FlowCost::Bounded(80));
+    assert!(report.over_budget(100).is_empty());
+    assert_eq!(report.over_budget(79), vec!["Client"]);
+}
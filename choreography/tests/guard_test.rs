@@ -0,0 +1,275 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for capability guards
+
+mod common;
+
+use common::{endpoints, generated_guard_points, roles};
+use rumpsteak_aura_choreography::compiler::{parse_choreography_str, project, ProjectionError};
+use rumpsteak_aura_choreography::effects::handlers::rumpsteak::{
+    RumpsteakEndpoint, RumpsteakHandler,
+};
+use rumpsteak_aura_choreography::runtime::guard::{CapabilitySet, GuardPoint};
+use rumpsteak_aura_choreography::runtime::monitor::ActionKind;
+use rumpsteak_aura_choreography::{
+    Cancellable, ChoreoHandler, ChoreographyError, Guarded, Label, SessionHandle,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+roles!(TestRole { Client, Manager }: Request);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct Request(u32);
+
+const APPROVAL: &str = r#"
+choreography Approval {
+    roles: Client, Manager
+    Client[@guard_capability = "submit"] -> Manager: Request
+    [@guard_capability = "approve"]
+    choice Manager {
+        accept: {
+            Manager -> Client: Accepted
+        }
+        reject: {
+            Manager -> Client: Rejected
+        }
+    }
+}
+"#;
+
+/// Guard points generated for `role` of `APPROVAL`
+fn points(role: &str) -> &'static [GuardPoint] {
+    generated_guard_points(&parse_choreography_str(APPROVAL).unwrap(), role)
+}
+
+type Handler = Guarded<RumpsteakHandler<TestRole, Request>>;
+
+fn setup(
+    capabilities: CapabilitySet,
+) -> (
+    (Handler, RumpsteakEndpoint<TestRole>),
+    (Handler, RumpsteakEndpoint<TestRole>),
+) {
+    let (client_ep, manager_ep) = endpoints(TestRole::Client, TestRole::Manager);
+
+    let capabilities = Arc::new(capabilities);
+    let client = Guarded::new(
+        RumpsteakHandler::new(),
+        TestRole::Client,
+        capabilities.clone(),
+        points("Client"),
+    );
+    let manager = Guarded::new(
+        RumpsteakHandler::new(),
+        TestRole::Manager,
+        capabilities,
+        points("Manager"),
+    );
+    ((client, client_ep), (manager, manager_ep))
+}
+
+#[tokio::test]
+async fn test_granted_capabilities_pass() {
+    let caps = CapabilitySet::new()
+        .grant("Client", "submit")
+        .grant("Manager", "approve");
+    let ((mut client, mut client_ep), (mut manager, mut manager_ep)) = setup(caps);
+
+    client
+        .send_labelled(&mut client_ep, TestRole::Manager, "Request", &Request(1))
+        .await
+        .unwrap();
+    let req: Request = manager
+        .recv(&mut manager_ep, TestRole::Client)
+        .await
+        .unwrap();
+    assert_eq!(req, Request(1));
+
+    manager
+        .choose(&mut manager_ep, TestRole::Client, Label("accept"))
+        .await
+        .unwrap();
+    let label = client
+        .offer(
+            &mut client_ep,
+            TestRole::Manager,
+            &[Label("accept"), Label("reject")],
+        )
+        .await
+        .unwrap();
+    assert_eq!(label, Label("accept"));
+}
+
+#[tokio::test]
+async fn test_missing_capability_is_denied() {
+    let caps = CapabilitySet::new().grant("Client", "submit");
+    let ((mut client, mut client_ep), (mut manager, mut manager_ep)) = setup(caps);
+
+    let err = manager
+        .choose(&mut manager_ep, TestRole::Client, Label("accept"))
+        .await
+        .unwrap_err();
+    match err {
+        ChoreographyError::GuardDenied(denied) => {
+            assert_eq!(denied.role, "Manager");
+            assert_eq!(denied.capability, "approve");
+            assert_eq!(denied.action, ActionKind::Select);
+        }
+        other => panic!("expected GuardDenied, got {other:?}"),
+    }
+
+    // Steps guarded by a granted capability are not affected
+    client
+        .send_labelled(&mut client_ep, TestRole::Manager, "Request", &Request(2))
+        .await
+        .unwrap();
+    let req: Request = manager
+        .recv(&mut manager_ep, TestRole::Client)
+        .await
+        .unwrap();
+    assert_eq!(req, Request(2));
+}
+
+#[tokio::test]
+async fn test_denied_send_reaches_no_peer() {
+    let caps = |role: &str, _cap: &str| role != "Client";
+    let ((mut client, mut client_ep), (mut manager, mut manager_ep)) = setup(CapabilitySet::new());
+    client = Guarded::new(
+        client.into_inner(),
+        TestRole::Client,
+        Arc::new(caps),
+        points("Client"),
+    );
+
+    let err = client
+        .send_labelled(&mut client_ep, TestRole::Manager, "Request", &Request(7))
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Guard denied: Client lacks capability 'submit' required to send Request (Manager)"
+    );
+
+    // Nothing was transmitted, so the manager sees the next permitted message first
+    let mut open = Guarded::new(
+        client.into_inner(),
+        TestRole::Client,
+        Arc::new(|_: &str, _: &str| true),
+        points("Client"),
+    );
+    open.send_labelled(&mut client_ep, TestRole::Manager, "Request", &Request(8))
+        .await
+        .unwrap();
+    let req: Request = manager
+        .recv(&mut manager_ep, TestRole::Client)
+        .await
+        .unwrap();
+    assert_eq!(req, Request(8));
+}
+
+#[tokio::test]
+async fn test_unnamed_sends_to_guarded_peers_are_denied() {
+    let ((mut client, mut client_ep), _) = setup(CapabilitySet::new());
+
+    // Without the message name the guard cannot be told apart, so it applies
+    let err = client
+        .send(&mut client_ep, TestRole::Manager, &Request(1))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ChoreographyError::GuardDenied(_)));
+}
+
+#[tokio::test]
+async fn test_guards_hold_under_framing_middleware() {
+    let ((client, mut client_ep), _) = setup(CapabilitySet::new());
+    let mut client = Cancellable::new(client, SessionHandle::new(), vec![TestRole::Manager]);
+
+    // The message name reaches the guard though the payload is wrapped in a frame
+    let err = client
+        .send_labelled(&mut client_ep, TestRole::Manager, "Request", &Request(1))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ChoreographyError::GuardDenied(_)));
+}
+
+#[test]
+fn test_role_annotation_guards_are_parsed() {
+    let choreography = parse_choreography_str(
+        r#"
+choreography Approval {
+    roles: Client, Manager
+    Client[@guard_capability = "submit"] -> Manager[@guard_capability = "review"]: Request
+}
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        choreography
+            .protocol
+            .get_from_annotations()
+            .and_then(|a| a.get("guard_capability"))
+            .map(String::as_str),
+        Some("submit")
+    );
+    assert_eq!(
+        choreography
+            .protocol
+            .get_to_annotations()
+            .and_then(|a| a.get("guard_capability"))
+            .map(String::as_str),
+        Some("review")
+    );
+}
+
+#[test]
+fn test_projection_rejects_guard_on_uninvolved_role() {
+    let choreography = parse_choreography_str(
+        r#"
+choreography Approval {
+    roles: Client, Manager, Auditor
+    [@guard_capability = "audit", @guard_role = "Auditor"]
+    Client -> Manager: Request
+}
+"#,
+    )
+    .unwrap();
+
+    let err = project(&choreography, &choreography.roles[0]).unwrap_err();
+    assert!(matches!(err, ProjectionError::MisplacedGuard { .. }));
+    assert_eq!(
+        err.to_string(),
+        "Capability guard 'audit' names role Auditor, which does not take part in Client -> Manager: Request"
+    );
+}
+
+#[test]
+fn test_projection_accepts_guard_on_choice_participant() {
+    let choreography = parse_choreography_str(
+        r#"
+choreography Approval {
+    roles: Client, Manager
+    [@guard_capability = "read", @guard_role = "Client"]
+    choice Manager {
+        accept: {
+            Manager -> Client: Accepted
+        }
+        reject: {
+            Manager -> Client: Rejected
+        }
+    }
+}
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        choreography.protocol.get_annotation("guard_capability"),
+        Some(&"read".to_string())
+    );
+    for role in &choreography.roles {
+        project(&choreography, role).unwrap();
+    }
+}
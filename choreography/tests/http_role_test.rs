@@ -0,0 +1,148 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for roles implemented by an external HTTP service
+
+use rumpsteak_aura_choreography::runtime::http::{HttpRoleHandler, HttpRoute, RouteTable};
+use rumpsteak_aura_choreography::{ChoreoHandler, ChoreographyError, Label, NoOpHandler};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpListener;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Role {
+    Bank,
+    Api,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+struct Charge {
+    cents: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+struct Receipt {
+    id: String,
+}
+
+fn routes() -> RouteTable {
+    RouteTable::new()
+        .with_route("Charge", HttpRoute::for_message("Charge"))
+        .with_route("Receipt", "POST /hooks/receipt".parse().unwrap())
+}
+
+/// Serve one request, answering it with `response` and returning its
+/// request line and body
+async fn serve_once(listener: TcpListener, response: &'static str) -> (String, String) {
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut stream = BufReader::new(stream);
+    let mut request_line = String::new();
+    stream.read_line(&mut request_line).await.unwrap();
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        stream.read_line(&mut line).await.unwrap();
+        if line == "\r\n" {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse().unwrap();
+        }
+    }
+    let mut body = vec![0; length];
+    stream.read_exact(&mut body).await.unwrap();
+    let reply = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{response}",
+        response.len()
+    );
+    stream.get_mut().write_all(reply.as_bytes()).await.unwrap();
+    (
+        request_line.trim_end().to_string(),
+        String::from_utf8(body).unwrap(),
+    )
+}
+
+#[tokio::test]
+async fn test_send_becomes_request_and_response_is_received() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = tokio::spawn(serve_once(listener, r#"{"id":"r-1"}"#));
+
+    let mut shop = HttpRoleHandler::new(NoOpHandler::new(), Role::Api, address, routes());
+    shop.send(&mut (), Role::Api, &Charge { cents: 250 })
+        .await
+        .unwrap();
+    let receipt: Receipt = shop.recv(&mut (), Role::Api).await.unwrap();
+    assert_eq!(receipt.id, "r-1");
+    // Other roles are left to the wrapped handler
+    shop.send(&mut (), Role::Bank, &Charge { cents: 250 })
+        .await
+        .unwrap();
+
+    let (request_line, body) = server.await.unwrap();
+    assert_eq!(request_line, "POST /charge HTTP/1.1");
+    assert_eq!(body, r#"{"cents":250}"#);
+}
+
+#[tokio::test]
+async fn test_webhook_is_received_from_external_role() {
+    let mut shop = HttpRoleHandler::new(
+        NoOpHandler::new(),
+        Role::Api,
+        "127.0.0.1:9".parse().unwrap(),
+        routes(),
+    );
+    let sink = shop.webhook_sink();
+
+    let (mut service, local) = tokio::io::duplex(1024);
+    let accepted = tokio::spawn(async move { sink.accept(local.compat()).await });
+    let body = r#"{"id":"r-2"}"#;
+    let request = format!(
+        "POST /hooks/receipt HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+    service.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    service.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 202"), "{response}");
+    assert_eq!(accepted.await.unwrap().unwrap(), "Receipt");
+
+    let receipt: Receipt = shop.recv(&mut (), Role::Api).await.unwrap();
+    assert_eq!(receipt.id, "r-2");
+
+    // Routes of no message are refused
+    let sink = shop.webhook_sink();
+    let (mut service, local) = tokio::io::duplex(1024);
+    let accepted = tokio::spawn(async move { sink.accept(local.compat()).await });
+    service
+        .write_all(b"POST /elsewhere HTTP/1.1\r\nContent-Length: 0\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    service.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 404"), "{response}");
+    assert!(accepted.await.unwrap().is_err());
+}
+
+#[tokio::test]
+async fn test_choice_of_external_role_is_resolved_against_offered_branches() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = tokio::spawn(async move {
+        let (first, _) = serve_once(listener, r#""refund""#).await;
+        first
+    });
+
+    let mut shop = HttpRoleHandler::new(NoOpHandler::new(), Role::Api, address, routes());
+    shop.send(&mut (), Role::Api, &Charge { cents: 250 })
+        .await
+        .unwrap();
+    let labels = [Label("accept"), Label("reject")];
+    let error = shop.offer(&mut (), Role::Api, &labels).await.unwrap_err();
+    assert!(
+        matches!(error, ChoreographyError::ProtocolViolation(_)),
+        "{error}"
+    );
+    server.await.unwrap();
+}
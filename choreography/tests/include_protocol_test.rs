@@ -0,0 +1,61 @@
+#![allow(clippy::unwrap_used)]
+
+// Fragments spliced into choreographies with `include_protocol!`
+
+use quote::quote;
+use rumpsteak_aura_choreography::compiler::{
+    choreography_macro, generate_sequence_diagram, parse_choreography_str,
+};
+
+const CHECKOUT: &str = r#"
+choreography Checkout {
+    roles: Client, Shop, Bank
+    Client -> Shop: Order
+    include_protocol!("tests/fragments/payment.choreo", Payer = Client, Payee = Shop);
+    include_protocol!("tests/fragments/payment.choreo", Payer = Shop, Payee = Bank);
+}
+"#;
+
+#[test]
+fn test_fragments_are_spliced_with_roles_mapped() {
+    let choreography = parse_choreography_str(CHECKOUT).unwrap();
+    choreography.validate().unwrap();
+    for role in &choreography.roles {
+        choreography.project(role).unwrap();
+    }
+
+    let diagram = generate_sequence_diagram(&choreography);
+    for line in [
+        "Client->>Shop: Order",
+        "Client->>Shop: Payment",
+        "Shop->>Client: Receipt",
+        "Shop->>Bank: Payment",
+        "Bank->>Shop: Receipt",
+    ] {
+        assert!(diagram.contains(line), "missing `{line}` in\n{diagram}");
+    }
+}
+
+#[test]
+fn test_fragment_errors_point_at_the_include() {
+    let error = |replace: &str, with: &str| {
+        parse_choreography_str(&CHECKOUT.replacen(replace, with, 1))
+            .unwrap_err()
+            .to_string()
+    };
+    assert!(error("payment.choreo", "missing.choreo")
+        .contains("cannot read fragment tests/fragments/missing.choreo"));
+    assert!(error("payment.choreo", "cycle.choreo")
+        .contains("fragment tests/fragments/cycle.choreo includes itself"));
+    let unmapped = error(", Payee = Shop", "");
+    assert!(unmapped.contains("in fragment tests/fragments/payment.choreo"));
+    assert!(unmapped.contains("Undefined role 'Payee'"));
+    assert!(error("Payee = Shop", "Payer = Shop").contains("role Payer is mapped twice"));
+}
+
+#[test]
+fn test_macro_depends_on_included_fragments() {
+    let code = choreography_macro(quote! { #CHECKOUT }).to_string();
+    assert!(!code.contains("compile_error"), "{code}");
+    assert_eq!(code.matches("include_bytes !").count(), 2);
+}
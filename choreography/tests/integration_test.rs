@@ -6,17 +6,17 @@
 // These tests verify the complete pipeline from choreography construction
 // through projection and analysis.
 
-use proc_macro2::{Ident, Span};
+use proc_macro2::Ident;
 use quote::quote;
 use rumpsteak_aura_choreography::ast::{
-    Branch, Choreography, Condition, MessageType, Protocol, Role,
+    Branch, Choreography, Condition, MessageType, Protocol, Role, Span,
 };
 use rumpsteak_aura_choreography::compiler::{analyze, project};
 use std::collections::HashMap;
 
 // Helper to create identifiers
 fn ident(s: &str) -> Ident {
-    Ident::new(s, Span::call_site())
+    Ident::new(s, proc_macro2::Span::call_site())
 }
 
 // Helper to create a message type
@@ -55,10 +55,12 @@ fn test_simple_two_party_protocol() {
             annotations: HashMap::new(),
             from_annotations: HashMap::new(),
             to_annotations: HashMap::new(),
+            span: Span::default(),
         }),
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     let choreography = Choreography {
@@ -67,6 +69,11 @@ fn test_simple_two_party_protocol() {
         roles: vec![alice.clone(), bob.clone()],
         protocol,
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     // Validate the choreography
@@ -112,14 +119,17 @@ fn test_three_party_protocol() {
                 annotations: HashMap::new(),
                 from_annotations: HashMap::new(),
                 to_annotations: HashMap::new(),
+                span: Span::default(),
             }),
             annotations: HashMap::new(),
             from_annotations: HashMap::new(),
             to_annotations: HashMap::new(),
+            span: Span::default(),
         }),
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     let choreography = Choreography {
@@ -128,6 +138,11 @@ fn test_three_party_protocol() {
         roles: vec![alice.clone(), bob.clone(), carol.clone()],
         protocol,
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     assert!(choreography.validate().is_ok());
@@ -149,6 +164,7 @@ fn test_broadcast_protocol() {
         continuation: Box::new(Protocol::End),
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     let choreography = Choreography {
@@ -157,6 +173,11 @@ fn test_broadcast_protocol() {
         roles: vec![alice.clone(), bob.clone(), carol.clone()],
         protocol,
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     assert!(choreography.validate().is_ok());
@@ -178,6 +199,7 @@ fn test_choice_protocol() {
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     let reject_branch = Protocol::Send {
@@ -188,6 +210,7 @@ fn test_choice_protocol() {
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     let protocol = Protocol::Choice {
@@ -196,15 +219,20 @@ fn test_choice_protocol() {
             Branch {
                 label: ident("accept"),
                 guard: None,
+                probability: None,
                 protocol: accept_branch,
+                span: Span::default(),
             },
             Branch {
                 label: ident("reject"),
                 guard: None,
+                probability: None,
                 protocol: reject_branch,
+                span: Span::default(),
             },
         ],
         annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     let choreography = Choreography {
@@ -213,6 +241,11 @@ fn test_choice_protocol() {
         roles: vec![alice.clone(), bob.clone()],
         protocol,
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     assert!(choreography.validate().is_ok());
@@ -233,11 +266,13 @@ fn test_loop_protocol() {
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     let protocol = Protocol::Loop {
         condition: Some(Condition::Count(5)),
         body: Box::new(body),
+        span: Span::default(),
     };
 
     let choreography = Choreography {
@@ -246,6 +281,11 @@ fn test_loop_protocol() {
         roles: vec![alice.clone(), bob.clone()],
         protocol,
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     assert!(choreography.validate().is_ok());
@@ -267,6 +307,7 @@ fn test_parallel_protocol() {
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     let branch2 = Protocol::Send {
@@ -277,10 +318,12 @@ fn test_parallel_protocol() {
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     let protocol = Protocol::Parallel {
         protocols: vec![branch1, branch2],
+        span: Span::default(),
     };
 
     let choreography = Choreography {
@@ -289,6 +332,11 @@ fn test_parallel_protocol() {
         roles: vec![alice.clone(), bob.clone(), carol.clone()],
         protocol,
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     assert!(choreography.validate().is_ok());
@@ -312,11 +360,13 @@ fn test_recursive_protocol() {
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     let protocol = Protocol::Rec {
         label: var_label,
         body: Box::new(body),
+        span: Span::default(),
     };
 
     let choreography = Choreography {
@@ -325,6 +375,11 @@ fn test_recursive_protocol() {
         roles: vec![alice.clone(), bob.clone()],
         protocol,
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     assert!(choreography.validate().is_ok());
@@ -345,6 +400,7 @@ fn test_complex_negotiation() {
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     let counter = Protocol::Send {
@@ -355,6 +411,7 @@ fn test_complex_negotiation() {
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     let choice = Protocol::Choice {
@@ -363,15 +420,20 @@ fn test_complex_negotiation() {
             Branch {
                 label: ident("accept"),
                 guard: None,
+                probability: None,
                 protocol: accept,
+                span: Span::default(),
             },
             Branch {
                 label: ident("counter"),
                 guard: None,
+                probability: None,
                 protocol: counter,
+                span: Span::default(),
             },
         ],
         annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     let protocol = Protocol::Send {
@@ -382,6 +444,7 @@ fn test_complex_negotiation() {
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     let choreography = Choreography {
@@ -390,6 +453,11 @@ fn test_complex_negotiation() {
         roles: vec![buyer.clone(), seller.clone()],
         protocol,
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     assert!(choreography.validate().is_ok());
@@ -413,6 +481,7 @@ fn test_invalid_choreography_missing_role() {
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     let choreography = Choreography {
@@ -421,6 +490,11 @@ fn test_invalid_choreography_missing_role() {
         roles: vec![alice, bob], // Carol missing!
         protocol,
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     // Should fail validation
@@ -443,6 +517,7 @@ fn test_projection_consistency() {
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     let choreography = Choreography {
@@ -451,6 +526,11 @@ fn test_projection_consistency() {
         roles: vec![alice.clone(), bob.clone()],
         protocol,
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     let alice_local = project(&choreography, &alice).expect("Alice projection");
@@ -488,10 +568,12 @@ fn test_analysis_detects_roles() {
             annotations: HashMap::new(),
             from_annotations: HashMap::new(),
             to_annotations: HashMap::new(),
+            span: Span::default(),
         }),
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     let choreography = Choreography {
@@ -500,6 +582,11 @@ fn test_analysis_detects_roles() {
         roles: vec![alice, bob, carol],
         protocol,
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     let analysis = analyze(&choreography);
@@ -521,6 +608,7 @@ fn test_message_with_payload() {
         annotations: HashMap::new(),
         from_annotations: HashMap::new(),
         to_annotations: HashMap::new(),
+        span: Span::default(),
     };
 
     let choreography = Choreography {
@@ -529,6 +617,11 @@ fn test_message_with_payload() {
         roles: vec![alice.clone(), bob.clone()],
         protocol,
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     assert!(choreography.validate().is_ok());
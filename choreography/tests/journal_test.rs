@@ -0,0 +1,180 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for the session journal
+
+mod common;
+
+use common::{endpoints, roles};
+use rumpsteak_aura_choreography::effects::handlers::rumpsteak::{
+    RumpsteakEndpoint, RumpsteakHandler,
+};
+use rumpsteak_aura_choreography::runtime::journal::{
+    verify_chain, FileSink, Journal, JournalPoint, MemorySink,
+};
+use rumpsteak_aura_choreography::runtime::monitor::ActionKind;
+use rumpsteak_aura_choreography::{ChoreoHandler, ChoreographyError, Journaled};
+use serde::{Deserialize, Serialize};
+
+roles!(TestRole { Payer, Payee }: Pay);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct Pay(u64);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct Receipt;
+
+// What generated code emits for `[@journal_facts = "payment"] Payer -> Payee: Pay`
+const PAYER_POINTS: &[JournalPoint] = &[JournalPoint {
+    action: ActionKind::Send,
+    peer: "Payee",
+    label: "Pay",
+    facts: "payment",
+    retain_ms: None,
+}];
+
+const PAYEE_POINTS: &[JournalPoint] = &[JournalPoint {
+    action: ActionKind::Receive,
+    peer: "Payer",
+    label: "Pay",
+    facts: "payment",
+    retain_ms: None,
+}];
+
+type Handler = Journaled<RumpsteakHandler<TestRole, Pay>>;
+
+fn setup(
+    payer_journal: Journal,
+    payee_journal: Journal,
+) -> (
+    (Handler, RumpsteakEndpoint<TestRole>),
+    (Handler, RumpsteakEndpoint<TestRole>),
+) {
+    let (payer_ep, payee_ep) = endpoints(TestRole::Payer, TestRole::Payee);
+
+    let payer = Journaled::new(
+        RumpsteakHandler::new(),
+        TestRole::Payer,
+        payer_journal,
+        PAYER_POINTS,
+    );
+    let payee = Journaled::new(
+        RumpsteakHandler::new(),
+        TestRole::Payee,
+        payee_journal,
+        PAYEE_POINTS,
+    );
+    ((payer, payer_ep), (payee, payee_ep))
+}
+
+#[tokio::test]
+async fn test_only_annotated_steps_are_journaled() {
+    let payer_sink = MemorySink::new();
+    let payee_sink = MemorySink::new();
+    let ((mut payer, mut payer_ep), (mut payee, mut payee_ep)) = setup(
+        Journal::open(payer_sink.clone(), "s-1").unwrap(),
+        Journal::open(payee_sink.clone(), "s-1").unwrap(),
+    );
+
+    payer
+        .send_labelled(&mut payer_ep, TestRole::Payee, "Pay", &Pay(10))
+        .await
+        .unwrap();
+    let _: Pay = payee
+        .recv_labelled(&mut payee_ep, TestRole::Payer, "Pay")
+        .await
+        .unwrap();
+    payee
+        .send_labelled(&mut payee_ep, TestRole::Payer, "Receipt", &Receipt)
+        .await
+        .unwrap();
+    let _: Receipt = payer
+        .recv_labelled(&mut payer_ep, TestRole::Payee, "Receipt")
+        .await
+        .unwrap();
+
+    let payer_records = payer_sink.records();
+    assert_eq!(payer_records.len(), 1);
+    assert_eq!(payer_records[0].role, "Payer");
+    assert_eq!(payer_records[0].action, "send");
+    assert_eq!(payer_records[0].facts, "payment");
+
+    let payee_records = payee_sink.records();
+    assert_eq!(payee_records.len(), 1);
+    assert_eq!(payee_records[0].action, "receive");
+    assert!(verify_chain(&payee_records).is_ok());
+}
+
+#[tokio::test]
+async fn test_unnamed_journaled_steps_are_refused() {
+    let payer_sink = MemorySink::new();
+    let ((mut payer, mut payer_ep), _) = setup(
+        Journal::open(payer_sink.clone(), "s-1").unwrap(),
+        Journal::open(MemorySink::new(), "s-1").unwrap(),
+    );
+
+    // A send to the payee could be the journaled payment, so it fails unrecorded
+    let err = payer
+        .send(&mut payer_ep, TestRole::Payee, &Pay(10))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ChoreographyError::Journal(_)));
+    assert!(payer_sink.records().is_empty());
+}
+
+#[tokio::test]
+async fn test_file_journal_survives_reopen() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("payer.journal");
+
+    for _ in 0..2 {
+        let ((mut payer, mut payer_ep), (mut payee, mut payee_ep)) = setup(
+            Journal::open(FileSink::open(&path).unwrap(), "s-1").unwrap(),
+            Journal::open(MemorySink::new(), "s-1").unwrap(),
+        );
+        payer
+            .send_labelled(&mut payer_ep, TestRole::Payee, "Pay", &Pay(1))
+            .await
+            .unwrap();
+        let _: Pay = payee
+            .recv_labelled(&mut payee_ep, TestRole::Payer, "Pay")
+            .await
+            .unwrap();
+    }
+
+    let records = FileSink::read(&path).unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[1].seq, 1);
+    assert!(verify_chain(&records).is_ok());
+}
+
+#[tokio::test]
+async fn test_retained_payload_is_logged_and_redacted() {
+    // `[@journal_facts = "payment", @retain = "0s"] Payer -> Payee: Pay`
+    const RETAINED: &[JournalPoint] = &[JournalPoint {
+        retain_ms: Some(0),
+        ..PAYER_POINTS[0]
+    }];
+
+    let sink = MemorySink::new();
+    let (mut payer_ep, _payee_ep) = endpoints(TestRole::Payer, TestRole::Payee);
+    let journal = Journal::open(sink.clone(), "s-1").unwrap();
+    let mut payer = Journaled::new(
+        RumpsteakHandler::<TestRole, Pay>::new(),
+        TestRole::Payer,
+        journal.clone(),
+        RETAINED,
+    );
+
+    payer
+        .send_labelled(&mut payer_ep, TestRole::Payee, "Pay", &Pay(42))
+        .await
+        .unwrap();
+    let retained = sink.records()[0].retained.clone().unwrap();
+    assert_eq!(retained.payload.as_deref(), Some("42"));
+
+    assert_eq!(journal.redact_expired().unwrap(), 1);
+    let records = sink.records();
+    assert_eq!(records[0].retained.as_ref().unwrap().payload, None);
+    assert!(verify_chain(&records).is_ok());
+}
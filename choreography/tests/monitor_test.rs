@@ -0,0 +1,118 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Tests for runtime conformance monitor generation
+
+use rumpsteak_aura_choreography::ast::LocalType;
+use rumpsteak_aura_choreography::compiler::{
+    generate_choreography_code_with_monitors, generate_monitors, parse_choreography_str, project,
+};
+use rumpsteak_aura_choreography::{
+    parse_and_generate_with_extensions, parse_and_generate_with_jobs, Choreography,
+    ExtensionRegistry, Role,
+};
+
+fn project_all(choreo: &Choreography) -> Vec<(Role, LocalType)> {
+    choreo
+        .roles
+        .iter()
+        .map(|role| (role.clone(), project(choreo, role).unwrap()))
+        .collect()
+}
+
+#[test]
+fn test_monitor_per_role() {
+    let choreo = parse_choreography_str(
+        r"
+choreography Negotiation {
+    roles: Buyer, Seller
+    Buyer -> Seller: Offer
+    choice Seller {
+        accept: {
+            Seller -> Buyer: Deal
+        }
+        reject: {
+            Seller -> Buyer: NoDeal
+        }
+    }
+}
+",
+    )
+    .unwrap();
+
+    let code = generate_monitors(&project_all(&choreo)).to_string();
+    assert!(code.contains("pub struct BuyerMonitor"));
+    assert!(code.contains("pub struct SellerMonitor"));
+    assert!(code.contains("role : \"Buyer\""));
+    assert!(code.contains("ActionKind :: Branch"));
+    assert!(code.contains("label : \"accept\""));
+    assert!(code.contains("label : \"NoDeal\""));
+}
+
+#[test]
+fn test_loops_become_cycles() {
+    let choreo = parse_choreography_str(
+        r"
+choreography Stream {
+    roles: Producer, Consumer
+    loop (decides: Producer) {
+        Producer -> Consumer: Item
+    }
+}
+",
+    )
+    .unwrap();
+
+    let code = generate_monitors(&project_all(&choreo)).to_string();
+    // The loop state has an epsilon edge back to itself via the body and on to
+    // the accepting state.
+    assert!(code.contains("epsilons : & [(2usize , 0usize) , (0usize , 1usize)]"));
+}
+
+#[test]
+fn test_monitors_module_alongside_endpoints() {
+    let choreo = parse_choreography_str(
+        r"
+choreography Ping {
+    roles: A, B
+    A -> B: Ping
+    B -> A: Pong
+}
+",
+    )
+    .unwrap();
+
+    let code = generate_choreography_code_with_monitors(&choreo, &project_all(&choreo)).to_string();
+    assert!(code.contains("pub mod monitors"));
+    assert!(code.contains("pub struct AMonitor"));
+    assert!(code.contains("ConformanceMonitor"));
+}
+
+#[test]
+fn test_monitors_from_annotation() {
+    let ping = r"
+choreography Ping {
+    roles: A, B
+    A -> B: Ping
+    B -> A: Pong
+}
+";
+    let registry = ExtensionRegistry::new();
+
+    let monitored = format!("@codegen(monitor){ping}");
+    let code = parse_and_generate_with_extensions(&monitored, &registry)
+        .unwrap()
+        .to_string();
+    assert!(code.contains("pub mod monitors"));
+    assert!(code.contains("pub struct AMonitor"));
+    assert!(code.contains("pub struct BMonitor"));
+    assert_eq!(
+        parse_and_generate_with_jobs(&monitored, &registry, 2).unwrap(),
+        code
+    );
+
+    let code = parse_and_generate_with_extensions(ping, &registry)
+        .unwrap()
+        .to_string();
+    assert!(!code.contains("pub mod monitors"));
+}
@@ -0,0 +1,92 @@
+#![allow(clippy::unwrap_used)]
+
+// Nested choreographies and the private roles they hide
+
+use rumpsteak_aura_choreography::compiler::{generate_sequence_diagram, parse_choreography_str};
+
+const SHOP: &str = r"
+choreography Shop {
+    roles: Client, Server
+    Client -> Server: Request
+    choreography Lookup {
+        private roles: Cache;
+        Server -> Cache: Get
+        Cache -> Server: Hit
+    }
+    Server -> Client: Response
+}
+";
+
+#[test]
+fn test_private_roles_are_hidden_from_the_outer_protocol() {
+    let choreography = parse_choreography_str(SHOP).unwrap();
+    choreography.validate().unwrap();
+    let names: Vec<String> = choreography
+        .roles
+        .iter()
+        .map(|r| r.name.to_string())
+        .collect();
+    assert_eq!(names, ["Client", "Server"]);
+    for role in &choreography.roles {
+        let local = format!("{:?}", choreography.project(role).unwrap());
+        assert!(!local.contains("Cache"), "{role:?} sees Cache: {local}");
+    }
+    let diagram = generate_sequence_diagram(&choreography);
+    assert!(diagram.contains("Client->>Server: Request"), "{diagram}");
+    assert!(diagram.contains("Server->>Client: Response"), "{diagram}");
+    assert!(!diagram.contains("Cache"), "{diagram}");
+
+    let [lookup] = &choreography.scopes[..] else {
+        panic!("expected one nested choreography");
+    };
+    assert_eq!(lookup.name, "Lookup");
+    let names: Vec<String> = lookup.roles.iter().map(|r| r.name.to_string()).collect();
+    assert_eq!(names, ["Server", "Cache"]);
+    lookup.validate().unwrap();
+    for role in &lookup.roles {
+        lookup.project(role).unwrap();
+    }
+    let diagram = generate_sequence_diagram(lookup);
+    assert!(diagram.contains("Server->>Cache: Get"), "{diagram}");
+    assert!(!diagram.contains("Client"), "{diagram}");
+}
+
+#[test]
+fn test_private_roles_are_scoped_to_their_choreography() {
+    let error = |replace: &str, with: &str| {
+        parse_choreography_str(&SHOP.replacen(replace, with, 1))
+            .unwrap_err()
+            .to_string()
+    };
+    assert!(error("Server -> Client: Response", "Server -> Cache: Put")
+        .contains("Undefined role 'Cache'"));
+    assert!(error("private roles: Cache", "private roles: Client").contains("Client"));
+    assert!(error(
+        "Cache -> Server: Hit",
+        "choice Cache { hit: { Cache -> Server: Hit } miss: { Server -> Client: Wait } }"
+    )
+    .contains("choice by private role Cache decides what roles outside its choreography do"));
+
+    // A choice only the nested choreography sees is left out
+    let choreography = parse_choreography_str(&SHOP.replacen(
+        "Cache -> Server: Hit",
+        "choice Cache { hit: { Cache -> Server: Hit } miss: { Cache -> Server: Miss } }",
+        1,
+    ))
+    .unwrap();
+    for role in &choreography.roles {
+        choreography.project(role).unwrap();
+    }
+}
+
+#[test]
+fn test_composed_parts_may_reuse_private_roles() {
+    let search = parse_choreography_str(&SHOP.replace("Shop", "Search")).unwrap();
+    let order = parse_choreography_str(SHOP).unwrap().then(search).unwrap();
+    let names: Vec<String> = order.roles.iter().map(|r| r.name.to_string()).collect();
+    assert_eq!(names, ["Client", "Server"]);
+    assert_eq!(order.scopes.len(), 2);
+    for role in &order.roles {
+        order.project(role).unwrap();
+    }
+}
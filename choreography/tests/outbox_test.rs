@@ -0,0 +1,144 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for the persistent outbox
+
+mod common;
+
+use common::{endpoints, roles};
+use rumpsteak_aura_choreography::effects::handlers::rumpsteak::RumpsteakHandler;
+use rumpsteak_aura_choreography::runtime::outbox::{
+    MemoryOutbox, OutboxError, OutboxMessage, OutboxStore,
+};
+use rumpsteak_aura_choreography::{ChoreoHandler, ChoreographyError, Outboxed};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+roles!(TestRole { Alice, Bob }: TestMessage);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct TestMessage(u32);
+
+type Inner = RumpsteakHandler<TestRole, TestMessage>;
+
+/// Memory store whose acknowledgements can be made to fail, standing in for
+/// a process that stops between transmitting a message and acknowledging it
+#[derive(Clone, Default)]
+struct CrashingStore {
+    inner: MemoryOutbox,
+    crash: Arc<AtomicBool>,
+}
+
+impl OutboxStore for CrashingStore {
+    fn persist(&mut self, message: &OutboxMessage) -> Result<(), OutboxError> {
+        self.inner.persist(message)
+    }
+
+    fn acknowledge(&mut self, session_id: &str, role: &str, seq: u64) -> Result<(), OutboxError> {
+        if self.crash.load(Ordering::SeqCst) {
+            return Err(OutboxError::Store("crashed".into()));
+        }
+        self.inner.acknowledge(session_id, role, seq)
+    }
+
+    fn pending(&mut self, session_id: &str, role: &str) -> Result<Vec<OutboxMessage>, OutboxError> {
+        self.inner.pending(session_id, role)
+    }
+
+    fn last_seq(&mut self, session_id: &str, role: &str) -> Result<Option<u64>, OutboxError> {
+        self.inner.last_seq(session_id, role)
+    }
+
+    fn mark_delivered(
+        &mut self,
+        session_id: &str,
+        role: &str,
+        peer: &str,
+        seq: u64,
+    ) -> Result<(), OutboxError> {
+        self.inner.mark_delivered(session_id, role, peer, seq)
+    }
+
+    fn last_delivered(
+        &mut self,
+        session_id: &str,
+        role: &str,
+        peer: &str,
+    ) -> Result<Option<u64>, OutboxError> {
+        self.inner.last_delivered(session_id, role, peer)
+    }
+}
+
+#[tokio::test]
+async fn test_sends_are_persisted_and_acknowledged() {
+    let (mut alice_ep, mut bob_ep) = endpoints(TestRole::Alice, TestRole::Bob);
+
+    let mut store = MemoryOutbox::new();
+    let mut alice = Outboxed::new(Inner::new(), TestRole::Alice, "s-1", store.clone()).unwrap();
+    let mut bob = Outboxed::new(Inner::new(), TestRole::Bob, "s-1", store.clone()).unwrap();
+
+    alice
+        .send(&mut alice_ep, TestRole::Bob, &TestMessage(1))
+        .await
+        .unwrap();
+    assert_eq!(store.last_seq("s-1", "Alice").unwrap(), Some(1));
+    assert!(store.pending("s-1", "Alice").unwrap().is_empty());
+
+    let received: TestMessage = bob.recv(&mut bob_ep, TestRole::Alice).await.unwrap();
+    assert_eq!(received, TestMessage(1));
+    assert_eq!(
+        store.last_delivered("s-1", "Bob", "Alice").unwrap(),
+        Some(1)
+    );
+}
+
+#[tokio::test]
+async fn test_restart_delivers_exactly_once() {
+    let (mut alice_ep, mut bob_ep) = endpoints(TestRole::Alice, TestRole::Bob);
+
+    let alice_store = CrashingStore::default();
+    let bob_store = MemoryOutbox::new();
+    let mut alice =
+        Outboxed::new(Inner::new(), TestRole::Alice, "s-1", alice_store.clone()).unwrap();
+    let mut bob = Outboxed::new(Inner::new(), TestRole::Bob, "s-1", bob_store.clone()).unwrap();
+
+    // Alice stops after transmitting message 2 but before acknowledging it
+    alice
+        .send(&mut alice_ep, TestRole::Bob, &TestMessage(1))
+        .await
+        .unwrap();
+    alice_store.crash.store(true, Ordering::SeqCst);
+    let error = alice
+        .send(&mut alice_ep, TestRole::Bob, &TestMessage(2))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ChoreographyError::Outbox(_)));
+
+    // Bob takes both messages, then restarts from its store
+    let first: TestMessage = bob.recv(&mut bob_ep, TestRole::Alice).await.unwrap();
+    let second: TestMessage = bob.recv(&mut bob_ep, TestRole::Alice).await.unwrap();
+    assert_eq!((first, second), (TestMessage(1), TestMessage(2)));
+    drop(bob);
+    let mut bob = Outboxed::new(Inner::new(), TestRole::Bob, "s-1", bob_store).unwrap();
+
+    // Restarted Alice retransmits message 2 and carries on with 3
+    alice_store.crash.store(false, Ordering::SeqCst);
+    let mut alice = Outboxed::new(Inner::new(), TestRole::Alice, "s-1", alice_store).unwrap();
+    assert_eq!(
+        alice.flush(&mut alice_ep, &[TestRole::Bob]).await.unwrap(),
+        1
+    );
+    assert_eq!(
+        alice.flush(&mut alice_ep, &[TestRole::Bob]).await.unwrap(),
+        0
+    );
+    alice
+        .send(&mut alice_ep, TestRole::Bob, &TestMessage(3))
+        .await
+        .unwrap();
+
+    // Bob drops the retransmission of 2
+    let third: TestMessage = bob.recv(&mut bob_ep, TestRole::Alice).await.unwrap();
+    assert_eq!(third, TestMessage(3));
+}
@@ -1294,3 +1294,423 @@ choreography ParameterizedLoop {
         result.err()
     );
 }
+
+#[test]
+fn test_parse_external_http_role() {
+    let input = r#"
+choreography Payment {
+    roles: Shop, Api external http
+
+    Shop -> Api: Charge
+    [@route = "GET /status"]
+    Shop -> Api: Status
+    Api -> Shop: Receipt
+}
+"#;
+
+    let choreo = parse_choreography_str(input).unwrap();
+    let api = &choreo.roles[1];
+    assert_eq!(choreo.external_transport(&api.name), Some("http"));
+    assert_eq!(choreo.external_transport(&choreo.roles[0].name), None);
+    let external: Vec<_> = choreo
+        .external_roles()
+        .map(|r| r.name.to_string())
+        .collect();
+    assert_eq!(external, ["Api"]);
+}
+
+#[test]
+fn test_parse_external_role_errors() {
+    let unknown_transport = r"
+choreography Payment {
+    roles: Shop, Api external grpc
+    Shop -> Api: Charge
+}
+";
+    let err = parse_choreography_str(unknown_transport).unwrap_err();
+    assert!(err.to_string().contains("expected `http`"), "{err}");
+
+    let family = r"
+choreography Payment {
+    roles: Shop, Api[3] external http
+    Shop -> Api[0]: Charge
+}
+";
+    assert!(parse_choreography_str(family).is_err());
+
+    let internal_route = r#"
+choreography Payment {
+    roles: Shop, Bank, Api external http
+    [@route = "/charge"]
+    Shop -> Bank: Charge
+}
+"#;
+    let err = parse_choreography_str(internal_route).unwrap_err();
+    assert!(err.to_string().contains("external"), "{err}");
+
+    let bad_route = r#"
+choreography Payment {
+    roles: Shop, Api external http
+    [@route = "charge"]
+    Shop -> Api: Charge
+}
+"#;
+    assert!(parse_choreography_str(bad_route).is_err());
+}
+
+#[test]
+fn test_parse_branch_probabilities() {
+    use rumpsteak_aura_choreography::ast::{Branch, Protocol};
+
+    let input = r"
+choreography Checkout {
+    roles: Client, Server
+    choice Client {
+        buy [prob = 0.6] when (balance > price): {
+            Client -> Server: Purchase
+        }
+        save: {
+            Client -> Server: Save
+        }
+        cancel: {
+            Client -> Server: Cancel
+        }
+    }
+}
+";
+    let choreography = parse_choreography_str(input).unwrap();
+    let Protocol::Choice { branches, .. } = &choreography.protocol else {
+        panic!("expected a choice");
+    };
+    assert_eq!(branches[0].probability, Some(0.6));
+    assert!(branches[0].guard.is_some());
+    assert_eq!(branches[1].probability, None);
+    let probabilities = Branch::probabilities(branches);
+    assert!((probabilities[1] - 0.2).abs() < 1e-9);
+    assert!((probabilities[2] - 0.2).abs() < 1e-9);
+
+    for bad in ["[prob = 1.5]", "[prob = 0.5]"] {
+        let input = format!(
+            "choreography Bad {{
+    roles: A, B
+    choice A {{
+        x {bad}: {{ A -> B: X }}
+        y [prob = 0.7]: {{ A -> B: Y }}
+    }}
+}}"
+        );
+        let err = parse_choreography_str(&input).unwrap_err();
+        assert!(matches!(err, ParseError::InvalidAnnotation { .. }), "{err}");
+    }
+}
+
+#[test]
+fn test_parse_confidential_payload_fields() {
+    use rumpsteak_aura_choreography::ast::{Protocol, ValidationError};
+
+    let input = r#"
+choreography Payment {
+    roles: Shop, Bank, Auditor
+    Shop -> Bank: Charge(card: String [confidential to = "Bank"], amount: u64)
+    [@derived_from = "Charge.card"]
+    Bank -> Auditor: Report(entry: String)
+}
+"#;
+    let choreography = parse_choreography_str(input).unwrap();
+    let Protocol::Send {
+        message,
+        annotations,
+        ..
+    } = &choreography.protocol
+    else {
+        panic!("expected a send");
+    };
+    assert_eq!(
+        message.payload.as_ref().unwrap().to_string(),
+        "card : String , amount : u64"
+    );
+    assert_eq!(
+        annotations.get("confidential.card").map(String::as_str),
+        Some("Bank")
+    );
+
+    let errors = choreography.validate_all();
+    assert!(matches!(
+        &errors[..],
+        [ValidationError::InformationLeak { field, role, path }]
+            if field == "Charge.card" && role == "Auditor" && path == "Charge.card -> Report"
+    ));
+    assert_eq!(errors[0].code(), "RA0108");
+
+    for (bad, code) in [
+        (r#"card: String [confidential to = "Mallory"]"#, "RA0003"),
+        (r#"card: String [confidential for "Bank"]"#, "RA0011"),
+    ] {
+        let input = format!(
+            "choreography Bad {{
+    roles: Shop, Bank
+    Shop -> Bank: Charge({bad})
+}}"
+        );
+        let err = parse_choreography_str(&input).unwrap_err();
+        assert_eq!(err.code(), code, "{err}");
+    }
+}
+
+#[test]
+fn test_unused_and_orphan_messages() {
+    use rumpsteak_aura_choreography::ast::ValidationError;
+    use rumpsteak_aura_choreography::compiler::Diagnostic;
+
+    let input = r"
+choreography Shipping {
+    roles: Shop, Carrier, Warehouse
+    messages: Order, Pickup, Refund
+    Shop -> Carrier: Order
+    Carrier -> Warehouse: Pickup
+}
+";
+    let choreography = parse_choreography_str(input).unwrap();
+    assert_eq!(choreography.messages.len(), 3);
+    let errors = choreography.validate_all();
+    assert!(
+        matches!(&errors[..], [ValidationError::UnusedMessage(message)] if message == "Refund")
+    );
+    assert_eq!(errors[0].code(), "RA0110");
+    let diagnostic = Diagnostic::from_validation_error(&errors[0], &choreography);
+    let span = diagnostic.span.unwrap();
+    assert_eq!((span.line, span.column), (4, 30));
+
+    let err = parse_choreography_str(
+        "choreography Twice {\n    roles: A, B\n    messages: Ping, Ping\n    A -> B: Ping\n}",
+    )
+    .unwrap_err();
+    assert!(
+        err.to_string().contains("message Ping is declared twice"),
+        "{err}"
+    );
+
+    // The Warehouse's part of the first thread hides its part of the second
+    let input = r"
+choreography Dispatch {
+    roles: Shop, Carrier, Warehouse
+    parallel {
+        choice Shop {
+            express: {
+                Shop -> Warehouse: Express
+            }
+            standard: {
+                Shop -> Warehouse: Standard
+            }
+        }
+    |
+        Carrier -> Warehouse: Manifest
+    }
+}
+";
+    let choreography = parse_choreography_str(input).unwrap();
+    let errors = choreography.validate_all();
+    assert!(matches!(
+        &errors[..],
+        [ValidationError::OrphanMessage { message, from, to }]
+            if message == "Manifest" && from == "Carrier" && to == "Warehouse"
+    ));
+    assert_eq!(errors[0].code(), "RA0111");
+    let diagnostic = Diagnostic::from_validation_error(&errors[0], &choreography);
+    assert_eq!(diagnostic.span.unwrap().line, 14);
+}
+
+#[test]
+fn test_parse_host_expressions() {
+    use rumpsteak_aura_choreography::ast::{host_expr, Protocol, DEFAULT_MESSAGE};
+
+    let input = r#"
+choreography Fetch {
+    roles: Client, Server
+    [@timeout = #{ config::REQUEST_TIMEOUT }, @default = #{ Request(Settings { page: 1 }) }]
+    Client -> Server: Request
+    choice Server {
+        found when #{ cache.contains(&key) }: { Server -> Client: Hit }
+        missing: { Server -> Client: Miss }
+    }
+}
+"#;
+    let choreography = parse_choreography_str(input).unwrap();
+    let Protocol::Send {
+        annotations,
+        continuation,
+        ..
+    } = &choreography.protocol
+    else {
+        panic!("expected a send");
+    };
+    assert_eq!(
+        host_expr(&annotations["timeout"]),
+        Some("config::REQUEST_TIMEOUT")
+    );
+    assert_eq!(
+        host_expr(&annotations[DEFAULT_MESSAGE]),
+        Some("Request(Settings { page: 1 })")
+    );
+    let Protocol::Choice { branches, .. } = continuation.as_ref() else {
+        panic!("expected a choice");
+    };
+    assert_eq!(
+        branches[0].guard.as_ref().unwrap().to_string(),
+        "cache . contains (& key)"
+    );
+
+    let invalid = input.replace("config::REQUEST_TIMEOUT", "config::");
+    let err = parse_choreography_str(&invalid).unwrap_err();
+    assert!(err.to_string().contains("Invalid host expression"), "{err}");
+}
+
+#[test]
+fn test_parse_role_state() {
+    let input = r"
+choreography Voting {
+    roles: Coordinator, Voter
+    state at Coordinator { round: u32, commitments: Vec<Commitment>, }
+    state at Voter {}
+    Coordinator -> Voter: Proposal
+}
+";
+    let choreography = parse_choreography_str(input).unwrap();
+    assert_eq!(choreography.state.len(), 2);
+    let coordinator = choreography
+        .role_state(&choreography.roles[0].name)
+        .unwrap();
+    let fields: Vec<_> = coordinator
+        .fields
+        .iter()
+        .map(|field| format!("{}: {}", field.name, field.ty))
+        .collect();
+    assert_eq!(fields, ["round: u32", "commitments: Vec < Commitment >"]);
+    assert_eq!(coordinator.struct_name(), "CoordinatorState");
+    assert!(choreography.state[1].fields.is_empty());
+
+    let undeclared = input.replace("state at Voter", "state at Auditor");
+    let err = parse_choreography_str(&undeclared).unwrap_err();
+    assert!(err.to_string().contains("Auditor"), "{err}");
+
+    let twice = input.replace("state at Voter", "state at Coordinator");
+    let err = parse_choreography_str(&twice).unwrap_err();
+    assert!(err.to_string().contains("declared twice"), "{err}");
+
+    let field_twice = input.replace("commitments:", "round:");
+    let err = parse_choreography_str(&field_twice).unwrap_err();
+    assert!(err.to_string().contains("`round`"), "{err}");
+}
+
+#[test]
+fn test_parse_while_loop() {
+    use rumpsteak_aura_choreography::ast::Protocol;
+
+    let input = r"
+choreography Gossip {
+    roles: Leader, Follower
+    while at Leader {
+        Leader -> Follower: Round
+    }
+    Leader -> Follower: Done
+}
+";
+    let choreography = parse_choreography_str(input).unwrap();
+    let Protocol::Rec { label, body, .. } = &choreography.protocol else {
+        panic!("Expected the loop to be a recursion");
+    };
+    assert!(body.is_while());
+    let Protocol::Choice { role, branches, .. } = body.as_ref() else {
+        panic!("Expected the Leader to choose");
+    };
+    assert_eq!(role.name, "Leader");
+    let labels: Vec<String> = branches.iter().map(|b| b.label.to_string()).collect();
+    assert_eq!(labels, ["Continue", "Break"]);
+
+    // Each round goes back to the decision; breaking out runs the rest
+    let Protocol::Send { continuation, .. } = &branches[0].protocol else {
+        panic!("Expected the round to send");
+    };
+    assert!(matches!(continuation.as_ref(), Protocol::Var(var) if var == label));
+    assert!(matches!(
+        &branches[1].protocol,
+        Protocol::Send { message, .. } if message.name == "Done"
+    ));
+
+    let undeclared = input.replace("while at Leader", "while at Auditor");
+    let err = parse_choreography_str(&undeclared).unwrap_err();
+    assert!(err.to_string().contains("Auditor"), "{err}");
+}
+
+#[test]
+fn test_parse_break_and_continue() {
+    use rumpsteak_aura_choreography::ast::Protocol;
+
+    let input = r"
+choreography Retry {
+    roles: Client, Server
+    rec Attempt {
+        Client -> Server: Request
+        choice Server {
+            retry: {
+                Server -> Client: Busy
+                continue Attempt;
+            }
+            done: {
+                Server -> Client: Response
+                break;
+            }
+        }
+    }
+    Client -> Server: Bye
+}
+";
+    let choreography = parse_choreography_str(input).unwrap();
+    let Protocol::Rec { label, body, .. } = &choreography.protocol else {
+        panic!("Expected a recursion");
+    };
+    let Protocol::Send { continuation, .. } = body.as_ref() else {
+        panic!("Expected the Client to send the Request");
+    };
+    let Protocol::Choice { branches, .. } = continuation.as_ref() else {
+        panic!("Expected the Server to choose");
+    };
+    let Protocol::Send { continuation, .. } = &branches[0].protocol else {
+        panic!("Expected the Server to answer Busy");
+    };
+    assert!(matches!(continuation.as_ref(), Protocol::Var(var) if var == label));
+    // Breaking out goes on with the statements after the loop
+    let Protocol::Send { continuation, .. } = &branches[1].protocol else {
+        panic!("Expected the Server to answer with the Response");
+    };
+    assert!(matches!(
+        continuation.as_ref(),
+        Protocol::Send { message, .. } if message.name == "Bye"
+    ));
+
+    let errors = [
+        (input.replace("break;", "continue;"), "never ends"),
+        (
+            input.replace("continue Attempt", "continue Other"),
+            "rec Other",
+        ),
+        (
+            input.replace("break;", "break;\n Client -> Server: Late"),
+            "never reached",
+        ),
+        (
+            input
+                .replace("rec Attempt {", "loop (count: 3) {")
+                .replace(" Attempt;", ";"),
+            "condition",
+        ),
+        (
+            input.replace("Client -> Server: Bye", "break"),
+            "not inside a loop",
+        ),
+    ];
+    for (input, message) in errors {
+        let err = parse_choreography_str(&input).unwrap_err();
+        assert!(err.to_string().contains(message), "{err}");
+    }
+}
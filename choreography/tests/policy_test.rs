@@ -0,0 +1,218 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for authorization policies
+
+mod common;
+
+use common::{endpoints, generated_guard_points, roles};
+use rumpsteak_aura_choreography::compiler::parse_choreography_str;
+use rumpsteak_aura_choreography::effects::handlers::rumpsteak::RumpsteakHandler;
+use rumpsteak_aura_choreography::runtime::guard::CapabilitySet;
+use rumpsteak_aura_choreography::runtime::monitor::ActionKind;
+use rumpsteak_aura_choreography::{ChoreoHandler, ChoreographyError, Guarded, Label};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+roles!(TestRole { Coordinator, Signer }: Signature);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct Signature(u32);
+
+#[test]
+fn test_policy_is_checked_against_the_protocol() {
+    let choreography = parse_choreography_str(
+        r#"
+choreography Signing {
+    roles: Coordinator, Signer
+    policy {
+        Coordinator may initiate;
+        Signer[*] may only respond;
+    }
+    Coordinator -> Signer: Request
+    choice Signer {
+        sign: {
+            Signer -> Coordinator: Signature
+        }
+        refuse: {
+            Signer -> Coordinator: Refusal
+        }
+    }
+}
+"#,
+    )
+    .unwrap();
+
+    let policy = choreography.policy.as_ref().unwrap();
+    assert_eq!(policy.rules.len(), 2);
+    assert!(policy.rules[1].only);
+
+    let errors = choreography.validate_all();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code(), "RA0107");
+    assert_eq!(
+        errors[0].to_string(),
+        "Role Signer would decide between sign, refuse, which needs 'initiate' but the policy does not grant it"
+    );
+}
+
+#[test]
+fn test_policy_allows_conforming_protocol() {
+    let choreography = parse_choreography_str(
+        r#"
+choreography Signing {
+    roles: Coordinator, Signer
+    policy {
+        Coordinator may initiate, respond;
+        Signer may only respond;
+    }
+    Coordinator -> Signer: Request
+    Signer -> Coordinator: Signature
+    Coordinator -> Signer: Receipt
+}
+"#,
+    )
+    .unwrap();
+
+    assert!(choreography.validate().is_ok());
+}
+
+#[test]
+fn test_policy_rules_are_checked_when_parsed() {
+    let undefined = parse_choreography_str(
+        r#"
+choreography Signing {
+    roles: Coordinator, Signer
+    policy {
+        Notary may respond;
+    }
+    Coordinator -> Signer: Request
+}
+"#,
+    )
+    .unwrap_err();
+    assert_eq!(undefined.code(), "RA0003");
+
+    let conflicting = parse_choreography_str(
+        r#"
+choreography Signing {
+    roles: Coordinator, Signer
+    policy {
+        Signer may only respond;
+        Signer may initiate;
+    }
+    Coordinator -> Signer: Request
+}
+"#,
+    )
+    .unwrap_err();
+    assert!(conflicting.to_string().contains("conflict"));
+}
+
+#[tokio::test]
+async fn test_generated_policy_guards_hold_at_run_time() {
+    let choreography = parse_choreography_str(
+        r#"
+choreography Signing {
+    roles: Coordinator, Signer
+    policy {
+        Signer may only respond;
+    }
+    Coordinator -> Signer: Request
+    Signer -> Coordinator: Signature
+}
+"#,
+    )
+    .unwrap();
+    let points = generated_guard_points(&choreography, "Signer");
+
+    let (mut signer_ep, mut coordinator_ep) = endpoints(TestRole::Signer, TestRole::Coordinator);
+    let mut coordinator = RumpsteakHandler::<TestRole, Signature>::new();
+
+    let mut signer = Guarded::new(
+        RumpsteakHandler::new(),
+        TestRole::Signer,
+        Arc::new(CapabilitySet::new()),
+        points,
+    );
+    let err = signer
+        .send_labelled(
+            &mut signer_ep,
+            TestRole::Coordinator,
+            "Signature",
+            &Signature(1),
+        )
+        .await
+        .unwrap_err();
+    match err {
+        ChoreographyError::GuardDenied(denied) => {
+            assert_eq!(denied.capability, "respond");
+            assert_eq!(denied.action, ActionKind::Send);
+        }
+        other => panic!("expected GuardDenied, got {other:?}"),
+    }
+
+    let mut signer = Guarded::new(
+        signer.into_inner(),
+        TestRole::Signer,
+        Arc::new(CapabilitySet::new().grant("Signer", "respond")),
+        points,
+    );
+    signer
+        .send_labelled(
+            &mut signer_ep,
+            TestRole::Coordinator,
+            "Signature",
+            &Signature(2),
+        )
+        .await
+        .unwrap();
+    let signature: Signature = coordinator
+        .recv(&mut coordinator_ep, TestRole::Signer)
+        .await
+        .unwrap();
+    assert_eq!(signature, Signature(2));
+}
+
+#[tokio::test]
+async fn test_generated_policy_guards_refuse_decisions_the_policy_withholds() {
+    let choreography = parse_choreography_str(
+        r#"
+choreography Signing {
+    roles: Coordinator, Signer
+    policy {
+        Signer may only respond;
+    }
+    Coordinator -> Signer: Request
+    choice Signer {
+        sign: {
+            Signer -> Coordinator: Signature
+        }
+        refuse: {
+            Signer -> Coordinator: Refusal
+        }
+    }
+}
+"#,
+    )
+    .unwrap();
+
+    let (mut signer_ep, _coordinator_ep) = endpoints(TestRole::Signer, TestRole::Coordinator);
+    let mut signer = Guarded::new(
+        RumpsteakHandler::<TestRole, Signature>::new(),
+        TestRole::Signer,
+        Arc::new(CapabilitySet::new().grant("Signer", "respond")),
+        generated_guard_points(&choreography, "Signer"),
+    );
+    let err = signer
+        .choose(&mut signer_ep, TestRole::Signer, Label("sign"))
+        .await
+        .unwrap_err();
+    match err {
+        ChoreographyError::GuardDenied(denied) => {
+            assert_eq!(denied.capability, "initiate");
+            assert_eq!(denied.action, ActionKind::Select);
+        }
+        other => panic!("expected GuardDenied, got {other:?}"),
+    }
+}
@@ -0,0 +1,147 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for extension projection hooks
+
+use quote::format_ident;
+use rumpsteak_aura_choreography::ast::{LocalType, MessageType, Protocol};
+use rumpsteak_aura_choreography::compiler::{
+    parse_choreography_str, project, project_with_extensions, ProjectionError,
+};
+use rumpsteak_aura_choreography::{ExtensionRegistry, ProjectionContext, ProjectionHook};
+
+/// Reports every message a role sends to the `Monitor` role first
+#[derive(Debug)]
+struct MonitorHook;
+
+impl ProjectionHook for MonitorHook {
+    fn hook_id(&self) -> &'static str {
+        "monitor"
+    }
+
+    fn after_project(
+        &self,
+        protocol: &Protocol,
+        context: &ProjectionContext,
+        local_type: LocalType,
+    ) -> Result<LocalType, ProjectionError> {
+        let Protocol::Send { from, .. } = protocol else {
+            return Ok(local_type);
+        };
+        if from.name != context.current_role.name {
+            return Ok(local_type);
+        }
+        let monitor = context
+            .all_roles
+            .iter()
+            .find(|r| r.name == "Monitor")
+            .expect("Monitor role");
+        Ok(LocalType::Send {
+            to: monitor.clone(),
+            message: MessageType {
+                name: format_ident!("Audit"),
+                type_annotation: None,
+                payload: None,
+            },
+            continuation: Box::new(local_type),
+        })
+    }
+}
+
+/// Cuts every projection off at the `Secret` message
+#[derive(Debug)]
+struct TruncateHook;
+
+impl ProjectionHook for TruncateHook {
+    fn hook_id(&self) -> &'static str {
+        "truncate"
+    }
+
+    fn before_project(
+        &self,
+        protocol: &Protocol,
+        _context: &ProjectionContext,
+    ) -> Result<Option<LocalType>, ProjectionError> {
+        match protocol {
+            Protocol::Send { message, .. } if message.name == "Secret" => Ok(Some(LocalType::End)),
+            _ => Ok(None),
+        }
+    }
+}
+
+const CHOREOGRAPHY: &str = r#"
+choreography Audited {
+    roles: Client, Server, Monitor
+    Client -> Server: Request
+    Server -> Client: Secret
+    Server -> Client: Response
+}
+"#;
+
+fn sends(mut local_type: &LocalType) -> Vec<String> {
+    let mut labels = Vec::new();
+    loop {
+        match local_type {
+            LocalType::Send {
+                to,
+                message,
+                continuation,
+            } => {
+                labels.push(format!("!{}:{}", to.name, message.name));
+                local_type = continuation;
+            }
+            LocalType::Receive {
+                from,
+                message,
+                continuation,
+            } => {
+                labels.push(format!("?{}:{}", from.name, message.name));
+                local_type = continuation;
+            }
+            _ => return labels,
+        }
+    }
+}
+
+#[test]
+fn test_without_hooks_projection_is_unchanged() {
+    let choreography = parse_choreography_str(CHOREOGRAPHY).unwrap();
+    let client = &choreography.roles[0];
+    assert_eq!(
+        project_with_extensions(&choreography, client, &ExtensionRegistry::new()).unwrap(),
+        project(&choreography, client).unwrap()
+    );
+}
+
+#[test]
+fn test_after_hook_wraps_plain_sends() {
+    let choreography = parse_choreography_str(CHOREOGRAPHY).unwrap();
+    let mut registry = ExtensionRegistry::new();
+    registry.register_projection_hook(MonitorHook);
+
+    let server = &choreography.roles[1];
+    let local = project_with_extensions(&choreography, server, &registry).unwrap();
+    assert_eq!(
+        sends(&local),
+        vec![
+            "?Client:Request",
+            "!Monitor:Audit",
+            "!Client:Secret",
+            "!Monitor:Audit",
+            "!Client:Response",
+        ]
+    );
+}
+
+#[test]
+fn test_before_hook_replaces_projection() {
+    let choreography = parse_choreography_str(CHOREOGRAPHY).unwrap();
+    let mut registry = ExtensionRegistry::new();
+    registry.register_projection_hook(TruncateHook);
+    registry.register_projection_hook(MonitorHook);
+
+    let client = &choreography.roles[0];
+    let local = project_with_extensions(&choreography, client, &registry).unwrap();
+    assert_eq!(sends(&local), vec!["!Monitor:Audit", "!Server:Request"]);
+    assert_eq!(registry.projection_hooks()[0].hook_id(), "truncate");
+}
@@ -7,12 +7,20 @@
 // 1. Choice branches without initial Send (local choices)
 // 2. Loop conditions preserved in projections
 // 3. Improved parallel branch merging with conflict detection
+// 4. Choices a non-participating role cannot tell apart
+// 5. Failure notifications of `or on failure` sends
+// 6. Decisions of `while at` loops
+// 7. Loops left by `break` and `continue`
+// 8. Pretty-printed projections
+// 9. Compensations of `compensate` blocks undone by a failed send
+// 10. Outcomes of `within` blocks told to every role of the block
 
 use quote::{format_ident, quote};
 use rumpsteak_aura_choreography::ast::{
-    protocol::Condition, Branch, Choreography, LocalType, MessageType, Protocol, Role,
+    protocol::Condition, Branch, Choreography, LocalType, MessageType, Protocol, Role, Span,
 };
-use rumpsteak_aura_choreography::compiler::projection::project;
+use rumpsteak_aura_choreography::compiler::parser::parse_choreography_str;
+use rumpsteak_aura_choreography::compiler::projection::{project, ProjectionError};
 use std::collections::HashMap;
 
 #[test]
@@ -31,17 +39,27 @@ fn test_local_choice_without_send() {
                 Branch {
                     label: format_ident!("option1"),
                     guard: None,
+                    probability: None,
                     protocol: Protocol::End, // No Send - local decision
+                    span: Span::default(),
                 },
                 Branch {
                     label: format_ident!("option2"),
                     guard: None,
+                    probability: None,
                     protocol: Protocol::End,
+                    span: Span::default(),
                 },
             ],
             annotations: HashMap::new(),
+            span: Span::default(),
         },
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     let projected = project(&choreo, &alice).unwrap();
@@ -83,9 +101,16 @@ fn test_loop_with_condition() {
                 annotations: HashMap::new(),
                 from_annotations: HashMap::new(),
                 to_annotations: HashMap::new(),
+                span: Span::default(),
             }),
+            span: Span::default(),
         },
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     let alice_proj = project(&choreo, &alice).unwrap();
@@ -133,6 +158,7 @@ fn test_parallel_no_conflict() {
                     annotations: HashMap::new(),
                     from_annotations: HashMap::new(),
                     to_annotations: HashMap::new(),
+                    span: Span::default(),
                 },
                 Protocol::Send {
                     from: alice.clone(),
@@ -147,10 +173,17 @@ fn test_parallel_no_conflict() {
                     annotations: HashMap::new(),
                     from_annotations: HashMap::new(),
                     to_annotations: HashMap::new(),
+                    span: Span::default(),
                 },
             ],
+            span: Span::default(),
         },
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     // Alice's projection should succeed (no conflict - different recipients)
@@ -193,6 +226,7 @@ fn test_parallel_with_conflict() {
                     annotations: HashMap::new(),
                     from_annotations: HashMap::new(),
                     to_annotations: HashMap::new(),
+                    span: Span::default(),
                 },
                 Protocol::Send {
                     from: alice.clone(),
@@ -207,10 +241,17 @@ fn test_parallel_with_conflict() {
                     annotations: HashMap::new(),
                     from_annotations: HashMap::new(),
                     to_annotations: HashMap::new(),
+                    span: Span::default(),
                 },
             ],
+            span: Span::default(),
         },
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     // Alice's projection should fail (conflict detected)
@@ -234,6 +275,7 @@ fn test_mixed_choice_communicated_vs_local() {
                 Branch {
                     label: format_ident!("yes"),
                     guard: None,
+                    probability: None,
                     protocol: Protocol::Send {
                         from: alice.clone(),
                         to: bob.clone(),
@@ -247,11 +289,14 @@ fn test_mixed_choice_communicated_vs_local() {
                         annotations: HashMap::new(),
                         from_annotations: HashMap::new(),
                         to_annotations: HashMap::new(),
+                        span: Span::default(),
                     },
+                    span: Span::default(),
                 },
                 Branch {
                     label: format_ident!("no"),
                     guard: None,
+                    probability: None,
                     protocol: Protocol::Send {
                         from: alice.clone(),
                         to: bob.clone(),
@@ -265,12 +310,20 @@ fn test_mixed_choice_communicated_vs_local() {
                         annotations: HashMap::new(),
                         from_annotations: HashMap::new(),
                         to_annotations: HashMap::new(),
+                        span: Span::default(),
                     },
+                    span: Span::default(),
                 },
             ],
             annotations: HashMap::new(),
+            span: Span::default(),
         },
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     // Alice should get Select (communicated choice)
@@ -307,8 +360,14 @@ fn test_loop_without_condition() {
         protocol: Protocol::Loop {
             condition: None,
             body: Box::new(Protocol::End),
+            span: Span::default(),
         },
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     let projected = project(&choreo, &alice).unwrap();
@@ -316,3 +375,492 @@ fn test_loop_without_condition() {
     // Since body is End and Alice doesn't participate, should project to End
     assert_eq!(projected, LocalType::End);
 }
+
+#[test]
+fn test_bystander_cannot_distinguish_branches() {
+    // Test: Carol acts in only one branch and is never told which was taken
+    let choreo = parse_choreography_str(
+        r"
+choreography Purchase {
+    roles: Alice, Bob, Carol
+    choice Alice {
+        buy: {
+            Alice -> Bob: Order
+            Bob -> Carol: Receipt
+        }
+        cancel: {
+            Alice -> Bob: Cancel
+        }
+    }
+}
+",
+    )
+    .unwrap();
+    let carol = choreo.roles[2].clone();
+
+    let error = project(&choreo, &carol).unwrap_err();
+    let ProjectionError::IndistinguishableBranches {
+        role,
+        chooser,
+        branches,
+    } = &error
+    else {
+        panic!("Expected IndistinguishableBranches, got: {error:?}");
+    };
+    assert_eq!((role.as_str(), chooser.as_str()), ("Carol", "Alice"));
+    assert_eq!(branches[0].label, "buy");
+    assert_eq!(branches[0].action, "receives Receipt from Bob");
+    assert_eq!(branches[1].action, "does nothing");
+    assert_eq!(branches[1].span.line, 9);
+
+    let explanation = error.explain().unwrap();
+    assert!(explanation.contains("in branch `cancel`, Carol does nothing"));
+    assert!(error
+        .help()
+        .unwrap()
+        .starts_with("have Alice send a message to Carol"));
+}
+
+#[test]
+fn test_bystander_distinguishes_by_first_message() {
+    // Test: Carol learns the branch from the first message Bob sends her
+    let choreo = parse_choreography_str(
+        r"
+choreography Purchase {
+    roles: Alice, Bob, Carol
+    choice Alice {
+        buy: {
+            Alice -> Bob: Order
+            Bob -> Carol: Receipt
+        }
+        cancel: {
+            Alice -> Bob: Cancel
+            Bob -> Carol: Refund
+        }
+    }
+}
+",
+    )
+    .unwrap();
+    let carol = choreo.roles[2].clone();
+    choreo.validate().unwrap();
+
+    // She follows whichever branch Bob's message names
+    assert_eq!(
+        project(&choreo, &carol).unwrap().to_pretty_string(),
+        "\
+branch from Bob {
+    buy: {
+        receive Receipt from Bob
+    }
+    cancel: {
+        receive Refund from Bob
+    }
+}
+"
+    );
+}
+
+#[test]
+fn test_failure_notifies_affected_roles() {
+    // Test: only the roles whose part depends on the outcome are told it
+    let choreo = parse_choreography_str(
+        r"
+choreography Dispatch {
+    roles: Coordinator, Worker, Backup, Auditor
+    Coordinator -> Worker: Task or on failure {
+        Coordinator -> Backup: Reassign
+    }
+    Coordinator -> Auditor: Done
+}
+",
+    )
+    .unwrap();
+    let [coordinator, worker, backup, auditor] = [0, 1, 2, 3].map(|i| choreo.roles[i].clone());
+
+    let LocalType::Send { continuation, .. } = project(&choreo, &coordinator).unwrap() else {
+        panic!("Expected the Coordinator to send the Task");
+    };
+    let LocalType::Select { to, branches } = *continuation else {
+        panic!("Expected the Coordinator to tell the Backup the outcome");
+    };
+    assert_eq!(to.name, "Backup");
+    let labels: Vec<String> = branches.iter().map(|(l, _)| l.to_string()).collect();
+    assert_eq!(labels, ["delivered", "failed"]);
+    assert!(matches!(&branches[1].1, LocalType::Send { to, .. } if to.name == "Backup"));
+
+    let LocalType::Branch { from, branches } = project(&choreo, &backup).unwrap() else {
+        panic!("Expected the Backup to be told the outcome");
+    };
+    assert_eq!(from.name, "Coordinator");
+    assert_eq!(branches[0].1, LocalType::End);
+    assert!(matches!(branches[1].1, LocalType::Receive { .. }));
+
+    // The Worker carries on as though it received the Task
+    let LocalType::Receive { continuation, .. } = project(&choreo, &worker).unwrap() else {
+        panic!("Expected the Worker to receive the Task");
+    };
+    assert_eq!(*continuation, LocalType::End);
+
+    // The Auditor does the same either way
+    assert!(matches!(
+        project(&choreo, &auditor).unwrap(),
+        LocalType::Receive { continuation, .. } if *continuation == LocalType::End
+    ));
+}
+
+#[test]
+fn test_while_loop_notifies_every_role() {
+    // Test: the deciding role tells every other role whether to go round again
+    let choreo = parse_choreography_str(
+        r"
+choreography Gossip {
+    roles: Leader, Follower, Observer
+    while at Leader {
+        Leader -> Follower: Round
+    }
+    Leader -> Follower: Done
+}
+",
+    )
+    .unwrap();
+    let [leader, follower, observer] = [0, 1, 2].map(|i| choreo.roles[i].clone());
+
+    assert_eq!(
+        choreo.project(&leader).unwrap().to_pretty_string(),
+        "\
+rec while_65 {
+    select to Follower {
+        Continue: {
+            select to Observer {
+                Continue: {
+                    send Round to Follower
+                    continue while_65
+                }
+            }
+        }
+        Break: {
+            select to Observer {
+                Break: {
+                    send Done to Follower
+                }
+            }
+        }
+    }
+}
+"
+    );
+    assert_eq!(
+        choreo.project(&follower).unwrap().to_pretty_string(),
+        "\
+rec while_65 {
+    branch from Leader {
+        Continue: {
+            receive Round from Leader
+            continue while_65
+        }
+        Break: {
+            receive Done from Leader
+        }
+    }
+}
+"
+    );
+
+    // The Observer takes no part in the loop but must still follow it
+    assert_eq!(
+        choreo.project(&observer).unwrap().to_pretty_string(),
+        "\
+rec while_65 {
+    branch from Leader {
+        Continue: {
+            continue while_65
+        }
+        Break: end
+    }
+}
+"
+    );
+}
+
+#[test]
+fn test_loop_with_break_projects_to_recursion() {
+    let choreo = parse_choreography_str(
+        r"
+choreography Poll {
+    roles: Client, Server
+    loop {
+        Client -> Server: Poll
+        choice Server {
+            pending: {
+                Server -> Client: Pending
+            }
+            ready: {
+                Server -> Client: Ready
+                break;
+            }
+        }
+    }
+    Client -> Server: Ack
+}
+",
+    )
+    .unwrap();
+    let [client, server] = [0, 1].map(|i| choreo.roles[i].clone());
+
+    assert_eq!(
+        choreo.project(&client).unwrap().to_pretty_string(),
+        "\
+rec loop_51 {
+    send Poll to Server
+    branch from Server {
+        pending: {
+            receive Pending from Server
+            continue loop_51
+        }
+        ready: {
+            receive Ready from Server
+            send Ack to Server
+        }
+    }
+}
+"
+    );
+    assert_eq!(
+        choreo.project(&server).unwrap().to_pretty_string(),
+        "\
+rec loop_51 {
+    receive Poll from Client
+    select to Client {
+        pending: {
+            continue loop_51
+        }
+        ready: {
+            receive Ack from Client
+        }
+    }
+}
+"
+    );
+}
+
+#[test]
+fn test_projection_pretty_printing() {
+    let choreo = parse_choreography_str(
+        r"
+choreography Checkout {
+    roles: Buyer, Seller
+    Buyer -> Seller: Request(Vec<String>)
+    Seller -> Buyer: Quote(u64)
+    choice Buyer {
+        accept: {
+            Buyer -> Seller: Accept
+        }
+        haggle: {
+            Buyer -> Seller: Counter(u64)
+            loop (count: 3) {
+                Seller -> Buyer: Quote(u64)
+            }
+        }
+    }
+}
+",
+    )
+    .unwrap();
+    let seller = choreo.roles[1].clone();
+    let local = choreo.project(&seller).unwrap();
+    assert_eq!(
+        local.to_pretty_string(),
+        "\
+receive Request(Vec<String>) from Buyer
+send Quote(u64) to Buyer
+branch from Buyer {
+    accept: {
+        receive Accept from Buyer
+    }
+    haggle: {
+        receive Counter(u64) from Buyer
+        loop (count: 3) {
+            send Quote(u64) to Buyer
+        }
+    }
+}
+"
+    );
+
+    let all = choreo.project_all().unwrap();
+    assert_eq!(all.len(), 2);
+    let buyer = all[0].1.to_string();
+    assert!(
+        buyer.starts_with("send Request(Vec<String>) to Seller\nreceive Quote(u64) from Seller\n")
+    );
+    assert!(buyer.contains("select to Seller {\n    accept: end\n    haggle: {\n        loop"));
+    assert_eq!(LocalType::End.to_string(), "end");
+}
+
+#[test]
+fn test_failure_runs_completed_compensations() {
+    // Test: a failed send undoes the completed `compensate` blocks, latest first
+    let choreo = parse_choreography_str(
+        r"
+choreography Trip {
+    roles: Agent, Airline, Hotel, Bank
+    compensate {
+        Agent -> Airline: BookFlight
+    } with {
+        Agent -> Airline: CancelFlight
+    }
+    compensate {
+        Agent -> Hotel: BookRoom or on failure {}
+    } with {
+        Agent -> Hotel: CancelRoom
+    }
+    Agent -> Bank: Charge or on failure {}
+    Agent -> Airline: Confirm
+}
+",
+    )
+    .unwrap();
+    choreo.validate().unwrap();
+    let [agent, airline, hotel, _] = [0, 1, 2, 3].map(|i| choreo.roles[i].clone());
+
+    // Once the room is booked, a failed charge cancels it and then the flight
+    let agent = project(&choreo, &agent).unwrap().to_pretty_string();
+    assert!(agent.contains(
+        "\
+                            failed: {
+                                send CancelRoom to Hotel
+                                send CancelFlight to Airline
+                            }"
+    ));
+
+    // The flight is confirmed only if every step succeeded
+    assert_eq!(
+        project(&choreo, &airline).unwrap().to_pretty_string(),
+        "\
+receive BookFlight from Agent
+branch from Agent {
+    delivered: {
+        branch from Agent {
+            delivered: {
+                receive Confirm from Agent
+            }
+            failed: {
+                receive CancelFlight from Agent
+            }
+        }
+    }
+    failed: {
+        receive CancelFlight from Agent
+    }
+}
+"
+    );
+
+    // The room is not cancelled when its own booking fails
+    assert_eq!(
+        project(&choreo, &hotel).unwrap().to_pretty_string(),
+        "\
+receive BookRoom from Agent
+branch from Agent {
+    delivered: end
+    failed: {
+        receive CancelRoom from Agent
+    }
+}
+"
+    );
+
+    // A compensation runs outside the loops around it
+    let error = parse_choreography_str(
+        r"
+choreography Retry {
+    roles: A, B
+    loop {
+        compensate { A -> B: Hold } with { A -> B: Release break }
+        A -> B: Commit or on failure {}
+    }
+}
+",
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains("a compensation"));
+}
+
+#[test]
+fn test_within_block_outcome_reaches_its_roles() {
+    // Test: the timekeeper tells every role of the block whether it ran in time
+    let choreo = parse_choreography_str(
+        r"
+choreography Lookup {
+    roles: Client, Server, Cache
+    within 2s {
+        Client -> Server: Query
+        Server -> Cache: Fetch
+        Cache -> Server: Hit
+        Server -> Client: Answer
+    } or on deadline {
+        Client -> Server: Cancel
+    }
+    Client -> Server: Done
+}
+",
+    )
+    .unwrap();
+    choreo.validate().unwrap();
+    let [_, server, cache] = [0, 1, 2].map(|i| choreo.roles[i].clone());
+
+    // Both outcomes go on with the statements after the block
+    assert_eq!(
+        project(&choreo, &server).unwrap().to_pretty_string(),
+        "\
+receive Query from Client
+send Fetch to Cache
+receive Hit from Cache
+send Answer to Client
+branch from Client {
+    in_time: {
+        receive InTime from Client
+        receive Done from Client
+    }
+    deadline_exceeded: {
+        receive DeadlineExceeded from Client
+        receive Cancel from Client
+        receive Done from Client
+    }
+}
+"
+    );
+
+    // A role the timekeeper never messages in the block learns it too
+    assert_eq!(
+        project(&choreo, &cache).unwrap().to_pretty_string(),
+        "\
+receive Fetch from Server
+send Hit to Server
+branch from Client {
+    in_time: {
+        receive InTime from Client
+    }
+    deadline_exceeded: {
+        receive DeadlineExceeded from Client
+    }
+}
+"
+    );
+
+    let error = parse_choreography_str(
+        r"
+choreography Lookup {
+    roles: Client, Server
+    within 2s {
+        loop { Client -> Server: Query; break }
+    }
+}
+",
+    )
+    .unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("a `within` block must start with a send, whose sender keeps its time"));
+}
@@ -12,7 +12,7 @@
 use proptest::prelude::*;
 use proptest::strategy::BoxedStrategy;
 use quote::{format_ident, quote};
-use rumpsteak_aura_choreography::ast::{Choreography, MessageType, Protocol, Role};
+use rumpsteak_aura_choreography::ast::{Choreography, MessageType, Protocol, Role, Span};
 use rumpsteak_aura_choreography::compiler::analysis::analyze;
 use std::collections::HashMap;
 
@@ -102,6 +102,7 @@ fn simple_protocol_strategy() -> BoxedStrategy<Protocol> {
                                 .map(|(i, msg)| Branch {
                                     label: format_ident!("branch{}", i),
                                     guard: None,
+                                    probability: None,
                                     protocol: Protocol::Send {
                                         from: chooser.clone(),
                                         to: other.clone(),
@@ -177,6 +178,11 @@ fn choreography_strategy() -> impl Strategy<Value = Choreography> {
             roles,
             protocol,
             attrs: HashMap::new(),
+            policy: None,
+            state: Vec::new(),
+            messages: Vec::new(),
+            unreachable: Vec::new(),
+            scopes: Vec::new(),
         }
     })
 }
@@ -308,13 +314,18 @@ proptest! {
                     continuation: Box::new(Protocol::End),
                     annotations: HashMap::new(),
                     from_annotations: HashMap::new(),
-                    to_annotations: HashMap::new(),
+                    to_annotations: HashMap::new(), span: Span::default(),
                 }),
                 annotations: HashMap::new(),
                 from_annotations: HashMap::new(),
-                to_annotations: HashMap::new(),
+                to_annotations: HashMap::new(), span: Span::default(),
             },
             attrs: HashMap::new(),
+            policy: None,
+            state: Vec::new(),
+            messages: Vec::new(),
+            unreachable: Vec::new(),
+            scopes: Vec::new(),
         };
 
         let result = analyze(&choreo);
@@ -335,6 +346,11 @@ proptest! {
             roles: roles.clone(),
             protocol: Protocol::End,
             attrs: HashMap::new(),
+            policy: None,
+            state: Vec::new(),
+            messages: Vec::new(),
+            unreachable: Vec::new(),
+            scopes: Vec::new(),
         };
 
         let result = analyze(&choreo);
@@ -366,9 +382,14 @@ proptest! {
                 continuation: Box::new(Protocol::End),
                 annotations: HashMap::new(),
                 from_annotations: HashMap::new(),
-                to_annotations: HashMap::new(),
+                to_annotations: HashMap::new(), span: Span::default(),
             },
             attrs: HashMap::new(),
+            policy: None,
+            state: Vec::new(),
+            messages: Vec::new(),
+            unreachable: Vec::new(),
+            scopes: Vec::new(),
         };
 
         let result = analyze(&choreo);
@@ -423,8 +444,14 @@ mod unit_tests {
                 annotations: HashMap::new(),
                 from_annotations: HashMap::new(),
                 to_annotations: HashMap::new(),
+                span: Span::default(),
             },
             attrs: HashMap::new(),
+            policy: None,
+            state: Vec::new(),
+            messages: Vec::new(),
+            unreachable: Vec::new(),
+            scopes: Vec::new(),
         };
 
         let result = analyze(&choreo);
@@ -463,8 +490,14 @@ mod unit_tests {
                 annotations: HashMap::new(),
                 from_annotations: HashMap::new(),
                 to_annotations: HashMap::new(),
+                span: Span::default(),
             },
             attrs: HashMap::new(),
+            policy: None,
+            state: Vec::new(),
+            messages: Vec::new(),
+            unreachable: Vec::new(),
+            scopes: Vec::new(),
         };
 
         let result = analyze(&choreo);
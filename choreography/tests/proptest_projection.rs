@@ -28,6 +28,11 @@ proptest! {
             roles: vec![role.clone()],
             protocol: Protocol::End,
             attrs: HashMap::new(),
+            policy: None,
+            state: Vec::new(),
+            messages: Vec::new(),
+            unreachable: Vec::new(),
+            scopes: Vec::new(),
         };
 
         // Projection should complete without panicking
@@ -44,6 +49,11 @@ fn test_end_projection() {
         roles: vec![alice.clone()],
         protocol: Protocol::End,
         attrs: HashMap::new(),
+        policy: None,
+        state: Vec::new(),
+        messages: Vec::new(),
+        unreachable: Vec::new(),
+        scopes: Vec::new(),
     };
 
     let projected = project(&choreo, &alice).unwrap();
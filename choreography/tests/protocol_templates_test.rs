@@ -0,0 +1,144 @@
+#![allow(clippy::unwrap_used)]
+
+// The protocol templates of `rumpsteak_aura::protocols`, instantiated
+
+use rumpsteak_aura::protocols;
+use rumpsteak_aura_choreography::compiler::{generate_sequence_diagram, parse_choreography_str};
+use rumpsteak_aura_choreography::Choreography;
+
+/// A choreography of `roles` calling the instance `instance` of a template
+fn instantiate(roles: &str, instance: &str) -> Choreography {
+    let source = format!(
+        "choreography Instance {{\n    roles: {roles}\n    instantiate {instance} as Body;\n    call Body\n}}\n"
+    );
+    let choreography = parse_choreography_str(&source).unwrap();
+    choreography.validate().unwrap();
+    for role in &choreography.roles {
+        choreography.project(role).unwrap();
+    }
+    choreography
+}
+
+#[test]
+fn test_every_template_is_a_single_definition() {
+    for template in protocols::ALL {
+        let source =
+            format!("choreography Standalone {{\n    roles: A\n{template}    A -> A: Unused\n}}\n");
+        let choreography = parse_choreography_str(&source);
+        assert!(choreography.is_ok(), "{template}: {choreography:?}");
+    }
+}
+
+/// Assert the sequence diagram of `choreography` contains `lines`
+fn assert_diagram(choreography: &Choreography, lines: &[&str]) {
+    let diagram = generate_sequence_diagram(choreography);
+    for line in lines {
+        assert!(diagram.contains(line), "missing `{line}` in\n{diagram}");
+    }
+}
+
+#[test]
+fn test_request_response() {
+    let choreography = instantiate(
+        "Client, Server",
+        "RequestResponse<Client, Server, Query, Answer>",
+    );
+    assert_diagram(
+        &choreography,
+        &["Client->>Server: Query", "Server->>Client: Answer"],
+    );
+}
+
+#[test]
+fn test_request_response_with_timeout() {
+    let choreography = instantiate(
+        "Client, Server",
+        "RequestResponseWithTimeout<Client, Server, Query, Answer>",
+    );
+    assert_diagram(
+        &choreography,
+        &[
+            "Client->>Server: Query",
+            "alt Server: respond",
+            "Server->>Client: Answer",
+            "else Server: expire",
+            "Server->>Client: DeadlineExceeded",
+        ],
+    );
+}
+
+#[test]
+fn test_retry() {
+    let choreography = instantiate("Sender, Receiver", "Retry<3, Payment, Sender, Receiver>");
+    assert_diagram(
+        &choreography,
+        &[
+            "loop 3 times",
+            "alt Sender: attempt",
+            "Sender->>Receiver: Payment",
+            "Receiver->>Sender: Outcome",
+            "else Sender: skip",
+        ],
+    );
+}
+
+#[test]
+fn test_two_phase_commit() {
+    let choreography = instantiate("Bank, Branch", "TwoPhaseCommit<Bank, Branch>");
+    assert_diagram(
+        &choreography,
+        &[
+            "Bank->>Branch: Prepare",
+            "Branch->>Bank: Vote",
+            "alt Bank: commit",
+            "Bank->>Branch: Commit",
+            "else Bank: abort",
+            "Bank->>Branch: Abort",
+        ],
+    );
+}
+
+#[test]
+fn test_leader_election() {
+    let choreography = instantiate("Node, Peer", "LeaderElection<Node, Peer>");
+    assert_diagram(
+        &choreography,
+        &[
+            "Node->>Peer: RequestVote",
+            "alt Peer: grant",
+            "Node->>Peer: Elected",
+            "else Peer: deny",
+            "Peer->>Node: VoteDenied",
+        ],
+    );
+}
+
+#[test]
+fn test_scatter_gather() {
+    let choreography = instantiate(
+        "Coordinator, Worker[3]",
+        "ScatterGather<Coordinator, Worker, Task, Reply>",
+    );
+    assert_diagram(
+        &choreography,
+        &["Coordinator->>Worker: Task", "Worker->>Coordinator: Reply"],
+    );
+}
+
+#[test]
+fn test_paxos() {
+    let choreography = instantiate(
+        "Proposer, Acceptor, Learner",
+        "Paxos<Proposer, Acceptor, Learner>",
+    );
+    assert_diagram(
+        &choreography,
+        &[
+            "Proposer->>Acceptor: Prepare",
+            "alt Acceptor: promise",
+            "Acceptor->>Learner: Learn",
+            "else Acceptor: refuse",
+            "Acceptor->>Learner: NoValue",
+        ],
+    );
+}
@@ -0,0 +1,114 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for threshold receives from a quorum of role instances
+
+use rumpsteak_aura_choreography::runtime::address_book::RoleAddressBook;
+use rumpsteak_aura_choreography::runtime::harness::ChannelHandler;
+use rumpsteak_aura_choreography::runtime::quorum::{QuorumSpec, StragglerPolicy};
+use rumpsteak_aura_choreography::ChoreoHandler;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Role {
+    Coordinator,
+    Signer(u32),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct Share(u32);
+
+fn address_book(signers: u32) -> RoleAddressBook<Role> {
+    let mut book = RoleAddressBook::new();
+    book.insert_role("Coordinator", Role::Coordinator);
+    book.insert_all("Signer", (0..signers).map(Role::Signer))
+        .unwrap();
+    book
+}
+
+fn spec(stragglers: StragglerPolicy) -> QuorumSpec {
+    QuorumSpec {
+        role: "Signer".to_string(),
+        threshold: 2,
+        of: 3,
+        stragglers,
+    }
+}
+
+#[tokio::test]
+async fn test_quorum_does_not_wait_for_stragglers() {
+    let mut handlers = ChannelHandler::mesh(&[
+        Role::Coordinator,
+        Role::Signer(0),
+        Role::Signer(1),
+        Role::Signer(2),
+    ]);
+    let mut coordinator = handlers.remove(&Role::Coordinator).unwrap();
+    let mut book = address_book(3);
+
+    // Signer 0 has not answered, which must not hold up the quorum
+    for i in [2, 1] {
+        let signer = handlers.get_mut(&Role::Signer(i)).unwrap();
+        signer
+            .send(&mut (), Role::Coordinator, &Share(i * 10))
+            .await
+            .unwrap();
+    }
+
+    let mut quorum = book
+        .gather_quorum::<_, Share>(
+            &mut coordinator,
+            &mut (),
+            &spec(StragglerPolicy::CollectLate),
+        )
+        .await
+        .unwrap();
+    let mut indices: Vec<u32> = quorum.responses().iter().map(|&(i, _)| i).collect();
+    indices.sort_unstable();
+    assert_eq!(indices, vec![1, 2]);
+    assert_eq!(quorum.stragglers(), &[(0, Role::Signer(0))]);
+
+    let signer = handlers.get_mut(&Role::Signer(0)).unwrap();
+    signer
+        .send(&mut (), Role::Coordinator, &Share(0))
+        .await
+        .unwrap();
+    let late = quorum
+        .collect_late(&mut coordinator, &mut ())
+        .await
+        .unwrap();
+    assert_eq!(late, vec![(0, Share(0))]);
+    assert_eq!(book.count("Signer"), 3);
+}
+
+#[tokio::test]
+async fn test_aborted_stragglers_leave_the_session() {
+    let mut handlers = ChannelHandler::mesh(&[
+        Role::Coordinator,
+        Role::Signer(0),
+        Role::Signer(1),
+        Role::Signer(2),
+    ]);
+    let mut coordinator = handlers.remove(&Role::Coordinator).unwrap();
+    let mut book = address_book(3);
+
+    for i in [0, 1] {
+        let signer = handlers.get_mut(&Role::Signer(i)).unwrap();
+        signer
+            .send(&mut (), Role::Coordinator, &Share(i))
+            .await
+            .unwrap();
+    }
+
+    let mut quorum = book
+        .gather_quorum::<_, Share>(&mut coordinator, &mut (), &spec(StragglerPolicy::Abort))
+        .await
+        .unwrap();
+    assert_eq!(quorum.responses().len(), 2);
+    assert_eq!(book.count("Signer"), 2);
+    assert!(book.get("Signer", 2).is_none());
+    assert!(quorum
+        .collect_late(&mut coordinator, &mut ())
+        .await
+        .is_err());
+}
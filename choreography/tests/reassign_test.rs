@@ -0,0 +1,131 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for handing a role over to another participant
+
+use rumpsteak_aura_choreography::runtime::address_book::{RoleAddressBook, RoleSelector};
+use rumpsteak_aura_choreography::runtime::harness::ChannelHandler;
+use rumpsteak_aura_choreography::runtime::reassign::{Handover, LowestIndex};
+use rumpsteak_aura_choreography::{ChoreoHandler, ChoreographyError};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Role {
+    Coordinator,
+    Worker(u32),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct Ledger {
+    committed: Vec<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct Answer(u32);
+
+fn address_book() -> RoleAddressBook<Role> {
+    let mut book = RoleAddressBook::new();
+    book.insert_role("Coordinator", Role::Coordinator);
+    book.insert_all("Worker", (0..3).map(Role::Worker)).unwrap();
+    book
+}
+
+#[tokio::test]
+async fn test_elected_worker_takes_over_coordinator() {
+    let roles = [
+        Role::Coordinator,
+        Role::Worker(0),
+        Role::Worker(1),
+        Role::Worker(2),
+    ];
+    let mut handlers = ChannelHandler::mesh(&roles);
+    let ledger = Ledger {
+        committed: vec![3, 5],
+    };
+
+    let mut coordinator = handlers.remove(&Role::Coordinator).unwrap();
+    let mut book = address_book();
+    let mut elect_last = |_: &str, candidates: &[(u32, &Role)]| candidates.last().map(|&(i, _)| i);
+    let elected = book
+        .hand_over(
+            &mut coordinator,
+            &mut (),
+            "Coordinator",
+            &RoleSelector::all("Worker"),
+            &mut elect_last,
+            &ledger,
+        )
+        .await
+        .unwrap();
+    assert_eq!(elected, 2);
+    assert_eq!(book.get("Coordinator", 0), Some(&Role::Worker(2)));
+
+    let mut books = Vec::new();
+    for i in 0..3 {
+        let worker = handlers.get_mut(&Role::Worker(i)).unwrap();
+        let mut book = address_book();
+        let handover: Handover<Ledger> = book
+            .take_over(worker, &mut (), "Coordinator")
+            .await
+            .unwrap();
+        assert_eq!(handover.is_elected(i), i == 2);
+        assert_eq!(handover.state.is_some(), i == 2);
+        if i == 2 {
+            assert_eq!(handover.state.as_ref(), Some(&ledger));
+        }
+        books.push(book);
+    }
+
+    // Messages to the coordinator now reach the elected worker
+    let worker = handlers.get_mut(&Role::Worker(0)).unwrap();
+    books[0]
+        .send_to(
+            worker,
+            &mut (),
+            &RoleSelector::one("Coordinator", 0),
+            &Answer(8),
+        )
+        .await
+        .unwrap();
+    let new_coordinator = handlers.get_mut(&Role::Worker(2)).unwrap();
+    let answer: Answer = new_coordinator
+        .recv(&mut (), Role::Worker(0))
+        .await
+        .unwrap();
+    assert_eq!(answer, Answer(8));
+}
+
+#[tokio::test]
+async fn test_hand_over_without_candidates_fails() {
+    let mut handlers = ChannelHandler::mesh(&[Role::Coordinator, Role::Worker(0)]);
+    let mut coordinator = handlers.remove(&Role::Coordinator).unwrap();
+    let mut book = address_book();
+
+    let mut nobody = |_: &str, _: &[(u32, &Role)]| None;
+    let error = book
+        .hand_over(
+            &mut coordinator,
+            &mut (),
+            "Coordinator",
+            &RoleSelector::all("Worker"),
+            &mut nobody,
+            &(),
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ChoreographyError::Address(_)));
+    assert_eq!(book.get("Coordinator", 0), Some(&Role::Coordinator));
+
+    let elected = book
+        .hand_over(
+            &mut coordinator,
+            &mut (),
+            "Coordinator",
+            &RoleSelector::one("Worker", 0),
+            &mut LowestIndex,
+            &(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(elected, 0);
+}
@@ -5,9 +5,10 @@
 
 use rumpsteak_aura_choreography::effects::{
     handlers::rumpsteak::{RumpsteakEndpoint, RumpsteakHandler, RumpsteakSession, SimpleChannel},
-    ChoreoHandler,
+    ChoreoHandler, ChoreographyError, SessionPolicy,
 };
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 enum TestRole {
@@ -257,7 +258,7 @@ async fn test_choice_selection() {
 
     // Bob receives the choice
     let received_label = bob_handler
-        .offer(&mut bob_endpoint, TestRole::Alice)
+        .offer(&mut bob_endpoint, TestRole::Alice, &[choice_label])
         .await
         .expect("Bob should receive choice");
 
@@ -286,6 +287,7 @@ async fn test_multiple_choices() {
 
     // Test multiple choice sequences
     let choices = vec!["buy", "sell", "hold", "cancel"];
+    let labels: Vec<Label> = choices.iter().copied().map(Label).collect();
 
     for choice_str in choices {
         let choice_label = Label(choice_str);
@@ -296,7 +298,7 @@ async fn test_multiple_choices() {
             .expect("Alice should choose successfully");
 
         let received_label = bob_handler
-            .offer(&mut bob_endpoint, TestRole::Alice)
+            .offer(&mut bob_endpoint, TestRole::Alice, &labels)
             .await
             .expect("Bob should receive choice");
 
@@ -348,7 +350,7 @@ async fn test_choice_with_messages() {
         .expect("Choice should succeed");
 
     let received_choice = alice_handler
-        .offer(&mut alice_endpoint, TestRole::Bob)
+        .offer(&mut alice_endpoint, TestRole::Bob, &[choice_label])
         .await
         .expect("Offer should succeed");
 
@@ -432,7 +434,7 @@ async fn test_session_state_tracking() {
 
     // Bob offers
     let _label = bob_handler
-        .offer(&mut bob_endpoint, TestRole::Alice)
+        .offer(&mut bob_endpoint, TestRole::Alice, &[choice_label])
         .await
         .expect("Offer should succeed");
 
@@ -576,7 +578,7 @@ async fn test_dynamic_session_flow() {
         .expect("dynamic choose should succeed");
 
     let offered = alice_handler
-        .offer(&mut alice_endpoint, TestRole::Bob)
+        .offer(&mut alice_endpoint, TestRole::Bob, &[label])
         .await
         .expect("dynamic offer should succeed");
 
@@ -600,3 +602,58 @@ async fn test_drop_cleanup() {
     // Drop implementation should have cleaned up
     // (verified by lack of panic and proper tracing output)
 }
+
+#[tokio::test]
+async fn test_policy_timeout_keeps_channel() {
+    let policy = SessionPolicy::new().with_timeout(Duration::from_millis(20));
+    let mut alice_endpoint = RumpsteakEndpoint::with_policy(TestRole::Alice, policy);
+    let mut bob_endpoint = RumpsteakEndpoint::new(TestRole::Bob);
+
+    let (alice_channel, bob_channel) = SimpleChannel::pair();
+    alice_endpoint.register_channel(TestRole::Bob, alice_channel);
+    bob_endpoint.register_channel(TestRole::Alice, bob_channel);
+
+    let mut alice_handler = RumpsteakHandler::<TestRole, TestMessage>::new();
+    let mut bob_handler = RumpsteakHandler::<TestRole, TestMessage>::new();
+
+    // Nothing has been sent yet, so the receive hits the policy deadline
+    let result = alice_handler
+        .recv::<TestMessage>(&mut alice_endpoint, TestRole::Bob)
+        .await;
+    assert!(matches!(result, Err(ChoreographyError::Timeout(_))));
+    assert!(alice_endpoint.has_channel(&TestRole::Bob));
+
+    let msg = TestMessage {
+        content: "late".to_string(),
+    };
+    bob_handler
+        .send(&mut bob_endpoint, TestRole::Alice, &msg)
+        .await
+        .unwrap();
+    let received: TestMessage = alice_handler
+        .recv(&mut alice_endpoint, TestRole::Bob)
+        .await
+        .expect("channel should survive the timeout");
+    assert_eq!(received, msg);
+}
+
+#[tokio::test]
+async fn test_policy_max_message_size() {
+    let policy = SessionPolicy::new().with_max_message_size(16);
+    let mut alice_endpoint = RumpsteakEndpoint::with_policy(TestRole::Alice, policy);
+    let (alice_channel, _bob_channel) = SimpleChannel::pair();
+    alice_endpoint.register_channel(TestRole::Bob, alice_channel);
+
+    let mut alice_handler = RumpsteakHandler::<TestRole, TestMessage>::new();
+    let oversized = TestMessage {
+        content: "x".repeat(64),
+    };
+
+    let result = alice_handler
+        .send(&mut alice_endpoint, TestRole::Bob, &oversized)
+        .await;
+    assert!(matches!(
+        result,
+        Err(ChoreographyError::MessageTooLarge { max: 16, .. })
+    ));
+}
@@ -0,0 +1,129 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for scheduled session initiation
+
+use std::time::Duration;
+
+use rumpsteak_aura_choreography::runtime::bootstrap::ProtocolDescriptor;
+use rumpsteak_aura_choreography::runtime::scheduler::{
+    Launch, Schedule, Scheduler, SessionConfig, Trigger,
+};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio_util::compat::TokioAsyncReadCompatExt;
+use uuid::Uuid;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+enum Role {
+    Dealer,
+    Holder,
+}
+
+/// Endpoint made for a session, naming it
+#[derive(Debug)]
+struct Endpoint {
+    session_id: Uuid,
+}
+
+fn config() -> SessionConfig<Role, Endpoint> {
+    let protocol = ProtocolDescriptor::new("KeyRotation", 7);
+    SessionConfig::new(protocol, Role::Dealer, |assignment| Endpoint {
+        session_id: assignment.session_id,
+    })
+    .assign(Role::Dealer, "dealer.example")
+    .assign(Role::Holder, "holder.example")
+}
+
+#[tokio::test]
+async fn test_sessions_start_on_schedule_and_triggers() {
+    let scheduler =
+        Scheduler::new(config()).with_schedule(Schedule::Every(Duration::from_millis(20)));
+    let handle = scheduler.handle();
+    let (launched, mut launches) = mpsc::unbounded_channel::<Launch<Role, Endpoint>>();
+    let running = tokio::spawn(scheduler.run(move |launch| {
+        let launched = launched.clone();
+        async move { launched.send(launch).map_err(|_| "test over") }
+    }));
+
+    let first = launches.recv().await.unwrap();
+    assert_eq!(first.index, 0);
+    assert!(matches!(first.trigger, Trigger::Scheduled { .. }));
+    assert_eq!(first.endpoint.session_id, first.assignment.session_id);
+    let initiator = first.initiator();
+    assert_eq!(initiator.session_id(), first.assignment.session_id);
+    assert!(initiator.validate().is_ok());
+    assert_eq!(first.assignment.roster.len(), 2);
+    let second = launches.recv().await.unwrap();
+    assert!(matches!(second.trigger, Trigger::Scheduled { .. }));
+    assert_ne!(second.assignment.session_id, first.assignment.session_id);
+
+    assert!(handle.fire("ops", b"now".to_vec()));
+    let fired = loop {
+        let launch = launches.recv().await.unwrap();
+        if let Trigger::External { source, payload } = launch.trigger {
+            break (source, payload);
+        }
+    };
+    assert_eq!(fired, ("ops".to_string(), b"now".to_vec()));
+
+    handle.stop();
+    let started = running.await.unwrap();
+    assert!(started >= 3, "{started}");
+    assert!(!handle.fire("ops", Vec::new()));
+}
+
+#[tokio::test]
+async fn test_webhooks_trigger_sessions() {
+    let scheduler = Scheduler::new(config());
+    let handle = scheduler.handle();
+    let webhook = scheduler.webhook();
+    let (launched, mut launches) = mpsc::unbounded_channel::<Launch<Role, Endpoint>>();
+    let running = tokio::spawn(scheduler.run(move |launch| {
+        let launched = launched.clone();
+        async move { launched.send(launch).map_err(|_| "test over") }
+    }));
+
+    let (mut service, local) = tokio::io::duplex(1024);
+    let hook = webhook.clone();
+    let accepted = tokio::spawn(async move { hook.accept(local.compat()).await });
+    let body = r#"{"reason":"compromise"}"#;
+    let request = format!(
+        "POST /hooks/rotate HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+        body.len()
+    );
+    service.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    service.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 202"), "{response}");
+    assert_eq!(
+        accepted.await.unwrap().unwrap().to_string(),
+        "POST /hooks/rotate"
+    );
+
+    let launch = launches.recv().await.unwrap();
+    assert_eq!(
+        launch.trigger,
+        Trigger::External {
+            source: "POST /hooks/rotate".to_string(),
+            payload: body.as_bytes().to_vec(),
+        }
+    );
+
+    // Without schedules the scheduler only starts triggered sessions
+    handle.stop();
+    assert_eq!(running.await.unwrap(), 1);
+
+    // Once it stopped, webhooks are refused
+    let (mut service, local) = tokio::io::duplex(1024);
+    let accepted = tokio::spawn(async move { webhook.accept(local.compat()).await });
+    service
+        .write_all(b"POST /hooks/rotate HTTP/1.1\r\nContent-Length: 0\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    service.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 503"), "{response}");
+    assert!(accepted.await.unwrap().is_err());
+}
@@ -0,0 +1,188 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for branch decisions and guards made by scripts
+
+use rumpsteak_aura_choreography::runtime::guard::CapabilityProvider;
+use rumpsteak_aura_choreography::runtime::script::{
+    decide, Decision, DecisionScript, ScriptError, ScriptGuard,
+};
+use rumpsteak_aura_choreography::ChoreographyError;
+use serde::Serialize;
+
+/// As generated for `choice Server { accept: ... reject: ... }`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ServerChoiceAcceptReject {
+    Accept,
+    Reject,
+}
+
+impl Decision for ServerChoiceAcceptReject {
+    const ROLE: &'static str = "Server";
+    const FUNCTION: &'static str = "choose_accept_or_reject";
+    const LABELS: &'static [&'static str] = &["accept", "reject"];
+
+    fn from_label(label: &str) -> Option<Self> {
+        match label {
+            "accept" => Some(Self::Accept),
+            "reject" => Some(Self::Reject),
+            _ => None,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Accept => "accept",
+            Self::Reject => "reject",
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Order {
+    total: u32,
+}
+
+/// Script accepting orders up to a limit, and granting every capability
+/// but `refund`
+struct Limit(u64);
+
+impl DecisionScript for Limit {
+    fn choose(
+        &self,
+        function: &str,
+        role: &str,
+        context: &serde_json::Value,
+    ) -> Result<String, ScriptError> {
+        assert_eq!((function, role), ("choose_accept_or_reject", "Server"));
+        Ok(match context["total"].as_u64() {
+            Some(total) if total <= self.0 => "accept".into(),
+            Some(_) => "reject".into(),
+            None => "maybe".into(),
+        })
+    }
+
+    fn allow(&self, _role: &str, capability: &str) -> Result<bool, ScriptError> {
+        if capability == "unknown" {
+            return Err(ScriptError::MissingFunction("allow".into()));
+        }
+        Ok(capability != "refund")
+    }
+}
+
+#[test]
+fn test_script_picks_a_branch() {
+    let script = Limit(100);
+    let decision: ServerChoiceAcceptReject = decide(&script, &Order { total: 40 }).unwrap();
+    assert_eq!(decision, ServerChoiceAcceptReject::Accept);
+    let decision: ServerChoiceAcceptReject = decide(&script, &Order { total: 400 }).unwrap();
+    assert_eq!(decision.label(), "reject");
+
+    // Labels outside the choice are not branches
+    let err = decide::<ServerChoiceAcceptReject, _>(&script, &()).unwrap_err();
+    assert_eq!(
+        err,
+        ScriptError::UnknownLabel {
+            function: "choose_accept_or_reject".into(),
+            label: "maybe".into(),
+            expected: vec!["accept".into(), "reject".into()],
+        }
+    );
+    assert!(matches!(
+        ChoreographyError::from(err),
+        ChoreographyError::Script(_)
+    ));
+}
+
+#[test]
+fn test_script_guards_deny_unless_allowed() {
+    let guard = ScriptGuard::new(Limit(0));
+    assert!(guard.has_capability("Server", "fulfil"));
+    assert!(!guard.has_capability("Server", "refund"));
+    // A script that cannot answer denies
+    assert!(!guard.has_capability("Server", "unknown"));
+}
+
+#[cfg(feature = "rhai")]
+mod rhai {
+    use super::*;
+    use rumpsteak_aura_choreography::runtime::script::RhaiScript;
+    use std::time::{Duration, SystemTime};
+
+    const LIMIT: &str = r#"
+        fn choose_accept_or_reject(role, order) {
+            if order.total > 100 { "reject" } else { "accept" }
+        }
+
+        fn allow(role, capability) {
+            role == "Server" && capability != "refund"
+        }
+    "#;
+
+    #[test]
+    fn test_rhai_script_decides() {
+        let script = RhaiScript::new(LIMIT).unwrap();
+        let decision: ServerChoiceAcceptReject = decide(&script, &Order { total: 40 }).unwrap();
+        assert_eq!(decision, ServerChoiceAcceptReject::Accept);
+        let decision: ServerChoiceAcceptReject = decide(&script, &Order { total: 400 }).unwrap();
+        assert_eq!(decision, ServerChoiceAcceptReject::Reject);
+
+        let guard = ScriptGuard::new(script);
+        assert!(guard.has_capability("Server", "fulfil"));
+        assert!(!guard.has_capability("Server", "refund"));
+        assert!(!guard.has_capability("Client", "fulfil"));
+
+        let script = RhaiScript::new(r#"fn choose_accept_or_reject(role, order) { 1 }"#).unwrap();
+        let err = decide::<ServerChoiceAcceptReject, _>(&script, &()).unwrap_err();
+        assert!(matches!(err, ScriptError::Call { .. }));
+        assert_eq!(
+            script.allow("Server", "fulfil"),
+            Err(ScriptError::MissingFunction("allow".into()))
+        );
+    }
+
+    #[test]
+    fn test_reload_keeps_the_script_that_compiles() {
+        let script = RhaiScript::new(LIMIT).unwrap();
+        assert!(matches!(
+            script.reload("fn choose_accept_or_reject(role, order) {"),
+            Err(ScriptError::Compile(_))
+        ));
+        let decision: ServerChoiceAcceptReject = decide(&script, &Order { total: 40 }).unwrap();
+        assert_eq!(decision, ServerChoiceAcceptReject::Accept);
+
+        script
+            .reload(r#"fn choose_accept_or_reject(role, order) { "reject" }"#)
+            .unwrap();
+        let decision: ServerChoiceAcceptReject = decide(&script, &Order { total: 40 }).unwrap();
+        assert_eq!(decision, ServerChoiceAcceptReject::Reject);
+    }
+
+    #[test]
+    fn test_script_file_is_reloaded_when_it_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("decisions.rhai");
+        let rewrite = |source: &str, age: u64| {
+            std::fs::write(&path, source).unwrap();
+            let file = std::fs::File::options().write(true).open(&path).unwrap();
+            file.set_modified(SystemTime::now() - Duration::from_secs(age))
+                .unwrap();
+        };
+        rewrite(LIMIT, 60);
+        let script = RhaiScript::from_file(&path).unwrap();
+        assert!(!script.refresh().unwrap());
+
+        rewrite(
+            r#"fn choose_accept_or_reject(role, order) { "reject" }"#,
+            30,
+        );
+        let decision: ServerChoiceAcceptReject = decide(&script, &Order { total: 40 }).unwrap();
+        assert_eq!(decision, ServerChoiceAcceptReject::Reject);
+
+        // An edit that does not compile leaves the previous script running
+        rewrite("fn choose_accept_or_reject(role, order) {", 0);
+        let decision: ServerChoiceAcceptReject = decide(&script, &Order { total: 40 }).unwrap();
+        assert_eq!(decision, ServerChoiceAcceptReject::Reject);
+        assert!(!script.refresh().unwrap());
+    }
+}
@@ -0,0 +1,233 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+#![cfg(feature = "secure")]
+
+// Integration tests for end-to-end payload encryption
+
+mod common;
+
+use common::{endpoints, roles};
+use rumpsteak_aura_choreography::effects::handlers::rumpsteak::{
+    RumpsteakEndpoint, RumpsteakHandler,
+};
+use rumpsteak_aura_choreography::effects::middleware::Encrypted;
+use rumpsteak_aura_choreography::runtime::secure::{SecureCodec, StaticKeys};
+use rumpsteak_aura_choreography::{ChoreoHandler, ChoreographyError, Label};
+use serde::{Deserialize, Serialize};
+
+roles!(TestRole { Alice, Bob }: TestMessage);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct TestMessage(String);
+
+type Inner = RumpsteakHandler<TestRole, TestMessage>;
+
+/// Mirror of the frame `Encrypted` sends, as a relay sees it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Kind {
+    Message,
+    Label,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Sealed {
+    kind: Kind,
+    payload: Vec<u8>,
+}
+
+fn encrypted(keys: &StaticKeys, role: TestRole) -> Encrypted<Inner> {
+    let codec = SecureCodec::new(keys.for_role(&format!("{role:?}")));
+    Encrypted::new(Inner::new(), role, "s-1", codec)
+}
+
+/// Alice and Bob linked through a relay, with the relay's endpoints towards
+/// each of them
+struct Relayed {
+    alice_ep: RumpsteakEndpoint<TestRole>,
+    bob_ep: RumpsteakEndpoint<TestRole>,
+    from_alice: RumpsteakEndpoint<TestRole>,
+    to_bob: RumpsteakEndpoint<TestRole>,
+}
+
+fn relayed() -> Relayed {
+    let (alice_ep, from_alice) = endpoints(TestRole::Alice, TestRole::Bob);
+    let (to_bob, bob_ep) = endpoints(TestRole::Alice, TestRole::Bob);
+    Relayed {
+        alice_ep,
+        bob_ep,
+        from_alice,
+        to_bob,
+    }
+}
+
+#[tokio::test]
+async fn test_encrypted_round_trip() {
+    let keys = StaticKeys::generate(&["Alice", "Bob"]);
+    let (mut alice_ep, mut bob_ep) = endpoints(TestRole::Alice, TestRole::Bob);
+    let mut alice = encrypted(&keys, TestRole::Alice);
+    let mut bob = encrypted(&keys, TestRole::Bob);
+
+    for i in 0..3 {
+        let message = TestMessage(format!("account {i}"));
+        alice
+            .send(&mut alice_ep, TestRole::Bob, &message)
+            .await
+            .unwrap();
+        let received: TestMessage = bob.recv(&mut bob_ep, TestRole::Alice).await.unwrap();
+        assert_eq!(received, message);
+    }
+
+    alice
+        .choose(&mut alice_ep, TestRole::Bob, Label("approve"))
+        .await
+        .unwrap();
+    let label = bob
+        .offer(
+            &mut bob_ep,
+            TestRole::Alice,
+            &[Label("approve"), Label("deny")],
+        )
+        .await
+        .unwrap();
+    assert_eq!(label, Label("approve"));
+}
+
+#[tokio::test]
+async fn test_intermediaries_see_ciphertext() {
+    let keys = StaticKeys::generate(&["Alice", "Bob"]);
+    let mut link = relayed();
+    let mut alice = encrypted(&keys, TestRole::Alice);
+    let mut relay = Inner::new();
+
+    alice
+        .send(
+            &mut link.alice_ep,
+            TestRole::Bob,
+            &TestMessage("account 42".into()),
+        )
+        .await
+        .unwrap();
+    alice
+        .choose(&mut link.alice_ep, TestRole::Bob, Label("approve"))
+        .await
+        .unwrap();
+    for _ in 0..2 {
+        let sealed: Sealed = relay
+            .recv(&mut link.from_alice, TestRole::Alice)
+            .await
+            .unwrap();
+        assert!(!sealed
+            .payload
+            .windows(7)
+            .any(|window| window == b"account" || window == b"approve"));
+        relay
+            .send(&mut link.to_bob, TestRole::Bob, &sealed)
+            .await
+            .unwrap();
+    }
+
+    // Only Bob's key opens them
+    let mut impostor = Encrypted::new(
+        Inner::new(),
+        TestRole::Bob,
+        "s-1",
+        SecureCodec::new(keys.for_role("Alice")),
+    );
+    let error = impostor
+        .recv::<TestMessage>(&mut link.bob_ep, TestRole::Alice)
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("no secret key for role Bob"));
+}
+
+#[tokio::test]
+async fn test_tampered_label_is_rejected() {
+    let keys = StaticKeys::generate(&["Alice", "Bob"]);
+    let mut link = relayed();
+    let mut alice = encrypted(&keys, TestRole::Alice);
+    let mut bob = encrypted(&keys, TestRole::Bob);
+    let mut relay = Inner::new();
+
+    alice
+        .choose(&mut link.alice_ep, TestRole::Bob, Label("reject"))
+        .await
+        .unwrap();
+    let mut sealed: Sealed = relay
+        .recv(&mut link.from_alice, TestRole::Alice)
+        .await
+        .unwrap();
+    *sealed.payload.last_mut().unwrap() ^= 1;
+    relay
+        .send(&mut link.to_bob, TestRole::Bob, &sealed)
+        .await
+        .unwrap();
+
+    let error = bob
+        .offer(
+            &mut link.bob_ep,
+            TestRole::Alice,
+            &[Label("approve"), Label("deny")],
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ChoreographyError::Transport(_)));
+}
+
+#[tokio::test]
+async fn test_replayed_payload_is_rejected() {
+    let keys = StaticKeys::generate(&["Alice", "Bob"]);
+    let mut link = relayed();
+    let mut alice = encrypted(&keys, TestRole::Alice);
+    let mut bob = encrypted(&keys, TestRole::Bob);
+    let mut relay = Inner::new();
+
+    alice
+        .send(
+            &mut link.alice_ep,
+            TestRole::Bob,
+            &TestMessage("transfer 100".into()),
+        )
+        .await
+        .unwrap();
+    let sealed: Sealed = relay
+        .recv(&mut link.from_alice, TestRole::Alice)
+        .await
+        .unwrap();
+    for _ in 0..2 {
+        relay
+            .send(&mut link.to_bob, TestRole::Bob, &sealed)
+            .await
+            .unwrap();
+    }
+
+    let received: TestMessage = bob.recv(&mut link.bob_ep, TestRole::Alice).await.unwrap();
+    assert_eq!(received.0, "transfer 100");
+    let error = bob
+        .recv::<TestMessage>(&mut link.bob_ep, TestRole::Alice)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ChoreographyError::Transport(_)));
+}
+
+#[tokio::test]
+async fn test_payloads_of_other_sessions_are_rejected() {
+    let keys = StaticKeys::generate(&["Alice", "Bob"]);
+    let (mut alice_ep, mut bob_ep) = endpoints(TestRole::Alice, TestRole::Bob);
+    let mut alice = encrypted(&keys, TestRole::Alice);
+    let mut bob = Encrypted::new(
+        Inner::new(),
+        TestRole::Bob,
+        "s-2",
+        SecureCodec::new(keys.for_role("Bob")),
+    );
+
+    alice
+        .send(&mut alice_ep, TestRole::Bob, &TestMessage("hi".into()))
+        .await
+        .unwrap();
+    let error = bob
+        .recv::<TestMessage>(&mut bob_ep, TestRole::Alice)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ChoreographyError::Transport(_)));
+}
@@ -0,0 +1,113 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for coordinated child sessions
+
+mod common;
+
+use common::{endpoints, roles};
+use rumpsteak_aura_choreography::effects::handlers::rumpsteak::RumpsteakHandler;
+use rumpsteak_aura_choreography::runtime::group::SessionGroup;
+use rumpsteak_aura_choreography::{Cancellable, ChoreoHandler, ChoreographyError, SessionHandle};
+use serde::{Deserialize, Serialize};
+
+roles!(Role { Auctioneer, Bidder }: Bid);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct Bid(u32);
+
+type Handler = Cancellable<RumpsteakHandler<Role, Bid>>;
+
+#[tokio::test]
+async fn test_auction_collects_bids_and_cancels_stragglers() {
+    let mut bidders = Vec::new();
+    let mut group = SessionGroup::new();
+    for (name, bid) in [("alice", Some(30)), ("bob", Some(45)), ("carol", None)] {
+        let (mut auctioneer_ep, mut bidder_ep) = endpoints(Role::Auctioneer, Role::Bidder);
+        let mut bidder = Handler::new(RumpsteakHandler::new(), SessionHandle::new(), vec![]);
+        bidders.push(tokio::spawn(async move {
+            if let Some(bid) = bid {
+                bidder
+                    .send(&mut bidder_ep, Role::Auctioneer, &Bid(bid))
+                    .await
+                    .unwrap();
+            }
+            // Keep the channel open, as a bidder that has not decided yet
+            bidder_ep
+        }));
+        let group_id = group.id();
+        group.spawn(name, move |child| async move {
+            assert_eq!(child.group, group_id);
+            let mut auctioneer =
+                Handler::new(RumpsteakHandler::new(), child.handle, vec![Role::Bidder]);
+            let Bid(bid) = auctioneer.recv(&mut auctioneer_ep, Role::Bidder).await?;
+            Ok::<_, ChoreographyError>(bid)
+        });
+    }
+    assert_eq!(group.pending(), 3);
+
+    let mut bids = Vec::new();
+    while bids.len() < 2 {
+        let (name, result) = group.next().await.unwrap();
+        bids.push((name, result.unwrap()));
+    }
+    bids.sort_unstable();
+    assert_eq!(bids, [("alice", 30), ("bob", 45)]);
+
+    // The auction closes, and the negotiation still waiting stops
+    group.cancel();
+    let results = group.join().await;
+    assert_eq!(results.results.len(), 1);
+    let (name, result) = &results.results[0];
+    assert_eq!(*name, "carol");
+    assert!(matches!(result, Err(ChoreographyError::Cancelled)));
+    for bidder in bidders {
+        bidder.await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn test_cancellation_reaches_nested_groups() {
+    let parent = SessionHandle::new();
+    let mut group = SessionGroup::within(&parent);
+    group.spawn("negotiation", |child| async move {
+        let mut rounds = child.group();
+        let group = child.group;
+        for round in 0..3 {
+            rounds.spawn(round, move |grandchild| async move {
+                assert_eq!(grandchild.parent, Some(group));
+                grandchild.handle.cancelled().await;
+                Err::<(), _>(format!("round {round} cancelled"))
+            });
+        }
+        let results = rounds.join().await;
+        Ok::<_, String>(results.failed().count())
+    });
+
+    parent.cancel();
+    assert!(group.is_cancelled());
+    let results = group.join().await.into_result().unwrap();
+    assert_eq!(results, [("negotiation", 3)]);
+}
+
+#[tokio::test]
+async fn test_fail_fast_cancels_siblings() {
+    let mut group = SessionGroup::new().fail_fast();
+    group.spawn("slow", |child| async move {
+        child.handle.cancelled().await;
+        Err("cancelled")
+    });
+    group.spawn("broken", |_| async { Err("no route to bidder") });
+    group.spawn("done", |_| async { Ok(1) });
+
+    let results = group.join().await;
+    assert!(!results.all_succeeded());
+    assert_eq!(results.succeeded().collect::<Vec<_>>(), [(&"done", &1)]);
+    let mut failed: Vec<_> = results.failed().collect();
+    failed.sort_unstable();
+    assert_eq!(
+        failed,
+        [(&"broken", &"no route to bidder"), (&"slow", &"cancelled")]
+    );
+    assert_eq!(results.into_result().unwrap_err().0, "broken");
+}
@@ -0,0 +1,189 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for session metrics hooks
+
+mod common;
+
+use common::{endpoints, roles};
+use rumpsteak_aura_choreography::effects::handlers::rumpsteak::{
+    RumpsteakEndpoint, RumpsteakHandler,
+};
+use rumpsteak_aura_choreography::runtime::monitor::ActionKind;
+use rumpsteak_aura_choreography::{
+    ChoreoHandler, ChoreographyError, Instrumented, Label, SessionError, SessionMetrics,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+roles!(TestRole { Alice, Bob }: Ping);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct Ping(u32);
+
+#[derive(Default)]
+struct Recorded {
+    events: Mutex<Vec<String>>,
+    steps: Mutex<Vec<(String, ActionKind)>>,
+}
+
+impl SessionMetrics for Recorded {
+    fn message_sent(&self, role: &str, to: &str, label: &str) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("{role} sent {label} to {to}"));
+    }
+
+    fn message_received(&self, role: &str, from: &str, label: &str) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("{role} received {label} from {from}"));
+    }
+
+    fn branch_selected(&self, role: &str, to: &str, branch: &str) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("{role} selected {branch} for {to}"));
+    }
+
+    fn branch_offered(&self, role: &str, from: &str, branch: &str) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("{role} offered {branch} by {from}"));
+    }
+
+    fn step_completed(&self, role: &str, step: ActionKind, _latency: Duration) {
+        self.steps.lock().unwrap().push((role.to_string(), step));
+    }
+
+    fn step_failed(&self, role: &str, step: ActionKind, error: &ChoreographyError) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("{role} failed {step}: {error}"));
+    }
+
+    fn session_started(&self, role: &str) {
+        self.events.lock().unwrap().push(format!("{role} started"));
+    }
+
+    fn session_ended(&self, role: &str, _duration: Duration, error: Option<&SessionError>) {
+        let outcome = error.map_or("completed".to_string(), ToString::to_string);
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("{role} ended: {outcome}"));
+    }
+
+    fn timed_out(&self, role: &str, at: &str, after: Duration) {
+        self.events
+            .lock()
+            .unwrap()
+            .push(format!("{role} timed out at {at} after {after:?}"));
+    }
+}
+
+#[tokio::test]
+async fn test_hooks_follow_protocol_steps() {
+    let (mut alice_ep, mut bob_ep) = endpoints(TestRole::Alice, TestRole::Bob);
+
+    let recorded = Arc::new(Recorded::default());
+    let mut alice = Instrumented::new(
+        RumpsteakHandler::<TestRole, Ping>::new(),
+        TestRole::Alice,
+        recorded.clone(),
+    );
+    let mut bob = Instrumented::new(
+        RumpsteakHandler::<TestRole, Ping>::new(),
+        TestRole::Bob,
+        recorded.clone(),
+    );
+
+    alice
+        .send(&mut alice_ep, TestRole::Bob, &Ping(1))
+        .await
+        .unwrap();
+    let _: Ping = bob.recv(&mut bob_ep, TestRole::Alice).await.unwrap();
+    bob.choose(&mut bob_ep, TestRole::Alice, Label("done"))
+        .await
+        .unwrap();
+    alice
+        .offer(&mut alice_ep, TestRole::Bob, &[Label("done")])
+        .await
+        .unwrap();
+
+    assert_eq!(
+        *recorded.events.lock().unwrap(),
+        vec![
+            "Alice sent Ping to Bob",
+            "Bob received Ping from Alice",
+            "Bob selected done for Alice",
+            "Alice offered done by Bob",
+        ]
+    );
+    assert_eq!(
+        *recorded.steps.lock().unwrap(),
+        vec![
+            ("Alice".to_string(), ActionKind::Send),
+            ("Bob".to_string(), ActionKind::Receive),
+            ("Bob".to_string(), ActionKind::Select),
+            ("Alice".to_string(), ActionKind::Branch),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_failures_are_reported() {
+    let mut alice_ep = RumpsteakEndpoint::new(TestRole::Alice);
+    let recorded = Arc::new(Recorded::default());
+    let mut alice = Instrumented::new(
+        RumpsteakHandler::<TestRole, Ping>::new(),
+        TestRole::Alice,
+        recorded.clone(),
+    );
+
+    // No channel registered for Bob
+    assert!(alice
+        .send(&mut alice_ep, TestRole::Bob, &Ping(1))
+        .await
+        .is_err());
+    let events = recorded.events.lock().unwrap();
+    assert_eq!(events.len(), 1);
+    assert!(events[0].starts_with("Alice failed send"));
+}
+
+#[tokio::test]
+async fn test_session_and_timeouts_are_reported() {
+    let mut alice_ep = RumpsteakEndpoint::new(TestRole::Alice);
+    let recorded = Arc::new(Recorded::default());
+    let mut alice = Instrumented::new(
+        RumpsteakHandler::<TestRole, Ping>::new(),
+        TestRole::Alice,
+        recorded.clone(),
+    );
+
+    alice.start_session();
+    let result = alice
+        .with_timeout(
+            &mut alice_ep,
+            TestRole::Bob,
+            Duration::from_millis(10),
+            std::future::pending::<rumpsteak_aura_choreography::Result<()>>(),
+        )
+        .await;
+    alice.end_session(&result.map_err(SessionError::from));
+
+    assert_eq!(
+        *recorded.events.lock().unwrap(),
+        vec![
+            "Alice started",
+            "Alice timed out at Bob after 10ms",
+            "Alice ended: timed out after 10ms",
+        ]
+    );
+}
@@ -0,0 +1,36 @@
+#![allow(clippy::unwrap_used)]
+
+// Named states in generated session types
+
+use rumpsteak_aura_choreography::{parse_and_generate_with_extensions, ExtensionRegistry};
+
+#[test]
+fn test_generated_states_are_named_after_their_step() {
+    let source = r#"
+choreography Purchase {
+    roles: Buyer, Seller;
+
+    Buyer -> Seller: Request;
+    Seller -> Buyer: Quote;
+    choice Buyer {
+        accept: {
+            Buyer -> Seller: Accept;
+        }
+        reject: {
+            Buyer -> Seller: Reject;
+        }
+    }
+}
+"#;
+    let code = parse_and_generate_with_extensions(source, &ExtensionRegistry::new())
+        .unwrap()
+        .to_string();
+    assert!(code.contains("type Buyer_Purchase = Send < Seller , Request , Buyer_AwaitQuote >"));
+    assert!(code
+        .contains("type Buyer_AwaitQuote = Receive < Seller , Quote , Buyer_ChooseAcceptReject >"));
+    assert!(code.contains(
+        "type Seller_OfferAcceptReject = Branch < Buyer , Seller_OfferAcceptRejectBranches >"
+    ));
+    assert!(code.contains("doc = \" `Seller -> Buyer: Quote`, line 6\""));
+    assert!(code.contains("doc = \" `choice Buyer`, line 7\""));
+}
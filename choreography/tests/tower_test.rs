@@ -0,0 +1,181 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+#![cfg(feature = "tower")]
+
+// Integration tests for roles hosted in an axum service
+
+use axum::body::Body;
+use axum::routing::get;
+use axum::Router;
+use http_body_util::BodyExt;
+use rumpsteak_aura_choreography::runtime::http::{HttpRoute, RouteTable};
+use rumpsteak_aura_choreography::runtime::tower::{SessionHost, MESSAGE_HEADER, SESSION_HEADER};
+use rumpsteak_aura_choreography::{ChoreoHandler, Label, NoOpHandler};
+use serde::{Deserialize, Serialize};
+use tower_service::Service;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Role {
+    Client,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+struct Order {
+    item: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+struct Receipt {
+    total: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Eq)]
+struct Shipped;
+
+/// Request of the session `session`, if any, naming `message` in a header
+fn build(
+    session: Option<&str>,
+    method: &str,
+    path: &str,
+    message: Option<&str>,
+    body: &str,
+) -> http::Request<Body> {
+    let mut request = http::Request::builder().method(method).uri(path);
+    if let Some(session) = session {
+        request = request.header(SESSION_HEADER, session);
+    }
+    if let Some(message) = message {
+        request = request.header(MESSAGE_HEADER, message);
+    }
+    request.body(Body::from(body.to_string())).unwrap()
+}
+
+/// Status, message and body of the app's response to `request`
+async fn call(mut app: Router, request: http::Request<Body>) -> (u16, Option<String>, String) {
+    let response = app.call(request).await.unwrap();
+    let status = response.status().as_u16();
+    let message = response
+        .headers()
+        .get(MESSAGE_HEADER)
+        .map(|value| value.to_str().unwrap().to_string());
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, message, String::from_utf8(body.to_vec()).unwrap())
+}
+
+async fn request(
+    app: &Router,
+    session: Option<&str>,
+    method: &str,
+    path: &str,
+    body: &str,
+) -> (u16, Option<String>, String) {
+    call(app.clone(), build(session, method, path, None, body)).await
+}
+
+#[tokio::test]
+async fn test_hosted_role_follows_its_session() {
+    let routes = RouteTable::new().with_route("Order", HttpRoute::for_message("Order"));
+    let host = SessionHost::new(routes);
+    let app = Router::new()
+        .route("/health", get(|| async { "ok" }))
+        .layer(host.layer());
+
+    let mut shop = host.host("s-1", NoOpHandler::<Role>::new(), Role::Client);
+    let session = tokio::spawn(async move {
+        let order: Order = shop.recv(&mut (), Role::Client).await?;
+        let total = if order.item == "tea" { 4 } else { 10 };
+        shop.send(&mut (), Role::Client, &Receipt { total }).await?;
+        shop.send(&mut (), Role::Client, &Shipped).await?;
+        let Label(label) = shop
+            .offer(&mut (), Role::Client, &[Label("rate"), Label("skip")])
+            .await?;
+        Ok::<_, rumpsteak_aura_choreography::ChoreographyError>(label)
+    });
+
+    // Requests without a session go to the app
+    let health = request(&app, None, "GET", "/health", "").await;
+    assert_eq!(health, (200, None, "ok".to_string()));
+    let unknown = request(&app, Some("s-2"), "POST", "/order", "{}").await;
+    assert_eq!(unknown.0, 404);
+
+    // Only the message the session receives is accepted
+    let wrong = request(&app, Some("s-1"), "POST", "/refund", "{}").await;
+    assert_eq!(wrong.0, 409);
+    assert_eq!(wrong.2, "session s-1 receives Order, not POST /refund");
+
+    let receipt = request(&app, Some("s-1"), "POST", "/order", r#"{"item":"tea"}"#).await;
+    assert_eq!(
+        receipt,
+        (
+            200,
+            Some("Receipt".to_string()),
+            r#"{"total":4}"#.to_string()
+        )
+    );
+    // Sent with no request open, collected by the next poll
+    let shipped = request(&app, Some("s-1"), "GET", "/", "").await;
+    assert_eq!(
+        shipped,
+        (200, Some("Shipped".to_string()), "null".to_string())
+    );
+
+    let choice = request(&app, Some("s-1"), "POST", "/choice", r#""rate""#).await;
+    assert_eq!(choice.0, 202);
+    assert_eq!(session.await.unwrap().unwrap(), "rate");
+
+    assert!(host.sessions().is_empty());
+    let ended = request(&app, Some("s-1"), "POST", "/order", "{}").await;
+    assert_eq!(ended.0, 404);
+}
+
+#[tokio::test]
+async fn test_requests_wait_for_the_session_to_receive() {
+    let host = SessionHost::new(RouteTable::new());
+    let app = Router::new().layer(host.layer());
+    let mut shop = host.host("s-1", NoOpHandler::<Role>::new(), Role::Client);
+    let post = |body: &str| {
+        let request = build(Some("s-1"), "POST", "/", Some("Order"), body);
+        tokio::spawn(call(app.clone(), request))
+    };
+
+    // Sent before the role receives, and answered when it sends
+    let order = post(r#"{"item":"cake"}"#);
+    tokio::task::yield_now().await;
+    let received: Order = shop.recv(&mut (), Role::Client).await.unwrap();
+    assert_eq!(received.item, "cake");
+    shop.send(&mut (), Role::Client, &Receipt { total: 10 })
+        .await
+        .unwrap();
+    assert_eq!(order.await.unwrap().0, 200);
+
+    // Malformed messages are refused, and fail the receive
+    let malformed = post("not json");
+    let result: Result<Order, _> = shop.recv(&mut (), Role::Client).await;
+    assert!(result.is_err());
+    assert_eq!(malformed.await.unwrap().0, 400);
+
+    // Requests left when the session ends are refused
+    let late = post(r#"{"item":"pie"}"#);
+    tokio::task::yield_now().await;
+    drop(shop);
+    let (status, _, reason) = late.await.unwrap();
+    assert_eq!((status, reason.as_str()), (409, "session s-1 ended"));
+}
+
+#[tokio::test]
+async fn test_choices_outside_the_offered_branches_are_refused() {
+    let host = SessionHost::new(RouteTable::new());
+    let app = Router::new().layer(host.layer());
+    let mut shop = host.host("s-1", NoOpHandler::<Role>::new(), Role::Client);
+    let session = tokio::spawn(async move {
+        shop.offer(&mut (), Role::Client, &[Label("rate"), Label("skip")])
+            .await
+    });
+
+    let choice = request(&app, Some("s-1"), "POST", "/choice", r#""refund""#).await;
+    assert_eq!(choice.0, 400);
+    assert!(matches!(
+        session.await.unwrap(),
+        Err(rumpsteak_aura_choreography::ChoreographyError::ProtocolViolation(_))
+    ));
+}
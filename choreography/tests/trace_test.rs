@@ -0,0 +1,124 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for execution traces and their replay
+
+mod common;
+
+use common::{endpoints, roles};
+use rumpsteak_aura_choreography::compiler::{parse_choreography_str, replay};
+use rumpsteak_aura_choreography::effects::handlers::rumpsteak::RumpsteakHandler;
+use rumpsteak_aura_choreography::runtime::journal::JournalRecord;
+use rumpsteak_aura_choreography::runtime::trace::{read_trace, TraceRecorder};
+use rumpsteak_aura_choreography::{ChoreoHandler, Instrumented};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+roles!(Role { Client, Server }: Ping);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct Ping(u32);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct Pong(u32);
+
+const PING: &str = r"
+choreography PingPong {
+    roles: Client, Server
+    Client -> Server: Ping
+    Server -> Client: Pong
+}
+";
+
+/// Writer appending to a buffer the test reads afterwards
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_recorded_session_replays_without_divergence() {
+    let (mut client_ep, mut server_ep) = endpoints(Role::Client, Role::Server);
+
+    let output = Shared::default();
+    let recorder = Arc::new(TraceRecorder::new("session-1").with_writer(output.clone()));
+    let mut client = Instrumented::new(
+        RumpsteakHandler::<Role, Ping>::new(),
+        Role::Client,
+        recorder.clone(),
+    );
+    let mut server = Instrumented::new(
+        RumpsteakHandler::<Role, Ping>::new(),
+        Role::Server,
+        recorder.clone(),
+    );
+
+    client
+        .send(&mut client_ep, Role::Server, &Ping(1))
+        .await
+        .unwrap();
+    let Ping(n) = server.recv(&mut server_ep, Role::Client).await.unwrap();
+    server
+        .send(&mut server_ep, Role::Client, &Pong(n))
+        .await
+        .unwrap();
+    let _: Pong = client.recv(&mut client_ep, Role::Server).await.unwrap();
+
+    let bytes = output.0.lock().unwrap().clone();
+    let trace = read_trace(bytes.as_slice()).unwrap();
+    assert_eq!(trace, recorder.records());
+    assert_eq!(trace.len(), 4);
+    assert_eq!(trace[0].role, "Client");
+    assert_eq!(trace[0].span_name(), "choreo.send");
+    assert_eq!(
+        (trace[0].peer.as_str(), trace[0].label.as_str()),
+        ("Server", "Ping")
+    );
+    assert_eq!(trace[3].seq, 1);
+
+    let choreography = parse_choreography_str(PING).unwrap();
+    let report = replay(&choreography, trace);
+    assert!(!report.diverged());
+    let diagram = report.to_mermaid(Duration::from_secs(60));
+    assert!(diagram.contains("Client->>Server: +"));
+    assert!(diagram.contains("Server->>Client: +"));
+    assert!(!diagram.contains("Note"));
+}
+
+#[test]
+fn test_journal_records_are_read_as_trace() {
+    let journal = JournalRecord {
+        seq: 0,
+        timestamp_ms: 1_700_000_000_000,
+        session_id: "session-2".into(),
+        role: "Client".into(),
+        action: "send".into(),
+        peer: "Server".into(),
+        label: "Pong".into(),
+        facts: String::new(),
+        prev_hash: String::new(),
+        hash: String::new(),
+        retained: None,
+    };
+    let input = format!("{}\n\n", serde_json::to_string(&journal).unwrap());
+    let trace = read_trace(input.as_bytes()).unwrap();
+    assert_eq!(trace.len(), 1);
+    assert_eq!(trace[0].start_time_unix_nano, 1_700_000_000_000_000_000);
+    assert_eq!(trace[0].duration(), Duration::ZERO);
+
+    let choreography = parse_choreography_str(PING).unwrap();
+    let report = replay(&choreography, trace);
+    assert_eq!(report.divergences().count(), 1);
+
+    assert!(read_trace("{\"seq\": 1}\n".as_bytes()).is_err());
+}
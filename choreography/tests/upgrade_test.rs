@@ -0,0 +1,180 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// Integration tests for protocol version negotiation and upgrade adapters
+
+mod common;
+
+use common::{connect, roles};
+use rumpsteak_aura_choreography::compiler::upgrade::{generate_upgrade_adapter, UpgradeError};
+use rumpsteak_aura_choreography::effects::handlers::rumpsteak::{
+    RumpsteakEndpoint, RumpsteakHandler,
+};
+use rumpsteak_aura_choreography::runtime::bootstrap::ProtocolDescriptor;
+use rumpsteak_aura_choreography::runtime::upgrade::{
+    AddedBranch, NegotiationError, UpgradeSpec, VersionAdapter, VersionNegotiator,
+};
+use rumpsteak_aura_choreography::{ChoreoHandler, ChoreographyError, Label, NoOpHandler};
+use serde::{Deserialize, Serialize};
+
+roles!(Node { Client, Server, Warehouse }: Msg);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Msg;
+
+const V1: &str = "
+@protocol(version = 1)
+choreography Checkout {
+    roles: Client, Server, Warehouse
+    Client -> Server: PlaceOrder
+    choice Server {
+        accept: {
+            Server -> Warehouse: Reserve
+            Server -> Client: OrderAccepted
+        }
+        reject: {
+            Server -> Client: OrderRejected
+        }
+    }
+}
+";
+
+/// As generated for `V1` and the next version, which adds a `backorder`
+/// branch
+static CHECKOUT_UPGRADE_FROM_V1: UpgradeSpec = UpgradeSpec {
+    protocol: "Checkout",
+    previous: 1,
+    previous_hash: 0x0bad_cafe,
+    current: 2,
+    current_hash: 0xfeed_f00d,
+    added_branches: &[AddedBranch {
+        chooser: "Server",
+        label: "backorder",
+    }],
+};
+
+fn star_endpoints() -> (
+    RumpsteakEndpoint<Node>,
+    RumpsteakEndpoint<Node>,
+    RumpsteakEndpoint<Node>,
+) {
+    let mut server = RumpsteakEndpoint::new(Node::Server);
+    let mut client = RumpsteakEndpoint::new(Node::Client);
+    let mut warehouse = RumpsteakEndpoint::new(Node::Warehouse);
+
+    connect(&mut server, &mut client);
+    connect(&mut server, &mut warehouse);
+
+    (server, client, warehouse)
+}
+
+/// Versions the server, client and warehouse negotiate
+async fn negotiate(
+    server: &VersionNegotiator,
+    client: &VersionNegotiator,
+    warehouse: &VersionNegotiator,
+) -> [Result<u32, NegotiationError>; 3] {
+    let (mut server_ep, mut client_ep, mut warehouse_ep) = star_endpoints();
+    let mut server_handler = RumpsteakHandler::<Node, Msg>::new();
+    let mut client_handler = RumpsteakHandler::<Node, Msg>::new();
+    let mut warehouse_handler = RumpsteakHandler::<Node, Msg>::new();
+
+    let (server, client, warehouse) = tokio::join!(
+        server.negotiate(
+            &mut server_handler,
+            &mut server_ep,
+            &[Node::Client, Node::Warehouse]
+        ),
+        client.accept(&mut client_handler, &mut client_ep, Node::Server),
+        warehouse.accept(&mut warehouse_handler, &mut warehouse_ep, Node::Server),
+    );
+    [server, client, warehouse].map(|outcome| outcome.map(|negotiated| negotiated.version))
+}
+
+#[tokio::test]
+async fn test_rollout_runs_the_highest_shared_version() {
+    let upgraded = CHECKOUT_UPGRADE_FROM_V1.negotiator();
+    let previous = CHECKOUT_UPGRADE_FROM_V1.previous_version();
+    let legacy = VersionNegotiator::new().support(previous.version, previous.descriptor);
+
+    // While the warehouse still runs version 1
+    let [server, client, warehouse] = negotiate(&upgraded, &upgraded, &legacy).await;
+    assert_eq!(
+        (server.unwrap(), client.unwrap(), warehouse.unwrap()),
+        (1, 1, 1)
+    );
+
+    // And once every endpoint is upgraded
+    let [server, client, warehouse] = negotiate(&upgraded, &upgraded, &upgraded).await;
+    assert_eq!(
+        (server.unwrap(), client.unwrap(), warehouse.unwrap()),
+        (2, 2, 2)
+    );
+}
+
+#[tokio::test]
+async fn test_versions_from_other_definitions_are_not_shared() {
+    let upgraded = CHECKOUT_UPGRADE_FROM_V1.negotiator();
+    let forked =
+        VersionNegotiator::new().support(2, ProtocolDescriptor::new("Checkout", 0xdead_beef));
+
+    let [server, client, warehouse] = negotiate(&upgraded, &upgraded, &forked).await;
+    assert!(matches!(
+        server,
+        Err(NegotiationError::NoCommonVersion { ref role, .. }) if role == "Warehouse"
+    ));
+    assert!(matches!(client, Err(NegotiationError::Aborted { .. })));
+    assert!(matches!(warehouse, Err(NegotiationError::Aborted { .. })));
+}
+
+#[tokio::test]
+async fn test_adapter_refuses_added_branches_in_previous_sessions() {
+    let previous = CHECKOUT_UPGRADE_FROM_V1.previous_version();
+    let mut server = VersionAdapter::new(
+        NoOpHandler::<Node>::new(),
+        &CHECKOUT_UPGRADE_FROM_V1,
+        &previous,
+    );
+    assert_eq!(server.version(), 1);
+    server
+        .choose(&mut (), Node::Server, Label("accept"))
+        .await
+        .unwrap();
+    let err = server
+        .choose(&mut (), Node::Server, Label("backorder"))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ChoreographyError::ProtocolViolation(_)));
+
+    let current = CHECKOUT_UPGRADE_FROM_V1.current_version();
+    let mut server = VersionAdapter::new(
+        NoOpHandler::<Node>::new(),
+        &CHECKOUT_UPGRADE_FROM_V1,
+        &current,
+    );
+    server
+        .choose(&mut (), Node::Server, Label("backorder"))
+        .await
+        .unwrap();
+}
+
+#[test]
+fn test_adapters_are_generated_for_compatible_versions() {
+    let v2 = V1.replace("version = 1", "version = 2").replace(
+        "        reject: {",
+        "        backorder: {\n            Server -> Client: OrderDelayed\n        }\n        reject: {",
+    );
+    let adapter = generate_upgrade_adapter(V1, &v2).unwrap().to_string();
+    assert!(adapter.contains("CHECKOUT_UPGRADE_FROM_V1"));
+    assert!(adapter.contains("label : \"backorder\""));
+
+    // Reserving stock only after accepting changes what the warehouse sees
+    let reordered = v2.replace(
+        "            Server -> Warehouse: Reserve\n            Server -> Client: OrderAccepted",
+        "            Server -> Client: OrderAccepted\n            Server -> Warehouse: Reserve",
+    );
+    assert!(matches!(
+        generate_upgrade_adapter(V1, &reordered),
+        Err(UpgradeError::Incompatible { line: 8, .. })
+    ));
+}
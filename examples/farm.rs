@@ -0,0 +1,89 @@
+#![allow(clippy::unwrap_used)]
+#![allow(clippy::expect_used)]
+
+// A master farming tasks out to a role family of workers
+//
+// `Worker<I>` is one generic role covering every instance of the family, and
+// the session types are written once whatever the number of workers.
+
+use futures::{channel::mpsc, executor, join};
+use rumpsteak_aura::{
+    channel::Bidirectional, session, try_session, End, Message, Receive, Role, Send,
+};
+use std::{error::Error, result};
+
+type Result<T> = result::Result<T, Box<dyn Error>>;
+
+type Channel = Bidirectional<mpsc::UnboundedSender<Label>, mpsc::UnboundedReceiver<Label>>;
+
+#[derive(Role)]
+#[message(Label)]
+struct Master(#[routes(Worker)] Vec<Channel>);
+
+#[derive(Role)]
+#[message(Label)]
+struct Worker<const I: usize>(#[route(Master)] Channel);
+
+#[derive(Message)]
+enum Label {
+    Task(Task),
+    Answer(Answer),
+}
+
+struct Task(u64);
+
+struct Answer(u64);
+
+#[session]
+type FarmMaster<const I: usize> = Send<Worker<I>, Task, Receive<Worker<I>, Answer, End>>;
+
+#[session]
+type FarmWorker = Receive<Master, Task, Send<Master, Answer, End>>;
+
+async fn master<const I: usize>(role: &mut Master, task: u64) -> Result<u64> {
+    try_session(role, |s: FarmMaster<'_, _, I>| async {
+        let s = s.send(Task(task)).await?;
+        let (Answer(answer), s) = s.receive().await?;
+        Ok((answer, s))
+    })
+    .await
+}
+
+async fn worker<const I: usize>(role: &mut Worker<I>) -> Result<()> {
+    try_session(role, |s: FarmWorker<'_, _>| async {
+        let (Task(task), s) = s.receive().await?;
+        let s = s.send(Answer(task * task)).await?;
+        Ok(((), s))
+    })
+    .await
+}
+
+fn pair() -> (Channel, Channel) {
+    let (left_sender, right_receiver) = mpsc::unbounded();
+    let (right_sender, left_receiver) = mpsc::unbounded();
+    (
+        Bidirectional::new(left_sender, left_receiver),
+        Bidirectional::new(right_sender, right_receiver),
+    )
+}
+
+fn main() {
+    let (master_0, worker_0) = pair();
+    let (master_1, worker_1) = pair();
+    let mut m = Master(vec![master_0, master_1]);
+    let mut w0 = Worker::<0>(worker_0);
+    let mut w1 = Worker::<1>(worker_1);
+
+    let (answers, _, _) = executor::block_on(async {
+        join!(
+            async {
+                let first = master::<0>(&mut m, 3).await.unwrap();
+                let second = master::<1>(&mut m, 4).await.unwrap();
+                (first, second)
+            },
+            async { worker(&mut w0).await.unwrap() },
+            async { worker(&mut w1).await.unwrap() },
+        )
+    });
+    println!("answers = {answers:?}");
+}
@@ -0,0 +1,73 @@
+// WASM example: a browser role talking to native roles
+//
+// The Browser role runs here and reaches the Gateway role over WebSocket.
+// The Gateway and Worker roles run natively in the `websocket_gateway`
+// example of the choreography crate, which must be started first.
+
+use rumpsteak_aura_choreography::effects::{
+    handlers::rumpsteak::{RumpsteakEndpoint, RumpsteakHandler},
+    ChoreoHandler, ChoreographyError,
+};
+use rumpsteak_aura_choreography::runtime::{session_promise, websocket::WebSocketTransport};
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+
+/// Initialize panic hook for better error messages in browser console
+#[wasm_bindgen(start)]
+pub fn init() {
+    console_error_panic_hook::set_once();
+}
+
+/// Role definitions, matching the native side
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Role {
+    Browser,
+    Gateway,
+    Worker,
+}
+
+impl rumpsteak_aura::Role for Role {
+    type Message = Message;
+
+    fn seal(&mut self) {}
+    fn is_sealed(&self) -> bool {
+        false
+    }
+}
+
+/// Message types, matching the native side
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message {
+    Question(String),
+    Answer(String),
+}
+
+impl rumpsteak_aura::Message<Box<dyn std::any::Any + Send>> for Message {
+    fn upcast(msg: Box<dyn std::any::Any + Send>) -> Self {
+        *msg.downcast::<Message>().unwrap()
+    }
+
+    fn downcast(self) -> Result<Box<dyn std::any::Any + Send>, Self> {
+        Ok(Box::new(self))
+    }
+}
+
+/// Ask the gateway at `url` a question, resolving with its answer
+#[wasm_bindgen]
+pub fn ask(url: String, question: String) -> js_sys::Promise {
+    session_promise(async move {
+        let session = WebSocketTransport::new().open(&url).await?;
+        let mut endpoint = RumpsteakEndpoint::new(Role::Browser);
+        endpoint.register_session(Role::Gateway, session);
+
+        let mut handler = RumpsteakHandler::<Role, Message>::new();
+        let question = Message::Question(question);
+        handler.send(&mut endpoint, Role::Gateway, &question).await?;
+        match handler.recv(&mut endpoint, Role::Gateway).await? {
+            Message::Answer(answer) => Ok(answer),
+            other => Err(ChoreographyError::ProtocolViolation(format!(
+                "expected an answer, got {other:?}"
+            ))),
+        }
+    })
+}
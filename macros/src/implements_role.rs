@@ -0,0 +1,79 @@
+//! Implementation of the `#[implements_role]` attribute macro.
+//!
+//! Checks at compile time that a struct implements the handler trait
+//! generated for a choreography role. `#[implements_role(Checkout::Buyer)]`
+//! on `struct MyBuyer` refers to the `BuyerHandlers` trait and to the
+//! `__IMPLEMENTS_CHECKOUT_BUYER` requirement that handler code generation
+//! emits next to it, so a path prefix such as `checkout::Checkout::Buyer`
+//! points at the module the choreography was generated in.
+//!
+//! A struct without any `impl BuyerHandlers` fails with one error stating
+//! the role and every step it must handle. An incomplete impl fails with the
+//! compiler's own list of missing trait items.
+
+use proc_macro2::TokenStream;
+use quote::{format_ident, quote, quote_spanned};
+use syn::{parse2, spanned::Spanned, Error, ItemStruct, Path, Result};
+
+pub fn implements_role(attr: TokenStream, input: TokenStream) -> Result<TokenStream> {
+    let role_path = parse2::<Path>(attr)?;
+    let item = parse2::<ItemStruct>(input)?;
+
+    let count = role_path.segments.len();
+    if count < 2 {
+        return Err(Error::new_spanned(
+            &role_path,
+            "expected a `Protocol::Role` path, e.g. #[implements_role(Checkout::Buyer)]",
+        ));
+    }
+    if !item.generics.params.is_empty() {
+        return Err(Error::new_spanned(
+            &item.generics,
+            "#[implements_role(...)] does not support generic structs",
+        ));
+    }
+
+    let protocol = &role_path.segments[count - 2].ident;
+    let role = &role_path.segments[count - 1].ident;
+    let mut prefix = role_path.clone();
+    prefix.segments = role_path.segments.iter().take(count - 2).cloned().collect();
+
+    let trait_name = format_ident!("{}Handlers", role, span = role.span());
+    let requirement = format_ident!(
+        "__IMPLEMENTS_{}_{}",
+        protocol.to_string().to_uppercase(),
+        role.to_string().to_uppercase(),
+        span = protocol.span()
+    );
+    let (trait_path, requirement_path) = if prefix.segments.is_empty() {
+        (quote!(#trait_name), quote!(#requirement))
+    } else {
+        (quote!(#prefix::#trait_name), quote!(#prefix::#requirement))
+    };
+
+    let ident = &item.ident;
+    // Inherent associated items shadow trait ones only when the impl's
+    // bounds hold, so `IMPLEMENTED` is true exactly when the struct
+    // implements the trait
+    let check = quote_spanned! {role_path.span()=>
+        #[allow(dead_code)]
+        const _: () = {
+            struct Check<T: ?Sized>(::core::marker::PhantomData<T>);
+            trait Unimplemented {
+                const IMPLEMENTED: bool = false;
+            }
+            impl<T: ?Sized> Unimplemented for Check<T> {}
+            impl<T: ?Sized + #trait_path> Check<T> {
+                const IMPLEMENTED: bool = true;
+            }
+            if !<Check<#ident>>::IMPLEMENTED {
+                ::core::panic!("{}", #requirement_path);
+            }
+        };
+    };
+
+    Ok(quote! {
+        #item
+        #check
+    })
+}
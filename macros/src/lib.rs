@@ -15,6 +15,7 @@
 use proc_macro::TokenStream;
 
 mod choreography;
+mod implements_role;
 mod message;
 mod parse;
 mod role;
@@ -46,6 +47,10 @@ pub fn message(input: TokenStream) -> TokenStream {
 ///
 /// Requires `#[message(MessageType)]` attribute to specify the message type,
 /// and `#[route(OtherRole)]` attributes on fields to specify communication routes.
+/// A `#[routes(Worker)]` field, such as a `Vec<Channel>`, holds the routes to
+/// every instance of a role family `Worker<const I: usize>`.
+/// `#[protocol(Name)]` names the protocol when a session of the role is
+/// abandoned in a debug build.
 ///
 /// # Example
 ///
@@ -54,7 +59,7 @@ pub fn message(input: TokenStream) -> TokenStream {
 /// #[message(Label)]
 /// struct Client(#[route(Server)] Channel);
 /// ```
-#[proc_macro_derive(Role, attributes(message, route))]
+#[proc_macro_derive(Role, attributes(message, protocol, route, routes))]
 pub fn role(input: TokenStream) -> TokenStream {
     role::role(input.into())
         .unwrap_or_else(|err| err.to_compile_error())
@@ -118,3 +123,28 @@ pub fn choreography(input: TokenStream) -> TokenStream {
         .unwrap_or_else(|err| err.to_compile_error())
         .into()
 }
+
+/// Attribute macro checking that a struct implements a choreography role.
+///
+/// Refers to the `<Role>Handlers` trait generated with
+/// `@codegen(style = "handlers")`. A struct that does not implement it fails
+/// to compile with a single error listing every step of the role, instead of
+/// trait-bound errors wherever the struct is used as handlers.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// #[implements_role(Checkout::Buyer)]
+/// struct MyBuyer;
+///
+/// #[async_trait]
+/// impl BuyerHandlers for MyBuyer {
+///     // ...
+/// }
+/// ```
+#[proc_macro_attribute]
+pub fn implements_role(attr: TokenStream, input: TokenStream) -> TokenStream {
+    implements_role::implements_role(attr.into(), input.into())
+        .unwrap_or_else(|err| err.to_compile_error())
+        .into()
+}
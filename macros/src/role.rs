@@ -6,15 +6,27 @@
 use crate::parse;
 use proc_macro2::TokenStream;
 use quote::{quote, ToTokens};
-use syn::{parse2, spanned::Spanned, Data, DeriveInput, Error, Index, Result, Type};
+use syn::{
+    parse2, parse_quote, spanned::Spanned, Data, DeriveInput, Error, Ident, Index, Result, Type,
+};
 
 /// Implements the `Role` and `Route` traits for the given type.
 ///
 /// Requires `#[message(...)]` attribute and `#[route(...)]` attributes on fields.
+/// An optional `#[protocol(Name)]` attribute names the protocol in the
+/// diagnostics of abandoned sessions.
+/// A field marked `#[routes(Family)]` instead holds one route per instance of
+/// a role family `Family<const I: usize>`, indexed by the instance. Such
+/// routes are not sealed with the role, so it can run one session with each
+/// instance in turn.
 pub fn role(input: TokenStream) -> Result<TokenStream> {
     let input = parse2::<DeriveInput>(input)?;
 
     let message = parse::attribute::<Type>(&input.attrs, "message", input.span())?;
+    let protocol = parse::optional_attribute::<Ident>(&input.attrs, "protocol")?.map(|protocol| {
+        let protocol = protocol.to_string();
+        quote! { const PROTOCOL: ::core::option::Option<&'static str> = ::core::option::Option::Some(#protocol); }
+    });
 
     let ident = &input.ident;
     let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
@@ -24,10 +36,15 @@ pub fn role(input: TokenStream) -> Result<TokenStream> {
         _ => Err(Error::new_spanned(&input, "expected a struct")),
     }?;
 
-    // Collect field identifiers for seal/is_sealed implementations
+    // Collect field identifiers for seal/is_sealed implementations. Routes to
+    // a role family are left out: the role runs one session with each
+    // instance, and ending one must not close the routes to the others.
     let mut field_idents = Vec::new();
 
     for (i, field) in fields.iter().enumerate() {
+        if parse::optional_attribute::<Type>(&field.attrs, "routes")?.is_some() {
+            continue;
+        }
         let field_ident = match &field.ident {
             Some(ident) => ident.to_token_stream(),
             None => Index::from(i).to_token_stream(),
@@ -38,6 +55,7 @@ pub fn role(input: TokenStream) -> Result<TokenStream> {
     let mut output = quote! {
         impl #impl_generics ::rumpsteak_aura::Role for #ident #ty_generics #where_clause {
             type Message = #message;
+            #protocol
 
             fn seal(&mut self) {
                 #(
@@ -58,14 +76,31 @@ pub fn role(input: TokenStream) -> Result<TokenStream> {
     };
 
     for (i, field) in fields.iter().enumerate() {
-        let route = parse::attribute::<Type>(&field.attrs, "route", field.span())?;
-
         let field_ty = &field.ty;
         let field_ident = match &field.ident {
             Some(ident) => ident.to_token_stream(),
             None => Index::from(i).to_token_stream(),
         };
 
+        if let Some(family) = parse::optional_attribute::<Type>(&field.attrs, "routes")? {
+            let mut generics = input.generics.clone();
+            generics.params.push(parse_quote!(const __I: usize));
+            let (impl_generics, _, _) = generics.split_for_impl();
+
+            output.extend(quote! {
+                impl #impl_generics ::rumpsteak_aura::Route<#family<__I>> for #ident #ty_generics #where_clause {
+                    type Route = <#field_ty as ::core::ops::Index<usize>>::Output;
+
+                    fn route(&mut self) -> &mut Self::Route {
+                        &mut self.#field_ident[__I]
+                    }
+                }
+            });
+            continue;
+        }
+
+        let route = parse::attribute::<Type>(&field.attrs, "route", field.span())?;
+
         output.extend(quote! {
             impl #impl_generics ::rumpsteak_aura::Route<#route> for #ident #ty_generics #where_clause {
                 type Route = #field_ty;
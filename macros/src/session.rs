@@ -256,6 +256,7 @@ fn session_enum(mut input: ItemEnum) -> Result<TokenStream> {
                     Err(message) => message
                 };)*
 
+                ::rumpsteak_aura::State::discard(state);
                 Err(message)
             }
         }
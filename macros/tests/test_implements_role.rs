@@ -0,0 +1,33 @@
+// The attribute compiles away for a struct implementing its role's trait.
+// Structs that do not implement it fail to compile, which a runtime test
+// cannot observe.
+
+mod checkout {
+    pub trait BuyerHandlers {
+        fn make_order(&mut self) -> u32;
+    }
+
+    pub const __IMPLEMENTS_CHECKOUT_BUYER: &str =
+        "type does not implement the Buyer role of Checkout: it needs `impl BuyerHandlers` with make_order";
+}
+
+use checkout::BuyerHandlers;
+use rumpsteak_aura_macros::implements_role;
+
+#[implements_role(checkout::Checkout::Buyer)]
+struct MyBuyer {
+    orders: u32,
+}
+
+impl BuyerHandlers for MyBuyer {
+    fn make_order(&mut self) -> u32 {
+        self.orders += 1;
+        self.orders
+    }
+}
+
+#[test]
+fn test_implemented_role_compiles() {
+    let mut buyer = MyBuyer { orders: 0 };
+    assert_eq!(buyer.make_order(), 1);
+}
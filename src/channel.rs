@@ -1,14 +1,18 @@
 // Channel abstractions for session-typed communication
 //
 // Provides bidirectional channels with Sink and Stream implementations,
-// and channel pairing utilities for session types.
+// and channel pairing utilities for session types. The `futures` mpsc
+// channels need the "std" feature; `Bidirectional` works with any
+// `Sink`/`Stream` pair.
 
 use crate::Sealable;
-use futures::{channel::mpsc, Sink, Stream};
-use std::{
+use core::{
     pin::Pin,
     task::{Context, Poll},
 };
+#[cfg(feature = "std")]
+use futures::channel::mpsc;
+use futures::{Sink, Stream};
 
 pub trait Pair<P: Pair<Self>>: Sized {
     fn pair() -> (Self, P);
@@ -35,12 +39,14 @@ impl Sealable for Nil {
     }
 }
 
+#[cfg(feature = "std")]
 impl<T> Pair<mpsc::UnboundedReceiver<T>> for mpsc::UnboundedSender<T> {
     fn pair() -> (Self, mpsc::UnboundedReceiver<T>) {
         mpsc::unbounded()
     }
 }
 
+#[cfg(feature = "std")]
 impl<T> Pair<mpsc::UnboundedSender<T>> for mpsc::UnboundedReceiver<T> {
     fn pair() -> (Self, mpsc::UnboundedSender<T>) {
         let (sender, receiver) = Pair::pair();
@@ -48,6 +54,7 @@ impl<T> Pair<mpsc::UnboundedSender<T>> for mpsc::UnboundedReceiver<T> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<T> Sealable for mpsc::UnboundedSender<T> {
     fn seal(&mut self) {
         // Close the sender
@@ -60,6 +67,7 @@ impl<T> Sealable for mpsc::UnboundedSender<T> {
     }
 }
 
+#[cfg(feature = "std")]
 impl<T> Sealable for mpsc::UnboundedReceiver<T> {
     fn seal(&mut self) {
         // Close the receiver by dropping
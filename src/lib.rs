@@ -1,20 +1,30 @@
 // Core Rumpsteak library for session-typed communication
 //
 // Provides session types (Send, Receive, Select, Branch, End) and channel abstractions.
+//
+// Without the default "std" feature the crate is no_std and only needs
+// `alloc`, so generated session types can run on embedded devices over any
+// `Sink`/`Stream` transport.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
 
 pub mod channel;
+pub mod protocols;
 pub mod serialize;
 
 pub use rumpsteak_aura_macros::{session, Message, Role, Roles};
 
-use futures::{FutureExt, Sink, SinkExt, Stream, StreamExt};
-use std::{
+use alloc::boxed::Box;
+use core::{
     any::Any,
     convert::Infallible,
+    fmt,
     future::Future,
     marker::{self, PhantomData},
 };
-use thiserror::Error;
+use futures::{FutureExt, Sink, SinkExt, Stream, StreamExt};
 
 /// Trait for types that can be sealed to prevent further use
 pub trait Sealable {
@@ -25,27 +35,54 @@ pub trait Sealable {
     fn is_sealed(&self) -> bool;
 }
 
-#[derive(Debug, Error)]
+#[derive(Debug)]
 pub enum SessionError<E> {
-    #[error("session was used after being sealed")]
     Sealed,
-    #[error(transparent)]
     Channel(E),
 }
 
+impl<E: fmt::Display> fmt::Display for SessionError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sealed => f.write_str("session was used after being sealed"),
+            Self::Channel(error) => error.fmt(f),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: std::error::Error + 'static> std::error::Error for SessionError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Sealed => None,
+            Self::Channel(error) => error.source(),
+        }
+    }
+}
+
 pub type SendError<Q, R> =
     SessionError<<<Q as Route<R>>::Route as Sink<<Q as Role>::Message>>::Error>;
 
-#[derive(Debug, Error)]
+#[derive(Debug)]
 pub enum ReceiveError {
-    #[error("receiver stream is empty")]
     EmptyStream,
-    #[error("received message with an unexpected type")]
     UnexpectedType,
-    #[error("session was used after being sealed")]
     Sealed,
 }
 
+impl fmt::Display for ReceiveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::EmptyStream => "receiver stream is empty",
+            Self::UnexpectedType => "received message with an unexpected type",
+            Self::Sealed => "session was used after being sealed",
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ReceiveError {}
+
 /// This trait represents a message to be exchanged between two participants.
 /// The generic type L is the type of the label (i.e. the content of the
 /// message).
@@ -92,6 +129,9 @@ impl<L: marker::Send + Sync + 'static> Message<L> for Box<dyn Any + marker::Send
 pub trait Role {
     type Message;
 
+    /// Protocol the role takes part in, named in diagnostics
+    const PROTOCOL: Option<&'static str> = None;
+
     /// Seal all routes for this role, preventing further communication
     fn seal(&mut self);
 
@@ -110,15 +150,139 @@ pub trait Route<R>: Role + Sized {
 /// e.g. a `Send`, a `Receive`, etc, contains a `State`, as well as some type
 /// bounds. When an action is taken (e.g. when `send` is called on a `Send`),
 /// the `Send` will take it state and convert it into the continuation.
+///
+/// In debug builds, a `State` dropped before the protocol reached `End`,
+/// other than by an operation failing, panics with the protocol, the role and
+/// the step it was abandoned at.
 pub struct State<'r, R: Role> {
     role: &'r mut R,
+    #[cfg(all(debug_assertions, feature = "std"))]
+    step: Option<Step>,
 }
 
 impl<'r, R: Role> State<'r, R> {
     #[inline]
     fn new(role: &'r mut R) -> Self {
-        Self { role }
+        Self {
+            role,
+            #[cfg(all(debug_assertions, feature = "std"))]
+            step: None,
+        }
+    }
+
+    /// Record the step the protocol waits at
+    #[inline]
+    fn enter(&mut self, _step: impl FnOnce() -> Step) {
+        #[cfg(all(debug_assertions, feature = "std"))]
+        {
+            self.step = Some(_step());
+        }
+    }
+
+    /// The protocol no longer waits at a step, as it ended or failed
+    #[inline]
+    fn defuse(&mut self) {
+        #[cfg(all(debug_assertions, feature = "std"))]
+        {
+            self.step = None;
+        }
+    }
+
+    /// `error`, after defusing the state it ends
+    #[inline]
+    fn fail<E>(&mut self, error: E) -> E {
+        self.defuse();
+        error
+    }
+
+    /// Drop the state without reporting it as abandoned, for branches that
+    /// do not match the received message
+    #[doc(hidden)]
+    #[inline]
+    pub fn discard(mut self) {
+        self.defuse();
+    }
+}
+
+#[cfg(all(debug_assertions, feature = "std"))]
+impl<R: Role> Drop for State<'_, R> {
+    fn drop(&mut self) {
+        let Some(step) = self.step else {
+            return;
+        };
+        if std::thread::panicking() {
+            return;
+        }
+        let role = short_type_name(core::any::type_name::<R>());
+        match R::PROTOCOL {
+            Some(protocol) => panic!(
+                "{role} of {protocol} dropped at `{step}` without completing the session. This indicates a protocol violation."
+            ),
+            None => panic!(
+                "{role} dropped at `{step}` without completing the session. This indicates a protocol violation."
+            ),
+        }
+    }
+}
+
+/// Step of a session type, as in the text form of local types
+#[cfg_attr(not(all(debug_assertions, feature = "std")), allow(dead_code))]
+#[derive(Clone, Copy)]
+struct Step {
+    action: &'static str,
+    peer: &'static str,
+    label: Option<&'static str>,
+}
+
+impl Step {
+    fn of<R, L>(action: &'static str) -> Self {
+        Self {
+            action,
+            peer: core::any::type_name::<R>(),
+            label: Some(core::any::type_name::<L>()),
+        }
+    }
+
+    fn choice<R>(action: &'static str) -> Self {
+        Self {
+            action,
+            peer: core::any::type_name::<R>(),
+            label: None,
+        }
+    }
+}
+
+#[cfg(all(debug_assertions, feature = "std"))]
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let peer = short_type_name(self.peer);
+        match (self.action, self.label) {
+            ("send", Some(label)) => write!(f, "send {} to {peer}", short_type_name(label)),
+            ("receive", Some(label)) => {
+                write!(f, "receive {} from {peer}", short_type_name(label))
+            }
+            ("select", _) => write!(f, "select to {peer}"),
+            (action, _) => write!(f, "{action} from {peer}"),
+        }
+    }
+}
+
+/// `name` without the module paths of the types in it
+#[cfg(all(debug_assertions, feature = "std"))]
+fn short_type_name(name: &str) -> String {
+    let mut short = String::new();
+    let mut segments = name.split("::").peekable();
+    while let Some(segment) = segments.next() {
+        if segments.peek().is_none() {
+            short.push_str(segment);
+        } else {
+            let path_start = segment
+                .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .map_or(0, |index| index + 1);
+            short.push_str(&segment[..path_start]);
+        }
     }
+    short
 }
 
 pub trait FromState<'r> {
@@ -144,7 +308,8 @@ impl<'r, R: Role> FromState<'r> for End<'r, R> {
     type Role = R;
 
     #[inline]
-    fn from_state(state: State<'r, Self::Role>) -> Self {
+    fn from_state(mut state: State<'r, Self::Role>) -> Self {
+        state.defuse();
         Self { state }
     }
 }
@@ -168,6 +333,7 @@ impl<R: Role> private::Session for End<'_, R> {}
 impl<'r, R: Role> Session<'r> for End<'r, R> {}
 
 /// This structure represents a protocol which next action is to send.
+#[must_use = "session states must be used to continue the protocol"]
 pub struct Send<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> {
     state: State<'q, Q>,
     phantom: PhantomData<(R, L, S)>,
@@ -177,7 +343,8 @@ impl<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> FromState<'q> for Send<'q, Q
     type Role = Q;
 
     #[inline]
-    fn from_state(state: State<'q, Self::Role>) -> Self {
+    fn from_state(mut state: State<'q, Self::Role>) -> Self {
+        state.enter(|| Step::of::<R, L>("send"));
         Self {
             state,
             phantom: PhantomData,
@@ -191,16 +358,12 @@ where
     Q::Route: Sink<Q::Message> + Unpin,
 {
     #[inline]
-    pub async fn send(self, label: L) -> Result<S, SendError<Q, R>> {
+    pub async fn send(mut self, label: L) -> Result<S, SendError<Q, R>> {
         if self.state.role.is_sealed() {
-            return Err(SessionError::Sealed);
+            return Err(self.state.fail(SessionError::Sealed));
         }
-        self.state
-            .role
-            .route()
-            .send(Message::upcast(label))
-            .await
-            .map_err(SessionError::Channel)?;
+        let sent = self.state.role.route().send(Message::upcast(label)).await;
+        sent.map_err(|error| self.state.fail(SessionError::Channel(error)))?;
         Ok(FromState::from_state(self.state))
     }
 }
@@ -210,6 +373,7 @@ impl<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> private::Session for Send<'q
 impl<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> Session<'q> for Send<'q, Q, R, L, S> {}
 
 /// This structure represents a protocol which next action is to receive .
+#[must_use = "session states must be used to continue the protocol"]
 pub struct Receive<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> {
     state: State<'q, Q>,
     phantom: PhantomData<(R, L, S)>,
@@ -219,7 +383,8 @@ impl<'q, Q: Role, R, L, S: FromState<'q, Role = Q>> FromState<'q> for Receive<'q
     type Role = Q;
 
     #[inline]
-    fn from_state(state: State<'q, Self::Role>) -> Self {
+    fn from_state(mut state: State<'q, Self::Role>) -> Self {
+        state.enter(|| Step::of::<R, L>("receive"));
         Self {
             state,
             phantom: PhantomData,
@@ -233,13 +398,15 @@ where
     Q::Route: Stream<Item = Q::Message> + Unpin,
 {
     #[inline]
-    pub async fn receive(self) -> Result<(L, S), ReceiveError> {
+    pub async fn receive(mut self) -> Result<(L, S), ReceiveError> {
         if self.state.role.is_sealed() {
-            return Err(ReceiveError::Sealed);
+            return Err(self.state.fail(ReceiveError::Sealed));
         }
         let message = self.state.role.route().next().await;
-        let message = message.ok_or(ReceiveError::EmptyStream)?;
-        let label = message.downcast().or(Err(ReceiveError::UnexpectedType))?;
+        let message = message.ok_or_else(|| self.state.fail(ReceiveError::EmptyStream))?;
+        let label = message
+            .downcast()
+            .map_err(|_| self.state.fail(ReceiveError::UnexpectedType))?;
         Ok((label, FromState::from_state(self.state)))
     }
 }
@@ -252,6 +419,7 @@ pub trait Choice<'r, L> {
     type Session: FromState<'r>;
 }
 
+#[must_use = "session states must be used to continue the protocol"]
 pub struct Select<'q, Q: Role, R, C> {
     state: State<'q, Q>,
     phantom: PhantomData<(R, C)>,
@@ -261,7 +429,8 @@ impl<'q, Q: Role, R, C> FromState<'q> for Select<'q, Q, R, C> {
     type Role = Q;
 
     #[inline]
-    fn from_state(state: State<'q, Self::Role>) -> Self {
+    fn from_state(mut state: State<'q, Self::Role>) -> Self {
+        state.enter(|| Step::choice::<R>("select"));
         Self {
             state,
             phantom: PhantomData,
@@ -274,21 +443,20 @@ where
     Q::Route: Sink<Q::Message> + Unpin,
 {
     #[inline]
-    pub async fn select<L>(self, label: L) -> Result<<C as Choice<'q, L>>::Session, SendError<Q, R>>
+    pub async fn select<L>(
+        mut self,
+        label: L,
+    ) -> Result<<C as Choice<'q, L>>::Session, SendError<Q, R>>
     where
         Q::Message: Message<L>,
         C: Choice<'q, L>,
         C::Session: FromState<'q, Role = Q>,
     {
         if self.state.role.is_sealed() {
-            return Err(SessionError::Sealed);
+            return Err(self.state.fail(SessionError::Sealed));
         }
-        self.state
-            .role
-            .route()
-            .send(Message::upcast(label))
-            .await
-            .map_err(SessionError::Channel)?;
+        let sent = self.state.role.route().send(Message::upcast(label)).await;
+        sent.map_err(|error| self.state.fail(SessionError::Channel(error)))?;
         Ok(FromState::from_state(self.state))
     }
 }
@@ -306,6 +474,7 @@ pub trait Choices<'r>: Sized {
     ) -> Result<Self, <Self::Role as Role>::Message>;
 }
 
+#[must_use = "session states must be used to continue the protocol"]
 pub struct Branch<'q, Q: Role, R, C> {
     state: State<'q, Q>,
     phantom: PhantomData<(R, C)>,
@@ -315,7 +484,8 @@ impl<'q, Q: Role, R, C> FromState<'q> for Branch<'q, Q, R, C> {
     type Role = Q;
 
     #[inline]
-    fn from_state(state: State<'q, Self::Role>) -> Self {
+    fn from_state(mut state: State<'q, Self::Role>) -> Self {
+        state.enter(|| Step::choice::<R>("branch"));
         Self {
             state,
             phantom: PhantomData,
@@ -328,12 +498,12 @@ where
     Q::Route: Stream<Item = Q::Message> + Unpin,
 {
     #[inline]
-    pub async fn branch(self) -> Result<C, ReceiveError> {
+    pub async fn branch(mut self) -> Result<C, ReceiveError> {
         if self.state.role.is_sealed() {
-            return Err(ReceiveError::Sealed);
+            return Err(self.state.fail(ReceiveError::Sealed));
         }
         let message = self.state.role.route().next().await;
-        let message = message.ok_or(ReceiveError::EmptyStream)?;
+        let message = message.ok_or_else(|| self.state.fail(ReceiveError::EmptyStream))?;
         let choice = C::downcast(self.state, message);
         choice.or(Err(ReceiveError::UnexpectedType))
     }
@@ -361,8 +531,10 @@ impl SessionGuard {
 impl Drop for SessionGuard {
     fn drop(&mut self) {
         if !self.completed {
-            // In debug mode, panic if the session was not properly completed
-            #[cfg(debug_assertions)]
+            // In debug mode, panic if the session was not properly completed.
+            // Without std there is no way to tell whether we are already
+            // unwinding, and panicking again would abort.
+            #[cfg(all(debug_assertions, feature = "std"))]
             {
                 assert!(
                     std::thread::panicking(),
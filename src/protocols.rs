@@ -0,0 +1,212 @@
+// Protocol templates
+//
+// Parameterized choreographies for common interaction patterns, written in
+// the choreography DSL. Each is a `protocol Name<...> { ... }` definition:
+// a choreography instantiates one with its own roles and messages,
+//
+//     instantiate TwoPhaseCommit<Bank, Branch> as Settle;
+//     call Settle
+//
+// and can extend it by copying the definition under a new name. Every
+// template is instantiable by name in any choreography, without being
+// copied, and is checked by the choreography crate's tests to validate and
+// project for every role.
+//
+// Parameters without a kind stand for a role or a message name, `usize`
+// parameters for a count. Message names that are not parameters are fixed
+// by the template and listed in its documentation.
+
+/// `Client` sends `Request`, `Server` answers with `Response`
+///
+/// ```mermaid
+/// sequenceDiagram
+///     Client->>Server: Request
+///     Server->>Client: Response
+/// ```
+pub const REQUEST_RESPONSE: &str = "\
+protocol RequestResponse<Client, Server, Request, Response> {
+    Client -> Server: Request
+    Server -> Client: Response
+}
+";
+
+/// `Client` sends `Request` and waits at most five seconds at each step;
+/// `Server` answers with `Response` or, past its own deadline, with
+/// `DeadlineExceeded`
+///
+/// ```mermaid
+/// sequenceDiagram
+///     Client->>Server: Request
+///     alt Server: respond
+///         Server->>Client: Response
+///     else Server: expire
+///         Server->>Client: DeadlineExceeded
+///     end
+/// ```
+pub const REQUEST_RESPONSE_WITH_TIMEOUT: &str = "\
+protocol RequestResponseWithTimeout<Client, Server, Request, Response> {
+    [@timeout = 5000]
+    Client -> Server: Request
+    choice Server {
+        respond: {
+            [@timeout = 5000]
+            Server -> Client: Response
+        }
+        expire: {
+            Server -> Client: DeadlineExceeded
+        }
+    }
+}
+";
+
+/// Up to `N` rounds in which `Sender` either attempts to deliver `Msg`,
+/// answered with an `Outcome`, or skips the round once an attempt succeeded
+///
+/// ```mermaid
+/// sequenceDiagram
+///     loop N times
+///         alt Sender: attempt
+///             Sender->>Receiver: Msg
+///             Receiver->>Sender: Outcome
+///         else Sender: skip
+///             Sender->>Receiver: Skip
+///         end
+///     end
+/// ```
+pub const RETRY: &str = "\
+protocol Retry<N: usize, Msg, Sender, Receiver> {
+    loop (count: N) {
+        choice Sender {
+            attempt: {
+                Sender -> Receiver: Msg
+                Receiver -> Sender: Outcome
+            }
+            skip: {
+                Sender -> Receiver: Skip
+            }
+        }
+    }
+}
+";
+
+/// `Coordinator` asks `Participant` to `Prepare` and collects its `Vote`,
+/// then decides to `Commit` or `Abort`, acknowledged with `Ack`
+///
+/// ```mermaid
+/// sequenceDiagram
+///     Coordinator->>Participant: Prepare
+///     Participant->>Coordinator: Vote
+///     alt Coordinator: commit
+///         Coordinator->>Participant: Commit
+///         Participant->>Coordinator: Ack
+///     else Coordinator: abort
+///         Coordinator->>Participant: Abort
+///         Participant->>Coordinator: Ack
+///     end
+/// ```
+pub const TWO_PHASE_COMMIT: &str = "\
+protocol TwoPhaseCommit<Coordinator, Participant> {
+    Coordinator -> Participant: Prepare
+    Participant -> Coordinator: Vote
+    choice Coordinator {
+        commit: {
+            Coordinator -> Participant: Commit
+            Participant -> Coordinator: Ack
+        }
+        abort: {
+            Coordinator -> Participant: Abort
+            Participant -> Coordinator: Ack
+        }
+    }
+}
+";
+
+/// `Candidate` asks `Voter` for its vote; once granted, it announces it was
+/// `Elected`
+///
+/// ```mermaid
+/// sequenceDiagram
+///     Candidate->>Voter: RequestVote
+///     alt Voter: grant
+///         Voter->>Candidate: VoteGranted
+///         Candidate->>Voter: Elected
+///     else Voter: deny
+///         Voter->>Candidate: VoteDenied
+///     end
+/// ```
+pub const LEADER_ELECTION: &str = "\
+protocol LeaderElection<Candidate, Voter> {
+    Candidate -> Voter: RequestVote
+    choice Voter {
+        grant: {
+            Voter -> Candidate: VoteGranted
+            Candidate -> Voter: Elected
+        }
+        deny: {
+            Voter -> Candidate: VoteDenied
+        }
+    }
+}
+";
+
+/// `Coordinator` sends `Task` to every instance of the parameterized role
+/// `Worker` and gathers a `Reply` from each
+///
+/// ```mermaid
+/// sequenceDiagram
+///     Coordinator->>Worker: Task
+///     Worker->>Coordinator: Reply
+/// ```
+pub const SCATTER_GATHER: &str = "\
+protocol ScatterGather<Coordinator, Worker, Task, Reply> {
+    Coordinator -> Worker[*]: Task
+    Worker[*] -> Coordinator: Reply
+}
+";
+
+/// One round of single-decree Paxos: `Proposer` prepares a ballot and, if
+/// `Acceptor` promises it, proposes a value, which `Acceptor` accepts and
+/// `Learner` learns; a refused ballot tells `Learner` there is no value this
+/// round
+///
+/// ```mermaid
+/// sequenceDiagram
+///     Proposer->>Acceptor: Prepare
+///     alt Acceptor: promise
+///         Acceptor->>Proposer: Promise
+///         Proposer->>Acceptor: Propose
+///         Acceptor->>Proposer: Accepted
+///         Acceptor->>Learner: Learn
+///     else Acceptor: refuse
+///         Acceptor->>Proposer: Refused
+///         Acceptor->>Learner: NoValue
+///     end
+/// ```
+pub const PAXOS: &str = "\
+protocol Paxos<Proposer, Acceptor, Learner> {
+    Proposer -> Acceptor: Prepare
+    choice Acceptor {
+        promise: {
+            Acceptor -> Proposer: Promise
+            Proposer -> Acceptor: Propose
+            Acceptor -> Proposer: Accepted
+            Acceptor -> Learner: Learn
+        }
+        refuse: {
+            Acceptor -> Proposer: Refused
+            Acceptor -> Learner: NoValue
+        }
+    }
+}
+";
+
+/// Every template
+pub const ALL: &[&str] = &[
+    REQUEST_RESPONSE,
+    REQUEST_RESPONSE_WITH_TIMEOUT,
+    RETRY,
+    TWO_PHASE_COMMIT,
+    LEADER_ELECTION,
+    SCATTER_GATHER,
+    PAXOS,
+];